@@ -0,0 +1,79 @@
+//! Steps of one generated 200k-compartment Hodgkin-Huxley cell, solved in
+//! one sweep and branch-parallel on 4 and 8 threads. Run with
+//! `cargo bench --bench parallel_solve`.
+
+use std::fmt::Write;
+use std::hint::black_box;
+use std::time::Instant;
+
+use compartment_rs::channels::{ChannelType, Dynamics, HodgkinHuxley};
+use compartment_rs::solver::{Simulation, SolverOptions};
+use compartment_rs::{Channel, Compartments, ReaderOptions, swc_reader_from_bytes};
+
+const STEPS: usize = 20;
+
+fn generated_cell(nodes: usize) -> Compartments {
+    let mut swc = String::from("# Generated benchmark cell\n");
+    writeln!(swc, "1 1 0.0 0.0 0.0 5.0 -1").unwrap();
+    for id in 2..=nodes {
+        // Short unbranched runs hanging off every 50th node
+        let parent = if id % 50 == 2 { id / 2 } else { id - 1 };
+        writeln!(
+            swc,
+            "{} 3 {}.5 {}.25 {}.0 0.5 {}",
+            id,
+            id % 1000,
+            id % 700,
+            id % 300,
+            parent
+        )
+        .unwrap();
+    }
+    let skeleton = swc_reader_from_bytes(swc.as_bytes(), &ReaderOptions::default()).unwrap();
+    let mut compartments = Compartments::from_skeleton(skeleton);
+    for c in compartments.components.iter_mut() {
+        let mut channel = Channel::default();
+        channel.channel_type = ChannelType::HodgkinHuxley(HodgkinHuxley::new());
+        channel.resistance = 100.0;
+        channel.capacitance = 1.0;
+        c.set_channel(channel);
+    }
+    compartments
+}
+
+/// Best of five after one warm-up round, in seconds per step
+fn best(mut simulation: Simulation) -> f64 {
+    let mut best = f64::INFINITY;
+    for _ in 0..6 {
+        let start = Instant::now();
+        for _ in 0..STEPS {
+            simulation.inject(2, 0.5).unwrap();
+            simulation.step().unwrap();
+        }
+        best = best.min(start.elapsed().as_secs_f64() / STEPS as f64);
+    }
+    black_box(simulation.voltages());
+    best
+}
+
+fn main() {
+    let compartments = generated_cell(200_000);
+    let build = || Simulation::new(&compartments, 0.025).unwrap();
+    let serial = best(build());
+    println!("serial:    {:8.3} ms per step", serial * 1e3);
+    for threads in [4, 8] {
+        let simulation = build().with_solver(SolverOptions::parallel_tree(threads, 1000));
+        let partition = simulation.partition().unwrap();
+        let time = best(simulation);
+        println!(
+            "{} threads: {:8.3} ms per step, {:.2}x ({} subtrees, {} junctions, \
+             largest share {})",
+            threads,
+            time * 1e3,
+            serial / time,
+            partition.subtrees,
+            partition.junctions,
+            partition.largest_share
+        );
+    }
+}
@@ -0,0 +1,101 @@
+//! `quick_look` against the separate calls it replaces (read, morphometrics,
+//! passive snapshot) on generated 1k-, 10k- and 100k-node cells. Run with
+//! `cargo bench --bench quick_look`.
+
+use std::fmt::Write;
+use std::hint::black_box;
+use std::time::Instant;
+
+use compartment_rs::analysis::{electrotonic_lengths, soma_transfer_impedances};
+use compartment_rs::morphometry::Morphometry;
+use compartment_rs::quick::{QuickLookOptions, quick_look_from_bytes};
+use compartment_rs::{Channel, Compartments, ReaderOptions, swc_reader_from_bytes};
+
+fn generated_swc(nodes: usize) -> Vec<u8> {
+    let mut out = String::from("# Generated benchmark cell\n");
+    writeln!(out, "1 1 0.0 0.0 0.0 5.0 -1").unwrap();
+    for id in 2..=nodes {
+        // Short unbranched runs hanging off every 50th node
+        let parent = if id % 50 == 2 { id / 2 } else { id - 1 };
+        writeln!(
+            out,
+            "{} 3 {}.5 {}.25 {}.0 0.5 {}",
+            id,
+            id % 1000,
+            id % 700,
+            id % 300,
+            parent
+        )
+        .unwrap();
+    }
+    out.into_bytes()
+}
+
+/// What a caller did before `quick_look`: each step on its own
+fn composed(data: &[u8], options: &QuickLookOptions) {
+    let skeleton = swc_reader_from_bytes(data, &ReaderOptions::default()).unwrap();
+    let morphometry = Morphometry::new(&skeleton.nodes);
+    black_box((
+        morphometry.total_length(),
+        morphometry.total_area(),
+        morphometry.max_branching_degree(),
+        morphometry.spatial_metrics().bounding_box,
+    ));
+    if skeleton.nodes.len() <= options.snapshot_max_nodes {
+        let mut compartments = Compartments::from_skeleton(skeleton);
+        for c in compartments.components.iter_mut() {
+            c.set_channel(Channel::passive(
+                options.resistance,
+                options.capacitance,
+                options.conductance,
+            ));
+        }
+        if compartments.coarsen(options.snapshot_compartments).is_err() {
+            compartments.coarsen_by_length(f64::INFINITY);
+        }
+        black_box(soma_transfer_impedances(&compartments, 0.0));
+        black_box(electrotonic_lengths(&compartments));
+    }
+}
+
+/// Best of five after one warm-up round, in seconds
+fn best(mut run: impl FnMut()) -> f64 {
+    let mut best = f64::INFINITY;
+    for _ in 0..6 {
+        let start = Instant::now();
+        run();
+        best = best.min(start.elapsed().as_secs_f64());
+    }
+    best
+}
+
+fn main() {
+    // Snapshots for all but the largest cell
+    let options = QuickLookOptions {
+        snapshot_max_nodes: 20_000,
+        ..QuickLookOptions::default()
+    };
+    let no_snapshot = QuickLookOptions {
+        snapshot: false,
+        ..QuickLookOptions::default()
+    };
+    for nodes in [1_000, 10_000, 100_000] {
+        let data = generated_swc(nodes);
+        let quick = best(|| {
+            black_box(quick_look_from_bytes(&data, &options).unwrap());
+        });
+        let bare = best(|| {
+            black_box(quick_look_from_bytes(&data, &no_snapshot).unwrap());
+        });
+        let separate = best(|| composed(&data, &options));
+        println!(
+            "{:>7} nodes: quick_look {:8.3} ms ({:.3} ms without the snapshot), \
+             separate calls {:8.3} ms, {:.1}x",
+            nodes,
+            quick * 1e3,
+            bare * 1e3,
+            separate * 1e3,
+            separate / quick
+        );
+    }
+}
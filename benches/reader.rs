@@ -0,0 +1,56 @@
+//! Reader throughput with and without stats collection, on a generated
+//! 500k-node file. Run with `cargo bench --bench reader`.
+
+use std::fmt::Write;
+use std::hint::black_box;
+use std::time::Instant;
+
+use compartment_rs::{ReaderOptions, swc_reader_from_bytes};
+
+fn generated_swc(nodes: usize) -> Vec<u8> {
+    let mut out = String::from("# Generated benchmark cell\n");
+    writeln!(out, "1 1 0.0 0.0 0.0 5.0 -1").unwrap();
+    for id in 2..=nodes {
+        // Short unbranched runs hanging off every 50th node, varied types
+        let parent = if id % 50 == 2 { id / 2 } else { id - 1 };
+        let kind = 2 + id % 3;
+        let radius = if id % 97 == 0 { 0.0 } else { 0.5 };
+        writeln!(
+            out,
+            "{} {} {}.5 {}.25 {}.0 {} {}",
+            id,
+            kind,
+            id % 1000,
+            id % 700,
+            id % 300,
+            radius,
+            parent
+        )
+        .unwrap();
+    }
+    out.into_bytes()
+}
+
+fn main() {
+    let data = generated_swc(500_000);
+    for collect_stats in [true, false] {
+        let options = ReaderOptions {
+            emit_warnings: false,
+            collect_stats,
+            ..Default::default()
+        };
+        // One warm-up round, then the best of five
+        let mut best = f64::INFINITY;
+        for _ in 0..6 {
+            let start = Instant::now();
+            black_box(swc_reader_from_bytes(&data, &options).unwrap());
+            best = best.min(start.elapsed().as_secs_f64());
+        }
+        println!(
+            "collect_stats = {:5}: {:.3} s, {:.0} nodes/s",
+            collect_stats,
+            best,
+            500_000.0 / best
+        );
+    }
+}
@@ -0,0 +1,27 @@
+//! Records which commit the crate was built from, for `manifest::BuildInfo`
+
+use std::process::Command;
+
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+fn main() {
+    let commit = match (git(&["rev-parse", "HEAD"]), git(&["status", "--porcelain"])) {
+        (Some(commit), Some(status)) if !status.is_empty() => format!("{}-dirty", commit),
+        (Some(commit), _) => commit,
+        (None, _) => "unknown".to_owned(),
+    };
+    println!("cargo:rustc-env=COMPARTMENT_RS_GIT_COMMIT={}", commit);
+    println!(
+        "cargo:rustc-env=COMPARTMENT_RS_PROFILE={}",
+        std::env::var("PROFILE").unwrap_or_default()
+    );
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/index");
+    println!("cargo:rerun-if-changed=build.rs");
+}
@@ -0,0 +1,166 @@
+"""Reference values of features::morphometrics for every preset, written to
+reference.csv next to this file.
+
+Run from the repository root: python3 data/conventions/reference.py
+
+Independent of the Rust code and of any package. `native` walks the SWC
+graph segment by segment. `neurom` follows NeuroM's definitions on
+MorphIO-style sections: soma points are the soma, each neurite's first
+section starts at its first point, every child section starts with a copy of
+its parent's last point, branch order is the number of upstream sections and
+path distances run along sections from the neurite's first point. It does
+not import NeuroM; regenerate with NeuroM itself when it is at hand.
+"""
+
+import math
+import os
+
+HERE = os.path.dirname(os.path.abspath(__file__))
+FILES = ["data/basic.swc", "data/soma_three_point.swc", "data/conventions/fixture.swc"]
+FEATURES = [
+    "total_length",
+    "stem_count",
+    "branch_count",
+    "tip_count",
+    "max_branch_order",
+    "mean_branch_order",
+    "mean_branch_length",
+    "mean_tortuosity",
+    "max_path_distance",
+    "mean_path_distance",
+]
+SOMA = 1
+
+
+def read(path):
+    nodes = {}
+    for line in open(path):
+        line = line.split("#")[0].strip()
+        if not line:
+            continue
+        i, t, x, y, z, r, p = line.split()[:7]
+        nodes[int(i)] = (int(t), (float(x), float(y), float(z)), float(r), int(p))
+    children = {i: [] for i in nodes}
+    for i, (_, _, _, p) in sorted(nodes.items()):
+        if p != -1:
+            children[p].append(i)
+    root = next(i for i, n in nodes.items() if n[3] == -1)
+    return nodes, children, root
+
+
+def dist(a, b):
+    return math.sqrt(sum((p - q) ** 2 for p, q in zip(a, b)))
+
+
+def mean(values):
+    return sum(values) / len(values) if values else 0.0
+
+
+def native(nodes, children, root):
+    def pos(i):
+        return nodes[i][1]
+
+    distance, order = {root: 0.0}, {root: 0}
+    stack = [root]
+    while stack:
+        i = stack.pop()
+        for c in children[i]:
+            distance[c] = distance[i] + dist(pos(c), pos(i))
+            order[c] = order[i] + (1 if len(children[i]) > 1 else 0)
+            stack.append(c)
+    lengths, orders, tortuosities, tips = [], [], [], 0
+    for end in nodes:
+        if end == root or len(children[end]) == 1:
+            continue
+        start = nodes[end][3]
+        while start != root and len(children[start]) == 1:
+            start = nodes[start][3]
+        length = distance[end] - distance[start]
+        straight = dist(pos(start), pos(end))
+        lengths.append(length)
+        orders.append(order[end])
+        tortuosities.append(length / straight if straight > 0 else 1.0)
+        tips += not children[end]
+    neurites = [i for i in nodes if i != root and nodes[i][0] != SOMA]
+    stems = [i for i in neurites if nodes[i][3] == root or nodes[nodes[i][3]][0] == SOMA]
+    points = [distance[i] for i in neurites]
+    return [
+        sum(lengths),
+        len(stems),
+        len(lengths),
+        tips,
+        max(orders, default=0),
+        mean(orders),
+        mean(lengths),
+        mean(tortuosities),
+        max(points, default=0.0),
+        mean(points),
+    ]
+
+
+def neurom(nodes, children, root):
+    def pos(i):
+        return nodes[i][1]
+
+    stems = [
+        i
+        for i in nodes
+        if i != root and nodes[i][0] != SOMA and (nodes[i][3] == root or nodes[nodes[i][3]][0] == SOMA)
+    ]
+    # (points, upstream sections, path length before the first point)
+    sections = []
+    points = []
+    pending = [(s, [s], 0, 0.0) for s in stems]
+    while pending:
+        first, ids, upstream, before = pending.pop()
+        i = first
+        while len(children[i]) == 1:
+            i = children[i][0]
+            ids.append(i)
+        length = sum(dist(pos(a), pos(b)) for a, b in zip(ids, ids[1:]))
+        sections.append((ids, upstream, length))
+        along = before
+        for a, b in zip([None] + ids, ids):
+            if a is not None:
+                along += dist(pos(a), pos(b))
+                points.append(along)
+            elif upstream == 0:
+                points.append(along)
+        for c in children[i]:
+            pending.append((c, [i, c], upstream + 1, before + length))
+    lengths = [s[2] for s in sections]
+    orders = [s[1] for s in sections]
+    tortuosities = []
+    for ids, _, length in sections:
+        straight = dist(pos(ids[0]), pos(ids[-1]))
+        tortuosities.append(length / straight if straight > 0 else 1.0)
+    tips = sum(1 for ids, _, _ in sections if not children[ids[-1]])
+    return [
+        sum(lengths),
+        len(stems),
+        len(sections),
+        tips,
+        max(orders, default=0),
+        mean(orders),
+        mean(lengths),
+        mean(tortuosities),
+        max(points, default=0.0),
+        mean(points),
+    ]
+
+
+def main():
+    root_dir = os.path.dirname(os.path.dirname(HERE))
+    rows = ["# Written by reference.py; file,preset,feature,value"]
+    rows.append("file,preset,feature,value")
+    for path in FILES:
+        nodes, children, root = read(os.path.join(root_dir, path))
+        for preset, features in [("native", native), ("neurom", neurom)]:
+            for name, value in zip(FEATURES, features(nodes, children, root)):
+                rows.append("{},{},{},{}".format(path, preset, name, repr(float(value))))
+    with open(os.path.join(HERE, "reference.csv"), "w") as out:
+        out.write("\n".join(rows) + "\n")
+
+
+if __name__ == "__main__":
+    main()
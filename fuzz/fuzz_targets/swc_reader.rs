@@ -0,0 +1,15 @@
+#![no_main]
+
+use compartment_rs::{ReaderOptions, swc_reader_from_bytes};
+use libfuzzer_sys::fuzz_target;
+
+// Any input must come back as Ok or a typed SwcError, never a panic or hang.
+// From the repo root, seeded with the fixtures:
+// `cargo fuzz run swc_reader fuzz/corpus/swc_reader data data/fuzz`
+fuzz_target!(|data: &[u8]| {
+    let options = ReaderOptions {
+        emit_warnings: false,
+        ..Default::default()
+    };
+    let _ = swc_reader_from_bytes(data, &options);
+});
@@ -0,0 +1,322 @@
+//! Ion accumulation: intracellular and periaxonal concentrations that
+//! follow the membrane currents, and the Nernst potentials they set.
+//!
+//! Every compartment has its cytoplasm and a thin shell of extracellular
+//! space around it, as in Frankenhaeuser & Hodgkin (1956) and NEURON's
+//! `kext`. Outward current of an ion moves it from the one to the other,
+//! and the shell relaxes towards the bath with a first-order time constant.
+//!
+//! A solver that wants accumulation holds an `IonAccumulation`, books each
+//! mechanism's current with `record` as it would for an `EnergyLedger`,
+//! calls `advance` once per step and `update_reversals` before the next
+//! step's currents. One that does not pays nothing, and every reversal
+//! potential stays as set. `Simulation::with_accumulation` does all of
+//! that inside each step.
+//!
+//! Currents are in nA, times in ms, lengths in µm, concentrations in mM and
+//! potentials in mV. Outward currents are positive.
+
+use std::f64::consts::PI;
+
+use crate::channels::{ChannelType, HodgkinHuxley, Ion};
+use crate::compartments::Compartments;
+use crate::reversal::resolve;
+
+/// In C/mol
+pub const FARADAY: f64 = 96485.33212;
+/// In J/(mol·K)
+pub const GAS_CONSTANT: f64 = 8.314462618;
+
+/// Reversal potential of an ion of charge number `valence`, in mV
+pub fn nernst(valence: i32, inside: f64, outside: f64, celsius: f64) -> f64 {
+    let thermal = GAS_CONSTANT * (celsius + 273.15) / (valence as f64 * FARADAY);
+    1e3 * thermal * (outside / inside).ln()
+}
+
+/// An ion whose concentrations are followed, with where they start
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrackedIon {
+    pub ion: Ion,
+    /// Starting intracellular concentration
+    pub inside: f64,
+    /// Concentration in the bath, which the shell starts at and relaxes to
+    pub bath: f64,
+}
+
+impl TrackedIon {
+    /// NEURON's defaults, `ki` and `ko`
+    pub fn potassium() -> TrackedIon {
+        TrackedIon {
+            ion: Ion::K,
+            inside: 54.4,
+            bath: 2.5,
+        }
+    }
+
+    /// NEURON's defaults, `nai` and `nao`
+    pub fn sodium() -> TrackedIon {
+        TrackedIon {
+            ion: Ion::Na,
+            inside: 10.0,
+            bath: 140.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccumulationConfig {
+    pub ions: Vec<TrackedIon>,
+    /// Thickness of the periaxonal shell, in µm; `kext`'s `fhspace`
+    pub shell_thickness: f64,
+    /// Time constant of the exchange between shell and bath, in ms; `kext`'s
+    /// `txfer`. Infinite seals the shell.
+    pub tau: f64,
+    /// Temperature for the Nernst potentials, in °C
+    pub celsius: f64,
+}
+
+impl Default for AccumulationConfig {
+    fn default() -> Self {
+        AccumulationConfig {
+            ions: vec![TrackedIon::potassium(), TrackedIon::sodium()],
+            shell_thickness: 0.03,
+            tau: 50.0,
+            celsius: 6.3,
+        }
+    }
+}
+
+/// Concentrations of one tracked ion in every compartment
+#[derive(Debug, Clone, PartialEq)]
+struct Pool {
+    ion: Ion,
+    valence: f64,
+    bath: f64,
+    inside: Vec<f64>,
+    outside: Vec<f64>,
+    /// Current booked since the last `advance`, in nA
+    pending: Vec<f64>,
+}
+
+/// Concentration state of every compartment
+#[derive(Debug, Clone, PartialEq)]
+pub struct IonAccumulation {
+    config: AccumulationConfig,
+    /// Cytoplasm and shell volumes, in µm³
+    volume: Vec<f64>,
+    shell: Vec<f64>,
+    pools: Vec<Pool>,
+}
+
+impl IonAccumulation {
+    /// Every compartment at its starting concentrations. Compartments
+    /// without volume, such as the dummy root and a point soma, have no
+    /// membrane either and keep them.
+    pub fn new(
+        compartments: &Compartments,
+        config: AccumulationConfig,
+    ) -> Result<IonAccumulation, String> {
+        if !(config.shell_thickness > 0.0 && config.shell_thickness.is_finite()) {
+            return Err(format!(
+                "Shell thickness must be positive, got {}",
+                config.shell_thickness
+            ));
+        }
+        if config.tau.is_nan() || config.tau <= 0.0 {
+            return Err(format!(
+                "Time constant must be positive, got {}",
+                config.tau
+            ));
+        }
+        if !(config.celsius > -273.15 && config.celsius.is_finite()) {
+            return Err(format!("No temperature of {} °C", config.celsius));
+        }
+        let n = compartments.components.len();
+        let mut pools: Vec<Pool> = Vec::new();
+        for tracked in &config.ions {
+            let Some(valence) = tracked.ion.valence() else {
+                return Err("Only specific ions accumulate".to_owned());
+            };
+            if pools.iter().any(|p| p.ion == tracked.ion) {
+                return Err(format!("{:?} is tracked twice", tracked.ion));
+            }
+            for c in [tracked.inside, tracked.bath] {
+                if !(c > 0.0 && c.is_finite()) {
+                    return Err(format!(
+                        "{:?} concentrations must be positive, got {}",
+                        tracked.ion, c
+                    ));
+                }
+            }
+            pools.push(Pool {
+                ion: tracked.ion,
+                valence: valence as f64,
+                bath: tracked.bath,
+                inside: vec![tracked.inside; n],
+                outside: vec![tracked.bath; n],
+                pending: vec![0.0; n],
+            });
+        }
+
+        let d = config.shell_thickness;
+        let (volume, shell) = compartments
+            .components
+            .iter()
+            .map(|c| {
+                let r = c.diam / 2.0;
+                (
+                    PI * r * r * c.length,
+                    PI * ((r + d) * (r + d) - r * r) * c.length,
+                )
+            })
+            .unzip();
+        Ok(IonAccumulation {
+            config,
+            volume,
+            shell,
+            pools,
+        })
+    }
+
+    pub fn config(&self) -> &AccumulationConfig {
+        &self.config
+    }
+
+    /// Books `current` carried by `ion` through compartment `idx` for the
+    /// coming step. Currents of untracked ions are ignored.
+    pub fn record(&mut self, idx: usize, ion: Ion, current: f64) {
+        if let Some(pool) = self.pools.iter_mut().find(|p| p.ion == ion) {
+            pool.pending[idx] += current;
+        }
+    }
+
+    /// Moves the charge booked since the last call across the membranes,
+    /// the booked currents held over `dt`, and relaxes the shells. Fails,
+    /// changing nothing, if a concentration would drop to zero.
+    pub fn advance(&mut self, dt: f64) -> Result<(), String> {
+        let tau = self.config.tau;
+        let decay = (-dt / tau).exp();
+        let mut next = Vec::with_capacity(self.pools.len());
+        for pool in &self.pools {
+            let (mut inside, mut outside) = (pool.inside.clone(), pool.outside.clone());
+            for idx in 0..inside.len() {
+                if self.volume[idx] == 0.0 {
+                    continue;
+                }
+                // nA over µm³ to mM/ms: pC is 1e-12 C, µm³ is 1e-15 l
+                let moles = pool.pending[idx] / (pool.valence * FARADAY) * 1e6;
+                inside[idx] -= moles * dt / self.volume[idx];
+                // Exact for a constant influx into a shell leaking to the bath
+                let influx = moles / self.shell[idx];
+                outside[idx] = if tau.is_infinite() {
+                    outside[idx] + influx * dt
+                } else {
+                    let settled = pool.bath + influx * tau;
+                    settled + (outside[idx] - settled) * decay
+                };
+                if !(inside[idx] > 0.0 && outside[idx] > 0.0) {
+                    return Err(format!(
+                        "{:?} ran out in compartment {}, at {} mM inside and {} mM outside",
+                        pool.ion, idx, inside[idx], outside[idx]
+                    ));
+                }
+            }
+            next.push((inside, outside));
+        }
+        for (pool, (inside, outside)) in self.pools.iter_mut().zip(next) {
+            pool.inside = inside;
+            pool.outside = outside;
+            pool.pending.iter_mut().for_each(|p| *p = 0.0);
+        }
+        Ok(())
+    }
+
+    fn pool(&self, ion: Ion) -> Option<&Pool> {
+        self.pools.iter().find(|p| p.ion == ion)
+    }
+
+    /// Intracellular concentration of `ion` in every compartment, None if it
+    /// is not tracked
+    pub fn inside(&self, ion: Ion) -> Option<&[f64]> {
+        self.pool(ion).map(|p| &p.inside[..])
+    }
+
+    /// Periaxonal concentration of `ion` in every compartment
+    pub fn outside(&self, ion: Ion) -> Option<&[f64]> {
+        self.pool(ion).map(|p| &p.outside[..])
+    }
+
+    /// Nernst potential of `ion` in every compartment
+    pub fn reversals(&self, ion: Ion) -> Option<Vec<f64>> {
+        let pool = self.pool(ion)?;
+        let valence = ion.valence()?;
+        Some(
+            pool.inside
+                .iter()
+                .zip(&pool.outside)
+                .map(|(&i, &o)| nernst(valence, i, o, self.config.celsius))
+                .collect(),
+        )
+    }
+
+    /// Sets the reversals of `hh`, the mechanism of compartment `idx`, from
+    /// the current concentrations if it opted in, as `update_reversals`
+    /// does for a simulation's own copy
+    pub(crate) fn refresh(&self, idx: usize, hh: &mut HodgkinHuxley) {
+        if !hh.use_dynamic_reversal {
+            return;
+        }
+        let celsius = self.config.celsius;
+        for (ion, e, explicit) in hh.reversal_slots() {
+            if let (false, Some(pool)) = (explicit, self.pool(ion)) {
+                let valence = ion.valence().expect("tracked ions are specific");
+                *e = nernst(valence, pool.inside[idx], pool.outside[idx], celsius);
+            }
+        }
+    }
+
+    /// Number of compartments the concentrations are kept for
+    pub(crate) fn len(&self) -> usize {
+        self.volume.len()
+    }
+
+    /// Sets the reversal potentials of every compartment whose mechanism
+    /// opted in from the current concentrations, in the compartment's own
+    /// slots, and resolves the mechanism against them, see `reversal`.
+    /// Ions that are not tracked keep what they were set to, and reversals
+    /// marked explicit on the mechanism stay.
+    pub fn update_reversals(&self, compartments: &mut Compartments) -> Result<(), String> {
+        if compartments.components.len() != self.volume.len() {
+            return Err(format!(
+                "Accumulation is for {} compartments, not {}",
+                self.volume.len(),
+                compartments.components.len()
+            ));
+        }
+        let celsius = self.config.celsius;
+        let reversal = |ion: Ion, idx: usize| {
+            self.pool(ion).map(|p| {
+                nernst(
+                    ion.valence().expect("tracked ions are specific"),
+                    p.inside[idx],
+                    p.outside[idx],
+                    celsius,
+                )
+            })
+        };
+        let cell = compartments.ion_reversals.cell.clone();
+        for (idx, c) in compartments.components.iter_mut().enumerate() {
+            if let ChannelType::HodgkinHuxley(hh) = &c.channel.channel_type
+                && hh.use_dynamic_reversal
+            {
+                for ion in HodgkinHuxley::IONS {
+                    if let Some(e) = reversal(ion, idx) {
+                        c.channel.reversals.insert(ion, e);
+                    }
+                }
+                resolve(&mut c.channel, &cell);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,235 @@
+//! The axon initial segment (AIS): the first stretch of axon past the soma,
+//! where spikes start, modelled with a much higher sodium density than the
+//! rest of the axon.
+//!
+//! `detect_ais` finds it on a skeleton. `EditableSkeleton::split_ais` puts a
+//! node on its distal end so it ends on a compartment boundary, and
+//! `Compartments::tag_ais` then tags every compartment it covers with
+//! `AIS_TAG`. `Compartments::set_tag_param` and `@ais` rows of a parameter
+//! table set parameters on the tagged compartments only.
+
+use std::collections::HashMap;
+
+use log::warn;
+
+use crate::compartments::Compartments;
+use crate::edit::EditableSkeleton;
+use crate::index_map::{Attachment, AttachmentKind};
+use crate::swc_reader::{Node, StructureIdentifier};
+
+/// Tag of the AIS compartments, reserved for `tag_ais`
+pub const AIS_TAG: &str = "ais";
+
+/// Default AIS length in µm, within the 20 to 60 µm seen in cortical cells
+pub const DEFAULT_AIS_LENGTH: f64 = 40.0;
+
+/// An end closer than this to a node, in µm, lands on the node
+const ON_NODE: f64 = 1e-9;
+
+/// Which child the AIS follows where the proximal axon branches
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AxonChoice {
+    /// The child with the larger radius, the first listed on a tie
+    #[default]
+    Thicker,
+    /// The first child listed
+    First,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AisOptions {
+    /// Path length of the AIS from the soma, in µm
+    pub length: f64,
+    /// Which axon leaves the soma, if several do, and where the AIS goes at
+    /// a branch point
+    pub follow: AxonChoice,
+}
+
+impl Default for AisOptions {
+    fn default() -> Self {
+        AisOptions {
+            length: DEFAULT_AIS_LENGTH,
+            follow: AxonChoice::Thicker,
+        }
+    }
+}
+
+/// The AIS on a skeleton: the segments leading up to each of `nodes`, the
+/// last of them only up to `end_fraction` of its length
+#[derive(Debug, Clone, PartialEq)]
+pub struct AisRegion {
+    /// Axon nodes from the first past the soma outwards
+    pub nodes: Vec<u64>,
+    /// Where the AIS ends along the segment leading up to the last node, 0
+    /// at its proximal end. 1 when it ends on the node.
+    pub end_fraction: f64,
+    /// Path length from the soma, in µm
+    pub length: f64,
+    /// The axon ended before the requested length, so the AIS is the whole
+    /// path followed
+    pub short: bool,
+}
+
+impl AisRegion {
+    /// Share of the segment leading up to `node_id` inside the AIS, counted
+    /// from its proximal end
+    fn covered(&self, node_id: u64) -> f64 {
+        match self.nodes.iter().position(|&n| n == node_id) {
+            Some(k) if k + 1 == self.nodes.len() => self.end_fraction,
+            Some(_) => 1.0,
+            None => 0.0,
+        }
+    }
+}
+
+fn distance(a: &Node, b: &Node) -> f64 {
+    ((a.x_pos - b.x_pos).powi(2) + (a.y_pos - b.y_pos).powi(2) + (a.z_pos - b.z_pos).powi(2)).sqrt()
+}
+
+fn pick(candidates: Vec<&Node>, follow: AxonChoice) -> Option<&Node> {
+    match follow {
+        AxonChoice::First => candidates.first().copied(),
+        AxonChoice::Thicker => candidates
+            .into_iter()
+            .reduce(|best, n| if n.radius > best.radius { n } else { best }),
+    }
+}
+
+/// The first `options.length` µm of path along the axon leaving the soma,
+/// measured from the soma node it leaves from. None if no axon node hangs
+/// off a soma node, or the length is not positive. An axon shorter than the
+/// length gives all of it, with `short` set and a warning logged.
+pub fn detect_ais(
+    nodes: &[Node],
+    parent_child_map: &HashMap<u64, Vec<u64>>,
+    options: &AisOptions,
+) -> Option<AisRegion> {
+    if !(options.length > 0.0 && options.length.is_finite()) {
+        return None;
+    }
+    let by_id: HashMap<u64, &Node> = nodes.iter().map(|n| (n.node_id, n)).collect();
+    let axon_children = |id: u64| -> Vec<&Node> {
+        parent_child_map
+            .get(&id)
+            .into_iter()
+            .flatten()
+            .filter(|&&c| c != id)
+            .filter_map(|c| by_id.get(c).copied())
+            .filter(|n| n.structured_identifier == StructureIdentifier::Axon)
+            .collect()
+    };
+
+    let stems = nodes
+        .iter()
+        .filter(|n| n.structured_identifier == StructureIdentifier::Soma)
+        .flat_map(|n| axon_children(n.node_id))
+        .collect();
+    let mut node = pick(stems, options.follow)?;
+    let mut region = AisRegion {
+        nodes: Vec::new(),
+        end_fraction: 1.0,
+        length: 0.0,
+        short: false,
+    };
+    loop {
+        let segment = distance(by_id.get(&node.parent_id)?, node);
+        let remaining = options.length - region.length;
+        region.nodes.push(node.node_id);
+        if segment > remaining + ON_NODE {
+            region.end_fraction = remaining / segment;
+            region.length = options.length;
+            return Some(region);
+        }
+        region.length += segment;
+        if segment >= remaining - ON_NODE {
+            return Some(region);
+        }
+        match pick(axon_children(node.node_id), options.follow) {
+            Some(next) => node = next,
+            None => {
+                warn!(
+                    "Axon ends {:.1} µm from the soma, short of the {} µm AIS; using all of it",
+                    region.length, options.length
+                );
+                region.short = true;
+                return Some(region);
+            }
+        }
+    }
+}
+
+impl EditableSkeleton {
+    /// Detects the AIS and, if it ends inside a segment, splits the segment
+    /// there with `insert_node_on_edge`, the radius interpolated, and
+    /// renumbers with `finalize`. The region returned then ends on a node,
+    /// in the new IDs. Ok(None) if there is no axon.
+    pub fn split_ais(&mut self, options: &AisOptions) -> Result<Option<AisRegion>, String> {
+        if !(options.length > 0.0 && options.length.is_finite()) {
+            return Err(format!(
+                "AIS length must be positive and finite, got {}",
+                options.length
+            ));
+        }
+        let Some(region) = detect_ais(&self.nodes, &self.parent_child_map, options) else {
+            return Ok(None);
+        };
+        if region.end_fraction == 1.0 {
+            return Ok(Some(region));
+        }
+        let id_to_idx = self.id_to_idx();
+        let end = self.nodes[id_to_idx[region.nodes.last().expect("regions are never empty")]];
+        let parent = self.nodes[id_to_idx[&end.parent_id]];
+        let radius = parent.radius + (end.radius - parent.radius) * region.end_fraction;
+        self.insert_node_on_edge(parent.node_id, end.node_id, region.end_fraction, radius)?;
+        self.finalize()?;
+        Ok(detect_ais(&self.nodes, &self.parent_child_map, options))
+    }
+}
+
+impl Compartments {
+    /// Tags with `AIS_TAG` the stretch of every compartment that `region`
+    /// covers, found through each compartment's provenance, replacing any
+    /// earlier AIS tags. Returns how many compartments were tagged.
+    ///
+    /// With `split_ais` first, every tagged compartment lies wholly in the
+    /// AIS; otherwise the last one is tagged over its proximal part only.
+    pub fn tag_ais(&mut self, region: &AisRegion) -> Result<usize, String> {
+        let mut tags = Vec::new();
+        for (idx, spans) in self.provenance.iter().enumerate().skip(1) {
+            let total: f64 = spans.iter().map(|s| s.to - s.from).sum();
+            let inside: f64 = spans
+                .iter()
+                .map(|s| (s.to.min(region.covered(s.node_id)) - s.from).max(0.0))
+                .sum();
+            if inside > 0.0 {
+                tags.push(Attachment {
+                    name: AIS_TAG.to_owned(),
+                    kind: AttachmentKind::Tag,
+                    idx,
+                    from: 0.0,
+                    to: (inside / total).min(1.0),
+                    weight: 1.0,
+                    waveform: None,
+                });
+            }
+        }
+        if tags.is_empty() {
+            return Err(format!(
+                "No compartment covers the AIS ending at node {:?}",
+                region.nodes.last()
+            ));
+        }
+        self.attachments
+            .retain(|a| !(a.kind == AttachmentKind::Tag && a.name == AIS_TAG));
+        let tagged = tags.len();
+        self.attachments.extend(tags);
+        self.log(
+            "tag_ais",
+            &[
+                ("length", region.length.into()),
+                ("compartments", tagged.into()),
+            ],
+        );
+        Ok(tagged)
+    }
+}
@@ -0,0 +1,998 @@
+//! Closed-form passive properties of a compartmental model: electrotonic
+//! lengths and transfer impedances, without stepping through time, and the
+//! impedance profile measured from a ZAP run for comparison. From any run,
+//! the peak depolarization of each compartment and how much of the soma's
+//! spike reaches it.
+//!
+//! The model's own units are µm, Ω·cm, µF/cm² and S/cm². Impedances come out
+//! in MΩ, i.e. mV per nA, and frequencies go in as Hz.
+//!
+//! Also here: how far branch points are from Rall's 3/2 power rule, the
+//! condition under which a junction's daughters load their parent like a
+//! continuation of the parent cable would; and close appositions between
+//! the arbors of two cells, the potential synapses of Peters' rule.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::f64::consts::PI;
+use std::path::PathBuf;
+
+use crate::compartments::Compartments;
+use crate::geometry::{self, Vec3};
+use crate::protocols::Protocol;
+use crate::solver::SimulationResult;
+use crate::standardize::{Dataset, read_source};
+use crate::swc_reader::{
+    Node, ReaderOptions, Skeleton, StructureIdentifier, swc_reader_from_bytes,
+};
+
+/// A complex impedance, in MΩ
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Impedance {
+    pub re: f64,
+    pub im: f64,
+}
+
+impl Impedance {
+    pub fn magnitude(self) -> f64 {
+        self.re.hypot(self.im)
+    }
+
+    /// Phase in radians; negative when the voltage lags the current
+    pub fn phase(self) -> f64 {
+        self.im.atan2(self.re)
+    }
+
+    fn div(self, o: Impedance) -> Impedance {
+        let d = o.re * o.re + o.im * o.im;
+        Impedance {
+            re: (self.re * o.re + self.im * o.im) / d,
+            im: (self.im * o.re - self.re * o.im) / d,
+        }
+    }
+
+    fn add(self, o: Impedance) -> Impedance {
+        Impedance {
+            re: self.re + o.re,
+            im: self.im + o.im,
+        }
+    }
+
+    fn scale(self, s: f64) -> Impedance {
+        Impedance {
+            re: self.re * s,
+            im: self.im * s,
+        }
+    }
+
+    fn sub(self, o: Impedance) -> Impedance {
+        Impedance {
+            re: self.re - o.re,
+            im: self.im - o.im,
+        }
+    }
+}
+
+/// `axial_resistance` is in Ω·cm/µm
+const OHM_PER_AXIAL_UNIT: f64 = 1e4;
+/// Areas are in µm² and densities per cm²
+const CM2_PER_UM2: f64 = 1e-8;
+/// S to 1/MΩ
+const PER_MOHM_PER_S: f64 = 1e6;
+
+/// Electrotonic length `L/λ` of every branch: each unbranched run of
+/// compartments starting at the soma or at a branch point, in order of its
+/// first compartment. Tapering branches add up `length/λ` compartment by
+/// compartment, each with its own `λ = sqrt(d / (4 Ra g))`. The soma is
+/// not a branch.
+pub fn electrotonic_lengths(compartments: &Compartments) -> Vec<f64> {
+    let c = &compartments.components;
+    let mut lengths = Vec::new();
+    for start in 2..c.len() {
+        let parent = c[start].parent_idxs[0] as usize;
+        if parent > 1 && c[parent].children_idxs.len() == 1 {
+            continue;
+        }
+        let mut total = 0.0;
+        let mut idx = start;
+        loop {
+            let comp = &c[idx];
+            let diam_cm = comp.diam * 1e-4;
+            let lambda_cm =
+                (diam_cm / (4.0 * comp.channel.resistance * comp.channel.conductance)).sqrt();
+            total += comp.length * 1e-4 / lambda_cm;
+            match comp.children_idxs[..] {
+                [child] => idx = child as usize,
+                _ => break,
+            }
+        }
+        lengths.push(total);
+    }
+    lengths
+}
+
+/// Tree-ordered admittance matrix at `frequency`, already eliminated from the
+/// leaves up: the pivots, plus the (real) coupling of each compartment to
+/// its parent
+struct Factored {
+    pivots: Vec<Impedance>,
+    coupling: Vec<f64>,
+    parents: Vec<usize>,
+}
+
+impl Factored {
+    fn new(compartments: &Compartments, frequency: f64) -> Factored {
+        let c = &compartments.components;
+        let n = c.len();
+        let omega = 2.0 * PI * frequency;
+        // Admittances in 1/MΩ
+        let mut pivots: Vec<Impedance> = c
+            .iter()
+            .map(|comp| Impedance {
+                re: comp.membrane_conductance() * CM2_PER_UM2 * PER_MOHM_PER_S,
+                // capacitance() is in µF, 1e-6 F
+                im: omega * comp.capacitance() * 1e-6 * CM2_PER_UM2 * PER_MOHM_PER_S,
+            })
+            .collect();
+        let mut coupling = vec![0.0; n];
+        let mut parents = vec![0; n];
+        for (parent, child, g) in compartments.axial_conductances() {
+            let g = g / OHM_PER_AXIAL_UNIT * PER_MOHM_PER_S;
+            coupling[child] = g;
+            parents[child] = parent;
+            pivots[parent].re += g;
+            pivots[child].re += g;
+        }
+        // Parents come before their children, so eliminating in reverse
+        // index order only ever touches a parent's pivot
+        for i in (2..n).rev() {
+            let g = coupling[i];
+            let fill = Impedance { re: g * g, im: 0.0 }.div(pivots[i]);
+            pivots[parents[i]] = pivots[parents[i]].sub(fill);
+        }
+        Factored {
+            pivots,
+            coupling,
+            parents,
+        }
+    }
+
+    /// Voltages for a unit current injected into `at`. The off-diagonal
+    /// entries are `-g`, hence the additions where elimination subtracts.
+    fn solve(&self, at: usize) -> Vec<Impedance> {
+        let n = self.pivots.len();
+        let mut rhs = vec![Impedance::default(); n];
+        rhs[at].re = 1.0;
+        for i in (2..n).rev() {
+            let carried = rhs[i].div(self.pivots[i]).scale(self.coupling[i]);
+            rhs[self.parents[i]] = rhs[self.parents[i]].add(carried);
+        }
+        let mut v = vec![Impedance::default(); n];
+        for i in 1..n {
+            let from_parent = v[self.parents[i]].scale(self.coupling[i]);
+            v[i] = rhs[i].add(from_parent).div(self.pivots[i]);
+        }
+        v
+    }
+}
+
+/// Voltage in every compartment per unit current injected into the soma, at
+/// `frequency` (0 for DC). Indexed like `components`; the dummy root's
+/// entry is zero. One linear solve, linear in the number of compartments.
+pub fn soma_transfer_impedances(compartments: &Compartments, frequency: f64) -> Vec<Impedance> {
+    if compartments.components.len() < 2 {
+        return vec![Impedance::default(); compartments.components.len()];
+    }
+    Factored::new(compartments, frequency).solve(1)
+}
+
+/// Transfer impedance between every pair of compartments at `frequency`:
+/// entry `[i][j]` is the voltage in `i` per unit current injected into `j`.
+/// Indexed like `components`, with the dummy root's row and column zero.
+/// Needs one solve per compartment, so it grows with the square of their
+/// number; prefer `soma_transfer_impedances` when only the soma matters.
+pub fn transfer_impedance_matrix(
+    compartments: &Compartments,
+    frequency: f64,
+) -> Vec<Vec<Impedance>> {
+    let n = compartments.components.len();
+    let mut matrix = vec![vec![Impedance::default(); n]; n];
+    if n < 2 {
+        return matrix;
+    }
+    // The admittance matrix is symmetric, so its inverse is too and each
+    // solve fills a row as well as a column
+    let factored = Factored::new(compartments, frequency);
+    for (j, row) in matrix.iter_mut().enumerate().skip(1) {
+        *row = factored.solve(j);
+    }
+    matrix
+}
+
+/// Impedance measured from a run, see `impedance_profile`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImpedanceProfile {
+    /// In Hz, every frequency the run resolves within the sweep, ascending
+    pub frequencies: Vec<f64>,
+    pub impedances: Vec<Impedance>,
+    /// Frequency of the largest magnitude, in Hz
+    pub resonance_frequency: f64,
+    /// Largest magnitude over the magnitude at the lowest frequency, 1
+    /// when there is no resonance
+    pub q: f64,
+}
+
+impl ImpedanceProfile {
+    /// In MΩ
+    pub fn magnitudes(&self) -> Vec<f64> {
+        self.impedances.iter().map(|z| z.magnitude()).collect()
+    }
+
+    /// In radians, negative when the voltage lags
+    pub fn phases(&self) -> Vec<f64> {
+        self.impedances.iter().map(|z| z.phase()).collect()
+    }
+}
+
+/// In-place radix-2 FFT of `(re, im)` pairs, whose number must be a power
+/// of two
+fn fft(data: &mut [(f64, f64)]) {
+    let n = data.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            data.swap(i, j);
+        }
+    }
+    let twiddles: Vec<(f64, f64)> = (0..n / 2)
+        .map(|k| {
+            let (sin, cos) = (-2.0 * PI * k as f64 / n as f64).sin_cos();
+            (cos, sin)
+        })
+        .collect();
+    let mut len = 2;
+    while len <= n {
+        let half = len / 2;
+        for start in (0..n).step_by(len) {
+            for k in 0..half {
+                let (c, s) = twiddles[k * (n / len)];
+                let (a, b) = (data[start + k], data[start + k + half]);
+                let t = (b.0 * c - b.1 * s, b.0 * s + b.1 * c);
+                data[start + k] = (a.0 + t.0, a.1 + t.1);
+                data[start + k + half] = (a.0 - t.0, a.1 - t.1);
+            }
+        }
+        len *= 2;
+    }
+}
+
+/// Input impedance at compartment `site` from a run that injected the ZAP
+/// protocol `stimulus` there: the spectrum of the voltage response over
+/// that of the current, at every frequency the sweep covers.
+///
+/// The response is taken from the start of the sweep to the end of the run
+/// and measured from the voltage at the start, so the cell should be at
+/// rest then and the run should go on long enough after the sweep for the
+/// response to die down. Nonlinear membranes give the impedance of their
+/// response at this amplitude.
+pub fn impedance_profile(
+    result: &SimulationResult,
+    site: usize,
+    stimulus: &Protocol,
+) -> Result<ImpedanceProfile, String> {
+    let Protocol::Chirp {
+        start,
+        duration,
+        f0,
+        f1,
+        ..
+    } = *stimulus
+    else {
+        return Err(format!(
+            "Impedance profiles need a zap protocol, got {}",
+            stimulus.name()
+        ));
+    };
+    let voltage = result
+        .voltages
+        .get(site)
+        .filter(|_| site > 0)
+        .ok_or_else(|| format!("No compartment {} in the result", site))?;
+    let dt = result.dt;
+    let steps = voltage.len().saturating_sub(1);
+    if start + duration > steps as f64 * dt {
+        return Err(format!(
+            "The sweep ends at {} ms, after the run's {} ms",
+            start + duration,
+            steps as f64 * dt
+        ));
+    }
+    let current = stimulus.render(0, dt, steps)?;
+    let first = (start / dt).floor() as usize;
+    let n = (steps - first).next_power_of_two();
+    let rest = voltage[first];
+    let mut i_spectrum = vec![(0.0, 0.0); n];
+    let mut v_spectrum = vec![(0.0, 0.0); n];
+    for k in first..steps {
+        i_spectrum[k - first].0 = current[k];
+        // The current is the average over the step, so compare it with the
+        // voltage half way through
+        v_spectrum[k - first].0 = (voltage[k] + voltage[k + 1]) / 2.0 - rest;
+    }
+    fft(&mut i_spectrum);
+    fft(&mut v_spectrum);
+
+    // Bins are 1 / (n dt) apart, dt in ms
+    let resolution = 1000.0 / (n as f64 * dt);
+    let (low, high) = (f0.min(f1), f0.max(f1));
+    let mut frequencies = Vec::new();
+    let mut impedances = Vec::new();
+    for k in 1..=n / 2 {
+        let f = k as f64 * resolution;
+        if f < low || f > high {
+            continue;
+        }
+        let (v, i) = (v_spectrum[k], i_spectrum[k]);
+        frequencies.push(f);
+        impedances.push(Impedance { re: v.0, im: v.1 }.div(Impedance { re: i.0, im: i.1 }));
+    }
+    let magnitudes: Vec<f64> = impedances.iter().map(|z| z.magnitude()).collect();
+    let Some(peak) = (0..magnitudes.len()).max_by(|&a, &b| magnitudes[a].total_cmp(&magnitudes[b]))
+    else {
+        return Err(format!(
+            "The run resolves frequencies {} Hz apart, none between {} and {} Hz",
+            resolution, low, high
+        ));
+    };
+    Ok(ImpedanceProfile {
+        resonance_frequency: frequencies[peak],
+        q: magnitudes[peak] / magnitudes[0],
+        frequencies,
+        impedances,
+    })
+}
+
+/// Highest voltage of every compartment between `window.0` and
+/// `window.1` ms of the run, both included, indexed like
+/// `SimulationResult::voltages`. NaN for the dummy root and for
+/// compartments the run did not record. The window must hold at least one
+/// sample.
+pub fn peak_depolarization(
+    result: &SimulationResult,
+    window: (f64, f64),
+) -> Result<Vec<f64>, String> {
+    let (t0, t1) = window;
+    let steps = result
+        .voltages
+        .iter()
+        .map(|trace| trace.len().saturating_sub(1))
+        .max()
+        .unwrap_or(0);
+    let end = steps as f64 * result.dt;
+    if !(t0 >= 0.0 && t0 <= t1 && t1.is_finite()) {
+        return Err(format!("Window {} to {} ms is not a time span", t0, t1));
+    }
+    let first = (t0 / result.dt - 1e-9).ceil() as usize;
+    let last = ((t1 / result.dt + 1e-9).floor() as usize).min(steps);
+    if first > last {
+        return Err(format!(
+            "Window {} to {} ms holds no sample of the {} ms run",
+            t0, t1, end
+        ));
+    }
+    Ok(result
+        .voltages
+        .iter()
+        .enumerate()
+        .map(|(i, trace)| match trace.get(first..=last) {
+            Some(samples) if i > 0 => samples.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            _ => f64::NAN,
+        })
+        .collect())
+}
+
+/// How much of a spike at `soma_idx` reaches every compartment: its peak
+/// over the run minus its voltage at the start, over the same for the
+/// soma, indexed like `SimulationResult::voltages`. NaN for the dummy root
+/// and for compartments the run did not record. The run should start at
+/// rest, and the soma must have been recorded and depolarized.
+pub fn backpropagation_efficacy(
+    result: &SimulationResult,
+    soma_idx: usize,
+) -> Result<Vec<f64>, String> {
+    let rise = |trace: &Vec<f64>| {
+        trace.iter().copied().fold(f64::NEG_INFINITY, f64::max)
+            - trace.first().copied().unwrap_or(f64::NAN)
+    };
+    let soma = result
+        .voltages
+        .get(soma_idx)
+        .filter(|trace| soma_idx > 0 && !trace.is_empty())
+        .ok_or_else(|| format!("Run did not record compartment {}", soma_idx))?;
+    let soma_rise = rise(soma);
+    if soma_rise.is_nan() || soma_rise <= 0.0 {
+        return Err(format!(
+            "Compartment {} never depolarized past its start",
+            soma_idx
+        ));
+    }
+    Ok(result
+        .voltages
+        .iter()
+        .enumerate()
+        .map(|(i, trace)| match i > 0 && !trace.is_empty() {
+            true => rise(trace) / soma_rise,
+            false => f64::NAN,
+        })
+        .collect())
+}
+
+/// Membrane current of a run summed over spatial bins, see
+/// `binned_membrane_current`
+#[derive(Debug, Clone, PartialEq)]
+pub struct BinnedCurrent {
+    /// Increasing, in µm, one more than there are bins
+    pub edges: Vec<f64>,
+    /// Per bin, the net membrane current of its compartments at the start
+    /// and after every step, in nA, like `SimulationResult::traces`
+    pub currents: Vec<Vec<f64>>,
+    /// Compartments whose centre lies outside the edges, in index order
+    pub unassigned: Vec<usize>,
+}
+
+/// Sums the membrane current of every compartment into the bin its centre,
+/// halfway between its proximal and distal ends, falls in along `axis`, 0,
+/// 1 or 2 for x, y or z. Bins take their lower edge and the last also its
+/// upper one. The run must have recorded `comp[idx].i_membrane` of every
+/// compartment, see `Simulation::record_membrane_currents`, and the model
+/// must be the one it ran.
+pub fn binned_membrane_current(
+    result: &SimulationResult,
+    compartments: &Compartments,
+    axis: usize,
+    bin_edges: &[f64],
+) -> Result<BinnedCurrent, String> {
+    if axis > 2 {
+        return Err(format!("Axis must be 0, 1 or 2, got {}", axis));
+    }
+    if bin_edges.len() < 2
+        || bin_edges.iter().any(|e| !e.is_finite())
+        || bin_edges.windows(2).any(|w| w[0] >= w[1])
+    {
+        return Err(format!(
+            "Bin edges must be at least two increasing finite values, got {:?}",
+            bin_edges
+        ));
+    }
+    let traces: HashMap<&str, &Vec<f64>> = result
+        .traces
+        .iter()
+        .map(|(path, trace)| (path.as_str(), trace))
+        .collect();
+    let samples = result.traces.first().map_or(0, |(_, trace)| trace.len());
+    let mut currents = vec![vec![0.0; samples]; bin_edges.len() - 1];
+    let mut unassigned = Vec::new();
+    for (idx, c) in compartments.components.iter().enumerate().skip(1) {
+        let trace = traces
+            .get(format!("comp[{}].i_membrane", idx).as_str())
+            .ok_or_else(|| {
+                format!(
+                    "Run did not record the membrane current of compartment {}",
+                    idx
+                )
+            })?;
+        if trace.len() != samples {
+            return Err(format!(
+                "Membrane current of compartment {} has {} samples, not {}",
+                idx,
+                trace.len(),
+                samples
+            ));
+        }
+        let centre = (c.proximal[axis] + c.distal[axis]) / 2.0;
+        let last = bin_edges.len() - 1;
+        let bin = match bin_edges.partition_point(|&e| e <= centre) {
+            0 => None,
+            k if k <= last => Some(k - 1),
+            _ if centre == bin_edges[last] => Some(last - 1),
+            _ => None,
+        };
+        match bin {
+            Some(k) => currents[k]
+                .iter_mut()
+                .zip(trace.iter())
+                .for_each(|(sum, i)| *sum += i),
+            None => unassigned.push(idx),
+        }
+    }
+    Ok(BinnedCurrent {
+        edges: bin_edges.to_vec(),
+        currents,
+        unassigned,
+    })
+}
+
+/// One branch point checked against Rall's 3/2 power rule. Diameters in µm.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BranchPointReport {
+    pub node_id: u64,
+    pub structure: StructureIdentifier,
+    /// Diameter at the branch point itself
+    pub parent_diam: f64,
+    /// Diameters of the first node of each daughter, in child order
+    pub child_diams: Vec<f64>,
+    /// `Σ child_diam^(3/2) / parent_diam^(3/2)`, 1 when the rule holds
+    pub ratio: f64,
+}
+
+/// Ratios of one structure type, see `rall_summary`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RallSummary {
+    pub count: usize,
+    pub mean: f64,
+    pub median: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+/// Ratios counted as close enough to 1, both ends included
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RallBand {
+    pub low: f64,
+    pub high: f64,
+}
+
+impl Default for RallBand {
+    fn default() -> Self {
+        RallBand {
+            low: 0.8,
+            high: 1.25,
+        }
+    }
+}
+
+impl RallBand {
+    pub fn contains(&self, ratio: f64) -> bool {
+        (self.low..=self.high).contains(&ratio)
+    }
+}
+
+/// Every node with two or more children, in node order, as read from
+/// `parent_child_map` (the root's entry for itself is skipped). Somata are
+/// left out: they are not cables, so the rule says nothing about them.
+pub fn rall_ratios(
+    nodes: &[Node],
+    parent_child_map: &HashMap<u64, Vec<u64>>,
+) -> Vec<BranchPointReport> {
+    let by_id: HashMap<u64, &Node> = nodes.iter().map(|n| (n.node_id, n)).collect();
+    nodes
+        .iter()
+        .filter(|n| n.structured_identifier != StructureIdentifier::Soma)
+        .filter_map(|node| {
+            let child_diams: Vec<f64> = parent_child_map
+                .get(&node.node_id)?
+                .iter()
+                .filter(|&&c| c != node.node_id)
+                .filter_map(|c| by_id.get(c))
+                .map(|c| 2.0 * c.radius)
+                .collect();
+            if child_diams.len() < 2 {
+                return None;
+            }
+            let parent_diam = 2.0 * node.radius;
+            let ratio =
+                child_diams.iter().map(|d| d.powf(1.5)).sum::<f64>() / parent_diam.powf(1.5);
+            Some(BranchPointReport {
+                node_id: node.node_id,
+                structure: node.structured_identifier,
+                parent_diam,
+                child_diams,
+                ratio,
+            })
+        })
+        .collect()
+}
+
+/// Distribution of the ratios per structure type of the branch point. Every
+/// report lands in exactly one entry.
+pub fn rall_summary(reports: &[BranchPointReport]) -> BTreeMap<StructureIdentifier, RallSummary> {
+    let mut by_type: BTreeMap<StructureIdentifier, Vec<f64>> = BTreeMap::new();
+    for report in reports {
+        by_type
+            .entry(report.structure)
+            .or_default()
+            .push(report.ratio);
+    }
+    by_type
+        .into_iter()
+        .map(|(structure, mut ratios)| {
+            let n = ratios.len();
+            let summary = RallSummary {
+                count: n,
+                mean: ratios.iter().sum::<f64>() / n as f64,
+                median: median(&mut ratios).unwrap_or(f64::NAN),
+                min: ratios[0],
+                max: ratios[n - 1],
+            };
+            (structure, summary)
+        })
+        .collect()
+}
+
+/// Sorts `values` and returns their median, None if there are none
+pub(crate) fn median(values: &mut [f64]) -> Option<f64> {
+    values.sort_by(f64::total_cmp);
+    let n = values.len();
+    match n {
+        0 => None,
+        _ if n % 2 == 1 => Some(values[n / 2]),
+        _ => Some((values[n / 2 - 1] + values[n / 2]) / 2.0),
+    }
+}
+
+/// One junction compartment of a model, loaded by its daughters. Conductances
+/// in nS.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JunctionReport {
+    /// Index into `components`
+    pub idx: usize,
+    pub structure: StructureIdentifier,
+    /// Input conductance of a semi-infinite cable continuing the junction
+    pub parent_conductance: f64,
+    /// The same for each daughter, in child order
+    pub child_conductances: Vec<f64>,
+    /// Daughter over parent conductance, 1 when impedances match
+    pub ratio: f64,
+}
+
+/// `rall_ratios` with the passive parameters in: each cable loads as a
+/// semi-infinite one, `G∞ = (π/2) d^(3/2) sqrt(g / Ra)`, with the axial
+/// resistivity and leak conductance of its own compartment. With the same
+/// parameters everywhere this reduces to the geometric ratio.
+pub fn junction_load_ratios(compartments: &Compartments) -> Vec<JunctionReport> {
+    let c = &compartments.components;
+    let load = |idx: usize| {
+        let comp = &c[idx];
+        let diam_cm = comp.diam * 1e-4;
+        // S to nS
+        PI / 2.0
+            * diam_cm.powf(1.5)
+            * (comp.channel.conductance / comp.channel.resistance).sqrt()
+            * 1e9
+    };
+    (2..c.len())
+        .filter(|&idx| {
+            c[idx].children_idxs.len() >= 2 && c[idx].structure != StructureIdentifier::Soma
+        })
+        .map(|idx| {
+            let parent_conductance = load(idx);
+            let child_conductances: Vec<f64> = c[idx]
+                .children_idxs
+                .iter()
+                .map(|&k| load(k as usize))
+                .collect();
+            let ratio = child_conductances.iter().sum::<f64>() / parent_conductance;
+            JunctionReport {
+                idx,
+                structure: c[idx].structure,
+                parent_conductance,
+                child_conductances,
+                ratio,
+            }
+        })
+        .collect()
+}
+
+/// A contiguous stretch where the arbors of two cells come within the
+/// threshold of each other. Segments are named by their child node, as in
+/// `Morphometry`, and lengths are in µm.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Apposition {
+    /// Every pair of segments within the threshold, first cell first, sorted
+    pub pairs: Vec<(u64, u64)>,
+    /// Segments of the first cell taking part, sorted
+    pub a_segments: Vec<u64>,
+    pub b_segments: Vec<u64>,
+    /// The closest points of the stretch, on either cell
+    pub a_point: [f64; 3],
+    pub b_point: [f64; 3],
+    pub distance: f64,
+    /// Cable length of `a_segments`
+    pub a_extent: f64,
+    pub b_extent: f64,
+}
+
+/// A straight piece of cable from a node's parent to the node
+struct Segment {
+    id: u64,
+    parent: u64,
+    from: Vec3,
+    to: Vec3,
+}
+
+impl Segment {
+    fn length(&self) -> f64 {
+        geometry::norm(geometry::sub(self.to, self.from))
+    }
+}
+
+fn segments(skeleton: &Skeleton, structure: StructureIdentifier) -> Vec<Segment> {
+    let by_id: HashMap<u64, &Node> = skeleton.nodes.iter().map(|n| (n.node_id, n)).collect();
+    let at = |n: &Node| [n.x_pos, n.y_pos, n.z_pos];
+    skeleton
+        .nodes
+        .iter()
+        .filter(|n| n.structured_identifier == structure && n.parent_id != n.node_id)
+        .filter_map(|n| {
+            let parent = by_id.get(&n.parent_id)?;
+            Some(Segment {
+                id: n.node_id,
+                parent: n.parent_id,
+                from: at(parent),
+                to: at(n),
+            })
+        })
+        .collect()
+}
+
+/// Uniform grid over segment bounding boxes, keyed by cell so only occupied
+/// cells cost memory
+struct Grid {
+    cell: f64,
+    cells: HashMap<[i64; 3], Vec<usize>>,
+}
+
+impl Grid {
+    fn new(segments: &[Segment], cell: f64) -> Grid {
+        let mut grid = Grid {
+            cell,
+            cells: HashMap::new(),
+        };
+        for (k, segment) in segments.iter().enumerate() {
+            for key in grid.keys(segment, 0.0) {
+                grid.cells.entry(key).or_default().push(k);
+            }
+        }
+        grid
+    }
+
+    /// Cells overlapping the bounding box of `segment` grown by `margin`
+    fn keys(&self, segment: &Segment, margin: f64) -> Vec<[i64; 3]> {
+        let lo: [i64; 3] = std::array::from_fn(|k| {
+            ((segment.from[k].min(segment.to[k]) - margin) / self.cell).floor() as i64
+        });
+        let hi: [i64; 3] = std::array::from_fn(|k| {
+            ((segment.from[k].max(segment.to[k]) + margin) / self.cell).floor() as i64
+        });
+        let mut keys = Vec::new();
+        for x in lo[0]..=hi[0] {
+            for y in lo[1]..=hi[1] {
+                for z in lo[2]..=hi[2] {
+                    keys.push([x, y, z]);
+                }
+            }
+        }
+        keys
+    }
+}
+
+/// Segment pairs closer than `threshold`, with their closest points
+struct Hit {
+    a: usize,
+    b: usize,
+    a_point: Vec3,
+    b_point: Vec3,
+    distance: f64,
+}
+
+/// Segments of each cell touching each other at a node: parent, children
+/// and siblings
+fn neighbours(segments: &[Segment]) -> Vec<Vec<usize>> {
+    let index: HashMap<u64, usize> = segments
+        .iter()
+        .enumerate()
+        .map(|(k, s)| (s.id, k))
+        .collect();
+    let mut children: HashMap<u64, Vec<usize>> = HashMap::new();
+    for (k, s) in segments.iter().enumerate() {
+        children.entry(s.parent).or_default().push(k);
+    }
+    segments
+        .iter()
+        .map(|s| {
+            let mut near: Vec<usize> = index.get(&s.parent).copied().into_iter().collect();
+            near.extend(children.get(&s.id).into_iter().flatten());
+            near.extend(
+                children[&s.parent]
+                    .iter()
+                    .filter(|&&k| segments[k].id != s.id),
+            );
+            near
+        })
+        .collect()
+}
+
+/// Representative of `h` in a union-find forest, halving paths on the way
+fn find(root: &mut [usize], mut h: usize) -> usize {
+    while root[h] != h {
+        root[h] = root[root[h]];
+        h = root[h];
+    }
+    h
+}
+
+/// Close appositions between segments of `cells.0` of type `type_filter.0`
+/// and segments of `cells.1` of type `type_filter.1`, e.g. the axon of one
+/// cell and the dendrites of another, both in one frame. A segment's type is
+/// its child node's. Pairs of segments within `threshold` µm of each other
+/// that share or neighbour both their segments merge into one apposition, so
+/// two cables running alongside count once. Candidates come from a grid over
+/// the second cell's segments, and only they are measured exactly.
+/// Sorted by their first pair; none for a negative or infinite threshold.
+pub fn appositions(
+    cells: (&Skeleton, &Skeleton),
+    type_filter: (StructureIdentifier, StructureIdentifier),
+    threshold: f64,
+) -> Vec<Apposition> {
+    let a = segments(cells.0, type_filter.0);
+    let b = segments(cells.1, type_filter.1);
+    if a.is_empty() || b.is_empty() || !threshold.is_finite() || threshold < 0.0 {
+        return Vec::new();
+    }
+    // Cells about as large as a segment keep both the lists per cell and
+    // the cells per segment short
+    let mean_length = b.iter().map(Segment::length).sum::<f64>() / b.len() as f64;
+    let cell = [mean_length, threshold, 1e-3]
+        .into_iter()
+        .fold(0.0, f64::max);
+    let grid = Grid::new(&b, cell);
+
+    let mut hits = Vec::new();
+    let mut seen = HashSet::new();
+    for (i, segment) in a.iter().enumerate() {
+        seen.clear();
+        for key in grid.keys(segment, threshold) {
+            for &j in grid.cells.get(&key).into_iter().flatten() {
+                if !seen.insert(j) {
+                    continue;
+                }
+                let other = &b[j];
+                let (s, t) =
+                    geometry::closest_on_segments(segment.from, segment.to, other.from, other.to);
+                let a_point = geometry::lerp(segment.from, segment.to, s);
+                let b_point = geometry::lerp(other.from, other.to, t);
+                let distance = geometry::norm(geometry::sub(a_point, b_point));
+                if distance <= threshold {
+                    hits.push(Hit {
+                        a: i,
+                        b: j,
+                        a_point,
+                        b_point,
+                        distance,
+                    });
+                }
+            }
+        }
+    }
+
+    // Union-find over hits whose segments coincide or neighbour on both
+    // cells
+    let (a_near, b_near) = (neighbours(&a), neighbours(&b));
+    let mut by_pair: HashMap<(usize, usize), usize> = HashMap::new();
+    for (h, hit) in hits.iter().enumerate() {
+        by_pair.insert((hit.a, hit.b), h);
+    }
+    let mut root: Vec<usize> = (0..hits.len()).collect();
+    for (h, hit) in hits.iter().enumerate() {
+        let a_side = std::iter::once(hit.a).chain(a_near[hit.a].iter().copied());
+        for i in a_side {
+            let b_side = std::iter::once(hit.b).chain(b_near[hit.b].iter().copied());
+            for j in b_side {
+                if let Some(&other) = by_pair.get(&(i, j)) {
+                    let (x, y) = (find(&mut root, h), find(&mut root, other));
+                    root[x] = y;
+                }
+            }
+        }
+    }
+
+    let mut zones: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for h in 0..hits.len() {
+        let r = find(&mut root, h);
+        zones.entry(r).or_default().push(h);
+    }
+    let mut out: Vec<Apposition> = zones
+        .into_values()
+        .map(|members| {
+            let closest = members
+                .iter()
+                .map(|&h| &hits[h])
+                .min_by(|x, y| x.distance.total_cmp(&y.distance))
+                .expect("zones are never empty");
+            let mut pairs: Vec<(u64, u64)> = members
+                .iter()
+                .map(|&h| (a[hits[h].a].id, b[hits[h].b].id))
+                .collect();
+            pairs.sort();
+            let a_members: BTreeSet<usize> = members.iter().map(|&h| hits[h].a).collect();
+            let b_members: BTreeSet<usize> = members.iter().map(|&h| hits[h].b).collect();
+            let mut a_segments: Vec<u64> = a_members.iter().map(|&i| a[i].id).collect();
+            let mut b_segments: Vec<u64> = b_members.iter().map(|&j| b[j].id).collect();
+            a_segments.sort();
+            b_segments.sort();
+            Apposition {
+                pairs,
+                a_segments,
+                b_segments,
+                a_point: closest.a_point,
+                b_point: closest.b_point,
+                distance: closest.distance,
+                a_extent: a_members.iter().map(|&i| a[i].length()).sum(),
+                b_extent: b_members.iter().map(|&j| b[j].length()).sum(),
+            }
+        })
+        .collect();
+    out.sort_by(|x, y| x.pairs[0].cmp(&y.pairs[0]));
+    out
+}
+
+/// Appositions of one ordered pair of files of a `Dataset`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PairAppositions {
+    pub a: PathBuf,
+    pub b: PathBuf,
+    pub appositions: Vec<Apposition>,
+}
+
+/// `appositions` between every ordered pair of distinct files of
+/// `dataset`, so both directions of each pair. Errs before measuring
+/// anything when that is more than `max_pairs` pairs or a file cannot be
+/// read; coordinates are scaled as the SWC header asks.
+pub fn dataset_appositions(
+    dataset: &Dataset,
+    type_filter: (StructureIdentifier, StructureIdentifier),
+    threshold: f64,
+    max_pairs: usize,
+) -> Result<Vec<PairAppositions>, String> {
+    let n = dataset.paths.len();
+    let pairs = n * n.saturating_sub(1);
+    if pairs > max_pairs {
+        return Err(format!(
+            "{} files make {} pairs, over the limit of {}",
+            n, pairs, max_pairs
+        ));
+    }
+    let options = ReaderOptions {
+        apply_scale: true,
+        collect_stats: false,
+        ..ReaderOptions::default()
+    };
+    let skeletons = dataset
+        .paths
+        .iter()
+        .map(|path| {
+            read_source(path)
+                .map_err(|e| e.to_string())
+                .and_then(|data| swc_reader_from_bytes(&data, &options).map_err(|e| e.to_string()))
+                .map_err(|e| format!("{}: {}", path.display(), e))
+        })
+        .collect::<Result<Vec<Skeleton>, String>>()?;
+    let mut out = Vec::with_capacity(pairs);
+    for (i, a) in skeletons.iter().enumerate() {
+        for (j, b) in skeletons.iter().enumerate() {
+            if i != j {
+                out.push(PairAppositions {
+                    a: dataset.paths[i].clone(),
+                    b: dataset.paths[j].clone(),
+                    appositions: appositions((a, b), type_filter, threshold),
+                });
+            }
+        }
+    }
+    Ok(out)
+}
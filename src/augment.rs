@@ -0,0 +1,128 @@
+//! Seeded data augmentation for training models on morphologies. Every
+//! function returns a new skeleton and leaves its input alone.
+
+use std::collections::{HashMap, HashSet};
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+
+use crate::features::{by_id, euclidean, root_of};
+use crate::swc_reader::{NodeFlags, Skeleton};
+
+/// Standard normal sample via Box-Muller
+pub(crate) fn normal(rng: &mut StdRng) -> f64 {
+    let u1: f64 = 1.0 - rng.random::<f64>();
+    let u2: f64 = rng.random();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+}
+
+/// Moves every node by Gaussian noise of standard deviation `amplitude` per
+/// axis. The noise is smoothed along the tree: a node's displacement keeps
+/// `exp(-segment_length / correlation_length)` of its parent's, so branches
+/// bend rather than turning into fuzz. Topology is untouched.
+pub fn jitter_coordinates(
+    skeleton: &Skeleton,
+    amplitude: f64,
+    correlation_length: f64,
+    seed: u64,
+) -> Result<Skeleton, String> {
+    let root = root_of(skeleton)?;
+    let original = by_id(skeleton);
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut displacement: HashMap<u64, [f64; 3]> = HashMap::new();
+    for id in skeleton.subtree(root) {
+        let node = original[&id];
+        let white = [normal(&mut rng), normal(&mut rng), normal(&mut rng)];
+        let d = if id == root {
+            white.map(|w| amplitude * w)
+        } else {
+            let parent = original[&node.parent_id];
+            let rho = if correlation_length > 0.0 {
+                (-euclidean(node, parent) / correlation_length).exp()
+            } else {
+                0.0
+            };
+            let inherited = displacement[&parent.node_id];
+            let fresh = (1.0 - rho * rho).sqrt() * amplitude;
+            std::array::from_fn(|k| rho * inherited[k] + fresh * white[k])
+        };
+        displacement.insert(id, d);
+    }
+
+    let mut out = skeleton.edit();
+    for node in out.nodes_mut().iter_mut() {
+        let d = displacement[&node.node_id];
+        node.x_pos += d[0];
+        node.y_pos += d[1];
+        node.z_pos += d[2];
+        node.flags |= NodeFlags::COORD_JITTERED;
+    }
+    out.commit()
+}
+
+/// Multiplies every radius by a factor drawn uniformly from `range`
+pub fn scale_radii(skeleton: &Skeleton, range: (f64, f64), seed: u64) -> Result<Skeleton, String> {
+    let (lo, hi) = range;
+    if !(lo > 0.0 && lo <= hi && hi.is_finite()) {
+        return Err(format!("Invalid radius scale range {:?}", range));
+    }
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut out = skeleton.edit();
+    for node in out.nodes_mut().iter_mut() {
+        node.radius *= rng.random_range(lo..=hi);
+        node.flags |= NodeFlags::RADIUS_SCALED;
+    }
+    out.commit()
+}
+
+/// Removes `fraction` of the terminal branches (rounded to the nearest
+/// count), picked at random. A terminal branch runs from a branch point, or
+/// the root, to a tip with no branch point in between; its proximal node
+/// stays and is flagged `BRANCH_PRUNED`. The result is renumbered like any
+/// processed skeleton.
+pub fn drop_terminal_branches(
+    skeleton: &Skeleton,
+    fraction: f64,
+    seed: u64,
+) -> Result<Skeleton, String> {
+    if !(0.0..=1.0).contains(&fraction) {
+        return Err(format!("Fraction must be within [0, 1], got {}", fraction));
+    }
+    let root = root_of(skeleton)?;
+    let mut terminal: Vec<Vec<u64>> = skeleton
+        .subtree(root)
+        .into_iter()
+        .filter(|&id| id != root && skeleton.children_of(id).is_empty())
+        .map(|tip| skeleton.branch_path(tip))
+        .collect::<Result<_, _>>()?;
+    let count = (fraction * terminal.len() as f64).round() as usize;
+    terminal.shuffle(&mut StdRng::seed_from_u64(seed));
+
+    let mut out = skeleton.edit();
+    let mut removed = HashSet::new();
+    let mut pruned = HashSet::new();
+    for path in terminal.into_iter().take(count) {
+        pruned.insert(path[0]);
+        removed.extend(path[1..].iter().copied());
+    }
+    let nodes = out.nodes_mut();
+    nodes.retain(|n| !removed.contains(&n.node_id));
+    for node in nodes.iter_mut().filter(|n| pruned.contains(&n.node_id)) {
+        node.flags |= NodeFlags::BRANCH_PRUNED;
+    }
+    for id in &removed {
+        out.child_parent_map_mut().remove(id);
+        out.extras_mut().remove(id);
+    }
+    let parent_child_map = out.parent_child_map_mut();
+    for id in &removed {
+        parent_child_map.remove(id);
+    }
+    for children in parent_child_map.values_mut() {
+        children.retain(|c| !removed.contains(c));
+    }
+    parent_child_map.retain(|_, children| !children.is_empty());
+    out.finalize()?;
+    out.commit()
+}
@@ -0,0 +1,346 @@
+//! Command line front end.
+//!
+//! ```text
+//! compartment-rs standardize <input_dir> <output_dir> [--options <recipe>] [--threads <n>]
+//! compartment-rs compare <simulated> <reference> [--json <report>] [--rms <mV>] [--max <mV>]
+//!     [--threshold <mV>] [--spike-window <ms>] [--spike-tolerance <ms>]
+//! compartment-rs inspect <swc> [--tree] [--depth <n>] [--longest <n>] [--svg <path>] [--passive]
+//! compartment-rs run <experiment.toml> [--output <dir>]
+//! compartment-rs validate <experiment.toml>
+//! ```
+//!
+//! `standardize` prints one line per file, `compare` a summary per trace;
+//! `compare` takes two `.csv` or `.npy` traces, or two directories of them
+//! paired by name. An `<output_dir>` ending in `.bundle` makes `standardize`
+//! write one bundle file there instead. Both exit with 1 if any file failed; files a recipe's
+//! `quarantine_below` holds back are listed but are not failures. `inspect` prints a
+//! line of counts and the QC score for one file, with `--tree` its branches as an indented
+//! tree and with `--svg` also writes a dendrogram. `--passive` adds the
+//! passive snapshot to the line, see `compartment_rs::snapshot`. `run` simulates an
+//! experiment file, see `compartment_rs::experiment`, and prints the files
+//! it wrote; `validate` only checks it. Both print every problem as
+//! `file:line:column: key: message` and exit with 1 if there were any.
+
+use std::path::Path;
+use std::process::ExitCode;
+
+use compartment_rs::bundle::is_bundle;
+use compartment_rs::experiment::{ConfigError, Experiment};
+use compartment_rs::qc::{QcReport, QcRubric, score_skeleton};
+use compartment_rs::render::AsciiOptions;
+use compartment_rs::snapshot::{PassiveSnapshot, quick_passive_snapshot};
+use compartment_rs::standardize::{Dataset, Pipeline, StandardizeOptions};
+use compartment_rs::validation::{
+    ComparisonOptions, ReferenceTrace, compare_dirs, compare_traces, reports_to_json,
+};
+use compartment_rs::{Compartments, Morphometry, ReaderOptions, swc_reader};
+
+const USAGE: &str = "Usage: compartment-rs standardize <input_dir> <output_dir> [--options <recipe>] [--threads <n>]
+       compartment-rs compare <simulated> <reference> [--json <report>] [--rms <mV>] [--max <mV>]
+           [--threshold <mV>] [--spike-window <ms>] [--spike-tolerance <ms>]
+       compartment-rs inspect <swc> [--tree] [--depth <n>] [--longest <n>] [--svg <path>] [--passive]
+       compartment-rs run <experiment.toml> [--output <dir>]
+       compartment-rs validate <experiment.toml>";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("standardize") => standardize(&args[1..]),
+        Some("compare") => compare(&args[1..]),
+        Some("inspect") => inspect(&args[1..]),
+        Some("run") => run(&args[1..]),
+        Some("validate") => validate(&args[1..]),
+        _ => Err(USAGE.to_owned()),
+    };
+    match result {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::from(2)
+        }
+    }
+}
+
+fn standardize(args: &[String]) -> Result<ExitCode, String> {
+    let mut positional = Vec::new();
+    let mut options = StandardizeOptions::default();
+    let mut threads = 0;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--options" => {
+                let path = args.next().ok_or(USAGE)?;
+                let text = std::fs::read_to_string(path)
+                    .map_err(|e| format!("Could not read {}: {}", path, e))?;
+                options = StandardizeOptions::parse(&text).map_err(|e| e.to_string())?;
+            }
+            "--threads" => {
+                threads = args
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .ok_or("--threads needs a number")?;
+            }
+            _ => positional.push(arg),
+        }
+    }
+    let [input, output] = positional[..] else {
+        return Err(USAGE.to_owned());
+    };
+
+    let dataset = Dataset::from_dir(input).map_err(|e| e.to_string())?;
+    let pipeline = Pipeline::standardize(options).with_threads(threads);
+    let report = if is_bundle(Path::new(output)) {
+        pipeline
+            .run_to_bundle(&dataset, output)
+            .map_err(|e| e.to_string())?
+    } else {
+        pipeline.run(&dataset, output).map_err(|e| e.to_string())?
+    };
+    for file in &report.files {
+        match (&file.output, &file.error, &file.qc) {
+            (Some(out), _, _) => println!(
+                "ok {} -> {}: {}",
+                file.source.display(),
+                out.display(),
+                if file.operations.is_empty() {
+                    "unchanged".to_owned()
+                } else {
+                    file.operations.join("; ")
+                }
+            ),
+            (None, None, Some(qc)) if file.quarantined => {
+                println!("quarantined {}: {}", file.source.display(), qc_line(qc))
+            }
+            (None, error, _) => println!(
+                "failed {}: {}",
+                file.source.display(),
+                error
+                    .as_ref()
+                    .map_or("unknown error".to_owned(), |e| e.to_string())
+            ),
+        }
+    }
+    Ok(if report.failures().next().is_some() {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+fn compare(args: &[String]) -> Result<ExitCode, String> {
+    let mut positional = Vec::new();
+    let mut options = ComparisonOptions::default();
+    let mut json = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let target = match arg.as_str() {
+            "--json" => {
+                json = Some(args.next().ok_or(USAGE)?);
+                continue;
+            }
+            "--rms" => &mut options.rms_tolerance,
+            "--max" => &mut options.max_tolerance,
+            "--threshold" => &mut options.spike_threshold,
+            "--spike-window" => &mut options.spike_window,
+            "--spike-tolerance" => &mut options.spike_time_tolerance,
+            _ => {
+                positional.push(arg);
+                continue;
+            }
+        };
+        *target = args
+            .next()
+            .and_then(|x| x.parse().ok())
+            .ok_or_else(|| format!("{} needs a number", arg))?;
+    }
+    let [simulated, reference] = positional[..] else {
+        return Err(USAGE.to_owned());
+    };
+
+    let reports = if std::path::Path::new(simulated).is_dir() {
+        compare_dirs(simulated, reference, &options)?
+    } else {
+        let name = std::path::Path::new(simulated)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or(simulated);
+        vec![compare_traces(
+            name,
+            &ReferenceTrace::load(simulated)?,
+            &ReferenceTrace::load(reference)?,
+            &options,
+        )?]
+    };
+    for report in &reports {
+        print!("{}", report.summary());
+    }
+    if let Some(path) = json {
+        std::fs::write(path, reports_to_json(&reports))
+            .map_err(|e| format!("Could not write {}: {}", path, e))?;
+    }
+    Ok(if reports.iter().all(|r| r.passed()) {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
+
+fn inspect(args: &[String]) -> Result<ExitCode, String> {
+    let mut positional = Vec::new();
+    let mut tree = false;
+    let mut passive = false;
+    let mut svg = None;
+    let mut options = AsciiOptions::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let target = match arg.as_str() {
+            "--tree" => {
+                tree = true;
+                continue;
+            }
+            "--passive" => {
+                passive = true;
+                continue;
+            }
+            "--svg" => {
+                svg = Some(args.next().ok_or(USAGE)?);
+                continue;
+            }
+            "--depth" => &mut options.max_depth,
+            "--longest" => &mut options.longest_paths,
+            _ => {
+                positional.push(arg);
+                continue;
+            }
+        };
+        *target = Some(
+            args.next()
+                .and_then(|n| n.parse().ok())
+                .ok_or_else(|| format!("{} needs a number", arg))?,
+        );
+    }
+    let [path] = positional[..] else {
+        return Err(USAGE.to_owned());
+    };
+
+    let skeleton = swc_reader(path, &ReaderOptions::default()).map_err(|e| e.to_string())?;
+    let morphometry = Morphometry::new(&skeleton.nodes);
+    let snapshot = if passive {
+        let compartments = Compartments::from_skeleton(skeleton.clone());
+        format!(
+            ", {}",
+            passive_line(&quick_passive_snapshot(&compartments)?)
+        )
+    } else {
+        String::new()
+    };
+    println!(
+        "{}: {} nodes, {:.1} µm of cable, max branching degree {}, {}{}",
+        path,
+        skeleton.nodes.len(),
+        morphometry.total_length(),
+        morphometry.max_branching_degree(),
+        qc_line(&score_skeleton(&skeleton, &QcRubric::default())),
+        snapshot
+    );
+    if tree {
+        print!("{}", skeleton.render_ascii(&options)?);
+    }
+    if let Some(svg) = svg {
+        skeleton.render_dendrogram_svg(svg)?;
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// The score and the checks that flagged anything, e.g.
+/// `qc score 0.917 (pass; flagged spacing_gaps)`
+fn qc_line(qc: &QcReport) -> String {
+    let flagged: Vec<&str> = qc.failed_checks().iter().map(|c| c.name()).collect();
+    format!(
+        "qc score {:.3} ({}{})",
+        qc.score,
+        if qc.passed { "pass" } else { "fail" },
+        if flagged.is_empty() {
+            String::new()
+        } else {
+            format!("; flagged {}", flagged.join(", "))
+        }
+    )
+}
+
+/// e.g. `input resistance 212.4 MΩ, tau 33.33 ms, tip attenuation 0.71 0.74 0.88`
+fn passive_line(snapshot: &PassiveSnapshot) -> String {
+    let tips: Vec<String> = snapshot
+        .tips
+        .iter()
+        .map(|t| format!("{:.2}", t.attenuation))
+        .collect();
+    format!(
+        "input resistance {:.1} MΩ, tau {:.2} ms, tip attenuation {}",
+        snapshot.input_resistance,
+        snapshot.tau,
+        tips.join(" ")
+    )
+}
+
+/// Loads and checks an experiment, printing its problems against `path`
+fn checked_experiment(path: &str) -> Option<Experiment> {
+    let report = |errors: Vec<ConfigError>| {
+        for e in errors {
+            if e.location.is_some() {
+                eprintln!("{}:{}", path, e);
+            } else {
+                eprintln!("{}: {}", path, e);
+            }
+        }
+    };
+    let experiment = Experiment::load(path).map_err(report).ok()?;
+    experiment.validate().map_err(report).ok()?;
+    Some(experiment)
+}
+
+fn run(args: &[String]) -> Result<ExitCode, String> {
+    let mut positional = Vec::new();
+    let mut output = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--output" => output = Some(args.next().ok_or(USAGE)?),
+            _ => positional.push(arg),
+        }
+    }
+    let [path] = positional[..] else {
+        return Err(USAGE.to_owned());
+    };
+
+    let Some(mut experiment) = checked_experiment(path) else {
+        return Ok(ExitCode::FAILURE);
+    };
+    if let Some(output) = output {
+        experiment.output.directory = output.into();
+    }
+    let result = experiment.run()?;
+    for file in experiment.write(&result)? {
+        println!("wrote {}", file.display());
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn validate(args: &[String]) -> Result<ExitCode, String> {
+    let [path] = args else {
+        return Err(USAGE.to_owned());
+    };
+    Ok(match checked_experiment(path) {
+        Some(experiment) => {
+            println!(
+                "ok {}: {} stimuli, {} probes, {} steps",
+                path,
+                experiment.stimuli.len(),
+                experiment.probes.len(),
+                experiment.steps()
+            );
+            ExitCode::SUCCESS
+        }
+        None => ExitCode::FAILURE,
+    })
+}
@@ -0,0 +1,582 @@
+//! Distribution rules for channel densities, written the way papers state
+//! them: `gkbar = 0.02 + 0.01*exp(-dist/100)` on apical dendrites.
+//!
+//! A rule is an expression in a small language, kept as text so a profile
+//! can be saved, shared and quoted in a model description. It is parsed
+//! when added to a `BiophysicsProfile`, so a typo fails there with the
+//! position of the offending character, and evaluated per compartment by
+//! `BiophysicsProfile::apply`.
+//!
+//! Expressions combine numbers, `+ - * / ^`, parentheses and
+//!
+//! - the variables `dist` (path distance from the soma, in µm, as
+//!   `parameter_map("path_distance")`), `diam` (in µm), `branch_order`, and
+//!   `x`, `y`, `z` (the compartment's midpoint, in µm)
+//! - the functions `exp`, `log` (natural), `min`, `max` and `clamp(v, lo, hi)`
+//!
+//! Division by zero, `log` of a non-positive number and results that are
+//! not finite fail the evaluation, naming the compartment.
+
+use std::fmt;
+
+use crate::compartments::Compartments;
+use crate::parameters::set_parameter;
+use crate::swc_reader::StructureIdentifier;
+
+/// What an expression can refer to, for one compartment
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Variables {
+    pub dist: f64,
+    pub diam: f64,
+    pub branch_order: f64,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+const VARIABLES: [&str; 6] = ["dist", "diam", "branch_order", "x", "y", "z"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Function {
+    Exp,
+    Log,
+    Min,
+    Max,
+    Clamp,
+}
+
+impl Function {
+    fn named(name: &str) -> Option<Function> {
+        Some(match name {
+            "exp" => Function::Exp,
+            "log" => Function::Log,
+            "min" => Function::Min,
+            "max" => Function::Max,
+            "clamp" => Function::Clamp,
+            _ => return None,
+        })
+    }
+
+    fn arity(self) -> usize {
+        match self {
+            Function::Exp | Function::Log => 1,
+            Function::Min | Function::Max => 2,
+            Function::Clamp => 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Number(f64),
+    /// Index into `VARIABLES`
+    Variable(usize),
+    Negate(Box<Node>),
+    Binary(char, Box<Node>, Box<Node>),
+    Call(Function, Vec<Node>),
+}
+
+/// Where and why an expression does not parse
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// Of the offending character, counting from 0
+    pub position: usize,
+    pub message: String,
+    pub source: String,
+}
+
+impl fmt::Display for ParseError {
+    /// The message, then the expression with a caret under the position
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at character {}\n  {}\n  {}^",
+            self.message,
+            self.position + 1,
+            self.source,
+            " ".repeat(self.position)
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// A parsed distribution expression
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expression {
+    source: String,
+    root: Node,
+}
+
+impl Expression {
+    pub fn parse(source: &str) -> Result<Expression, ParseError> {
+        let mut parser = Parser {
+            chars: source.chars().collect(),
+            at: 0,
+            source,
+        };
+        let root = parser.sum()?;
+        parser.skip_spaces();
+        if parser.at < parser.chars.len() {
+            return Err(parser.error("Unexpected character"));
+        }
+        Ok(Expression {
+            source: source.to_owned(),
+            root,
+        })
+    }
+
+    /// The text it was parsed from, verbatim
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn evaluate(&self, variables: &Variables) -> Result<f64, String> {
+        let value = evaluate(&self.root, variables)?;
+        if !value.is_finite() {
+            return Err(format!("'{}' gives {}", self.source, value));
+        }
+        Ok(value)
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+fn evaluate(node: &Node, v: &Variables) -> Result<f64, String> {
+    Ok(match node {
+        Node::Number(x) => *x,
+        Node::Variable(i) => [v.dist, v.diam, v.branch_order, v.x, v.y, v.z][*i],
+        Node::Negate(a) => -evaluate(a, v)?,
+        Node::Binary(op, a, b) => {
+            let (a, b) = (evaluate(a, v)?, evaluate(b, v)?);
+            match op {
+                '+' => a + b,
+                '-' => a - b,
+                '*' => a * b,
+                '/' if b == 0.0 => return Err("Division by zero".to_owned()),
+                '/' => a / b,
+                _ => a.powf(b),
+            }
+        }
+        Node::Call(function, args) => {
+            let args = args
+                .iter()
+                .map(|a| evaluate(a, v))
+                .collect::<Result<Vec<f64>, String>>()?;
+            match function {
+                Function::Exp => args[0].exp(),
+                Function::Log if args[0] <= 0.0 => {
+                    return Err(format!("log of non-positive {}", args[0]));
+                }
+                Function::Log => args[0].ln(),
+                Function::Min => args[0].min(args[1]),
+                Function::Max => args[0].max(args[1]),
+                Function::Clamp if args[1] > args[2] => {
+                    return Err(format!(
+                        "clamp bounds {} and {} are the wrong way round",
+                        args[1], args[2]
+                    ));
+                }
+                Function::Clamp => args[0].clamp(args[1], args[2]),
+            }
+        }
+    })
+}
+
+/// Recursive descent over `chars`, lowest precedence first
+struct Parser<'a> {
+    chars: Vec<char>,
+    at: usize,
+    source: &'a str,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> ParseError {
+        ParseError {
+            position: self.at.min(self.chars.len()),
+            message: message.to_owned(),
+            source: self.source.to_owned(),
+        }
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.chars.get(self.at).is_some_and(|c| c.is_whitespace()) {
+            self.at += 1;
+        }
+    }
+
+    /// The next non-space character, without taking it
+    fn peek(&mut self) -> Option<char> {
+        self.skip_spaces();
+        self.chars.get(self.at).copied()
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), ParseError> {
+        if self.peek() != Some(c) {
+            return Err(self.error(&format!("Expected '{}'", c)));
+        }
+        self.at += 1;
+        Ok(())
+    }
+
+    fn sum(&mut self) -> Result<Node, ParseError> {
+        let mut node = self.product()?;
+        while let Some(op @ ('+' | '-')) = self.peek() {
+            self.at += 1;
+            node = Node::Binary(op, Box::new(node), Box::new(self.product()?));
+        }
+        Ok(node)
+    }
+
+    fn product(&mut self) -> Result<Node, ParseError> {
+        let mut node = self.unary()?;
+        while let Some(op @ ('*' | '/')) = self.peek() {
+            self.at += 1;
+            node = Node::Binary(op, Box::new(node), Box::new(self.unary()?));
+        }
+        Ok(node)
+    }
+
+    fn unary(&mut self) -> Result<Node, ParseError> {
+        match self.peek() {
+            Some('-') => {
+                self.at += 1;
+                Ok(Node::Negate(Box::new(self.unary()?)))
+            }
+            Some('+') => {
+                self.at += 1;
+                self.unary()
+            }
+            _ => self.power(),
+        }
+    }
+
+    /// `^` binds tighter than a sign on its left and groups to the right,
+    /// so `-2^2` is -4 and `2^3^2` is 512
+    fn power(&mut self) -> Result<Node, ParseError> {
+        let base = self.atom()?;
+        if self.peek() == Some('^') {
+            self.at += 1;
+            return Ok(Node::Binary('^', Box::new(base), Box::new(self.unary()?)));
+        }
+        Ok(base)
+    }
+
+    fn atom(&mut self) -> Result<Node, ParseError> {
+        match self.peek() {
+            Some('(') => {
+                self.at += 1;
+                let node = self.sum()?;
+                self.expect(')')?;
+                Ok(node)
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => self.number(),
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => self.name(),
+            Some(_) => Err(self.error("Unexpected character")),
+            None => Err(self.error("Unexpected end of expression")),
+        }
+    }
+
+    fn number(&mut self) -> Result<Node, ParseError> {
+        let start = self.at;
+        let digits = |p: &mut Self| {
+            while p.chars.get(p.at).is_some_and(|c| c.is_ascii_digit()) {
+                p.at += 1;
+            }
+        };
+        digits(self);
+        if self.chars.get(self.at) == Some(&'.') {
+            self.at += 1;
+            digits(self);
+        }
+        if matches!(self.chars.get(self.at), Some('e' | 'E')) {
+            self.at += 1;
+            if matches!(self.chars.get(self.at), Some('+' | '-')) {
+                self.at += 1;
+            }
+            if !self.chars.get(self.at).is_some_and(|c| c.is_ascii_digit()) {
+                return Err(self.error("Expected an exponent"));
+            }
+            digits(self);
+        }
+        let text: String = self.chars[start..self.at].iter().collect();
+        text.parse().map(Node::Number).map_err(|_| ParseError {
+            position: start,
+            message: format!("Invalid number '{}'", text),
+            source: self.source.to_owned(),
+        })
+    }
+
+    fn name(&mut self) -> Result<Node, ParseError> {
+        let start = self.at;
+        while self
+            .chars
+            .get(self.at)
+            .is_some_and(|c| c.is_ascii_alphanumeric() || *c == '_')
+        {
+            self.at += 1;
+        }
+        let name: String = self.chars[start..self.at].iter().collect();
+        let at_start = |message: String| ParseError {
+            position: start,
+            message,
+            source: self.source.to_owned(),
+        };
+        if self.peek() != Some('(') {
+            return VARIABLES
+                .iter()
+                .position(|v| *v == name)
+                .map(Node::Variable)
+                .ok_or_else(|| {
+                    at_start(format!(
+                        "Unknown variable '{}'; use one of {}",
+                        name,
+                        VARIABLES.join(", ")
+                    ))
+                });
+        }
+        let function = Function::named(&name).ok_or_else(|| {
+            at_start(format!(
+                "Unknown function '{}'; use exp, log, min, max or clamp",
+                name
+            ))
+        })?;
+        self.at += 1;
+        let mut args = vec![self.sum()?];
+        while self.peek() == Some(',') {
+            self.at += 1;
+            args.push(self.sum()?);
+        }
+        if args.len() != function.arity() {
+            return Err(at_start(format!(
+                "'{}' takes {} arguments, got {}",
+                name,
+                function.arity(),
+                args.len()
+            )));
+        }
+        self.expect(')')?;
+        Ok(Node::Call(function, args))
+    }
+}
+
+/// Mechanisms rules can set, each with its parameters and the name
+/// `parameter_map` knows the parameter by
+const MECHANISMS: [(&str, &[(&str, &str)]); 2] = [
+    (
+        "hh",
+        &[
+            ("gnabar", "gnabar_hh"),
+            ("gkbar", "gkbar_hh"),
+            ("gl", "gl_hh"),
+        ],
+    ),
+    ("pas", &[("g", "conductance")]),
+];
+
+/// One distribution rule
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub structure: StructureIdentifier,
+    pub mechanism: String,
+    pub parameter: String,
+    pub expression: Expression,
+}
+
+impl Rule {
+    /// The parameter as `parameter_map` names it, e.g. `gkbar_hh`
+    pub fn target(&self) -> &'static str {
+        target(&self.mechanism, &self.parameter).expect("checked when the rule was added")
+    }
+}
+
+fn target(mechanism: &str, parameter: &str) -> Option<&'static str> {
+    MECHANISMS
+        .iter()
+        .find(|(m, _)| *m == mechanism)?
+        .1
+        .iter()
+        .find(|(p, _)| *p == parameter)
+        .map(|(_, t)| *t)
+}
+
+/// SWC region names for the usual types, the type number otherwise
+fn structure_name(structure: StructureIdentifier) -> String {
+    match structure {
+        StructureIdentifier::Undefined => "0".to_owned(),
+        StructureIdentifier::Soma => "soma".to_owned(),
+        StructureIdentifier::Axon => "axon".to_owned(),
+        StructureIdentifier::BasalDendrite => "dend".to_owned(),
+        StructureIdentifier::ApicalDendrite => "apic".to_owned(),
+        StructureIdentifier::ForkPoint => "5".to_owned(),
+        StructureIdentifier::EndPoint => "6".to_owned(),
+        _ => "7".to_owned(),
+    }
+}
+
+fn parse_structure(name: &str) -> Option<StructureIdentifier> {
+    Some(match name {
+        "soma" => StructureIdentifier::Soma,
+        "axon" => StructureIdentifier::Axon,
+        "dend" => StructureIdentifier::BasalDendrite,
+        "apic" => StructureIdentifier::ApicalDendrite,
+        _ => name.parse::<u8>().ok()?.into(),
+    })
+}
+
+/// Distribution rules applied in order, a later rule overriding an earlier
+/// one where both match
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BiophysicsProfile {
+    pub rules: Vec<Rule>,
+}
+
+impl BiophysicsProfile {
+    pub fn new() -> BiophysicsProfile {
+        BiophysicsProfile::default()
+    }
+
+    /// Adds a rule setting `parameter` of `mechanism` on every compartment
+    /// of `structure` to `expression`. Mechanisms are `hh` (`gnabar`,
+    /// `gkbar`, `gl`) and `pas` (`g`). Fails on an unknown parameter or an
+    /// expression that does not parse, pointing at the character.
+    pub fn add_rule(
+        &mut self,
+        structure: StructureIdentifier,
+        mechanism: &str,
+        parameter: &str,
+        expression: &str,
+    ) -> Result<&mut BiophysicsProfile, String> {
+        if target(mechanism, parameter).is_none() {
+            let known: Vec<String> = MECHANISMS
+                .iter()
+                .flat_map(|(m, params)| params.iter().map(move |(p, _)| format!("{} {}", m, p)))
+                .collect();
+            return Err(format!(
+                "Unknown parameter '{}' of '{}'; use one of {}",
+                parameter,
+                mechanism,
+                known.join(", ")
+            ));
+        }
+        let expression = Expression::parse(expression)
+            .map_err(|e| format!("Rule for {} {}: {}", mechanism, parameter, e))?;
+        self.rules.push(Rule {
+            structure,
+            mechanism: mechanism.to_owned(),
+            parameter: parameter.to_owned(),
+            expression,
+        });
+        Ok(self)
+    }
+
+    /// Evaluates every rule on every compartment it covers and sets the
+    /// result. Returns how many values were set. Stops at the first
+    /// compartment a rule fails on, or that lacks the rule's mechanism.
+    pub fn apply(&self, compartments: &mut Compartments) -> Result<usize, String> {
+        let variables = compartments.rule_variables();
+        let mut set = 0;
+        for rule in &self.rules {
+            for (idx, v) in variables.iter().enumerate().skip(1) {
+                let c = &mut compartments.components[idx];
+                if c.structure != rule.structure {
+                    continue;
+                }
+                let value = rule.expression.evaluate(v).map_err(|e| {
+                    format!(
+                        "Compartment {}: {} = {}: {}",
+                        idx,
+                        rule.target(),
+                        rule.expression,
+                        e
+                    )
+                })?;
+                set_parameter(c, rule.target(), value).map_err(|e| e.to_string())?;
+                set += 1;
+            }
+        }
+        compartments.log(
+            "apply_profile",
+            &[("rules", self.rules.len().into()), ("set", set.into())],
+        );
+        Ok(set)
+    }
+
+    /// One line per rule, `<region> <mechanism> <parameter> = <expression>`,
+    /// the expression verbatim
+    pub fn to_text(&self) -> String {
+        self.rules
+            .iter()
+            .map(|r| {
+                format!(
+                    "{} {} {} = {}\n",
+                    structure_name(r.structure),
+                    r.mechanism,
+                    r.parameter,
+                    r.expression
+                )
+            })
+            .collect()
+    }
+
+    /// Reads back the output of `to_text`, skipping blank lines and `#`
+    /// comments. The expression is everything after the space following
+    /// `=`, so it comes back verbatim.
+    pub fn parse(text: &str) -> Result<BiophysicsProfile, String> {
+        let mut profile = BiophysicsProfile::new();
+        for (i, line) in text.lines().enumerate() {
+            if line.trim().is_empty() || line.trim_start().starts_with('#') {
+                continue;
+            }
+            let malformed = || {
+                format!(
+                    "Line {}: expected '<region> <mechanism> <parameter> = <expression>'",
+                    i + 1
+                )
+            };
+            let (head, expression) = line.split_once('=').ok_or_else(malformed)?;
+            let [region, mechanism, parameter] = head.split_whitespace().collect::<Vec<_>>()[..]
+            else {
+                return Err(malformed());
+            };
+            let structure = parse_structure(region)
+                .ok_or_else(|| format!("Line {}: unknown region '{}'", i + 1, region))?;
+            let expression = expression.strip_prefix(' ').unwrap_or(expression);
+            profile
+                .add_rule(structure, mechanism, parameter, expression)
+                .map_err(|e| format!("Line {}: {}", i + 1, e))?;
+        }
+        Ok(profile)
+    }
+}
+
+impl Compartments {
+    /// The variables of every compartment, indexed like `components`
+    pub(crate) fn rule_variables(&self) -> Vec<Variables> {
+        let dist = self
+            .parameter_map("path_distance")
+            .expect("path_distance is known");
+        let order = self
+            .parameter_map("branch_order")
+            .expect("branch_order is known");
+        self.components
+            .iter()
+            .enumerate()
+            .map(|(i, c)| {
+                let mid = |k: usize| (c.proximal[k] + c.distal[k]) / 2.0;
+                Variables {
+                    dist: dist[i],
+                    diam: c.diam,
+                    branch_order: order[i],
+                    x: mid(0),
+                    y: mid(1),
+                    z: mid(2),
+                }
+            })
+            .collect()
+    }
+}
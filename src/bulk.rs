@@ -0,0 +1,386 @@
+//! Morphometrics of a whole `Dataset` written straight to Parquet, one row
+//! per input file, for analytics stacks that would otherwise go through a
+//! dict per cell.
+//!
+//! Cells are measured in parallel a row group at a time, and each group is
+//! written before the next is started, so however large the dataset only
+//! `ParquetOptions::row_group_size` rows are held at once.
+//!
+//! The schema only depends on the selected `MorphometricColumns` and the
+//! `FeatureConfig`, see `schema`. Columns come in this order:
+//!
+//! | column | type | null when |
+//! |---|---|---|
+//! | `source` | utf8 | never; the path as given in `Dataset::paths` |
+//! | `cell_id` | utf8 | the file failed; otherwise `CellId` in its string form |
+//! | `error` | utf8 | the file was read; otherwise why it was not |
+//! | `node_count` | int64 | the file failed |
+//! | `max_branching_degree` | int64 | the file failed |
+//! | `total_length`, `total_area` | float64 | the file failed |
+//! | `bbox_x`, `bbox_y`, `bbox_z` | float64 | the file failed |
+//! | `oriented_volume` | float64 | failed, or the arbor is planar or collinear |
+//! | `hull_volume`, `hull_area` | float64 | failed, or the arbor is planar or collinear |
+//! | `density` | float64 | failed, or the arbor is planar or collinear |
+//! | `rall_ratio_median` | float64 | failed, or no branch point off the soma |
+//! | `qc_score` | float64 | the file failed; `qc::score` with the default rubric |
+//! | one per `FeatureVector` name | float64 | failed, or the branches cannot be walked |
+//!
+//! The first three are always present; the rest only when selected. Lengths
+//! are in µm, areas in µm², volumes in µm³, density in µm⁻². Coordinates are
+//! scaled as the SWC header asks, as in `standardize`.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use arrow_array::{ArrayRef, Float64Array, Int64Array, RecordBatch, StringArray};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use parquet::arrow::ArrowWriter;
+use parquet::file::properties::WriterProperties;
+
+use crate::analysis::{median, rall_ratios};
+use crate::features::{FeatureConfig, feature_names, morphology_features};
+use crate::morphometry::Morphometry;
+use crate::qc::{QcRubric, score_skeleton};
+use crate::standardize::{Dataset, read_source};
+use crate::swc_reader::{ConflictPolicy, ReaderOptions, swc_reader_from_bytes};
+use crate::write;
+
+bitflags::bitflags! {
+    /// Groups of columns to measure, see the module documentation for the
+    /// columns of each
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+    pub struct MorphometricColumns: u32 {
+        const NODE_COUNT = 1;
+        const TOTAL_LENGTH = 1 << 1;
+        const TOTAL_AREA = 1 << 2;
+        /// Extents of the axis-aligned bounding box
+        const BOUNDING_BOX = 1 << 3;
+        const ORIENTED_VOLUME = 1 << 4;
+        const HULL = 1 << 5;
+        const DENSITY = 1 << 6;
+        /// The `morphology_features` histograms
+        const FEATURES = 1 << 7;
+        /// Median of `analysis::rall_ratios` over the whole arbor
+        const RALL_RATIO = 1 << 8;
+        /// `Morphometry::max_branching_degree`
+        const BRANCHING_DEGREE = 1 << 9;
+        /// `qc::score_skeleton` with the default `QcRubric`
+        const QC_SCORE = 1 << 10;
+    }
+}
+
+impl MorphometricColumns {
+    /// Groups needing a convex hull, the expensive part of a row
+    const SPATIAL: MorphometricColumns = MorphometricColumns::BOUNDING_BOX
+        .union(MorphometricColumns::ORIENTED_VOLUME)
+        .union(MorphometricColumns::HULL)
+        .union(MorphometricColumns::DENSITY);
+}
+
+/// How `Dataset::morphometrics_to_parquet_with` works through the files
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParquetOptions {
+    /// Rows measured, held and written together
+    pub row_group_size: usize,
+    /// Worker threads; 0 uses every available core
+    pub threads: usize,
+    pub features: FeatureConfig,
+}
+
+impl Default for ParquetOptions {
+    fn default() -> Self {
+        ParquetOptions {
+            row_group_size: 4096,
+            threads: 0,
+            features: FeatureConfig::default(),
+        }
+    }
+}
+
+/// What was written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ParquetSummary {
+    pub rows: usize,
+    /// Rows with an `error`
+    pub failed: usize,
+    pub row_groups: usize,
+    /// Most rows measured but not yet written at any one time
+    pub peak_buffered_rows: usize,
+}
+
+/// The columns written for `columns`, in order, see the module documentation
+pub fn schema(columns: MorphometricColumns, features: &FeatureConfig) -> Schema {
+    let text = |name: &str, nullable| Field::new(name, DataType::Utf8, nullable);
+    let mut fields = vec![
+        text("source", false),
+        text("cell_id", true),
+        text("error", true),
+    ];
+    if columns.contains(MorphometricColumns::NODE_COUNT) {
+        fields.push(Field::new("node_count", DataType::Int64, true));
+    }
+    if columns.contains(MorphometricColumns::BRANCHING_DEGREE) {
+        fields.push(Field::new("max_branching_degree", DataType::Int64, true));
+    }
+    fields.extend(
+        float_names(columns, features)
+            .iter()
+            .map(|name| Field::new(name, DataType::Float64, true)),
+    );
+    Schema::new(fields)
+}
+
+/// Names of the float columns, in order
+fn float_names(columns: MorphometricColumns, features: &FeatureConfig) -> Vec<String> {
+    let groups: [(MorphometricColumns, &[&str]); 8] = [
+        (MorphometricColumns::TOTAL_LENGTH, &["total_length"]),
+        (MorphometricColumns::TOTAL_AREA, &["total_area"]),
+        (
+            MorphometricColumns::BOUNDING_BOX,
+            &["bbox_x", "bbox_y", "bbox_z"],
+        ),
+        (MorphometricColumns::ORIENTED_VOLUME, &["oriented_volume"]),
+        (MorphometricColumns::HULL, &["hull_volume", "hull_area"]),
+        (MorphometricColumns::DENSITY, &["density"]),
+        (MorphometricColumns::RALL_RATIO, &["rall_ratio_median"]),
+        (MorphometricColumns::QC_SCORE, &["qc_score"]),
+    ];
+    let mut names: Vec<String> = groups
+        .iter()
+        .filter(|(group, _)| columns.contains(*group))
+        .flat_map(|(_, names)| names.iter().map(|n| n.to_string()))
+        .collect();
+    if columns.contains(MorphometricColumns::FEATURES) {
+        names.extend(feature_names(features));
+    }
+    names
+}
+
+/// One measured file
+struct Row {
+    source: PathBuf,
+    cell_id: Option<String>,
+    error: Option<String>,
+    node_count: Option<i64>,
+    max_branching_degree: Option<i64>,
+    values: Vec<Option<f64>>,
+}
+
+impl Row {
+    fn failed(source: &Path, error: String, width: usize) -> Row {
+        Row {
+            source: source.to_owned(),
+            cell_id: None,
+            error: Some(error),
+            node_count: None,
+            max_branching_degree: None,
+            values: vec![None; width],
+        }
+    }
+
+    fn measure(
+        source: &Path,
+        columns: MorphometricColumns,
+        features: &FeatureConfig,
+        width: usize,
+    ) -> Row {
+        let options = ReaderOptions {
+            apply_scale: true,
+            collect_stats: false,
+            ..ReaderOptions::default()
+        };
+        let skeleton = read_source(source)
+            .map_err(|e| e.to_string())
+            .and_then(|data| swc_reader_from_bytes(&data, &options).map_err(|e| e.to_string()));
+        let skeleton = match skeleton {
+            Ok(skeleton) => skeleton,
+            Err(error) => return Row::failed(source, error, width),
+        };
+
+        let morphometry = Morphometry::new(&skeleton.nodes);
+        let mut values = Vec::with_capacity(width);
+        if columns.contains(MorphometricColumns::TOTAL_LENGTH) {
+            values.push(Some(morphometry.total_length()));
+        }
+        if columns.contains(MorphometricColumns::TOTAL_AREA) {
+            values.push(Some(morphometry.total_area()));
+        }
+        if columns.intersects(MorphometricColumns::SPATIAL) {
+            let spatial = morphometry.spatial_metrics();
+            let solid = |v: f64| (!spatial.degenerate).then_some(v);
+            if columns.contains(MorphometricColumns::BOUNDING_BOX) {
+                values.extend(spatial.bounding_box.extents().map(Some));
+            }
+            if columns.contains(MorphometricColumns::ORIENTED_VOLUME) {
+                values.push(solid(spatial.oriented_volume));
+            }
+            if columns.contains(MorphometricColumns::HULL) {
+                values.push(solid(spatial.hull_volume));
+                values.push(solid(spatial.hull_area));
+            }
+            if columns.contains(MorphometricColumns::DENSITY) {
+                values.push(solid(spatial.density));
+            }
+        }
+        if columns.contains(MorphometricColumns::RALL_RATIO) {
+            let mut ratios: Vec<f64> = rall_ratios(&skeleton.nodes, &skeleton.parent_child_map)
+                .iter()
+                .map(|r| r.ratio)
+                .collect();
+            values.push(median(&mut ratios));
+        }
+        if columns.contains(MorphometricColumns::QC_SCORE) {
+            values.push(Some(score_skeleton(&skeleton, &QcRubric::default()).score));
+        }
+        if columns.contains(MorphometricColumns::FEATURES) {
+            match morphology_features(&skeleton, features) {
+                Ok(vector) => values.extend(vector.values.into_iter().map(Some)),
+                Err(_) => values.resize(width, None),
+            }
+        }
+        Row {
+            source: source.to_owned(),
+            cell_id: Some(skeleton.cell_id().to_string()),
+            error: None,
+            node_count: Some(skeleton.nodes.len() as i64),
+            max_branching_degree: Some(morphometry.max_branching_degree() as i64),
+            values,
+        }
+    }
+}
+
+/// The rows as one record batch of `schema`
+fn batch(
+    rows: &[Row],
+    schema: &SchemaRef,
+    columns: MorphometricColumns,
+    width: usize,
+) -> Result<RecordBatch, String> {
+    let mut arrays: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from_iter_values(
+            rows.iter().map(|r| r.source.to_string_lossy()),
+        )),
+        Arc::new(StringArray::from_iter(
+            rows.iter().map(|r| r.cell_id.as_deref()),
+        )),
+        Arc::new(StringArray::from_iter(
+            rows.iter().map(|r| r.error.as_deref()),
+        )),
+    ];
+    if columns.contains(MorphometricColumns::NODE_COUNT) {
+        arrays.push(Arc::new(Int64Array::from_iter(
+            rows.iter().map(|r| r.node_count),
+        )));
+    }
+    if columns.contains(MorphometricColumns::BRANCHING_DEGREE) {
+        arrays.push(Arc::new(Int64Array::from_iter(
+            rows.iter().map(|r| r.max_branching_degree),
+        )));
+    }
+    for k in 0..width {
+        arrays.push(Arc::new(Float64Array::from_iter(
+            rows.iter().map(|r| r.values[k]),
+        )));
+    }
+    RecordBatch::try_new(schema.clone(), arrays).map_err(|e| e.to_string())
+}
+
+impl Dataset {
+    /// `morphometrics_to_parquet_with` with default options
+    pub fn morphometrics_to_parquet(
+        &self,
+        path: impl AsRef<Path>,
+        columns: MorphometricColumns,
+    ) -> Result<ParquetSummary, String> {
+        self.morphometrics_to_parquet_with(path, columns, &ParquetOptions::default())
+    }
+
+    /// Measures every file and writes one row each to `path`, replacing it
+    /// atomically, with the schema documented in `bulk`. Files that cannot
+    /// be read are rows with an `error`; only failing to write is an `Err`.
+    pub fn morphometrics_to_parquet_with(
+        &self,
+        path: impl AsRef<Path>,
+        columns: MorphometricColumns,
+        options: &ParquetOptions,
+    ) -> Result<ParquetSummary, String> {
+        let group = options.row_group_size.max(1);
+        let threads = match options.threads {
+            0 => thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        }
+        .min(group)
+        .max(1);
+        let schema = Arc::new(schema(columns, &options.features));
+        let width = float_names(columns, &options.features).len();
+        let properties = WriterProperties::builder()
+            .set_max_row_group_size(group)
+            .build();
+        // Rows measured but not yet written, to show the table never is
+        let buffered = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+
+        let written = write::write_atomic_with(path.as_ref(), ConflictPolicy::Overwrite, |file| {
+            let mut writer = ArrowWriter::try_new(file, schema.clone(), Some(properties))
+                .map_err(std::io::Error::other)?;
+            let mut summary = ParquetSummary::default();
+            for chunk in self.paths.chunks(group) {
+                let next = AtomicUsize::new(0);
+                let mut rows: Vec<Option<Row>> = Vec::new();
+                rows.resize_with(chunk.len(), || None);
+                thread::scope(|scope| {
+                    let workers: Vec<_> = (0..threads.min(chunk.len()))
+                        .map(|_| {
+                            scope.spawn(|| {
+                                let mut done = Vec::new();
+                                loop {
+                                    let i = next.fetch_add(1, Ordering::Relaxed);
+                                    let Some(source) = chunk.get(i) else {
+                                        break;
+                                    };
+                                    let row =
+                                        Row::measure(source, columns, &options.features, width);
+                                    let held = buffered.fetch_add(1, Ordering::Relaxed) + 1;
+                                    peak.fetch_max(held, Ordering::Relaxed);
+                                    done.push((i, row));
+                                }
+                                done
+                            })
+                        })
+                        .collect();
+                    for worker in workers {
+                        for (i, row) in worker.join().unwrap_or_default() {
+                            rows[i] = Some(row);
+                        }
+                    }
+                });
+                let rows: Vec<Row> = rows
+                    .into_iter()
+                    .zip(chunk)
+                    .map(|(row, source)| {
+                        row.unwrap_or_else(|| {
+                            Row::failed(source, "Measuring panicked".to_owned(), width)
+                        })
+                    })
+                    .collect();
+                let batch = batch(&rows, &schema, columns, width).map_err(std::io::Error::other)?;
+                writer.write(&batch).map_err(std::io::Error::other)?;
+                // Close the row group so its rows leave memory now
+                writer.flush().map_err(std::io::Error::other)?;
+                summary.rows += rows.len();
+                summary.failed += rows.iter().filter(|r| r.error.is_some()).count();
+                summary.row_groups += 1;
+                buffered.fetch_sub(rows.len(), Ordering::Relaxed);
+            }
+            writer.close().map_err(std::io::Error::other)?;
+            summary.peak_buffered_rows = peak.load(Ordering::Relaxed);
+            Ok(summary)
+        });
+        match written {
+            Ok(summary) => Ok(summary.unwrap_or_default()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+}
@@ -0,0 +1,394 @@
+//! Many processed skeletons packed into one file, so a dataset travels as
+//! a single file instead of tens of thousands.
+//!
+//! A bundle is a header, then one record per cell, then an index. Each
+//! record holds the cell's SWC text, gzipped, with its `CellId`, length and
+//! CRC-32, so it describes itself; the index lists where every record
+//! starts and is only written by `BundleWriter::finish`. `Bundle::open`
+//! reads the index alone, and `get` seeks to one record and reads just
+//! that, so opening a large bundle and reading one cell stays cheap.
+//!
+//! Records are written straight to the file as they are added. If the
+//! writer dies before `finish`, or in the middle of a record, there is no
+//! index at the end; `open` then walks the records from the start and keeps
+//! every complete one, and `Bundle::append` carries on from there.
+//!
+//! Layout, little-endian:
+//!
+//! ```text
+//! header   MAGIC
+//! record   RECORD_MAGIC, cell id (12 bytes), payload length u64, CRC-32 u32, payload
+//! index    INDEX_MAGIC, length u64, per record: cell id, offset u64, length u64, CRC-32 u32,
+//!          then record count u64, index offset u64, CRC-32 of the entries u32, INDEX_END
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use flate2::Compression;
+use flate2::write::GzEncoder;
+
+use crate::cell_id::CellId;
+use crate::swc_reader::{ReaderOptions, Skeleton, swc_reader_from_bytes, to_swc_string};
+
+/// Extension `Dataset` recognises bundles by
+pub const BUNDLE_EXTENSION: &str = "bundle";
+
+const MAGIC: &[u8; 8] = b"CRSBNDL1";
+const RECORD_MAGIC: &[u8; 4] = b"REC1";
+const INDEX_MAGIC: &[u8; 4] = b"IDX1";
+const INDEX_END: &[u8; 8] = b"CRSINDEX";
+/// Magic, cell id, payload length and CRC
+const RECORD_HEADER: u64 = 4 + 12 + 8 + 4;
+/// Cell id, offset, length and CRC
+const INDEX_ENTRY: usize = 12 + 8 + 8 + 4;
+/// Count, offset, CRC and end marker
+const INDEX_TRAILER: usize = 8 + 8 + 4 + 8;
+
+/// Where one record sits in the file
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Entry {
+    id: CellId,
+    /// Of the record header
+    offset: u64,
+    /// Of the payload
+    length: u64,
+    crc: u32,
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = flate2::Crc::new();
+    crc.update(data);
+    crc.sum()
+}
+
+fn io_error(path: &Path, e: std::io::Error) -> String {
+    format!("{}: {}", path.display(), e)
+}
+
+/// A bundle open for reading
+pub struct Bundle {
+    path: PathBuf,
+    file: Mutex<File>,
+    entries: Vec<Entry>,
+    by_id: HashMap<CellId, usize>,
+    recovered: bool,
+    records_read: AtomicUsize,
+    bytes_read: AtomicUsize,
+}
+
+/// Adds records to a bundle; see `Bundle::create`
+pub struct BundleWriter {
+    path: PathBuf,
+    file: File,
+    entries: Vec<Entry>,
+    ids: HashSet<CellId>,
+    /// Where the next record goes
+    end: u64,
+}
+
+impl Bundle {
+    /// Starts a new, empty bundle at `path`, replacing any file there
+    pub fn create(path: impl AsRef<Path>) -> Result<BundleWriter, String> {
+        let path = path.as_ref();
+        let mut file = File::create(path).map_err(|e| io_error(path, e))?;
+        file.write_all(MAGIC).map_err(|e| io_error(path, e))?;
+        Ok(BundleWriter {
+            path: path.to_owned(),
+            file,
+            entries: Vec::new(),
+            ids: HashSet::new(),
+            end: MAGIC.len() as u64,
+        })
+    }
+
+    /// Reopens the bundle at `path` to add more records. An index or a torn
+    /// record at the end is cut off first; `finish` writes the index again.
+    pub fn append(path: impl AsRef<Path>) -> Result<BundleWriter, String> {
+        let path = path.as_ref();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .map_err(|e| io_error(path, e))?;
+        let (entries, _) =
+            read_entries(&mut file).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let end = entries
+            .last()
+            .map_or(MAGIC.len() as u64, |e| e.offset + RECORD_HEADER + e.length);
+        file.set_len(end).map_err(|e| io_error(path, e))?;
+        file.seek(SeekFrom::Start(end))
+            .map_err(|e| io_error(path, e))?;
+        Ok(BundleWriter {
+            path: path.to_owned(),
+            file,
+            ids: entries.iter().map(|e| e.id).collect(),
+            entries,
+            end,
+        })
+    }
+
+    /// Opens the bundle at `path`, reading its index, or walking its records
+    /// if it has none
+    pub fn open(path: impl AsRef<Path>) -> Result<Bundle, String> {
+        let path = path.as_ref();
+        let mut file = File::open(path).map_err(|e| io_error(path, e))?;
+        let (entries, recovered) =
+            read_entries(&mut file).map_err(|e| format!("{}: {}", path.display(), e))?;
+        if recovered {
+            log::warn!(
+                "{} has no index, recovered {} records",
+                path.display(),
+                entries.len()
+            );
+        }
+        Ok(Bundle {
+            path: path.to_owned(),
+            file: Mutex::new(file),
+            by_id: entries.iter().enumerate().map(|(i, e)| (e.id, i)).collect(),
+            entries,
+            recovered,
+            records_read: AtomicUsize::new(0),
+            bytes_read: AtomicUsize::new(0),
+        })
+    }
+
+    /// Cells in the order they were added
+    pub fn list(&self) -> Vec<CellId> {
+        self.entries.iter().map(|e| e.id).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn contains(&self, id: &CellId) -> bool {
+        self.by_id.contains_key(id)
+    }
+
+    /// Whether the index was missing or damaged, so the records were found
+    /// by walking the file
+    pub fn recovered(&self) -> bool {
+        self.recovered
+    }
+
+    /// Reads cell `id`, checking its record's checksum. None if the bundle
+    /// does not hold it.
+    pub fn get(&self, id: &CellId) -> Result<Option<Skeleton>, String> {
+        let Some(&i) = self.by_id.get(id) else {
+            return Ok(None);
+        };
+        let entry = self.entries[i];
+        let mut payload = vec![0; entry.length as usize];
+        {
+            let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+            file.seek(SeekFrom::Start(entry.offset + RECORD_HEADER))
+                .and_then(|_| file.read_exact(&mut payload))
+                .map_err(|e| io_error(&self.path, e))?;
+        }
+        self.records_read.fetch_add(1, Ordering::Relaxed);
+        self.bytes_read.fetch_add(payload.len(), Ordering::Relaxed);
+        if crc32(&payload) != entry.crc {
+            return Err(format!(
+                "{}: checksum mismatch in the record of cell {}",
+                self.path.display(),
+                id
+            ));
+        }
+        let options = ReaderOptions {
+            collect_stats: false,
+            ..ReaderOptions::default()
+        };
+        swc_reader_from_bytes(&payload, &options)
+            .map(Some)
+            .map_err(|e| format!("{}: cell {}: {}", self.path.display(), id, e))
+    }
+
+    /// Records read by `get` so far
+    pub fn records_read(&self) -> usize {
+        self.records_read.load(Ordering::Relaxed)
+    }
+
+    /// Payload bytes read by `get` so far
+    pub fn bytes_read(&self) -> usize {
+        self.bytes_read.load(Ordering::Relaxed)
+    }
+}
+
+impl BundleWriter {
+    /// Appends `skeleton`, with its metadata and extra columns, and returns
+    /// its id. A cell already in the bundle is not written again.
+    pub fn add(&mut self, skeleton: &Skeleton) -> Result<CellId, String> {
+        let id = skeleton.cell_id();
+        if self.ids.contains(&id) {
+            return Ok(id);
+        }
+        let options = ReaderOptions {
+            write_extras: true,
+            ..ReaderOptions::default()
+        };
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder
+            .write_all(to_swc_string(skeleton, &options).as_bytes())
+            .map_err(|e| io_error(&self.path, e))?;
+        let payload = encoder.finish().map_err(|e| io_error(&self.path, e))?;
+        let entry = Entry {
+            id,
+            offset: self.end,
+            length: payload.len() as u64,
+            crc: crc32(&payload),
+        };
+
+        // One write, so a crash leaves at most this record torn
+        let mut record = Vec::with_capacity(RECORD_HEADER as usize + payload.len());
+        record.extend_from_slice(RECORD_MAGIC);
+        record.extend_from_slice(&id.to_bytes());
+        record.extend_from_slice(&entry.length.to_le_bytes());
+        record.extend_from_slice(&entry.crc.to_le_bytes());
+        record.extend_from_slice(&payload);
+        self.file
+            .write_all(&record)
+            .map_err(|e| io_error(&self.path, e))?;
+        self.end += record.len() as u64;
+        self.entries.push(entry);
+        self.ids.insert(id);
+        Ok(id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Writes the index and flushes the file to disk
+    pub fn finish(mut self) -> Result<(), String> {
+        let mut entries = Vec::with_capacity(self.entries.len() * INDEX_ENTRY);
+        for e in &self.entries {
+            entries.extend_from_slice(&e.id.to_bytes());
+            entries.extend_from_slice(&e.offset.to_le_bytes());
+            entries.extend_from_slice(&e.length.to_le_bytes());
+            entries.extend_from_slice(&e.crc.to_le_bytes());
+        }
+        let mut index = Vec::with_capacity(12 + entries.len() + INDEX_TRAILER);
+        index.extend_from_slice(INDEX_MAGIC);
+        index.extend_from_slice(&((entries.len() + INDEX_TRAILER) as u64).to_le_bytes());
+        index.extend_from_slice(&entries);
+        index.extend_from_slice(&(self.entries.len() as u64).to_le_bytes());
+        index.extend_from_slice(&self.end.to_le_bytes());
+        index.extend_from_slice(&crc32(&entries).to_le_bytes());
+        index.extend_from_slice(INDEX_END);
+        self.file
+            .write_all(&index)
+            .and_then(|_| self.file.sync_all())
+            .map_err(|e| io_error(&self.path, e))
+    }
+}
+
+/// The records of a bundle, from its index if it has a sound one, otherwise
+/// by walking them, and whether it came to that
+fn read_entries(file: &mut File) -> Result<(Vec<Entry>, bool), String> {
+    let size = file.metadata().map_err(|e| e.to_string())?.len();
+    let mut magic = [0; 8];
+    file.seek(SeekFrom::Start(0))
+        .and_then(|_| file.read_exact(&mut magic))
+        .map_err(|_| "Not a bundle: too short".to_owned())?;
+    if &magic != MAGIC {
+        return Err("Not a bundle".to_owned());
+    }
+    match read_index(file, size) {
+        Some(entries) => Ok((entries, false)),
+        None => Ok((walk_records(file, size), true)),
+    }
+}
+
+/// The entries of the index at the end of the file, None if there is none
+/// or it does not add up
+fn read_index(file: &mut File, size: u64) -> Option<Vec<Entry>> {
+    let header = MAGIC.len() as u64;
+    if size < header + 12 + INDEX_TRAILER as u64 {
+        return None;
+    }
+    let mut trailer = [0; INDEX_TRAILER];
+    file.seek(SeekFrom::Start(size - INDEX_TRAILER as u64))
+        .ok()?;
+    file.read_exact(&mut trailer).ok()?;
+    if &trailer[20..] != INDEX_END {
+        return None;
+    }
+    let count = u64::from_le_bytes(trailer[..8].try_into().unwrap());
+    let start = u64::from_le_bytes(trailer[8..16].try_into().unwrap());
+    let crc = u32::from_le_bytes(trailer[16..20].try_into().unwrap());
+    let length = count.checked_mul(INDEX_ENTRY as u64)?;
+    if start.checked_add(12)?.checked_add(length)? != size - INDEX_TRAILER as u64 {
+        return None;
+    }
+    let mut entries = vec![0; length as usize];
+    file.seek(SeekFrom::Start(start + 12)).ok()?;
+    file.read_exact(&mut entries).ok()?;
+    if crc32(&entries) != crc {
+        return None;
+    }
+    entries
+        .chunks_exact(INDEX_ENTRY)
+        .map(|e| {
+            let entry = Entry {
+                id: CellId::from_bytes(e[..12].try_into().unwrap()),
+                offset: u64::from_le_bytes(e[12..20].try_into().unwrap()),
+                length: u64::from_le_bytes(e[20..28].try_into().unwrap()),
+                crc: u32::from_le_bytes(e[28..].try_into().unwrap()),
+            };
+            let end = entry
+                .offset
+                .checked_add(RECORD_HEADER)?
+                .checked_add(entry.length)?;
+            (entry.offset >= header && end <= start).then_some(entry)
+        })
+        .collect()
+}
+
+/// Every complete record from the start of the file, stopping at the index
+/// or at the first record that is cut short or not a record
+fn walk_records(file: &mut File, size: u64) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let mut offset = MAGIC.len() as u64;
+    let mut header = [0; RECORD_HEADER as usize];
+    while offset + RECORD_HEADER <= size {
+        if file.seek(SeekFrom::Start(offset)).is_err() || file.read_exact(&mut header).is_err() {
+            break;
+        }
+        if &header[..4] != RECORD_MAGIC {
+            break;
+        }
+        let entry = Entry {
+            id: CellId::from_bytes(header[4..16].try_into().unwrap()),
+            offset,
+            length: u64::from_le_bytes(header[16..24].try_into().unwrap()),
+            crc: u32::from_le_bytes(header[24..].try_into().unwrap()),
+        };
+        match (offset + RECORD_HEADER).checked_add(entry.length) {
+            Some(end) if end <= size => {
+                entries.push(entry);
+                offset = end;
+            }
+            _ => break,
+        }
+    }
+    entries
+}
+
+/// Whether `path` names a bundle, by its extension
+pub fn is_bundle(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case(BUNDLE_EXTENSION))
+}
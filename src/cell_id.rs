@@ -0,0 +1,150 @@
+//! Content-addressed identity for cells, so the same neuron is recognised
+//! whatever file name or path it was read from.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use log::warn;
+use sha2::{Digest, Sha256};
+
+use crate::swc_reader::{ReaderOptions, Skeleton, to_swc_string};
+
+/// Identity of a processed skeleton. `morphology` hashes the nodes alone;
+/// `content` also covers header metadata and extra columns, so two files that
+/// differ only in their annotations share a morphology but not an id.
+///
+/// The string form is `<morphology as 16 hex digits>-<content as 8 hex digits>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CellId {
+    morphology: u64,
+    content: u32,
+}
+
+impl CellId {
+    pub fn of(skeleton: &Skeleton) -> CellId {
+        let mut options = ReaderOptions::default();
+        let nodes_only: String = to_swc_string(skeleton, &options)
+            .lines()
+            .filter(|l| !l.starts_with('#'))
+            .flat_map(|l| [l, "\n"])
+            .collect();
+        options.write_extras = true;
+        let everything = to_swc_string(skeleton, &options);
+
+        let morphology = Sha256::digest(nodes_only.as_bytes());
+        let content = Sha256::digest(everything.as_bytes());
+        CellId {
+            morphology: u64::from_be_bytes(morphology[..8].try_into().unwrap()),
+            content: u32::from_be_bytes(content[..4].try_into().unwrap()),
+        }
+    }
+
+    pub fn parse(s: &str) -> Result<CellId, String> {
+        let invalid = || format!("Invalid cell id '{}'", s);
+        let (morphology, content) = s.split_once('-').ok_or_else(invalid)?;
+        if morphology.len() != 16 || content.len() != 8 {
+            return Err(invalid());
+        }
+        Ok(CellId {
+            morphology: u64::from_str_radix(morphology, 16).map_err(|_| invalid())?,
+            content: u32::from_str_radix(content, 16).map_err(|_| invalid())?,
+        })
+    }
+
+    /// Hash of the nodes alone; equal for cells that differ only in metadata
+    pub fn morphology(&self) -> u64 {
+        self.morphology
+    }
+
+    /// Big-endian, morphology then content, as in the string form
+    pub(crate) fn to_bytes(self) -> [u8; 12] {
+        let mut bytes = [0; 12];
+        bytes[..8].copy_from_slice(&self.morphology.to_be_bytes());
+        bytes[8..].copy_from_slice(&self.content.to_be_bytes());
+        bytes
+    }
+
+    pub(crate) fn from_bytes(bytes: [u8; 12]) -> CellId {
+        CellId {
+            morphology: u64::from_be_bytes(bytes[..8].try_into().unwrap()),
+            content: u32::from_be_bytes(bytes[8..].try_into().unwrap()),
+        }
+    }
+}
+
+impl fmt::Display for CellId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}-{:08x}", self.morphology, self.content)
+    }
+}
+
+impl FromStr for CellId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        CellId::parse(s)
+    }
+}
+
+impl Skeleton {
+    /// Content-addressed identity; see `CellId`
+    pub fn cell_id(&self) -> CellId {
+        CellId::of(self)
+    }
+}
+
+/// Pairs of files whose names and contents disagree, from `duplicate_report`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DuplicateReport {
+    /// Same file stem, different cells: joins on file name would mix them up
+    pub same_name_different_content: Vec<(PathBuf, PathBuf)>,
+    /// Same cell under different file stems, e.g. a renamed copy
+    pub same_content_different_name: Vec<(PathBuf, PathBuf)>,
+}
+
+impl DuplicateReport {
+    pub fn is_empty(&self) -> bool {
+        self.same_name_different_content.is_empty() && self.same_content_different_name.is_empty()
+    }
+}
+
+/// Cross-checks file names against cell ids, logging a warning per
+/// suspicious pair. Pairs come out in input order.
+pub fn duplicate_report(cells: &[(PathBuf, CellId)]) -> DuplicateReport {
+    let stem = |p: &Path| p.file_stem().map(|s| s.to_os_string());
+    let mut report = DuplicateReport::default();
+    let mut by_stem: HashMap<_, Vec<usize>> = HashMap::new();
+    let mut by_id: HashMap<CellId, Vec<usize>> = HashMap::new();
+    for (i, (path, id)) in cells.iter().enumerate() {
+        for &j in by_stem.get(&stem(path)).into_iter().flatten() {
+            if cells[j].1 != *id {
+                warn!(
+                    "{} and {} share a name but hold different cells",
+                    cells[j].0.display(),
+                    path.display()
+                );
+                report
+                    .same_name_different_content
+                    .push((cells[j].0.clone(), path.clone()));
+            }
+        }
+        for &j in by_id.get(id).into_iter().flatten() {
+            if stem(&cells[j].0) != stem(path) {
+                warn!(
+                    "{} and {} hold the same cell {}",
+                    cells[j].0.display(),
+                    path.display(),
+                    id
+                );
+                report
+                    .same_content_different_name
+                    .push((cells[j].0.clone(), path.clone()));
+            }
+        }
+        by_stem.entry(stem(path)).or_default().push(i);
+        by_id.entry(*id).or_default().push(i);
+    }
+    report
+}
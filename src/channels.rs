@@ -1,9 +1,15 @@
+use std::collections::BTreeMap;
+
+use crate::stochastic::GatingMode;
+use crate::units::{MicroFaradPerCm2, OhmCm, SiemensPerCm2};
+
 ///
 /// The channels defined the dynamics that take place within the compartment
 /// Some based on: https://nrn.readthedocs.io/en/9.0.0/tutorials/scripting-neuron-basics.html#Biophysical-mechanisms
 ///
 
-#[derive(Default)]
+#[derive(Default, Clone)]
+#[non_exhaustive]
 pub enum ChannelType {
     #[default]
     Unspecified,
@@ -12,24 +18,256 @@ pub enum ChannelType {
     HodgkinHuxley(HodgkinHuxley),
 }
 
-#[derive(Default)]
+/// Ion a current is carried by, for charge accounting
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Ion {
+    Na,
+    K,
+    Ca,
+    Cl,
+    /// Mixed or unspecified carriers, e.g. a leak
+    NonSpecific,
+}
+
+impl Ion {
+    /// Charge number, None for `NonSpecific`
+    pub fn valence(self) -> Option<i32> {
+        match self {
+            Ion::Na | Ion::K => Some(1),
+            Ion::Ca => Some(2),
+            Ion::Cl => Some(-1),
+            Ion::NonSpecific => None,
+        }
+    }
+}
+
+#[derive(Default, Clone)]
+#[non_exhaustive]
 pub struct Channel {
-    channel_type: ChannelType,
-    resistance: f64,
-    capacitance: f64,
-    conductance: f64,
+    pub channel_type: ChannelType,
+    /// Axial resistivity, in Ω·cm
+    pub resistance: f64,
+    /// Specific membrane capacitance, per unit membrane area, in µF/cm²
+    pub capacitance: f64,
+    /// Membrane conductance density, per unit membrane area, in S/cm²
+    pub conductance: f64,
+    /// Reversal potentials of this compartment by ion, in mV, over the
+    /// cell's; see `Compartments::set_reversal_at`
+    pub reversals: BTreeMap<Ion, f64>,
+}
+
+impl Channel {
+    /// Passive membrane with the units spelled out
+    pub fn passive(
+        resistance: OhmCm,
+        capacitance: MicroFaradPerCm2,
+        conductance: SiemensPerCm2,
+    ) -> Channel {
+        Channel {
+            channel_type: ChannelType::Passive(Passive::new()),
+            resistance: resistance.value(),
+            capacitance: capacitance.value(),
+            conductance: conductance.value(),
+            reversals: BTreeMap::new(),
+        }
+    }
 }
 
+/// How a mechanism's state and current evolve. Each `Simulation::step`
+/// first calls `update` on every gated mechanism at the voltages the last
+/// step ended with, and then solves for the new voltages with the currents
+/// the updated gates give.
 pub trait Dynamics {
-    fn new() -> Self {}
-    fn propagate(self) -> () {}
-    fn update(self) -> () {}
+    fn new() -> Self;
+    /// Advances `gates` over `dt` ms at a voltage held at `v`. Mechanisms
+    /// without gates leave them alone.
+    fn update(&self, _gates: &mut [f64; 3], _v: f64, _dt: f64) {}
+    /// Membrane current density at `v` with `gates`, in mA/cm², outward
+    /// positive. `conductance` is the channel's own, in S/cm².
+    fn current(&self, _gates: &[f64; 3], _v: f64, _conductance: f64) -> f64 {
+        0.0
+    }
+}
+
+/// Squid axon sodium, potassium and leak currents, as in NEURON's `hh`
+/// mechanism at its reference temperature of 6.3 °C. Conductance densities
+/// are in S/cm², reversal potentials in mV.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HodgkinHuxley {
+    pub gnabar: f64,
+    pub gkbar: f64,
+    pub gl: f64,
+    pub ena: f64,
+    pub ek: f64,
+    pub el: f64,
+    /// Keep `ena` and `ek` as set here wherever an ion-scoped reversal
+    /// applies, see `Compartments::set_reversal`
+    pub explicit_ena: bool,
+    pub explicit_ek: bool,
+    /// Take `ena` and `ek` from the concentrations each step, see
+    /// `IonAccumulation::update_reversals`, instead of keeping them fixed
+    pub use_dynamic_reversal: bool,
+    /// How the sodium and potassium gates evolve: as continuous fractions,
+    /// the default, or as finite numbers of channels opening at random
+    pub gating: GatingMode,
+    /// Single channel conductances, in pS. With `gnabar` and `gkbar` they
+    /// set how many channels a compartment has when gating is stochastic.
+    pub gamma_na: f64,
+    pub gamma_k: f64,
 }
 
-pub struct HodgkinHuxley {}
+impl Default for HodgkinHuxley {
+    fn default() -> Self {
+        HodgkinHuxley {
+            gnabar: 0.12,
+            gkbar: 0.036,
+            gl: 0.0003,
+            ena: 50.0,
+            ek: -77.0,
+            el: -54.3,
+            explicit_ena: false,
+            explicit_ek: false,
+            use_dynamic_reversal: false,
+            gating: GatingMode::Deterministic,
+            gamma_na: 20.0,
+            gamma_k: 20.0,
+        }
+    }
+}
+
+impl Dynamics for HodgkinHuxley {
+    fn new() -> Self {
+        Self::default()
+    }
+
+    fn update(&self, gates: &mut [f64; 3], v: f64, dt: f64) {
+        HodgkinHuxley::step_gates(gates, v, dt);
+    }
+
+    fn current(&self, gates: &[f64; 3], v: f64, _conductance: f64) -> f64 {
+        self.currents(gates, v).iter().sum()
+    }
+}
+
+/// `x / (exp(x / y) - 1)`, continued through its removable singularity at 0
+fn vtrap(x: f64, y: f64) -> f64 {
+    if (x / y).abs() < 1e-6 {
+        y * (1.0 - x / y / 2.0)
+    } else {
+        x / ((x / y).exp() - 1.0)
+    }
+}
+
+impl HodgkinHuxley {
+    /// Carriers of the currents `currents` returns, in the same order
+    pub const IONS: [Ion; 3] = [Ion::Na, Ion::K, Ion::NonSpecific];
+
+    /// Reversal potential of the current carried by `ion`, None if no
+    /// current here is
+    pub fn reversal(&self, ion: Ion) -> Option<f64> {
+        match ion {
+            Ion::Na => Some(self.ena),
+            Ion::K => Some(self.ek),
+            _ => None,
+        }
+    }
 
-impl Dynamics for HodgkinHuxley {}
+    /// Sets `ena` or `ek` and keeps it over ion-scoped reversals. Fails for
+    /// ions `hh` has no current of; the leak is set through `el`.
+    pub fn set_explicit_reversal(&mut self, ion: Ion, e: f64) -> Result<(), String> {
+        match ion {
+            Ion::Na => (self.ena, self.explicit_ena) = (e, true),
+            Ion::K => (self.ek, self.explicit_ek) = (e, true),
+            _ => return Err(format!("hh carries no {:?} current", ion)),
+        }
+        Ok(())
+    }
+
+    /// `(ion, reversal, explicit)` of each specific ion's current
+    pub(crate) fn reversal_slots(&mut self) -> [(Ion, &mut f64, bool); 2] {
+        [
+            (Ion::Na, &mut self.ena, self.explicit_ena),
+            (Ion::K, &mut self.ek, self.explicit_ek),
+        ]
+    }
+
+    /// Opening and closing rates `(alpha, beta)` of the m, h and n gates at
+    /// `v`, in 1/ms
+    pub fn rates(v: f64) -> [(f64, f64); 3] {
+        [
+            (
+                0.1 * vtrap(-(v + 40.0), 10.0),
+                4.0 * (-(v + 65.0) / 18.0).exp(),
+            ),
+            (
+                0.07 * (-(v + 65.0) / 20.0).exp(),
+                1.0 / ((-(v + 35.0) / 10.0).exp() + 1.0),
+            ),
+            (
+                0.01 * vtrap(-(v + 55.0), 10.0),
+                0.125 * (-(v + 65.0) / 80.0).exp(),
+            ),
+        ]
+    }
+
+    /// Values the m, h and n gates settle at when held at `v`
+    pub fn steady_state(v: f64) -> [f64; 3] {
+        HodgkinHuxley::rates(v).map(|(a, b)| a / (a + b))
+    }
+
+    /// Advances gates `[m, h, n]` by `dt` at a voltage held at `v`, exactly
+    /// for piecewise constant voltage
+    pub fn step_gates(gates: &mut [f64; 3], v: f64, dt: f64) {
+        for (x, (a, b)) in gates.iter_mut().zip(HodgkinHuxley::rates(v)) {
+            let x_inf = a / (a + b);
+            *x = x_inf + (*x - x_inf) * (-(a + b) * dt).exp();
+        }
+    }
+
+    /// Sodium, potassium and leak current densities at `v` with gates
+    /// `[m, h, n]`, in mA/cm², outward positive
+    pub fn currents(&self, gates: &[f64; 3], v: f64) -> [f64; 3] {
+        let [m, h, n] = *gates;
+        [
+            self.gnabar * m.powi(3) * h * (v - self.ena),
+            self.gkbar * n.powi(4) * (v - self.ek),
+            self.gl * (v - self.el),
+        ]
+    }
+}
+
+#[derive(Default, Clone)]
 pub struct Extracellular {}
-impl Dynamics for Extracellular {}
-pub struct Passive {}
-impl Dynamics for Passive {}
+
+impl Dynamics for Extracellular {
+    fn new() -> Self {
+        Self {}
+    }
+}
+
+/// Leak current through `Channel::conductance`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Passive {
+    /// Reversal potential, in mV; NEURON's `e_pas`
+    pub e: f64,
+}
+
+impl Passive {
+    pub const ION: Ion = Ion::NonSpecific;
+}
+
+impl Default for Passive {
+    fn default() -> Self {
+        Passive { e: -70.0 }
+    }
+}
+
+impl Dynamics for Passive {
+    fn new() -> Self {
+        Self::default()
+    }
+
+    fn current(&self, _gates: &[f64; 3], v: f64, conductance: f64) -> f64 {
+        conductance * (v - self.e)
+    }
+}
@@ -0,0 +1,395 @@
+//! Merging runs of short compartments into fewer, longer ones, for quick
+//! approximate models.
+
+use std::f64::consts::PI;
+use std::mem;
+
+use crate::channels::{ChannelType, Dynamics, Passive};
+use crate::compartments::{Compartment, Compartments};
+use crate::geometry;
+use crate::index_map::{IndexMap, Link, translated};
+use crate::sections::build_sections;
+use crate::spines::translated_spines;
+use crate::swc_reader::NodeFlags;
+
+/// What `Compartments::apply_thin_neurite_policy` does with compartments
+/// below the radius threshold
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThinNeuritePolicy {
+    /// Swap active mechanisms for a passive membrane with the same
+    /// conductance density
+    MakePassive,
+    /// Fold them into the nearest ancestor that is thick enough, keeping
+    /// their membrane area but not their axial resistance. Ones whose
+    /// ancestor has no membrane area, such as a point soma, stay. Refused
+    /// if something is attached to one of them.
+    MergeIntoParent,
+    /// Fail, listing them
+    Error,
+}
+
+impl Compartments {
+    /// Merges consecutive compartments until at most `max_compartments` are
+    /// left (not counting the dummy root), keeping merged pieces as even in
+    /// length as possible. See `coarsen_by_length` for what merging keeps.
+    /// Fails without changing anything if branch points and boundaries alone
+    /// already need more compartments than that. Returns the new count.
+    pub fn coarsen(&mut self, max_compartments: usize) -> Result<usize, String> {
+        let runs = self.runs();
+        let fixed = self.components.len() - 1 - runs.iter().map(Vec::len).sum::<usize>();
+        if fixed + runs.len() > max_compartments {
+            return Err(format!(
+                "Cannot coarsen below {} compartments, asked for {}",
+                fixed + runs.len(),
+                max_compartments
+            ));
+        }
+        // The greedy grouping only gets coarser as the length limit grows, so
+        // bisect for the smallest limit that fits
+        let count = |limit: f64| fixed + self.groups(&runs, limit).len();
+        let (mut lo, mut hi) = (0.0, self.components.iter().map(|c| c.length).sum::<f64>());
+        if count(lo) > max_compartments {
+            for _ in 0..100 {
+                let mid = (lo + hi) / 2.0;
+                if count(mid) > max_compartments {
+                    lo = mid;
+                } else {
+                    hi = mid;
+                }
+            }
+            lo = hi;
+        }
+        let groups = self.groups(&runs, lo);
+        self.merge(groups, merge_into, true)?;
+        self.log(
+            "coarsen",
+            &[
+                ("max_compartments", max_compartments.into()),
+                ("count", (self.components.len() - 1).into()),
+            ],
+        );
+        Ok(self.components.len() - 1)
+    }
+
+    /// Merges consecutive compartments into pieces of at most `max_length`.
+    /// Merging never crosses a branch point, the soma, or a change in
+    /// structure type, flags or channel type. A merged compartment keeps the
+    /// total membrane area, the total axial resistance, and the total
+    /// capacitance and membrane conductance of its parts. Returns the new
+    /// count, not counting the dummy root.
+    pub fn coarsen_by_length(&mut self, max_length: f64) -> usize {
+        let runs = self.runs();
+        let groups = self.groups(&runs, max_length);
+        self.merge(groups, merge_into, true)
+            .expect("merging end to end keeps every compartment");
+        self.log(
+            "coarsen_by_length",
+            &[
+                ("max_length", max_length.into()),
+                ("count", (self.components.len() - 1).into()),
+            ],
+        );
+        self.components.len() - 1
+    }
+
+    /// Guards against compartments thinner than `min_radius`, whose tiny
+    /// areas and large axial resistances make active mechanisms numerically
+    /// brutal. The soma is never touched. Returns the indices, before any
+    /// renumbering, of the compartments the policy applied to; compartments
+    /// that absorb others, or are made passive, are flagged.
+    pub fn apply_thin_neurite_policy(
+        &mut self,
+        min_radius: f64,
+        policy: ThinNeuritePolicy,
+    ) -> Result<Vec<usize>, String> {
+        let thin: Vec<usize> = (2..self.components.len())
+            .filter(|&i| self.components[i].diam / 2.0 < min_radius)
+            .collect();
+        let affected = match policy {
+            ThinNeuritePolicy::Error => {
+                if thin.is_empty() {
+                    return Ok(thin);
+                }
+                let names: Vec<&str> = thin
+                    .iter()
+                    .map(|&i| self.components[i].name.as_str())
+                    .collect();
+                return Err(format!(
+                    "Compartments thinner than {} um radius: {}",
+                    min_radius,
+                    names.join(", ")
+                ));
+            }
+            ThinNeuritePolicy::MakePassive => {
+                let mut changed = Vec::new();
+                for &i in &thin {
+                    let c = &mut self.components[i];
+                    if matches!(c.channel.channel_type, ChannelType::HodgkinHuxley(_)) {
+                        c.channel.channel_type = ChannelType::Passive(Passive::new());
+                        c.flags |= NodeFlags::MECHANISM_PASSIVATED;
+                        changed.push(i);
+                    }
+                }
+                changed
+            }
+            ThinNeuritePolicy::MergeIntoParent => {
+                // Parents come first, so each thin compartment's ancestor is
+                // settled by the time it is reached
+                let mut target: Vec<usize> = (0..self.components.len()).collect();
+                for &i in &thin {
+                    target[i] = target[self.components[i].parent_idxs[0] as usize];
+                }
+                // A point soma has no membrane to carry the extra area, so
+                // whatever would end up there stays put
+                let thin: Vec<usize> = thin
+                    .into_iter()
+                    .filter(|&i| self.components[target[i]].membrane_area() > 0.0)
+                    .collect();
+                let mut groups: Vec<Vec<usize>> = Vec::new();
+                let mut group_of = vec![usize::MAX; self.components.len()];
+                for &i in &thin {
+                    let t = target[i];
+                    if group_of[t] == usize::MAX {
+                        group_of[t] = groups.len();
+                        groups.push(vec![t]);
+                    }
+                    groups[group_of[t]].push(i);
+                }
+                self.merge(groups, absorb, false)?;
+                thin
+            }
+        };
+        self.log(
+            "thin_neurite_policy",
+            &[
+                ("min_radius", min_radius.into()),
+                ("policy", format!("{:?}", policy).into()),
+                ("affected", affected.len().into()),
+            ],
+        );
+        Ok(affected)
+    }
+
+    fn mergeable(&self, parent: usize, child: usize) -> bool {
+        let (p, c) = (&self.components[parent], &self.components[child]);
+        parent > 1
+            && p.children_idxs.len() == 1
+            && c.parent_idxs.len() == 1
+            && p.structure == c.structure
+            && p.flags == c.flags
+            && mem::discriminant(&p.channel.channel_type)
+                == mem::discriminant(&c.channel.channel_type)
+    }
+
+    /// Maximal chains of two or more compartments that could be merged, in
+    /// index order
+    fn runs(&self) -> Vec<Vec<usize>> {
+        let mut runs = Vec::new();
+        for start in 2..self.components.len() {
+            let parent = self.components[start].parent_idxs[0] as usize;
+            if self.mergeable(parent, start) {
+                continue;
+            }
+            let mut run = vec![start];
+            let mut last = start;
+            while let [child] = self.components[last].children_idxs[..] {
+                if !self.mergeable(last, child as usize) {
+                    break;
+                }
+                last = child as usize;
+                run.push(last);
+            }
+            if run.len() > 1 {
+                runs.push(run);
+            }
+        }
+        runs
+    }
+
+    /// Greedy split of each run into consecutive groups of at most `limit`
+    /// total length; a compartment longer than that stays on its own
+    fn groups(&self, runs: &[Vec<usize>], limit: f64) -> Vec<Vec<usize>> {
+        let mut groups = Vec::new();
+        for run in runs {
+            let mut group: Vec<usize> = Vec::new();
+            let mut length = 0.0;
+            for &idx in run {
+                let l = self.components[idx].length;
+                if !group.is_empty() && length + l > limit {
+                    groups.push(mem::take(&mut group));
+                    length = 0.0;
+                }
+                group.push(idx);
+                length += l;
+            }
+            groups.push(group);
+        }
+        groups
+    }
+
+    /// Replaces each group by one compartment and renumbers everything,
+    /// keeping parents ahead of their children. Groups `end_to_end` run
+    /// along the cable; otherwise the first member absorbs the rest, which
+    /// lose their place. Fails, changing nothing, if that would strand an
+    /// attachment.
+    pub(crate) fn merge(
+        &mut self,
+        groups: Vec<Vec<usize>>,
+        fold: fn(&mut Compartment, &[&Compartment]),
+        end_to_end: bool,
+    ) -> Result<(), String> {
+        let n = self.components.len();
+        // Old index -> first member of its group
+        let mut head: Vec<usize> = (0..n).collect();
+        let mut members: Vec<Vec<usize>> = (0..n).map(|i| vec![i]).collect();
+        for group in groups {
+            for &idx in &group {
+                head[idx] = group[0];
+            }
+            let first = group[0];
+            members[first] = group;
+        }
+        let heads: Vec<usize> = (0..n).filter(|&i| head[i] == i).collect();
+        let mut new_idx = vec![0u64; n];
+        for (k, &h) in heads.iter().enumerate() {
+            for &m in &members[h] {
+                new_idx[m] = k as u64;
+            }
+        }
+
+        let mut links = Vec::new();
+        for (k, &h) in heads.iter().enumerate() {
+            let group = if end_to_end { &members[h][..] } else { &[h] };
+            let total: f64 = group.iter().map(|&i| self.components[i].length).sum();
+            let mut start = 0.0;
+            for (j, &i) in group.iter().enumerate() {
+                let l = self.components[i].length;
+                let (new_from, new_to) = if total > 0.0 {
+                    (start / total, (start + l) / total)
+                } else {
+                    let n = group.len() as f64;
+                    (j as f64 / n, (j + 1) as f64 / n)
+                };
+                links.push(Link {
+                    old: i,
+                    old_from: 0.0,
+                    old_to: 1.0,
+                    new: k,
+                    new_from,
+                    new_to,
+                });
+                start += l;
+            }
+        }
+        let map = IndexMap::from_links(links, n, heads.len());
+        let attachments = translated(&self.attachments, &map)?;
+        let spines = translated_spines(&self.spines, &map)?;
+
+        let mut old = mem::take(&mut self.components);
+        let mut old_provenance = mem::take(&mut self.provenance);
+        for (k, &h) in heads.iter().enumerate() {
+            let group = &members[h];
+            // Children of any member that are not members themselves
+            let children: Vec<u64> = group
+                .iter()
+                .flat_map(|&i| &old[i].children_idxs)
+                .map(|&c| new_idx[c as usize])
+                .filter(|&c| c != k as u64)
+                .collect();
+            let mut merged = mem::take(&mut old[h]);
+            if group.len() > 1 {
+                let rest: Vec<&Compartment> = group[1..].iter().map(|&i| &old[i]).collect();
+                fold(&mut merged, &rest);
+            }
+            merged.idx = k as u64;
+            if k > 1 {
+                merged.name = format!("Compartment: {}", k);
+            }
+            merged.parent_idxs = merged
+                .parent_idxs
+                .iter()
+                .map(|&p| new_idx[p as usize])
+                .collect();
+            merged.children_idxs = children;
+            self.components.push(merged);
+            self.provenance.push(
+                group
+                    .iter()
+                    .flat_map(|&i| mem::take(&mut old_provenance[i]))
+                    .collect(),
+            );
+        }
+        self.sections = build_sections(&self.components);
+        self.record_transform(map, attachments, spines);
+        Ok(())
+    }
+}
+
+/// Folds `parts` into `first`, which comes before them along the cable
+fn merge_into(first: &mut Compartment, parts: &[&Compartment]) {
+    let all = || std::iter::once(&*first).chain(parts.iter().copied());
+    let length: f64 = all().map(|c| c.length).sum();
+    let area: f64 = all().map(Compartment::membrane_area).sum();
+    let resistance: f64 = all().map(Compartment::axial_resistance).sum();
+    let capacitance: f64 = all().map(Compartment::capacitance).sum();
+    let conductance: f64 = all().map(Compartment::membrane_conductance).sum();
+    let resistivity = if length > 0.0 {
+        all().map(|c| c.channel.resistance * c.length).sum::<f64>() / length
+    } else {
+        first.channel.resistance
+    };
+    let mean_diam = if length > 0.0 {
+        all().map(|c| c.diam * c.length).sum::<f64>() / length
+    } else {
+        first.diam
+    };
+    let distal = parts.last().map_or(first.distal, |c| c.distal);
+
+    // The diameter fixes the axial resistance; the area factor then makes up
+    // the membrane area
+    let diam = if resistivity > 0.0 && resistance > 0.0 {
+        (4.0 * resistivity * length / (PI * resistance)).sqrt()
+    } else {
+        mean_diam
+    };
+    let area_factor = if diam > 0.0 && length > 0.0 {
+        area / (PI * diam * length)
+    } else {
+        first.area_factor
+    };
+    let span = geometry::sub(distal, first.proximal);
+    let span_length = geometry::norm(span);
+    if span_length > 0.0 {
+        let tangent = geometry::scale(span, 1.0 / span_length);
+        first.normal = geometry::transport(first.normal, first.tangent, tangent);
+        first.tangent = tangent;
+    }
+
+    first.length = length;
+    first.diam = diam;
+    first.area_factor = area_factor;
+    first.distal = distal;
+    first.channel.resistance = resistivity;
+    if area > 0.0 {
+        first.channel.capacitance = capacitance / area;
+        first.channel.conductance = conductance / area;
+    }
+}
+
+/// Folds side branches `parts` into `first`, which keeps its own cable
+/// geometry and takes on their membrane area through its area factor
+fn absorb(first: &mut Compartment, parts: &[&Compartment]) {
+    let all = || std::iter::once(&*first).chain(parts.iter().copied());
+    let area: f64 = all().map(Compartment::membrane_area).sum();
+    let capacitance: f64 = all().map(Compartment::capacitance).sum();
+    let conductance: f64 = all().map(Compartment::membrane_conductance).sum();
+    let own = PI * first.diam * first.length;
+    if own > 0.0 {
+        first.area_factor = area / own;
+    }
+    if area > 0.0 {
+        first.channel.capacitance = capacitance / area;
+        first.channel.conductance = conductance / area;
+    }
+    first.flags |= NodeFlags::THIN_MERGED;
+}
@@ -0,0 +1,128 @@
+//! Stable, machine-readable codes for every error and warning the crate
+//! produces, so pipelines can branch on what went wrong without matching on
+//! messages.
+//!
+//! Codes are never renumbered or reused. When one is retired, its string
+//! moves to `RETIRED` so nobody hands it out again.
+
+/// How bad a code is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// One registry entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CodeInfo {
+    pub code: Code,
+    pub id: &'static str,
+    pub severity: Severity,
+    pub description: &'static str,
+}
+
+/// Defines `Code` and `REGISTRY` from one table, so a code cannot exist
+/// without its registry entry
+macro_rules! codes {
+    ($($variant:ident => ($id:literal, $severity:ident, $description:literal),)*) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        #[non_exhaustive]
+        pub enum Code {
+            $($variant,)*
+        }
+
+        /// Every code in use, in the order they were added
+        pub const REGISTRY: &[CodeInfo] = &[
+            $(CodeInfo {
+                code: Code::$variant,
+                id: $id,
+                severity: Severity::$severity,
+                description: $description,
+            },)*
+        ];
+
+        impl Code {
+            pub fn info(self) -> CodeInfo {
+                match self {
+                    $(Code::$variant => CodeInfo {
+                        code: self,
+                        id: $id,
+                        severity: Severity::$severity,
+                        description: $description,
+                    },)*
+                }
+            }
+        }
+    };
+}
+
+codes! {
+    LengthMismatch => ("E_SWC_0001_LENGTH_MISMATCH", Error, "Input arrays have different lengths"),
+    InvalidNodeId => ("E_SWC_0002_INVALID_NODE_ID", Error, "A node ID is not a positive integer"),
+    InvalidParentId => ("E_SWC_0003_INVALID_PARENT_ID", Error, "A parent ID is neither -1 nor a positive integer"),
+    ReadFailure => ("E_SWC_0004_READ_FAILURE", Error, "The input could not be read or decompressed"),
+    MissingField => ("E_SWC_0005_MISSING_FIELD", Error, "A data line has fewer than seven columns"),
+    InvalidField => ("E_SWC_0006_INVALID_FIELD", Error, "A column does not parse as the expected number"),
+    DanglingParent => ("E_SWC_0007_DANGLING_PARENT", Error, "A node refers to a parent that does not exist"),
+    DecimalComma => ("E_SWC_0008_DECIMAL_COMMA", Error, "A number uses a decimal comma and decimal_comma is off"),
+    StrictZeroRadius => ("E_SWC_0009_STRICT_ZERO_RADIUS", Error, "A non-endpoint node has zero radius in strict mode"),
+    NoRoot => ("E_SWC_0010_NO_ROOT", Error, "No node has parent -1"),
+    MalformedScale => ("E_SWC_0011_MALFORMED_SCALE", Error, "The SCALE header entry is not three numbers"),
+    ChecksumMismatch => ("E_SWC_0012_CHECKSUM_MISMATCH", Error, "The input does not match the expected sha256"),
+    Io => ("E_SWC_0013_IO", Error, "Reading or writing a file failed"),
+    DuplicateNodeId => ("E_SWC_0014_DUPLICATE_NODE_ID", Error, "Two nodes share an ID"),
+    LimitExceeded => ("E_SWC_0015_LIMIT_EXCEEDED", Error, "The input is larger or slower to read than the configured limits"),
+    NotSwc => ("E_SWC_0016_NOT_SWC", Error, "None of the first data lines parse; the input is probably not SWC"),
+    UnknownParameter => ("E_PARAM_0001_UNKNOWN", Error, "No parameter by that name"),
+    UnknownSection => ("E_PARAM_0002_UNKNOWN_SECTION", Error, "No section by that name"),
+    InvalidPosition => ("E_PARAM_0003_INVALID_POSITION", Error, "A position along a section is outside 0 to 1"),
+    ReadOnlyParameter => ("E_PARAM_0004_READ_ONLY", Error, "The parameter is derived and cannot be set"),
+    MalformedParameterRow => ("E_PARAM_0005_MALFORMED_ROW", Error, "A parameter table row does not parse"),
+    ZeroRadius => ("W_SWC_0001_ZERO_RADIUS", Warning, "Nodes with zero radius, set to 1.0"),
+    RallMismatch => ("W_MORPH_0001_RALL_MISMATCH", Warning, "A branch point is far from Rall's 3/2 power rule"),
+    UnknownStatePath => ("E_STATE_0001_UNKNOWN_PATH", Error, "A state path does not follow the path grammar"),
+    NoSuchCompartment => ("E_STATE_0002_NO_COMPARTMENT", Error, "A state path names a compartment the model does not have"),
+    StateUnavailable => ("E_STATE_0003_UNAVAILABLE", Error, "The compartment has no such state, e.g. gates on a passive membrane"),
+    ReadOnlyState => ("E_STATE_0004_READ_ONLY", Error, "The state variable can be read but not set"),
+    StateOutOfRange => ("E_STATE_0005_OUT_OF_RANGE", Error, "A value set on a state variable is outside its physical range"),
+    HighBranchingDegree => ("W_MORPH_0002_HIGH_BRANCHING_DEGREE", Warning, "A node off the soma has suspiciously many children"),
+    UnknownTag => ("E_PARAM_0006_UNKNOWN_TAG", Error, "No compartment carries that tag"),
+    MissingMechanism => ("E_PARAM_0007_MISSING_MECHANISM", Error, "The parameter belongs to a mechanism the compartment does not have"),
+    Unreachable => ("W_SWC_0002_UNREACHABLE", Warning, "Nodes not connected to the root, left out"),
+    NonPositiveRadius => ("W_MORPH_0003_NON_POSITIVE_RADIUS", Warning, "A node's radius was zero or negative in the input"),
+    RadiusOutlier => ("W_MORPH_0004_RADIUS_OUTLIER", Warning, "A radius is far from the cell's median radius"),
+    OrphanedFragment => ("W_MORPH_0005_ORPHANED_FRAGMENT", Warning, "Nodes are not connected to the root"),
+    SpacingGap => ("W_MORPH_0006_SPACING_GAP", Warning, "A segment is far longer than the cell's typical node spacing"),
+    SuspectUnits => ("W_MORPH_0007_SUSPECT_UNITS", Warning, "Radii or extent are implausible for µm"),
+    SessionClosed => ("E_SIM_0001_SESSION_CLOSED", Error, "The simulation session was closed and its state freed"),
+    Diverged => ("E_SIM_0002_DIVERGED", Error, "A voltage stopped being finite during a run"),
+    InvalidModel => ("E_SIM_0003_INVALID_MODEL", Error, "The model, or something added to it, cannot be simulated"),
+    NoCompartment => ("E_SIM_0004_NO_COMPARTMENT", Error, "An index names no compartment of the simulation"),
+    NoSpine => ("E_SIM_0005_NO_SPINE", Error, "An index names no spine of the simulation"),
+    InvalidSimulationInput => ("E_SIM_0006_INVALID_INPUT", Error, "A stimulus, probe or time window does not fit the run"),
+    IonDepleted => ("E_SIM_0007_ION_DEPLETED", Error, "An accumulated ion concentration would drop to zero"),
+    OutputFailure => ("E_SIM_0008_OUTPUT", Error, "Writing or reading streamed traces failed"),
+    SolverPanicked => ("E_SIM_0009_SOLVER_PANICKED", Error, "A solver thread panicked"),
+    StoreIo => ("E_STORE_0001_IO", Error, "Reading, writing or locking a result store failed"),
+    NotAStore => ("E_STORE_0002_NOT_A_STORE", Error, "The file is not a result store"),
+    CorruptStore => ("E_STORE_0003_CORRUPT", Error, "A stored run fails its checksum or does not decode"),
+    UnknownRun => ("E_STORE_0004_UNKNOWN_RUN", Error, "No run by that id in the store"),
+    InvalidStoreEntry => ("E_STORE_0005_INVALID_ENTRY", Error, "A tag or parameter name cannot be stored"),
+    MalformedRecipe => ("E_DATA_0001_MALFORMED_RECIPE", Error, "A standardization recipe line does not parse"),
+    DatasetIo => ("E_DATA_0002_IO", Error, "Listing, reading or writing a dataset file failed"),
+    UnsupportedFile => ("E_DATA_0003_UNSUPPORTED_FILE", Error, "A dataset file is in a format that cannot be read"),
+    OutputCollision => ("E_DATA_0004_OUTPUT_COLLISION", Error, "Two inputs would be standardized to the same output"),
+    BundleFailure => ("E_DATA_0005_BUNDLE", Error, "Writing a bundle failed"),
+    RejectedCell => ("E_DATA_0006_REJECTED", Error, "The recipe cannot standardize the cell"),
+    StandardizePanicked => ("E_DATA_0007_PANICKED", Error, "Standardizing a file panicked"),
+}
+
+/// Codes that were once in use and must not be handed out again
+pub const RETIRED: &[&str] = &[];
+
+impl Code {
+    /// The stable string form, e.g. `E_SWC_0007_DANGLING_PARENT`
+    pub fn id(self) -> &'static str {
+        self.info().id
+    }
+}
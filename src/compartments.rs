@@ -1,29 +1,145 @@
 use std::collections::HashMap;
 
-use crate::channels::Channel;
+use std::f64::consts::PI;
+use std::sync::Arc;
 
-use crate::swc_reader::Node;
+use crate::cell_id::CellId;
+use crate::channels::Channel;
+use crate::filter::NodeFilter;
+use crate::geometry;
+use crate::index_map::{Attachment, AttachmentKind, IndexMap};
+use crate::reversal::IonReversals;
+use crate::run_log::{LogValue, RunLog};
+use crate::sections::{Section, build_sections};
+use crate::solver::Simulation;
+use crate::spines::Spine;
+use crate::swc_reader::{Node, NodeFlags, Skeleton, StructureIdentifier};
 
-#[derive(Default)]
+#[derive(Clone)]
+#[non_exhaustive]
 pub struct Compartment {
-    pub(crate) name: String,             // Name string for easier identification
-    idx: u64,                 // Index into our compartments list
-    parent_idxs: Vec<u64>,   // Index into our compartments lists
-    children_idxs: Vec<u64>, // Index into our compartments lists
+    pub name: String,            // Name string for easier identification
+    pub idx: u64,                // Index into our compartments list
+    pub parent_idxs: Vec<u64>,   // Index into our compartments lists
+    pub children_idxs: Vec<u64>, // Index into our compartments lists
+
+    pub length: f64,
+    pub diam: f64,
 
-    length: f64,
-    diam: f64,
+    pub channel: Channel,
 
-    channel: Channel,
+    /// Union of the flags of the nodes this compartment was built from
+    pub flags: NodeFlags,
+    pub structure: StructureIdentifier,
+    /// Multiplier on the membrane area, e.g. to account for spines missing
+    /// from the reconstruction. See `Compartments::apply_spine_correction`.
+    pub area_factor: f64,
+
+    /// Ends of the centerline, from the parent side to the child side
+    pub proximal: [f64; 3],
+    pub distal: [f64; 3],
+    /// Unit direction of the centerline and a unit normal carried along the
+    /// tree without twisting; see `Compartments::local_frame`
+    pub(crate) tangent: [f64; 3],
+    pub(crate) normal: [f64; 3],
+}
+
+impl Default for Compartment {
+    fn default() -> Self {
+        Compartment {
+            name: String::new(),
+            idx: 0,
+            parent_idxs: Vec::new(),
+            children_idxs: Vec::new(),
+            length: 0.0,
+            diam: 0.0,
+            channel: Channel::default(),
+            flags: NodeFlags::empty(),
+            structure: StructureIdentifier::Undefined,
+            area_factor: 1.0,
+            proximal: [0.0; 3],
+            distal: [0.0; 3],
+            tangent: [1.0, 0.0, 0.0],
+            normal: [0.0, 1.0, 0.0],
+        }
+    }
 }
 
 impl Compartment {
+    pub fn set_channel(&mut self, channel: Channel) {
+        self.channel = channel;
+    }
+
+    /// Lateral area of the cylinder, scaled by `area_factor`
+    pub fn membrane_area(&self) -> f64 {
+        PI * self.diam * self.length * self.area_factor
+    }
+
+    /// Total membrane capacitance: specific capacitance times membrane area
+    pub fn capacitance(&self) -> f64 {
+        self.channel.capacitance * self.membrane_area()
+    }
+
+    /// Total membrane conductance: conductance density times membrane area
+    pub fn membrane_conductance(&self) -> f64 {
+        self.channel.conductance * self.membrane_area()
+    }
+
+    /// Axial resistance along the compartment. Depends on the cross section
+    /// only, so `area_factor` does not enter here.
+    pub fn axial_resistance(&self) -> f64 {
+        self.axial_resistance_with(self.channel.resistance)
+    }
 
-    fn set_channel() -> () {}
+    /// `axial_resistance` for axial resistivity `resistivity` instead of
+    /// the channel's own
+    pub(crate) fn axial_resistance_with(&self, resistivity: f64) -> f64 {
+        let cross_section = PI * self.diam * self.diam / 4.0;
+        resistivity * self.length / cross_section
+    }
+}
+
+/// Local coordinate frame on a compartment's centerline. `tangent`, `normal`
+/// and `binormal` are orthonormal and right-handed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frame {
+    pub origin: [f64; 3],
+    pub tangent: [f64; 3],
+    pub normal: [f64; 3],
+    pub binormal: [f64; 3],
 }
 
+/// Stretch of the segment leading up to a skeleton node, as fractions of its
+/// arc length from the parent (0.0) to the node itself (1.0)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NodeSpan {
+    pub node_id: u64,
+    pub from: f64,
+    pub to: f64,
+}
+
+#[derive(Clone)]
 pub struct Compartments {
     pub components: Vec<Compartment>,
+    /// Skeleton nodes each compartment was built from, in order along the
+    /// compartment and indexed like `components`. The dummy root covers none.
+    pub provenance: Vec<Vec<NodeSpan>>,
+    /// Identity of the skeleton these were built from, if built from one
+    pub cell_id: Option<CellId>,
+    /// Kept in step with the topology, see `Compartments::sections`
+    pub(crate) sections: Vec<Section>,
+    /// Where changes to the model are recorded, see `with_run_log`
+    pub(crate) run_log: Option<RunLog>,
+    /// Probes, stimuli and the like, see `attach`
+    pub(crate) attachments: Vec<Attachment>,
+    /// Two-compartment spines hung off the tree, see `add_spine`
+    pub(crate) spines: Vec<Spine>,
+    /// See `index_map` and `last_index_map`
+    pub(crate) index_map: IndexMap,
+    pub(crate) last_index_map: Option<IndexMap>,
+    /// Cell-scope reversal potentials and how mechanisms that disagree with
+    /// them are treated, see `set_reversal`
+    pub(crate) ion_reversals: IonReversals,
 }
 
 fn square(x: f64) -> f64 {
@@ -31,15 +147,51 @@ fn square(x: f64) -> f64 {
 }
 
 /// Assumes simple direct path between the nodes
-fn compute_length(curr: &Node, other: &Node) -> f64{
+fn compute_length(curr: &Node, other: &Node) -> f64 {
     let x_diff = square(curr.x_pos - other.x_pos);
     let y_diff = square(curr.y_pos - other.y_pos);
     let z_diff = square(curr.z_pos - other.z_pos);
-    return (x_diff + y_diff + z_diff).sqrt()
+    (x_diff + y_diff + z_diff).sqrt()
 }
 
 impl Compartments {
-    fn from_sorted_nodes(
+    pub fn from_skeleton(skeleton: Skeleton) -> Compartments {
+        let cell_id = skeleton.cell_id();
+        Compartments {
+            cell_id: Some(cell_id),
+            ..Compartments::from_sorted_nodes(
+                Arc::unwrap_or_clone(skeleton.nodes),
+                Arc::unwrap_or_clone(skeleton.parent_child_map),
+                Arc::unwrap_or_clone(skeleton.child_parent_map),
+            )
+        }
+    }
+
+    /// Records every later change to the model (coarsening, spine
+    /// correction, parameter settings) in `log`, starting with the model as
+    /// it stands
+    pub fn with_run_log(mut self, log: RunLog) -> Compartments {
+        self.run_log = Some(log);
+        let cell_id = self.cell_id.map_or("none".to_owned(), |id| id.to_string());
+        self.log(
+            "compartments",
+            &[
+                ("count", (self.components.len() - 1).into()),
+                ("cell_id", cell_id.into()),
+            ],
+        );
+        self
+    }
+
+    pub(crate) fn log(&self, op: &str, args: &[(&str, LogValue)]) {
+        if let Some(log) = &self.run_log {
+            log.record(op, args);
+        }
+    }
+
+    /// Builds the compartment list from the output of `swc_reader`. Index 0 is
+    /// a dummy root, so the soma ends up at index 1.
+    pub fn from_sorted_nodes(
         sorted_nodes: Vec<Node>,
         parent_child_map: HashMap<u64, Vec<u64>>,
         child_parent_map: HashMap<u64, Vec<u64>>,
@@ -52,95 +204,305 @@ impl Compartments {
             .collect();
 
         let mut components = Vec::new();
+        let mut provenance = vec![Vec::new()];
         // Add a dummy root to make it so that the soma (element 1) maps correctly
         // and has the parent being the dummy
-        let dummy_root = Compartment{
+        let dummy_root = Compartment {
             name: "Dummy Root".to_owned(),
-            idx: 0,
-            parent_idxs: Vec::new(),
-            children_idxs: Vec::new(),
-            length: 0.0,
-            diam: 0.0,
-            channel: Channel::default()
+            ..Default::default()
         };
 
         // First pass - we populate the network "going forward" to fill up the parents
         components.push(dummy_root);
-        for (i, node) in sorted_nodes.iter().enumerate(){
-            let name;
-            if i == 0{
-                name = "Compartment: 1 (Soma)".to_owned();
+        for (i, node) in sorted_nodes.iter().enumerate() {
+            let name = if i == 0 {
+                "Compartment: 1 (Soma)".to_owned()
             } else {
-                name = format!("Compartment: {}", (i+1).to_string());
-            }
+                format!("Compartment: {}", i + 1)
+            };
 
+            let distal = [node.x_pos, node.y_pos, node.z_pos];
             // Compute length from parent
-            let length = if node.parent_id == 0 {
+            let (length, proximal, tangent, normal) = if node.parent_id == node.node_id {
                 // Soma: parent is dummy root, no meaningful length between them
-                0.0
+                let root = &components[0];
+                (0.0, distal, root.tangent, root.normal)
             } else {
                 // Look up parent by its node_id, not by direct indexing
                 let parent_idx = node_id_to_idx[&node.parent_id];
                 let parent_node = &sorted_nodes[parent_idx];
-                compute_length(node, parent_node)
+                let parent = &components[parent_idx + 1];
+                let length = compute_length(node, parent_node);
+                let proximal = [parent_node.x_pos, parent_node.y_pos, parent_node.z_pos];
+                if length > 0.0 {
+                    let tangent = geometry::scale(geometry::sub(distal, proximal), 1.0 / length);
+                    let normal = geometry::transport(parent.normal, parent.tangent, tangent);
+                    (length, proximal, tangent, normal)
+                } else {
+                    (length, proximal, parent.tangent, parent.normal)
+                }
             };
 
+            // Node IDs are shifted by one for the dummy root, which also
+            // stands in for the parent of the soma
             let parents = child_parent_map
                 .get(&node.node_id)
-                .cloned()
-                .unwrap_or_default();
-            let children= parent_child_map
+                .into_iter()
+                .flatten()
+                .map(|&p| if p == node.node_id { 0 } else { p + 1 })
+                .collect();
+            let children = parent_child_map
                 .get(&node.node_id)
-                .cloned()
-                .unwrap_or_default();
+                .into_iter()
+                .flatten()
+                .filter(|&&c| c != node.node_id)
+                .map(|&c| c + 1)
+                .collect();
 
-            let compartment = Compartment{
+            let compartment = Compartment {
                 name,
                 idx: components.len() as u64,
                 parent_idxs: parents,
                 children_idxs: children,
                 length,
                 diam: node.radius * 2.0,
-                channel: Channel::default()
+                channel: Channel::default(),
+                flags: node.flags,
+                structure: node.structured_identifier,
+                area_factor: 1.0,
+                proximal,
+                distal,
+                tangent,
+                normal,
             };
 
             components.push(compartment);
+            provenance.push(vec![NodeSpan {
+                node_id: node.node_id,
+                from: 0.0,
+                to: 1.0,
+            }]);
+        }
+        if components.len() > 1 {
+            components[0].children_idxs.push(1);
         }
 
-        return Compartments {components}
+        Compartments {
+            sections: build_sections(&components),
+            index_map: IndexMap::identity(components.len()),
+            components,
+            provenance,
+            cell_id: None,
+            run_log: None,
+            attachments: Vec::new(),
+            spines: Vec::new(),
+            last_index_map: None,
+            ion_reversals: IonReversals::default(),
+        }
     }
 
-    ///# Reasonable default values for most models.
-    /// Taken from https://jaxley.readthedocs.io/en/stable/how_to_guide/set_ncomp.html
-    // frequency = 100.0
-    // d_lambda = 0.1  # Larger -> more coarse-grained.
-
-    // for branch in cell.branches:
-    //     diameter = 2 * branch.nodes["radius"].to_numpy()[0]
-    //     c_m = branch.nodes["capacitance"].to_numpy()[0]
-    //     r_a = branch.nodes["axial_resistivity"].to_numpy()[0]
-    //     l = branch.nodes["length"].to_numpy()[0]
+    /// Axial coupling conductance of every parent-child edge, as
+    /// `(parent_idx, child_idx, g)` sorted by child. The resistance between
+    /// two neighbours is half of each one's own axial resistance, centre to
+    /// centre, each side with its own diameter and resistivity. A branch
+    /// point is then a node with no volume where the currents from the parent
+    /// and all children sum to zero; each child couples to the parent
+    /// independently, so sibling order does not matter.
+    ///
+    /// Edges to the dummy root are left out. Two zero-length neighbours, e.g.
+    /// a soma point and a duplicated node, couple with infinite conductance.
+    pub fn axial_conductances(&self) -> Vec<(usize, usize, f64)> {
+        self.axial_conductances_with(|idx| self.components[idx].channel.resistance)
+    }
 
-    //     lambda_f = 1e5 * np.sqrt(diameter / (4 * np.pi * frequency * c_m * r_a))
-    //     ncomp = int((l / (d_lambda * lambda_f) + 0.9) / 2) * 2 + 1
-    //     branch.set_ncomp(ncomp, initialize=False)
+    /// `axial_conductances` with the axial resistivity of each compartment
+    /// given by `resistivity`, e.g. from a cell's own channels
+    pub(crate) fn axial_conductances_with(
+        &self,
+        resistivity: impl Fn(usize) -> f64,
+    ) -> Vec<(usize, usize, f64)> {
+        let mut edges = Vec::new();
+        for (child, c) in self.components.iter().enumerate().skip(1) {
+            for &parent in &c.parent_idxs {
+                let parent = parent as usize;
+                if parent == 0 {
+                    continue;
+                }
+                let p = &self.components[parent];
+                let half = (p.axial_resistance_with(resistivity(parent))
+                    + c.axial_resistance_with(resistivity(child)))
+                    / 2.0;
+                edges.push((parent, child, 1.0 / half));
+            }
+        }
+        edges
+    }
 
-    fn d_lambda_rule(mut self, frequency: f64, d_lambda: f64) -> Compartments {
-        let new_compartments: Vec<Compartment>= Vec::new();
-        for compartment in self.components{
-            
+    /// Frame at `fraction` (0 at the proximal end, 1 at the distal end) along
+    /// compartment `idx`. The normal is carried from the soma outwards with
+    /// the smallest possible turn at every node, so it never flips between
+    /// neighbouring compartments.
+    pub fn local_frame(&self, idx: usize, fraction: f64) -> Result<Frame, String> {
+        if idx == 0 || idx >= self.components.len() {
+            return Err(format!("No compartment at index {}", idx));
         }
+        if !(0.0..=1.0).contains(&fraction) {
+            return Err(format!("Fraction must be within [0, 1], got {}", fraction));
+        }
+        let c = &self.components[idx];
+        let along = geometry::scale(geometry::sub(c.distal, c.proximal), fraction);
+        Ok(Frame {
+            origin: geometry::add(c.proximal, along),
+            tangent: c.tangent,
+            normal: c.normal,
+            binormal: geometry::cross(c.tangent, c.normal),
+        })
+    }
 
-        return Compartments {components: new_compartments}
+    /// Point on the membrane at `fraction` along compartment `idx`, turned
+    /// `azimuth` radians from the frame's normal towards its binormal
+    pub fn surface_point(
+        &self,
+        idx: usize,
+        fraction: f64,
+        azimuth: f64,
+    ) -> Result<[f64; 3], String> {
+        let frame = self.local_frame(idx, fraction)?;
+        let radius = self.components[idx].diam / 2.0;
+        let offset = geometry::add(
+            geometry::scale(frame.normal, radius * azimuth.cos()),
+            geometry::scale(frame.binormal, radius * azimuth.sin()),
+        );
+        Ok(geometry::add(frame.origin, offset))
+    }
 
+    /// The compartment holding skeleton node `node_id`, and where along it the
+    /// node sits as a fraction of the compartment's length. None if no
+    /// compartment covers the node.
+    pub fn node_to_compartment(&self, node_id: u64) -> Option<(usize, f64)> {
+        self.provenance.iter().enumerate().find_map(|(idx, spans)| {
+            let covered: f64 = spans.iter().map(|s| s.to - s.from).sum();
+            let mut before = 0.0;
+            for span in spans {
+                before += span.to - span.from;
+                // The node point is the distal end of its own segment
+                if span.node_id == node_id && span.to == 1.0 {
+                    let fraction = if covered > 0.0 { before / covered } else { 0.0 };
+                    return Some((idx, fraction));
+                }
+            }
+            None
+        })
     }
 
-    fn attach_stimuli(mut compartments, stimulus: Vec<f64>) -> {
-        todo!("Attach a stimuli pattern to a specific compartment. HAS to be of equal length to T/dt")
+    /// Total membrane capacitance of every compartment, indexed like
+    /// `components`
+    pub fn capacitances(&self) -> Vec<f64> {
+        self.components
+            .iter()
+            .map(Compartment::capacitance)
+            .collect()
     }
 
-    fn simulate(dt: f64, T:f64) -> (){
-        todo!("")
+    /// Scales the membrane area of every compartment matching `filter` by
+    /// `area_factor`, which scales its capacitance and membrane conductances
+    /// but leaves axial properties alone. The dummy root is never touched.
+    ///
+    /// Calling this again on the same compartments multiplies: applying 2.0
+    /// twice leaves an `area_factor` of 4.0. Returns the number of compartments
+    /// changed.
+    pub fn apply_spine_correction(
+        &mut self,
+        filter: &NodeFilter,
+        area_factor: f64,
+    ) -> Result<usize, String> {
+        if !area_factor.is_finite() || area_factor <= 0.0 {
+            return Err(format!(
+                "Area factor must be positive and finite, got {}",
+                area_factor
+            ));
+        }
+        let mut changed = 0;
+        for compartment in self.components.iter_mut().skip(1) {
+            if filter.matches_parts(compartment.structure, compartment.flags) {
+                compartment.area_factor *= area_factor;
+                changed += 1;
+            }
+        }
+        self.log(
+            "spine_correction",
+            &[
+                ("filter", format!("{:?}", filter).into()),
+                ("area_factor", area_factor.into()),
+                ("changed", changed.into()),
+            ],
+        );
+        Ok(changed)
+    }
+
+    /// Injects `stimulus[s]`, in nA, into the middle of compartment `idx`
+    /// over step `s` of every later `simulate`, on top of anything attached
+    /// before. It follows the compartment like any other attachment, and
+    /// must have one value per step of the run.
+    pub fn attach_stimuli(&mut self, idx: usize, stimulus: Vec<f64>) -> Result<(), String> {
+        if let Some(i) = stimulus.iter().position(|i| !i.is_finite()) {
+            return Err(format!("Stimulus value {} is {}", i, stimulus[i]));
+        }
+        let count = self
+            .attachments
+            .iter()
+            .filter(|a| a.kind == AttachmentKind::Stimulus)
+            .count();
+        let name = format!("stimuli[{}]", count);
+        self.attach(AttachmentKind::Stimulus, &name, idx, 0.5, 0.5)?;
+        self.attachments.last_mut().unwrap().waveform = Some(stimulus);
+        Ok(())
     }
-}
 
+    /// Runs the cell from rest for `t` ms in steps of `dt` with the stimuli
+    /// from `attach_stimuli`, and returns the voltage of every compartment
+    /// at the start and after each step, see `SimulationResult::voltages`.
+    /// With holding currents from `hold_at`, rest is where they settle the
+    /// cell, and they are injected on every step.
+    /// Fails if a stimulus was attached without a waveform.
+    pub fn simulate(&self, dt: f64, t: f64) -> Result<Vec<Vec<f64>>, String> {
+        if !t.is_finite() || t < 0.0 {
+            return Err(format!(
+                "Duration must be finite and non-negative, got {}",
+                t
+            ));
+        }
+        let mut simulation = Simulation::new(self, dt).map_err(|e| e.to_string())?;
+        let steps = (t / dt).round() as usize;
+        if (steps as f64 * dt - t).abs() > 1e-9 * t.max(dt) {
+            return Err(format!(
+                "Duration {} ms is not a whole number of {} ms steps",
+                t, dt
+            ));
+        }
+        let stimuli = self
+            .attachments
+            .iter()
+            .filter(|a| a.kind == AttachmentKind::Stimulus)
+            .map(|a| {
+                let waveform = a.waveform.as_ref().ok_or_else(|| {
+                    format!(
+                        "Stimulus '{}' on compartment {} has no waveform; attach it with attach_stimuli",
+                        a.name, a.idx
+                    )
+                })?;
+                Ok((a.idx, waveform.iter().map(|i| i * a.weight).collect()))
+            })
+            .collect::<Result<Vec<(usize, Vec<f64>)>, String>>()?;
+        self.settle_holding(&mut simulation)?;
+        let holding = self
+            .holding_currents()
+            .into_iter()
+            .map(|(idx, current)| (idx, vec![current; steps]));
+        let stimuli: Vec<(usize, Vec<f64>)> = stimuli.into_iter().chain(holding).collect();
+        Ok(simulation
+            .run(steps, &stimuli)
+            .map_err(|e| e.to_string())?
+            .voltages)
+    }
+}
@@ -0,0 +1,239 @@
+//! Counting conventions of morphometrics, for matching features computed by
+//! other tools.
+//!
+//! Tools agree on what a feature means but not on the details: whether cable
+//! between soma nodes is dendrite, whether a neurite starts at the soma or at
+//! its first point, whether a stem is branch order 0 or 1, and whether path
+//! distances go to node centers or segment midpoints. `Conventions` names
+//! these choices and `features::morphometrics` follows them. Only nodes of
+//! type soma are treated specially; a skeleton without any is measured the
+//! same under every convention but branch order.
+//!
+//! Presets: `native`, what this crate's functions have always done and the
+//! default, and `neurom`, the conventions of NeuroM's neurite features.
+//! `MORPHOMETRICS` lists every feature with its tolerance for comparisons
+//! and the conventions it depends on; a feature depends on nothing else.
+
+use crate::swc_reader::StructureIdentifier;
+
+/// A choice `Conventions` makes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Convention {
+    SomaAsCable,
+    StemsFromSoma,
+    BranchOrder,
+    PathDistance,
+}
+
+/// How branches are numbered going away from the soma
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BranchOrder {
+    /// Branch points between the root and the branch's distal end, the root
+    /// included when it branches, as `features::branches` counts
+    BranchPoints,
+    /// Branches leaving the soma are this order, and every branch point
+    /// further out adds one
+    FromStem(usize),
+}
+
+/// Where path distances are measured to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathDistance {
+    /// Every neurite node
+    NodeCenters,
+    /// The midpoint of every neurite segment, as NEURON measures to the
+    /// center of a segment
+    SegmentMidpoints,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Conventions {
+    pub name: &'static str,
+    /// Segments between soma nodes count as cable, and soma nodes past the
+    /// root end branches like any other node. Otherwise the soma is a point
+    /// neurites leave from, whichever of its nodes they attach to.
+    pub soma_as_cable: bool,
+    /// Each neurite starts at the soma node it attaches to, so the link to
+    /// its first node is part of its first branch. Otherwise it starts at
+    /// its first node and the link counts nowhere.
+    pub stems_from_soma: bool,
+    pub branch_order: BranchOrder,
+    pub path_distance: PathDistance,
+    /// Known differences from the tool the preset follows, bugs on either
+    /// side included
+    pub notes: &'static [&'static str],
+}
+
+impl Default for Conventions {
+    fn default() -> Self {
+        Conventions::native()
+    }
+}
+
+impl Conventions {
+    /// What `features::branches` and `Morphometry::total_length` count
+    pub fn native() -> Conventions {
+        Conventions {
+            name: "native",
+            soma_as_cable: true,
+            stems_from_soma: true,
+            branch_order: BranchOrder::BranchPoints,
+            path_distance: PathDistance::NodeCenters,
+            notes: &[
+                "Branch order counts the root only where it branches, so the stem of a \
+                 one-stem cell is order 0 and the stems of any other cell order 1. Kept so \
+                 feature vectors stay comparable with earlier ones; FromStem(1) numbers \
+                 every stem alike.",
+            ],
+        }
+    }
+
+    /// NeuroM's neurite features: soma nodes are the soma, not cable, each
+    /// neurite starts at its first point, stems are order 0 and path
+    /// distances go to points
+    pub fn neurom() -> Conventions {
+        Conventions {
+            name: "neurom",
+            soma_as_cable: false,
+            stems_from_soma: false,
+            branch_order: BranchOrder::FromStem(0),
+            path_distance: PathDistance::NodeCenters,
+            notes: &[
+                "The values in data/conventions/reference.csv come from reference.py next \
+                 to it, which follows NeuroM's definitions without importing NeuroM. \
+                 Regenerate them with NeuroM itself before relying on agreement beyond \
+                 those fixtures.",
+            ],
+        }
+    }
+
+    /// Every preset, `native` first
+    pub fn presets() -> [Conventions; 2] {
+        [Conventions::native(), Conventions::neurom()]
+    }
+
+    /// The preset called `name`
+    pub fn preset(name: &str) -> Result<Conventions, String> {
+        Conventions::presets()
+            .into_iter()
+            .find(|c| c.name == name)
+            .ok_or_else(|| {
+                let names: Vec<&str> = Conventions::presets().iter().map(|c| c.name).collect();
+                format!(
+                    "Unknown conventions '{}'; use one of {}",
+                    name,
+                    names.join(", ")
+                )
+            })
+    }
+
+    /// Whether the segment from a `parent` node to a `child` node of these
+    /// types is cable
+    pub(crate) fn is_cable(&self, child: StructureIdentifier, parent: StructureIdentifier) -> bool {
+        match (child, parent) {
+            (StructureIdentifier::Soma, _) => self.soma_as_cable,
+            (_, StructureIdentifier::Soma) => self.stems_from_soma,
+            _ => true,
+        }
+    }
+
+    /// The choices where `self` and `other` differ
+    pub fn differences(&self, other: &Conventions) -> Vec<Convention> {
+        [
+            (
+                Convention::SomaAsCable,
+                self.soma_as_cable != other.soma_as_cable,
+            ),
+            (
+                Convention::StemsFromSoma,
+                self.stems_from_soma != other.stems_from_soma,
+            ),
+            (
+                Convention::BranchOrder,
+                self.branch_order != other.branch_order,
+            ),
+            (
+                Convention::PathDistance,
+                self.path_distance != other.path_distance,
+            ),
+        ]
+        .into_iter()
+        .filter(|&(_, differs)| differs)
+        .map(|(c, _)| c)
+        .collect()
+    }
+}
+
+/// A feature of `features::morphometrics`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FeatureSpec {
+    pub name: &'static str,
+    /// Largest difference, in the feature's unit, that still agrees with
+    /// another tool: zero for counts, rounding of sums in another order for
+    /// the rest
+    pub tolerance: f64,
+    pub depends_on: &'static [Convention],
+}
+
+/// The features of `features::morphometrics`, in order. Lengths and
+/// distances are in µm.
+pub const MORPHOMETRICS: [FeatureSpec; 10] = [
+    FeatureSpec {
+        name: "total_length",
+        tolerance: 1e-9,
+        depends_on: &[Convention::SomaAsCable, Convention::StemsFromSoma],
+    },
+    FeatureSpec {
+        name: "stem_count",
+        tolerance: 0.0,
+        depends_on: &[],
+    },
+    FeatureSpec {
+        name: "branch_count",
+        tolerance: 0.0,
+        depends_on: &[Convention::SomaAsCable],
+    },
+    FeatureSpec {
+        name: "tip_count",
+        tolerance: 0.0,
+        depends_on: &[Convention::SomaAsCable],
+    },
+    FeatureSpec {
+        name: "max_branch_order",
+        tolerance: 0.0,
+        depends_on: &[Convention::SomaAsCable, Convention::BranchOrder],
+    },
+    FeatureSpec {
+        name: "mean_branch_order",
+        tolerance: 1e-12,
+        depends_on: &[Convention::SomaAsCable, Convention::BranchOrder],
+    },
+    FeatureSpec {
+        name: "mean_branch_length",
+        tolerance: 1e-9,
+        depends_on: &[Convention::SomaAsCable, Convention::StemsFromSoma],
+    },
+    FeatureSpec {
+        name: "mean_tortuosity",
+        tolerance: 1e-12,
+        depends_on: &[Convention::SomaAsCable, Convention::StemsFromSoma],
+    },
+    FeatureSpec {
+        name: "max_path_distance",
+        tolerance: 1e-9,
+        depends_on: &[
+            Convention::SomaAsCable,
+            Convention::StemsFromSoma,
+            Convention::PathDistance,
+        ],
+    },
+    FeatureSpec {
+        name: "mean_path_distance",
+        tolerance: 1e-9,
+        depends_on: &[
+            Convention::SomaAsCable,
+            Convention::StemsFromSoma,
+            Convention::PathDistance,
+        ],
+    },
+];
@@ -0,0 +1,58 @@
+//! Python entry points kept for a while after a rename or move, so scripts
+//! written against an older layout keep working and say what to change.
+//!
+//! Each old name stays as a thin wrapper around its replacement. The first
+//! call from a given line of Python raises a `CompartmentDeprecationWarning`
+//! there, a `DeprecationWarning` carrying `replacement` and `removal`;
+//! later calls from the same line stay quiet. `compartment_rs.deprecations()`
+//! lists every shim, so downstream CI can fail on any use.
+//!
+//! The table is checked against `data/golden/deprecations.txt`, so removing
+//! a shim or moving its removal means updating that file too.
+
+/// An old Python name and where it went
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shim {
+    /// Fully qualified old name, e.g. `compartment_rs.load_dataset`
+    pub name: &'static str,
+    /// Fully qualified name to call instead
+    pub replacement: &'static str,
+    /// Version that deprecated it
+    pub since: &'static str,
+    /// First version without it
+    pub removal: &'static str,
+}
+
+/// Every shim still in place, in the order they were added
+pub const SHIMS: &[Shim] = &[
+    Shim {
+        name: "compartment_rs.standardize",
+        replacement: "compartment_rs.io.standardize",
+        since: "0.1.0",
+        removal: "0.2.0",
+    },
+    Shim {
+        name: "compartment_rs.load_dataset",
+        replacement: "compartment_rs.io.load_dataset",
+        since: "0.1.0",
+        removal: "0.2.0",
+    },
+];
+
+/// The shim for the old name `name`, fully qualified
+pub fn shim(name: &str) -> Option<&'static Shim> {
+    SHIMS.iter().find(|s| s.name == name)
+}
+
+/// One tab-separated line per shim: name, replacement, since, removal
+pub fn to_text() -> String {
+    SHIMS
+        .iter()
+        .map(|s| {
+            format!(
+                "{}\t{}\t{}\t{}\n",
+                s.name, s.replacement, s.since, s.removal
+            )
+        })
+        .collect()
+}
@@ -0,0 +1,253 @@
+//! A description of the model as it stands, for the methods section of a
+//! paper: size and membrane area per region, the passive parameters and the
+//! mechanisms in use. Everything is read off the live compartments, so the
+//! description cannot drift from what was actually configured.
+//!
+//! Regions follow the NEURON section names: `soma`, `axon`, `dend` and
+//! `apic`. Reversal potentials are listed per ion wherever the currents of
+//! a region use one, as resolved from ion-scoped and mechanism settings.
+//! Distribution rules a profile set parameters from are quoted as
+//! written, since the ranges alone do not say how values vary.
+
+use std::fmt::Write;
+
+use crate::biophysics::BiophysicsProfile;
+use crate::channels::ChannelType;
+use crate::compartments::Compartments;
+use crate::geometry::stable_sum;
+use crate::reversal::REVERSALS;
+use crate::sections;
+use crate::swc_reader::format_float;
+
+/// Parameters reported per region, with their units
+const PARAMETERS: [(&str, &str); 4] = [
+    ("capacitance", "µF/cm²"),
+    ("conductance", "S/cm²"),
+    ("resistance", "Ω·cm"),
+    ("area_factor", ""),
+];
+
+/// Smallest and largest value of a parameter over a region
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Range {
+    pub min: f64,
+    pub max: f64,
+}
+
+impl Range {
+    fn render(&self) -> String {
+        if self.min == self.max {
+            format_float(self.min)
+        } else {
+            format!("{} to {}", format_float(self.min), format_float(self.max))
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegionDescription {
+    /// Section name prefix, e.g. `dend`
+    pub name: String,
+    pub compartments: usize,
+    pub sections: usize,
+    /// In µm², `area_factor` included
+    pub membrane_area: f64,
+    /// `(name, unit, range)` for each passive parameter, then each ion
+    /// reversal the region has
+    pub parameters: Vec<(String, String, Range)>,
+    /// Mechanism name and the number of compartments it is inserted in
+    pub mechanisms: Vec<(String, usize)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelDescription {
+    pub cell_id: Option<String>,
+    pub compartments: usize,
+    pub sections: usize,
+    /// In µm², `area_factor` included
+    pub membrane_area: f64,
+    /// In order of each region's first section
+    pub regions: Vec<RegionDescription>,
+    /// Distribution rules, one `BiophysicsProfile::to_text` line each
+    pub rules: Vec<String>,
+}
+
+/// NEURON's name for the mechanism
+pub(crate) fn mechanism_name(channel_type: &ChannelType) -> &'static str {
+    match channel_type {
+        ChannelType::Unspecified => "none",
+        ChannelType::Passive(_) => "pas",
+        ChannelType::Extracellular(_) => "extracellular",
+        ChannelType::HodgkinHuxley(_) => "hh",
+    }
+}
+
+impl ModelDescription {
+    /// Markdown with a summary line and one table per region
+    pub fn to_markdown(&self) -> String {
+        let mut text = String::from("## Model\n\n");
+        if let Some(id) = &self.cell_id {
+            let _ = writeln!(text, "Cell `{}`.", id);
+        }
+        let _ = writeln!(
+            text,
+            "{} compartments in {} sections, with a total membrane area of {} µm².",
+            self.compartments,
+            self.sections,
+            format_float(self.membrane_area)
+        );
+        for region in &self.regions {
+            let _ = write!(
+                text,
+                "\n### {}\n\n{} compartments in {} sections, {} µm².\n\n",
+                region.name,
+                region.compartments,
+                region.sections,
+                format_float(region.membrane_area)
+            );
+            text.push_str("| Parameter | Value | Unit |\n|---|---|---|\n");
+            for (name, unit, range) in &region.parameters {
+                let _ = writeln!(text, "| {} | {} | {} |", name, range.render(), unit);
+            }
+            let mechanisms: Vec<String> = region
+                .mechanisms
+                .iter()
+                .map(|(name, count)| format!("{} ({} compartments)", name, count))
+                .collect();
+            let _ = writeln!(text, "\nMechanisms: {}.", mechanisms.join(", "));
+        }
+        if !self.rules.is_empty() {
+            text.push_str("\n### Distributions\n\n");
+            for rule in &self.rules {
+                let _ = writeln!(text, "- `{}`", rule);
+            }
+        }
+        text
+    }
+
+    /// One `(key, value)` row per fact, keys dotted by region, e.g.
+    /// `dend.conductance.max`
+    pub fn to_table(&self) -> Vec<(String, String)> {
+        let mut rows = Vec::new();
+        if let Some(id) = &self.cell_id {
+            rows.push(("cell_id".to_owned(), id.clone()));
+        }
+        rows.push(("compartments".to_owned(), self.compartments.to_string()));
+        rows.push(("sections".to_owned(), self.sections.to_string()));
+        rows.push(("membrane_area".to_owned(), format_float(self.membrane_area)));
+        for region in &self.regions {
+            let key = |k: &str| format!("{}.{}", region.name, k);
+            rows.push((key("compartments"), region.compartments.to_string()));
+            rows.push((key("sections"), region.sections.to_string()));
+            rows.push((key("membrane_area"), format_float(region.membrane_area)));
+            for (name, _, range) in &region.parameters {
+                rows.push((key(&format!("{}.min", name)), format_float(range.min)));
+                rows.push((key(&format!("{}.max", name)), format_float(range.max)));
+            }
+            for (name, count) in &region.mechanisms {
+                rows.push((key(&format!("mechanism.{}", name)), count.to_string()));
+            }
+        }
+        for (i, rule) in self.rules.iter().enumerate() {
+            rows.push((format!("rule.{}", i + 1), rule.clone()));
+        }
+        rows
+    }
+}
+
+impl Compartments {
+    /// Describes the model from its current compartments. Parameter ranges
+    /// are the extremes of `parameter_map` over each region.
+    pub fn describe(&self) -> ModelDescription {
+        let maps: Vec<Vec<f64>> = PARAMETERS
+            .iter()
+            .map(|(name, _)| {
+                self.parameter_map(name)
+                    .expect("described parameters are all known")
+            })
+            .collect();
+
+        let reversals: Vec<(&str, Vec<f64>)> = REVERSALS
+            .iter()
+            .map(|&(ion, name)| (name, self.effective_reversals(ion)))
+            .collect();
+
+        let mut regions: Vec<RegionDescription> = Vec::new();
+        let mut members: Vec<Vec<usize>> = Vec::new();
+        for section in &self.sections {
+            let name = sections::prefix(section.structure);
+            let k = match regions.iter().position(|r| r.name == name) {
+                Some(k) => k,
+                None => {
+                    regions.push(RegionDescription {
+                        name: name.to_owned(),
+                        compartments: 0,
+                        sections: 0,
+                        membrane_area: 0.0,
+                        parameters: Vec::new(),
+                        mechanisms: Vec::new(),
+                    });
+                    members.push(Vec::new());
+                    regions.len() - 1
+                }
+            };
+            regions[k].sections += 1;
+            members[k].extend(&section.compartments);
+        }
+
+        for (region, idxs) in regions.iter_mut().zip(&members) {
+            region.compartments = idxs.len();
+            region.membrane_area =
+                stable_sum(idxs.iter().map(|&i| self.components[i].membrane_area()));
+            region.parameters = PARAMETERS
+                .iter()
+                .zip(&maps)
+                .map(|((name, unit), map)| {
+                    let values = idxs.iter().map(|&i| map[i]);
+                    let range = Range {
+                        min: values.clone().fold(f64::INFINITY, f64::min),
+                        max: values.fold(f64::NEG_INFINITY, f64::max),
+                    };
+                    (name.to_string(), unit.to_string(), range)
+                })
+                .collect();
+            for (name, map) in &reversals {
+                let values = idxs.iter().map(|&i| map[i]).filter(|v| !v.is_nan());
+                if values.clone().next().is_some() {
+                    let range = Range {
+                        min: values.clone().fold(f64::INFINITY, f64::min),
+                        max: values.fold(f64::NEG_INFINITY, f64::max),
+                    };
+                    region
+                        .parameters
+                        .push((name.to_string(), "mV".to_owned(), range));
+                }
+            }
+            for &i in idxs {
+                let name = mechanism_name(&self.components[i].channel.channel_type);
+                match region.mechanisms.iter_mut().find(|(m, _)| m == name) {
+                    Some((_, count)) => *count += 1,
+                    None => region.mechanisms.push((name.to_owned(), 1)),
+                }
+            }
+            region.mechanisms.sort();
+        }
+
+        ModelDescription {
+            cell_id: self.cell_id.map(|id| id.to_string()),
+            compartments: self.components.len() - 1,
+            sections: self.sections.len(),
+            membrane_area: stable_sum(self.components[1..].iter().map(|c| c.membrane_area())),
+            regions,
+            rules: Vec::new(),
+        }
+    }
+
+    /// `describe`, quoting the rules of the profile the model was set up with
+    pub fn describe_with(&self, profile: &BiophysicsProfile) -> ModelDescription {
+        ModelDescription {
+            rules: profile.to_text().lines().map(str::to_owned).collect(),
+            ..self.describe()
+        }
+    }
+}
@@ -0,0 +1,529 @@
+//! NEURON's d_lambda rule as a reusable artifact. How many compartments a
+//! section gets depends only on its geometry, its axial resistivity and its
+//! capacitance, so the result can be computed once and reused while channel
+//! densities are being fitted.
+//!
+//! A `Discretization` carries a hash of exactly those inputs. It applies to
+//! any model with the same hash, whatever its mechanisms, and is recomputed
+//! by `DiscretizationCache` only when the hash changes.
+
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::fmt::Write;
+
+use sha2::{Digest, Sha256};
+
+use crate::compartments::{Compartment, Compartments, NodeSpan};
+use crate::geometry;
+use crate::index_map::{IndexMap, Link, translated};
+use crate::sections::{Section, build_sections};
+use crate::spines::translated_spines;
+use crate::swc_reader::{NodeFlags, format_float};
+
+/// Compartment count and split positions of one section
+#[derive(Debug, Clone, PartialEq)]
+pub struct SectionSplit {
+    pub name: String,
+    /// NEURON's `nseg`, always odd
+    pub ncomp: usize,
+    /// Interior boundaries between compartments as fractions of the
+    /// section's length, `ncomp - 1` of them in increasing order
+    pub splits: Vec<f64>,
+}
+
+/// Compartment layout for every section of a model
+#[derive(Debug, Clone, PartialEq)]
+pub struct Discretization {
+    /// Hash of the inputs this was computed from, see `input_hash`
+    pub input_hash: u64,
+    /// In Hz
+    pub frequency: f64,
+    pub d_lambda: f64,
+    /// In the order of `Compartments::sections`
+    pub sections: Vec<SectionSplit>,
+}
+
+/// Hash of everything the d_lambda rule depends on: the section layout, and
+/// each compartment's length, diameter, area factor, axial resistivity and
+/// capacitance, plus the rule's own settings. Mechanisms and conductances
+/// do not enter.
+pub fn input_hash(compartments: &Compartments, frequency: f64, d_lambda: f64) -> u64 {
+    let mut text = format!("{} {}\n", format_float(frequency), format_float(d_lambda));
+    for section in compartments.sections() {
+        text.push_str(&section.name);
+        for &i in &section.compartments {
+            let c = &compartments.components[i];
+            let _ = write!(
+                text,
+                " {},{},{},{},{}",
+                format_float(c.length),
+                format_float(c.diam),
+                format_float(c.area_factor),
+                format_float(c.channel.resistance),
+                format_float(c.channel.capacitance)
+            );
+        }
+        text.push('\n');
+    }
+    let digest = Sha256::digest(text.as_bytes());
+    u64::from_be_bytes(digest[..8].try_into().unwrap())
+}
+
+/// Electrotonic length of `section` at `frequency`, adding up `length / λ_f`
+/// compartment by compartment. Spines and other area factors load the cable
+/// like extra capacitance.
+fn ac_length(
+    compartments: &Compartments,
+    section: &Section,
+    frequency: f64,
+) -> Result<f64, String> {
+    let mut total = 0.0;
+    for &i in &section.compartments {
+        let c = &compartments.components[i];
+        if c.length == 0.0 {
+            continue;
+        }
+        let (ra, cm) = (c.channel.resistance, c.channel.capacitance * c.area_factor);
+        if !(ra > 0.0 && cm > 0.0 && c.diam > 0.0) {
+            return Err(format!(
+                "{} needs a positive diameter, Ra and cm for the d_lambda rule, got {}, {} and {}",
+                c.name, c.diam, ra, cm
+            ));
+        }
+        let lambda = 1e5 * (c.diam / (4.0 * PI * frequency * ra * cm)).sqrt();
+        total += c.length / lambda;
+    }
+    Ok(total)
+}
+
+impl Discretization {
+    /// Applies the d_lambda rule to every section: `ncomp` is the smallest
+    /// odd number that keeps each compartment within `d_lambda` of the AC
+    /// length constant at `frequency`, as in NEURON's `fixnseg.hoc`, and
+    /// compartments are equally long. 100 Hz and 0.1 are the usual choices.
+    pub fn compute(
+        compartments: &Compartments,
+        frequency: f64,
+        d_lambda: f64,
+    ) -> Result<Discretization, String> {
+        if !(frequency > 0.0 && frequency.is_finite()) {
+            return Err(format!("Frequency must be positive, got {}", frequency));
+        }
+        if !(d_lambda > 0.0 && d_lambda.is_finite()) {
+            return Err(format!("d_lambda must be positive, got {}", d_lambda));
+        }
+        let sections = compartments
+            .sections()
+            .iter()
+            .map(|section| {
+                let length = ac_length(compartments, section, frequency)?;
+                let ncomp = ((length / d_lambda + 0.9) / 2.0) as usize * 2 + 1;
+                Ok(SectionSplit {
+                    name: section.name.clone(),
+                    ncomp,
+                    splits: (1..ncomp).map(|k| k as f64 / ncomp as f64).collect(),
+                })
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Discretization {
+            input_hash: input_hash(compartments, frequency, d_lambda),
+            frequency,
+            d_lambda,
+            sections,
+        })
+    }
+
+    /// Whether this still applies to `compartments` as they stand
+    pub fn is_valid_for(&self, compartments: &Compartments) -> bool {
+        self.input_hash == input_hash(compartments, self.frequency, self.d_lambda)
+    }
+
+    /// Plain text, one header line and then one line per section with its
+    /// name, `ncomp` and split positions. Numbers round-trip exactly.
+    pub fn to_text(&self) -> String {
+        let mut text = format!(
+            "discretization {:016x} {} {}\n",
+            self.input_hash,
+            format_float(self.frequency),
+            format_float(self.d_lambda)
+        );
+        for s in &self.sections {
+            let _ = write!(text, "{} {}", s.name, s.ncomp);
+            for x in &s.splits {
+                let _ = write!(text, " {}", format_float(*x));
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Reads back the output of `to_text`
+    pub fn parse(text: &str) -> Result<Discretization, String> {
+        let mut lines = text.lines().enumerate();
+        let header: Vec<&str> = lines
+            .next()
+            .map(|(_, l)| l.split_whitespace().collect())
+            .unwrap_or_default();
+        let [_, hash, frequency, d_lambda] = header[..] else {
+            return Err("Missing discretization header".to_owned());
+        };
+        if header[0] != "discretization" {
+            return Err(format!("Not a discretization: '{}'", header.join(" ")));
+        }
+        let number = |line: usize, v: &str| {
+            v.parse::<f64>()
+                .map_err(|_| format!("Line {}: '{}' is not a number", line + 1, v))
+        };
+        let mut sections = Vec::new();
+        for (i, line) in lines {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [name, ncomp, splits @ ..] = &fields[..] else {
+                return Err(format!("Line {}: expected a section name and ncomp", i + 1));
+            };
+            let ncomp: usize = ncomp
+                .parse()
+                .map_err(|_| format!("Line {}: '{}' is not a count", i + 1, ncomp))?;
+            let splits = splits
+                .iter()
+                .map(|v| number(i, v))
+                .collect::<Result<Vec<f64>, String>>()?;
+            if ncomp == 0 || splits.len() != ncomp - 1 {
+                return Err(format!(
+                    "Line {}: {} compartments need {} splits, got {}",
+                    i + 1,
+                    ncomp,
+                    ncomp.saturating_sub(1),
+                    splits.len()
+                ));
+            }
+            sections.push(SectionSplit {
+                name: name.to_string(),
+                ncomp,
+                splits,
+            });
+        }
+        Ok(Discretization {
+            input_hash: u64::from_str_radix(hash, 16)
+                .map_err(|_| format!("Invalid input hash '{}'", hash))?,
+            frequency: number(0, frequency)?,
+            d_lambda: number(0, d_lambda)?,
+            sections,
+        })
+    }
+}
+
+/// Keeps the last discretization and recomputes it only when its inputs
+/// change
+#[derive(Debug, Clone, Default)]
+pub struct DiscretizationCache {
+    cached: Option<Discretization>,
+    computations: usize,
+}
+
+impl DiscretizationCache {
+    pub fn new() -> DiscretizationCache {
+        DiscretizationCache::default()
+    }
+
+    /// The discretization of `compartments`, reused if still valid
+    pub fn get(
+        &mut self,
+        compartments: &Compartments,
+        frequency: f64,
+        d_lambda: f64,
+    ) -> Result<&Discretization, String> {
+        let hash = input_hash(compartments, frequency, d_lambda);
+        if self.cached.as_ref().map(|d| d.input_hash) != Some(hash) {
+            self.cached = Some(Discretization::compute(compartments, frequency, d_lambda)?);
+            self.computations += 1;
+        }
+        Ok(self.cached.as_ref().unwrap())
+    }
+
+    /// How many times the rule has actually been run
+    pub fn computations(&self) -> usize {
+        self.computations
+    }
+}
+
+/// The piece `[from, to]` of compartment `idx`, as fractions of its length
+#[derive(Clone, Copy)]
+struct Part {
+    idx: usize,
+    from: f64,
+    to: f64,
+}
+
+impl Compartments {
+    /// Rebuilds the model with the compartments `discretization` lays out.
+    /// Each new compartment keeps the membrane area, axial resistance,
+    /// capacitance and membrane conductance of the stretch of cable it
+    /// covers, like `coarsen` does, and takes its mechanism from the old
+    /// compartment it overlaps most. Provenance is carried over, assuming
+    /// each old compartment's nodes were spread along it in proportion to
+    /// their spans, which is exact for compartments built one per node.
+    ///
+    /// Fails if the discretization was computed for different inputs.
+    pub fn build_with(&self, discretization: &Discretization) -> Result<Compartments, String> {
+        if !discretization.is_valid_for(self) {
+            return Err(format!(
+                "Discretization {:016x} is stale for this model; compute a new one",
+                discretization.input_hash
+            ));
+        }
+        let names = discretization.sections.iter().map(|s| &s.name);
+        if !names.eq(self.sections.iter().map(|s| &s.name)) {
+            return Err("Discretization sections do not match the model's".to_owned());
+        }
+
+        let mut components = vec![Compartment {
+            name: "Dummy Root".to_owned(),
+            ..Default::default()
+        }];
+        let mut provenance = vec![Vec::new()];
+        let mut links = vec![Link {
+            old: 0,
+            old_from: 0.0,
+            old_to: 1.0,
+            new: 0,
+            new_from: 0.0,
+            new_to: 1.0,
+        }];
+        // Old compartment ending a section -> new compartment ending it
+        let mut section_end: HashMap<usize, usize> = HashMap::new();
+        for (section, split) in self.sections.iter().zip(&discretization.sections) {
+            let first = section.compartments[0];
+            let parent = match self.components[first].parent_idxs.first() {
+                Some(&p) if p > 0 => section_end[&(p as usize)],
+                _ => 0,
+            };
+            let mut previous = parent;
+            for parts in self.pieces(section, &split.splits) {
+                let idx = components.len();
+                let mut piece = self.resample(&parts);
+                piece.idx = idx as u64;
+                piece.name = if idx == 1 {
+                    "Compartment: 1 (Soma)".to_owned()
+                } else {
+                    format!("Compartment: {}", idx)
+                };
+                piece.parent_idxs = vec![previous as u64];
+                components[previous].children_idxs.push(idx as u64);
+                components.push(piece);
+                provenance.push(parts.iter().flat_map(|p| self.spans_of(*p)).collect());
+                links.extend(self.links(idx, &parts));
+                previous = idx;
+            }
+            section_end.insert(*section.compartments.last().unwrap(), previous);
+        }
+
+        let map = IndexMap::from_links(links, self.components.len(), components.len());
+        let attachments = translated(&self.attachments, &map)?;
+        let spines = translated_spines(&self.spines, &map)?;
+        let mut rebuilt = Compartments {
+            sections: build_sections(&components),
+            components,
+            provenance,
+            cell_id: self.cell_id,
+            run_log: self.run_log.clone(),
+            attachments: Vec::new(),
+            spines: Vec::new(),
+            index_map: self.index_map.clone(),
+            last_index_map: None,
+            ion_reversals: self.ion_reversals.clone(),
+        };
+        rebuilt.record_transform(map, attachments, spines);
+        rebuilt.log(
+            "discretize",
+            &[
+                ("frequency", discretization.frequency.into()),
+                ("d_lambda", discretization.d_lambda.into()),
+                ("count", (rebuilt.components.len() - 1).into()),
+            ],
+        );
+        Ok(rebuilt)
+    }
+
+    /// Splits `section` at `splits` (fractions of its length) and lists the
+    /// old compartments each piece covers. A section without length keeps
+    /// its compartments whole, all in one piece.
+    fn pieces(&self, section: &Section, splits: &[f64]) -> Vec<Vec<Part>> {
+        let lengths: Vec<f64> = section
+            .compartments
+            .iter()
+            .map(|&i| self.components[i].length)
+            .collect();
+        let total: f64 = lengths.iter().sum();
+        let whole = |idx| Part {
+            idx,
+            from: 0.0,
+            to: 1.0,
+        };
+        if total == 0.0 {
+            return vec![section.compartments.iter().map(|&i| whole(i)).collect()];
+        }
+
+        let bounds: Vec<f64> = std::iter::once(0.0)
+            .chain(splits.iter().map(|x| x * total))
+            .chain(std::iter::once(total))
+            .collect();
+        bounds
+            .windows(2)
+            .enumerate()
+            .map(|(k, w)| {
+                let (a, b) = (w[0], w[1]);
+                let last = k == bounds.len() - 2;
+                let mut start = 0.0;
+                let mut parts = Vec::new();
+                for (&idx, &l) in section.compartments.iter().zip(&lengths) {
+                    let end = start + l;
+                    if l == 0.0 {
+                        // A zero-length compartment goes with the piece it
+                        // starts in
+                        if (a <= start && start < b) || (last && start >= b) {
+                            parts.push(whole(idx));
+                        }
+                    } else if end.min(b) > start.max(a) {
+                        parts.push(Part {
+                            idx,
+                            from: (start.max(a) - start) / l,
+                            to: (end.min(b) - start) / l,
+                        });
+                    }
+                    start = end;
+                }
+                parts
+            })
+            .collect()
+    }
+
+    /// One compartment standing in for `parts`, which run along the cable
+    /// in order
+    fn resample(&self, parts: &[Part]) -> Compartment {
+        let share = |p: &Part| p.to - p.from;
+        let of = |p: &Part| &self.components[p.idx];
+        let sum = |f: &dyn Fn(&Part) -> f64| geometry::stable_sum(parts.iter().map(f));
+
+        let length = sum(&|p| of(p).length * share(p));
+        let area = sum(&|p| of(p).membrane_area() * share(p));
+        let resistance = sum(&|p| of(p).axial_resistance() * share(p));
+        let capacitance = sum(&|p| of(p).capacitance() * share(p));
+        let conductance = sum(&|p| of(p).membrane_conductance() * share(p));
+
+        let main = parts
+            .iter()
+            .max_by(|a, b| {
+                (of(a).length * share(a))
+                    .total_cmp(&(of(b).length * share(b)))
+                    .then(b.idx.cmp(&a.idx))
+            })
+            .unwrap();
+        let (first, last) = (parts[0], parts[parts.len() - 1]);
+        let at = |p: &Part, x: f64| {
+            let c = of(p);
+            geometry::add(
+                c.proximal,
+                geometry::scale(geometry::sub(c.distal, c.proximal), x),
+            )
+        };
+
+        let mut piece = Compartment {
+            channel: of(main).channel.clone(),
+            diam: of(main).diam,
+            area_factor: of(main).area_factor,
+            structure: of(&first).structure,
+            flags: parts
+                .iter()
+                .fold(NodeFlags::empty(), |f, p| f | of(p).flags),
+            proximal: at(&first, first.from),
+            distal: at(&last, last.to),
+            tangent: of(&first).tangent,
+            normal: of(&first).normal,
+            length,
+            ..Default::default()
+        };
+        if length > 0.0 {
+            let resistivity = sum(&|p| of(p).channel.resistance * of(p).length * share(p)) / length;
+            piece.channel.resistance = resistivity;
+            if resistance > 0.0 {
+                piece.diam = (4.0 * resistivity * length / (PI * resistance)).sqrt();
+            }
+            if piece.diam > 0.0 {
+                piece.area_factor = area / (PI * piece.diam * length);
+            }
+            let span = geometry::sub(piece.distal, piece.proximal);
+            let span_length = geometry::norm(span);
+            if span_length > 0.0 {
+                let tangent = geometry::scale(span, 1.0 / span_length);
+                piece.normal = geometry::transport(piece.normal, piece.tangent, tangent);
+                piece.tangent = tangent;
+            }
+        }
+        if area > 0.0 {
+            piece.channel.capacitance = capacitance / area;
+            piece.channel.conductance = conductance / area;
+        }
+        piece
+    }
+
+    /// Where `parts` ended up along new compartment `new`
+    fn links(&self, new: usize, parts: &[Part]) -> Vec<Link> {
+        let lengths: Vec<f64> = parts
+            .iter()
+            .map(|p| self.components[p.idx].length * (p.to - p.from))
+            .collect();
+        let total: f64 = lengths.iter().sum();
+        let mut start = 0.0;
+        parts
+            .iter()
+            .zip(&lengths)
+            .enumerate()
+            .map(|(k, (p, l))| {
+                let (new_from, new_to) = if total > 0.0 {
+                    (start / total, (start + l) / total)
+                } else {
+                    let n = parts.len() as f64;
+                    (k as f64 / n, (k + 1) as f64 / n)
+                };
+                start += l;
+                Link {
+                    old: p.idx,
+                    old_from: p.from,
+                    old_to: p.to,
+                    new,
+                    new_from,
+                    new_to,
+                }
+            })
+            .collect()
+    }
+
+    /// The node spans covered by `part` of its compartment
+    fn spans_of(&self, part: Part) -> Vec<NodeSpan> {
+        let spans = &self.provenance[part.idx];
+        let total: f64 = spans.iter().map(|s| s.to - s.from).sum();
+        if total == 0.0 || (part.from == 0.0 && part.to == 1.0) {
+            return spans.clone();
+        }
+        let (lo, hi) = (part.from * total, part.to * total);
+        let mut start = 0.0;
+        let mut out = Vec::new();
+        for s in spans {
+            let end = start + (s.to - s.from);
+            if end.min(hi) > start.max(lo) {
+                out.push(NodeSpan {
+                    node_id: s.node_id,
+                    from: s.from + (start.max(lo) - start),
+                    to: s.from + (end.min(hi) - start),
+                });
+            }
+            start = end;
+        }
+        out
+    }
+
+    /// `build_with` of a freshly computed discretization
+    pub fn d_lambda_rule(&self, frequency: f64, d_lambda: f64) -> Result<Compartments, String> {
+        self.build_with(&Discretization::compute(self, frequency, d_lambda)?)
+    }
+}
@@ -0,0 +1,556 @@
+//! Surgical edits on a `Skeleton` for proofreading workflows.
+//!
+//! A skeleton is edited in a session: `Skeleton::edit` gives an
+//! `EditableSkeleton`, the one type with methods that change a skeleton in
+//! place, and `commit` checks the result and hands back a plain skeleton.
+//! The session starts out sharing every column with the skeleton it came
+//! from and copies a column on its first change, so the original never
+//! sees an edit. `smoothed`, `pruned` and the other transformations run a
+//! session and commit it in one go.
+//!
+//! Edits keep both maps consistent but do not renumber anything: new nodes
+//! get fresh IDs past the current maximum. Call `finalize` once done editing
+//! to get back to sequential, topologically sorted IDs.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ops::Deref;
+use std::sync::Arc;
+
+use crate::swc_reader::{Node, NodeFlags, Skeleton, StructureIdentifier};
+
+impl Skeleton {
+    pub(crate) fn id_to_idx(&self) -> HashMap<u64, usize> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .map(|(idx, n)| (n.node_id, idx))
+            .collect()
+    }
+
+    fn node(&self, node_id: u64) -> Result<&Node, String> {
+        self.nodes
+            .iter()
+            .find(|n| n.node_id == node_id)
+            .ok_or_else(|| format!("No node with ID {}", node_id))
+    }
+
+    /// Children of `node_id`, leaving out the root's self reference
+    pub fn children_of(&self, node_id: u64) -> Vec<u64> {
+        self.parent_child_map
+            .get(&node_id)
+            .map(|c| c.iter().copied().filter(|&c| c != node_id).collect())
+            .unwrap_or_default()
+    }
+
+    /// `node_id` and everything distal to it, in breadth first order
+    pub fn subtree(&self, node_id: u64) -> Vec<u64> {
+        let mut out = Vec::new();
+        // Guards against hand-broken maps containing a cycle
+        let mut visited = HashSet::new();
+        let mut queue = VecDeque::from([node_id]);
+        while let Some(id) = queue.pop_front() {
+            if visited.insert(id) {
+                out.push(id);
+                queue.extend(self.children_of(id));
+            }
+        }
+        out
+    }
+
+    /// Checks that the node list and both maps describe the same tree: one
+    /// self-referencing root, every parent present, both maps agreeing with the
+    /// `parent_id`s, and every node reachable from the root.
+    pub fn validate_maps(&self) -> Result<(), String> {
+        let id_to_idx = self.id_to_idx();
+        if id_to_idx.len() != self.nodes.len() {
+            return Err("Duplicate node IDs".to_owned());
+        }
+
+        let roots: Vec<u64> = self
+            .nodes
+            .iter()
+            .filter(|n| n.parent_id == n.node_id)
+            .map(|n| n.node_id)
+            .collect();
+        if roots.len() != 1 {
+            return Err(format!("Expected exactly one root, found {:?}", roots));
+        }
+
+        for node in self.nodes.iter() {
+            if !id_to_idx.contains_key(&node.parent_id) {
+                return Err(format!(
+                    "Node {} has unknown parent {}",
+                    node.node_id, node.parent_id
+                ));
+            }
+            if self.child_parent_map.get(&node.node_id) != Some(&vec![node.parent_id]) {
+                return Err(format!(
+                    "child_parent_map disagrees with node {}",
+                    node.node_id
+                ));
+            }
+            let listed = self
+                .parent_child_map
+                .get(&node.parent_id)
+                .is_some_and(|c| c.iter().filter(|&&c| c == node.node_id).count() == 1);
+            if !listed {
+                return Err(format!(
+                    "parent_child_map is missing node {} under {}",
+                    node.node_id, node.parent_id
+                ));
+            }
+        }
+        let map_entries: usize = self.parent_child_map.values().map(Vec::len).sum();
+        if map_entries != self.nodes.len() || self.child_parent_map.len() != self.nodes.len() {
+            return Err("Maps reference nodes that do not exist".to_owned());
+        }
+
+        if self.subtree(roots[0]).len() != self.nodes.len() {
+            return Err("Not every node is reachable from the root".to_owned());
+        }
+        Ok(())
+    }
+
+    /// The unbranched run of nodes ending at `branch_end_id`, starting from the
+    /// closest proximal branch point (or the root), both ends included
+    pub fn branch_path(&self, branch_end_id: u64) -> Result<Vec<u64>, String> {
+        let id_to_idx = self.id_to_idx();
+        let mut node = *self.node(branch_end_id)?;
+        let mut path = vec![node.node_id];
+        while node.parent_id != node.node_id {
+            node = self.nodes[id_to_idx[&node.parent_id]];
+            path.push(node.node_id);
+            if node.parent_id == node.node_id || self.children_of(node.node_id).len() > 1 {
+                break;
+            }
+        }
+        path.reverse();
+        Ok(path)
+    }
+
+    /// Opens an edit session on a copy of this skeleton, sharing its
+    /// columns until they change
+    pub fn edit(&self) -> EditableSkeleton {
+        EditableSkeleton {
+            skeleton: self.clone(),
+        }
+    }
+
+    /// A copy with `EditableSkeleton::smooth_coordinates` applied; the maps
+    /// and extras stay shared
+    pub fn smoothed(&self, iterations: usize) -> Skeleton {
+        let mut editing = self.edit();
+        editing.smooth_coordinates(iterations);
+        editing.skeleton
+    }
+
+    /// A copy without `node_id` and everything distal to it
+    pub fn pruned(&self, node_id: u64) -> Result<Skeleton, String> {
+        let mut editing = self.edit();
+        editing.prune_subtree(node_id)?;
+        editing.commit()
+    }
+
+    /// A copy rooted at `node_id`, see `EditableSkeleton::reroot_at`
+    pub fn rerooted(&self, node_id: u64) -> Result<Skeleton, String> {
+        let mut editing = self.edit();
+        editing.reroot_at(node_id)?;
+        editing.commit()
+    }
+
+    /// A copy with every segment longer than `spacing` µm split into equal
+    /// pieces, numbered as the standardize pipeline numbers it. Extras do
+    /// not carry over.
+    pub fn resampled(&self, spacing: f64) -> Result<Skeleton, String> {
+        if !(spacing > 0.0 && spacing.is_finite()) {
+            return Err(format!("Spacing must be positive, got {}", spacing));
+        }
+        Ok(crate::standardize::resample(self.clone(), spacing))
+    }
+
+    /// A copy with `EditableSkeleton::repair_zero_radii` applied
+    pub fn with_repaired_radii(&self) -> Skeleton {
+        let mut editing = self.edit();
+        editing.repair_zero_radii();
+        editing.skeleton
+    }
+
+    /// A copy numbered sequentially in breadth first order, see
+    /// `EditableSkeleton::finalize`
+    pub fn normalized(&self) -> Result<Skeleton, String> {
+        let mut editing = self.edit();
+        editing.finalize()?;
+        Ok(editing.skeleton)
+    }
+}
+
+/// An edit session from `Skeleton::edit`. Reads go through to the skeleton
+/// as edited so far.
+#[derive(Debug, Clone)]
+pub struct EditableSkeleton {
+    skeleton: Skeleton,
+}
+
+impl Deref for EditableSkeleton {
+    type Target = Skeleton;
+
+    fn deref(&self) -> &Skeleton {
+        &self.skeleton
+    }
+}
+
+impl EditableSkeleton {
+    /// The skeleton as edited, once `validate_maps` passes
+    pub fn commit(self) -> Result<Skeleton, String> {
+        self.skeleton.validate_maps()?;
+        Ok(self.skeleton)
+    }
+
+    /// The node list, for edits the methods here do not cover. Keeping the
+    /// maps in line is up to the caller; `commit` checks.
+    pub fn nodes_mut(&mut self) -> &mut Vec<Node> {
+        Arc::make_mut(&mut self.skeleton.nodes)
+    }
+
+    pub fn parent_child_map_mut(&mut self) -> &mut HashMap<u64, Vec<u64>> {
+        Arc::make_mut(&mut self.skeleton.parent_child_map)
+    }
+
+    pub fn child_parent_map_mut(&mut self) -> &mut HashMap<u64, Vec<u64>> {
+        Arc::make_mut(&mut self.skeleton.child_parent_map)
+    }
+
+    pub fn extras_mut(&mut self) -> &mut HashMap<u64, Vec<f64>> {
+        Arc::make_mut(&mut self.skeleton.extras)
+    }
+
+    /// Inserts a node on the edge `parent_id -> child_id`, `position_fraction`
+    /// of the way from the parent. Returns the new node's ID.
+    pub fn insert_node_on_edge(
+        &mut self,
+        parent_id: u64,
+        child_id: u64,
+        position_fraction: f64,
+        radius: f64,
+    ) -> Result<u64, String> {
+        if !(0.0..=1.0).contains(&position_fraction) {
+            return Err(format!(
+                "Position fraction must be within [0, 1], got {}",
+                position_fraction
+            ));
+        }
+        let parent = *self.node(parent_id)?;
+        let child = *self.node(child_id)?;
+        if child.parent_id != parent_id || child_id == parent_id {
+            return Err(format!(
+                "Nodes {} and {} are not connected by an edge",
+                parent_id, child_id
+            ));
+        }
+
+        let new_id = self.nodes.iter().map(|n| n.node_id).max().unwrap_or(0) + 1;
+        let lerp = |a: f64, b: f64| a + (b - a) * position_fraction;
+        let mut node = Node::new(
+            new_id,
+            child.structured_identifier,
+            lerp(parent.x_pos, child.x_pos),
+            lerp(parent.y_pos, child.y_pos),
+            lerp(parent.z_pos, child.z_pos),
+            radius,
+            parent_id,
+        );
+        node.flags = NodeFlags::COORD_INTERPOLATED;
+
+        // Put the new node in the child's slot so sibling order is kept
+        let parent_child_map = self.parent_child_map_mut();
+        if let Some(children) = parent_child_map.get_mut(&parent_id) {
+            for c in children.iter_mut().filter(|c| **c == child_id) {
+                *c = new_id;
+            }
+        }
+        parent_child_map.insert(new_id, vec![child_id]);
+        let child_parent_map = self.child_parent_map_mut();
+        child_parent_map.insert(new_id, vec![parent_id]);
+        child_parent_map.insert(child_id, vec![new_id]);
+
+        let idx = self.id_to_idx()[&child_id];
+        let nodes = self.nodes_mut();
+        nodes[idx].parent_id = new_id;
+        nodes.push(node);
+        Ok(new_id)
+    }
+
+    /// Moves the subtree rooted at `subtree_root_id` under `new_parent_id`.
+    /// Refuses to move the root or to attach a subtree under its own
+    /// descendant, which would create a cycle.
+    pub fn reattach_subtree(
+        &mut self,
+        subtree_root_id: u64,
+        new_parent_id: u64,
+    ) -> Result<(), String> {
+        let subtree_root = *self.node(subtree_root_id)?;
+        self.node(new_parent_id)?;
+        if subtree_root.parent_id == subtree_root_id {
+            return Err("Cannot reattach the root".to_owned());
+        }
+        if self.subtree(subtree_root_id).contains(&new_parent_id) {
+            return Err(format!(
+                "Reattaching {} under {} would create a cycle: {} is inside that subtree",
+                subtree_root_id, new_parent_id, new_parent_id
+            ));
+        }
+
+        let old_parent = subtree_root.parent_id;
+        let parent_child_map = self.parent_child_map_mut();
+        if let Some(children) = parent_child_map.get_mut(&old_parent) {
+            children.retain(|&c| c != subtree_root_id);
+            if children.is_empty() {
+                parent_child_map.remove(&old_parent);
+            }
+        }
+        parent_child_map
+            .entry(new_parent_id)
+            .or_default()
+            .push(subtree_root_id);
+        self.child_parent_map_mut()
+            .insert(subtree_root_id, vec![new_parent_id]);
+
+        let idx = self.id_to_idx()[&subtree_root_id];
+        self.nodes_mut()[idx].parent_id = new_parent_id;
+        Ok(())
+    }
+
+    /// Splits the branch ending at `branch_end_id` by inserting a node
+    /// `arc_length` along it from its proximal end. If that lands exactly on an
+    /// existing node, no node is inserted and that node's ID is returned.
+    pub fn split_branch_at(&mut self, branch_end_id: u64, arc_length: f64) -> Result<u64, String> {
+        let path = self.branch_path(branch_end_id)?;
+        let mut walked = 0.0;
+        for pair in path.windows(2) {
+            let parent = *self.node(pair[0])?;
+            let child = *self.node(pair[1])?;
+            let seg = ((child.x_pos - parent.x_pos).powi(2)
+                + (child.y_pos - parent.y_pos).powi(2)
+                + (child.z_pos - parent.z_pos).powi(2))
+            .sqrt();
+            if arc_length == walked {
+                return Ok(parent.node_id);
+            }
+            if arc_length < walked + seg {
+                let fraction = (arc_length - walked) / seg;
+                let radius = parent.radius + (child.radius - parent.radius) * fraction;
+                return self.insert_node_on_edge(parent.node_id, child.node_id, fraction, radius);
+            }
+            walked += seg;
+        }
+        if arc_length == walked {
+            return Ok(branch_end_id);
+        }
+        Err(format!(
+            "Arc length {} is outside the branch ending at {} (length {})",
+            arc_length, branch_end_id, walked
+        ))
+    }
+
+    /// Removes `node_id` and everything distal to it. Returns how many nodes
+    /// went.
+    pub fn prune_subtree(&mut self, node_id: u64) -> Result<usize, String> {
+        let node = *self.node(node_id)?;
+        if node.parent_id == node_id {
+            return Err("Cannot prune the root".to_owned());
+        }
+        let removed: HashSet<u64> = self.subtree(node_id).into_iter().collect();
+        let parent_child_map = self.parent_child_map_mut();
+        if let Some(children) = parent_child_map.get_mut(&node.parent_id) {
+            children.retain(|&c| c != node_id);
+            if children.is_empty() {
+                parent_child_map.remove(&node.parent_id);
+            }
+        }
+        for id in &removed {
+            parent_child_map.remove(id);
+        }
+        let child_parent_map = self.child_parent_map_mut();
+        for id in &removed {
+            child_parent_map.remove(id);
+        }
+        // Extras stay shared unless the pruned nodes had some
+        if removed.iter().any(|id| self.extras.contains_key(id)) {
+            let extras = self.extras_mut();
+            for id in &removed {
+                extras.remove(id);
+            }
+        }
+        self.nodes_mut().retain(|n| !removed.contains(&n.node_id));
+        Ok(removed.len())
+    }
+
+    /// Makes `node_id` the root by turning around the edges between it and
+    /// the current root. IDs are kept.
+    pub fn reroot_at(&mut self, node_id: u64) -> Result<(), String> {
+        let id_to_idx = self.id_to_idx();
+        let mut path = vec![self.node(node_id)?.node_id];
+        loop {
+            let node = self.nodes[id_to_idx[&path[path.len() - 1]]];
+            if node.parent_id == node.node_id {
+                break;
+            }
+            if path.len() > self.nodes.len() {
+                return Err("Parents form a cycle".to_owned());
+            }
+            path.push(node.parent_id);
+        }
+        if path.len() == 1 {
+            return Ok(());
+        }
+
+        let unlink = |map: &mut HashMap<u64, Vec<u64>>, parent: u64, child: u64| {
+            if let Some(children) = map.get_mut(&parent) {
+                children.retain(|&c| c != child);
+                if children.is_empty() {
+                    map.remove(&parent);
+                }
+            }
+        };
+        let old_root = path[path.len() - 1];
+        let skeleton = &mut self.skeleton;
+        let parent_child_map = Arc::make_mut(&mut skeleton.parent_child_map);
+        let child_parent_map = Arc::make_mut(&mut skeleton.child_parent_map);
+        let nodes = Arc::make_mut(&mut skeleton.nodes);
+        unlink(parent_child_map, old_root, old_root);
+        for pair in path.windows(2) {
+            let (child, parent) = (pair[0], pair[1]);
+            unlink(parent_child_map, parent, child);
+            parent_child_map.entry(child).or_default().push(parent);
+            child_parent_map.insert(parent, vec![child]);
+            nodes[id_to_idx[&parent]].parent_id = child;
+        }
+        // The root lists itself first, as the reader writes it
+        parent_child_map
+            .entry(node_id)
+            .or_default()
+            .insert(0, node_id);
+        child_parent_map.insert(node_id, vec![node_id]);
+        nodes[id_to_idx[&node_id]].parent_id = node_id;
+        Ok(())
+    }
+
+    /// Moves every node inside an unbranched run towards its neighbours,
+    /// `iterations` times: each pass puts it at a quarter of its parent, half
+    /// of itself and a quarter of its child. Roots, branch points, tips and
+    /// soma nodes stay. Returns how many nodes moved.
+    pub fn smooth_coordinates(&mut self, iterations: usize) -> usize {
+        let id_to_idx = self.id_to_idx();
+        let inner: Vec<(usize, usize, usize)> = self
+            .nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, n)| {
+                n.parent_id != n.node_id && n.structured_identifier != StructureIdentifier::Soma
+            })
+            .filter_map(|(i, n)| match self.children_of(n.node_id)[..] {
+                [child] => Some((id_to_idx[&n.parent_id], i, id_to_idx[&child])),
+                _ => None,
+            })
+            .collect();
+        if inner.is_empty() {
+            return 0;
+        }
+        for _ in 0..iterations {
+            let old = Arc::clone(&self.nodes);
+            let nodes = self.nodes_mut();
+            for &(p, i, c) in &inner {
+                let mix =
+                    |f: fn(&Node) -> f64| 0.25 * f(&old[p]) + 0.5 * f(&old[i]) + 0.25 * f(&old[c]);
+                let node = &mut nodes[i];
+                (node.x_pos, node.y_pos, node.z_pos) =
+                    (mix(|n| n.x_pos), mix(|n| n.y_pos), mix(|n| n.z_pos));
+            }
+        }
+        if iterations == 0 { 0 } else { inner.len() }
+    }
+
+    /// Gives nodes whose zero radius the reader replaced with 1, flagged
+    /// `ZERO_RADIUS_FIXED`, their parent's radius instead, and a flagged
+    /// root that of the first node with a real one. Returns how many nodes
+    /// were repaired.
+    pub fn repair_zero_radii(&mut self) -> usize {
+        let fixed = |n: &Node| n.flags.contains(NodeFlags::ZERO_RADIUS_FIXED);
+        let fallback = self
+            .nodes
+            .iter()
+            .find(|n| !fixed(n))
+            .map_or(1.0, |n| n.radius);
+        let Some(root) = self.nodes.iter().find(|n| n.parent_id == n.node_id) else {
+            return 0;
+        };
+        let id_to_idx = self.id_to_idx();
+        let mut repaired = 0;
+        // Breadth first, so a parent is repaired before its children
+        for id in self.subtree(root.node_id) {
+            let i = id_to_idx[&id];
+            if fixed(&self.nodes[i]) {
+                let parent = self.nodes[i].parent_id;
+                let radius = if parent == id {
+                    fallback
+                } else {
+                    self.nodes[id_to_idx[&parent]].radius
+                };
+                self.nodes_mut()[i].radius = radius;
+                repaired += 1;
+            }
+        }
+        repaired
+    }
+
+    /// Renumbers nodes sequentially in breadth first order from the root, the
+    /// same ordering `swc_reader` produces, and rebuilds both maps.
+    pub fn finalize(&mut self) -> Result<(), String> {
+        self.validate_maps()?;
+        let root = self
+            .nodes
+            .iter()
+            .find(|n| n.parent_id == n.node_id)
+            .map(|n| n.node_id)
+            .ok_or("No root node found")?;
+
+        let order = self.subtree(root);
+        let old_to_new: HashMap<u64, u64> = order
+            .iter()
+            .enumerate()
+            .map(|(new_id, old_id)| (*old_id, new_id as u64))
+            .collect();
+        let id_to_idx = self.id_to_idx();
+
+        let mut parent_child_map: HashMap<u64, Vec<u64>> = HashMap::new();
+        let mut child_parent_map: HashMap<u64, Vec<u64>> = HashMap::new();
+        let nodes: Vec<Node> = order
+            .iter()
+            .map(|old_id| {
+                let mut node = self.nodes[id_to_idx[old_id]];
+                node.node_id = old_to_new[old_id];
+                node.parent_id = old_to_new[&node.parent_id];
+                parent_child_map
+                    .entry(node.parent_id)
+                    .or_default()
+                    .push(node.node_id);
+                child_parent_map
+                    .entry(node.node_id)
+                    .or_default()
+                    .push(node.parent_id);
+                node
+            })
+            .collect();
+
+        let extras = self
+            .extras
+            .iter()
+            .map(|(old_id, e)| (old_to_new[old_id], e.clone()))
+            .collect();
+        self.skeleton.extras = Arc::new(extras);
+        self.skeleton.nodes = Arc::new(nodes);
+        self.skeleton.parent_child_map = Arc::new(parent_child_map);
+        self.skeleton.child_parent_map = Arc::new(child_parent_map);
+        Ok(())
+    }
+}
@@ -0,0 +1,80 @@
+//! Charge and energy bookkeeping for metabolic-cost estimates. Na⁺ charge
+//! per compartment is the usual proxy for the ATP the pumps spend restoring
+//! the gradients.
+//!
+//! A solver that wants accounting holds an `EnergyLedger` and records each
+//! mechanism's current once per step; one that does not pays nothing. Sums
+//! are compensated, so long runs with tiny per-step charges stay accurate.
+//!
+//! Currents are in nA, voltages in mV and times in ms, so charges come out
+//! in pC and energies in fJ. Outward currents are positive.
+
+use std::collections::BTreeMap;
+
+use crate::channels::Ion;
+use crate::geometry::{Neumaier, stable_sum};
+
+const IONS: [Ion; 5] = [Ion::Na, Ion::K, Ion::Ca, Ion::Cl, Ion::NonSpecific];
+
+/// Running charge per ion and dissipated energy for every compartment
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnergyLedger {
+    charge: Vec<[Neumaier; 5]>,
+    energy: Vec<Neumaier>,
+}
+
+/// Totals from an `EnergyLedger`. Every ion is listed, at zero when nothing
+/// carried it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnergyReport {
+    /// Per compartment, the charge each ion carried, in pC
+    pub charge: Vec<BTreeMap<Ion, f64>>,
+    /// Per compartment, the energy dissipated in the conductances, in fJ
+    pub energy: Vec<f64>,
+    /// Whole cell, summed over compartments independently of their order
+    pub total_charge: BTreeMap<Ion, f64>,
+    pub total_energy: f64,
+}
+
+impl EnergyLedger {
+    pub fn new(compartments: usize) -> EnergyLedger {
+        EnergyLedger {
+            charge: vec![Default::default(); compartments],
+            energy: vec![Neumaier::default(); compartments],
+        }
+    }
+
+    /// Books `current` carried by `ion` through compartment `idx` for `dt`,
+    /// with `driving_force` the membrane potential minus the current's
+    /// reversal potential
+    pub fn record(&mut self, idx: usize, ion: Ion, current: f64, driving_force: f64, dt: f64) {
+        // `IONS` is in declaration order
+        self.charge[idx][ion as usize].add(current * dt);
+        // A conductance dissipates I (V - E), whichever way the current flows
+        self.energy[idx].add(current * driving_force * dt);
+    }
+
+    pub fn report(&self) -> EnergyReport {
+        let charge: Vec<BTreeMap<Ion, f64>> = self
+            .charge
+            .iter()
+            .map(|sums| {
+                IONS.iter()
+                    .zip(sums)
+                    .map(|(i, s)| (*i, s.value()))
+                    .collect()
+            })
+            .collect();
+        let energy: Vec<f64> = self.energy.iter().map(Neumaier::value).collect();
+        let total_charge = IONS
+            .iter()
+            .map(|ion| (*ion, stable_sum(charge.iter().map(|c| c[ion]))))
+            .collect();
+        EnergyReport {
+            total_energy: stable_sum(energy.iter().copied()),
+            charge,
+            energy,
+            total_charge,
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::codes::Code;
+
+/// Everything that can go wrong while reading a skeleton
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum SwcError {
+    /// The input does not describe a valid skeleton; `code` says how.
+    /// `line` is the offending input line, when the input is a file.
+    Invalid {
+        code: Code,
+        message: String,
+        line: Option<usize>,
+    },
+    /// Reading or writing `path` failed
+    Io {
+        path: PathBuf,
+        kind: io::ErrorKind,
+        message: String,
+    },
+    /// The input's sha256 is not the one the caller expected. Both digests are
+    /// lowercase hex.
+    ChecksumMismatch { expected: String, actual: String },
+    /// One of the reader's defensive limits was hit. `observed` is how far
+    /// reading got before it stopped, so it may be well short of the input's
+    /// real size.
+    LimitExceeded {
+        which: Limit,
+        limit: u64,
+        observed: u64,
+    },
+}
+
+/// The defensive limits in `ReaderOptions`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Limit {
+    /// `max_nodes`, counted in nodes
+    Nodes,
+    /// `max_file_bytes`, counted in bytes
+    FileBytes,
+    /// `timeout`, counted in milliseconds
+    Timeout,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unit = match self {
+            Limit::Nodes => "nodes",
+            Limit::FileBytes => "bytes",
+            Limit::Timeout => "ms",
+        };
+        write!(f, "{}", unit)
+    }
+}
+
+impl fmt::Display for SwcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SwcError::Invalid { message, .. } => write!(f, "{}", message),
+            SwcError::Io { path, message, .. } => write!(f, "{}: {}", path.display(), message),
+            SwcError::ChecksumMismatch { expected, actual } => {
+                write!(
+                    f,
+                    "Checksum mismatch: expected {}, got {}",
+                    expected, actual
+                )
+            }
+            SwcError::LimitExceeded {
+                which,
+                limit,
+                observed,
+            } => write!(
+                f,
+                "Input exceeds the limit of {} {}: stopped at {}",
+                limit, which, observed
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SwcError {}
+
+impl SwcError {
+    pub(crate) fn invalid(code: Code, message: impl Into<String>) -> Self {
+        SwcError::Invalid {
+            code,
+            message: message.into(),
+            line: None,
+        }
+    }
+
+    pub(crate) fn invalid_at(code: Code, line: usize, message: impl Into<String>) -> Self {
+        SwcError::Invalid {
+            code,
+            message: message.into(),
+            line: Some(line),
+        }
+    }
+
+    pub(crate) fn io(path: &Path, err: io::Error) -> Self {
+        SwcError::Io {
+            path: path.to_path_buf(),
+            kind: err.kind(),
+            message: err.to_string(),
+        }
+    }
+
+    /// Input line the error was found on, if it is tied to one
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            SwcError::Invalid { line, .. } => *line,
+            _ => None,
+        }
+    }
+
+    /// Machine-readable code, see `codes::REGISTRY`
+    pub fn code(&self) -> Code {
+        match self {
+            SwcError::Invalid { code, .. } => *code,
+            SwcError::Io { .. } => Code::Io,
+            SwcError::ChecksumMismatch { .. } => Code::ChecksumMismatch,
+            SwcError::LimitExceeded { .. } => Code::LimitExceeded,
+        }
+    }
+}
@@ -0,0 +1,1288 @@
+//! A whole simulation described in one TOML file, for running standard
+//! experiments without writing code: which morphology to read and how, the
+//! membrane and its distribution rules, stimuli, probes, the solver and
+//! what to write.
+//!
+//! ```toml
+//! [morphology]
+//! path = "basic.swc"          # relative to this file
+//!
+//! [standardize]               # optional, the keys of a standardize recipe
+//! soma = "single_point"
+//!
+//! [membrane]
+//! mechanism = "hh"            # or "pas"
+//! resistance = 100.0          # Ω·cm
+//! capacitance = 1.0           # µF/cm²
+//!
+//! [[rules]]
+//! region = "dend"
+//! mechanism = "hh"
+//! parameter = "gkbar"
+//! expression = "0.036 * exp(-dist / 200)"
+//!
+//! [[stimuli]]
+//! site = "soma[0](0.5)"       # section(x), as NEURON writes it
+//! kind = "step"               # or "alpha", "biexp", "trace", "protocol"
+//! amplitude = 0.5             # nA
+//! start = 5.0                 # ms
+//! duration = 20.0
+//!
+//! [[stimuli]]
+//! site = "dend[0](1)"
+//! kind = "protocol"           # a spec of the protocol library
+//! spec = "zap f1=20"
+//! sweep = 0                   # optional, for protocols of several sweeps
+//!
+//! [[probes]]
+//! name = "soma"
+//! site = "soma[0](0.5)"
+//!
+//! [simulation]
+//! dt = 0.025                  # ms
+//! duration = 50.0
+//!
+//! [solver]                    # optional, branch-parallel solving
+//! threads = 4
+//! min_subtree = 1000
+//!
+//! [output]
+//! directory = "results"       # relative to this file
+//! formats = ["csv", "npy"]
+//! manifest = true
+//! ```
+//!
+//! `Experiment::load` reads such a file into an `Experiment`, which can as
+//! well be built and changed in code; `run` and `write` do what the
+//! `compartment-rs run` command does. Every problem is reported as a
+//! `ConfigError` with the line and column of the offending key or value:
+//! unknown keys, wrong types and out-of-range values when the file is read,
+//! and sites that do not exist or rules that do not apply once the
+//! morphology is (`Experiment::validate`).
+//!
+//! Traces are written one file per probe, `<name>.csv` with a header and
+//! time and voltage columns or `<name>.npy` of shape `(n, 2)`, both of
+//! which `compartment-rs compare` reads; the manifest goes to
+//! `manifest.txt`.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use toml::Spanned;
+use toml::de::{DeTable, DeValue};
+
+use crate::biophysics::{BiophysicsProfile, Expression};
+use crate::channels::{Channel, ChannelType, Dynamics, HodgkinHuxley};
+use crate::compartments::Compartments;
+use crate::manifest::Manifest;
+use crate::parameters::edit_distance;
+use crate::protocols::Protocol;
+use crate::solver::{Simulation, SolverOptions};
+use crate::standardize::{FileReport, Pipeline, StandardizeOptions};
+use crate::stimulus::Stimulus;
+use crate::swc_reader::{ConflictPolicy, ReaderOptions, format_float, swc_reader};
+use crate::units::{MicroFaradPerCm2, OhmCm, SiemensPerCm2};
+use crate::write;
+
+/// Line and column in a configuration file, both from 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// One problem with an experiment
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigError {
+    /// Dotted path of the key, e.g. `probes[1].site`; empty for syntax
+    /// errors
+    pub key: String,
+    /// Where in the file, None for experiments built in code
+    pub location: Option<Location>,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    /// `line:column: key: message`, to follow the file name
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(l) = self.location {
+            write!(f, "{}:{}: ", l.line, l.column)?;
+        }
+        if !self.key.is_empty() {
+            write!(f, "{}: ", self.key)?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+/// The membrane every compartment starts with, before the rules
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MembraneKind {
+    Passive,
+    HodgkinHuxley,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MembraneSpec {
+    pub kind: MembraneKind,
+    /// Axial resistivity, in Ω·cm
+    pub resistance: f64,
+    /// In µF/cm²
+    pub capacitance: f64,
+    /// Leak conductance of `pas`, in S/cm²
+    pub conductance: f64,
+}
+
+impl Default for MembraneSpec {
+    fn default() -> Self {
+        MembraneSpec {
+            kind: MembraneKind::Passive,
+            resistance: 100.0,
+            capacitance: 1.0,
+            conductance: 1e-4,
+        }
+    }
+}
+
+/// Current injected at a site, in nA
+#[derive(Debug, Clone, PartialEq)]
+pub enum Waveform {
+    /// `amplitude` from `start` for `duration` ms
+    Step {
+        start: f64,
+        duration: f64,
+        amplitude: f64,
+    },
+    /// An alpha or biexponential waveform
+    Shaped(Stimulus),
+    /// One value per step
+    Trace(Vec<f64>),
+    /// One sweep of a `protocols::library` protocol
+    Protocol { protocol: Protocol, sweep: usize },
+}
+
+impl Waveform {
+    /// The average current over each of `steps` steps of `dt`
+    fn render(&self, dt: f64, steps: usize) -> Result<Vec<f64>, String> {
+        match self {
+            Waveform::Step {
+                start,
+                duration,
+                amplitude,
+            } => Ok((0..steps)
+                .map(|s| {
+                    let (t0, t1) = (s as f64 * dt, (s + 1) as f64 * dt);
+                    let overlap = (t1.min(start + duration) - t0.max(*start)).max(0.0);
+                    amplitude * overlap / dt
+                })
+                .collect()),
+            Waveform::Shaped(stimulus) => {
+                let mut values = stimulus.render(dt, steps as f64 * dt)?;
+                values.resize(steps, 0.0);
+                Ok(values)
+            }
+            Waveform::Protocol { protocol, sweep } => protocol.render(*sweep, dt, steps),
+            Waveform::Trace(values) if values.len() == steps => Ok(values.clone()),
+            Waveform::Trace(values) => Err(format!(
+                "{} values for {} steps of {} ms",
+                values.len(),
+                steps,
+                dt
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StimulusSpec {
+    /// `section(x)`, e.g. `soma[0](0.5)` or `dend[2](1)`
+    pub site: String,
+    pub waveform: Waveform,
+}
+
+impl StimulusSpec {
+    /// A `[[stimuli]]` table of an experiment file, numbers written exactly
+    pub fn to_toml(&self) -> String {
+        let (kind, numbers) = match &self.waveform {
+            Waveform::Step {
+                start,
+                duration,
+                amplitude,
+            } => (
+                "step",
+                vec![
+                    ("amplitude", *amplitude),
+                    ("start", *start),
+                    ("duration", *duration),
+                ],
+            ),
+            Waveform::Shaped(Stimulus::Alpha {
+                onset,
+                tau,
+                amplitude,
+            }) => (
+                "alpha",
+                vec![("amplitude", *amplitude), ("onset", *onset), ("tau", *tau)],
+            ),
+            Waveform::Shaped(Stimulus::BiExp {
+                onset,
+                tau_rise,
+                tau_decay,
+                amplitude,
+            }) => (
+                "biexp",
+                vec![
+                    ("amplitude", *amplitude),
+                    ("onset", *onset),
+                    ("tau_rise", *tau_rise),
+                    ("tau_decay", *tau_decay),
+                ],
+            ),
+            Waveform::Trace(_) => ("trace", Vec::new()),
+            Waveform::Protocol { .. } => ("protocol", Vec::new()),
+        };
+        // Sites and specs never hold quotes or backslashes
+        let mut text = format!(
+            "[[stimuli]]\nsite = \"{}\"\nkind = \"{}\"\n",
+            self.site, kind
+        );
+        for (key, value) in numbers {
+            text.push_str(&format!("{} = {}\n", key, format_float(value)));
+        }
+        match &self.waveform {
+            Waveform::Trace(values) => {
+                let values: Vec<String> = values.iter().map(|v| format_float(*v)).collect();
+                text.push_str(&format!("values = [{}]\n", values.join(", ")));
+            }
+            Waveform::Protocol { protocol, sweep } => {
+                text.push_str(&format!("spec = \"{}\"\n", protocol.to_spec()));
+                if *sweep > 0 {
+                    text.push_str(&format!("sweep = {}\n", sweep));
+                }
+            }
+            _ => {}
+        }
+        text
+    }
+}
+
+/// A voltage recorded at a site and written as `name`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Probe {
+    pub name: String,
+    pub site: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    Csv,
+    Npy,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputSpec {
+    pub directory: PathBuf,
+    pub formats: Vec<TraceFormat>,
+    /// Also write the run's `Manifest` to `manifest.txt`
+    pub manifest: bool,
+}
+
+/// Everything one simulation needs, see the module documentation
+#[derive(Debug, Clone)]
+pub struct Experiment {
+    pub morphology: PathBuf,
+    pub reader: ReaderOptions,
+    /// Standardization applied after reading, if any
+    pub standardize: Option<StandardizeOptions>,
+    pub membrane: MembraneSpec,
+    pub profile: BiophysicsProfile,
+    pub stimuli: Vec<StimulusSpec>,
+    pub probes: Vec<Probe>,
+    /// In ms
+    pub dt: f64,
+    pub duration: f64,
+    /// Seed for stochastic gating
+    pub seed: u64,
+    pub solver: SolverOptions,
+    pub output: OutputSpec,
+    /// Where the keys came from, by dotted path
+    locations: HashMap<String, Location>,
+}
+
+/// An experiment's model, ready to simulate
+#[derive(Clone)]
+pub struct Prepared {
+    pub compartments: Compartments,
+    /// Compartment and waveform of every stimulus, as `Simulation::run`
+    /// takes them
+    pub stimuli: Vec<(usize, Vec<f64>)>,
+    /// Name and compartment of every probe
+    pub probes: Vec<(String, usize)>,
+}
+
+/// Voltage traces of an experiment's run
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExperimentResult {
+    pub dt: f64,
+    /// Name and voltage of every probe, with `steps + 1` values each
+    pub traces: Vec<(String, Vec<f64>)>,
+    pub manifest: Manifest,
+}
+
+fn join(errors: &[ConfigError]) -> String {
+    let lines: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+    lines.join("\n")
+}
+
+impl Experiment {
+    /// Defaults for everything but the morphology: a passive membrane, no
+    /// stimuli or probes, 100 ms in steps of 0.025 ms, serial solving and
+    /// CSV traces in `results`
+    pub fn new(morphology: impl Into<PathBuf>) -> Experiment {
+        Experiment {
+            morphology: morphology.into(),
+            reader: ReaderOptions::default(),
+            standardize: None,
+            membrane: MembraneSpec::default(),
+            profile: BiophysicsProfile::new(),
+            stimuli: Vec::new(),
+            probes: Vec::new(),
+            dt: 0.025,
+            duration: 100.0,
+            seed: 0,
+            solver: SolverOptions::default(),
+            output: OutputSpec {
+                directory: PathBuf::from("results"),
+                formats: vec![TraceFormat::Csv],
+                manifest: true,
+            },
+            locations: HashMap::new(),
+        }
+    }
+
+    /// Reads the configuration at `path`; relative paths in it are taken
+    /// from its directory
+    pub fn load(path: impl AsRef<Path>) -> Result<Experiment, Vec<ConfigError>> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|e| {
+            vec![ConfigError {
+                key: String::new(),
+                location: None,
+                message: format!("Could not read {}: {}", path.display(), e),
+            }]
+        })?;
+        Experiment::from_toml(&text, path.parent().unwrap_or(Path::new("")))
+    }
+
+    /// Parses a configuration, taking relative paths from `base`. Checks
+    /// everything that does not need the morphology.
+    pub fn from_toml(text: &str, base: impl AsRef<Path>) -> Result<Experiment, Vec<ConfigError>> {
+        let document = DeTable::parse(text).map_err(|e| {
+            vec![ConfigError {
+                key: String::new(),
+                location: e.span().map(|s| location(text, s.start)),
+                message: e.message().trim().to_owned(),
+            }]
+        })?;
+        let mut walker = Walker {
+            source: text,
+            errors: Vec::new(),
+            locations: HashMap::new(),
+        };
+        let experiment = walker.experiment(document.get_ref(), base.as_ref());
+        let Walker {
+            mut errors,
+            locations,
+            ..
+        } = walker;
+        if !errors.is_empty() {
+            errors.sort_by_key(|e| e.location.map(|l| (l.line, l.column)));
+            return Err(errors);
+        }
+        Ok(Experiment {
+            locations,
+            ..experiment
+        })
+    }
+
+    /// Number of steps of `dt` the simulation takes
+    pub fn steps(&self) -> usize {
+        (self.duration / self.dt).round() as usize
+    }
+
+    fn error(&self, key: &str, message: impl Into<String>) -> ConfigError {
+        ConfigError {
+            key: key.to_owned(),
+            location: self.locations.get(key).copied(),
+            message: message.into(),
+        }
+    }
+
+    /// Reads and standardizes the morphology, builds the compartments,
+    /// sets the membrane, applies the rules and locates every site,
+    /// reporting every problem found
+    pub fn build(&self) -> Result<Prepared, Vec<ConfigError>> {
+        let skeleton = swc_reader(&self.morphology, &self.reader).map_err(|e| {
+            vec![self.error(
+                "morphology.path",
+                format!("{}: {}", self.morphology.display(), e),
+            )]
+        })?;
+        let skeleton = match &self.standardize {
+            Some(options) => Pipeline::standardize(options.clone())
+                .standardize_skeleton(skeleton, &mut FileReport::default())
+                .map_err(|e| vec![self.error("standardize", e.to_string())])?,
+            None => skeleton,
+        };
+        let mut compartments = Compartments::from_skeleton(skeleton);
+        let m = &self.membrane;
+        let channel = match (
+            OhmCm::new(m.resistance),
+            MicroFaradPerCm2::new(m.capacitance),
+            SiemensPerCm2::new(m.conductance),
+        ) {
+            (Ok(r), Ok(c), Ok(g)) => {
+                let mut channel = Channel::passive(r, c, g);
+                if m.kind == MembraneKind::HodgkinHuxley {
+                    channel.channel_type = ChannelType::HodgkinHuxley(HodgkinHuxley::new());
+                }
+                channel
+            }
+            (r, c, g) => {
+                let e = [r.err(), c.err(), g.err()].into_iter().flatten().next();
+                return Err(vec![self.error("membrane", e.unwrap_or_default())]);
+            }
+        };
+        for c in compartments.components.iter_mut() {
+            c.set_channel(channel.clone());
+        }
+
+        let mut errors = Vec::new();
+        // One at a time, so a failure names its rule
+        for (k, rule) in self.profile.rules.iter().enumerate() {
+            let single = BiophysicsProfile {
+                rules: vec![rule.clone()],
+            };
+            if let Err(e) = single.apply(&mut compartments) {
+                errors.push(self.error(&format!("rules[{}]", k), e));
+            }
+        }
+        let steps = self.steps();
+        let mut stimuli = Vec::new();
+        for (k, s) in self.stimuli.iter().enumerate() {
+            let key = format!("stimuli[{}]", k);
+            let idx = locate(&compartments, &s.site)
+                .map_err(|e| errors.push(self.error(&format!("{}.site", key), e)));
+            let values = s
+                .waveform
+                .render(self.dt, steps)
+                .map_err(|e| errors.push(self.error(&key, e)));
+            if let (Ok(idx), Ok(values)) = (idx, values) {
+                stimuli.push((idx, values));
+            }
+        }
+        let mut probes = Vec::new();
+        for (k, p) in self.probes.iter().enumerate() {
+            match locate(&compartments, &p.site) {
+                Ok(idx) => probes.push((p.name.clone(), idx)),
+                Err(e) => errors.push(self.error(&format!("probes[{}].site", k), e)),
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(Prepared {
+            compartments,
+            stimuli,
+            probes,
+        })
+    }
+
+    /// Everything `run` would find wrong before simulating
+    pub fn validate(&self) -> Result<(), Vec<ConfigError>> {
+        self.build().map(|_| ())
+    }
+
+    /// Builds the model and simulates it
+    pub fn run(&self) -> Result<ExperimentResult, String> {
+        let prepared = self.build().map_err(|e| join(&e))?;
+        let mut simulation = Simulation::new(&prepared.compartments, self.dt)
+            .map_err(|e| e.to_string())?
+            .with_solver(self.solver);
+        simulation.reseed(self.seed);
+        let result = simulation
+            .run(self.steps(), &prepared.stimuli)
+            .map_err(|e| e.to_string())?;
+        Ok(ExperimentResult {
+            dt: result.dt,
+            traces: prepared
+                .probes
+                .into_iter()
+                .map(|(name, idx)| (name, result.voltages[idx].clone()))
+                .collect(),
+            manifest: result.manifest,
+        })
+    }
+
+    /// Writes `result` as `output` says. Returns the files written.
+    pub fn write(&self, result: &ExperimentResult) -> Result<Vec<PathBuf>, String> {
+        let dir = &self.output.directory;
+        let mut written = Vec::new();
+        let mut put = |name: String, bytes: Vec<u8>| -> Result<(), String> {
+            let path = dir.join(name);
+            write::write_atomic(&path, &bytes, ConflictPolicy::Overwrite)
+                .map_err(|e| e.to_string())?;
+            written.push(path);
+            Ok(())
+        };
+        for (name, trace) in &result.traces {
+            let times = (0..trace.len()).map(|k| k as f64 * result.dt);
+            for format in &self.output.formats {
+                match format {
+                    TraceFormat::Csv => {
+                        let mut csv = String::from("time,voltage\n");
+                        for (t, v) in times.clone().zip(trace) {
+                            csv.push_str(&format!("{},{}\n", t, v));
+                        }
+                        put(format!("{}.csv", name), csv.into_bytes())?;
+                    }
+                    TraceFormat::Npy => {
+                        let values: Vec<f64> = times
+                            .clone()
+                            .zip(trace)
+                            .flat_map(|(t, &v)| [t, v])
+                            .collect();
+                        put(format!("{}.npy", name), npy(&values, trace.len()))?;
+                    }
+                }
+            }
+        }
+        if self.output.manifest {
+            put(
+                "manifest.txt".to_owned(),
+                result.manifest.to_text().into_bytes(),
+            )?;
+        }
+        Ok(written)
+    }
+}
+
+/// A little-endian float64 array of shape `(rows, 2)`, as `numpy.save`
+/// writes it
+fn npy(values: &[f64], rows: usize) -> Vec<u8> {
+    let mut header = format!(
+        "{{'descr': '<f8', 'fortran_order': False, 'shape': ({}, 2), }}",
+        rows
+    );
+    // Magic, version and length take 10 bytes; pad to 64 with a newline
+    let total = (10 + header.len() + 1).div_ceil(64) * 64;
+    header.push_str(&" ".repeat(total - 10 - header.len() - 1));
+    header.push('\n');
+    let mut out = b"\x93NUMPY\x01\x00".to_vec();
+    out.extend_from_slice(&(header.len() as u16).to_le_bytes());
+    out.extend_from_slice(header.as_bytes());
+    for v in values {
+        out.extend_from_slice(&v.to_le_bytes());
+    }
+    out
+}
+
+/// The compartment at `section(x)`
+fn locate(compartments: &Compartments, site: &str) -> Result<usize, String> {
+    let malformed = || {
+        format!(
+            "'{}' is not a site; write section(x), e.g. soma[0](0.5)",
+            site
+        )
+    };
+    let (section, x) = site
+        .trim()
+        .strip_suffix(')')
+        .and_then(|s| s.rsplit_once('('))
+        .ok_or_else(malformed)?;
+    let x: f64 = x.trim().parse().map_err(|_| malformed())?;
+    compartments
+        .compartment_at(section.trim(), x)
+        .map_err(|e| e.to_string())
+}
+
+fn location(source: &str, offset: usize) -> Location {
+    let before = &source[..offset.min(source.len())];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    Location {
+        line: before.matches('\n').count() + 1,
+        column: before[line_start..].chars().count() + 1,
+    }
+}
+
+type Value<'i> = Spanned<DeValue<'i>>;
+
+/// Walks a parsed document, collecting errors and where each key was
+struct Walker<'s> {
+    source: &'s str,
+    errors: Vec<ConfigError>,
+    locations: HashMap<String, Location>,
+}
+
+impl Walker<'_> {
+    fn error(&mut self, key: &str, span: Range<usize>, message: impl Into<String>) {
+        self.errors.push(ConfigError {
+            key: key.to_owned(),
+            location: Some(location(self.source, span.start)),
+            message: message.into(),
+        });
+    }
+
+    /// Reports every key of `table`, at `path`, that is not `known`, and
+    /// notes where the known ones are
+    fn keys(&mut self, path: &str, table: &DeTable, known: &[&str]) {
+        for (key, value) in table.iter() {
+            let name = key.get_ref().as_ref();
+            let dotted = join_key(path, name);
+            if known.contains(&name) {
+                self.locations
+                    .insert(dotted, location(self.source, value.span().start));
+                continue;
+            }
+            let closest = known
+                .iter()
+                .min_by_key(|k| edit_distance(name, k))
+                .filter(|k| edit_distance(name, k) <= 2);
+            let message = match closest {
+                Some(k) => format!("Unknown key '{}'; did you mean '{}'?", name, k),
+                None => format!(
+                    "Unknown key '{}'; expected one of {}",
+                    name,
+                    known.join(", ")
+                ),
+            };
+            self.error(&dotted, key.span(), message);
+        }
+    }
+
+    fn table<'t, 'i>(
+        &mut self,
+        path: &str,
+        parent: &'t DeTable<'i>,
+        key: &str,
+    ) -> Option<&'t DeTable<'i>> {
+        let value = parent.get(key)?;
+        match value.get_ref() {
+            DeValue::Table(t) => Some(t),
+            other => {
+                let message = format!("Expected a table, found {}", other.type_str());
+                self.error(&join_key(path, key), value.span(), message);
+                None
+            }
+        }
+    }
+
+    /// An array of tables, each with its span
+    fn tables<'t, 'i>(
+        &mut self,
+        parent: &'t DeTable<'i>,
+        key: &str,
+    ) -> Vec<(&'t DeTable<'i>, Range<usize>)> {
+        let Some(value) = parent.get(key) else {
+            return Vec::new();
+        };
+        let DeValue::Array(items) = value.get_ref() else {
+            let message = format!("Expected [[{}]] tables", key);
+            self.error(key, value.span(), message);
+            return Vec::new();
+        };
+        let mut out = Vec::new();
+        for (k, item) in items.iter().enumerate() {
+            match item.get_ref() {
+                DeValue::Table(t) => out.push((t, item.span())),
+                other => {
+                    let message = format!("Expected a table, found {}", other.type_str());
+                    self.error(&format!("{}[{}]", key, k), item.span(), message);
+                }
+            }
+        }
+        out
+    }
+
+    fn value<'t, 'i>(
+        &mut self,
+        path: &str,
+        table: &'t DeTable<'i>,
+        key: &str,
+        span: &Range<usize>,
+        required: bool,
+    ) -> Option<&'t Value<'i>> {
+        let value = table.get(key);
+        if value.is_none() && required {
+            self.error(
+                path,
+                span.clone(),
+                format!("Missing required key '{}'", key),
+            );
+        }
+        value
+    }
+
+    fn string(
+        &mut self,
+        path: &str,
+        table: &DeTable,
+        key: &str,
+        span: &Range<usize>,
+        required: bool,
+    ) -> Option<(String, Range<usize>)> {
+        let value = self.value(path, table, key, span, required)?;
+        match value.get_ref() {
+            DeValue::String(s) => Some((s.to_string(), value.span())),
+            other => {
+                let message = format!("Expected a string, found {}", other.type_str());
+                self.error(&join_key(path, key), value.span(), message);
+                None
+            }
+        }
+    }
+
+    fn bool(&mut self, path: &str, table: &DeTable, key: &str, default: bool) -> bool {
+        let Some(value) = table.get(key) else {
+            return default;
+        };
+        match value.get_ref() {
+            DeValue::Boolean(b) => *b,
+            other => {
+                let message = format!("Expected true or false, found {}", other.type_str());
+                self.error(&join_key(path, key), value.span(), message);
+                default
+            }
+        }
+    }
+
+    /// A number, integer or float, that must satisfy `valid`, described by
+    /// `expected`
+    #[allow(clippy::too_many_arguments)]
+    fn number(
+        &mut self,
+        path: &str,
+        table: &DeTable,
+        key: &str,
+        span: &Range<usize>,
+        required: bool,
+        valid: impl Fn(f64) -> bool,
+        expected: &str,
+    ) -> Option<f64> {
+        let value = self.value(path, table, key, span, required)?;
+        let number = match value.get_ref() {
+            DeValue::Float(f) => f.as_str().parse::<f64>().ok(),
+            DeValue::Integer(i) => i64::from_str_radix(i.as_str(), i.radix())
+                .ok()
+                .map(|i| i as f64),
+            _ => None,
+        };
+        match number {
+            Some(x) if valid(x) => Some(x),
+            Some(x) => {
+                let message = format!("Must be {}, got {}", expected, x);
+                self.error(&join_key(path, key), value.span(), message);
+                None
+            }
+            None => {
+                let message = format!("Expected a number, found {}", value.get_ref().type_str());
+                self.error(&join_key(path, key), value.span(), message);
+                None
+            }
+        }
+    }
+
+    fn count(&mut self, path: &str, table: &DeTable, key: &str) -> Option<u64> {
+        let value = table.get(key)?;
+        let parsed = match value.get_ref() {
+            DeValue::Integer(i) => u64::from_str_radix(i.as_str(), i.radix()).ok(),
+            _ => None,
+        };
+        if parsed.is_none() {
+            self.error(
+                &join_key(path, key),
+                value.span(),
+                "Expected a whole number of at least 0",
+            );
+        }
+        parsed
+    }
+
+    fn experiment(&mut self, root: &DeTable, base: &Path) -> Experiment {
+        self.keys(
+            "",
+            root,
+            &[
+                "morphology",
+                "standardize",
+                "membrane",
+                "rules",
+                "stimuli",
+                "probes",
+                "simulation",
+                "solver",
+                "output",
+            ],
+        );
+        let mut experiment = Experiment::new(PathBuf::new());
+
+        match self.table("", root, "morphology") {
+            Some(t) => self.morphology(t, &root["morphology"].span(), base, &mut experiment),
+            None if root.get("morphology").is_none() => {
+                self.error("morphology", 0..0, "Missing the [morphology] table")
+            }
+            None => {}
+        }
+        if let Some(t) = self.table("", root, "standardize") {
+            experiment.standardize = self.standardize(t);
+        }
+        if let Some(t) = self.table("", root, "membrane") {
+            self.membrane(t, &root["membrane"].span(), &mut experiment.membrane);
+        }
+        if let Some(t) = self.table("", root, "simulation") {
+            let span = root["simulation"].span();
+            self.keys("simulation", t, &["dt", "duration", "seed"]);
+            let positive = |x: f64| x > 0.0 && x.is_finite();
+            if let Some(dt) = self.number("simulation", t, "dt", &span, false, positive, "positive")
+            {
+                experiment.dt = dt;
+            }
+            if let Some(d) = self.number(
+                "simulation",
+                t,
+                "duration",
+                &span,
+                false,
+                positive,
+                "positive",
+            ) {
+                experiment.duration = d;
+            }
+            if let Some(seed) = self.count("simulation", t, "seed") {
+                experiment.seed = seed;
+            }
+        }
+        if let Some(t) = self.table("", root, "solver") {
+            self.keys("solver", t, &["threads", "min_subtree"]);
+            let threads = self.count("solver", t, "threads").unwrap_or(0);
+            let min_subtree = self.count("solver", t, "min_subtree").unwrap_or(1000);
+            experiment.solver =
+                SolverOptions::parallel_tree(threads as usize, min_subtree as usize);
+        }
+        if let Some(t) = self.table("", root, "output") {
+            self.output(t, &root["output"].span(), base, &mut experiment.output);
+        } else {
+            experiment.output.directory = base.join(&experiment.output.directory);
+        }
+        for (k, (t, span)) in self.tables(root, "rules").into_iter().enumerate() {
+            self.rule(k, t, &span, &mut experiment.profile);
+        }
+        let steps = experiment.steps();
+        for (k, (t, span)) in self.tables(root, "stimuli").into_iter().enumerate() {
+            if let Some(s) = self.stimulus(k, t, &span, experiment.dt, steps) {
+                experiment.stimuli.push(s);
+            }
+        }
+        let mut names: HashMap<String, usize> = HashMap::new();
+        for (k, (t, span)) in self.tables(root, "probes").into_iter().enumerate() {
+            let path = format!("probes[{}]", k);
+            self.keys(&path, t, &["name", "site"]);
+            let name = self.string(&path, t, "name", &span, true);
+            let site = self.string(&path, t, "site", &span, true);
+            let (Some((name, name_span)), Some((site, _))) = (name, site) else {
+                continue;
+            };
+            let bad = name.is_empty()
+                || !name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "_-.".contains(c));
+            if bad {
+                let message = format!(
+                    "Probe names become file names, use letters, digits, '_', '-' and '.', got '{}'",
+                    name
+                );
+                self.error(&format!("{}.name", path), name_span, message);
+                continue;
+            }
+            if let Some(first) = names.insert(name.clone(), k) {
+                let message = format!("Probe '{}' is already probes[{}]", name, first);
+                self.error(&format!("{}.name", path), name_span, message);
+                continue;
+            }
+            experiment.probes.push(Probe { name, site });
+        }
+        experiment
+    }
+
+    fn morphology(
+        &mut self,
+        t: &DeTable,
+        span: &Range<usize>,
+        base: &Path,
+        experiment: &mut Experiment,
+    ) {
+        let path = "morphology";
+        self.keys(
+            path,
+            t,
+            &[
+                "path",
+                "strict",
+                "decimal_comma",
+                "apply_scale",
+                "max_nodes",
+                "sha256",
+            ],
+        );
+        if let Some((p, _)) = self.string(path, t, "path", span, true) {
+            experiment.morphology = base.join(p);
+        }
+        let reader = &mut experiment.reader;
+        reader.strict = self.bool(path, t, "strict", reader.strict);
+        reader.decimal_comma = self.bool(path, t, "decimal_comma", reader.decimal_comma);
+        reader.apply_scale = self.bool(path, t, "apply_scale", reader.apply_scale);
+        if let Some(n) = self.count(path, t, "max_nodes") {
+            reader.max_nodes = Some(n as usize);
+        }
+        if let Some((sha, _)) = self.string(path, t, "sha256", span, false) {
+            reader.expected_sha256 = Some(sha);
+        }
+    }
+
+    /// The table as a standardize recipe, each key checked by
+    /// `StandardizeOptions::parse`
+    fn standardize(&mut self, t: &DeTable) -> Option<StandardizeOptions> {
+        let known = [
+            "units",
+            "infer_types",
+            "reroot_at_soma",
+            "soma",
+            "zero_radius",
+            "max_spacing",
+            "normalize_frame",
+            "quarantine_below",
+        ];
+        self.keys("standardize", t, &known);
+        let mut recipe = String::new();
+        let mut failed = false;
+        for (key, value) in t.iter() {
+            let name = key.get_ref().as_ref();
+            if !known.contains(&name) {
+                continue;
+            }
+            let text = match value.get_ref() {
+                DeValue::String(s) => s.to_string(),
+                DeValue::Boolean(b) => b.to_string(),
+                DeValue::Float(f) => f.as_str().to_owned(),
+                DeValue::Integer(i) => i.as_str().to_owned(),
+                other => other.type_str().to_owned(),
+            };
+            let line = format!("{} {}", name, text);
+            if let Err(e) = StandardizeOptions::parse(&line) {
+                self.error(&join_key("standardize", name), value.span(), e.to_string());
+                failed = true;
+            }
+            recipe.push_str(&line);
+            recipe.push('\n');
+        }
+        if failed {
+            return None;
+        }
+        StandardizeOptions::parse(&recipe).ok()
+    }
+
+    fn membrane(&mut self, t: &DeTable, span: &Range<usize>, membrane: &mut MembraneSpec) {
+        let path = "membrane";
+        self.keys(
+            path,
+            t,
+            &["mechanism", "resistance", "capacitance", "conductance"],
+        );
+        if let Some((m, m_span)) = self.string(path, t, "mechanism", span, false) {
+            match m.as_str() {
+                "pas" => membrane.kind = MembraneKind::Passive,
+                "hh" => membrane.kind = MembraneKind::HodgkinHuxley,
+                _ => self.error(
+                    "membrane.mechanism",
+                    m_span,
+                    format!("Unknown mechanism '{}'; use pas or hh", m),
+                ),
+            }
+        }
+        let positive = |x: f64| x > 0.0 && x.is_finite();
+        let non_negative = |x: f64| x >= 0.0 && x.is_finite();
+        if let Some(r) = self.number(path, t, "resistance", span, false, positive, "positive") {
+            membrane.resistance = r;
+        }
+        if let Some(c) = self.number(path, t, "capacitance", span, false, positive, "positive") {
+            membrane.capacitance = c;
+        }
+        if let Some(g) = self.number(
+            path,
+            t,
+            "conductance",
+            span,
+            false,
+            non_negative,
+            "non-negative",
+        ) {
+            membrane.conductance = g;
+        }
+    }
+
+    fn rule(
+        &mut self,
+        k: usize,
+        t: &DeTable,
+        span: &Range<usize>,
+        profile: &mut BiophysicsProfile,
+    ) {
+        let path = format!("rules[{}]", k);
+        self.locations
+            .insert(path.clone(), location(self.source, span.start));
+        self.keys(
+            &path,
+            t,
+            &["region", "mechanism", "parameter", "expression"],
+        );
+        let region = self.string(&path, t, "region", span, true);
+        let mechanism = self.string(&path, t, "mechanism", span, true);
+        let parameter = self.string(&path, t, "parameter", span, true);
+        let expression = self.string(&path, t, "expression", span, true);
+        let (Some(region), Some(mechanism), Some(parameter), Some((expression, e_span))) =
+            (region, mechanism, parameter, expression)
+        else {
+            return;
+        };
+        if let Err(e) = Expression::parse(&expression) {
+            // Past the opening quote, at the offending character
+            let at = e_span.start
+                + 1
+                + expression
+                    .char_indices()
+                    .nth(e.position)
+                    .map_or(expression.len(), |(i, _)| i);
+            self.error(
+                &format!("{}.expression", path),
+                at..at,
+                format!("{} in '{}'", e.message, expression),
+            );
+            return;
+        }
+        // The profile's own text form checks the region and parameter
+        let line = format!(
+            "{} {} {} = {}",
+            region.0, mechanism.0, parameter.0, expression
+        );
+        match BiophysicsProfile::parse(&line) {
+            Ok(parsed) => profile.rules.extend(parsed.rules),
+            Err(e) => {
+                let message = e.trim_start_matches("Line 1: ").to_owned();
+                let span = if message.starts_with("unknown region") {
+                    region.1
+                } else {
+                    parameter.1
+                };
+                self.error(&path, span, message);
+            }
+        }
+    }
+
+    fn stimulus(
+        &mut self,
+        k: usize,
+        t: &DeTable,
+        span: &Range<usize>,
+        dt: f64,
+        steps: usize,
+    ) -> Option<StimulusSpec> {
+        let path = format!("stimuli[{}]", k);
+        self.locations
+            .insert(path.clone(), location(self.source, span.start));
+        let kind = self.string(&path, t, "kind", span, true);
+        let site = self.string(&path, t, "site", span, true);
+        let (kind, kind_span) = kind?;
+        let finite = |x: f64| x.is_finite();
+        let non_negative = |x: f64| x >= 0.0 && x.is_finite();
+        let positive = |x: f64| x > 0.0 && x.is_finite();
+        let num = |w: &mut Self, key: &str, valid: &dyn Fn(f64) -> bool, expected: &str| {
+            w.number(&path, t, key, span, true, valid, expected)
+        };
+        let waveform = match kind.as_str() {
+            "step" => {
+                self.keys(
+                    &path,
+                    t,
+                    &["site", "kind", "amplitude", "start", "duration"],
+                );
+                let amplitude = num(self, "amplitude", &finite, "finite");
+                let start = num(self, "start", &non_negative, "non-negative");
+                let duration = num(self, "duration", &positive, "positive");
+                Waveform::Step {
+                    start: start?,
+                    duration: duration?,
+                    amplitude: amplitude?,
+                }
+            }
+            "alpha" => {
+                self.keys(&path, t, &["site", "kind", "amplitude", "onset", "tau"]);
+                let amplitude = num(self, "amplitude", &finite, "finite");
+                let onset = num(self, "onset", &non_negative, "non-negative");
+                let tau = num(self, "tau", &positive, "positive");
+                Waveform::Shaped(Stimulus::Alpha {
+                    onset: onset?,
+                    tau: tau?,
+                    amplitude: amplitude?,
+                })
+            }
+            "biexp" => {
+                self.keys(
+                    &path,
+                    t,
+                    &[
+                        "site",
+                        "kind",
+                        "amplitude",
+                        "onset",
+                        "tau_rise",
+                        "tau_decay",
+                    ],
+                );
+                let amplitude = num(self, "amplitude", &finite, "finite");
+                let onset = num(self, "onset", &non_negative, "non-negative");
+                let tau_rise = num(self, "tau_rise", &positive, "positive");
+                let tau_decay = num(self, "tau_decay", &positive, "positive");
+                let (tau_rise, tau_decay) = (tau_rise?, tau_decay?);
+                if tau_rise >= tau_decay {
+                    self.error(
+                        &format!("{}.tau_rise", path),
+                        t["tau_rise"].span(),
+                        format!(
+                            "Must be shorter than tau_decay, got {} and {}",
+                            tau_rise, tau_decay
+                        ),
+                    );
+                    return None;
+                }
+                Waveform::Shaped(Stimulus::BiExp {
+                    onset: onset?,
+                    tau_rise,
+                    tau_decay,
+                    amplitude: amplitude?,
+                })
+            }
+            "protocol" => {
+                self.keys(&path, t, &["site", "kind", "spec", "sweep"]);
+                let (spec, spec_span) = self.string(&path, t, "spec", span, true)?;
+                let protocol = match Protocol::from_spec(&spec) {
+                    Ok(protocol) => protocol,
+                    Err(e) => {
+                        self.error(&format!("{}.spec", path), spec_span, e);
+                        return None;
+                    }
+                };
+                let sweep = self.count(&path, t, "sweep").unwrap_or(0) as usize;
+                if sweep >= protocol.sweeps() {
+                    let message = format!(
+                        "No sweep {} of {}, which has {}",
+                        sweep,
+                        protocol.name(),
+                        protocol.sweeps()
+                    );
+                    self.error(&format!("{}.sweep", path), t["sweep"].span(), message);
+                    return None;
+                }
+                Waveform::Protocol { protocol, sweep }
+            }
+            "trace" => {
+                self.keys(&path, t, &["site", "kind", "values"]);
+                let value = self.value(&path, t, "values", span, true)?;
+                let values: Option<Vec<f64>> = match value.get_ref() {
+                    DeValue::Array(items) => items
+                        .iter()
+                        .map(|item| match item.get_ref() {
+                            DeValue::Float(f) => f.as_str().parse::<f64>().ok(),
+                            DeValue::Integer(i) => i.as_str().parse::<i64>().ok().map(|i| i as f64),
+                            _ => None,
+                        })
+                        .collect(),
+                    _ => None,
+                };
+                let key = format!("{}.values", path);
+                let Some(values) = values else {
+                    self.error(&key, value.span(), "Expected an array of numbers, in nA");
+                    return None;
+                };
+                if values.len() != steps {
+                    let message = format!(
+                        "{} values for {} steps of {} ms; give one per step",
+                        values.len(),
+                        steps,
+                        dt
+                    );
+                    self.error(&key, value.span(), message);
+                    return None;
+                }
+                Waveform::Trace(values)
+            }
+            _ => {
+                self.error(
+                    &format!("{}.kind", path),
+                    kind_span,
+                    format!(
+                        "Unknown stimulus kind '{}'; use step, alpha, biexp, trace or protocol",
+                        kind
+                    ),
+                );
+                return None;
+            }
+        };
+        Some(StimulusSpec {
+            site: site?.0,
+            waveform,
+        })
+    }
+
+    fn output(&mut self, t: &DeTable, span: &Range<usize>, base: &Path, output: &mut OutputSpec) {
+        let path = "output";
+        self.keys(path, t, &["directory", "formats", "manifest"]);
+        if let Some((dir, _)) = self.string(path, t, "directory", span, false) {
+            output.directory = PathBuf::from(dir);
+        }
+        output.directory = base.join(&output.directory);
+        output.manifest = self.bool(path, t, "manifest", output.manifest);
+        let Some(value) = t.get("formats") else {
+            return;
+        };
+        let DeValue::Array(items) = value.get_ref() else {
+            self.error(
+                "output.formats",
+                value.span(),
+                "Expected an array, e.g. [\"csv\"]",
+            );
+            return;
+        };
+        output.formats.clear();
+        for item in items.iter() {
+            match item.get_ref().as_str() {
+                Some("csv") => output.formats.push(TraceFormat::Csv),
+                Some("npy") => output.formats.push(TraceFormat::Npy),
+                _ => self.error(
+                    "output.formats",
+                    item.span(),
+                    "Unknown format; use \"csv\" or \"npy\"",
+                ),
+            }
+        }
+    }
+}
+
+fn join_key(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_owned()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
@@ -0,0 +1,250 @@
+//! Node tables with optional topology columns, so downstream analyses can
+//! ask "is this a tip" or "how far from the soma" without rebuilding the
+//! tree from the parent column.
+//!
+//! The base columns are the SWC ones. Derived columns are picked with
+//! `ExportColumns` and always come after them, in the order the flags are
+//! declared. However many are picked, the tree is walked once root to tips
+//! and once tips to root.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::error::SwcError;
+use crate::features::{euclidean, root_of};
+use crate::swc_reader::{ConflictPolicy, Skeleton, format_float};
+use crate::write;
+
+bitflags::bitflags! {
+    /// Derived columns to add to a node table
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+    pub struct ExportColumns: u32 {
+        const N_CHILDREN = 1;
+        const IS_TIP = 1 << 1;
+        const IS_BRANCH_POINT = 1 << 2;
+        /// Branch points between the root and the node, as in `features`
+        const BRANCH_ORDER = 1 << 3;
+        /// 1 at tips, going up by one where two branches of the same order
+        /// meet
+        const STRAHLER_ORDER = 1 << 4;
+        /// Path length to the root, in µm
+        const PATH_DISTANCE = 1 << 5;
+        /// Cable distal to the node, its own parent segment left out, in µm
+        const SUBTREE_CABLE_LENGTH = 1 << 6;
+        /// The `NodeFlags` bits as an integer
+        const FLAGS = 1 << 7;
+    }
+}
+
+/// Values of one column, one per node
+#[derive(Debug, Clone, PartialEq)]
+pub enum Column {
+    Int(Vec<i64>),
+    Float(Vec<f64>),
+    Bool(Vec<bool>),
+}
+
+impl Column {
+    fn render(&self, row: usize) -> String {
+        match self {
+            Column::Int(v) => v[row].to_string(),
+            Column::Float(v) => format_float(v[row]),
+            Column::Bool(v) => v[row].to_string(),
+        }
+    }
+}
+
+/// Named columns in the order they are written, rows in the skeleton's node
+/// order
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeTable {
+    pub columns: Vec<(&'static str, Column)>,
+}
+
+impl NodeTable {
+    pub fn names(&self) -> Vec<&'static str> {
+        self.columns.iter().map(|(name, _)| *name).collect()
+    }
+
+    pub fn column(&self, name: &str) -> Option<&Column> {
+        self.columns
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, column)| column)
+    }
+
+    pub fn rows(&self) -> usize {
+        match self.columns.first() {
+            Some((_, Column::Int(v))) => v.len(),
+            Some((_, Column::Float(v))) => v.len(),
+            Some((_, Column::Bool(v))) => v.len(),
+            None => 0,
+        }
+    }
+
+    /// Comma separated, with a header row of the column names. Booleans are
+    /// written `true`/`false`, which pandas reads back as bools.
+    pub fn to_csv(&self) -> String {
+        let mut output = self.names().join(",");
+        output.push('\n');
+        for row in 0..self.rows() {
+            let fields: Vec<String> = self.columns.iter().map(|(_, c)| c.render(row)).collect();
+            output.push_str(&fields.join(","));
+            output.push('\n');
+        }
+        output
+    }
+
+    /// Writes `to_csv` to `path` atomically
+    pub fn write_csv(
+        &self,
+        path: impl AsRef<Path>,
+        policy: ConflictPolicy,
+    ) -> Result<(), SwcError> {
+        write::write_atomic(path.as_ref(), self.to_csv().as_bytes(), policy)
+    }
+}
+
+impl Skeleton {
+    /// The nodes as a table: `id`, `type`, `x`, `y`, `z`, `radius` and
+    /// `parent` (-1 at the root), then the selected derived columns
+    pub fn node_table(&self, columns: ExportColumns) -> Result<NodeTable, String> {
+        let root = root_of(self)?;
+        let order = self.subtree(root);
+        if order.len() != self.nodes.len() {
+            return Err(format!(
+                "{} of {} nodes are not connected to the root",
+                self.nodes.len() - order.len(),
+                self.nodes.len()
+            ));
+        }
+        let index: HashMap<u64, usize> = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(i, n)| (n.node_id, i))
+            .collect();
+        let parent: Vec<usize> = self.nodes.iter().map(|n| index[&n.parent_id]).collect();
+        let order: Vec<usize> = order.iter().map(|id| index[id]).collect();
+        let n = self.nodes.len();
+        let (root, order) = (order[0], &order[1..]);
+
+        let mut n_children = vec![0usize; n];
+        for &i in order {
+            n_children[parent[i]] += 1;
+        }
+
+        // Root to tips: everything that depends on the path from the root
+        let mut distance = vec![0.0; n];
+        let mut branch_order = vec![0usize; n];
+        let mut segment = vec![0.0; n];
+        for &i in order {
+            let p = parent[i];
+            segment[i] = euclidean(&self.nodes[i], &self.nodes[p]);
+            distance[i] = distance[p] + segment[i];
+            branch_order[i] = branch_order[p] + usize::from(n_children[p] > 1);
+        }
+
+        // Tips to root: everything that depends on what is distal. Children
+        // come after their parent in breadth first order, so going backwards
+        // finishes every node before its parent is reached.
+        let mut subtree_length = vec![0.0; n];
+        let mut strahler = vec![1usize; n];
+        // Highest child order seen so far and whether two children had it
+        let mut highest = vec![(0usize, false); n];
+        for &i in order.iter().rev() {
+            let (max, tied) = highest[i];
+            if n_children[i] > 0 {
+                strahler[i] = max + usize::from(tied);
+            }
+            let p = parent[i];
+            subtree_length[p] += subtree_length[i] + segment[i];
+            highest[p] = match strahler[i].cmp(&highest[p].0) {
+                std::cmp::Ordering::Greater => (strahler[i], false),
+                std::cmp::Ordering::Equal => (strahler[i], true),
+                std::cmp::Ordering::Less => highest[p],
+            };
+        }
+        if n_children[root] > 0 {
+            let (max, tied) = highest[root];
+            strahler[root] = max + usize::from(tied);
+        }
+
+        let as_int = |v: &[usize]| Column::Int(v.iter().map(|&x| x as i64).collect());
+        let mut table: Vec<(&'static str, Column)> = vec![
+            (
+                "id",
+                Column::Int(self.nodes.iter().map(|n| n.node_id as i64).collect()),
+            ),
+            (
+                "type",
+                Column::Int(
+                    self.nodes
+                        .iter()
+                        .map(|n| n.structured_identifier as i64)
+                        .collect(),
+                ),
+            ),
+            (
+                "x",
+                Column::Float(self.nodes.iter().map(|n| n.x_pos).collect()),
+            ),
+            (
+                "y",
+                Column::Float(self.nodes.iter().map(|n| n.y_pos).collect()),
+            ),
+            (
+                "z",
+                Column::Float(self.nodes.iter().map(|n| n.z_pos).collect()),
+            ),
+            (
+                "radius",
+                Column::Float(self.nodes.iter().map(|n| n.radius).collect()),
+            ),
+            (
+                "parent",
+                Column::Int(
+                    self.nodes
+                        .iter()
+                        .map(|n| {
+                            if n.parent_id == n.node_id {
+                                -1
+                            } else {
+                                n.parent_id as i64
+                            }
+                        })
+                        .collect(),
+                ),
+            ),
+        ];
+        let derived = [
+            (ExportColumns::N_CHILDREN, "n_children"),
+            (ExportColumns::IS_TIP, "is_tip"),
+            (ExportColumns::IS_BRANCH_POINT, "is_branch_point"),
+            (ExportColumns::BRANCH_ORDER, "branch_order"),
+            (ExportColumns::STRAHLER_ORDER, "strahler_order"),
+            (ExportColumns::PATH_DISTANCE, "path_distance"),
+            (ExportColumns::SUBTREE_CABLE_LENGTH, "subtree_cable_length"),
+            (ExportColumns::FLAGS, "flags"),
+        ];
+        for (flag, name) in derived {
+            if !columns.contains(flag) {
+                continue;
+            }
+            let column = match flag {
+                ExportColumns::N_CHILDREN => as_int(&n_children),
+                ExportColumns::IS_TIP => Column::Bool(n_children.iter().map(|&c| c == 0).collect()),
+                ExportColumns::IS_BRANCH_POINT => {
+                    Column::Bool(n_children.iter().map(|&c| c > 1).collect())
+                }
+                ExportColumns::BRANCH_ORDER => as_int(&branch_order),
+                ExportColumns::STRAHLER_ORDER => as_int(&strahler),
+                ExportColumns::PATH_DISTANCE => Column::Float(distance.clone()),
+                ExportColumns::SUBTREE_CABLE_LENGTH => Column::Float(subtree_length.clone()),
+                _ => Column::Int(self.nodes.iter().map(|n| n.flags.bits() as i64).collect()),
+            };
+            table.push((name, column));
+        }
+        Ok(NodeTable { columns: table })
+    }
+}
@@ -0,0 +1,130 @@
+//! Extracellular stimulation by point-source electrodes. Each electrode sets
+//! up the quasi-static field `V_e = I / (4πσr)` of a current source in an
+//! infinite homogeneous medium, and the fields of several electrodes add.
+//!
+//! The cable feels the field through the difference in `V_e` between
+//! neighbouring compartments: the activating function. It enters like an
+//! injected current, `g_axial (V_e,parent - V_e,child)` into the child and
+//! the opposite into the parent, which is the form a solver adds to its
+//! axial terms.
+//!
+//! Positions are in µm, electrode currents in nA and σ in S/m, which puts
+//! `V_e` in mV. Activating currents are in nA, positive depolarizing.
+
+use crate::compartments::{Compartment, Compartments};
+use crate::geometry;
+
+/// `g` from `axial_conductances` is per Ω·cm/µm, and nA per mV per S is 1e6
+const NA_PER_MV_PER_AXIAL_UNIT: f64 = 1e-4 * 1e6;
+
+/// One point-source electrode
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtracellularStimulus {
+    pub position: [f64; 3],
+    /// Electrode current at each time step, in nA; negative for a cathode
+    pub current_waveform: Vec<f64>,
+    /// Conductivity of the medium, in S/m
+    pub sigma: f64,
+}
+
+impl ExtracellularStimulus {
+    fn validate(&self) -> Result<(), String> {
+        if !(self.sigma > 0.0 && self.sigma.is_finite()) {
+            return Err(format!(
+                "Conductivity must be positive, got {} S/m",
+                self.sigma
+            ));
+        }
+        if self.position.iter().any(|p| !p.is_finite()) {
+            return Err(format!(
+                "Electrode position must be finite, got {:?}",
+                self.position
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Middle of the compartment's centerline
+fn center(c: &Compartment) -> [f64; 3] {
+    geometry::scale(geometry::add(c.proximal, c.distal), 0.5)
+}
+
+/// `V_e` at every compartment centre and every step, as `[step][idx]` in mV
+/// and indexed like `components`; the dummy root stays at zero. An
+/// electrode closer to a centre than the compartment's radius is taken to
+/// be at its surface, which keeps the field finite. All waveforms must have
+/// the same length.
+pub fn extracellular_potentials(
+    compartments: &Compartments,
+    electrodes: &[ExtracellularStimulus],
+) -> Result<Vec<Vec<f64>>, String> {
+    let steps = electrodes.first().map_or(0, |e| e.current_waveform.len());
+    for e in electrodes {
+        e.validate()?;
+        if e.current_waveform.len() != steps {
+            return Err(format!(
+                "Electrode waveforms differ in length: {} and {} steps",
+                steps,
+                e.current_waveform.len()
+            ));
+        }
+    }
+
+    // Potential per nA of each electrode at each compartment; the field is
+    // linear in the current, so only the scale changes from step to step
+    let per_na: Vec<Vec<f64>> = electrodes
+        .iter()
+        .map(|e| {
+            compartments
+                .components
+                .iter()
+                .enumerate()
+                .map(|(idx, c)| {
+                    if idx == 0 {
+                        return 0.0;
+                    }
+                    let r = geometry::norm(geometry::sub(center(c), e.position)).max(c.diam / 2.0);
+                    1.0 / (4.0 * std::f64::consts::PI * e.sigma * r)
+                })
+                .collect()
+        })
+        .collect();
+
+    Ok((0..steps)
+        .map(|k| {
+            let mut v_e = vec![0.0; compartments.components.len()];
+            for (e, field) in electrodes.iter().zip(&per_na) {
+                let current = e.current_waveform[k];
+                for (v, f) in v_e.iter_mut().zip(field) {
+                    *v += current * f;
+                }
+            }
+            v_e
+        })
+        .collect())
+}
+
+/// The current the field `v_e` (one step of `extracellular_potentials`)
+/// drives into each compartment through its axial connections, in nA.
+/// Exactly zero everywhere when the field is uniform.
+pub fn activating_currents(compartments: &Compartments, v_e: &[f64]) -> Result<Vec<f64>, String> {
+    if v_e.len() != compartments.components.len() {
+        return Err(format!(
+            "Need one potential per compartment, got {} for {}",
+            v_e.len(),
+            compartments.components.len()
+        ));
+    }
+    let mut currents = vec![0.0; v_e.len()];
+    for (parent, child, g) in compartments.axial_conductances() {
+        // Zero-length neighbours act as one node, with nothing in between
+        if g.is_infinite() {
+            continue;
+        }
+        let i = g * NA_PER_MV_PER_AXIAL_UNIT * (v_e[parent] - v_e[child]);
+        currents[child] += i;
+        currents[parent] -= i;
+    }
+    Ok(currents)
+}
@@ -0,0 +1,393 @@
+//! Fixed-length morphology feature vectors for clustering.
+//!
+//! A branch is an unbranched run of nodes from the root or a branch point to
+//! the next branch point or tip, as returned by `Skeleton::branch_path`. The
+//! vector is laid out as four histograms, in this order: branch length, branch
+//! order, tortuosity and radius taper rate. Each histogram has one bin more
+//! than it has edges: below the first edge, between consecutive edges, and at
+//! or above the last edge. So every branch is counted once per histogram.
+//!
+//! `morphometrics` is a short vector of scalar features instead, computed
+//! under a choice of `Conventions` so it can match other tools.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::conventions::{BranchOrder, Conventions, MORPHOMETRICS, PathDistance};
+use crate::morphometry::Morphometry;
+use crate::swc_reader::{Node, Skeleton, StructureIdentifier};
+
+/// Bin edges for each histogram in the feature vector
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeatureConfig {
+    pub branch_length_edges: Vec<f64>,
+    pub branch_order_edges: Vec<f64>,
+    pub tortuosity_edges: Vec<f64>,
+    pub taper_edges: Vec<f64>,
+}
+
+impl Default for FeatureConfig {
+    fn default() -> Self {
+        FeatureConfig {
+            branch_length_edges: vec![10.0, 25.0, 50.0, 100.0, 200.0, 400.0],
+            branch_order_edges: vec![1.0, 2.0, 3.0, 4.0, 6.0, 8.0],
+            tortuosity_edges: vec![1.05, 1.1, 1.2, 1.5, 2.0],
+            taper_edges: vec![-0.01, 0.0, 0.005, 0.01, 0.02, 0.05],
+        }
+    }
+}
+
+/// Feature values with a name for each, in the documented order
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeatureVector {
+    pub names: Vec<String>,
+    pub values: Vec<f64>,
+}
+
+/// Per-branch quantities the histograms are built from
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct BranchStats {
+    /// Node IDs from the proximal to the distal end, both included
+    pub path: Vec<u64>,
+    pub length: f64,
+    /// Path length over the straight-line distance between the ends; 1.0 for a
+    /// straight branch, and also for a zero-length one
+    pub tortuosity: f64,
+    /// Radius lost per unit length going distally
+    pub taper: f64,
+    /// Number of branch points between the root and the branch's distal end,
+    /// the root included when it branches
+    pub order: usize,
+}
+
+/// Path distance from the root and branch order of every node
+pub(crate) fn per_node(skeleton: &Skeleton, root: u64) -> (HashMap<u64, f64>, HashMap<u64, usize>) {
+    let nodes = by_id(skeleton);
+    let mut distance = HashMap::from([(root, 0.0)]);
+    let mut order = HashMap::from([(root, 0)]);
+    for id in skeleton.subtree(root).into_iter().skip(1) {
+        let node = nodes[&id];
+        let parent = nodes[&node.parent_id];
+        let branches = usize::from(skeleton.children_of(parent.node_id).len() > 1);
+        distance.insert(id, distance[&parent.node_id] + euclidean(node, parent));
+        order.insert(id, order[&parent.node_id] + branches);
+    }
+    (distance, order)
+}
+
+pub(crate) fn by_id(skeleton: &Skeleton) -> HashMap<u64, &Node> {
+    skeleton.nodes.iter().map(|n| (n.node_id, n)).collect()
+}
+
+pub(crate) fn euclidean(a: &Node, b: &Node) -> f64 {
+    ((a.x_pos - b.x_pos).powi(2) + (a.y_pos - b.y_pos).powi(2) + (a.z_pos - b.z_pos).powi(2)).sqrt()
+}
+
+pub(crate) fn root_of(skeleton: &Skeleton) -> Result<u64, String> {
+    skeleton
+        .nodes
+        .iter()
+        .find(|n| n.parent_id == n.node_id)
+        .map(|n| n.node_id)
+        .ok_or_else(|| "No root node found".to_owned())
+}
+
+/// Every branch of the skeleton, in breadth first order of their distal ends
+pub fn branches(skeleton: &Skeleton) -> Result<Vec<BranchStats>, String> {
+    branches_with(skeleton, &Conventions::native())
+}
+
+/// Path distance from the root of every node, counting only the segments
+/// `conventions` count as cable
+fn cable_distances(skeleton: &Skeleton, root: u64, conventions: &Conventions) -> HashMap<u64, f64> {
+    let nodes = by_id(skeleton);
+    let mut distance = HashMap::from([(root, 0.0)]);
+    for id in skeleton.subtree(root).into_iter().skip(1) {
+        let node = nodes[&id];
+        let parent = nodes[&node.parent_id];
+        let step = if conventions.is_cable(node.structured_identifier, parent.structured_identifier)
+        {
+            euclidean(node, parent)
+        } else {
+            0.0
+        };
+        distance.insert(id, distance[&parent.node_id] + step);
+    }
+    distance
+}
+
+/// Branch order of every node under `FromStem(first)`
+fn stem_orders(skeleton: &Skeleton, root: u64, first: usize) -> HashMap<u64, usize> {
+    let nodes = by_id(skeleton);
+    let mut order = HashMap::from([(root, first)]);
+    for id in skeleton.subtree(root).into_iter().skip(1) {
+        let parent = nodes[&nodes[&id].parent_id];
+        let o = if parent.node_id == root
+            || parent.structured_identifier == StructureIdentifier::Soma
+        {
+            first
+        } else {
+            order[&parent.node_id] + usize::from(skeleton.children_of(parent.node_id).len() > 1)
+        };
+        order.insert(id, o);
+    }
+    order
+}
+
+/// `branches` under `conventions`. A branch leaving the soma starts at its
+/// first neurite node unless `stems_from_soma`, and soma nodes only end
+/// branches with `soma_as_cable`.
+pub fn branches_with(
+    skeleton: &Skeleton,
+    conventions: &Conventions,
+) -> Result<Vec<BranchStats>, String> {
+    let root = root_of(skeleton)?;
+    let nodes = by_id(skeleton);
+    let soma = |id: u64| nodes[&id].structured_identifier == StructureIdentifier::Soma;
+    let distance = cable_distances(skeleton, root, conventions);
+    let order = match conventions.branch_order {
+        BranchOrder::BranchPoints => per_node(skeleton, root).1,
+        BranchOrder::FromStem(first) => stem_orders(skeleton, root, first),
+    };
+    Ok(skeleton
+        .subtree(root)
+        .into_iter()
+        .filter(|&id| {
+            id != root
+                && skeleton.children_of(id).len() != 1
+                && (conventions.soma_as_cable || !soma(id))
+        })
+        .map(|end| {
+            let mut path = vec![end];
+            let mut id = end;
+            loop {
+                id = nodes[&id].parent_id;
+                path.push(id);
+                if id == root
+                    || skeleton.children_of(id).len() > 1
+                    || (!conventions.soma_as_cable && soma(id))
+                {
+                    break;
+                }
+            }
+            path.reverse();
+            if !conventions.stems_from_soma && soma(path[0]) && !soma(path[1]) {
+                path.remove(0);
+            }
+            let (first, last) = (nodes[&path[0]], nodes[&end]);
+            let length = distance[&end] - distance[&first.node_id];
+            let straight = euclidean(first, last);
+            let tortuosity = if straight > 0.0 {
+                length / straight
+            } else {
+                1.0
+            };
+            let taper = if length > 0.0 {
+                (first.radius - last.radius) / length
+            } else {
+                0.0
+            };
+            BranchStats {
+                path,
+                length,
+                tortuosity,
+                taper,
+                order: order[&end],
+            }
+        })
+        .collect())
+}
+
+fn mean(values: impl Iterator<Item = f64>) -> f64 {
+    let (sum, n) = values.fold((0.0, 0usize), |(sum, n), v| (sum + v, n + 1));
+    if n == 0 { 0.0 } else { sum / n as f64 }
+}
+
+/// The features of `conventions::MORPHOMETRICS` under `conventions`:
+/// total cable length; stems, the neurites leaving the soma (or the root of
+/// a skeleton without one); the number of `branches_with`, of those ending
+/// in a tip, and their largest and mean order, mean length and mean
+/// tortuosity; and the largest and mean path distance from the root to the
+/// neurite nodes or segment midpoints. Means of nothing are zero.
+pub fn morphometrics(
+    skeleton: &Skeleton,
+    conventions: &Conventions,
+) -> Result<FeatureVector, String> {
+    let root = root_of(skeleton)?;
+    let nodes = by_id(skeleton);
+    let branches = branches_with(skeleton, conventions)?;
+    let distance = cable_distances(skeleton, root, conventions);
+    let soma = |n: &Node| n.structured_identifier == StructureIdentifier::Soma;
+    let neurites = skeleton
+        .nodes
+        .iter()
+        .filter(|n| n.node_id != root && !soma(n));
+    let stems = neurites
+        .clone()
+        .filter(|n| n.parent_id == root || soma(nodes[&n.parent_id]))
+        .count();
+    let points: Vec<f64> = match conventions.path_distance {
+        PathDistance::NodeCenters => neurites.map(|n| distance[&n.node_id]).collect(),
+        PathDistance::SegmentMidpoints => neurites
+            .filter_map(|n| {
+                let parent = nodes[&n.parent_id];
+                conventions
+                    .is_cable(n.structured_identifier, parent.structured_identifier)
+                    .then(|| distance[&parent.node_id] + euclidean(n, parent) / 2.0)
+            })
+            .collect(),
+    };
+
+    let values = vec![
+        Morphometry::new(&skeleton.nodes).total_length_with(conventions),
+        stems as f64,
+        branches.len() as f64,
+        branches
+            .iter()
+            .filter(|b| skeleton.children_of(*b.path.last().unwrap()).is_empty())
+            .count() as f64,
+        branches.iter().map(|b| b.order).max().unwrap_or(0) as f64,
+        mean(branches.iter().map(|b| b.order as f64)),
+        mean(branches.iter().map(|b| b.length)),
+        mean(branches.iter().map(|b| b.tortuosity)),
+        points.iter().copied().fold(0.0, f64::max),
+        mean(points.iter().copied()),
+    ];
+    Ok(FeatureVector {
+        names: MORPHOMETRICS.iter().map(|f| f.name.to_owned()).collect(),
+        values,
+    })
+}
+
+fn histogram(name: &str, edges: &[f64], values: impl Iterator<Item = f64>) -> FeatureVector {
+    let mut counts = vec![0.0; edges.len() + 1];
+    for v in values {
+        counts[edges.partition_point(|&e| e <= v)] += 1.0;
+    }
+    let names = (0..=edges.len())
+        .map(|bin| match bin {
+            0 => format!(
+                "{}<{}",
+                name,
+                edges.first().copied().unwrap_or(f64::INFINITY)
+            ),
+            b if b == edges.len() => format!("{}>={}", name, edges[b - 1]),
+            b => format!("{}[{},{})", name, edges[b - 1], edges[b]),
+        })
+        .collect();
+    FeatureVector {
+        names,
+        values: counts,
+    }
+}
+
+/// The feature vector of one cell. Its length and names only depend on
+/// `config`, so vectors of different cells line up.
+pub fn morphology_features(
+    skeleton: &Skeleton,
+    config: &FeatureConfig,
+) -> Result<FeatureVector, String> {
+    Ok(histograms(&branches(skeleton)?, config))
+}
+
+/// Names of the `morphology_features` values under `config`
+pub fn feature_names(config: &FeatureConfig) -> Vec<String> {
+    histograms(&[], config).names
+}
+
+fn histograms(branches: &[BranchStats], config: &FeatureConfig) -> FeatureVector {
+    let parts = [
+        histogram(
+            "branch_length",
+            &config.branch_length_edges,
+            branches.iter().map(|b| b.length),
+        ),
+        histogram(
+            "branch_order",
+            &config.branch_order_edges,
+            branches.iter().map(|b| b.order as f64),
+        ),
+        histogram(
+            "tortuosity",
+            &config.tortuosity_edges,
+            branches.iter().map(|b| b.tortuosity),
+        ),
+        histogram(
+            "taper",
+            &config.taper_edges,
+            branches.iter().map(|b| b.taper),
+        ),
+    ];
+    let mut out = FeatureVector {
+        names: Vec::new(),
+        values: Vec::new(),
+    };
+    for part in parts {
+        out.names.extend(part.names);
+        out.values.extend(part.values);
+    }
+    out
+}
+
+/// One `morphology_features` row per skeleton
+pub fn feature_matrix(
+    skeletons: &[Skeleton],
+    config: &FeatureConfig,
+) -> Result<Vec<Vec<f64>>, String> {
+    skeletons
+        .iter()
+        .map(|s| morphology_features(s, config).map(|f| f.values))
+        .collect()
+}
+
+/// Along-the-tree distances between branch midpoints, with branches ordered as
+/// in `branches`. With `max_branches` set, an evenly spaced subset of that
+/// many branches is used.
+pub fn geodesic_matrix(
+    skeleton: &Skeleton,
+    max_branches: Option<usize>,
+) -> Result<Vec<Vec<f64>>, String> {
+    let root = root_of(skeleton)?;
+    let (distance, _) = per_node(skeleton, root);
+    let mut branches = branches(skeleton)?;
+    if let Some(k) = max_branches.filter(|&k| k < branches.len()) {
+        let n = branches.len();
+        branches = (0..k).map(|i| branches[i * n / k].clone()).collect();
+    }
+
+    let nodes = by_id(skeleton);
+    let ancestors = |id: u64| {
+        let mut out = vec![id];
+        let mut id = id;
+        while nodes[&id].parent_id != id {
+            id = nodes[&id].parent_id;
+            out.push(id);
+        }
+        out
+    };
+    let midpoint = |b: &BranchStats| distance[&b.path[0]] + b.length / 2.0;
+
+    let n = branches.len();
+    let mut matrix = vec![vec![0.0; n]; n];
+    for i in 0..n {
+        let above_i: HashSet<u64> = ancestors(*branches[i].path.last().unwrap())
+            .into_iter()
+            .collect();
+        for j in 0..i {
+            let end_j = *branches[j].path.last().unwrap();
+            let meet = ancestors(end_j)
+                .into_iter()
+                .find(|id| above_i.contains(id))
+                .unwrap_or(root);
+            let (mi, mj) = (midpoint(&branches[i]), midpoint(&branches[j]));
+            // Deepest point both midpoints pass through on the way to the root:
+            // the meeting node, or a midpoint itself if one branch lies on the
+            // other's path
+            let shared = distance[&meet].min(mi).min(mj);
+            let d = mi + mj - 2.0 * shared;
+            matrix[i][j] = d;
+            matrix[j][i] = d;
+        }
+    }
+    Ok(matrix)
+}
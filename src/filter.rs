@@ -0,0 +1,123 @@
+use std::cmp::Ordering;
+
+use crate::swc_reader::{Node, NodeFlags, Skeleton, StructureIdentifier};
+
+/// One of the columns past the standard seven, by position or by the name
+/// declared in the file's `# columns:` header
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExtraColumn {
+    Index(usize),
+    Name(String),
+}
+
+impl From<usize> for ExtraColumn {
+    fn from(idx: usize) -> Self {
+        ExtraColumn::Index(idx)
+    }
+}
+
+impl From<&str> for ExtraColumn {
+    fn from(name: &str) -> Self {
+        ExtraColumn::Name(name.to_owned())
+    }
+}
+
+/// Declarative selection of nodes. An empty filter matches everything; each
+/// added condition narrows it down further.
+#[derive(Debug, Clone, Default)]
+pub struct NodeFilter {
+    structures: Vec<StructureIdentifier>,
+    required_flags: NodeFlags,
+    excluded_flags: NodeFlags,
+    /// Each extra column value must compare to the threshold this way
+    extra_conditions: Vec<(ExtraColumn, Ordering, f64)>,
+}
+
+impl NodeFilter {
+    pub fn new() -> Self {
+        NodeFilter::default()
+    }
+
+    /// Only nodes of this structure type. Can be called several times to allow
+    /// any of a set of types.
+    pub fn structure(mut self, structure: StructureIdentifier) -> Self {
+        self.structures.push(structure);
+        self
+    }
+
+    /// Only nodes that have all of `flags` set
+    pub fn has_flag(mut self, flags: NodeFlags) -> Self {
+        self.required_flags |= flags;
+        self
+    }
+
+    /// Only nodes that have none of `flags` set
+    pub fn not_flag(mut self, flags: NodeFlags) -> Self {
+        self.excluded_flags |= flags;
+        self
+    }
+
+    /// Only nodes whose extra `column` is greater than `threshold`
+    pub fn extra_gt(mut self, column: impl Into<ExtraColumn>, threshold: f64) -> Self {
+        self.extra_conditions
+            .push((column.into(), Ordering::Greater, threshold));
+        self
+    }
+
+    /// Only nodes whose extra `column` is less than `threshold`
+    pub fn extra_lt(mut self, column: impl Into<ExtraColumn>, threshold: f64) -> Self {
+        self.extra_conditions
+            .push((column.into(), Ordering::Less, threshold));
+        self
+    }
+
+    /// A bare node carries no extra columns, so this never matches when
+    /// extra column conditions are set; use `select` for those.
+    pub fn matches(&self, node: &Node) -> bool {
+        self.extra_conditions.is_empty()
+            && self.matches_parts(node.structured_identifier, node.flags)
+    }
+
+    /// Like `matches`, with the node's extra columns looked up in `skeleton`
+    pub fn matches_in(&self, skeleton: &Skeleton, node: &Node) -> bool {
+        let extras = skeleton
+            .extras
+            .get(&node.node_id)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let extras_ok = self
+            .extra_conditions
+            .iter()
+            .all(|(column, ordering, threshold)| {
+                let idx = match column {
+                    ExtraColumn::Index(idx) => Some(*idx),
+                    ExtraColumn::Name(name) => {
+                        skeleton.extra_columns.iter().position(|c| c == name)
+                    }
+                };
+                idx.and_then(|i| extras.get(i))
+                    .and_then(|v| v.partial_cmp(threshold))
+                    == Some(*ordering)
+            });
+        extras_ok && self.matches_parts(node.structured_identifier, node.flags)
+    }
+
+    /// Shared by anything carrying a structure type and flags, e.g. compartments
+    pub(crate) fn matches_parts(&self, structure: StructureIdentifier, flags: NodeFlags) -> bool {
+        (self.structures.is_empty() || self.structures.contains(&structure))
+            && flags.contains(self.required_flags)
+            && !flags.intersects(self.excluded_flags)
+    }
+
+    pub fn apply<'a>(&'a self, nodes: &'a [Node]) -> impl Iterator<Item = &'a Node> + 'a {
+        nodes.iter().filter(move |n| self.matches(n))
+    }
+
+    /// `apply` over a skeleton's nodes, able to check extra columns
+    pub fn select<'a>(&'a self, skeleton: &'a Skeleton) -> impl Iterator<Item = &'a Node> + 'a {
+        skeleton
+            .nodes
+            .iter()
+            .filter(move |n| self.matches_in(skeleton, n))
+    }
+}
@@ -0,0 +1,364 @@
+//! Small, dependency free 3-D helpers shared by the morphology code.
+//! Everything works on `[f64; 3]` so nodes can be fed in without conversion.
+
+pub(crate) type Vec3 = [f64; 3];
+
+pub(crate) fn sub(a: Vec3, b: Vec3) -> Vec3 {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+pub(crate) fn dot(a: Vec3, b: Vec3) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+pub(crate) fn cross(a: Vec3, b: Vec3) -> Vec3 {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+pub(crate) fn norm(a: Vec3) -> f64 {
+    dot(a, a).sqrt()
+}
+
+pub(crate) fn scale(a: Vec3, s: f64) -> Vec3 {
+    [a[0] * s, a[1] * s, a[2] * s]
+}
+
+pub(crate) fn add(a: Vec3, b: Vec3) -> Vec3 {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+/// Parameters `(s, t)` in [0, 1] of the closest points `p0 + s (p1 - p0)`
+/// and `q0 + t (q1 - q0)` of two segments, either of which may be a point.
+/// After Ericson, Real-Time Collision Detection, 5.1.9.
+pub(crate) fn closest_on_segments(p0: Vec3, p1: Vec3, q0: Vec3, q1: Vec3) -> (f64, f64) {
+    let d1 = sub(p1, p0);
+    let d2 = sub(q1, q0);
+    let r = sub(p0, q0);
+    let (a, e, f) = (dot(d1, d1), dot(d2, d2), dot(d2, r));
+    const EPSILON: f64 = 1e-18;
+    if a <= EPSILON && e <= EPSILON {
+        return (0.0, 0.0);
+    }
+    if a <= EPSILON {
+        return (0.0, (f / e).clamp(0.0, 1.0));
+    }
+    let c = dot(d1, r);
+    if e <= EPSILON {
+        return ((-c / a).clamp(0.0, 1.0), 0.0);
+    }
+    let b = dot(d1, d2);
+    let denominator = a * e - b * b;
+    // Parallel segments have no unique closest pair; any s does
+    let mut s = if denominator > EPSILON * a * e {
+        ((b * f - c * e) / denominator).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let mut t = (b * s + f) / e;
+    if t < 0.0 {
+        t = 0.0;
+        s = (-c / a).clamp(0.0, 1.0);
+    } else if t > 1.0 {
+        t = 1.0;
+        s = ((b - c) / a).clamp(0.0, 1.0);
+    }
+    (s, t)
+}
+
+/// `a + s (b - a)`
+pub(crate) fn lerp(a: Vec3, b: Vec3, s: f64) -> Vec3 {
+    add(a, scale(sub(b, a), s))
+}
+
+/// Turns `v` by the smallest rotation taking unit vector `from` onto unit
+/// vector `to`, then strips any component along `to` left by round-off.
+/// Carrying a normal along a polyline like this gives a frame that does not
+/// twist about the curve.
+pub(crate) fn transport(v: Vec3, from: Vec3, to: Vec3) -> Vec3 {
+    let axis = cross(from, to);
+    let sin = norm(axis);
+    let cos = dot(from, to);
+    let rotated = if sin < 1e-12 {
+        // Straight on or a full reversal; either way `v` is already
+        // perpendicular to `to`
+        v
+    } else {
+        let k = scale(axis, 1.0 / sin);
+        add(
+            add(scale(v, cos), scale(cross(k, v), sin)),
+            scale(k, dot(k, v) * (1.0 - cos)),
+        )
+    };
+    let n = sub(rotated, scale(to, dot(rotated, to)));
+    scale(n, 1.0 / norm(n))
+}
+
+/// Running sum with Neumaier compensation, accurate to about one rounding
+/// however many terms go in
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct Neumaier {
+    sum: f64,
+    compensation: f64,
+}
+
+impl Neumaier {
+    pub(crate) fn add(&mut self, v: f64) {
+        let t = self.sum + v;
+        self.compensation += if self.sum.abs() >= v.abs() {
+            (self.sum - t) + v
+        } else {
+            (v - t) + self.sum
+        };
+        self.sum = t;
+    }
+
+    pub(crate) fn value(&self) -> f64 {
+        self.sum + self.compensation
+    }
+}
+
+/// Sum that comes out bit-identical whatever order the values arrive in:
+/// they are sorted first, then added with Neumaier compensation so the
+/// result is also accurate to about one rounding. Use it wherever a total
+/// feeds a cache key or a comparison across machines.
+pub(crate) fn stable_sum(values: impl IntoIterator<Item = f64>) -> f64 {
+    let mut values: Vec<f64> = values.into_iter().collect();
+    values.sort_by(f64::total_cmp);
+    let mut sum = Neumaier::default();
+    for v in values {
+        sum.add(v);
+    }
+    sum.value()
+}
+
+pub(crate) fn centroid(points: &[Vec3]) -> Vec3 {
+    let n = points.len().max(1) as f64;
+    std::array::from_fn(|k| stable_sum(points.iter().map(|p| p[k])) / n)
+}
+
+/// Covariance matrix of the points around their centroid
+pub(crate) fn covariance(points: &[Vec3]) -> [[f64; 3]; 3] {
+    let c = centroid(points);
+    let n = points.len().max(1) as f64;
+    let deviations: Vec<Vec3> = points.iter().map(|p| sub(*p, c)).collect();
+    std::array::from_fn(|i| {
+        std::array::from_fn(|j| stable_sum(deviations.iter().map(|d| d[i] * d[j])) / n)
+    })
+}
+
+/// Eigen decomposition of a symmetric NxN matrix via cyclic Jacobi rotations.
+/// Returns eigenvalues in descending order, with the matching unit eigenvectors
+/// as the rows of the second element.
+#[allow(clippy::needless_range_loop)]
+pub(crate) fn symmetric_eigen<const N: usize>(m: [[f64; N]; N]) -> ([f64; N], [[f64; N]; N]) {
+    let mut a = m;
+    let mut v = [[0.0; N]; N];
+    for (i, row) in v.iter_mut().enumerate() {
+        row[i] = 1.0;
+    }
+
+    for _ in 0..64 {
+        let mut off = 0.0;
+        for p in 0..N {
+            for q in p + 1..N {
+                off += a[p][q].powi(2);
+            }
+        }
+        if off < 1e-30 {
+            break;
+        }
+        for p in 0..N {
+            for q in p + 1..N {
+                if a[p][q].abs() < 1e-300 {
+                    continue;
+                }
+                let theta = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let s = t * c;
+
+                // a <- J^T a J
+                for k in 0..N {
+                    let akp = a[k][p];
+                    let akq = a[k][q];
+                    a[k][p] = c * akp - s * akq;
+                    a[k][q] = s * akp + c * akq;
+                }
+                for k in 0..N {
+                    let apk = a[p][k];
+                    let aqk = a[q][k];
+                    a[p][k] = c * apk - s * aqk;
+                    a[q][k] = s * apk + c * aqk;
+                }
+                // Accumulate the rotation, eigenvectors end up as columns of v
+                for row in v.iter_mut() {
+                    let vp = row[p];
+                    let vq = row[q];
+                    row[p] = c * vp - s * vq;
+                    row[q] = s * vp + c * vq;
+                }
+            }
+        }
+    }
+
+    let mut order = [0usize; N];
+    for (i, o) in order.iter_mut().enumerate() {
+        *o = i;
+    }
+    order.sort_by(|&i, &j| a[j][j].total_cmp(&a[i][i]));
+    let values = order.map(|i| a[i][i]);
+    let vectors = order.map(|col| {
+        let mut e = [0.0; N];
+        for (k, row) in v.iter().enumerate() {
+            e[k] = row[col];
+        }
+        e
+    });
+    (values, vectors)
+}
+
+/// Why a point set does not span a volume
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Degeneracy {
+    TooFewPoints,
+    Collinear,
+    Planar,
+}
+
+/// Closed triangle mesh with outward facing winding
+pub(crate) struct Hull {
+    pub faces: Vec<[usize; 3]>,
+}
+
+impl Hull {
+    pub fn volume(&self, points: &[Vec3]) -> f64 {
+        // Sum of signed tetrahedra against the origin; the sign cancels out for
+        // a closed mesh, so no interior reference point is needed.
+        let v = stable_sum(
+            self.faces
+                .iter()
+                .map(|f| dot(points[f[0]], cross(points[f[1]], points[f[2]]))),
+        );
+        v.abs() / 6.0
+    }
+
+    pub fn area(&self, points: &[Vec3]) -> f64 {
+        stable_sum(self.faces.iter().map(|f| {
+            let ab = sub(points[f[1]], points[f[0]]);
+            let ac = sub(points[f[2]], points[f[0]]);
+            norm(cross(ab, ac)) / 2.0
+        }))
+    }
+}
+
+/// Incremental 3-D convex hull. Coplanar points are treated as inside, so a
+/// lattice does not blow up the face count with slivers.
+pub(crate) fn convex_hull(points: &[Vec3]) -> Result<Hull, Degeneracy> {
+    if points.len() < 4 {
+        return Err(Degeneracy::TooFewPoints);
+    }
+
+    let scale = points
+        .iter()
+        .flat_map(|p| p.iter())
+        .fold(0.0f64, |acc, v| acc.max(v.abs()))
+        .max(1.0);
+    let eps = 1e-9 * scale;
+
+    // Initial tetrahedron from extreme points
+    let p0 = (0..points.len())
+        .min_by(|&i, &j| points[i][0].total_cmp(&points[j][0]))
+        .unwrap();
+    let p1 = (0..points.len())
+        .max_by(|&i, &j| {
+            norm(sub(points[i], points[p0])).total_cmp(&norm(sub(points[j], points[p0])))
+        })
+        .unwrap();
+    let axis = sub(points[p1], points[p0]);
+    if norm(axis) < eps {
+        return Err(Degeneracy::Collinear);
+    }
+    let line_dist = |i: usize| norm(cross(axis, sub(points[i], points[p0]))) / norm(axis);
+    let p2 = (0..points.len())
+        .max_by(|&i, &j| line_dist(i).total_cmp(&line_dist(j)))
+        .unwrap();
+    if line_dist(p2) < eps {
+        return Err(Degeneracy::Collinear);
+    }
+    let plane_normal = cross(axis, sub(points[p2], points[p0]));
+    let plane_dist = |i: usize| dot(plane_normal, sub(points[i], points[p0])) / norm(plane_normal);
+    let p3 = (0..points.len())
+        .max_by(|&i, &j| plane_dist(i).abs().total_cmp(&plane_dist(j).abs()))
+        .unwrap();
+    if plane_dist(p3).abs() < eps {
+        return Err(Degeneracy::Planar);
+    }
+
+    let interior = centroid(&[points[p0], points[p1], points[p2], points[p3]]);
+    let orient = |f: [usize; 3]| -> [usize; 3] {
+        let n = cross(
+            sub(points[f[1]], points[f[0]]),
+            sub(points[f[2]], points[f[0]]),
+        );
+        if dot(n, sub(interior, points[f[0]])) > 0.0 {
+            [f[0], f[2], f[1]]
+        } else {
+            f
+        }
+    };
+    let mut faces: Vec<[usize; 3]> = vec![
+        orient([p0, p1, p2]),
+        orient([p0, p1, p3]),
+        orient([p0, p2, p3]),
+        orient([p1, p2, p3]),
+    ];
+
+    let seeds = [p0, p1, p2, p3];
+    for (i, p) in points.iter().enumerate() {
+        if seeds.contains(&i) {
+            continue;
+        }
+        let visible: Vec<bool> = faces
+            .iter()
+            .map(|f| {
+                let n = cross(
+                    sub(points[f[1]], points[f[0]]),
+                    sub(points[f[2]], points[f[0]]),
+                );
+                dot(n, sub(*p, points[f[0]])) > eps * norm(n)
+            })
+            .collect();
+        if !visible.iter().any(|&v| v) {
+            continue;
+        }
+
+        // Horizon: directed edges of visible faces whose reverse is not also
+        // part of a visible face.
+        let visible_edges: Vec<(usize, usize)> = faces
+            .iter()
+            .zip(&visible)
+            .filter(|(_, v)| **v)
+            .flat_map(|(f, _)| [(f[0], f[1]), (f[1], f[2]), (f[2], f[0])])
+            .collect();
+        let horizon: Vec<(usize, usize)> = visible_edges
+            .iter()
+            .filter(|(a, b)| !visible_edges.contains(&(*b, *a)))
+            .copied()
+            .collect();
+
+        let mut kept: Vec<[usize; 3]> = faces
+            .iter()
+            .zip(&visible)
+            .filter(|(_, v)| !**v)
+            .map(|(f, _)| *f)
+            .collect();
+        kept.extend(horizon.into_iter().map(|(a, b)| [a, b, i]));
+        faces = kept;
+    }
+
+    Ok(Hull { faces })
+}
@@ -0,0 +1,330 @@
+//! Morphology that changes during a run: compartments switched off and back
+//! on at scheduled times, for growth and degeneration.
+//!
+//! An inactive compartment is cut out of the cell: it has no membrane
+//! current, no coupling to its parent, children or spines, and ignores
+//! injected current and clamps. It keeps its index, so probes and stimuli
+//! on the rest of the cell stay where they were, and its voltage and gates
+//! stay frozen at what they were when it was switched off.
+//!
+//! Switching is a mask on the existing system, not a rebuild: the solver
+//! skips the rows of inactive compartments and their couplings are zeroed,
+//! so a change costs as much as the compartments it touches. A schedule
+//! makes the cell solve serially, see `Simulation::with_solver`.
+//!
+//! A compartment is only active when its parent is, so a deactivation takes
+//! everything distal to it along, or is refused, per `DistalPolicy`.
+
+use crate::compartments::Compartments;
+use crate::solver::{RESTING_POTENTIAL, SimError, Simulation, SolverOptions, settle};
+
+/// Compartments a scheduled change applies to
+#[derive(Debug, Clone, PartialEq)]
+pub enum Selector {
+    Compartment(usize),
+    /// The compartment and everything distal to it
+    Subtree(usize),
+    /// Every compartment of a section, by its NEURON name
+    Section(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Activate,
+    Deactivate,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScheduledChange {
+    /// In ms. Applies at the end of the first step ending at or after it,
+    /// or on registration for the current time.
+    pub time: f64,
+    pub change: Change,
+    pub selector: Selector,
+}
+
+/// What a deactivation does with active compartments distal to the ones it
+/// selects
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DistalPolicy {
+    /// Deactivates them too
+    #[default]
+    Include,
+    /// Refuses the schedule at registration
+    Error,
+}
+
+/// State a reactivated compartment starts from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Reactivation {
+    /// The voltage of its parent at the switch, the resting potential for
+    /// the soma, with gates at steady state for it. Reactivating is then no
+    /// jump for the parent.
+    #[default]
+    Parent,
+    /// The resting potential, with gates at steady state for it, as at the
+    /// start of a run
+    Rest,
+}
+
+/// Changes to which compartments take part, in any order; changes at the
+/// same time apply in the order given
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MorphologySchedule {
+    pub changes: Vec<ScheduledChange>,
+    pub distal: DistalPolicy,
+    pub reactivation: Reactivation,
+}
+
+impl MorphologySchedule {
+    pub fn new() -> MorphologySchedule {
+        MorphologySchedule::default()
+    }
+
+    pub fn deactivate(mut self, time: f64, selector: Selector) -> MorphologySchedule {
+        self.changes.push(ScheduledChange {
+            time,
+            change: Change::Deactivate,
+            selector,
+        });
+        self
+    }
+
+    pub fn activate(mut self, time: f64, selector: Selector) -> MorphologySchedule {
+        self.changes.push(ScheduledChange {
+            time,
+            change: Change::Activate,
+            selector,
+        });
+        self
+    }
+
+    pub fn with_distal(mut self, distal: DistalPolicy) -> MorphologySchedule {
+        self.distal = distal;
+        self
+    }
+
+    pub fn with_reactivation(mut self, reactivation: Reactivation) -> MorphologySchedule {
+        self.reactivation = reactivation;
+        self
+    }
+}
+
+/// Compartments that changed at one step of a run
+#[derive(Debug, Clone, PartialEq)]
+pub struct ActivationChange {
+    /// In ms
+    pub time: f64,
+    /// In index order
+    pub activated: Vec<usize>,
+    pub deactivated: Vec<usize>,
+}
+
+/// A registered schedule, resolved to steps and compartments
+#[derive(Debug, Clone)]
+pub(crate) struct ScheduleState {
+    /// Step, change and compartments in index order, sorted by step
+    events: Vec<(usize, Change, Vec<usize>)>,
+    next: usize,
+    reactivation: Reactivation,
+    /// Coupling of every compartment to restore on reactivation
+    axial: Vec<f64>,
+    history: Vec<ActivationChange>,
+}
+
+/// `idx` and everything distal to it, in index order
+fn subtree(parent: &[usize], idx: usize) -> Vec<usize> {
+    let mut inside = vec![false; parent.len()];
+    inside[idx] = true;
+    (idx..parent.len())
+        .filter(|&i| {
+            inside[i] = inside[i] || inside[parent[i]] && parent[i] != 0;
+            inside[i]
+        })
+        .collect()
+}
+
+impl Simulation {
+    /// Applies `schedule` as the run goes, compartments and sections
+    /// resolved against `compartments`, which this simulation was built
+    /// from. Errors on a compartment or section that does not exist, a change in the
+    /// past, a deactivation that leaves compartments distal to it active
+    /// under `DistalPolicy::Error`, and an activation under an inactive
+    /// parent.
+    pub fn with_schedule(
+        mut self,
+        compartments: &Compartments,
+        schedule: MorphologySchedule,
+    ) -> Result<Simulation, SimError> {
+        let n = self.v.len();
+        if compartments.components.len() != n {
+            return Err(SimError::InvalidModel {
+                reason: format!(
+                    "Schedule is for {} compartments, the simulation has {}",
+                    compartments.components.len(),
+                    n
+                ),
+            });
+        }
+        let mut events = Vec::with_capacity(schedule.changes.len());
+        for (k, change) in schedule.changes.iter().enumerate() {
+            let context = |e: String| SimError::InvalidModel {
+                reason: format!("Scheduled change {}: {}", k, e),
+            };
+            if !change.time.is_finite() {
+                return Err(context(format!("Time {} is not finite", change.time)));
+            }
+            let step = (change.time / self.dt() - 1e-9).ceil().max(0.0) as usize;
+            if step < self.steps {
+                return Err(context(format!(
+                    "Time {} ms is before the current time, {} ms",
+                    change.time,
+                    self.time()
+                )));
+            }
+            let selected = match &change.selector {
+                Selector::Compartment(idx) => {
+                    self.check(*idx).map_err(|e| context(e.to_string()))?;
+                    vec![*idx]
+                }
+                Selector::Subtree(idx) => {
+                    self.check(*idx).map_err(|e| context(e.to_string()))?;
+                    subtree(&self.parent, *idx)
+                }
+                Selector::Section(name) => compartments
+                    .section(name)
+                    .map_err(|e| context(e.to_string()))?
+                    .compartments
+                    .clone(),
+            };
+            events.push((step, k, change.change, selected));
+        }
+        events.sort_by_key(|&(step, k, _, _)| (step, k));
+
+        // Replay to check every state along the way keeps the tree whole
+        let mut active = self.active.clone();
+        let events = events
+            .into_iter()
+            .map(|(step, k, change, mut selected)| {
+                match change {
+                    Change::Deactivate => {
+                        let distal: Vec<usize> = selected
+                            .iter()
+                            .flat_map(|&i| subtree(&self.parent, i))
+                            .filter(|&i| active[i] && !selected.contains(&i))
+                            .collect();
+                        if let (Some(&i), DistalPolicy::Error) = (distal.first(), schedule.distal)
+                        {
+                            return Err(SimError::InvalidModel {
+                                reason: format!(
+                                    "Scheduled change {}: Deactivating leaves compartment {} distal to it active",
+                                    k, i
+                                ),
+                            });
+                        }
+                        selected.extend(distal);
+                        selected.sort_unstable();
+                        selected.dedup();
+                        selected.iter().for_each(|&i| active[i] = false);
+                    }
+                    Change::Activate => {
+                        selected.sort_unstable();
+                        selected.iter().for_each(|&i| active[i] = true);
+                        if let Some(&i) = selected
+                            .iter()
+                            .find(|&&i| self.parent[i] != 0 && !active[self.parent[i]])
+                        {
+                            return Err(SimError::InvalidModel {
+                                reason: format!(
+                                    "Scheduled change {}: Compartment {} would be active under inactive compartment {}",
+                                    k, i, self.parent[i]
+                                ),
+                            });
+                        }
+                    }
+                }
+                Ok((step, change, selected))
+            })
+            .collect::<Result<Vec<_>, SimError>>()?;
+
+        self = self.with_solver(SolverOptions::default());
+        self.schedule = Some(Box::new(ScheduleState {
+            events,
+            next: 0,
+            reactivation: schedule.reactivation,
+            axial: self.axial.clone(),
+            history: Vec::new(),
+        }));
+        self.apply_schedule();
+        Ok(self)
+    }
+
+    pub fn is_active(&self, idx: usize) -> bool {
+        self.active[idx]
+    }
+
+    /// Whether each compartment takes part, the dummy root included
+    pub fn active(&self) -> &[bool] {
+        &self.active
+    }
+
+    /// Every change applied so far, in order
+    pub fn activation_history(&self) -> &[ActivationChange] {
+        self.schedule.as_ref().map_or(&[], |s| &s.history)
+    }
+
+    /// Applies the scheduled changes due by the current time
+    pub(crate) fn apply_schedule(&mut self) {
+        let Some(mut schedule) = self.schedule.take() else {
+            return;
+        };
+        let mut applied = ActivationChange {
+            time: self.time(),
+            activated: Vec::new(),
+            deactivated: Vec::new(),
+        };
+        while let Some((step, change, selected)) = schedule.events.get(schedule.next) {
+            if *step > self.steps {
+                break;
+            }
+            for &i in selected {
+                if self.active[i] == (*change == Change::Activate) {
+                    continue;
+                }
+                match change {
+                    Change::Deactivate => {
+                        self.active[i] = false;
+                        self.axial[i] = 0.0;
+                        applied.deactivated.push(i);
+                    }
+                    Change::Activate => {
+                        // Parents come first, so the parent's voltage is
+                        // already what the rule wants
+                        let v = match (schedule.reactivation, self.parent[i]) {
+                            (Reactivation::Parent, p) if p != 0 => self.v[p],
+                            _ => RESTING_POTENTIAL,
+                        };
+                        self.active[i] = true;
+                        self.axial[i] = schedule.axial[i];
+                        self.v[i] = v;
+                        settle(&mut self.membranes[i], v, &mut self.rng);
+                        for spine in self.spines.iter_mut().filter(|s| s.parent == i) {
+                            spine.v = [v; 2];
+                            for m in &mut spine.membranes {
+                                settle(m, v, &mut self.rng);
+                            }
+                        }
+                        applied.activated.push(i);
+                    }
+                }
+            }
+            schedule.next += 1;
+        }
+        if !applied.activated.is_empty() || !applied.deactivated.is_empty() {
+            applied.activated.sort_unstable();
+            applied.deactivated.sort_unstable();
+            schedule.history.push(applied);
+        }
+        self.schedule = Some(schedule);
+    }
+}
@@ -0,0 +1,439 @@
+//! Undo and redo for interactive editing of a `Skeleton`.
+//!
+//! `EditHistory` owns the skeleton and applies every `Edit` itself. Each
+//! entry keeps only what its edit changed, the node slots and map entries
+//! before and after, so a long session on a big cell costs about as much
+//! as the edits rather than a copy of the cell per step. At most
+//! `max_entries` are kept; the oldest go first, with a warning.
+//!
+//! Checkpoints name a point in the history. `restore` undoes or redoes back
+//! to it, which works as long as that point is still on the history: not
+//! dropped for room and not on a branch discarded by editing after an undo.
+//!
+//! With a `RunLog` every edit, undo and redo is recorded, so the log says
+//! which edits remain applied.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::Arc;
+
+use crate::edit::EditableSkeleton;
+use crate::run_log::{LogValue, RunLog};
+use crate::swc_reader::{Node, Skeleton};
+
+/// Entries kept unless `with_max_entries` says otherwise
+pub const DEFAULT_MAX_ENTRIES: usize = 100;
+
+/// A change to a skeleton, see the `EditableSkeleton` method of the same
+/// name
+#[derive(Debug, Clone, PartialEq)]
+pub enum Edit {
+    InsertNodeOnEdge {
+        parent_id: u64,
+        child_id: u64,
+        position_fraction: f64,
+        radius: f64,
+    },
+    ReattachSubtree {
+        subtree_root_id: u64,
+        new_parent_id: u64,
+    },
+    SplitBranchAt {
+        branch_end_id: u64,
+        arc_length: f64,
+    },
+    /// `Skeleton::prune_subtree`
+    Prune {
+        node_id: u64,
+    },
+    /// `Skeleton::reroot_at`
+    Reroot {
+        node_id: u64,
+    },
+    /// `Skeleton::smooth_coordinates`
+    Smooth {
+        iterations: usize,
+    },
+    /// `Skeleton::repair_zero_radii`
+    RepairZeroRadii,
+}
+
+impl Edit {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Edit::InsertNodeOnEdge { .. } => "insert_node_on_edge",
+            Edit::ReattachSubtree { .. } => "reattach_subtree",
+            Edit::SplitBranchAt { .. } => "split_branch_at",
+            Edit::Prune { .. } => "prune",
+            Edit::Reroot { .. } => "reroot",
+            Edit::Smooth { .. } => "smooth",
+            Edit::RepairZeroRadii => "repair_zero_radii",
+        }
+    }
+
+    pub fn args(&self) -> Vec<(&'static str, LogValue)> {
+        match *self {
+            Edit::InsertNodeOnEdge {
+                parent_id,
+                child_id,
+                position_fraction,
+                radius,
+            } => vec![
+                ("parent_id", parent_id.into()),
+                ("child_id", child_id.into()),
+                ("position_fraction", position_fraction.into()),
+                ("radius", radius.into()),
+            ],
+            Edit::ReattachSubtree {
+                subtree_root_id,
+                new_parent_id,
+            } => vec![
+                ("subtree_root_id", subtree_root_id.into()),
+                ("new_parent_id", new_parent_id.into()),
+            ],
+            Edit::SplitBranchAt {
+                branch_end_id,
+                arc_length,
+            } => vec![
+                ("branch_end_id", branch_end_id.into()),
+                ("arc_length", arc_length.into()),
+            ],
+            Edit::Prune { node_id } | Edit::Reroot { node_id } => {
+                vec![("node_id", node_id.into())]
+            }
+            Edit::Smooth { iterations } => vec![("iterations", iterations.into())],
+            Edit::RepairZeroRadii => Vec::new(),
+        }
+    }
+
+    fn apply(&self, skeleton: &mut EditableSkeleton) -> Result<(), String> {
+        match *self {
+            Edit::InsertNodeOnEdge {
+                parent_id,
+                child_id,
+                position_fraction,
+                radius,
+            } => skeleton
+                .insert_node_on_edge(parent_id, child_id, position_fraction, radius)
+                .map(drop),
+            Edit::ReattachSubtree {
+                subtree_root_id,
+                new_parent_id,
+            } => skeleton.reattach_subtree(subtree_root_id, new_parent_id),
+            Edit::SplitBranchAt {
+                branch_end_id,
+                arc_length,
+            } => skeleton
+                .split_branch_at(branch_end_id, arc_length)
+                .map(drop),
+            Edit::Prune { node_id } => skeleton.prune_subtree(node_id).map(drop),
+            Edit::Reroot { node_id } => skeleton.reroot_at(node_id),
+            Edit::Smooth { iterations } => {
+                skeleton.smooth_coordinates(iterations);
+                Ok(())
+            }
+            Edit::RepairZeroRadii => {
+                skeleton.repair_zero_radii();
+                Ok(())
+            }
+        }
+    }
+}
+
+/// As a call, e.g. `prune(node_id=4)`
+impl fmt::Display for Edit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let args: Vec<String> = self
+            .args()
+            .iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect();
+        write!(f, "{}({})", self.name(), args.join(", "))
+    }
+}
+
+/// A map entry that differs, with its value before and after
+type Change<V> = (u64, Option<V>, Option<V>);
+
+/// Keys of `before` and `after` whose values differ, with both values
+fn diff_map<V: Clone + PartialEq>(
+    before: &HashMap<u64, V>,
+    after: &HashMap<u64, V>,
+) -> Vec<Change<V>> {
+    let mut changes: Vec<_> = before
+        .iter()
+        .filter(|(k, v)| after.get(k) != Some(v))
+        .map(|(k, v)| (*k, Some(v.clone()), after.get(k).cloned()))
+        .chain(
+            after
+                .iter()
+                .filter(|(k, _)| !before.contains_key(k))
+                .map(|(k, v)| (*k, None, Some(v.clone()))),
+        )
+        .collect();
+    changes.sort_by_key(|c| c.0);
+    changes
+}
+
+/// `diff_map`, skipping the comparison for a column the edit left shared
+fn diff_shared<V: Clone + PartialEq>(
+    before: &Arc<HashMap<u64, V>>,
+    after: &Arc<HashMap<u64, V>>,
+) -> Vec<Change<V>> {
+    if Arc::ptr_eq(before, after) {
+        Vec::new()
+    } else {
+        diff_map(before, after)
+    }
+}
+
+/// Sets every key `diff_map` listed to its value before or after
+fn set_all<V: Clone>(map: &mut HashMap<u64, V>, changes: &[Change<V>], forward: bool) {
+    for (key, before, after) in changes {
+        match if forward { after } else { before } {
+            Some(v) => map.insert(*key, v.clone()),
+            None => map.remove(key),
+        };
+    }
+}
+
+fn same_node(a: &Node, b: &Node) -> bool {
+    a.node_id == b.node_id
+        && a.parent_id == b.parent_id
+        && a.structured_identifier == b.structured_identifier
+        && a.flags == b.flags
+        && [a.x_pos, a.y_pos, a.z_pos, a.radius]
+            .iter()
+            .zip([b.x_pos, b.y_pos, b.z_pos, b.radius])
+            .all(|(x, y)| x.to_bits() == y.to_bits())
+}
+
+/// What one edit changed
+#[derive(Debug, Clone)]
+struct Delta {
+    /// Node count before and after
+    len: (usize, usize),
+    /// Node slots that differ, with their contents before and after, None
+    /// past the end
+    nodes: Vec<(usize, Option<Node>, Option<Node>)>,
+    parent_child: Vec<Change<Vec<u64>>>,
+    child_parent: Vec<Change<Vec<u64>>>,
+    extras: Vec<Change<Vec<f64>>>,
+}
+
+impl Delta {
+    fn between(before: &Skeleton, after: &Skeleton) -> Delta {
+        let len = (before.nodes.len(), after.nodes.len());
+        let nodes = (0..len.0.max(len.1))
+            .map(|i| (i, before.nodes.get(i).copied(), after.nodes.get(i).copied()))
+            .filter(|(_, b, a)| match (b, a) {
+                (Some(b), Some(a)) => !same_node(b, a),
+                _ => true,
+            })
+            .collect();
+        Delta {
+            len,
+            nodes,
+            parent_child: diff_shared(&before.parent_child_map, &after.parent_child_map),
+            child_parent: diff_shared(&before.child_parent_map, &after.child_parent_map),
+            extras: diff_shared(&before.extras, &after.extras),
+        }
+    }
+
+    /// Puts `skeleton` into the state before (`forward` false) or after the
+    /// edit
+    fn apply(&self, skeleton: &mut Skeleton, forward: bool) {
+        let nodes = Arc::make_mut(&mut skeleton.nodes);
+        nodes.truncate(if forward { self.len.1 } else { self.len.0 });
+        // Slots come in order, so the ones past the end are pushed in turn
+        for (i, before, after) in &self.nodes {
+            if let Some(node) = if forward { after } else { before } {
+                match nodes.get_mut(*i) {
+                    Some(slot) => *slot = *node,
+                    None => nodes.push(*node),
+                }
+            }
+        }
+        // Columns the edit left alone stay shared
+        if !self.parent_child.is_empty() {
+            set_all(
+                Arc::make_mut(&mut skeleton.parent_child_map),
+                &self.parent_child,
+                forward,
+            );
+        }
+        if !self.child_parent.is_empty() {
+            set_all(
+                Arc::make_mut(&mut skeleton.child_parent_map),
+                &self.child_parent,
+                forward,
+            );
+        }
+        if !self.extras.is_empty() {
+            set_all(Arc::make_mut(&mut skeleton.extras), &self.extras, forward);
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    /// Position in the history, counting from 1 for the first edit
+    seq: u64,
+    edit: Edit,
+    delta: Delta,
+}
+
+/// A skeleton with its undoable edits, see the module docs
+#[derive(Debug, Clone)]
+pub struct EditHistory {
+    skeleton: Skeleton,
+    done: VecDeque<Entry>,
+    undone: Vec<Entry>,
+    max_entries: usize,
+    next_seq: u64,
+    /// Position of the oldest state still reachable: 0, or the last entry
+    /// dropped for room
+    floor: u64,
+    checkpoints: HashMap<String, u64>,
+    run_log: Option<RunLog>,
+}
+
+impl EditHistory {
+    pub fn new(skeleton: Skeleton) -> EditHistory {
+        EditHistory {
+            skeleton,
+            done: VecDeque::new(),
+            undone: Vec::new(),
+            max_entries: DEFAULT_MAX_ENTRIES,
+            next_seq: 1,
+            floor: 0,
+            checkpoints: HashMap::new(),
+            run_log: None,
+        }
+    }
+
+    /// Keeps at most `max_entries` edits, at least one
+    pub fn with_max_entries(mut self, max_entries: usize) -> EditHistory {
+        self.max_entries = max_entries.max(1);
+        self
+    }
+
+    /// Records every edit, undo and redo in `log`
+    pub fn with_run_log(mut self, log: RunLog) -> EditHistory {
+        self.run_log = Some(log);
+        self
+    }
+
+    pub fn skeleton(&self) -> &Skeleton {
+        &self.skeleton
+    }
+
+    /// The skeleton as edited, dropping the history
+    pub fn into_skeleton(self) -> Skeleton {
+        self.skeleton
+    }
+
+    fn log(&self, op: &str, edit: &Edit) {
+        if let Some(log) = &self.run_log {
+            let mut args = vec![("edit", LogValue::from(edit.name()))];
+            args.extend(edit.args());
+            log.record(op, &args);
+        }
+    }
+
+    /// Applies `edit`, discarding whatever was undone before. A failed edit
+    /// leaves everything as it was.
+    pub fn apply(&mut self, edit: Edit) -> Result<(), String> {
+        let mut editing = self.skeleton.edit();
+        edit.apply(&mut editing)?;
+        let after = editing.commit()?;
+        let delta = Delta::between(&self.skeleton, &after);
+        self.skeleton = after;
+        self.log("edit", &edit);
+        self.undone.clear();
+        self.done.push_back(Entry {
+            seq: self.next_seq,
+            edit,
+            delta,
+        });
+        self.next_seq += 1;
+        while self.done.len() > self.max_entries {
+            if let Some(dropped) = self.done.pop_front() {
+                log::warn!(
+                    "Edit history is full at {} entries; dropping the oldest, {}",
+                    self.max_entries,
+                    dropped.edit
+                );
+                self.floor = dropped.seq;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reverts the last edit still applied, returning it, None if there is
+    /// none
+    pub fn undo(&mut self) -> Option<&Edit> {
+        let entry = self.done.pop_back()?;
+        entry.delta.apply(&mut self.skeleton, false);
+        self.log("undo", &entry.edit);
+        self.undone.push(entry);
+        self.undone.last().map(|e| &e.edit)
+    }
+
+    /// Applies the last undone edit again, returning it, None if there is
+    /// none
+    pub fn redo(&mut self) -> Option<&Edit> {
+        let entry = self.undone.pop()?;
+        entry.delta.apply(&mut self.skeleton, true);
+        self.log("redo", &entry.edit);
+        self.done.push_back(entry);
+        self.done.back().map(|e| &e.edit)
+    }
+
+    /// Edits still applied, oldest first
+    pub fn history(&self) -> Vec<&Edit> {
+        self.done.iter().map(|e| &e.edit).collect()
+    }
+
+    /// Descriptions of the edits still applied, as in `Edit`'s `Display`
+    pub fn descriptions(&self) -> Vec<String> {
+        self.done.iter().map(|e| e.edit.to_string()).collect()
+    }
+
+    /// Edits `redo` would apply, next first
+    pub fn redoable(&self) -> Vec<&Edit> {
+        self.undone.iter().rev().map(|e| &e.edit).collect()
+    }
+
+    fn position(&self) -> u64 {
+        self.done.back().map_or(self.floor, |e| e.seq)
+    }
+
+    /// Names the current state, replacing any checkpoint of that name
+    pub fn checkpoint(&mut self, name: &str) {
+        self.checkpoints.insert(name.to_owned(), self.position());
+    }
+
+    /// Undoes or redoes back to checkpoint `name`
+    pub fn restore(&mut self, name: &str) -> Result<(), String> {
+        let target = *self
+            .checkpoints
+            .get(name)
+            .ok_or_else(|| format!("No checkpoint named '{}'", name))?;
+        let behind = target == self.floor || self.done.iter().any(|e| e.seq == target);
+        let ahead = self.undone.iter().any(|e| e.seq == target);
+        if !behind && !ahead {
+            return Err(format!(
+                "Checkpoint '{}' is no longer in the history: its edits were dropped or undone and replaced",
+                name
+            ));
+        }
+        while self.position() != target {
+            let moved = if behind { self.undo() } else { self.redo() };
+            if moved.is_none() {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
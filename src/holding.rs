@@ -0,0 +1,167 @@
+//! Holding currents: the constant current that keeps a site at a target
+//! potential, for models whose mechanisms together rest somewhere else.
+//!
+//! `Compartments::compute_holding_current` clamps the site at the target
+//! with every compartment starting there, and steps the cell with long
+//! steps until the clamp current stops changing: the rest of the cell
+//! settles around the site, so the axial part of the current changes with
+//! it. The clamp then supplies the net membrane current of the site plus
+//! what flows from it into the rest of the cell, which is the current that
+//! holds it. `hold_at` attaches that current, and `simulate` injects it on
+//! every step, starting from the state it holds the cell in.
+
+use crate::compartments::Compartments;
+use crate::index_map::AttachmentKind;
+use crate::reduce::SiteLocation;
+use crate::solver::Simulation;
+
+/// Step used to settle the cell, in ms. Backward Euler settles where the
+/// continuous system does, so only the number of steps depends on it.
+const SETTLE_DT: f64 = 1.0;
+/// Change of the clamp current and of every voltage over one step below
+/// which the cell counts as settled, in nA and mV
+const TOLERANCE: f64 = 1e-9;
+/// Most settling steps
+const MAX_STEPS: usize = 10_000;
+
+/// Outcome of `Compartments::compute_holding_current`
+#[derive(Debug, Clone, PartialEq)]
+pub struct HoldingCurrent {
+    /// Current that holds the site at the target, in nA, depolarizing when
+    /// positive
+    pub current: f64,
+    /// Settling steps taken
+    pub steps: usize,
+    /// Largest change of the clamp current or a voltage over the last step
+    pub change: f64,
+    /// Whether `change` fell below the tolerance within the step cap
+    pub converged: bool,
+}
+
+impl Compartments {
+    /// Current that holds `at` at `target_v` mV once the rest of the cell
+    /// has settled, with the holding currents already attached in place.
+    /// See the module docs; check `converged` before relying on it.
+    pub fn compute_holding_current(
+        &self,
+        target_v: f64,
+        at: SiteLocation,
+    ) -> Result<HoldingCurrent, String> {
+        if !target_v.is_finite() {
+            return Err(format!("Target potential must be finite, got {}", target_v));
+        }
+        if at.idx == 0 || at.idx >= self.components.len() {
+            return Err(format!("No compartment {} to hold", at.idx));
+        }
+        let mut simulation = Simulation::new(self, SETTLE_DT).map_err(|e| e.to_string())?;
+        for idx in 1..self.components.len() {
+            simulation
+                .set_voltage(idx, target_v)
+                .map_err(|e| e.to_string())?;
+        }
+        for k in 0..self.spines.len() {
+            simulation
+                .set_spine_voltage(k, target_v)
+                .map_err(|e| e.to_string())?;
+        }
+        simulation
+            .clamp(at.idx, Some(target_v))
+            .map_err(|e| e.to_string())?;
+        let holding = self.holding_currents();
+        let mut current = f64::NAN;
+        let mut change = f64::INFINITY;
+        let mut steps = 0;
+        while steps < MAX_STEPS && change >= TOLERANCE {
+            let (last, v) = (current, simulation.voltages().to_vec());
+            step_with(&mut simulation, &holding)?;
+            steps += 1;
+            current = simulation.clamp_current(at.idx).unwrap_or(0.0);
+            // No change to compare on the first step
+            let current_change = if steps == 1 {
+                f64::INFINITY
+            } else {
+                (current - last).abs()
+            };
+            change = largest_change(&v, simulation.voltages()).max(current_change);
+        }
+        Ok(HoldingCurrent {
+            current,
+            steps,
+            change,
+            converged: change < TOLERANCE,
+        })
+    }
+
+    /// Computes the holding current for `target_v` at `site` and attaches
+    /// it, on top of any attached before, so every later `simulate` holds
+    /// the site there. Fails, attaching nothing, if it did not converge.
+    pub fn hold_at(&mut self, target_v: f64, site: SiteLocation) -> Result<HoldingCurrent, String> {
+        let holding = self.compute_holding_current(target_v, site)?;
+        if !holding.converged {
+            return Err(format!(
+                "Holding current for compartment {} at {} mV did not settle in {} steps, last change {}",
+                site.idx, target_v, holding.steps, holding.change
+            ));
+        }
+        let count = self
+            .attachments
+            .iter()
+            .filter(|a| a.kind == AttachmentKind::Holding)
+            .count();
+        let name = format!("holding[{}]", count);
+        self.attach(AttachmentKind::Holding, &name, site.idx, site.x, site.x)?;
+        self.attachments.last_mut().unwrap().waveform = Some(vec![holding.current]);
+        Ok(holding)
+    }
+
+    /// Attached holding currents as `(idx, current)`, in nA
+    pub fn holding_currents(&self) -> Vec<(usize, f64)> {
+        self.attachments
+            .iter()
+            .filter(|a| a.kind == AttachmentKind::Holding)
+            .filter_map(|a| Some((a.idx, a.waveform.as_ref()?.first()? * a.weight)))
+            .collect()
+    }
+
+    /// Puts `simulation` at the state the attached holding currents settle
+    /// the cell in, if there are any
+    pub(crate) fn settle_holding(&self, simulation: &mut Simulation) -> Result<(), String> {
+        let holding = self.holding_currents();
+        if holding.is_empty() {
+            return Ok(());
+        }
+        let mut settling = Simulation::new(self, SETTLE_DT).map_err(|e| e.to_string())?;
+        let mut change = f64::INFINITY;
+        let mut steps = 0;
+        while steps < MAX_STEPS && change >= TOLERANCE {
+            let v = settling.voltages().to_vec();
+            step_with(&mut settling, &holding)?;
+            steps += 1;
+            change = largest_change(&v, settling.voltages());
+        }
+        for (idx, &v) in settling.voltages().iter().enumerate().skip(1) {
+            simulation.set_voltage(idx, v).map_err(|e| e.to_string())?;
+        }
+        for (k, v) in settling.head_voltages().into_iter().enumerate() {
+            simulation
+                .set_spine_voltage(k, v)
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+fn step_with(simulation: &mut Simulation, holding: &[(usize, f64)]) -> Result<(), String> {
+    for &(idx, current) in holding {
+        simulation.inject(idx, current).map_err(|e| e.to_string())?;
+    }
+    simulation.step().map_err(|e| e.to_string())
+}
+
+fn largest_change(before: &[f64], after: &[f64]) -> f64 {
+    before
+        .iter()
+        .zip(after)
+        .map(|(a, b)| (a - b).abs())
+        .fold(0.0, f64::max)
+}
@@ -0,0 +1,371 @@
+//! Keeping references to compartments valid when the compartments change.
+//!
+//! Every operation that renumbers, merges or splits compartments records an
+//! `IndexMap` from the indices before to the indices after. A map is a list
+//! of links, each tying a stretch of an old compartment to a stretch of a
+//! new one, both as fractions of the compartment's length from its proximal
+//! end. Old compartments without a link are gone.
+//!
+//! Probes, stimuli, tags and synapses attached with `Compartments::attach`
+//! move with every operation, and so do spines. An operation that would leave one of them
+//! without a compartment fails first, naming it.
+
+use crate::ais::AIS_TAG;
+use crate::compartments::Compartments;
+use crate::spines::{Spine, translated_spines};
+
+/// The stretch `[old_from, old_to]` of compartment `old` became the stretch
+/// `[new_from, new_to]` of compartment `new`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Link {
+    pub old: usize,
+    pub old_from: f64,
+    pub old_to: f64,
+    pub new: usize,
+    pub new_from: f64,
+    pub new_to: f64,
+}
+
+impl Link {
+    /// Carries position `x` along the old compartment over to the new one
+    fn carry(&self, x: f64) -> f64 {
+        let width = self.old_to - self.old_from;
+        if width > 0.0 {
+            self.new_from + (x - self.old_from) / width * (self.new_to - self.new_from)
+        } else {
+            (self.new_from + self.new_to) / 2.0
+        }
+    }
+
+    fn carry_back(&self, x: f64) -> f64 {
+        let width = self.new_to - self.new_from;
+        if width > 0.0 {
+            self.old_from + (x - self.new_from) / width * (self.old_to - self.old_from)
+        } else {
+            (self.old_from + self.old_to) / 2.0
+        }
+    }
+}
+
+/// Which of the links covering a compartment holds position `x`: the first
+/// whose stretch contains it, a boundary going to the distal side
+fn holding<'a>(
+    links: &[&'a Link],
+    x: f64,
+    range: impl Fn(&Link) -> (f64, f64),
+) -> Option<&'a Link> {
+    links
+        .iter()
+        .find(|l| {
+            let (from, to) = range(l);
+            from <= x && (x < to || (to == 1.0 && x == 1.0) || from == to)
+        })
+        .or_else(|| links.last())
+        .copied()
+}
+
+/// Where the compartments of one model went in another. The dummy root
+/// always maps to itself.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexMap {
+    links: Vec<Link>,
+    old_len: usize,
+    new_len: usize,
+}
+
+impl IndexMap {
+    /// Every compartment stays where it is
+    pub fn identity(len: usize) -> IndexMap {
+        IndexMap::from_links(
+            (0..len)
+                .map(|i| Link {
+                    old: i,
+                    old_from: 0.0,
+                    old_to: 1.0,
+                    new: i,
+                    new_from: 0.0,
+                    new_to: 1.0,
+                })
+                .collect(),
+            len,
+            len,
+        )
+    }
+
+    pub(crate) fn from_links(mut links: Vec<Link>, old_len: usize, new_len: usize) -> IndexMap {
+        links.sort_by(|a, b| {
+            a.old
+                .cmp(&b.old)
+                .then(a.old_from.total_cmp(&b.old_from))
+                .then(a.new.cmp(&b.new))
+        });
+        IndexMap {
+            links,
+            old_len,
+            new_len,
+        }
+    }
+
+    pub fn links(&self) -> &[Link] {
+        &self.links
+    }
+
+    /// Number of compartments, the dummy root included, before and after
+    pub fn old_len(&self) -> usize {
+        self.old_len
+    }
+
+    pub fn new_len(&self) -> usize {
+        self.new_len
+    }
+
+    /// Where old compartment `old` went, proximal stretch first; None if it
+    /// was removed
+    pub fn forward(&self, old: usize) -> Option<Vec<Link>> {
+        let start = self.links.partition_point(|l| l.old < old);
+        let links: Vec<Link> = self.links[start..]
+            .iter()
+            .take_while(|l| l.old == old)
+            .copied()
+            .collect();
+        (!links.is_empty()).then_some(links)
+    }
+
+    /// What new compartment `new` was made from
+    pub fn backward(&self, new: usize) -> Vec<Link> {
+        let mut links: Vec<Link> = self
+            .links
+            .iter()
+            .filter(|l| l.new == new)
+            .copied()
+            .collect();
+        links.sort_by(|a, b| a.new_from.total_cmp(&b.new_from));
+        links
+    }
+
+    /// The new compartment and position holding position `x` along old
+    /// compartment `old`
+    pub fn forward_position(&self, old: usize, x: f64) -> Option<(usize, f64)> {
+        let links = self.forward(old)?;
+        let links: Vec<&Link> = links.iter().collect();
+        let link = holding(&links, x, |l| (l.old_from, l.old_to))?;
+        Some((link.new, link.carry(x)))
+    }
+
+    /// The old compartment and position that position `x` along new
+    /// compartment `new` came from
+    pub fn backward_position(&self, new: usize, x: f64) -> Option<(usize, f64)> {
+        let links = self.backward(new);
+        let links: Vec<&Link> = links.iter().collect();
+        let link = holding(&links, x, |l| (l.new_from, l.new_to))?;
+        Some((link.old, link.carry_back(x)))
+    }
+
+    /// This map followed by `next`, which must start where this one ends
+    pub fn then(&self, next: &IndexMap) -> Result<IndexMap, String> {
+        if self.new_len != next.old_len {
+            return Err(format!(
+                "Cannot chain a map to {} compartments with one from {}",
+                self.new_len, next.old_len
+            ));
+        }
+        let mut by_old: Vec<Vec<&Link>> = vec![Vec::new(); next.old_len];
+        for l in &next.links {
+            by_old[l.old].push(l);
+        }
+        let mut links = Vec::new();
+        for a in &self.links {
+            for b in &by_old[a.new] {
+                let (lo, hi) = (a.new_from.max(b.old_from), a.new_to.min(b.old_to));
+                let point = a.new_from == a.new_to || b.old_from == b.old_to;
+                if hi < lo || (hi == lo && !point) {
+                    continue;
+                }
+                links.push(Link {
+                    old: a.old,
+                    old_from: a.carry_back(lo),
+                    old_to: a.carry_back(hi),
+                    new: b.new,
+                    new_from: b.carry(lo),
+                    new_to: b.carry(hi),
+                });
+            }
+        }
+        Ok(IndexMap::from_links(links, self.old_len, next.new_len))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttachmentKind {
+    /// Records membrane state
+    Probe,
+    /// Injects current
+    Stimulus,
+    /// Marks a place, e.g. for later lookup
+    Tag,
+    /// Synaptic input
+    Synapse,
+    /// A constant current, see `Compartments::hold_at`
+    Holding,
+}
+
+/// Something tied to a place on the cell: a point when `from == to`,
+/// otherwise a stretch of the compartment that it acts over evenly
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attachment {
+    pub name: String,
+    pub kind: AttachmentKind,
+    pub idx: usize,
+    /// Where it starts and ends along the compartment, 0 at its proximal end
+    pub from: f64,
+    pub to: f64,
+    /// Share of the attachment as first made, 1 unless its stretch was split
+    /// over several compartments
+    pub weight: f64,
+    /// Current injected over each step, in nA, for stimuli attached with
+    /// `Compartments::attach_stimuli`; the one value of a holding current
+    pub waveform: Option<Vec<f64>>,
+}
+
+impl Attachment {
+    pub fn midpoint(&self) -> f64 {
+        (self.from + self.to) / 2.0
+    }
+}
+
+impl Compartments {
+    /// Ties `name` to the stretch `[from, to]` of compartment `idx`, so it
+    /// follows the compartments through later operations. A stretch split
+    /// over several compartments becomes one piece on each, weighted by its
+    /// share of the length. The tag `ais` is reserved for `tag_ais`.
+    pub fn attach(
+        &mut self,
+        kind: AttachmentKind,
+        name: &str,
+        idx: usize,
+        from: f64,
+        to: f64,
+    ) -> Result<(), String> {
+        if idx == 0 || idx >= self.components.len() {
+            return Err(format!("No compartment {} to attach '{}' to", idx, name));
+        }
+        if !(0.0 <= from && from <= to && to <= 1.0) {
+            return Err(format!(
+                "Stretch must lie within [0, 1], got [{}, {}]",
+                from, to
+            ));
+        }
+        if kind == AttachmentKind::Tag && name == AIS_TAG {
+            return Err(format!("The tag '{}' is reserved for tag_ais", AIS_TAG));
+        }
+        self.attachments.push(Attachment {
+            name: name.to_owned(),
+            kind,
+            idx,
+            from,
+            to,
+            weight: 1.0,
+            waveform: None,
+        });
+        Ok(())
+    }
+
+    pub fn attachments(&self) -> &[Attachment] {
+        &self.attachments
+    }
+
+    /// Compartments carrying the tag `tag`, in index order
+    pub fn tagged(&self, tag: &str) -> Vec<usize> {
+        let mut idxs: Vec<usize> = self
+            .attachments
+            .iter()
+            .filter(|a| a.kind == AttachmentKind::Tag && a.name == tag)
+            .map(|a| a.idx)
+            .collect();
+        idxs.sort_unstable();
+        idxs.dedup();
+        idxs
+    }
+
+    /// Map from the compartments as first built to the current ones
+    pub fn index_map(&self) -> &IndexMap {
+        &self.index_map
+    }
+
+    /// Map of the most recent operation that changed the compartments
+    pub fn last_index_map(&self) -> Option<&IndexMap> {
+        self.last_index_map.as_ref()
+    }
+
+    /// Moves every attachment and spine through `map`. Fails, changing
+    /// nothing, if any of them sits on a compartment the map removed.
+    pub fn translate_attachments(&mut self, map: &IndexMap) -> Result<(), String> {
+        let attachments = translated(&self.attachments, map)?;
+        self.spines = translated_spines(&self.spines, map)?;
+        self.attachments = attachments;
+        Ok(())
+    }
+
+    /// Records that the compartments just changed as `map` describes, with
+    /// attachments and spines already checked by `translated` and
+    /// `translated_spines`
+    pub(crate) fn record_transform(
+        &mut self,
+        map: IndexMap,
+        attachments: Vec<Attachment>,
+        spines: Vec<Spine>,
+    ) {
+        self.attachments = attachments;
+        self.spines = spines;
+        self.index_map = self
+            .index_map
+            .then(&map)
+            .expect("each transform starts from the current compartments");
+        self.last_index_map = Some(map);
+    }
+}
+
+/// `attachments` moved through `map`
+pub(crate) fn translated(
+    attachments: &[Attachment],
+    map: &IndexMap,
+) -> Result<Vec<Attachment>, String> {
+    let mut out = Vec::new();
+    for a in attachments {
+        let gone = || {
+            format!(
+                "{:?} '{}' is on compartment {}, which is removed",
+                a.kind, a.name, a.idx
+            )
+        };
+        let links = map.forward(a.idx).ok_or_else(gone)?;
+        if a.from == a.to {
+            let (idx, x) = map.forward_position(a.idx, a.from).ok_or_else(gone)?;
+            out.push(Attachment {
+                idx,
+                from: x,
+                to: x,
+                ..a.clone()
+            });
+            continue;
+        }
+        let (width, before) = (a.to - a.from, out.len());
+        for l in &links {
+            let (lo, hi) = (a.from.max(l.old_from), a.to.min(l.old_to));
+            if hi <= lo {
+                continue;
+            }
+            out.push(Attachment {
+                idx: l.new,
+                from: l.carry(lo),
+                to: l.carry(hi),
+                weight: a.weight * (hi - lo) / width,
+                ..a.clone()
+            });
+        }
+        if out.len() == before {
+            return Err(gone());
+        }
+    }
+    Ok(out)
+}
@@ -1,13 +1,1922 @@
-use pyo3::prelude::*;
-mod channels;
-mod compartments;
-mod swc_reader;
+pub mod accumulation;
+pub mod ais;
+pub mod analysis;
+pub mod augment;
+pub mod biophysics;
+#[cfg(feature = "parquet")]
+pub mod bulk;
+pub mod bundle;
+pub mod cell_id;
+pub mod channels;
+mod coarsen;
+pub mod codes;
+pub mod compartments;
+pub mod conventions;
+pub mod deprecation;
+pub mod describe;
+pub mod discretize;
+mod edit;
+pub mod energy;
+pub mod error;
+pub mod experiment;
+pub mod export;
+pub mod extracellular;
+pub mod features;
+pub mod filter;
+mod geometry;
+pub mod growth;
+pub mod history;
+pub mod holding;
+pub mod index_map;
+pub mod manifest;
+pub mod markov;
+pub mod mesh;
+pub mod metadata;
+pub mod morphometry;
+pub mod network;
+pub mod objectives;
+pub mod output;
+pub mod parameters;
+pub mod placement;
+pub mod plasticity;
+pub mod preview;
+pub mod protocols;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod qc;
+pub mod quick;
+pub mod recording;
+pub mod reduce;
+pub mod refine;
+pub mod registration;
+pub mod registry;
+pub mod render;
+pub mod reversal;
+pub mod run_log;
+pub mod sections;
+pub mod session;
+pub mod simplify;
+pub mod snapshot;
+pub mod solver;
+pub mod soma;
+pub mod spikes;
+pub mod spines;
+pub mod standardize;
+pub mod state;
+pub mod stimulus;
+pub mod stochastic;
+pub mod store;
+pub mod subtree;
+pub mod swc_reader;
+pub mod sweep;
+pub mod synapses;
+pub mod tmd;
+pub mod units;
+pub mod validation;
+pub mod warnings;
+pub mod watch;
+mod write;
+
+pub use cell_id::CellId;
+pub use channels::{Channel, ChannelType, Ion};
+pub use coarsen::ThinNeuritePolicy;
+pub use codes::{Code, Severity};
+pub use compartments::{Compartment, Compartments, Frame, NodeSpan};
+pub use describe::ModelDescription;
+pub use edit::EditableSkeleton;
+pub use error::{Limit, SwcError};
+pub use export::{ExportColumns, NodeTable};
+pub use features::{FeatureConfig, FeatureVector};
+pub use filter::{ExtraColumn, NodeFilter};
+pub use index_map::{AttachmentKind, IndexMap};
+pub use markov::{MarkovChannel, MarkovScheme, RateFn};
+pub use metadata::SwcMetadata;
+pub use morphometry::{BoundingBox, Morphometry, SpatialMetrics};
+pub use parameters::ParamError;
+pub use preview::Preview;
+pub use registration::Transform;
+pub use run_log::{LogValue, RunLog};
+pub use sections::Section;
+pub use state::StateError;
+pub use stimulus::Stimulus;
+pub use swc_reader::{
+    ConflictPolicy, Node, NodeFlags, ReadStats, ReaderOptions, Skeleton, StructureIdentifier,
+    swc_reader, swc_reader_from_buf, swc_reader_from_bytes,
+};
+pub use tmd::Filtration;
+pub use warnings::{SwcWarning, WarningKind};
+pub use watch::{ChangeSummary, SkeletonWatcher};
 
 /// A Python module implemented in Rust.
-#[pymodule]
+#[cfg(feature = "python")]
+#[pyo3::pymodule]
 mod compartment_rs {
+    use crate::python::{invalid_argument, os_error};
     use pyo3::prelude::*;
 
+    #[pymodule_init]
+    fn init(m: &Bound<'_, PyModule>) -> PyResult<()> {
+        super::python::register(m)
+    }
+
+    /// `manifest::BuildInfo::current()` as a dict
+    #[pyfunction]
+    fn build_info(py: Python<'_>) -> PyResult<Bound<'_, pyo3::types::PyDict>> {
+        let info = crate::manifest::BuildInfo::current();
+        let dict = pyo3::types::PyDict::new(py);
+        dict.set_item("version", info.version)?;
+        dict.set_item("git_commit", info.git_commit)?;
+        dict.set_item("features", info.features)?;
+        dict.set_item("target_arch", info.target_arch)?;
+        dict.set_item("target_os", info.target_os)?;
+        dict.set_item("profile", info.profile)?;
+        dict.set_item("target_features", info.target_features)?;
+        Ok(dict)
+    }
+
+    /// A skeleton read from SWC and edited with undo and redo, see
+    /// `history::EditHistory`. Edits raise ValueError when they do not apply.
+    #[pyclass(name = "Morphology")]
+    struct Morphology {
+        history: crate::history::EditHistory,
+    }
+
+    #[pymethods]
+    impl Morphology {
+        #[new]
+        #[pyo3(signature = (path, max_history=crate::history::DEFAULT_MAX_ENTRIES))]
+        fn new(path: std::path::PathBuf, max_history: usize) -> PyResult<Self> {
+            let skeleton = crate::swc_reader(path, &crate::ReaderOptions::default())?;
+            Ok(Morphology {
+                history: crate::history::EditHistory::new(skeleton).with_max_entries(max_history),
+            })
+        }
+
+        /// A skeleton from numpy columns, see `Skeleton::from_arrays`: int64
+        /// `node_ids` and `parent_ids` (-1 for the root), uint8 `types`, an
+        /// (n, 3) float64 `xyz` and float64 `radii`. Wrong dtypes or shapes
+        /// raise ValueError, an invalid skeleton SwcValidationError.
+        #[staticmethod]
+        #[pyo3(signature = (node_ids, types, xyz, radii, parent_ids, max_history=crate::history::DEFAULT_MAX_ENTRIES))]
+        fn from_arrays(
+            node_ids: &Bound<'_, PyAny>,
+            types: &Bound<'_, PyAny>,
+            xyz: &Bound<'_, PyAny>,
+            radii: &Bound<'_, PyAny>,
+            parent_ids: &Bound<'_, PyAny>,
+            max_history: usize,
+        ) -> PyResult<Self> {
+            use numpy::PyArray2;
+            use numpy::prelude::*;
+
+            let node_ids = column::<i64>(node_ids, "node_ids", "int64")?;
+            let types = column::<u8>(types, "types", "uint8")?;
+            let radii = column::<f64>(radii, "radii", "float64")?;
+            let parent_ids = column::<i64>(parent_ids, "parent_ids", "int64")?;
+            let xyz = xyz
+                .cast::<PyArray2<f64>>()
+                .map_err(|_| {
+                    invalid_argument(format!(
+                        "xyz must be an (n, 3) float64 array, got {}",
+                        describe_array(xyz)
+                    ))
+                })?
+                .readonly();
+            let shape = xyz.as_array().dim();
+            if shape.1 != 3 {
+                return Err(invalid_argument(format!(
+                    "xyz must be an (n, 3) float64 array, got shape ({}, {})",
+                    shape.0, shape.1
+                )));
+            }
+            let xyz: Vec<[f64; 3]> = xyz
+                .as_array()
+                .rows()
+                .into_iter()
+                .map(|row| [row[0], row[1], row[2]])
+                .collect();
+            let skeleton = crate::Skeleton::from_arrays(
+                &contiguous(&node_ids),
+                &contiguous(&types),
+                &xyz,
+                &contiguous(&radii),
+                &contiguous(&parent_ids),
+                &crate::ReaderOptions::default(),
+            )?;
+            Ok(Morphology {
+                history: crate::history::EditHistory::new(skeleton).with_max_entries(max_history),
+            })
+        }
+
+        fn __len__(&self) -> usize {
+            self.history.skeleton().nodes.len()
+        }
+
+        /// The node at a position, or a list of them for a slice, see
+        /// `Node`
+        fn __getitem__<'py>(
+            &self,
+            py: Python<'py>,
+            index: &Bound<'py, PyAny>,
+        ) -> PyResult<Bound<'py, PyAny>> {
+            node_views(py, &self.history.skeleton().nodes, index)
+        }
+
+        fn __iter__(&self) -> NodeIterator {
+            NodeIterator {
+                nodes: self.history.skeleton().nodes.clone(),
+                next: 0,
+            }
+        }
+
+        /// The skeleton as it stands, as SWC text
+        fn to_swc(&self) -> String {
+            crate::swc_reader::to_swc_string(
+                self.history.skeleton(),
+                &crate::ReaderOptions::default(),
+            )
+        }
+
+        /// `Skeleton::render_ascii` of the skeleton as it stands
+        #[pyo3(signature = (max_depth=None, longest_paths=None))]
+        fn tree_str(
+            &self,
+            max_depth: Option<usize>,
+            longest_paths: Option<usize>,
+        ) -> PyResult<String> {
+            let options = crate::render::AsciiOptions {
+                max_depth,
+                longest_paths,
+                ..Default::default()
+            };
+            self.history
+                .skeleton()
+                .render_ascii(&options)
+                .map_err(invalid_argument)
+        }
+
+        /// Writes `Skeleton::dendrogram_svg` to `path`
+        fn render_dendrogram_svg(&self, path: std::path::PathBuf) -> PyResult<()> {
+            self.history
+                .skeleton()
+                .render_dendrogram_svg(path)
+                .map_err(os_error)
+        }
+
+        fn insert_node_on_edge(
+            &mut self,
+            parent_id: u64,
+            child_id: u64,
+            position_fraction: f64,
+            radius: f64,
+        ) -> PyResult<()> {
+            self.apply(crate::history::Edit::InsertNodeOnEdge {
+                parent_id,
+                child_id,
+                position_fraction,
+                radius,
+            })
+        }
+
+        fn reattach_subtree(&mut self, subtree_root_id: u64, new_parent_id: u64) -> PyResult<()> {
+            self.apply(crate::history::Edit::ReattachSubtree {
+                subtree_root_id,
+                new_parent_id,
+            })
+        }
+
+        fn split_branch_at(&mut self, branch_end_id: u64, arc_length: f64) -> PyResult<()> {
+            self.apply(crate::history::Edit::SplitBranchAt {
+                branch_end_id,
+                arc_length,
+            })
+        }
+
+        fn prune(&mut self, node_id: u64) -> PyResult<()> {
+            self.apply(crate::history::Edit::Prune { node_id })
+        }
+
+        fn reroot(&mut self, node_id: u64) -> PyResult<()> {
+            self.apply(crate::history::Edit::Reroot { node_id })
+        }
+
+        #[pyo3(signature = (iterations=1))]
+        fn smooth(&mut self, iterations: usize) -> PyResult<()> {
+            self.apply(crate::history::Edit::Smooth { iterations })
+        }
+
+        fn repair_zero_radii(&mut self) -> PyResult<()> {
+            self.apply(crate::history::Edit::RepairZeroRadii)
+        }
+
+        /// Reverts the last edit, returning its description, None if there
+        /// was nothing to undo
+        fn undo(&mut self) -> Option<String> {
+            self.history.undo().map(ToString::to_string)
+        }
+
+        /// Applies the last undone edit again, returning its description
+        fn redo(&mut self) -> Option<String> {
+            self.history.redo().map(ToString::to_string)
+        }
+
+        /// Descriptions of the edits still applied, oldest first
+        fn history(&self) -> Vec<String> {
+            self.history.descriptions()
+        }
+
+        /// `analysis::rall_ratios` of the skeleton as it stands, one dict
+        /// per branch point with `node_id`, `type`, `parent_diam`,
+        /// `child_diams` and `ratio`
+        fn rall_ratios<'py>(
+            &self,
+            py: Python<'py>,
+        ) -> PyResult<Vec<Bound<'py, pyo3::types::PyDict>>> {
+            let skeleton = self.history.skeleton();
+            crate::analysis::rall_ratios(&skeleton.nodes, &skeleton.parent_child_map)
+                .into_iter()
+                .map(|report| {
+                    let dict = pyo3::types::PyDict::new(py);
+                    dict.set_item("node_id", report.node_id)?;
+                    dict.set_item("type", report.structure as u8)?;
+                    dict.set_item("parent_diam", report.parent_diam)?;
+                    dict.set_item("child_diams", report.child_diams)?;
+                    dict.set_item("ratio", report.ratio)?;
+                    Ok(dict)
+                })
+                .collect()
+        }
+
+        fn checkpoint(&mut self, name: &str) {
+            self.history.checkpoint(name)
+        }
+
+        fn restore(&mut self, name: &str) -> PyResult<()> {
+            self.history.restore(name).map_err(invalid_argument)
+        }
+
+        /// The skeleton as it stands as a read-only `SharedMorphology`,
+        /// raising ValueError if its maps disagree with its nodes
+        fn commit(&self) -> PyResult<SharedMorphology> {
+            let skeleton = self.history.skeleton().clone();
+            skeleton.validate_maps().map_err(invalid_argument)?;
+            Ok(SharedMorphology {
+                skeleton: std::sync::Arc::new(skeleton),
+            })
+        }
+    }
+
+    impl Morphology {
+        fn apply(&mut self, edit: crate::history::Edit) -> PyResult<()> {
+            self.history.apply(edit).map_err(invalid_argument)
+        }
+    }
+
+    /// A read-only skeleton shared with `registry`, so every object handed
+    /// out for one cell uses the same data. Transformations return new
+    /// objects sharing the columns they leave alone; `edit` makes an
+    /// editable copy.
+    #[pyclass(name = "SharedMorphology", frozen)]
+    struct SharedMorphology {
+        skeleton: std::sync::Arc<crate::Skeleton>,
+    }
+
+    #[pymethods]
+    impl SharedMorphology {
+        fn __len__(&self) -> usize {
+            self.skeleton.nodes.len()
+        }
+
+        fn __getitem__<'py>(
+            &self,
+            py: Python<'py>,
+            index: &Bound<'py, PyAny>,
+        ) -> PyResult<Bound<'py, PyAny>> {
+            node_views(py, &self.skeleton.nodes, index)
+        }
+
+        fn __iter__(&self) -> NodeIterator {
+            NodeIterator {
+                nodes: self.skeleton.nodes.clone(),
+                next: 0,
+            }
+        }
+
+        #[getter]
+        fn cell_id(&self) -> String {
+            self.skeleton.cell_id().to_string()
+        }
+
+        /// Address of the shared skeleton, equal for objects sharing data
+        fn data_ptr(&self) -> usize {
+            std::sync::Arc::as_ptr(&self.skeleton) as usize
+        }
+
+        fn to_swc(&self) -> String {
+            crate::swc_reader::to_swc_string(&self.skeleton, &crate::ReaderOptions::default())
+        }
+
+        /// `Skeleton::render_ascii`
+        #[pyo3(signature = (max_depth=None, longest_paths=None))]
+        fn tree_str(
+            &self,
+            max_depth: Option<usize>,
+            longest_paths: Option<usize>,
+        ) -> PyResult<String> {
+            let options = crate::render::AsciiOptions {
+                max_depth,
+                longest_paths,
+                ..Default::default()
+            };
+            self.skeleton
+                .render_ascii(&options)
+                .map_err(invalid_argument)
+        }
+
+        /// Addresses of the `nodes`, `parent_child_map`, `child_parent_map`
+        /// and `extras` columns, equal where two objects share a column
+        fn buffer_ptrs(&self) -> std::collections::HashMap<&'static str, usize> {
+            let skeleton = &self.skeleton;
+            std::collections::HashMap::from([
+                ("nodes", std::sync::Arc::as_ptr(&skeleton.nodes) as usize),
+                (
+                    "parent_child_map",
+                    std::sync::Arc::as_ptr(&skeleton.parent_child_map) as usize,
+                ),
+                (
+                    "child_parent_map",
+                    std::sync::Arc::as_ptr(&skeleton.child_parent_map) as usize,
+                ),
+                ("extras", std::sync::Arc::as_ptr(&skeleton.extras) as usize),
+            ])
+        }
+
+        /// `Skeleton::smoothed`
+        #[pyo3(signature = (iterations=1))]
+        fn smoothed(&self, iterations: usize) -> SharedMorphology {
+            SharedMorphology::from(self.skeleton.smoothed(iterations))
+        }
+
+        /// `Skeleton::pruned`
+        fn pruned(&self, node_id: u64) -> PyResult<SharedMorphology> {
+            Ok(SharedMorphology::from(
+                self.skeleton.pruned(node_id).map_err(invalid_argument)?,
+            ))
+        }
+
+        /// `Skeleton::rerooted`
+        fn rerooted(&self, node_id: u64) -> PyResult<SharedMorphology> {
+            Ok(SharedMorphology::from(
+                self.skeleton.rerooted(node_id).map_err(invalid_argument)?,
+            ))
+        }
+
+        /// `Skeleton::resampled`
+        fn resampled(&self, spacing: f64) -> PyResult<SharedMorphology> {
+            Ok(SharedMorphology::from(
+                self.skeleton.resampled(spacing).map_err(invalid_argument)?,
+            ))
+        }
+
+        /// `Skeleton::normalized`
+        fn normalized(&self) -> PyResult<SharedMorphology> {
+            Ok(SharedMorphology::from(
+                self.skeleton.normalized().map_err(invalid_argument)?,
+            ))
+        }
+
+        /// An editable `Morphology` starting from a copy of the skeleton
+        #[pyo3(signature = (max_history=crate::history::DEFAULT_MAX_ENTRIES))]
+        fn edit(&self, max_history: usize) -> Morphology {
+            Morphology {
+                history: crate::history::EditHistory::new((*self.skeleton).clone())
+                    .with_max_entries(max_history),
+            }
+        }
+    }
+
+    impl From<crate::Skeleton> for SharedMorphology {
+        fn from(skeleton: crate::Skeleton) -> Self {
+            SharedMorphology {
+                skeleton: std::sync::Arc::new(skeleton),
+            }
+        }
+    }
+
+    /// `array` as a 1-d numpy array of `T`, ValueError "`name` must be a 1-d
+    /// `dtype` array" otherwise
+    fn column<'py, T: numpy::Element>(
+        array: &Bound<'py, PyAny>,
+        name: &str,
+        dtype: &str,
+    ) -> PyResult<numpy::PyReadonlyArray1<'py, T>> {
+        use numpy::prelude::*;
+
+        match array.cast::<numpy::PyArray1<T>>() {
+            Ok(array) => Ok(array.readonly()),
+            Err(_) => Err(invalid_argument(format!(
+                "{} must be a 1-d {} array, got {}",
+                name,
+                dtype,
+                describe_array(array)
+            ))),
+        }
+    }
+
+    /// The elements of `array` in place, or copied out of a strided view
+    fn contiguous<'a, T: numpy::Element + Clone>(
+        array: &'a numpy::PyReadonlyArray1<'_, T>,
+    ) -> std::borrow::Cow<'a, [T]> {
+        match array.as_slice() {
+            Ok(slice) => std::borrow::Cow::Borrowed(slice),
+            Err(_) => std::borrow::Cow::Owned(array.as_array().to_vec()),
+        }
+    }
+
+    /// "a `dtype` array with `ndim` dimensions", or the type name of
+    /// anything that is not a numpy array
+    fn describe_array(value: &Bound<'_, PyAny>) -> String {
+        match (value.getattr("dtype"), value.getattr("ndim")) {
+            (Ok(dtype), Ok(ndim)) => format!("a {} array with {} dimensions", dtype, ndim),
+            _ => value
+                .get_type()
+                .name()
+                .map(|name| name.to_string())
+                .unwrap_or_else(|_| "an unknown object".to_owned()),
+        }
+    }
+
+    /// Positions `index` picks out of `len` items, as a list would: an int,
+    /// negative from the end, or a slice. Ints out of range raise
+    /// IndexError "`what` index out of range".
+    fn positions(index: &Bound<'_, PyAny>, len: usize, what: &str) -> PyResult<Selection> {
+        if let Ok(slice) = index.cast::<pyo3::types::PySlice>() {
+            let range = slice.indices(len as isize)?;
+            return Ok(Selection::Many(
+                (0..range.slicelength as isize)
+                    .map(|k| (range.start + k * range.step) as usize)
+                    .collect(),
+            ));
+        }
+        let i: isize = index.extract()?;
+        let position = if i < 0 { i + len as isize } else { i };
+        if position < 0 || position >= len as isize {
+            return Err(pyo3::exceptions::PyIndexError::new_err(format!(
+                "{} index out of range",
+                what
+            )));
+        }
+        Ok(Selection::One(position as usize))
+    }
+
+    enum Selection {
+        One(usize),
+        Many(Vec<usize>),
+    }
+
+    impl Selection {
+        /// `view` of the one position, or a list of views of every one
+        fn views<'py, T>(
+            self,
+            py: Python<'py>,
+            view: impl Fn(usize) -> T,
+        ) -> PyResult<Bound<'py, PyAny>>
+        where
+            T: pyo3::PyClass + Into<pyo3::PyClassInitializer<T>>,
+        {
+            match self {
+                Selection::One(i) => Ok(Bound::new(py, view(i))?.into_any()),
+                Selection::Many(positions) => {
+                    let views = positions
+                        .into_iter()
+                        .map(|i| Bound::new(py, view(i)))
+                        .collect::<PyResult<Vec<_>>>()?;
+                    Ok(pyo3::types::PyList::new(py, views)?.into_any())
+                }
+            }
+        }
+    }
+
+    /// One node of a skeleton, read-only. Holds the node column it came
+    /// from rather than a copy, so it shows the skeleton as it was when
+    /// the view was taken even if a `Morphology` is edited after.
+    #[pyclass(name = "Node", frozen)]
+    struct NodeView {
+        nodes: std::sync::Arc<Vec<crate::Node>>,
+        position: usize,
+    }
+
+    impl NodeView {
+        fn node(&self) -> &crate::Node {
+            &self.nodes[self.position]
+        }
+    }
+
+    #[pymethods]
+    impl NodeView {
+        #[getter]
+        fn node_id(&self) -> u64 {
+            self.node().node_id
+        }
+
+        /// SWC structure type
+        #[getter]
+        fn r#type(&self) -> u8 {
+            self.node().structured_identifier as u8
+        }
+
+        #[getter]
+        fn x(&self) -> f64 {
+            self.node().x_pos
+        }
+
+        #[getter]
+        fn y(&self) -> f64 {
+            self.node().y_pos
+        }
+
+        #[getter]
+        fn z(&self) -> f64 {
+            self.node().z_pos
+        }
+
+        #[getter]
+        fn radius(&self) -> f64 {
+            self.node().radius
+        }
+
+        /// None for the root
+        #[getter]
+        fn parent_id(&self) -> Option<u64> {
+            let n = self.node();
+            (n.parent_id != n.node_id).then_some(n.parent_id)
+        }
+
+        fn __repr__(&self) -> String {
+            let n = self.node();
+            format!(
+                "Node(node_id={}, type={}, x={}, y={}, z={}, radius={}, parent_id={})",
+                n.node_id,
+                n.structured_identifier as u8,
+                n.x_pos,
+                n.y_pos,
+                n.z_pos,
+                n.radius,
+                self.parent_id()
+                    .map_or("None".to_owned(), |p| p.to_string())
+            )
+        }
+    }
+
+    /// Iterates over the nodes of a skeleton, in file order
+    #[pyclass(name = "NodeIterator")]
+    struct NodeIterator {
+        nodes: std::sync::Arc<Vec<crate::Node>>,
+        next: usize,
+    }
+
+    #[pymethods]
+    impl NodeIterator {
+        fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+            slf
+        }
+
+        fn __next__(&mut self) -> Option<NodeView> {
+            let position = self.next;
+            (position < self.nodes.len()).then(|| {
+                self.next += 1;
+                NodeView {
+                    nodes: self.nodes.clone(),
+                    position,
+                }
+            })
+        }
+    }
+
+    /// `__getitem__` of a skeleton's `nodes`
+    fn node_views<'py>(
+        py: Python<'py>,
+        nodes: &std::sync::Arc<Vec<crate::Node>>,
+        index: &Bound<'py, PyAny>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        positions(index, nodes.len(), "node")?.views(py, |position| NodeView {
+            nodes: nodes.clone(),
+            position,
+        })
+    }
+
+    /// Reading and writing cells
+    #[pymodule]
+    mod io {
+        use super::SharedMorphology;
+        use pyo3::prelude::*;
+
+        /// Runs `standardize::Pipeline` over every file in `input_dir`,
+        /// writing into `output_dir`. `options` is a recipe as written by
+        /// `StandardizeOptions::to_text`, the defaults if None. Returns one
+        /// dict per file with `source`, `output`, `detected`, `operations`,
+        /// `error`, `qc_score` (None if it failed first) and `quarantined`.
+        #[pyfunction]
+        #[pyo3(signature = (input_dir, output_dir, options=None, threads=0))]
+        pub(super) fn standardize<'py>(
+            py: Python<'py>,
+            input_dir: std::path::PathBuf,
+            output_dir: std::path::PathBuf,
+            options: Option<&str>,
+            threads: usize,
+        ) -> PyResult<Vec<Bound<'py, pyo3::types::PyDict>>> {
+            use crate::standardize::{Dataset, Pipeline, StandardizeOptions};
+
+            let options = match options {
+                Some(text) => StandardizeOptions::parse(text)?,
+                None => StandardizeOptions::default(),
+            };
+            let dataset = Dataset::from_dir(&input_dir)?;
+            let report = py.detach(|| {
+                Pipeline::standardize(options)
+                    .with_threads(threads)
+                    .run(&dataset, &output_dir)
+            })?;
+            report
+                .files
+                .into_iter()
+                .map(|file| {
+                    let dict = pyo3::types::PyDict::new(py);
+                    dict.set_item("source", file.source)?;
+                    dict.set_item("output", file.output)?;
+                    dict.set_item("detected", file.detected)?;
+                    dict.set_item("operations", file.operations)?;
+                    dict.set_item("error", file.error.map(|e| e.to_string()))?;
+                    dict.set_item("qc_score", file.qc.map(|qc| qc.score))?;
+                    dict.set_item("quarantined", file.quarantined)?;
+                    Ok(dict)
+                })
+                .collect()
+        }
+
+        /// `quick::quick_look` of a path, or of SWC data given as bytes, as
+        /// one dict: `nodes`, `type_counts`, `zero_radius_fixed`,
+        /// `total_length`, `total_area`, `max_branching_degree`,
+        /// `branch_points`, `tips`, `bounding_box` as `(min, max)`,
+        /// `streamed`, and `snapshot` with `compartments`,
+        /// `input_resistance` and `max_electrotonic_length`, None unless
+        /// `snapshot_status` is "computed"
+        #[pyfunction]
+        #[pyo3(signature = (
+            source,
+            snapshot=true,
+            snapshot_max_nodes=None,
+            snapshot_compartments=None,
+        ))]
+        fn quick_look<'py>(
+            py: Python<'py>,
+            source: &Bound<'py, PyAny>,
+            snapshot: bool,
+            snapshot_max_nodes: Option<usize>,
+            snapshot_compartments: Option<usize>,
+        ) -> PyResult<Bound<'py, pyo3::types::PyDict>> {
+            use crate::quick::{QuickLookOptions, Snapshot};
+
+            let defaults = QuickLookOptions::default();
+            let options = QuickLookOptions {
+                snapshot,
+                snapshot_max_nodes: snapshot_max_nodes.unwrap_or(defaults.snapshot_max_nodes),
+                snapshot_compartments: snapshot_compartments
+                    .unwrap_or(defaults.snapshot_compartments),
+                ..defaults
+            };
+            let look = if let Ok(bytes) = source.cast::<pyo3::types::PyBytes>() {
+                let data = bytes.as_bytes();
+                py.detach(|| crate::quick::quick_look_from_bytes(data, &options))?
+            } else {
+                let path: std::path::PathBuf = source.extract()?;
+                py.detach(|| crate::quick::quick_look(path, &options))?
+            };
+
+            let dict = pyo3::types::PyDict::new(py);
+            dict.set_item("nodes", look.nodes)?;
+            let counts: std::collections::BTreeMap<u8, usize> = look
+                .type_counts
+                .iter()
+                .map(|(t, n)| (*t as u8, *n))
+                .collect();
+            dict.set_item("type_counts", counts)?;
+            dict.set_item("zero_radius_fixed", look.zero_radius_fixed)?;
+            dict.set_item("total_length", look.total_length)?;
+            dict.set_item("total_area", look.total_area)?;
+            dict.set_item("max_branching_degree", look.max_branching_degree)?;
+            dict.set_item("branch_points", look.branch_points)?;
+            dict.set_item("tips", look.tips)?;
+            dict.set_item(
+                "bounding_box",
+                (look.bounding_box.min, look.bounding_box.max),
+            )?;
+            dict.set_item("streamed", look.streamed)?;
+            let (status, snapshot) = match look.snapshot {
+                Snapshot::Computed(s) => {
+                    let snapshot = pyo3::types::PyDict::new(py);
+                    snapshot.set_item("compartments", s.compartments)?;
+                    snapshot.set_item("input_resistance", s.input_resistance)?;
+                    snapshot.set_item("max_electrotonic_length", s.max_electrotonic_length)?;
+                    ("computed", Some(snapshot))
+                }
+                Snapshot::Skipped { .. } => ("skipped", None),
+                Snapshot::NotRequested => ("not_requested", None),
+            };
+            dict.set_item("snapshot_status", status)?;
+            dict.set_item("snapshot", snapshot)?;
+            Ok(dict)
+        }
+
+        /// `swc_reader` of `path` as plain Python data: `nodes`, one dict per
+        /// node with `node_id`, `type`, `x`, `y`, `z`, `radius` and
+        /// `parent_id` (-1 at the root), `parent_child_map` and
+        /// `child_parent_map` as dicts of node ID to list of node IDs, and
+        /// `warnings` as strings. Raises `SwcIoError` when the file cannot be
+        /// read and `SwcParseError` or `SwcValidationError`, with `line_no`,
+        /// when its contents are wrong.
+        #[pyfunction]
+        #[pyo3(signature = (path, emit_warnings=true, strict=false, write_path=None))]
+        fn read_swc<'py>(
+            py: Python<'py>,
+            path: std::path::PathBuf,
+            emit_warnings: bool,
+            strict: bool,
+            write_path: Option<std::path::PathBuf>,
+        ) -> PyResult<Bound<'py, pyo3::types::PyDict>> {
+            let options = crate::ReaderOptions {
+                emit_warnings,
+                strict,
+                write_path,
+                ..crate::ReaderOptions::default()
+            };
+            let skeleton = py.detach(|| crate::swc_reader(path, &options))?;
+            let nodes = skeleton
+                .nodes
+                .iter()
+                .map(|node| {
+                    let dict = pyo3::types::PyDict::new(py);
+                    dict.set_item("node_id", node.node_id)?;
+                    dict.set_item("type", node.structured_identifier as u8)?;
+                    dict.set_item("x", node.x_pos)?;
+                    dict.set_item("y", node.y_pos)?;
+                    dict.set_item("z", node.z_pos)?;
+                    dict.set_item("radius", node.radius)?;
+                    let parent_id = if node.parent_id == node.node_id {
+                        -1
+                    } else {
+                        node.parent_id as i64
+                    };
+                    dict.set_item("parent_id", parent_id)?;
+                    Ok(dict)
+                })
+                .collect::<PyResult<Vec<_>>>()?;
+
+            let dict = pyo3::types::PyDict::new(py);
+            dict.set_item("nodes", nodes)?;
+            dict.set_item("parent_child_map", &*skeleton.parent_child_map)?;
+            dict.set_item("child_parent_map", &*skeleton.child_parent_map)?;
+            let warnings: Vec<String> = skeleton.warnings.iter().map(|w| w.to_string()).collect();
+            dict.set_item("warnings", warnings)?;
+            Ok(dict)
+        }
+
+        /// `Dataset::passive_snapshots` of every file in `input_dir`: one
+        /// dict per file with `source`, `snapshot` as
+        /// `Compartments.passive_snapshot` gives it, and `error`, one of
+        /// them None
+        #[pyfunction]
+        #[pyo3(signature = (input_dir, threads=0))]
+        fn passive_snapshots(
+            py: Python<'_>,
+            input_dir: std::path::PathBuf,
+            threads: usize,
+        ) -> PyResult<Vec<Bound<'_, pyo3::types::PyDict>>> {
+            let dataset = crate::standardize::Dataset::from_dir(&input_dir)?;
+            py.detach(|| dataset.passive_snapshots(threads))
+                .into_iter()
+                .map(|(source, result)| {
+                    let dict = pyo3::types::PyDict::new(py);
+                    dict.set_item("source", source)?;
+                    match result {
+                        Ok(snapshot) => {
+                            dict.set_item(
+                                "snapshot",
+                                super::passive_snapshot_dict(py, &snapshot)?,
+                            )?;
+                            dict.set_item("error", py.None())?;
+                        }
+                        Err(error) => {
+                            dict.set_item("snapshot", py.None())?;
+                            dict.set_item("error", error)?;
+                        }
+                    }
+                    Ok(dict)
+                })
+                .collect()
+        }
+
+        /// Reads every file in `input_dir`, or every cell of a bundle, see
+        /// `Dataset::load`. With
+        /// `register`, files go through `registry`: unchanged files already
+        /// there are not read again, and new ones are stored.
+        #[pyfunction]
+        #[pyo3(signature = (input_dir, register=false))]
+        pub(super) fn load_dataset(
+            py: Python<'_>,
+            input_dir: std::path::PathBuf,
+            register: bool,
+        ) -> PyResult<Vec<SharedMorphology>> {
+            use crate::python::os_error;
+
+            let dataset = crate::standardize::Dataset::from_source(&input_dir)?;
+            let registry = register.then(crate::registry::Registry::global);
+            let skeletons = py
+                .detach(|| dataset.load(&crate::ReaderOptions::default(), registry))
+                .map_err(os_error)?;
+            Ok(skeletons
+                .into_iter()
+                .map(|skeleton| SharedMorphology { skeleton })
+                .collect())
+        }
+    }
+
+    /// Deprecated, use `io.standardize`
+    #[pyfunction]
+    #[pyo3(signature = (input_dir, output_dir, options=None, threads=0))]
+    fn standardize<'py>(
+        py: Python<'py>,
+        input_dir: std::path::PathBuf,
+        output_dir: std::path::PathBuf,
+        options: Option<&str>,
+        threads: usize,
+    ) -> PyResult<Vec<Bound<'py, pyo3::types::PyDict>>> {
+        crate::python::warn_deprecated(py, "compartment_rs.standardize")?;
+        io::standardize(py, input_dir, output_dir, options, threads)
+    }
+
+    /// Deprecated, use `io.load_dataset`
+    #[pyfunction]
+    #[pyo3(signature = (input_dir, register=false))]
+    fn load_dataset(
+        py: Python<'_>,
+        input_dir: std::path::PathBuf,
+        register: bool,
+    ) -> PyResult<Vec<SharedMorphology>> {
+        crate::python::warn_deprecated(py, "compartment_rs.load_dataset")?;
+        io::load_dataset(py, input_dir, register)
+    }
+
+    /// Every deprecated entry point still in place, see `deprecation`: one
+    /// dict per shim with `name`, `replacement`, `since` and `removal`
+    #[pyfunction]
+    fn deprecations(py: Python<'_>) -> PyResult<Vec<Bound<'_, pyo3::types::PyDict>>> {
+        crate::deprecation::SHIMS
+            .iter()
+            .map(|shim| {
+                let dict = pyo3::types::PyDict::new(py);
+                dict.set_item("name", shim.name)?;
+                dict.set_item("replacement", shim.replacement)?;
+                dict.set_item("since", shim.since)?;
+                dict.set_item("removal", shim.removal)?;
+                Ok(dict)
+            })
+            .collect()
+    }
+
+    /// The process-wide `registry::Registry` of skeletons by cell id. Ids
+    /// are strings; a malformed one raises ValueError, a missing one
+    /// KeyError.
+    #[pymodule]
+    mod registry {
+        use super::{Morphology, SharedMorphology};
+        use crate::python::invalid_argument;
+        use crate::registry::Registry;
+        use pyo3::exceptions::{PyKeyError, PyTypeError};
+        use pyo3::prelude::*;
+
+        fn parse(cell_id: &str) -> PyResult<crate::CellId> {
+            crate::CellId::parse(cell_id).map_err(invalid_argument)
+        }
+
+        fn ids(ids: Vec<crate::CellId>) -> Vec<String> {
+            ids.iter().map(ToString::to_string).collect()
+        }
+
+        /// Stores a `SharedMorphology` as is, or a `Morphology` as it
+        /// stands, under its cell id. Returns the id and the ids evicted to
+        /// stay within the budget.
+        #[pyfunction]
+        fn put(morphology: &Bound<'_, PyAny>) -> PyResult<(String, Vec<String>)> {
+            let skeleton = if let Ok(shared) = morphology.cast::<SharedMorphology>() {
+                std::sync::Arc::clone(&shared.get().skeleton)
+            } else if let Ok(editable) = morphology.cast::<Morphology>() {
+                std::sync::Arc::new(editable.borrow().history.skeleton().clone())
+            } else {
+                return Err(PyTypeError::new_err(
+                    "Expected a Morphology or SharedMorphology",
+                ));
+            };
+            let stored = Registry::global().put(skeleton).map_err(invalid_argument)?;
+            Ok((stored.id.to_string(), ids(stored.evicted)))
+        }
+
+        #[pyfunction]
+        fn get(cell_id: &str) -> PyResult<SharedMorphology> {
+            Registry::global()
+                .get(&parse(cell_id)?)
+                .map(|skeleton| SharedMorphology { skeleton })
+                .ok_or_else(|| PyKeyError::new_err(cell_id.to_owned()))
+        }
+
+        /// Ids stored, least recently used first
+        #[pyfunction]
+        fn list() -> Vec<String> {
+            ids(Registry::global().list())
+        }
+
+        /// Whether there was an entry to drop
+        #[pyfunction]
+        fn evict(cell_id: &str) -> PyResult<bool> {
+            Ok(Registry::global().evict(&parse(cell_id)?))
+        }
+
+        /// Drops every entry, returning how many there were
+        #[pyfunction]
+        fn clear() -> usize {
+            Registry::global().clear()
+        }
+
+        #[pyfunction]
+        fn accounted_bytes() -> usize {
+            Registry::global().accounted_bytes()
+        }
+
+        #[pyfunction]
+        fn budget() -> Option<usize> {
+            Registry::global().budget()
+        }
+
+        /// Sets the budget in bytes, None for no limit, returning the ids
+        /// evicted to fit it
+        #[pyfunction]
+        fn set_budget(budget: Option<usize>) -> Vec<String> {
+            ids(Registry::global().set_budget(budget))
+        }
+    }
+
+    /// `analysis::appositions` between the skeletons of `a` and `b` as
+    /// they stand, segments of SWC type `a_type` on `a` against `b_type` on
+    /// `b`. One dict per apposition with `pairs`, `a_segments`,
+    /// `b_segments`, `a_point`, `b_point`, `distance`, `a_extent` and
+    /// `b_extent`; the count is the list's length.
+    #[pyfunction]
+    #[pyo3(signature = (a, b, threshold, a_type=2, b_type=3))]
+    fn appositions<'py>(
+        py: Python<'py>,
+        a: PyRef<'py, Morphology>,
+        b: PyRef<'py, Morphology>,
+        threshold: f64,
+        a_type: u8,
+        b_type: u8,
+    ) -> PyResult<Vec<Bound<'py, pyo3::types::PyDict>>> {
+        crate::analysis::appositions(
+            (a.history.skeleton(), b.history.skeleton()),
+            (a_type.into(), b_type.into()),
+            threshold,
+        )
+        .into_iter()
+        .map(|apposition| {
+            let dict = pyo3::types::PyDict::new(py);
+            dict.set_item("pairs", apposition.pairs)?;
+            dict.set_item("a_segments", apposition.a_segments)?;
+            dict.set_item("b_segments", apposition.b_segments)?;
+            dict.set_item("a_point", apposition.a_point)?;
+            dict.set_item("b_point", apposition.b_point)?;
+            dict.set_item("distance", apposition.distance)?;
+            dict.set_item("a_extent", apposition.a_extent)?;
+            dict.set_item("b_extent", apposition.b_extent)?;
+            Ok(dict)
+        })
+        .collect()
+    }
+
+    /// Apposition counts between every ordered pair of files in `input_dir`,
+    /// see `analysis::dataset_appositions`, as dicts with `a`, `b` and
+    /// `count`. Raises ValueError beyond `max_pairs` pairs.
+    #[pyfunction]
+    #[pyo3(signature = (input_dir, threshold, max_pairs=10000, a_type=2, b_type=3))]
+    fn dataset_appositions<'py>(
+        py: Python<'py>,
+        input_dir: std::path::PathBuf,
+        threshold: f64,
+        max_pairs: usize,
+        a_type: u8,
+        b_type: u8,
+    ) -> PyResult<Vec<Bound<'py, pyo3::types::PyDict>>> {
+        let dataset = crate::standardize::Dataset::from_dir(&input_dir)?;
+        let pairs = py
+            .detach(|| {
+                crate::analysis::dataset_appositions(
+                    &dataset,
+                    (a_type.into(), b_type.into()),
+                    threshold,
+                    max_pairs,
+                )
+            })
+            .map_err(invalid_argument)?;
+        pairs
+            .into_iter()
+            .map(|pair| {
+                let dict = pyo3::types::PyDict::new(py);
+                dict.set_item("a", pair.a)?;
+                dict.set_item("b", pair.b)?;
+                dict.set_item("count", pair.appositions.len())?;
+                Ok(dict)
+            })
+            .collect()
+    }
+
+    /// The compartments of `morphology` as it stands, every one with
+    /// `mechanism` ("hh" or "passive", 1e-4 S/cm²), 100 Ω·cm and 1 µF/cm²
+    fn model(morphology: &Morphology, mechanism: &str) -> PyResult<crate::Compartments> {
+        use crate::channels::{Dynamics, HodgkinHuxley, Passive};
+
+        let channel_type = match mechanism {
+            "hh" => crate::ChannelType::HodgkinHuxley(HodgkinHuxley::new()),
+            "passive" => crate::ChannelType::Passive(Passive::default()),
+            _ => {
+                return Err(invalid_argument(format!(
+                    "Unknown mechanism '{}'; use hh or passive",
+                    mechanism
+                )));
+            }
+        };
+        let mut compartments =
+            crate::Compartments::from_skeleton(morphology.history.skeleton().clone());
+        for c in compartments.components.iter_mut() {
+            c.set_channel(crate::Channel {
+                channel_type: channel_type.clone(),
+                resistance: 100.0,
+                capacitance: 1.0,
+                conductance: 1e-4,
+                ..Default::default()
+            });
+        }
+        Ok(compartments)
+    }
+
+    /// The compartments of a `Morphology` as it stands, read-only, indexed
+    /// as in Rust: 0 is the dummy root, then one per compartment. Indexing,
+    /// slicing and iterating give `Compartment` views sharing the model
+    /// rather than copies of it.
+    #[pyclass(name = "Compartments", frozen)]
+    struct PyCompartments {
+        compartments: std::sync::Arc<crate::Compartments>,
+    }
+
+    #[pymethods]
+    impl PyCompartments {
+        #[new]
+        #[pyo3(signature = (morphology, mechanism="hh"))]
+        fn new(morphology: PyRef<'_, Morphology>, mechanism: &str) -> PyResult<Self> {
+            Ok(PyCompartments {
+                compartments: std::sync::Arc::new(model(&morphology, mechanism)?),
+            })
+        }
+
+        fn __len__(&self) -> usize {
+            self.compartments.components.len()
+        }
+
+        fn __getitem__<'py>(
+            &self,
+            py: Python<'py>,
+            index: &Bound<'py, PyAny>,
+        ) -> PyResult<Bound<'py, PyAny>> {
+            positions(index, self.__len__(), "compartment")?.views(py, |idx| CompartmentView {
+                compartments: self.compartments.clone(),
+                idx,
+            })
+        }
+
+        fn __iter__(&self) -> CompartmentIterator {
+            CompartmentIterator {
+                compartments: self.compartments.clone(),
+                next: 0,
+            }
+        }
+
+        /// `snapshot::quick_passive_snapshot`, see `passive_snapshot_dict`
+        fn passive_snapshot<'py>(
+            &self,
+            py: Python<'py>,
+        ) -> PyResult<Bound<'py, pyo3::types::PyDict>> {
+            let snapshot = py
+                .detach(|| crate::snapshot::quick_passive_snapshot(&self.compartments))
+                .map_err(invalid_argument)?;
+            passive_snapshot_dict(py, &snapshot)
+        }
+    }
+
+    /// `input_resistance`, `tau`, `fit_points` and `tips`, one dict per tip
+    /// with `idx`, `path_distance` and `attenuation`
+    fn passive_snapshot_dict<'py>(
+        py: Python<'py>,
+        snapshot: &crate::snapshot::PassiveSnapshot,
+    ) -> PyResult<Bound<'py, pyo3::types::PyDict>> {
+        let dict = pyo3::types::PyDict::new(py);
+        dict.set_item("input_resistance", snapshot.input_resistance)?;
+        dict.set_item("tau", snapshot.tau)?;
+        dict.set_item("fit_points", snapshot.fit_points)?;
+        let tips = snapshot
+            .tips
+            .iter()
+            .map(|tip| {
+                let tip_dict = pyo3::types::PyDict::new(py);
+                tip_dict.set_item("idx", tip.idx)?;
+                tip_dict.set_item("path_distance", tip.path_distance)?;
+                tip_dict.set_item("attenuation", tip.attenuation)?;
+                Ok(tip_dict)
+            })
+            .collect::<PyResult<Vec<_>>>()?;
+        dict.set_item("tips", tips)?;
+        Ok(dict)
+    }
+
+    /// One compartment, read-only, see `Compartments`
+    #[pyclass(name = "Compartment", frozen)]
+    struct CompartmentView {
+        compartments: std::sync::Arc<crate::Compartments>,
+        idx: usize,
+    }
+
+    impl CompartmentView {
+        fn compartment(&self) -> &crate::Compartment {
+            &self.compartments.components[self.idx]
+        }
+    }
+
+    #[pymethods]
+    impl CompartmentView {
+        #[getter]
+        fn idx(&self) -> usize {
+            self.idx
+        }
+
+        #[getter]
+        fn name(&self) -> &str {
+            &self.compartment().name
+        }
+
+        /// In µm
+        #[getter]
+        fn length(&self) -> f64 {
+            self.compartment().length
+        }
+
+        /// In µm
+        #[getter]
+        fn diam(&self) -> f64 {
+            self.compartment().diam
+        }
+
+        /// SWC structure type
+        #[getter]
+        fn structure(&self) -> u8 {
+            self.compartment().structure as u8
+        }
+
+        /// Index of the parent, None for the dummy root
+        #[getter]
+        fn parent(&self) -> Option<u64> {
+            self.compartment().parent_idxs.first().copied()
+        }
+
+        fn __repr__(&self) -> String {
+            let c = self.compartment();
+            format!(
+                "Compartment(idx={}, name={:?}, length={}, diam={})",
+                self.idx, c.name, c.length, c.diam
+            )
+        }
+    }
+
+    /// Iterates over compartments in index order, the dummy root first
+    #[pyclass(name = "CompartmentIterator")]
+    struct CompartmentIterator {
+        compartments: std::sync::Arc<crate::Compartments>,
+        next: usize,
+    }
+
+    #[pymethods]
+    impl CompartmentIterator {
+        fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+            slf
+        }
+
+        fn __next__(&mut self) -> Option<CompartmentView> {
+            let idx = self.next;
+            (idx < self.compartments.components.len()).then(|| {
+                self.next += 1;
+                CompartmentView {
+                    compartments: self.compartments.clone(),
+                    idx,
+                }
+            })
+        }
+    }
+
+    /// A simulation stepped from Python, with state read and set by path
+    /// between steps, see `state`. Built from `morphology` as it stands,
+    /// every compartment with `mechanism` ("hh" or "passive", 1e-4 S/cm²),
+    /// 100 Ω·cm and 1 µF/cm². State errors raise SimulationError, anything
+    /// else ValueError.
+    #[pyclass(name = "Session")]
+    struct Session {
+        simulation: crate::solver::Simulation,
+    }
+
+    #[pymethods]
+    impl Session {
+        #[new]
+        #[pyo3(signature = (morphology, dt=0.025, mechanism="hh"))]
+        fn new(morphology: PyRef<'_, Morphology>, dt: f64, mechanism: &str) -> PyResult<Self> {
+            let simulation = crate::solver::Simulation::new(&model(&morphology, mechanism)?, dt)?;
+            Ok(Session { simulation })
+        }
+
+        /// Time since the start, in ms
+        #[getter]
+        fn time(&self) -> f64 {
+            self.simulation.time()
+        }
+
+        /// Advances by `n` steps
+        #[pyo3(signature = (n=1))]
+        fn step(&mut self, n: usize) -> PyResult<()> {
+            for _ in 0..n {
+                self.simulation.step()?;
+            }
+            Ok(())
+        }
+
+        fn get(&self, path: &str) -> PyResult<f64> {
+            Ok(self.simulation.get(path)?)
+        }
+
+        fn set(&mut self, path: &str, value: f64) -> PyResult<()> {
+            Ok(self.simulation.set(path, value)?)
+        }
+
+        #[pyo3(signature = (prefix=""))]
+        fn list_paths(&self, prefix: &str) -> Vec<String> {
+            self.simulation.list_paths(prefix)
+        }
+
+        /// Adds `current`, in nA, to what compartment `idx` receives over
+        /// the next step
+        fn inject(&mut self, idx: usize, current: f64) -> PyResult<()> {
+            self.simulation.inject(idx, current).map_err(PyErr::from)
+        }
+
+        /// Runs `steps` steps with `stimuli` mapping compartment indices to
+        /// one current per step, and returns every voltage trace, see
+        /// `Simulation::run`
+        #[pyo3(signature = (steps, stimuli=std::collections::HashMap::new()))]
+        fn run(
+            &mut self,
+            steps: usize,
+            stimuli: std::collections::HashMap<usize, Vec<f64>>,
+        ) -> PyResult<Vec<Vec<f64>>> {
+            let stimuli: Vec<(usize, Vec<f64>)> = stimuli.into_iter().collect();
+            self.simulation
+                .run(steps, &stimuli)
+                .map(|result| result.voltages)
+                .map_err(PyErr::from)
+        }
+    }
+
+    /// Simulations whose buffers go when a `with` block ends, rather than
+    /// when Python drops the last reference:
+    ///
+    /// ```python
+    /// with compartment_rs.simulation.session(cell) as sim:
+    ///     sim.record("comp[2].hh.m")
+    ///     voltages = sim.run(400, {2: current})["voltages"]
+    /// ```
+    ///
+    /// Results come back as dicts of numpy arrays copied out of the run, so
+    /// they stay valid after the session closes. Every method of a closed
+    /// session raises SessionClosedError.
+    #[pymodule]
+    mod simulation {
+        use super::{Morphology, model};
+        use crate::python::invalid_argument;
+        use crate::session::SimulationSession;
+        use crate::solver::{SimError, SimulationResult};
+        use numpy::ndarray::Array2;
+        use numpy::{IntoPyArray, PyArray1};
+        use pyo3::prelude::*;
+        use pyo3::types::PyDict;
+
+        /// A `session::SimulationSession` built like `Session`
+        #[pyclass(name = "SimulationSession")]
+        struct PySimulationSession {
+            session: SimulationSession,
+            /// The model the session runs, for the analyses that need its
+            /// geometry
+            compartments: crate::compartments::Compartments,
+        }
+
+        impl PySimulationSession {
+            fn simulation(&mut self) -> PyResult<&mut crate::solver::Simulation> {
+                Ok(self.session.simulation()?)
+            }
+
+            fn last(&self) -> PyResult<&SimulationResult> {
+                self.session
+                    .result()?
+                    .ok_or_else(|| invalid_argument("Nothing has run yet"))
+            }
+        }
+
+        /// `result` as a dict: `dt`, `time` (ms), `voltages` (compartments
+        /// by samples, NaN rows for compartments not recorded),
+        /// `head_voltages` (spines by samples), `traces` by path,
+        /// `axial_currents` by (parent, child) and `snippets` by path, each
+        /// a list of (event_time, samples)
+        fn results<'py>(
+            py: Python<'py>,
+            result: &SimulationResult,
+        ) -> PyResult<Bound<'py, PyDict>> {
+            let samples = result.voltages.iter().map(Vec::len).max().unwrap_or(0);
+            let matrix = |rows: &[Vec<f64>]| {
+                Array2::from_shape_fn((rows.len(), samples), |(i, s)| {
+                    rows[i].get(s).copied().unwrap_or(f64::NAN)
+                })
+                .into_pyarray(py)
+            };
+            let dict = PyDict::new(py);
+            dict.set_item("dt", result.dt)?;
+            let time: Vec<f64> = (0..samples).map(|s| s as f64 * result.dt).collect();
+            dict.set_item("time", PyArray1::from_vec(py, time))?;
+            dict.set_item("voltages", matrix(&result.voltages))?;
+            dict.set_item("head_voltages", matrix(&result.head_voltages))?;
+            let traces = PyDict::new(py);
+            for (path, trace) in &result.traces {
+                traces.set_item(path, PyArray1::from_slice(py, trace))?;
+            }
+            dict.set_item("traces", traces)?;
+            let axial = PyDict::new(py);
+            for (pair, trace) in &result.axial_currents {
+                axial.set_item(pair, PyArray1::from_slice(py, trace))?;
+            }
+            dict.set_item("axial_currents", axial)?;
+            let snippets = PyDict::new(py);
+            for (path, found) in &result.snippets {
+                let found: Vec<(f64, Bound<'py, PyArray1<f64>>)> = found
+                    .iter()
+                    .map(|s| (s.event_time, PyArray1::from_slice(py, &s.samples)))
+                    .collect();
+                snippets.set_item(path, found)?;
+            }
+            dict.set_item("snippets", snippets)?;
+            Ok(dict)
+        }
+
+        #[pymethods]
+        impl PySimulationSession {
+            fn __enter__(slf: PyRef<'_, Self>) -> PyResult<PyRef<'_, Self>> {
+                match slf.session.is_closed() {
+                    true => Err(SimError::SessionClosed.into()),
+                    false => Ok(slf),
+                }
+            }
+
+            /// Closes the session; exceptions from the block propagate
+            fn __exit__(
+                &mut self,
+                _exc_type: Option<&Bound<'_, PyAny>>,
+                _exc_value: Option<&Bound<'_, PyAny>>,
+                _traceback: Option<&Bound<'_, PyAny>>,
+            ) -> bool {
+                self.session.close();
+                false
+            }
+
+            /// Frees the simulation and its last results; closing again
+            /// does nothing
+            fn close(&mut self) {
+                self.session.close();
+            }
+
+            #[getter]
+            fn closed(&self) -> bool {
+                self.session.is_closed()
+            }
+
+            /// Time since the start, in ms
+            #[getter]
+            fn time(&mut self) -> PyResult<f64> {
+                Ok(self.simulation()?.time())
+            }
+
+            /// Runs `steps` steps with `stimuli` mapping compartment indices
+            /// to one current per step, and returns the results
+            #[pyo3(signature = (steps, stimuli=std::collections::HashMap::new()))]
+            fn run<'py>(
+                &mut self,
+                py: Python<'py>,
+                steps: usize,
+                stimuli: std::collections::HashMap<usize, Vec<f64>>,
+            ) -> PyResult<Bound<'py, PyDict>> {
+                self.simulation()?;
+                let stimuli: Vec<(usize, Vec<f64>)> = stimuli.into_iter().collect();
+                let result = self.session.run(steps, &stimuli)?;
+                results(py, result)
+            }
+
+            /// Results of the last run, None before the first
+            #[getter]
+            fn results<'py>(&self, py: Python<'py>) -> PyResult<Option<Bound<'py, PyDict>>> {
+                match self.session.result() {
+                    Ok(Some(result)) => results(py, result).map(Some),
+                    Ok(None) => Ok(None),
+                    Err(e) => Err(e.into()),
+                }
+            }
+
+            /// Has `run` record the state path `path` into `traces`
+            fn record(&mut self, path: &str) -> PyResult<()> {
+                Ok(self.simulation()?.record(path)?)
+            }
+
+            /// Has `run` record the voltages of `idxs` only
+            fn record_voltages_of(&mut self, idxs: Vec<usize>) -> PyResult<()> {
+                self.simulation()?
+                    .record_voltages_of(&idxs)
+                    .map_err(PyErr::from)
+            }
+
+            /// Has `run` record the current from `parent_idx` into
+            /// `child_idx`, in nA, into `axial_currents`
+            fn add_axial_current_probe(
+                &mut self,
+                parent_idx: usize,
+                child_idx: usize,
+            ) -> PyResult<()> {
+                self.simulation()?
+                    .add_axial_current_probe(parent_idx, child_idx)
+                    .map_err(PyErr::from)
+            }
+
+            /// `analysis::peak_depolarization` of the last run between
+            /// `t0` and `t1` ms, by compartment index with NaN where
+            /// nothing was recorded
+            fn peak_depolarization<'py>(
+                &self,
+                py: Python<'py>,
+                t0: f64,
+                t1: f64,
+            ) -> PyResult<Bound<'py, PyArray1<f64>>> {
+                let peaks = crate::analysis::peak_depolarization(self.last()?, (t0, t1))
+                    .map_err(invalid_argument)?;
+                Ok(PyArray1::from_vec(py, peaks))
+            }
+
+            /// `analysis::backpropagation_efficacy` of the last run, by
+            /// compartment index with NaN where nothing was recorded
+            fn backpropagation_efficacy<'py>(
+                &self,
+                py: Python<'py>,
+                soma_idx: usize,
+            ) -> PyResult<Bound<'py, PyArray1<f64>>> {
+                let efficacy = crate::analysis::backpropagation_efficacy(self.last()?, soma_idx)
+                    .map_err(invalid_argument)?;
+                Ok(PyArray1::from_vec(py, efficacy))
+            }
+
+            /// Has `run` record the membrane current of every compartment,
+            /// in nA, into `traces`
+            fn record_membrane_currents(&mut self) -> PyResult<()> {
+                self.simulation()?.record_membrane_currents();
+                Ok(())
+            }
+
+            /// `analysis::binned_membrane_current` of the last run as a
+            /// dict: `currents` (bins by samples, in nA), `edges` (µm) and
+            /// `unassigned`, the compartments outside them
+            fn binned_membrane_current<'py>(
+                &self,
+                py: Python<'py>,
+                axis: usize,
+                bin_edges: Vec<f64>,
+            ) -> PyResult<Bound<'py, PyDict>> {
+                let binned = crate::analysis::binned_membrane_current(
+                    self.last()?,
+                    &self.compartments,
+                    axis,
+                    &bin_edges,
+                )
+                .map_err(invalid_argument)?;
+                let samples = binned.currents.first().map_or(0, Vec::len);
+                let currents = Array2::from_shape_fn((binned.currents.len(), samples), |(k, s)| {
+                    binned.currents[k][s]
+                });
+                let dict = PyDict::new(py);
+                dict.set_item("currents", currents.into_pyarray(py))?;
+                dict.set_item("edges", PyArray1::from_vec(py, binned.edges))?;
+                dict.set_item("unassigned", binned.unassigned)?;
+                Ok(dict)
+            }
+
+            /// Has `run` record `path` from `pre_ms` before to `post_ms`
+            /// after every upward crossing of `threshold` mV by compartment
+            /// `source`, into `snippets`. Overlapping windows merge into
+            /// one snippet unless `merge` is False.
+            #[pyo3(signature = (path, source, threshold, pre_ms, post_ms, merge=true))]
+            fn record_around_events(
+                &mut self,
+                path: &str,
+                source: usize,
+                threshold: f64,
+                pre_ms: f64,
+                post_ms: f64,
+                merge: bool,
+            ) -> PyResult<()> {
+                use crate::recording::{AroundEvents, Overlap, TriggeredProbe};
+                let window = AroundEvents {
+                    pre_ms,
+                    post_ms,
+                    overlap: if merge {
+                        Overlap::Merge
+                    } else {
+                        Overlap::Separate
+                    },
+                };
+                self.simulation()?
+                    .record_around_events(TriggeredProbe {
+                        path: path.to_owned(),
+                        source,
+                        threshold,
+                        window,
+                    })
+                    .map_err(PyErr::from)
+            }
+        }
+
+        /// `store::ResultStore` from Python: runs go in from a session and
+        /// come back as the dicts `run` returns. Parameters are dicts of
+        /// numbers by name.
+        #[pyclass(name = "ResultStore", frozen)]
+        struct PyResultStore {
+            store: crate::store::ResultStore,
+        }
+
+        #[pymethods]
+        impl PyResultStore {
+            /// Opens or creates the store at `path`
+            #[new]
+            fn new(path: std::path::PathBuf) -> PyResult<Self> {
+                let store = crate::store::ResultStore::open(path)?;
+                Ok(PyResultStore { store })
+            }
+
+            /// Stores the last run of `session` and returns its run id
+            fn append(
+                &self,
+                tag: &str,
+                params: &Bound<'_, PyDict>,
+                session: PyRef<'_, PySimulationSession>,
+            ) -> PyResult<u64> {
+                let mut set = crate::sweep::ParamSet {
+                    names: Vec::new(),
+                    values: Vec::new(),
+                };
+                for (name, value) in params.iter() {
+                    set.names.push(name.extract()?);
+                    set.values.push(value.extract()?);
+                }
+                let result = session.last()?;
+                self.store
+                    .append(tag, &set, result)
+                    .map(|id| id.0)
+                    .map_err(PyErr::from)
+            }
+
+            /// Ids of the runs tagged `tag`, if given, whose parameters
+            /// match `params`: each a value, or a (low, high) range both
+            /// included
+            #[pyo3(signature = (tag=None, params=None))]
+            fn query(
+                &self,
+                tag: Option<&str>,
+                params: Option<&Bound<'_, PyDict>>,
+            ) -> PyResult<Vec<u64>> {
+                let mut ranges = Vec::new();
+                for (name, value) in params.into_iter().flat_map(|p| p.iter()) {
+                    let name: String = name.extract()?;
+                    let range = match value.extract::<(f64, f64)>() {
+                        Ok(range) => range,
+                        Err(_) => {
+                            let v: f64 = value.extract()?;
+                            (v, v)
+                        }
+                    };
+                    ranges.push((name, range));
+                }
+                let ids = self.store.query(|run| {
+                    tag.is_none_or(|t| run.tag == t)
+                        && ranges.iter().all(|(name, (low, high))| {
+                            run.params
+                                .get(name)
+                                .is_some_and(|v| (*low..=*high).contains(&v))
+                        })
+                });
+                Ok(ids.into_iter().map(|id| id.0).collect())
+            }
+
+            /// Run `run_id` as the dict `run` returned
+            fn load<'py>(&self, py: Python<'py>, run_id: u64) -> PyResult<Bound<'py, PyDict>> {
+                let result = self.store.load(crate::store::RunId(run_id))?;
+                results(py, &result)
+            }
+        }
+
+        /// `sweep::SweepRunner` over a copy of the `Session` model of
+        /// `morphology`. `params` are dicts of `name`, `target` ("model",
+        /// the default, or "protocol"), `low`, `high`, `points` (1) and
+        /// `scale` ("linear" or "log"). Each set runs `steps` steps with the
+        /// stimuli `protocol(params)` returns, as for `run`, and is scored
+        /// by `objective(params, results)`; `params` are dicts by name.
+        /// Grid sweeps unless `samples` sets are drawn from `seed`. Returns
+        /// (params, score) pairs best first, and writes the table to `csv`
+        /// if given. Errors raised by the callables propagate.
+        #[pyfunction]
+        #[pyo3(signature = (
+            morphology, params, steps, protocol, objective, dt=0.025, mechanism="hh",
+            samples=None, seed=0, threads=0, csv=None
+        ))]
+        #[allow(clippy::too_many_arguments)]
+        fn sweep<'py>(
+            py: Python<'py>,
+            morphology: PyRef<'_, Morphology>,
+            params: Vec<Bound<'py, PyDict>>,
+            steps: usize,
+            protocol: Py<PyAny>,
+            objective: Py<PyAny>,
+            dt: f64,
+            mechanism: &str,
+            samples: Option<usize>,
+            seed: u64,
+            threads: usize,
+            csv: Option<std::path::PathBuf>,
+        ) -> PyResult<Vec<(Bound<'py, PyDict>, f64)>> {
+            use crate::sweep::{Param, ParamSet, ParamSpace, ParamTarget, Scale, SweepRunner};
+            use std::sync::Mutex;
+
+            let mut space = ParamSpace::new();
+            for param in &params {
+                let item =
+                    |key: &str| -> PyResult<Option<Bound<'py, PyAny>>> { param.get_item(key) };
+                let required = |key: &str| -> PyResult<Bound<'py, PyAny>> {
+                    item(key)?.ok_or_else(|| {
+                        invalid_argument(format!("Sweep parameter without '{}'", key))
+                    })
+                };
+                let name: String = required("name")?.extract()?;
+                let target = match item("target")?.map(|t| t.extract::<String>()).transpose()? {
+                    None => ParamTarget::Model,
+                    Some(t) if t == "model" => ParamTarget::Model,
+                    Some(t) if t == "protocol" => ParamTarget::Protocol,
+                    Some(t) => {
+                        return Err(invalid_argument(format!(
+                            "Unknown target '{}'; use model or protocol",
+                            t
+                        )));
+                    }
+                };
+                let scale = match item("scale")?.map(|t| t.extract::<String>()).transpose()? {
+                    None => Scale::Linear,
+                    Some(t) if t == "linear" => Scale::Linear,
+                    Some(t) if t == "log" => Scale::Log,
+                    Some(t) => {
+                        return Err(invalid_argument(format!(
+                            "Unknown scale '{}'; use linear or log",
+                            t
+                        )));
+                    }
+                };
+                let points = item("points")?.map(|p| p.extract()).transpose()?;
+                space
+                    .add(Param {
+                        name,
+                        target,
+                        low: required("low")?.extract()?,
+                        high: required("high")?.extract()?,
+                        points: points.unwrap_or(1),
+                        scale,
+                    })
+                    .map_err(invalid_argument)?;
+            }
+            let runner = match samples {
+                Some(n) => SweepRunner::random(space, n, seed),
+                None => SweepRunner::grid(space),
+            }
+            .with_threads(threads);
+            let compartments = model(&morphology, mechanism)?;
+
+            // The first error a callable raised, reported once the sweep
+            // is over
+            let raised: Mutex<Option<PyErr>> = Mutex::new(None);
+            let keep = |err: PyErr| {
+                raised
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .get_or_insert(err);
+            };
+            let as_dict = |py: Python<'_>, set: &ParamSet| -> PyResult<Py<PyDict>> {
+                let dict = PyDict::new(py);
+                for (name, value) in set.names.iter().zip(&set.values) {
+                    dict.set_item(name, value)?;
+                }
+                Ok(dict.unbind())
+            };
+            let table = py.detach(|| {
+                runner.run(
+                    &compartments,
+                    dt,
+                    |set, simulation| {
+                        let stimuli = Python::attach(|py| {
+                            protocol
+                                .call1(py, (as_dict(py, set)?,))?
+                                .extract::<std::collections::HashMap<usize, Vec<f64>>>(py)
+                        });
+                        let stimuli: Vec<(usize, Vec<f64>)> = match stimuli {
+                            Ok(stimuli) => stimuli.into_iter().collect(),
+                            Err(err) => {
+                                keep(err);
+                                return Err(crate::solver::SimError::InvalidInput {
+                                    reason: "protocol raised".to_owned(),
+                                });
+                            }
+                        };
+                        simulation.run(steps, &stimuli)
+                    },
+                    |set, result| {
+                        let score = Python::attach(|py| {
+                            objective
+                                .call1(py, (as_dict(py, set)?, results(py, result)?))?
+                                .extract::<f64>(py)
+                        });
+                        score.unwrap_or_else(|err| {
+                            keep(err);
+                            f64::NAN
+                        })
+                    },
+                )
+            });
+            if let Some(err) = raised.into_inner().unwrap_or_else(|e| e.into_inner()) {
+                return Err(err);
+            }
+            let table = table.map_err(invalid_argument)?;
+            if let Some(path) = csv {
+                table
+                    .write_csv(path, crate::swc_reader::ConflictPolicy::Overwrite)
+                    .map_err(invalid_argument)?;
+            }
+            table
+                .rows
+                .iter()
+                .map(|row| {
+                    let dict = PyDict::new(py);
+                    for (name, value) in table.names.iter().zip(&row.values) {
+                        dict.set_item(name, value)?;
+                    }
+                    Ok((dict, row.score))
+                })
+                .collect()
+        }
+
+        /// A session on `morphology` as it stands, see `Session` for the
+        /// model
+        #[pyfunction]
+        #[pyo3(signature = (morphology, dt=0.025, mechanism="hh"))]
+        fn session(
+            morphology: PyRef<'_, Morphology>,
+            dt: f64,
+            mechanism: &str,
+        ) -> PyResult<PySimulationSession> {
+            let compartments = model(&morphology, mechanism)?;
+            let simulation = crate::solver::Simulation::new(&compartments, dt)?;
+            Ok(PySimulationSession {
+                session: SimulationSession::new(simulation),
+                compartments,
+            })
+        }
+    }
+
     /// Formats the sum of two numbers as string.
     #[pyfunction]
     fn sum_as_string(a: usize, b: usize) -> PyResult<String> {
@@ -0,0 +1,272 @@
+//! What produced a result, for tracking down differences between machines.
+//!
+//! `BuildInfo` is fixed when the crate is compiled: version, commit, enabled
+//! features, target and the CPU features the code was compiled for.
+//! `RuntimeInfo` is captured when a run starts: the solver, whether its SIMD
+//! path ran and whether the floating-point unit flushes subnormals to zero,
+//! which some libraries loaded into the same process switch on behind
+//! everyone's back.
+//!
+//! A `Manifest` holds both and round-trips through `to_text`/`parse`, one
+//! `key value` per line. `check_compatibility` compares a recorded manifest
+//! with the running build and warns about every difference known to change
+//! numbers.
+
+use std::collections::HashMap;
+use std::hint::black_box;
+
+use log::warn;
+
+/// A release whose changes alter numerical results; results recorded by an
+/// earlier version may not be reproduced exactly by this or a later one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NumericsChange {
+    pub version: &'static str,
+    pub description: &'static str,
+}
+
+/// Releases that changed numbers, oldest first. Add a row with every
+/// change to a solver default or anything else that moves results.
+pub const NUMERICS_CHANGES: &[NumericsChange] = &[];
+
+/// Cargo features that exist, in the order they are listed
+const FEATURES: &[(&str, bool)] = &[("python", cfg!(feature = "python"))];
+
+/// CPU features the code may have been compiled to use
+const TARGET_FEATURES: &[(&str, bool)] = &[
+    ("sse2", cfg!(target_feature = "sse2")),
+    ("avx", cfg!(target_feature = "avx")),
+    ("avx2", cfg!(target_feature = "avx2")),
+    ("fma", cfg!(target_feature = "fma")),
+    ("neon", cfg!(target_feature = "neon")),
+];
+
+fn enabled(table: &[(&str, bool)]) -> Vec<String> {
+    table
+        .iter()
+        .filter(|(_, on)| *on)
+        .map(|(name, _)| (*name).to_owned())
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildInfo {
+    pub version: String,
+    /// With a `-dirty` suffix if the tree had uncommitted changes, or
+    /// `unknown` if built outside a git checkout
+    pub git_commit: String,
+    pub features: Vec<String>,
+    pub target_arch: String,
+    pub target_os: String,
+    /// `debug` or `release`
+    pub profile: String,
+    pub target_features: Vec<String>,
+}
+
+impl BuildInfo {
+    /// The build this code is part of
+    pub fn current() -> BuildInfo {
+        BuildInfo {
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            git_commit: env!("COMPARTMENT_RS_GIT_COMMIT").to_owned(),
+            features: enabled(FEATURES),
+            target_arch: std::env::consts::ARCH.to_owned(),
+            target_os: std::env::consts::OS.to_owned(),
+            profile: env!("COMPARTMENT_RS_PROFILE").to_owned(),
+            target_features: enabled(TARGET_FEATURES),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuntimeInfo {
+    /// Name of the integration method, e.g. `backward_euler`
+    pub solver: String,
+    /// Whether the solver's SIMD path ran
+    pub simd: bool,
+    /// Subnormal results are flushed to zero
+    pub flush_to_zero: bool,
+    /// Subnormal inputs are read as zero
+    pub denormals_are_zero: bool,
+}
+
+impl RuntimeInfo {
+    /// The floating-point environment right now, probed by computing with
+    /// subnormals, for a run with `solver` that did or did not use `simd`
+    pub fn capture(solver: &str, simd: bool) -> RuntimeInfo {
+        let smallest_normal = black_box(f64::MIN_POSITIVE);
+        let subnormal = black_box(f64::MIN_POSITIVE / 4.0);
+        RuntimeInfo {
+            solver: solver.to_owned(),
+            simd,
+            flush_to_zero: smallest_normal / black_box(2.0) == 0.0,
+            denormals_are_zero: subnormal * black_box(4.0) == 0.0,
+        }
+    }
+}
+
+/// Everything recorded about how a result was produced
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    pub build: BuildInfo,
+    pub runtime: RuntimeInfo,
+}
+
+impl Manifest {
+    /// The current build, with the floating-point environment as it is now
+    pub fn capture(solver: &str, simd: bool) -> Manifest {
+        Manifest {
+            build: BuildInfo::current(),
+            runtime: RuntimeInfo::capture(solver, simd),
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        let (b, r) = (&self.build, &self.runtime);
+        let lines = [
+            ("manifest", "1".to_owned()),
+            ("version", b.version.clone()),
+            ("git_commit", b.git_commit.clone()),
+            ("features", b.features.join(" ")),
+            ("target_arch", b.target_arch.clone()),
+            ("target_os", b.target_os.clone()),
+            ("profile", b.profile.clone()),
+            ("target_features", b.target_features.join(" ")),
+            ("solver", r.solver.clone()),
+            ("simd", r.simd.to_string()),
+            ("flush_to_zero", r.flush_to_zero.to_string()),
+            ("denormals_are_zero", r.denormals_are_zero.to_string()),
+        ];
+        let mut text = String::new();
+        for (key, value) in lines {
+            // An empty list leaves the key on its own
+            text.push_str([key, &value].join(" ").trim_end());
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Reads back the output of `to_text`
+    pub fn parse(text: &str) -> Result<Manifest, String> {
+        let mut entries = HashMap::new();
+        for line in text.lines().filter(|l| !l.trim().is_empty()) {
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            if entries.insert(key, value.trim()).is_some() {
+                return Err(format!("'{}' appears twice", key));
+            }
+        }
+        if entries.get("manifest") != Some(&"1") {
+            return Err("Not a version 1 manifest".to_owned());
+        }
+        let get = |key: &str| {
+            entries
+                .get(key)
+                .map(|v| (*v).to_owned())
+                .ok_or_else(|| format!("Manifest has no '{}'", key))
+        };
+        let list = |key: &str| get(key).map(|v| v.split_whitespace().map(str::to_owned).collect());
+        let flag = |key: &str| {
+            get(key)?
+                .parse::<bool>()
+                .map_err(|_| format!("'{}' must be true or false", key))
+        };
+        Ok(Manifest {
+            build: BuildInfo {
+                version: get("version")?,
+                git_commit: get("git_commit")?,
+                features: list("features")?,
+                target_arch: get("target_arch")?,
+                target_os: get("target_os")?,
+                profile: get("profile")?,
+                target_features: list("target_features")?,
+            },
+            runtime: RuntimeInfo {
+                solver: get("solver")?,
+                simd: flag("simd")?,
+                flush_to_zero: flag("flush_to_zero")?,
+                denormals_are_zero: flag("denormals_are_zero")?,
+            },
+        })
+    }
+
+    /// Ways in which `current` may not reproduce the numbers this manifest
+    /// recorded, given the releases in `changes` that moved results
+    pub fn numerics_differences(
+        &self,
+        current: &Manifest,
+        changes: &[NumericsChange],
+    ) -> Vec<String> {
+        let mut differences = Vec::new();
+        let (was, now) = (&self.build, &current.build);
+        if was.features != now.features {
+            differences.push(format!(
+                "Recorded with features [{}], running with [{}]",
+                was.features.join(", "),
+                now.features.join(", ")
+            ));
+        }
+        if was.target_arch != now.target_arch || was.target_os != now.target_os {
+            differences.push(format!(
+                "Recorded on {}-{}, running on {}-{}; libm results may differ in the last bits",
+                was.target_arch, was.target_os, now.target_arch, now.target_os
+            ));
+        }
+        let (was, now) = (&self.runtime, &current.runtime);
+        if was.solver != now.solver {
+            differences.push(format!(
+                "Recorded with solver {}, running {}",
+                was.solver, now.solver
+            ));
+        }
+        if was.simd != now.simd {
+            differences.push(format!(
+                "Recorded with the SIMD path {}, running with it {}",
+                on_off(was.simd),
+                on_off(now.simd)
+            ));
+        }
+        if (was.flush_to_zero, was.denormals_are_zero)
+            != (now.flush_to_zero, now.denormals_are_zero)
+        {
+            differences.push(format!(
+                "Recorded with subnormal flushing {}, running with it {}",
+                on_off(was.flush_to_zero || was.denormals_are_zero),
+                on_off(now.flush_to_zero || now.denormals_are_zero)
+            ));
+        }
+        let recorded = version(&self.build.version);
+        let running = version(&current.build.version);
+        for change in changes {
+            let at = version(change.version);
+            if recorded < at && at <= running {
+                differences.push(format!(
+                    "Recorded with {}, before {} changed results: {}",
+                    self.build.version, change.version, change.description
+                ));
+            }
+        }
+        differences
+    }
+}
+
+fn on_off(on: bool) -> &'static str {
+    if on { "on" } else { "off" }
+}
+
+/// `major.minor.patch`, missing or unreadable parts counting as 0
+fn version(v: &str) -> [u64; 3] {
+    let mut parts = v.split(['.', '-', '+']).map(|p| p.parse().unwrap_or(0));
+    [(); 3].map(|_| parts.next().unwrap_or(0))
+}
+
+/// Warns about, and returns, every way the running build may not reproduce
+/// the results `recorded` describes. Loading is not a run, so the recorded
+/// solver and SIMD path are taken as the ones that would be used again.
+pub fn check_compatibility(recorded: &Manifest) -> Vec<String> {
+    let current = Manifest::capture(&recorded.runtime.solver, recorded.runtime.simd);
+    let differences = recorded.numerics_differences(&current, NUMERICS_CHANGES);
+    for difference in &differences {
+        warn!("{}", difference);
+    }
+    differences
+}
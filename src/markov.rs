@@ -0,0 +1,294 @@
+//! Channels published as Markov kinetic schemes: a set of states with
+//! voltage-dependent transition rates, conducting in proportion to the
+//! probability of being in an open state. Voltages are in mV, rates in 1/ms.
+
+/// Voltage dependence of one transition rate
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RateFn {
+    /// `a * exp(b * v)`
+    Exp { a: f64, b: f64 },
+    /// `a / (1 + exp((v - v_half) / k))`; negative `k` rises with voltage
+    Sigmoid { a: f64, v_half: f64, k: f64 },
+    /// `max(a + b * v, 0)`
+    Linear { a: f64, b: f64 },
+}
+
+impl RateFn {
+    pub fn exp(a: f64, b: f64) -> Self {
+        RateFn::Exp { a, b }
+    }
+
+    pub fn sigmoid(a: f64, v_half: f64, k: f64) -> Self {
+        RateFn::Sigmoid { a, v_half, k }
+    }
+
+    pub fn linear(a: f64, b: f64) -> Self {
+        RateFn::Linear { a, b }
+    }
+
+    pub fn constant(rate: f64) -> Self {
+        RateFn::Linear { a: rate, b: 0.0 }
+    }
+
+    pub fn rate(&self, v: f64) -> f64 {
+        match *self {
+            RateFn::Exp { a, b } => a * (b * v).exp(),
+            RateFn::Sigmoid { a, v_half, k } => a / (1.0 + ((v - v_half) / k).exp()),
+            RateFn::Linear { a, b } => (a + b * v).max(0.0),
+        }
+    }
+}
+
+/// States and transitions of a kinetic scheme, built up with
+/// `MarkovScheme::new().state("C").open_state("O").rate("C", "O", ...)`.
+/// Mistakes such as unknown state names are reported by `MarkovChannel::new`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MarkovScheme {
+    states: Vec<String>,
+    open: Vec<bool>,
+    rates: Vec<(String, String, RateFn)>,
+}
+
+impl MarkovScheme {
+    pub fn new() -> Self {
+        MarkovScheme::default()
+    }
+
+    /// Adds a non-conducting state
+    pub fn state(mut self, name: &str) -> Self {
+        self.states.push(name.to_owned());
+        self.open.push(false);
+        self
+    }
+
+    /// Adds a conducting state
+    pub fn open_state(mut self, name: &str) -> Self {
+        self.states.push(name.to_owned());
+        self.open.push(true);
+        self
+    }
+
+    /// Adds the transition `from -> to`
+    pub fn rate(mut self, from: &str, to: &str, rate: RateFn) -> Self {
+        self.rates.push((from.to_owned(), to.to_owned(), rate));
+        self
+    }
+
+    pub fn states(&self) -> &[String] {
+        &self.states
+    }
+
+    fn validate(&self) -> Result<Vec<(usize, usize, RateFn)>, String> {
+        if self.states.is_empty() {
+            return Err("Markov scheme has no states".to_owned());
+        }
+        if !self.open.contains(&true) {
+            return Err("Markov scheme has no open state".to_owned());
+        }
+        for (i, name) in self.states.iter().enumerate() {
+            if self.states[..i].contains(name) {
+                return Err(format!("Duplicate state '{}'", name));
+            }
+        }
+        let index = |name: &str| {
+            self.states
+                .iter()
+                .position(|s| s == name)
+                .ok_or_else(|| format!("Unknown state '{}'", name))
+        };
+        self.rates
+            .iter()
+            .map(|(from, to, rate)| {
+                let (i, j) = (index(from)?, index(to)?);
+                if i == j {
+                    return Err(format!("Transition from '{}' to itself", from));
+                }
+                Ok((i, j, *rate))
+            })
+            .collect()
+    }
+}
+
+/// A kinetic scheme with its state occupancies, for one compartment
+#[derive(Debug, Clone)]
+pub struct MarkovChannel {
+    scheme: MarkovScheme,
+    transitions: Vec<(usize, usize, RateFn)>,
+    /// Occupancy of each state, in the order the states were declared
+    pub probabilities: Vec<f64>,
+    /// Maximal conductance density, reached with every channel open
+    pub conductance: f64,
+    pub reversal: f64,
+}
+
+type Matrix = Vec<Vec<f64>>;
+
+impl MarkovChannel {
+    /// Starts the channel at steady state for `v`
+    pub fn new(
+        scheme: MarkovScheme,
+        conductance: f64,
+        reversal: f64,
+        v: f64,
+    ) -> Result<Self, String> {
+        let transitions = scheme.validate()?;
+        let mut channel = MarkovChannel {
+            probabilities: Vec::new(),
+            scheme,
+            transitions,
+            conductance,
+            reversal,
+        };
+        channel.probabilities = channel.steady_state(v)?;
+        Ok(channel)
+    }
+
+    pub fn scheme(&self) -> &MarkovScheme {
+        &self.scheme
+    }
+
+    /// Generator matrix at `v`: `dp/dt = Q p`, so every column sums to zero
+    fn generator(&self, v: f64) -> Matrix {
+        let n = self.scheme.states.len();
+        let mut q = vec![vec![0.0; n]; n];
+        for &(from, to, rate) in &self.transitions {
+            let r = rate.rate(v);
+            q[to][from] += r;
+            q[from][from] -= r;
+        }
+        q
+    }
+
+    /// Occupancies the scheme settles into when held at `v`. Fails if the
+    /// scheme falls apart into pieces that do not exchange probability.
+    pub fn steady_state(&self, v: f64) -> Result<Vec<f64>, String> {
+        let mut q = self.generator(v);
+        let n = q.len();
+        // Q p = 0 has a one-dimensional solution space; pin it down by
+        // swapping the last equation for sum(p) = 1
+        q[n - 1] = vec![1.0; n];
+        let mut rhs = vec![0.0; n];
+        rhs[n - 1] = 1.0;
+        solve(q, rhs).ok_or_else(|| format!("Markov scheme has no unique steady state at {} mV", v))
+    }
+
+    /// Advances the occupancies by `dt` at a voltage held at `v`, exactly for
+    /// piecewise constant voltage. The matrix exponential stays stable however
+    /// stiff the scheme is.
+    pub fn step(&mut self, v: f64, dt: f64) {
+        let q = self.generator(v);
+        let propagator = expm(&q, dt);
+        let mut next: Vec<f64> = propagator
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .zip(&self.probabilities)
+                    .map(|(a, p)| a * p)
+                    .sum()
+            })
+            .collect();
+        // Round-off can push tiny occupancies below zero or let the total
+        // drift; neither may accumulate over a long run
+        for p in next.iter_mut() {
+            *p = p.max(0.0);
+        }
+        let total: f64 = next.iter().sum();
+        for p in next.iter_mut() {
+            *p /= total;
+        }
+        self.probabilities = next;
+    }
+
+    /// Total probability of the open states
+    pub fn open_probability(&self) -> f64 {
+        self.probabilities
+            .iter()
+            .zip(&self.scheme.open)
+            .filter(|(_, open)| **open)
+            .map(|(p, _)| p)
+            .sum()
+    }
+
+    /// Current density at membrane potential `v`, outward positive
+    pub fn current(&self, v: f64) -> f64 {
+        self.conductance * self.open_probability() * (v - self.reversal)
+    }
+}
+
+/// `exp(q * dt)` by scaling and squaring a Taylor series
+fn expm(q: &Matrix, dt: f64) -> Matrix {
+    let n = q.len();
+    let norm = (0..n)
+        .map(|j| (0..n).map(|i| (q[i][j] * dt).abs()).sum::<f64>())
+        .fold(0.0, f64::max);
+    let squarings = if norm > 0.5 {
+        (norm / 0.5).log2().ceil() as i32
+    } else {
+        0
+    };
+    let scale = dt / 2f64.powi(squarings);
+    let a: Matrix = q
+        .iter()
+        .map(|row| row.iter().map(|v| v * scale).collect())
+        .collect();
+
+    let identity: Matrix = (0..n)
+        .map(|i| (0..n).map(|j| if i == j { 1.0 } else { 0.0 }).collect())
+        .collect();
+    let mut result = identity.clone();
+    let mut term = identity;
+    // With the norm below 0.5, 20 terms are well past double precision
+    for k in 1..=20 {
+        term = matmul(&term, &a)
+            .into_iter()
+            .map(|row| row.into_iter().map(|v| v / k as f64).collect())
+            .collect();
+        for (r, t) in result.iter_mut().zip(&term) {
+            for (x, y) in r.iter_mut().zip(t) {
+                *x += y;
+            }
+        }
+    }
+    for _ in 0..squarings {
+        result = matmul(&result, &result);
+    }
+    result
+}
+
+fn matmul(a: &Matrix, b: &Matrix) -> Matrix {
+    let n = a.len();
+    (0..n)
+        .map(|i| {
+            (0..n)
+                .map(|j| (0..n).map(|k| a[i][k] * b[k][j]).sum())
+                .collect()
+        })
+        .collect()
+}
+
+/// Gaussian elimination with partial pivoting; None if `a` is singular
+#[allow(clippy::needless_range_loop)]
+pub(crate) fn solve(mut a: Matrix, mut b: Vec<f64>) -> Option<Vec<f64>> {
+    let n = b.len();
+    for col in 0..n {
+        let pivot = (col..n).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+        if a[pivot][col].abs() < 1e-14 {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+        for row in col + 1..n {
+            let factor = a[row][col] / a[col][col];
+            for k in col..n {
+                a[row][k] -= factor * a[col][k];
+            }
+            b[row] -= factor * b[col];
+        }
+    }
+    let mut x = vec![0.0; n];
+    for row in (0..n).rev() {
+        let tail: f64 = (row + 1..n).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - tail) / a[row][row];
+    }
+    Some(x)
+}
@@ -0,0 +1,293 @@
+//! Triangle meshes of a morphology for rendering, e.g. in Blender or
+//! MeshLab. Every compartment becomes a tube of its own diameter along its
+//! centerline, with the cross sections laid out in the compartment's frame
+//! so the tube does not twist. A point soma becomes a sphere. Tubes overlap
+//! at branch points instead of being welded together.
+
+use std::f64::consts::PI;
+use std::path::Path;
+
+use crate::compartments::Compartments;
+use crate::error::SwcError;
+use crate::geometry::{self, Vec3};
+use crate::swc_reader::ConflictPolicy;
+use crate::write;
+
+/// Resolution of `to_tube_mesh`
+#[derive(Debug, Clone, PartialEq)]
+pub struct MeshOptions {
+    /// Vertices around each cross section, at least 3
+    pub radial_segments: usize,
+    /// Longest stretch of a tube between two cross sections, in um. Each
+    /// compartment is cut into equal pieces no longer than this.
+    pub max_segment_length: f64,
+    /// Radii below this are drawn at this size, so zero-radius nodes do not
+    /// collapse into degenerate faces
+    pub min_radius: f64,
+}
+
+impl Default for MeshOptions {
+    fn default() -> Self {
+        MeshOptions {
+            radial_segments: 12,
+            max_segment_length: 5.0,
+            min_radius: 0.01,
+        }
+    }
+}
+
+/// Triangle mesh with one unit normal per vertex. Faces wind
+/// counter-clockwise seen from outside.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Mesh {
+    pub vertices: Vec<[f64; 3]>,
+    pub normals: Vec<[f64; 3]>,
+    pub faces: Vec<[usize; 3]>,
+    /// Per vertex, the scalar of the compartment it belongs to; empty
+    /// when no scalars were given
+    pub scalars: Vec<f64>,
+}
+
+impl Mesh {
+    fn push(&mut self, vertex: Vec3, normal: Vec3, scalar: Option<f64>) -> usize {
+        self.vertices.push(vertex);
+        self.normals.push(normal);
+        self.scalars.extend(scalar);
+        self.vertices.len() - 1
+    }
+
+    /// Wavefront OBJ text with vertices, normals and faces. OBJ has no
+    /// place for the scalars; use PLY to keep them.
+    pub fn to_obj(&self) -> String {
+        let mut text = String::new();
+        for v in &self.vertices {
+            text.push_str(&format!("v {} {} {}\n", v[0], v[1], v[2]));
+        }
+        for n in &self.normals {
+            text.push_str(&format!("vn {} {} {}\n", n[0], n[1], n[2]));
+        }
+        for f in &self.faces {
+            let (a, b, c) = (f[0] + 1, f[1] + 1, f[2] + 1);
+            text.push_str(&format!("f {a}//{a} {b}//{b} {c}//{c}\n"));
+        }
+        text
+    }
+
+    /// Binary little-endian PLY. Vertices carry `x y z nx ny nz` as floats,
+    /// plus a float `scalar` when the mesh has scalars.
+    pub fn to_ply(&self) -> Vec<u8> {
+        let scalar = if self.scalars.is_empty() {
+            ""
+        } else {
+            "property float scalar\n"
+        };
+        let header = format!(
+            "ply\nformat binary_little_endian 1.0\n\
+             element vertex {}\n\
+             property float x\nproperty float y\nproperty float z\n\
+             property float nx\nproperty float ny\nproperty float nz\n\
+             {}element face {}\n\
+             property list uchar uint vertex_indices\nend_header\n",
+            self.vertices.len(),
+            scalar,
+            self.faces.len()
+        );
+        let mut bytes = header.into_bytes();
+        for (i, (v, n)) in self.vertices.iter().zip(&self.normals).enumerate() {
+            for x in v.iter().chain(n).chain(self.scalars.get(i)) {
+                bytes.extend((*x as f32).to_le_bytes());
+            }
+        }
+        for f in &self.faces {
+            bytes.push(3);
+            for &i in f {
+                bytes.extend((i as u32).to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    /// Writes `to_obj` to `path` atomically
+    pub fn write_obj(
+        &self,
+        path: impl AsRef<Path>,
+        policy: ConflictPolicy,
+    ) -> Result<(), SwcError> {
+        write::write_atomic(path.as_ref(), self.to_obj().as_bytes(), policy)
+    }
+
+    /// Writes `to_ply` to `path` atomically
+    pub fn write_ply(
+        &self,
+        path: impl AsRef<Path>,
+        policy: ConflictPolicy,
+    ) -> Result<(), SwcError> {
+        write::write_atomic(path.as_ref(), &self.to_ply(), policy)
+    }
+}
+
+/// Tube mesh of every compartment. `scalars`, when given, is indexed like
+/// `components` (e.g. from `parameter_map`) and copied onto each vertex of
+/// the compartment's tube for color mapping.
+///
+/// Zero-length compartments are skipped, except a point soma, which is
+/// drawn as a sphere. Tubes are capped where the cell ends: at tips, and
+/// at the start of a tube hanging off the root.
+pub fn to_tube_mesh(
+    compartments: &Compartments,
+    scalars: Option<&[f64]>,
+    options: &MeshOptions,
+) -> Result<Mesh, String> {
+    let m = options.radial_segments;
+    if m < 3 {
+        return Err(format!("Need at least 3 radial segments, got {}", m));
+    }
+    if !(options.max_segment_length > 0.0 && options.max_segment_length.is_finite()) {
+        return Err(format!(
+            "Segment length must be positive, got {}",
+            options.max_segment_length
+        ));
+    }
+    if let Some(scalars) = scalars
+        && scalars.len() != compartments.components.len()
+    {
+        return Err(format!(
+            "Expected {} scalars, one per compartment, got {}",
+            compartments.components.len(),
+            scalars.len()
+        ));
+    }
+
+    let mut mesh = Mesh::default();
+    for (idx, c) in compartments.components.iter().enumerate().skip(1) {
+        let scalar = scalars.map(|s| s[idx]);
+        let radius = (c.diam / 2.0).max(options.min_radius);
+        if !(c.length > 0.0 && c.length.is_finite()) {
+            if c.parent_idxs.first() == Some(&0) {
+                sphere(&mut mesh, c.proximal, radius, m, scalar);
+            }
+            continue;
+        }
+        let segments = (c.length / options.max_segment_length).ceil().max(1.0) as usize;
+        let frame = compartments.local_frame(idx, 0.0)?;
+        let around: Vec<Vec3> = (0..m)
+            .map(|j| {
+                let phi = 2.0 * PI * j as f64 / m as f64;
+                geometry::add(
+                    geometry::scale(frame.normal, phi.cos()),
+                    geometry::scale(frame.binormal, phi.sin()),
+                )
+            })
+            .collect();
+        let axis = geometry::sub(c.distal, c.proximal);
+        let centre = |i: usize| {
+            geometry::add(
+                c.proximal,
+                geometry::scale(axis, i as f64 / segments as f64),
+            )
+        };
+
+        let first = mesh.vertices.len();
+        for i in 0..=segments {
+            for r in &around {
+                mesh.push(
+                    geometry::add(centre(i), geometry::scale(*r, radius)),
+                    *r,
+                    scalar,
+                );
+            }
+        }
+        let at = |i: usize, j: usize| first + i * m + j % m;
+        for i in 0..segments {
+            for j in 0..m {
+                mesh.faces.push([at(i, j), at(i, j + 1), at(i + 1, j + 1)]);
+                mesh.faces.push([at(i, j), at(i + 1, j + 1), at(i + 1, j)]);
+            }
+        }
+
+        if c.parent_idxs.first() == Some(&0) {
+            let outward = geometry::scale(frame.tangent, -1.0);
+            cap(&mut mesh, centre(0), outward, &around, radius, scalar);
+        }
+        if c.children_idxs.is_empty() {
+            cap(
+                &mut mesh,
+                centre(segments),
+                frame.tangent,
+                &around,
+                radius,
+                scalar,
+            );
+        }
+    }
+    Ok(mesh)
+}
+
+/// Flat disc closing a tube end, facing `outward`
+fn cap(
+    mesh: &mut Mesh,
+    centre: Vec3,
+    outward: Vec3,
+    around: &[Vec3],
+    radius: f64,
+    scalar: Option<f64>,
+) {
+    let m = around.len();
+    let hub = mesh.push(centre, outward, scalar);
+    for r in around {
+        mesh.push(
+            geometry::add(centre, geometry::scale(*r, radius)),
+            outward,
+            scalar,
+        );
+    }
+    // `around` turns counter-clockwise about the tube's tangent
+    let facing_tangent = geometry::dot(outward, geometry::cross(around[0], around[1])) > 0.0;
+    for j in 0..m {
+        let (a, b) = (hub + 1 + j, hub + 1 + (j + 1) % m);
+        mesh.faces.push(if facing_tangent {
+            [hub, a, b]
+        } else {
+            [hub, b, a]
+        });
+    }
+}
+
+/// UV sphere with `m` vertices per ring and `m / 2` bands from pole to pole
+fn sphere(mesh: &mut Mesh, centre: Vec3, radius: f64, m: usize, scalar: Option<f64>) {
+    let bands = (m / 2).max(2);
+    let mut point = |normal: Vec3| {
+        mesh.push(
+            geometry::add(centre, geometry::scale(normal, radius)),
+            normal,
+            scalar,
+        )
+    };
+    let top = point([0.0, 0.0, 1.0]);
+    for band in 1..bands {
+        let theta = PI * band as f64 / bands as f64;
+        for j in 0..m {
+            let phi = 2.0 * PI * j as f64 / m as f64;
+            point([
+                theta.sin() * phi.cos(),
+                theta.sin() * phi.sin(),
+                theta.cos(),
+            ]);
+        }
+    }
+    let bottom = point([0.0, 0.0, -1.0]);
+    let ring = |band: usize, j: usize| top + 1 + (band - 1) * m + j % m;
+    for j in 0..m {
+        mesh.faces.push([top, ring(1, j), ring(1, j + 1)]);
+        mesh.faces
+            .push([bottom, ring(bands - 1, j + 1), ring(bands - 1, j)]);
+    }
+    for band in 1..bands - 1 {
+        for j in 0..m {
+            mesh.faces
+                .push([ring(band, j), ring(band + 1, j), ring(band + 1, j + 1)]);
+            mesh.faces
+                .push([ring(band, j), ring(band + 1, j + 1), ring(band, j + 1)]);
+        }
+    }
+}
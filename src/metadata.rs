@@ -0,0 +1,102 @@
+//! Key-value header metadata from the standardized SWC header, e.g.
+//! `# ORIGINAL_SOURCE NeuroMorpho.Org` or `# SCALE 1.0 1.0 1.0`.
+
+use std::collections::BTreeMap;
+
+use crate::codes::Code;
+use crate::error::SwcError;
+use crate::swc_reader::format_float;
+
+/// Header metadata. Keys the reader has no special handling for end up in
+/// `other`, so nothing is lost on a read/write round trip.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[non_exhaustive]
+pub struct SwcMetadata {
+    pub original_source: Option<String>,
+    /// Per-axis coordinate scale factors
+    pub scale: Option<[f64; 3]>,
+    pub soma_type: Option<String>,
+    pub other: BTreeMap<String, String>,
+}
+
+impl SwcMetadata {
+    pub fn is_empty(&self) -> bool {
+        *self == SwcMetadata::default()
+    }
+
+    /// Picks a metadata entry out of a header comment (without the `#`).
+    /// Returns false if the comment is not a `KEY value` entry. Keys are upper
+    /// case, which keeps free text comments out.
+    pub(crate) fn parse_comment(
+        &mut self,
+        comment: &str,
+        line_no: usize,
+    ) -> Result<bool, SwcError> {
+        let comment = comment.trim();
+        let (key, value) = comment
+            .split_once(char::is_whitespace)
+            .unwrap_or((comment, ""));
+        let is_key = key.chars().any(|c| c.is_ascii_uppercase())
+            && key
+                .chars()
+                .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_');
+        if !is_key {
+            return Ok(false);
+        }
+        let value = value.trim();
+        match key {
+            "ORIGINAL_SOURCE" => self.original_source = Some(value.to_owned()),
+            "SOMA_TYPE" => self.soma_type = Some(value.to_owned()),
+            "SCALE" => {
+                let factors: Vec<f64> = value
+                    .split_whitespace()
+                    .map(str::parse)
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| malformed_scale(value, line_no))?;
+                let [x, y, z] = factors[..] else {
+                    return Err(malformed_scale(value, line_no));
+                };
+                self.scale = Some([x, y, z]);
+            }
+            _ => {
+                self.other.insert(key.to_owned(), value.to_owned());
+            }
+        }
+        Ok(true)
+    }
+
+    /// Header lines in the standard `# KEY value` format. `SCALE` is left out
+    /// when `include_scale` is false, e.g. because it was already applied.
+    pub(crate) fn to_header(&self, include_scale: bool) -> String {
+        let mut out = String::new();
+        if let Some(source) = &self.original_source {
+            out.push_str(&format!("# ORIGINAL_SOURCE {}\n", source));
+        }
+        if let (Some([x, y, z]), true) = (self.scale, include_scale) {
+            out.push_str(&format!(
+                "# SCALE {} {} {}\n",
+                format_float(x),
+                format_float(y),
+                format_float(z)
+            ));
+        }
+        if let Some(soma_type) = &self.soma_type {
+            out.push_str(&format!("# SOMA_TYPE {}\n", soma_type));
+        }
+        for (key, value) in &self.other {
+            out.push_str(&format!("# {} {}\n", key, value));
+        }
+        out
+    }
+}
+
+fn malformed_scale(value: &str, line_no: usize) -> SwcError {
+    SwcError::invalid_at(
+        Code::MalformedScale,
+        line_no,
+        format!(
+            "Malformed SCALE '{}' at line {}; expected three numbers",
+            value, line_no
+        ),
+    )
+}
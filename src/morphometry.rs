@@ -0,0 +1,220 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::conventions::Conventions;
+use crate::geometry::{self, Vec3};
+use crate::swc_reader::{Node, StructureIdentifier};
+
+/// Morphometrics over a set of nodes as returned by `swc_reader`.
+///
+/// Cheap quantities are computed on the fly; anything needing a convex hull
+/// sits behind `spatial_metrics` so callers only pay for it when asked.
+pub struct Morphometry<'a> {
+    nodes: &'a [Node],
+    id_to_idx: HashMap<u64, usize>,
+}
+
+/// Axis-aligned bounding box
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct BoundingBox {
+    pub min: [f64; 3],
+    pub max: [f64; 3],
+}
+
+impl BoundingBox {
+    pub fn extents(&self) -> [f64; 3] {
+        [
+            self.max[0] - self.min[0],
+            self.max[1] - self.min[1],
+            self.max[2] - self.min[2],
+        ]
+    }
+
+    pub fn volume(&self) -> f64 {
+        self.extents().iter().product()
+    }
+}
+
+/// Spatial envelope of an arbor.
+///
+/// For planar or collinear arbors the volumes come back as zero and
+/// `degenerate` is set rather than reporting nonsense.
+#[derive(Debug, Clone, PartialEq, Default)]
+#[non_exhaustive]
+pub struct SpatialMetrics {
+    pub bounding_box: BoundingBox,
+    /// Principal axes of the node coordinates, largest variance first
+    pub principal_axes: [[f64; 3]; 3],
+    /// Extents of the PCA-oriented bounding box along `principal_axes`
+    pub oriented_extents: [f64; 3],
+    pub oriented_volume: f64,
+    pub hull_volume: f64,
+    pub hull_area: f64,
+    pub cable_length: f64,
+    /// Cable length per unit hull volume, zero when degenerate
+    pub density: f64,
+    pub degenerate: bool,
+}
+
+impl<'a> Morphometry<'a> {
+    pub fn new(nodes: &'a [Node]) -> Self {
+        let id_to_idx = nodes
+            .iter()
+            .enumerate()
+            .map(|(idx, n)| (n.node_id, idx))
+            .collect();
+        Morphometry { nodes, id_to_idx }
+    }
+
+    fn parent_of(&self, node: &Node) -> Option<&Node> {
+        if node.parent_id == node.node_id {
+            return None;
+        }
+        self.id_to_idx
+            .get(&node.parent_id)
+            .map(|&idx| &self.nodes[idx])
+    }
+
+    /// Length of the segment from `node` back to its parent, zero for the root
+    fn segment_length(&self, node: &Node) -> f64 {
+        match self.parent_of(node) {
+            Some(parent) => geometry::norm(geometry::sub(position(node), position(parent))),
+            None => 0.0,
+        }
+    }
+
+    /// Total cable length, counting each segment towards its child node.
+    /// Bit-identical however the nodes are ordered.
+    pub fn total_length(&self) -> f64 {
+        geometry::stable_sum(self.nodes.iter().map(|n| self.segment_length(n)))
+    }
+
+    /// `total_length` under `conventions`, leaving out the segments they do
+    /// not count as cable
+    pub fn total_length_with(&self, conventions: &Conventions) -> f64 {
+        geometry::stable_sum(self.nodes.iter().map(|n| match self.parent_of(n) {
+            Some(parent)
+                if !conventions.is_cable(n.structured_identifier, parent.structured_identifier) =>
+            {
+                0.0
+            }
+            _ => self.segment_length(n),
+        }))
+    }
+
+    /// Total membrane area, treating each segment as a truncated cone
+    /// between its two end radii. Bit-identical however the nodes are ordered.
+    pub fn total_area(&self) -> f64 {
+        geometry::stable_sum(self.nodes.iter().map(|n| match self.parent_of(n) {
+            Some(parent) => {
+                let d = geometry::sub(position(n), position(parent));
+                // Not `hypot`, whose last bit varies between libms
+                let slant = (geometry::dot(d, d) + (n.radius - parent.radius).powi(2)).sqrt();
+                std::f64::consts::PI * (n.radius + parent.radius) * slant
+            }
+            None => 0.0,
+        }))
+    }
+
+    /// Most children of any one node: 2 for a strictly binary arbor, 0 for
+    /// a lone root. High values off the soma often mean skeletonization
+    /// errors, see `validation::branching_qc`.
+    pub fn max_branching_degree(&self) -> usize {
+        let mut children = vec![0usize; self.nodes.len()];
+        for node in self.nodes {
+            if let Some(parent) = self.parent_of(node) {
+                children[self.id_to_idx[&parent.node_id]] += 1;
+            }
+        }
+        children.into_iter().max().unwrap_or(0)
+    }
+
+    /// Spatial envelope metrics over the whole arbor
+    pub fn spatial_metrics(&self) -> SpatialMetrics {
+        self.spatial_metrics_where(|_| true)
+    }
+
+    /// Same as `spatial_metrics`, restricted to nodes of one structure type
+    pub fn spatial_metrics_for(&self, structure: StructureIdentifier) -> SpatialMetrics {
+        self.spatial_metrics_where(|n| n.structured_identifier == structure)
+    }
+
+    /// `spatial_metrics_for` over every structure type present in the arbor,
+    /// iterating in type order
+    pub fn spatial_metrics_by_type(&self) -> BTreeMap<StructureIdentifier, SpatialMetrics> {
+        let mut types: Vec<StructureIdentifier> =
+            self.nodes.iter().map(|n| n.structured_identifier).collect();
+        types.sort();
+        types.dedup();
+        types
+            .into_iter()
+            .map(|t| (t, self.spatial_metrics_for(t)))
+            .collect()
+    }
+
+    fn spatial_metrics_where(&self, keep: impl Fn(&Node) -> bool) -> SpatialMetrics {
+        let selected: Vec<&Node> = self.nodes.iter().filter(|n| keep(n)).collect();
+        let points: Vec<Vec3> = selected.iter().map(|n| position(n)).collect();
+        if points.is_empty() {
+            return SpatialMetrics {
+                degenerate: true,
+                ..Default::default()
+            };
+        }
+
+        let mut bounding_box = BoundingBox {
+            min: points[0],
+            max: points[0],
+        };
+        for p in &points {
+            bounding_box.min = std::array::from_fn(|k| bounding_box.min[k].min(p[k]));
+            bounding_box.max = std::array::from_fn(|k| bounding_box.max[k].max(p[k]));
+        }
+
+        let (_, principal_axes) = geometry::symmetric_eigen(geometry::covariance(&points));
+        let mut oriented_extents = [0.0; 3];
+        for (k, axis) in principal_axes.iter().enumerate() {
+            let (lo, hi) = points
+                .iter()
+                .map(|p| geometry::dot(*p, *axis))
+                .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), v| {
+                    (lo.min(v), hi.max(v))
+                });
+            oriented_extents[k] = hi - lo;
+        }
+
+        // A segment counts towards the type of its child node
+        let cable_length = geometry::stable_sum(selected.iter().map(|n| self.segment_length(n)));
+
+        let (hull_volume, hull_area, degenerate) = match geometry::convex_hull(&points) {
+            Ok(hull) => (hull.volume(&points), hull.area(&points), false),
+            Err(_) => (0.0, 0.0, true),
+        };
+
+        let oriented_volume = if degenerate {
+            0.0
+        } else {
+            oriented_extents.iter().product()
+        };
+        let density = if degenerate {
+            0.0
+        } else {
+            cable_length / hull_volume
+        };
+
+        SpatialMetrics {
+            bounding_box,
+            principal_axes,
+            oriented_extents,
+            oriented_volume,
+            hull_volume,
+            hull_area,
+            cable_length,
+            density,
+            degenerate,
+        }
+    }
+}
+
+fn position(node: &Node) -> Vec3 {
+    [node.x_pos, node.y_pos, node.z_pos]
+}
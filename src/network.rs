@@ -0,0 +1,223 @@
+//! Populations of cells sharing one morphology.
+//!
+//! A `Cell` holds its morphology behind an `Arc` and only its mechanisms,
+//! one `Channel` per compartment, as its own. `Network::add_cells` builds N
+//! cells from one template with a single copy of the geometry, so a
+//! population costs N times the mechanisms rather than N times the model.
+//! Simulation state is per run, see `Cell::simulation`.
+
+use std::collections::HashSet;
+use std::mem::size_of;
+use std::sync::Arc;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::channels::{Channel, ChannelType};
+use crate::compartments::{Compartment, Compartments};
+use crate::index_map::Attachment;
+use crate::sections::Section;
+use crate::solver::{SimError, Simulation};
+use crate::spines::Spine;
+
+/// One cell of a population: shared geometry and topology, its own
+/// mechanisms
+#[derive(Clone)]
+pub struct Cell {
+    morphology: Arc<Compartments>,
+    /// Mechanism parameters of each compartment, indexed like
+    /// `morphology().components`
+    pub channels: Vec<Channel>,
+}
+
+impl Cell {
+    /// The shared model. Its own channels are the template's; this cell's
+    /// are in `channels`.
+    pub fn morphology(&self) -> &Compartments {
+        &self.morphology
+    }
+
+    pub fn shares_morphology_with(&self, other: &Cell) -> bool {
+        Arc::ptr_eq(&self.morphology, &other.morphology)
+    }
+
+    /// A simulation of this cell with its own mechanisms
+    pub fn simulation(&self, dt: f64) -> Result<Simulation, SimError> {
+        let channels: Vec<&Channel> = self.channels.iter().collect();
+        Simulation::with_channels(&self.morphology, &channels, dt)
+    }
+
+    /// Approximate bytes this cell holds on its own, leaving out the shared
+    /// morphology
+    pub fn accounted_bytes(&self) -> usize {
+        size_of::<Cell>() + self.channels.capacity() * size_of::<Channel>()
+    }
+}
+
+impl Compartments {
+    /// A cell sharing this model's geometry and topology, starting with a
+    /// copy of its mechanisms
+    pub fn clone_shallow(self: &Arc<Self>) -> Cell {
+        Cell {
+            morphology: Arc::clone(self),
+            channels: self.components.iter().map(|c| c.channel.clone()).collect(),
+        }
+    }
+
+    /// Approximate bytes held by the model: the structs themselves and the
+    /// heap buffers they own, by capacity, without allocator overhead
+    pub fn accounted_bytes(&self) -> usize {
+        let components: usize = self
+            .components
+            .iter()
+            .map(|c| {
+                size_of::<Compartment>()
+                    + c.name.capacity()
+                    + (c.parent_idxs.capacity() + c.children_idxs.capacity()) * size_of::<u64>()
+            })
+            .sum();
+        let provenance: usize = self
+            .provenance
+            .iter()
+            .map(|p| size_of::<Vec<()>>() + std::mem::size_of_val(&p[..]))
+            .sum();
+        let sections: usize = self
+            .sections
+            .iter()
+            .map(|s| {
+                size_of::<Section>()
+                    + s.name.capacity()
+                    + s.compartments.capacity() * size_of::<usize>()
+            })
+            .sum();
+        let attachments: usize = self
+            .attachments
+            .iter()
+            .map(|a| size_of::<Attachment>() + a.name.capacity())
+            .sum();
+        let spines = self.spines.capacity() * size_of::<Spine>();
+        let index_maps = self
+            .last_index_map
+            .iter()
+            .chain([&self.index_map])
+            .map(|m| std::mem::size_of_val(m.links()))
+            .sum::<usize>();
+        size_of::<Compartments>()
+            + (self.components.capacity() - self.components.len()) * size_of::<Compartment>()
+            + components
+            + provenance
+            + sections
+            + attachments
+            + spines
+            + index_maps
+    }
+}
+
+/// Index of a cell in its `Network`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CellHandle(usize);
+
+impl CellHandle {
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+/// Per-cell spread of the leak conductance in a population
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LeakJitter {
+    /// Each cell's leak is scaled by a factor drawn uniformly from
+    /// `1 ± fraction`, the same factor for all its compartments
+    pub fraction: f64,
+    pub seed: u64,
+}
+
+#[derive(Clone, Default)]
+pub struct Network {
+    cells: Vec<Cell>,
+}
+
+impl Network {
+    pub fn new() -> Network {
+        Network::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty()
+    }
+
+    pub fn cell(&self, handle: CellHandle) -> &Cell {
+        &self.cells[handle.0]
+    }
+
+    pub fn cell_mut(&mut self, handle: CellHandle) -> &mut Cell {
+        &mut self.cells[handle.0]
+    }
+
+    pub fn cells(&self) -> &[Cell] {
+        &self.cells
+    }
+
+    /// Adds `n` identical cells built from `template`, which is copied once
+    /// and shared between all of them
+    pub fn add_cells(&mut self, template: &Compartments, n: usize) -> Vec<CellHandle> {
+        let morphology = Arc::new(template.clone());
+        let first = self.cells.len();
+        self.cells
+            .extend((0..n).map(|_| morphology.clone_shallow()));
+        (first..first + n).map(CellHandle).collect()
+    }
+
+    /// `add_cells` with each cell's leak conductance scaled as `jitter`
+    /// says. The same seed gives the same population.
+    pub fn add_cells_jittered(
+        &mut self,
+        template: &Compartments,
+        n: usize,
+        jitter: LeakJitter,
+    ) -> Result<Vec<CellHandle>, String> {
+        if !(0.0..1.0).contains(&jitter.fraction) {
+            return Err(format!(
+                "Jitter fraction must be in [0, 1), got {}",
+                jitter.fraction
+            ));
+        }
+        let handles = self.add_cells(template, n);
+        let mut rng = StdRng::seed_from_u64(jitter.seed);
+        for &handle in &handles {
+            let factor = rng.random_range(1.0 - jitter.fraction..=1.0 + jitter.fraction);
+            for channel in self.cells[handle.0].channels.iter_mut() {
+                channel.conductance *= factor;
+                if let ChannelType::HodgkinHuxley(hh) = &mut channel.channel_type {
+                    hh.gl *= factor;
+                }
+            }
+        }
+        Ok(handles)
+    }
+
+    /// Moves the cells of `other` into this network, returning their new
+    /// handles in their old order. Shared morphologies stay shared.
+    pub fn merge(&mut self, other: Network) -> Vec<CellHandle> {
+        let first = self.cells.len();
+        self.cells.extend(other.cells);
+        (first..self.cells.len()).map(CellHandle).collect()
+    }
+
+    /// Approximate bytes held by the population, counting every shared
+    /// morphology once
+    pub fn accounted_bytes(&self) -> usize {
+        let mut seen = HashSet::new();
+        let shared: usize = self
+            .cells
+            .iter()
+            .filter(|c| seen.insert(Arc::as_ptr(&c.morphology)))
+            .map(|c| c.morphology.accounted_bytes())
+            .sum();
+        size_of::<Network>() + shared + self.cells.iter().map(Cell::accounted_bytes).sum::<usize>()
+    }
+}
@@ -0,0 +1,547 @@
+//! Objectives for fitting a model to a recorded trace: how far a probe of a
+//! `SimulationResult` is from a target given as `(times, values)`. Lower is
+//! better and identical traces score 0 on every objective.
+//!
+//! The probe is a path given to `Simulation::record`, or `comp[idx].v` for
+//! a voltage the run recorded anyway; its samples are `dt` apart from 0 ms.
+//! The two traces need not share a time base or a length: everything is
+//! compared over the time both cover, and `rms_error` interpolates the
+//! probe linearly onto the target's samples within it. NaN in either trace
+//! is an error rather than a NaN score.
+//!
+//! Spikes are upward crossings of `ObjectiveOptions::spike_threshold`,
+//! timed by linear interpolation as `validation` times them. Features are
+//! taken per spike and averaged: the amplitude from the first sample of the
+//! compared span to the peak, the width at half that amplitude, and the
+//! AHP depth, how far below that first sample the trace falls between the
+//! spike and the next one. Spikes that have not fallen back through half
+//! their amplitude by the end are left out.
+//!
+//! `WeightedObjective` adds several up, and `SweepRunner::fit` ranks a
+//! sweep by one.
+
+use std::fmt;
+
+use crate::solver::SimulationResult;
+use crate::validation::ReferenceTrace;
+
+/// Which of the two traces an error is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Model,
+    Target,
+}
+
+impl fmt::Display for Side {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Side::Model => write!(f, "model"),
+            Side::Target => write!(f, "target"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum ObjectiveError {
+    /// The result has no trace at the probe path
+    NoProbe { probe: String },
+    /// The target has a different number of times and values
+    LengthMismatch { times: usize, values: usize },
+    /// A sample that is NaN or infinite, time or value
+    NotFinite { side: Side, sample: usize },
+    /// Too short, or times that do not increase
+    BadTrace { side: Side, reason: String },
+    /// The two traces share no time, in ms
+    NoOverlap {
+        model: (f64, f64),
+        target: (f64, f64),
+    },
+    /// Features need a complete spike in both traces
+    NoSpikes { side: Side },
+    /// A weight that is negative or not finite
+    BadWeight { weight: f64 },
+}
+
+impl fmt::Display for ObjectiveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjectiveError::NoProbe { probe } => write!(f, "The run did not record '{}'", probe),
+            ObjectiveError::LengthMismatch { times, values } => {
+                write!(f, "Target has {} times for {} values", times, values)
+            }
+            ObjectiveError::NotFinite { side, sample } => {
+                write!(f, "Sample {} of the {} trace is not finite", sample, side)
+            }
+            ObjectiveError::BadTrace { side, reason } => {
+                write!(f, "The {} trace is unusable: {}", side, reason)
+            }
+            ObjectiveError::NoOverlap { model, target } => write!(
+                f,
+                "The model covers {} to {} ms and the target {} to {} ms",
+                model.0, model.1, target.0, target.1
+            ),
+            ObjectiveError::NoSpikes { side } => {
+                write!(f, "The {} trace has no complete spike", side)
+            }
+            ObjectiveError::BadWeight { weight } => {
+                write!(f, "Weight must be non-negative, got {}", weight)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ObjectiveError {}
+
+/// Spike detection and spike time costs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ObjectiveOptions {
+    pub spike_threshold: f64,
+    /// Victor-Purpura cost of moving a spike, per ms; adding or removing
+    /// one costs 1
+    pub shift_cost: f64,
+}
+
+impl Default for ObjectiveOptions {
+    fn default() -> Self {
+        ObjectiveOptions {
+            spike_threshold: 0.0,
+            shift_cost: 0.1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RmsScore {
+    /// Root mean square of the probe minus the target
+    pub score: f64,
+    /// Target samples compared
+    pub samples: usize,
+    pub max_error: f64,
+    /// Where the largest error is, in ms
+    pub max_error_time: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpikeDistanceScore {
+    /// Victor-Purpura distance between the spike trains
+    pub score: f64,
+    pub model_spikes: usize,
+    pub target_spikes: usize,
+    /// Spikes moved onto one of the other train
+    pub shifted: usize,
+    /// What moving them cost
+    pub shift_cost: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpikeCountScore {
+    /// Absolute difference of the counts, or of the rates in Hz
+    pub score: f64,
+    pub model_spikes: usize,
+    pub target_spikes: usize,
+    pub model_rate: f64,
+    pub target_rate: f64,
+    /// Span compared, in ms
+    pub duration: f64,
+}
+
+/// Spike features of one trace, averaged over its spikes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpikeFeatures {
+    pub spikes: usize,
+    /// In the unit of the trace
+    pub amplitude: f64,
+    /// In ms
+    pub half_width: f64,
+    /// In the unit of the trace, positive below the first sample
+    pub ahp_depth: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeatureScore {
+    /// Sum of the absolute differences of the three features, each in its
+    /// own unit
+    pub score: f64,
+    pub model: SpikeFeatures,
+    pub target: SpikeFeatures,
+}
+
+/// Root mean square difference, see the module docs
+pub fn rms_error(
+    result: &SimulationResult,
+    probe: &str,
+    target: (&[f64], &[f64]),
+) -> Result<RmsScore, ObjectiveError> {
+    let (model, target, (t0, t1)) = compared(result, probe, target)?;
+    let (mut sum, mut samples) = (0.0, 0);
+    let (mut max_error, mut max_error_time) = (0.0, t0);
+    for (&t, &value) in target.times().iter().zip(target.values()) {
+        if t < t0 || t > t1 {
+            continue;
+        }
+        let error = (model.value_at(t) - value).abs();
+        sum += error * error;
+        samples += 1;
+        if error > max_error {
+            (max_error, max_error_time) = (error, t);
+        }
+    }
+    if samples == 0 {
+        return Err(ObjectiveError::BadTrace {
+            side: Side::Target,
+            reason: format!("no sample between {} and {} ms", t0, t1),
+        });
+    }
+    Ok(RmsScore {
+        score: (sum / samples as f64).sqrt(),
+        samples,
+        max_error,
+        max_error_time,
+    })
+}
+
+/// Victor-Purpura distance between the spike trains, see
+/// `ObjectiveOptions::shift_cost`. A train without spikes is as far from
+/// the other as that has spikes.
+pub fn spike_distance(
+    result: &SimulationResult,
+    probe: &str,
+    target: (&[f64], &[f64]),
+    options: &ObjectiveOptions,
+) -> Result<SpikeDistanceScore, ObjectiveError> {
+    let (model, target, span) = compared(result, probe, target)?;
+    let a = spikes_within(&model, options.spike_threshold, span);
+    let b = spikes_within(&target, options.spike_threshold, span);
+    let q = options.shift_cost;
+    // cost[i][j] is the distance between the first i and the first j
+    let mut cost = vec![vec![0.0; b.len() + 1]; a.len() + 1];
+    for (i, row) in cost.iter_mut().enumerate() {
+        row[0] = i as f64;
+    }
+    for (j, first) in cost[0].iter_mut().enumerate() {
+        *first = j as f64;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            cost[i][j] = (cost[i - 1][j] + 1.0)
+                .min(cost[i][j - 1] + 1.0)
+                .min(cost[i - 1][j - 1] + q * (a[i - 1] - b[j - 1]).abs());
+        }
+    }
+    let (mut shifted, mut shift_cost) = (0, 0.0);
+    let (mut i, mut j) = (a.len(), b.len());
+    while i > 0 && j > 0 {
+        let shift = q * (a[i - 1] - b[j - 1]).abs();
+        if cost[i][j] == cost[i - 1][j - 1] + shift {
+            shifted += 1;
+            shift_cost += shift;
+            (i, j) = (i - 1, j - 1);
+        } else if cost[i][j] == cost[i - 1][j] + 1.0 {
+            i -= 1;
+        } else {
+            j -= 1;
+        }
+    }
+    Ok(SpikeDistanceScore {
+        score: cost[a.len()][b.len()],
+        model_spikes: a.len(),
+        target_spikes: b.len(),
+        shifted,
+        shift_cost,
+    })
+}
+
+/// Absolute difference of the spike counts
+pub fn spike_count_error(
+    result: &SimulationResult,
+    probe: &str,
+    target: (&[f64], &[f64]),
+    options: &ObjectiveOptions,
+) -> Result<SpikeCountScore, ObjectiveError> {
+    let mut counts = spike_counts(result, probe, target, options)?;
+    counts.score = counts.model_spikes.abs_diff(counts.target_spikes) as f64;
+    Ok(counts)
+}
+
+/// Absolute difference of the firing rates over the span compared, in Hz
+pub fn frequency_error(
+    result: &SimulationResult,
+    probe: &str,
+    target: (&[f64], &[f64]),
+    options: &ObjectiveOptions,
+) -> Result<SpikeCountScore, ObjectiveError> {
+    let mut counts = spike_counts(result, probe, target, options)?;
+    counts.score = (counts.model_rate - counts.target_rate).abs();
+    Ok(counts)
+}
+
+/// Differences of the spike features, see the module docs
+pub fn feature_error(
+    result: &SimulationResult,
+    probe: &str,
+    target: (&[f64], &[f64]),
+    options: &ObjectiveOptions,
+) -> Result<FeatureScore, ObjectiveError> {
+    let (model, target, span) = compared(result, probe, target)?;
+    let features = |trace: &ReferenceTrace, side: Side| {
+        spike_features(trace, options.spike_threshold, span)
+            .ok_or(ObjectiveError::NoSpikes { side })
+    };
+    let (model, target) = (
+        features(&model, Side::Model)?,
+        features(&target, Side::Target)?,
+    );
+    Ok(FeatureScore {
+        score: (model.amplitude - target.amplitude).abs()
+            + (model.half_width - target.half_width).abs()
+            + (model.ahp_depth - target.ahp_depth).abs(),
+        model,
+        target,
+    })
+}
+
+/// One of the objectives above, by name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Objective {
+    Rms,
+    SpikeDistance,
+    SpikeCount,
+    Frequency,
+    Features,
+}
+
+impl Objective {
+    /// The score alone
+    pub fn score(
+        self,
+        result: &SimulationResult,
+        probe: &str,
+        target: (&[f64], &[f64]),
+        options: &ObjectiveOptions,
+    ) -> Result<f64, ObjectiveError> {
+        Ok(match self {
+            Objective::Rms => rms_error(result, probe, target)?.score,
+            Objective::SpikeDistance => spike_distance(result, probe, target, options)?.score,
+            Objective::SpikeCount => spike_count_error(result, probe, target, options)?.score,
+            Objective::Frequency => frequency_error(result, probe, target, options)?.score,
+            Objective::Features => feature_error(result, probe, target, options)?.score,
+        })
+    }
+}
+
+/// A weighted sum of objectives
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct WeightedObjective {
+    pub terms: Vec<(Objective, f64)>,
+    pub options: ObjectiveOptions,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WeightedScore {
+    pub score: f64,
+    /// Every objective with its weight and unweighted score, in order
+    pub terms: Vec<(Objective, f64, f64)>,
+}
+
+impl WeightedObjective {
+    pub fn new(options: ObjectiveOptions) -> WeightedObjective {
+        WeightedObjective {
+            terms: Vec::new(),
+            options,
+        }
+    }
+
+    /// Adds `objective` with `weight`
+    pub fn with(
+        mut self,
+        objective: Objective,
+        weight: f64,
+    ) -> Result<WeightedObjective, ObjectiveError> {
+        if !(weight >= 0.0 && weight.is_finite()) {
+            return Err(ObjectiveError::BadWeight { weight });
+        }
+        self.terms.push((objective, weight));
+        Ok(self)
+    }
+
+    pub fn evaluate(
+        &self,
+        result: &SimulationResult,
+        probe: &str,
+        target: (&[f64], &[f64]),
+    ) -> Result<WeightedScore, ObjectiveError> {
+        let mut terms = Vec::with_capacity(self.terms.len());
+        for &(objective, weight) in &self.terms {
+            if !(weight >= 0.0 && weight.is_finite()) {
+                return Err(ObjectiveError::BadWeight { weight });
+            }
+            let score = objective.score(result, probe, target, &self.options)?;
+            terms.push((objective, weight, score));
+        }
+        Ok(WeightedScore {
+            score: terms.iter().map(|(_, w, s)| w * s).sum(),
+            terms,
+        })
+    }
+}
+
+/// The probe and the target as traces, and the span both cover
+fn compared(
+    result: &SimulationResult,
+    probe: &str,
+    (times, values): (&[f64], &[f64]),
+) -> Result<(ReferenceTrace, ReferenceTrace, (f64, f64)), ObjectiveError> {
+    let samples = probe_samples(result, probe)?;
+    if let Some(sample) = samples.iter().position(|v| !v.is_finite()) {
+        return Err(ObjectiveError::NotFinite {
+            side: Side::Model,
+            sample,
+        });
+    }
+    if times.len() != values.len() {
+        return Err(ObjectiveError::LengthMismatch {
+            times: times.len(),
+            values: values.len(),
+        });
+    }
+    if let Some(sample) =
+        (0..times.len()).find(|&k| !(times[k].is_finite() && values[k].is_finite()))
+    {
+        return Err(ObjectiveError::NotFinite {
+            side: Side::Target,
+            sample,
+        });
+    }
+    let model_times = (0..samples.len()).map(|k| k as f64 * result.dt).collect();
+    let model = ReferenceTrace::new(model_times, samples.to_vec()).map_err(|reason| {
+        ObjectiveError::BadTrace {
+            side: Side::Model,
+            reason,
+        }
+    })?;
+    let target = ReferenceTrace::new(times.to_vec(), values.to_vec()).map_err(|reason| {
+        ObjectiveError::BadTrace {
+            side: Side::Target,
+            reason,
+        }
+    })?;
+    let span = (
+        model.start().max(target.start()),
+        model.end().min(target.end()),
+    );
+    if span.0 > span.1 {
+        return Err(ObjectiveError::NoOverlap {
+            model: (model.start(), model.end()),
+            target: (target.start(), target.end()),
+        });
+    }
+    Ok((model, target, span))
+}
+
+fn probe_samples<'a>(
+    result: &'a SimulationResult,
+    probe: &str,
+) -> Result<&'a [f64], ObjectiveError> {
+    if let Some((_, trace)) = result.traces.iter().find(|(path, _)| path == probe) {
+        return Ok(trace);
+    }
+    probe
+        .strip_prefix("comp[")
+        .and_then(|p| p.strip_suffix("].v"))
+        .and_then(|idx| idx.parse::<usize>().ok())
+        .filter(|&idx| idx > 0)
+        .and_then(|idx| result.voltages.get(idx))
+        .filter(|trace| !trace.is_empty())
+        .map(Vec::as_slice)
+        .ok_or_else(|| ObjectiveError::NoProbe {
+            probe: probe.to_owned(),
+        })
+}
+
+fn spikes_within(trace: &ReferenceTrace, threshold: f64, (t0, t1): (f64, f64)) -> Vec<f64> {
+    trace
+        .spike_times(threshold)
+        .into_iter()
+        .filter(|t| (t0..=t1).contains(t))
+        .collect()
+}
+
+fn spike_counts(
+    result: &SimulationResult,
+    probe: &str,
+    target: (&[f64], &[f64]),
+    options: &ObjectiveOptions,
+) -> Result<SpikeCountScore, ObjectiveError> {
+    let (model, target, span) = compared(result, probe, target)?;
+    let model_spikes = spikes_within(&model, options.spike_threshold, span).len();
+    let target_spikes = spikes_within(&target, options.spike_threshold, span).len();
+    let duration = span.1 - span.0;
+    let rate = |n: usize| match duration > 0.0 {
+        true => n as f64 * 1000.0 / duration,
+        false => 0.0,
+    };
+    Ok(SpikeCountScore {
+        score: 0.0,
+        model_spikes,
+        target_spikes,
+        model_rate: rate(model_spikes),
+        target_rate: rate(target_spikes),
+        duration,
+    })
+}
+
+/// Features of the complete spikes of `trace` within `span`, None if there
+/// are none
+fn spike_features(
+    trace: &ReferenceTrace,
+    threshold: f64,
+    (t0, t1): (f64, f64),
+) -> Option<SpikeFeatures> {
+    let (times, values) = (trace.times(), trace.values());
+    let within: Vec<usize> = (0..times.len())
+        .filter(|&k| times[k] >= t0 && times[k] <= t1)
+        .collect();
+    let (first, last) = (*within.first()?, *within.last()?);
+    let (times, values) = (&times[first..=last], &values[first..=last]);
+    let baseline = values[0];
+    // Sample after every upward crossing
+    let onsets: Vec<usize> = (1..values.len())
+        .filter(|&k| values[k - 1] < threshold && values[k] >= threshold)
+        .collect();
+    let cross = |k: usize, level: f64| {
+        let (v0, v1) = (values[k - 1], values[k]);
+        times[k - 1] + (times[k] - times[k - 1]) * (level - v0) / (v1 - v0)
+    };
+    let mut sums = [0.0; 3];
+    let mut spikes = 0;
+    for (n, &onset) in onsets.iter().enumerate() {
+        let next = onsets.get(n + 1).copied().unwrap_or(values.len());
+        let Some(peak) = (onset..next).max_by(|&a, &b| values[a].total_cmp(&values[b])) else {
+            continue;
+        };
+        let amplitude = values[peak] - baseline;
+        let half = baseline + amplitude / 2.0;
+        let Some(fall) = (peak + 1..next).find(|&k| values[k] < half) else {
+            continue;
+        };
+        let Some(rise) = (1..=peak).rev().find(|&k| values[k - 1] < half) else {
+            continue;
+        };
+        let ahp = values[fall..next]
+            .iter()
+            .copied()
+            .fold(f64::INFINITY, f64::min);
+        sums[0] += amplitude;
+        sums[1] += cross(fall, half) - cross(rise, half);
+        sums[2] += baseline - ahp;
+        spikes += 1;
+    }
+    let mean = |sum: f64| sum / spikes as f64;
+    (spikes > 0).then(|| SpikeFeatures {
+        spikes,
+        amplitude: mean(sums[0]),
+        half_width: mean(sums[1]),
+        ahp_depth: mean(sums[2]),
+    })
+}
@@ -0,0 +1,375 @@
+//! Where `Simulation::run` puts what it records. In memory by default; with
+//! `OutputSink::File`, the recorded traces go to a file `chunk_steps`
+//! samples at a time and the buffers are emptied after every chunk, so a
+//! long run of a big cell never holds more than a chunk of them.
+//!
+//! A streamed run's `SimulationResult` keeps its traces empty and holds a
+//! `TraceFile` instead: `SimulationResult::slice` reads a time window back
+//! and `load` all of it. Snippets, energy and the manifest are small and
+//! stay in memory. Triggered recording and the divergence check work on
+//! every step as they are taken, so they do not need the traces; a run
+//! that fails leaves the chunks written before it in the file.
+//!
+//! Layout, little-endian, rows of one value per column for every sample:
+//!
+//! ```text
+//! header   MAGIC, bytes per value u8, dt f64, compartments u64,
+//!          spines u64, columns u64, column*
+//! column   0 u8, idx u64          a compartment voltage
+//!        | 1 u8, k u64            a spine head voltage
+//!        | 2 u8, length u64, path a state path
+//!        | 3 u8, parent u64, child u64
+//!                                 an axial current
+//! data     row*
+//! ```
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::solver::{SimError, SimulationResult};
+
+const MAGIC: &[u8; 8] = b"CRSTRACE";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    F32,
+    F64,
+}
+
+impl OutputFormat {
+    fn bytes(self) -> usize {
+        match self {
+            OutputFormat::F32 => 4,
+            OutputFormat::F64 => 8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum OutputSink {
+    /// Everything in the `SimulationResult`
+    #[default]
+    Memory,
+    /// Streamed to `path`, replacing any file there, every `chunk_steps`
+    /// samples
+    File {
+        path: PathBuf,
+        chunk_steps: usize,
+        format: OutputFormat,
+    },
+}
+
+impl OutputSink {
+    /// Samples `run` holds at once over `steps` steps
+    pub(crate) fn buffered(&self, steps: usize) -> usize {
+        match self {
+            OutputSink::Memory => steps + 1,
+            OutputSink::File { chunk_steps, .. } => (*chunk_steps).min(steps + 1),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Column {
+    Voltage(usize),
+    Head(usize),
+    Trace(usize),
+    Axial(usize),
+}
+
+/// The traces of a streamed run, on disk
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceFile {
+    path: PathBuf,
+    format: OutputFormat,
+    columns: Vec<Column>,
+    compartments: usize,
+    /// Recorded paths and axial probes, in the order of their columns
+    paths: Vec<String>,
+    pairs: Vec<(usize, usize)>,
+    spines: usize,
+    data_start: u64,
+    samples: usize,
+    peak_buffered: usize,
+}
+
+impl TraceFile {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Samples written, the one before the first step included
+    pub fn samples(&self) -> usize {
+        self.samples
+    }
+
+    /// Most samples held in memory at once while writing, at most the
+    /// sink's `chunk_steps`
+    pub fn peak_buffered(&self) -> usize {
+        self.peak_buffered
+    }
+
+    /// Samples `first..=last` as `SimulationResult` traces, with the rest of
+    /// `result` as it is
+    fn read(
+        &self,
+        result: &SimulationResult,
+        first: usize,
+        last: usize,
+    ) -> Result<SimulationResult, SimError> {
+        let width = self.columns.len() * self.format.bytes();
+        let rows = last + 1 - first;
+        let mut bytes = vec![0; rows * width];
+        let mut file = File::open(&self.path).map_err(|e| io_error(&self.path, e))?;
+        file.seek(SeekFrom::Start(self.data_start + (first * width) as u64))
+            .and_then(|_| file.read_exact(&mut bytes))
+            .map_err(|e| io_error(&self.path, e))?;
+        let mut columns = vec![Vec::with_capacity(rows); self.columns.len()];
+        for row in bytes.chunks_exact(width.max(1)).take(rows) {
+            for (column, value) in columns
+                .iter_mut()
+                .zip(row.chunks_exact(self.format.bytes()))
+            {
+                column.push(match self.format {
+                    OutputFormat::F32 => f32::from_le_bytes(value.try_into().unwrap()) as f64,
+                    OutputFormat::F64 => f64::from_le_bytes(value.try_into().unwrap()),
+                });
+            }
+        }
+        let mut window = SimulationResult {
+            voltages: vec![Vec::new(); self.compartments],
+            head_voltages: vec![Vec::new(); self.spines],
+            traces: self.paths.iter().map(|p| (p.clone(), Vec::new())).collect(),
+            axial_currents: self.pairs.iter().map(|&p| (p, Vec::new())).collect(),
+            stream: None,
+            ..result.clone()
+        };
+        for (column, values) in self.columns.iter().zip(columns) {
+            match *column {
+                Column::Voltage(i) => window.voltages[i] = values,
+                Column::Head(k) => window.head_voltages[k] = values,
+                Column::Trace(k) => window.traces[k].1 = values,
+                Column::Axial(k) => window.axial_currents[k].1 = values,
+            }
+        }
+        Ok(window)
+    }
+}
+
+fn io_error(path: &Path, e: std::io::Error) -> SimError {
+    SimError::Output {
+        path: path.to_owned(),
+        message: e.to_string(),
+    }
+}
+
+/// Buffers of a run being streamed, see the module docs
+pub(crate) struct ChunkWriter {
+    file: File,
+    trace: TraceFile,
+    chunk_steps: usize,
+    /// Samples in the buffers now
+    held: usize,
+}
+
+impl ChunkWriter {
+    /// Creates the file for traces laid out as `run` holds them, each with
+    /// its first sample; empty voltage traces are not recorded
+    pub(crate) fn create(
+        sink: &OutputSink,
+        dt: f64,
+        voltages: &[Vec<f64>],
+        head_voltages: &[Vec<f64>],
+        traces: &[(String, Vec<f64>)],
+        axial_currents: &[((usize, usize), Vec<f64>)],
+    ) -> Result<Option<ChunkWriter>, SimError> {
+        let OutputSink::File {
+            path,
+            chunk_steps,
+            format,
+        } = sink
+        else {
+            return Ok(None);
+        };
+        let mut header = MAGIC.to_vec();
+        header.push(format.bytes() as u8);
+        header.extend_from_slice(&dt.to_le_bytes());
+        let mut columns: Vec<Column> = (0..voltages.len())
+            .filter(|&i| !voltages[i].is_empty())
+            .map(Column::Voltage)
+            .collect();
+        columns.extend((0..head_voltages.len()).map(Column::Head));
+        columns.extend((0..traces.len()).map(Column::Trace));
+        columns.extend((0..axial_currents.len()).map(Column::Axial));
+        for n in [voltages.len(), head_voltages.len(), columns.len()] {
+            header.extend_from_slice(&(n as u64).to_le_bytes());
+        }
+        for column in &columns {
+            let (kind, fields) = match *column {
+                Column::Voltage(i) => (0, vec![i]),
+                Column::Head(k) => (1, vec![k]),
+                Column::Trace(k) => (2, vec![traces[k].0.len()]),
+                Column::Axial(k) => (3, vec![axial_currents[k].0.0, axial_currents[k].0.1]),
+            };
+            header.push(kind);
+            for field in fields {
+                header.extend_from_slice(&(field as u64).to_le_bytes());
+            }
+            if let Column::Trace(k) = column {
+                header.extend_from_slice(traces[*k].0.as_bytes());
+            }
+        }
+        let mut file = File::create(path).map_err(|e| io_error(path, e))?;
+        file.write_all(&header).map_err(|e| io_error(path, e))?;
+        Ok(Some(ChunkWriter {
+            file,
+            trace: TraceFile {
+                path: path.clone(),
+                format: *format,
+                columns,
+                compartments: voltages.len(),
+                paths: traces.iter().map(|(p, _)| p.clone()).collect(),
+                pairs: axial_currents.iter().map(|(p, _)| *p).collect(),
+                spines: head_voltages.len(),
+                data_start: header.len() as u64,
+                samples: 0,
+                peak_buffered: 0,
+            },
+            chunk_steps: *chunk_steps,
+            held: 0,
+        }))
+    }
+
+    /// Counts the sample just added to every buffer, and writes and empties
+    /// them once they hold a chunk
+    pub(crate) fn sampled(
+        &mut self,
+        voltages: &mut [Vec<f64>],
+        head_voltages: &mut [Vec<f64>],
+        traces: &mut [(String, Vec<f64>)],
+        axial_currents: &mut [((usize, usize), Vec<f64>)],
+    ) -> Result<(), SimError> {
+        self.held += 1;
+        self.trace.peak_buffered = self.trace.peak_buffered.max(self.held);
+        if self.held < self.chunk_steps {
+            return Ok(());
+        }
+        self.flush(voltages, head_voltages, traces, axial_currents)
+    }
+
+    /// Writes and empties the buffers
+    pub(crate) fn flush(
+        &mut self,
+        voltages: &mut [Vec<f64>],
+        head_voltages: &mut [Vec<f64>],
+        traces: &mut [(String, Vec<f64>)],
+        axial_currents: &mut [((usize, usize), Vec<f64>)],
+    ) -> Result<(), SimError> {
+        let format = self.trace.format;
+        let mut bytes = Vec::with_capacity(self.held * self.trace.columns.len() * format.bytes());
+        for s in 0..self.held {
+            for column in &self.trace.columns {
+                let value = match *column {
+                    Column::Voltage(i) => voltages[i][s],
+                    Column::Head(k) => head_voltages[k][s],
+                    Column::Trace(k) => traces[k].1[s],
+                    Column::Axial(k) => axial_currents[k].1[s],
+                };
+                match format {
+                    OutputFormat::F32 => bytes.extend_from_slice(&(value as f32).to_le_bytes()),
+                    OutputFormat::F64 => bytes.extend_from_slice(&value.to_le_bytes()),
+                }
+            }
+        }
+        self.file
+            .write_all(&bytes)
+            .map_err(|e| io_error(&self.trace.path, e))?;
+        voltages.iter_mut().for_each(Vec::clear);
+        head_voltages.iter_mut().for_each(Vec::clear);
+        traces.iter_mut().for_each(|(_, t)| t.clear());
+        axial_currents.iter_mut().for_each(|(_, t)| t.clear());
+        self.trace.samples += self.held;
+        self.held = 0;
+        Ok(())
+    }
+
+    /// The file, once the last chunk is flushed
+    pub(crate) fn finish(self) -> TraceFile {
+        self.trace
+    }
+}
+
+impl SimulationResult {
+    /// Samples between `t0` and `t1` ms of the run, both included, read
+    /// from the file if the run was streamed. The window must hold at
+    /// least one sample; the first is at the first multiple of `dt` from
+    /// `t0` on. Snippets and energy are those of the whole run.
+    pub fn slice(&self, t0: f64, t1: f64) -> Result<SimulationResult, SimError> {
+        let samples = self.samples();
+        if !(t0 >= 0.0 && t0 <= t1 && !t1.is_nan()) {
+            return Err(SimError::InvalidInput {
+                reason: format!("Window {} to {} ms is not a time span", t0, t1),
+            });
+        }
+        let first = (t0 / self.dt - 1e-9).ceil() as usize;
+        // Saturates for an open end
+        let last = ((t1 / self.dt + 1e-9).floor() as usize).min(samples.saturating_sub(1));
+        if first > last || samples == 0 {
+            return Err(SimError::InvalidInput {
+                reason: format!(
+                    "Window {} to {} ms holds no sample of the {} ms run",
+                    t0,
+                    t1,
+                    samples.saturating_sub(1) as f64 * self.dt
+                ),
+            });
+        }
+        if let Some(trace) = &self.stream {
+            return trace.read(self, first, last);
+        }
+        let cut = |trace: &Vec<f64>| match trace.is_empty() {
+            true => Vec::new(),
+            false => trace[first..=last].to_vec(),
+        };
+        Ok(SimulationResult {
+            voltages: self.voltages.iter().map(cut).collect(),
+            head_voltages: self.head_voltages.iter().map(cut).collect(),
+            traces: self
+                .traces
+                .iter()
+                .map(|(p, t)| (p.clone(), cut(t)))
+                .collect(),
+            axial_currents: self
+                .axial_currents
+                .iter()
+                .map(|(p, t)| (*p, cut(t)))
+                .collect(),
+            ..self.clone()
+        })
+    }
+
+    /// The whole run in memory, read from the file if it was streamed
+    pub fn load(&self) -> Result<SimulationResult, SimError> {
+        match self.stream {
+            Some(_) => self.slice(0.0, f64::INFINITY),
+            None => Ok(self.clone()),
+        }
+    }
+
+    /// Samples of the run, the one before the first step included
+    pub fn samples(&self) -> usize {
+        match &self.stream {
+            Some(trace) => trace.samples,
+            None => self
+                .voltages
+                .iter()
+                .chain(&self.head_voltages)
+                .chain(self.traces.iter().map(|(_, t)| t))
+                .chain(self.axial_currents.iter().map(|(_, t)| t))
+                .map(Vec::len)
+                .max()
+                .unwrap_or(0),
+        }
+    }
+}
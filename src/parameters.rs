@@ -0,0 +1,258 @@
+//! Per-compartment parameter maps, e.g. for colouring morphology plots by what
+//! the model actually contains.
+//!
+//! Names are the `Channel` fields (`capacitance`, `conductance`,
+//! `resistance`), the `Compartment` geometry (`length`, `diam`, `area_factor`)
+//! and derived quantities: `area` (membrane area including `area_factor`),
+//! `path_distance` (cable length from the soma) and `branch_order` (number of
+//! branch points between the soma and the compartment, the soma included).
+//! The `hh` conductance densities go by their NEURON names, `gnabar_hh`,
+//! `gkbar_hh` and `gl_hh`, and are zero where `hh` is not inserted.
+//! Reversal potentials by ion go by NEURON's `ena`, `ek`, `eca` and `ecl`:
+//! the value the currents of that ion use, see `reversal`, and NaN where
+//! nothing sets one. Setting them sets the compartment's own.
+
+use std::fmt;
+
+use crate::channels::{ChannelType, HodgkinHuxley, Ion};
+use crate::codes::Code;
+use crate::compartments::{Compartment, Compartments};
+use crate::reversal::REVERSALS;
+
+const PARAMETERS: [&str; 16] = [
+    "capacitance",
+    "conductance",
+    "resistance",
+    "length",
+    "diam",
+    "area_factor",
+    "area",
+    "path_distance",
+    "branch_order",
+    "gnabar_hh",
+    "gkbar_hh",
+    "gl_hh",
+    "ena",
+    "ek",
+    "eca",
+    "ecl",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum ParamError {
+    /// No parameter by that name; `suggestions` holds the close matches
+    Unknown {
+        name: String,
+        suggestions: Vec<String>,
+    },
+    /// No section by that name, see `Compartments::sections`
+    UnknownSection { name: String },
+    /// A position along a section outside 0..=1
+    InvalidPosition { x: f64 },
+    /// The parameter is derived from others and cannot be set directly
+    ReadOnly { name: String },
+    /// A parameter table row that does not parse; `line` is 1-based
+    MalformedRow { line: usize, message: String },
+    /// No compartment carries the tag, see `Compartments::tagged`
+    UnknownTag { name: String },
+    /// The parameter belongs to a mechanism compartment `idx` does not have
+    MissingMechanism { name: String, idx: usize },
+}
+
+impl fmt::Display for ParamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParamError::Unknown { name, suggestions } if suggestions.is_empty() => {
+                write!(f, "Unknown parameter '{}'", name)
+            }
+            ParamError::Unknown { name, suggestions } => write!(
+                f,
+                "Unknown parameter '{}'; did you mean {}?",
+                name,
+                suggestions.join(", ")
+            ),
+            ParamError::UnknownSection { name } => write!(f, "Unknown section '{}'", name),
+            ParamError::InvalidPosition { x } => {
+                write!(f, "Position {} is outside the section; use 0 to 1", x)
+            }
+            ParamError::ReadOnly { name } => write!(f, "Parameter '{}' cannot be set", name),
+            ParamError::MalformedRow { line, message } => {
+                write!(f, "Malformed row at line {}: {}", line, message)
+            }
+            ParamError::UnknownTag { name } => {
+                write!(f, "No compartment is tagged '{}'", name)
+            }
+            ParamError::MissingMechanism { name, idx } => write!(
+                f,
+                "Compartment {} has no mechanism with parameter '{}'",
+                idx, name
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ParamError {}
+
+impl ParamError {
+    /// Machine-readable code, see `codes::REGISTRY`
+    pub fn code(&self) -> Code {
+        match self {
+            ParamError::Unknown { .. } => Code::UnknownParameter,
+            ParamError::UnknownSection { .. } => Code::UnknownSection,
+            ParamError::InvalidPosition { .. } => Code::InvalidPosition,
+            ParamError::ReadOnly { .. } => Code::ReadOnlyParameter,
+            ParamError::MalformedRow { .. } => Code::MalformedParameterRow,
+            ParamError::UnknownTag { .. } => Code::UnknownTag,
+            ParamError::MissingMechanism { .. } => Code::MissingMechanism,
+        }
+    }
+}
+
+impl Compartments {
+    /// Every name `parameter_map` accepts for this model
+    pub fn list_parameters(&self) -> Vec<String> {
+        PARAMETERS.iter().map(|p| p.to_string()).collect()
+    }
+
+    /// The value of parameter `name` for every compartment, indexed like
+    /// `components`. The dummy root is not part of the cell and gets NaN.
+    pub fn parameter_map(&self, name: &str) -> Result<Vec<f64>, ParamError> {
+        let per_compartment: fn(&Compartment) -> f64 = match name {
+            "capacitance" => |c| c.channel.capacitance,
+            "conductance" => |c| c.channel.conductance,
+            "resistance" => |c| c.channel.resistance,
+            "length" => |c| c.length,
+            "diam" => |c| c.diam,
+            "area_factor" => |c| c.area_factor,
+            "area" => Compartment::membrane_area,
+            "path_distance" => return Ok(self.path_distances()),
+            "branch_order" => return Ok(self.branch_orders()),
+            "gnabar_hh" => |c| hh(c).map_or(0.0, |hh| hh.gnabar),
+            "gkbar_hh" => |c| hh(c).map_or(0.0, |hh| hh.gkbar),
+            "gl_hh" => |c| hh(c).map_or(0.0, |hh| hh.gl),
+            _ if let Some(ion) = reversal_ion(name) => return Ok(self.effective_reversals(ion)),
+            _ => return Err(unknown(name, &PARAMETERS)),
+        };
+        Ok(self
+            .components
+            .iter()
+            .enumerate()
+            .map(|(i, c)| if i == 0 { f64::NAN } else { per_compartment(c) })
+            .collect())
+    }
+
+    /// Parents always come before their children, so one forward pass over
+    /// the compartments is enough for the cumulative quantities. The soma
+    /// starts at zero.
+    fn accumulate(&self, step: impl Fn(&Compartment, &Compartment, f64) -> f64) -> Vec<f64> {
+        let mut out = vec![f64::NAN; self.components.len()];
+        for (i, c) in self.components.iter().enumerate().skip(1) {
+            out[i] = match c.parent_idxs.first() {
+                Some(&p) if p != 0 => step(c, &self.components[p as usize], out[p as usize]),
+                _ => 0.0,
+            };
+        }
+        out
+    }
+
+    fn path_distances(&self) -> Vec<f64> {
+        self.accumulate(|c, _, parent_distance| parent_distance + c.length)
+    }
+
+    fn branch_orders(&self) -> Vec<f64> {
+        self.accumulate(|_, parent, parent_order| {
+            if parent.children_idxs.len() > 1 {
+                parent_order + 1.0
+            } else {
+                parent_order
+            }
+        })
+    }
+}
+
+fn reversal_ion(name: &str) -> Option<Ion> {
+    REVERSALS
+        .iter()
+        .find(|(_, n)| *n == name)
+        .map(|(ion, _)| *ion)
+}
+
+fn hh(c: &Compartment) -> Option<&HodgkinHuxley> {
+    match &c.channel.channel_type {
+        ChannelType::HodgkinHuxley(hh) => Some(hh),
+        _ => None,
+    }
+}
+
+/// Sets a settable parameter (a `Channel` field, an `hh` conductance or
+/// `area_factor`) on one compartment
+pub(crate) fn set_parameter(c: &mut Compartment, name: &str, value: f64) -> Result<(), ParamError> {
+    let idx = c.idx as usize;
+    if let Some(slot) = hh_conductance(c, name) {
+        *slot.ok_or_else(|| ParamError::MissingMechanism {
+            name: name.to_owned(),
+            idx,
+        })? = value;
+        return Ok(());
+    }
+    if let Some(ion) = reversal_ion(name) {
+        c.channel.reversals.insert(ion, value);
+        return Ok(());
+    }
+    match name {
+        "capacitance" => c.channel.capacitance = value,
+        "conductance" => c.channel.conductance = value,
+        "resistance" => c.channel.resistance = value,
+        "area_factor" => c.area_factor = value,
+        _ if PARAMETERS.contains(&name) => {
+            return Err(ParamError::ReadOnly {
+                name: name.to_owned(),
+            });
+        }
+        _ => return Err(unknown(name, &PARAMETERS)),
+    }
+    Ok(())
+}
+
+/// The `hh` conductance `name` of `c`: None if `name` is not one, Some(None)
+/// if `c` has no `hh`
+fn hh_conductance<'a>(c: &'a mut Compartment, name: &str) -> Option<Option<&'a mut f64>> {
+    let field: fn(&mut HodgkinHuxley) -> &mut f64 = match name {
+        "gnabar_hh" => |hh| &mut hh.gnabar,
+        "gkbar_hh" => |hh| &mut hh.gkbar,
+        "gl_hh" => |hh| &mut hh.gl,
+        _ => return None,
+    };
+    Some(match &mut c.channel.channel_type {
+        ChannelType::HodgkinHuxley(hh) => Some(field(hh)),
+        _ => None,
+    })
+}
+
+fn unknown(name: &str, known: &[&str]) -> ParamError {
+    let suggestions = known
+        .iter()
+        .filter(|p| p.contains(name) || edit_distance(p, name) <= 3)
+        .map(|p| p.to_string())
+        .collect();
+    ParamError::Unknown {
+        name: name.to_owned(),
+        suggestions,
+    }
+}
+
+/// Levenshtein distance, for suggesting names
+pub(crate) fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut curr = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = prev[j] + usize::from(ca != *cb);
+            curr.push(substitution.min(prev[j + 1] + 1).min(curr[j] + 1));
+        }
+        prev = curr;
+    }
+    prev[b.len()]
+}
@@ -0,0 +1,234 @@
+//! Synapses placed at random over the membrane, for when there is no
+//! measured synapse table: a fixed number or a density per µm², spread in
+//! proportion to membrane area, optionally weighted by a relative density
+//! written in the language of `biophysics` rules, e.g. `exp(dist/200)`.
+//!
+//! Placements are fully determined by the seed, and come out ordered by
+//! compartment and position along it.
+
+use std::f64::consts::TAU;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::biophysics::Expression;
+use crate::compartments::Compartments;
+use crate::filter::NodeFilter;
+use crate::protocols::TrainInput;
+use crate::spikes::{SpikeTrainSource, sub_seed};
+use crate::stimulus::Stimulus;
+
+/// How many synapses to place
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SynapseCount {
+    /// Exactly this many over all selected compartments
+    Exactly(usize),
+    /// This many per µm² of membrane on average, where the relative density
+    /// is 1. Each compartment's count is then Poisson distributed.
+    PerArea(f64),
+}
+
+/// Where synapses go: the count, and how their density varies
+#[derive(Debug, Clone, PartialEq)]
+pub struct DensitySpec {
+    pub count: SynapseCount,
+    /// Density relative to elsewhere, evaluated per compartment; uniform
+    /// when None
+    pub relative_density: Option<Expression>,
+}
+
+impl DensitySpec {
+    pub fn exactly(count: usize) -> DensitySpec {
+        DensitySpec {
+            count: SynapseCount::Exactly(count),
+            relative_density: None,
+        }
+    }
+
+    pub fn per_area(per_um2: f64) -> DensitySpec {
+        DensitySpec {
+            count: SynapseCount::PerArea(per_um2),
+            relative_density: None,
+        }
+    }
+
+    /// Weights each compartment by `expression`, over the variables of a
+    /// distribution rule
+    pub fn relative_density(mut self, expression: &str) -> Result<DensitySpec, String> {
+        let expression = Expression::parse(expression).map_err(|e| e.to_string())?;
+        self.relative_density = Some(expression);
+        Ok(self)
+    }
+}
+
+/// One synapse: compartment `idx` at fraction `x` of its length and
+/// `azimuth` radians around its axis
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Placement {
+    pub idx: usize,
+    pub x: f64,
+    pub azimuth: f64,
+}
+
+/// Expected number of synapses on each compartment, indexed like
+/// `components`: membrane area times relative density, scaled to the
+/// requested total or density. Compartments `filter` rejects get 0.
+pub fn expected_counts(
+    compartments: &Compartments,
+    filter: &NodeFilter,
+    spec: &DensitySpec,
+) -> Result<Vec<f64>, String> {
+    let variables = compartments.rule_variables();
+    let mut weights = vec![0.0; compartments.components.len()];
+    for (idx, c) in compartments.components.iter().enumerate().skip(1) {
+        if !filter.matches_parts(c.structure, c.flags) {
+            continue;
+        }
+        let relative = match &spec.relative_density {
+            Some(expression) => expression
+                .evaluate(&variables[idx])
+                .map_err(|e| format!("Compartment {}: {}", idx, e))?,
+            None => 1.0,
+        };
+        if relative < 0.0 {
+            return Err(format!(
+                "Compartment {}: relative density '{}' is negative, {}",
+                idx,
+                spec.relative_density.as_ref().expect("1 otherwise"),
+                relative
+            ));
+        }
+        weights[idx] = c.membrane_area() * relative;
+    }
+    let scale = match spec.count {
+        SynapseCount::Exactly(0) => 0.0,
+        SynapseCount::Exactly(n) => {
+            let total: f64 = weights.iter().sum();
+            if total <= 0.0 {
+                return Err(format!(
+                    "Nowhere to place {} synapses: no selected membrane",
+                    n
+                ));
+            }
+            n as f64 / total
+        }
+        SynapseCount::PerArea(d) if !(d >= 0.0 && d.is_finite()) => {
+            return Err(format!(
+                "Synapse density must be non-negative and finite, got {} per µm²",
+                d
+            ));
+        }
+        SynapseCount::PerArea(d) => d,
+    };
+    Ok(weights.into_iter().map(|w| w * scale).collect())
+}
+
+/// Places synapses over the compartments matching `filter` as `spec` asks,
+/// with positions along and around each compartment uniform. With an exact
+/// count, each synapse picks its compartment with probability proportional
+/// to its expected count; with a density, each compartment draws a Poisson
+/// count from its own stream of `seed`, as `add_spines` does.
+pub fn sample_synapses(
+    compartments: &Compartments,
+    filter: &NodeFilter,
+    spec: &DensitySpec,
+    seed: u64,
+) -> Result<Vec<Placement>, String> {
+    let expected = expected_counts(compartments, filter, spec)?;
+    let mut placements = Vec::new();
+    match spec.count {
+        SynapseCount::Exactly(n) => {
+            let cumulative: Vec<f64> = expected
+                .iter()
+                .scan(0.0, |sum, e| {
+                    *sum += e;
+                    Some(*sum)
+                })
+                .collect();
+            let total = cumulative.last().copied().unwrap_or(0.0);
+            let mut rng = StdRng::seed_from_u64(seed);
+            for _ in 0..n {
+                let u = rng.random::<f64>() * total;
+                // The first compartment whose running total passes `u`; one
+                // with nothing expected never does
+                let idx = cumulative
+                    .partition_point(|&c| c <= u)
+                    .min(cumulative.len() - 1);
+                placements.push(Placement {
+                    idx,
+                    x: rng.random(),
+                    azimuth: rng.random::<f64>() * TAU,
+                });
+            }
+            placements.sort_by(|a, b| a.idx.cmp(&b.idx).then(a.x.total_cmp(&b.x)));
+        }
+        SynapseCount::PerArea(_) => {
+            for (idx, &mean) in expected.iter().enumerate() {
+                if mean <= 0.0 {
+                    continue;
+                }
+                // Unit-rate arrivals before `mean` are a Poisson count, and
+                // spread uniformly along the compartment
+                let mut rng = StdRng::seed_from_u64(sub_seed(seed, idx as u64));
+                let mut at = 0.0;
+                loop {
+                    at += -(1.0 - rng.random::<f64>()).ln();
+                    if at >= mean {
+                        break;
+                    }
+                    placements.push(Placement {
+                        idx,
+                        x: at / mean,
+                        azimuth: rng.random::<f64>() * TAU,
+                    });
+                }
+            }
+        }
+    }
+    compartments.log(
+        "sample_synapses",
+        &[
+            ("filter", format!("{:?}", filter).into()),
+            ("spec", format!("{:?}", spec.count).into()),
+            (
+                "relative_density",
+                spec.relative_density
+                    .as_ref()
+                    .map_or("1", |e| e.source())
+                    .into(),
+            ),
+            ("seed", seed.into()),
+            ("placed", placements.len().into()),
+        ],
+    );
+    Ok(placements)
+}
+
+/// Each placement as a synapse driven by its own Poisson train at `rate_hz`
+/// from `start` until before `stop`, every presynaptic spike starting a
+/// `kernel` current in its compartment. Synapse `k` draws from
+/// `sub_seed(seed, k)`. Ready for `RepeatedRun::inputs`, which reseeds the
+/// trains in every trial.
+pub fn poisson_inputs(
+    placements: &[Placement],
+    kernel: &Stimulus,
+    rate_hz: f64,
+    start: f64,
+    stop: f64,
+    seed: u64,
+) -> Vec<TrainInput> {
+    placements
+        .iter()
+        .enumerate()
+        .map(|(k, p)| TrainInput {
+            idx: p.idx,
+            source: SpikeTrainSource::Poisson {
+                rate_hz,
+                start,
+                stop,
+                seed: sub_seed(seed, k as u64),
+            },
+            kernel: *kernel,
+        })
+        .collect()
+}
@@ -0,0 +1,118 @@
+//! Pair-based spike-timing-dependent plasticity. Each synapse keeps a
+//! presynaptic and a postsynaptic trace that jump by 1 at their spikes and
+//! decay exponentially in between. A postsynaptic spike potentiates the
+//! weight by `a_plus` times the presynaptic trace; a presynaptic spike
+//! depresses it by `a_minus` times the postsynaptic trace. Times are in ms.
+//!
+//! Traces are only brought up to date when a spike arrives, using the exact
+//! decay since the last one, so a synapse costs nothing between events.
+
+/// Parameters of the additive pair-based rule with hard bounds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StdpParams {
+    pub a_plus: f64,
+    pub a_minus: f64,
+    pub tau_plus: f64,
+    pub tau_minus: f64,
+    pub w_min: f64,
+    pub w_max: f64,
+}
+
+impl StdpParams {
+    fn check(&self) -> Result<(), String> {
+        if !(self.tau_plus > 0.0 && self.tau_minus > 0.0) {
+            return Err(format!(
+                "STDP time constants must be positive, got {} and {} ms",
+                self.tau_plus, self.tau_minus
+            ));
+        }
+        if self.w_min.is_nan() || self.w_max.is_nan() || self.w_min > self.w_max {
+            return Err(format!(
+                "STDP weight bounds are reversed: {} > {}",
+                self.w_min, self.w_max
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// How a synapse's weight changes during a run
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub enum Plasticity {
+    Stdp(StdpParams),
+}
+
+/// Weight and traces of one plastic synapse
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StdpState {
+    pub params: StdpParams,
+    /// Multiplies the synapse's maximal conductance
+    pub weight: f64,
+    pre_trace: f64,
+    post_trace: f64,
+    last_event: f64,
+}
+
+impl StdpState {
+    /// Starts with both traces empty. Errors on non-positive time constants
+    /// or reversed bounds.
+    pub fn new(params: StdpParams, weight: f64) -> Result<StdpState, String> {
+        params.check()?;
+        Ok(StdpState {
+            params,
+            weight: weight.clamp(params.w_min, params.w_max),
+            pre_trace: 0.0,
+            post_trace: 0.0,
+            last_event: f64::NEG_INFINITY,
+        })
+    }
+
+    /// Decays both traces from the last event to `t`
+    fn advance(&mut self, t: f64) {
+        if t > self.last_event {
+            let elapsed = t - self.last_event;
+            self.pre_trace *= (-elapsed / self.params.tau_plus).exp();
+            self.post_trace *= (-elapsed / self.params.tau_minus).exp();
+            self.last_event = t;
+        }
+    }
+
+    /// A presynaptic spike at `t`: depresses, then bumps the pre trace.
+    /// Returns the new weight.
+    pub fn on_pre(&mut self, t: f64) -> f64 {
+        self.advance(t);
+        let p = self.params;
+        self.weight = (self.weight - p.a_minus * self.post_trace).clamp(p.w_min, p.w_max);
+        self.pre_trace += 1.0;
+        self.weight
+    }
+
+    /// A postsynaptic spike at `t`: potentiates, then bumps the post trace.
+    /// Returns the new weight.
+    pub fn on_post(&mut self, t: f64) -> f64 {
+        self.advance(t);
+        let p = self.params;
+        self.weight = (self.weight + p.a_plus * self.pre_trace).clamp(p.w_min, p.w_max);
+        self.post_trace += 1.0;
+        self.weight
+    }
+
+    /// Runs sorted pre and post spike times through the rule and returns the
+    /// weight after every event, as `(time, weight)`. A pre and a post spike
+    /// at the same time count as pre first.
+    pub fn run(&mut self, pre: &[f64], post: &[f64]) -> Vec<(f64, f64)> {
+        let (mut i, mut j) = (0, 0);
+        let mut trajectory = Vec::with_capacity(pre.len() + post.len());
+        while i < pre.len() || j < post.len() {
+            if j == post.len() || (i < pre.len() && pre[i] <= post[j]) {
+                trajectory.push((pre[i], self.on_pre(pre[i])));
+                i += 1;
+            } else {
+                trajectory.push((post[j], self.on_post(post[j])));
+                j += 1;
+            }
+        }
+        trajectory
+    }
+}
@@ -0,0 +1,142 @@
+//! Cheap, topologically faithful previews of large skeletons for plotting and
+//! rough morphometrics.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::swc_reader::Skeleton;
+
+/// A downsampled copy of a skeleton, plus the way back to the original
+#[derive(Debug, Clone)]
+pub struct Preview {
+    /// The preview itself, renumbered like any processed skeleton
+    pub skeleton: Skeleton,
+    /// For each preview node, the original node IDs it stands in for: the
+    /// dropped nodes between it and its preview parent, proximal first, and
+    /// finally the original node itself
+    pub origin: Vec<Vec<u64>>,
+}
+
+impl Preview {
+    /// The original ID of preview node `preview_id`
+    pub fn original_id(&self, preview_id: u64) -> Option<u64> {
+        self.origin
+            .get(preview_id as usize)
+            .and_then(|ids| ids.last().copied())
+    }
+
+    /// Original node IDs covered by a set of preview nodes, e.g. a branch
+    /// picked on the preview
+    pub fn to_original(&self, preview_ids: &[u64]) -> Vec<u64> {
+        preview_ids
+            .iter()
+            .filter_map(|&id| self.origin.get(id as usize))
+            .flatten()
+            .copied()
+            .collect()
+    }
+}
+
+impl Skeleton {
+    /// Builds a preview with at most `max_nodes` nodes. The root, every branch
+    /// point and every tip are kept; unbranched runs are thinned by keeping
+    /// every n-th node, with n chosen as small as the budget allows. Errors if
+    /// the branch points and tips alone exceed the budget.
+    pub fn preview(&self, max_nodes: usize) -> Result<Preview, String> {
+        let root = self
+            .nodes
+            .iter()
+            .find(|n| n.parent_id == n.node_id)
+            .map(|n| n.node_id)
+            .ok_or("No root node found")?;
+        let order = self.subtree(root);
+        let id_to_idx = self.id_to_idx();
+        let parent = |id: u64| self.nodes[id_to_idx[&id]].parent_id;
+        let structural: HashMap<u64, bool> = order
+            .iter()
+            .map(|&id| (id, id == root || self.children_of(id).len() != 1))
+            .collect();
+
+        // Position of each interior node within its unbranched run, from 1
+        let mut run: HashMap<u64, usize> = HashMap::new();
+        for &id in order.iter().skip(1) {
+            let p = parent(id);
+            let pos = if structural[&p] { 1 } else { run[&p] + 1 };
+            run.insert(id, pos);
+        }
+
+        let n_structural = structural.values().filter(|&&s| s).count();
+        if n_structural > max_nodes {
+            return Err(format!(
+                "{} branch points and tips alone exceed the budget of {} nodes",
+                n_structural, max_nodes
+            ));
+        }
+        let interior: Vec<usize> = order
+            .iter()
+            .filter(|id| !structural[id])
+            .map(|id| run[id])
+            .collect();
+        let longest = interior.iter().copied().max().unwrap_or(0);
+        let mut histogram = vec![0usize; longest + 1];
+        for pos in interior {
+            histogram[pos] += 1;
+        }
+        // Keeping every stride-th node of each run; stride past the longest
+        // run keeps none of them, so this always terminates
+        let stride = (1..=longest + 1)
+            .find(|&s| {
+                let kept: usize = histogram.iter().step_by(s).skip(1).sum();
+                n_structural + kept <= max_nodes
+            })
+            .unwrap_or(1);
+        let kept = |id: u64| structural[&id] || run[&id].is_multiple_of(stride);
+
+        let mut nodes = Vec::new();
+        let mut origin_by_id: HashMap<u64, Vec<u64>> = HashMap::new();
+        let mut parent_child_map: HashMap<u64, Vec<u64>> = HashMap::new();
+        let mut child_parent_map: HashMap<u64, Vec<u64>> = HashMap::new();
+        for &id in order.iter().filter(|&&id| kept(id)) {
+            let mut node = self.nodes[id_to_idx[&id]];
+            let mut covered = vec![id];
+            if id != root {
+                let mut p = parent(id);
+                while !kept(p) {
+                    covered.push(p);
+                    p = parent(p);
+                }
+                node.parent_id = p;
+            }
+            covered.reverse();
+            origin_by_id.insert(id, covered);
+            parent_child_map.entry(node.parent_id).or_default().push(id);
+            child_parent_map.insert(id, vec![node.parent_id]);
+            nodes.push(node);
+        }
+
+        let skeleton = Skeleton {
+            nodes: Arc::new(nodes),
+            parent_child_map: Arc::new(parent_child_map),
+            child_parent_map: Arc::new(child_parent_map),
+            warnings: Vec::new(),
+            extras: Arc::new(
+                self.extras
+                    .iter()
+                    .filter(|(id, _)| origin_by_id.contains_key(id))
+                    .map(|(id, e)| (*id, e.clone()))
+                    .collect(),
+            ),
+            extra_columns: self.extra_columns.clone(),
+            metadata: self.metadata.clone(),
+            stats: None,
+        };
+        // `finalize` numbers nodes in exactly this order
+        let origin = skeleton
+            .subtree(root)
+            .iter()
+            .map(|id| origin_by_id.remove(id).unwrap_or_default())
+            .collect();
+        let skeleton = skeleton.normalized()?;
+        Ok(Preview { skeleton, origin })
+    }
+}
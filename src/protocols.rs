@@ -0,0 +1,606 @@
+//! Voltage-clamp protocols for characterising the channels of a
+//! compartment: families of voltage steps, and currents isolated by running
+//! a family with and without one conductance and subtracting.
+//!
+//! The clamp is ideal and the clamped compartment is treated as isolated, a
+//! perfect space clamp. Gates advance exactly for the piecewise constant
+//! command voltage, so a time step only sets how finely the traces are
+//! sampled. Currents are in nA, outward positive.
+//!
+//! `repeat` runs one current-clamp protocol over many trials that differ
+//! only in their random draws, for time-locked averages as an experimenter
+//! would take them.
+//!
+//! `library` holds named current-clamp presets, listed by `available`.
+
+pub mod library;
+
+use std::thread;
+
+use crate::channels::{ChannelType, Dynamics, HodgkinHuxley};
+use crate::compartments::{Compartment, Compartments};
+use crate::solver::{Simulation, SimulationResult};
+use crate::spikes::{SpikeTrainSource, sub_seed};
+use crate::stimulus::Stimulus;
+use crate::validation::ReferenceTrace;
+
+pub use library::{Preset, Protocol, available};
+
+/// Membrane areas are in µm² and current densities in mA/cm²
+const NA_PER_MA_PER_CM2_PER_UM2: f64 = 1e-8 * 1e6;
+/// µF/cm² times mV/ms gives µA/cm²
+const MA_PER_UA: f64 = 1e-3;
+
+/// Steps from a holding potential, one run per step level. All voltages
+/// are in mV and times in ms.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StepProtocol {
+    pub holding: f64,
+    /// Step levels, one trace each
+    pub steps: Vec<f64>,
+    /// Time at holding before the step, of the step itself, and at holding
+    /// after it
+    pub durations: [f64; 3],
+    pub dt: f64,
+}
+
+impl StepProtocol {
+    /// Samples before, during and after the step
+    fn samples(&self) -> Result<[usize; 3], String> {
+        if !(self.dt > 0.0 && self.dt.is_finite()) {
+            return Err(format!("Time step must be positive, got {}", self.dt));
+        }
+        if self.durations.iter().any(|d| !(*d >= 0.0 && d.is_finite())) {
+            return Err(format!(
+                "Durations must be non-negative, got {:?}",
+                self.durations
+            ));
+        }
+        Ok(self.durations.map(|d| (d / self.dt).round() as usize))
+    }
+}
+
+/// Clamp current of one step level. Sample `k` is at time `k * dt`; the
+/// first sample is at holding, before any step.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClampTrace {
+    pub level: f64,
+    /// Current the amplifier injects to hold the command voltage, the
+    /// capacitive transients at the voltage jumps included
+    pub current: Vec<f64>,
+}
+
+/// A current isolated by subtraction, with its I-V curves
+#[derive(Debug, Clone, PartialEq)]
+pub struct IsolatedCurrent {
+    /// Full run minus the run without the conductance, per step level
+    pub traces: Vec<ClampTrace>,
+    /// `(level, current)` at the largest magnitude during each step
+    pub peak: Vec<(f64, f64)>,
+    /// `(level, current)` at the end of each step
+    pub steady_state: Vec<(f64, f64)>,
+}
+
+/// Conductances of the compartment's mechanism that can be zeroed, by
+/// their NEURON names
+fn conductance_names(c: &Compartment) -> &'static [&'static str] {
+    match c.channel.channel_type {
+        ChannelType::HodgkinHuxley(_) => &["gnabar_hh", "gkbar_hh", "gl_hh"],
+        ChannelType::Passive(_) => &["g_pas"],
+        _ => &[],
+    }
+}
+
+/// Compartment `site` with its reversals resolved, see `reversal`
+fn clamped(compartments: &Compartments, site: usize) -> Result<Compartment, String> {
+    let c = compartments
+        .components
+        .get(site)
+        .filter(|_| site > 0)
+        .ok_or_else(|| format!("No compartment {} to clamp", site))?;
+    let area = c.membrane_area();
+    if !(area > 0.0 && area.is_finite()) {
+        return Err(format!("Compartment {} has no membrane to clamp", site));
+    }
+    let mut c = c.clone();
+    c.channel = compartments.resolved_channel(&c.channel);
+    Ok(c)
+}
+
+/// Ionic current density through `c` at `v`, in mA/cm², with `zeroed`
+/// left out
+fn ionic(c: &Compartment, gates: &[f64; 3], v: f64, zeroed: Option<&str>) -> f64 {
+    match &c.channel.channel_type {
+        ChannelType::HodgkinHuxley(hh) => {
+            let mut hh = *hh;
+            match zeroed {
+                Some("gnabar_hh") => hh.gnabar = 0.0,
+                Some("gkbar_hh") => hh.gkbar = 0.0,
+                Some("gl_hh") => hh.gl = 0.0,
+                _ => {}
+            }
+            hh.current(gates, v, c.channel.conductance)
+        }
+        ChannelType::Passive(pas) if zeroed != Some("g_pas") => {
+            pas.current(gates, v, c.channel.conductance)
+        }
+        _ => 0.0,
+    }
+}
+
+fn run_family(
+    c: &Compartment,
+    protocol: &StepProtocol,
+    zeroed: Option<&str>,
+) -> Result<Vec<ClampTrace>, String> {
+    let [pre, during, post] = protocol.samples()?;
+    let dt = protocol.dt;
+    let area = c.membrane_area();
+    Ok(protocol
+        .steps
+        .iter()
+        .map(|&level| {
+            let command = |k: usize| {
+                if k > pre && k <= pre + during {
+                    level
+                } else {
+                    protocol.holding
+                }
+            };
+            let mut gates = HodgkinHuxley::steady_state(protocol.holding);
+            let current = (0..=pre + during + post)
+                .map(|k| {
+                    let v = command(k);
+                    let mut density = 0.0;
+                    if k > 0 {
+                        HodgkinHuxley::step_gates(&mut gates, v, dt);
+                        // The charge moving the membrane to the new command
+                        // flows within the one step
+                        density += c.channel.capacitance * (v - command(k - 1)) / dt * MA_PER_UA;
+                    }
+                    density += ionic(c, &gates, v, zeroed);
+                    density * area * NA_PER_MA_PER_CM2_PER_UM2
+                })
+                .collect();
+            ClampTrace { level, current }
+        })
+        .collect())
+}
+
+/// Runs the clamp protocol at compartment `site` for each step level
+pub fn voltage_step_family(
+    compartments: &Compartments,
+    site: usize,
+    protocol: &StepProtocol,
+) -> Result<Vec<ClampTrace>, String> {
+    run_family(&clamped(compartments, site)?, protocol, None)
+}
+
+/// Isolates the current through `conductance` (e.g. `gkbar_hh`) at `site`:
+/// runs the family as configured and again with that conductance at zero,
+/// and subtracts. Errors, listing what is there, when the compartment has
+/// no such conductance.
+pub fn isolate_current(
+    compartments: &Compartments,
+    site: usize,
+    protocol: &StepProtocol,
+    conductance: &str,
+) -> Result<IsolatedCurrent, String> {
+    let c = clamped(compartments, site)?;
+    let names = conductance_names(&c);
+    if !names.contains(&conductance) {
+        return Err(format!(
+            "No conductance '{}' at compartment {}; available: {}",
+            conductance,
+            site,
+            if names.is_empty() {
+                "none".to_owned()
+            } else {
+                names.join(", ")
+            }
+        ));
+    }
+    let full = run_family(&c, protocol, None)?;
+    let without = run_family(&c, protocol, Some(conductance))?;
+    let traces: Vec<ClampTrace> = full
+        .into_iter()
+        .zip(without)
+        .map(|(f, w)| ClampTrace {
+            level: f.level,
+            current: f
+                .current
+                .iter()
+                .zip(&w.current)
+                .map(|(a, b)| a - b)
+                .collect(),
+        })
+        .collect();
+
+    let [pre, during, _] = protocol.samples()?;
+    let window = pre + 1..=pre + during;
+    let mut peak = Vec::new();
+    let mut steady_state = Vec::new();
+    for trace in &traces {
+        let step = &trace.current[window.clone()];
+        if let Some(&last) = step.last() {
+            let largest = step
+                .iter()
+                .copied()
+                .fold(0.0, |m: f64, i| if i.abs() > m.abs() { i } else { m });
+            peak.push((trace.level, largest));
+            steady_state.push((trace.level, last));
+        }
+    }
+    Ok(IsolatedCurrent {
+        traces,
+        peak,
+        steady_state,
+    })
+}
+
+/// Presynaptic spikes arriving at one compartment, each starting a `kernel`
+/// current at its own time
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrainInput {
+    pub idx: usize,
+    /// A Poisson source's own seed is replaced in every trial
+    pub source: SpikeTrainSource,
+    pub kernel: Stimulus,
+}
+
+/// The protocol `repeat` runs in every trial
+#[derive(Debug, Clone, PartialEq)]
+pub struct RepeatedRun {
+    pub dt: f64,
+    pub steps: usize,
+    /// Injected the same in every trial, as in `Simulation::run`
+    pub stimuli: Vec<(usize, Vec<f64>)>,
+    pub inputs: Vec<TrainInput>,
+    /// Compartments to summarize and collect spikes from; empty for all
+    pub record: Vec<usize>,
+    /// Upward crossings of this voltage are spikes, in mV
+    pub spike_threshold: f64,
+    /// Trial `t` runs with seed `spikes::sub_seed(seed, t)`, and input `k`
+    /// of that trial with `sub_seed` of that and `k`
+    pub seed: u64,
+    /// Trials run at once; 0 uses every available core
+    pub threads: usize,
+    /// With `RepeatMode::KeepAll`, refuse to run when the kept voltages
+    /// would take more bytes than this
+    pub max_kept_bytes: Option<usize>,
+}
+
+impl Default for RepeatedRun {
+    fn default() -> Self {
+        RepeatedRun {
+            dt: 0.025,
+            steps: 0,
+            stimuli: Vec::new(),
+            inputs: Vec::new(),
+            record: Vec::new(),
+            spike_threshold: 0.0,
+            seed: 0,
+            threads: 0,
+            max_kept_bytes: None,
+        }
+    }
+}
+
+/// What `repeat` keeps of the trials
+#[derive(Debug, Clone, PartialEq)]
+pub enum RepeatMode {
+    /// Every trial's `SimulationResult`, besides the summary
+    KeepAll,
+    /// Only the summary, accumulated as trials finish, with estimates of
+    /// these percentiles (0 to 100) of every sample as well
+    SummaryOnly { percentiles: Vec<f64> },
+}
+
+/// Time-locked statistics over trials, indexed like `components` and then
+/// by sample. Compartments not recorded have empty traces.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrialSummary {
+    pub trials: usize,
+    pub mean: Vec<Vec<f64>>,
+    /// Sample variance, over `trials - 1`; zero for a single trial
+    pub variance: Vec<Vec<f64>>,
+    /// Each requested percentile with its traces. P² estimates (Jain and
+    /// Chlamtac, 1985), exact up to five trials.
+    pub percentiles: Vec<(f64, Vec<Vec<f64>>)>,
+}
+
+/// Outcome of `repeat`
+#[derive(Debug, Clone, PartialEq)]
+pub struct RepeatResult {
+    pub dt: f64,
+    /// Seed of each trial
+    pub seeds: Vec<u64>,
+    /// Every trial with `RepeatMode::KeepAll`, none otherwise
+    pub trials: Vec<SimulationResult>,
+    pub summary: TrialSummary,
+    /// Spike times, indexed like `components` and then by trial, whatever
+    /// the mode. Compartments not recorded have no trials.
+    pub rasters: Vec<Vec<Vec<f64>>>,
+    /// Most trials' voltages held at once, at most the number of threads
+    /// unless they are all kept
+    pub peak_held_trials: usize,
+}
+
+/// Running mean and sum of squared deviations, Welford's update
+#[derive(Debug, Clone)]
+struct Welford {
+    count: usize,
+    mean: Vec<f64>,
+    m2: Vec<f64>,
+}
+
+impl Welford {
+    fn new(samples: usize) -> Welford {
+        Welford {
+            count: 0,
+            mean: vec![0.0; samples],
+            m2: vec![0.0; samples],
+        }
+    }
+
+    fn add(&mut self, trace: &[f64]) {
+        self.count += 1;
+        let n = self.count as f64;
+        for ((mean, m2), &x) in self.mean.iter_mut().zip(self.m2.iter_mut()).zip(trace) {
+            let delta = x - *mean;
+            *mean += delta / n;
+            *m2 += delta * (x - *mean);
+        }
+    }
+
+    fn variance(&self) -> Vec<f64> {
+        match self.count {
+            0 | 1 => vec![0.0; self.m2.len()],
+            n => self.m2.iter().map(|m2| m2 / (n - 1) as f64).collect(),
+        }
+    }
+}
+
+/// P² estimate of one quantile, in constant memory
+#[derive(Debug, Clone)]
+struct P2 {
+    /// Quantile as a fraction
+    p: f64,
+    count: usize,
+    heights: [f64; 5],
+    positions: [f64; 5],
+    desired: [f64; 5],
+}
+
+impl P2 {
+    fn new(p: f64) -> P2 {
+        P2 {
+            p,
+            count: 0,
+            heights: [0.0; 5],
+            positions: [1.0, 2.0, 3.0, 4.0, 5.0],
+            desired: [1.0, 1.0 + 2.0 * p, 1.0 + 4.0 * p, 3.0 + 2.0 * p, 5.0],
+        }
+    }
+
+    fn add(&mut self, x: f64) {
+        if self.count < 5 {
+            self.heights[self.count] = x;
+            self.count += 1;
+            if self.count == 5 {
+                self.heights.sort_by(f64::total_cmp);
+            }
+            return;
+        }
+        self.count += 1;
+        let q = &mut self.heights;
+        let cell = if x < q[0] {
+            q[0] = x;
+            0
+        } else if x >= q[4] {
+            q[4] = x;
+            3
+        } else {
+            (0..4).find(|&i| x < q[i + 1]).unwrap_or(3)
+        };
+        for n in &mut self.positions[cell + 1..] {
+            *n += 1.0;
+        }
+        let p = self.p;
+        for (d, step) in self
+            .desired
+            .iter_mut()
+            .zip([0.0, p / 2.0, p, (1.0 + p) / 2.0, 1.0])
+        {
+            *d += step;
+        }
+        let n = &mut self.positions;
+        for i in 1..4 {
+            let off = self.desired[i] - n[i];
+            if (off >= 1.0 && n[i + 1] - n[i] > 1.0) || (off <= -1.0 && n[i - 1] - n[i] < -1.0) {
+                let d = off.signum();
+                let parabolic = q[i]
+                    + d / (n[i + 1] - n[i - 1])
+                        * ((n[i] - n[i - 1] + d) * (q[i + 1] - q[i]) / (n[i + 1] - n[i])
+                            + (n[i + 1] - n[i] - d) * (q[i] - q[i - 1]) / (n[i] - n[i - 1]));
+                q[i] = if q[i - 1] < parabolic && parabolic < q[i + 1] {
+                    parabolic
+                } else {
+                    let j = if d > 0.0 { i + 1 } else { i - 1 };
+                    q[i] + d * (q[j] - q[i]) / (n[j] - n[i])
+                };
+                n[i] += d;
+            }
+        }
+    }
+
+    fn value(&self) -> f64 {
+        if self.count >= 5 {
+            return self.heights[2];
+        }
+        // Exact, interpolating between the order statistics
+        let mut seen = self.heights[..self.count].to_vec();
+        seen.sort_by(f64::total_cmp);
+        match seen.len() {
+            0 => f64::NAN,
+            len => {
+                let rank = self.p * (len - 1) as f64;
+                let (lo, hi) = (rank.floor() as usize, rank.ceil() as usize);
+                seen[lo] + (rank - lo as f64) * (seen[hi] - seen[lo])
+            }
+        }
+    }
+}
+
+/// Runs `trials` repetitions of `run` on `compartments`, each with its own
+/// seed for stochastic gating and Poisson inputs, and summarizes them. The
+/// model is set up once and every trial restarts from a copy of that state.
+/// Trials run `threads` at a time and are folded into the summary in trial
+/// order, so results do not depend on the thread count.
+pub fn repeat(
+    compartments: &Compartments,
+    run: &RepeatedRun,
+    trials: usize,
+    mode: RepeatMode,
+) -> Result<RepeatResult, String> {
+    if trials == 0 {
+        return Err("Need at least one trial".to_owned());
+    }
+    let n = compartments.components.len();
+    let record: Vec<usize> = if run.record.is_empty() {
+        (1..n).collect()
+    } else {
+        run.record.clone()
+    };
+    if let Some(&idx) = record.iter().find(|&&idx| idx == 0 || idx >= n) {
+        return Err(format!("No compartment at index {}", idx));
+    }
+    let percentiles = match &mode {
+        RepeatMode::KeepAll => Vec::new(),
+        RepeatMode::SummaryOnly { percentiles } => percentiles.clone(),
+    };
+    if let Some(p) = percentiles.iter().find(|p| !(0.0..=100.0).contains(*p)) {
+        return Err(format!("Percentiles must be from 0 to 100, got {}", p));
+    }
+    let keep = mode == RepeatMode::KeepAll;
+    if let (true, Some(limit)) = (keep, run.max_kept_bytes) {
+        let bytes = trials * n * (run.steps + 1) * std::mem::size_of::<f64>();
+        if bytes > limit {
+            return Err(format!(
+                "Keeping {} trials needs {} bytes, over the limit of {}",
+                trials, bytes, limit
+            ));
+        }
+    }
+    let initial = Simulation::new(compartments, run.dt).map_err(|e| e.to_string())?;
+    let t_stop = run.steps as f64 * run.dt;
+    let seeds: Vec<u64> = (0..trials as u64).map(|t| sub_seed(run.seed, t)).collect();
+
+    let trial = |seed: u64| -> Result<SimulationResult, String> {
+        let mut stimuli = run.stimuli.clone();
+        for (k, input) in run.inputs.iter().enumerate() {
+            let mut source = input.source.clone();
+            if let SpikeTrainSource::Poisson { seed: own, .. } = &mut source {
+                *own = sub_seed(seed, k as u64);
+            }
+            let current = input
+                .kernel
+                .render_train(&source.events()?, run.dt, t_stop)?;
+            stimuli.push((input.idx, current));
+        }
+        let mut simulation = initial.clone();
+        simulation.reseed(seed);
+        simulation
+            .run(run.steps, &stimuli)
+            .map_err(|e| e.to_string())
+    };
+
+    let samples = run.steps + 1;
+    let mut welford: Vec<Option<Welford>> = vec![None; n];
+    let mut estimates: Vec<Vec<Vec<P2>>> = vec![Vec::new(); n];
+    let mut rasters: Vec<Vec<Vec<f64>>> = vec![Vec::new(); n];
+    for &idx in &record {
+        welford[idx] = Some(Welford::new(samples));
+        estimates[idx] = percentiles
+            .iter()
+            .map(|p| vec![P2::new(p / 100.0); samples])
+            .collect();
+    }
+    let threads = match run.threads {
+        0 => thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    }
+    .clamp(1, trials);
+    let mut kept = Vec::new();
+    let mut peak_held_trials = 0;
+    for wave in seeds.chunks(threads) {
+        let results: Vec<Result<SimulationResult, String>> = thread::scope(|scope| {
+            let workers: Vec<_> = wave
+                .iter()
+                .map(|&seed| scope.spawn(move || trial(seed)))
+                .collect();
+            workers
+                .into_iter()
+                .map(|w| {
+                    w.join()
+                        .unwrap_or_else(|_| Err("A trial panicked".to_owned()))
+                })
+                .collect()
+        });
+        peak_held_trials = peak_held_trials.max(kept.len() + results.len());
+        for result in results {
+            let result = result?;
+            for &idx in &record {
+                let trace = &result.voltages[idx];
+                if let Some(w) = &mut welford[idx] {
+                    w.add(trace);
+                }
+                for estimate in &mut estimates[idx] {
+                    for (p2, &v) in estimate.iter_mut().zip(trace) {
+                        p2.add(v);
+                    }
+                }
+                rasters[idx].push(
+                    ReferenceTrace::from_simulation(&result, idx)?.spike_times(run.spike_threshold),
+                );
+            }
+            if keep {
+                kept.push(result);
+            }
+        }
+    }
+
+    let summary = TrialSummary {
+        trials,
+        mean: welford
+            .iter()
+            .map(|w| w.as_ref().map_or(Vec::new(), |w| w.mean.clone()))
+            .collect(),
+        variance: welford
+            .iter()
+            .map(|w| w.as_ref().map_or(Vec::new(), Welford::variance))
+            .collect(),
+        percentiles: percentiles
+            .iter()
+            .enumerate()
+            .map(|(k, &p)| {
+                let traces = estimates
+                    .iter()
+                    .map(|e| {
+                        e.get(k)
+                            .map_or(Vec::new(), |e| e.iter().map(P2::value).collect())
+                    })
+                    .collect();
+                (p, traces)
+            })
+            .collect(),
+    };
+    Ok(RepeatResult {
+        dt: run.dt,
+        seeds,
+        trials: kept,
+        summary,
+        rasters,
+        peak_held_trials,
+    })
+}
@@ -0,0 +1,454 @@
+//! Named current-clamp protocols, so that everyone in a lab runs the same
+//! step family, ZAP sweep or theta burst instead of their own take on it.
+//!
+//! Every preset has defaults, listed by `available`, and a short text form,
+//! its spec: the preset's name followed by whichever parameters differ, e.g.
+//! `zap f0=0.5 f1=40 duration=20000`. `Protocol::to_spec` writes every
+//! parameter exactly, so a protocol read back from its spec renders the same
+//! waveform bit for bit. Experiment files take specs as stimuli of kind
+//! `protocol`, see `experiment`.
+//!
+//! Times are in ms, frequencies in Hz and currents in nA.
+
+use std::f64::consts::PI;
+
+use crate::parameters::edit_distance;
+use crate::stimulus::Stimulus;
+use crate::swc_reader::format_float;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Protocol {
+    /// `count` current steps, one per sweep, from `first` up by
+    /// `increment`; the smallest that makes the cell fire is its rheobase
+    StepFamily {
+        start: f64,
+        duration: f64,
+        first: f64,
+        increment: f64,
+        count: usize,
+    },
+    /// ZAP current: a sine of `amplitude` whose frequency sweeps
+    /// exponentially from `f0` at `start` to `f1` at `start + duration`,
+    /// see `analysis::impedance_profile`
+    Chirp {
+        start: f64,
+        duration: f64,
+        f0: f64,
+        f1: f64,
+        amplitude: f64,
+    },
+    /// `bursts` bursts, `burst_interval` apart, of `spikes` synaptic events
+    /// `spike_interval` apart, each an alpha current of time constant `tau`
+    /// peaking at `amplitude`
+    ThetaBurst {
+        start: f64,
+        bursts: usize,
+        spikes: usize,
+        spike_interval: f64,
+        burst_interval: f64,
+        tau: f64,
+        amplitude: f64,
+    },
+}
+
+/// A protocol as the library ships it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Preset {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub defaults: Protocol,
+}
+
+/// Every preset, with its defaults
+pub fn available() -> Vec<Preset> {
+    vec![
+        Preset {
+            name: "rheobase_steps",
+            description: "500 ms current steps from 0.05 to 0.5 nA, one per sweep",
+            defaults: Protocol::StepFamily {
+                start: 100.0,
+                duration: 500.0,
+                first: 0.05,
+                increment: 0.05,
+                count: 10,
+            },
+        },
+        Preset {
+            name: "zap",
+            description: "10 s exponential chirp from 0.5 to 40 Hz, for impedance profiles",
+            defaults: Protocol::Chirp {
+                start: 100.0,
+                duration: 10000.0,
+                f0: 0.5,
+                f1: 40.0,
+                amplitude: 0.05,
+            },
+        },
+        Preset {
+            name: "theta_burst",
+            description: "10 bursts at 5 Hz of 4 alpha synaptic currents at 100 Hz",
+            defaults: Protocol::ThetaBurst {
+                start: 100.0,
+                bursts: 10,
+                spikes: 4,
+                spike_interval: 10.0,
+                burst_interval: 200.0,
+                tau: 2.0,
+                amplitude: 0.5,
+            },
+        },
+    ]
+}
+
+impl Protocol {
+    /// The defaults of the preset `name`
+    pub fn preset(name: &str) -> Result<Protocol, String> {
+        let presets = available();
+        match presets.iter().find(|p| p.name == name) {
+            Some(p) => Ok(p.defaults.clone()),
+            None => {
+                let names: Vec<&str> = presets.iter().map(|p| p.name).collect();
+                Err(format!(
+                    "Unknown protocol '{}'; available: {}",
+                    name,
+                    names.join(", ")
+                ))
+            }
+        }
+    }
+
+    /// Name of the preset this is a variant of
+    pub fn name(&self) -> &'static str {
+        match self {
+            Protocol::StepFamily { .. } => "rheobase_steps",
+            Protocol::Chirp { .. } => "zap",
+            Protocol::ThetaBurst { .. } => "theta_burst",
+        }
+    }
+
+    /// Every parameter, in spec order
+    pub fn parameters(&self) -> Vec<(&'static str, f64)> {
+        match *self {
+            Protocol::StepFamily {
+                start,
+                duration,
+                first,
+                increment,
+                count,
+            } => vec![
+                ("start", start),
+                ("duration", duration),
+                ("first", first),
+                ("increment", increment),
+                ("count", count as f64),
+            ],
+            Protocol::Chirp {
+                start,
+                duration,
+                f0,
+                f1,
+                amplitude,
+            } => vec![
+                ("start", start),
+                ("duration", duration),
+                ("f0", f0),
+                ("f1", f1),
+                ("amplitude", amplitude),
+            ],
+            Protocol::ThetaBurst {
+                start,
+                bursts,
+                spikes,
+                spike_interval,
+                burst_interval,
+                tau,
+                amplitude,
+            } => vec![
+                ("start", start),
+                ("bursts", bursts as f64),
+                ("spikes", spikes as f64),
+                ("spike_interval", spike_interval),
+                ("burst_interval", burst_interval),
+                ("tau", tau),
+                ("amplitude", amplitude),
+            ],
+        }
+    }
+
+    /// Sets one parameter, by its spec name
+    pub fn set(&mut self, key: &str, value: f64) -> Result<(), String> {
+        let whole = |value: f64| {
+            if value >= 0.0 && value.fract() == 0.0 && value <= u32::MAX as f64 {
+                Ok(value as usize)
+            } else {
+                Err(format!("'{}' must be a whole number, got {}", key, value))
+            }
+        };
+        match (self, key) {
+            (
+                Protocol::StepFamily { start: x, .. }
+                | Protocol::Chirp { start: x, .. }
+                | Protocol::ThetaBurst { start: x, .. },
+                "start",
+            )
+            | (
+                Protocol::StepFamily { duration: x, .. } | Protocol::Chirp { duration: x, .. },
+                "duration",
+            )
+            | (Protocol::StepFamily { first: x, .. }, "first")
+            | (Protocol::StepFamily { increment: x, .. }, "increment")
+            | (Protocol::Chirp { f0: x, .. }, "f0")
+            | (Protocol::Chirp { f1: x, .. }, "f1")
+            | (
+                Protocol::Chirp { amplitude: x, .. } | Protocol::ThetaBurst { amplitude: x, .. },
+                "amplitude",
+            )
+            | (
+                Protocol::ThetaBurst {
+                    spike_interval: x, ..
+                },
+                "spike_interval",
+            )
+            | (
+                Protocol::ThetaBurst {
+                    burst_interval: x, ..
+                },
+                "burst_interval",
+            )
+            | (Protocol::ThetaBurst { tau: x, .. }, "tau") => *x = value,
+            (Protocol::StepFamily { count: n, .. }, "count")
+            | (Protocol::ThetaBurst { bursts: n, .. }, "bursts")
+            | (Protocol::ThetaBurst { spikes: n, .. }, "spikes") => *n = whole(value)?,
+            (protocol, _) => {
+                let names: Vec<&str> = protocol.parameters().iter().map(|p| p.0).collect();
+                let closest = names
+                    .iter()
+                    .min_by_key(|n| edit_distance(key, n))
+                    .filter(|n| edit_distance(key, n) <= 2);
+                return Err(match closest {
+                    Some(n) => format!(
+                        "Unknown parameter '{}' of {}; did you mean '{}'?",
+                        key,
+                        protocol.name(),
+                        n
+                    ),
+                    None => format!(
+                        "Unknown parameter '{}' of {}; expected one of {}",
+                        key,
+                        protocol.name(),
+                        names.join(", ")
+                    ),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads a spec: a preset name, then `key=value` for every parameter
+    /// that differs from the preset's defaults
+    pub fn from_spec(spec: &str) -> Result<Protocol, String> {
+        let mut words = spec.split_whitespace();
+        let name = words.next().ok_or("Empty protocol spec")?;
+        let mut protocol = Protocol::preset(name)?;
+        for word in words {
+            let (key, value) = word
+                .split_once('=')
+                .ok_or_else(|| format!("Expected key=value, got '{}'", word))?;
+            let value: f64 = value
+                .parse()
+                .map_err(|_| format!("'{}' is not a number in '{}'", value, word))?;
+            protocol.set(key, value)?;
+        }
+        protocol.validate()?;
+        Ok(protocol)
+    }
+
+    /// The spec with every parameter written out, numbers exactly
+    pub fn to_spec(&self) -> String {
+        let mut spec = self.name().to_owned();
+        for (key, value) in self.parameters() {
+            let text = match key {
+                "count" | "bursts" | "spikes" => (value as usize).to_string(),
+                _ => format_float(value),
+            };
+            spec.push_str(&format!(" {}={}", key, text));
+        }
+        spec
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let parameters = self.parameters();
+        if let Some((name, value)) = parameters.iter().find(|(_, v)| !v.is_finite()) {
+            return Err(format!("'{}' must be finite, got {}", name, value));
+        }
+        let positive = |name: &str, value: f64| {
+            if value > 0.0 {
+                Ok(())
+            } else {
+                Err(format!("'{}' must be positive, got {}", name, value))
+            }
+        };
+        // Every protocol starts with its start
+        let start = parameters[0].1;
+        if start < 0.0 {
+            return Err(format!("'start' must be non-negative, got {}", start));
+        }
+        match *self {
+            Protocol::StepFamily {
+                duration, count, ..
+            } => {
+                positive("duration", duration)?;
+                positive("count", count as f64)
+            }
+            Protocol::Chirp {
+                duration, f0, f1, ..
+            } => {
+                positive("duration", duration)?;
+                positive("f0", f0)?;
+                positive("f1", f1)
+            }
+            Protocol::ThetaBurst {
+                bursts,
+                spikes,
+                spike_interval,
+                burst_interval,
+                tau,
+                ..
+            } => {
+                positive("bursts", bursts as f64)?;
+                positive("spikes", spikes as f64)?;
+                positive("spike_interval", spike_interval)?;
+                positive("burst_interval", burst_interval)?;
+                positive("tau", tau)?;
+                if (spikes - 1) as f64 * spike_interval >= burst_interval {
+                    return Err(format!(
+                        "Bursts of {} spikes {} ms apart overlap when {} ms apart",
+                        spikes, spike_interval, burst_interval
+                    ));
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Runs the protocol takes, one waveform each
+    pub fn sweeps(&self) -> usize {
+        match *self {
+            Protocol::StepFamily { count, .. } => count,
+            _ => 1,
+        }
+    }
+
+    /// Onsets of the synaptic events of a theta burst, in order; none for
+    /// other protocols
+    pub fn events(&self) -> Vec<f64> {
+        let Protocol::ThetaBurst {
+            start,
+            bursts,
+            spikes,
+            spike_interval,
+            burst_interval,
+            ..
+        } = *self
+        else {
+            return Vec::new();
+        };
+        (0..bursts)
+            .flat_map(|b| {
+                (0..spikes)
+                    .map(move |s| start + b as f64 * burst_interval + s as f64 * spike_interval)
+            })
+            .collect()
+    }
+
+    /// Phase of a chirp in radians, `s` ms after its start
+    fn chirp_phase(f0: f64, f1: f64, duration: f64, s: f64) -> f64 {
+        // Frequencies are per second and times in ms
+        let ratio = f1 / f0;
+        if (ratio - 1.0).abs() < 1e-12 {
+            return 2.0 * PI * f0 * s / 1000.0;
+        }
+        2.0 * PI * f0 * duration / ratio.ln() * (ratio.powf(s / duration) - 1.0) / 1000.0
+    }
+
+    /// Current of `sweep` at time `t`
+    pub fn value_at(&self, sweep: usize, t: f64) -> f64 {
+        match *self {
+            Protocol::StepFamily {
+                start,
+                duration,
+                first,
+                increment,
+                ..
+            } if (start..start + duration).contains(&t) => first + sweep as f64 * increment,
+            Protocol::Chirp {
+                start,
+                duration,
+                f0,
+                f1,
+                amplitude,
+            } if (start..start + duration).contains(&t) => {
+                amplitude * Protocol::chirp_phase(f0, f1, duration, t - start).sin()
+            }
+            Protocol::ThetaBurst { tau, amplitude, .. } => self
+                .events()
+                .iter()
+                .map(|&onset| {
+                    Stimulus::Alpha {
+                        onset,
+                        tau,
+                        amplitude,
+                    }
+                    .value_at(t)
+                })
+                .sum(),
+            _ => 0.0,
+        }
+    }
+
+    /// The current of `sweep` over each of `steps` steps of `dt`, as
+    /// `Simulation::run` takes it. Steps and synaptic currents are exact
+    /// averages over each step, see `stimulus`; a chirp is sampled at the
+    /// middle of each step.
+    pub fn render(&self, sweep: usize, dt: f64, steps: usize) -> Result<Vec<f64>, String> {
+        self.validate()?;
+        if !(dt > 0.0 && dt.is_finite()) {
+            return Err(format!("Time step must be positive, got {}", dt));
+        }
+        if sweep >= self.sweeps() {
+            return Err(format!(
+                "No sweep {} of {}, which has {}",
+                sweep,
+                self.name(),
+                self.sweeps()
+            ));
+        }
+        match *self {
+            Protocol::StepFamily {
+                start, duration, ..
+            } => {
+                let amplitude = self.value_at(sweep, start);
+                Ok((0..steps)
+                    .map(|s| {
+                        let (t0, t1) = (s as f64 * dt, (s + 1) as f64 * dt);
+                        let overlap = (t1.min(start + duration) - t0.max(start)).max(0.0);
+                        amplitude * overlap / dt
+                    })
+                    .collect())
+            }
+            Protocol::Chirp { .. } => Ok((0..steps)
+                .map(|s| self.value_at(sweep, (s as f64 + 0.5) * dt))
+                .collect()),
+            Protocol::ThetaBurst { tau, amplitude, .. } => {
+                let kernel = Stimulus::Alpha {
+                    onset: 0.0,
+                    tau,
+                    amplitude,
+                };
+                let mut values = kernel.render_train(&self.events(), dt, steps as f64 * dt)?;
+                values.resize(steps, 0.0);
+                Ok(values)
+            }
+        }
+    }
+}
@@ -0,0 +1,440 @@
+//! Python side of the error types. Every binding returns `PyResult` and
+//! turns Rust errors into Python ones through the `From` impls here, so
+//! each failure class raises its own exception type:
+//!
+//! ```text
+//! CompartmentError
+//! ├── SwcIoError
+//! ├── SwcParseError
+//! ├── SwcValidationError
+//! ├── ChecksumError
+//! ├── LimitExceededError
+//! ├── ParameterError
+//! └── SimulationError
+//!     ├── DivergenceError
+//!     └── SessionClosedError
+//! ```
+//!
+//! Every exception carries `code` (the `codes::REGISTRY` id) and `path`,
+//! `line_no` and `compartment_idx`, each None when it does not apply.
+//! Checksum errors add `expected` and `actual`, limit errors `unit`,
+//! `limit` and `observed`, divergence errors `time` and, for a spine,
+//! `spine_idx`. Store and recipe errors, which have no class of their own,
+//! raise `CompartmentError` itself.
+//!
+//! Failures that have no code yet raise the builtin `ValueError` or
+//! `OSError` through `invalid_argument` and `os_error`, so that every
+//! binding still goes through this module.
+//!
+//! Deprecated entry points warn with `CompartmentDeprecationWarning`, a
+//! `DeprecationWarning`, see `deprecation`.
+
+use std::collections::HashSet;
+use std::sync::{LazyLock, Mutex};
+
+use pyo3::PyTypeInfo;
+use pyo3::create_exception;
+use pyo3::exceptions::{PyDeprecationWarning, PyException, PyOSError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyModule;
+
+use crate::codes::Code;
+use crate::deprecation;
+use crate::error::SwcError;
+use crate::parameters::ParamError;
+use crate::solver::SimError;
+use crate::standardize::DatasetError;
+use crate::state::StateError;
+use crate::store::StoreError;
+
+create_exception!(
+    compartment_rs,
+    CompartmentError,
+    PyException,
+    "Base class of every error raised by compartment_rs"
+);
+create_exception!(
+    compartment_rs,
+    SwcIoError,
+    CompartmentError,
+    "Reading or writing a file failed"
+);
+create_exception!(
+    compartment_rs,
+    SwcParseError,
+    CompartmentError,
+    "The input could not be read as SWC"
+);
+create_exception!(
+    compartment_rs,
+    SwcValidationError,
+    CompartmentError,
+    "The input parsed but does not describe a valid skeleton"
+);
+create_exception!(
+    compartment_rs,
+    ChecksumError,
+    CompartmentError,
+    "The input's sha256 is not the expected one"
+);
+create_exception!(
+    compartment_rs,
+    LimitExceededError,
+    CompartmentError,
+    "The input exceeds one of the reader's limits"
+);
+create_exception!(
+    compartment_rs,
+    ParameterError,
+    CompartmentError,
+    "A model parameter could not be read or set"
+);
+create_exception!(
+    compartment_rs,
+    SimulationError,
+    CompartmentError,
+    "A simulation could not be run"
+);
+create_exception!(
+    compartment_rs,
+    DivergenceError,
+    SimulationError,
+    "A simulation blew up numerically"
+);
+create_exception!(
+    compartment_rs,
+    SessionClosedError,
+    SimulationError,
+    "A simulation session was used after it was closed"
+);
+
+create_exception!(
+    compartment_rs,
+    CompartmentDeprecationWarning,
+    PyDeprecationWarning,
+    "An old entry point was called; `replacement` names the one to call instead"
+);
+
+/// Adds the exception classes to the module
+pub(crate) fn register(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    let py = m.py();
+    m.add("CompartmentError", py.get_type::<CompartmentError>())?;
+    m.add("SwcIoError", py.get_type::<SwcIoError>())?;
+    m.add("SwcParseError", py.get_type::<SwcParseError>())?;
+    m.add("SwcValidationError", py.get_type::<SwcValidationError>())?;
+    m.add("ChecksumError", py.get_type::<ChecksumError>())?;
+    m.add("LimitExceededError", py.get_type::<LimitExceededError>())?;
+    m.add("ParameterError", py.get_type::<ParameterError>())?;
+    m.add("SimulationError", py.get_type::<SimulationError>())?;
+    m.add("DivergenceError", py.get_type::<DivergenceError>())?;
+    m.add("SessionClosedError", py.get_type::<SessionClosedError>())?;
+    m.add(
+        "CompartmentDeprecationWarning",
+        py.get_type::<CompartmentDeprecationWarning>(),
+    )?;
+    Ok(())
+}
+
+/// A shim and the file and line of Python it was called from
+type CallSite = (&'static str, String, u32);
+
+/// Call sites that already warned
+static WARNED: LazyLock<Mutex<HashSet<CallSite>>> = LazyLock::new(Mutex::default);
+
+/// Warns that the shim `name` was called, once per line of Python calling
+/// it. The warning carries `name`, `replacement`, `since` and `removal`.
+/// Fails if a warnings filter turns it into an error.
+pub(crate) fn warn_deprecated(py: Python<'_>, name: &str) -> PyResult<()> {
+    let shim = deprecation::shim(name).expect("every shim is in deprecation::SHIMS");
+    // Called from Rust, the innermost frame is the caller's
+    let frame = py.import("sys")?.getattr("_getframe")?.call1((0,));
+    let (file, line) = match frame {
+        Ok(frame) => (
+            frame.getattr("f_code")?.getattr("co_filename")?.extract()?,
+            frame.getattr("f_lineno")?.extract()?,
+        ),
+        Err(_) => (String::new(), 0),
+    };
+    let first = WARNED
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert((shim.name, file, line));
+    if !first {
+        return Ok(());
+    }
+    let message = format!(
+        "{} is deprecated since {} and goes in {}; use {}",
+        shim.name, shim.since, shim.removal, shim.replacement
+    );
+    let warning = py
+        .get_type::<CompartmentDeprecationWarning>()
+        .call1((message,))?;
+    warning.setattr("name", shim.name)?;
+    warning.setattr("replacement", shim.replacement)?;
+    warning.setattr("since", shim.since)?;
+    warning.setattr("removal", shim.removal)?;
+    py.import("warnings")?.call_method1(
+        "warn",
+        (warning, py.get_type::<CompartmentDeprecationWarning>(), 1),
+    )?;
+    Ok(())
+}
+
+/// Structured context attached to every exception
+#[derive(Default)]
+struct Context {
+    path: Option<String>,
+    line_no: Option<usize>,
+    compartment_idx: Option<usize>,
+    /// Further attributes specific to one exception class
+    extra: Vec<(&'static str, Attribute)>,
+}
+
+enum Attribute {
+    Str(String),
+    Int(u64),
+    Float(f64),
+}
+
+fn raise<E: PyTypeInfo>(code: Code, message: String, context: Context) -> PyErr {
+    Python::attach(|py| {
+        let err = PyErr::new::<E, _>(message);
+        let value = err.value(py);
+        let attributes = [
+            ("code", Some(code.id()).into_pyobject(py)),
+            ("path", context.path.into_pyobject(py)),
+            ("line_no", context.line_no.into_pyobject(py)),
+            ("compartment_idx", context.compartment_idx.into_pyobject(py)),
+        ];
+        for (name, attribute) in attributes {
+            // Setting attributes on a fresh exception instance cannot fail
+            // short of running out of memory
+            let Ok(a) = attribute;
+            let _ = value.setattr(name, a);
+        }
+        for (name, attribute) in context.extra {
+            let _ = match attribute {
+                Attribute::Str(s) => value.setattr(name, s),
+                Attribute::Int(v) => value.setattr(name, v),
+                Attribute::Float(v) => value.setattr(name, v),
+            };
+        }
+        err
+    })
+}
+
+/// Raises `code` as the exception class it belongs to. Exhaustive, so a new
+/// code has to pick its class here.
+fn raise_code(code: Code, message: String, context: Context) -> PyErr {
+    match code {
+        Code::ReadFailure
+        | Code::MissingField
+        | Code::InvalidField
+        | Code::DecimalComma
+        | Code::MalformedScale
+        | Code::NotSwc
+        | Code::UnsupportedFile => raise::<SwcParseError>(code, message, context),
+        Code::LengthMismatch
+        | Code::InvalidNodeId
+        | Code::InvalidParentId
+        | Code::DanglingParent
+        | Code::StrictZeroRadius
+        | Code::NoRoot
+        | Code::DuplicateNodeId
+        | Code::RejectedCell => raise::<SwcValidationError>(code, message, context),
+        Code::Io | Code::OutputFailure | Code::StoreIo | Code::DatasetIo | Code::BundleFailure => {
+            raise::<SwcIoError>(code, message, context)
+        }
+        Code::ChecksumMismatch => raise::<ChecksumError>(code, message, context),
+        Code::LimitExceeded => raise::<LimitExceededError>(code, message, context),
+        Code::UnknownParameter
+        | Code::UnknownSection
+        | Code::InvalidPosition
+        | Code::ReadOnlyParameter
+        | Code::MalformedParameterRow
+        | Code::UnknownTag
+        | Code::MissingMechanism => raise::<ParameterError>(code, message, context),
+        // Warnings only become errors in strict mode, and then the input is
+        // what is wrong
+        Code::ZeroRadius
+        | Code::Unreachable
+        | Code::RallMismatch
+        | Code::HighBranchingDegree
+        | Code::NonPositiveRadius
+        | Code::RadiusOutlier
+        | Code::OrphanedFragment
+        | Code::SpacingGap
+        | Code::SuspectUnits => raise::<SwcValidationError>(code, message, context),
+        Code::UnknownStatePath
+        | Code::NoSuchCompartment
+        | Code::StateUnavailable
+        | Code::ReadOnlyState
+        | Code::StateOutOfRange
+        | Code::InvalidModel
+        | Code::NoCompartment
+        | Code::NoSpine
+        | Code::InvalidSimulationInput
+        | Code::IonDepleted
+        | Code::SolverPanicked => raise::<SimulationError>(code, message, context),
+        Code::Diverged => raise::<DivergenceError>(code, message, context),
+        Code::SessionClosed => raise::<SessionClosedError>(code, message, context),
+        // Stores and recipes have no class of their own
+        Code::NotAStore
+        | Code::CorruptStore
+        | Code::UnknownRun
+        | Code::InvalidStoreEntry
+        | Code::MalformedRecipe
+        | Code::OutputCollision
+        | Code::StandardizePanicked => raise::<CompartmentError>(code, message, context),
+    }
+}
+
+impl From<SwcError> for PyErr {
+    fn from(err: SwcError) -> PyErr {
+        let code = err.code();
+        let message = err.to_string();
+        match err {
+            SwcError::Invalid { code, line, .. } => {
+                let context = Context {
+                    line_no: line,
+                    ..Default::default()
+                };
+                raise_code(code, message, context)
+            }
+            SwcError::Io { path, .. } => {
+                let context = Context {
+                    path: Some(path.display().to_string()),
+                    ..Default::default()
+                };
+                raise::<SwcIoError>(code, message, context)
+            }
+            SwcError::ChecksumMismatch { expected, actual } => {
+                let context = Context {
+                    extra: vec![
+                        ("expected", Attribute::Str(expected)),
+                        ("actual", Attribute::Str(actual)),
+                    ],
+                    ..Default::default()
+                };
+                raise::<ChecksumError>(code, message, context)
+            }
+            SwcError::LimitExceeded {
+                which,
+                limit,
+                observed,
+            } => {
+                let context = Context {
+                    extra: vec![
+                        ("unit", Attribute::Str(which.to_string())),
+                        ("limit", Attribute::Int(limit)),
+                        ("observed", Attribute::Int(observed)),
+                    ],
+                    ..Default::default()
+                };
+                raise::<LimitExceededError>(code, message, context)
+            }
+        }
+    }
+}
+
+impl From<ParamError> for PyErr {
+    fn from(err: ParamError) -> PyErr {
+        let line_no = match err {
+            ParamError::MalformedRow { line, .. } => Some(line),
+            _ => None,
+        };
+        let context = Context {
+            line_no,
+            ..Default::default()
+        };
+        raise::<ParameterError>(err.code(), err.to_string(), context)
+    }
+}
+
+impl From<StateError> for PyErr {
+    fn from(err: StateError) -> PyErr {
+        let context = Context {
+            compartment_idx: err.compartment_idx(),
+            ..Default::default()
+        };
+        raise::<SimulationError>(err.code(), err.to_string(), context)
+    }
+}
+
+impl From<SimError> for PyErr {
+    fn from(err: SimError) -> PyErr {
+        let code = err.code();
+        let message = err.to_string();
+        let mut context = Context {
+            compartment_idx: err.compartment_idx(),
+            ..Default::default()
+        };
+        match err {
+            SimError::State(e) => return e.into(),
+            SimError::Diverged {
+                spine_idx, time, ..
+            } => {
+                context.extra.push(("time", Attribute::Float(time)));
+                if let Some(k) = spine_idx {
+                    context.extra.push(("spine_idx", Attribute::Int(k as u64)));
+                }
+            }
+            SimError::Output { path, .. } => context.path = Some(path.display().to_string()),
+            _ => {}
+        }
+        raise_code(code, message, context)
+    }
+}
+
+impl From<StoreError> for PyErr {
+    fn from(err: StoreError) -> PyErr {
+        let path = match &err {
+            StoreError::Io { path, .. }
+            | StoreError::NotAStore { path }
+            | StoreError::Corrupt { path, .. }
+            | StoreError::UnknownRun { path, .. } => Some(path.display().to_string()),
+            _ => None,
+        };
+        match err {
+            StoreError::Simulation(e) => e.into(),
+            err => {
+                let context = Context {
+                    path,
+                    ..Default::default()
+                };
+                raise_code(err.code(), err.to_string(), context)
+            }
+        }
+    }
+}
+
+impl From<DatasetError> for PyErr {
+    fn from(err: DatasetError) -> PyErr {
+        let path = match &err {
+            DatasetError::Io { path, .. }
+            | DatasetError::Unsupported { path, .. }
+            | DatasetError::Bundle { path, .. } => Some(path.display().to_string()),
+            _ => None,
+        };
+        match err {
+            DatasetError::Read(e) => e.into(),
+            err => {
+                let context = Context {
+                    path,
+                    ..Default::default()
+                };
+                raise_code(err.code(), err.to_string(), context)
+            }
+        }
+    }
+}
+
+/// A bad argument that has no code of its own
+pub(crate) fn invalid_argument(message: impl Into<String>) -> PyErr {
+    PyValueError::new_err(message.into())
+}
+
+/// A failed read or write that has no code of its own
+pub(crate) fn os_error(message: impl Into<String>) -> PyErr {
+    PyOSError::new_err(message.into())
+}
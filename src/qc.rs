@@ -0,0 +1,527 @@
+//! One number for how trustworthy a reconstruction is, for triaging a
+//! dataset before spending compute on it.
+//!
+//! `score` runs every `Check` over a cell and turns each into a sub-score
+//! in [0, 1]: 1 when nothing is flagged, falling linearly to 0 once
+//! `QcRubric::saturation` of the places looked at are. The score is the
+//! weighted mean of the sub-scores, so a check weighted 0 has no say. A
+//! cell passes when its score reaches `QcRubric::pass_threshold`;
+//! `standardize` can quarantine the ones that do not.
+//!
+//! The checks reuse `validation::rall_qc` and the branching degree limit of
+//! `validation::branching_qc`, and flag nodes with the same `QcWarning`s.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::analysis::{RallBand, median, rall_ratios};
+use crate::codes::Code;
+use crate::standardize::{Dataset, read_source};
+use crate::swc_reader::{
+    ConflictPolicy, Node, NodeFlags, ReaderOptions, Skeleton, StructureIdentifier,
+    swc_reader_from_bytes,
+};
+use crate::validation::{HIGH_BRANCHING_DEGREE, QcWarning, rall_qc};
+use crate::warnings::WarningKind;
+use crate::write;
+
+/// Median neurite radius, in µm, outside of which units are suspect. Above
+/// is `standardize`'s cue for nanometres.
+const PLAUSIBLE_RADIUS: (f64, f64) = (0.05, 20.0);
+
+/// Farthest a node may be from the root, in µm, before units are suspect.
+/// Below, a cell of more than one node is more likely in mm.
+const PLAUSIBLE_EXTENT: (f64, f64) = (1.0, 5000.0);
+
+/// One thing `score` looks at
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Check {
+    /// Nodes whose radius was zero or negative, repaired or not
+    NonPositiveRadius,
+    /// Neurite radii more than `radius_outlier_factor` from the median
+    RadiusOutliers,
+    /// Nodes not connected to the root, including those the reader left out
+    OrphanedFragments,
+    /// Branch points outside `rall_band`, see `analysis::rall_ratios`
+    RallRatio,
+    /// Branch points off the soma with `max_branching_degree` or more
+    /// children
+    BranchingDegree,
+    /// Segments more than `gap_factor` times the median segment length
+    SpacingGaps,
+    /// The median neurite radius and the extent from the root, both of
+    /// which fail the sub-score outright when implausible for µm
+    Units,
+}
+
+impl Check {
+    /// In the order of `QcReport::sub_scores`
+    pub const ALL: [Check; 7] = [
+        Check::NonPositiveRadius,
+        Check::RadiusOutliers,
+        Check::OrphanedFragments,
+        Check::RallRatio,
+        Check::BranchingDegree,
+        Check::SpacingGaps,
+        Check::Units,
+    ];
+
+    /// The name in reports and CSV headers
+    pub fn name(self) -> &'static str {
+        match self {
+            Check::NonPositiveRadius => "non_positive_radius",
+            Check::RadiusOutliers => "radius_outliers",
+            Check::OrphanedFragments => "orphaned_fragments",
+            Check::RallRatio => "rall_ratio",
+            Check::BranchingDegree => "branching_degree",
+            Check::SpacingGaps => "spacing_gaps",
+            Check::Units => "units",
+        }
+    }
+}
+
+/// How checks are weighed and where they draw the line
+#[derive(Debug, Clone, PartialEq)]
+pub struct QcRubric {
+    /// Weight of each check in the score; checks left out weigh 0
+    pub weights: BTreeMap<Check, f64>,
+    /// Fraction of flagged places at which a sub-score reaches 0
+    pub saturation: f64,
+    /// How many times larger or smaller than the median a radius may be
+    pub radius_outlier_factor: f64,
+    pub rall_band: RallBand,
+    pub max_branching_degree: usize,
+    /// How many times longer than the median a segment may be
+    pub gap_factor: f64,
+    /// Lowest score that passes
+    pub pass_threshold: f64,
+}
+
+impl Default for QcRubric {
+    fn default() -> Self {
+        QcRubric {
+            weights: Check::ALL.iter().map(|&c| (c, 1.0)).collect(),
+            saturation: 0.2,
+            radius_outlier_factor: 5.0,
+            rall_band: RallBand::default(),
+            max_branching_degree: HIGH_BRANCHING_DEGREE,
+            gap_factor: 10.0,
+            pass_threshold: 0.8,
+        }
+    }
+}
+
+impl QcRubric {
+    /// The default rubric with `check` weighted `weight`
+    pub fn with_weight(mut self, check: Check, weight: f64) -> QcRubric {
+        self.weights.insert(check, weight);
+        self
+    }
+
+    fn weight(&self, check: Check) -> f64 {
+        self.weights.get(&check).copied().unwrap_or(0.0)
+    }
+}
+
+/// How a cell fared in one check
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubScore {
+    pub check: Check,
+    /// In [0, 1], 1 when nothing was flagged
+    pub score: f64,
+    pub weight: f64,
+    /// Places looked at, e.g. nodes or branch points
+    pub checked: usize,
+    pub flagged: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct QcReport {
+    /// Weighted mean of the sub-scores, 1 when every weight is 0
+    pub score: f64,
+    /// One per check, in the order of `Check::ALL`
+    pub sub_scores: Vec<SubScore>,
+    /// Every flagged place, by check
+    pub failures: Vec<QcWarning>,
+    /// Whether `score` reached the rubric's `pass_threshold`
+    pub passed: bool,
+}
+
+impl QcReport {
+    pub fn sub_score(&self, check: Check) -> f64 {
+        self.sub_scores
+            .iter()
+            .find(|s| s.check == check)
+            .map_or(1.0, |s| s.score)
+    }
+
+    /// The checks that flagged anything
+    pub fn failed_checks(&self) -> Vec<Check> {
+        self.sub_scores
+            .iter()
+            .filter(|s| s.flagged > 0)
+            .map(|s| s.check)
+            .collect()
+    }
+}
+
+/// Scores the cell of `nodes`, connected through `parent_child_map` as
+/// `Skeleton` keeps them. Nodes no path from the root reaches count as
+/// orphaned; for those the reader already left out, see `score_skeleton`.
+pub fn score(
+    nodes: &[Node],
+    parent_child_map: &HashMap<u64, Vec<u64>>,
+    rubric: &QcRubric,
+) -> QcReport {
+    score_with_dropped(nodes, parent_child_map, None, rubric)
+}
+
+/// `score`, also counting the nodes the reader left out of `skeleton`
+/// because nothing connected them to the root
+pub fn score_skeleton(skeleton: &Skeleton, rubric: &QcRubric) -> QcReport {
+    let dropped = skeleton
+        .warnings
+        .iter()
+        // Individual warnings come first, the summary last
+        .rfind(|w| w.kind == WarningKind::Unreachable)
+        .map(|w| (w.count, w.first_line, w.last_line));
+    score_with_dropped(&skeleton.nodes, &skeleton.parent_child_map, dropped, rubric)
+}
+
+/// Flagged places of one check
+struct Outcome {
+    checked: usize,
+    flagged: usize,
+    warnings: Vec<QcWarning>,
+}
+
+impl Outcome {
+    fn of(checked: usize, warnings: Vec<QcWarning>) -> Outcome {
+        Outcome {
+            checked,
+            flagged: warnings.len(),
+            warnings,
+        }
+    }
+}
+
+fn warning(code: Code, node: &Node, message: String) -> QcWarning {
+    QcWarning {
+        code,
+        node_id: node.node_id,
+        message,
+    }
+}
+
+fn distance(a: &Node, b: &Node) -> f64 {
+    ((a.x_pos - b.x_pos).powi(2) + (a.y_pos - b.y_pos).powi(2) + (a.z_pos - b.z_pos).powi(2)).sqrt()
+}
+
+/// `score` with `dropped` nodes left out on reading, as count and first
+/// and last line
+fn score_with_dropped(
+    nodes: &[Node],
+    parent_child_map: &HashMap<u64, Vec<u64>>,
+    dropped: Option<(usize, usize, usize)>,
+    rubric: &QcRubric,
+) -> QcReport {
+    let by_id: HashMap<u64, &Node> = nodes.iter().map(|n| (n.node_id, n)).collect();
+    let root = nodes.iter().find(|n| n.parent_id == n.node_id);
+    let is_soma = |n: &Node| n.structured_identifier == StructureIdentifier::Soma;
+    let repaired = |n: &Node| n.radius <= 0.0 || n.flags.contains(NodeFlags::ZERO_RADIUS_FIXED);
+    let children = |n: &Node| -> Vec<u64> {
+        parent_child_map
+            .get(&n.node_id)
+            .map(|c| c.iter().copied().filter(|&c| c != n.node_id).collect())
+            .unwrap_or_default()
+    };
+    // Radii of neurite nodes that were not made up
+    let mut radii: Vec<f64> = nodes
+        .iter()
+        .filter(|n| !is_soma(n) && !repaired(n))
+        .map(|n| n.radius)
+        .collect();
+    let median_radius = median(&mut radii);
+
+    let mut outcomes: BTreeMap<Check, Outcome> = BTreeMap::new();
+
+    outcomes.insert(
+        Check::NonPositiveRadius,
+        Outcome::of(
+            nodes.len(),
+            nodes
+                .iter()
+                .filter(|n| repaired(n))
+                .map(|n| {
+                    let message = if n.radius <= 0.0 {
+                        format!("radius {}", n.radius)
+                    } else {
+                        format!("zero radius, read as {}", n.radius)
+                    };
+                    warning(Code::NonPositiveRadius, n, message)
+                })
+                .collect(),
+        ),
+    );
+
+    let factor = rubric.radius_outlier_factor;
+    let outliers = nodes
+        .iter()
+        .filter(|n| !is_soma(n) && !repaired(n))
+        .filter_map(|n| {
+            let m = median_radius?;
+            (n.radius > m * factor || n.radius < m / factor).then(|| {
+                warning(
+                    Code::RadiusOutlier,
+                    n,
+                    format!("radius {} µm against a median of {} µm", n.radius, m),
+                )
+            })
+        })
+        .collect();
+    outcomes.insert(Check::RadiusOutliers, Outcome::of(radii.len(), outliers));
+
+    let mut reached = HashSet::new();
+    let mut stack: Vec<&Node> = root.into_iter().collect();
+    while let Some(node) = stack.pop() {
+        if reached.insert(node.node_id) {
+            stack.extend(children(node).iter().filter_map(|c| by_id.get(c)));
+        }
+    }
+    let mut orphans = Outcome::of(
+        nodes.len(),
+        nodes
+            .iter()
+            .filter(|n| !reached.contains(&n.node_id))
+            .map(|n| {
+                warning(
+                    Code::OrphanedFragment,
+                    n,
+                    "not connected to the root".to_owned(),
+                )
+            })
+            .collect(),
+    );
+    if let (Some((count, first, last)), Some(root)) = (dropped, root) {
+        orphans.checked += count;
+        orphans.flagged += count;
+        orphans.warnings.push(warning(
+            Code::OrphanedFragment,
+            root,
+            format!(
+                "{} nodes not connected to the root left out on reading, lines {} to {}",
+                count, first, last
+            ),
+        ));
+    }
+    outcomes.insert(Check::OrphanedFragments, orphans);
+
+    let rall = rall_qc(&rall_ratios(nodes, parent_child_map), &rubric.rall_band);
+    outcomes.insert(Check::RallRatio, Outcome::of(rall.checked, rall.warnings));
+
+    let branch_points: Vec<(&Node, usize)> = nodes
+        .iter()
+        .filter(|n| !is_soma(n))
+        .map(|n| (n, children(n).len()))
+        .filter(|&(_, degree)| degree >= 2)
+        .collect();
+    let max_degree = rubric.max_branching_degree;
+    outcomes.insert(
+        Check::BranchingDegree,
+        Outcome::of(
+            branch_points.len(),
+            branch_points
+                .iter()
+                .filter(|&&(_, degree)| degree >= max_degree)
+                .map(|&(n, degree)| {
+                    warning(
+                        Code::HighBranchingDegree,
+                        n,
+                        format!("{} children, {} or more allowed", degree, max_degree),
+                    )
+                })
+                .collect(),
+        ),
+    );
+
+    // Segments within the soma say nothing about the tracing's spacing
+    let segments: Vec<(&Node, f64)> = nodes
+        .iter()
+        .filter(|n| n.parent_id != n.node_id && !is_soma(n))
+        .filter_map(|n| Some((n, distance(n, by_id.get(&n.parent_id)?))))
+        .collect();
+    let mut lengths: Vec<f64> = segments.iter().map(|&(_, l)| l).collect();
+    let median_length = median(&mut lengths);
+    let gaps = segments
+        .iter()
+        .filter_map(|&(n, length)| {
+            let m = median_length?;
+            (length > m * rubric.gap_factor).then(|| {
+                warning(
+                    Code::SpacingGap,
+                    n,
+                    format!("{} µm from its parent against a median of {} µm", length, m),
+                )
+            })
+        })
+        .collect();
+    outcomes.insert(Check::SpacingGaps, Outcome::of(segments.len(), gaps));
+
+    let mut units = Vec::new();
+    let mut looked = 0;
+    if let Some(root) = root {
+        if let Some(m) = median_radius {
+            looked += 1;
+            if !(PLAUSIBLE_RADIUS.0..=PLAUSIBLE_RADIUS.1).contains(&m) {
+                units.push(warning(
+                    Code::SuspectUnits,
+                    root,
+                    format!(
+                        "median neurite radius {} outside {} to {} µm",
+                        m, PLAUSIBLE_RADIUS.0, PLAUSIBLE_RADIUS.1
+                    ),
+                ));
+            }
+        }
+        if nodes.len() > 1 {
+            looked += 1;
+            let extent = nodes.iter().map(|n| distance(n, root)).fold(0.0, f64::max);
+            if !(PLAUSIBLE_EXTENT.0..=PLAUSIBLE_EXTENT.1).contains(&extent) {
+                units.push(warning(
+                    Code::SuspectUnits,
+                    root,
+                    format!(
+                        "extent {} from the root outside {} to {} µm",
+                        extent, PLAUSIBLE_EXTENT.0, PLAUSIBLE_EXTENT.1
+                    ),
+                ));
+            }
+        }
+    }
+    let mut units = Outcome::of(looked, units);
+    // Either heuristic failing is enough
+    units.flagged = units.flagged.min(1);
+    units.checked = units.checked.min(1);
+    outcomes.insert(Check::Units, units);
+
+    let mut sub_scores = Vec::with_capacity(Check::ALL.len());
+    let mut failures = Vec::new();
+    for check in Check::ALL {
+        let outcome = outcomes.remove(&check).expect("every check ran");
+        let fraction = match outcome.checked {
+            0 => 0.0,
+            n => outcome.flagged as f64 / n as f64,
+        };
+        let saturation = if check == Check::Units {
+            1.0
+        } else {
+            rubric.saturation
+        };
+        sub_scores.push(SubScore {
+            check,
+            score: (1.0 - fraction / saturation).clamp(0.0, 1.0),
+            weight: rubric.weight(check),
+            checked: outcome.checked,
+            flagged: outcome.flagged,
+        });
+        failures.extend(outcome.warnings);
+    }
+    let total: f64 = sub_scores.iter().map(|s| s.weight).sum();
+    let score = if total > 0.0 {
+        sub_scores.iter().map(|s| s.weight * s.score).sum::<f64>() / total
+    } else {
+        1.0
+    };
+    QcReport {
+        score,
+        sub_scores,
+        failures,
+        passed: score >= rubric.pass_threshold,
+    }
+}
+
+/// Quotes a CSV field when it needs it
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_owned()
+    }
+}
+
+/// A CSV of one row per file: `source`, `qc_score`, `passed`, one column
+/// per check named as `Check::name`, and `error` for files that could not
+/// be scored, whose other columns are empty
+pub(crate) fn qc_csv<'a>(
+    rows: impl IntoIterator<Item = (&'a Path, Result<&'a QcReport, &'a str>)>,
+) -> String {
+    let mut header = vec!["source", "qc_score", "passed"];
+    header.extend(Check::ALL.iter().map(|c| c.name()));
+    header.push("error");
+    let mut text = header.join(",");
+    text.push('\n');
+    for (source, result) in rows {
+        let mut fields = vec![csv_field(&source.to_string_lossy())];
+        match result {
+            Ok(report) => {
+                fields.push(report.score.to_string());
+                fields.push(report.passed.to_string());
+                fields.extend(report.sub_scores.iter().map(|s| s.score.to_string()));
+                fields.push(String::new());
+            }
+            Err(error) => {
+                fields.extend((0..Check::ALL.len() + 2).map(|_| String::new()));
+                fields.push(csv_field(error));
+            }
+        }
+        text.push_str(&fields.join(","));
+        text.push('\n');
+    }
+    text
+}
+
+/// A file of a dataset and its report, or why it could not be read
+pub type FileQc = (PathBuf, Result<QcReport, String>);
+
+impl Dataset {
+    /// `score_skeleton` of every file, read with its `SCALE` header applied
+    /// as `standardize` reads it, in the order of the dataset
+    pub fn qc(&self, rubric: &QcRubric) -> Vec<FileQc> {
+        let options = ReaderOptions {
+            apply_scale: true,
+            collect_stats: false,
+            ..ReaderOptions::default()
+        };
+        self.paths
+            .iter()
+            .map(|path| {
+                let report = read_source(path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|data| {
+                        swc_reader_from_bytes(&data, &options).map_err(|e| e.to_string())
+                    })
+                    .map(|skeleton| score_skeleton(&skeleton, rubric));
+                (path.clone(), report)
+            })
+            .collect()
+    }
+
+    /// Writes `qc` to `path` as CSV, one row per file with a `qc_score`
+    /// column, replacing the file atomically. Files that cannot be read are
+    /// rows with an `error`; only failing to write is an `Err`.
+    pub fn qc_to_csv(
+        &self,
+        path: impl AsRef<Path>,
+        rubric: &QcRubric,
+    ) -> Result<Vec<FileQc>, String> {
+        let reports = self.qc(rubric);
+        let text = qc_csv(
+            reports
+                .iter()
+                .map(|(p, r)| (p.as_path(), r.as_ref().map_err(String::as_str))),
+        );
+        write::write_atomic(path.as_ref(), text.as_bytes(), ConflictPolicy::Overwrite)
+            .map_err(|e| e.to_string())?;
+        Ok(reports)
+    }
+}
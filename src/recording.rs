@@ -0,0 +1,268 @@
+//! Recording a signal only around events, e.g. the voltage waveform around
+//! each spike of a long run, instead of keeping the whole trace.
+//!
+//! A `TriggeredRecorder` is fed one sample per time step together with
+//! whether its source event fired on that step. It keeps the last `pre_ms`
+//! in a rolling buffer and, on an event, stores that buffer plus the next
+//! `post_ms` as a `Snippet`. Memory is bounded by the buffer and the
+//! snippets, whatever the length of the run.
+//!
+//! A `Simulation` records this way with `record_around_events`, a state
+//! path cut around the spikes of one compartment, and its run returns the
+//! snippets with `SimulationResult::snippets`.
+
+use std::collections::VecDeque;
+
+use crate::solver::{SimError, Simulation};
+use crate::state::StateError;
+
+/// Upward threshold crossings of a signal, the usual spike detector
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThresholdDetector {
+    /// In the signal's units, e.g. mV
+    pub threshold: f64,
+    previous: Option<f64>,
+}
+
+impl ThresholdDetector {
+    pub fn new(threshold: f64) -> ThresholdDetector {
+        ThresholdDetector {
+            threshold,
+            previous: None,
+        }
+    }
+
+    /// Whether the signal crossed the threshold from below on this sample.
+    /// The first sample never counts, as there is nothing to cross from.
+    pub fn push(&mut self, value: f64) -> bool {
+        let fired = self
+            .previous
+            .is_some_and(|p| p < self.threshold && value >= self.threshold);
+        self.previous = Some(value);
+        fired
+    }
+}
+
+/// What to do with an event that arrives while the window of an earlier one
+/// is still open
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overlap {
+    /// Extend the open snippet to `post_ms` after the new event, so a burst
+    /// is stored once
+    #[default]
+    Merge,
+    /// Store a full window for every event, repeating the shared samples
+    Separate,
+}
+
+/// Window recorded around each event
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AroundEvents {
+    /// Kept before the event, in ms
+    pub pre_ms: f64,
+    /// Kept after the event, in ms
+    pub post_ms: f64,
+    pub overlap: Overlap,
+}
+
+/// Samples recorded around one event, or one burst when merged
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snippet {
+    /// Time of the event that opened the snippet, in ms
+    pub event_time: f64,
+    /// Times of the further events merged into it, in ms
+    pub merged: Vec<f64>,
+    /// Time of `samples[0]`, in ms. Later than `event_time - pre_ms` for an
+    /// event too close to the start of the run.
+    pub start_time: f64,
+    pub samples: Vec<f64>,
+}
+
+/// A snippet still waiting for samples after its last event
+#[derive(Debug, Clone)]
+struct Open {
+    snippet: Snippet,
+    /// Samples still to take
+    remaining: usize,
+}
+
+/// Event-triggered recording of a sampled signal. Sample `k` is at time
+/// `k * dt`.
+#[derive(Debug, Clone)]
+pub struct TriggeredRecorder {
+    window: AroundEvents,
+    dt: f64,
+    pre: usize,
+    post: usize,
+    buffer: VecDeque<f64>,
+    open: Vec<Open>,
+    snippets: Vec<Snippet>,
+    step: usize,
+}
+
+impl TriggeredRecorder {
+    /// Windows are rounded to whole samples. Errors on a non-positive `dt`
+    /// or a negative window.
+    pub fn new(window: AroundEvents, dt: f64) -> Result<TriggeredRecorder, String> {
+        if !(dt > 0.0 && dt.is_finite()) {
+            return Err(format!("Time step must be positive, got {}", dt));
+        }
+        let (pre_ms, post_ms) = (window.pre_ms, window.post_ms);
+        if !(pre_ms >= 0.0 && post_ms >= 0.0 && (pre_ms + post_ms).is_finite()) {
+            return Err(format!(
+                "Event window must be non-negative, got {} ms before and {} ms after",
+                pre_ms, post_ms
+            ));
+        }
+        let pre = (pre_ms / dt).round() as usize;
+        Ok(TriggeredRecorder {
+            window,
+            dt,
+            pre,
+            post: (post_ms / dt).round() as usize,
+            buffer: VecDeque::with_capacity(pre + 1),
+            open: Vec::new(),
+            snippets: Vec::new(),
+            step: 0,
+        })
+    }
+
+    /// Records the next sample; `event` says whether the source fired on
+    /// this step. The event's own sample is part of its window, at offset
+    /// `round(pre_ms / dt)` into the snippet.
+    pub fn push(&mut self, value: f64, event: bool) {
+        let t = self.step as f64 * self.dt;
+        self.step += 1;
+
+        for open in &mut self.open {
+            open.snippet.samples.push(value);
+            open.remaining -= 1;
+        }
+        if event {
+            let merge_into = match self.window.overlap {
+                Overlap::Merge => self.open.last_mut(),
+                Overlap::Separate => None,
+            };
+            if let Some(open) = merge_into {
+                open.snippet.merged.push(t);
+                open.remaining = self.post;
+            } else {
+                let mut samples = Vec::with_capacity(self.pre + 1 + self.post);
+                samples.extend(&self.buffer);
+                samples.push(value);
+                self.open.push(Open {
+                    snippet: Snippet {
+                        event_time: t,
+                        merged: Vec::new(),
+                        start_time: (self.step - 1 - self.buffer.len()) as f64 * self.dt,
+                        samples,
+                    },
+                    remaining: self.post,
+                });
+            }
+        }
+        // Snippets complete in the order they opened, as all windows are
+        // equally long
+        let done = self.open.iter().take_while(|o| o.remaining == 0).count();
+        self.snippets
+            .extend(self.open.drain(..done).map(|o| o.snippet));
+
+        if self.pre > 0 {
+            if self.buffer.len() == self.pre {
+                self.buffer.pop_front();
+            }
+            self.buffer.push_back(value);
+        }
+    }
+
+    /// Snippets completed so far
+    pub fn snippets(&self) -> &[Snippet] {
+        &self.snippets
+    }
+
+    /// Ends the recording. Snippets whose window runs past the last sample
+    /// are kept, cut short.
+    pub fn finish(mut self) -> Vec<Snippet> {
+        self.snippets
+            .extend(self.open.into_iter().map(|o| o.snippet));
+        self.snippets
+    }
+}
+
+/// A state path recorded only around the spikes of a compartment, see
+/// `Simulation::record_around_events`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TriggeredProbe {
+    /// What is recorded, see `state`
+    pub path: String,
+    /// Compartment whose voltage crossing `threshold` upwards is the event
+    pub source: usize,
+    /// In mV
+    pub threshold: f64,
+    pub window: AroundEvents,
+}
+
+impl Simulation {
+    /// Has `run` record `probe.path` around every spike of `probe.source`
+    /// instead of throughout, into `SimulationResult::snippets`. Snippet
+    /// times are those of the simulation. Fails, recording nothing, unless
+    /// the path reads in the current state, the source exists and the
+    /// window is valid.
+    pub fn record_around_events(&mut self, probe: TriggeredProbe) -> Result<(), SimError> {
+        self.get(&probe.path)?;
+        self.check(probe.source)?;
+        TriggeredRecorder::new(probe.window, self.dt())
+            .map_err(|reason| SimError::InvalidInput { reason })?;
+        self.triggered.push(probe);
+        Ok(())
+    }
+
+    /// A fresh detector and recorder for each probe given to
+    /// `record_around_events`
+    pub(crate) fn triggered_recorders(&self) -> Vec<(ThresholdDetector, TriggeredRecorder)> {
+        self.triggered
+            .iter()
+            .map(|p| {
+                let recorder =
+                    TriggeredRecorder::new(p.window, self.dt()).expect("checked when added");
+                (ThresholdDetector::new(p.threshold), recorder)
+            })
+            .collect()
+    }
+
+    /// Pushes the current sample of each probe, and whether its source
+    /// just spiked, to `recorders`
+    pub(crate) fn sample_triggered(
+        &self,
+        recorders: &mut [(ThresholdDetector, TriggeredRecorder)],
+    ) -> Result<(), StateError> {
+        for (probe, (detector, recorder)) in self.triggered.iter().zip(recorders) {
+            let value = self.get(&probe.path)?;
+            let event = detector.push(self.voltages()[probe.source]);
+            recorder.push(value, event);
+        }
+        Ok(())
+    }
+
+    /// The snippets of each probe in `recorders`, which started at `start`
+    /// ms, in simulation time
+    pub(crate) fn finish_triggered(
+        &self,
+        recorders: Vec<(ThresholdDetector, TriggeredRecorder)>,
+        start: f64,
+    ) -> Vec<(String, Vec<Snippet>)> {
+        self.triggered
+            .iter()
+            .zip(recorders)
+            .map(|(probe, (_, recorder))| {
+                let mut snippets = recorder.finish();
+                for s in &mut snippets {
+                    s.event_time += start;
+                    s.start_time += start;
+                    s.merged.iter_mut().for_each(|t| *t += start);
+                }
+                (probe.path.clone(), snippets)
+            })
+            .collect()
+    }
+}
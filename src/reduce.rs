@@ -0,0 +1,616 @@
+//! Reduced models fitted to a detailed passive cell: the soma and one
+//! equivalent cable per stem, for network models where the full morphology
+//! costs too much.
+//!
+//! Each stem's subtree collapses into a uniform cable with the same membrane
+//! area, membrane and axial properties averaged over that area, and an
+//! electrotonic length of twice the mean electrotonic distance of the
+//! membrane from the stem's origin. Membrane spread evenly over `L/λ`, as on
+//! a cable or a tree meeting Rall's conditions for an equivalent cylinder,
+//! has its mean halfway, so such a tree collapses into the cylinder it is
+//! equivalent to. The soma keeps its membrane, as one isopotential
+//! compartment.
+//!
+//! The collapse only looks at geometry. Optionally the cable diameters and
+//! lengths are then refined by least squares against the detailed model's
+//! impedances (see `analysis`): the somatic input resistance and charging
+//! time, and the input and target-site transfer impedances at DC and a few
+//! frequencies.
+//!
+//! Lengths are in µm, impedances in MΩ, times in ms and frequencies in Hz.
+
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+use crate::analysis::{Impedance, soma_transfer_impedances};
+use crate::channels::Channel;
+use crate::compartments::Compartments;
+use crate::geometry::{self, Vec3};
+use crate::markov::solve;
+use crate::swc_reader::{Node, StructureIdentifier};
+
+/// A point on the membrane, `x` along compartment `idx` from its proximal
+/// end
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SiteLocation {
+    pub idx: usize,
+    pub x: f64,
+}
+
+/// How `equivalent_cable` builds and fits the reduced model
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReductionOptions {
+    /// Compartments per equivalent cable
+    pub segments: usize,
+    /// Refine the cable dimensions by least squares after the collapse
+    pub refine: bool,
+    /// Frequencies the refinement fits besides DC, in Hz
+    pub frequencies: Vec<f64>,
+    /// Most steps the refinement takes
+    pub max_iterations: usize,
+}
+
+impl Default for ReductionOptions {
+    fn default() -> Self {
+        ReductionOptions {
+            segments: 20,
+            refine: true,
+            frequencies: vec![10.0, 100.0],
+            max_iterations: 100,
+        }
+    }
+}
+
+/// The cable standing in for one stem. Diameters and lengths in µm.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EquivalentCable {
+    /// First compartment of the stem in the detailed model
+    pub stem: usize,
+    /// First compartment of the cable in the reduced model
+    pub start: usize,
+    pub diam: f64,
+    pub length: f64,
+    /// `L/λ` of the cable as built
+    pub electrotonic_length: f64,
+    /// Dimensions from the collapse, before any refinement
+    pub collapsed_diam: f64,
+    pub collapsed_length: f64,
+}
+
+/// A property of the detailed model against the reduced model's
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FitError {
+    pub detailed: f64,
+    pub reduced: f64,
+}
+
+impl FitError {
+    pub fn relative(&self) -> f64 {
+        ((self.reduced - self.detailed) / self.detailed).abs()
+    }
+}
+
+/// Transfer impedance from a target site to the soma, in both models
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SiteFit {
+    pub site: SiteLocation,
+    /// Where the site lands in the reduced model: as far along its stem's
+    /// cable, as a fraction of the cable's electrotonic length, as it is
+    /// from the stem's origin in the detailed model
+    pub reduced_site: SiteLocation,
+    /// In Hz
+    pub frequency: f64,
+    pub detailed: Impedance,
+    pub reduced: Impedance,
+}
+
+impl SiteFit {
+    /// Magnitude of the difference relative to the detailed impedance
+    pub fn relative_error(&self) -> f64 {
+        let (re, im) = (
+            self.reduced.re - self.detailed.re,
+            self.reduced.im - self.detailed.im,
+        );
+        re.hypot(im) / self.detailed.magnitude()
+    }
+}
+
+/// How well the reduced model matches the detailed one
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReductionReport {
+    /// One per stem, in order of the stems' first compartments
+    pub cables: Vec<EquivalentCable>,
+    /// Somatic input resistance, in MΩ
+    pub input_resistance: FitError,
+    /// Mean charging time of the soma, in ms: the centre of mass in time of
+    /// its voltage response to a brief current pulse, which is the membrane
+    /// time constant for a lone compartment
+    pub time_constant: FitError,
+    /// Every target site at DC, then at each of the options' frequencies
+    pub sites: Vec<SiteFit>,
+    /// Steps the refinement took, 0 without one
+    pub iterations: usize,
+}
+
+impl ReductionReport {
+    /// Largest relative error over the input resistance, the time constant
+    /// and the sites
+    pub fn max_relative_error(&self) -> f64 {
+        self.sites.iter().map(SiteFit::relative_error).fold(
+            self.input_resistance
+                .relative()
+                .max(self.time_constant.relative()),
+            f64::max,
+        )
+    }
+}
+
+/// The soma as one compartment. Diameter and length in µm.
+struct Soma {
+    diam: f64,
+    length: f64,
+    channel: Channel,
+}
+
+/// A stem's subtree, summed up
+struct Stem {
+    root: usize,
+    structure: StructureIdentifier,
+    /// Unit direction the cable is laid out in, the stem's own
+    direction: Vec3,
+    /// Area-weighted means of the stem's membrane and axial properties
+    channel: Channel,
+    /// Membrane area, in µm²
+    area: f64,
+    electrotonic_length: f64,
+}
+
+/// A detailed cell taken apart for reducing
+struct Collapse<'a> {
+    detailed: &'a Compartments,
+    soma: Soma,
+    stems: Vec<Stem>,
+    /// For every compartment outside the soma: its stem, the electrotonic
+    /// distance of its proximal end from the stem's origin and its own `L/λ`
+    place: Vec<Option<(usize, f64, f64)>>,
+    segments: usize,
+}
+
+/// Impedances of a model at the soma and at some compartments
+struct Measured {
+    /// Input impedance of the soma, by frequency
+    soma: Vec<Impedance>,
+    /// Transfer impedance to the soma, by frequency and compartment
+    sites: Vec<Vec<Impedance>>,
+    /// Mean charging time of the soma, in ms
+    charging_time: f64,
+}
+
+fn measure(compartments: &Compartments, sites: &[usize], frequencies: &[f64]) -> Measured {
+    let mut soma = Vec::with_capacity(frequencies.len());
+    let mut at_sites = Vec::with_capacity(frequencies.len());
+    let mut charging_time = 0.0;
+    for (k, &f) in frequencies.iter().enumerate() {
+        let z = soma_transfer_impedances(compartments, f);
+        if k == 0 {
+            // dZ/dω at DC is -i Z C Z, so the soma's first moment is
+            // sum_k Z_sk² C_k / Z_ss; capacitances in µF, MΩ µF is a second
+            let moment: f64 = compartments
+                .components
+                .iter()
+                .zip(&z)
+                .map(|(c, z)| z.re * z.re * c.capacitance() * 1e-8)
+                .sum();
+            charging_time = moment / z[1].re * 1e3;
+        }
+        soma.push(z[1]);
+        at_sites.push(sites.iter().map(|&i| z[i]).collect());
+    }
+    Measured {
+        soma,
+        sites: at_sites,
+        charging_time,
+    }
+}
+
+/// `L/λ` of a compartment, with `λ = sqrt(d / (4 Ra g))`
+fn electrotonic(compartments: &Compartments, idx: usize) -> Result<f64, String> {
+    let c = &compartments.components[idx];
+    let (ra, g) = (c.channel.resistance, c.channel.conductance);
+    if !(g > 0.0 && ra > 0.0) {
+        return Err(format!(
+            "Compartment {} needs a positive membrane conductance and axial resistivity to be reduced, got {} S/cm² and {} Ω·cm",
+            idx, g, ra
+        ));
+    }
+    if c.length == 0.0 {
+        return Ok(0.0);
+    }
+    let lambda_cm = (c.diam * 1e-4 / (4.0 * ra * g)).sqrt();
+    Ok(c.length * 1e-4 / lambda_cm)
+}
+
+/// Diameter and length, in µm, of a uniform cable with membrane area `area`
+/// µm² and electrotonic length `electrotonic_length` under `channel`
+fn cable_dimensions(channel: &Channel, area: f64, electrotonic_length: f64) -> (f64, f64) {
+    // A = π d l and L = l sqrt(4 Ra g / d), so d^(3/2) = A sqrt(4 Ra g) / (π L)
+    let area_cm2 = area * 1e-8;
+    let root = (4.0 * channel.resistance * channel.conductance).sqrt();
+    let diam_cm = (area_cm2 * root / (PI * electrotonic_length)).powf(2.0 / 3.0);
+    (diam_cm * 1e4, area_cm2 / (PI * diam_cm) * 1e4)
+}
+
+impl<'a> Collapse<'a> {
+    fn new(detailed: &'a Compartments, segments: usize) -> Result<Collapse<'a>, String> {
+        let c = &detailed.components;
+        let n = c.len();
+        if n < 2 {
+            return Err("No compartments to reduce".to_owned());
+        }
+        // The soma is compartment 1 and any soma compartments hanging off it
+        let mut in_soma = vec![false; n];
+        in_soma[1] = true;
+        let parent = |i: usize| c[i].parent_idxs[0] as usize;
+        for i in 2..n {
+            in_soma[i] = in_soma[parent(i)] && c[i].structure == StructureIdentifier::Soma;
+        }
+        let (mut area, mut g, mut cm) = (0.0, 0.0, 0.0);
+        for comp in c.iter().zip(&in_soma).filter(|(_, s)| **s).map(|(c, _)| c) {
+            area += comp.membrane_area();
+            g += comp.membrane_conductance();
+            cm += comp.capacitance();
+        }
+        let soma = if area > 0.0 {
+            // A cylinder as long as it is wide has area π d²
+            let diam = (area / PI).sqrt();
+            let mut channel = c[1].channel.clone();
+            channel.conductance = g / area;
+            channel.capacitance = cm / area;
+            Soma {
+                diam,
+                length: diam,
+                channel,
+            }
+        } else {
+            Soma {
+                diam: c[1].diam,
+                length: 0.0,
+                channel: c[1].channel.clone(),
+            }
+        };
+
+        let mut place: Vec<Option<(usize, f64, f64)>> = vec![None; n];
+        // Per stem: area, area times distance to the middle, and the
+        // area-weighted sums of g, c and Ra
+        let mut sums: Vec<[f64; 5]> = Vec::new();
+        let mut stems: Vec<Stem> = Vec::new();
+        for i in 2..n {
+            if in_soma[i] {
+                continue;
+            }
+            let p = parent(i);
+            let (s, start) = match place[p] {
+                Some((s, from, l)) => (s, from + l),
+                None => {
+                    stems.push(Stem {
+                        root: i,
+                        structure: c[i].structure,
+                        direction: c[i].tangent,
+                        channel: c[i].channel.clone(),
+                        area: 0.0,
+                        electrotonic_length: 0.0,
+                    });
+                    sums.push([0.0; 5]);
+                    (stems.len() - 1, 0.0)
+                }
+            };
+            let l = electrotonic(detailed, i)?;
+            place[i] = Some((s, start, l));
+            let a = c[i].membrane_area();
+            let ch = &c[i].channel;
+            for (sum, x) in sums[s].iter_mut().zip([
+                a,
+                a * (start + l / 2.0),
+                a * ch.conductance,
+                a * ch.capacitance,
+                a * ch.resistance,
+            ]) {
+                *sum += x;
+            }
+        }
+        if stems.is_empty() {
+            return Err("The cell has no dendrites to reduce".to_owned());
+        }
+        for (stem, [a, ax, ag, ac, ar]) in stems.iter_mut().zip(sums) {
+            if !(a > 0.0 && ax > 0.0) {
+                return Err(format!(
+                    "The stem at compartment {} has no membrane to collapse",
+                    stem.root
+                ));
+            }
+            stem.area = a;
+            stem.electrotonic_length = 2.0 * ax / a;
+            stem.channel.conductance = ag / a;
+            stem.channel.capacitance = ac / a;
+            stem.channel.resistance = ar / a;
+        }
+        Ok(Collapse {
+            detailed,
+            soma,
+            stems,
+            place,
+            segments,
+        })
+    }
+
+    /// Dimensions of every stem's cable from the collapse
+    fn collapsed(&self) -> Vec<(f64, f64)> {
+        self.stems
+            .iter()
+            .map(|s| cable_dimensions(&s.channel, s.area, s.electrotonic_length))
+            .collect()
+    }
+
+    fn start(&self, stem: usize) -> usize {
+        2 + stem * self.segments
+    }
+
+    /// The reduced model with cables of `dims`, diameter and length each
+    fn build(&self, dims: &[(f64, f64)]) -> Compartments {
+        let origin = self.detailed.components[1].distal;
+        let [x, y, z] = origin;
+        let mut nodes = vec![Node::new(
+            0,
+            StructureIdentifier::Soma,
+            x,
+            y,
+            z,
+            self.soma.diam / 2.0,
+            0,
+        )];
+        let mut parents: HashMap<u64, Vec<u64>> = HashMap::from([(0, vec![0])]);
+        let mut children: HashMap<u64, Vec<u64>> = HashMap::new();
+        for (stem, &(diam, length)) in self.stems.iter().zip(dims) {
+            for k in 1..=self.segments {
+                let id = nodes.len() as u64;
+                let parent = if k == 1 { 0 } else { id - 1 };
+                let step = length * k as f64 / self.segments as f64;
+                let [x, y, z] = geometry::add(origin, geometry::scale(stem.direction, step));
+                nodes.push(Node::new(id, stem.structure, x, y, z, diam / 2.0, parent));
+                parents.insert(id, vec![parent]);
+                children.entry(parent).or_default().push(id);
+            }
+        }
+        let mut reduced = Compartments::from_sorted_nodes(nodes, children, parents);
+        reduced.components[1].length = self.soma.length;
+        reduced.components[1].set_channel(self.soma.channel.clone());
+        for (s, stem) in self.stems.iter().enumerate() {
+            let start = self.start(s);
+            for c in &mut reduced.components[start..start + self.segments] {
+                c.set_channel(stem.channel.clone());
+            }
+        }
+        reduced.ion_reversals = self.detailed.ion_reversals.clone();
+        reduced
+    }
+
+    /// Where `site` lands in the reduced model
+    fn reduced_site(&self, site: SiteLocation) -> Result<SiteLocation, String> {
+        if site.idx == 0 || site.idx >= self.place.len() {
+            return Err(format!("No compartment {} to put a site on", site.idx));
+        }
+        if !(0.0..=1.0).contains(&site.x) {
+            return Err(format!(
+                "Site position must lie within [0, 1], got {}",
+                site.x
+            ));
+        }
+        let Some((s, start, l)) = self.place[site.idx] else {
+            return Ok(SiteLocation { idx: 1, x: 0.5 });
+        };
+        let fraction = ((start + site.x * l) / self.stems[s].electrotonic_length).clamp(0.0, 1.0);
+        let position = fraction * self.segments as f64;
+        let k = (position.floor() as usize).min(self.segments - 1);
+        Ok(SiteLocation {
+            idx: self.start(s) + k,
+            x: position - k as f64,
+        })
+    }
+}
+
+/// Relative differences of `reduced` from `detailed`: input resistance,
+/// charging time, then the real and imaginary parts of the input and site
+/// impedances at each frequency
+fn residuals(detailed: &Measured, reduced: &Measured) -> Vec<f64> {
+    let mut out = vec![
+        reduced.soma[0].re / detailed.soma[0].re - 1.0,
+        reduced.charging_time / detailed.charging_time - 1.0,
+    ];
+    let pairs = detailed.soma.iter().zip(&reduced.soma).chain(
+        detailed
+            .sites
+            .iter()
+            .flatten()
+            .zip(reduced.sites.iter().flatten()),
+    );
+    for (d, r) in pairs {
+        let scale = d.magnitude();
+        out.push((r.re - d.re) / scale);
+        out.push((r.im - d.im) / scale);
+    }
+    out
+}
+
+/// Levenberg-Marquardt over the logarithms of the cable dimensions, from
+/// `dims`. Returns the best dimensions found and the steps taken.
+fn refine(
+    cost_of: impl Fn(&[(f64, f64)]) -> Vec<f64>,
+    dims: Vec<(f64, f64)>,
+    max_iterations: usize,
+) -> (Vec<(f64, f64)>, usize) {
+    let to_dims =
+        |p: &[f64]| -> Vec<(f64, f64)> { p.chunks(2).map(|d| (d[0].exp(), d[1].exp())).collect() };
+    let sum_sq = |r: &[f64]| r.iter().map(|x| x * x).sum::<f64>();
+    let mut p: Vec<f64> = dims.iter().flat_map(|&(d, l)| [d.ln(), l.ln()]).collect();
+    let mut r = cost_of(&dims);
+    let mut cost = sum_sq(&r);
+    let mut damping = 1e-3;
+    let mut steps = 0;
+    while steps < max_iterations && cost > 1e-24 {
+        let h = 1e-6;
+        let jacobian: Vec<Vec<f64>> = (0..p.len())
+            .map(|j| {
+                let mut q = p.clone();
+                q[j] += h;
+                let rq = cost_of(&to_dims(&q));
+                rq.iter().zip(&r).map(|(a, b)| (a - b) / h).collect()
+            })
+            .collect();
+        let m = p.len();
+        let normal: Vec<Vec<f64>> = (0..m)
+            .map(|a| {
+                (0..m)
+                    .map(|b| {
+                        jacobian[a]
+                            .iter()
+                            .zip(&jacobian[b])
+                            .map(|(x, y)| x * y)
+                            .sum()
+                    })
+                    .collect()
+            })
+            .collect();
+        let gradient: Vec<f64> = jacobian
+            .iter()
+            .map(|col| -col.iter().zip(&r).map(|(x, y)| x * y).sum::<f64>())
+            .collect();
+        let mut accepted = None;
+        for _ in 0..12 {
+            let mut damped = normal.clone();
+            for (j, row) in damped.iter_mut().enumerate() {
+                row[j] += damping * normal[j][j].max(1e-12);
+            }
+            if let Some(delta) = solve(damped, gradient.clone()) {
+                let q: Vec<f64> = p.iter().zip(&delta).map(|(a, b)| a + b).collect();
+                let rq = cost_of(&to_dims(&q));
+                let new = sum_sq(&rq);
+                if new.is_finite() && new < cost {
+                    accepted = Some((q, rq, new));
+                    damping = (damping / 10.0).max(1e-12);
+                    break;
+                }
+            }
+            damping *= 10.0;
+        }
+        let Some((q, rq, new)) = accepted else {
+            break;
+        };
+        let improvement = cost - new;
+        (p, r, cost) = (q, rq, new);
+        steps += 1;
+        if improvement <= 1e-12 * cost {
+            break;
+        }
+    }
+    (to_dims(&p), steps)
+}
+
+/// Reduces the passive cell `compartments` to its soma and one uniform
+/// cable per stem, each cut into `options.segments` compartments, and
+/// reports how far the reduced model's somatic input resistance, charging
+/// time and transfer impedances from `targets` are from the detailed ones.
+/// The reduced model has the same soma position and reversal settings, and
+/// is ready for `Simulation`.
+///
+/// Every compartment needs a positive membrane conductance and axial
+/// resistivity; only those and the capacitance enter.
+pub fn equivalent_cable(
+    compartments: &Compartments,
+    targets: &[SiteLocation],
+    options: &ReductionOptions,
+) -> Result<(Compartments, ReductionReport), String> {
+    if options.segments == 0 {
+        return Err("Equivalent cables need at least one compartment".to_owned());
+    }
+    if let Some(f) = options
+        .frequencies
+        .iter()
+        .find(|f| !(f.is_finite() && **f >= 0.0))
+    {
+        return Err(format!(
+            "Frequencies must be finite and non-negative, got {}",
+            f
+        ));
+    }
+    let collapse = Collapse::new(compartments, options.segments)?;
+    let reduced_sites = targets
+        .iter()
+        .map(|&s| collapse.reduced_site(s))
+        .collect::<Result<Vec<_>, String>>()?;
+    let frequencies: Vec<f64> = std::iter::once(0.0)
+        .chain(options.frequencies.iter().copied())
+        .collect();
+    let site_idxs: Vec<usize> = targets.iter().map(|s| s.idx).collect();
+    let reduced_idxs: Vec<usize> = reduced_sites.iter().map(|s| s.idx).collect();
+    let detailed = measure(compartments, &site_idxs, &frequencies);
+
+    let collapsed = collapse.collapsed();
+    let (dims, iterations) = if options.refine {
+        let cost_of = |dims: &[(f64, f64)]| {
+            let reduced = measure(&collapse.build(dims), &reduced_idxs, &frequencies);
+            residuals(&detailed, &reduced)
+        };
+        refine(cost_of, collapsed.clone(), options.max_iterations)
+    } else {
+        (collapsed.clone(), 0)
+    };
+
+    let model = collapse.build(&dims);
+    let reduced = measure(&model, &reduced_idxs, &frequencies);
+    let cables = collapse
+        .stems
+        .iter()
+        .enumerate()
+        .zip(dims.iter().zip(&collapsed))
+        .map(|((s, stem), (&(diam, length), &(d0, l0)))| {
+            let lambda = (diam * 1e-4 / (4.0 * stem.channel.resistance * stem.channel.conductance))
+                .sqrt()
+                * 1e4;
+            EquivalentCable {
+                stem: stem.root,
+                start: collapse.start(s),
+                diam,
+                length,
+                electrotonic_length: length / lambda,
+                collapsed_diam: d0,
+                collapsed_length: l0,
+            }
+        })
+        .collect();
+    let mut sites = Vec::with_capacity(frequencies.len() * targets.len());
+    for (k, &frequency) in frequencies.iter().enumerate() {
+        for (j, (&site, &reduced_site)) in targets.iter().zip(&reduced_sites).enumerate() {
+            sites.push(SiteFit {
+                site,
+                reduced_site,
+                frequency,
+                detailed: detailed.sites[k][j],
+                reduced: reduced.sites[k][j],
+            });
+        }
+    }
+    let report = ReductionReport {
+        cables,
+        input_resistance: FitError {
+            detailed: detailed.soma[0].re,
+            reduced: reduced.soma[0].re,
+        },
+        time_constant: FitError {
+            detailed: detailed.charging_time,
+            reduced: reduced.charging_time,
+        },
+        sites,
+        iterations,
+    };
+    Ok((model, report))
+}
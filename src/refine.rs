@@ -0,0 +1,379 @@
+//! Adaptive refinement of the d_lambda discretization, driven by how much
+//! the voltages of a calibration run still change when the compartments
+//! are made finer.
+//!
+//! Each round simulates the model as discretized and the same model with
+//! every section's `ncomp` tripled. Tripling keeps every old compartment
+//! centre as a new centre, so the two runs compare compartment by
+//! compartment without interpolation; the largest difference over a
+//! section is its estimated error, in mV. Sections above the tolerance are
+//! tripled, and the next round checks again, until every section is within
+//! it or the rounds run out.
+//!
+//! The result is a `Discretization` like any other, for `build_with`. The
+//! report keeps a key of everything it depends on and round-trips through
+//! text, so `auto_refine_reusing` can skip the calibration when nothing
+//! changed.
+
+use std::fmt::Write;
+
+use sha2::{Digest, Sha256};
+
+use crate::compartments::Compartments;
+use crate::discretize::{Discretization, SectionSplit};
+use crate::solver::Simulation;
+use crate::swc_reader::format_float;
+
+/// The run each round of `auto_refine` simulates
+#[derive(Debug, Clone, PartialEq)]
+pub struct CalibrationProtocol {
+    /// d_lambda rule the first round starts from, in Hz
+    pub frequency: f64,
+    pub d_lambda: f64,
+    pub dt: f64,
+    pub steps: usize,
+    /// Injected as in `Simulation::run`, by compartment of the model being
+    /// refined. Each goes into the compartment holding that one's middle.
+    pub stimuli: Vec<(usize, Vec<f64>)>,
+}
+
+impl Default for CalibrationProtocol {
+    fn default() -> Self {
+        CalibrationProtocol {
+            frequency: 100.0,
+            d_lambda: 0.1,
+            dt: 0.025,
+            steps: 0,
+            stimuli: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefinementStatus {
+    /// Every section ended within the tolerance
+    Converged,
+    /// The rounds ran out first
+    NotConverged,
+}
+
+/// One round of `auto_refine`
+#[derive(Debug, Clone, PartialEq)]
+pub struct RefinementRound {
+    /// Compartments simulated, without the dummy root
+    pub compartments: usize,
+    /// Largest estimated error of any section, in mV
+    pub max_error: f64,
+    /// Sections tripled after this round
+    pub refined: Vec<String>,
+}
+
+/// Outcome of `auto_refine`
+#[derive(Debug, Clone, PartialEq)]
+pub struct RefinementReport {
+    /// Hash of the model's discretization inputs, the protocol and the
+    /// refinement settings
+    pub key: u64,
+    pub status: RefinementStatus,
+    /// Estimated error of the final discretization, in mV
+    pub max_error: f64,
+    pub rounds: Vec<RefinementRound>,
+    pub discretization: Discretization,
+    /// Runs simulated to get here, 0 when reused
+    pub simulations: usize,
+}
+
+fn key(
+    compartments: &Compartments,
+    protocol: &CalibrationProtocol,
+    tolerance: f64,
+    max_iters: usize,
+) -> u64 {
+    let hash = crate::discretize::input_hash(compartments, protocol.frequency, protocol.d_lambda);
+    let mut text = format!(
+        "{:016x} {} {} {} {} {}\n",
+        hash,
+        format_float(protocol.dt),
+        protocol.steps,
+        format_float(tolerance),
+        max_iters,
+        protocol.stimuli.len()
+    );
+    for (idx, waveform) in &protocol.stimuli {
+        let _ = write!(text, "{}", idx);
+        for x in waveform {
+            let _ = write!(text, " {}", format_float(*x));
+        }
+        text.push('\n');
+    }
+    let digest = Sha256::digest(text.as_bytes());
+    u64::from_be_bytes(digest[..8].try_into().unwrap())
+}
+
+fn with_ncomp(name: &str, ncomp: usize) -> SectionSplit {
+    SectionSplit {
+        name: name.to_owned(),
+        ncomp,
+        splits: (1..ncomp).map(|k| k as f64 / ncomp as f64).collect(),
+    }
+}
+
+/// `discretization` with every section split three times as finely
+fn tripled(discretization: &Discretization) -> Discretization {
+    Discretization {
+        sections: discretization
+            .sections
+            .iter()
+            .map(|s| with_ncomp(&s.name, s.ncomp * 3))
+            .collect(),
+        ..discretization.clone()
+    }
+}
+
+impl Compartments {
+    /// Voltages of `protocol` on the model built with `discretization`
+    fn calibrate(
+        &self,
+        discretization: &Discretization,
+        protocol: &CalibrationProtocol,
+    ) -> Result<(Compartments, Vec<Vec<f64>>), String> {
+        let built = self.build_with(discretization)?;
+        let map = built.last_index_map().expect("build_with records its map");
+        let stimuli = protocol
+            .stimuli
+            .iter()
+            .map(|(idx, waveform)| {
+                let (host, _) = map
+                    .forward_position(*idx, 0.5)
+                    .ok_or_else(|| format!("No compartment {} to inject into", idx))?;
+                Ok((host, waveform.clone()))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        let result = Simulation::new(&built, protocol.dt)
+            .map_err(|e| e.to_string())?
+            .run(protocol.steps, &stimuli)
+            .map_err(|e| e.to_string())?;
+        Ok((built, result.voltages))
+    }
+
+    /// Estimated error of every section of `discretization`, in mV, against
+    /// the run with every section tripled, and the number of compartments
+    /// simulated. A section without length stays one compartment either
+    /// way, so its error is 0.
+    fn section_errors(
+        &self,
+        discretization: &Discretization,
+        protocol: &CalibrationProtocol,
+    ) -> Result<(Vec<f64>, usize), String> {
+        let (coarse, v) = self.calibrate(discretization, protocol)?;
+        let (fine, w) = self.calibrate(&tripled(discretization), protocol)?;
+        let errors = coarse
+            .sections()
+            .iter()
+            .zip(fine.sections())
+            .map(|(c, f)| {
+                if f.compartments.len() != 3 * c.compartments.len() {
+                    return 0.0;
+                }
+                c.compartments
+                    .iter()
+                    .enumerate()
+                    .map(|(k, &i)| {
+                        // Centre k of n is centre 3k + 1 of 3n
+                        let j = f.compartments[3 * k + 1];
+                        v[i].iter()
+                            .zip(&w[j])
+                            .map(|(a, b)| (a - b).abs())
+                            .fold(0.0, f64::max)
+                    })
+                    .fold(0.0, f64::max)
+            })
+            .collect();
+        Ok((errors, coarse.components.len() - 1))
+    }
+
+    /// Refines the d_lambda discretization of `protocol` until simulating
+    /// it changes no section's voltages by more than `tolerance` mV when
+    /// the section is made three times finer, tripling the sections above
+    /// it and those they hang off for at most `max_iters` rounds. Each
+    /// round simulates twice.
+    pub fn auto_refine(
+        &self,
+        protocol: &CalibrationProtocol,
+        tolerance: f64,
+        max_iters: usize,
+    ) -> Result<RefinementReport, String> {
+        if !(tolerance >= 0.0 && tolerance.is_finite()) {
+            return Err(format!(
+                "Tolerance must be non-negative and finite, got {} mV",
+                tolerance
+            ));
+        }
+        let mut discretization =
+            Discretization::compute(self, protocol.frequency, protocol.d_lambda)?;
+        // A section hangs off the centre of its parent's last compartment,
+        // so its error also comes from how coarse that one is
+        let parents: Vec<Option<usize>> = self
+            .sections
+            .iter()
+            .map(|section| {
+                let p = *self.components[section.compartments[0]]
+                    .parent_idxs
+                    .first()? as usize;
+                self.sections
+                    .iter()
+                    .position(|s| s.compartments.last() == Some(&p))
+            })
+            .collect();
+        let mut rounds = Vec::new();
+        let mut simulations = 0;
+        let status = loop {
+            let (errors, compartments) = self.section_errors(&discretization, protocol)?;
+            simulations += 2;
+            let max_error = errors.iter().copied().fold(0.0, f64::max);
+            rounds.push(RefinementRound {
+                compartments,
+                max_error,
+                refined: Vec::new(),
+            });
+            if max_error <= tolerance {
+                break RefinementStatus::Converged;
+            }
+            if rounds.len() > max_iters {
+                break RefinementStatus::NotConverged;
+            }
+            let mut over: Vec<bool> = errors.iter().map(|&e| e > tolerance).collect();
+            for (i, &p) in parents.iter().enumerate() {
+                if let Some(p) = p.filter(|_| errors[i] > tolerance) {
+                    over[p] = true;
+                }
+            }
+            let refined = &mut rounds.last_mut().expect("just pushed").refined;
+            for (section, _) in discretization
+                .sections
+                .iter_mut()
+                .zip(over)
+                .filter(|(_, o)| *o)
+            {
+                *section = with_ncomp(&section.name, section.ncomp * 3);
+                refined.push(section.name.clone());
+            }
+        };
+        let max_error = rounds.last().expect("at least one round").max_error;
+        self.log(
+            "auto_refine",
+            &[
+                ("tolerance", tolerance.into()),
+                ("rounds", rounds.len().into()),
+                ("max_error", max_error.into()),
+                ("converged", (status == RefinementStatus::Converged).into()),
+            ],
+        );
+        Ok(RefinementReport {
+            key: key(self, protocol, tolerance, max_iters),
+            status,
+            max_error,
+            rounds,
+            discretization,
+            simulations,
+        })
+    }
+
+    /// `saved` as is if it was refined from this model with the same
+    /// protocol and settings, otherwise `auto_refine` from scratch
+    pub fn auto_refine_reusing(
+        &self,
+        protocol: &CalibrationProtocol,
+        tolerance: f64,
+        max_iters: usize,
+        saved: &RefinementReport,
+    ) -> Result<RefinementReport, String> {
+        if saved.key == key(self, protocol, tolerance, max_iters)
+            && saved.discretization.is_valid_for(self)
+        {
+            return Ok(RefinementReport {
+                simulations: 0,
+                ..saved.clone()
+            });
+        }
+        self.auto_refine(protocol, tolerance, max_iters)
+    }
+}
+
+impl RefinementReport {
+    /// Plain text: a header line, one line per round, then the
+    /// discretization as `Discretization::to_text` writes it. Numbers
+    /// round-trip exactly.
+    pub fn to_text(&self) -> String {
+        let status = match self.status {
+            RefinementStatus::Converged => "converged",
+            RefinementStatus::NotConverged => "not_converged",
+        };
+        let mut text = format!(
+            "refinement {:016x} {} {}\n",
+            self.key,
+            status,
+            format_float(self.max_error)
+        );
+        for round in &self.rounds {
+            let _ = write!(
+                text,
+                "round {} {}",
+                round.compartments,
+                format_float(round.max_error)
+            );
+            for name in &round.refined {
+                let _ = write!(text, " {}", name);
+            }
+            text.push('\n');
+        }
+        text + &self.discretization.to_text()
+    }
+
+    /// Reads back the output of `to_text`
+    pub fn parse(text: &str) -> Result<RefinementReport, String> {
+        let start = text
+            .find("discretization ")
+            .ok_or("Missing discretization in refinement")?;
+        let discretization = Discretization::parse(&text[start..])?;
+        let mut lines = text[..start].lines().enumerate();
+        let header: Vec<&str> = lines
+            .next()
+            .map(|(_, l)| l.split_whitespace().collect())
+            .unwrap_or_default();
+        let ["refinement", key, status, max_error] = header[..] else {
+            return Err(format!("Not a refinement: '{}'", header.join(" ")));
+        };
+        let number = |line: usize, v: &str| {
+            v.parse::<f64>()
+                .map_err(|_| format!("Line {}: '{}' is not a number", line + 1, v))
+        };
+        let status = match status {
+            "converged" => RefinementStatus::Converged,
+            "not_converged" => RefinementStatus::NotConverged,
+            other => return Err(format!("Unknown refinement status '{}'", other)),
+        };
+        let mut rounds = Vec::new();
+        for (i, line) in lines {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let ["round", compartments, max_error, refined @ ..] = &fields[..] else {
+                return Err(format!("Line {}: expected a round", i + 1));
+            };
+            rounds.push(RefinementRound {
+                compartments: compartments
+                    .parse()
+                    .map_err(|_| format!("Line {}: '{}' is not a count", i + 1, compartments))?,
+                max_error: number(i, max_error)?,
+                refined: refined.iter().map(|s| s.to_string()).collect(),
+            });
+        }
+        Ok(RefinementReport {
+            key: u64::from_str_radix(key, 16).map_err(|_| format!("Invalid key '{}'", key))?,
+            status,
+            max_error: number(0, max_error)?,
+            rounds,
+            discretization,
+            simulations: 0,
+        })
+    }
+}
@@ -0,0 +1,483 @@
+//! Aligning one morphology onto another, either from matched landmarks or by
+//! iterative closest point on the node clouds.
+
+use crate::geometry::{self, Vec3};
+use crate::swc_reader::{Node, Skeleton, StructureIdentifier};
+
+/// Similarity transform `p -> scale * rotation * p + translation`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    /// Proper rotation matrix, row major
+    pub rotation: [[f64; 3]; 3],
+    pub translation: [f64; 3],
+    pub scale: f64,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform::identity()
+    }
+}
+
+impl Transform {
+    pub fn identity() -> Self {
+        Transform {
+            rotation: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+            translation: [0.0; 3],
+            scale: 1.0,
+        }
+    }
+
+    pub fn apply(&self, p: [f64; 3]) -> [f64; 3] {
+        let r = &self.rotation;
+        std::array::from_fn(|i| self.scale * geometry::dot(r[i], p) + self.translation[i])
+    }
+
+    /// The transform undoing this one
+    pub fn inverse(&self) -> Transform {
+        let r = &self.rotation;
+        let rotation = std::array::from_fn(|i| std::array::from_fn(|j| r[j][i]));
+        let scale = 1.0 / self.scale;
+        let back: [f64; 3] = std::array::from_fn(|i| geometry::dot(rotation[i], self.translation));
+        Transform {
+            rotation,
+            translation: back.map(|v| -scale * v),
+            scale,
+        }
+    }
+}
+
+/// Returns a copy of `skeleton` moved by `transform`. Radii are multiplied by
+/// the scale so the morphology stays similar.
+pub fn apply_transform(skeleton: &Skeleton, transform: &Transform) -> Skeleton {
+    let mut out = skeleton.edit();
+    for node in out.nodes_mut().iter_mut() {
+        [node.x_pos, node.y_pos, node.z_pos] =
+            transform.apply([node.x_pos, node.y_pos, node.z_pos]);
+        node.radius *= transform.scale;
+    }
+    out.commit()
+        .expect("moving nodes leaves the maps as they were")
+}
+
+/// Least-squares transform taking the first point of each pair onto the
+/// second (Horn's quaternion method). Without `allow_scale` the result is
+/// rigid. Needs three pairs that are not collinear.
+pub fn fit_landmarks(pairs: &[(Vec3, Vec3)], allow_scale: bool) -> Result<Transform, String> {
+    if pairs.len() < 3 {
+        return Err(format!(
+            "Need at least 3 landmark pairs, got {}",
+            pairs.len()
+        ));
+    }
+    let source: Vec<Vec3> = pairs.iter().map(|(a, _)| *a).collect();
+    let target: Vec<Vec3> = pairs.iter().map(|(_, b)| *b).collect();
+    let (cs, ct) = (geometry::centroid(&source), geometry::centroid(&target));
+
+    // s[i][j] = sum of a_i * b_j over the centred pairs
+    let mut s = [[0.0; 3]; 3];
+    let mut source_spread = 0.0;
+    for (a, b) in source.iter().zip(&target) {
+        let a = geometry::sub(*a, cs);
+        let b = geometry::sub(*b, ct);
+        for i in 0..3 {
+            for j in 0..3 {
+                s[i][j] += a[i] * b[j];
+            }
+        }
+        source_spread += geometry::dot(a, a);
+    }
+    if source_spread == 0.0 {
+        return Err("Source landmarks all coincide".to_owned());
+    }
+
+    let n = [
+        [
+            s[0][0] + s[1][1] + s[2][2],
+            s[1][2] - s[2][1],
+            s[2][0] - s[0][2],
+            s[0][1] - s[1][0],
+        ],
+        [
+            s[1][2] - s[2][1],
+            s[0][0] - s[1][1] - s[2][2],
+            s[0][1] + s[1][0],
+            s[2][0] + s[0][2],
+        ],
+        [
+            s[2][0] - s[0][2],
+            s[0][1] + s[1][0],
+            -s[0][0] + s[1][1] - s[2][2],
+            s[1][2] + s[2][1],
+        ],
+        [
+            s[0][1] - s[1][0],
+            s[2][0] + s[0][2],
+            s[1][2] + s[2][1],
+            -s[0][0] - s[1][1] + s[2][2],
+        ],
+    ];
+    let (values, vectors) = geometry::symmetric_eigen(n);
+    // A repeated top eigenvalue means the rotation is not pinned down
+    let tolerance = 1e-9 * values[0].abs().max(values[3].abs()).max(f64::MIN_POSITIVE);
+    if values[0] - values[1] <= tolerance {
+        return Err("Landmarks are collinear; the rotation is ambiguous".to_owned());
+    }
+    let rotation = quaternion_to_matrix(vectors[0]);
+
+    let scale = if allow_scale {
+        let aligned: f64 = source
+            .iter()
+            .zip(&target)
+            .map(|(a, b)| {
+                let ra = rotation.map(|row| geometry::dot(row, geometry::sub(*a, cs)));
+                geometry::dot(ra, geometry::sub(*b, ct))
+            })
+            .sum();
+        aligned / source_spread
+    } else {
+        1.0
+    };
+    let rc = rotation.map(|row| geometry::dot(row, cs));
+    let translation = std::array::from_fn(|i| ct[i] - scale * rc[i]);
+    Ok(Transform {
+        rotation,
+        translation,
+        scale,
+    })
+}
+
+fn quaternion_to_matrix(q: [f64; 4]) -> [[f64; 3]; 3] {
+    let norm = q.iter().map(|v| v * v).sum::<f64>().sqrt();
+    let [w, x, y, z] = q.map(|v| v / norm);
+    [
+        [
+            1.0 - 2.0 * (y * y + z * z),
+            2.0 * (x * y - z * w),
+            2.0 * (x * z + y * w),
+        ],
+        [
+            2.0 * (x * y + z * w),
+            1.0 - 2.0 * (x * x + z * z),
+            2.0 * (y * z - x * w),
+        ],
+        [
+            2.0 * (x * z - y * w),
+            2.0 * (y * z + x * w),
+            1.0 - 2.0 * (x * x + y * y),
+        ],
+    ]
+}
+
+/// Knobs for `icp`
+#[derive(Debug, Clone)]
+pub struct IcpOptions {
+    pub max_iterations: usize,
+    /// Stop once the RMS distance improves by less than this between two
+    /// iterations
+    pub tolerance: f64,
+    pub allow_scale: bool,
+    /// Source nodes used for matching; larger clouds are thinned evenly
+    pub max_points: usize,
+    /// A converged fit whose RMS distance exceeds this fraction of the
+    /// target's RMS radius is reported as `IcpStatus::PoorFit`
+    pub max_relative_rms: f64,
+}
+
+impl Default for IcpOptions {
+    fn default() -> Self {
+        IcpOptions {
+            max_iterations: 50,
+            tolerance: 1e-9,
+            allow_scale: false,
+            max_points: 500,
+            max_relative_rms: 0.1,
+        }
+    }
+}
+
+/// How an `icp` run ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IcpStatus {
+    Converged,
+    /// Still improving when `max_iterations` ran out
+    MaxIterations,
+    /// Settled, but far from the target; most likely a wrong local minimum or
+    /// two morphologies that do not correspond
+    PoorFit,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IcpReport {
+    pub status: IcpStatus,
+    pub iterations: usize,
+    /// RMS distance from each used source node to its closest target node
+    pub rms: f64,
+}
+
+/// Iterative closest point: alternates matching every source node to its
+/// closest target node and refitting the transform on those matches. Starts
+/// from the transform that lines up the two centroids, so the clouds should
+/// already be roughly oriented alike.
+pub fn icp(
+    source_nodes: &[Node],
+    target_nodes: &[Node],
+    options: &IcpOptions,
+) -> Result<(Transform, IcpReport), String> {
+    if source_nodes.len() < 3 || target_nodes.is_empty() {
+        return Err("icp needs at least 3 source nodes and a non-empty target".to_owned());
+    }
+    let position = |n: &Node| [n.x_pos, n.y_pos, n.z_pos];
+    let stride = source_nodes.len().div_ceil(options.max_points.max(3));
+    let source: Vec<Vec3> = source_nodes.iter().step_by(stride).map(position).collect();
+    let target: Vec<Vec3> = target_nodes.iter().map(position).collect();
+
+    let (cs, ct) = (geometry::centroid(&source), geometry::centroid(&target));
+    let mut transform = Transform {
+        translation: geometry::sub(ct, cs),
+        ..Transform::identity()
+    };
+    let closest = |p: Vec3| {
+        target
+            .iter()
+            .map(|t| {
+                let d = geometry::sub(p, *t);
+                (geometry::dot(d, d), *t)
+            })
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+            .unwrap()
+    };
+    let match_all = |transform: &Transform| {
+        let mut pairs = Vec::with_capacity(source.len());
+        let mut squared = 0.0;
+        for p in &source {
+            let (d2, t) = closest(transform.apply(*p));
+            squared += d2;
+            pairs.push((*p, t));
+        }
+        (pairs, (squared / source.len() as f64).sqrt())
+    };
+
+    let (mut pairs, mut rms) = match_all(&transform);
+    let mut status = IcpStatus::MaxIterations;
+    let mut iterations = 0;
+    while iterations < options.max_iterations {
+        iterations += 1;
+        let Ok(next) = fit_landmarks(&pairs, options.allow_scale) else {
+            // Everything matched onto a line or a point; nothing to refine
+            status = IcpStatus::PoorFit;
+            break;
+        };
+        let (next_pairs, next_rms) = match_all(&next);
+        let improvement = rms - next_rms;
+        if next_rms <= rms {
+            transform = next;
+            pairs = next_pairs;
+            rms = next_rms;
+        }
+        if improvement < options.tolerance {
+            status = IcpStatus::Converged;
+            break;
+        }
+    }
+
+    let spread = (target
+        .iter()
+        .map(|t| {
+            let d = geometry::sub(*t, ct);
+            geometry::dot(d, d)
+        })
+        .sum::<f64>()
+        / target.len() as f64)
+        .sqrt();
+    if status == IcpStatus::Converged && rms > options.max_relative_rms * spread {
+        status = IcpStatus::PoorFit;
+    }
+    Ok((
+        transform,
+        IcpReport {
+            status,
+            iterations,
+            rms,
+        },
+    ))
+}
+
+/// Knobs for `normalize_frame`
+#[derive(Debug, Clone)]
+pub struct FrameOptions {
+    /// Node types whose positions set the principal axes. When the cell has
+    /// none of them, every node but the soma is used instead.
+    pub structures: Vec<StructureIdentifier>,
+    /// Point +x towards the side with more apical cable (all dendritic cable
+    /// when there is no apical) and +y towards the side with more cable.
+    /// Off, the axes keep whatever sign the eigen solver gave them, so a
+    /// rotated copy of a cell may come out mirrored.
+    pub sign_convention: bool,
+    /// Variances closer than this fraction of the largest count as equal
+    pub isotropy_tolerance: f64,
+}
+
+impl Default for FrameOptions {
+    fn default() -> Self {
+        FrameOptions {
+            structures: vec![
+                StructureIdentifier::BasalDendrite,
+                StructureIdentifier::ApicalDendrite,
+            ],
+            sign_convention: true,
+            isotropy_tolerance: 1e-6,
+        }
+    }
+}
+
+/// How `normalize_frame` moved a cell
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct FrameInfo {
+    /// Takes the original coordinates to the canonical frame; its
+    /// `inverse` maps results back
+    pub transform: Transform,
+    /// Soma centroid in the original coordinates, the new origin
+    pub origin: [f64; 3],
+    /// Variance along the new x, y and z
+    pub variances: [f64; 3],
+    /// Some variances were equal, so the axes spanning them were set by
+    /// the fallback rule instead of the data
+    pub isotropic: bool,
+}
+
+/// Moves `skeleton` into a canonical frame: origin at the centroid of the
+/// soma nodes (the root if there are none), x, y and z along the principal
+/// axes of the arbor from most to least variance, right-handed.
+///
+/// When variances tie, as for an isotropic cloud or a straight line, the
+/// axes within the tie are not defined by the data. They are then taken as
+/// the world x, y and z axes, in that order, projected into the tied
+/// subspace and orthonormalised, so the result never depends on round-off.
+pub fn normalize_frame(
+    skeleton: &Skeleton,
+    options: &FrameOptions,
+) -> Result<(Skeleton, FrameInfo), String> {
+    let nodes = &skeleton.nodes;
+    let root = nodes
+        .iter()
+        .find(|n| n.parent_id == n.node_id)
+        .ok_or_else(|| "No root node found".to_owned())?;
+    let position = |n: &Node| [n.x_pos, n.y_pos, n.z_pos];
+    let soma: Vec<Vec3> = nodes
+        .iter()
+        .filter(|n| n.structured_identifier == StructureIdentifier::Soma)
+        .map(position)
+        .collect();
+    let origin = if soma.is_empty() {
+        position(root)
+    } else {
+        geometry::centroid(&soma)
+    };
+
+    let mut points: Vec<Vec3> = nodes
+        .iter()
+        .filter(|n| options.structures.contains(&n.structured_identifier))
+        .map(position)
+        .collect();
+    if points.is_empty() {
+        points = nodes
+            .iter()
+            .filter(|n| n.structured_identifier != StructureIdentifier::Soma)
+            .map(position)
+            .collect();
+    }
+    let covariance = geometry::covariance(&points);
+    let (values, vectors) = geometry::symmetric_eigen(covariance);
+    let (mut axes, isotropic) = resolve_ties(values, vectors, options.isotropy_tolerance);
+
+    if options.sign_convention {
+        let moment = |axis: Vec3, keep: &dyn Fn(&Node) -> bool| {
+            geometry::stable_sum(nodes.iter().filter(|n| keep(n)).map(|n| {
+                let parent = &nodes[n.parent_id as usize];
+                let length = geometry::norm(geometry::sub(position(n), position(parent)));
+                let middle = geometry::scale(geometry::add(position(n), position(parent)), 0.5);
+                length * geometry::dot(axis, geometry::sub(middle, origin))
+            }))
+        };
+        let neurite = |n: &Node| n.structured_identifier != StructureIdentifier::Soma;
+        let apical = |n: &Node| n.structured_identifier == StructureIdentifier::ApicalDendrite;
+        let x_moment = if nodes.iter().any(apical) {
+            moment(axes[0], &apical)
+        } else {
+            moment(axes[0], &|n: &Node| {
+                neurite(n) && n.structured_identifier != StructureIdentifier::Axon
+            })
+        };
+        if x_moment < 0.0 {
+            axes[0] = geometry::scale(axes[0], -1.0);
+        }
+        if moment(axes[1], &neurite) < 0.0 {
+            axes[1] = geometry::scale(axes[1], -1.0);
+        }
+    }
+    axes[2] = geometry::cross(axes[0], axes[1]);
+
+    let rotated_origin = axes.map(|axis| geometry::dot(axis, origin));
+    let transform = Transform {
+        rotation: axes,
+        translation: rotated_origin.map(|v| -v),
+        scale: 1.0,
+    };
+    let variances =
+        axes.map(|axis| geometry::dot(axis, covariance.map(|row| geometry::dot(row, axis))));
+    Ok((
+        apply_transform(skeleton, &transform),
+        FrameInfo {
+            transform,
+            origin,
+            variances,
+            isotropic,
+        },
+    ))
+}
+
+/// Replaces eigenvectors whose eigenvalues tie with the world axes
+/// projected into their common subspace
+fn resolve_ties(values: [f64; 3], vectors: [[f64; 3]; 3], tolerance: f64) -> ([Vec3; 3], bool) {
+    let tied = |i: usize| values[i] - values[i + 1] <= tolerance * values[0].abs();
+    let mut axes = vectors;
+    let mut isotropic = false;
+    let mut start = 0;
+    while start < 3 {
+        let mut end = start + 1;
+        while end < 3 && tied(end - 1) {
+            end += 1;
+        }
+        if end - start > 1 {
+            isotropic = true;
+            let project = |v: Vec3| {
+                (start..end).fold([0.0; 3], |acc, k| {
+                    geometry::add(
+                        acc,
+                        geometry::scale(vectors[k], geometry::dot(v, vectors[k])),
+                    )
+                })
+            };
+            let world = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+            let mut next = start;
+            for w in world {
+                if next == end {
+                    break;
+                }
+                let mut u = project(w);
+                for axis in &axes[start..next] {
+                    u = geometry::sub(u, geometry::scale(*axis, geometry::dot(u, *axis)));
+                }
+                let length = geometry::norm(u);
+                if length > 1e-6 {
+                    axes[next] = geometry::scale(u, 1.0 / length);
+                    next += 1;
+                }
+            }
+        }
+        start = end;
+    }
+    (axes, isotropic)
+}
@@ -0,0 +1,347 @@
+//! An opt-in, in-process registry of loaded skeletons keyed by `CellId`, so
+//! notebook helpers and batch code can hand cells around by id instead of
+//! reading them again.
+//!
+//! Entries are `Arc<Skeleton>`, shared read-only: every `get` hands out the
+//! same data, without copies. With a budget, the least recently used
+//! entries go once the accounted bytes (`Skeleton::accounted_bytes`) would
+//! exceed it, and `put` reports which went. The map sits behind a mutex, so
+//! one registry serves any number of threads; `Registry::global` is the one
+//! the Python bindings use.
+
+use std::collections::HashMap;
+use std::fs;
+use std::mem::size_of;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::SystemTime;
+
+use log::info;
+
+use crate::bundle::{Bundle, is_bundle};
+use crate::cell_id::CellId;
+use crate::standardize::{Dataset, read_source};
+use crate::swc_reader::{Node, ReaderOptions, Skeleton, swc_reader_from_bytes};
+use crate::warnings::SwcWarning;
+
+/// Outcome of `Registry::put`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stored {
+    pub id: CellId,
+    /// Entries evicted to make room, least recently used first
+    pub evicted: Vec<CellId>,
+}
+
+struct Entry {
+    skeleton: Arc<Skeleton>,
+    bytes: usize,
+    last_used: u64,
+}
+
+/// A file as `Dataset::load` read it. A change to the file or the options
+/// makes it a different source.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Source {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    options: String,
+}
+
+#[derive(Default)]
+struct Inner {
+    entries: HashMap<CellId, Entry>,
+    sources: HashMap<Source, CellId>,
+    budget: Option<usize>,
+    bytes: usize,
+    clock: u64,
+}
+
+impl Inner {
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    fn remove(&mut self, id: &CellId) -> bool {
+        let Some(entry) = self.entries.remove(id) else {
+            return false;
+        };
+        self.bytes -= entry.bytes;
+        self.sources.retain(|_, v| v != id);
+        true
+    }
+
+    /// Evicts least recently used entries until `extra` more bytes fit the
+    /// budget
+    fn make_room(&mut self, extra: usize) -> Vec<CellId> {
+        let mut evicted = Vec::new();
+        let Some(budget) = self.budget else {
+            return evicted;
+        };
+        while self.bytes + extra > budget {
+            let Some(oldest) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, e)| e.last_used)
+                .map(|(id, _)| *id)
+            else {
+                break;
+            };
+            self.remove(&oldest);
+            evicted.push(oldest);
+        }
+        if !evicted.is_empty() {
+            info!(
+                "Evicted {} cells to stay within {} bytes",
+                evicted.len(),
+                budget
+            );
+        }
+        evicted
+    }
+}
+
+#[derive(Default)]
+pub struct Registry {
+    inner: Mutex<Inner>,
+}
+
+impl Registry {
+    /// An empty registry holding at most `budget` accounted bytes, no limit
+    /// if None
+    pub fn new(budget: Option<usize>) -> Registry {
+        Registry {
+            inner: Mutex::new(Inner {
+                budget,
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// The process-wide registry, without a budget until one is set
+    pub fn global() -> &'static Registry {
+        static GLOBAL: OnceLock<Registry> = OnceLock::new();
+        GLOBAL.get_or_init(Registry::default)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Stores `skeleton` under its `CellId`, replacing any entry already
+    /// there, and evicts what no longer fits. A skeleton larger than the
+    /// whole budget is refused.
+    pub fn put(&self, skeleton: Arc<Skeleton>) -> Result<Stored, String> {
+        // Both walk the whole skeleton, so stay outside the lock
+        let id = skeleton.cell_id();
+        let bytes = skeleton.accounted_bytes();
+        let mut inner = self.lock();
+        if let Some(budget) = inner.budget
+            && bytes > budget
+        {
+            return Err(format!(
+                "Cell {} takes {} bytes, over the budget of {}",
+                id, bytes, budget
+            ));
+        }
+        // Same id, same content, so files loaded as it still match
+        if let Some(old) = inner.entries.remove(&id) {
+            inner.bytes -= old.bytes;
+        }
+        let evicted = inner.make_room(bytes);
+        let last_used = inner.tick();
+        inner.entries.insert(
+            id,
+            Entry {
+                skeleton,
+                bytes,
+                last_used,
+            },
+        );
+        inner.bytes += bytes;
+        Ok(Stored { id, evicted })
+    }
+
+    /// The skeleton stored under `id`, marking it as just used
+    pub fn get(&self, id: &CellId) -> Option<Arc<Skeleton>> {
+        let mut inner = self.lock();
+        let now = inner.tick();
+        let entry = inner.entries.get_mut(id)?;
+        entry.last_used = now;
+        Some(Arc::clone(&entry.skeleton))
+    }
+
+    pub fn contains(&self, id: &CellId) -> bool {
+        self.lock().entries.contains_key(id)
+    }
+
+    /// Every id stored, least recently used first, so in the order a budget
+    /// would evict them
+    pub fn list(&self) -> Vec<CellId> {
+        let inner = self.lock();
+        let mut entries: Vec<(u64, CellId)> = inner
+            .entries
+            .iter()
+            .map(|(id, e)| (e.last_used, *id))
+            .collect();
+        entries.sort_unstable();
+        entries.into_iter().map(|(_, id)| id).collect()
+    }
+
+    /// Drops the entry for `id`. Returns whether there was one.
+    pub fn evict(&self, id: &CellId) -> bool {
+        self.lock().remove(id)
+    }
+
+    /// Drops every entry, returning how many there were. The skeletons are
+    /// freed once no one else holds them.
+    pub fn clear(&self) -> usize {
+        let mut inner = self.lock();
+        let count = inner.entries.len();
+        inner.entries.clear();
+        inner.sources.clear();
+        inner.bytes = 0;
+        count
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().entries.is_empty()
+    }
+
+    /// Sum of `Skeleton::accounted_bytes` over the entries
+    pub fn accounted_bytes(&self) -> usize {
+        self.lock().bytes
+    }
+
+    pub fn budget(&self) -> Option<usize> {
+        self.lock().budget
+    }
+
+    /// Sets the budget, None for no limit, and returns the entries evicted
+    /// to fit it
+    pub fn set_budget(&self, budget: Option<usize>) -> Vec<CellId> {
+        let mut inner = self.lock();
+        inner.budget = budget;
+        inner.make_room(0)
+    }
+
+    fn get_source(&self, source: &Source) -> Option<Arc<Skeleton>> {
+        let id = *self.lock().sources.get(source)?;
+        self.get(&id)
+    }
+
+    fn add_source(&self, source: Source, id: CellId) {
+        let mut inner = self.lock();
+        if inner.entries.contains_key(&id) {
+            inner.sources.insert(source, id);
+        }
+    }
+}
+
+impl Dataset {
+    /// Reads every file, stopping at the first that fails. A bundle (see
+    /// `bundle`) stands for all its cells, in the order they were added.
+    /// Through `registry`, a file already read with the same options and
+    /// unchanged on disk since comes back from the registry while it holds
+    /// the cell, as does any cell of a bundle it holds, and every cell read
+    /// is stored there. Cells in a bundle are read as they were written,
+    /// whatever `options` say.
+    pub fn load(
+        &self,
+        options: &ReaderOptions,
+        registry: Option<&Registry>,
+    ) -> Result<Vec<Arc<Skeleton>>, String> {
+        let read = |path: &Path| {
+            read_source(path)
+                .map_err(|e| e.to_string())
+                .and_then(|data| swc_reader_from_bytes(&data, options).map_err(|e| e.to_string()))
+                .map(Arc::new)
+                .map_err(|e| format!("{}: {}", path.display(), e))
+        };
+        let mut cells = Vec::new();
+        for path in &self.paths {
+            if is_bundle(path) {
+                load_bundle(path, registry, &mut cells)?;
+                continue;
+            }
+            let Some(registry) = registry else {
+                cells.push(read(path)?);
+                continue;
+            };
+            let source = Source {
+                path: path.clone(),
+                modified: fs::metadata(path).and_then(|m| m.modified()).ok(),
+                options: format!("{:?}", options),
+            };
+            if let Some(skeleton) = registry.get_source(&source) {
+                cells.push(skeleton);
+                continue;
+            }
+            let skeleton = read(path)?;
+            let stored = registry
+                .put(Arc::clone(&skeleton))
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+            registry.add_source(source, stored.id);
+            cells.push(skeleton);
+        }
+        Ok(cells)
+    }
+}
+
+/// Appends every cell of the bundle at `path` to `cells`
+fn load_bundle(
+    path: &Path,
+    registry: Option<&Registry>,
+    cells: &mut Vec<Arc<Skeleton>>,
+) -> Result<(), String> {
+    let bundle = Bundle::open(path)?;
+    for id in bundle.list() {
+        if let Some(skeleton) = registry.and_then(|r| r.get(&id)) {
+            cells.push(skeleton);
+            continue;
+        }
+        let skeleton = Arc::new(bundle.get(&id)?.expect("listed cells are in the bundle"));
+        if let Some(registry) = registry {
+            registry
+                .put(Arc::clone(&skeleton))
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+        }
+        cells.push(skeleton);
+    }
+    Ok(())
+}
+
+impl Skeleton {
+    /// Approximate bytes held by the skeleton: the struct itself and the
+    /// heap buffers it owns, by capacity, without allocator or hash table
+    /// overhead
+    pub fn accounted_bytes(&self) -> usize {
+        let map = |m: &HashMap<u64, Vec<u64>>| -> usize {
+            m.values()
+                .map(|v| size_of::<(u64, Vec<u64>)>() + v.capacity() * size_of::<u64>())
+                .sum()
+        };
+        let extras: usize = self
+            .extras
+            .values()
+            .map(|v| size_of::<(u64, Vec<f64>)>() + v.capacity() * size_of::<f64>())
+            .sum();
+        let strings = |s: &mut dyn Iterator<Item = &String>| -> usize {
+            s.map(|s| size_of::<String>() + s.capacity()).sum()
+        };
+        let metadata = &self.metadata;
+        size_of::<Skeleton>()
+            + self.nodes.capacity() * size_of::<Node>()
+            + map(&self.parent_child_map)
+            + map(&self.child_parent_map)
+            + self.warnings.capacity() * size_of::<SwcWarning>()
+            + extras
+            + strings(&mut self.extra_columns.iter())
+            + strings(&mut metadata.original_source.iter().chain(&metadata.soma_type))
+            + strings(&mut metadata.other.iter().flat_map(|(k, v)| [k, v]))
+    }
+}
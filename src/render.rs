@@ -0,0 +1,411 @@
+//! Plotting-free views of a skeleton's topology: an indented tree with one
+//! line per branch for a terminal, `Skeleton::render_ascii`, and a
+//! standalone SVG dendrogram, `Skeleton::dendrogram_svg`.
+//!
+//! Branches are those of `features::branches`, runs between branch points,
+//! tips and the root, typed by their distal node. Lengths and path distances
+//! are in µm.
+
+use std::fmt::Write;
+use std::path::Path;
+
+use crate::features::{branches, by_id, euclidean, root_of};
+use crate::swc_reader::{ConflictPolicy, Skeleton, StructureIdentifier};
+use crate::write;
+
+/// Blocks in a full scalar bar
+pub const BAR_WIDTH: usize = 8;
+
+/// Per-branch quantity drawn as a bar after each line of `render_ascii`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BranchScalar {
+    Length,
+    /// Mean diameter, each segment weighted by its length
+    MeanDiameter,
+    /// Path distance from the root to the distal end
+    EndDistance,
+    /// Tips at or beyond the branch
+    Tips,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AsciiOptions {
+    /// Deepest branches shown, 0 for those starting at the root. Deeper ones
+    /// are summarized in one line under their parent.
+    pub max_depth: Option<usize>,
+    /// Only show the branches on the `n` longest paths from the root to a
+    /// tip, for cells too large to read in full
+    pub longest_paths: Option<usize>,
+    /// A bar of up to `BAR_WIDTH` blocks per branch, scaled between the
+    /// smallest and largest value over all branches
+    pub bar: Option<BranchScalar>,
+}
+
+/// Full blocks `value` gets on a bar from `min` (none) to `max`
+/// (`BAR_WIDTH`), rounded to the nearest. A flat range fills the bar; NaN
+/// leaves it empty.
+pub fn bar_blocks(value: f64, min: f64, max: f64) -> usize {
+    if value.is_nan() {
+        return 0;
+    }
+    if max <= min {
+        return BAR_WIDTH;
+    }
+    let fraction = ((value - min) / (max - min)).clamp(0.0, 1.0);
+    (fraction * BAR_WIDTH as f64).round() as usize
+}
+
+struct Branch {
+    structure: StructureIdentifier,
+    length: f64,
+    mean_diameter: f64,
+    end_distance: f64,
+    depth: usize,
+    children: Vec<usize>,
+    /// Tips and branch points at or beyond the branch
+    tips: usize,
+    branch_points: usize,
+}
+
+impl Branch {
+    fn scalar(&self, scalar: BranchScalar) -> f64 {
+        match scalar {
+            BranchScalar::Length => self.length,
+            BranchScalar::MeanDiameter => self.mean_diameter,
+            BranchScalar::EndDistance => self.end_distance,
+            BranchScalar::Tips => self.tips as f64,
+        }
+    }
+}
+
+/// The branch tree: every branch, the root's node ID and type, and the
+/// branches starting at the root
+struct Tree {
+    root: (u64, StructureIdentifier),
+    branches: Vec<Branch>,
+    top: Vec<usize>,
+}
+
+impl Tree {
+    fn new(skeleton: &Skeleton) -> Result<Tree, String> {
+        let root = root_of(skeleton)?;
+        let nodes = by_id(skeleton);
+        let stats = branches(skeleton)?;
+        let ends: std::collections::HashMap<u64, usize> = stats
+            .iter()
+            .enumerate()
+            .map(|(i, b)| (*b.path.last().unwrap_or(&root), i))
+            .collect();
+        let mut tree = Tree {
+            root: (root, nodes[&root].structured_identifier),
+            branches: Vec::with_capacity(stats.len()),
+            top: Vec::new(),
+        };
+        // Distal ends come in breadth first order, so parents come first
+        for (i, stat) in stats.iter().enumerate() {
+            // Each segment counts with the diameter of its distal node, so
+            // a soma at the proximal end does not swell the first branches
+            let (mut weighted, mut plain) = (0.0, 0.0);
+            for pair in stat.path.windows(2) {
+                let (a, b) = (nodes[&pair[0]], nodes[&pair[1]]);
+                weighted += euclidean(a, b) * 2.0 * b.radius;
+                plain += 2.0 * b.radius;
+            }
+            let mean_diameter = if stat.length > 0.0 {
+                weighted / stat.length
+            } else {
+                plain / (stat.path.len() - 1).max(1) as f64
+            };
+            let parent = ends.get(&stat.path[0]).copied();
+            let (depth, start) = match parent {
+                Some(p) => (tree.branches[p].depth + 1, tree.branches[p].end_distance),
+                None => (0, 0.0),
+            };
+            match parent {
+                Some(p) => tree.branches[p].children.push(i),
+                None => tree.top.push(i),
+            }
+            tree.branches.push(Branch {
+                structure: nodes[&stat.path[stat.path.len() - 1]].structured_identifier,
+                length: stat.length,
+                mean_diameter,
+                end_distance: start + stat.length,
+                depth,
+                children: Vec::new(),
+                tips: 0,
+                branch_points: 0,
+            });
+        }
+        for i in (0..tree.branches.len()).rev() {
+            let children = std::mem::take(&mut tree.branches[i].children);
+            let (tips, branch_points) = if children.is_empty() {
+                (1, 0)
+            } else {
+                children.iter().fold((0, 1), |(t, b), &c| {
+                    (
+                        t + tree.branches[c].tips,
+                        b + tree.branches[c].branch_points,
+                    )
+                })
+            };
+            let branch = &mut tree.branches[i];
+            (branch.children, branch.tips, branch.branch_points) = (children, tips, branch_points);
+        }
+        Ok(tree)
+    }
+
+    fn subtree_size(&self, i: usize) -> usize {
+        1 + self.branches[i]
+            .children
+            .iter()
+            .map(|&c| self.subtree_size(c))
+            .sum::<usize>()
+    }
+
+    /// Branches on the `n` longest root to tip paths
+    fn on_longest_paths(&self, n: usize) -> Vec<bool> {
+        let mut parent = vec![None; self.branches.len()];
+        for (i, b) in self.branches.iter().enumerate() {
+            for &c in &b.children {
+                parent[c] = Some(i);
+            }
+        }
+        let mut tips: Vec<usize> = (0..self.branches.len())
+            .filter(|&i| self.branches[i].children.is_empty())
+            .collect();
+        tips.sort_by(|&a, &b| {
+            self.branches[b]
+                .end_distance
+                .total_cmp(&self.branches[a].end_distance)
+                .then(a.cmp(&b))
+        });
+        let mut keep = vec![false; self.branches.len()];
+        for &tip in tips.iter().take(n) {
+            let mut at = Some(tip);
+            while let Some(i) = at.filter(|&i| !keep[i]) {
+                keep[i] = true;
+                at = parent[i];
+            }
+        }
+        keep
+    }
+}
+
+fn type_name(structure: StructureIdentifier) -> &'static str {
+    match structure {
+        StructureIdentifier::Undefined => "undefined",
+        StructureIdentifier::Soma => "soma",
+        StructureIdentifier::Axon => "axon",
+        StructureIdentifier::BasalDendrite => "basal",
+        StructureIdentifier::ApicalDendrite => "apical",
+        StructureIdentifier::ForkPoint => "fork",
+        StructureIdentifier::EndPoint => "end",
+        StructureIdentifier::Custom => "custom",
+    }
+}
+
+fn plural(n: usize, one: &str, many: &str) -> String {
+    format!("{} {}", n, if n == 1 { one } else { many })
+}
+
+/// Writes the lines under one branch, or under the root, to `out`
+struct Printer<'a> {
+    tree: &'a Tree,
+    shown: Vec<bool>,
+    /// Scalar and its range, when bars are drawn
+    bar: Option<(BranchScalar, f64, f64)>,
+    out: String,
+}
+
+impl Printer<'_> {
+    fn children(&mut self, children: &[usize], prefix: &str) {
+        let (shown, hidden): (Vec<usize>, Vec<usize>) =
+            children.iter().partition(|&&c| self.shown[c]);
+        let hidden: usize = hidden.iter().map(|&c| self.tree.subtree_size(c)).sum();
+        for (k, &c) in shown.iter().enumerate() {
+            let last = k + 1 == shown.len() && hidden == 0;
+            self.line(c, prefix, last);
+        }
+        if hidden > 0 {
+            let _ = writeln!(
+                self.out,
+                "{}└── … {} not shown",
+                prefix,
+                plural(hidden, "more branch", "more branches")
+            );
+        }
+    }
+
+    fn line(&mut self, i: usize, prefix: &str, last: bool) {
+        let branch = &self.tree.branches[i];
+        let _ = write!(
+            self.out,
+            "{}{}{} {:.1} µm, ⌀ {:.2} µm",
+            prefix,
+            if last { "└── " } else { "├── " },
+            type_name(branch.structure),
+            branch.length,
+            branch.mean_diameter
+        );
+        if branch.children.is_empty() {
+            self.out.push_str(", tip");
+        } else {
+            let _ = write!(
+                self.out,
+                ", {}, {}",
+                plural(branch.tips, "tip", "tips"),
+                plural(branch.branch_points, "branch point", "branch points")
+            );
+        }
+        if let Some((scalar, min, max)) = self.bar {
+            let blocks = bar_blocks(branch.scalar(scalar), min, max);
+            let _ = write!(
+                self.out,
+                "  [{}{}]",
+                "█".repeat(blocks),
+                " ".repeat(BAR_WIDTH - blocks)
+            );
+        }
+        self.out.push('\n');
+        let prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+        self.children(&branch.children, &prefix);
+    }
+}
+
+/// Horizontal scale and vertical spacing of the dendrogram, in pixels
+const SVG_WIDTH: f64 = 800.0;
+const SVG_MARGIN: f64 = 20.0;
+const SVG_ROW: f64 = 14.0;
+
+fn colour(structure: StructureIdentifier) -> &'static str {
+    match structure {
+        StructureIdentifier::Soma => "#000000",
+        StructureIdentifier::Axon => "#1f77b4",
+        StructureIdentifier::BasalDendrite => "#d62728",
+        StructureIdentifier::ApicalDendrite => "#9467bd",
+        _ => "#7f7f7f",
+    }
+}
+
+impl Skeleton {
+    /// An indented tree of the skeleton's branches, one line each with
+    /// type, length, mean diameter and the tips and branch points beyond
+    /// it, under a line for the root
+    pub fn render_ascii(&self, options: &AsciiOptions) -> Result<String, String> {
+        let tree = Tree::new(self)?;
+        let mut shown = match options.longest_paths {
+            Some(n) => tree.on_longest_paths(n),
+            None => vec![true; tree.branches.len()],
+        };
+        if let Some(depth) = options.max_depth {
+            for (keep, branch) in shown.iter_mut().zip(&tree.branches) {
+                *keep &= branch.depth <= depth;
+            }
+        }
+        let bar = options.bar.map(|scalar| {
+            let values = tree.branches.iter().map(|b| b.scalar(scalar));
+            let min = values.clone().fold(f64::INFINITY, f64::min);
+            let max = values.fold(f64::NEG_INFINITY, f64::max);
+            (scalar, min, max)
+        });
+        let tips = tree.top.iter().map(|&i| tree.branches[i].tips).sum();
+        let length: f64 = tree.branches.iter().map(|b| b.length).sum();
+        let mut printer = Printer {
+            tree: &tree,
+            shown,
+            bar,
+            out: format!(
+                "{} root, node {}: {}, {}, {:.1} µm of cable\n",
+                type_name(tree.root.1),
+                tree.root.0,
+                plural(tree.branches.len(), "branch", "branches"),
+                plural(tips, "tip", "tips"),
+                length
+            ),
+        };
+        printer.children(&tree.top, "");
+        Ok(printer.out)
+    }
+
+    /// A standalone SVG dendrogram: every branch one elbow `path` from its
+    /// parent's end, with x the path distance from the root and tips spread
+    /// evenly down the page, coloured by type
+    pub fn dendrogram_svg(&self) -> Result<String, String> {
+        let tree = Tree::new(self)?;
+        let longest = tree
+            .branches
+            .iter()
+            .map(|b| b.end_distance)
+            .fold(0.0, f64::max);
+        let scale = if longest > 0.0 {
+            (SVG_WIDTH - 2.0 * SVG_MARGIN) / longest
+        } else {
+            0.0
+        };
+        // Tips take rows in depth first order, every other branch sits
+        // midway between its first and last child
+        let mut y = vec![0.0; tree.branches.len()];
+        let mut rows = 0;
+        fn place(tree: &Tree, i: usize, y: &mut [f64], rows: &mut usize) {
+            let children = &tree.branches[i].children;
+            for &c in children {
+                place(tree, c, y, rows);
+            }
+            y[i] = match (children.first(), children.last()) {
+                (Some(&a), Some(&b)) => (y[a] + y[b]) / 2.0,
+                _ => {
+                    *rows += 1;
+                    SVG_MARGIN + (*rows - 1) as f64 * SVG_ROW
+                }
+            };
+        }
+        for &i in &tree.top {
+            place(&tree, i, &mut y, &mut rows);
+        }
+        let root_y = match (tree.top.first(), tree.top.last()) {
+            (Some(&a), Some(&b)) => (y[a] + y[b]) / 2.0,
+            _ => SVG_MARGIN,
+        };
+        let height = 2.0 * SVG_MARGIN + rows.saturating_sub(1) as f64 * SVG_ROW;
+
+        let mut svg = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">\n\
+             <g fill=\"none\" stroke-width=\"1.5\">\n",
+            w = SVG_WIDTH,
+            h = height
+        );
+        let mut stack: Vec<(usize, f64, f64)> =
+            tree.top.iter().rev().map(|&i| (i, 0.0, root_y)).collect();
+        while let Some((i, start, parent_y)) = stack.pop() {
+            let branch = &tree.branches[i];
+            let x0 = SVG_MARGIN + start * scale;
+            let x1 = SVG_MARGIN + branch.end_distance * scale;
+            let _ = writeln!(
+                svg,
+                "<path d=\"M {:.2} {:.2} V {:.2} H {:.2}\" stroke=\"{}\"/>",
+                x0,
+                parent_y,
+                y[i],
+                x1,
+                colour(branch.structure)
+            );
+            stack.extend(
+                branch
+                    .children
+                    .iter()
+                    .rev()
+                    .map(|&c| (c, branch.end_distance, y[i])),
+            );
+        }
+        svg.push_str("</g>\n</svg>\n");
+        Ok(svg)
+    }
+
+    /// Writes `dendrogram_svg` to `path`, replacing any file there
+    pub fn render_dendrogram_svg(&self, path: impl AsRef<Path>) -> Result<(), String> {
+        let svg = self.dendrogram_svg()?;
+        write::write_atomic(path.as_ref(), svg.as_bytes(), ConflictPolicy::Overwrite)
+            .map_err(|e| e.to_string())
+    }
+}
@@ -0,0 +1,282 @@
+//! Reversal potentials by ion, set once for the cell or per compartment
+//! instead of on every mechanism that carries the ion.
+//!
+//! A current of a specific ion, such as the sodium and potassium currents
+//! of `hh`, reverses at the most specific of: the mechanism's own value
+//! when it is marked explicit, the compartment's (`Channel::reversals`),
+//! the cell's (`Compartments::set_reversal`), and failing those the
+//! mechanism's own value after all. Nonspecific currents, a leak for
+//! instance, only ever use their own. Ion accumulation writes its Nernst
+//! potentials into the compartment slots of the mechanisms that opted in,
+//! so they resolve like any other compartment-scope setting.
+//!
+//! Building a `Simulation` resolves every mechanism this way, and checks
+//! the explicit values against the ion-scoped ones, see `ReversalCheck`.
+//! Potentials are in mV.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use log::warn;
+
+use crate::channels::{Channel, ChannelType, Ion};
+use crate::compartments::Compartments;
+use crate::describe::mechanism_name;
+use crate::filter::NodeFilter;
+
+/// Ions with a reversal that can be set for them, with NEURON's names for it
+pub const REVERSALS: [(Ion, &str); 4] = [
+    (Ion::Na, "ena"),
+    (Ion::K, "ek"),
+    (Ion::Ca, "eca"),
+    (Ion::Cl, "ecl"),
+];
+
+/// How building a simulation treats explicit mechanism reversals that
+/// disagree with the ion-scoped ones
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReversalCheck {
+    /// Largest difference that still agrees, in mV
+    pub tolerance: f64,
+    /// Refuse to build, listing the mismatches, instead of warning
+    pub strict: bool,
+}
+
+impl Default for ReversalCheck {
+    fn default() -> Self {
+        ReversalCheck {
+            tolerance: 0.1,
+            strict: false,
+        }
+    }
+}
+
+/// A mechanism keeping its own reversal where the ion is set to another
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReversalMismatch {
+    pub idx: usize,
+    /// NEURON's name for the mechanism, e.g. `hh`
+    pub mechanism: String,
+    pub ion: Ion,
+    /// The mechanism's own value
+    pub explicit: f64,
+    /// What the ion is set to at the compartment
+    pub scoped: f64,
+}
+
+impl fmt::Display for ReversalMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Compartment {}: {} keeps the {:?} reversal at {} mV, the ion is set to {} mV",
+            self.idx, self.mechanism, self.ion, self.explicit, self.scoped
+        )
+    }
+}
+
+/// Cell-scope settings, held by `Compartments`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct IonReversals {
+    pub(crate) cell: BTreeMap<Ion, f64>,
+    check: ReversalCheck,
+}
+
+fn checked(ion: Ion, e: f64) -> Result<(), String> {
+    if ion.valence().is_none() {
+        return Err(
+            "Nonspecific currents have no ion-scoped reversal; set the mechanism's own".to_owned(),
+        );
+    }
+    if !e.is_finite() {
+        return Err(format!("Reversal potential must be finite, got {}", e));
+    }
+    Ok(())
+}
+
+/// Takes every reversal of `channel`'s mechanism that is not explicit from
+/// the channel's own slots, or else from `cell`
+pub(crate) fn resolve(channel: &mut Channel, cell: &BTreeMap<Ion, f64>) {
+    let Channel {
+        channel_type,
+        reversals,
+        ..
+    } = channel;
+    if let ChannelType::HodgkinHuxley(hh) = channel_type {
+        for (ion, e, explicit) in hh.reversal_slots() {
+            if let (false, Some(&scoped)) = (explicit, reversals.get(&ion).or(cell.get(&ion))) {
+                *e = scoped;
+            }
+        }
+    }
+}
+
+impl Compartments {
+    /// Sets the reversal of `ion` for the whole cell. Compartments with their
+    /// own value for it, and mechanisms marked explicit, keep theirs.
+    pub fn set_reversal(&mut self, ion: Ion, e: f64) -> Result<(), String> {
+        checked(ion, e)?;
+        self.ion_reversals.cell.insert(ion, e);
+        self.log(
+            "set_reversal",
+            &[("ion", format!("{:?}", ion).into()), ("e", e.into())],
+        );
+        Ok(())
+    }
+
+    /// The cell-scope reversal of `ion`, if set
+    pub fn reversal(&self, ion: Ion) -> Option<f64> {
+        self.ion_reversals.cell.get(&ion).copied()
+    }
+
+    /// Sets the reversal of `ion` in compartment `idx` only, over the cell's
+    pub fn set_reversal_at(&mut self, idx: usize, ion: Ion, e: f64) -> Result<(), String> {
+        checked(ion, e)?;
+        if idx == 0 || idx >= self.components.len() {
+            return Err(format!("No compartment at index {}", idx));
+        }
+        self.components[idx].channel.reversals.insert(ion, e);
+        self.log(
+            "set_reversal",
+            &[
+                ("ion", format!("{:?}", ion).into()),
+                ("e", e.into()),
+                ("idx", idx.into()),
+            ],
+        );
+        Ok(())
+    }
+
+    /// `set_reversal_at` for every compartment matching `filter`. Returns the
+    /// number of compartments changed.
+    pub fn set_reversal_where(
+        &mut self,
+        filter: &NodeFilter,
+        ion: Ion,
+        e: f64,
+    ) -> Result<usize, String> {
+        checked(ion, e)?;
+        let mut changed = 0;
+        for c in self.components.iter_mut().skip(1) {
+            if filter.matches_parts(c.structure, c.flags) {
+                c.channel.reversals.insert(ion, e);
+                changed += 1;
+            }
+        }
+        self.log(
+            "set_reversal",
+            &[
+                ("ion", format!("{:?}", ion).into()),
+                ("e", e.into()),
+                ("filter", format!("{:?}", filter).into()),
+                ("changed", changed.into()),
+            ],
+        );
+        Ok(changed)
+    }
+
+    /// What `ion` is set to at compartment `idx`, its own value over the
+    /// cell's; None where neither is set
+    pub fn scoped_reversal(&self, idx: usize, ion: Ion) -> Option<f64> {
+        let c = self.components.get(idx)?;
+        c.channel
+            .reversals
+            .get(&ion)
+            .or(self.ion_reversals.cell.get(&ion))
+            .copied()
+    }
+
+    /// The reversal of `ion` every compartment's currents use, indexed like
+    /// `components`: the resolved mechanism's where it carries the ion, the
+    /// ion-scoped value elsewhere, NaN where there is neither and for the
+    /// dummy root
+    pub fn effective_reversals(&self, ion: Ion) -> Vec<f64> {
+        self.components
+            .iter()
+            .enumerate()
+            .map(|(idx, c)| {
+                if idx == 0 {
+                    return f64::NAN;
+                }
+                let carried = match &self.resolved_channel(&c.channel).channel_type {
+                    ChannelType::HodgkinHuxley(hh) => hh.reversal(ion),
+                    _ => None,
+                };
+                carried
+                    .or_else(|| self.scoped_reversal(idx, ion))
+                    .unwrap_or(f64::NAN)
+            })
+            .collect()
+    }
+
+    /// How building a simulation treats mismatched reversals
+    pub fn set_reversal_check(&mut self, check: ReversalCheck) {
+        self.ion_reversals.check = check;
+    }
+
+    pub fn reversal_check(&self) -> ReversalCheck {
+        self.ion_reversals.check
+    }
+
+    /// Every explicit mechanism reversal further than the check's tolerance
+    /// from the ion-scoped value at its compartment
+    pub fn reversal_mismatches(&self) -> Vec<ReversalMismatch> {
+        let channels: Vec<&Channel> = self.components.iter().map(|c| &c.channel).collect();
+        self.mismatches_in(&channels)
+    }
+
+    fn mismatches_in(&self, channels: &[&Channel]) -> Vec<ReversalMismatch> {
+        let tolerance = self.ion_reversals.check.tolerance;
+        let mut out = Vec::new();
+        for (idx, channel) in channels.iter().enumerate().skip(1) {
+            let ChannelType::HodgkinHuxley(hh) = &channel.channel_type else {
+                continue;
+            };
+            let mut hh = *hh;
+            for (ion, e, explicit) in hh.reversal_slots() {
+                let scoped = channel
+                    .reversals
+                    .get(&ion)
+                    .or(self.ion_reversals.cell.get(&ion));
+                if let (true, Some(&scoped)) = (explicit, scoped)
+                    && (*e - scoped).abs() > tolerance
+                {
+                    out.push(ReversalMismatch {
+                        idx,
+                        mechanism: mechanism_name(&channel.channel_type).to_owned(),
+                        ion,
+                        explicit: *e,
+                        scoped,
+                    });
+                }
+            }
+        }
+        out
+    }
+
+    /// `channel` with its reversals resolved against this cell's
+    pub(crate) fn resolved_channel(&self, channel: &Channel) -> Channel {
+        let mut channel = channel.clone();
+        resolve(&mut channel, &self.ion_reversals.cell);
+        channel
+    }
+
+    /// Runs the reversal check over `channels`, indexed like `components`:
+    /// warns about every mismatch, or fails listing them when strict
+    pub(crate) fn check_reversals(&self, channels: &[&Channel]) -> Result<(), String> {
+        let mismatches = self.mismatches_in(channels);
+        if mismatches.is_empty() {
+            return Ok(());
+        }
+        if self.ion_reversals.check.strict {
+            let listed: Vec<String> = mismatches.iter().map(|m| m.to_string()).collect();
+            return Err(format!(
+                "Mechanism reversals disagree with their ions: {}",
+                listed.join("; ")
+            ));
+        }
+        for m in &mismatches {
+            warn!("{}", m);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,156 @@
+//! Opt-in provenance log: one JSON object per line for every significant
+//! operation, written and flushed as it happens so a crash still leaves
+//! everything up to that point.
+//!
+//! Each record is `{"seq":..,"time":..,"op":..,"args":{..}}`, with `seq`
+//! counting up from 0 and `time` in seconds since the Unix epoch.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::SwcError;
+use crate::swc_reader::format_float;
+
+/// A value in a record's `args`
+#[derive(Debug, Clone, PartialEq)]
+pub enum LogValue {
+    Str(String),
+    Num(f64),
+    Int(u64),
+    Bool(bool),
+}
+
+impl From<&str> for LogValue {
+    fn from(s: &str) -> Self {
+        LogValue::Str(s.to_owned())
+    }
+}
+
+impl From<String> for LogValue {
+    fn from(s: String) -> Self {
+        LogValue::Str(s)
+    }
+}
+
+impl From<f64> for LogValue {
+    fn from(v: f64) -> Self {
+        LogValue::Num(v)
+    }
+}
+
+impl From<u64> for LogValue {
+    fn from(v: u64) -> Self {
+        LogValue::Int(v)
+    }
+}
+
+impl From<usize> for LogValue {
+    fn from(v: usize) -> Self {
+        LogValue::Int(v as u64)
+    }
+}
+
+impl From<bool> for LogValue {
+    fn from(v: bool) -> Self {
+        LogValue::Bool(v)
+    }
+}
+
+impl fmt::Display for LogValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogValue::Str(s) => write_json_string(f, s),
+            LogValue::Num(v) if v.is_finite() => write!(f, "{}", format_float(*v)),
+            LogValue::Num(_) => write!(f, "null"),
+            LogValue::Int(v) => write!(f, "{}", v),
+            LogValue::Bool(b) => write!(f, "{}", b),
+        }
+    }
+}
+
+fn write_json_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+struct Inner {
+    writer: BufWriter<File>,
+    seq: u64,
+}
+
+/// Handle on a JSONL log file. Clones share the file and the sequence
+/// counter, and records from different threads never interleave. Use one
+/// log per cell when processing many cells at once.
+#[derive(Clone)]
+pub struct RunLog {
+    path: PathBuf,
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl fmt::Debug for RunLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RunLog").field("path", &self.path).finish()
+    }
+}
+
+impl RunLog {
+    /// Starts a new log at `path`, replacing any file already there
+    pub fn create(path: impl AsRef<Path>) -> Result<RunLog, SwcError> {
+        let path = path.as_ref();
+        let file = File::create(path).map_err(|e| SwcError::io(path, e))?;
+        Ok(RunLog {
+            path: path.to_path_buf(),
+            inner: Arc::new(Mutex::new(Inner {
+                writer: BufWriter::new(file),
+                seq: 0,
+            })),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Appends one record and flushes it to disk. Logging must never take
+    /// the pipeline down, so write failures are only reported through `log`.
+    pub fn record(&self, op: &str, args: &[(&str, LogValue)]) {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0.0, |d| d.as_secs_f64());
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let mut line = format!(
+            "{{\"seq\":{},\"time\":{},\"op\":{},\"args\":{{",
+            inner.seq,
+            format_float(time),
+            LogValue::from(op)
+        );
+        for (i, (key, value)) in args.iter().enumerate() {
+            let sep = if i == 0 { "" } else { "," };
+            line.push_str(&format!("{}{}:{}", sep, LogValue::from(*key), value));
+        }
+        line.push_str("}}\n");
+        inner.seq += 1;
+        let written = inner
+            .writer
+            .write_all(line.as_bytes())
+            .and_then(|_| inner.writer.flush());
+        if let Err(e) = written {
+            log::warn!("Could not write to run log {}: {}", self.path.display(), e);
+        }
+    }
+}
@@ -0,0 +1,256 @@
+//! NEURON-style sections: the unbranched cables between branch points, named
+//! the way NEURON's Import3d names them (`soma[0]`, `axon[2]`, `dend[5]`,
+//! `apic[10]`), so parameter specs written for NEURON apply unchanged.
+//!
+//! Positions along a section follow NEURON's `sec(x)`: `x` runs from 0 at
+//! the proximal end to 1 at the distal end, and picks the compartment whose
+//! share of the section's length contains it.
+
+use std::collections::HashMap;
+
+use crate::compartments::{Compartment, Compartments};
+use crate::geometry::stable_sum;
+use crate::parameters::{ParamError, set_parameter};
+use crate::swc_reader::StructureIdentifier;
+
+/// One unbranched run of compartments, from just past a branch point (or
+/// the soma) up to the next branch point or a tip. The soma is a section of
+/// its own.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Section {
+    pub name: String,
+    /// Structure type of the first compartment
+    pub structure: StructureIdentifier,
+    /// Compartment indices from proximal to distal. Sibling sections
+    /// interleave in index order, so these are not always consecutive.
+    pub compartments: Vec<usize>,
+}
+
+impl Section {
+    /// Number of compartments, NEURON's `nseg`
+    pub fn nseg(&self) -> usize {
+        self.compartments.len()
+    }
+
+    /// Start and end of each compartment as fractions of the section. A
+    /// section without length is split evenly.
+    fn spans(&self, components: &[Compartment]) -> Vec<(f64, f64)> {
+        let lengths: Vec<f64> = self
+            .compartments
+            .iter()
+            .map(|&i| components[i].length)
+            .collect();
+        let total = stable_sum(lengths.iter().copied());
+        let n = lengths.len() as f64;
+        let mut start = 0.0;
+        lengths
+            .iter()
+            .enumerate()
+            .map(|(k, l)| {
+                if total > 0.0 {
+                    let span = (start / total, (start + l) / total);
+                    start += l;
+                    span
+                } else {
+                    (k as f64 / n, (k + 1) as f64 / n)
+                }
+            })
+            .collect()
+    }
+}
+
+pub(crate) fn prefix(structure: StructureIdentifier) -> &'static str {
+    match structure {
+        StructureIdentifier::Soma => "soma",
+        StructureIdentifier::Axon => "axon",
+        StructureIdentifier::ApicalDendrite => "apic",
+        _ => "dend",
+    }
+}
+
+/// Splits the compartments into sections, numbered per name in order of
+/// their first compartment
+pub(crate) fn build_sections(components: &[Compartment]) -> Vec<Section> {
+    let starts_section = |i: usize| match components[i].parent_idxs.first() {
+        Some(&p) if p > 1 => components[p as usize].children_idxs.len() != 1,
+        _ => true,
+    };
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    let mut sections = Vec::new();
+    for start in (1..components.len()).filter(|&i| starts_section(i)) {
+        let mut run = vec![start];
+        let mut last = start;
+        // The soma ends its section even with a single child
+        while let (&[child], true) = (&components[last].children_idxs[..], last != 1) {
+            last = child as usize;
+            run.push(last);
+        }
+        let structure = components[start].structure;
+        let count = counts.entry(prefix(structure)).or_default();
+        sections.push(Section {
+            name: format!("{}[{}]", prefix(structure), count),
+            structure,
+            compartments: run,
+        });
+        *count += 1;
+    }
+    sections
+}
+
+impl Compartments {
+    pub fn sections(&self) -> &[Section] {
+        &self.sections
+    }
+
+    pub fn section(&self, name: &str) -> Result<&Section, ParamError> {
+        self.sections
+            .iter()
+            .find(|s| s.name == name)
+            .ok_or_else(|| ParamError::UnknownSection {
+                name: name.to_owned(),
+            })
+    }
+
+    /// The section holding compartment `idx`, and the position of the
+    /// compartment's centre along it. None for the dummy root.
+    pub fn section_of(&self, idx: usize) -> Option<(&Section, f64)> {
+        self.sections.iter().find_map(|section| {
+            let k = section.compartments.iter().position(|&i| i == idx)?;
+            let (from, to) = section.spans(&self.components)[k];
+            Some((section, (from + to) / 2.0))
+        })
+    }
+
+    /// The compartment at position `x` along `section`, as NEURON's
+    /// `section(x)` would pick it. A position on a boundary between two
+    /// compartments goes to the distal one.
+    pub fn compartment_at(&self, section: &str, x: f64) -> Result<usize, ParamError> {
+        if !(0.0..=1.0).contains(&x) {
+            return Err(ParamError::InvalidPosition { x });
+        }
+        let section = self.section(section)?;
+        let spans = section.spans(&self.components);
+        let k = spans
+            .iter()
+            .position(|&(_, to)| to > x)
+            .unwrap_or(spans.len() - 1);
+        Ok(section.compartments[k])
+    }
+
+    /// Sets parameter `name` on the compartment at `x` along `section`, like
+    /// `section(x).name = value` in NEURON. Returns the compartment index.
+    /// Settable parameters are the `Channel` fields and `area_factor`.
+    pub fn set_param_at(
+        &mut self,
+        section: &str,
+        x: f64,
+        name: &str,
+        value: f64,
+    ) -> Result<usize, ParamError> {
+        let idx = self.compartment_at(section, x)?;
+        set_parameter(&mut self.components[idx], name, value)?;
+        self.log(
+            "set_param",
+            &[
+                ("section", section.into()),
+                ("x", x.into()),
+                ("parameter", name.into()),
+                ("value", value.into()),
+                ("compartment", idx.into()),
+            ],
+        );
+        Ok(idx)
+    }
+
+    /// Sets parameter `name` on every compartment of `section`, like
+    /// `section.name = value` in NEURON
+    pub fn set_section_param(
+        &mut self,
+        section: &str,
+        name: &str,
+        value: f64,
+    ) -> Result<(), ParamError> {
+        let compartments = self.section(section)?.compartments.clone();
+        for idx in compartments {
+            set_parameter(&mut self.components[idx], name, value)?;
+        }
+        self.log(
+            "set_param",
+            &[
+                ("section", section.into()),
+                ("parameter", name.into()),
+                ("value", value.into()),
+            ],
+        );
+        Ok(())
+    }
+
+    /// Sets parameter `name` on every compartment tagged `tag`, e.g. the AIS
+    /// after `tag_ais`. A compartment tagged over part of its length takes
+    /// the value throughout. Returns the number of compartments set.
+    pub fn set_tag_param(
+        &mut self,
+        tag: &str,
+        name: &str,
+        value: f64,
+    ) -> Result<usize, ParamError> {
+        let compartments = self.tagged(tag);
+        if compartments.is_empty() {
+            return Err(ParamError::UnknownTag {
+                name: tag.to_owned(),
+            });
+        }
+        for &idx in &compartments {
+            set_parameter(&mut self.components[idx], name, value)?;
+        }
+        self.log(
+            "set_param",
+            &[
+                ("tag", tag.into()),
+                ("parameter", name.into()),
+                ("value", value.into()),
+            ],
+        );
+        Ok(compartments.len())
+    }
+
+    /// Applies a parameter table, one setting per row as
+    /// `section,x,parameter,value`. An empty `x` sets the whole section.
+    /// A section written `@tag` sets every compartment tagged `tag` and
+    /// takes no `x`, so `@ais,,gnabar_hh,3.0` sets the AIS. A header row starting with `section`, blank lines and `#` comments
+    /// are skipped. Rows apply in order, so later rows win. Returns the
+    /// number of rows applied; on error, earlier rows stay applied.
+    pub fn apply_param_table(&mut self, table: &str) -> Result<usize, ParamError> {
+        let mut applied = 0;
+        for (i, line) in table.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with("section") {
+                continue;
+            }
+            let malformed = |message: &str| ParamError::MalformedRow {
+                line: i + 1,
+                message: message.to_owned(),
+            };
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            let [section, x, name, value] = fields[..] else {
+                return Err(malformed("expected section,x,parameter,value"));
+            };
+            let value: f64 = value
+                .parse()
+                .map_err(|_| malformed("value is not a number"))?;
+            if let Some(tag) = section.strip_prefix('@') {
+                if !x.is_empty() {
+                    return Err(malformed("a tag takes no x"));
+                }
+                self.set_tag_param(tag, name, value)?;
+            } else if x.is_empty() {
+                self.set_section_param(section, name, value)?;
+            } else {
+                let x: f64 = x.parse().map_err(|_| malformed("x is not a number"))?;
+                self.set_param_at(section, x, name, value)?;
+            }
+            applied += 1;
+        }
+        Ok(applied)
+    }
+}
@@ -0,0 +1,59 @@
+//! A `Simulation` with an explicit end, for callers that cannot rely on
+//! when it is dropped, such as Python's `with` blocks.
+//!
+//! `close` frees the simulation and the results of its last run at once;
+//! after it every use fails with `SimError::SessionClosed` instead of
+//! reaching freed state, and closing again does nothing.
+
+use crate::solver::{SimError, Simulation, SimulationResult};
+
+#[derive(Debug, Default)]
+pub struct SimulationSession {
+    simulation: Option<Box<Simulation>>,
+    result: Option<SimulationResult>,
+}
+
+impl SimulationSession {
+    pub fn new(simulation: Simulation) -> SimulationSession {
+        SimulationSession {
+            simulation: Some(Box::new(simulation)),
+            result: None,
+        }
+    }
+
+    /// The simulation, to step it or register probes
+    pub fn simulation(&mut self) -> Result<&mut Simulation, SimError> {
+        self.simulation
+            .as_deref_mut()
+            .ok_or(SimError::SessionClosed)
+    }
+
+    /// Runs as `Simulation::run` and keeps the result as `result`
+    pub fn run(
+        &mut self,
+        steps: usize,
+        stimuli: &[(usize, Vec<f64>)],
+    ) -> Result<&SimulationResult, SimError> {
+        let result = self.simulation()?.run(steps, stimuli)?;
+        Ok(self.result.insert(result))
+    }
+
+    /// Result of the last run, None before the first
+    pub fn result(&self) -> Result<Option<&SimulationResult>, SimError> {
+        match self.simulation {
+            Some(_) => Ok(self.result.as_ref()),
+            None => Err(SimError::SessionClosed),
+        }
+    }
+
+    /// Frees the simulation and the last result. Returns whether the
+    /// session was still open.
+    pub fn close(&mut self) -> bool {
+        self.result = None;
+        self.simulation.take().is_some()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.simulation.is_none()
+    }
+}
@@ -0,0 +1,161 @@
+//! Node-level simplification that follows electrotonic rather than physical
+//! distance. A thick proximal dendrite attenuates little per micrometre and
+//! can lose most of its nodes; a thin distal branch keeps more of them.
+//!
+//! Works on the skeleton, so any builder downstream can use the result.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::features::{by_id, euclidean, root_of};
+use crate::swc_reader::{NodeFlags, Skeleton};
+use crate::units::{OhmCm, SiemensPerCm2};
+
+/// How much one unbranched run of nodes was thinned
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BranchCompression {
+    /// Proximal end of the run (a branch point or the root) and its distal
+    /// end (a branch point or a tip), as IDs in the input skeleton
+    pub start: u64,
+    pub end: u64,
+    /// Segments along the run before and after
+    pub segments_before: usize,
+    pub segments_after: usize,
+}
+
+impl BranchCompression {
+    /// Segments before per segment after; 1 when nothing was merged
+    pub fn ratio(&self) -> f64 {
+        self.segments_before as f64 / self.segments_after as f64
+    }
+}
+
+/// Space constant of a cylinder of radius `radius` µm, in µm
+fn lambda(radius: f64, resistance: f64, conductance: f64) -> f64 {
+    let diam_cm = 2.0 * radius * 1e-4;
+    (diam_cm / (4.0 * resistance * conductance)).sqrt() * 1e4
+}
+
+/// Drops nodes along unbranched runs as long as the steady-state attenuation
+/// across each merged segment, `1 - exp(-L/λ)` with `L/λ` summed over the
+/// original segments, stays at or below `max_attenuation`. Each segment's
+/// `λ` comes from the radius of its distal node, which is the diameter the
+/// compartment built from it gets.
+///
+/// The root, branch points, tips and nodes where the structure type changes
+/// are always kept. Kept nodes that stand in for dropped ones are flagged
+/// `ELECTROTONIC_MERGED`. The result is renumbered like any processed
+/// skeleton; the report uses the input's IDs, one entry per run.
+pub fn simplify_electrotonic(
+    skeleton: &Skeleton,
+    resistance: OhmCm,
+    conductance: SiemensPerCm2,
+    max_attenuation: f64,
+) -> Result<(Skeleton, Vec<BranchCompression>), String> {
+    let (ra, gm) = (resistance.value(), conductance.value());
+    if !(ra > 0.0 && gm > 0.0 && ra.is_finite() && gm.is_finite()) {
+        return Err(format!(
+            "Passive parameters must be positive, got {} Ω·cm and {} S/cm²",
+            ra, gm
+        ));
+    }
+    if !(max_attenuation > 0.0 && max_attenuation < 1.0) {
+        return Err(format!(
+            "Attenuation must be between 0 and 1, got {}",
+            max_attenuation
+        ));
+    }
+    let max_electrotonic = -(1.0 - max_attenuation).ln();
+
+    let root = root_of(skeleton)?;
+    let nodes = by_id(skeleton);
+    let keep = |id: u64| {
+        let node = nodes[&id];
+        id == root
+            || skeleton.children_of(id).len() != 1
+            || nodes[&node.parent_id].structured_identifier != node.structured_identifier
+    };
+
+    let mut removed = HashSet::new();
+    let mut merged = HashSet::new();
+    let mut report = Vec::new();
+    for end in skeleton.subtree(root).into_iter().filter(|&id| id != root) {
+        if !keep(end) {
+            continue;
+        }
+        // Runs end at every kept node, so cut them there too
+        let mut path = vec![end];
+        let mut id = end;
+        loop {
+            id = nodes[&id].parent_id;
+            path.push(id);
+            if keep(id) {
+                break;
+            }
+        }
+        path.reverse();
+
+        let mut kept = vec![path[0]];
+        let mut since_kept = 0.0;
+        for pair in path.windows(2) {
+            let (parent, child) = (nodes[&pair[0]], nodes[&pair[1]]);
+            let x = euclidean(child, parent) / lambda(child.radius, ra, gm);
+            if since_kept > 0.0 && since_kept + x > max_electrotonic {
+                // Merging past the parent would attenuate too much
+                kept.push(parent.node_id);
+                since_kept = 0.0;
+            }
+            since_kept += x;
+        }
+        kept.push(end);
+        kept.dedup();
+
+        for (a, b) in kept.iter().zip(&kept[1..]) {
+            let from = path.iter().position(|id| id == a).unwrap_or(0);
+            let to = path.iter().position(|id| id == b).unwrap_or(from);
+            if to > from + 1 {
+                removed.extend(path[from + 1..to].iter().copied());
+                merged.insert(*b);
+            }
+        }
+        report.push(BranchCompression {
+            start: path[0],
+            end,
+            segments_before: path.len() - 1,
+            segments_after: kept.len() - 1,
+        });
+    }
+
+    // Every removed node has exactly one child; walk each kept node up past
+    // removed ones to its new parent
+    let mut out = skeleton.edit();
+    for node in out.nodes_mut().iter_mut() {
+        let mut parent = node.parent_id;
+        while removed.contains(&parent) {
+            parent = nodes[&parent].parent_id;
+        }
+        node.parent_id = parent;
+        if merged.contains(&node.node_id) {
+            node.flags |= NodeFlags::ELECTROTONIC_MERGED;
+        }
+    }
+    out.nodes_mut().retain(|n| !removed.contains(&n.node_id));
+    for id in &removed {
+        out.extras_mut().remove(id);
+    }
+    let mut parent_child_map: HashMap<u64, Vec<u64>> = HashMap::new();
+    let mut child_parent_map: HashMap<u64, Vec<u64>> = HashMap::new();
+    for node in out.nodes.iter() {
+        parent_child_map
+            .entry(node.parent_id)
+            .or_default()
+            .push(node.node_id);
+        child_parent_map
+            .entry(node.node_id)
+            .or_default()
+            .push(node.parent_id);
+    }
+    *out.parent_child_map_mut() = parent_child_map;
+    *out.child_parent_map_mut() = child_parent_map;
+    out.finalize()?;
+    Ok((out.commit()?, report))
+}
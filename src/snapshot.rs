@@ -0,0 +1,234 @@
+//! A cheap, standardized simulation for QC across many cells: the cell made
+//! passive everywhere and a -0.1 nA step injected at the soma.
+//!
+//! The steady state comes from one backward Euler step long enough that
+//! the capacitance drops out, which solves the cable equations at DC. The
+//! charging curve is run with 0.1 ms steps until the soma is within 0.5% of
+//! it. The membrane time constant is fitted to the tail of that curve,
+//! from 30% down to 1% of the swing left, where the faster equalizing
+//! components have died out: a least squares line through the logarithm of
+//! what is left, refitted once without points more than three robust
+//! standard deviations off it. The decay per step is converted back to a
+//! time constant as backward Euler decays, so a single compartment gives
+//! RC exactly.
+//!
+//! Unlike `quick::passive_snapshot`, which solves a coarsened model in the
+//! frequency domain, this simulates the model as given.
+
+use std::path::PathBuf;
+use std::thread;
+
+use crate::analysis::median;
+use crate::channels::{Channel, Passive};
+use crate::compartments::Compartments;
+use crate::quick::QuickLookOptions;
+use crate::solver::Simulation;
+use crate::standardize::{Dataset, read_source};
+use crate::swc_reader::{ReaderOptions, swc_reader_from_bytes};
+
+/// Injected at the soma, in nA
+const STEP: f64 = -0.1;
+/// Time step of the charging curve, in ms
+const DT: f64 = 0.1;
+/// Long enough for the capacitance to drop out of one step, in ms
+const DC_STEP: f64 = 1e9;
+/// The charging curve ends once this share of the swing is left
+const SETTLED: f64 = 0.005;
+/// Share of the swing left over which the tail is fitted
+const TAIL: (f64, f64) = (0.3, 0.01);
+/// Most steps of the charging curve
+const MAX_STEPS: usize = 200_000;
+/// Distal tips reported
+const TIPS: usize = 3;
+
+/// Steady-state attenuation at one tip
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TipAttenuation {
+    pub idx: usize,
+    /// From the soma to the far end of the tip, in µm
+    pub path_distance: f64,
+    /// Voltage change at the tip over the change at the soma
+    pub attenuation: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PassiveSnapshot {
+    /// At the soma, in MΩ
+    pub input_resistance: f64,
+    /// Membrane time constant fitted to the charging curve at the soma, in
+    /// ms
+    pub tau: f64,
+    /// Charging curve samples the fit kept
+    pub fit_points: usize,
+    /// The most distal tips by path distance, farthest first
+    pub tips: Vec<TipAttenuation>,
+}
+
+/// The passive response of `compartments` with the membrane of
+/// `QuickLookOptions::default()` everywhere, see the module docs
+pub fn quick_passive_snapshot(compartments: &Compartments) -> Result<PassiveSnapshot, String> {
+    if compartments.components.len() < 2 {
+        return Err("No soma to inject into".to_owned());
+    }
+    let options = QuickLookOptions::default();
+    let membrane = Channel::passive(options.resistance, options.capacitance, options.conductance);
+    let mut model = compartments.clone();
+    for c in model.components.iter_mut() {
+        c.set_channel(membrane.clone());
+    }
+    let rest = Passive::default().e;
+    let at_rest = |dt: f64| -> Result<Simulation, String> {
+        let mut simulation = Simulation::new(&model, dt).map_err(|e| e.to_string())?;
+        for idx in 1..model.components.len() {
+            simulation
+                .set_voltage(idx, rest)
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(simulation)
+    };
+
+    let mut dc = at_rest(DC_STEP)?;
+    dc.inject(1, STEP).map_err(|e| e.to_string())?;
+    dc.step().map_err(|e| e.to_string())?;
+    let steady: Vec<f64> = dc.voltages().iter().map(|v| v - rest).collect();
+    let swing = steady[1];
+    if !(swing < 0.0 && swing.is_finite()) {
+        return Err(format!(
+            "The soma did not respond to the step: {} mV",
+            swing
+        ));
+    }
+
+    let mut charging = at_rest(DT)?;
+    let mut left = vec![-swing];
+    while left.len() <= MAX_STEPS && left.last().unwrap().abs() > SETTLED * swing.abs() {
+        charging.inject(1, STEP).map_err(|e| e.to_string())?;
+        charging.step().map_err(|e| e.to_string())?;
+        left.push(charging.voltages()[1] - rest - swing);
+    }
+    let (decay, fit_points) = fit_decay(&left, swing.abs())?;
+
+    let components = &model.components;
+    let mut distance = vec![0.0; components.len()];
+    for (i, c) in components.iter().enumerate().skip(1) {
+        let parent = c.parent_idxs.first().map_or(0, |&p| p as usize);
+        distance[i] = distance[parent] + c.length;
+    }
+    let mut tips: Vec<TipAttenuation> = (2..components.len())
+        .filter(|&i| components[i].children_idxs.is_empty())
+        .map(|idx| TipAttenuation {
+            idx,
+            path_distance: distance[idx],
+            attenuation: steady[idx] / swing,
+        })
+        .collect();
+    tips.sort_by(|a, b| b.path_distance.total_cmp(&a.path_distance));
+    tips.truncate(TIPS);
+
+    Ok(PassiveSnapshot {
+        input_resistance: swing / STEP,
+        // Backward Euler divides what is left by 1 + dt / tau every step
+        tau: DT * decay / (1.0 - decay),
+        fit_points,
+        tips,
+    })
+}
+
+/// Decay of `left` per step over the tail, and the points the fit kept
+fn fit_decay(left: &[f64], swing: f64) -> Result<(f64, usize), String> {
+    let mut points: Vec<(f64, f64)> = left
+        .iter()
+        .enumerate()
+        .filter(|(_, x)| (TAIL.1 * swing..=TAIL.0 * swing).contains(&x.abs()))
+        .map(|(n, x)| (n as f64, x.abs().ln()))
+        .collect();
+    let mut line = fit_line(&points)?;
+    let residuals: Vec<f64> = points
+        .iter()
+        .map(|&(n, y)| (y - line.0 - line.1 * n).abs())
+        .collect();
+    // 1.4826 MAD estimates the standard deviation of normal residuals
+    let spread = 1.4826 * median(&mut residuals.clone()).unwrap_or(0.0);
+    if spread > 0.0 {
+        let kept: Vec<(f64, f64)> = points
+            .iter()
+            .zip(&residuals)
+            .filter(|&(_, r)| *r <= 3.0 * spread)
+            .map(|(&p, _)| p)
+            .collect();
+        if kept.len() < points.len() {
+            points = kept;
+            line = fit_line(&points)?;
+        }
+    }
+    let decay = line.1.exp();
+    if !(decay < 1.0 && decay > 0.0) {
+        return Err(format!(
+            "The charging curve does not decay: {} per step",
+            decay
+        ));
+    }
+    Ok((decay, points.len()))
+}
+
+/// Intercept and slope of the least squares line through `points`
+fn fit_line(points: &[(f64, f64)]) -> Result<(f64, f64), String> {
+    if points.len() < 3 {
+        return Err(format!(
+            "Only {} samples on the tail of the charging curve to fit",
+            points.len()
+        ));
+    }
+    let n = points.len() as f64;
+    let (mx, my) = points
+        .iter()
+        .fold((0.0, 0.0), |(sx, sy), (x, y)| (sx + x / n, sy + y / n));
+    let (sxy, sxx) = points.iter().fold((0.0, 0.0), |(sxy, sxx), (x, y)| {
+        (sxy + (x - mx) * (y - my), sxx + (x - mx) * (x - mx))
+    });
+    let slope = sxy / sxx;
+    Ok((my - slope * mx, slope))
+}
+
+/// A file of a dataset and its snapshot, or why there is none
+pub type FileSnapshot = (PathBuf, Result<PassiveSnapshot, String>);
+
+impl Dataset {
+    /// `quick_passive_snapshot` of every file, `threads` at a time (0 for
+    /// every available core), read with its `SCALE` header applied as
+    /// `qc` reads it, in the order of the dataset
+    pub fn passive_snapshots(&self, threads: usize) -> Vec<FileSnapshot> {
+        let options = ReaderOptions {
+            apply_scale: true,
+            collect_stats: false,
+            ..ReaderOptions::default()
+        };
+        let snapshot = |path: &PathBuf| {
+            read_source(path)
+                .map_err(|e| e.to_string())
+                .and_then(|data| swc_reader_from_bytes(&data, &options).map_err(|e| e.to_string()))
+                .and_then(|skeleton| quick_passive_snapshot(&Compartments::from_skeleton(skeleton)))
+        };
+        let threads = match threads {
+            0 => thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        }
+        .clamp(1, self.paths.len().max(1));
+        let mut snapshots = Vec::with_capacity(self.paths.len());
+        for wave in self.paths.chunks(threads) {
+            thread::scope(|scope| {
+                let workers: Vec<_> = wave
+                    .iter()
+                    .map(|path| scope.spawn(move || snapshot(path)))
+                    .collect();
+                for (path, worker) in wave.iter().zip(workers) {
+                    let result = worker
+                        .join()
+                        .unwrap_or_else(|_| Err("The snapshot panicked".to_owned()));
+                    snapshots.push((path.clone(), result));
+                }
+            });
+        }
+        snapshots
+    }
+}
@@ -0,0 +1,1675 @@
+//! Backward-Euler integration of the cable equation over the compartment
+//! tree, solved in linear time by Hines elimination.
+//!
+//! Each step first advances the gates at the old voltages, exactly for a
+//! voltage held over the step, and then solves for the new voltages with
+//! every membrane current linear in them. The voltage update is implicit,
+//! so any `dt` is stable. Compartments without membrane, such as a point
+//! soma, are branch points where the axial currents sum to zero.
+//!
+//! Voltages are in mV, currents in nA and times in ms. Membrane currents
+//! are outward positive, injected currents depolarize when positive.
+//!
+//! Mechanisms with stochastic gating draw from one generator per
+//! simulation, seeded with 0 unless `reseed` says otherwise.
+//!
+//! Spines added to the compartments are two more unknowns each, neck and
+//! head, eliminated into the compartment they hang off before the tree is.
+//!
+//! Between steps, any state variable can be read or set by name, see
+//! `state`.
+//!
+//! A large cell can be solved on several threads, see `SolverOptions`;
+//! the voltages are the same as with the single sweep.
+//!
+//! Compartments can be switched off and on during a run, see `growth`.
+//!
+//! Synapses driven by presynaptic spike trains, see `synapses`, add their
+//! conductances to their compartment's row like a membrane current.
+//!
+//! Electrodes in the extracellular space, see `with_extracellular`, drive
+//! each compartment through its axial conductances with the difference of
+//! their field across them, added to the step's injected currents.
+//!
+//! With ion accumulation, see `with_accumulation`, each step books the
+//! currents of the new voltages with the concentrations, advances them and
+//! then takes the reversals of the mechanisms that opted in from them, so
+//! the next step's currents see them. Energy accounting, see
+//! `with_energy_accounting`, books the same currents.
+
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+
+use crate::accumulation::IonAccumulation;
+use crate::channels::{Channel, ChannelType, Dynamics, HodgkinHuxley, Ion};
+use crate::codes::Code;
+use crate::compartments::Compartments;
+use crate::energy::{EnergyLedger, EnergyReport};
+use crate::extracellular::{ExtracellularStimulus, extracellular_potentials};
+use crate::growth::ScheduleState;
+use crate::manifest::Manifest;
+use crate::output::{ChunkWriter, OutputSink, TraceFile};
+use crate::recording::{Snippet, TriggeredProbe};
+use crate::run_log::RunLog;
+use crate::state::StateError;
+use crate::stochastic::{ChannelNoise, OpenChannels};
+use crate::synapses::SynapseState;
+
+/// Where every compartment starts, in mV
+pub const RESTING_POTENTIAL: f64 = -65.0;
+
+/// Ionic currents of one compartment, with conductances in nS
+#[derive(Debug, Clone)]
+pub(crate) enum Membrane {
+    /// No ionic current, e.g. an unspecified channel or no membrane at all
+    Inert,
+    Leak {
+        g: f64,
+        e: f64,
+    },
+    HodgkinHuxley {
+        hh: HodgkinHuxley,
+        /// Membrane area, in µm²
+        area: f64,
+        gates: [f64; 3],
+        /// Channel states when gating is stochastic
+        noise: Option<Box<ChannelNoise>>,
+    },
+}
+
+impl Membrane {
+    /// `(sum g, sum g E)` of the currents once the gates are settled for
+    /// the step
+    fn linearized(&self) -> (f64, f64) {
+        match self {
+            Membrane::Inert => (0.0, 0.0),
+            Membrane::Leak { g, e } => (*g, g * e),
+            Membrane::HodgkinHuxley { hh, .. } => {
+                let g = self.hh_conductances();
+                let e = [hh.ena, hh.ek, hh.el];
+                (g.iter().sum(), g.iter().zip(e).map(|(g, e)| g * e).sum())
+            }
+        }
+    }
+
+    /// Sodium, potassium and leak conductances of a Hodgkin-Huxley
+    /// membrane, in nS; zero for any other
+    fn hh_conductances(&self) -> [f64; 3] {
+        let Membrane::HodgkinHuxley {
+            hh,
+            area,
+            gates,
+            noise,
+        } = self
+        else {
+            return [0.0; 3];
+        };
+        let [m, h, n] = *gates;
+        // S/cm² over µm² is 10 nS
+        let mut g =
+            [hh.gnabar * m.powi(3) * h, hh.gkbar * n.powi(4), hh.gl].map(|g| g * area * 10.0);
+        if let Some(noise) = noise {
+            // pS to nS
+            let open = noise.open(gates);
+            g[0] = open.na_open * hh.gamma_na * 1e-3;
+            g[1] = open.k_open * hh.gamma_k * 1e-3;
+        }
+        g
+    }
+
+    /// `(ion, current, driving force)` of each current at `v`, in nA and
+    /// mV, as the step that settled the gates integrated it
+    fn currents(&self, v: f64) -> Vec<(Ion, f64, f64)> {
+        match self {
+            Membrane::Inert => Vec::new(),
+            // nS times mV is pA
+            Membrane::Leak { g, e } => vec![(Ion::NonSpecific, g * (v - e) * 1e-3, v - e)],
+            Membrane::HodgkinHuxley { hh, .. } => {
+                let e = [hh.ena, hh.ek, hh.el];
+                HodgkinHuxley::IONS
+                    .into_iter()
+                    .zip(self.hh_conductances())
+                    .zip(e)
+                    .map(|((ion, g), e)| (ion, g * (v - e) * 1e-3, v - e))
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Ionic currents of a compartment or spine part of `area` µm² with the
+/// mechanism of `channel`, gates settled at rest
+fn membrane(channel: &Channel, area: f64, rng: &mut StdRng) -> Membrane {
+    match &channel.channel_type {
+        _ if area == 0.0 => Membrane::Inert,
+        ChannelType::Passive(p) => Membrane::Leak {
+            g: channel.conductance * area * 10.0,
+            e: p.e,
+        },
+        ChannelType::HodgkinHuxley(hh) => {
+            let gates = HodgkinHuxley::steady_state(RESTING_POTENTIAL);
+            Membrane::HodgkinHuxley {
+                hh: *hh,
+                area,
+                noise: ChannelNoise::new(hh, area, &gates, rng).map(Box::new),
+                gates,
+            }
+        }
+        _ => Membrane::Inert,
+    }
+}
+
+/// Puts the gates of `m` at their steady state for `v`
+pub(crate) fn settle(m: &mut Membrane, v: f64, rng: &mut StdRng) {
+    if let Membrane::HodgkinHuxley { gates, noise, .. } = m {
+        *gates = HodgkinHuxley::steady_state(v);
+        if let Some(noise) = noise {
+            noise.settle(gates, rng);
+        }
+    }
+}
+
+/// Neck and head of a spine, in that order wherever there are two of
+/// something
+#[derive(Debug, Clone)]
+pub(crate) struct SpineUnit {
+    /// Compartment the neck leaves from
+    pub(crate) parent: usize,
+    /// Coupling across half the neck, in nS
+    axial: f64,
+    /// In pF
+    capacitance: [f64; 2],
+    pub(crate) membranes: [Membrane; 2],
+    pub(crate) v: [f64; 2],
+    /// Synaptic conductance on the head, in nS, and its reversal, in mV
+    synapse: (f64, f64),
+    /// Current injected into the head over the coming step, in nA
+    injected: f64,
+}
+
+/// How `Simulation::step` solves for the new voltages
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SolverOptions {
+    /// Solve the branches on several threads, None for one sweep over the
+    /// whole tree
+    pub parallel_tree: Option<ParallelTree>,
+}
+
+impl SolverOptions {
+    /// Branch-parallel solving on `threads` threads (0 for every available
+    /// core), in shares of at least `min_subtree` compartments
+    pub fn parallel_tree(threads: usize, min_subtree: usize) -> SolverOptions {
+        SolverOptions {
+            parallel_tree: Some(ParallelTree {
+                threads,
+                min_subtree,
+            }),
+        }
+    }
+}
+
+/// Branch-parallel solving: the tree is cut at a few junctions into whole
+/// subtrees, which are shared out over the threads, largest first, and
+/// advanced and eliminated at once. The compartments above the cuts are
+/// then solved on one thread, and the subtrees substituted back at once.
+/// Every row sees the same operations in the same order as in the serial
+/// sweep, so the voltages are the same to the bit.
+///
+/// Works best on branched cells: a long unbranched trunk stays above the
+/// cuts. Cells with stochastic gating in their compartments, whose draws
+/// follow compartment order, are solved serially.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParallelTree {
+    /// 0 for every available core
+    pub threads: usize,
+    /// Cells with fewer than twice this many compartments are solved
+    /// serially, and no subtree is cut smaller than needed to get there
+    pub min_subtree: usize,
+}
+
+/// How the tree of a simulation is shared out over threads
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Partition {
+    /// Threads solving subtrees, at most `ParallelTree::threads`
+    pub threads: usize,
+    pub subtrees: usize,
+    /// Compartments above the cuts, solved on one thread
+    pub junctions: usize,
+    /// Compartments of the largest share
+    pub largest_share: usize,
+}
+
+/// Whole subtrees solved on one thread
+#[derive(Debug)]
+struct Share {
+    /// Compartments, ascending
+    nodes: Vec<usize>,
+    /// Position of each one's parent in `nodes`, None for subtree roots
+    local_parent: Vec<Option<usize>>,
+    /// Spines on these compartments, with the position of theirs
+    spines: Vec<(usize, usize)>,
+}
+
+#[derive(Debug)]
+struct TreePlan {
+    shares: Vec<Share>,
+    /// Share of every compartment, None above the cuts
+    owner: Vec<Option<usize>>,
+    /// Compartments above the cuts and the subtree roots, ascending
+    junctions: Vec<usize>,
+    /// Spines on compartments above the cuts
+    top_spines: Vec<usize>,
+    subtrees: usize,
+}
+
+/// What solving a step leaves for the rest of it: the linearized membrane
+/// currents, the spine rows and the voltages before the step
+struct Solved {
+    membrane: Vec<(f64, f64)>,
+    spine_rows: Vec<[(f64, f64); 2]>,
+    old: Vec<f64>,
+}
+
+/// Rows of a share after elimination, indexed like `Share::nodes`
+struct ShareRows {
+    d: Vec<f64>,
+    rhs: Vec<f64>,
+    membrane: Vec<(f64, f64)>,
+}
+
+/// Cuts the tree given by `parent` above every maximal subtree of no more
+/// than a target size, and shares the subtrees out over `threads` by
+/// size. None if the cell is too small to be worth it.
+fn plan_tree(
+    parent: &[usize],
+    spine_parents: &[usize],
+    threads: usize,
+    min_subtree: usize,
+) -> Option<TreePlan> {
+    let n = parent.len();
+    let cells = n.saturating_sub(1);
+    if threads == 0 || cells < 2 * min_subtree.max(1) {
+        return None;
+    }
+    let mut size = vec![1; n];
+    for i in (2..n).rev() {
+        if parent[i] != 0 {
+            size[parent[i]] += size[i];
+        }
+    }
+    // A few subtrees per thread, so that they can be balanced
+    let target = min_subtree.max(cells.div_ceil(4 * threads));
+    let mut roots: Vec<usize> = (1..n)
+        .filter(|&i| size[i] <= target && (parent[i] == 0 || size[parent[i]] > target))
+        .collect();
+    roots.sort_by_key(|&i| (std::cmp::Reverse(size[i]), i));
+    let bins = threads.min(roots.len());
+    let mut load = vec![0; bins];
+    let mut root_bin = vec![None; n];
+    for &r in &roots {
+        let b = (0..bins).min_by_key(|&b| (load[b], b))?;
+        load[b] += size[r];
+        root_bin[r] = Some(b);
+    }
+
+    let mut owner: Vec<Option<usize>> = vec![None; n];
+    let mut position = vec![0; n];
+    let mut shares: Vec<Share> = (0..bins)
+        .map(|_| Share {
+            nodes: Vec::new(),
+            local_parent: Vec::new(),
+            spines: Vec::new(),
+        })
+        .collect();
+    let mut junctions = Vec::new();
+    for i in 1..n {
+        owner[i] = root_bin[i].or(if parent[i] != 0 {
+            owner[parent[i]]
+        } else {
+            None
+        });
+        if let Some(b) = owner[i] {
+            let share = &mut shares[b];
+            position[i] = share.nodes.len();
+            share.nodes.push(i);
+            share
+                .local_parent
+                .push(root_bin[i].is_none().then(|| position[parent[i]]));
+        }
+        if owner[i].is_none() || root_bin[i].is_some() {
+            junctions.push(i);
+        }
+    }
+    let mut top_spines = Vec::new();
+    for (k, &p) in spine_parents.iter().enumerate() {
+        match owner[p] {
+            Some(b) => shares[b].spines.push((k, position[p])),
+            None => top_spines.push(k),
+        }
+    }
+    Some(TreePlan {
+        shares,
+        owner,
+        junctions,
+        top_spines,
+        subtrees: roots.len(),
+    })
+}
+
+/// Advances the gates of `m` over a step at `v`
+fn advance_gates(m: &mut Membrane, v: f64, dt: f64, rng: &mut StdRng) {
+    if let Membrane::HodgkinHuxley {
+        hh, gates, noise, ..
+    } = m
+    {
+        match noise {
+            Some(noise) => noise.step(gates, v, dt, rng),
+            None => hh.update(gates, v, dt),
+        }
+    }
+}
+
+/// Everything that can go wrong setting up or running a `Simulation`
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum SimError {
+    /// The model, or something added to it, cannot be simulated; `reason`
+    /// says why
+    InvalidModel {
+        reason: String,
+    },
+    /// An index naming no compartment, or the dummy root
+    NoCompartment {
+        idx: usize,
+    },
+    NoSpine {
+        spine_idx: usize,
+    },
+    /// A stimulus, probe or time window that does not fit; `reason` says
+    /// why
+    InvalidInput {
+        reason: String,
+    },
+    /// A voltage stopped being finite at `time` ms. For a spine,
+    /// `spine_idx` is set and `compartment_idx` is the compartment it hangs
+    /// off.
+    Diverged {
+        compartment_idx: usize,
+        spine_idx: Option<usize>,
+        time: f64,
+    },
+    /// An accumulated concentration would drop to zero; `reason` says
+    /// which and where
+    Depleted {
+        reason: String,
+    },
+    /// A state path given to a probe
+    State(StateError),
+    /// Writing or reading the streamed traces at `path` failed
+    Output {
+        path: PathBuf,
+        message: String,
+    },
+    /// A solver thread panicked
+    Panicked,
+    /// The `SimulationSession` was closed
+    SessionClosed,
+}
+
+impl fmt::Display for SimError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SimError::InvalidModel { reason }
+            | SimError::InvalidInput { reason }
+            | SimError::Depleted { reason } => f.write_str(reason),
+            SimError::NoCompartment { idx } => write!(f, "No compartment at index {}", idx),
+            SimError::NoSpine { spine_idx } => write!(f, "No spine at index {}", spine_idx),
+            SimError::Diverged {
+                compartment_idx,
+                spine_idx: None,
+                time,
+            } => write!(
+                f,
+                "Voltage of compartment {} diverged at {} ms",
+                compartment_idx, time
+            ),
+            SimError::Diverged {
+                compartment_idx,
+                spine_idx: Some(k),
+                time,
+            } => write!(
+                f,
+                "Voltage of spine {} on compartment {} diverged at {} ms",
+                k, compartment_idx, time
+            ),
+            SimError::State(e) => e.fmt(f),
+            SimError::Output { path, message } => {
+                write!(f, "Trace file {}: {}", path.display(), message)
+            }
+            SimError::Panicked => f.write_str("A solver thread panicked"),
+            SimError::SessionClosed => f.write_str("Session is closed"),
+        }
+    }
+}
+
+impl std::error::Error for SimError {}
+
+impl From<StateError> for SimError {
+    fn from(err: StateError) -> SimError {
+        SimError::State(err)
+    }
+}
+
+impl SimError {
+    /// Machine-readable code, see `codes::REGISTRY`
+    pub fn code(&self) -> Code {
+        match self {
+            SimError::InvalidModel { .. } => Code::InvalidModel,
+            SimError::NoCompartment { .. } => Code::NoCompartment,
+            SimError::NoSpine { .. } => Code::NoSpine,
+            SimError::InvalidInput { .. } => Code::InvalidSimulationInput,
+            SimError::Diverged { .. } => Code::Diverged,
+            SimError::Depleted { .. } => Code::IonDepleted,
+            SimError::State(e) => e.code(),
+            SimError::Output { .. } => Code::OutputFailure,
+            SimError::Panicked => Code::SolverPanicked,
+            SimError::SessionClosed => Code::SessionClosed,
+        }
+    }
+
+    /// Compartment the error is about, if any
+    pub fn compartment_idx(&self) -> Option<usize> {
+        match self {
+            SimError::NoCompartment { idx } => Some(*idx),
+            SimError::Diverged {
+                compartment_idx, ..
+            } => Some(*compartment_idx),
+            SimError::State(e) => e.compartment_idx(),
+            _ => None,
+        }
+    }
+}
+
+/// Voltage traces of a finished run
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulationResult {
+    pub dt: f64,
+    /// Per compartment, the voltage at the start and after every step, so
+    /// `steps + 1` values each, or none for compartments left out by
+    /// `Simulation::record_voltages_of`. The dummy root stays at rest.
+    pub voltages: Vec<Vec<f64>>,
+    /// Per spine, the head voltage like `voltages`
+    pub head_voltages: Vec<Vec<f64>>,
+    /// Charge and energy since the simulation started, with energy
+    /// accounting
+    pub energy: Option<EnergyReport>,
+    /// Every path given to `Simulation::record` with its value like
+    /// `voltages`, in the order they were given
+    pub traces: Vec<(String, Vec<f64>)>,
+    /// Parent and child of every probe added with
+    /// `Simulation::add_axial_current_probe`, with the current from the one
+    /// into the other in nA, like `voltages`
+    pub axial_currents: Vec<((usize, usize), Vec<f64>)>,
+    /// Path and snippets of every probe given to
+    /// `Simulation::record_around_events`, in the order they were given
+    pub snippets: Vec<(String, Vec<Snippet>)>,
+    pub manifest: Manifest,
+    /// Where the traces are when the run streamed them to a file, leaving
+    /// the fields above empty, see `output`
+    pub stream: Option<TraceFile>,
+}
+
+impl SimulationResult {
+    /// Charge per ion and dissipated energy of every compartment, None
+    /// unless the simulation had `with_energy_accounting`
+    pub fn energy_report(&self) -> Option<&EnergyReport> {
+        self.energy.as_ref()
+    }
+}
+
+/// State of a cell being integrated
+#[derive(Debug, Clone)]
+pub struct Simulation {
+    dt: f64,
+    pub(crate) steps: usize,
+    /// Parent of each compartment, 0 for the soma and the dummy root
+    pub(crate) parent: Vec<usize>,
+    /// Coupling to the parent, in nS, 0 while the compartment is inactive
+    pub(crate) axial: Vec<f64>,
+    /// In pF
+    capacitance: Vec<f64>,
+    pub(crate) membranes: Vec<Membrane>,
+    pub(crate) v: Vec<f64>,
+    /// Current injected over the coming step, in nA
+    injected: Vec<f64>,
+    pub(crate) clamps: Vec<Option<f64>>,
+    /// Current each clamp supplied over the last step, in nA
+    clamp_currents: Vec<f64>,
+    /// Current out through each membrane over the last step, in nA, see
+    /// `membrane_current`
+    pub(crate) membrane_currents: Vec<f64>,
+    /// Indexed like `Compartments::spines`
+    pub(crate) spines: Vec<SpineUnit>,
+    pub(crate) rng: StdRng,
+    /// Which compartments take part, all but while a schedule says
+    /// otherwise, see `growth`
+    pub(crate) active: Vec<bool>,
+    pub(crate) schedule: Option<Box<ScheduleState>>,
+    /// Where writes through `set` are recorded, see `with_run_log`
+    pub(crate) run_log: Option<RunLog>,
+    /// Subtrees to solve on their own threads, see `with_solver`
+    plan: Option<Arc<TreePlan>>,
+    /// Concentrations the currents move, see `with_accumulation`
+    pub(crate) accumulation: Option<Box<IonAccumulation>>,
+    /// Charge and energy of the ionic currents, see
+    /// `with_energy_accounting`
+    ledger: Option<Box<EnergyLedger>>,
+    /// Field per nA at every compartment and current waveform of each
+    /// electrode, see `with_extracellular`
+    electrodes: Vec<(Vec<f64>, Vec<f64>)>,
+    /// State paths `run` records, see `record`
+    pub(crate) recorded: Vec<String>,
+    /// Paths `run` records around events, see `record_around_events`
+    pub(crate) triggered: Vec<TriggeredProbe>,
+    /// Parent and child pairs `run` records the axial current of, see
+    /// `add_axial_current_probe`
+    axial_probes: Vec<(usize, usize)>,
+    /// See `add_synapse`
+    pub(crate) synapses: Vec<SynapseState>,
+    /// `(sum g, sum g E)` of the synapses on each compartment over the
+    /// coming step, in nS; empty without synapses
+    pub(crate) synaptic: Vec<(f64, f64)>,
+    /// Compartments whose voltages `run` records, all unless
+    /// `record_voltages_of` says otherwise
+    recorded_voltages: Option<Vec<bool>>,
+    /// Where `run` puts what it records, see `with_output`
+    output: OutputSink,
+}
+
+impl Simulation {
+    /// Every compartment at `RESTING_POTENTIAL` with its gates settled there.
+    /// Reversals set by ion apply, see `reversal`, and mechanisms keeping
+    /// their own against them are reported as `Compartments::reversal_check`
+    /// says.
+    pub fn new(compartments: &Compartments, dt: f64) -> Result<Simulation, SimError> {
+        let channels: Vec<&Channel> = compartments.components.iter().map(|c| &c.channel).collect();
+        Simulation::with_channels(compartments, &channels, dt)
+    }
+
+    /// Like `new`, with the mechanisms of each compartment taken from
+    /// `channels` instead of the compartments themselves. Their reversals
+    /// resolve against the compartments' ion-scoped ones all the same.
+    pub(crate) fn with_channels(
+        compartments: &Compartments,
+        channels: &[&Channel],
+        dt: f64,
+    ) -> Result<Simulation, SimError> {
+        if !(dt > 0.0 && dt.is_finite()) {
+            return Err(SimError::InvalidModel {
+                reason: format!("Time step must be positive, got {}", dt),
+            });
+        }
+        let n = compartments.components.len();
+        if channels.len() != n {
+            return Err(SimError::InvalidModel {
+                reason: format!("{} channels for {} compartments", channels.len(), n),
+            });
+        }
+        compartments
+            .check_reversals(channels)
+            .map_err(|reason| SimError::InvalidModel { reason })?;
+        let resolved: Vec<Channel> = channels
+            .iter()
+            .map(|c| compartments.resolved_channel(c))
+            .collect();
+        let channels: Vec<&Channel> = resolved.iter().collect();
+        let mut parent = vec![0; n];
+        for (i, c) in compartments.components.iter().enumerate().skip(1) {
+            match c.parent_idxs[..] {
+                [p] if (p as usize) < i => parent[i] = p as usize,
+                _ => {
+                    return Err(SimError::InvalidModel {
+                        reason: format!(
+                            "Compartment {} needs one parent ahead of it, has {:?}",
+                            i, c.parent_idxs
+                        ),
+                    });
+                }
+            }
+        }
+        let mut axial = vec![0.0; n];
+        for (p, c, g) in compartments.axial_conductances_with(|i| channels[i].resistance) {
+            if !g.is_finite() {
+                return Err(SimError::InvalidModel {
+                    reason: format!(
+                        "Compartments {} and {} are both zero length; merge or drop duplicated nodes first",
+                        p, c
+                    ),
+                });
+            }
+            // 1 / (Ω·cm/µm) is 1e5 nS
+            axial[c] = g * 1e5;
+        }
+        let mut rng = StdRng::seed_from_u64(0);
+        let membranes = compartments
+            .components
+            .iter()
+            .zip(&channels)
+            .map(|(c, channel)| membrane(channel, c.membrane_area(), &mut rng))
+            .collect();
+        let spines = compartments
+            .spines()
+            .iter()
+            .map(|spine| {
+                let shape = &spine.shape;
+                let areas = [shape.neck_area(), shape.head_area];
+                let channel = compartments.resolved_channel(&shape.channel);
+                SpineUnit {
+                    parent: spine.idx,
+                    // Each half of the neck, so the two in series make all of it
+                    axial: 2.0 * shape.neck_conductance(),
+                    capacitance: areas.map(|a| shape.channel.capacitance * a * 1e-2),
+                    membranes: areas.map(|a| membrane(&channel, a, &mut rng)),
+                    v: [RESTING_POTENTIAL; 2],
+                    synapse: (0.0, 0.0),
+                    injected: 0.0,
+                }
+            })
+            .collect();
+        Ok(Simulation {
+            dt,
+            steps: 0,
+            parent,
+            axial,
+            // µF/cm² over µm² is 1e-2 pF
+            capacitance: compartments
+                .components
+                .iter()
+                .zip(&channels)
+                .map(|(c, channel)| channel.capacitance * c.membrane_area() * 1e-2)
+                .collect(),
+            membranes,
+            v: vec![RESTING_POTENTIAL; n],
+            injected: vec![0.0; n],
+            clamps: vec![None; n],
+            clamp_currents: vec![0.0; n],
+            membrane_currents: vec![0.0; n],
+            spines,
+            rng,
+            active: vec![true; n],
+            schedule: None,
+            run_log: None,
+            plan: None,
+            accumulation: None,
+            ledger: None,
+            electrodes: Vec::new(),
+            recorded: Vec::new(),
+            triggered: Vec::new(),
+            axial_probes: Vec::new(),
+            synapses: Vec::new(),
+            synaptic: Vec::new(),
+            recorded_voltages: None,
+            output: OutputSink::Memory,
+        })
+    }
+
+    /// Solves every step as `options` say. Falls back to the serial sweep
+    /// where a parallel one is not worth it or would change the results,
+    /// see `ParallelTree`, and with a morphology schedule.
+    pub fn with_solver(mut self, options: SolverOptions) -> Simulation {
+        self.plan = options.parallel_tree.and_then(|p| {
+            let stochastic = self
+                .membranes
+                .iter()
+                .any(|m| matches!(m, Membrane::HodgkinHuxley { noise: Some(_), .. }));
+            if stochastic || self.schedule.is_some() {
+                return None;
+            }
+            let threads = match p.threads {
+                0 => thread::available_parallelism().map_or(1, |n| n.get()),
+                n => n,
+            };
+            let spine_parents: Vec<usize> = self.spines.iter().map(|s| s.parent).collect();
+            plan_tree(&self.parent, &spine_parents, threads, p.min_subtree).map(Arc::new)
+        });
+        self
+    }
+
+    /// How the tree is shared out over threads, None when it is solved
+    /// serially
+    pub fn partition(&self) -> Option<Partition> {
+        let plan = self.plan.as_ref()?;
+        Some(Partition {
+            threads: plan.shares.len(),
+            subtrees: plan.subtrees,
+            junctions: plan.owner.iter().skip(1).filter(|o| o.is_none()).count(),
+            largest_share: plan.shares.iter().map(|s| s.nodes.len()).max().unwrap_or(0),
+        })
+    }
+
+    /// Records every state variable set through `set` in `log`
+    pub fn with_run_log(mut self, log: RunLog) -> Simulation {
+        self.run_log = Some(log);
+        self
+    }
+
+    /// Moves the concentrations of `accumulation` with the membrane
+    /// currents of every step, and takes the reversals of mechanisms with
+    /// `use_dynamic_reversal` from them, starting now. Spines keep their
+    /// reversals. Fails if `accumulation` is for another number of
+    /// compartments.
+    pub fn with_accumulation(
+        mut self,
+        accumulation: IonAccumulation,
+    ) -> Result<Simulation, SimError> {
+        if accumulation.len() != self.v.len() {
+            return Err(SimError::InvalidModel {
+                reason: format!(
+                    "Accumulation is for {} compartments, not {}",
+                    accumulation.len(),
+                    self.v.len()
+                ),
+            });
+        }
+        self.accumulation = Some(Box::new(accumulation));
+        self.refresh_reversals();
+        Ok(self)
+    }
+
+    /// The concentrations, None without accumulation
+    pub fn accumulation(&self) -> Option<&IonAccumulation> {
+        self.accumulation.as_deref()
+    }
+
+    /// Books the charge each ion carries through every compartment's
+    /// membrane, and the energy the conductances dissipate, over every
+    /// step from now on, see `energy`. Spines are not counted.
+    pub fn with_energy_accounting(mut self) -> Simulation {
+        self.ledger = Some(Box::new(EnergyLedger::new(self.v.len())));
+        self
+    }
+
+    /// Has `run` put what it records in `sink`, see `output`. Fails for a
+    /// file sink with no steps to a chunk.
+    pub fn with_output(mut self, sink: OutputSink) -> Result<Simulation, SimError> {
+        if let OutputSink::File { chunk_steps: 0, .. } = sink {
+            return Err(SimError::InvalidInput {
+                reason: "A chunk must hold at least one step".to_owned(),
+            });
+        }
+        self.output = sink;
+        Ok(self)
+    }
+
+    /// What the ledger holds so far, None without energy accounting
+    pub fn energy_report(&self) -> Option<EnergyReport> {
+        self.ledger.as_ref().map(|l| l.report())
+    }
+
+    /// Adds the fields of `electrodes`, placed around `compartments`, to
+    /// the simulation, electrode currents indexed by step from now on and
+    /// zero past their end. Their activating function enters every step
+    /// through the solver's own axial conductances, see `extracellular`.
+    pub fn with_extracellular(
+        mut self,
+        compartments: &Compartments,
+        electrodes: &[ExtracellularStimulus],
+    ) -> Result<Simulation, SimError> {
+        if compartments.components.len() != self.v.len() {
+            return Err(SimError::InvalidModel {
+                reason: format!(
+                    "Electrodes placed around {} compartments, not {}",
+                    compartments.components.len(),
+                    self.v.len()
+                ),
+            });
+        }
+        let first = self.steps;
+        for electrode in electrodes {
+            let unit = ExtracellularStimulus {
+                current_waveform: vec![1.0],
+                ..electrode.clone()
+            };
+            let mut field = extracellular_potentials(compartments, &[unit])
+                .map_err(|reason| SimError::InvalidModel { reason })?;
+            // Indexed from the start of the simulation like `steps`
+            let mut waveform = vec![0.0; first];
+            waveform.extend(&electrode.current_waveform);
+            self.electrodes.push((field.remove(0), waveform));
+        }
+        Ok(self)
+    }
+
+    /// Adds the current the electrodes' field drives into each compartment
+    /// over the coming step to `injected`
+    fn inject_extracellular(&mut self) {
+        let step = self.steps;
+        let mut v_e = vec![0.0; self.v.len()];
+        for (field, waveform) in &self.electrodes {
+            let current = waveform.get(step).copied().unwrap_or(0.0);
+            for (v, f) in v_e.iter_mut().zip(field) {
+                *v += current * f;
+            }
+        }
+        for i in 1..self.v.len() {
+            let p = self.parent[i];
+            if p == 0 {
+                continue;
+            }
+            // nS times mV is pA
+            let current = self.axial[i] * (v_e[p] - v_e[i]) * 1e-3;
+            self.injected[i] += current;
+            self.injected[p] -= current;
+        }
+    }
+
+    /// Reversals of the opted-in mechanisms from the current concentrations
+    fn refresh_reversals(&mut self) {
+        let Some(accumulation) = &self.accumulation else {
+            return;
+        };
+        for (i, m) in self.membranes.iter_mut().enumerate() {
+            if let Membrane::HodgkinHuxley { hh, .. } = m {
+                accumulation.refresh(i, hh);
+            }
+        }
+    }
+
+    pub fn dt(&self) -> f64 {
+        self.dt
+    }
+
+    /// Time since the start, in ms
+    pub fn time(&self) -> f64 {
+        self.steps as f64 * self.dt
+    }
+
+    pub fn voltages(&self) -> &[f64] {
+        &self.v
+    }
+
+    /// Sets compartment `idx` to `v`, with its gates settled there
+    pub fn set_voltage(&mut self, idx: usize, v: f64) -> Result<(), SimError> {
+        self.check(idx)?;
+        self.v[idx] = v;
+        settle(&mut self.membranes[idx], v, &mut self.rng);
+        Ok(())
+    }
+
+    /// Restarts the random draws of stochastic gating from `seed` and
+    /// redraws every channel state from its gates. The same seed, model and
+    /// stimuli give the same run.
+    pub fn reseed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+        let spines = self.spines.iter_mut().flat_map(|s| &mut s.membranes);
+        for m in self.membranes.iter_mut().chain(spines) {
+            if let Membrane::HodgkinHuxley {
+                gates,
+                noise: Some(noise),
+                ..
+            } = m
+            {
+                noise.settle(gates, &mut self.rng);
+            }
+        }
+    }
+
+    /// Open and total channels of compartment `idx`, None unless its
+    /// mechanism gates stochastically
+    pub fn open_channels(&self, idx: usize) -> Option<OpenChannels> {
+        match self.membranes.get(idx)? {
+            Membrane::HodgkinHuxley {
+                gates,
+                noise: Some(noise),
+                ..
+            } => Some(noise.open(gates)),
+            _ => None,
+        }
+    }
+
+    /// Adds `current` to what compartment `idx` receives over the next step
+    pub fn inject(&mut self, idx: usize, current: f64) -> Result<(), SimError> {
+        self.check(idx)?;
+        self.injected[idx] += current;
+        Ok(())
+    }
+
+    /// Holds compartment `idx` at `v` from the next step on, or lets it go
+    pub fn clamp(&mut self, idx: usize, v: Option<f64>) -> Result<(), SimError> {
+        self.check(idx)?;
+        self.clamps[idx] = v;
+        Ok(())
+    }
+
+    /// Current the clamp on `idx` supplied over the last step, None if the
+    /// compartment is not clamped
+    pub fn clamp_current(&self, idx: usize) -> Option<f64> {
+        self.clamps.get(idx)?.map(|_| self.clamp_currents[idx])
+    }
+
+    /// Net current out through the membrane of `idx` over the last step, in
+    /// nA: capacitive, ionic and synaptic, plus what flowed into the spines
+    /// on it. 0 before the first step and while the compartment is
+    /// inactive. Over the whole cell it adds up to what was injected.
+    pub fn membrane_current(&self, idx: usize) -> Option<f64> {
+        self.membrane_currents.get(idx).copied()
+    }
+
+    /// Has `run` record `comp[idx].i_membrane` of every compartment, see
+    /// `record`
+    pub fn record_membrane_currents(&mut self) {
+        self.recorded
+            .extend((1..self.v.len()).map(|idx| format!("comp[{}].i_membrane", idx)));
+    }
+
+    /// Current flowing from `parent_idx` into `child_idx` over the last
+    /// step, in nA: the solver's axial conductance times the difference of
+    /// the voltages it solved for, as the implicit step integrated it. None
+    /// unless the one is the other's parent.
+    pub fn axial_current(&self, parent_idx: usize, child_idx: usize) -> Option<f64> {
+        if child_idx == 0 || self.parent.get(child_idx) != Some(&parent_idx) {
+            return None;
+        }
+        // nS times mV is pA
+        Some(self.axial[child_idx] * (self.v[parent_idx] - self.v[child_idx]) * 1e-3)
+    }
+
+    /// Has `run` record `axial_current(parent_idx, child_idx)` into
+    /// `SimulationResult::axial_currents`. Fails unless the two are
+    /// connected that way round.
+    pub fn add_axial_current_probe(
+        &mut self,
+        parent_idx: usize,
+        child_idx: usize,
+    ) -> Result<(), SimError> {
+        self.check(parent_idx)?;
+        self.check(child_idx)?;
+        if self.axial_current(parent_idx, child_idx).is_none() {
+            return Err(SimError::InvalidInput {
+                reason: format!(
+                    "Compartment {} is not the parent of compartment {}",
+                    parent_idx, child_idx
+                ),
+            });
+        }
+        self.axial_probes.push((parent_idx, child_idx));
+        Ok(())
+    }
+
+    /// Has `run` record the voltages of `idxs` only, leaving the traces of
+    /// the other compartments in `SimulationResult::voltages` empty
+    pub fn record_voltages_of(&mut self, idxs: &[usize]) -> Result<(), SimError> {
+        let mut recorded = vec![false; self.v.len()];
+        for &idx in idxs {
+            self.check(idx)?;
+            recorded[idx] = true;
+        }
+        self.recorded_voltages = Some(recorded);
+        Ok(())
+    }
+
+    /// Appends the current through each axial probe to its trace
+    fn sample_axial(&self, traces: &mut [((usize, usize), Vec<f64>)]) {
+        for ((parent, child), trace) in traces.iter_mut() {
+            trace.push(
+                self.axial_current(*parent, *child)
+                    .expect("checked when added"),
+            );
+        }
+    }
+
+    /// Neck and head voltage of spine `k`
+    pub fn spine_voltages(&self, k: usize) -> Option<[f64; 2]> {
+        self.spines.get(k).map(|s| s.v)
+    }
+
+    /// Head voltage of every spine, indexed like `Compartments::spines`
+    pub fn head_voltages(&self) -> Vec<f64> {
+        self.spines.iter().map(|s| s.v[1]).collect()
+    }
+
+    /// Sets the neck and head of spine `k` to `v`, with their gates
+    /// settled there
+    pub fn set_spine_voltage(&mut self, k: usize, v: f64) -> Result<(), SimError> {
+        self.check_spine(k)?;
+        let spine = &mut self.spines[k];
+        spine.v = [v; 2];
+        for m in spine.membranes.iter_mut() {
+            settle(m, v, &mut self.rng);
+        }
+        Ok(())
+    }
+
+    /// Opens a synaptic conductance of `g` nS, reversing at `e` mV, on the
+    /// head of spine `k`. It holds from the next step on until set again;
+    /// a `g` of 0 closes it.
+    pub fn set_spine_synapse(&mut self, k: usize, g: f64, e: f64) -> Result<(), SimError> {
+        self.check_spine(k)?;
+        if !(g >= 0.0 && g.is_finite() && e.is_finite()) {
+            return Err(SimError::InvalidInput {
+                reason: format!(
+                    "Synaptic conductance must be non-negative and finite, got {} nS at {} mV",
+                    g, e
+                ),
+            });
+        }
+        self.spines[k].synapse = (g, e);
+        Ok(())
+    }
+
+    /// Adds `current` to what the head of spine `k` receives over the next
+    /// step
+    pub fn inject_spine(&mut self, k: usize, current: f64) -> Result<(), SimError> {
+        self.check_spine(k)?;
+        self.spines[k].injected += current;
+        Ok(())
+    }
+
+    fn check_spine(&self, k: usize) -> Result<(), SimError> {
+        if k >= self.spines.len() {
+            return Err(SimError::NoSpine { spine_idx: k });
+        }
+        Ok(())
+    }
+
+    pub(crate) fn check(&self, idx: usize) -> Result<(), SimError> {
+        if idx == 0 || idx >= self.v.len() {
+            return Err(SimError::NoCompartment { idx });
+        }
+        Ok(())
+    }
+
+    /// Each spine as `(d, rhs)` of its neck and head rows, gates advanced:
+    /// d_n V_n - a V_parent - a V_h = rhs_n and d_h V_h - a V_n = rhs_h
+    fn spine_rows(&self) -> Vec<[(f64, f64); 2]> {
+        let dt = self.dt;
+        let mut spine_rows = Vec::with_capacity(self.spines.len());
+        for spine in &self.spines {
+            if !self.active[spine.parent] {
+                spine_rows.push([(0.0, 0.0); 2]);
+                continue;
+            }
+            let a = spine.axial;
+            let mut rows = [(0.0, 0.0); 2];
+            for (k, row) in rows.iter_mut().enumerate() {
+                let (g, ge) = spine.membranes[k].linearized();
+                let c = spine.capacitance[k] / dt;
+                *row = (c + g + a, c * spine.v[k] + ge);
+            }
+            rows[0].0 += a;
+            let (g, e) = spine.synapse;
+            rows[1].0 += g;
+            rows[1].1 += g * e + spine.injected * 1e3;
+            // The head only talks to the neck, so fold it in right away
+            if rows[1].0 != 0.0 {
+                rows[0].0 -= a * a / rows[1].0;
+                rows[0].1 += a * rows[1].1 / rows[1].0;
+            }
+            spine_rows.push(rows);
+        }
+        spine_rows
+    }
+
+    /// Diagonal and right-hand side of row `i` before any coupling to
+    /// children, for membrane currents `(g, ge)`
+    fn own_row(&self, i: usize, (g, ge): (f64, f64)) -> (f64, f64) {
+        let (g, ge) = match self.synaptic.get(i) {
+            Some((gs, gse)) => (g + gs, ge + gse),
+            None => (g, ge),
+        };
+        let c = self.capacitance[i] / self.dt;
+        (
+            c + g + self.axial[i],
+            c * self.v[i] + ge + self.injected[i] * 1e3,
+        )
+    }
+
+    /// Advances the gates and solves for the new voltages in one sweep
+    /// over the tree
+    fn solve_serial(&mut self) -> Solved {
+        let n = self.v.len();
+        let dt = self.dt;
+        let active = &self.active;
+        let spines = self
+            .spines
+            .iter_mut()
+            .filter(|s| active[s.parent])
+            .flat_map(|s| {
+                let v = s.v;
+                s.membranes.iter_mut().zip(v)
+            });
+        for (m, v) in self
+            .membranes
+            .iter_mut()
+            .zip(self.v.iter().copied())
+            .zip(active)
+            .filter_map(|(mv, &on)| on.then_some(mv))
+            .chain(spines)
+        {
+            advance_gates(m, v, dt, &mut self.rng);
+        }
+
+        // Row i: d[i] V_i - axial[i] V_parent - sum over children axial V_child = rhs[i],
+        // in nS and pA. Inactive compartments keep an empty row and so their
+        // voltage.
+        let mut d = vec![0.0; n];
+        let mut rhs = vec![0.0; n];
+        let mut membrane = vec![(0.0, 0.0); n];
+        for i in 1..n {
+            if !self.active[i] {
+                continue;
+            }
+            membrane[i] = self.membranes[i].linearized();
+            let (own, r) = self.own_row(i, membrane[i]);
+            d[i] += own;
+            rhs[i] = r;
+            if self.parent[i] != 0 {
+                d[self.parent[i]] += self.axial[i];
+            }
+        }
+        let spine_rows = self.spine_rows();
+        for spine in self.spines.iter().filter(|s| self.active[s.parent]) {
+            d[spine.parent] += spine.axial;
+        }
+        let clamped = |i: usize| self.clamps[i].is_some() && self.active[i];
+        for i in 1..n {
+            if let (Some(v), true) = (self.clamps[i], self.active[i]) {
+                (d[i], rhs[i]) = (1.0, v);
+            }
+        }
+        // Spines are leaves on their compartment, eliminated like children
+        for (spine, rows) in self.spines.iter().zip(&spine_rows) {
+            let (dn, rn) = rows[0];
+            if dn != 0.0 && !clamped(spine.parent) {
+                d[spine.parent] -= spine.axial * spine.axial / dn;
+                rhs[spine.parent] += spine.axial * rn / dn;
+            }
+        }
+        // Children come after their parents, so eliminating from the end
+        // leaves every row with its diagonal and its parent only
+        for i in (2..n).rev() {
+            let p = self.parent[i];
+            if p == 0 || d[i] == 0.0 || clamped(p) {
+                continue;
+            }
+            let factor = -self.axial[i] / d[i];
+            if !clamped(i) {
+                d[p] += factor * self.axial[i];
+            }
+            rhs[p] -= factor * rhs[i];
+        }
+        let old = self.v.clone();
+        for i in 1..n {
+            let p = self.parent[i];
+            if d[i] == 0.0 {
+                // Nothing ties it to anything
+                continue;
+            }
+            self.v[i] = if clamped(i) || p == 0 {
+                rhs[i] / d[i]
+            } else {
+                (rhs[i] + self.axial[i] * self.v[p]) / d[i]
+            };
+        }
+        Solved {
+            membrane,
+            spine_rows,
+            old,
+        }
+    }
+
+    /// `solve_serial` with the subtrees of `plan` advanced, eliminated and
+    /// substituted on their own threads
+    fn solve_partitioned(&mut self, plan: &TreePlan) -> Result<Solved, SimError> {
+        let n = self.v.len();
+        let dt = self.dt;
+        // No compartment gates stochastically, so the spines' draws are the
+        // only ones and come in the same order
+        for spine in self.spines.iter_mut() {
+            for (m, v) in spine.membranes.iter_mut().zip(spine.v) {
+                advance_gates(m, v, dt, &mut self.rng);
+            }
+        }
+        let spine_rows = self.spine_rows();
+
+        let mut membrane = vec![(0.0, 0.0); n];
+        let mut membranes = std::mem::take(&mut self.membranes);
+        let mut buckets: Vec<Vec<&mut Membrane>> = plan
+            .shares
+            .iter()
+            .map(|s| Vec::with_capacity(s.nodes.len()))
+            .collect();
+        let mut top = Vec::new();
+        for (i, (m, owner)) in membranes.iter_mut().zip(&plan.owner).enumerate().skip(1) {
+            match owner {
+                Some(b) => buckets[*b].push(m),
+                None => top.push((i, m)),
+            }
+        }
+        let this = &*self;
+        let eliminated = thread::scope(|scope| {
+            let workers: Vec<_> = plan
+                .shares
+                .iter()
+                .zip(buckets)
+                .map(|(share, ms)| scope.spawn(|| this.eliminate_share(share, ms, &spine_rows)))
+                .collect();
+            for (i, m) in top {
+                if let Membrane::HodgkinHuxley { hh, gates, .. } = m {
+                    hh.update(gates, this.v[i], dt);
+                }
+                membrane[i] = m.linearized();
+            }
+            workers
+                .into_iter()
+                .map(|w| w.join().map_err(|_| SimError::Panicked))
+                .collect::<Result<Vec<ShareRows>, SimError>>()
+        });
+        self.membranes = membranes;
+        let eliminated = eliminated?;
+
+        let mut d = vec![0.0; n];
+        let mut rhs = vec![0.0; n];
+        for (share, rows) in plan.shares.iter().zip(&eliminated) {
+            for (k, &i) in share.nodes.iter().enumerate() {
+                membrane[i] = rows.membrane[k];
+                if share.local_parent[k].is_none() {
+                    (d[i], rhs[i]) = (rows.d[k], rows.rhs[k]);
+                }
+            }
+        }
+        // Above the cuts, in the order of the serial sweep
+        let above = |i: usize| plan.owner[i].is_none();
+        for &i in &plan.junctions {
+            if above(i) {
+                let (own, r) = self.own_row(i, membrane[i]);
+                d[i] += own;
+                rhs[i] = r;
+            }
+            if self.parent[i] != 0 {
+                d[self.parent[i]] += self.axial[i];
+            }
+        }
+        for &k in &plan.top_spines {
+            d[self.spines[k].parent] += self.spines[k].axial;
+        }
+        let clamped = |i: usize| self.clamps[i].is_some();
+        for &i in plan.junctions.iter().filter(|&&i| above(i)) {
+            if let Some(v) = self.clamps[i] {
+                (d[i], rhs[i]) = (1.0, v);
+            }
+        }
+        for &k in &plan.top_spines {
+            let spine = &self.spines[k];
+            let (dn, rn) = spine_rows[k][0];
+            if dn != 0.0 && !clamped(spine.parent) {
+                d[spine.parent] -= spine.axial * spine.axial / dn;
+                rhs[spine.parent] += spine.axial * rn / dn;
+            }
+        }
+        for &i in plan.junctions.iter().rev() {
+            let p = self.parent[i];
+            if p == 0 || d[i] == 0.0 || clamped(p) {
+                continue;
+            }
+            let factor = -self.axial[i] / d[i];
+            if !clamped(i) {
+                d[p] += factor * self.axial[i];
+            }
+            rhs[p] -= factor * rhs[i];
+        }
+        let old = self.v.clone();
+        for &i in &plan.junctions {
+            let p = self.parent[i];
+            if d[i] == 0.0 {
+                continue;
+            }
+            self.v[i] = if clamped(i) || p == 0 {
+                rhs[i] / d[i]
+            } else {
+                (rhs[i] + self.axial[i] * self.v[p]) / d[i]
+            };
+        }
+
+        let this = &*self;
+        let substituted = thread::scope(|scope| {
+            let workers: Vec<_> = plan
+                .shares
+                .iter()
+                .zip(&eliminated)
+                .map(|(share, rows)| scope.spawn(|| this.substitute_share(share, rows)))
+                .collect();
+            workers
+                .into_iter()
+                .map(|w| w.join().map_err(|_| SimError::Panicked))
+                .collect::<Result<Vec<Vec<f64>>, SimError>>()
+        })?;
+        for (share, v) in plan.shares.iter().zip(substituted) {
+            for (&i, v) in share.nodes.iter().zip(v) {
+                self.v[i] = v;
+            }
+        }
+        Ok(Solved {
+            membrane,
+            spine_rows,
+            old,
+        })
+    }
+
+    /// Assembles the rows of `share`, whose membranes are `membranes`, and
+    /// eliminates every one but the subtree roots
+    fn eliminate_share(
+        &self,
+        share: &Share,
+        membranes: Vec<&mut Membrane>,
+        spine_rows: &[[(f64, f64); 2]],
+    ) -> ShareRows {
+        let len = share.nodes.len();
+        let mut d = vec![0.0; len];
+        let mut rhs = vec![0.0; len];
+        let mut membrane = Vec::with_capacity(len);
+        for (k, (&i, m)) in share.nodes.iter().zip(membranes).enumerate() {
+            if let Membrane::HodgkinHuxley { hh, gates, .. } = m {
+                hh.update(gates, self.v[i], self.dt);
+            }
+            membrane.push(m.linearized());
+            let (own, r) = self.own_row(i, membrane[k]);
+            d[k] += own;
+            rhs[k] = r;
+            if let Some(lp) = share.local_parent[k] {
+                d[lp] += self.axial[i];
+            }
+        }
+        for &(s, k) in &share.spines {
+            d[k] += self.spines[s].axial;
+        }
+        let clamped = |i: usize| self.clamps[i].is_some();
+        for (k, &i) in share.nodes.iter().enumerate() {
+            if let Some(v) = self.clamps[i] {
+                (d[k], rhs[k]) = (1.0, v);
+            }
+        }
+        for &(s, k) in &share.spines {
+            let spine = &self.spines[s];
+            let (dn, rn) = spine_rows[s][0];
+            if dn != 0.0 && !clamped(spine.parent) {
+                d[k] -= spine.axial * spine.axial / dn;
+                rhs[k] += spine.axial * rn / dn;
+            }
+        }
+        for k in (0..len).rev() {
+            let Some(lp) = share.local_parent[k] else {
+                continue;
+            };
+            let i = share.nodes[k];
+            if d[k] == 0.0 || clamped(self.parent[i]) {
+                continue;
+            }
+            let factor = -self.axial[i] / d[k];
+            if !clamped(i) {
+                d[lp] += factor * self.axial[i];
+            }
+            rhs[lp] -= factor * rhs[k];
+        }
+        ShareRows { d, rhs, membrane }
+    }
+
+    /// New voltages of `share`, its roots already solved
+    fn substitute_share(&self, share: &Share, rows: &ShareRows) -> Vec<f64> {
+        let mut v: Vec<f64> = Vec::with_capacity(share.nodes.len());
+        for (k, &i) in share.nodes.iter().enumerate() {
+            let new = match share.local_parent[k] {
+                Some(lp) if rows.d[k] != 0.0 => {
+                    if self.clamps[i].is_some() {
+                        rows.rhs[k] / rows.d[k]
+                    } else {
+                        (rows.rhs[k] + self.axial[i] * v[lp]) / rows.d[k]
+                    }
+                }
+                _ => self.v[i],
+            };
+            v.push(new);
+        }
+        v
+    }
+
+    /// Advances by one time step, then applies the changes a morphology
+    /// schedule has for the new time
+    pub fn step(&mut self) -> Result<(), SimError> {
+        let n = self.v.len();
+        let dt = self.dt;
+        if !self.electrodes.is_empty() {
+            self.inject_extracellular();
+        }
+        if !self.synapses.is_empty() {
+            self.open_synapses();
+        }
+        let Solved {
+            membrane,
+            spine_rows,
+            old,
+        } = match self.plan.clone() {
+            Some(plan) => self.solve_partitioned(&plan)?,
+            None => self.solve_serial(),
+        };
+        let clamped = |i: usize| self.clamps[i].is_some() && self.active[i];
+
+        for (spine, rows) in self.spines.iter_mut().zip(&spine_rows) {
+            let [(dn, rn), (dh, rh)] = *rows;
+            if dn != 0.0 {
+                spine.v[0] = (rn + spine.axial * self.v[spine.parent]) / dn;
+            }
+            if dh != 0.0 {
+                spine.v[1] = (rh + spine.axial * spine.v[0]) / dh;
+            }
+        }
+
+        // What each clamp had to supply: everything leaving the compartment
+        // minus what was injected
+        for i in (1..n).filter(|&i| clamped(i)) {
+            let (mut g, mut ge) = membrane[i];
+            if let Some((gs, gse)) = self.synaptic.get(i) {
+                (g, ge) = (g + gs, ge + gse);
+            }
+            let mut out = self.capacitance[i] / dt * (self.v[i] - old[i]) + g * self.v[i] - ge;
+            out += self.axial[i] * (self.v[i] - self.v[self.parent[i]]);
+            for j in (i + 1..n).filter(|&j| self.parent[j] == i) {
+                out += self.axial[j] * (self.v[i] - self.v[j]);
+            }
+            for spine in self.spines.iter().filter(|s| s.parent == i) {
+                out += spine.axial * (self.v[i] - spine.v[0]);
+            }
+            self.clamp_currents[i] = out * 1e-3 - self.injected[i];
+        }
+
+        for i in 1..n {
+            self.membrane_currents[i] = match self.active[i] {
+                true => {
+                    let (mut g, mut ge) = membrane[i];
+                    if let Some((gs, gse)) = self.synaptic.get(i) {
+                        (g, ge) = (g + gs, ge + gse);
+                    }
+                    (self.capacitance[i] / dt * (self.v[i] - old[i]) + g * self.v[i] - ge) * 1e-3
+                }
+                false => 0.0,
+            };
+        }
+        for spine in &self.spines {
+            self.membrane_currents[spine.parent] +=
+                spine.axial * (self.v[spine.parent] - spine.v[0]) * 1e-3;
+        }
+
+        if self.accumulation.is_some() || self.ledger.is_some() {
+            for i in (1..n).filter(|&i| self.active[i]) {
+                for (ion, current, driving_force) in self.membranes[i].currents(self.v[i]) {
+                    if let Some(accumulation) = self.accumulation.as_deref_mut() {
+                        accumulation.record(i, ion, current);
+                    }
+                    if let Some(ledger) = self.ledger.as_deref_mut() {
+                        ledger.record(i, ion, current, driving_force, dt);
+                    }
+                }
+            }
+        }
+        if let Some(accumulation) = self.accumulation.as_deref_mut() {
+            accumulation
+                .advance(dt)
+                .map_err(|reason| SimError::Depleted { reason })?;
+            self.refresh_reversals();
+        }
+
+        self.injected.iter_mut().for_each(|c| *c = 0.0);
+        self.spines.iter_mut().for_each(|s| s.injected = 0.0);
+        self.steps += 1;
+        if !self.synapses.is_empty() {
+            self.close_synapses();
+        }
+        self.apply_schedule();
+        if let Some(i) = self.v.iter().position(|v| !v.is_finite()) {
+            return Err(SimError::Diverged {
+                compartment_idx: i,
+                spine_idx: None,
+                time: self.time(),
+            });
+        }
+        if let Some(k) = self
+            .spines
+            .iter()
+            .position(|s| s.v.iter().any(|v| !v.is_finite()))
+        {
+            return Err(SimError::Diverged {
+                compartment_idx: self.spines[k].parent,
+                spine_idx: Some(k),
+                time: self.time(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Runs `steps` steps, injecting `stimuli[k].1[s]` into compartment
+    /// `stimuli[k].0` over step `s`, and records every voltage (or those
+    /// given to `record_voltages_of`), every path
+    /// given to `record`, every axial current probe and every path given to
+    /// `record_around_events`
+    pub fn run(
+        &mut self,
+        steps: usize,
+        stimuli: &[(usize, Vec<f64>)],
+    ) -> Result<SimulationResult, SimError> {
+        for (idx, waveform) in stimuli {
+            self.check(*idx)?;
+            if waveform.len() != steps {
+                return Err(SimError::InvalidInput {
+                    reason: format!(
+                        "Stimulus for compartment {} has {} values for {} steps",
+                        idx,
+                        waveform.len(),
+                        steps
+                    ),
+                });
+            }
+        }
+        let recorded: Vec<bool> = (0..self.v.len())
+            .map(|i| self.recorded_voltages.as_ref().is_none_or(|r| r[i]))
+            .collect();
+        let buffered = self.output.buffered(steps);
+        let mut voltages: Vec<Vec<f64>> = self
+            .v
+            .iter()
+            .enumerate()
+            .map(|(i, &v)| match recorded[i] {
+                true => {
+                    let mut trace = Vec::with_capacity(buffered);
+                    trace.push(v);
+                    trace
+                }
+                false => Vec::new(),
+            })
+            .collect();
+        let mut head_voltages: Vec<Vec<f64>> = self.spines.iter().map(|s| vec![s.v[1]]).collect();
+        let mut traces: Vec<(String, Vec<f64>)> = self
+            .recorded
+            .iter()
+            .map(|path| (path.clone(), Vec::with_capacity(buffered)))
+            .collect();
+        self.sample(&mut traces)?;
+        let mut axial_currents: Vec<((usize, usize), Vec<f64>)> = self
+            .axial_probes
+            .iter()
+            .map(|&pair| (pair, Vec::with_capacity(buffered)))
+            .collect();
+        self.sample_axial(&mut axial_currents);
+        let start = self.time();
+        let mut recorders = self.triggered_recorders();
+        self.sample_triggered(&mut recorders)?;
+        let mut writer = ChunkWriter::create(
+            &self.output,
+            self.dt,
+            &voltages,
+            &head_voltages,
+            &traces,
+            &axial_currents,
+        )?;
+        if let Some(writer) = &mut writer {
+            writer.sampled(
+                &mut voltages,
+                &mut head_voltages,
+                &mut traces,
+                &mut axial_currents,
+            )?;
+        }
+        for s in 0..steps {
+            for (idx, waveform) in stimuli {
+                self.injected[*idx] += waveform[s];
+            }
+            self.step()?;
+            for ((trace, &v), &recorded) in voltages.iter_mut().zip(&self.v).zip(&recorded) {
+                if recorded {
+                    trace.push(v);
+                }
+            }
+            for (trace, spine) in head_voltages.iter_mut().zip(&self.spines) {
+                trace.push(spine.v[1]);
+            }
+            self.sample(&mut traces)?;
+            self.sample_axial(&mut axial_currents);
+            self.sample_triggered(&mut recorders)?;
+            if let Some(writer) = &mut writer {
+                writer.sampled(
+                    &mut voltages,
+                    &mut head_voltages,
+                    &mut traces,
+                    &mut axial_currents,
+                )?;
+            }
+        }
+        let stream = match writer {
+            Some(mut writer) => {
+                writer.flush(
+                    &mut voltages,
+                    &mut head_voltages,
+                    &mut traces,
+                    &mut axial_currents,
+                )?;
+                Some(writer.finish())
+            }
+            None => None,
+        };
+        Ok(SimulationResult {
+            dt: self.dt,
+            voltages,
+            head_voltages,
+            energy: self.energy_report(),
+            traces,
+            axial_currents,
+            snippets: self.finish_triggered(recorders, start),
+            manifest: Manifest::capture("backward_euler", false),
+            stream,
+        })
+    }
+}
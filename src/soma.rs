@@ -0,0 +1,381 @@
+//! How a file draws the soma, and the cylinder that stands in for it.
+//!
+//! Reconstructions encode the soma in one of four ways: a single point with
+//! the soma radius, the NeuroMorpho three-point convention (a centre with
+//! two children one radius either side of it), a contour tracing its outline
+//! or a chain of frusta along it. Each has a different equivalent geometry,
+//! and reading one as another gets the soma area badly wrong.
+//!
+//! The equivalent is a cylinder, as NEURON models a soma, with the surface
+//! area the convention implies: that of a sphere of the soma radius for a
+//! point, the NeuroMorpho cylinder for three points, a sphere of the mean
+//! centroid distance for a contour and the summed frustum sides for a chain.
+
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+use log::warn;
+
+use crate::compartments::{Compartment, Compartments};
+use crate::geometry::{self, Vec3};
+use crate::swc_reader::{Node, NodeFlags, Skeleton, StructureIdentifier};
+
+/// Relative tolerance on the three-point convention's symmetry
+const THREE_POINT_TOLERANCE: f64 = 1e-2;
+/// Largest spread of centroid distances, relative to their mean, that a
+/// contour may have
+const CONTOUR_SPREAD: f64 = 0.2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SomaStyle {
+    /// One node whose radius is the soma radius
+    SinglePoint,
+    /// NeuroMorpho's centre plus two points one radius either side of it
+    ThreePoint,
+    /// An unbranched path of soma nodes tracing the outline and closing on
+    /// itself
+    Contour,
+    /// An unbranched path of soma nodes along the soma, each a cross section
+    Chain,
+}
+
+/// The cylinder standing in for the soma
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SomaGeometry {
+    pub center: [f64; 3],
+    /// Unit direction the cylinder runs along
+    pub axis: [f64; 3],
+    /// In µm
+    pub length: f64,
+    pub diam: f64,
+}
+
+impl SomaGeometry {
+    /// Lateral area of the cylinder, in µm²
+    pub fn area(&self) -> f64 {
+        PI * self.diam * self.length
+    }
+}
+
+/// Soma nodes connected to the root through soma nodes, the root first and
+/// parents ahead of their children
+struct SomaPoints {
+    position: Vec<Vec3>,
+    radius: Vec<f64>,
+    /// Soma children of each point, as indices into these lists
+    children: Vec<Vec<usize>>,
+    /// Soma nodes anywhere in the tree, connected or not
+    total: usize,
+}
+
+impl SomaPoints {
+    fn of_skeleton(nodes: &[Node], parent_child_map: &HashMap<u64, Vec<u64>>) -> SomaPoints {
+        let by_id: HashMap<u64, &Node> = nodes.iter().map(|n| (n.node_id, n)).collect();
+        let is_soma = |n: &Node| n.structured_identifier == StructureIdentifier::Soma;
+        let root = nodes.iter().find(|n| n.parent_id == n.node_id);
+        let start: Vec<u64> = root
+            .filter(|n| is_soma(n))
+            .map(|n| n.node_id)
+            .into_iter()
+            .collect();
+        SomaPoints::walk(
+            start,
+            |id| {
+                let n = by_id[&id];
+                ([n.x_pos, n.y_pos, n.z_pos], n.radius)
+            },
+            |id| {
+                parent_child_map
+                    .get(&id)
+                    .into_iter()
+                    .flatten()
+                    .copied()
+                    .filter(|&c| c != id && is_soma(by_id[&c]))
+                    .collect()
+            },
+            nodes.iter().filter(|n| is_soma(n)).count(),
+        )
+    }
+
+    fn of_compartments(compartments: &Compartments) -> SomaPoints {
+        let components = &compartments.components;
+        let is_soma = |c: &Compartment| c.structure == StructureIdentifier::Soma;
+        let start: Vec<u64> = components
+            .get(1)
+            .filter(|c| is_soma(c))
+            .map(|_| 1)
+            .into_iter()
+            .collect();
+        SomaPoints::walk(
+            start,
+            |idx| {
+                let c = &components[idx as usize];
+                (c.distal, c.diam / 2.0)
+            },
+            |idx| {
+                components[idx as usize]
+                    .children_idxs
+                    .iter()
+                    .copied()
+                    .filter(|&c| is_soma(&components[c as usize]))
+                    .collect()
+            },
+            components.iter().skip(1).filter(|c| is_soma(c)).count(),
+        )
+    }
+
+    /// Breadth first from `start`, so an unbranched path comes out in order
+    fn walk(
+        start: Vec<u64>,
+        point: impl Fn(u64) -> (Vec3, f64),
+        soma_children: impl Fn(u64) -> Vec<u64>,
+        total: usize,
+    ) -> SomaPoints {
+        let mut points = SomaPoints {
+            position: Vec::new(),
+            radius: Vec::new(),
+            children: Vec::new(),
+            total,
+        };
+        let mut keys = start;
+        let mut i = 0;
+        while i < keys.len() {
+            let (position, radius) = point(keys[i]);
+            points.position.push(position);
+            points.radius.push(radius);
+            let children = soma_children(keys[i]);
+            points
+                .children
+                .push((keys.len()..keys.len() + children.len()).collect());
+            keys.extend(children);
+            i += 1;
+        }
+        points
+    }
+
+    fn len(&self) -> usize {
+        self.position.len()
+    }
+
+    fn classify(&self) -> Result<SomaStyle, String> {
+        let n = self.len();
+        if n == 0 {
+            return Err("the root is not a soma node".to_owned());
+        }
+        if n < self.total {
+            return Err(format!(
+                "{} of {} soma nodes are not connected to the root",
+                self.total - n,
+                self.total
+            ));
+        }
+        if n == 1 {
+            return Ok(SomaStyle::SinglePoint);
+        }
+        if let [a, b] = self.children[0][..]
+            && n == 3
+        {
+            let (c, r) = (self.position[0], self.radius[0]);
+            let (da, db) = (
+                geometry::sub(self.position[a], c),
+                geometry::sub(self.position[b], c),
+            );
+            let tolerance = THREE_POINT_TOLERANCE * r;
+            let symmetric = geometry::norm(geometry::add(da, db)) <= tolerance;
+            let at_radius = (geometry::norm(da) - r).abs() <= tolerance
+                && (geometry::norm(db) - r).abs() <= tolerance;
+            if symmetric && at_radius {
+                return Ok(SomaStyle::ThreePoint);
+            }
+            return Err(
+                "three soma nodes around the root, but not one radius either side of it".to_owned(),
+            );
+        }
+        if self.children.iter().any(|c| c.len() > 1) {
+            return Err("the soma nodes branch".to_owned());
+        }
+
+        // An unbranched path: a contour if every point is about as far from
+        // the centroid as the others and the path comes back to its start
+        if n >= 3 {
+            let center = geometry::centroid(&self.position);
+            let distances: Vec<f64> = self
+                .position
+                .iter()
+                .map(|&p| geometry::norm(geometry::sub(p, center)))
+                .collect();
+            let mean = distances.iter().sum::<f64>() / n as f64;
+            let variance = distances.iter().map(|d| (d - mean).powi(2)).sum::<f64>() / n as f64;
+            let step = self.path_length() / (n - 1) as f64;
+            let gap = geometry::norm(geometry::sub(self.position[n - 1], self.position[0]));
+            if mean > 0.0 && variance.sqrt() <= CONTOUR_SPREAD * mean && gap <= 2.0 * step {
+                return Ok(SomaStyle::Contour);
+            }
+        }
+        Ok(SomaStyle::Chain)
+    }
+
+    /// Summed distance between consecutive points of an unbranched path
+    fn path_length(&self) -> f64 {
+        self.position
+            .windows(2)
+            .map(|w| geometry::norm(geometry::sub(w[1], w[0])))
+            .sum()
+    }
+
+    fn geometry(&self, style: SomaStyle) -> Result<SomaGeometry, String> {
+        let n = self.len();
+        if n == 0 {
+            return Err("No soma at the root".to_owned());
+        }
+        let x_axis = [1.0, 0.0, 0.0];
+        let sphere = |center, r: f64| SomaGeometry {
+            center,
+            axis: x_axis,
+            length: 2.0 * r,
+            diam: 2.0 * r,
+        };
+        match style {
+            SomaStyle::SinglePoint => Ok(sphere(self.position[0], self.radius[0])),
+            SomaStyle::ThreePoint => {
+                let [a, b] = self.children[0][..] else {
+                    return Err("Three-point soma needs two soma nodes on the root".to_owned());
+                };
+                let along = geometry::sub(self.position[b], self.position[a]);
+                let length = geometry::norm(along);
+                if length == 0.0 {
+                    return Err("Three-point soma has its outer points together".to_owned());
+                }
+                Ok(SomaGeometry {
+                    center: self.position[0],
+                    axis: geometry::scale(along, 1.0 / length),
+                    length,
+                    diam: 2.0 * self.radius[0],
+                })
+            }
+            SomaStyle::Contour | SomaStyle::Chain if n < 2 => {
+                Err(format!("{:?} soma needs at least two soma nodes", style))
+            }
+            SomaStyle::Contour | SomaStyle::Chain if self.children.iter().any(|c| c.len() > 1) => {
+                Err(format!("{:?} soma needs unbranched soma nodes", style))
+            }
+            SomaStyle::Contour => {
+                let center = geometry::centroid(&self.position);
+                let mean = self
+                    .position
+                    .iter()
+                    .map(|&p| geometry::norm(geometry::sub(p, center)))
+                    .sum::<f64>()
+                    / n as f64;
+                Ok(sphere(center, mean))
+            }
+            SomaStyle::Chain => {
+                let length = self.path_length();
+                if length == 0.0 {
+                    return Err("Chain soma has all its nodes together".to_owned());
+                }
+                let area: f64 = (1..n)
+                    .map(|i| {
+                        let (r0, r1) = (self.radius[i - 1], self.radius[i]);
+                        let h =
+                            geometry::norm(geometry::sub(self.position[i], self.position[i - 1]));
+                        PI * (r0 + r1) * ((r0 - r1).powi(2) + h * h).sqrt()
+                    })
+                    .sum();
+                let along = geometry::sub(self.position[n - 1], self.position[0]);
+                let span = geometry::norm(along);
+                Ok(SomaGeometry {
+                    center: geometry::centroid(&self.position),
+                    axis: if span > 0.0 {
+                        geometry::scale(along, 1.0 / span)
+                    } else {
+                        x_axis
+                    },
+                    length,
+                    diam: area / (PI * length),
+                })
+            }
+        }
+    }
+}
+
+/// Which convention the soma of a read skeleton follows, from the number of
+/// soma nodes connected to the root, how they connect and whether three of
+/// them sit symmetrically one radius around the root. Anything that fits
+/// none of the conventions, or no soma at all, is warned about and treated
+/// as a single point.
+pub fn detect_soma_style(nodes: &[Node], parent_child_map: &HashMap<u64, Vec<u64>>) -> SomaStyle {
+    classify_soma(nodes, parent_child_map).unwrap_or_else(|reason| {
+        warn!("Unclear soma, treating it as a single point: {}", reason);
+        SomaStyle::SinglePoint
+    })
+}
+
+/// Like `detect_soma_style`, but saying why an unclear soma is unclear
+/// instead of falling back
+pub fn classify_soma(
+    nodes: &[Node],
+    parent_child_map: &HashMap<u64, Vec<u64>>,
+) -> Result<SomaStyle, String> {
+    SomaPoints::of_skeleton(nodes, parent_child_map).classify()
+}
+
+impl Skeleton {
+    /// See `detect_soma_style`
+    pub fn soma_style(&self) -> SomaStyle {
+        detect_soma_style(&self.nodes, &self.parent_child_map)
+    }
+
+    /// Equivalent cylinder of the soma read as `style`
+    pub fn soma_geometry(&self, style: SomaStyle) -> Result<SomaGeometry, String> {
+        SomaPoints::of_skeleton(&self.nodes, &self.parent_child_map).geometry(style)
+    }
+}
+
+impl Compartments {
+    /// Replaces the soma nodes, as built from the skeleton, with one
+    /// compartment at index 1 shaped as `style` says. A single-point soma
+    /// keeps only the root's node and leaves any other soma nodes alone.
+    /// Neurites on the merged nodes move onto the new soma.
+    pub fn model_soma(&mut self, style: SomaStyle) -> Result<SomaGeometry, String> {
+        let points = SomaPoints::of_compartments(self);
+        let shape = points.geometry(style)?;
+        if style != SomaStyle::SinglePoint && points.len() > 1 {
+            let mut group = vec![1];
+            let mut frontier = vec![1usize];
+            while let Some(idx) = frontier.pop() {
+                for &c in &self.components[idx].children_idxs {
+                    if self.components[c as usize].structure == StructureIdentifier::Soma {
+                        group.push(c as usize);
+                        frontier.push(c as usize);
+                    }
+                }
+            }
+            self.merge(vec![group], mark_soma_merged, false)?;
+        }
+
+        let soma = &mut self.components[1];
+        let half = geometry::scale(shape.axis, shape.length / 2.0);
+        soma.proximal = geometry::sub(shape.center, half);
+        soma.distal = geometry::add(shape.center, half);
+        soma.normal = geometry::transport(soma.normal, soma.tangent, shape.axis);
+        soma.tangent = shape.axis;
+        soma.length = shape.length;
+        soma.diam = shape.diam;
+        soma.area_factor = 1.0;
+        self.log(
+            "model_soma",
+            &[
+                ("style", format!("{:?}", style).into()),
+                ("length", shape.length.into()),
+                ("diam", shape.diam.into()),
+            ],
+        );
+        Ok(shape)
+    }
+}
+
+/// The geometry is set afterwards; only the flag is folded
+fn mark_soma_merged(first: &mut Compartment, _parts: &[&Compartment]) {
+    first.flags |= NodeFlags::SOMA_MERGED;
+}
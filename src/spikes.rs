@@ -0,0 +1,164 @@
+//! Presynaptic spike trains from outside the model, e.g. background input
+//! standing in for cells that are not simulated. Times are in ms, rates in
+//! Hz.
+//!
+//! Random trains are fully determined by their seed. Give each synapse its
+//! own seed with `sub_seed`, so a batch or parallel run draws the same
+//! trains whatever order the synapses are processed in.
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SpikeTrainSource {
+    /// Spikes at exponentially distributed intervals from `start` until
+    /// before `stop`
+    Poisson {
+        rate_hz: f64,
+        start: f64,
+        stop: f64,
+        seed: u64,
+    },
+    /// One spike every `1000 / rate_hz` ms from `start` until before `stop`
+    Regular { rate_hz: f64, start: f64, stop: f64 },
+    /// Exactly these spike times
+    FromTimes(Vec<f64>),
+}
+
+/// Step for integrating envelopes, in ms
+const ENVELOPE_STEP: f64 = 0.01;
+
+impl SpikeTrainSource {
+    /// Spike times in increasing order
+    pub fn events(&self) -> Result<Vec<f64>, String> {
+        match *self {
+            SpikeTrainSource::Poisson {
+                rate_hz,
+                start,
+                stop,
+                seed,
+            } => {
+                check_rate(rate_hz)?;
+                let mut rng = StdRng::seed_from_u64(seed);
+                let mean_interval = 1000.0 / rate_hz;
+                let mut events = Vec::new();
+                let mut t = start;
+                loop {
+                    t += -(1.0 - rng.random::<f64>()).ln() * mean_interval;
+                    if t.is_nan() || t >= stop {
+                        return Ok(events);
+                    }
+                    events.push(t);
+                }
+            }
+            SpikeTrainSource::Regular {
+                rate_hz,
+                start,
+                stop,
+            } => {
+                check_rate(rate_hz)?;
+                let interval = 1000.0 / rate_hz;
+                Ok((0..)
+                    .map(|k| start + k as f64 * interval)
+                    .take_while(|&t| t < stop)
+                    .collect())
+            }
+            SpikeTrainSource::FromTimes(_) => self.events_with_envelope(|_| 1.0),
+        }
+    }
+
+    /// Spike times with the rate scaled by `envelope(t)` at every instant,
+    /// for time-varying drive. The rate is integrated in steps of 0.01 ms,
+    /// so this draws different times than `events` for the same seed. Negative envelope values count as 0.
+    /// `FromTimes` trains ignore the envelope.
+    pub fn events_with_envelope(&self, envelope: impl Fn(f64) -> f64) -> Result<Vec<f64>, String> {
+        match *self {
+            SpikeTrainSource::Poisson {
+                rate_hz,
+                start,
+                stop,
+                seed,
+            } => {
+                check_rate(rate_hz)?;
+                let mut rng = StdRng::seed_from_u64(seed);
+                // Time rescaling: unit-rate Poisson events in the integrated
+                // rate are Poisson events at that rate
+                let mut draw = move || -(1.0 - rng.random::<f64>()).ln();
+                let first = draw();
+                Ok(integrate(rate_hz, start, stop, &envelope, first, draw))
+            }
+            SpikeTrainSource::Regular {
+                rate_hz,
+                start,
+                stop,
+            } => {
+                check_rate(rate_hz)?;
+                // A spike each time the integrated rate passes a whole number
+                Ok(integrate(rate_hz, start, stop, &envelope, 0.0, || 1.0))
+            }
+            SpikeTrainSource::FromTimes(ref times) => {
+                if let Some(t) = times.iter().find(|t| !t.is_finite()) {
+                    return Err(format!("Spike time must be finite, got {}", t));
+                }
+                let mut times = times.clone();
+                times.sort_by(f64::total_cmp);
+                Ok(times)
+            }
+        }
+    }
+}
+
+fn check_rate(rate_hz: f64) -> Result<(), String> {
+    if rate_hz >= 0.0 && rate_hz.is_finite() {
+        Ok(())
+    } else {
+        Err(format!("Rate must be non-negative, got {} Hz", rate_hz))
+    }
+}
+
+/// Times at which the integral of `rate_hz * envelope` from `start` reaches
+/// `first`, then each further increment from `next`
+fn integrate(
+    rate_hz: f64,
+    start: f64,
+    stop: f64,
+    envelope: &impl Fn(f64) -> f64,
+    first: f64,
+    mut next: impl FnMut() -> f64,
+) -> Vec<f64> {
+    let rate = rate_hz / 1000.0;
+    let mut events = Vec::new();
+    let mut integral = 0.0;
+    let mut target = first;
+    let mut t = start;
+    while t < stop {
+        let h = ENVELOPE_STEP.min(stop - t);
+        let step = rate * h * envelope(t + h / 2.0).max(0.0);
+        while step > 0.0 && integral + step >= target {
+            events.push(t + h * (target - integral) / step);
+            target += next();
+        }
+        integral += step;
+        t += h;
+    }
+    events
+}
+
+/// Envelope from values sampled every `dt` ms starting at 0, held constant
+/// between samples and after the last one
+pub fn sampled_envelope(dt: f64, values: Vec<f64>) -> impl Fn(f64) -> f64 {
+    move |t| {
+        let k = (t / dt).floor().max(0.0) as usize;
+        values.get(k).or(values.last()).copied().unwrap_or(1.0)
+    }
+}
+
+/// Seed for item `index` (e.g. a synapse) derived from a run's `seed`, so
+/// every item gets an independent stream that does not depend on the order
+/// items are handled in. SplitMix64 finalizer.
+pub fn sub_seed(seed: u64, index: u64) -> u64 {
+    let mut z = seed ^ index.wrapping_add(1).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
@@ -0,0 +1,195 @@
+//! Dendritic spines as two-compartment units hung off the compartment tree:
+//! a cylindrical neck and a head, each one more unknown in the solver,
+//! coupled to the compartment they sit on through the neck's axial
+//! resistance. They leave the compartments and their indices as they are.
+//!
+//! A compartment is isopotential, so where along it a spine sits only
+//! matters for bookkeeping: the position follows the compartment through
+//! coarsening and discretization like a point attachment. Synapses go on
+//! the heads through `Simulation::set_spine_synapse`, and every run records
+//! the head voltages.
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::channels::{Channel, ChannelType, Passive};
+use crate::compartments::Compartments;
+use crate::filter::NodeFilter;
+use crate::index_map::IndexMap;
+use crate::spikes::sub_seed;
+
+/// Geometry and membrane of a spine
+#[derive(Clone)]
+pub struct SpineShape {
+    /// In µm
+    pub neck_length: f64,
+    /// In µm. A zero diameter cuts the spine off from its compartment.
+    pub neck_diam: f64,
+    /// In µm²
+    pub head_area: f64,
+    /// Membrane of the neck and the head. Its `resistance` is the neck's
+    /// axial resistivity.
+    pub channel: Channel,
+}
+
+impl Default for SpineShape {
+    /// A mushroom-sized spine: a 1 µm long, 0.1 µm wide neck of 100 Ω·cm,
+    /// which is about 127 MΩ, under a 1 µm² head, with a passive membrane
+    fn default() -> Self {
+        SpineShape {
+            neck_length: 1.0,
+            neck_diam: 0.1,
+            head_area: 1.0,
+            channel: Channel {
+                channel_type: ChannelType::Passive(Passive::default()),
+                resistance: 100.0,
+                capacitance: 1.0,
+                conductance: 1e-4,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl SpineShape {
+    /// Conductance of the whole neck end to end, in nS
+    pub fn neck_conductance(&self) -> f64 {
+        let cross_section = std::f64::consts::PI * self.neck_diam * self.neck_diam / 4.0;
+        // 1 / (Ω·cm/µm) is 1e5 nS
+        cross_section / (self.channel.resistance * self.neck_length) * 1e5
+    }
+
+    /// Lateral area of the neck, in µm²
+    pub fn neck_area(&self) -> f64 {
+        std::f64::consts::PI * self.neck_diam * self.neck_length
+    }
+
+    fn check(&self) -> Result<(), String> {
+        let positive = |x: f64| x > 0.0 && x.is_finite();
+        let non_negative = |x: f64| x >= 0.0 && x.is_finite();
+        if !positive(self.neck_length) || !non_negative(self.neck_diam) {
+            return Err(format!(
+                "Spine neck needs a positive length and a non-negative diameter, got {} and {} µm",
+                self.neck_length, self.neck_diam
+            ));
+        }
+        if !non_negative(self.head_area) {
+            return Err(format!(
+                "Spine head area cannot be negative, got {} µm²",
+                self.head_area
+            ));
+        }
+        if !positive(self.channel.resistance) {
+            return Err(format!(
+                "Spine neck resistivity must be positive, got {} Ω·cm",
+                self.channel.resistance
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A spine on compartment `idx`, at `x` along it, 0 at its proximal end
+#[derive(Clone)]
+pub struct Spine {
+    pub idx: usize,
+    pub x: f64,
+    pub shape: SpineShape,
+}
+
+impl Compartments {
+    /// Hangs `spine` off its compartment and returns its index among the
+    /// spines, which is also its index in `Simulation`
+    pub fn add_spine(&mut self, spine: Spine) -> Result<usize, String> {
+        if spine.idx == 0 || spine.idx >= self.components.len() {
+            return Err(format!("No compartment {} to put a spine on", spine.idx));
+        }
+        if !(0.0..=1.0).contains(&spine.x) {
+            return Err(format!(
+                "Spine position must lie within [0, 1], got {}",
+                spine.x
+            ));
+        }
+        spine.shape.check()?;
+        self.spines.push(spine);
+        Ok(self.spines.len() - 1)
+    }
+
+    /// Scatters spines of `shape` over every compartment matching `filter`,
+    /// at `density` per µm of length on average, as a Poisson process along
+    /// each. Each compartment draws from its own stream of `seed`, so the
+    /// same compartments and seed always give the same spines. Returns how
+    /// many were added.
+    pub fn add_spines(
+        &mut self,
+        filter: &NodeFilter,
+        density: f64,
+        shape: &SpineShape,
+        seed: u64,
+    ) -> Result<usize, String> {
+        if !(density >= 0.0 && density.is_finite()) {
+            return Err(format!(
+                "Spine density must be non-negative and finite, got {} per µm",
+                density
+            ));
+        }
+        shape.check()?;
+        let before = self.spines.len();
+        for (idx, c) in self.components.iter().enumerate().skip(1) {
+            if density == 0.0 || c.length <= 0.0 || !filter.matches_parts(c.structure, c.flags) {
+                continue;
+            }
+            let mut rng = StdRng::seed_from_u64(sub_seed(seed, idx as u64));
+            let mut at = 0.0;
+            loop {
+                let u: f64 = 1.0 - rng.random::<f64>();
+                at += -u.ln() / density;
+                if at >= c.length {
+                    break;
+                }
+                self.spines.push(Spine {
+                    idx,
+                    x: at / c.length,
+                    shape: shape.clone(),
+                });
+            }
+        }
+        let added = self.spines.len() - before;
+        self.log(
+            "add_spines",
+            &[
+                ("filter", format!("{:?}", filter).into()),
+                ("density", density.into()),
+                ("seed", seed.into()),
+                ("added", added.into()),
+            ],
+        );
+        Ok(added)
+    }
+
+    pub fn spines(&self) -> &[Spine] {
+        &self.spines
+    }
+
+    /// Drops every spine, returning how many there were
+    pub fn clear_spines(&mut self) -> usize {
+        std::mem::take(&mut self.spines).len()
+    }
+}
+
+/// `spines` moved through `map`, each to where its point lands
+pub(crate) fn translated_spines(spines: &[Spine], map: &IndexMap) -> Result<Vec<Spine>, String> {
+    spines
+        .iter()
+        .map(|s| {
+            let (idx, x) = map
+                .forward_position(s.idx, s.x)
+                .ok_or_else(|| format!("A spine is on compartment {}, which is removed", s.idx))?;
+            Ok(Spine {
+                idx,
+                x,
+                shape: s.shape.clone(),
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,1114 @@
+//! Normalizing a collection of reconstructions onto one set of conventions.
+//!
+//! `Pipeline::standardize` takes a `StandardizeOptions` recipe and applies
+//! it to every file of a `Dataset`, in parallel, in this order:
+//!
+//! 1. read, applying any `SCALE` header
+//! 2. convert nanometre coordinates to µm
+//! 3. infer types for files that only mark forks and ends, as FlyWire does
+//! 4. re-root at the soma
+//! 5. replace a multi-node soma with a single point of the same area
+//! 6. repair or reject zero radii
+//! 7. split segments longer than the target spacing
+//! 8. optionally, move the cell into its canonical frame
+//! 9. score with `qc::score_skeleton` and, with `quarantine_below` set,
+//!    hold back a cell that scores lower
+//! 10. write SWC with IDs from 1, the original header entries and
+//!     provenance entries naming the source file and the recipe
+//!
+//! Each file gets a `FileReport` saying what was detected and which steps
+//! changed it; a file that fails is reported and the batch carries on. The
+//! recipe is written next to the outputs as `RECIPE_FILE`, and the scores of
+//! held back files, when quarantining, as `QUARANTINE_FILE`. Standardizing
+//! standardized output changes nothing, byte for byte, except that frame
+//! normalization may move coordinates in their last bits.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::f64::consts::PI;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use sha2::{Digest, Sha256};
+
+use crate::bundle::{Bundle, BundleWriter};
+use crate::codes::Code;
+use crate::error::SwcError;
+use crate::qc::{QcReport, QcRubric, qc_csv, score_skeleton};
+use crate::registration::{FrameOptions, normalize_frame};
+use crate::soma::SomaStyle;
+use crate::swc_reader::{
+    ConflictPolicy, Node, NodeFlags, ReaderOptions, Skeleton, StructureIdentifier, format_float,
+    swc_reader_from_bytes, to_swc_string,
+};
+use crate::write;
+
+/// Name of the recipe written next to the standardized files
+pub const RECIPE_FILE: &str = "standardize.txt";
+
+/// Name of the report of quarantined files written next to the
+/// standardized files, in the CSV form of `Dataset::qc_to_csv`
+pub const QUARANTINE_FILE: &str = "quarantine.csv";
+
+/// Median radius above which `LengthUnit::Auto` takes a file to be in nm.
+/// Neurites in µm are rarely more than a few µm thick, in nm hundreds.
+const NANOMETRE_MEDIAN_RADIUS: f64 = 20.0;
+
+/// Segments up to this fraction longer than the target spacing are left
+/// alone, so round-off in a previous split does not split them again
+const SPACING_SLACK: f64 = 1e-9;
+
+/// Why a recipe, a dataset or one of its files could not be standardized
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum DatasetError {
+    /// A recipe line does not parse
+    MalformedRecipe { reason: String },
+    /// Listing, reading or writing a file failed
+    Io { path: PathBuf, message: String },
+    /// A file the pipeline cannot read, e.g. Neurolucida `.asc`
+    Unsupported { path: PathBuf, reason: String },
+    /// Two inputs would be written to the same output
+    NameCollision { name: String },
+    /// A file does not read as SWC
+    Read(SwcError),
+    /// Writing the bundle failed
+    Bundle { path: PathBuf, reason: String },
+    /// A cell the recipe cannot standardize, e.g. one with no nodes
+    Rejected { reason: String },
+    /// The worker standardizing the file panicked
+    Panicked,
+}
+
+impl fmt::Display for DatasetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DatasetError::MalformedRecipe { reason } | DatasetError::Rejected { reason } => {
+                write!(f, "{}", reason)
+            }
+            DatasetError::Io { path, message } => write!(f, "{}: {}", path.display(), message),
+            DatasetError::Unsupported { path, reason } | DatasetError::Bundle { path, reason } => {
+                write!(f, "{}: {}", path.display(), reason)
+            }
+            DatasetError::NameCollision { name } => {
+                write!(f, "Another input also becomes {}", name)
+            }
+            DatasetError::Read(e) => write!(f, "{}", e),
+            DatasetError::Panicked => write!(f, "Standardizing panicked"),
+        }
+    }
+}
+
+impl std::error::Error for DatasetError {}
+
+impl From<SwcError> for DatasetError {
+    fn from(e: SwcError) -> Self {
+        DatasetError::Read(e)
+    }
+}
+
+impl DatasetError {
+    pub fn code(&self) -> Code {
+        match self {
+            DatasetError::MalformedRecipe { .. } => Code::MalformedRecipe,
+            DatasetError::Io { .. } => Code::DatasetIo,
+            DatasetError::Unsupported { .. } => Code::UnsupportedFile,
+            DatasetError::NameCollision { .. } => Code::OutputCollision,
+            DatasetError::Read(e) => e.code(),
+            DatasetError::Bundle { .. } => Code::BundleFailure,
+            DatasetError::Rejected { .. } => Code::RejectedCell,
+            DatasetError::Panicked => Code::StandardizePanicked,
+        }
+    }
+
+    fn rejected(reason: impl Into<String>) -> DatasetError {
+        DatasetError::Rejected {
+            reason: reason.into(),
+        }
+    }
+
+    fn recipe(reason: String) -> DatasetError {
+        DatasetError::MalformedRecipe { reason }
+    }
+
+    fn io(path: &Path, e: std::io::Error) -> DatasetError {
+        DatasetError::Io {
+            path: path.to_owned(),
+            message: e.to_string(),
+        }
+    }
+
+    fn bundle(path: &Path, reason: String) -> DatasetError {
+        DatasetError::Bundle {
+            path: path.to_owned(),
+            reason,
+        }
+    }
+
+    fn not_swc(path: &Path) -> DatasetError {
+        DatasetError::Unsupported {
+            path: path.to_owned(),
+            reason: "Not a .swc, .swc.gz or .asc file".to_owned(),
+        }
+    }
+}
+
+/// Unit the coordinates and radii of the inputs are in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LengthUnit {
+    /// Nanometres if the median radius is over 20, µm otherwise
+    #[default]
+    Auto,
+    Micrometers,
+    Nanometers,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SomaHandling {
+    /// Leave the soma nodes as they are
+    Keep,
+    /// Replace the soma nodes connected to the root by one node at their
+    /// centre, with the radius of the sphere of the same area
+    #[default]
+    SinglePoint,
+}
+
+/// What to do with radii the reader found to be zero
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ZeroRadiusPolicy {
+    /// Take the parent's radius, or for the root that of the nearest node
+    /// with a real one
+    #[default]
+    Repair,
+    /// Fail the file
+    Reject,
+}
+
+/// The recipe, serializable with `to_text`/`parse` so it can ship with the
+/// dataset it produced
+#[derive(Debug, Clone, PartialEq)]
+pub struct StandardizeOptions {
+    pub units: LengthUnit,
+    /// Infer soma and neurite types when no node is typed soma, axon or
+    /// dendrite. The widest node becomes the soma and all others dendrite,
+    /// since such files do not tell axon from dendrite.
+    pub infer_types: bool,
+    /// Re-root at the widest soma node if the root is not a soma node
+    pub reroot_at_soma: bool,
+    pub soma: SomaHandling,
+    pub zero_radius: ZeroRadiusPolicy,
+    /// Split longer segments into equal pieces at most this long, in µm
+    pub max_spacing: Option<f64>,
+    /// Move each cell into the frame of `registration::normalize_frame`
+    pub normalize_frame: bool,
+    /// Hold back cells whose QC score under the default `QcRubric` is
+    /// below this, instead of writing them
+    pub quarantine_below: Option<f64>,
+}
+
+impl Default for StandardizeOptions {
+    fn default() -> Self {
+        StandardizeOptions {
+            units: LengthUnit::Auto,
+            infer_types: true,
+            reroot_at_soma: true,
+            soma: SomaHandling::SinglePoint,
+            zero_radius: ZeroRadiusPolicy::Repair,
+            max_spacing: None,
+            normalize_frame: false,
+            quarantine_below: None,
+        }
+    }
+}
+
+impl StandardizeOptions {
+    pub fn to_text(&self) -> String {
+        let lines = [
+            ("standardize", "1".to_owned()),
+            (
+                "units",
+                match self.units {
+                    LengthUnit::Auto => "auto",
+                    LengthUnit::Micrometers => "um",
+                    LengthUnit::Nanometers => "nm",
+                }
+                .to_owned(),
+            ),
+            ("infer_types", self.infer_types.to_string()),
+            ("reroot_at_soma", self.reroot_at_soma.to_string()),
+            (
+                "soma",
+                match self.soma {
+                    SomaHandling::Keep => "keep",
+                    SomaHandling::SinglePoint => "single_point",
+                }
+                .to_owned(),
+            ),
+            (
+                "zero_radius",
+                match self.zero_radius {
+                    ZeroRadiusPolicy::Repair => "repair",
+                    ZeroRadiusPolicy::Reject => "reject",
+                }
+                .to_owned(),
+            ),
+            (
+                "max_spacing",
+                self.max_spacing.map_or("none".to_owned(), format_float),
+            ),
+            ("normalize_frame", self.normalize_frame.to_string()),
+            (
+                "quarantine_below",
+                self.quarantine_below
+                    .map_or("none".to_owned(), format_float),
+            ),
+        ];
+        lines
+            .iter()
+            .map(|(key, value)| format!("{} {}\n", key, value))
+            .collect()
+    }
+
+    /// Reads back the output of `to_text`. Keys left out keep their
+    /// defaults, so a recipe only needs to list what it changes.
+    pub fn parse(text: &str) -> Result<StandardizeOptions, DatasetError> {
+        let mut options = StandardizeOptions::default();
+        let mut seen = Vec::new();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            let value = value.trim();
+            if seen.contains(&key) {
+                return Err(DatasetError::recipe(format!("'{}' appears twice", key)));
+            }
+            seen.push(key);
+            let flag = || {
+                value
+                    .parse::<bool>()
+                    .map_err(|_| DatasetError::recipe(format!("'{}' must be true or false", key)))
+            };
+            match key {
+                "standardize" if value == "1" => {}
+                "standardize" => {
+                    return Err(DatasetError::recipe(format!(
+                        "Unknown recipe version '{}'",
+                        value
+                    )));
+                }
+                "units" => {
+                    options.units = match value {
+                        "auto" => LengthUnit::Auto,
+                        "um" => LengthUnit::Micrometers,
+                        "nm" => LengthUnit::Nanometers,
+                        _ => {
+                            return Err(DatasetError::recipe(format!("Unknown units '{}'", value)));
+                        }
+                    }
+                }
+                "infer_types" => options.infer_types = flag()?,
+                "reroot_at_soma" => options.reroot_at_soma = flag()?,
+                "soma" => {
+                    options.soma = match value {
+                        "keep" => SomaHandling::Keep,
+                        "single_point" => SomaHandling::SinglePoint,
+                        _ => {
+                            return Err(DatasetError::recipe(format!(
+                                "Unknown soma handling '{}'",
+                                value
+                            )));
+                        }
+                    }
+                }
+                "zero_radius" => {
+                    options.zero_radius = match value {
+                        "repair" => ZeroRadiusPolicy::Repair,
+                        "reject" => ZeroRadiusPolicy::Reject,
+                        _ => {
+                            return Err(DatasetError::recipe(format!(
+                                "Unknown zero radius policy '{}'",
+                                value
+                            )));
+                        }
+                    }
+                }
+                "max_spacing" => {
+                    options.max_spacing = match value {
+                        "none" => None,
+                        _ => match value.parse::<f64>() {
+                            Ok(s) if s > 0.0 && s.is_finite() => Some(s),
+                            _ => {
+                                return Err(DatasetError::recipe(format!(
+                                    "Invalid max_spacing '{}'",
+                                    value
+                                )));
+                            }
+                        },
+                    }
+                }
+                "normalize_frame" => options.normalize_frame = flag()?,
+                "quarantine_below" => {
+                    options.quarantine_below = match value {
+                        "none" => None,
+                        _ => match value.parse::<f64>() {
+                            Ok(s) if (0.0..=1.0).contains(&s) => Some(s),
+                            _ => {
+                                return Err(DatasetError::recipe(format!(
+                                    "Invalid quarantine_below '{}'",
+                                    value
+                                )));
+                            }
+                        },
+                    }
+                }
+                _ => return Err(DatasetError::recipe(format!("Unknown key '{}'", key))),
+            }
+        }
+        Ok(options)
+    }
+
+    /// First 16 hex digits of the sha256 of `to_text`, written into every
+    /// output so files can be matched to the recipe that made them
+    pub fn digest(&self) -> String {
+        Sha256::digest(self.to_text().as_bytes())
+            .iter()
+            .take(8)
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+}
+
+/// Files to standardize
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Dataset {
+    pub paths: Vec<PathBuf>,
+}
+
+impl Dataset {
+    /// Every `.swc`, `.swc.gz` and `.asc` file directly in `dir`, by name
+    pub fn from_dir(dir: impl AsRef<Path>) -> Result<Dataset, DatasetError> {
+        let dir = dir.as_ref();
+        let entries = fs::read_dir(dir).map_err(|e| DatasetError::io(dir, e))?;
+        let mut paths = Vec::new();
+        for entry in entries {
+            let path = entry.map_err(|e| DatasetError::io(dir, e))?.path();
+            if path.is_file() && output_name(&path).is_some() {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        Ok(Dataset { paths })
+    }
+
+    /// `from_dir` for a directory; a bundle, or any other file, on its own
+    pub fn from_source(source: impl AsRef<Path>) -> Result<Dataset, DatasetError> {
+        let source = source.as_ref();
+        if source.is_dir() {
+            Dataset::from_dir(source)
+        } else {
+            Ok(Dataset::from_paths([source]))
+        }
+    }
+
+    pub fn from_paths(paths: impl IntoIterator<Item = impl Into<PathBuf>>) -> Dataset {
+        Dataset {
+            paths: paths.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+/// Contents of one file of a `Dataset`, refusing what cannot be parsed
+pub(crate) fn read_source(source: &Path) -> Result<Vec<u8>, DatasetError> {
+    if source
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("asc"))
+    {
+        return Err(DatasetError::Unsupported {
+            path: source.to_owned(),
+            reason: "Neurolucida .asc files cannot be read yet; convert them to SWC".to_owned(),
+        });
+    }
+    fs::read(source).map_err(|e| DatasetError::io(source, e))
+}
+
+/// `cell.swc`, `cell.swc.gz` and `cell.asc` all become `cell.swc`
+fn output_name(path: &Path) -> Option<String> {
+    let name = path.file_name()?.to_str()?;
+    let lower = name.to_lowercase();
+    [".swc.gz", ".swc", ".asc"]
+        .iter()
+        .find(|ext| lower.ends_with(*ext) && lower.len() > ext.len())
+        .map(|ext| format!("{}.swc", &name[..name.len() - ext.len()]))
+}
+
+/// What happened to one file
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FileReport {
+    pub source: PathBuf,
+    /// Where the standardized file went, None if it failed
+    pub output: Option<PathBuf>,
+    /// Conventions found in the input, e.g. `units nm`
+    pub detected: Vec<String>,
+    /// Steps that changed the cell, each starting with the step's name,
+    /// e.g. `convert_units`, `infer_types`, `reroot`, `single_point_soma`,
+    /// `repair_zero_radius`, `resample` or `normalize_frame`
+    pub operations: Vec<String>,
+    pub error: Option<DatasetError>,
+    /// QC of the standardized cell, None if it failed before
+    pub qc: Option<QcReport>,
+    /// Held back for scoring below `quarantine_below`; not an error
+    pub quarantined: bool,
+}
+
+impl FileReport {
+    /// Whether a step whose name starts `operations` ran
+    pub fn applied(&self, step: &str) -> bool {
+        self.operations
+            .iter()
+            .any(|op| op.split_whitespace().next() == Some(step))
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BatchReport {
+    /// In the order of the dataset
+    pub files: Vec<FileReport>,
+}
+
+impl BatchReport {
+    pub fn failures(&self) -> impl Iterator<Item = &FileReport> {
+        self.files.iter().filter(|f| f.error.is_some())
+    }
+
+    pub fn successes(&self) -> impl Iterator<Item = &FileReport> {
+        self.files
+            .iter()
+            .filter(|f| f.error.is_none() && !f.quarantined)
+    }
+
+    pub fn quarantined(&self) -> impl Iterator<Item = &FileReport> {
+        self.files.iter().filter(|f| f.quarantined)
+    }
+}
+
+pub struct Pipeline {
+    options: StandardizeOptions,
+    threads: usize,
+}
+
+impl Pipeline {
+    /// A pipeline applying `options`, on as many threads as there are cores
+    pub fn standardize(options: StandardizeOptions) -> Pipeline {
+        Pipeline {
+            options,
+            threads: 0,
+        }
+    }
+
+    /// Uses `threads` worker threads; 0 picks one per core
+    pub fn with_threads(mut self, threads: usize) -> Pipeline {
+        self.threads = threads;
+        self
+    }
+
+    pub fn options(&self) -> &StandardizeOptions {
+        &self.options
+    }
+
+    /// Standardizes every file of `dataset` into `out_dir`, overwriting
+    /// files of the same name, and when quarantining lists the files held
+    /// back in `QUARANTINE_FILE`. Fails only if the recipe or that list
+    /// cannot be written; failures of single files are in the report.
+    pub fn run(
+        &self,
+        dataset: &Dataset,
+        out_dir: impl AsRef<Path>,
+    ) -> Result<BatchReport, DatasetError> {
+        let out_dir = out_dir.as_ref();
+        write::write_atomic(
+            &out_dir.join(RECIPE_FILE),
+            self.options.to_text().as_bytes(),
+            ConflictPolicy::Overwrite,
+        )?;
+
+        // Two inputs that would land on the same output both fail, rather
+        // than one silently replacing the other
+        let names: Vec<Option<String>> = dataset.paths.iter().map(|p| output_name(p)).collect();
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for name in names.iter().flatten() {
+            *counts.entry(name).or_default() += 1;
+        }
+
+        let reports = self.each_file(dataset, |i, source| {
+            let output = match &names[i] {
+                Some(name) if counts[name.as_str()] > 1 => {
+                    Err(DatasetError::NameCollision { name: name.clone() })
+                }
+                Some(name) => Ok(out_dir.join(name)),
+                None => Err(DatasetError::not_swc(source)),
+            };
+            self.standardize_file(source, output)
+        });
+        let report = batch_report(dataset, reports);
+        if self.options.quarantine_below.is_some() {
+            let text = qc_csv(
+                report
+                    .quarantined()
+                    .filter_map(|f| Some((f.source.as_path(), Ok(f.qc.as_ref()?)))),
+            );
+            write::write_atomic(
+                &out_dir.join(QUARANTINE_FILE),
+                text.as_bytes(),
+                ConflictPolicy::Overwrite,
+            )?;
+        }
+        Ok(report)
+    }
+
+    /// Standardizes every file of `dataset` into a new bundle at `path`,
+    /// in the order of the dataset, replacing any file there. A cell that
+    /// comes out the same as an earlier one is stored once. Each cell
+    /// carries the recipe's digest, but the recipe itself is not written,
+    /// nor are quarantined files listed; see `BatchReport::quarantined`.
+    /// Fails only if the bundle cannot be written; failures of single
+    /// files are in the report.
+    pub fn run_to_bundle(
+        &self,
+        dataset: &Dataset,
+        path: impl AsRef<Path>,
+    ) -> Result<BatchReport, DatasetError> {
+        let path = path.as_ref();
+        let read_options = ReaderOptions {
+            apply_scale: true,
+            collect_stats: false,
+            ..ReaderOptions::default()
+        };
+        // Files finish in any order; each worker writes out whatever is
+        // ready in dataset order, so the bundle keeps that order without
+        // holding every cell
+        struct Queue {
+            writer: BundleWriter,
+            next: usize,
+            ready: BTreeMap<usize, (FileReport, Result<Option<Skeleton>, DatasetError>)>,
+            reports: Vec<Option<FileReport>>,
+            failed: Option<DatasetError>,
+        }
+        let write_ready = |queue: &mut Queue| {
+            while let Some((mut report, result)) = queue.ready.remove(&queue.next) {
+                match result {
+                    Ok(None) => {}
+                    Ok(_) if queue.failed.is_some() => {}
+                    Ok(Some(skeleton)) => match queue.writer.add(&skeleton) {
+                        Ok(_) => report.output = Some(path.to_owned()),
+                        Err(e) => queue.failed = Some(DatasetError::bundle(path, e)),
+                    },
+                    Err(e) => report.error = Some(e),
+                }
+                queue.reports[queue.next] = Some(report);
+                queue.next += 1;
+            }
+        };
+        let queue = Mutex::new(Queue {
+            writer: Bundle::create(path).map_err(|e| DatasetError::bundle(path, e))?,
+            next: 0,
+            ready: BTreeMap::new(),
+            reports: vec![None; dataset.paths.len()],
+            failed: None,
+        });
+        self.each_file(dataset, |i, source| {
+            let mut report = FileReport {
+                source: source.to_owned(),
+                ..FileReport::default()
+            };
+            let result = match output_name(source) {
+                Some(_) => self
+                    .standardize_path(source, &mut report)
+                    .and_then(|text| match text {
+                        Some(text) => swc_reader_from_bytes(text.as_bytes(), &read_options)
+                            .map(Some)
+                            .map_err(DatasetError::Read),
+                        None => Ok(None),
+                    }),
+                None => Err(DatasetError::not_swc(source)),
+            };
+            let mut queue = queue.lock().unwrap_or_else(|e| e.into_inner());
+            queue.ready.insert(i, (report, result));
+            write_ready(&mut queue);
+        });
+        let mut queue = queue.into_inner().unwrap_or_else(|e| e.into_inner());
+        // Past a file whose worker panicked, the rest are still waiting
+        while let Some(&i) = queue.ready.keys().next() {
+            queue.next = i;
+            write_ready(&mut queue);
+        }
+        if let Some(e) = queue.failed {
+            return Err(e);
+        }
+        queue
+            .writer
+            .finish()
+            .map_err(|e| DatasetError::bundle(path, e))?;
+        Ok(batch_report(dataset, queue.reports))
+    }
+
+    /// Runs `work` on every file of `dataset` across the worker threads,
+    /// returning what it gave per file, None where it panicked
+    fn each_file<T: Send>(
+        &self,
+        dataset: &Dataset,
+        work: impl Fn(usize, &Path) -> T + Sync,
+    ) -> Vec<Option<T>> {
+        let threads = match self.threads {
+            0 => thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        }
+        .min(dataset.paths.len())
+        .max(1);
+        let next = AtomicUsize::new(0);
+        let mut results: Vec<Option<T>> = (0..dataset.paths.len()).map(|_| None).collect();
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut done = Vec::new();
+                        loop {
+                            let i = next.fetch_add(1, Ordering::Relaxed);
+                            let Some(source) = dataset.paths.get(i) else {
+                                break;
+                            };
+                            done.push((i, work(i, source)));
+                        }
+                        done
+                    })
+                })
+                .collect();
+            for worker in workers {
+                // A panicking worker takes its files with it; the caller
+                // reports them
+                for (i, result) in worker.join().unwrap_or_default() {
+                    results[i] = Some(result);
+                }
+            }
+        });
+        results
+    }
+
+    fn standardize_file(&self, source: &Path, output: Result<PathBuf, DatasetError>) -> FileReport {
+        let mut report = FileReport {
+            source: source.to_owned(),
+            ..FileReport::default()
+        };
+        let result = output.and_then(|output| {
+            let Some(text) = self.standardize_path(source, &mut report)? else {
+                return Ok(None);
+            };
+            write::write_atomic(&output, text.as_bytes(), ConflictPolicy::Overwrite)?;
+            Ok(Some(output))
+        });
+        match result {
+            Ok(output) => report.output = output,
+            Err(e) => report.error = Some(e),
+        }
+        report
+    }
+
+    /// The standardized SWC text, None if the cell was quarantined
+    fn standardize_path(
+        &self,
+        source: &Path,
+        report: &mut FileReport,
+    ) -> Result<Option<String>, DatasetError> {
+        let data = read_source(source)?;
+        let options = ReaderOptions {
+            apply_scale: true,
+            collect_stats: false,
+            ..ReaderOptions::default()
+        };
+        let skeleton = swc_reader_from_bytes(&data, &options)?;
+        // Kept for QC, which counts the nodes the reader left out
+        let warnings = skeleton.warnings.clone();
+        let mut skeleton = self.standardize_skeleton(skeleton, report)?;
+        skeleton.warnings = warnings;
+        let rubric = QcRubric {
+            pass_threshold: self
+                .options
+                .quarantine_below
+                .unwrap_or(QcRubric::default().pass_threshold),
+            ..QcRubric::default()
+        };
+        let qc = score_skeleton(&skeleton, &rubric);
+        let quarantine = self.options.quarantine_below.is_some() && !qc.passed;
+        report.qc = Some(qc);
+        if quarantine {
+            report.quarantined = true;
+            return Ok(None);
+        }
+
+        let metadata = &mut skeleton.metadata;
+        let name = source
+            .file_name()
+            .map_or(String::new(), |n| n.to_string_lossy().into_owned());
+        // Standardized output names its own source already
+        metadata
+            .other
+            .entry("SOURCE_FILE".to_owned())
+            .or_insert(name);
+        metadata
+            .other
+            .entry("SOURCE_SHA256".to_owned())
+            .or_insert_with(|| {
+                Sha256::digest(&data)
+                    .iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect()
+            });
+        metadata
+            .other
+            .insert("STANDARDIZE_RECIPE".to_owned(), self.options.digest());
+        Ok(Some(to_swc_string(&skeleton, &options)))
+    }
+
+    /// Applies every step but reading and writing to `skeleton`, noting in
+    /// `report` what was detected and done
+    pub fn standardize_skeleton(
+        &self,
+        skeleton: Skeleton,
+        report: &mut FileReport,
+    ) -> Result<Skeleton, DatasetError> {
+        let options = &self.options;
+        let Skeleton {
+            nodes, metadata, ..
+        } = skeleton;
+        let mut nodes = Arc::unwrap_or_clone(nodes);
+        if nodes.is_empty() {
+            return Err(DatasetError::rejected("No nodes"));
+        }
+        if let Some(n) = nodes.iter().find(|n| n.radius < 0.0) {
+            return Err(DatasetError::rejected(format!(
+                "Node {} has negative radius {}",
+                n.node_id + 1,
+                n.radius
+            )));
+        }
+
+        let nanometres = match options.units {
+            LengthUnit::Auto => {
+                let mut radii: Vec<f64> = nodes
+                    .iter()
+                    .filter(|n| !n.flags.contains(NodeFlags::ZERO_RADIUS_FIXED))
+                    .map(|n| n.radius)
+                    .collect();
+                radii.sort_by(f64::total_cmp);
+                radii
+                    .get(radii.len() / 2)
+                    .is_some_and(|&r| r > NANOMETRE_MEDIAN_RADIUS)
+            }
+            LengthUnit::Micrometers => false,
+            LengthUnit::Nanometers => true,
+        };
+        report
+            .detected
+            .push(format!("units {}", if nanometres { "nm" } else { "um" }));
+        if nanometres {
+            for n in nodes.iter_mut() {
+                (n.x_pos, n.y_pos, n.z_pos) = (n.x_pos / 1e3, n.y_pos / 1e3, n.z_pos / 1e3);
+                if !n.flags.contains(NodeFlags::ZERO_RADIUS_FIXED) {
+                    n.radius /= 1e3;
+                }
+            }
+            report.operations.push("convert_units nm to um".to_owned());
+        }
+
+        let typed = nodes.iter().any(|n| {
+            matches!(
+                n.structured_identifier,
+                StructureIdentifier::Soma
+                    | StructureIdentifier::Axon
+                    | StructureIdentifier::BasalDendrite
+                    | StructureIdentifier::ApicalDendrite
+            )
+        });
+        if !typed {
+            report.detected.push("types fork/end only".to_owned());
+            if options.infer_types {
+                let soma = widest(&nodes, |_| true);
+                for n in nodes.iter_mut() {
+                    n.structured_identifier = if n.node_id == soma {
+                        StructureIdentifier::Soma
+                    } else {
+                        StructureIdentifier::BasalDendrite
+                    };
+                    n.flags |= NodeFlags::TYPE_INFERRED;
+                }
+                report.operations.push(format!(
+                    "infer_types soma at {}",
+                    position(&nodes[soma as usize])
+                ));
+            }
+        }
+
+        let is_soma = |n: &Node| n.structured_identifier == StructureIdentifier::Soma;
+        let mut root = root_of(&nodes);
+        if !is_soma(&nodes[root]) {
+            report.detected.push("root not a soma node".to_owned());
+            if options.reroot_at_soma && nodes.iter().any(is_soma) {
+                root = widest(&nodes, is_soma) as usize;
+                reroot(&mut nodes, root);
+                report
+                    .operations
+                    .push(format!("reroot at soma node at {}", position(&nodes[root])));
+            }
+        }
+        let mut skeleton = sorted(nodes, root, metadata);
+
+        let style = skeleton.soma_style();
+        report.detected.push(format!("soma {:?}", style));
+        if options.soma == SomaHandling::SinglePoint && style != SomaStyle::SinglePoint {
+            skeleton = single_point_soma(skeleton, style)?;
+            report
+                .operations
+                .push(format!("single_point_soma from {:?}", style));
+        }
+
+        let fixed = skeleton
+            .nodes
+            .iter()
+            .filter(|n| n.flags.contains(NodeFlags::ZERO_RADIUS_FIXED))
+            .count();
+        if fixed > 0 {
+            if options.zero_radius == ZeroRadiusPolicy::Reject {
+                return Err(DatasetError::rejected(format!(
+                    "{} nodes have zero radius",
+                    fixed
+                )));
+            }
+            skeleton = skeleton.with_repaired_radii();
+            report
+                .operations
+                .push(format!("repair_zero_radius {} nodes", fixed));
+        }
+
+        if let Some(spacing) = options.max_spacing {
+            let before = skeleton.nodes.len();
+            skeleton = resample(skeleton, spacing);
+            let added = skeleton.nodes.len() - before;
+            if added > 0 {
+                report
+                    .operations
+                    .push(format!("resample added {} nodes", added));
+            }
+        }
+
+        if options.normalize_frame {
+            let (moved, _) = normalize_frame(&skeleton, &FrameOptions::default())
+                .map_err(DatasetError::rejected)?;
+            skeleton = moved;
+            report.operations.push("normalize_frame".to_owned());
+        }
+        Ok(skeleton)
+    }
+}
+
+/// `reports` in the order of `dataset`, a missing one meaning the file's
+/// worker panicked
+fn batch_report(dataset: &Dataset, reports: Vec<Option<FileReport>>) -> BatchReport {
+    BatchReport {
+        files: reports
+            .into_iter()
+            .zip(&dataset.paths)
+            .map(|(report, source)| {
+                report.unwrap_or_else(|| FileReport {
+                    source: source.clone(),
+                    error: Some(DatasetError::Panicked),
+                    ..FileReport::default()
+                })
+            })
+            .collect(),
+    }
+}
+
+fn position(n: &Node) -> String {
+    format!(
+        "({}, {}, {})",
+        format_float(n.x_pos),
+        format_float(n.y_pos),
+        format_float(n.z_pos)
+    )
+}
+
+fn root_of(nodes: &[Node]) -> usize {
+    nodes
+        .iter()
+        .position(|n| n.parent_id == n.node_id)
+        .unwrap_or(0)
+}
+
+/// ID of the node with the largest radius among those `keep` accepts, the
+/// first of them on ties
+fn widest(nodes: &[Node], keep: impl Fn(&Node) -> bool) -> u64 {
+    nodes
+        .iter()
+        .filter(|n| keep(n))
+        .fold(None::<&Node>, |best, n| match best {
+            Some(b) if b.radius >= n.radius => Some(b),
+            _ => Some(n),
+        })
+        .map_or(0, |n| n.node_id)
+}
+
+/// Turns the edges between `new_root` and the old root around. Node IDs
+/// are indices into `nodes`.
+fn reroot(nodes: &mut [Node], new_root: usize) {
+    let mut node = new_root;
+    let mut new_parent = new_root;
+    loop {
+        let old_parent = nodes[node].parent_id as usize;
+        nodes[node].parent_id = new_parent as u64;
+        if old_parent == node {
+            break;
+        }
+        new_parent = node;
+        node = old_parent;
+    }
+}
+
+/// Renumbers `nodes` breadth first from `root`, as the reader does, and
+/// rebuilds the maps. Node IDs on entry are indices into `nodes`; nodes not
+/// reachable from `root` are dropped.
+fn sorted(nodes: Vec<Node>, root: usize, metadata: crate::metadata::SwcMetadata) -> Skeleton {
+    let mut children: Vec<Vec<usize>> = vec![Vec::new(); nodes.len()];
+    for (i, n) in nodes.iter().enumerate() {
+        if i != root {
+            children[n.parent_id as usize].push(i);
+        }
+    }
+    let mut order = Vec::with_capacity(nodes.len());
+    let mut queue = VecDeque::from([root]);
+    while let Some(i) = queue.pop_front() {
+        order.push(i);
+        queue.extend(&children[i]);
+    }
+    let mut new_id = vec![0; nodes.len()];
+    for (new, &old) in order.iter().enumerate() {
+        new_id[old] = new as u64;
+    }
+
+    let mut parent_child_map: HashMap<u64, Vec<u64>> = HashMap::new();
+    let mut child_parent_map: HashMap<u64, Vec<u64>> = HashMap::new();
+    let nodes: Vec<Node> = order
+        .iter()
+        .map(|&old| {
+            let mut node = nodes[old];
+            node.node_id = new_id[old];
+            node.parent_id = new_id[node.parent_id as usize];
+            parent_child_map
+                .entry(node.parent_id)
+                .or_default()
+                .push(node.node_id);
+            child_parent_map
+                .entry(node.node_id)
+                .or_default()
+                .push(node.parent_id);
+            node
+        })
+        .collect();
+    Skeleton {
+        nodes: Arc::new(nodes),
+        parent_child_map: Arc::new(parent_child_map),
+        child_parent_map: Arc::new(child_parent_map),
+        warnings: Vec::new(),
+        extras: Arc::default(),
+        extra_columns: Vec::new(),
+        metadata,
+        stats: None,
+    }
+}
+
+/// Replaces the soma nodes connected to the root with the root alone, at
+/// the centre of the soma read as `style` and with the radius of a sphere
+/// of the same area
+fn single_point_soma(skeleton: Skeleton, style: SomaStyle) -> Result<Skeleton, DatasetError> {
+    let shape = skeleton
+        .soma_geometry(style)
+        .map_err(DatasetError::rejected)?;
+    let Skeleton {
+        nodes, metadata, ..
+    } = skeleton;
+    let mut nodes = Arc::unwrap_or_clone(nodes);
+    let is_soma = |n: &Node| n.structured_identifier == StructureIdentifier::Soma;
+    let root = root_of(&nodes);
+    // Parents come first, so one pass finds the soma nodes hanging off the
+    // root through soma nodes
+    let mut merged = vec![false; nodes.len()];
+    merged[root] = true;
+    for i in 0..nodes.len() {
+        let parent = nodes[i].parent_id as usize;
+        if i != root && merged[parent] && is_soma(&nodes[i]) {
+            merged[i] = true;
+        }
+    }
+    for i in 0..nodes.len() {
+        let parent = nodes[i].parent_id as usize;
+        if !merged[i] && merged[parent] {
+            nodes[i].parent_id = root as u64;
+        }
+    }
+    let soma = &mut nodes[root];
+    [soma.x_pos, soma.y_pos, soma.z_pos] = shape.center;
+    soma.radius = (shape.area() / (4.0 * PI)).sqrt();
+    soma.flags |= NodeFlags::SOMA_MERGED;
+    // Merged nodes other than the root are now unreachable
+    for i in (0..nodes.len()).filter(|&i| merged[i] && i != root) {
+        nodes[i].parent_id = i as u64;
+    }
+    Ok(sorted(nodes, root, metadata))
+}
+
+/// Splits every segment longer than `spacing` into equal pieces, placing
+/// the new nodes on the straight line with interpolated radii
+pub(crate) fn resample(skeleton: Skeleton, spacing: f64) -> Skeleton {
+    let Skeleton {
+        nodes, metadata, ..
+    } = skeleton;
+    let mut nodes = Arc::unwrap_or_clone(nodes);
+    let root = root_of(&nodes);
+    for i in 0..nodes.len() {
+        let parent = nodes[i].parent_id as usize;
+        if parent == i {
+            continue;
+        }
+        let (a, b) = (nodes[parent], nodes[i]);
+        let length = ((b.x_pos - a.x_pos).powi(2)
+            + (b.y_pos - a.y_pos).powi(2)
+            + (b.z_pos - a.z_pos).powi(2))
+        .sqrt();
+        if length <= spacing * (1.0 + SPACING_SLACK) {
+            continue;
+        }
+        let pieces = (length / spacing).ceil() as usize;
+        let mut previous = parent as u64;
+        for k in 1..pieces {
+            let t = k as f64 / pieces as f64;
+            let lerp = |from: f64, to: f64| from + (to - from) * t;
+            let id = nodes.len() as u64;
+            nodes.push(Node {
+                node_id: id,
+                structured_identifier: b.structured_identifier,
+                x_pos: lerp(a.x_pos, b.x_pos),
+                y_pos: lerp(a.y_pos, b.y_pos),
+                z_pos: lerp(a.z_pos, b.z_pos),
+                radius: lerp(a.radius, b.radius),
+                parent_id: previous,
+                flags: NodeFlags::COORD_INTERPOLATED,
+            });
+            previous = id;
+        }
+        nodes[i].parent_id = previous;
+    }
+    sorted(nodes, root, metadata)
+}
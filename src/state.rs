@@ -0,0 +1,395 @@
+//! Named access to the state of a `Simulation` between steps, for debugging
+//! and for driving a run step by step from outside.
+//!
+//! Paths follow
+//!
+//! ```text
+//! path := "t" | "comp[" idx "]." var | "syn[" k "]." ("w" | "g")
+//! var  := "v" | "i_clamp" | "i_membrane" | "hh." gate | "hh.na_open" | "hh.k_open"
+//!       | ion "i" | ion "o" | "e" ion
+//! gate := "m" | "h" | "n"
+//! ion  := "na" | "k" | "ca" | "cl"
+//! ```
+//!
+//! `t` is the time in ms and `idx` a compartment index, 1 for the soma.
+//! `v` is the voltage in mV and `i_clamp` the current the clamp on the
+//! compartment supplied over the last step, in nA. `i_membrane` is the net
+//! current out through its membrane over the last step, in nA, see
+//! `Simulation::membrane_current`. `hh.m`, `hh.h` and `hh.n`
+//! are the gates of a Hodgkin-Huxley membrane, and `hh.na_open` and
+//! `hh.k_open` its open channel counts when gating is stochastic.
+//! With ion accumulation, see `Simulation::with_accumulation`, `ki` and `ko`
+//! are the intracellular and periaxonal concentrations of a tracked ion in
+//! mM, as NEURON names them, and `ek` its Nernst potential in mV.
+//! `syn[k].w` and `syn[k].g` are the weight and conductance, in nS, of
+//! synapse `k`, see `synapses`.
+//! `Simulation::list_paths` gives every path that reads in the current state.
+//! `Simulation::record` has a path recorded through a run.
+//!
+//! Only voltages and deterministic gates can be set, gates within 0 to 1. A
+//! voltage set this way leaves the gates as they are, unlike
+//! `Simulation::set_voltage`. With a run log, see `Simulation::with_run_log`,
+//! every write is recorded as `set_state`.
+
+use std::fmt;
+
+use crate::accumulation::nernst;
+use crate::channels::Ion;
+use crate::codes::Code;
+use crate::parameters::edit_distance;
+use crate::solver::{Membrane, Simulation};
+
+const GATES: [&str; 3] = ["m", "h", "n"];
+
+/// Inside, outside and reversal names of each ion that can accumulate
+const IONS: [(Ion, [&str; 3]); 4] = [
+    (Ion::Na, ["nai", "nao", "ena"]),
+    (Ion::K, ["ki", "ko", "ek"]),
+    (Ion::Ca, ["cai", "cao", "eca"]),
+    (Ion::Cl, ["cli", "clo", "ecl"]),
+];
+
+/// At most this many close matches are suggested for an unknown path
+const MAX_SUGGESTIONS: usize = 5;
+
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum StateError {
+    /// Not a path of the grammar; `suggestions` holds the close matches
+    Unknown {
+        path: String,
+        suggestions: Vec<String>,
+    },
+    /// A well-formed path naming a compartment index the model lacks
+    NoSuchCompartment { path: String, idx: usize },
+    /// The compartment has no such state, e.g. gates on a passive membrane;
+    /// `reason` says why
+    Unavailable { path: String, reason: String },
+    /// Read-only state such as the time or a clamp current
+    ReadOnly { path: String },
+    /// A value outside `min..=max`
+    OutOfRange {
+        path: String,
+        value: f64,
+        min: f64,
+        max: f64,
+    },
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::Unknown { path, suggestions } if suggestions.is_empty() => {
+                write!(f, "Unknown state path '{}'", path)
+            }
+            StateError::Unknown { path, suggestions } => write!(
+                f,
+                "Unknown state path '{}'; did you mean {}?",
+                path,
+                suggestions.join(", ")
+            ),
+            StateError::NoSuchCompartment { path, idx } => {
+                write!(f, "No compartment at index {} in '{}'", idx, path)
+            }
+            StateError::Unavailable { path, reason } => {
+                write!(f, "No state at '{}': {}", path, reason)
+            }
+            StateError::ReadOnly { path } => write!(f, "State '{}' cannot be set", path),
+            StateError::OutOfRange {
+                path,
+                value,
+                min,
+                max,
+            } => write!(
+                f,
+                "Value {} for '{}' is outside {} to {}",
+                value, path, min, max
+            ),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+impl StateError {
+    /// Machine-readable code, see `codes::REGISTRY`
+    pub fn code(&self) -> Code {
+        match self {
+            StateError::Unknown { .. } => Code::UnknownStatePath,
+            StateError::NoSuchCompartment { .. } => Code::NoSuchCompartment,
+            StateError::Unavailable { .. } => Code::StateUnavailable,
+            StateError::ReadOnly { .. } => Code::ReadOnlyState,
+            StateError::OutOfRange { .. } => Code::StateOutOfRange,
+        }
+    }
+
+    /// Compartment the path names, if it got that far
+    pub fn compartment_idx(&self) -> Option<usize> {
+        let path = match self {
+            StateError::NoSuchCompartment { idx, .. } => return Some(*idx),
+            StateError::Unknown { path, .. }
+            | StateError::Unavailable { path, .. }
+            | StateError::ReadOnly { path }
+            | StateError::OutOfRange { path, .. } => path,
+        };
+        parse(path).and_then(|p| match p {
+            Path::Time | Path::Synapse(..) => None,
+            Path::Compartment(idx, _) => Some(idx),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Var {
+    Voltage,
+    ClampCurrent,
+    MembraneCurrent,
+    Gate(usize),
+    NaOpen,
+    KOpen,
+    Inside(Ion),
+    Outside(Ion),
+    Reversal(Ion),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Path {
+    Time,
+    Compartment(usize, Var),
+    /// Weight when true, else conductance
+    Synapse(usize, bool),
+}
+
+fn parse(path: &str) -> Option<Path> {
+    if path == "t" {
+        return Some(Path::Time);
+    }
+    if let Some((k, var)) = path.strip_prefix("syn[").and_then(|p| p.split_once("].")) {
+        let weight = match var {
+            "w" => true,
+            "g" => false,
+            _ => return None,
+        };
+        return Some(Path::Synapse(k.parse().ok()?, weight));
+    }
+    let (idx, var) = path.strip_prefix("comp[")?.split_once("].")?;
+    let var = match var {
+        "v" => Var::Voltage,
+        "i_clamp" => Var::ClampCurrent,
+        "i_membrane" => Var::MembraneCurrent,
+        "hh.na_open" => Var::NaOpen,
+        "hh.k_open" => Var::KOpen,
+        _ if let Some((ion, names)) = IONS.iter().find(|(_, names)| names.contains(&var)) => {
+            match names.iter().position(|n| *n == var) {
+                Some(0) => Var::Inside(*ion),
+                Some(1) => Var::Outside(*ion),
+                _ => Var::Reversal(*ion),
+            }
+        }
+        _ => Var::Gate(
+            GATES
+                .iter()
+                .position(|g| Some(*g) == var.strip_prefix("hh."))?,
+        ),
+    };
+    Some(Path::Compartment(idx.parse().ok()?, var))
+}
+
+fn unavailable(path: &str, reason: &str) -> StateError {
+    StateError::Unavailable {
+        path: path.to_owned(),
+        reason: reason.to_owned(),
+    }
+}
+
+impl Simulation {
+    /// Parses `path` and checks its compartment exists
+    fn resolve(&self, path: &str) -> Result<Path, StateError> {
+        let Some(parsed) = parse(path) else {
+            let mut close: Vec<(usize, String)> = self
+                .list_paths("")
+                .into_iter()
+                .map(|p| (edit_distance(&p, path), p))
+                .filter(|(d, _)| *d <= 3)
+                .collect();
+            close.sort();
+            return Err(StateError::Unknown {
+                path: path.to_owned(),
+                suggestions: close
+                    .into_iter()
+                    .take(MAX_SUGGESTIONS)
+                    .map(|(_, p)| p)
+                    .collect(),
+            });
+        };
+        match parsed {
+            Path::Compartment(idx, _) if idx == 0 || idx >= self.v.len() => {
+                Err(StateError::NoSuchCompartment {
+                    path: path.to_owned(),
+                    idx,
+                })
+            }
+            Path::Synapse(k, _) if k >= self.synapses.len() => Err(unavailable(
+                path,
+                &format!("there are {} synapses", self.synapses.len()),
+            )),
+            _ => Ok(parsed),
+        }
+    }
+
+    /// The value at `path`, see the module docs for the grammar
+    pub fn get(&self, path: &str) -> Result<f64, StateError> {
+        let (idx, var) = match self.resolve(path)? {
+            Path::Time => return Ok(self.time()),
+            Path::Synapse(k, true) => return Ok(self.synapses[k].weight),
+            Path::Synapse(k, false) => return Ok(self.synapses[k].g),
+            Path::Compartment(idx, var) => (idx, var),
+        };
+        match var {
+            Var::Voltage => Ok(self.v[idx]),
+            Var::ClampCurrent => self
+                .clamp_current(idx)
+                .ok_or_else(|| unavailable(path, "the compartment is not clamped")),
+            Var::MembraneCurrent => Ok(self.membrane_currents[idx]),
+            Var::Gate(g) => match &self.membranes[idx] {
+                Membrane::HodgkinHuxley { gates, .. } => Ok(gates[g]),
+                _ => Err(unavailable(path, "no Hodgkin-Huxley membrane")),
+            },
+            Var::NaOpen | Var::KOpen => {
+                let open = self
+                    .open_channels(idx)
+                    .ok_or_else(|| unavailable(path, "gating is not stochastic"))?;
+                Ok(if var == Var::NaOpen {
+                    open.na_open
+                } else {
+                    open.k_open
+                })
+            }
+            Var::Inside(ion) | Var::Outside(ion) | Var::Reversal(ion) => {
+                let accumulation = self
+                    .accumulation()
+                    .ok_or_else(|| unavailable(path, "ions do not accumulate"))?;
+                let (Some(inside), Some(outside)) =
+                    (accumulation.inside(ion), accumulation.outside(ion))
+                else {
+                    return Err(unavailable(path, "the ion is not tracked"));
+                };
+                Ok(match var {
+                    Var::Inside(_) => inside[idx],
+                    Var::Outside(_) => outside[idx],
+                    _ => nernst(
+                        ion.valence().expect("tracked ions are specific"),
+                        inside[idx],
+                        outside[idx],
+                        accumulation.config().celsius,
+                    ),
+                })
+            }
+        }
+    }
+
+    /// Sets the voltage or a gate at `path` for the coming steps. A failed
+    /// write changes nothing.
+    pub fn set(&mut self, path: &str, value: f64) -> Result<(), StateError> {
+        let out_of_range = |min, max| StateError::OutOfRange {
+            path: path.to_owned(),
+            value,
+            min,
+            max,
+        };
+        let read_only = || StateError::ReadOnly {
+            path: path.to_owned(),
+        };
+        let (idx, var) = match self.resolve(path)? {
+            Path::Time | Path::Synapse(..) => return Err(read_only()),
+            Path::Compartment(idx, var) => (idx, var),
+        };
+        match var {
+            Var::Voltage if !value.is_finite() => {
+                return Err(out_of_range(f64::NEG_INFINITY, f64::INFINITY));
+            }
+            Var::Voltage => self.v[idx] = value,
+            Var::Gate(g) => match &mut self.membranes[idx] {
+                Membrane::HodgkinHuxley { noise: Some(_), .. } => {
+                    return Err(unavailable(
+                        path,
+                        "gating is stochastic, so the gates follow the channel states",
+                    ));
+                }
+                Membrane::HodgkinHuxley { gates, .. } => {
+                    if !(0.0..=1.0).contains(&value) {
+                        return Err(out_of_range(0.0, 1.0));
+                    }
+                    gates[g] = value;
+                }
+                _ => return Err(unavailable(path, "no Hodgkin-Huxley membrane")),
+            },
+            Var::ClampCurrent
+            | Var::MembraneCurrent
+            | Var::NaOpen
+            | Var::KOpen
+            | Var::Inside(_)
+            | Var::Outside(_)
+            | Var::Reversal(_) => return Err(read_only()),
+        }
+        if let Some(log) = &self.run_log {
+            log.record(
+                "set_state",
+                &[
+                    ("path", path.into()),
+                    ("value", value.into()),
+                    ("time", self.time().into()),
+                ],
+            );
+        }
+        Ok(())
+    }
+
+    /// Every path `get` reads in the current state that starts with
+    /// `prefix`, compartments in index order
+    pub fn list_paths(&self, prefix: &str) -> Vec<String> {
+        let mut paths = vec!["t".to_owned()];
+        for idx in 1..self.v.len() {
+            let mut vars = vec!["v", "i_membrane"];
+            if self.clamps[idx].is_some() {
+                vars.push("i_clamp");
+            }
+            if let Membrane::HodgkinHuxley { noise, .. } = &self.membranes[idx] {
+                vars.extend(["hh.m", "hh.h", "hh.n"]);
+                if noise.is_some() {
+                    vars.extend(["hh.na_open", "hh.k_open"]);
+                }
+            }
+            if let Some(accumulation) = self.accumulation() {
+                for (ion, names) in &IONS {
+                    if accumulation.inside(*ion).is_some() {
+                        vars.extend(names);
+                    }
+                }
+            }
+            paths.extend(vars.iter().map(|var| format!("comp[{}].{}", idx, var)));
+        }
+        for k in 0..self.synapses.len() {
+            paths.extend(["w", "g"].map(|var| format!("syn[{}].{}", k, var)));
+        }
+        paths.retain(|p| p.starts_with(prefix));
+        paths
+    }
+
+    /// Has `run` record the value at `path` before the first step and
+    /// after every one, see `SimulationResult::traces`. Fails, recording
+    /// nothing, unless the path reads in the current state.
+    pub fn record(&mut self, path: &str) -> Result<(), StateError> {
+        self.get(path)?;
+        self.recorded.push(path.to_owned());
+        Ok(())
+    }
+
+    /// Appends the current value of each path in `traces` to its trace
+    pub(crate) fn sample(&self, traces: &mut [(String, Vec<f64>)]) -> Result<(), StateError> {
+        for (path, trace) in traces.iter_mut() {
+            trace.push(self.get(path)?);
+        }
+        Ok(())
+    }
+}
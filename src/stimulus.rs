@@ -0,0 +1,183 @@
+//! Synaptic-like current injection waveforms, rendered for a fixed time step.
+//! Times are in ms, amplitudes in nA.
+//!
+//! Rendering does not sample the formula at each step. Each entry is the
+//! exact average current over its step, carried from step to step by the
+//! usual exponential recursion. The injected charge, `sum * dt`, is then
+//! exact whatever the step size.
+
+use std::f64::consts::E;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stimulus {
+    /// `amplitude * s / tau * exp(1 - s / tau)` for `s = t - onset >= 0`,
+    /// peaking at `amplitude` at `s = tau`
+    Alpha {
+        onset: f64,
+        tau: f64,
+        amplitude: f64,
+    },
+    /// Difference of a decaying and a rising exponential, scaled so the peak
+    /// is `amplitude`
+    BiExp {
+        onset: f64,
+        tau_rise: f64,
+        tau_decay: f64,
+        amplitude: f64,
+    },
+}
+
+impl Stimulus {
+    fn onset(&self) -> f64 {
+        match *self {
+            Stimulus::Alpha { onset, .. } | Stimulus::BiExp { onset, .. } => onset,
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        match *self {
+            Stimulus::Alpha { tau, .. } if !(tau > 0.0 && tau.is_finite()) => {
+                Err(format!("Alpha time constant must be positive, got {}", tau))
+            }
+            Stimulus::BiExp {
+                tau_rise,
+                tau_decay,
+                ..
+            } if !(tau_rise > 0.0 && tau_rise < tau_decay && tau_decay.is_finite()) => {
+                Err(format!(
+                    "Need 0 < tau_rise < tau_decay, got {} and {}",
+                    tau_rise, tau_decay
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Time after onset at which the current peaks
+    pub fn peak_time(&self) -> f64 {
+        match *self {
+            Stimulus::Alpha { tau, .. } => tau,
+            Stimulus::BiExp {
+                tau_rise,
+                tau_decay,
+                ..
+            } => tau_rise * tau_decay / (tau_decay - tau_rise) * (tau_decay / tau_rise).ln(),
+        }
+    }
+
+    /// Scale on `exp(-s/tau_decay) - exp(-s/tau_rise)` that makes the peak 1
+    fn biexp_norm(tau_rise: f64, tau_decay: f64) -> f64 {
+        let tp = tau_rise * tau_decay / (tau_decay - tau_rise) * (tau_decay / tau_rise).ln();
+        1.0 / ((-tp / tau_decay).exp() - (-tp / tau_rise).exp())
+    }
+
+    /// Current at time `t`, for plotting against the rendered steps
+    pub fn value_at(&self, t: f64) -> f64 {
+        let s = t - self.onset();
+        if s < 0.0 {
+            return 0.0;
+        }
+        match *self {
+            Stimulus::Alpha { tau, amplitude, .. } => amplitude * s / tau * (1.0 - s / tau).exp(),
+            Stimulus::BiExp {
+                tau_rise,
+                tau_decay,
+                amplitude,
+                ..
+            } => {
+                amplitude
+                    * Stimulus::biexp_norm(tau_rise, tau_decay)
+                    * ((-s / tau_decay).exp() - (-s / tau_rise).exp())
+            }
+        }
+    }
+
+    /// Total charge of one event, integrated to infinity
+    pub fn charge(&self) -> f64 {
+        match *self {
+            Stimulus::Alpha { tau, amplitude, .. } => amplitude * E * tau,
+            Stimulus::BiExp {
+                tau_rise,
+                tau_decay,
+                amplitude,
+                ..
+            } => amplitude * Stimulus::biexp_norm(tau_rise, tau_decay) * (tau_decay - tau_rise),
+        }
+    }
+
+    /// Average current over each step of `dt` from 0 up to `t_stop`, for the
+    /// single event at the stimulus' own onset
+    pub fn render(&self, dt: f64, t_stop: f64) -> Result<Vec<f64>, String> {
+        self.render_train(&[self.onset()], dt, t_stop)
+    }
+
+    /// Like `render`, with one event at each of `onsets` instead. Overlapping
+    /// events add up.
+    pub fn render_train(&self, onsets: &[f64], dt: f64, t_stop: f64) -> Result<Vec<f64>, String> {
+        self.validate()?;
+        if !(dt > 0.0 && dt.is_finite()) {
+            return Err(format!("Time step must be positive, got {}", dt));
+        }
+        let steps = (t_stop / dt).round().max(0.0) as usize;
+        let mut onsets: Vec<f64> = onsets.to_vec();
+        onsets.sort_by(f64::total_cmp);
+
+        // Each kernel is a sum of terms `c * s^p * exp(-s / tau)` with p 0 or
+        // 1. State per time constant: the sums of exp(-s/tau) and
+        // s * exp(-s/tau) over the events so far, at the current step edge.
+        let (terms, scale): (Vec<(f64, f64, f64)>, f64) = match *self {
+            // (tau, coefficient of s*exp, coefficient of exp)
+            Stimulus::Alpha { tau, amplitude, .. } => (vec![(tau, 1.0 / tau, 0.0)], amplitude * E),
+            Stimulus::BiExp {
+                tau_rise,
+                tau_decay,
+                amplitude,
+                ..
+            } => (
+                vec![(tau_decay, 0.0, 1.0), (tau_rise, 0.0, -1.0)],
+                amplitude * Stimulus::biexp_norm(tau_rise, tau_decay),
+            ),
+        };
+
+        // Integrals from 0 to h of exp(-s/tau) and s * exp(-s/tau), starting
+        // at a point where both states are (e, u)
+        let step_integral = |tau: f64, e: f64, u: f64, h: f64| {
+            let decay = (-h / tau).exp();
+            let int_e = tau * e * (1.0 - decay);
+            // s * exp(-s/tau) integrates to -tau * (s + tau) * exp(-s/tau)
+            let e_end = e * decay;
+            let u_end = (u + h * e) * decay;
+            let int_u = tau * (u + tau * e) - tau * (u_end + tau * e_end);
+            (int_e, int_u, e_end, u_end)
+        };
+
+        let mut state: Vec<(f64, f64)> = vec![(0.0, 0.0); terms.len()];
+        let mut out = Vec::with_capacity(steps);
+        let mut next = 0;
+        for k in 0..steps {
+            let (t0, t1) = (k as f64 * dt, (k + 1) as f64 * dt);
+            let mut charge = 0.0;
+            for (&(tau, cu, ce), (e, u)) in terms.iter().zip(state.iter_mut()) {
+                let (int_e, int_u, e_end, u_end) = step_integral(tau, *e, *u, dt);
+                charge += ce * int_e + cu * int_u;
+                (*e, *u) = (e_end, u_end);
+            }
+            // Events starting within this step only contribute from onset on
+            while next < onsets.len() && onsets[next] < t1 {
+                // Onsets before 0 start part way along their kernel
+                let s = (t0 - onsets[next]).max(0.0);
+                let h = t1 - onsets[next].max(t0);
+                for (&(tau, cu, ce), (e, u)) in terms.iter().zip(state.iter_mut()) {
+                    let e0 = (-s / tau).exp();
+                    let (int_e, int_u, e_end, u_end) = step_integral(tau, e0, s * e0, h);
+                    charge += ce * int_e + cu * int_u;
+                    *e += e_end;
+                    *u += u_end;
+                }
+                next += 1;
+            }
+            out.push(scale * charge / dt);
+        }
+        Ok(out)
+    }
+}
@@ -0,0 +1,295 @@
+//! Channel noise in Hodgkin-Huxley membranes.
+//!
+//! A compartment of membrane area A holds `gnabar * A / gamma_na` sodium
+//! and `gkbar * A / gamma_k` potassium channels. With
+//! `GatingMode::Binomial` each of them is tracked: every step moves a
+//! binomially drawn number of channels between the states of the Markov
+//! schemes equivalent to the m³h and n⁴ gates, and only the channels in the
+//! open state conduct. `GatingMode::DiffusionApprox` keeps the gates as
+//! continuous fractions and adds to each the noise that finitely many
+//! channels would cause (Fox, 1997), which is cheaper for large counts.
+//!
+//! Both draw from the simulation's own seeded generator, so a run is
+//! reproduced by its seed, see `Simulation::reseed`. Small compartments are
+//! noisy enough to fire spontaneously; as the area grows the mean trace
+//! approaches the deterministic one.
+
+use rand::Rng;
+use rand::rngs::StdRng;
+
+use crate::augment::normal;
+use crate::channels::HodgkinHuxley;
+
+/// How the gates of a `HodgkinHuxley` mechanism evolve
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GatingMode {
+    /// Gates are fractions following their rate equations exactly
+    #[default]
+    Deterministic,
+    /// Gates are fractions with Gaussian noise scaled by the channel counts
+    DiffusionApprox,
+    /// Whole channels change state at random
+    Binomial,
+}
+
+/// Channels of one compartment, see `Simulation::open_channels`. With
+/// `GatingMode::DiffusionApprox` the open counts are not whole numbers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OpenChannels {
+    pub na_open: f64,
+    pub na_total: f64,
+    pub k_open: f64,
+    pub k_total: f64,
+}
+
+/// Number of channels of single channel conductance `gamma`, in pS, making
+/// up a conductance density of `gbar`, in S/cm², over `area` µm²
+pub fn channel_count(gbar: f64, area: f64, gamma: f64) -> u64 {
+    // S/cm² over µm² is 1e-8 S, a pS is 1e-12 S
+    (gbar * area * 1e4 / gamma).round().max(0.0) as u64
+}
+
+/// Stochastic state of one compartment's sodium and potassium channels
+#[derive(Debug, Clone)]
+pub(crate) struct ChannelNoise {
+    mode: GatingMode,
+    n_na: u64,
+    n_k: u64,
+    /// Sodium channels by state, indexed by open m subunits plus 4 if the h
+    /// subunit is open, so index 7 conducts. Binomial mode only.
+    na: [u64; 8],
+    /// Potassium channels by open n subunits, index 4 conducts. Binomial
+    /// mode only.
+    k: [u64; 5],
+}
+
+impl ChannelNoise {
+    /// None for deterministic gating. Binomial states are drawn around
+    /// `gates`.
+    pub(crate) fn new(
+        hh: &HodgkinHuxley,
+        area: f64,
+        gates: &[f64; 3],
+        rng: &mut StdRng,
+    ) -> Option<ChannelNoise> {
+        if hh.gating == GatingMode::Deterministic {
+            return None;
+        }
+        let mut noise = ChannelNoise {
+            mode: hh.gating,
+            n_na: channel_count(hh.gnabar, area, hh.gamma_na),
+            n_k: channel_count(hh.gkbar, area, hh.gamma_k),
+            na: [0; 8],
+            k: [0; 5],
+        };
+        noise.settle(gates, rng);
+        Some(noise)
+    }
+
+    /// Redraws the channel states as if `gates` were the probabilities of
+    /// each subunit being open
+    pub(crate) fn settle(&mut self, gates: &[f64; 3], rng: &mut StdRng) {
+        if self.mode != GatingMode::Binomial {
+            return;
+        }
+        let [m, h, n] = *gates;
+        let na_probabilities: [f64; 8] = std::array::from_fn(|s| {
+            let (i, open_h) = (s % 4, s >= 4);
+            subunits_open(3, i, m) * if open_h { h } else { 1.0 - h }
+        });
+        self.na = multinomial(self.n_na, &na_probabilities, rng);
+        self.k = multinomial(
+            self.n_k,
+            &std::array::from_fn(|i| subunits_open(4, i, n)),
+            rng,
+        );
+    }
+
+    /// Advances the channels by `dt` at a voltage held at `v`. In binomial
+    /// mode `gates` follow the fractions of open subunits.
+    pub(crate) fn step(&mut self, gates: &mut [f64; 3], v: f64, dt: f64, rng: &mut StdRng) {
+        let rates = HodgkinHuxley::rates(v);
+        match self.mode {
+            GatingMode::Binomial => {
+                let [(am, bm), (ah, bh), (an, bn)] = rates;
+                jump(
+                    &mut self.na,
+                    |s| {
+                        let i = s % 4;
+                        [
+                            (s + 1, if i < 3 { (3 - i) as f64 * am } else { 0.0 }),
+                            (s.wrapping_sub(1), i as f64 * bm),
+                            if s < 4 { (s + 4, ah) } else { (s - 4, bh) },
+                        ]
+                    },
+                    dt,
+                    rng,
+                );
+                jump(
+                    &mut self.k,
+                    |i| {
+                        [
+                            (i + 1, if i < 4 { (4 - i) as f64 * an } else { 0.0 }),
+                            (i.wrapping_sub(1), i as f64 * bn),
+                            (i, 0.0),
+                        ]
+                    },
+                    dt,
+                    rng,
+                );
+                let fraction = |open: u64, total: u64| {
+                    if total == 0 {
+                        0.0
+                    } else {
+                        open as f64 / total as f64
+                    }
+                };
+                let m_open: u64 = self
+                    .na
+                    .iter()
+                    .enumerate()
+                    .map(|(s, c)| (s % 4) as u64 * c)
+                    .sum();
+                let h_open: u64 = self.na[4..].iter().sum();
+                let n_open: u64 = self.k.iter().enumerate().map(|(i, c)| i as u64 * c).sum();
+                *gates = [
+                    fraction(m_open, 3 * self.n_na),
+                    fraction(h_open, self.n_na),
+                    fraction(n_open, 4 * self.n_k),
+                ];
+            }
+            _ => {
+                let counts = [self.n_na, self.n_na, self.n_k];
+                for ((x, (a, b)), count) in gates.iter_mut().zip(rates).zip(counts) {
+                    let x_inf = a / (a + b);
+                    let old = *x;
+                    *x = x_inf + (old - x_inf) * (-(a + b) * dt).exp();
+                    if count > 0 {
+                        let variance = (a * (1.0 - old) + b * old) * dt / count as f64;
+                        *x = (*x + variance.max(0.0).sqrt() * normal(rng)).clamp(0.0, 1.0);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Open sodium and potassium channels with gates `gates`
+    pub(crate) fn open(&self, gates: &[f64; 3]) -> OpenChannels {
+        let [m, h, n] = *gates;
+        let (na_open, k_open) = match self.mode {
+            GatingMode::Binomial => (self.na[7] as f64, self.k[4] as f64),
+            _ => (
+                self.n_na as f64 * m.powi(3) * h,
+                self.n_k as f64 * n.powi(4),
+            ),
+        };
+        OpenChannels {
+            na_open,
+            na_total: self.n_na as f64,
+            k_open,
+            k_total: self.n_k as f64,
+        }
+    }
+}
+
+/// Probability that exactly `open` of `subunits` independent subunits are
+/// open, each with probability `p`
+fn subunits_open(subunits: u32, open: usize, p: f64) -> f64 {
+    let open = open as u32;
+    if open > subunits {
+        return 0.0;
+    }
+    let ways = (0..open).fold(1.0, |w, k| w * (subunits - k) as f64 / (k + 1) as f64);
+    ways * p.powi(open as i32) * (1.0 - p).powi((subunits - open) as i32)
+}
+
+/// Moves channels out of every state with probability `1 - exp(-r dt)` for
+/// its total exit rate `r`, and splits those leaving between the
+/// destinations by rate. `exits(s)` lists `(destination, rate)` of state
+/// `s`; entries with zero rate are ignored. Totals are conserved.
+fn jump<const S: usize>(
+    counts: &mut [u64; S],
+    exits: impl Fn(usize) -> [(usize, f64); 3],
+    dt: f64,
+    rng: &mut StdRng,
+) {
+    let mut next = *counts;
+    for (s, &count) in counts.iter().enumerate() {
+        let out = exits(s);
+        let total: f64 = out.iter().map(|&(_, r)| r).sum();
+        if count == 0 || total <= 0.0 {
+            continue;
+        }
+        let mut leaving = binomial(count, 1.0 - (-total * dt).exp(), rng);
+        next[s] -= leaving;
+        let last = out.iter().rposition(|&(_, r)| r > 0.0).unwrap_or(0);
+        let mut rest = total;
+        for (k, &(dest, rate)) in out.iter().enumerate() {
+            if leaving == 0 {
+                break;
+            }
+            if rate <= 0.0 {
+                continue;
+            }
+            let moved = if k == last {
+                leaving
+            } else {
+                binomial(leaving, rate / rest, rng)
+            };
+            next[dest] += moved;
+            leaving -= moved;
+            rest -= rate;
+        }
+    }
+    *counts = next;
+}
+
+/// `n` items put into bins with probabilities `p`, which are normalized
+fn multinomial<const S: usize>(n: u64, p: &[f64; S], rng: &mut StdRng) -> [u64; S] {
+    let mut counts = [0; S];
+    let mut left = n;
+    let mut rest: f64 = p.iter().sum();
+    for (count, &p) in counts.iter_mut().zip(p) {
+        if left == 0 || rest <= 0.0 {
+            break;
+        }
+        *count = binomial(left, p / rest, rng);
+        left -= *count;
+        rest -= p;
+    }
+    if let Some(last) = p.iter().rposition(|&p| p > 0.0) {
+        counts[last] += left;
+    }
+    counts
+}
+
+/// Successes in `n` trials of probability `p`: exact for small means, a
+/// rounded and clamped normal approximation beyond
+pub(crate) fn binomial(n: u64, p: f64, rng: &mut StdRng) -> u64 {
+    if n == 0 || p <= 0.0 {
+        return 0;
+    }
+    if p >= 1.0 {
+        return n;
+    }
+    if p > 0.5 {
+        return n - binomial(n, 1.0 - p, rng);
+    }
+    let mean = n as f64 * p;
+    if mean < 25.0 {
+        // Skip over geometrically distributed runs of failures
+        let log_q = (-p).ln_1p();
+        let mut count = 0;
+        let mut position = 0.0;
+        loop {
+            let u: f64 = 1.0 - rng.random::<f64>();
+            position += (u.ln() / log_q).floor() + 1.0;
+            if position > n as f64 {
+                return count;
+            }
+            count += 1;
+        }
+    }
+    let sd = (mean * (1.0 - p)).sqrt();
+    (mean + sd * normal(rng)).round().clamp(0.0, n as f64) as u64
+}
@@ -0,0 +1,596 @@
+//! An append-only store of simulation results in one file, so a sweep's
+//! thousands of runs do not become thousands of files.
+//!
+//! Each run is one record: a short metadata section with its tag and
+//! parameters, then the result itself, gzipped. `ResultStore::open` walks
+//! the record headers and metadata, skipping the payloads, to build the
+//! index `query` searches, and `load` seeks to one record and reads just
+//! that. A `RunId` is the offset of its record, so it stays valid across
+//! opens.
+//!
+//! Records go to the end of the file in one write under a lock, so
+//! threads and processes appending at once do not interleave. A writer
+//! that dies mid-record leaves it torn; the next `open` cuts it off and
+//! keeps every complete record before it. Runs appended by another
+//! process show up on the next `open`.
+//!
+//! Layout, little-endian:
+//!
+//! ```text
+//! header   MAGIC
+//! record   RECORD_MAGIC, metadata length u32, payload length u64,
+//!          CRC-32 of metadata and payload u32, metadata, payload
+//! ```
+//!
+//! The metadata is text: the tag on the first line, then one
+//! `name=value` line per parameter.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+
+use crate::channels::Ion;
+use crate::codes::Code;
+use crate::energy::EnergyReport;
+use crate::manifest::Manifest;
+use crate::recording::Snippet;
+use crate::solver::{SimError, SimulationResult};
+use crate::sweep::ParamSet;
+
+const MAGIC: &[u8; 8] = b"CRSSTOR1";
+const RECORD_MAGIC: &[u8; 4] = b"RUN1";
+/// Magic, metadata length, payload length and CRC
+const RECORD_HEADER: u64 = 4 + 4 + 8 + 4;
+/// Ions by their code in a payload
+const IONS: [Ion; 5] = [Ion::Na, Ion::K, Ion::Ca, Ion::Cl, Ion::NonSpecific];
+
+/// One run in a store, by the offset of its record
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RunId(pub u64);
+
+impl fmt::Display for RunId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// What the index holds about a run
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunInfo {
+    pub id: RunId,
+    pub tag: String,
+    pub params: ParamSet,
+}
+
+/// Why a store could not be opened, written or read
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum StoreError {
+    /// Reading, writing or locking the file failed
+    Io { path: PathBuf, message: String },
+    /// The file does not start like a result store
+    NotAStore { path: PathBuf },
+    /// A record fails its checksum or does not decode
+    Corrupt { path: PathBuf, reason: String },
+    /// No run by that id in the store
+    UnknownRun { path: PathBuf, id: RunId },
+    /// A tag or parameter name that cannot be stored
+    InvalidEntry { reason: String },
+    /// Loading the traces of a streamed result failed
+    Simulation(SimError),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Io { path, message } => write!(f, "{}: {}", path.display(), message),
+            StoreError::NotAStore { path } => {
+                write!(f, "{}: not a result store", path.display())
+            }
+            StoreError::Corrupt { path, reason } => write!(f, "{}: {}", path.display(), reason),
+            StoreError::UnknownRun { path, id } => {
+                write!(f, "{}: no run {}", path.display(), id)
+            }
+            StoreError::InvalidEntry { reason } => write!(f, "{}", reason),
+            StoreError::Simulation(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<SimError> for StoreError {
+    fn from(e: SimError) -> Self {
+        StoreError::Simulation(e)
+    }
+}
+
+impl StoreError {
+    pub fn code(&self) -> Code {
+        match self {
+            StoreError::Io { .. } => Code::StoreIo,
+            StoreError::NotAStore { .. } => Code::NotAStore,
+            StoreError::Corrupt { .. } => Code::CorruptStore,
+            StoreError::UnknownRun { .. } => Code::UnknownRun,
+            StoreError::InvalidEntry { .. } => Code::InvalidStoreEntry,
+            StoreError::Simulation(e) => e.code(),
+        }
+    }
+}
+
+/// Where a run's record sits
+#[derive(Debug, Clone)]
+struct Entry {
+    info: RunInfo,
+    metadata: u32,
+    length: u64,
+    crc: u32,
+}
+
+struct Inner {
+    file: File,
+    entries: Vec<Entry>,
+}
+
+pub struct ResultStore {
+    path: PathBuf,
+    inner: Mutex<Inner>,
+    recovered: u64,
+}
+
+fn crc32(parts: &[&[u8]]) -> u32 {
+    let mut crc = flate2::Crc::new();
+    for part in parts {
+        crc.update(part);
+    }
+    crc.sum()
+}
+
+fn io_error(path: &Path, e: std::io::Error) -> StoreError {
+    StoreError::Io {
+        path: path.to_owned(),
+        message: e.to_string(),
+    }
+}
+
+impl ResultStore {
+    /// Opens the store at `path`, creating it if there is none, and cuts
+    /// off a record torn by a crash
+    pub fn open(path: impl AsRef<Path>) -> Result<ResultStore, StoreError> {
+        let path = path.as_ref();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(|e| io_error(path, e))?;
+        file.lock().map_err(|e| io_error(path, e))?;
+        let size = file.metadata().map_err(|e| io_error(path, e))?.len();
+        if size == 0 {
+            file.write_all(MAGIC).map_err(|e| io_error(path, e))?;
+        } else {
+            let mut magic = [0; 8];
+            file.seek(SeekFrom::Start(0))
+                .and_then(|_| file.read_exact(&mut magic))
+                .map_err(|_| StoreError::NotAStore {
+                    path: path.to_owned(),
+                })?;
+            if &magic != MAGIC {
+                return Err(StoreError::NotAStore {
+                    path: path.to_owned(),
+                });
+            }
+        }
+        let (entries, end) =
+            walk_records(&mut file, size.max(MAGIC.len() as u64)).map_err(|e| io_error(path, e))?;
+        let recovered = size.saturating_sub(end);
+        if recovered > 0 {
+            log::warn!(
+                "{} ends in a torn record, cut off {} bytes",
+                path.display(),
+                recovered
+            );
+            file.set_len(end)
+                .and_then(|_| file.sync_all())
+                .map_err(|e| io_error(path, e))?;
+        }
+        file.unlock().map_err(|e| io_error(path, e))?;
+        Ok(ResultStore {
+            path: path.to_owned(),
+            inner: Mutex::new(Inner { file, entries }),
+            recovered,
+        })
+    }
+
+    /// Bytes of a torn record cut off by `open`, 0 if there was none
+    pub fn recovered_bytes(&self) -> u64 {
+        self.recovered
+    }
+
+    /// Adds `result` under `tag` and `params` and returns its id. Tags and
+    /// parameter names cannot hold line breaks, nor names `=`.
+    pub fn append(
+        &self,
+        tag: &str,
+        params: &ParamSet,
+        result: &SimulationResult,
+    ) -> Result<RunId, StoreError> {
+        if tag.contains('\n') {
+            return Err(StoreError::InvalidEntry {
+                reason: format!("Tag {:?} holds a line break", tag),
+            });
+        }
+        let mut metadata = format!("{}\n", tag);
+        for (name, value) in params.names.iter().zip(&params.values) {
+            if name.is_empty() || name.contains(['\n', '=']) {
+                return Err(StoreError::InvalidEntry {
+                    reason: format!("Parameter name {:?} cannot be stored", name),
+                });
+            }
+            metadata.push_str(&format!("{}={}\n", name, value));
+        }
+        // A streamed run goes in with its traces
+        let loaded;
+        let result = match result.stream {
+            Some(_) => {
+                loaded = result.load()?;
+                &loaded
+            }
+            None => result,
+        };
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder
+            .write_all(&encode(result))
+            .map_err(|e| io_error(&self.path, e))?;
+        let payload = encoder.finish().map_err(|e| io_error(&self.path, e))?;
+        let crc = crc32(&[metadata.as_bytes(), &payload]);
+        let mut record =
+            Vec::with_capacity(RECORD_HEADER as usize + metadata.len() + payload.len());
+        record.extend_from_slice(RECORD_MAGIC);
+        record.extend_from_slice(&(metadata.len() as u32).to_le_bytes());
+        record.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+        record.extend_from_slice(&crc.to_le_bytes());
+        record.extend_from_slice(metadata.as_bytes());
+        record.extend_from_slice(&payload);
+
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let file = &mut inner.file;
+        file.lock().map_err(|e| io_error(&self.path, e))?;
+        // One write at the end under the lock, so a crash leaves at most
+        // this record torn
+        let written = file
+            .seek(SeekFrom::End(0))
+            .and_then(|offset| file.write_all(&record).map(|_| offset));
+        let unlocked = file.unlock();
+        let offset = written.map_err(|e| io_error(&self.path, e))?;
+        unlocked.map_err(|e| io_error(&self.path, e))?;
+        let id = RunId(offset);
+        inner.entries.push(Entry {
+            info: RunInfo {
+                id,
+                tag: tag.to_owned(),
+                params: params.clone(),
+            },
+            metadata: metadata.len() as u32,
+            length: payload.len() as u64,
+            crc,
+        });
+        Ok(id)
+    }
+
+    /// Every run, in the order they were appended
+    pub fn runs(&self) -> Vec<RunInfo> {
+        let inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        inner.entries.iter().map(|e| e.info.clone()).collect()
+    }
+
+    /// The runs `filter` accepts, in the order they were appended
+    pub fn query(&self, filter: impl Fn(&RunInfo) -> bool) -> Vec<RunId> {
+        let inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        inner
+            .entries
+            .iter()
+            .filter(|e| filter(&e.info))
+            .map(|e| e.info.id)
+            .collect()
+    }
+
+    /// Runs tagged `tag`
+    pub fn with_tag(&self, tag: &str) -> Vec<RunId> {
+        self.query(|run| run.tag == tag)
+    }
+
+    /// Reads run `id`, checking its record's checksum
+    pub fn load(&self, id: RunId) -> Result<SimulationResult, StoreError> {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = inner
+            .entries
+            .iter()
+            .find(|e| e.info.id == id)
+            .cloned()
+            .ok_or_else(|| StoreError::UnknownRun {
+                path: self.path.clone(),
+                id,
+            })?;
+        let mut record = vec![0; entry.metadata as usize + entry.length as usize];
+        inner
+            .file
+            .seek(SeekFrom::Start(id.0 + RECORD_HEADER))
+            .and_then(|_| inner.file.read_exact(&mut record))
+            .map_err(|e| io_error(&self.path, e))?;
+        drop(inner);
+        if crc32(&[&record]) != entry.crc {
+            return Err(StoreError::Corrupt {
+                path: self.path.clone(),
+                reason: format!("checksum mismatch in run {}", id),
+            });
+        }
+        let mut decoded = Vec::new();
+        GzDecoder::new(&record[entry.metadata as usize..])
+            .read_to_end(&mut decoded)
+            .map_err(|e| io_error(&self.path, e))?;
+        decode(&decoded).map_err(|e| StoreError::Corrupt {
+            path: self.path.clone(),
+            reason: format!("run {}: {}", id, e),
+        })
+    }
+}
+
+/// Every complete record after the header and where the last one ends
+fn walk_records(file: &mut File, size: u64) -> std::io::Result<(Vec<Entry>, u64)> {
+    let mut entries = Vec::new();
+    let mut offset = MAGIC.len() as u64;
+    let mut header = [0; RECORD_HEADER as usize];
+    while offset + RECORD_HEADER <= size {
+        file.seek(SeekFrom::Start(offset))
+            .and_then(|_| file.read_exact(&mut header))?;
+        if &header[..4] != RECORD_MAGIC {
+            break;
+        }
+        let metadata = u32::from_le_bytes(header[4..8].try_into().unwrap());
+        let length = u64::from_le_bytes(header[8..16].try_into().unwrap());
+        let crc = u32::from_le_bytes(header[16..].try_into().unwrap());
+        let end = match (offset + RECORD_HEADER)
+            .checked_add(metadata as u64)
+            .and_then(|e| e.checked_add(length))
+        {
+            Some(end) if end <= size => end,
+            _ => break,
+        };
+        let mut text = vec![0; metadata as usize];
+        file.read_exact(&mut text)?;
+        let Some((tag, params)) = parse_metadata(&text) else {
+            break;
+        };
+        entries.push(Entry {
+            info: RunInfo {
+                id: RunId(offset),
+                tag,
+                params,
+            },
+            metadata,
+            length,
+            crc,
+        });
+        offset = end;
+    }
+    Ok((entries, offset))
+}
+
+fn parse_metadata(text: &[u8]) -> Option<(String, ParamSet)> {
+    let text = std::str::from_utf8(text).ok()?;
+    let mut lines = text.lines();
+    let tag = lines.next()?.to_owned();
+    let mut params = ParamSet {
+        names: Vec::new(),
+        values: Vec::new(),
+    };
+    for line in lines {
+        let (name, value) = line.split_once('=')?;
+        params.names.push(name.to_owned());
+        params.values.push(value.parse().ok()?);
+    }
+    Some((tag, params))
+}
+
+fn put_u64(out: &mut Vec<u8>, v: u64) {
+    out.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_f64s(out: &mut Vec<u8>, values: &[f64]) {
+    put_u64(out, values.len() as u64);
+    for v in values {
+        out.extend_from_slice(&v.to_le_bytes());
+    }
+}
+
+fn put_str(out: &mut Vec<u8>, s: &str) {
+    put_u64(out, s.len() as u64);
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn put_charges(out: &mut Vec<u8>, charges: &BTreeMap<Ion, f64>) {
+    put_u64(out, charges.len() as u64);
+    for (ion, q) in charges {
+        out.push(IONS.iter().position(|i| i == ion).expect("every ion") as u8);
+        out.extend_from_slice(&q.to_le_bytes());
+    }
+}
+
+/// `result` as bytes, every field in order
+fn encode(result: &SimulationResult) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&result.dt.to_le_bytes());
+    put_str(&mut out, &result.manifest.to_text());
+    for traces in [&result.voltages, &result.head_voltages] {
+        put_u64(&mut out, traces.len() as u64);
+        for trace in traces {
+            put_f64s(&mut out, trace);
+        }
+    }
+    put_u64(&mut out, result.traces.len() as u64);
+    for (path, trace) in &result.traces {
+        put_str(&mut out, path);
+        put_f64s(&mut out, trace);
+    }
+    put_u64(&mut out, result.axial_currents.len() as u64);
+    for ((parent, child), trace) in &result.axial_currents {
+        put_u64(&mut out, *parent as u64);
+        put_u64(&mut out, *child as u64);
+        put_f64s(&mut out, trace);
+    }
+    put_u64(&mut out, result.snippets.len() as u64);
+    for (path, snippets) in &result.snippets {
+        put_str(&mut out, path);
+        put_u64(&mut out, snippets.len() as u64);
+        for s in snippets {
+            out.extend_from_slice(&s.event_time.to_le_bytes());
+            put_f64s(&mut out, &s.merged);
+            out.extend_from_slice(&s.start_time.to_le_bytes());
+            put_f64s(&mut out, &s.samples);
+        }
+    }
+    match &result.energy {
+        None => out.push(0),
+        Some(report) => {
+            out.push(1);
+            put_u64(&mut out, report.charge.len() as u64);
+            for charges in &report.charge {
+                put_charges(&mut out, charges);
+            }
+            put_f64s(&mut out, &report.energy);
+            put_charges(&mut out, &report.total_charge);
+            out.extend_from_slice(&report.total_energy.to_le_bytes());
+        }
+    }
+    out
+}
+
+/// Reads back the output of `encode`
+struct Decoder<'a> {
+    bytes: &'a [u8],
+}
+
+impl Decoder<'_> {
+    fn take(&mut self, n: usize) -> Result<&[u8], String> {
+        if n > self.bytes.len() {
+            return Err("payload is cut short".to_owned());
+        }
+        let (head, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn len(&mut self) -> Result<usize, String> {
+        let n = self.u64()?;
+        // Every element takes at least a byte
+        usize::try_from(n)
+            .ok()
+            .filter(|&n| n <= self.bytes.len())
+            .ok_or_else(|| "payload is cut short".to_owned())
+    }
+
+    fn f64(&mut self) -> Result<f64, String> {
+        Ok(f64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn f64s(&mut self) -> Result<Vec<f64>, String> {
+        let n = self.len()?;
+        (0..n).map(|_| self.f64()).collect()
+    }
+
+    fn str(&mut self) -> Result<String, String> {
+        let n = self.len()?;
+        String::from_utf8(self.take(n)?.to_vec()).map_err(|e| e.to_string())
+    }
+
+    fn charges(&mut self) -> Result<BTreeMap<Ion, f64>, String> {
+        let n = self.len()?;
+        (0..n)
+            .map(|_| {
+                let ion = *IONS
+                    .get(self.u8()? as usize)
+                    .ok_or_else(|| "unknown ion".to_owned())?;
+                Ok((ion, self.f64()?))
+            })
+            .collect()
+    }
+}
+
+fn decode(bytes: &[u8]) -> Result<SimulationResult, String> {
+    let mut d = Decoder { bytes };
+    let dt = d.f64()?;
+    let manifest = Manifest::parse(&d.str()?)?;
+    let mut traces = || -> Result<Vec<Vec<f64>>, String> {
+        let n = d.len()?;
+        (0..n).map(|_| d.f64s()).collect()
+    };
+    let voltages = traces()?;
+    let head_voltages = traces()?;
+    let n = d.len()?;
+    let traces = (0..n)
+        .map(|_| Ok((d.str()?, d.f64s()?)))
+        .collect::<Result<_, String>>()?;
+    let n = d.len()?;
+    let axial_currents = (0..n)
+        .map(|_| Ok(((d.u64()? as usize, d.u64()? as usize), d.f64s()?)))
+        .collect::<Result<_, String>>()?;
+    let n = d.len()?;
+    let snippets = (0..n)
+        .map(|_| {
+            let path = d.str()?;
+            let m = d.len()?;
+            let found = (0..m)
+                .map(|_| {
+                    Ok(Snippet {
+                        event_time: d.f64()?,
+                        merged: d.f64s()?,
+                        start_time: d.f64()?,
+                        samples: d.f64s()?,
+                    })
+                })
+                .collect::<Result<_, String>>()?;
+            Ok((path, found))
+        })
+        .collect::<Result<_, String>>()?;
+    let energy = match d.u8()? {
+        0 => None,
+        _ => {
+            let n = d.len()?;
+            let charge = (0..n).map(|_| d.charges()).collect::<Result<_, _>>()?;
+            Some(EnergyReport {
+                charge,
+                energy: d.f64s()?,
+                total_charge: d.charges()?,
+                total_energy: d.f64()?,
+            })
+        }
+    };
+    Ok(SimulationResult {
+        dt,
+        voltages,
+        head_voltages,
+        energy,
+        traces,
+        axial_currents,
+        snippets,
+        manifest,
+        stream: None,
+    })
+}
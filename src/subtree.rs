@@ -0,0 +1,270 @@
+//! Re-simulating one subtree against the voltage a full-cell run recorded
+//! where it was cut off.
+//!
+//! `SubtreeSimulation::from_full_run` copies the cut compartment and
+//! everything distal to it into a model of its own, where the cut comes
+//! first. Each run clamps the cut to the recorded voltage, step by step, so
+//! parameters inside the subtree can be changed and re-simulated at the cost
+//! of the subtree alone.
+//!
+//! This holds as long as the changes do not feed back into the voltage at
+//! the cut. `validity` measures that: the current the clamp supplies is the
+//! current the rest of the cell would have had to supply, and it is compared
+//! with the current that crossed the cut in the full run.
+
+use crate::compartments::Compartments;
+use crate::index_map::IndexMap;
+use crate::sections::build_sections;
+use crate::solver::{RESTING_POTENTIAL, Simulation, SimulationResult};
+use crate::spines::Spine;
+
+/// Outcome of `SubtreeSimulation::run`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubtreeRun {
+    /// Per compartment of the reduced model, the voltage at the start and
+    /// after every step
+    pub voltages: Vec<Vec<f64>>,
+    /// Current from the rest of the cell into the cut over every step, in nA
+    pub boundary_current: Vec<f64>,
+}
+
+pub struct SubtreeSimulation {
+    /// The cut at index 1 and its descendants after it, in their original
+    /// order. Edit parameters here before calling `run`.
+    pub compartments: Compartments,
+    /// Index in the full model of every compartment here
+    full_idx: Vec<usize>,
+    dt: f64,
+    /// Recorded voltage at the cut, `steps + 1` values
+    boundary: Vec<f64>,
+    /// Recorded voltage of every compartment here at the start
+    initial: Vec<f64>,
+    /// Recorded head voltage of every spine here at the start
+    initial_heads: Vec<f64>,
+    /// Current that crossed the cut in the full run, in nA
+    full_boundary_current: Vec<f64>,
+}
+
+impl SubtreeSimulation {
+    /// Cuts compartment `cut_idx` and its descendants out of `compartments`,
+    /// the model `result` was simulated with. The run is taken to have
+    /// started with its gates settled at the recorded initial voltages.
+    pub fn from_full_run(
+        result: &SimulationResult,
+        compartments: &Compartments,
+        cut_idx: usize,
+    ) -> Result<SubtreeSimulation, String> {
+        let components = &compartments.components;
+        if result.voltages.len() != components.len() {
+            return Err(format!(
+                "Run has {} compartments, the model {}",
+                result.voltages.len(),
+                components.len()
+            ));
+        }
+        let parent = match components.get(cut_idx).map(|c| &c.parent_idxs[..]) {
+            Some(&[p]) if p != 0 => p as usize,
+            Some(_) => return Err(format!("Compartment {} has no parent to cut from", cut_idx)),
+            None => return Err(format!("No compartment at index {}", cut_idx)),
+        };
+
+        // Parents come before children, so one pass finds the descendants
+        let mut inside = vec![false; components.len()];
+        inside[cut_idx] = true;
+        let mut full_idx = vec![0, cut_idx];
+        for (i, c) in components.iter().enumerate().skip(cut_idx + 1) {
+            if c.parent_idxs.iter().any(|&p| inside[p as usize]) {
+                inside[i] = true;
+                full_idx.push(i);
+            }
+        }
+        if let Some(&i) = full_idx[1..]
+            .iter()
+            .chain([&parent])
+            .find(|&&i| result.voltages[i].is_empty())
+        {
+            return Err(format!("Run did not record compartment {}", i));
+        }
+        let mut reduced_idx = vec![0; components.len()];
+        for (new, &old) in full_idx.iter().enumerate() {
+            reduced_idx[old] = new;
+        }
+
+        let mut reduced = vec![components[0].clone()];
+        reduced[0].children_idxs = vec![1];
+        for (new, &old) in full_idx.iter().enumerate().skip(1) {
+            let mut c = components[old].clone();
+            c.idx = new as u64;
+            c.parent_idxs = if new == 1 {
+                vec![0]
+            } else {
+                c.parent_idxs
+                    .iter()
+                    .map(|&p| reduced_idx[p as usize] as u64)
+                    .collect()
+            };
+            c.children_idxs = c
+                .children_idxs
+                .iter()
+                .map(|&ch| reduced_idx[ch as usize] as u64)
+                .collect();
+            reduced.push(c);
+        }
+
+        let spines: Vec<(usize, &Spine)> = compartments
+            .spines
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| inside[s.idx])
+            .collect();
+        let g = compartments
+            .axial_conductances()
+            .into_iter()
+            .find(|&(p, c, _)| (p, c) == (parent, cut_idx))
+            .map(|(_, _, g)| g)
+            .ok_or_else(|| format!("Compartment {} has no axial coupling", cut_idx))?;
+        let (vp, vc) = (&result.voltages[parent], &result.voltages[cut_idx]);
+        // Over each step, at the voltages it ended with
+        let full_boundary_current = vp
+            .iter()
+            .zip(vc)
+            .skip(1)
+            .map(|(vp, vc)| 100.0 * g * (vp - vc))
+            .collect();
+
+        Ok(SubtreeSimulation {
+            compartments: Compartments {
+                sections: build_sections(&reduced),
+                index_map: IndexMap::identity(reduced.len()),
+                provenance: full_idx
+                    .iter()
+                    .map(|&i| compartments.provenance[i].clone())
+                    .collect(),
+                components: reduced,
+                cell_id: None,
+                run_log: None,
+                attachments: Vec::new(),
+                spines: spines
+                    .iter()
+                    .map(|&(_, s)| Spine {
+                        idx: reduced_idx[s.idx],
+                        ..s.clone()
+                    })
+                    .collect(),
+                last_index_map: None,
+                ion_reversals: compartments.ion_reversals.clone(),
+            },
+            initial: full_idx.iter().map(|&i| result.voltages[i][0]).collect(),
+            initial_heads: spines
+                .iter()
+                .map(|&(k, _)| {
+                    result
+                        .head_voltages
+                        .get(k)
+                        .map_or(RESTING_POTENTIAL, |h| h[0])
+                })
+                .collect(),
+            full_idx,
+            dt: result.dt,
+            boundary: vc.clone(),
+            full_boundary_current,
+        })
+    }
+
+    /// Number of steps the full run took, and every run here takes
+    pub fn steps(&self) -> usize {
+        self.boundary.len() - 1
+    }
+
+    /// Index in the full model of reduced compartment `idx`
+    pub fn full_index(&self, idx: usize) -> Option<usize> {
+        self.full_idx.get(idx).copied().filter(|&i| i != 0)
+    }
+
+    /// Index here of compartment `full_idx` of the full model, None if it
+    /// lies outside the subtree
+    pub fn reduced_index(&self, full_idx: usize) -> Option<usize> {
+        self.full_idx
+            .iter()
+            .skip(1)
+            .position(|&i| i == full_idx)
+            .map(|i| i + 1)
+    }
+
+    /// Current that crossed the cut in the full run over every step, in nA
+    pub fn full_boundary_current(&self) -> &[f64] {
+        &self.full_boundary_current
+    }
+
+    /// Simulates the subtree with the cut following the recorded voltage.
+    /// `stimuli` are given as for `Simulation::run`, by full-model index;
+    /// those outside the subtree are left out, as their effect already is in
+    /// the recorded voltage.
+    pub fn run(&self, stimuli: &[(usize, Vec<f64>)]) -> Result<SubtreeRun, String> {
+        let stimuli: Vec<(usize, Vec<f64>)> = stimuli
+            .iter()
+            .filter_map(|(i, w)| Some((self.reduced_index(*i)?, w.clone())))
+            .collect();
+        let mut simulation =
+            Simulation::new(&self.compartments, self.dt).map_err(|e| e.to_string())?;
+        for (idx, &v) in self.initial.iter().enumerate().skip(1) {
+            simulation.set_voltage(idx, v).map_err(|e| e.to_string())?;
+        }
+        for (k, &v) in self.initial_heads.iter().enumerate() {
+            simulation
+                .set_spine_voltage(k, v)
+                .map_err(|e| e.to_string())?;
+        }
+        let steps = self.steps();
+        let mut voltages: Vec<Vec<f64>> = simulation.voltages().iter().map(|&v| vec![v]).collect();
+        let mut boundary_current = Vec::with_capacity(steps);
+        for (idx, waveform) in &stimuli {
+            if waveform.len() != steps {
+                return Err(format!(
+                    "Stimulus for compartment {} has {} values for {} steps",
+                    self.full_idx[*idx],
+                    waveform.len(),
+                    steps
+                ));
+            }
+        }
+        for s in 0..steps {
+            for (idx, waveform) in &stimuli {
+                simulation
+                    .inject(*idx, waveform[s])
+                    .map_err(|e| e.to_string())?;
+            }
+            simulation
+                .clamp(1, Some(self.boundary[s + 1]))
+                .map_err(|e| e.to_string())?;
+            simulation.step().map_err(|e| e.to_string())?;
+            boundary_current.push(simulation.clamp_current(1).unwrap_or(0.0));
+            for (trace, &v) in voltages.iter_mut().zip(simulation.voltages()) {
+                trace.push(v);
+            }
+        }
+        Ok(SubtreeRun {
+            voltages,
+            boundary_current,
+        })
+    }
+
+    /// Root mean square difference between the current `run` drew through
+    /// the cut and the current the full run had there, relative to the root
+    /// mean square of the latter. Near 0 the recorded boundary still holds;
+    /// from around 0.1 the changes reach back past the cut and a full run is
+    /// needed.
+    pub fn validity(&self, run: &SubtreeRun) -> f64 {
+        let rms = |x: &mut dyn Iterator<Item = f64>| {
+            let (sum, n) = x.fold((0.0, 0), |(s, n), x| (s + x * x, n + 1));
+            if n == 0 { 0.0 } else { (sum / n as f64).sqrt() }
+        };
+        let error = rms(&mut run
+            .boundary_current
+            .iter()
+            .zip(&self.full_boundary_current)
+            .map(|(a, b)| a - b));
+        let scale = rms(&mut self.full_boundary_current.iter().copied());
+        if error == 0.0 { 0.0 } else { error / scale }
+    }
+}
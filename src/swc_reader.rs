@@ -1,15 +1,27 @@
-use itertools::Itertools;
+use flate2::bufread::GzDecoder;
 use log::{info, warn};
-use std::collections::HashMap;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
 use std::collections::{HashSet, VecDeque};
-use std::fs;
 use std::fs::File;
 use std::hash::{Hash, Hasher};
 use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::codes::Code;
+use crate::error::{Limit, SwcError};
+use crate::metadata::SwcMetadata;
+use crate::run_log::{LogValue, RunLog};
+use crate::warnings::{SwcWarning, WarningCollector, WarningKind};
+use crate::write;
 
 /// We use the CNIC spec, as per: http://www.neuronland.org/NLMorphologyConverter/MorphologyFormats/SWC/Spec.html
 #[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Copy, Clone)]
-pub(crate) enum StructureIdentifier {
+#[non_exhaustive]
+pub enum StructureIdentifier {
     Undefined,
     Soma,
     Axon,
@@ -35,8 +47,39 @@ impl From<u8> for StructureIdentifier {
     }
 }
 
-#[derive(Copy, Clone)]
-pub(crate) struct Node {
+bitflags::bitflags! {
+    /// What, if anything, was repaired or made up about a node. Carried through
+    /// remapping so repaired regions can be excluded from analysis later.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+    pub struct NodeFlags: u32 {
+        const ZERO_RADIUS_FIXED = 1;
+        const RADIUS_OUTLIER_FIXED = 1 << 1;
+        const COORD_INTERPOLATED = 1 << 2;
+        const TYPE_INFERRED = 1 << 3;
+        const SOMA_MERGED = 1 << 4;
+        const ORPHAN_REATTACHED = 1 << 5;
+        /// Coordinates moved by `augment::jitter_coordinates`
+        const COORD_JITTERED = 1 << 6;
+        /// Radius changed by `augment::scale_radii`
+        const RADIUS_SCALED = 1 << 7;
+        /// A terminal branch below this node was removed by
+        /// `augment::drop_terminal_branches`
+        const BRANCH_PRUNED = 1 << 8;
+        /// Active mechanisms swapped for passive ones by
+        /// `Compartments::apply_thin_neurite_policy`
+        const MECHANISM_PASSIVATED = 1 << 9;
+        /// Absorbed thinner-than-allowed children, see
+        /// `Compartments::apply_thin_neurite_policy`
+        const THIN_MERGED = 1 << 10;
+        /// Stands in for nodes dropped just proximal to it by
+        /// `simplify::simplify_electrotonic`
+        const ELECTROTONIC_MERGED = 1 << 11;
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+#[non_exhaustive]
+pub struct Node {
     pub node_id: u64,
     pub structured_identifier: StructureIdentifier,
     pub x_pos: f64,
@@ -44,6 +87,30 @@ pub(crate) struct Node {
     pub z_pos: f64,
     pub radius: f64,
     pub parent_id: u64,
+    pub flags: NodeFlags,
+}
+
+impl Node {
+    pub fn new(
+        node_id: u64,
+        structured_identifier: StructureIdentifier,
+        x_pos: f64,
+        y_pos: f64,
+        z_pos: f64,
+        radius: f64,
+        parent_id: u64,
+    ) -> Self {
+        Node {
+            node_id,
+            structured_identifier,
+            x_pos,
+            y_pos,
+            z_pos,
+            radius,
+            parent_id,
+            flags: NodeFlags::empty(),
+        }
+    }
 }
 
 impl Eq for Node {}
@@ -59,11 +126,220 @@ impl Hash for Node {
     }
 }
 
+/// What happens when the file being written already exists
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConflictPolicy {
+    #[default]
+    Overwrite,
+    /// Leave the existing file alone
+    Skip,
+    /// Fail with an `SwcError::Io` of kind `AlreadyExists`
+    Error,
+}
+
+/// Knobs for `swc_reader`.
+#[derive(Debug, Clone)]
+pub struct ReaderOptions {
+    /// Emit warnings for zero-radius points
+    pub emit_warnings: bool,
+    /// Also report individual warnings, up to `warning_cap` of them, before the
+    /// per-kind summaries
+    pub verbose_warnings: bool,
+    pub warning_cap: usize,
+    /// Terminate on the first warning instead of carrying on
+    pub strict: bool,
+    /// Accept `1,5` as 1.5. Off by default, in which case such values are
+    /// rejected instead of being misread.
+    pub decimal_comma: bool,
+    /// If given, the processed and sorted file is written here. Missing
+    /// directories are created and the file appears atomically.
+    pub write_path: Option<PathBuf>,
+    /// What to do when `write_path` already exists
+    pub write_conflict: ConflictPolicy,
+    /// Write extra columns back out after the standard seven
+    pub write_extras: bool,
+    /// Multiply coordinates by the header's `SCALE` factors, if it has one.
+    /// Radii are left alone.
+    pub apply_scale: bool,
+    /// Lowercase hex sha256 the raw input (before any decompression) must
+    /// match. Checked before parsing starts.
+    pub expected_sha256: Option<String>,
+    /// Count nodes per structure type into `Skeleton::stats` and log the
+    /// breakdown. Costs a pass over the nodes; turn off for bulk reading.
+    pub collect_stats: bool,
+    /// Stop with `SwcError::LimitExceeded` once more nodes than this are read
+    pub max_nodes: Option<usize>,
+    /// Stop with `SwcError::LimitExceeded` once more bytes than this are
+    /// read. Counted after decompression, so gzip bombs are caught too.
+    pub max_file_bytes: Option<u64>,
+    /// Stop with `SwcError::LimitExceeded` once parsing has taken longer
+    /// than this. Checked every few thousand lines.
+    pub timeout: Option<Duration>,
+    /// Stop with `Code::NotSwc` if this many data lines fail to parse before
+    /// any succeeds. A first line that fails on its own is reported as
+    /// usual. 0 turns the check off.
+    pub format_probe_lines: usize,
+    /// Record every read in this log, see `RunLog`
+    pub run_log: Option<RunLog>,
+}
+
+impl Default for ReaderOptions {
+    fn default() -> Self {
+        ReaderOptions {
+            emit_warnings: true,
+            verbose_warnings: false,
+            warning_cap: 20,
+            strict: false,
+            decimal_comma: false,
+            write_path: None,
+            write_conflict: ConflictPolicy::Overwrite,
+            write_extras: false,
+            apply_scale: false,
+            expected_sha256: None,
+            collect_stats: true,
+            max_nodes: None,
+            max_file_bytes: None,
+            timeout: None,
+            format_probe_lines: 5,
+            run_log: None,
+        }
+    }
+}
+
+/// The processed, topologically sorted skeleton. Node IDs are sequential from
+/// 0 with the root pointing at itself.
+///
+/// A skeleton is not changed in place. The node list, both maps and the
+/// extras are shared columns: a clone, or a transformed copy such as
+/// `smoothed`, shares every column it leaves alone and copies the ones it
+/// changes. Edits in place go through `edit`.
+#[derive(Debug, Clone)]
+pub struct Skeleton {
+    pub nodes: Arc<Vec<Node>>,
+    /// Map forward from the soma -> dendrites
+    pub parent_child_map: Arc<HashMap<u64, Vec<u64>>>,
+    /// Map backward from dendrites -> Soma
+    pub child_parent_map: Arc<HashMap<u64, Vec<u64>>>,
+    /// Warnings raised while reading, aggregated per kind
+    pub warnings: Vec<SwcWarning>,
+    /// Values of any columns past the standard seven, by node ID. Nodes
+    /// without extra columns have no entry.
+    pub extras: Arc<HashMap<u64, Vec<f64>>>,
+    /// Names of the extra columns, if the header declared them with a
+    /// `# columns:` comment
+    pub extra_columns: Vec<String>,
+    /// Standardized `# KEY value` header entries
+    pub metadata: SwcMetadata,
+    /// Per-type node counts from reading, if `ReaderOptions::collect_stats`
+    /// was set
+    pub stats: Option<ReadStats>,
+}
+
+/// Node counts gathered while reading, keyed by structure type
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReadStats {
+    pub type_counts: BTreeMap<StructureIdentifier, usize>,
+    /// Nodes whose zero radius was replaced by 1.0
+    pub zero_radius_fixed: BTreeMap<StructureIdentifier, usize>,
+}
+
+impl ReadStats {
+    fn of(nodes: &[Node]) -> ReadStats {
+        let mut stats = ReadStats::default();
+        for node in nodes {
+            *stats
+                .type_counts
+                .entry(node.structured_identifier)
+                .or_default() += 1;
+            if node.flags.contains(NodeFlags::ZERO_RADIUS_FIXED) {
+                *stats
+                    .zero_radius_fixed
+                    .entry(node.structured_identifier)
+                    .or_default() += 1;
+            }
+        }
+        stats
+    }
+
+    fn log(&self) {
+        if !self.zero_radius_fixed.is_empty() {
+            info!(
+                "SWC Label Convention: 0=undefined, 1=soma, 2=axon, 3=basal dendrite, 4=apical dendrite, 5=fork, 6=end"
+            );
+            info!(
+                "Fixed zero-radius points by type: {:?}",
+                self.zero_radius_fixed
+            );
+        }
+        info!("Node type breakdown: {:?}", self.type_counts);
+    }
+}
+
+impl Skeleton {
+    /// Builds a skeleton from column arrays instead of a file, running the same
+    /// validation and remapping as `swc_reader`. Parent IDs use -1 for the root,
+    /// exactly like the SWC column.
+    pub fn from_arrays(
+        node_ids: &[i64],
+        types: &[u8],
+        xyz: &[[f64; 3]],
+        radii: &[f64],
+        parent_ids: &[i64],
+        options: &ReaderOptions,
+    ) -> Result<Skeleton, SwcError> {
+        let n = node_ids.len();
+        for (name, len) in [
+            ("types", types.len()),
+            ("xyz", xyz.len()),
+            ("radii", radii.len()),
+            ("parent_ids", parent_ids.len()),
+        ] {
+            if len != n {
+                return Err(SwcError::invalid(
+                    Code::LengthMismatch,
+                    format!(
+                        "Length mismatch: node_ids has {} entries but {} has {}",
+                        n, name, len
+                    ),
+                ));
+            }
+        }
+
+        let nodes_vec = (0..n)
+            .map(|i| {
+                let (node_id, parent_id) = checked_ids(node_ids[i], parent_ids[i], "index", i)?;
+                Ok(Node {
+                    node_id,
+                    structured_identifier: types[i].into(),
+                    x_pos: xyz[i][0],
+                    y_pos: xyz[i][1],
+                    z_pos: xyz[i][2],
+                    radius: radii[i],
+                    parent_id,
+                    flags: NodeFlags::empty(),
+                })
+            })
+            .collect::<Result<Vec<Node>, SwcError>>()?;
+
+        let indices: Vec<usize> = (0..n).collect();
+        process_nodes(
+            nodes_vec,
+            Vec::new(),
+            Vec::new(),
+            SwcMetadata::default(),
+            &indices,
+            "index",
+            options,
+        )
+    }
+}
+
 /// Reads in swc from `read_path` and returns the generated compartment skeleton
 ///   If a `write_path` is given, we spit out the processed, sorted, file there,
 ///   with the comments at the start stripped out
 /// Optionally emits warnings for:
 ///   - zero-radius points
+///
 /// Strict mode:
 ///   - if any of the above warnings are hit, we terminate immediately
 ///
@@ -71,65 +347,539 @@ impl Hash for Node {
 /// For Flywire.ai skeletons, seems they only mark out:
 /// # 0 = undefined, 1 = soma, 5 = fork point, 6 = end point
 pub fn swc_reader(
-    read_path: String,
-    emit_warnings: Option<bool>,
-    strict: Option<bool>,
-    write_path: Option<String>,
-) -> Result<(Vec<Node>, HashMap<u64, Vec<u64>>, HashMap<u64, Vec<u64>>), String> {
-    let f = File::open(read_path).unwrap(); //.map_err(|x| format!("No such read path"));
-
-    let lines: Vec<String> = BufReader::new(f)
-        .lines()
-        .filter_map(|line| line.ok())
-        .filter(|line| !line.starts_with('#'))
-        .collect();
+    read_path: impl AsRef<Path>,
+    options: &ReaderOptions,
+) -> Result<Skeleton, SwcError> {
+    let read_path = read_path.as_ref();
+    let result = File::open(read_path)
+        .map_err(|e| SwcError::io(read_path, e))
+        .and_then(|f| read_buf(BufReader::new(f), options));
+    log_read(options, &read_path.display().to_string(), &result);
+    result
+}
 
-    let nodes_vec: Vec<Node> = lines
+/// Same as `swc_reader`, for SWC data already in memory, e.g. streamed from
+/// cloud storage. Gzipped data is detected and decompressed.
+pub fn swc_reader_from_bytes(data: &[u8], options: &ReaderOptions) -> Result<Skeleton, SwcError> {
+    let result = read_bytes(data, options);
+    log_read(options, "<bytes>", &result);
+    result
+}
+
+fn read_bytes(data: &[u8], options: &ReaderOptions) -> Result<Skeleton, SwcError> {
+    if let Some(expected) = &options.expected_sha256 {
+        let actual: String = Sha256::digest(data)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        if actual != expected.to_lowercase() {
+            return Err(SwcError::ChecksumMismatch {
+                expected: expected.clone(),
+                actual,
+            });
+        }
+    }
+    parse_swc(data, options)
+}
+
+/// Same as `swc_reader`, for any buffered source. Gzipped data is detected
+/// and decompressed. With `expected_sha256` set, the whole input is buffered
+/// so it can be verified before parsing.
+pub fn swc_reader_from_buf(
+    reader: impl BufRead,
+    options: &ReaderOptions,
+) -> Result<Skeleton, SwcError> {
+    let result = read_buf(reader, options);
+    log_read(options, "<stream>", &result);
+    result
+}
+
+fn read_buf(mut reader: impl BufRead, options: &ReaderOptions) -> Result<Skeleton, SwcError> {
+    if options.expected_sha256.is_some() {
+        let mut data = Vec::new();
+        reader
+            .read_to_end(&mut data)
+            .map_err(|e| read_failure(format!("Could not read SWC data: {}", e)))?;
+        return read_bytes(&data, options);
+    }
+    parse_swc(reader, options)
+}
+
+/// Records a read in the options' run log, if there is one. The options
+/// hash lets two reads be checked for identical settings at a glance.
+fn log_read(options: &ReaderOptions, source: &str, result: &Result<Skeleton, SwcError>) {
+    let Some(log) = &options.run_log else {
+        return;
+    };
+    let settings = format!(
+        "{:?}",
+        ReaderOptions {
+            run_log: None,
+            ..options.clone()
+        }
+    );
+    let hash: String = Sha256::digest(settings.as_bytes())[..8]
         .iter()
-        .map(|line| {
-            let mut v = line.split_whitespace();
-            let node_id = v.next().unwrap().parse::<u64>().unwrap();
-            let structured_identifier: StructureIdentifier =
-                v.next().unwrap().parse::<u8>().unwrap().into();
-
-            // Parse parent_id: -1 in file becomes 0 (temporary, will be self-referencing for root)
-            let parent_id_raw = v.next().unwrap().parse::<i64>().unwrap();
-            let parent_id = if parent_id_raw == -1 { 0 } else { parent_id_raw as u64 };
-            let node = Node {
-                node_id,
-                structured_identifier,
-                x_pos: v.next().unwrap().parse::<f64>().unwrap(),
-                y_pos: v.next().unwrap().parse::<f64>().unwrap(),
-                z_pos: v.next().unwrap().parse::<f64>().unwrap(),
-                radius: v.next().unwrap().parse::<f64>().unwrap(),
-                parent_id,
-            };
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    let mut args: Vec<(&str, LogValue)> =
+        vec![("source", source.into()), ("options_hash", hash.into())];
+    match result {
+        Ok(skeleton) => {
+            let repaired: usize = skeleton.warnings.iter().map(|w| w.count).sum();
+            args.push(("outcome", "ok".into()));
+            args.push(("nodes", skeleton.nodes.len().into()));
+            args.push(("repairs", repaired.into()));
+            args.push(("cell_id", skeleton.cell_id().to_string().into()));
+        }
+        Err(e) => {
+            args.push(("outcome", e.code().id().into()));
+            args.push(("message", e.to_string().into()));
+        }
+    }
+    log.record("read", &args);
+}
 
-            if node.radius == 0.0 && emit_warnings.unwrap_or(true) {
-                warn!(
-                    "Zero-radius for section ID: {} of type: {:?}",
-                    node_id, structured_identifier
-                );
-                if structured_identifier != StructureIdentifier::EndPoint && strict.unwrap_or(false)
-                {
-                    return Err("Zero-radius for non-endpoint");
+fn read_failure(message: String) -> SwcError {
+    SwcError::invalid(Code::ReadFailure, message)
+}
+
+fn parse_swc(mut reader: impl BufRead, options: &ReaderOptions) -> Result<Skeleton, SwcError> {
+    const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+    let head = reader
+        .fill_buf()
+        .map_err(|e| read_failure(format!("Could not read SWC data: {}", e)))?;
+    let reader: Box<dyn BufRead + '_> = if head.starts_with(&GZIP_MAGIC) {
+        Box::new(BufReader::new(GzDecoder::new(reader)))
+    } else {
+        Box::new(reader)
+    };
+
+    // One byte over the limit is enough to know it was exceeded, and stops a
+    // file without line breaks from being read whole
+    let byte_limit = options.max_file_bytes;
+    let mut reader = std::io::Read::take(
+        reader,
+        byte_limit.map_or(u64::MAX, |limit| limit.saturating_add(1)),
+    );
+    let started = Instant::now();
+
+    // Keep the 1-based line number of each data line around for error messages
+    let mut line_numbers = Vec::new();
+    let mut nodes_vec = Vec::new();
+    let mut extras = Vec::new();
+    let mut extra_columns = Vec::new();
+    let mut metadata = SwcMetadata::default();
+    // Data lines that failed before any parsed, and the first such failure
+    let mut probe_failures = 0;
+    let mut first_failure = None;
+    let mut bytes_read: u64 = 0;
+    let mut buf = Vec::new();
+    for i in 0.. {
+        buf.clear();
+        let n = reader.read_until(b'\n', &mut buf).map_err(|e| {
+            SwcError::invalid_at(
+                Code::ReadFailure,
+                i + 1,
+                format!("Could not read line {}: {}", i + 1, e),
+            )
+        })?;
+        if n == 0 {
+            break;
+        }
+        bytes_read += n as u64;
+        if let Some(limit) = byte_limit.filter(|&limit| bytes_read > limit) {
+            return Err(SwcError::LimitExceeded {
+                which: Limit::FileBytes,
+                limit,
+                observed: bytes_read,
+            });
+        }
+        if let Some(timeout) = options.timeout.filter(|_| i % 4096 == 0) {
+            let elapsed = started.elapsed();
+            if elapsed > timeout {
+                return Err(SwcError::LimitExceeded {
+                    which: Limit::Timeout,
+                    limit: timeout.as_millis() as u64,
+                    observed: elapsed.as_millis() as u64,
+                });
+            }
+        }
+
+        let parsed = std::str::from_utf8(&buf)
+            .map_err(|e| {
+                SwcError::invalid_at(
+                    Code::ReadFailure,
+                    i + 1,
+                    format!("Could not read line {}: {}", i + 1, e),
+                )
+            })
+            .and_then(|line| {
+                let line = line.strip_suffix('\n').unwrap_or(line);
+                let line = line.strip_suffix('\r').unwrap_or(line);
+                if let Some(comment) = line.strip_prefix('#') {
+                    // `# columns: id type x y z radius parent confidence ...`
+                    // names the extra columns
+                    if let Some(names) = comment.trim_start().strip_prefix("columns:") {
+                        extra_columns = names
+                            .split_whitespace()
+                            .skip(7)
+                            .map(str::to_owned)
+                            .collect();
+                    } else if nodes_vec.is_empty() {
+                        // Metadata only lives in the header
+                        metadata.parse_comment(comment, i + 1)?;
+                    }
+                    return Ok(None);
+                }
+                // Data lines may carry a trailing comment
+                let data = line.split('#').next().unwrap_or_default();
+                if data.trim().is_empty() {
+                    return Ok(None);
+                }
+                parse_line(data, i + 1, options).map(Some)
+            });
+        match parsed {
+            Ok(None) => {}
+            Ok(Some((node, node_extras))) => {
+                if let Some(err) = first_failure {
+                    return Err(err);
+                }
+                line_numbers.push(i + 1);
+                nodes_vec.push(node);
+                extras.push(node_extras);
+                if let Some(limit) = options.max_nodes.filter(|&limit| nodes_vec.len() > limit) {
+                    return Err(SwcError::LimitExceeded {
+                        which: Limit::Nodes,
+                        limit: limit as u64,
+                        observed: nodes_vec.len() as u64,
+                    });
                 }
             }
-            Ok(node)
+            Err(err) if nodes_vec.is_empty() && options.format_probe_lines > 0 => {
+                probe_failures += 1;
+                first_failure.get_or_insert(err);
+                if probe_failures >= options.format_probe_lines {
+                    return Err(SwcError::invalid(
+                        Code::NotSwc,
+                        format!(
+                            "None of the first {} data lines parse; the input may not be an SWC file",
+                            probe_failures
+                        ),
+                    ));
+                }
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    if let Some(err) = first_failure {
+        return Err(err);
+    }
+
+    if let (Some([sx, sy, sz]), true) = (metadata.scale, options.apply_scale) {
+        for node in nodes_vec.iter_mut() {
+            node.x_pos *= sx;
+            node.y_pos *= sy;
+            node.z_pos *= sz;
+        }
+    }
+
+    process_nodes(
+        nodes_vec,
+        extras,
+        extra_columns,
+        metadata,
+        &line_numbers,
+        "line",
+        options,
+    )
+}
+
+/// Parses one SWC data line, `id type x y z radius parent`, plus any extra
+/// numeric columns after that
+pub(crate) fn parse_line(
+    line: &str,
+    line_no: usize,
+    options: &ReaderOptions,
+) -> Result<(Node, Vec<f64>), SwcError> {
+    let mut v = line.split_whitespace();
+    let node_id: i64 = parse_field(v.next(), "node ID", line_no)?;
+    let structured_identifier: StructureIdentifier =
+        parse_field::<u8>(v.next(), "structure type", line_no)?.into();
+
+    let x_pos = parse_float(v.next(), "x", line_no, options.decimal_comma)?;
+    let y_pos = parse_float(v.next(), "y", line_no, options.decimal_comma)?;
+    let z_pos = parse_float(v.next(), "z", line_no, options.decimal_comma)?;
+    let radius = parse_float(v.next(), "radius", line_no, options.decimal_comma)?;
+
+    let parent_id: i64 = parse_field(v.next(), "parent ID", line_no)?;
+    let (node_id, parent_id) = checked_ids(node_id, parent_id, "line", line_no)?;
+    let extras = v
+        .enumerate()
+        .map(|(k, raw)| {
+            parse_float(
+                Some(raw),
+                &format!("extra column {}", k),
+                line_no,
+                options.decimal_comma,
+            )
         })
-        .collect::<Result<Vec<Node>, _>>()?;
+        .collect::<Result<Vec<f64>, SwcError>>()?;
+    let node = Node::new(
+        node_id,
+        structured_identifier,
+        x_pos,
+        y_pos,
+        z_pos,
+        radius,
+        parent_id,
+    );
+    Ok((node, extras))
+}
+
+/// Checks a node's ID and parent ID as given, the same for every input, and
+/// remaps a parent of -1 to the root marker 0. Node 0 would collide with
+/// that marker, so IDs start at 1. `unit` and `position` say where the node
+/// came from, as in `process_nodes`.
+fn checked_ids(
+    node_id: i64,
+    parent_id: i64,
+    unit: &str,
+    position: usize,
+) -> Result<(u64, u64), SwcError> {
+    let invalid = |code: Code, message: String| match unit {
+        "line" => SwcError::invalid_at(code, position, message),
+        _ => SwcError::invalid(code, message),
+    };
+    if node_id < 1 {
+        return Err(invalid(
+            Code::InvalidNodeId,
+            format!(
+                "Node ID must be positive, got {} at {} {}",
+                node_id, unit, position
+            ),
+        ));
+    }
+    let parent_id = match parent_id {
+        -1 => 0,
+        p if p < 1 => {
+            return Err(invalid(
+                Code::InvalidParentId,
+                format!("Invalid parent ID {} at {} {}", p, unit, position),
+            ));
+        }
+        p => p as u64,
+    };
+    Ok((node_id as u64, parent_id))
+}
+
+fn parse_field<T: FromStr>(field: Option<&str>, name: &str, line_no: usize) -> Result<T, SwcError> {
+    let raw = field.ok_or_else(|| {
+        SwcError::invalid_at(
+            Code::MissingField,
+            line_no,
+            format!("Missing {} at line {}", name, line_no),
+        )
+    })?;
+    raw.parse::<T>().map_err(|_| {
+        SwcError::invalid_at(
+            Code::InvalidField,
+            line_no,
+            format!("Invalid {} '{}' at line {}", name, raw, line_no),
+        )
+    })
+}
+
+/// Floats are parsed the same way regardless of system locale. Decimal commas
+/// are only accepted when asked for, since silently misreading them is worse
+/// than failing. `nan` and `inf` parse as floats but are rejected all the same.
+fn parse_float(
+    field: Option<&str>,
+    name: &str,
+    line_no: usize,
+    decimal_comma: bool,
+) -> Result<f64, SwcError> {
+    let value: f64 = match field {
+        Some(raw) if raw.contains(',') => {
+            if !decimal_comma {
+                return Err(SwcError::invalid_at(
+                    Code::DecimalComma,
+                    line_no,
+                    format!(
+                        "Decimal comma in {} '{}' at line {}; set decimal_comma to accept it",
+                        name, raw, line_no
+                    ),
+                ));
+            }
+            parse_field(Some(&raw.replace(',', ".")), name, line_no)?
+        }
+        _ => parse_field(field, name, line_no)?,
+    };
+    if !value.is_finite() {
+        return Err(SwcError::invalid_at(
+            Code::InvalidField,
+            line_no,
+            format!(
+                "Invalid {} '{}' at line {}",
+                name,
+                field.unwrap_or_default(),
+                line_no
+            ),
+        ));
+    }
+    Ok(value)
+}
+
+/// Shortest representation that reads back to the exact same float. Unlike
+/// `{:.2}` this is lossless for tiny values and byte-identical everywhere.
+pub(crate) fn format_float(v: f64) -> String {
+    if v == 0.0 {
+        // Don't write out -0
+        return "0.0".to_owned();
+    }
+    ryu::Buffer::new().format(v).to_owned()
+}
+
+/// Renders a skeleton as SWC text, numbered from 1 as SWC expects and with
+/// the root's parent written as -1, so it reads back as the same tree. Header
+/// metadata is written back out, leaving out `SCALE` if it was already
+/// applied, and extra columns are only written if asked for.
+pub(crate) fn to_swc_string(skeleton: &Skeleton, options: &ReaderOptions) -> String {
+    let mut output = String::new();
+    output.push_str("# Processed SWC file\n");
+    output.push_str(&skeleton.metadata.to_header(!options.apply_scale));
+    if options.write_extras && !skeleton.extra_columns.is_empty() {
+        output.push_str(&format!(
+            "# columns: id type x y z radius parent {}\n",
+            skeleton.extra_columns.join(" ")
+        ));
+    }
+
+    for node in skeleton.nodes.iter() {
+        // Root node (self-referencing) should be written as -1
+        let parent_id = if node.parent_id == node.node_id {
+            -1i64
+        } else {
+            node.parent_id as i64 + 1
+        };
+
+        output.push_str(&format!(
+            "{} {} {} {} {} {} {}",
+            node.node_id + 1,
+            match node.structured_identifier {
+                StructureIdentifier::Undefined => 0,
+                StructureIdentifier::Soma => 1,
+                StructureIdentifier::Axon => 2,
+                StructureIdentifier::BasalDendrite => 3,
+                StructureIdentifier::ApicalDendrite => 4,
+                StructureIdentifier::ForkPoint => 5,
+                StructureIdentifier::EndPoint => 6,
+                StructureIdentifier::Custom => 7,
+            },
+            format_float(node.x_pos),
+            format_float(node.y_pos),
+            format_float(node.z_pos),
+            format_float(node.radius),
+            parent_id
+        ));
+        let extras = skeleton
+            .extras
+            .get(&node.node_id)
+            .filter(|_| options.write_extras);
+        for v in extras.into_iter().flatten() {
+            output.push(' ');
+            output.push_str(&format_float(*v));
+        }
+        output.push('\n');
+    }
+    output
+}
 
-    // Quick debug logs for the count of the types
-    let accum_types: HashMap<StructureIdentifier, usize> = nodes_vec
+/// Everything after parsing: validation, topological sort, ID remapping and the
+/// optional write out. `positions[i]` is where the i-th input node came from
+/// (a line number or an array index, as named by `unit`), for messages.
+pub(crate) fn process_nodes(
+    nodes_vec: Vec<Node>,
+    extras_vec: Vec<Vec<f64>>,
+    extra_columns: Vec<String>,
+    metadata: SwcMetadata,
+    positions: &[usize],
+    unit: &str,
+    options: &ReaderOptions,
+) -> Result<Skeleton, SwcError> {
+    // Only file input has lines to point at
+    let invalid_at_node = |i: usize, code: Code, message: String| match unit {
+        "line" => SwcError::invalid_at(code, positions[i], message),
+        _ => SwcError::invalid(code, message),
+    };
+    let mut warnings = WarningCollector::new(options.verbose_warnings, options.warning_cap);
+    for (i, node) in nodes_vec.iter().enumerate() {
+        if node.radius == 0.0 && options.emit_warnings {
+            warnings.record(
+                WarningKind::ZeroRadius,
+                node.structured_identifier,
+                positions[i],
+            );
+            if node.structured_identifier != StructureIdentifier::EndPoint && options.strict {
+                return Err(invalid_at_node(
+                    i,
+                    Code::StrictZeroRadius,
+                    format!(
+                        "Zero-radius for non-endpoint node {} at {} {}",
+                        node.node_id, unit, positions[i]
+                    ),
+                ));
+            }
+        }
+    }
+
+    let mut known_ids: HashSet<u64> = HashSet::new();
+    for (i, node) in nodes_vec.iter().enumerate() {
+        if !known_ids.insert(node.node_id) {
+            return Err(invalid_at_node(
+                i,
+                Code::DuplicateNodeId,
+                format!(
+                    "Duplicate node ID {} at {} {}",
+                    node.node_id, unit, positions[i]
+                ),
+            ));
+        }
+        if node.parent_id == node.node_id {
+            return Err(invalid_at_node(
+                i,
+                Code::InvalidParentId,
+                format!(
+                    "Node {} is its own parent at {} {}",
+                    node.node_id, unit, positions[i]
+                ),
+            ));
+        }
+    }
+
+    // Every parent has to exist, otherwise the node silently falls off the tree
+    if let Some(i) = nodes_vec
         .iter()
-        .map(|&node| node.structured_identifier)
-        .counts();
-    for el in accum_types {
-        info!("{:?} - #{:?}", el.0, el.1);
+        .position(|n| n.parent_id != 0 && !known_ids.contains(&n.parent_id))
+    {
+        return Err(invalid_at_node(
+            i,
+            Code::DanglingParent,
+            format!(
+                "Unknown parent ID {} for node {} at {} {}",
+                nodes_vec[i].parent_id, nodes_vec[i].node_id, unit, positions[i]
+            ),
+        ));
     }
 
     // Create lookup map: node_id -> Node
     let nodes_by_id: HashMap<u64, Node> = nodes_vec.iter().map(|n| (n.node_id, *n)).collect();
+    let mut extras_by_id: HashMap<u64, Vec<f64>> = nodes_vec
+        .iter()
+        .zip(extras_vec)
+        .filter(|(_, e)| !e.is_empty())
+        .map(|(n, e)| (n.node_id, e))
+        .collect();
 
     ////////////////////////
     // BFS traversal for topological order
@@ -144,7 +894,7 @@ pub fn swc_reader(
     let root = nodes_vec
         .iter()
         .find(|n| n.parent_id == 0)
-        .ok_or("No root node found (parent_id == 0)")?;
+        .ok_or_else(|| SwcError::invalid(Code::NoRoot, "No root node found (parent_id == 0)"))?;
 
     let mut sorted_node_ids: Vec<u64> = Vec::new();
     let mut queue: VecDeque<u64> = VecDeque::new();
@@ -169,16 +919,26 @@ pub fn swc_reader(
         }
     }
 
+    // Whatever the walk did not reach is left out, but not silently
+    if options.emit_warnings && sorted_node_ids.len() < nodes_vec.len() {
+        for (i, node) in nodes_vec.iter().enumerate() {
+            if !visited.contains(&node.node_id) {
+                warnings.record(
+                    WarningKind::Unreachable,
+                    node.structured_identifier,
+                    positions[i],
+                );
+            }
+        }
+    }
+
     // Create old_id -> new_id mapping (sequential starting at 0)
     let mut old_to_new_id: HashMap<u64, u64> = HashMap::new();
     for (new_id, old_id) in sorted_node_ids.iter().enumerate() {
         old_to_new_id.insert(*old_id, new_id as u64);
     }
 
-    // Track statistics
-    let mut zero_radius_count: HashMap<String, usize> = HashMap::new();
-    let mut label_breakdown: HashMap<String, usize> = HashMap::new();
-
+    let mut extras: HashMap<u64, Vec<f64>> = HashMap::new();
     // Map forward from the soma -> dendrites
     let mut parent_child_map: HashMap<u64, Vec<u64>> = HashMap::new();
     // Map backward from dendrites -> Soma
@@ -191,6 +951,9 @@ pub fn swc_reader(
             let new_id = old_to_new_id[old_id];
 
             node.node_id = new_id;
+            if let Some(e) = extras_by_id.remove(old_id) {
+                extras.insert(new_id, e);
+            }
 
             // Remap parent ID: root node becomes self-referencing
             node.parent_id = if node.parent_id == 0 {
@@ -200,77 +963,53 @@ pub fn swc_reader(
             };
 
             // Fix radius if needed
-            let type_str = format!("{:?}", node.structured_identifier);
             if node.radius == 0.0 {
-                *zero_radius_count.entry(type_str.clone()).or_insert(0) += 1;
                 node.radius = 1.0;
+                node.flags |= NodeFlags::ZERO_RADIUS_FIXED;
             }
 
-            // Track label statistics
-            *label_breakdown.entry(type_str).or_insert(0) += 1;
-
             // parent_child_map.insert(node.parent_id, node.node_id);
             parent_child_map
                 .entry(node.parent_id)
-                .or_insert_with(Vec::new)
+                .or_default()
                 .push(node.node_id);
             child_parent_map
                 .entry(node.node_id)
-                .or_insert_with(Vec::new)
+                .or_default()
                 .push(node.parent_id);
 
             node
         })
         .collect();
 
-    // Write to file if requested
-    if let Some(output_path) = write_path {
-        let mut output = String::new();
-        output.push_str("# Processed SWC file\n");
-
-        for node in &remapped_nodes {
-            // Root node (self-referencing) should be written as -1
-            let parent_id = if node.parent_id == node.node_id {
-                -1i64
-            } else {
-                node.parent_id as i64
-            };
-
-            output.push_str(&format!(
-                "{} {} {:.2} {:.2} {:.2} {} {}\n",
-                node.node_id,
-                match node.structured_identifier {
-                    StructureIdentifier::Undefined => 0,
-                    StructureIdentifier::Soma => 1,
-                    StructureIdentifier::Axon => 2,
-                    StructureIdentifier::BasalDendrite => 3,
-                    StructureIdentifier::ApicalDendrite => 4,
-                    StructureIdentifier::ForkPoint => 5,
-                    StructureIdentifier::EndPoint => 6,
-                    StructureIdentifier::Custom => 7,
-                },
-                node.x_pos,
-                node.y_pos,
-                node.z_pos,
-                node.radius,
-                parent_id
-            ));
-        }
-
-        fs::write(output_path, output).unwrap();
-    }
-
     // Log summary
     info!("Processed {} nodes", remapped_nodes.len());
 
-    if !zero_radius_count.is_empty() {
-        info!(
-            "SWC Label Convention: 0=undefined, 1=soma, 2=axon, 3=basal dendrite, 4=apical dendrite, 5=fork, 6=end"
-        );
-        info!("Fixed zero-radius points by type: {:?}", zero_radius_count);
-    }
+    let stats = options.collect_stats.then(|| {
+        let stats = ReadStats::of(&remapped_nodes);
+        stats.log();
+        stats
+    });
+
+    let skeleton = Skeleton {
+        nodes: Arc::new(remapped_nodes),
+        parent_child_map: Arc::new(parent_child_map),
+        child_parent_map: Arc::new(child_parent_map),
+        warnings: warnings.finish(),
+        extras: Arc::new(extras),
+        extra_columns,
+        metadata,
+        stats,
+    };
 
-    info!("Node type breakdown: {:?}", label_breakdown);
+    // Write to file if requested
+    if let Some(output_path) = &options.write_path {
+        write::write_atomic(
+            output_path,
+            to_swc_string(&skeleton, options).as_bytes(),
+            options.write_conflict,
+        )?;
+    }
 
-    Ok((remapped_nodes, parent_child_map, child_parent_map))
+    Ok(skeleton)
 }
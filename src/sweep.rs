@@ -0,0 +1,407 @@
+//! Parameter sweeps: run the same protocol over many parameter sets and
+//! rank them by an objective, instead of writing the loop by hand.
+//!
+//! A `ParamSpace` names each parameter with its range. Model parameters
+//! are set on every compartment of a copy of the cell, as
+//! `Compartments::set_section_param` would, under the names of
+//! `parameters`; protocol parameters are only handed to the run, e.g. for
+//! a stimulus amplitude. `SweepRunner::grid` takes every combination of
+//! the grid points, `SweepRunner::random` draws sets reproducibly from a
+//! seed.
+//!
+//! Every set gets a fresh `Simulation`, since its parameters change the
+//! matrix. Sets run `threads` at a time and each result is scored and
+//! dropped before the next wave starts, so at most `threads` results are
+//! held at once. Lower scores rank first. `SweepRunner::fit` scores with
+//! the objectives of `objectives` against a target trace.
+
+use std::fmt::Write as _;
+use std::path::Path;
+use std::thread;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::compartments::Compartments;
+use crate::objectives::{ObjectiveError, WeightedObjective};
+use crate::parameters::set_parameter;
+use crate::solver::{SimError, Simulation, SimulationResult};
+use crate::swc_reader::ConflictPolicy;
+use crate::write;
+
+/// What a parameter changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParamTarget {
+    /// A settable parameter of every compartment, see `parameters`
+    Model,
+    /// Only passed to the run
+    Protocol,
+}
+
+/// How grid points are spaced and random values drawn between the ends
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scale {
+    Linear,
+    /// Evenly in the logarithm; both ends must be positive
+    Log,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Param {
+    pub name: String,
+    pub target: ParamTarget,
+    pub low: f64,
+    pub high: f64,
+    /// Grid points from `low` to `high`, both included; one gives `low`
+    pub points: usize,
+    pub scale: Scale,
+}
+
+impl Param {
+    pub fn linear(name: &str, target: ParamTarget, low: f64, high: f64, points: usize) -> Param {
+        Param {
+            name: name.to_owned(),
+            target,
+            low,
+            high,
+            points,
+            scale: Scale::Linear,
+        }
+    }
+
+    pub fn log(name: &str, target: ParamTarget, low: f64, high: f64, points: usize) -> Param {
+        Param {
+            scale: Scale::Log,
+            ..Param::linear(name, target, low, high, points)
+        }
+    }
+
+    /// The grid points, in increasing order
+    pub fn grid(&self) -> Vec<f64> {
+        let (low, high) = self.ends();
+        let steps = self.points.saturating_sub(1).max(1) as f64;
+        (0..self.points)
+            .map(|k| self.unscale(low + (high - low) * k as f64 / steps))
+            .collect()
+    }
+
+    fn sample(&self, rng: &mut StdRng) -> f64 {
+        let (low, high) = self.ends();
+        self.unscale(rng.random_range(low..=high))
+    }
+
+    /// The ends on the scale
+    fn ends(&self) -> (f64, f64) {
+        match self.scale {
+            Scale::Linear => (self.low, self.high),
+            Scale::Log => (self.low.ln(), self.high.ln()),
+        }
+    }
+
+    fn unscale(&self, x: f64) -> f64 {
+        match self.scale {
+            Scale::Linear => x,
+            Scale::Log => x.exp(),
+        }
+    }
+}
+
+/// The parameters of a sweep, in the order given
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ParamSpace {
+    params: Vec<Param>,
+}
+
+impl ParamSpace {
+    pub fn new() -> ParamSpace {
+        ParamSpace::default()
+    }
+
+    /// Adds `param`, refusing a repeated name or a range it cannot sweep
+    pub fn add(&mut self, param: Param) -> Result<(), String> {
+        if self.params.iter().any(|p| p.name == param.name) {
+            return Err(format!("Parameter '{}' is already swept", param.name));
+        }
+        if !(param.low.is_finite() && param.high.is_finite() && param.low <= param.high) {
+            return Err(format!(
+                "Parameter '{}' has no range from {} to {}",
+                param.name, param.low, param.high
+            ));
+        }
+        if param.scale == Scale::Log && param.low <= 0.0 {
+            return Err(format!(
+                "Parameter '{}' is swept in the logarithm from {}, which is not positive",
+                param.name, param.low
+            ));
+        }
+        if param.points == 0 {
+            return Err(format!("Parameter '{}' has no grid points", param.name));
+        }
+        self.params.push(param);
+        Ok(())
+    }
+
+    pub fn params(&self) -> &[Param] {
+        &self.params
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.params.iter().map(|p| p.name.as_str()).collect()
+    }
+}
+
+/// One set of values, in the order of the `ParamSpace`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParamSet {
+    pub names: Vec<String>,
+    pub values: Vec<f64>,
+}
+
+impl ParamSet {
+    /// The value of `name`, None if it is not swept
+    pub fn get(&self, name: &str) -> Option<f64> {
+        self.names
+            .iter()
+            .position(|n| n == name)
+            .map(|k| self.values[k])
+    }
+}
+
+impl std::fmt::Display for ParamSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let pairs: Vec<String> = self
+            .names
+            .iter()
+            .zip(&self.values)
+            .map(|(n, v)| format!("{}={}", n, v))
+            .collect();
+        write!(f, "{}", pairs.join(", "))
+    }
+}
+
+/// Generates parameter sets and runs them, see the module docs
+#[derive(Debug, Clone, PartialEq)]
+pub struct SweepRunner {
+    space: ParamSpace,
+    sets: Vec<Vec<f64>>,
+    threads: usize,
+}
+
+impl SweepRunner {
+    /// Every combination of the grid points, the last parameter varying
+    /// fastest
+    pub fn grid(space: ParamSpace) -> SweepRunner {
+        let mut sets = vec![Vec::new()];
+        for param in space.params() {
+            let points = param.grid();
+            sets = sets
+                .iter()
+                .flat_map(|set: &Vec<f64>| {
+                    points.iter().map(move |&x| {
+                        let mut set = set.clone();
+                        set.push(x);
+                        set
+                    })
+                })
+                .collect();
+        }
+        SweepRunner {
+            space,
+            sets,
+            threads: 0,
+        }
+    }
+
+    /// `n` sets drawn from `seed`, each parameter uniformly on its scale
+    pub fn random(space: ParamSpace, n: usize, seed: u64) -> SweepRunner {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let sets = (0..n)
+            .map(|_| space.params().iter().map(|p| p.sample(&mut rng)).collect())
+            .collect();
+        SweepRunner {
+            space,
+            sets,
+            threads: 0,
+        }
+    }
+
+    /// Sets run at once; 0, the default, uses every available core
+    pub fn with_threads(mut self, threads: usize) -> SweepRunner {
+        self.threads = threads;
+        self
+    }
+
+    /// The parameter sets, in the order they run
+    pub fn sets(&self) -> Vec<ParamSet> {
+        (0..self.sets.len()).map(|k| self.set(k)).collect()
+    }
+
+    fn set(&self, k: usize) -> ParamSet {
+        ParamSet {
+            names: self.space.names().iter().map(|n| n.to_string()).collect(),
+            values: self.sets[k].clone(),
+        }
+    }
+
+    /// Runs every set on a copy of `compartments` with its model
+    /// parameters applied: `run` gets the set and a `Simulation` at rest
+    /// with time step `dt`, and `objective` scores what it returns. The
+    /// first set that fails stops the sweep.
+    pub fn run<R, O>(
+        &self,
+        compartments: &Compartments,
+        dt: f64,
+        run: R,
+        objective: O,
+    ) -> Result<SweepTable, String>
+    where
+        R: Fn(&ParamSet, &mut Simulation) -> Result<SimulationResult, SimError> + Sync,
+        O: Fn(&ParamSet, &SimulationResult) -> f64 + Sync,
+    {
+        self.run_scored(compartments, dt, run, |set, result| {
+            Ok(objective(set, result))
+        })
+    }
+
+    /// `run` scored by `objective` between `probe` and `target`, see
+    /// `objectives`. A set whose run has no spike to take features from
+    /// scores NaN and ranks last; any other objective error stops the
+    /// sweep.
+    pub fn fit<R>(
+        &self,
+        compartments: &Compartments,
+        dt: f64,
+        run: R,
+        objective: &WeightedObjective,
+        probe: &str,
+        target: (&[f64], &[f64]),
+    ) -> Result<SweepTable, String>
+    where
+        R: Fn(&ParamSet, &mut Simulation) -> Result<SimulationResult, SimError> + Sync,
+    {
+        self.run_scored(compartments, dt, run, |_, result| {
+            match objective.evaluate(result, probe, target) {
+                Ok(score) => Ok(score.score),
+                Err(ObjectiveError::NoSpikes { .. }) => Ok(f64::NAN),
+                Err(e) => Err(e.to_string()),
+            }
+        })
+    }
+
+    fn run_scored<R, O>(
+        &self,
+        compartments: &Compartments,
+        dt: f64,
+        run: R,
+        objective: O,
+    ) -> Result<SweepTable, String>
+    where
+        R: Fn(&ParamSet, &mut Simulation) -> Result<SimulationResult, SimError> + Sync,
+        O: Fn(&ParamSet, &SimulationResult) -> Result<f64, String> + Sync,
+    {
+        let evaluate = |k: usize| -> Result<f64, String> {
+            let set = self.set(k);
+            let mut model = compartments.clone();
+            for (param, &value) in self.space.params().iter().zip(&set.values) {
+                if param.target == ParamTarget::Model {
+                    for c in model.components.iter_mut().skip(1) {
+                        set_parameter(c, &param.name, value).map_err(|e| e.to_string())?;
+                    }
+                }
+            }
+            let mut simulation = Simulation::new(&model, dt).map_err(|e| e.to_string())?;
+            let result = run(&set, &mut simulation).map_err(|e| e.to_string())?;
+            objective(&set, &result)
+        };
+        let threads = match self.threads {
+            0 => thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        }
+        .clamp(1, self.sets.len().max(1));
+        let order: Vec<usize> = (0..self.sets.len()).collect();
+        let mut scores = Vec::with_capacity(self.sets.len());
+        for wave in order.chunks(threads) {
+            let results: Vec<Result<f64, String>> = thread::scope(|scope| {
+                let workers: Vec<_> = wave
+                    .iter()
+                    .map(|&k| scope.spawn(move || evaluate(k)))
+                    .collect();
+                workers
+                    .into_iter()
+                    .map(|w| {
+                        w.join()
+                            .unwrap_or_else(|_| Err("The run panicked".to_owned()))
+                    })
+                    .collect()
+            });
+            for (&k, score) in wave.iter().zip(results) {
+                scores.push(
+                    score.map_err(|e| format!("Parameter set {} ({}): {}", k, self.set(k), e))?,
+                );
+            }
+        }
+
+        let mut rows: Vec<SweepRow> = self
+            .sets
+            .iter()
+            .zip(scores)
+            .map(|(values, score)| SweepRow {
+                values: values.clone(),
+                score,
+            })
+            .collect();
+        // Stable, so ties keep their run order; NaN scores go last
+        rows.sort_by(|a, b| match (a.score.is_nan(), b.score.is_nan()) {
+            (false, false) => a.score.total_cmp(&b.score),
+            (a, b) => a.cmp(&b),
+        });
+        Ok(SweepTable {
+            names: self.space.names().iter().map(|n| n.to_string()).collect(),
+            rows,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SweepRow {
+    /// In the order of `SweepTable::names`
+    pub values: Vec<f64>,
+    pub score: f64,
+}
+
+/// Every parameter set with its score, best first
+#[derive(Debug, Clone, PartialEq)]
+pub struct SweepTable {
+    pub names: Vec<String>,
+    pub rows: Vec<SweepRow>,
+}
+
+impl SweepTable {
+    /// The best set, None if the sweep was empty
+    pub fn best(&self) -> Option<ParamSet> {
+        self.rows.first().map(|row| ParamSet {
+            names: self.names.clone(),
+            values: row.values.clone(),
+        })
+    }
+
+    /// Comma separated, a header of the parameter names and `score`, then
+    /// a row per set in rank order
+    pub fn to_csv(&self) -> String {
+        let mut output = self.names.join(",");
+        output.push_str(",score\n");
+        for row in &self.rows {
+            for v in &row.values {
+                let _ = write!(output, "{},", v);
+            }
+            let _ = writeln!(output, "{}", row.score);
+        }
+        output
+    }
+
+    /// Writes `to_csv` to `path` atomically
+    pub fn write_csv(&self, path: impl AsRef<Path>, policy: ConflictPolicy) -> Result<(), String> {
+        write::write_atomic(path.as_ref(), self.to_csv().as_bytes(), policy)
+            .map_err(|e| e.to_string())
+    }
+}
@@ -0,0 +1,174 @@
+//! Conductance-based synapses onto the compartments of a `Simulation`,
+//! driven by presynaptic spike trains from outside the model.
+//!
+//! Each presynaptic spike opens the synapse by `g_max` times its weight at
+//! the start of the step it falls in; the conductance then decays with
+//! `tau` and enters the compartment's row like a membrane current. With
+//! `Plasticity::Stdp` the weight follows the pair-based rule of
+//! `plasticity`: presynaptic spikes depress it, and postsynaptic spikes,
+//! upward crossings of `post_threshold` by the compartment's voltage at
+//! the end of a step, potentiate it. The traces are only brought up to
+//! date at those events.
+//!
+//! Weights and conductances read as state paths, `syn[k].w` and
+//! `syn[k].g`, so `Simulation::record` gives weight trajectories.
+//!
+//! Conductances are in nS, potentials in mV and times in ms.
+
+use crate::plasticity::{Plasticity, StdpState};
+use crate::recording::ThresholdDetector;
+use crate::solver::{SimError, Simulation};
+use crate::spikes::SpikeTrainSource;
+
+/// Where the postsynaptic spikes STDP sees are detected by default, in mV
+pub const POST_THRESHOLD: f64 = -20.0;
+
+/// A synapse as registered with `Simulation::add_synapse`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Synapse {
+    /// Postsynaptic compartment
+    pub idx: usize,
+    /// Conductance a presynaptic spike opens at weight 1
+    pub g_max: f64,
+    /// Reversal potential
+    pub e: f64,
+    /// Decay time constant of the conductance
+    pub tau: f64,
+    pub source: SpikeTrainSource,
+    /// Starting weight, held within the plasticity rule's bounds
+    pub weight: f64,
+    pub plasticity: Option<Plasticity>,
+    /// Postsynaptic voltage a spike crosses
+    pub post_threshold: f64,
+}
+
+impl Synapse {
+    /// A static synapse of weight 1
+    pub fn new(idx: usize, g_max: f64, e: f64, tau: f64, source: SpikeTrainSource) -> Synapse {
+        Synapse {
+            idx,
+            g_max,
+            e,
+            tau,
+            source,
+            weight: 1.0,
+            plasticity: None,
+            post_threshold: POST_THRESHOLD,
+        }
+    }
+
+    /// Starting at `weight`, changed during the run by `plasticity`
+    pub fn with_plasticity(mut self, plasticity: Plasticity, weight: f64) -> Synapse {
+        self.plasticity = Some(plasticity);
+        self.weight = weight;
+        self
+    }
+}
+
+/// A registered synapse during a run
+#[derive(Debug, Clone)]
+pub(crate) struct SynapseState {
+    synapse: Synapse,
+    /// Presynaptic spike times, in increasing order
+    events: Vec<f64>,
+    /// Next of `events` to deliver
+    next: usize,
+    /// Current conductance, in nS
+    pub(crate) g: f64,
+    pub(crate) weight: f64,
+    stdp: Option<StdpState>,
+    detector: ThresholdDetector,
+}
+
+impl Simulation {
+    /// Registers `synapse` and returns its index. Spikes of its source
+    /// before the current time are never delivered.
+    pub fn add_synapse(&mut self, synapse: Synapse) -> Result<usize, SimError> {
+        self.check(synapse.idx)?;
+        if !(synapse.g_max >= 0.0 && synapse.g_max.is_finite() && synapse.e.is_finite()) {
+            return Err(SimError::InvalidModel {
+                reason: format!(
+                    "Synaptic conductance must be non-negative and finite, got {} nS at {} mV",
+                    synapse.g_max, synapse.e
+                ),
+            });
+        }
+        if !(synapse.tau > 0.0 && synapse.tau.is_finite()) {
+            return Err(SimError::InvalidModel {
+                reason: format!(
+                    "Synaptic time constant must be positive, got {} ms",
+                    synapse.tau
+                ),
+            });
+        }
+        if !synapse.weight.is_finite() {
+            return Err(SimError::InvalidModel {
+                reason: format!("Synaptic weight must be finite, got {}", synapse.weight),
+            });
+        }
+        let events = synapse
+            .source
+            .events()
+            .map_err(|reason| SimError::InvalidModel { reason })?;
+        let stdp = match synapse.plasticity {
+            Some(Plasticity::Stdp(params)) => Some(
+                StdpState::new(params, synapse.weight)
+                    .map_err(|reason| SimError::InvalidModel { reason })?,
+            ),
+            None => None,
+        };
+        let mut detector = ThresholdDetector::new(synapse.post_threshold);
+        detector.push(self.voltages()[synapse.idx]);
+        let now = self.time();
+        self.synapses.push(SynapseState {
+            next: events.partition_point(|&t| t < now),
+            events,
+            g: 0.0,
+            weight: stdp.map_or(synapse.weight, |s| s.weight),
+            stdp,
+            detector,
+            synapse,
+        });
+        if self.synaptic.is_empty() {
+            self.synaptic = vec![(0.0, 0.0); self.voltages().len()];
+        }
+        Ok(self.synapses.len() - 1)
+    }
+
+    /// Weight of synapse `k`, None if there is none
+    pub fn synapse_weight(&self, k: usize) -> Option<f64> {
+        self.synapses.get(k).map(|s| s.weight)
+    }
+
+    /// Delivers the presynaptic spikes that fall in the coming step and
+    /// sums the conductances per compartment for the solver
+    pub(crate) fn open_synapses(&mut self) {
+        let end = (self.steps + 1) as f64 * self.dt();
+        self.synaptic.iter_mut().for_each(|s| *s = (0.0, 0.0));
+        for s in &mut self.synapses {
+            while let Some(&t) = s.events.get(s.next).filter(|&&t| t < end) {
+                if let Some(stdp) = &mut s.stdp {
+                    s.weight = stdp.on_pre(t);
+                }
+                s.g += s.synapse.g_max * s.weight;
+                s.next += 1;
+            }
+            let row = &mut self.synaptic[s.synapse.idx];
+            row.0 += s.g;
+            row.1 += s.g * s.synapse.e;
+        }
+    }
+
+    /// Decays the conductances over the step just taken and passes
+    /// postsynaptic spikes, at the time it ended, to the plastic synapses
+    pub(crate) fn close_synapses(&mut self) {
+        let (t, dt) = (self.time(), self.dt());
+        for s in &mut self.synapses {
+            s.g *= (-dt / s.synapse.tau).exp();
+            let spiked = s.detector.push(self.v[s.synapse.idx]);
+            if let (true, Some(stdp)) = (spiked, &mut s.stdp) {
+                s.weight = stdp.on_post(t);
+            }
+        }
+    }
+}
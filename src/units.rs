@@ -0,0 +1,175 @@
+//! Unit-carrying wrappers for physical parameters at the API surface, so a
+//! conductance in mS/cm² cannot be passed where S/cm² is meant. Internal
+//! math stays on raw `f64` in the canonical unit of each type.
+
+use std::fmt;
+
+use log::warn;
+
+macro_rules! units {
+    ($($(#[$doc:meta])* $name:ident => $unit:literal, $sign:ident;)*) => {
+        $(
+            $(#[$doc])*
+            #[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+            pub struct $name(f64);
+
+            impl $name {
+                /// Value in the canonical unit
+                pub fn new(value: f64) -> Result<Self, String> {
+                    check(value, Sign::$sign, stringify!($name), $unit).map($name)
+                }
+
+                /// Value in the canonical unit
+                pub fn value(self) -> f64 {
+                    self.0
+                }
+            }
+
+            impl fmt::Display for $name {
+                fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                    write!(f, "{} {}", self.0, $unit)
+                }
+            }
+        )*
+    };
+}
+
+enum Sign {
+    Any,
+    NonNegative,
+}
+
+fn check(value: f64, sign: Sign, name: &str, unit: &str) -> Result<f64, String> {
+    if !value.is_finite() {
+        return Err(format!("{} must be finite, got {}", name, value));
+    }
+    if matches!(sign, Sign::NonNegative) && value < 0.0 {
+        return Err(format!(
+            "{} cannot be negative, got {} {}",
+            name, value, unit
+        ));
+    }
+    Ok(value)
+}
+
+units! {
+    /// Membrane potential, in mV
+    Mv => "mV", Any;
+    /// Time, in ms
+    Ms => "ms", NonNegative;
+    /// Current, in nA
+    NanoAmp => "nA", Any;
+    /// Specific membrane capacitance, in µF/cm²
+    MicroFaradPerCm2 => "uF/cm2", NonNegative;
+    /// Axial resistivity, in Ω·cm
+    OhmCm => "ohm*cm", NonNegative;
+    /// Conductance density, in S/cm²
+    SiemensPerCm2 => "S/cm2", NonNegative;
+    /// Length, in µm
+    Micron => "um", NonNegative;
+}
+
+impl Mv {
+    pub fn from_volts(v: f64) -> Result<Self, String> {
+        Mv::new(v * 1e3)
+    }
+
+    pub fn volts(self) -> f64 {
+        self.0 / 1e3
+    }
+}
+
+impl Ms {
+    pub fn from_seconds(s: f64) -> Result<Self, String> {
+        Ms::new(s * 1e3)
+    }
+
+    pub fn from_microseconds(us: f64) -> Result<Self, String> {
+        Ms::new(us / 1e3)
+    }
+
+    pub fn seconds(self) -> f64 {
+        self.0 / 1e3
+    }
+}
+
+impl NanoAmp {
+    pub fn from_picoamps(pa: f64) -> Result<Self, String> {
+        NanoAmp::new(pa / 1e3)
+    }
+
+    pub fn from_microamps(ua: f64) -> Result<Self, String> {
+        NanoAmp::new(ua * 1e3)
+    }
+
+    pub fn picoamps(self) -> f64 {
+        self.0 * 1e3
+    }
+}
+
+impl MicroFaradPerCm2 {
+    /// 1 F/m² is 100 µF/cm²
+    pub fn from_farads_per_m2(f: f64) -> Result<Self, String> {
+        MicroFaradPerCm2::new(f * 100.0)
+    }
+
+    pub fn farads_per_m2(self) -> f64 {
+        self.0 / 100.0
+    }
+}
+
+impl OhmCm {
+    pub fn from_ohm_m(r: f64) -> Result<Self, String> {
+        OhmCm::new(r * 100.0)
+    }
+
+    pub fn ohm_m(self) -> f64 {
+        self.0 / 100.0
+    }
+}
+
+impl SiemensPerCm2 {
+    pub fn from_millisiemens(ms: f64) -> Result<Self, String> {
+        SiemensPerCm2::new(ms / 1e3)
+    }
+
+    /// 1 S/m² is 1e-4 S/cm²
+    pub fn from_siemens_per_m2(s: f64) -> Result<Self, String> {
+        SiemensPerCm2::new(s / 1e4)
+    }
+
+    pub fn millisiemens(self) -> f64 {
+        self.0 * 1e3
+    }
+}
+
+impl Micron {
+    pub fn from_mm(mm: f64) -> Result<Self, String> {
+        Micron::new(mm * 1e3)
+    }
+
+    pub fn from_cm(cm: f64) -> Result<Self, String> {
+        Micron::new(cm * 1e4)
+    }
+
+    pub fn mm(self) -> f64 {
+        self.0 / 1e3
+    }
+}
+
+/// Time steps above this many ms are almost certainly a unit slip
+const MAX_PLAUSIBLE_DT: f64 = 100.0;
+
+/// Checks a time step for a probable unit error. Returns, and logs, a warning
+/// if `dt` is zero or implausibly long.
+pub fn check_dt(dt: Ms) -> Option<String> {
+    let message = if dt.0 == 0.0 {
+        "Time step is 0 ms".to_owned()
+    } else if dt.0 > MAX_PLAUSIBLE_DT {
+        format!("Time step of {} is implausibly long; dt is in ms", dt)
+    } else {
+        return None;
+    };
+    warn!("{}", message);
+    Some(message)
+}
@@ -0,0 +1,673 @@
+//! Comparing simulated traces with reference traces from other simulators,
+//! e.g. NEURON or Jaxley runs exported to CSV or NPY.
+//!
+//! The reference is interpolated linearly onto the simulated time base, so
+//! the two need not share a step, but they must cover the same time range:
+//! a reference that stops early or starts late is an error, not a shorter
+//! comparison. Spikes are upward threshold crossings, timed by linear
+//! interpolation, and each simulated spike is paired with the nearest
+//! unpaired reference spike within a window.
+//!
+//! Times are in ms and values in whatever unit the traces share, mV for
+//! voltages.
+//!
+//! Morphology checks report the same way, PASS or FAIL with a line per
+//! problem, through `QcReport`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::analysis::{BranchPointReport, RallBand};
+use crate::codes::Code;
+use crate::compartments::Compartments;
+use crate::index_map::AttachmentKind;
+use crate::run_log::LogValue;
+use crate::solver::SimulationResult;
+use crate::swc_reader::{Skeleton, StructureIdentifier};
+
+/// A sampled trace, with strictly increasing times
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReferenceTrace {
+    times: Vec<f64>,
+    values: Vec<f64>,
+}
+
+impl ReferenceTrace {
+    pub fn new(times: Vec<f64>, values: Vec<f64>) -> Result<ReferenceTrace, String> {
+        if times.len() != values.len() {
+            return Err(format!("{} times for {} values", times.len(), values.len()));
+        }
+        if times.len() < 2 {
+            return Err("A trace needs at least two samples".to_owned());
+        }
+        if let Some(i) = times.iter().chain(&values).position(|x| !x.is_finite()) {
+            return Err(format!("Sample {} is not finite", i % times.len()));
+        }
+        if let Some(i) = times.windows(2).position(|w| w[1] <= w[0]) {
+            return Err(format!(
+                "Times must increase, sample {} at {} ms follows {} ms",
+                i + 1,
+                times[i + 1],
+                times[i]
+            ));
+        }
+        Ok(ReferenceTrace { times, values })
+    }
+
+    /// The voltage of compartment `idx` in `result`
+    pub fn from_simulation(
+        result: &SimulationResult,
+        idx: usize,
+    ) -> Result<ReferenceTrace, String> {
+        let values = result
+            .voltages
+            .get(idx)
+            .filter(|_| idx != 0)
+            .ok_or_else(|| format!("No compartment at index {}", idx))?;
+        let times = (0..values.len()).map(|k| k as f64 * result.dt).collect();
+        ReferenceTrace::new(times, values.clone())
+    }
+
+    /// Reads `.csv` or `.npy`, by extension
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<ReferenceTrace, String> {
+        let path = path.as_ref();
+        let bytes =
+            fs::read(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        let trace = match path.extension().and_then(|e| e.to_str()) {
+            Some("csv") => std::str::from_utf8(&bytes)
+                .map_err(|e| e.to_string())
+                .and_then(ReferenceTrace::parse_csv),
+            Some("npy") => ReferenceTrace::parse_npy(&bytes),
+            _ => Err("expected a .csv or .npy file".to_owned()),
+        };
+        trace.map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Time and value in the first two columns. A header line, blank lines
+    /// and lines starting with `#` are skipped.
+    pub fn parse_csv(text: &str) -> Result<ReferenceTrace, String> {
+        let (mut times, mut values) = (Vec::new(), Vec::new());
+        let mut header = false;
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split(',').map(|f| f.trim().parse::<f64>());
+            match (fields.next(), fields.next()) {
+                (Some(Ok(t)), Some(Ok(v))) => {
+                    times.push(t);
+                    values.push(v);
+                }
+                _ if times.is_empty() && !header => header = true,
+                _ => return Err(format!("Line {} is not a time and a value", n + 1)),
+            }
+        }
+        ReferenceTrace::new(times, values)
+    }
+
+    /// A float array of shape `(n, 2)`, time in the first column, as written
+    /// by `numpy.save`
+    pub fn parse_npy(bytes: &[u8]) -> Result<ReferenceTrace, String> {
+        let rest = bytes.strip_prefix(b"\x93NUMPY").ok_or("Not an NPY file")?;
+        let (header, data) = match rest {
+            [1, _, a, b, rest @ ..] => rest.split_at_checked(u16::from_le_bytes([*a, *b]) as usize),
+            [2 | 3, _, a, b, c, d, rest @ ..] => {
+                rest.split_at_checked(u32::from_le_bytes([*a, *b, *c, *d]) as usize)
+            }
+            _ => None,
+        }
+        .ok_or("Unsupported or truncated NPY header")?;
+        let header = std::str::from_utf8(header).map_err(|e| e.to_string())?;
+        let field = |key: &str| {
+            let start = header
+                .find(&format!("'{}':", key))
+                .ok_or(format!("NPY header has no '{}'", key))?
+                + key.len()
+                + 3;
+            Ok::<&str, String>(header[start..].trim_start())
+        };
+
+        let descr = field("descr")?;
+        let width = if descr.starts_with("'<f8'") {
+            8
+        } else if descr.starts_with("'<f4'") {
+            4
+        } else {
+            return Err(format!(
+                "NPY data must be little-endian float64 or float32, got {}",
+                descr.split(',').next().unwrap_or(descr)
+            ));
+        };
+        let fortran = field("fortran_order")?.starts_with("True");
+        let shape = field("shape")?;
+        let dims: Vec<usize> = shape
+            .strip_prefix('(')
+            .and_then(|s| s.split(')').next())
+            .ok_or("NPY shape is not a tuple")?
+            .split(',')
+            .map(str::trim)
+            .filter(|d| !d.is_empty())
+            .map(|d| d.parse().map_err(|_| format!("Bad NPY dimension '{}'", d)))
+            .collect::<Result<_, _>>()?;
+        let n = match dims[..] {
+            [n, 2] => n,
+            _ => return Err(format!("NPY array must have shape (n, 2), got {:?}", dims)),
+        };
+        if data.len() < 2 * n * width {
+            return Err(format!("NPY data holds fewer than {} values", 2 * n));
+        }
+        let values: Vec<f64> = data[..2 * n * width]
+            .chunks_exact(width)
+            .map(|c| {
+                let mut b = [0; 8];
+                b[..width].copy_from_slice(c);
+                match width {
+                    4 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    _ => f64::from_le_bytes(b),
+                }
+            })
+            .collect();
+        let (times, values) = if fortran {
+            (values[..n].to_vec(), values[n..].to_vec())
+        } else {
+            (
+                values.iter().step_by(2).copied().collect(),
+                values.iter().skip(1).step_by(2).copied().collect(),
+            )
+        };
+        ReferenceTrace::new(times, values)
+    }
+
+    pub fn times(&self) -> &[f64] {
+        &self.times
+    }
+
+    pub fn values(&self) -> &[f64] {
+        &self.values
+    }
+
+    pub fn start(&self) -> f64 {
+        self.times[0]
+    }
+
+    pub fn end(&self) -> f64 {
+        self.times[self.times.len() - 1]
+    }
+
+    /// Linearly interpolated value at `t`, held at the ends outside the
+    /// trace
+    pub fn value_at(&self, t: f64) -> f64 {
+        let i = self.times.partition_point(|&x| x <= t);
+        if i == 0 {
+            return self.values[0];
+        }
+        if i == self.times.len() {
+            return self.values[i - 1];
+        }
+        let (t0, t1) = (self.times[i - 1], self.times[i]);
+        let (v0, v1) = (self.values[i - 1], self.values[i]);
+        v0 + (v1 - v0) * (t - t0) / (t1 - t0)
+    }
+
+    /// Times of the upward crossings of `threshold`
+    pub fn spike_times(&self, threshold: f64) -> Vec<f64> {
+        let mut spikes = Vec::new();
+        for i in 1..self.values.len() {
+            let (v0, v1) = (self.values[i - 1], self.values[i]);
+            if v0 < threshold && v1 >= threshold {
+                let (t0, t1) = (self.times[i - 1], self.times[i]);
+                spikes.push(t0 + (t1 - t0) * (threshold - v0) / (v1 - v0));
+            }
+        }
+        spikes
+    }
+
+    /// Largest spacing between samples
+    fn max_step(&self) -> f64 {
+        self.times
+            .windows(2)
+            .map(|w| w[1] - w[0])
+            .fold(0.0, f64::max)
+    }
+}
+
+/// Tolerances and spike detection for a comparison
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ComparisonOptions {
+    /// Largest root mean square error that passes
+    pub rms_tolerance: f64,
+    /// Largest pointwise error that passes
+    pub max_tolerance: f64,
+    pub spike_threshold: f64,
+    /// How far apart two spikes may be and still be paired, in ms
+    pub spike_window: f64,
+    /// Largest spike time difference that passes, in ms
+    pub spike_time_tolerance: f64,
+    /// How far the start and end times of the traces may differ, in ms.
+    /// None allows half the coarser sample spacing.
+    pub time_tolerance: Option<f64>,
+}
+
+impl Default for ComparisonOptions {
+    fn default() -> Self {
+        ComparisonOptions {
+            rms_tolerance: 1.0,
+            max_tolerance: 5.0,
+            spike_threshold: 0.0,
+            spike_window: 2.0,
+            spike_time_tolerance: 0.5,
+            time_tolerance: None,
+        }
+    }
+}
+
+/// How a simulated trace compares with its reference
+#[derive(Debug, Clone, PartialEq)]
+pub struct ComparisonReport {
+    pub probe: String,
+    /// Number of simulated samples compared
+    pub samples: usize,
+    pub rms_error: f64,
+    pub max_error: f64,
+    /// Where the largest error is, in ms
+    pub max_error_time: f64,
+    pub simulated_spikes: usize,
+    pub reference_spikes: usize,
+    /// Simulated minus reference time of every paired spike, in ms
+    pub spike_offsets: Vec<f64>,
+    pub unmatched_simulated: usize,
+    pub unmatched_reference: usize,
+    /// Why the comparison failed, empty if it passed
+    pub failures: Vec<String>,
+}
+
+impl ComparisonReport {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    /// Largest spike time difference, 0 without paired spikes
+    pub fn max_spike_offset(&self) -> f64 {
+        self.spike_offsets.iter().fold(0.0, |m, o| m.max(o.abs()))
+    }
+
+    /// A few lines for people
+    pub fn summary(&self) -> String {
+        let mut text = format!(
+            "{} {}: rms {:.4}, max {:.4} at {} ms, over {} samples\n  spikes: {} simulated, {} reference, {} paired, largest offset {:.4} ms\n",
+            if self.passed() { "PASS" } else { "FAIL" },
+            self.probe,
+            self.rms_error,
+            self.max_error,
+            self.max_error_time,
+            self.samples,
+            self.simulated_spikes,
+            self.reference_spikes,
+            self.spike_offsets.len(),
+            self.max_spike_offset(),
+        );
+        for failure in &self.failures {
+            text.push_str(&format!("  {}\n", failure));
+        }
+        text
+    }
+
+    /// One JSON object
+    pub fn to_json(&self) -> String {
+        let list = |items: Vec<LogValue>| {
+            let items: Vec<String> = items.iter().map(LogValue::to_string).collect();
+            format!("[{}]", items.join(","))
+        };
+        let fields = [
+            ("probe", LogValue::from(self.probe.as_str()).to_string()),
+            ("passed", LogValue::from(self.passed()).to_string()),
+            ("samples", LogValue::from(self.samples).to_string()),
+            ("rms_error", LogValue::from(self.rms_error).to_string()),
+            ("max_error", LogValue::from(self.max_error).to_string()),
+            (
+                "max_error_time",
+                LogValue::from(self.max_error_time).to_string(),
+            ),
+            (
+                "simulated_spikes",
+                LogValue::from(self.simulated_spikes).to_string(),
+            ),
+            (
+                "reference_spikes",
+                LogValue::from(self.reference_spikes).to_string(),
+            ),
+            (
+                "spike_offsets",
+                list(self.spike_offsets.iter().map(|&o| o.into()).collect()),
+            ),
+            (
+                "unmatched_simulated",
+                LogValue::from(self.unmatched_simulated).to_string(),
+            ),
+            (
+                "unmatched_reference",
+                LogValue::from(self.unmatched_reference).to_string(),
+            ),
+            (
+                "failures",
+                list(self.failures.iter().map(|f| f.as_str().into()).collect()),
+            ),
+        ];
+        let fields: Vec<String> = fields
+            .iter()
+            .map(|(k, v)| format!("\"{}\":{}", k, v))
+            .collect();
+        format!("{{{}}}", fields.join(","))
+    }
+}
+
+/// One node failing a morphology check
+#[derive(Debug, Clone, PartialEq)]
+pub struct QcWarning {
+    pub code: Code,
+    pub node_id: u64,
+    pub message: String,
+}
+
+/// How a morphology fared in one check
+#[derive(Debug, Clone, PartialEq)]
+pub struct QcReport {
+    pub check: String,
+    /// Number of places looked at
+    pub checked: usize,
+    pub warnings: Vec<QcWarning>,
+}
+
+impl QcReport {
+    pub fn passed(&self) -> bool {
+        self.warnings.is_empty()
+    }
+
+    /// A few lines for people
+    pub fn summary(&self) -> String {
+        let mut text = format!(
+            "{} {}: {} of {} flagged\n",
+            if self.passed() { "PASS" } else { "FAIL" },
+            self.check,
+            self.warnings.len(),
+            self.checked,
+        );
+        for warning in &self.warnings {
+            text.push_str(&format!(
+                "  {} node {}: {}\n",
+                warning.code.id(),
+                warning.node_id,
+                warning.message
+            ));
+        }
+        text
+    }
+
+    /// One JSON object
+    pub fn to_json(&self) -> String {
+        let warnings: Vec<String> = self
+            .warnings
+            .iter()
+            .map(|w| {
+                format!(
+                    "{{\"code\":{},\"node_id\":{},\"message\":{}}}",
+                    LogValue::from(w.code.id()),
+                    LogValue::from(w.node_id),
+                    LogValue::from(w.message.as_str())
+                )
+            })
+            .collect();
+        format!(
+            "{{\"check\":{},\"passed\":{},\"checked\":{},\"warnings\":[{}]}}",
+            LogValue::from(self.check.as_str()),
+            LogValue::from(self.passed()),
+            LogValue::from(self.checked),
+            warnings.join(",")
+        )
+    }
+}
+
+/// Flags the branch points of `reports` whose Rall ratio is outside `band`
+pub fn rall_qc(reports: &[BranchPointReport], band: &RallBand) -> QcReport {
+    QcReport {
+        check: "rall_ratio".to_owned(),
+        checked: reports.len(),
+        warnings: reports
+            .iter()
+            .filter(|r| !band.contains(r.ratio))
+            .map(|r| QcWarning {
+                code: Code::RallMismatch,
+                node_id: r.node_id,
+                message: format!(
+                    "ratio {:.3} outside {} to {} (parent {} µm, children {:?} µm)",
+                    r.ratio, band.low, band.high, r.parent_diam, r.child_diams
+                ),
+            })
+            .collect(),
+    }
+}
+
+/// Children off one node, outside the soma, from which `branching_qc`
+/// flags it by default
+pub const HIGH_BRANCHING_DEGREE: usize = 5;
+
+/// Flags the nodes outside the soma with `max_degree` or more children.
+/// Somata routinely carry many stems, so soma nodes are not looked at;
+/// every other branch point is.
+pub fn branching_qc(skeleton: &Skeleton, max_degree: usize) -> QcReport {
+    let mut checked = 0;
+    let mut warnings = Vec::new();
+    for node in skeleton.nodes.iter() {
+        let degree = skeleton.children_of(node.node_id).len();
+        if node.structured_identifier == StructureIdentifier::Soma || degree < 2 {
+            continue;
+        }
+        checked += 1;
+        if degree >= max_degree {
+            warnings.push(QcWarning {
+                code: Code::HighBranchingDegree,
+                node_id: node.node_id,
+                message: format!(
+                    "{} children, {} or more usually means a skeletonization error",
+                    degree, max_degree
+                ),
+            });
+        }
+    }
+    QcReport {
+        check: "branching_degree".to_owned(),
+        checked,
+        warnings,
+    }
+}
+
+/// A JSON array of `reports`
+pub fn reports_to_json(reports: &[ComparisonReport]) -> String {
+    let reports: Vec<String> = reports.iter().map(ComparisonReport::to_json).collect();
+    format!("[{}]\n", reports.join(",\n"))
+}
+
+/// Compares the voltage of compartment `probe` in `result` with `reference`
+pub fn compare_to_reference(
+    result: &SimulationResult,
+    probe: usize,
+    reference: &ReferenceTrace,
+    options: &ComparisonOptions,
+) -> Result<ComparisonReport, String> {
+    let simulated = ReferenceTrace::from_simulation(result, probe)?;
+    compare_traces(&probe.to_string(), &simulated, reference, options)
+}
+
+/// Compares two traces on the time base of `simulated`, naming the
+/// comparison `probe`
+pub fn compare_traces(
+    probe: &str,
+    simulated: &ReferenceTrace,
+    reference: &ReferenceTrace,
+    options: &ComparisonOptions,
+) -> Result<ComparisonReport, String> {
+    let tolerance = options
+        .time_tolerance
+        .unwrap_or(simulated.max_step().max(reference.max_step()) / 2.0);
+    if (simulated.start() - reference.start()).abs() > tolerance
+        || (simulated.end() - reference.end()).abs() > tolerance
+    {
+        return Err(format!(
+            "{}: time ranges differ, simulated {} to {} ms, reference {} to {} ms",
+            probe,
+            simulated.start(),
+            simulated.end(),
+            reference.start(),
+            reference.end()
+        ));
+    }
+
+    let (mut sum, mut max_error, mut max_error_time) = (0.0, 0.0, simulated.start());
+    for (&t, &v) in simulated.times.iter().zip(&simulated.values) {
+        let error = (v - reference.value_at(t)).abs();
+        sum += error * error;
+        if error > max_error {
+            (max_error, max_error_time) = (error, t);
+        }
+    }
+    let samples = simulated.times.len();
+    let rms_error = (sum / samples as f64).sqrt();
+
+    let ours = simulated.spike_times(options.spike_threshold);
+    let theirs = reference.spike_times(options.spike_threshold);
+    let mut taken = vec![false; theirs.len()];
+    let mut spike_offsets = Vec::new();
+    for &t in &ours {
+        let nearest = theirs
+            .iter()
+            .enumerate()
+            .filter(|&(j, &r)| !taken[j] && (t - r).abs() <= options.spike_window)
+            .min_by(|a, b| (t - a.1).abs().total_cmp(&(t - b.1).abs()));
+        if let Some((j, &r)) = nearest {
+            taken[j] = true;
+            spike_offsets.push(t - r);
+        }
+    }
+
+    let mut report = ComparisonReport {
+        probe: probe.to_owned(),
+        samples,
+        rms_error,
+        max_error,
+        max_error_time,
+        simulated_spikes: ours.len(),
+        reference_spikes: theirs.len(),
+        unmatched_simulated: ours.len() - spike_offsets.len(),
+        unmatched_reference: theirs.len() - spike_offsets.len(),
+        spike_offsets,
+        failures: Vec::new(),
+    };
+    if rms_error > options.rms_tolerance {
+        report.failures.push(format!(
+            "rms error {} exceeds {}",
+            rms_error, options.rms_tolerance
+        ));
+    }
+    if max_error > options.max_tolerance {
+        report.failures.push(format!(
+            "max error {} at {} ms exceeds {}",
+            max_error, max_error_time, options.max_tolerance
+        ));
+    }
+    if report.unmatched_simulated + report.unmatched_reference > 0 {
+        report.failures.push(format!(
+            "{} simulated and {} reference spikes have no partner within {} ms",
+            report.unmatched_simulated, report.unmatched_reference, options.spike_window
+        ));
+    }
+    if report.max_spike_offset() > options.spike_time_tolerance {
+        report.failures.push(format!(
+            "spike times differ by up to {} ms, more than {}",
+            report.max_spike_offset(),
+            options.spike_time_tolerance
+        ));
+    }
+    Ok(report)
+}
+
+/// Reference file for `name` in `dir`: `<name>.csv`, else `<name>.npy`
+fn reference_file(dir: &Path, name: &str) -> Option<PathBuf> {
+    ["csv", "npy"]
+        .iter()
+        .map(|ext| dir.join(format!("{}.{}", name, ext)))
+        .find(|p| p.is_file())
+}
+
+/// Compares every probe attached to `compartments`, the model `result` was
+/// simulated with, against the reference in `dir` named after it, e.g.
+/// `soma.csv` for the probe `soma`. Fails naming the probes without one.
+pub fn compare_probes<P: AsRef<Path>>(
+    result: &SimulationResult,
+    compartments: &Compartments,
+    dir: P,
+    options: &ComparisonOptions,
+) -> Result<Vec<ComparisonReport>, String> {
+    let dir = dir.as_ref();
+    let mut pairs = Vec::new();
+    let mut missing = Vec::new();
+    for probe in compartments
+        .attachments()
+        .iter()
+        .filter(|a| a.kind == AttachmentKind::Probe)
+    {
+        match reference_file(dir, &probe.name) {
+            Some(path) => pairs.push((probe, path)),
+            None => missing.push(probe.name.as_str()),
+        }
+    }
+    if !missing.is_empty() {
+        return Err(format!(
+            "No reference in {} for {}",
+            dir.display(),
+            missing.join(", ")
+        ));
+    }
+    pairs
+        .into_iter()
+        .map(|(probe, path)| {
+            let reference = ReferenceTrace::load(path)?;
+            let simulated = ReferenceTrace::from_simulation(result, probe.idx)?;
+            compare_traces(&probe.name, &simulated, &reference, options)
+        })
+        .collect()
+}
+
+/// Compares every `.csv` or `.npy` trace in `simulated_dir` with the one of
+/// the same name in `reference_dir`
+pub fn compare_dirs<P: AsRef<Path>, Q: AsRef<Path>>(
+    simulated_dir: P,
+    reference_dir: Q,
+    options: &ComparisonOptions,
+) -> Result<Vec<ComparisonReport>, String> {
+    let (simulated_dir, reference_dir) = (simulated_dir.as_ref(), reference_dir.as_ref());
+    let mut paths: Vec<_> = fs::read_dir(simulated_dir)
+        .map_err(|e| format!("Could not read {}: {}", simulated_dir.display(), e))?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| matches!(p.extension().and_then(|e| e.to_str()), Some("csv" | "npy")))
+        .collect();
+    paths.sort();
+    paths
+        .iter()
+        .map(|path| {
+            let name = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .ok_or_else(|| format!("{} has no usable name", path.display()))?;
+            let reference = reference_file(reference_dir, name).ok_or_else(|| {
+                format!("No reference in {} for {}", reference_dir.display(), name)
+            })?;
+            compare_traces(
+                name,
+                &ReferenceTrace::load(path)?,
+                &ReferenceTrace::load(reference)?,
+                options,
+            )
+        })
+        .collect()
+}
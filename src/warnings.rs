@@ -0,0 +1,136 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use log::warn;
+
+use crate::codes::Code;
+use crate::swc_reader::StructureIdentifier;
+
+/// The different things the reader warns about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[non_exhaustive]
+pub enum WarningKind {
+    ZeroRadius,
+    /// Nodes no path from the root reaches, e.g. a second tree in the file
+    Unreachable,
+}
+
+impl WarningKind {
+    /// Machine-readable code, see `codes::REGISTRY`
+    pub fn code(self) -> Code {
+        match self {
+            WarningKind::ZeroRadius => Code::ZeroRadius,
+            WarningKind::Unreachable => Code::Unreachable,
+        }
+    }
+}
+
+/// A reader warning, aggregated over every node that triggered it.
+///
+/// In verbose mode the first few occurrences are also reported one by one, in
+/// which case `count` is 1 and `first_line == last_line`. For array input the
+/// "lines" are indices into the arrays.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct SwcWarning {
+    pub kind: WarningKind,
+    pub count: usize,
+    pub by_type: BTreeMap<StructureIdentifier, usize>,
+    pub first_line: usize,
+    pub last_line: usize,
+}
+
+impl fmt::Display for SwcWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let what = match self.kind {
+            WarningKind::ZeroRadius => "zero-radius nodes",
+            WarningKind::Unreachable => "nodes not connected to the root, left out",
+        };
+        let breakdown = self
+            .by_type
+            .iter()
+            .map(|(t, c)| format!("{:?}: {}", t, thousands(*c)))
+            .collect::<Vec<_>>()
+            .join(", ");
+        write!(
+            f,
+            "{} {} ({}); first at line {}, last at line {}",
+            thousands(self.count),
+            what,
+            breakdown,
+            thousands(self.first_line),
+            thousands(self.last_line)
+        )
+    }
+}
+
+/// 12431 -> "12,431"
+fn thousands(n: usize) -> String {
+    let digits = n.to_string();
+    let mut out = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            out.push(',');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Collects warnings during a read. Formatting only happens for the individual
+/// warnings under the cap and once per kind at the end, which keeps huge files
+/// with many bad nodes from drowning in `warn!` calls.
+pub(crate) struct WarningCollector {
+    verbose: bool,
+    cap: usize,
+    individual: Vec<SwcWarning>,
+    aggregated: BTreeMap<WarningKind, SwcWarning>,
+}
+
+impl WarningCollector {
+    pub fn new(verbose: bool, cap: usize) -> Self {
+        WarningCollector {
+            verbose,
+            cap,
+            individual: Vec::new(),
+            aggregated: BTreeMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, kind: WarningKind, structure: StructureIdentifier, line: usize) {
+        if self.verbose && self.individual.len() < self.cap {
+            let single = SwcWarning {
+                kind,
+                count: 1,
+                by_type: BTreeMap::from([(structure, 1)]),
+                first_line: line,
+                last_line: line,
+            };
+            warn!("{}", single);
+            self.individual.push(single);
+        }
+
+        let entry = self.aggregated.entry(kind).or_insert_with(|| SwcWarning {
+            kind,
+            count: 0,
+            by_type: BTreeMap::new(),
+            first_line: line,
+            last_line: line,
+        });
+        entry.count += 1;
+        *entry.by_type.entry(structure).or_insert(0) += 1;
+        entry.first_line = entry.first_line.min(line);
+        entry.last_line = entry.last_line.max(line);
+    }
+
+    /// Logs the summaries and hands back everything collected, individual
+    /// warnings first
+    pub fn finish(self) -> Vec<SwcWarning> {
+        let mut all = self.individual;
+        for summary in self.aggregated.into_values() {
+            warn!("{}", summary);
+            all.push(summary);
+        }
+        all
+    }
+}
@@ -0,0 +1,245 @@
+//! Re-reading an SWC file that another program keeps rewriting, e.g. a
+//! proofreading tool, without redoing work when nothing changed.
+//!
+//! Polling is explicit: call `SkeletonWatcher::reload_if_changed` as often
+//! as suits. A file whose modification time and size are unchanged is not
+//! even opened; one whose bytes hash the same as last time is not parsed.
+
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use sha2::{Digest, Sha256};
+
+use crate::error::SwcError;
+use crate::geometry::{self, Vec3};
+use crate::morphometry::{Morphometry, SpatialMetrics};
+use crate::swc_reader::{Node, ReaderOptions, Skeleton, swc_reader_from_bytes};
+
+/// What changed between two versions of the file. Nodes from the previous
+/// version carry its IDs, nodes from the new one the new IDs.
+#[derive(Debug, Clone, Default)]
+pub struct ChangeSummary {
+    pub added: Vec<Node>,
+    pub removed: Vec<Node>,
+    /// Same node at a new position, as (before, after)
+    pub moved: Vec<(Node, Node)>,
+    /// Same node and position with a new radius or type, as (before, after)
+    pub changed: Vec<(Node, Node)>,
+    /// The IDs did not line up between versions, so nodes were matched by
+    /// where they sit in the tree instead. A node that moved then shows up
+    /// as removed and added, together with everything below it.
+    pub by_fingerprint: bool,
+}
+
+impl ChangeSummary {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.moved.is_empty()
+            && self.changed.is_empty()
+    }
+}
+
+/// A parsed SWC file plus derived quantities, kept up to date by polling
+pub struct SkeletonWatcher {
+    path: PathBuf,
+    options: ReaderOptions,
+    stamp: (Option<SystemTime>, u64),
+    hash: [u8; 32],
+    skeleton: Skeleton,
+    /// Length of the segment leading up to each node, indexed by node ID
+    segment_lengths: Vec<f64>,
+    spatial: Option<SpatialMetrics>,
+    parses: usize,
+}
+
+impl SkeletonWatcher {
+    pub fn open(
+        path: impl AsRef<Path>,
+        options: &ReaderOptions,
+    ) -> Result<SkeletonWatcher, SwcError> {
+        let path = path.as_ref().to_path_buf();
+        let stamp = stamp(&path)?;
+        let bytes = fs::read(&path).map_err(|e| SwcError::io(&path, e))?;
+        let skeleton = swc_reader_from_bytes(&bytes, options)?;
+        let segment_lengths = skeleton
+            .nodes
+            .iter()
+            .map(|n| segment_length(&skeleton, n))
+            .collect();
+        Ok(SkeletonWatcher {
+            path,
+            options: options.clone(),
+            stamp,
+            hash: Sha256::digest(&bytes).into(),
+            skeleton,
+            segment_lengths,
+            spatial: None,
+            parses: 1,
+        })
+    }
+
+    pub fn skeleton(&self) -> &Skeleton {
+        &self.skeleton
+    }
+
+    /// How many times the file has been parsed, the first read included
+    pub fn parse_count(&self) -> usize {
+        self.parses
+    }
+
+    /// Re-reads the file if it changed on disk and returns what changed.
+    /// None when the file is untouched, or rewritten with the same bytes.
+    /// On error the previous version stays in place.
+    pub fn reload_if_changed(&mut self) -> Result<Option<ChangeSummary>, SwcError> {
+        let stamp = stamp(&self.path)?;
+        if stamp == self.stamp {
+            return Ok(None);
+        }
+        let bytes = fs::read(&self.path).map_err(|e| SwcError::io(&self.path, e))?;
+        let hash: [u8; 32] = Sha256::digest(&bytes).into();
+        if hash == self.hash {
+            self.stamp = stamp;
+            return Ok(None);
+        }
+        let skeleton = swc_reader_from_bytes(&bytes, &self.options)?;
+        self.parses += 1;
+        self.stamp = stamp;
+        self.hash = hash;
+
+        let (summary, matched) = diff(&self.skeleton, &skeleton);
+        // Segment lengths carry over for nodes where neither end moved;
+        // everything else is measured afresh
+        let mut moved = vec![false; skeleton.nodes.len()];
+        for (_, after) in &summary.moved {
+            moved[after.node_id as usize] = true;
+        }
+        let lengths = skeleton
+            .nodes
+            .iter()
+            .map(|node| {
+                let (id, parent) = (node.node_id as usize, node.parent_id as usize);
+                match matched[id] {
+                    Some(old) if matched[parent].is_some() && !moved[id] && !moved[parent] => {
+                        self.segment_lengths[old as usize]
+                    }
+                    _ => segment_length(&skeleton, node),
+                }
+            })
+            .collect();
+        self.segment_lengths = lengths;
+        self.skeleton = skeleton;
+        if !summary.is_empty() {
+            self.spatial = None;
+        }
+        Ok(Some(summary))
+    }
+
+    /// Total cable length, kept up to date segment by segment. Equal to
+    /// `Morphometry::total_length` on the current skeleton, bit for bit.
+    pub fn total_length(&self) -> f64 {
+        geometry::stable_sum(self.segment_lengths.iter().copied())
+    }
+
+    /// `Morphometry::spatial_metrics` of the current skeleton, computed on
+    /// first use after each change
+    pub fn spatial_metrics(&mut self) -> &SpatialMetrics {
+        let nodes = &self.skeleton.nodes;
+        self.spatial
+            .get_or_insert_with(|| Morphometry::new(nodes).spatial_metrics())
+    }
+}
+
+fn stamp(path: &Path) -> Result<(Option<SystemTime>, u64), SwcError> {
+    let meta = fs::metadata(path).map_err(|e| SwcError::io(path, e))?;
+    Ok((meta.modified().ok(), meta.len()))
+}
+
+fn position(node: &Node) -> Vec3 {
+    [node.x_pos, node.y_pos, node.z_pos]
+}
+
+/// Computed exactly as `Morphometry` does, so sums agree to the bit
+fn segment_length(skeleton: &Skeleton, node: &Node) -> f64 {
+    if node.parent_id == node.node_id {
+        return 0.0;
+    }
+    let parent = &skeleton.nodes[node.parent_id as usize];
+    geometry::norm(geometry::sub(position(node), position(parent)))
+}
+
+/// Differences from `old` to `new`, and for each new node ID the old ID it
+/// was matched with
+fn diff(old: &Skeleton, new: &Skeleton) -> (ChangeSummary, Vec<Option<u64>>) {
+    // IDs are stable when every ID both versions have keeps its parent
+    let common = old.nodes.len().min(new.nodes.len());
+    let stable = (0..common).all(|i| old.nodes[i].parent_id == new.nodes[i].parent_id);
+    let matched: Vec<Option<u64>> = if stable {
+        (0..new.nodes.len() as u64)
+            .map(|i| (i < common as u64).then_some(i))
+            .collect()
+    } else {
+        by_fingerprint(old, new)
+    };
+
+    let mut summary = ChangeSummary {
+        by_fingerprint: !stable,
+        ..Default::default()
+    };
+    let mut seen = vec![false; old.nodes.len()];
+    for (node, m) in new.nodes.iter().zip(&matched) {
+        let Some(old_id) = *m else {
+            summary.added.push(*node);
+            continue;
+        };
+        seen[old_id as usize] = true;
+        let before = old.nodes[old_id as usize];
+        if position(&before) != position(node) {
+            summary.moved.push((before, *node));
+        } else if before.radius != node.radius
+            || before.structured_identifier != node.structured_identifier
+        {
+            summary.changed.push((before, *node));
+        }
+    }
+    summary.removed = old
+        .nodes
+        .iter()
+        .filter(|n| !seen[n.node_id as usize])
+        .copied()
+        .collect();
+    (summary, matched)
+}
+
+/// Key for a node from its type, its position and its parent's key, so it
+/// only depends on where the node sits in the tree and not on its ID
+fn fingerprints(skeleton: &Skeleton) -> Vec<u64> {
+    let mut keys = vec![0u64; skeleton.nodes.len()];
+    // Parents come before children in the sorted order
+    for node in skeleton.nodes.iter() {
+        let mut h = DefaultHasher::new();
+        if node.parent_id != node.node_id {
+            keys[node.parent_id as usize].hash(&mut h);
+        }
+        node.structured_identifier.hash(&mut h);
+        position(node).map(f64::to_bits).hash(&mut h);
+        keys[node.node_id as usize] = h.finish();
+    }
+    keys
+}
+
+fn by_fingerprint(old: &Skeleton, new: &Skeleton) -> Vec<Option<u64>> {
+    let mut available: HashMap<u64, Vec<u64>> = HashMap::new();
+    for (id, key) in fingerprints(old).into_iter().enumerate().rev() {
+        available.entry(key).or_default().push(id as u64);
+    }
+    // Nodes sharing a key pair up in ID order
+    fingerprints(new)
+        .into_iter()
+        .map(|key| available.get_mut(&key).and_then(Vec::pop))
+        .collect()
+}
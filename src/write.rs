@@ -0,0 +1,79 @@
+//! Writing processed files without leaving half-written output behind.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::error::SwcError;
+use crate::swc_reader::ConflictPolicy;
+
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Writes `contents` to a temporary file next to `path` and renames it into
+/// place, so readers only ever see the old file or the complete new one.
+/// Parent directories are created as needed. Conflicts are checked before
+/// writing, so two writers racing for the same path under `Skip` or `Error`
+/// can still both go ahead; the rename makes the last one win cleanly.
+pub(crate) fn write_atomic(
+    path: &Path,
+    contents: &[u8],
+    policy: ConflictPolicy,
+) -> Result<(), SwcError> {
+    write_atomic_with(path, policy, |file| file.write_all(contents)).map(|_| ())
+}
+
+/// Same as `write_atomic`, for output too large to hold in memory:
+/// `write` streams it into the temporary file. None when skipped.
+#[cfg_attr(not(feature = "parquet"), allow(dead_code))]
+pub(crate) fn write_atomic_with<T>(
+    path: &Path,
+    policy: ConflictPolicy,
+    write: impl FnOnce(&mut fs::File) -> io::Result<T>,
+) -> Result<Option<T>, SwcError> {
+    if path.exists() {
+        match policy {
+            ConflictPolicy::Overwrite => {}
+            ConflictPolicy::Skip => return Ok(None),
+            ConflictPolicy::Error => {
+                return Err(SwcError::io(
+                    path,
+                    io::Error::new(io::ErrorKind::AlreadyExists, "File already exists"),
+                ));
+            }
+        }
+    }
+
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    fs::create_dir_all(dir).map_err(|e| SwcError::io(dir, e))?;
+
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| SwcError::io(path, io::Error::other("Not a file path")))?;
+    let temp = dir.join(format!(
+        ".{}.{}.{}.tmp",
+        file_name.to_string_lossy(),
+        std::process::id(),
+        TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let written = fs::File::create(&temp).and_then(|mut file| {
+        let value = write(&mut file)?;
+        file.flush()?;
+        Ok(value)
+    });
+    let value = match written {
+        Ok(value) => value,
+        Err(e) => {
+            let _ = fs::remove_file(&temp);
+            return Err(SwcError::io(&temp, e));
+        }
+    };
+    fs::rename(&temp, path).map_err(|e| {
+        let _ = fs::remove_file(&temp);
+        SwcError::io(path, e)
+    })?;
+    Ok(Some(value))
+}
@@ -0,0 +1,405 @@
+use std::f64::consts::PI;
+
+use compartment_rs::accumulation::{
+    AccumulationConfig, FARADAY, IonAccumulation, TrackedIon, nernst,
+};
+use compartment_rs::channels::HodgkinHuxley;
+use compartment_rs::energy::EnergyLedger;
+use compartment_rs::solver::{SimError, Simulation};
+use compartment_rs::{
+    Channel, ChannelType, Compartments, Ion, ReaderOptions, swc_reader_from_bytes,
+};
+
+/// The one compartment with membrane: 100 µm long, 2 µm across
+const IDX: usize = 2;
+
+fn model(dynamic: bool) -> Compartments {
+    let swc = "1 1 0 0 0 5 -1\n2 3 100 0 0 1 1\n";
+    let skeleton = swc_reader_from_bytes(swc.as_bytes(), &ReaderOptions::default()).unwrap();
+    let mut compartments = Compartments::from_skeleton(skeleton);
+    let mut channel = Channel::default();
+    channel.channel_type = ChannelType::HodgkinHuxley(HodgkinHuxley {
+        use_dynamic_reversal: dynamic,
+        ..HodgkinHuxley::default()
+    });
+    compartments.components[IDX].set_channel(channel);
+    compartments
+}
+
+fn hh(compartments: &Compartments) -> HodgkinHuxley {
+    match &compartments.components[IDX].channel.channel_type {
+        ChannelType::HodgkinHuxley(hh) => *hh,
+        _ => unreachable!(),
+    }
+}
+
+/// A spike driven by a 1 ms pulse, simulated for 20 ms in steps of `dt`.
+/// Currents are booked in `accumulation` and `ledger` when given, and the
+/// reversals refreshed from the former every step. Returns the voltages.
+fn spike(
+    compartments: &mut Compartments,
+    mut accumulation: Option<&mut IonAccumulation>,
+    mut ledger: Option<&mut EnergyLedger>,
+    dt: f64,
+) -> Vec<f64> {
+    // mA/cm² over the membrane area, in nA
+    let to_na = compartments.components[IDX].membrane_area() * 1e-2;
+    let mut v = -65.0;
+    let mut gates = HodgkinHuxley::steady_state(v);
+    let mut trace = vec![v];
+    for k in 0..(20.0 / dt) as usize {
+        let t = k as f64 * dt;
+        let stimulus = if (1.0..2.0).contains(&t) { 20e-3 } else { 0.0 };
+        let hh = hh(compartments);
+        let currents = hh.currents(&gates, v);
+        let reversals = [hh.ena, hh.ek, hh.el];
+        for i in 0..3 {
+            let ion = HodgkinHuxley::IONS[i];
+            if let Some(acc) = accumulation.as_deref_mut() {
+                acc.record(IDX, ion, currents[i] * to_na);
+            }
+            if let Some(ledger) = ledger.as_deref_mut() {
+                ledger.record(IDX, ion, currents[i] * to_na, v - reversals[i], dt);
+            }
+        }
+        v += dt * 1e3 * (stimulus - currents.iter().sum::<f64>());
+        HodgkinHuxley::step_gates(&mut gates, v, dt);
+        if let Some(acc) = accumulation.as_deref_mut() {
+            acc.advance(dt).unwrap();
+            acc.update_reversals(compartments).unwrap();
+        }
+        trace.push(v);
+    }
+    trace
+}
+
+#[test]
+fn mechanisms_that_do_not_opt_in_are_untouched() {
+    let mut plain = model(false);
+    let reference = spike(&mut plain, None, None, 0.01);
+    assert!(reference.iter().cloned().fold(f64::MIN, f64::max) > 20.0);
+
+    let mut tracked = model(false);
+    let mut acc = IonAccumulation::new(&tracked, AccumulationConfig::default()).unwrap();
+    let trace = spike(&mut tracked, Some(&mut acc), None, 0.01);
+    let bits = |t: &[f64]| t.iter().map(|v| v.to_bits()).collect::<Vec<_>>();
+    assert_eq!(bits(&trace), bits(&reference));
+    assert_eq!(hh(&tracked), HodgkinHuxley::default());
+    // The concentrations moved all the same
+    assert!(acc.outside(Ion::K).unwrap()[IDX] > 2.5);
+}
+
+#[test]
+fn sealed_shell_follows_nernst() {
+    let mut compartments = model(true);
+    let config = AccumulationConfig {
+        tau: f64::INFINITY,
+        ..AccumulationConfig::default()
+    };
+    let mut acc = IonAccumulation::new(&compartments, config.clone()).unwrap();
+    let (current, dt, steps) = (0.02, 0.1, 2000);
+    for _ in 0..steps {
+        acc.record(IDX, Ion::K, current);
+        acc.record(IDX, Ion::NonSpecific, -current);
+        acc.advance(dt).unwrap();
+    }
+    acc.update_reversals(&mut compartments).unwrap();
+
+    // 4 pC of K moved out of the cytoplasm into the shell
+    let charge = current * dt * steps as f64;
+    let mmol = charge / FARADAY * 1e6;
+    let c = &compartments.components[IDX];
+    let r = c.diam / 2.0;
+    let volume = PI * r * r * c.length;
+    let d = config.shell_thickness;
+    let shell = PI * ((r + d).powi(2) - r * r) * c.length;
+    let (ki, ko) = (54.4 - mmol / volume, 2.5 + mmol / shell);
+    let inside = acc.inside(Ion::K).unwrap()[IDX];
+    let outside = acc.outside(Ion::K).unwrap()[IDX];
+    assert!((inside - ki).abs() < 1e-9);
+    assert!((outside - ko).abs() < 1e-9);
+    assert!(outside > 4.0);
+
+    let expected = nernst(1, ki, ko, config.celsius);
+    let at_rest = nernst(1, 54.4, 2.5, config.celsius);
+    assert!(expected - at_rest > 10.0);
+    assert!((hh(&compartments).ek - expected).abs() < 1e-9);
+    assert!((acc.reversals(Ion::K).unwrap()[IDX] - expected).abs() < 1e-9);
+    // Sodium carried nothing, and the soma has no membrane
+    assert!((hh(&compartments).ena - nernst(1, 10.0, 140.0, config.celsius)).abs() < 1e-12);
+    assert_eq!(acc.outside(Ion::K).unwrap()[1], 2.5);
+    assert!(acc.inside(Ion::Ca).is_none());
+}
+
+#[test]
+fn shell_relaxes_to_the_bath() {
+    let compartments = model(true);
+    let config = AccumulationConfig {
+        tau: 20.0,
+        ..AccumulationConfig::default()
+    };
+    let mut acc = IonAccumulation::new(&compartments, config).unwrap();
+    for _ in 0..10 {
+        acc.record(IDX, Ion::K, 0.5);
+        acc.advance(0.1).unwrap();
+    }
+    let excess = acc.outside(Ion::K).unwrap()[IDX] - 2.5;
+    assert!(excess > 0.2);
+    let dt = 0.025;
+    for _ in 0..(20.0 / dt) as usize {
+        acc.advance(dt).unwrap();
+    }
+    let left = acc.outside(Ion::K).unwrap()[IDX] - 2.5;
+    assert!((left / excess - (-1.0f64).exp()).abs() < 1e-12);
+    // The cytoplasm has no such exchange
+    let inside = acc.inside(Ion::K).unwrap()[IDX];
+    assert!(inside < 54.4);
+    for _ in 0..100 {
+        acc.advance(dt).unwrap();
+    }
+    assert_eq!(acc.inside(Ion::K).unwrap()[IDX], inside);
+}
+
+#[test]
+fn concentrations_agree_with_the_energy_ledger() {
+    let mut compartments = model(true);
+    let mut acc = IonAccumulation::new(&compartments, AccumulationConfig::default()).unwrap();
+    let mut ledger = EnergyLedger::new(compartments.components.len());
+    let trace = spike(&mut compartments, Some(&mut acc), Some(&mut ledger), 0.01);
+    assert!(trace.iter().cloned().fold(f64::MIN, f64::max) > 20.0);
+
+    let report = ledger.report();
+    let c = &compartments.components[IDX];
+    let volume = PI * c.diam * c.diam / 4.0 * c.length;
+    for (ion, start) in [(Ion::K, 54.4), (Ion::Na, 10.0)] {
+        let lost = start - acc.inside(ion).unwrap()[IDX];
+        let charge = lost * volume * FARADAY / 1e6;
+        let booked = report.charge[IDX][&ion];
+        assert!(
+            (charge - booked).abs() < 1e-9 * booked.abs(),
+            "{:?}: {} against {}",
+            ion,
+            charge,
+            booked
+        );
+    }
+    // The spike moved E_K up a little
+    assert!(hh(&compartments).ek > nernst(1, 54.4, 2.5, 6.3));
+}
+
+#[test]
+fn bad_configurations_are_refused() {
+    let compartments = model(true);
+    let new = |config| IonAccumulation::new(&compartments, config);
+    let base = AccumulationConfig::default;
+    assert!(new(base()).is_ok());
+    assert!(
+        new(AccumulationConfig {
+            shell_thickness: 0.0,
+            ..base()
+        })
+        .is_err()
+    );
+    assert!(
+        new(AccumulationConfig {
+            tau: -1.0,
+            ..base()
+        })
+        .is_err()
+    );
+    assert!(
+        new(AccumulationConfig {
+            ions: vec![TrackedIon::potassium(), TrackedIon::potassium()],
+            ..base()
+        })
+        .is_err()
+    );
+    let leak = TrackedIon {
+        ion: Ion::NonSpecific,
+        ..TrackedIon::sodium()
+    };
+    assert!(
+        new(AccumulationConfig {
+            ions: vec![leak],
+            ..base()
+        })
+        .is_err()
+    );
+
+    // Draining the cytoplasm fails without changing anything
+    let mut acc = new(base()).unwrap();
+    acc.record(IDX, Ion::Na, 1e6);
+    assert!(acc.advance(1.0).is_err());
+    assert_eq!(acc.inside(Ion::Na).unwrap()[IDX], 10.0);
+}
+
+/// `model` with the axial resistance and capacitance a simulation needs
+fn cell(dynamic: bool) -> Compartments {
+    let mut compartments = model(dynamic);
+    for c in compartments.components.iter_mut() {
+        let mut channel = c.channel.clone();
+        channel.resistance = 100.0;
+        channel.capacitance = 1.0;
+        c.set_channel(channel);
+    }
+    compartments
+}
+
+/// Only potassium tracked, with the shell exchanging with the bath over
+/// `tau`
+fn potassium(tau: f64) -> AccumulationConfig {
+    AccumulationConfig {
+        ions: vec![TrackedIon::potassium()],
+        tau,
+        ..AccumulationConfig::default()
+    }
+}
+
+#[test]
+fn a_simulation_without_opted_in_mechanisms_is_bit_identical() {
+    let compartments = cell(false);
+    let steps = 2000;
+    let pulse = (0..steps)
+        .map(|s| if (100..200).contains(&s) { 0.2 } else { 0.0 })
+        .collect();
+    let stimuli = [(IDX, pulse)];
+    let plain = Simulation::new(&compartments, 0.01)
+        .unwrap()
+        .run(steps, &stimuli)
+        .unwrap();
+    let acc = IonAccumulation::new(&compartments, AccumulationConfig::default()).unwrap();
+    let mut simulation = Simulation::new(&compartments, 0.01)
+        .unwrap()
+        .with_accumulation(acc)
+        .unwrap();
+    simulation.record("comp[2].ko").unwrap();
+    let tracked = simulation.run(steps, &stimuli).unwrap();
+
+    let v = &plain.voltages[IDX];
+    assert!(v.iter().cloned().fold(f64::MIN, f64::max) > 20.0);
+    let bits = |t: &[f64]| t.iter().map(|v| v.to_bits()).collect::<Vec<_>>();
+    assert_eq!(bits(&tracked.voltages[IDX]), bits(v));
+    assert!(plain.traces.is_empty());
+    // The concentrations moved all the same
+    let ko = &tracked.traces[0].1;
+    assert_eq!(ko.len(), steps + 1);
+    assert_eq!(ko[0], 2.5);
+    assert!(ko[steps] > 2.5);
+}
+
+#[test]
+fn a_sustained_k_current_in_a_sealed_compartment_moves_ek_by_nernst() {
+    let compartments = cell(true);
+    let config = potassium(f64::INFINITY);
+    let acc = IonAccumulation::new(&compartments, config.clone()).unwrap();
+    let (dt, steps) = (0.025, 80);
+    let mut simulation = Simulation::new(&compartments, dt)
+        .unwrap()
+        .with_accumulation(acc)
+        .unwrap();
+    simulation.clamp(IDX, Some(0.0)).unwrap();
+    for path in [
+        "comp[2].ki",
+        "comp[2].ko",
+        "comp[2].ek",
+        "comp[2].hh.n",
+        "comp[2].v",
+    ] {
+        simulation.record(path).unwrap();
+    }
+    // Sodium is not tracked, and the soma has no volume to keep any in
+    assert!(simulation.record("comp[2].nai").is_err());
+    assert!(
+        simulation
+            .list_paths("comp[2].")
+            .contains(&"comp[2].ek".to_owned())
+    );
+    let result = simulation.run(steps, &[]).unwrap();
+    let trace = |k: usize| &result.traces[k].1;
+    let (ki, ko, ek, n, v) = (trace(0), trace(1), trace(2), trace(3), trace(4));
+
+    // The mechanism's own E_K gave way to the concentrations' from the start
+    let at_rest = nernst(1, 54.4, 2.5, config.celsius);
+    assert_eq!(ek[0], at_rest);
+    assert_ne!(HodgkinHuxley::default().ek, at_rest);
+
+    // K charge through the clamped membrane, each step at the E_K the
+    // step before left
+    let hh = HodgkinHuxley::default();
+    let area = compartments.components[IDX].membrane_area();
+    let charge: f64 = (0..steps)
+        .map(|s| hh.gkbar * n[s + 1].powi(4) * area * 10.0 * (v[s + 1] - ek[s]) * 1e-3 * dt)
+        .sum();
+    assert!(charge > 0.0);
+    let mmol = charge / FARADAY * 1e6;
+    let c = &compartments.components[IDX];
+    let r = c.diam / 2.0;
+    let volume = PI * r * r * c.length;
+    let d = config.shell_thickness;
+    let shell = PI * ((r + d).powi(2) - r * r) * c.length;
+    let (expected_ki, expected_ko) = (54.4 - mmol / volume, 2.5 + mmol / shell);
+    assert!((ki[steps] - expected_ki).abs() < 1e-9 * expected_ki);
+    assert!((ko[steps] - expected_ko).abs() < 1e-9 * expected_ko);
+    assert!(ko[steps] > 4.0);
+
+    let expected = nernst(1, expected_ki, expected_ko, config.celsius);
+    assert!(
+        (ek[steps] - expected).abs() < 1e-9,
+        "{} against {}",
+        ek[steps],
+        expected
+    );
+    assert!(ek[steps] - at_rest > 10.0);
+    assert!(ek.windows(2).all(|w| w[1] > w[0]));
+}
+
+#[test]
+fn a_simulated_shell_relaxes_to_the_bath_over_tau() {
+    let compartments = cell(true);
+    let tau = 20.0;
+    let acc = IonAccumulation::new(&compartments, potassium(tau)).unwrap();
+    let dt = 0.025;
+    let mut simulation = Simulation::new(&compartments, dt)
+        .unwrap()
+        .with_accumulation(acc)
+        .unwrap();
+    simulation.clamp(IDX, Some(0.0)).unwrap();
+    for _ in 0..80 {
+        simulation.step().unwrap();
+    }
+    let excess = simulation.get("comp[2].ko").unwrap() - 2.5;
+    assert!(excess > 1.0);
+    let ki = simulation.get("comp[2].ki").unwrap();
+
+    // Held at E_K, no K crosses the membrane and the shell only drains
+    for _ in 0..(tau / dt) as usize {
+        let ek = simulation.get("comp[2].ek").unwrap();
+        simulation.clamp(IDX, Some(ek)).unwrap();
+        simulation.step().unwrap();
+    }
+    let left = simulation.get("comp[2].ko").unwrap() - 2.5;
+    assert!(
+        (left / excess - (-1.0f64).exp()).abs() < 1e-9,
+        "{}",
+        left / excess
+    );
+    assert_eq!(simulation.get("comp[2].ki").unwrap(), ki);
+}
+
+#[test]
+fn accumulation_must_fit_the_simulation() {
+    let compartments = cell(true);
+    let swc = "1 1 0 0 0 5 -1\n2 3 100 0 0 1 1\n3 3 200 0 0 1 2\n";
+    let skeleton = swc_reader_from_bytes(swc.as_bytes(), &ReaderOptions::default()).unwrap();
+    let other = Compartments::from_skeleton(skeleton);
+    let acc = IonAccumulation::new(&other, AccumulationConfig::default()).unwrap();
+    let simulation = Simulation::new(&compartments, 0.025).unwrap();
+    let error = simulation.with_accumulation(acc).unwrap_err();
+    assert!(matches!(error, SimError::InvalidModel { .. }), "{}", error);
+    assert!(error.to_string().contains("compartments"), "{}", error);
+
+    let simulation = Simulation::new(&compartments, 0.025).unwrap();
+    assert!(simulation.accumulation().is_none());
+    let error = simulation.get("comp[2].ko").unwrap_err();
+    assert!(error.to_string().contains("do not accumulate"), "{}", error);
+}
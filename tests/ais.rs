@@ -0,0 +1,217 @@
+use compartment_rs::ais::{AIS_TAG, AisOptions, AxonChoice, detect_ais};
+use compartment_rs::channels::{ChannelType, Dynamics, HodgkinHuxley};
+use compartment_rs::{
+    AttachmentKind, Channel, Code, Compartments, Morphometry, ReaderOptions, Skeleton,
+    swc_reader_from_bytes,
+};
+
+/// Point soma, a 40 µm dendrite and a 100 µm axon along x with nodes every
+/// 25 µm, so a 40 µm AIS ends 15 µm into the second axon segment
+const CELL: &[u8] = b"1 1 0 0 0 5 -1\n2 3 0 20 0 1 1\n3 3 0 40 0 1 2\n\
+    4 2 25 0 0 0.5 1\n5 2 50 0 0 0.5 4\n6 2 75 0 0 0.4 5\n7 2 100 0 0 0.3 6\n";
+
+fn read(swc: &[u8]) -> Skeleton {
+    swc_reader_from_bytes(swc, &ReaderOptions::default()).unwrap()
+}
+
+fn total_area(compartments: &Compartments) -> f64 {
+    compartments
+        .components
+        .iter()
+        .skip(1)
+        .map(|c| c.membrane_area())
+        .sum()
+}
+
+fn with_hh(mut compartments: Compartments) -> Compartments {
+    for c in compartments.components.iter_mut() {
+        let mut channel = Channel::default();
+        channel.channel_type = ChannelType::HodgkinHuxley(HodgkinHuxley::new());
+        channel.resistance = 100.0;
+        channel.capacitance = 1.0;
+        c.set_channel(channel);
+    }
+    compartments
+}
+
+#[test]
+fn ais_covers_the_first_40_um() {
+    let skeleton = read(CELL);
+    let morphometry = Morphometry::new(&skeleton.nodes);
+    let (length, area) = (morphometry.total_length(), morphometry.total_area());
+    let compartment_area = total_area(&Compartments::from_skeleton(skeleton.clone()));
+
+    let mut skeleton = skeleton.edit();
+    let region = skeleton.split_ais(&AisOptions::default()).unwrap().unwrap();
+    assert_eq!(region.nodes.len(), 2);
+    assert_eq!(region.end_fraction, 1.0);
+    assert!((region.length - 40.0).abs() < 1e-9);
+    assert!(!region.short);
+    assert_eq!(skeleton.nodes.len(), 8);
+    let boundary = skeleton
+        .nodes
+        .iter()
+        .find(|n| n.node_id == region.nodes[1])
+        .unwrap();
+    assert!((boundary.x_pos - 40.0).abs() < 1e-9);
+    assert_eq!(boundary.radius, 0.5);
+
+    // The split adds a node without changing the cable
+    let morphometry = Morphometry::new(&skeleton.nodes);
+    assert!((morphometry.total_length() - length).abs() < 1e-9);
+    assert!((morphometry.total_area() - area).abs() < 1e-9 * area);
+
+    let mut compartments = Compartments::from_skeleton(skeleton.commit().unwrap());
+    assert!((total_area(&compartments) - compartment_area).abs() < 1e-9 * compartment_area);
+    assert_eq!(compartments.tag_ais(&region).unwrap(), 2);
+    let tagged = compartments.tagged(AIS_TAG);
+    for (&idx, &node) in tagged.iter().zip(&region.nodes) {
+        assert_eq!(compartments.node_to_compartment(node), Some((idx, 1.0)));
+    }
+    for a in compartments.attachments() {
+        assert_eq!((a.kind, a.from, a.to), (AttachmentKind::Tag, 0.0, 1.0));
+    }
+
+    let distance = compartments.parameter_map("path_distance").unwrap();
+    let ais_length: f64 = tagged
+        .iter()
+        .map(|&i| compartments.components[i].length)
+        .sum();
+    assert!((ais_length - 40.0).abs() < 1e-9);
+    assert!((distance[*tagged.last().unwrap()] - 40.0).abs() < 1e-9);
+    for (idx, c) in compartments.components.iter().enumerate().skip(1) {
+        let axon = c.structure == compartment_rs::StructureIdentifier::Axon;
+        let inside = axon && distance[idx] <= 40.0 + 1e-9;
+        assert_eq!(tagged.contains(&idx), inside, "compartment {}", idx);
+    }
+
+    // Tagging again replaces the earlier tags
+    assert_eq!(compartments.tag_ais(&region).unwrap(), 2);
+    assert_eq!(compartments.attachments().len(), 2);
+}
+
+#[test]
+fn an_unsplit_ais_tags_part_of_the_last_compartment() {
+    let skeleton = read(CELL);
+    let region = detect_ais(
+        &skeleton.nodes,
+        &skeleton.parent_child_map,
+        &AisOptions::default(),
+    )
+    .unwrap();
+    assert!((region.end_fraction - 0.6).abs() < 1e-12);
+    assert_eq!(region.length, 40.0);
+
+    let mut compartments = Compartments::from_skeleton(skeleton);
+    assert_eq!(compartments.tag_ais(&region).unwrap(), 2);
+    let last = compartments.attachments().last().unwrap();
+    assert!((last.to - 0.6).abs() < 1e-12);
+}
+
+#[test]
+fn profiles_reach_only_the_tagged_compartments() {
+    let mut skeleton = read(CELL).edit();
+    let region = skeleton.split_ais(&AisOptions::default()).unwrap().unwrap();
+    let mut compartments = with_hh(Compartments::from_skeleton(skeleton.commit().unwrap()));
+    assert_eq!(
+        compartments
+            .apply_param_table("section,x,parameter,value\n@ais,,gnabar_hh,3.0\n")
+            .unwrap_err()
+            .code(),
+        Code::UnknownTag
+    );
+    compartments.tag_ais(&region).unwrap();
+    assert_eq!(
+        compartments
+            .apply_param_table("section,x,parameter,value\n@ais,,gnabar_hh,3.0\n")
+            .unwrap(),
+        1
+    );
+
+    let tagged = compartments.tagged(AIS_TAG);
+    let gnabar = compartments.parameter_map("gnabar_hh").unwrap();
+    let gkbar = compartments.parameter_map("gkbar_hh").unwrap();
+    for idx in 1..compartments.components.len() {
+        let expected = if tagged.contains(&idx) { 3.0 } else { 0.12 };
+        assert_eq!(gnabar[idx], expected, "compartment {}", idx);
+        assert_eq!(gkbar[idx], 0.036);
+    }
+
+    assert_eq!(
+        compartments
+            .apply_param_table("@ais,0.5,gnabar_hh,3.0\n")
+            .unwrap_err()
+            .code(),
+        Code::MalformedParameterRow
+    );
+    let mut passive = Compartments::from_skeleton(read(CELL));
+    passive.tag_ais(&region).unwrap();
+    assert_eq!(
+        passive
+            .set_tag_param(AIS_TAG, "gnabar_hh", 3.0)
+            .unwrap_err()
+            .code(),
+        Code::MissingMechanism
+    );
+    assert_eq!(passive.parameter_map("gnabar_hh").unwrap()[2], 0.0);
+    assert!(
+        passive
+            .attach(AttachmentKind::Tag, AIS_TAG, 2, 0.0, 1.0)
+            .is_err()
+    );
+}
+
+#[test]
+fn short_and_missing_axons() {
+    // A 30 µm axon is all AIS
+    let mut short = read(b"1 1 0 0 0 5 -1\n2 2 15 0 0 0.5 1\n3 2 30 0 0 0.5 2\n").edit();
+    let region = short.split_ais(&AisOptions::default()).unwrap().unwrap();
+    assert!(region.short);
+    assert_eq!(region.length, 30.0);
+    assert_eq!(region.nodes.len(), 2);
+    assert_eq!(short.nodes.len(), 3);
+    let mut compartments = Compartments::from_skeleton(short.commit().unwrap());
+    assert_eq!(compartments.tag_ais(&region).unwrap(), 2);
+
+    let mut dendrites = read(b"1 1 0 0 0 5 -1\n2 3 20 0 0 1 1\n").edit();
+    assert_eq!(
+        detect_ais(
+            &dendrites.nodes,
+            &dendrites.parent_child_map,
+            &AisOptions::default()
+        ),
+        None
+    );
+    assert_eq!(dendrites.split_ais(&AisOptions::default()), Ok(None));
+    let invalid = AisOptions {
+        length: 0.0,
+        ..Default::default()
+    };
+    assert!(dendrites.split_ais(&invalid).is_err());
+}
+
+#[test]
+fn a_forked_proximal_axon_follows_the_chosen_child() {
+    // The axon forks 20 µm out into a thin branch listed first and a thick one
+    let swc = b"1 1 0 0 0 5 -1\n2 2 20 0 0 0.5 1\n\
+        3 2 40 10 0 0.2 2\n4 2 60 20 0 0.2 3\n\
+        5 2 40 -10 0 0.4 2\n6 2 60 -20 0 0.4 5\n";
+    let skeleton = read(swc);
+    let end_y = |follow: AxonChoice| {
+        let options = AisOptions {
+            length: 35.0,
+            follow,
+        };
+        let region = detect_ais(&skeleton.nodes, &skeleton.parent_child_map, &options).unwrap();
+        assert_eq!(region.nodes.len(), 2);
+        let last = region.nodes[1];
+        skeleton
+            .nodes
+            .iter()
+            .find(|n| n.node_id == last)
+            .unwrap()
+            .y_pos
+    };
+    assert_eq!(end_y(AxonChoice::Thicker), -10.0);
+    assert_eq!(end_y(AxonChoice::First), 10.0);
+}
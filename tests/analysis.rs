@@ -0,0 +1,680 @@
+use std::collections::HashSet;
+
+use compartment_rs::analysis::{
+    Apposition, RallBand, appositions, backpropagation_efficacy, binned_membrane_current,
+    dataset_appositions, electrotonic_lengths, junction_load_ratios, peak_depolarization,
+    rall_ratios, rall_summary, soma_transfer_impedances, transfer_impedance_matrix,
+};
+use compartment_rs::solver::{Simulation, SimulationResult};
+use compartment_rs::standardize::Dataset;
+use compartment_rs::validation::rall_qc;
+use compartment_rs::{
+    Channel, Code, Compartments, ReaderOptions, Skeleton, StructureIdentifier,
+    swc_reader_from_bytes,
+};
+
+const RA: f64 = 100.0;
+const GM: f64 = 1e-4;
+const CM: f64 = 1.0;
+
+fn passive(compartments: &mut Compartments) {
+    for c in compartments.components.iter_mut() {
+        let mut channel = Channel::default();
+        channel.resistance = RA;
+        channel.conductance = GM;
+        channel.capacitance = CM;
+        c.set_channel(channel);
+    }
+}
+
+/// Soma point then `n` segments of `dx` um along x, all 1 um across
+fn cable(n: usize, dx: f64) -> Compartments {
+    let ids: Vec<i64> = (1..=n as i64 + 1).collect();
+    let mut types = vec![3; n + 1];
+    types[0] = 1;
+    let parents: Vec<i64> = (0..=n as i64)
+        .map(|i| if i == 0 { -1 } else { i })
+        .collect();
+    let xyz: Vec<[f64; 3]> = (0..=n).map(|i| [dx * i as f64, 0.0, 0.0]).collect();
+    let skeleton = Skeleton::from_arrays(
+        &ids,
+        &types,
+        &xyz,
+        &vec![0.5; n + 1],
+        &parents,
+        &ReaderOptions::default(),
+    )
+    .unwrap();
+    let mut compartments = Compartments::from_skeleton(skeleton);
+    passive(&mut compartments);
+    compartments
+}
+
+/// Hand-computed space constant of the 1 um cable: sqrt(d / (4 Ra gm))
+/// = sqrt(1e-4 cm / (4 * 100 * 1e-4)) = 0.05 cm
+const LAMBDA_UM: f64 = 500.0;
+
+#[test]
+fn electrotonic_length_is_length_over_lambda() {
+    let compartments = cable(40, 10.0);
+    let lengths = electrotonic_lengths(&compartments);
+    assert_eq!(lengths.len(), 1);
+    assert!((lengths[0] - 400.0 / LAMBDA_UM).abs() < 1e-12);
+}
+
+#[test]
+fn dc_profile_matches_sealed_cable() {
+    let (n, dx) = (200, 5.0);
+    let length = n as f64 * dx;
+    let compartments = cable(n, dx);
+    let z = soma_transfer_impedances(&compartments, 0.0);
+
+    // Input resistance of a sealed cable, r_a * lambda * coth(L / lambda),
+    // with r_a = Ra / (pi r^2) per cm; in MOhm
+    let lambda = LAMBDA_UM * 1e-4;
+    let r_a = RA / (std::f64::consts::PI * (0.5e-4f64).powi(2));
+    let r_inf = r_a * lambda / 1e6;
+    let l = length * 1e-4 / lambda;
+    let analytic = |x_um: f64| r_inf * (l - x_um * 1e-4 / lambda).cosh() / l.sinh();
+
+    assert!((z[1].re - analytic(0.0)).abs() / analytic(0.0) < 0.01);
+    for (i, zi) in z.iter().enumerate().skip(2) {
+        // Compartment i spans the segment ending at its node
+        let x = (i as f64 - 1.5) * dx;
+        let expected = analytic(x);
+        assert!(
+            (zi.re - expected).abs() / expected < 0.01,
+            "{}: {} vs {}",
+            i,
+            zi.re,
+            expected
+        );
+        assert_eq!(zi.im, 0.0);
+    }
+}
+
+#[test]
+fn dc_matrix_is_symmetric_and_matches_soma_column() {
+    // A small branched tree: soma, trunk, two daughters of different widths
+    let ids = [1, 2, 3, 4, 5, 6, 7];
+    let types = [1, 3, 3, 3, 3, 3, 3];
+    let xyz = [
+        [0.0, 0.0, 0.0],
+        [20.0, 0.0, 0.0],
+        [40.0, 0.0, 0.0],
+        [60.0, 20.0, 0.0],
+        [80.0, 40.0, 0.0],
+        [60.0, -20.0, 0.0],
+        [80.0, -40.0, 0.0],
+    ];
+    let radii = [5.0, 1.0, 1.0, 0.5, 0.5, 0.3, 0.3];
+    let parents = [-1, 1, 2, 3, 4, 3, 6];
+    let skeleton = Skeleton::from_arrays(
+        &ids,
+        &types,
+        &xyz,
+        &radii,
+        &parents,
+        &ReaderOptions::default(),
+    )
+    .unwrap();
+    let mut compartments = Compartments::from_skeleton(skeleton);
+    passive(&mut compartments);
+
+    for frequency in [0.0, 100.0] {
+        let matrix = transfer_impedance_matrix(&compartments, frequency);
+        let soma = soma_transfer_impedances(&compartments, frequency);
+        let n = matrix.len();
+        for i in 1..n {
+            for (j, row) in matrix.iter().enumerate().skip(1) {
+                let (a, b) = (matrix[i][j], row[i]);
+                assert!((a.re - b.re).abs() <= 1e-9 * a.magnitude());
+                assert!((a.im - b.im).abs() <= 1e-9 * a.magnitude());
+            }
+            assert!((matrix[i][1].re - soma[i].re).abs() <= 1e-12 * soma[i].magnitude());
+            // Attenuation: nothing is more than the input impedance
+            assert!(matrix[i][1].magnitude() <= matrix[1][1].magnitude() * (1.0 + 1e-12));
+        }
+    }
+
+    // Three branches: trunk and two daughters
+    assert_eq!(electrotonic_lengths(&compartments).len(), 3);
+}
+
+#[test]
+fn capacitance_filters_higher_frequencies() {
+    let compartments = cable(50, 10.0);
+    let dc = soma_transfer_impedances(&compartments, 0.0);
+    let ac = soma_transfer_impedances(&compartments, 500.0);
+    let tip = dc.len() - 1;
+    assert!(ac[1].magnitude() < dc[1].magnitude());
+    assert!(ac[tip].magnitude() / ac[1].magnitude() < dc[tip].magnitude() / dc[1].magnitude());
+    assert!(ac[1].phase() < 0.0);
+}
+
+/// Soma with a basal tree branching twice and an axon branching once. The
+/// first basal junction follows the 3/2 rule exactly, the axon's has a
+/// daughter fatter than its parent.
+fn junctions() -> Skeleton {
+    let parent = 0.5 * 2f64.powf(2.0 / 3.0);
+    let swc = format!(
+        "1 1 0 0 0 5 -1\n\
+         2 3 10 0 0 {parent} 1\n\
+         3 3 20 5 0 0.5 2\n\
+         4 3 20 -5 0 0.5 2\n\
+         5 2 -10 0 0 0.5 1\n\
+         6 2 -20 5 0 1.0 5\n\
+         7 2 -20 -5 0 0.5 5\n\
+         8 3 30 10 0 0.4 3\n\
+         9 3 30 0 0 0.4 3\n"
+    );
+    swc_reader_from_bytes(swc.as_bytes(), &ReaderOptions::default()).unwrap()
+}
+
+#[test]
+fn rall_ratios_find_every_branch_point_off_the_soma() {
+    let skeleton = junctions();
+    let reports = rall_ratios(&skeleton.nodes, &skeleton.parent_child_map);
+    assert_eq!(reports.len(), 3);
+    let exact = reports
+        .iter()
+        .find(|r| r.structure == StructureIdentifier::BasalDendrite && r.child_diams == [1.0, 1.0])
+        .unwrap();
+    assert!((exact.ratio - 1.0).abs() < 1e-12, "{}", exact.ratio);
+
+    let band = RallBand::default();
+    let axon = reports
+        .iter()
+        .find(|r| r.structure == StructureIdentifier::Axon)
+        .unwrap();
+    assert!(axon.ratio > band.high);
+    let qc = rall_qc(&reports, &band);
+    assert!(!qc.passed());
+    assert_eq!(qc.checked, 3);
+    let flagged: Vec<u64> = qc.warnings.iter().map(|w| w.node_id).collect();
+    assert!(flagged.contains(&axon.node_id));
+    assert!(!flagged.contains(&exact.node_id));
+    assert!(qc.warnings.iter().all(|w| w.code == Code::RallMismatch));
+    assert!(
+        qc.summary().starts_with("FAIL rall_ratio:"),
+        "{}",
+        qc.summary()
+    );
+    assert!(
+        qc.to_json()
+            .contains("\"code\":\"W_MORPH_0001_RALL_MISMATCH\"")
+    );
+}
+
+#[test]
+fn rall_summaries_partition_by_type() {
+    let skeleton = junctions();
+    let reports = rall_ratios(&skeleton.nodes, &skeleton.parent_child_map);
+    let summary = rall_summary(&reports);
+    let types: Vec<StructureIdentifier> = summary.keys().copied().collect();
+    assert_eq!(
+        types,
+        [
+            StructureIdentifier::Axon,
+            StructureIdentifier::BasalDendrite
+        ]
+    );
+    assert_eq!(
+        summary.values().map(|s| s.count).sum::<usize>(),
+        reports.len()
+    );
+    let basal = &summary[&StructureIdentifier::BasalDendrite];
+    assert_eq!(basal.count, 2);
+    let basal_ratios: Vec<f64> = reports
+        .iter()
+        .filter(|r| r.structure == StructureIdentifier::BasalDendrite)
+        .map(|r| r.ratio)
+        .collect();
+    assert_eq!(
+        basal.min,
+        basal_ratios.iter().copied().fold(f64::INFINITY, f64::min)
+    );
+    assert_eq!(basal.max, basal_ratios.iter().copied().fold(0.0, f64::max));
+    assert!((basal.median - (basal_ratios[0] + basal_ratios[1]) / 2.0).abs() < 1e-12);
+    assert_eq!(summary[&StructureIdentifier::Axon].count, 1);
+}
+
+#[test]
+fn uniform_junction_loads_match_the_geometric_ratio() {
+    let skeleton = junctions();
+    let reports = rall_ratios(&skeleton.nodes, &skeleton.parent_child_map);
+    let mut compartments = Compartments::from_skeleton(skeleton);
+    passive(&mut compartments);
+    let junctions = junction_load_ratios(&compartments);
+    assert_eq!(junctions.len(), reports.len());
+    for junction in &junctions {
+        // Compartments sit one past their node
+        let report = reports
+            .iter()
+            .find(|r| r.node_id + 1 == junction.idx as u64)
+            .unwrap();
+        assert!(
+            (junction.ratio - report.ratio).abs() < 1e-12,
+            "{} vs {}",
+            junction.ratio,
+            report.ratio
+        );
+        assert_eq!(junction.structure, report.structure);
+    }
+
+    // A leakier daughter draws more current than its diameter says
+    let first = junctions[0].idx;
+    let child = compartments.components[first].children_idxs[0] as usize;
+    compartments.components[child].channel.conductance *= 4.0;
+    let loaded = junction_load_ratios(&compartments);
+    assert!(loaded[0].ratio > junctions[0].ratio);
+}
+
+const AXON: StructureIdentifier = StructureIdentifier::Axon;
+const DENDRITE: StructureIdentifier = StructureIdentifier::BasalDendrite;
+
+/// SWC of a soma at `soma` with one straight run of `type_id` nodes through
+/// `points`
+fn run_swc(soma: [f64; 3], type_id: u8, points: &[[f64; 3]]) -> String {
+    let mut swc = format!("1 1 {} {} {} 2 -1\n", soma[0], soma[1], soma[2]);
+    for (k, p) in points.iter().enumerate() {
+        swc.push_str(&format!(
+            "{} {} {} {} {} 0.5 {}\n",
+            k + 2,
+            type_id,
+            p[0],
+            p[1],
+            p[2],
+            k + 1
+        ));
+    }
+    swc
+}
+
+fn read(swc: &str) -> Skeleton {
+    swc_reader_from_bytes(swc.as_bytes(), &ReaderOptions::default()).unwrap()
+}
+
+/// SWC of an axon along x and of a dendrite alongside it, `separation` µm
+/// away, both 100 µm long in 10 µm segments
+fn parallel_swc(separation: f64) -> (String, String) {
+    let line = |y: f64| -> Vec<[f64; 3]> { (1..=10).map(|k| [10.0 * k as f64, y, 0.0]).collect() };
+    (
+        run_swc([0.0, 0.0, 0.0], 2, &line(0.0)),
+        run_swc([0.0, separation, 0.0], 3, &line(separation)),
+    )
+}
+
+fn parallel(separation: f64) -> (Skeleton, Skeleton) {
+    let (a, b) = parallel_swc(separation);
+    (read(&a), read(&b))
+}
+
+#[test]
+fn parallel_cables_appose_once_the_threshold_reaches_them() {
+    let (a, b) = parallel(3.0);
+    assert!(appositions((&a, &b), (AXON, DENDRITE), 2.999).is_empty());
+    let found = appositions((&a, &b), (AXON, DENDRITE), 3.001);
+    // The whole run is one stretch, however many segment pairs it has
+    assert_eq!(found.len(), 1);
+    let zone = &found[0];
+    assert!(zone.pairs.len() >= 10);
+    assert_eq!(zone.a_segments.len(), 10);
+    assert_eq!(zone.b_segments.len(), 10);
+    assert!((zone.a_extent - 100.0).abs() < 1e-9);
+    assert!((zone.b_extent - 100.0).abs() < 1e-9);
+    assert!((zone.distance - 3.0).abs() < 1e-12);
+    assert!((zone.b_point[1] - zone.a_point[1] - 3.0).abs() < 1e-12);
+
+    // Wrong roles find nothing: the first cell has no dendrite
+    assert!(appositions((&a, &b), (DENDRITE, AXON), 10.0).is_empty());
+}
+
+#[test]
+fn separate_crossings_are_separate_appositions() {
+    let a = parallel(3.0).0;
+    let swc = "1 1 50 30 2 2 -1\n\
+               2 3 20 30 2 0.5 1\n\
+               3 3 20 -30 2 0.5 2\n\
+               4 3 80 30 2 0.5 1\n\
+               5 3 80 -30 2 0.5 4\n";
+    let b = read(swc);
+    let found = appositions((&a, &b), (AXON, DENDRITE), 2.5);
+    assert_eq!(found.len(), 2);
+    for (zone, x) in found.iter().zip([20.0, 80.0]) {
+        assert!((zone.distance - 2.0).abs() < 1e-12);
+        assert!((zone.a_point[0] - x).abs() < 1e-9, "{:?}", zone.a_point);
+        assert_eq!(zone.b_segments.len(), 1);
+    }
+}
+
+/// Pairs, segments on each side, distance to 1e-9 and extents as bits
+type Comparable = (Vec<(u64, u64)>, Vec<u64>, Vec<u64>, i64, u64, u64);
+
+/// Comparable form of an apposition with the cells' roles swapped; distances
+/// agree to rounding, as the closest-point solve runs with its arguments swapped
+fn swapped(zone: &Apposition) -> Comparable {
+    let mut pairs: Vec<(u64, u64)> = zone.pairs.iter().map(|&(a, b)| (b, a)).collect();
+    pairs.sort();
+    (
+        pairs,
+        zone.b_segments.clone(),
+        zone.a_segments.clone(),
+        (zone.distance * 1e9).round() as i64,
+        zone.b_extent.to_bits(),
+        zone.a_extent.to_bits(),
+    )
+}
+
+fn as_is(zone: &Apposition) -> Comparable {
+    (
+        zone.pairs.clone(),
+        zone.a_segments.clone(),
+        zone.b_segments.clone(),
+        (zone.distance * 1e9).round() as i64,
+        zone.a_extent.to_bits(),
+        zone.b_extent.to_bits(),
+    )
+}
+
+/// A random tree of `n` nodes of `type_id` grown in 5 µm steps from `soma`
+fn random_tree(seed: u64, n: usize, soma: [f64; 3], type_id: u8) -> Skeleton {
+    let mut state = seed;
+    let mut uniform = move || {
+        state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (state >> 11) as f64 / (1u64 << 53) as f64
+    };
+    let mut points = vec![soma];
+    let mut swc = format!("1 1 {} {} {} 2 -1\n", soma[0], soma[1], soma[2]);
+    for k in 1..n {
+        let parent = (uniform() * k as f64) as usize;
+        let (theta, z) = (uniform() * std::f64::consts::TAU, 2.0 * uniform() - 1.0);
+        let r = (1.0 - z * z).sqrt();
+        let p = points[parent];
+        let q = [
+            p[0] + 5.0 * r * theta.cos(),
+            p[1] + 5.0 * r * theta.sin(),
+            p[2] + 5.0 * z,
+        ];
+        swc.push_str(&format!(
+            "{} {} {} {} {} 0.5 {}\n",
+            k + 1,
+            type_id,
+            q[0],
+            q[1],
+            q[2],
+            parent + 1
+        ));
+        points.push(q);
+    }
+    read(&swc)
+}
+
+/// Distance between two segments by ternary search over the first, a
+/// different route from the one under test
+fn brute_distance(p0: [f64; 3], p1: [f64; 3], q0: [f64; 3], q1: [f64; 3]) -> f64 {
+    let at = |a: [f64; 3], b: [f64; 3], s: f64| -> [f64; 3] {
+        std::array::from_fn(|k| a[k] + s * (b[k] - a[k]))
+    };
+    let to_q = |p: [f64; 3]| {
+        let d: [f64; 3] = std::array::from_fn(|k| q1[k] - q0[k]);
+        let len2: f64 = d.iter().map(|x| x * x).sum();
+        let t = if len2 > 0.0 {
+            ((0..3).map(|k| (p[k] - q0[k]) * d[k]).sum::<f64>() / len2).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let c = at(q0, q1, t);
+        (0..3).map(|k| (p[k] - c[k]).powi(2)).sum::<f64>().sqrt()
+    };
+    let (mut lo, mut hi) = (0.0, 1.0);
+    for _ in 0..200 {
+        let (m1, m2) = (lo + (hi - lo) / 3.0, hi - (hi - lo) / 3.0);
+        if to_q(at(p0, p1, m1)) <= to_q(at(p0, p1, m2)) {
+            hi = m2;
+        } else {
+            lo = m1;
+        }
+    }
+    to_q(at(p0, p1, (lo + hi) / 2.0))
+}
+
+fn node_segments(
+    skeleton: &Skeleton,
+    type_id: StructureIdentifier,
+) -> Vec<(u64, [f64; 3], [f64; 3])> {
+    let at = |id: u64| {
+        let n = skeleton.nodes.iter().find(|n| n.node_id == id).unwrap();
+        [n.x_pos, n.y_pos, n.z_pos]
+    };
+    skeleton
+        .nodes
+        .iter()
+        .filter(|n| n.structured_identifier == type_id && n.parent_id != n.node_id)
+        .map(|n| (n.node_id, at(n.parent_id), at(n.node_id)))
+        .collect()
+}
+
+#[test]
+fn indexed_search_agrees_with_brute_force_and_is_symmetric() {
+    let a = random_tree(1, 150, [0.0, 0.0, 0.0], 2);
+    let b = random_tree(2, 150, [8.0, 0.0, 0.0], 3);
+    let threshold = 3.0;
+    let found = appositions((&a, &b), (AXON, DENDRITE), threshold);
+    assert!(found.len() > 1, "{} appositions", found.len());
+
+    let indexed: HashSet<(u64, u64)> = found.iter().flat_map(|z| z.pairs.clone()).collect();
+    let (mut expected, mut borderline) = (HashSet::new(), 0);
+    for (i, p0, p1) in node_segments(&a, AXON) {
+        for (j, q0, q1) in node_segments(&b, DENDRITE) {
+            let d = brute_distance(p0, p1, q0, q1);
+            if (d - threshold).abs() < 1e-9 {
+                borderline += 1;
+                continue;
+            }
+            if d < threshold {
+                expected.insert((i, j));
+            }
+        }
+    }
+    assert_eq!(borderline, 0);
+    assert_eq!(indexed, expected);
+
+    // Swapping the cells swaps the roles and nothing else
+    let reverse = appositions((&b, &a), (DENDRITE, AXON), threshold);
+    let mut forward: Vec<_> = found.iter().map(swapped).collect();
+    let mut backward: Vec<_> = reverse.iter().map(as_is).collect();
+    forward.sort();
+    backward.sort();
+    assert_eq!(forward, backward);
+}
+
+#[test]
+fn dataset_pairs_are_capped() {
+    let dir = std::env::temp_dir().join(format!("appositions-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let (axon, dendrite) = parallel_swc(3.0);
+    std::fs::write(dir.join("a.swc"), &axon).unwrap();
+    std::fs::write(dir.join("b.swc"), &dendrite).unwrap();
+    std::fs::write(dir.join("c.swc"), &axon).unwrap();
+    let dataset = Dataset::from_dir(&dir).unwrap();
+
+    let pairs = dataset_appositions(&dataset, (AXON, DENDRITE), 3.5, 6).unwrap();
+    assert_eq!(pairs.len(), 6);
+    let counts: Vec<(String, String, usize)> = pairs
+        .iter()
+        .map(|p| {
+            let name =
+                |path: &std::path::Path| path.file_name().unwrap().to_string_lossy().into_owned();
+            (name(&p.a), name(&p.b), p.appositions.len())
+        })
+        .filter(|(_, _, n)| *n > 0)
+        .collect();
+    assert_eq!(
+        counts,
+        [
+            ("a.swc".to_owned(), "b.swc".to_owned(), 1),
+            ("c.swc".to_owned(), "b.swc".to_owned(), 1)
+        ]
+    );
+    let error = dataset_appositions(&dataset, (AXON, DENDRITE), 3.5, 5).unwrap_err();
+    assert!(error.contains("6 pairs"), "{}", error);
+}
+
+/// A 1 ms spike-shaped current into the first compartment of a 500 um
+/// cable, run for 10 ms at 0.025 ms
+fn backpropagated(recorded: Option<&[usize]>) -> SimulationResult {
+    let compartments = cable(20, 25.0);
+    let mut simulation = Simulation::new(&compartments, 0.025).unwrap();
+    if let Some(idxs) = recorded {
+        simulation.record_voltages_of(idxs).unwrap();
+    }
+    let spike = (0..400)
+        .map(|s| match s {
+            40..60 => 0.05 * (s - 39) as f64,
+            60..80 => 0.05 * (80 - s) as f64,
+            _ => 0.0,
+        })
+        .collect();
+    simulation.run(400, &[(2, spike)]).unwrap()
+}
+
+#[test]
+fn backpropagation_decays_along_a_passive_cable() {
+    let result = backpropagated(None);
+    let efficacy = backpropagation_efficacy(&result, 2).unwrap();
+    assert_eq!(efficacy.len(), 22);
+    assert!(efficacy[0].is_nan());
+    assert_eq!(efficacy[2], 1.0);
+    for i in 3..22 {
+        assert!(
+            efficacy[i] < efficacy[i - 1] && efficacy[i] > 0.0,
+            "{:?}",
+            efficacy
+        );
+    }
+
+    let peaks = peak_depolarization(&result, (0.0, 10.0)).unwrap();
+    let rest = result.voltages[21][0];
+    assert!(((peaks[21] - rest) / (peaks[2] - rest) - efficacy[21]).abs() < 1e-12);
+    // Before the current starts, nothing has moved
+    let early = peak_depolarization(&result, (0.0, 0.5)).unwrap();
+    assert!(early[2..].iter().all(|&v| v == rest), "{:?}", early);
+    assert!(peak_depolarization(&result, (2.0, 1.0)).is_err());
+    assert!(peak_depolarization(&result, (11.0, 12.0)).is_err());
+}
+
+#[test]
+fn unrecorded_compartments_are_nan() {
+    let full = backpropagated(None);
+    let partial = backpropagated(Some(&[2, 5, 9, 21]));
+    let recorded = [2, 5, 9, 21];
+    for (i, trace) in partial.voltages.iter().enumerate() {
+        assert_eq!(trace.is_empty(), !recorded.contains(&i), "{}", i);
+    }
+
+    let efficacy = backpropagation_efficacy(&full, 2).unwrap();
+    let subset = backpropagation_efficacy(&partial, 2).unwrap();
+    let peaks = peak_depolarization(&partial, (1.0, 3.0)).unwrap();
+    for i in 0..22 {
+        match recorded.contains(&i) {
+            true => {
+                assert_eq!(subset[i], efficacy[i]);
+                assert!(peaks[i].is_finite());
+            }
+            false => assert!(subset[i].is_nan() && peaks[i].is_nan(), "{}", i),
+        }
+    }
+    let error = backpropagation_efficacy(&partial, 3).unwrap_err();
+    assert!(error.contains("compartment 3"), "{}", error);
+}
+
+/// `cable(n, 10.0)` run for 200 steps of 0.025 ms with every membrane
+/// current recorded and `stimuli` injected
+fn membrane_currents(n: usize, stimuli: &[(usize, Vec<f64>)]) -> (Compartments, SimulationResult) {
+    let compartments = cable(n, 10.0);
+    let mut simulation = Simulation::new(&compartments, 0.025).unwrap();
+    simulation.record_membrane_currents();
+    let result = simulation.run(200, stimuli).unwrap();
+    (compartments, result)
+}
+
+#[test]
+fn binned_membrane_currents_add_up_to_the_injected_current() {
+    let ramp: Vec<f64> = (0..200).map(|s| 0.002 * s as f64).collect();
+    let pulse: Vec<f64> = (0..200)
+        .map(|s| if (50..120).contains(&s) { -0.3 } else { 0.0 })
+        .collect();
+    let (compartments, result) = membrane_currents(20, &[(1, ramp.clone()), (15, pulse.clone())]);
+    let binned =
+        binned_membrane_current(&result, &compartments, 0, &[0.0, 50.0, 100.0, 150.0, 200.0])
+            .unwrap();
+    assert_eq!(binned.currents.len(), 4);
+    assert!(binned.unassigned.is_empty());
+    // Nothing flowed before the first step; after step s, what went in
+    // over it has left through some membrane
+    for (s, sample) in (0..201).map(|s| (s, binned.currents.iter().map(move |bin| bin[s]))) {
+        let total: f64 = sample.sum();
+        let injected = match s {
+            0 => 0.0,
+            s => ramp[s - 1] + pulse[s - 1],
+        };
+        assert!((total - injected).abs() < 1e-9, "{} at step {}", total, s);
+    }
+    // The pulse went in at 135 um, and kept out of the first bin mostly
+    assert!(binned.currents[2][100] < binned.currents[0][100]);
+}
+
+#[test]
+fn compartments_outside_the_bins_are_unassigned() {
+    let (compartments, result) = membrane_currents(20, &[]);
+    // Centres sit at 5, 15, ... 195 um past the soma at 0
+    let binned = binned_membrane_current(&result, &compartments, 0, &[0.0, 50.0, 100.0]).unwrap();
+    assert_eq!(binned.unassigned, (12..22).collect::<Vec<usize>>());
+    assert_eq!(binned.edges, [0.0, 50.0, 100.0]);
+    // Nothing lies off the cable's axis
+    let binned = binned_membrane_current(&result, &compartments, 1, &[1.0, 2.0]).unwrap();
+    assert_eq!(binned.unassigned.len(), 21);
+
+    assert!(binned_membrane_current(&result, &compartments, 3, &[0.0, 1.0]).is_err());
+    assert!(binned_membrane_current(&result, &compartments, 0, &[1.0, 1.0]).is_err());
+    assert!(binned_membrane_current(&result, &compartments, 0, &[0.0]).is_err());
+    let unrecorded = Simulation::new(&compartments, 0.025)
+        .unwrap()
+        .run(1, &[])
+        .unwrap();
+    let error = binned_membrane_current(&unrecorded, &compartments, 0, &[0.0, 1.0]).unwrap_err();
+    assert!(error.contains("compartment 1"), "{}", error);
+}
+
+#[test]
+fn two_compartments_land_in_their_own_bins() {
+    // Compartment 2 spans 0 to 10 um and 3 spans 10 to 20, so their
+    // centres fall in different bins, the soma's in the first
+    let compartments = cable(2, 10.0);
+    let mut simulation = Simulation::new(&compartments, 0.025).unwrap();
+    simulation.record_membrane_currents();
+    simulation.add_axial_current_probe(2, 3).unwrap();
+    let result = simulation.run(200, &[(3, vec![0.1; 200])]).unwrap();
+    let binned = binned_membrane_current(&result, &compartments, 0, &[-5.0, 10.0, 25.0]).unwrap();
+    assert!(binned.unassigned.is_empty());
+    // The point soma has no membrane, so compartment 2 loses all it gets
+    // from 3, and 3 the rest of what went in
+    let axial = &result.axial_currents[0].1;
+    for (s, a) in axial.iter().enumerate().skip(1) {
+        assert!((binned.currents[0][s] + a).abs() < 1e-12, "{}", s);
+        assert!((binned.currents[1][s] - (0.1 + a)).abs() < 1e-12, "{}", s);
+    }
+    // 10 um is electrically short, so by the end the two same-sized
+    // membranes share the current about evenly
+    let (near, far) = (binned.currents[0][200], binned.currents[1][200]);
+    assert!(
+        near > 0.0 && far >= near && far - near < 1e-6,
+        "{} {}",
+        near,
+        far
+    );
+}
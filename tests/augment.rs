@@ -0,0 +1,116 @@
+use compartment_rs::augment;
+use compartment_rs::{NodeFlags, ReaderOptions, Skeleton, swc_reader};
+
+fn basic() -> Skeleton {
+    swc_reader("data/basic.swc", &ReaderOptions::default()).unwrap()
+}
+
+fn coords(skeleton: &Skeleton) -> Vec<[f64; 3]> {
+    skeleton
+        .nodes
+        .iter()
+        .map(|n| [n.x_pos, n.y_pos, n.z_pos])
+        .collect()
+}
+
+#[test]
+fn seeded_and_topology_preserving() {
+    let skeleton = basic();
+    let a = augment::jitter_coordinates(&skeleton, 1.0, 10.0, 7).unwrap();
+    let b = augment::jitter_coordinates(&skeleton, 1.0, 10.0, 7).unwrap();
+    let c = augment::jitter_coordinates(&skeleton, 1.0, 10.0, 8).unwrap();
+    assert_eq!(coords(&a), coords(&b));
+    assert_ne!(coords(&a), coords(&c));
+    assert_eq!(a.nodes.len(), skeleton.nodes.len());
+    assert_eq!(a.parent_child_map, skeleton.parent_child_map);
+    assert!(a.validate_maps().is_ok());
+    assert!(
+        a.nodes
+            .iter()
+            .all(|n| n.flags.contains(NodeFlags::COORD_JITTERED))
+    );
+    // The input is left alone
+    assert_eq!(coords(&skeleton), coords(&basic()));
+
+    let scaled = augment::scale_radii(&skeleton, (0.5, 1.5), 7).unwrap();
+    assert!(scaled.validate_maps().is_ok());
+    for (s, o) in scaled.nodes.iter().zip(skeleton.nodes.iter()) {
+        let factor = s.radius / o.radius;
+        assert!((0.5..=1.5).contains(&factor));
+        assert!(s.flags.contains(NodeFlags::RADIUS_SCALED));
+    }
+    assert!(augment::scale_radii(&skeleton, (1.5, 0.5), 7).is_err());
+}
+
+#[test]
+fn drops_only_terminal_branches() {
+    let skeleton = basic();
+    let original = coords(&skeleton);
+
+    // All five terminal branches: what is left is the soma, the basal trunk
+    // up to its branch point and the apical trunk up to its branch point
+    let all = augment::drop_terminal_branches(&skeleton, 1.0, 3).unwrap();
+    assert!(all.validate_maps().is_ok());
+    assert_eq!(all.nodes.len(), 6);
+    let pruned: Vec<[f64; 3]> = all
+        .nodes
+        .iter()
+        .filter(|n| n.flags.contains(NodeFlags::BRANCH_PRUNED))
+        .map(|n| [n.x_pos, n.y_pos, n.z_pos])
+        .collect();
+    assert_eq!(
+        pruned,
+        vec![[0.0, 0.0, 0.0], [15.0, 0.0, 0.0], [0.0, 40.0, 0.0]]
+    );
+
+    let some = augment::drop_terminal_branches(&skeleton, 0.4, 3).unwrap();
+    assert_eq!(
+        augment::drop_terminal_branches(&skeleton, 0.4, 3)
+            .unwrap()
+            .nodes
+            .len(),
+        some.nodes.len()
+    );
+    assert!(some.validate_maps().is_ok());
+    // Every surviving node is an original one, and every branch point is kept
+    assert!(coords(&some).iter().all(|c| original.contains(c)));
+    for kept in [[0.0, 0.0, 0.0], [15.0, 0.0, 0.0], [0.0, 40.0, 0.0]] {
+        assert!(coords(&some).contains(&kept));
+    }
+    assert!(some.nodes.len() < skeleton.nodes.len());
+}
+
+#[test]
+fn correlation_length_matters() {
+    // A long straight cable with unit spacing
+    let n = 2000;
+    let ids: Vec<i64> = (1..=n).collect();
+    let parents: Vec<i64> = ids
+        .iter()
+        .map(|&i| if i == 1 { -1 } else { i - 1 })
+        .collect();
+    let xyz: Vec<[f64; 3]> = ids.iter().map(|&i| [i as f64, 0.0, 0.0]).collect();
+    let cable = Skeleton::from_arrays(
+        &ids,
+        &vec![3; n as usize],
+        &xyz,
+        &vec![1.0; n as usize],
+        &parents,
+        &ReaderOptions::default(),
+    )
+    .unwrap();
+
+    // Lag-one correlation of the y displacements along the cable
+    let lag_one = |correlation_length: f64| {
+        let jittered = augment::jitter_coordinates(&cable, 1.0, correlation_length, 11).unwrap();
+        let d: Vec<f64> = jittered.nodes.iter().map(|n| n.y_pos).collect();
+        let mean = d.iter().sum::<f64>() / d.len() as f64;
+        let var: f64 = d.iter().map(|v| (v - mean).powi(2)).sum();
+        let cov: f64 = d.windows(2).map(|w| (w[0] - mean) * (w[1] - mean)).sum();
+        cov / var
+    };
+    let smooth = lag_one(50.0);
+    let rough = lag_one(0.5);
+    assert!(smooth > 0.9, "{}", smooth);
+    assert!(rough < 0.3, "{}", rough);
+}
@@ -0,0 +1,84 @@
+use std::f64::consts::PI;
+
+use compartment_rs::{Channel, Compartments, ReaderOptions, Skeleton};
+
+fn build(ids: &[i64], xyz: &[[f64; 3]], radii: &[f64], parents: &[i64]) -> Compartments {
+    let mut types = vec![3; ids.len()];
+    types[0] = 1;
+    let skeleton =
+        Skeleton::from_arrays(ids, &types, xyz, radii, parents, &ReaderOptions::default()).unwrap();
+    let mut compartments = Compartments::from_skeleton(skeleton);
+    for c in compartments.components.iter_mut() {
+        let mut channel = Channel::default();
+        channel.resistance = 100.0;
+        c.set_channel(channel);
+    }
+    compartments
+}
+
+#[test]
+fn uniform_cable_matches_textbook_value() {
+    // Soma point then 10 segments of 5 um at radius 0.5 um
+    let ids: Vec<i64> = (1..=11).collect();
+    let parents: Vec<i64> = (0..11).map(|i| if i == 0 { -1 } else { i }).collect();
+    let xyz: Vec<[f64; 3]> = (0..11).map(|i| [5.0 * i as f64, 0.0, 0.0]).collect();
+    let compartments = build(&ids, &xyz, &[0.5; 11], &parents);
+
+    // Between two equal segments: Ra * L / (pi r^2)
+    let textbook = 1.0 / (100.0 * 5.0 / (PI * 0.25));
+    let edges = compartments.axial_conductances();
+    assert_eq!(edges.len(), 10);
+    assert_eq!((edges[0].0, edges[0].1), (1, 2));
+    // The soma point has no length, so the first segment couples over half
+    assert!((edges[0].2 - 2.0 * textbook).abs() < 1e-15);
+    for &(parent, child, g) in &edges[1..] {
+        assert_eq!(parent + 1, child);
+        assert!((g - textbook).abs() / textbook < 1e-12);
+    }
+}
+
+#[test]
+fn y_junction_halves_and_ignores_child_order() {
+    // Thick parent, two thin daughters of different lengths
+    let xyz = [
+        [0.0, 0.0, 0.0],
+        [20.0, 0.0, 0.0],
+        [30.0, 10.0, 0.0],
+        [20.0, -15.0, 0.0],
+    ];
+    let radii = [5.0, 2.0, 0.5, 0.8];
+    let first = build(&[1, 2, 3, 4], &xyz, &radii, &[-1, 1, 2, 2]);
+    // Same cell with the daughters listed the other way round
+    let second = build(
+        &[1, 2, 3, 4],
+        &[xyz[0], xyz[1], xyz[3], xyz[2]],
+        &[radii[0], radii[1], radii[3], radii[2]],
+        &[-1, 1, 2, 2],
+    );
+
+    let half = |length: f64, radius: f64| 0.5 * 100.0 * length / (PI * radius * radius);
+    let parent = half(20.0, 2.0);
+    let expected = [
+        1.0 / (parent + half(200f64.sqrt(), 0.5)),
+        1.0 / (parent + half(15.0, 0.8)),
+    ];
+
+    let gs: Vec<Vec<f64>> = [first, second]
+        .iter()
+        .map(|c| {
+            let edges = c.axial_conductances();
+            assert!(edges[1..].iter().all(|e| e.0 == 2));
+            let mut g: Vec<f64> = edges[1..].iter().map(|e| e.2).collect();
+            g.sort_by(f64::total_cmp);
+            g
+        })
+        .collect();
+    let mut expected = expected.to_vec();
+    expected.sort_by(f64::total_cmp);
+    for g in &gs {
+        for (a, b) in g.iter().zip(&expected) {
+            assert!((a - b).abs() / b < 1e-12);
+        }
+    }
+    assert_eq!(gs[0], gs[1]);
+}
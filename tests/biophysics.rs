@@ -0,0 +1,327 @@
+use compartment_rs::StructureIdentifier;
+use compartment_rs::biophysics::{BiophysicsProfile, Expression, Variables};
+use compartment_rs::channels::{ChannelType, HodgkinHuxley};
+use compartment_rs::{Channel, Compartments, ReaderOptions, swc_reader};
+
+/// `data/basic.swc` with `hh` everywhere
+fn model() -> Compartments {
+    let skeleton = swc_reader("data/basic.swc", &ReaderOptions::default()).unwrap();
+    let mut compartments = Compartments::from_skeleton(skeleton);
+    for c in compartments.components.iter_mut().skip(1) {
+        let mut channel = Channel::default();
+        channel.channel_type = ChannelType::HodgkinHuxley(HodgkinHuxley::default());
+        c.set_channel(channel);
+    }
+    compartments
+}
+
+fn apical(compartments: &Compartments) -> Vec<usize> {
+    compartments
+        .components
+        .iter()
+        .filter(|c| c.structure == StructureIdentifier::ApicalDendrite)
+        .map(|c| c.idx as usize)
+        .collect()
+}
+
+fn value(expression: &str, variables: &Variables) -> f64 {
+    Expression::parse(expression)
+        .unwrap()
+        .evaluate(variables)
+        .unwrap()
+}
+
+#[test]
+fn a_rule_gives_what_the_same_closure_gives() {
+    let mut compartments = model();
+    let mut profile = BiophysicsProfile::new();
+    profile
+        .add_rule(
+            StructureIdentifier::ApicalDendrite,
+            "hh",
+            "gkbar",
+            "0.02 + 0.01*exp(-dist/100)",
+        )
+        .unwrap();
+    let set = profile.apply(&mut compartments).unwrap();
+
+    let apic = apical(&compartments);
+    assert_eq!(set, apic.len());
+    assert!(set > 1);
+    let dist = compartments.parameter_map("path_distance").unwrap();
+    let gkbar = compartments.parameter_map("gkbar_hh").unwrap();
+    let rule = |dist: f64| 0.02 + 0.01 * (-dist / 100.0).exp();
+    for i in 1..compartments.components.len() {
+        let expected = if apic.contains(&i) {
+            rule(dist[i])
+        } else {
+            HodgkinHuxley::default().gkbar
+        };
+        assert_eq!(gkbar[i].to_bits(), expected.to_bits(), "compartment {}", i);
+    }
+}
+
+#[test]
+fn a_saved_profile_applies_identically() {
+    let mut profile = BiophysicsProfile::new();
+    profile
+        .add_rule(
+            StructureIdentifier::ApicalDendrite,
+            "hh",
+            "gkbar",
+            "0.02 + 0.01*exp(-dist/100)",
+        )
+        .unwrap()
+        .add_rule(
+            StructureIdentifier::BasalDendrite,
+            "hh",
+            "gnabar",
+            "clamp(0.12 - 0.001*dist, 0.01, 0.12)",
+        )
+        .unwrap()
+        .add_rule(
+            StructureIdentifier::Axon,
+            "pas",
+            "g",
+            "3e-5 * max(1, branch_order)^2",
+        )
+        .unwrap()
+        .add_rule(StructureIdentifier::Soma, "hh", "gl", "  1/(3000 +  diam) ")
+        .unwrap();
+    let text = profile.to_text();
+    let parsed = BiophysicsProfile::parse(&format!("# saved\n\n{}", text)).unwrap();
+    assert_eq!(parsed, profile);
+    assert_eq!(parsed.to_text(), text);
+    assert!(
+        text.contains("soma hh gl =   1/(3000 +  diam) \n"),
+        "{}",
+        text
+    );
+
+    let (mut a, mut b) = (model(), model());
+    assert_eq!(
+        profile.apply(&mut a).unwrap(),
+        parsed.apply(&mut b).unwrap()
+    );
+    for name in ["gnabar_hh", "gkbar_hh", "gl_hh", "conductance"] {
+        let bits = |c: &Compartments| -> Vec<u64> {
+            c.parameter_map(name).unwrap()[1..]
+                .iter()
+                .map(|x| x.to_bits())
+                .collect()
+        };
+        assert_eq!(bits(&a), bits(&b), "{}", name);
+    }
+}
+
+#[test]
+fn malformed_rules_fail_when_added_with_the_position() {
+    for (expression, position) in [
+        ("0.02 + * dist", 7),
+        ("0.02 + 0.01*exp(-dist/100", 25),
+        ("0.02 + 0.01*expo(-dist/100)", 12),
+        ("0.02 + 0.01*exp(-distance/100)", 17),
+        ("min(dist)", 0),
+        ("clamp(dist, 1, 2, 3)", 0),
+        ("1.5e", 4),
+        ("dist 2", 5),
+        ("dist $ 2", 5),
+        ("", 0),
+    ] {
+        let error = Expression::parse(expression).unwrap_err();
+        assert_eq!(error.position, position, "{}: {}", expression, error);
+
+        let mut profile = BiophysicsProfile::new();
+        let message = profile
+            .add_rule(
+                StructureIdentifier::ApicalDendrite,
+                "hh",
+                "gkbar",
+                expression,
+            )
+            .unwrap_err();
+        assert!(
+            message.contains(&format!("at character {}", position + 1)),
+            "{}",
+            message
+        );
+        assert!(profile.rules.is_empty());
+    }
+
+    let error = Expression::parse("1 + foo").unwrap_err().to_string();
+    assert!(error.ends_with("  1 + foo\n      ^"), "{}", error);
+
+    let mut profile = BiophysicsProfile::new();
+    for (mechanism, parameter) in [("hh", "gcabar"), ("kdr", "gkbar"), ("pas", "e")] {
+        let message = profile
+            .add_rule(StructureIdentifier::Soma, mechanism, parameter, "1")
+            .unwrap_err();
+        assert!(message.contains("Unknown parameter"), "{}", message);
+    }
+    let message = BiophysicsProfile::parse("apic hh gkbar 0.02\n").unwrap_err();
+    assert!(message.starts_with("Line 1:"), "{}", message);
+    let message = BiophysicsProfile::parse("soma hh gl = 1\nbasal hh gl = 1\n").unwrap_err();
+    assert!(
+        message.contains("Line 2: unknown region 'basal'"),
+        "{}",
+        message
+    );
+}
+
+#[test]
+fn variables_evaluate_to_the_compartment() {
+    let v = Variables {
+        dist: 120.0,
+        diam: 1.5,
+        branch_order: 2.0,
+        x: -3.0,
+        y: 4.5,
+        z: 7.25,
+    };
+    assert_eq!(value("dist", &v), 120.0);
+    assert_eq!(value("diam", &v), 1.5);
+    assert_eq!(value("branch_order", &v), 2.0);
+    assert_eq!(value("x", &v), -3.0);
+    assert_eq!(value("y", &v), 4.5);
+    assert_eq!(value("z", &v), 7.25);
+
+    // And those `apply` passes are the compartment's
+    let compartments = model();
+    let dist = compartments.parameter_map("path_distance").unwrap();
+    let order = compartments.parameter_map("branch_order").unwrap();
+    for i in apical(&compartments) {
+        let c = &compartments.components[i];
+        let mut profile = BiophysicsProfile::new();
+        let mut applied = model();
+        for (parameter, expression) in [
+            ("gnabar", "dist + branch_order"),
+            ("gkbar", "diam"),
+            ("gl", "x + 10*y + 100*z"),
+        ] {
+            profile
+                .add_rule(
+                    StructureIdentifier::ApicalDendrite,
+                    "hh",
+                    parameter,
+                    expression,
+                )
+                .unwrap();
+        }
+        profile.apply(&mut applied).unwrap();
+        let mid = |k: usize| (c.proximal[k] + c.distal[k]) / 2.0;
+        assert_eq!(
+            applied.parameter_map("gnabar_hh").unwrap()[i],
+            dist[i] + order[i]
+        );
+        assert_eq!(applied.parameter_map("gkbar_hh").unwrap()[i], c.diam);
+        assert_eq!(
+            applied.parameter_map("gl_hh").unwrap()[i],
+            mid(0) + 10.0 * mid(1) + 100.0 * mid(2)
+        );
+    }
+}
+
+#[test]
+fn operators_follow_the_usual_precedence() {
+    let v = Variables::default();
+    assert_eq!(value("1 + 2 * 3", &v), 7.0);
+    assert_eq!(value("(1 + 2) * 3", &v), 9.0);
+    assert_eq!(value("7 - 2 - 1", &v), 4.0);
+    assert_eq!(value("12 / 3 / 2", &v), 2.0);
+    assert_eq!(value("-2^2", &v), -4.0);
+    assert_eq!(value("2^3^2", &v), 512.0);
+    assert_eq!(value("2^-1", &v), 0.5);
+    assert_eq!(value("--3 + +1", &v), 4.0);
+    assert_eq!(value(".5 + 2.5E-1 + 1e+1", &v), 10.75);
+}
+
+#[test]
+fn functions_evaluate() {
+    let v = Variables {
+        dist: 50.0,
+        ..Variables::default()
+    };
+    assert_eq!(value("exp(-dist/100)", &v), (-0.5f64).exp());
+    assert_eq!(value("log(dist)", &v), 50f64.ln());
+    assert_eq!(value("min(dist, 20)", &v), 20.0);
+    assert_eq!(value("max(dist, 20)", &v), 50.0);
+    assert_eq!(value("clamp(dist, 0, 10)", &v), 10.0);
+    assert_eq!(value("clamp(dist, 60, 70)", &v), 60.0);
+    assert_eq!(value("clamp(dist, 0, 100)", &v), 50.0);
+}
+
+#[test]
+fn evaluation_errors_name_the_compartment() {
+    let v = Variables::default();
+    for (expression, message) in [
+        ("1 / dist", "Division by zero"),
+        ("log(dist)", "log of non-positive"),
+        ("log(-1)", "log of non-positive"),
+        ("exp(1000)", "gives inf"),
+        ("clamp(1, 2, 0)", "wrong way round"),
+    ] {
+        let error = Expression::parse(expression)
+            .unwrap()
+            .evaluate(&v)
+            .unwrap_err();
+        assert!(error.contains(message), "{}: {}", expression, error);
+    }
+
+    // The soma is where the path distance is 0
+    let mut compartments = model();
+    let mut profile = BiophysicsProfile::new();
+    profile
+        .add_rule(StructureIdentifier::Soma, "hh", "gl", "1 / dist")
+        .unwrap();
+    let error = profile.apply(&mut compartments).unwrap_err();
+    assert!(
+        error.starts_with("Compartment 1: gl_hh = 1 / dist: Division by zero"),
+        "{}",
+        error
+    );
+
+    // A rule for a mechanism the compartment lacks
+    let mut compartments = model();
+    compartments.components[1].set_channel(Channel::default());
+    let mut profile = BiophysicsProfile::new();
+    profile
+        .add_rule(StructureIdentifier::Soma, "hh", "gl", "0.001")
+        .unwrap();
+    assert!(profile.apply(&mut compartments).is_err());
+}
+
+#[test]
+fn the_description_quotes_the_rules() {
+    let mut profile = BiophysicsProfile::new();
+    profile
+        .add_rule(
+            StructureIdentifier::ApicalDendrite,
+            "hh",
+            "gkbar",
+            "0.02 + 0.01*exp(-dist/100)",
+        )
+        .unwrap();
+    let mut compartments = model();
+    profile.apply(&mut compartments).unwrap();
+
+    let plain = compartments.describe();
+    assert!(plain.rules.is_empty());
+    assert!(!plain.to_markdown().contains("Distributions"));
+    let description = compartments.describe_with(&profile);
+    assert_eq!(
+        description.rules,
+        ["apic hh gkbar = 0.02 + 0.01*exp(-dist/100)"]
+    );
+    assert!(
+        description
+            .to_markdown()
+            .ends_with("### Distributions\n\n- `apic hh gkbar = 0.02 + 0.01*exp(-dist/100)`\n")
+    );
+    assert_eq!(
+        description.to_table().last().unwrap(),
+        &(
+            "rule.1".to_owned(),
+            "apic hh gkbar = 0.02 + 0.01*exp(-dist/100)".to_owned()
+        )
+    );
+}
@@ -0,0 +1,257 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use compartment_rs::bundle::Bundle;
+use compartment_rs::morphometry::Morphometry;
+use compartment_rs::registry::Registry;
+use compartment_rs::standardize::{Dataset, Pipeline, StandardizeOptions};
+use compartment_rs::{ReaderOptions, Skeleton, swc_reader_from_bytes};
+
+fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("bundle-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// A soma with a dendrite of `k % 7 + 3` nodes forking at its end, and
+/// header metadata naming `k`
+fn cell(k: usize) -> Skeleton {
+    let mut swc = format!(
+        "# SOURCE_FILE cell_{}.swc\n# SPECIES mouse\n# DONOR {}\n1 1 0 0 0 5 -1\n",
+        k, k
+    );
+    let n = k % 7 + 3;
+    for i in 2..=n {
+        swc += &format!(
+            "{} 3 {} {} 0 {} {}\n",
+            i,
+            i * 10,
+            k,
+            1.0 + i as f64 / 10.0,
+            i - 1
+        );
+    }
+    swc += &format!("{} 3 {} 10 0 0.5 {}\n", n + 1, n * 10 + 5, n);
+    swc += &format!("{} 3 {} -10 0 0.5 {}\n", n + 2, n * 10 + 5, n);
+    swc_reader_from_bytes(swc.as_bytes(), &ReaderOptions::default()).unwrap()
+}
+
+fn assert_same(a: &Skeleton, b: &Skeleton) {
+    assert_eq!(a.cell_id(), b.cell_id());
+    assert_eq!(a.metadata, b.metadata);
+    assert_eq!(a.nodes.len(), b.nodes.len());
+    for (x, y) in a.nodes.iter().zip(b.nodes.iter()) {
+        assert_eq!(
+            (x.node_id, x.parent_id, x.structured_identifier),
+            (y.node_id, y.parent_id, y.structured_identifier)
+        );
+        assert_eq!(
+            [x.x_pos, x.y_pos, x.z_pos, x.radius],
+            [y.x_pos, y.y_pos, y.z_pos, y.radius]
+        );
+    }
+}
+
+fn write_cells(path: &PathBuf, cells: &[Skeleton]) {
+    let mut writer = Bundle::create(path).unwrap();
+    for c in cells {
+        assert_eq!(writer.add(c).unwrap(), c.cell_id());
+    }
+    writer.finish().unwrap();
+}
+
+#[test]
+fn cells_round_trip_with_their_metadata() {
+    let path = scratch("round_trip").join("cells.bundle");
+    let cells: Vec<Skeleton> = (0..100).map(cell).collect();
+    let mut writer = Bundle::create(&path).unwrap();
+    for c in &cells {
+        writer.add(c).unwrap();
+    }
+    // The same cell again is stored once
+    writer.add(&cells[0]).unwrap();
+    assert_eq!(writer.len(), 100);
+    writer.finish().unwrap();
+
+    let bundle = Bundle::open(&path).unwrap();
+    assert!(!bundle.recovered());
+    let ids: Vec<_> = cells.iter().map(|c| c.cell_id()).collect();
+    assert_eq!(bundle.list(), ids);
+    for c in &cells {
+        assert_same(&bundle.get(&c.cell_id()).unwrap().unwrap(), c);
+    }
+    assert!(bundle.get(&cell(100).cell_id()).unwrap().is_none());
+
+    let plain = scratch("not_a_bundle").join("cells.bundle");
+    fs::write(&plain, "1 1 0 0 0 5 -1\n").unwrap();
+    let Err(error) = Bundle::open(&plain) else {
+        panic!("read SWC as a bundle");
+    };
+    assert!(error.contains("Not a bundle"), "{}", error);
+}
+
+#[test]
+fn reading_one_cell_reads_only_its_record() {
+    let path = scratch("random_access").join("cells.bundle");
+    let cells: Vec<Skeleton> = (0..100).map(cell).collect();
+    write_cells(&path, &cells);
+    let size = fs::metadata(&path).unwrap().len() as usize;
+
+    let bundle = Bundle::open(&path).unwrap();
+    assert_eq!(bundle.len(), 100);
+    assert_eq!(bundle.records_read(), 0);
+    let fiftieth = bundle.list()[49];
+    assert_same(&bundle.get(&fiftieth).unwrap().unwrap(), &cells[49]);
+    assert_eq!(bundle.records_read(), 1);
+    // Its own record, a small share of the file
+    assert!(bundle.bytes_read() > 0);
+    assert!(
+        bundle.bytes_read() * 20 < size,
+        "{} of {}",
+        bundle.bytes_read(),
+        size
+    );
+}
+
+#[test]
+fn a_bundle_loads_like_the_directory_it_replaces() {
+    let out = scratch("dataset");
+    let dataset = Dataset::from_dir("data/standardize").unwrap();
+    let options = StandardizeOptions {
+        max_spacing: Some(10.0),
+        ..StandardizeOptions::default()
+    };
+    let pipeline = Pipeline::standardize(options).with_threads(3);
+    let to_dir = pipeline.run(&dataset, out.join("cells")).unwrap();
+    let path = out.join("cells.bundle");
+    let to_bundle = pipeline.run_to_bundle(&dataset, &path).unwrap();
+    assert_eq!(to_bundle.files.len(), to_dir.files.len());
+    for (a, b) in to_dir.files.iter().zip(&to_bundle.files) {
+        assert_eq!(a.source, b.source);
+        assert_eq!(a.error, b.error);
+        assert_eq!(a.operations, b.operations);
+        if b.error.is_none() {
+            assert_eq!(b.output.as_deref(), Some(path.as_path()));
+        }
+    }
+
+    let morphometrics = |cells: Vec<Arc<Skeleton>>| -> BTreeMap<_, _> {
+        cells
+            .iter()
+            .map(|c| {
+                let m = Morphometry::new(&c.nodes);
+                (
+                    c.cell_id(),
+                    (
+                        m.total_length().to_bits(),
+                        m.total_area().to_bits(),
+                        m.max_branching_degree(),
+                    ),
+                )
+            })
+            .collect()
+    };
+    let options = ReaderOptions::default();
+    let from_dir = Dataset::from_source(out.join("cells"))
+        .unwrap()
+        .load(&options, None)
+        .unwrap();
+    let bundle_dataset = Dataset::from_source(&path).unwrap();
+    assert_eq!(bundle_dataset, Dataset::from_paths([&path]));
+    let from_bundle = bundle_dataset.load(&options, None).unwrap();
+    assert_eq!(from_bundle.len(), to_bundle.successes().count());
+    assert_eq!(morphometrics(from_dir), morphometrics(from_bundle));
+
+    // Through a registry, cells it holds are not read again
+    let registry = Registry::new(None);
+    let first = bundle_dataset.load(&options, Some(&registry)).unwrap();
+    let second = bundle_dataset.load(&options, Some(&registry)).unwrap();
+    for (a, b) in first.iter().zip(&second) {
+        assert!(Arc::ptr_eq(a, b));
+    }
+}
+
+#[test]
+fn a_corrupted_record_fails_its_checksum() {
+    let path = scratch("corrupt").join("cells.bundle");
+    let cells: Vec<Skeleton> = (0..5).map(cell).collect();
+    write_cells(&path, &cells);
+
+    let mut bytes = fs::read(&path).unwrap();
+    let third = bytes
+        .windows(4)
+        .enumerate()
+        .filter(|(_, w)| w == b"REC1")
+        .nth(2)
+        .unwrap()
+        .0;
+    // Past the record header and into the payload
+    bytes[third + 28 + 12] ^= 0xff;
+    fs::write(&path, bytes).unwrap();
+
+    let bundle = Bundle::open(&path).unwrap();
+    let error = bundle.get(&cells[2].cell_id()).unwrap_err();
+    assert!(error.contains("checksum mismatch"), "{}", error);
+    assert!(error.contains(&cells[2].cell_id().to_string()));
+    for i in [0, 1, 3, 4] {
+        assert_same(
+            &bundle.get(&cells[i].cell_id()).unwrap().unwrap(),
+            &cells[i],
+        );
+    }
+}
+
+#[test]
+fn a_torn_append_keeps_the_earlier_records() {
+    let path = scratch("truncated").join("cells.bundle");
+    let cells: Vec<Skeleton> = (0..12).map(cell).collect();
+    write_cells(&path, &cells[..10]);
+
+    // The writer dies after one more record and in the middle of another
+    let mut writer = Bundle::append(&path).unwrap();
+    assert_eq!(writer.len(), 10);
+    writer.add(&cells[10]).unwrap();
+    writer.add(&cells[11]).unwrap();
+    drop(writer);
+    let size = fs::metadata(&path).unwrap().len();
+    fs::OpenOptions::new()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_len(size - 7)
+        .unwrap();
+
+    let bundle = Bundle::open(&path).unwrap();
+    assert!(bundle.recovered());
+    assert_eq!(bundle.len(), 11);
+    for c in &cells[..11] {
+        assert_same(&bundle.get(&c.cell_id()).unwrap().unwrap(), c);
+    }
+    assert!(!bundle.contains(&cells[11].cell_id()));
+
+    // Appending again picks up after the last whole record
+    let mut writer = Bundle::append(&path).unwrap();
+    assert_eq!(writer.len(), 11);
+    writer.add(&cells[11]).unwrap();
+    writer.finish().unwrap();
+    let bundle = Bundle::open(&path).unwrap();
+    assert!(!bundle.recovered());
+    let ids: Vec<_> = cells.iter().map(|c| c.cell_id()).collect();
+    assert_eq!(bundle.list(), ids);
+    assert_same(&bundle.get(&ids[11]).unwrap().unwrap(), &cells[11]);
+
+    // A torn index is no index
+    let size = fs::metadata(&path).unwrap().len();
+    fs::OpenOptions::new()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_len(size - 3)
+        .unwrap();
+    let bundle = Bundle::open(&path).unwrap();
+    assert!(bundle.recovered());
+    assert_eq!(bundle.list(), ids);
+}
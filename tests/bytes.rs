@@ -0,0 +1,67 @@
+use std::io::Write;
+
+use compartment_rs::{ReaderOptions, SwcError, swc_reader, swc_reader_from_bytes};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use sha2::{Digest, Sha256};
+
+fn basic_bytes() -> Vec<u8> {
+    std::fs::read("data/basic.swc").unwrap()
+}
+
+fn hex_sha256(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[test]
+fn bytes_match_file() {
+    let options = ReaderOptions::default();
+    let from_file = swc_reader("data/basic.swc", &options).unwrap();
+    let from_bytes = swc_reader_from_bytes(&basic_bytes(), &options).unwrap();
+
+    assert_eq!(from_file.nodes.len(), from_bytes.nodes.len());
+    for (a, b) in from_file.nodes.iter().zip(from_bytes.nodes.iter()) {
+        assert_eq!(
+            (a.node_id, a.parent_id, a.x_pos, a.y_pos, a.z_pos, a.radius),
+            (b.node_id, b.parent_id, b.x_pos, b.y_pos, b.z_pos, b.radius)
+        );
+    }
+    assert_eq!(from_file.parent_child_map, from_bytes.parent_child_map);
+    assert_eq!(from_file.child_parent_map, from_bytes.child_parent_map);
+}
+
+#[test]
+fn checksum_is_verified_before_parsing() {
+    // Not SWC at all, so getting a mismatch back means parsing never started
+    let garbage = b"definitely not an swc file";
+    let options = ReaderOptions {
+        expected_sha256: Some(hex_sha256(&basic_bytes())),
+        ..Default::default()
+    };
+    match swc_reader_from_bytes(garbage, &options) {
+        Err(SwcError::ChecksumMismatch { expected, actual }) => {
+            assert_eq!(expected, hex_sha256(&basic_bytes()));
+            assert_eq!(actual, hex_sha256(garbage));
+        }
+        other => panic!("Expected a checksum mismatch, got {:?}", other),
+    }
+
+    // The right digest goes through
+    assert!(swc_reader_from_bytes(&basic_bytes(), &options).is_ok());
+}
+
+#[test]
+fn gzipped_bytes_round_trip() {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&basic_bytes()).unwrap();
+    let gzipped = encoder.finish().unwrap();
+
+    let options = ReaderOptions::default();
+    let plain = swc_reader_from_bytes(&basic_bytes(), &options).unwrap();
+    let unzipped = swc_reader_from_bytes(&gzipped, &options).unwrap();
+    assert_eq!(plain.parent_child_map, unzipped.parent_child_map);
+    assert_eq!(plain.nodes.len(), unzipped.nodes.len());
+}
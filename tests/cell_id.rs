@@ -0,0 +1,94 @@
+use std::path::PathBuf;
+
+use compartment_rs::cell_id::{self, CellId};
+use compartment_rs::{Compartments, ReaderOptions, swc_reader, swc_reader_from_bytes};
+
+fn basic_bytes() -> Vec<u8> {
+    std::fs::read("data/basic.swc").unwrap()
+}
+
+#[test]
+fn identity_follows_content_not_name() {
+    let dir = std::env::temp_dir().join("compartment_rs_cell_id");
+    std::fs::create_dir_all(&dir).unwrap();
+    let copy = dir.join("renamed_copy.swc");
+    std::fs::write(&copy, basic_bytes()).unwrap();
+
+    let original = swc_reader("data/basic.swc", &ReaderOptions::default()).unwrap();
+    let copied = swc_reader(&copy, &ReaderOptions::default()).unwrap();
+    assert_eq!(original.cell_id(), copied.cell_id());
+    assert_eq!(
+        Compartments::from_skeleton(copied).cell_id,
+        Some(original.cell_id())
+    );
+
+    // Moving a single node is a different cell
+    let text = String::from_utf8(basic_bytes()).unwrap();
+    let edited = text.replace("4 3 25.0 5.0 0.0", "4 3 25.0 5.5 0.0");
+    let edited = swc_reader_from_bytes(edited.as_bytes(), &ReaderOptions::default()).unwrap();
+    assert_ne!(edited.cell_id(), original.cell_id());
+    assert_ne!(
+        edited.cell_id().morphology(),
+        original.cell_id().morphology()
+    );
+
+    // A new header comment changes the content digest but not the morphology
+    let annotated = format!("# CREATURE rat\n{}", text);
+    let annotated = swc_reader_from_bytes(annotated.as_bytes(), &ReaderOptions::default()).unwrap();
+    assert_ne!(annotated.cell_id(), original.cell_id());
+    assert_eq!(
+        annotated.cell_id().morphology(),
+        original.cell_id().morphology()
+    );
+}
+
+#[test]
+fn string_form_round_trips() {
+    let id = swc_reader("data/basic.swc", &ReaderOptions::default())
+        .unwrap()
+        .cell_id();
+    let s = id.to_string();
+    assert_eq!(s.len(), 25);
+    assert_eq!(CellId::parse(&s).unwrap(), id);
+    assert_eq!(s.parse::<CellId>().unwrap(), id);
+    assert!(CellId::parse("not-an-id").is_err());
+    assert!(CellId::parse(&s[1..]).is_err());
+}
+
+#[test]
+fn duplicate_report_lists_suspicious_pairs() {
+    let basic = swc_reader("data/basic.swc", &ReaderOptions::default())
+        .unwrap()
+        .cell_id();
+    let extras = swc_reader("data/extras.swc", &ReaderOptions::default())
+        .unwrap()
+        .cell_id();
+    let cells = vec![
+        (PathBuf::from("a/basic.swc"), basic),
+        (PathBuf::from("b/basic.swc"), extras),
+        (PathBuf::from("c/copy_of_basic.swc"), basic),
+        (PathBuf::from("d/basic.swc"), basic),
+    ];
+    let report = cell_id::duplicate_report(&cells);
+    assert_eq!(
+        report.same_name_different_content,
+        vec![
+            (PathBuf::from("a/basic.swc"), PathBuf::from("b/basic.swc")),
+            (PathBuf::from("b/basic.swc"), PathBuf::from("d/basic.swc")),
+        ]
+    );
+    assert_eq!(
+        report.same_content_different_name,
+        vec![
+            (
+                PathBuf::from("a/basic.swc"),
+                PathBuf::from("c/copy_of_basic.swc")
+            ),
+            (
+                PathBuf::from("c/copy_of_basic.swc"),
+                PathBuf::from("d/basic.swc")
+            ),
+        ]
+    );
+    assert!(cell_id::duplicate_report(&cells[..1]).is_empty());
+}
@@ -0,0 +1,159 @@
+use compartment_rs::{Channel, Compartments, ReaderOptions, Skeleton};
+
+/// Soma, a 10-segment trunk tapering from radius 1 to 0.5, then two
+/// 8-segment branches off its end, one basal and one apical
+fn tree() -> Compartments {
+    let mut ids = vec![1i64];
+    let mut types = vec![1];
+    let mut xyz = vec![[0.0; 3]];
+    let mut radii = vec![5.0];
+    let mut parents = vec![-1i64];
+    for i in 1..=10 {
+        ids.push(i + 1);
+        types.push(3);
+        xyz.push([3.0 * i as f64, 0.0, 0.0]);
+        radii.push(1.0 - 0.05 * i as f64);
+        parents.push(i);
+    }
+    for (branch, (kind, dy)) in [(3, 1.0), (4, -1.0)].into_iter().enumerate() {
+        for i in 1..=8 {
+            let id = 12 + 8 * branch as i64 + i - 1;
+            ids.push(id);
+            types.push(kind);
+            xyz.push([30.0 + 2.0 * i as f64, dy * 2.0 * i as f64, 0.0]);
+            radii.push(0.5 - 0.02 * i as f64);
+            parents.push(if i == 1 { 11 } else { id - 1 });
+        }
+    }
+    let skeleton = Skeleton::from_arrays(
+        &ids,
+        &types,
+        &xyz,
+        &radii,
+        &parents,
+        &ReaderOptions::default(),
+    )
+    .unwrap();
+    let mut compartments = Compartments::from_skeleton(skeleton);
+    for (i, c) in compartments.components.iter_mut().enumerate() {
+        let mut channel = Channel::default();
+        channel.resistance = 100.0 + i as f64;
+        channel.capacitance = 1.0;
+        channel.conductance = 1e-4 * (1.0 + i as f64 / 10.0);
+        c.set_channel(channel);
+    }
+    compartments
+}
+
+fn total(compartments: &Compartments, f: impl Fn(&compartment_rs::Compartment) -> f64) -> f64 {
+    compartments.components[1..].iter().map(f).sum()
+}
+
+/// Axial resistance summed from the soma out to each tip
+fn tip_resistances(compartments: &Compartments) -> Vec<f64> {
+    let c = &compartments.components;
+    let mut out = Vec::new();
+    for tip in c.iter().filter(|c| c.idx > 0 && c.children_idxs.is_empty()) {
+        let mut r = 0.0;
+        let mut idx = tip.idx as usize;
+        while idx > 0 {
+            r += c[idx].axial_resistance();
+            idx = c[idx].parent_idxs[0] as usize;
+        }
+        out.push(r);
+    }
+    out
+}
+
+fn close(a: f64, b: f64) -> bool {
+    (a - b).abs() <= 1e-9 * a.abs().max(b.abs())
+}
+
+#[test]
+fn coarsening_conserves_area_resistance_and_totals() {
+    let mut compartments = tree();
+    let area = total(&compartments, |c| c.membrane_area());
+    let resistance = total(&compartments, |c| c.axial_resistance());
+    let capacitance = total(&compartments, |c| c.capacitance());
+    let conductance = total(&compartments, |c| c.membrane_conductance());
+    let tips = tip_resistances(&compartments);
+
+    let count = compartments.coarsen(8).unwrap();
+    assert!(count <= 8);
+    assert_eq!(count, compartments.components.len() - 1);
+    assert!(close(area, total(&compartments, |c| c.membrane_area())));
+    assert!(close(
+        resistance,
+        total(&compartments, |c| c.axial_resistance())
+    ));
+    assert!(close(
+        capacitance,
+        total(&compartments, |c| c.capacitance())
+    ));
+    assert!(close(
+        conductance,
+        total(&compartments, |c| c.membrane_conductance())
+    ));
+    for (before, after) in tips.iter().zip(tip_resistances(&compartments)) {
+        assert!(close(*before, after), "{} {}", before, after);
+    }
+}
+
+#[test]
+fn merging_never_crosses_branch_points_or_types() {
+    let mut compartments = tree();
+    let soma_name = compartments.components[1].name.clone();
+    // Soma, trunk, and one per branch is as far as it goes
+    assert!(compartments.coarsen(3).is_err());
+    assert_eq!(compartments.components.len(), 28);
+    assert_eq!(compartments.coarsen(4), Ok(4));
+
+    let c = &compartments.components;
+    assert_eq!(c[1].name, soma_name);
+    assert_eq!(c[2].parent_idxs, vec![1]);
+    assert_eq!(c[2].children_idxs, vec![3, 4]);
+    assert!((c[2].length - 30.0).abs() < 1e-12);
+    assert_ne!(c[3].structure, c[4].structure);
+    for k in [3, 4] {
+        assert_eq!(c[k].parent_idxs, vec![2]);
+        assert!(c[k].children_idxs.is_empty());
+        assert_eq!(c[k].name, format!("Compartment: {}", k));
+    }
+    assert_eq!(c[2].proximal, [0.0; 3]);
+    assert_eq!(c[2].distal, [30.0, 0.0, 0.0]);
+}
+
+#[test]
+fn provenance_covers_every_node_once() {
+    let mut compartments = tree();
+    compartments.coarsen_by_length(7.0);
+    assert!(
+        compartments.components[2..]
+            .iter()
+            .all(|c| c.length <= 7.0 + 1e-12)
+    );
+    assert_eq!(compartments.provenance.len(), compartments.components.len());
+
+    let mut seen: Vec<u64> = compartments
+        .provenance
+        .iter()
+        .flatten()
+        .map(|span| span.node_id)
+        .collect();
+    seen.sort();
+    assert_eq!(seen, (0..27).collect::<Vec<u64>>());
+
+    for node in 1..27 {
+        let (idx, _) = compartments.node_to_compartment(node).unwrap();
+        assert!(idx >= 2);
+    }
+}
+
+#[test]
+fn short_limit_leaves_everything_alone() {
+    let mut compartments = tree();
+    let lengths: Vec<f64> = compartments.components.iter().map(|c| c.length).collect();
+    assert_eq!(compartments.coarsen_by_length(0.5), 27);
+    let after: Vec<f64> = compartments.components.iter().map(|c| c.length).collect();
+    assert_eq!(lengths, after);
+}
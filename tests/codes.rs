@@ -0,0 +1,264 @@
+use std::collections::HashSet;
+
+use compartment_rs::codes::{REGISTRY, RETIRED};
+use compartment_rs::solver::Simulation;
+use compartment_rs::standardize::{Dataset, StandardizeOptions};
+use compartment_rs::store::{ResultStore, RunId};
+use compartment_rs::units::{MicroFaradPerCm2, OhmCm, SiemensPerCm2};
+use compartment_rs::{
+    Channel, Code, Compartments, ReaderOptions, Severity, Skeleton, swc_reader,
+    swc_reader_from_bytes,
+};
+
+/// Codes ever published, in order. Append only: changing or removing an entry
+/// here means a downstream pipeline just broke.
+const PUBLISHED: &[&str] = &[
+    "E_SWC_0001_LENGTH_MISMATCH",
+    "E_SWC_0002_INVALID_NODE_ID",
+    "E_SWC_0003_INVALID_PARENT_ID",
+    "E_SWC_0004_READ_FAILURE",
+    "E_SWC_0005_MISSING_FIELD",
+    "E_SWC_0006_INVALID_FIELD",
+    "E_SWC_0007_DANGLING_PARENT",
+    "E_SWC_0008_DECIMAL_COMMA",
+    "E_SWC_0009_STRICT_ZERO_RADIUS",
+    "E_SWC_0010_NO_ROOT",
+    "E_SWC_0011_MALFORMED_SCALE",
+    "E_SWC_0012_CHECKSUM_MISMATCH",
+    "E_SWC_0013_IO",
+    "E_SWC_0014_DUPLICATE_NODE_ID",
+    "E_SWC_0015_LIMIT_EXCEEDED",
+    "E_SWC_0016_NOT_SWC",
+    "E_PARAM_0001_UNKNOWN",
+    "E_PARAM_0002_UNKNOWN_SECTION",
+    "E_PARAM_0003_INVALID_POSITION",
+    "E_PARAM_0004_READ_ONLY",
+    "E_PARAM_0005_MALFORMED_ROW",
+    "W_SWC_0001_ZERO_RADIUS",
+    "W_MORPH_0001_RALL_MISMATCH",
+    "E_STATE_0001_UNKNOWN_PATH",
+    "E_STATE_0002_NO_COMPARTMENT",
+    "E_STATE_0003_UNAVAILABLE",
+    "E_STATE_0004_READ_ONLY",
+    "E_STATE_0005_OUT_OF_RANGE",
+    "W_MORPH_0002_HIGH_BRANCHING_DEGREE",
+    "E_PARAM_0006_UNKNOWN_TAG",
+    "E_PARAM_0007_MISSING_MECHANISM",
+    "W_SWC_0002_UNREACHABLE",
+    "W_MORPH_0003_NON_POSITIVE_RADIUS",
+    "W_MORPH_0004_RADIUS_OUTLIER",
+    "W_MORPH_0005_ORPHANED_FRAGMENT",
+    "W_MORPH_0006_SPACING_GAP",
+    "W_MORPH_0007_SUSPECT_UNITS",
+    "E_SIM_0001_SESSION_CLOSED",
+    "E_SIM_0002_DIVERGED",
+    "E_SIM_0003_INVALID_MODEL",
+    "E_SIM_0004_NO_COMPARTMENT",
+    "E_SIM_0005_NO_SPINE",
+    "E_SIM_0006_INVALID_INPUT",
+    "E_SIM_0007_ION_DEPLETED",
+    "E_SIM_0008_OUTPUT",
+    "E_SIM_0009_SOLVER_PANICKED",
+    "E_STORE_0001_IO",
+    "E_STORE_0002_NOT_A_STORE",
+    "E_STORE_0003_CORRUPT",
+    "E_STORE_0004_UNKNOWN_RUN",
+    "E_STORE_0005_INVALID_ENTRY",
+    "E_DATA_0001_MALFORMED_RECIPE",
+    "E_DATA_0002_IO",
+    "E_DATA_0003_UNSUPPORTED_FILE",
+    "E_DATA_0004_OUTPUT_COLLISION",
+    "E_DATA_0005_BUNDLE",
+    "E_DATA_0006_REJECTED",
+    "E_DATA_0007_PANICKED",
+];
+
+#[test]
+fn registry_is_consistent() {
+    let ids: Vec<&str> = REGISTRY.iter().map(|c| c.id).collect();
+    // Nothing published disappears or changes meaning, nothing retired returns
+    for published in PUBLISHED {
+        assert!(
+            ids.contains(published) || RETIRED.contains(published),
+            "{} vanished",
+            published
+        );
+    }
+    assert!(ids.iter().all(|id| !RETIRED.contains(id)));
+
+    // Unique both as full strings and as their numbered prefix
+    assert_eq!(ids.iter().collect::<HashSet<_>>().len(), ids.len());
+    // The prefix is severity, area and number, e.g. `E_PARAM_0002`
+    let prefixes: HashSet<Vec<&str>> = ids
+        .iter()
+        .map(|id| id.splitn(4, '_').take(3).collect())
+        .collect();
+    assert_eq!(prefixes.len(), ids.len());
+
+    for entry in REGISTRY {
+        assert_eq!(entry.code.info(), *entry);
+        let expected = match entry.severity {
+            Severity::Error => "E_",
+            Severity::Warning => "W_",
+        };
+        assert!(entry.id.starts_with(expected), "{}", entry.id);
+        assert!(!entry.description.is_empty());
+    }
+}
+
+fn read_code(path: &str, options: &ReaderOptions) -> Code {
+    swc_reader(path, options).unwrap_err().code()
+}
+
+#[test]
+fn fixture_failures_carry_their_code() {
+    let default = ReaderOptions::default();
+    assert_eq!(
+        read_code("data/decimal_comma.swc", &default).id(),
+        "E_SWC_0008_DECIMAL_COMMA"
+    );
+    assert_eq!(
+        read_code("data/bad_scale.swc", &default),
+        Code::MalformedScale
+    );
+    assert_eq!(read_code("data/does_not_exist.swc", &default), Code::Io);
+
+    let strict = ReaderOptions {
+        strict: true,
+        ..Default::default()
+    };
+    let dangling = swc_reader_from_bytes(b"1 1 0 0 0 1 -1\n2 3 1 0 0 1 7\n", &strict);
+    assert_eq!(
+        dangling.unwrap_err().code().id(),
+        "E_SWC_0007_DANGLING_PARENT"
+    );
+    let zero = swc_reader_from_bytes(b"1 1 0 0 0 1 -1\n2 3 1 0 0 0 1\n3 3 2 0 0 1 2\n", &strict);
+    assert_eq!(zero.unwrap_err().code(), Code::StrictZeroRadius);
+
+    let checksum = ReaderOptions {
+        expected_sha256: Some("00".to_owned()),
+        ..Default::default()
+    };
+    assert_eq!(
+        read_code("data/basic.swc", &checksum),
+        Code::ChecksumMismatch
+    );
+
+    let mismatch = Skeleton::from_arrays(&[1], &[], &[], &[], &[], &default);
+    assert_eq!(mismatch.unwrap_err().code(), Code::LengthMismatch);
+}
+
+#[test]
+fn warnings_and_parameter_errors_carry_codes() {
+    let skeleton = swc_reader("data/basic.swc", &ReaderOptions::default()).unwrap();
+    assert_eq!(
+        skeleton.warnings[0].kind.code().id(),
+        "W_SWC_0001_ZERO_RADIUS"
+    );
+    let err = Compartments::from_skeleton(skeleton)
+        .parameter_map("nope")
+        .unwrap_err();
+    assert_eq!(err.code(), Code::UnknownParameter);
+}
+
+#[test]
+fn input_errors_carry_their_line() {
+    let default = ReaderOptions::default();
+    let bad_field = swc_reader_from_bytes(b"# header\n1 1 0 0 0 1 -1\n2 3 x 0 0 1 1\n", &default);
+    assert_eq!(bad_field.unwrap_err().line(), Some(3));
+
+    let strict = ReaderOptions {
+        strict: true,
+        ..Default::default()
+    };
+    let dangling = swc_reader_from_bytes(b"1 1 0 0 0 1 -1\n\n2 3 1 0 0 1 7\n", &strict);
+    assert_eq!(dangling.unwrap_err().line(), Some(3));
+
+    // Nothing to point at when the input is arrays, or the error is global
+    let mismatch = Skeleton::from_arrays(&[1], &[], &[], &[], &[], &default);
+    assert_eq!(mismatch.unwrap_err().line(), None);
+    assert_eq!(
+        swc_reader("data/does_not_exist.swc", &default)
+            .unwrap_err()
+            .line(),
+        None
+    );
+}
+
+#[test]
+fn files_and_arrays_reject_the_same_ids_alike() {
+    let default = ReaderOptions::default();
+    // (node ID, parent ID) of a second node under a valid root
+    for (node_id, parent_id, code) in [
+        (0, 1, Code::InvalidNodeId),
+        (-3, 1, Code::InvalidNodeId),
+        (2, 0, Code::InvalidParentId),
+        (2, -7, Code::InvalidParentId),
+        (2, 9, Code::DanglingParent),
+    ] {
+        let swc = format!("1 1 0 0 0 5 -1\n{} 3 10 0 0 1 {}\n", node_id, parent_id);
+        let from_file = swc_reader_from_bytes(swc.as_bytes(), &default).unwrap_err();
+        let from_arrays = Skeleton::from_arrays(
+            &[1, node_id],
+            &[1, 3],
+            &[[0.0; 3], [10.0, 0.0, 0.0]],
+            &[5.0, 1.0],
+            &[-1, parent_id],
+            &default,
+        )
+        .unwrap_err();
+        assert_eq!(from_file.code(), code, "{}", from_file);
+        assert_eq!(from_arrays.code(), code, "{}", from_arrays);
+        // Files point at the line, arrays name the index
+        assert_eq!(from_file.line(), Some(2));
+        assert!(
+            from_arrays.to_string().contains("index 1"),
+            "{}",
+            from_arrays
+        );
+    }
+}
+
+#[test]
+fn simulation_store_and_dataset_errors_carry_codes() {
+    let skeleton = swc_reader_from_bytes(
+        b"1 1 0 0 0 5 -1\n2 3 20 0 0 1 1\n3 3 40 0 0 1 2\n",
+        &ReaderOptions::default(),
+    )
+    .unwrap();
+    let mut compartments = Compartments::from_skeleton(skeleton);
+    for c in compartments.components.iter_mut() {
+        c.set_channel(Channel::passive(
+            OhmCm::new(100.0).unwrap(),
+            MicroFaradPerCm2::new(1.0).unwrap(),
+            SiemensPerCm2::new(1e-4).unwrap(),
+        ));
+    }
+    let dt = Simulation::new(&compartments, 0.0).err().unwrap();
+    assert_eq!(dt.code(), Code::InvalidModel);
+    let mut simulation = Simulation::new(&compartments, 0.025).unwrap();
+    let missing = simulation.set_voltage(9, -65.0).unwrap_err();
+    assert_eq!(missing.code(), Code::NoCompartment);
+    assert_eq!(missing.compartment_idx(), Some(9));
+    let diverged = simulation
+        .run(10, &[(2, vec![f64::INFINITY; 10])])
+        .unwrap_err();
+    assert_eq!(diverged.code().id(), "E_SIM_0002_DIVERGED");
+    assert!(diverged.compartment_idx().is_some(), "{}", diverged);
+
+    let path = std::env::temp_dir().join(format!("codes_{}.store", std::process::id()));
+    std::fs::write(&path, b"not a store").unwrap();
+    assert_eq!(
+        ResultStore::open(&path).err().unwrap().code(),
+        Code::NotAStore
+    );
+    std::fs::remove_file(&path).unwrap();
+    let store = ResultStore::open(&path).unwrap();
+    assert_eq!(store.load(RunId(8)).unwrap_err().code(), Code::UnknownRun);
+    std::fs::remove_file(&path).unwrap();
+
+    let recipe = StandardizeOptions::parse("colour blue\n").unwrap_err();
+    assert_eq!(recipe.code(), Code::MalformedRecipe);
+    let listing = Dataset::from_dir("data/does_not_exist").unwrap_err();
+    assert_eq!(listing.code(), Code::DatasetIo);
+}
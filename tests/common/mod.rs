@@ -0,0 +1,167 @@
+//! Closed-form answers for passive models, to check the solver against
+//! without re-deriving the math in every test.
+//!
+//! Parameters are in the model's units: µm, Ω·cm, µF/cm², S/cm², mV, nA
+//! and ms. Potentials are absolute, leaking towards `e`.
+
+#![allow(dead_code)]
+
+use std::f64::consts::PI;
+
+/// A stretch of passive membrane
+#[derive(Debug, Clone, Copy)]
+pub struct Membrane {
+    /// Specific capacitance, in µF/cm²
+    pub cm: f64,
+    /// Leak conductance density, in S/cm²
+    pub gm: f64,
+    /// Leak reversal, in mV
+    pub e: f64,
+}
+
+impl Membrane {
+    /// Leak conductance of `area` µm², in nS
+    pub fn conductance(&self, area: f64) -> f64 {
+        self.gm * area * 10.0
+    }
+
+    /// Capacitance of `area` µm², in pF
+    pub fn capacitance(&self, area: f64) -> f64 {
+        self.cm * area * 1e-2
+    }
+}
+
+/// Side area of a cylinder `length` long and `diam` across, in µm²
+pub fn cylinder_area(length: f64, diam: f64) -> f64 {
+    PI * diam * length
+}
+
+/// Conductance, in nS, of a core of resistivity `ra` `length` long and
+/// `diam` across: pi d² / (4 ra length)
+pub fn axial_conductance(ra: f64, length: f64, diam: f64) -> f64 {
+    // Ω·cm over µm is 1e4 Ω, and S is 1e9 nS
+    PI * diam * diam / (4.0 * ra * length) * 1e-4 * 1e9
+}
+
+/// Voltage at `t` of one isopotential compartment of `area` starting at
+/// `v0` with `current` injected from 0 on
+pub fn single_compartment_step(m: Membrane, area: f64, current: f64, v0: f64, t: f64) -> f64 {
+    let (g, c) = (m.conductance(area), m.capacitance(area));
+    let v_inf = m.e + current * 1e3 / g;
+    v_inf + (v0 - v_inf) * (-t * g / c).exp()
+}
+
+/// Two compartments coupled by `ga` nS, with capacitances `c` in pF, leak
+/// conductances `g` in nS towards `e`, starting at `v0` with `current`
+/// injected from 0 on: the voltages at `t`, solving
+/// `C dx/dt = -A x + I` exactly through the eigenvectors of `C⁻¹A`
+pub fn two_compartments(
+    c: [f64; 2],
+    g: [f64; 2],
+    ga: f64,
+    e: f64,
+    current: [f64; 2],
+    v0: [f64; 2],
+    t: f64,
+) -> [f64; 2] {
+    // x = v - e; A = [[g0 + ga, -ga], [-ga, g1 + ga]], currents in pA
+    let a = [[g[0] + ga, -ga], [-ga, g[1] + ga]];
+    let b = [current[0] * 1e3, current[1] * 1e3];
+    let det = a[0][0] * a[1][1] - a[0][1] * a[1][0];
+    let x_inf = [
+        (a[1][1] * b[0] - a[0][1] * b[1]) / det,
+        (a[0][0] * b[1] - a[1][0] * b[0]) / det,
+    ];
+    let m = [
+        [a[0][0] / c[0], a[0][1] / c[0]],
+        [a[1][0] / c[1], a[1][1] / c[1]],
+    ];
+    let (trace, det_m) = (m[0][0] + m[1][1], m[0][0] * m[1][1] - m[0][1] * m[1][0]);
+    // C⁻¹A is similar to a symmetric matrix, so its eigenvalues are real
+    let root = (trace * trace / 4.0 - det_m).max(0.0).sqrt();
+    let lambda = [trace / 2.0 + root, trace / 2.0 - root];
+    let x0 = [v0[0] - e - x_inf[0], v0[1] - e - x_inf[1]];
+    let x = if ga == 0.0 {
+        [x0[0] * (-m[0][0] * t).exp(), x0[1] * (-m[1][1] * t).exp()]
+    } else {
+        // Eigenvectors (m01, lambda - m00), then x0 in their basis
+        let vectors = lambda.map(|l| [m[0][1], l - m[0][0]]);
+        let det_v = vectors[0][0] * vectors[1][1] - vectors[1][0] * vectors[0][1];
+        let k = [
+            (x0[0] * vectors[1][1] - x0[1] * vectors[1][0]) / det_v,
+            (vectors[0][0] * x0[1] - vectors[0][1] * x0[0]) / det_v,
+        ];
+        let decay = lambda.map(|l| (-l * t).exp());
+        [
+            k[0] * decay[0] * vectors[0][0] + k[1] * decay[1] * vectors[1][0],
+            k[0] * decay[0] * vectors[0][1] + k[1] * decay[1] * vectors[1][1],
+        ]
+    };
+    [e + x_inf[0] + x[0], e + x_inf[1] + x[1]]
+}
+
+/// Steady voltage at `x` µm along a uniform cable `length` long and `diam`
+/// across with sealed ends, `current` injected at x = 0:
+/// `e + I R_inf cosh((L - x)/λ) / sinh(L/λ)`
+pub fn sealed_cable_steady_state(
+    m: Membrane,
+    ra: f64,
+    diam: f64,
+    length: f64,
+    current: f64,
+    x: f64,
+) -> f64 {
+    let lambda = space_constant(m, ra, diam);
+    // Input resistance of a semi-infinite cable, in MΩ
+    let r_inf = 1e3 / (axial_conductance(ra, lambda, diam));
+    m.e + current * r_inf * ((length - x) / lambda).cosh() / (length / lambda).sinh()
+}
+
+/// λ = sqrt(d / (4 ra gm)), in µm
+pub fn space_constant(m: Membrane, ra: f64, diam: f64) -> f64 {
+    (diam * 1e-4 / (4.0 * ra * m.gm)).sqrt() * 1e4
+}
+
+/// How far a trace is from its reference
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Deviation {
+    pub max: f64,
+    pub rms: f64,
+    /// Where `max` is
+    pub at: usize,
+}
+
+pub fn deviation(actual: &[f64], expected: &[f64]) -> Deviation {
+    assert_eq!(actual.len(), expected.len(), "traces differ in length");
+    let mut d = Deviation {
+        max: 0.0,
+        rms: 0.0,
+        at: 0,
+    };
+    for (i, (a, e)) in actual.iter().zip(expected).enumerate() {
+        let error = (a - e).abs();
+        if error > d.max || error.is_nan() {
+            (d.max, d.at) = (error, i);
+        }
+        d.rms += error * error;
+    }
+    d.rms = (d.rms / actual.len().max(1) as f64).sqrt();
+    d
+}
+
+/// Panics, with the max and RMS error, unless `actual` is within
+/// `tolerance` of `expected` everywhere
+pub fn assert_close(what: &str, actual: &[f64], expected: &[f64], tolerance: f64) {
+    let d = deviation(actual, expected);
+    assert!(
+        d.max <= tolerance,
+        "{}: max error {:.3e} at {} ({} against {}), RMS {:.3e}, tolerance {:.1e}",
+        what,
+        d.max,
+        d.at,
+        actual[d.at],
+        expected[d.at],
+        d.rms,
+        tolerance
+    );
+}
@@ -0,0 +1,237 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use compartment_rs::conventions::{
+    BranchOrder, Convention, Conventions, MORPHOMETRICS, PathDistance,
+};
+use compartment_rs::features;
+use compartment_rs::{Morphometry, ReaderOptions, Skeleton, swc_reader, swc_reader_from_bytes};
+
+fn read(path: &str) -> Skeleton {
+    swc_reader(path, &ReaderOptions::default()).unwrap()
+}
+
+fn values(skeleton: &Skeleton, conventions: &Conventions) -> Vec<f64> {
+    features::morphometrics(skeleton, conventions)
+        .unwrap()
+        .values
+}
+
+/// Names of the features that differ between `a` and `b`
+fn changed(skeleton: &Skeleton, a: &Conventions, b: &Conventions) -> BTreeSet<&'static str> {
+    values(skeleton, a)
+        .into_iter()
+        .zip(values(skeleton, b))
+        .zip(MORPHOMETRICS)
+        .filter(|((x, y), _)| x != y)
+        .map(|(_, f)| f.name)
+        .collect()
+}
+
+/// Names of the features depending on any of `conventions`
+fn dependents(conventions: &[Convention]) -> BTreeSet<&'static str> {
+    MORPHOMETRICS
+        .iter()
+        .filter(|f| f.depends_on.iter().any(|c| conventions.contains(c)))
+        .map(|f| f.name)
+        .collect()
+}
+
+#[test]
+fn every_feature_agrees_with_the_reference() {
+    let text = std::fs::read_to_string("data/conventions/reference.csv").unwrap();
+    let mut reference: BTreeMap<(String, String), BTreeMap<String, f64>> = BTreeMap::new();
+    for line in text.lines().skip(2) {
+        let [file, preset, feature, value] = line.split(',').collect::<Vec<_>>()[..] else {
+            panic!("bad row {}", line);
+        };
+        reference
+            .entry((file.to_owned(), preset.to_owned()))
+            .or_default()
+            .insert(feature.to_owned(), value.parse().unwrap());
+    }
+    let presets: BTreeSet<&str> = reference.keys().map(|(_, p)| p.as_str()).collect();
+    assert_eq!(presets, BTreeSet::from(["native", "neurom"]));
+    assert_eq!(reference.len(), 6);
+
+    for ((file, preset), expected) in &reference {
+        assert_eq!(expected.len(), MORPHOMETRICS.len(), "{} {}", file, preset);
+        let conventions = Conventions::preset(preset).unwrap();
+        let computed = features::morphometrics(&read(file), &conventions).unwrap();
+        for (spec, (name, value)) in MORPHOMETRICS
+            .iter()
+            .zip(computed.names.iter().zip(computed.values))
+        {
+            assert_eq!(spec.name, name);
+            let want = expected[spec.name];
+            assert!(
+                (value - want).abs() <= spec.tolerance,
+                "{} {} {}: {} against {}",
+                file,
+                preset,
+                name,
+                value,
+                want
+            );
+        }
+    }
+}
+
+#[test]
+fn switching_a_convention_changes_only_its_features() {
+    let skeleton = read("data/conventions/fixture.swc");
+    let native = Conventions::native();
+    let toggles: [(Convention, Conventions, &[&str]); 4] = [
+        (
+            Convention::SomaAsCable,
+            Conventions {
+                soma_as_cable: false,
+                ..native
+            },
+            &[
+                "total_length",
+                "branch_count",
+                "tip_count",
+                "mean_branch_order",
+                "mean_branch_length",
+                "mean_tortuosity",
+                "max_path_distance",
+                "mean_path_distance",
+            ],
+        ),
+        (
+            Convention::StemsFromSoma,
+            Conventions {
+                stems_from_soma: false,
+                ..native
+            },
+            &[
+                "total_length",
+                "mean_branch_length",
+                "mean_tortuosity",
+                "max_path_distance",
+                "mean_path_distance",
+            ],
+        ),
+        (
+            Convention::BranchOrder,
+            Conventions {
+                branch_order: BranchOrder::FromStem(0),
+                ..native
+            },
+            &["max_branch_order", "mean_branch_order"],
+        ),
+        (
+            Convention::PathDistance,
+            Conventions {
+                path_distance: PathDistance::SegmentMidpoints,
+                ..native
+            },
+            &["max_path_distance", "mean_path_distance"],
+        ),
+    ];
+    for (convention, other, expected) in toggles {
+        assert_eq!(native.differences(&other), [convention]);
+        let changed = changed(&skeleton, &native, &other);
+        assert_eq!(
+            changed,
+            expected.iter().copied().collect(),
+            "{:?}",
+            convention
+        );
+        assert!(changed.is_subset(&dependents(&[convention])));
+    }
+
+    // Between presets, the features of the conventions they differ in
+    let presets = Conventions::presets();
+    for a in &presets {
+        for b in &presets {
+            let differences = a.differences(b);
+            let changed = changed(&skeleton, a, b);
+            assert!(
+                changed.is_subset(&dependents(&differences)),
+                "{} against {}: {:?}",
+                a.name,
+                b.name,
+                changed
+            );
+            assert_eq!(changed.is_empty(), differences.is_empty());
+            assert!(!changed.contains("stem_count"));
+        }
+    }
+}
+
+#[test]
+fn native_is_what_the_crate_always_counted() {
+    for file in [
+        "data/basic.swc",
+        "data/soma_contour.swc",
+        "data/conventions/fixture.swc",
+    ] {
+        let skeleton = read(file);
+        let native = Conventions::native();
+        assert_eq!(
+            features::branches_with(&skeleton, &native).unwrap(),
+            features::branches(&skeleton).unwrap()
+        );
+        let morphometry = Morphometry::new(&skeleton.nodes);
+        assert_eq!(
+            morphometry.total_length_with(&native).to_bits(),
+            morphometry.total_length().to_bits()
+        );
+        assert_eq!(Conventions::default(), native);
+    }
+
+    // Without soma nodes only branch order can tell the presets apart, and
+    // with a root that does not branch it does not either
+    let text = std::fs::read_to_string("data/conventions/fixture.swc").unwrap();
+    let swc: String = text
+        .lines()
+        .filter(|l| (4..=11).contains(&l.split(' ').next().unwrap().parse().unwrap_or(0)))
+        .map(|l| {
+            if l.starts_with("4 ") {
+                "4 3 0 8 0 1.2 -1\n".to_owned()
+            } else {
+                format!("{}\n", l)
+            }
+        })
+        .collect();
+    let dendrite = swc_reader_from_bytes(swc.as_bytes(), &ReaderOptions::default()).unwrap();
+    let [native, neurom] = Conventions::presets();
+    assert!(changed(&dendrite, &native, &neurom).is_empty());
+}
+
+#[test]
+fn native_stem_order_depends_on_the_number_of_stems() {
+    // Documented in the native preset's notes
+    assert!(Conventions::native().notes[0].contains("one-stem cell"));
+    let one = "1 1 0 0 0 5 -1\n2 3 10 0 0 1 1\n3 3 20 0 0 1 2\n";
+    let two = "1 1 0 0 0 5 -1\n2 3 10 0 0 1 1\n3 3 20 0 0 1 2\n4 3 -10 0 0 1 1\n";
+    let orders = |swc: &str, conventions: &Conventions| -> Vec<usize> {
+        let skeleton = swc_reader_from_bytes(swc.as_bytes(), &ReaderOptions::default()).unwrap();
+        features::branches_with(&skeleton, conventions)
+            .unwrap()
+            .iter()
+            .map(|b| b.order)
+            .collect()
+    };
+    assert_eq!(orders(one, &Conventions::native()), [0]);
+    assert_eq!(orders(two, &Conventions::native()), [1, 1]);
+
+    let numbered = Conventions {
+        branch_order: BranchOrder::FromStem(1),
+        ..Conventions::native()
+    };
+    assert_eq!(orders(one, &numbered), [1]);
+    assert_eq!(orders(two, &numbered), [1, 1]);
+    assert_eq!(orders(one, &Conventions::neurom()), [0]);
+}
+
+#[test]
+fn presets_are_found_by_name() {
+    for preset in Conventions::presets() {
+        assert_eq!(Conventions::preset(preset.name).unwrap(), preset);
+        assert!(!preset.notes.is_empty());
+    }
+    let error = Conventions::preset("navis").unwrap_err();
+    assert!(error.contains("native, neurom"), "{}", error);
+}
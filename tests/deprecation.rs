@@ -0,0 +1,26 @@
+//! Removing a shim, or moving its removal, is deliberate: update
+//! `data/golden/deprecations.txt` along with `deprecation::SHIMS`.
+
+use compartment_rs::deprecation::{SHIMS, shim, to_text};
+
+const EXPECTED: &str = "data/golden/deprecations.txt";
+
+#[test]
+fn shims_match_the_expectations_file() {
+    assert_eq!(to_text(), std::fs::read_to_string(EXPECTED).unwrap());
+}
+
+#[test]
+fn every_shim_points_somewhere_else() {
+    for s in SHIMS {
+        assert_eq!(shim(s.name), Some(s));
+        assert_ne!(s.name, s.replacement);
+        assert!(s.name.starts_with("compartment_rs."));
+        assert!(s.replacement.starts_with("compartment_rs."));
+        // A replacement must not itself be on its way out
+        assert!(shim(s.replacement).is_none(), "{}", s.replacement);
+        let version = |v: &str| -> Vec<u32> { v.split('.').map(|p| p.parse().unwrap()).collect() };
+        assert!(version(s.since) < version(s.removal), "{}", s.name);
+    }
+    assert!(shim("compartment_rs.io.load_dataset").is_none());
+}
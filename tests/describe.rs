@@ -0,0 +1,103 @@
+//! Regenerate the golden description after an intended change with
+//! `UPDATE_DESCRIPTION=1 cargo test --test describe`.
+
+use compartment_rs::channels::{ChannelType, Dynamics, HodgkinHuxley};
+use compartment_rs::{Channel, Compartments, ReaderOptions, swc_reader};
+
+const GOLDEN: &str = "data/golden/description.md";
+
+/// Passive everywhere but the soma, with a conductance that grows along the
+/// basal dendrite
+fn configured() -> Compartments {
+    let skeleton = swc_reader("data/extras.swc", &ReaderOptions::default()).unwrap();
+    let mut compartments = Compartments::from_skeleton(skeleton);
+    for c in compartments.components.iter_mut().skip(1) {
+        let mut channel = Channel::default();
+        channel.channel_type = if c.idx == 1 {
+            ChannelType::HodgkinHuxley(HodgkinHuxley::new())
+        } else {
+            ChannelType::Passive(Dynamics::new())
+        };
+        channel.resistance = 150.0;
+        channel.capacitance = 1.0;
+        channel.conductance = 3e-5;
+        c.set_channel(channel);
+    }
+    compartments
+        .set_param_at("dend[0]", 1.0, "conductance", 9e-5)
+        .unwrap();
+    compartments
+        .set_section_param("apic[0]", "capacitance", 2.0)
+        .unwrap();
+    compartments
+}
+
+#[test]
+fn markdown_matches_golden() {
+    let markdown = configured().describe().to_markdown();
+    if std::env::var_os("UPDATE_DESCRIPTION").is_some() {
+        std::fs::write(GOLDEN, &markdown).unwrap();
+    }
+    assert_eq!(markdown, std::fs::read_to_string(GOLDEN).unwrap());
+}
+
+#[test]
+fn ranges_are_the_parameter_map_extremes() {
+    let compartments = configured();
+    let description = compartments.describe();
+    for region in &description.regions {
+        let section_idxs: Vec<usize> = compartments
+            .sections()
+            .iter()
+            .filter(|s| s.name.starts_with(&format!("{}[", region.name)))
+            .flat_map(|s| s.compartments.iter().copied())
+            .collect();
+        assert_eq!(region.compartments, section_idxs.len());
+        for (name, _, range) in &region.parameters {
+            let map = compartments.parameter_map(name).unwrap();
+            let values: Vec<f64> = section_idxs.iter().map(|&i| map[i]).collect();
+            assert_eq!(
+                range.min,
+                values.iter().copied().fold(f64::INFINITY, f64::min)
+            );
+            assert_eq!(
+                range.max,
+                values.iter().copied().fold(f64::NEG_INFINITY, f64::max)
+            );
+        }
+    }
+
+    let dend = &description.regions[1];
+    assert_eq!(dend.name, "dend");
+    let conductance = &dend.parameters[1];
+    assert_eq!(conductance.0, "conductance");
+    assert_eq!((conductance.2.min, conductance.2.max), (3e-5, 9e-5));
+
+    let table = description.to_table();
+    let lookup = |key: &str| &table.iter().find(|(k, _)| k == key).unwrap().1;
+    assert_eq!(lookup("dend.conductance.max"), "0.00009");
+    assert_eq!(lookup("soma.mechanism.hh"), "1");
+    assert_eq!(
+        lookup("compartments"),
+        &description.compartments.to_string()
+    );
+}
+
+#[test]
+fn describing_again_reflects_later_changes() {
+    let mut compartments = configured();
+    let before = compartments.describe();
+    let apic = compartments.section("apic[0]").unwrap().compartments[0];
+    let mut channel = Channel::default();
+    channel.channel_type = ChannelType::HodgkinHuxley(HodgkinHuxley::new());
+    compartments.components[apic].set_channel(channel);
+    let after = compartments.describe();
+    assert_ne!(before, after);
+
+    let mechanisms = |d: &compartment_rs::ModelDescription| d.regions[2].mechanisms.clone();
+    assert_eq!(mechanisms(&before), [("pas".to_owned(), 2)]);
+    assert_eq!(
+        mechanisms(&after),
+        [("hh".to_owned(), 1), ("pas".to_owned(), 1)]
+    );
+}
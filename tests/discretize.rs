@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+
+use compartment_rs::channels::HodgkinHuxley;
+use compartment_rs::discretize::{Discretization, DiscretizationCache};
+use compartment_rs::units::{MicroFaradPerCm2, OhmCm, SiemensPerCm2};
+use compartment_rs::{Channel, ChannelType, Compartments, ReaderOptions, swc_reader};
+
+fn model() -> Compartments {
+    let skeleton = swc_reader("data/basic.swc", &ReaderOptions::default()).unwrap();
+    let mut compartments = Compartments::from_skeleton(skeleton);
+    for c in compartments.components.iter_mut().skip(1) {
+        let mut channel = Channel::passive(
+            OhmCm::new(100.0).unwrap(),
+            MicroFaradPerCm2::new(1.0).unwrap(),
+            SiemensPerCm2::new(1e-4).unwrap(),
+        );
+        channel.channel_type = ChannelType::HodgkinHuxley(HodgkinHuxley::default());
+        c.set_channel(channel);
+    }
+    compartments
+}
+
+fn assert_same(a: &Compartments, b: &Compartments) {
+    assert_eq!(a.components.len(), b.components.len());
+    for (x, y) in a.components.iter().zip(&b.components) {
+        assert_eq!(x.length.to_bits(), y.length.to_bits());
+        assert_eq!(x.diam.to_bits(), y.diam.to_bits());
+        assert_eq!(x.parent_idxs, y.parent_idxs);
+        assert_eq!(x.children_idxs, y.children_idxs);
+    }
+    assert_eq!(a.provenance, b.provenance);
+}
+
+#[test]
+fn sections_get_odd_counts_that_grow_with_length() {
+    let compartments = model();
+    let coarse = Discretization::compute(&compartments, 100.0, 0.1).unwrap();
+    let fine = Discretization::compute(&compartments, 100.0, 0.01).unwrap();
+    for (c, f) in coarse.sections.iter().zip(&fine.sections) {
+        assert_eq!(c.ncomp % 2, 1);
+        assert_eq!(f.ncomp % 2, 1);
+        assert!(f.ncomp >= c.ncomp);
+        assert_eq!(f.splits.len(), f.ncomp - 1);
+    }
+    assert!(fine.sections.iter().any(|s| s.ncomp > 1));
+    // The point soma never splits
+    assert_eq!(fine.sections[0].ncomp, 1);
+}
+
+#[test]
+fn rebuilding_keeps_the_cable() {
+    let compartments = model();
+    let discretization = Discretization::compute(&compartments, 100.0, 0.01).unwrap();
+    let rebuilt = compartments.build_with(&discretization).unwrap();
+    let expected: usize = discretization.sections.iter().map(|s| s.ncomp).sum();
+    assert_eq!(rebuilt.components.len() - 1, expected);
+
+    let total = |c: &Compartments, f: fn(&compartment_rs::Compartment) -> f64| -> f64 {
+        c.components.iter().skip(1).map(f).sum()
+    };
+    for f in [
+        |c: &compartment_rs::Compartment| c.length,
+        |c: &compartment_rs::Compartment| c.membrane_area(),
+        |c: &compartment_rs::Compartment| c.axial_resistance(),
+        |c: &compartment_rs::Compartment| c.capacitance(),
+    ] {
+        let (before, after) = (total(&compartments, f), total(&rebuilt, f));
+        assert!((before - after).abs() < 1e-9 * before.abs().max(1.0));
+    }
+
+    // Every node is still covered exactly once
+    let mut covered: HashMap<u64, f64> = HashMap::new();
+    for span in rebuilt.provenance.iter().flatten() {
+        *covered.entry(span.node_id).or_default() += span.to - span.from;
+    }
+    assert_eq!(covered.len(), compartments.components.len() - 1);
+    for width in covered.values() {
+        assert!((width - 1.0).abs() < 1e-12);
+    }
+
+    // Section names and the mechanisms survive
+    let names = |c: &Compartments| {
+        c.sections()
+            .iter()
+            .map(|s| s.name.clone())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(names(&compartments), names(&rebuilt));
+    assert!(
+        rebuilt
+            .components
+            .iter()
+            .skip(1)
+            .all(|c| matches!(c.channel.channel_type, ChannelType::HodgkinHuxley(_)))
+    );
+}
+
+#[test]
+fn building_twice_is_identical() {
+    let compartments = model();
+    let discretization = Discretization::compute(&compartments, 100.0, 0.01).unwrap();
+    let a = compartments.build_with(&discretization).unwrap();
+    let b = model().build_with(&discretization).unwrap();
+    assert_same(&a, &b);
+}
+
+#[test]
+fn channel_densities_reuse_the_cache() {
+    let mut compartments = model();
+    let mut cache = DiscretizationCache::new();
+    let first = cache.get(&compartments, 100.0, 0.1).unwrap().clone();
+    for c in compartments.components.iter_mut().skip(1) {
+        if let ChannelType::HodgkinHuxley(hh) = &mut c.channel.channel_type {
+            hh.gnabar = 0.2;
+        }
+        c.channel.conductance = 3e-4;
+    }
+    let again = cache.get(&compartments, 100.0, 0.1).unwrap().clone();
+    assert_eq!(cache.computations(), 1);
+    assert_eq!(first, again);
+    assert!(first.is_valid_for(&compartments));
+
+    compartments.components[3].channel.resistance = 150.0;
+    assert!(!first.is_valid_for(&compartments));
+    cache.get(&compartments, 100.0, 0.1).unwrap();
+    assert_eq!(cache.computations(), 2);
+    // A different rule setting is a different artifact too
+    cache.get(&compartments, 100.0, 0.05).unwrap();
+    assert_eq!(cache.computations(), 3);
+}
+
+#[test]
+fn stale_discretizations_are_refused() {
+    let mut compartments = model();
+    let discretization = Discretization::compute(&compartments, 100.0, 0.1).unwrap();
+    compartments.components[2].channel.capacitance = 2.0;
+    let err = compartments.build_with(&discretization).err().unwrap();
+    assert!(err.contains("stale"));
+}
+
+#[test]
+fn text_round_trips_and_rebuilds_identically() {
+    let compartments = model();
+    let discretization = Discretization::compute(&compartments, 100.0, 0.01).unwrap();
+    let text = discretization.to_text();
+    let parsed = Discretization::parse(&text).unwrap();
+    assert_eq!(parsed, discretization);
+    assert_same(
+        &compartments.build_with(&discretization).unwrap(),
+        &compartments.build_with(&parsed).unwrap(),
+    );
+
+    assert!(Discretization::parse("").is_err());
+    assert!(Discretization::parse(&text.replace("dend[0] 5 ", "dend[0] 7 ")).is_err());
+}
+
+#[test]
+fn d_lambda_rule_needs_passive_properties() {
+    let skeleton = swc_reader("data/basic.swc", &ReaderOptions::default()).unwrap();
+    let bare = Compartments::from_skeleton(skeleton);
+    assert!(bare.d_lambda_rule(100.0, 0.1).is_err());
+    assert!(model().d_lambda_rule(0.0, 0.1).is_err());
+    assert!(model().d_lambda_rule(100.0, 0.1).is_ok());
+}
@@ -0,0 +1,160 @@
+use compartment_rs::{NodeFlags, ReaderOptions, Skeleton, swc_reader};
+
+fn basic() -> Skeleton {
+    swc_reader("data/basic.swc", &ReaderOptions::default()).unwrap()
+}
+
+fn distance(skeleton: &Skeleton, a: u64, b: u64) -> f64 {
+    let a = skeleton.nodes.iter().find(|n| n.node_id == a).unwrap();
+    let b = skeleton.nodes.iter().find(|n| n.node_id == b).unwrap();
+    ((a.x_pos - b.x_pos).powi(2) + (a.y_pos - b.y_pos).powi(2) + (a.z_pos - b.z_pos).powi(2)).sqrt()
+}
+
+#[test]
+fn reader_output_passes_validation() {
+    basic().validate_maps().unwrap();
+}
+
+#[test]
+fn insert_node_on_edge_keeps_maps_valid() {
+    let mut skeleton = basic().edit();
+    // Node 8 sits at (25, -5) under node 4 at (15, 0)
+    let new_id = skeleton.insert_node_on_edge(4, 8, 0.5, 0.8).unwrap();
+    skeleton.validate_maps().unwrap();
+
+    let node = skeleton.nodes.iter().find(|n| n.node_id == new_id).unwrap();
+    assert_eq!((node.x_pos, node.y_pos), (20.0, -2.5));
+    assert_eq!(node.parent_id, 4);
+    assert_eq!(node.flags, NodeFlags::COORD_INTERPOLATED);
+    assert_eq!(skeleton.children_of(new_id), vec![8]);
+    assert_eq!(skeleton.children_of(4), vec![7, new_id]);
+
+    assert!(skeleton.insert_node_on_edge(4, 9, 0.5, 1.0).is_err());
+}
+
+#[test]
+fn reattach_refuses_cycles() {
+    let mut skeleton = basic().edit();
+    // 7 is a grandchild of 1
+    let err = skeleton.reattach_subtree(1, 7).unwrap_err();
+    assert!(err.contains("cycle"), "{}", err);
+    assert!(skeleton.reattach_subtree(0, 7).is_err());
+    skeleton.validate_maps().unwrap();
+
+    skeleton.reattach_subtree(10, 2).unwrap();
+    skeleton.validate_maps().unwrap();
+    assert!(skeleton.children_of(6).is_empty());
+}
+
+#[test]
+fn split_branch_at_arc_length() {
+    let mut skeleton = basic().edit();
+    // The branch ending at node 11 starts at the branch point 4
+    assert_eq!(skeleton.branch_path(11).unwrap(), vec![4, 7, 11]);
+
+    let new_id = skeleton.split_branch_at(11, 5.0).unwrap();
+    skeleton.validate_maps().unwrap();
+    assert!((distance(&skeleton, 4, new_id) - 5.0).abs() < 1e-12);
+    assert_eq!(skeleton.branch_path(11).unwrap(), vec![4, new_id, 7, 11]);
+
+    // Landing on an existing node does not insert anything
+    let n = skeleton.nodes.len();
+    let at_seven = distance(&skeleton, 4, 7);
+    assert_eq!(skeleton.split_branch_at(11, at_seven).unwrap(), 7);
+    assert_eq!(skeleton.nodes.len(), n);
+
+    assert!(skeleton.split_branch_at(11, 1000.0).is_err());
+}
+
+#[test]
+fn insert_reattach_finalize_matches_hand_built_tree() {
+    let mut skeleton = basic().edit();
+    let new_id = skeleton.insert_node_on_edge(4, 8, 0.5, 0.8).unwrap();
+    skeleton.reattach_subtree(new_id, 7).unwrap();
+    skeleton.finalize().unwrap();
+    skeleton.validate_maps().unwrap();
+
+    // (id, parent, x, y) worked out by hand from data/basic.swc
+    let expected = [
+        (0, 0, 0.0, 0.0),
+        (1, 0, 5.0, 0.0),
+        (2, 0, 0.0, 5.0),
+        (3, 0, -5.0, 0.0),
+        (4, 1, 15.0, 0.0),
+        (5, 2, 0.0, 20.0),
+        (6, 3, -25.0, 0.0),
+        (7, 4, 25.0, 5.0),
+        (8, 5, 0.0, 40.0),
+        (9, 6, -45.0, 0.0),
+        (10, 7, 35.0, 10.0),
+        (11, 7, 20.0, -2.5),
+        (12, 8, -10.0, 50.0),
+        (13, 8, 10.0, 50.0),
+        (14, 11, 25.0, -5.0),
+        (15, 14, 35.0, -10.0),
+    ];
+    assert_eq!(skeleton.nodes.len(), expected.len());
+    for (node, (id, parent, x, y)) in skeleton.nodes.iter().zip(expected) {
+        assert_eq!(
+            (node.node_id, node.parent_id, node.x_pos, node.y_pos),
+            (id, parent, x, y)
+        );
+    }
+}
+
+#[test]
+fn prune_removes_the_whole_subtree() {
+    let mut skeleton = basic().edit();
+    // The apical dendrite: 2, 5, 9 and the tips 13 and 14
+    assert_eq!(skeleton.prune_subtree(2).unwrap(), 5);
+    skeleton.validate_maps().unwrap();
+    assert_eq!(skeleton.nodes.len(), 10);
+    assert_eq!(skeleton.children_of(0), vec![1, 3]);
+    assert!(skeleton.prune_subtree(0).is_err());
+    assert!(skeleton.prune_subtree(9).is_err());
+}
+
+#[test]
+fn reroot_turns_the_path_around() {
+    let mut skeleton = basic().edit();
+    skeleton.reroot_at(11).unwrap();
+    skeleton.validate_maps().unwrap();
+    let parent =
+        |s: &Skeleton, id: u64| s.nodes.iter().find(|n| n.node_id == id).unwrap().parent_id;
+    assert_eq!(parent(&skeleton, 11), 11);
+    assert_eq!(parent(&skeleton, 7), 11);
+    assert_eq!(parent(&skeleton, 4), 7);
+    assert_eq!(parent(&skeleton, 0), 1);
+    assert_eq!(skeleton.children_of(4), vec![8, 1]);
+
+    skeleton.finalize().unwrap();
+    assert_eq!(skeleton.nodes.len(), 15);
+}
+
+#[test]
+fn smoothing_moves_only_inner_nodes() {
+    let mut skeleton = basic().edit();
+    let before = skeleton.nodes.clone();
+    // 1, 2, 3, 5, 6, 7 and 8 have one child each; the rest are the root,
+    // branch points or tips
+    assert_eq!(skeleton.smooth_coordinates(1), 7);
+    skeleton.validate_maps().unwrap();
+    // Node 2 at (0, 5) sits between the root and (0, 20)
+    let two = skeleton.nodes.iter().find(|n| n.node_id == 2).unwrap();
+    assert_eq!((two.x_pos, two.y_pos), (0.0, 7.5));
+    for (a, b) in before.iter().zip(skeleton.nodes.iter()) {
+        if [0, 4, 9, 10, 11, 12, 13, 14].contains(&a.node_id) {
+            assert_eq!((a.x_pos, a.y_pos, a.z_pos), (b.x_pos, b.y_pos, b.z_pos));
+        }
+    }
+}
+
+#[test]
+fn zero_radii_take_their_parents() {
+    let mut skeleton = basic().edit();
+    // The axon tip at (-45, 0) was read with radius 0
+    assert_eq!(skeleton.repair_zero_radii(), 1);
+    let tip = skeleton.nodes.iter().find(|n| n.node_id == 10).unwrap();
+    assert_eq!(tip.radius, 0.4);
+    assert!(tip.flags.contains(NodeFlags::ZERO_RADIUS_FIXED));
+}
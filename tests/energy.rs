@@ -0,0 +1,264 @@
+use std::f64::consts::PI;
+
+use compartment_rs::accumulation::{AccumulationConfig, FARADAY, IonAccumulation};
+use compartment_rs::channels::HodgkinHuxley;
+use compartment_rs::energy::EnergyLedger;
+use compartment_rs::solver::Simulation;
+use compartment_rs::units::{MicroFaradPerCm2, OhmCm, SiemensPerCm2};
+use compartment_rs::{
+    Channel, ChannelType, Compartments, Ion, ReaderOptions, swc_reader_from_bytes,
+};
+
+/// Membrane area of the test compartment, in µm²
+const AREA: f64 = 1000.0;
+/// mA/cm² over `AREA`, in nA
+const TO_NA: f64 = AREA * 1e-2;
+
+/// One HH compartment driven by a 1 ms, 20 µA/cm² pulse over 30 ms, booking
+/// every current in `ledger` and returning the dense reference charges
+/// `[Na, K, leak]` summed plainly
+fn action_potential(ledger: &mut EnergyLedger, dt: f64) -> ([f64; 3], f64) {
+    let hh = HodgkinHuxley::default();
+    let mut v = -65.0;
+    let mut gates = HodgkinHuxley::steady_state(v);
+    let mut reference = [0.0; 3];
+    let mut peak = v;
+    for k in 0..(30.0 / dt) as usize {
+        let t = k as f64 * dt;
+        let stimulus = if (1.0..2.0).contains(&t) { 20e-3 } else { 0.0 };
+        let currents = hh.currents(&gates, v);
+        let reversals = [hh.ena, hh.ek, hh.el];
+        for i in 0..3 {
+            let current = currents[i] * TO_NA;
+            ledger.record(0, HodgkinHuxley::IONS[i], current, v - reversals[i], dt);
+            reference[i] += current * dt;
+        }
+        // 1 µF/cm²; currents in mA/cm² move v by 1e3 mV/ms each
+        v += dt * 1e3 * (stimulus - currents.iter().sum::<f64>());
+        HodgkinHuxley::step_gates(&mut gates, v, dt);
+        peak = peak.max(v);
+    }
+    (reference, peak)
+}
+
+#[test]
+fn sodium_and_potassium_charge_balance_over_a_spike() {
+    let mut ledger = EnergyLedger::new(1);
+    let (reference, peak) = action_potential(&mut ledger, 0.001);
+    assert!(peak > 20.0, "no spike, peak {}", peak);
+    let report = ledger.report();
+    let na = report.charge[0][&Ion::Na];
+    let k = report.charge[0][&Ion::K];
+    assert!(na < 0.0 && k > 0.0);
+    // Back at rest, the charge Na brought in has gone out again, most of
+    // it through K and the rest through the leak
+    let ratio = k / -na;
+    assert!((0.7..1.3).contains(&ratio), "K/Na {}", ratio);
+
+    for (i, ion) in [Ion::Na, Ion::K, Ion::NonSpecific].iter().enumerate() {
+        let booked = report.charge[0][ion];
+        assert!((booked - reference[i]).abs() <= 1e-9 * reference[i].abs());
+    }
+    assert_eq!(report.charge[0][&Ion::Ca], 0.0);
+    assert!(report.energy[0] > 0.0);
+}
+
+#[test]
+fn passive_compartments_carry_no_specific_ions() {
+    let mut ledger = EnergyLedger::new(2);
+    for _ in 0..1000 {
+        ledger.record(1, Ion::NonSpecific, 0.3, 5.0, 0.025);
+    }
+    let report = ledger.report();
+    for ion in [Ion::Na, Ion::K, Ion::Ca, Ion::Cl] {
+        assert_eq!(report.charge[1][&ion], 0.0);
+        assert_eq!(report.total_charge[&ion], 0.0);
+    }
+    assert!((report.charge[1][&Ion::NonSpecific] - 7.5).abs() < 1e-12);
+    assert!((report.energy[1] - 37.5).abs() < 1e-12);
+    assert_eq!(report.energy[0], 0.0);
+}
+
+#[test]
+fn whole_cell_totals_do_not_depend_on_compartment_order() {
+    let values: Vec<f64> = (0..50)
+        .map(|i| ((i * 37 % 11) as f64 - 5.0) * 0.1 + 1e-7 * i as f64)
+        .collect();
+    let mut forward = EnergyLedger::new(values.len());
+    let mut backward = EnergyLedger::new(values.len());
+    for (i, &current) in values.iter().enumerate() {
+        forward.record(i, Ion::Na, current, 1.0, 0.1);
+        backward.record(values.len() - 1 - i, Ion::Na, current, 1.0, 0.1);
+    }
+    let (a, b) = (forward.report(), backward.report());
+    assert_eq!(a.total_charge[&Ion::Na], b.total_charge[&Ion::Na]);
+    assert_eq!(a.total_energy, b.total_energy);
+    let plain: f64 = a.charge.iter().map(|c| c[&Ion::Na]).sum();
+    assert!((a.total_charge[&Ion::Na] - plain).abs() < 1e-12);
+}
+
+/// Point soma with 100 µm cylinders 2 µm across at indices 2 and 3, all
+/// with `channel`'s mechanism
+fn cable(channel: impl Fn() -> Channel) -> Compartments {
+    let swc = "1 1 0 0 0 5 -1\n2 3 100 0 0 1 1\n3 3 200 0 0 1 2\n";
+    let skeleton = swc_reader_from_bytes(swc.as_bytes(), &ReaderOptions::default()).unwrap();
+    let mut compartments = Compartments::from_skeleton(skeleton);
+    for c in compartments.components.iter_mut() {
+        c.set_channel(channel());
+    }
+    compartments
+}
+
+fn hh() -> Channel {
+    let mut channel = Channel::default();
+    channel.channel_type = ChannelType::HodgkinHuxley(HodgkinHuxley::default());
+    channel.resistance = 100.0;
+    channel.capacitance = 1.0;
+    channel
+}
+
+/// 1 ms of 0.5 nA into compartment 2 after 1 ms, 20 ms in all
+fn pulse(dt: f64) -> (usize, Vec<(usize, Vec<f64>)>) {
+    let steps = (20.0 / dt) as usize;
+    let waveform = (0..steps)
+        .map(|s| {
+            if (1.0..2.0).contains(&(s as f64 * dt)) {
+                0.5
+            } else {
+                0.0
+            }
+        })
+        .collect();
+    (steps, vec![(2, waveform)])
+}
+
+#[test]
+fn a_simulated_spike_books_its_currents() {
+    let compartments = cable(hh);
+    let dt = 0.01;
+    let (steps, stimuli) = pulse(dt);
+    let mut simulation = Simulation::new(&compartments, dt)
+        .unwrap()
+        .with_energy_accounting();
+    for path in ["comp[2].v", "comp[2].hh.m", "comp[2].hh.h", "comp[2].hh.n"] {
+        simulation.record(path).unwrap();
+    }
+    let result = simulation.run(steps, &stimuli).unwrap();
+    let [v, m, h, n] = [0, 1, 2, 3].map(|k| &result.traces[k].1);
+    assert!(v.iter().cloned().fold(f64::MIN, f64::max) > 20.0);
+    let report = result.energy_report().unwrap();
+    assert_eq!(Some(report), simulation.energy_report().as_ref());
+
+    // Each step's currents at its new voltage and gates, summed plainly
+    let hh = HodgkinHuxley::default();
+    let area = compartments.components[2].membrane_area();
+    let mut reference = [0.0; 3];
+    for s in 1..=steps {
+        let g = [
+            hh.gnabar * m[s].powi(3) * h[s],
+            hh.gkbar * n[s].powi(4),
+            hh.gl,
+        ];
+        let e = [hh.ena, hh.ek, hh.el];
+        for k in 0..3 {
+            reference[k] += g[k] * area * 10.0 * (v[s] - e[k]) * 1e-3 * dt;
+        }
+    }
+    for (k, ion) in [Ion::Na, Ion::K, Ion::NonSpecific].iter().enumerate() {
+        let booked = report.charge[2][ion];
+        assert!(
+            (booked - reference[k]).abs() <= 1e-9 * reference[k].abs(),
+            "{:?}: {} against {}",
+            ion,
+            booked,
+            reference[k]
+        );
+    }
+    let (na, k) = (report.charge[2][&Ion::Na], report.charge[2][&Ion::K]);
+    assert!(na < 0.0 && k > 0.0);
+    let ratio = k / -na;
+    assert!((0.7..1.3).contains(&ratio), "K/Na {}", ratio);
+    assert!(report.energy[2] > 0.0);
+
+    // The point soma has no membrane, and the whole cell is the sum of
+    // its compartments
+    assert!(report.charge[1].values().all(|&c| c == 0.0));
+    for ion in [Ion::Na, Ion::K, Ion::NonSpecific] {
+        let summed = report.charge[2][&ion] + report.charge[3][&ion];
+        assert!((report.total_charge[&ion] - summed).abs() <= 1e-12 * summed.abs());
+    }
+    assert_eq!(report.charge[2][&Ion::Ca], 0.0);
+}
+
+#[test]
+fn a_simulated_passive_cell_carries_no_specific_ions() {
+    let passive = || {
+        Channel::passive(
+            OhmCm::new(100.0).unwrap(),
+            MicroFaradPerCm2::new(1.0).unwrap(),
+            SiemensPerCm2::new(1e-4).unwrap(),
+        )
+    };
+    let compartments = cable(passive);
+    let (steps, stimuli) = pulse(0.025);
+    let result = Simulation::new(&compartments, 0.025)
+        .unwrap()
+        .with_energy_accounting()
+        .run(steps, &stimuli)
+        .unwrap();
+    let report = result.energy_report().unwrap();
+    for ion in [Ion::Na, Ion::K, Ion::Ca, Ion::Cl] {
+        assert_eq!(report.total_charge[&ion], 0.0);
+    }
+    assert!(report.charge[2][&Ion::NonSpecific] != 0.0);
+    assert!(report.total_energy > 0.0);
+
+    let plain = Simulation::new(&compartments, 0.025)
+        .unwrap()
+        .run(steps, &stimuli)
+        .unwrap();
+    assert!(plain.energy_report().is_none());
+    assert_eq!(plain.voltages, result.voltages);
+}
+
+#[test]
+fn concentrations_move_with_the_booked_charge() {
+    let mut compartments = cable(hh);
+    for c in compartments.components.iter_mut() {
+        let mut channel = c.channel.clone();
+        channel.channel_type = ChannelType::HodgkinHuxley(HodgkinHuxley {
+            use_dynamic_reversal: true,
+            ..HodgkinHuxley::default()
+        });
+        c.set_channel(channel);
+    }
+    let dt = 0.01;
+    let (steps, stimuli) = pulse(dt);
+    let accumulation = IonAccumulation::new(&compartments, AccumulationConfig::default()).unwrap();
+    let mut simulation = Simulation::new(&compartments, dt)
+        .unwrap()
+        .with_accumulation(accumulation)
+        .unwrap()
+        .with_energy_accounting();
+    simulation.run(steps, &stimuli).unwrap();
+
+    let report = simulation.energy_report().unwrap();
+    let accumulation = simulation.accumulation().unwrap();
+    for idx in [2, 3] {
+        let c = &compartments.components[idx];
+        let volume = PI * c.diam * c.diam / 4.0 * c.length;
+        for (ion, start) in [(Ion::K, 54.4), (Ion::Na, 10.0)] {
+            let lost = start - accumulation.inside(ion).unwrap()[idx];
+            let charge = lost * volume * FARADAY / 1e6;
+            let booked = report.charge[idx][&ion];
+            assert!(
+                (charge - booked).abs() < 1e-9 * booked.abs(),
+                "{:?} in {}: {} against {}",
+                ion,
+                idx,
+                charge,
+                booked
+            );
+        }
+    }
+}
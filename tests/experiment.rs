@@ -0,0 +1,264 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use compartment_rs::experiment::{ConfigError, Experiment, Location, TraceFormat, Waveform};
+use compartment_rs::validation::ReferenceTrace;
+
+fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("experiment-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn data() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("data")
+}
+
+fn cli(args: &[&str]) -> (bool, String, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_compartment-rs"))
+        .args(args)
+        .output()
+        .unwrap();
+    (
+        output.status.success(),
+        String::from_utf8(output.stdout).unwrap(),
+        String::from_utf8(output.stderr).unwrap(),
+    )
+}
+
+/// The checked-in example with its morphology and output made absolute and
+/// `replace` applied, written to `dir`
+fn config(dir: &Path, replace: &[(&str, &str)]) -> PathBuf {
+    let mut text = fs::read_to_string(data().join("experiment.toml")).unwrap();
+    let swc = data().join("basic.swc");
+    text = text.replace("\"basic.swc\"", &format!("{:?}", swc.display().to_string()));
+    for (from, to) in replace {
+        assert!(text.contains(from), "{}", from);
+        text = text.replace(from, to);
+    }
+    let path = dir.join("experiment.toml");
+    fs::write(&path, text).unwrap();
+    path
+}
+
+fn at(errors: &[ConfigError], key: &str) -> ConfigError {
+    errors
+        .iter()
+        .find(|e| e.key == key)
+        .unwrap_or_else(|| panic!("no error at {} in {:?}", key, errors))
+        .clone()
+}
+
+#[test]
+fn the_example_runs_end_to_end() {
+    let out = scratch("example");
+    let config = data().join("experiment.toml");
+    let (ok, stdout, stderr) = cli(&["validate", config.to_str().unwrap()]);
+    assert!(ok, "{}", stderr);
+    assert!(
+        stdout.contains("2 stimuli, 3 probes, 2000 steps"),
+        "{}",
+        stdout
+    );
+
+    let (ok, stdout, stderr) = cli(&[
+        "run",
+        config.to_str().unwrap(),
+        "--output",
+        out.to_str().unwrap(),
+    ]);
+    assert!(ok, "{}", stderr);
+    let mut written: Vec<String> = fs::read_dir(&out)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .collect();
+    written.sort();
+    assert_eq!(
+        written,
+        [
+            "axon.csv",
+            "axon.npy",
+            "dend.csv",
+            "dend.npy",
+            "manifest.txt",
+            "soma.csv",
+            "soma.npy"
+        ]
+    );
+    assert_eq!(stdout.lines().count(), 7);
+
+    let csv = ReferenceTrace::load(out.join("soma.csv")).unwrap();
+    let npy = ReferenceTrace::load(out.join("soma.npy")).unwrap();
+    assert_eq!(csv, npy);
+    assert_eq!(csv.times().len(), 2001);
+    assert_eq!(csv.values()[0], -65.0);
+    // The step fires the soma
+    assert!(csv.values().iter().any(|&v| v > 0.0));
+    let manifest = fs::read_to_string(out.join("manifest.txt")).unwrap();
+    assert!(manifest.starts_with("manifest 1\n"));
+}
+
+#[test]
+fn validation_points_at_the_offending_key() {
+    let dir = scratch("validate");
+    let path = config(
+        &dir,
+        &[
+            ("capacitance = 1.0", "capacitence = 1.0"),
+            ("exp(-dist / 50)", "exp(-dist / 50))"),
+            ("site = \"axon[0](0.5)\"", "site = \"axon[3](0.5)\""),
+        ],
+    );
+    let text = fs::read_to_string(&path).unwrap();
+
+    // The misspelling and the expression fail on reading
+    let errors = Experiment::load(&path).unwrap_err();
+    assert_eq!(errors.len(), 2, "{:?}", errors);
+    let typo = at(&errors, "membrane.capacitence");
+    assert_eq!(
+        typo.location,
+        Some(Location {
+            line: 11,
+            column: 1
+        })
+    );
+    assert!(
+        typo.message.contains("did you mean 'capacitance'"),
+        "{}",
+        typo
+    );
+    let expression = at(&errors, "rules[0].expression");
+    let line = text.lines().nth(16).unwrap();
+    assert_eq!(
+        expression.location,
+        Some(Location {
+            line: 17,
+            column: line.rfind(')').unwrap() + 1
+        })
+    );
+
+    // The probe site once the morphology is read
+    let path = config(
+        &dir,
+        &[("site = \"axon[0](0.5)\"", "site = \"axon[3](0.5)\"")],
+    );
+    let experiment = Experiment::load(&path).unwrap();
+    let errors = experiment.validate().unwrap_err();
+    assert_eq!(errors.len(), 1, "{:?}", errors);
+    let site = at(&errors, "probes[2].site");
+    assert_eq!(
+        site.location,
+        Some(Location {
+            line: 43,
+            column: 8
+        })
+    );
+    assert!(site.message.contains("axon[3]"), "{}", site);
+
+    // The command prints them against the file and fails
+    let (ok, _, stderr) = cli(&["validate", path.to_str().unwrap()]);
+    assert!(!ok);
+    assert_eq!(
+        stderr.trim(),
+        format!("{}:43:8: probes[2].site: {}", path.display(), site.message)
+    );
+    let (ok, _, stderr) = cli(&["run", path.to_str().unwrap()]);
+    assert!(!ok);
+    assert!(stderr.contains(":43:8: probes[2].site"), "{}", stderr);
+    assert!(!dir.join("experiment_results").exists());
+}
+
+#[test]
+fn malformed_values_are_reported_together() {
+    let text = r#"
+[morphology]
+path = "basic.swc"
+
+[simulation]
+dt = -1
+duration = "long"
+
+[[stimuli]]
+site = "soma[0](0.5)"
+kind = "ramp"
+
+[[probes]]
+name = "../soma"
+site = "soma[0](0.5)"
+
+[output]
+formats = ["csv", "hdf5"]
+"#;
+    let errors = Experiment::from_toml(text, data()).unwrap_err();
+    let keys: Vec<&str> = errors.iter().map(|e| e.key.as_str()).collect();
+    assert_eq!(
+        keys,
+        [
+            "simulation.dt",
+            "simulation.duration",
+            "stimuli[0].kind",
+            "probes[0].name",
+            "output.formats"
+        ]
+    );
+    assert_eq!(
+        errors[0].to_string(),
+        "6:6: simulation.dt: Must be positive, got -1"
+    );
+    assert_eq!(errors[4].location.unwrap().column, 19);
+
+    let syntax = Experiment::from_toml("[morphology\npath = 1", data()).unwrap_err();
+    assert_eq!(syntax.len(), 1);
+    assert_eq!(syntax[0].location.unwrap().line, 1);
+}
+
+#[test]
+fn a_programmatic_experiment_matches_the_command() {
+    let dir = scratch("programmatic");
+    let out = dir.join("out");
+    let path = config(&dir, &[]);
+    let (ok, _, stderr) = cli(&[
+        "run",
+        path.to_str().unwrap(),
+        "--output",
+        out.to_str().unwrap(),
+    ]);
+    assert!(ok, "{}", stderr);
+
+    // Loaded from the same file
+    let loaded = Experiment::load(data().join("experiment.toml")).unwrap();
+    assert_eq!(loaded.morphology, data().join("basic.swc"));
+    assert_eq!(loaded.output.formats, [TraceFormat::Csv, TraceFormat::Npy]);
+    let result = loaded.run().unwrap();
+    for (name, trace) in &result.traces {
+        let written = ReferenceTrace::load(out.join(format!("{}.csv", name))).unwrap();
+        assert!(written.values() == &trace[..], "{}", name);
+    }
+
+    // And built in code
+    let mut experiment = Experiment::new(data().join("basic.swc"));
+    experiment.membrane = loaded.membrane;
+    experiment.profile = loaded.profile.clone();
+    experiment.stimuli = loaded.stimuli.clone();
+    assert!(matches!(
+        experiment.stimuli[0].waveform,
+        Waveform::Step { amplitude, .. } if amplitude == 0.05
+    ));
+    experiment.probes = loaded.probes.clone();
+    experiment.dt = 0.025;
+    experiment.duration = 50.0;
+    experiment.seed = 1;
+    experiment.output.directory = dir.join("code");
+    let built = experiment.run().unwrap();
+    assert!(built.traces == result.traces);
+    experiment.write(&built).unwrap();
+    for name in ["soma", "dend", "axon"] {
+        let file = format!("{}.csv", name);
+        assert_eq!(
+            fs::read(out.join(&file)).unwrap(),
+            fs::read(dir.join("code").join(&file)).unwrap()
+        );
+    }
+}
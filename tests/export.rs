@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+use compartment_rs::export::Column;
+use compartment_rs::features;
+use compartment_rs::{
+    Compartments, ConflictPolicy, ExportColumns, NodeTable, ReaderOptions, Skeleton, swc_reader,
+};
+
+fn basic() -> Skeleton {
+    swc_reader("data/basic.swc", &ReaderOptions::default()).unwrap()
+}
+
+fn ints(table: &NodeTable, name: &str) -> Vec<i64> {
+    match table.column(name) {
+        Some(Column::Int(v)) => v.clone(),
+        other => panic!("{} is not an integer column: {:?}", name, other),
+    }
+}
+
+fn floats(table: &NodeTable, name: &str) -> Vec<f64> {
+    match table.column(name) {
+        Some(Column::Float(v)) => v.clone(),
+        other => panic!("{} is not a float column: {:?}", name, other),
+    }
+}
+
+fn bools(table: &NodeTable, name: &str) -> Vec<bool> {
+    match table.column(name) {
+        Some(Column::Bool(v)) => v.clone(),
+        other => panic!("{} is not a bool column: {:?}", name, other),
+    }
+}
+
+#[test]
+fn base_columns_only_by_default() {
+    let table = basic().node_table(ExportColumns::empty()).unwrap();
+    assert_eq!(
+        table.names(),
+        ["id", "type", "x", "y", "z", "radius", "parent"]
+    );
+    let csv = table.to_csv();
+    assert_eq!(csv.lines().next(), Some("id,type,x,y,z,radius,parent"));
+    assert_eq!(csv.lines().count(), 16);
+    assert_eq!(ints(&table, "parent")[0], -1);
+}
+
+#[test]
+fn topology_columns_match_the_skeleton() {
+    let skeleton = basic();
+    let table = skeleton.node_table(ExportColumns::all()).unwrap();
+    let n_children = ints(&table, "n_children");
+    let tips = bools(&table, "is_tip");
+    let branch_points = bools(&table, "is_branch_point");
+    for (k, node) in skeleton.nodes.iter().enumerate() {
+        let children = skeleton.children_of(node.node_id).len();
+        assert_eq!(n_children[k], children as i64);
+        assert_eq!(tips[k], children == 0);
+        assert_eq!(branch_points[k], children > 1);
+    }
+    assert_eq!(
+        ints(&table, "flags"),
+        skeleton
+            .nodes
+            .iter()
+            .map(|n| n.flags.bits() as i64)
+            .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn distances_and_orders_match_the_compartment_maps() {
+    let skeleton = basic();
+    let table = skeleton.node_table(ExportColumns::all()).unwrap();
+    let ids = ints(&table, "id");
+    let compartments = Compartments::from_skeleton(skeleton);
+    let distance = compartments.parameter_map("path_distance").unwrap();
+    let order = compartments.parameter_map("branch_order").unwrap();
+    // Compartment `id + 1` is built from node `id`
+    for (k, id) in ids.iter().enumerate() {
+        let idx = *id as usize + 1;
+        assert!((floats(&table, "path_distance")[k] - distance[idx]).abs() < 1e-9);
+        assert_eq!(ints(&table, "branch_order")[k] as f64, order[idx]);
+    }
+}
+
+#[test]
+fn branch_orders_match_features_at_branch_ends() {
+    let skeleton = basic();
+    let table = skeleton.node_table(ExportColumns::BRANCH_ORDER).unwrap();
+    let row: HashMap<i64, usize> = ints(&table, "id")
+        .into_iter()
+        .enumerate()
+        .map(|(k, id)| (id, k))
+        .collect();
+    for branch in features::branches(&skeleton).unwrap() {
+        let end = *branch.path.last().unwrap() as i64;
+        assert_eq!(ints(&table, "branch_order")[row[&end]], branch.order as i64);
+    }
+}
+
+#[test]
+fn strahler_and_subtree_length() {
+    let skeleton = basic();
+    let table = skeleton.node_table(ExportColumns::all()).unwrap();
+    let strahler = ints(&table, "strahler_order");
+    let subtree_length = floats(&table, "subtree_cable_length");
+    let at = |x: f64, y: f64| {
+        skeleton
+            .nodes
+            .iter()
+            .position(|n| n.x_pos == x && n.y_pos == y)
+            .unwrap()
+    };
+
+    // Basal and apical dendrites each fork once into two tips; the axon
+    // does not fork, so the soma joins two order 2 branches and one order 1
+    assert_eq!(strahler[at(0.0, 0.0)], 3);
+    assert_eq!(strahler[at(5.0, 0.0)], 2);
+    assert_eq!(strahler[at(0.0, 40.0)], 2);
+    assert_eq!(strahler[at(-5.0, 0.0)], 1);
+    assert_eq!(strahler[at(35.0, 10.0)], 1);
+
+    let total = compartment_rs::Morphometry::new(&skeleton.nodes).total_length();
+    assert!((subtree_length[at(0.0, 0.0)] - total).abs() < 1e-9);
+    assert_eq!(subtree_length[at(35.0, -10.0)], 0.0);
+    assert!((subtree_length[at(-25.0, 0.0)] - 20.0).abs() < 1e-12);
+}
+
+#[test]
+fn deselected_columns_are_left_out() {
+    let columns = ExportColumns::IS_TIP | ExportColumns::PATH_DISTANCE;
+    let table = basic().node_table(columns).unwrap();
+    assert_eq!(
+        table.names(),
+        [
+            "id",
+            "type",
+            "x",
+            "y",
+            "z",
+            "radius",
+            "parent",
+            "is_tip",
+            "path_distance"
+        ]
+    );
+    assert!(table.column("strahler_order").is_none());
+    let csv = table.to_csv();
+    let header = csv.lines().next().unwrap();
+    assert_eq!(header, "id,type,x,y,z,radius,parent,is_tip,path_distance");
+    assert!(csv.lines().skip(1).all(|l| l.split(',').count() == 9));
+}
+
+#[test]
+fn csv_is_written_to_disk() {
+    let dir = std::env::temp_dir().join(format!("export-{}", std::process::id()));
+    let path = dir.join("nodes.csv");
+    let table = basic().node_table(ExportColumns::all()).unwrap();
+    table.write_csv(&path, ConflictPolicy::Overwrite).unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), table.to_csv());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn large_trees_export_in_linear_time() {
+    // A binary tree of 200k nodes behind a long unbranched trunk, deep
+    // enough that anything recursive would overflow the stack
+    let n = 200_000;
+    let trunk = 50_000;
+    let ids: Vec<i64> = (1..=n as i64).collect();
+    let types = vec![3u8; n];
+    let parents: Vec<i64> = ids
+        .iter()
+        .map(|&id| match id {
+            1 => -1,
+            id if id <= trunk => id - 1,
+            id => trunk + (id - trunk - 1) / 2,
+        })
+        .collect();
+    let xyz: Vec<[f64; 3]> = ids.iter().map(|&id| [id as f64, 0.0, 0.0]).collect();
+    let radii = vec![1.0; n];
+    let skeleton = Skeleton::from_arrays(
+        &ids,
+        &types,
+        &xyz,
+        &radii,
+        &parents,
+        &ReaderOptions::default(),
+    )
+    .unwrap();
+
+    let start = Instant::now();
+    let table = skeleton.node_table(ExportColumns::all()).unwrap();
+    assert!(start.elapsed().as_secs() < 30);
+    assert_eq!(table.rows(), n);
+    let tips = bools(&table, "is_tip").iter().filter(|&&t| t).count();
+    assert!(tips > (n - trunk as usize) / 3);
+    assert!(ints(&table, "strahler_order").iter().all(|&s| s >= 1));
+}
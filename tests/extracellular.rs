@@ -0,0 +1,224 @@
+use compartment_rs::analysis::transfer_impedance_matrix;
+use compartment_rs::extracellular::{
+    ExtracellularStimulus, activating_currents, extracellular_potentials,
+};
+use compartment_rs::solver::Simulation;
+use compartment_rs::units::{MicroFaradPerCm2, OhmCm, SiemensPerCm2};
+use compartment_rs::{Channel, Compartments, ReaderOptions, swc_reader_from_bytes};
+
+/// Soma point at the origin and a straight 2 µm-diameter axon along x,
+/// 10 µm per compartment
+fn axon(compartments: usize) -> Compartments {
+    let mut swc = String::from("1 1 0 0 0 5 -1\n");
+    for k in 1..=compartments {
+        swc.push_str(&format!("{} 2 {} 0 0 1 {}\n", k + 1, 10 * k, k));
+    }
+    let skeleton = swc_reader_from_bytes(swc.as_bytes(), &ReaderOptions::default()).unwrap();
+    let mut compartments = Compartments::from_skeleton(skeleton);
+    for c in compartments.components.iter_mut().skip(1) {
+        c.set_channel(Channel::passive(
+            OhmCm::new(100.0).unwrap(),
+            MicroFaradPerCm2::new(1.0).unwrap(),
+            SiemensPerCm2::new(1e-4).unwrap(),
+        ));
+    }
+    compartments
+}
+
+fn electrode(x: f64, current: f64, sigma: f64) -> ExtracellularStimulus {
+    ExtracellularStimulus {
+        position: [x, 50.0, 0.0],
+        current_waveform: vec![current],
+        sigma,
+    }
+}
+
+/// Steady-state membrane potential change under the field, in mV
+fn response(compartments: &Compartments, electrodes: &[ExtracellularStimulus]) -> Vec<f64> {
+    let v_e = &extracellular_potentials(compartments, electrodes).unwrap()[0];
+    let drive = activating_currents(compartments, v_e).unwrap();
+    transfer_impedance_matrix(compartments, 0.0)
+        .iter()
+        .map(|row| row.iter().zip(&drive).map(|(z, i)| z.re * i).sum())
+        .collect()
+}
+
+#[test]
+fn field_falls_off_with_distance() {
+    let compartments = axon(200);
+    let v_e = &extracellular_potentials(&compartments, &[electrode(1000.0, 1.0, 0.3)]).unwrap()[0];
+    // Compartment 101 spans 990 to 1000 µm
+    let r = (5.0f64.powi(2) + 50.0f64.powi(2)).sqrt();
+    let expected = 1.0 / (4.0 * std::f64::consts::PI * 0.3 * r);
+    assert!((v_e[101] - expected).abs() < 1e-12);
+    assert!(v_e[101] > v_e[150] && v_e[150] > v_e[200]);
+    assert_eq!(v_e[0], 0.0);
+}
+
+#[test]
+fn cathode_depolarizes_underneath_and_hyperpolarizes_the_flanks() {
+    let compartments = axon(200);
+    let v = response(&compartments, &[electrode(1005.0, -10.0, 0.3)]);
+    // Compartment 101 is centred at 995 µm and 102 at 1005 µm
+    let under = v[101].max(v[102]);
+    assert!(under > 0.0);
+    let (lo, hi) = (v[60..90].iter(), v[115..145].iter());
+    let flank = lo.chain(hi).copied().fold(f64::INFINITY, f64::min);
+    assert!(flank < 0.0);
+    // An anode flips the pattern
+    let flipped = response(&compartments, &[electrode(1005.0, 10.0, 0.3)]);
+    for (a, b) in v.iter().zip(&flipped) {
+        assert!((a + b).abs() < 1e-12 * (1.0 + a.abs()));
+    }
+}
+
+#[test]
+fn doubling_sigma_halves_the_effect() {
+    let compartments = axon(200);
+    let v = response(&compartments, &[electrode(1005.0, -10.0, 0.3)]);
+    let halved = response(&compartments, &[electrode(1005.0, -10.0, 0.6)]);
+    for (a, b) in v.iter().zip(&halved) {
+        assert!((a - 2.0 * b).abs() < 1e-9 * (1.0 + a.abs()));
+    }
+}
+
+#[test]
+fn electrodes_superpose() {
+    let compartments = axon(100);
+    let (a, b) = (electrode(300.0, -5.0, 0.3), electrode(700.0, 3.0, 0.3));
+    let both = extracellular_potentials(&compartments, &[a.clone(), b.clone()]).unwrap();
+    let one = extracellular_potentials(&compartments, &[a]).unwrap();
+    let other = extracellular_potentials(&compartments, &[b]).unwrap();
+    for ((s, x), y) in both[0].iter().zip(&one[0]).zip(&other[0]) {
+        assert!((s - (x + y)).abs() < 1e-12);
+    }
+}
+
+#[test]
+fn zero_current_drives_nothing() {
+    let compartments = axon(100);
+    let v_e = &extracellular_potentials(&compartments, &[electrode(500.0, 0.0, 0.3)]).unwrap()[0];
+    let drive = activating_currents(&compartments, v_e).unwrap();
+    assert!(drive.iter().all(|&i| i.to_bits() == 0));
+}
+
+#[test]
+fn mismatched_waveforms_are_rejected() {
+    let compartments = axon(10);
+    let mut long = electrode(50.0, 1.0, 0.3);
+    long.current_waveform.push(1.0);
+    let err = extracellular_potentials(&compartments, &[electrode(50.0, 1.0, 0.3), long]);
+    assert!(err.unwrap_err().contains("differ in length"));
+    let bad = electrode(50.0, 1.0, 0.0);
+    assert!(extracellular_potentials(&compartments, &[bad]).is_err());
+    assert!(activating_currents(&compartments, &[0.0; 3]).is_err());
+}
+
+/// Voltages after 100 ms in steps of 0.1 ms with `electrodes` on, less
+/// those of the same run without them
+fn simulated_response(
+    compartments: &Compartments,
+    electrodes: &[ExtracellularStimulus],
+) -> Vec<f64> {
+    let steps = 1000;
+    let on: Vec<ExtracellularStimulus> = electrodes
+        .iter()
+        .map(|e| ExtracellularStimulus {
+            current_waveform: vec![e.current_waveform[0]; steps],
+            ..e.clone()
+        })
+        .collect();
+    let plain = Simulation::new(compartments, 0.1)
+        .unwrap()
+        .run(steps, &[])
+        .unwrap();
+    let stimulated = Simulation::new(compartments, 0.1)
+        .unwrap()
+        .with_extracellular(compartments, &on)
+        .unwrap()
+        .run(steps, &[])
+        .unwrap();
+    stimulated
+        .voltages
+        .iter()
+        .zip(&plain.voltages)
+        .map(|(s, p)| s[steps] - p[steps])
+        .collect()
+}
+
+#[test]
+fn a_simulated_axon_under_a_cathode_shows_the_activating_function() {
+    let compartments = axon(80);
+    // Compartment 41 is centred at 405 µm
+    let cathode = electrode(405.0, -10.0, 0.3);
+    let v = simulated_response(&compartments, std::slice::from_ref(&cathode));
+    assert!(v[41] > 0.0, "{}", v[41]);
+    let (lo, hi) = (v[10..35].iter(), v[47..72].iter());
+    assert!(lo.copied().fold(f64::INFINITY, f64::min) < 0.0);
+    assert!(hi.copied().fold(f64::INFINITY, f64::min) < 0.0);
+    // Settled, it is the steady-state response to the activating currents
+    let expected = response(&compartments, &[cathode]);
+    for (idx, (a, b)) in v.iter().zip(&expected).enumerate().skip(2) {
+        assert!(
+            (a - b).abs() < 1e-3 * (1e-3 + v[41].abs()),
+            "{}: {} against {}",
+            idx,
+            a,
+            b
+        );
+    }
+
+    // Doubling sigma halves the effect, and electrodes superpose
+    let halved = simulated_response(&compartments, &[electrode(405.0, -10.0, 0.6)]);
+    for (a, b) in v.iter().zip(&halved) {
+        assert!((a - 2.0 * b).abs() < 1e-9 * (1.0 + a.abs()));
+    }
+    let pair = [electrode(405.0, -10.0, 0.6), electrode(405.0, -10.0, 0.6)];
+    for (a, b) in v.iter().zip(simulated_response(&compartments, &pair)) {
+        assert!((a - b).abs() < 1e-9 * (1.0 + a.abs()));
+    }
+}
+
+#[test]
+fn a_silent_electrode_leaves_the_simulation_bit_identical() {
+    let compartments = axon(40);
+    let stimuli = [(20, vec![0.05; 200])];
+    let plain = Simulation::new(&compartments, 0.1)
+        .unwrap()
+        .run(200, &stimuli)
+        .unwrap();
+    let silent = ExtracellularStimulus {
+        current_waveform: vec![0.0; 200],
+        ..electrode(200.0, 0.0, 0.3)
+    };
+    let result = Simulation::new(&compartments, 0.1)
+        .unwrap()
+        .with_extracellular(&compartments, &[silent])
+        .unwrap()
+        .run(200, &stimuli)
+        .unwrap();
+    let bits = |r: &compartment_rs::solver::SimulationResult| -> Vec<Vec<u64>> {
+        r.voltages
+            .iter()
+            .map(|t| t.iter().map(|v| v.to_bits()).collect())
+            .collect()
+    };
+    assert_eq!(bits(&result), bits(&plain));
+
+    // Past the end of its waveform an electrode is off
+    let short = ExtracellularStimulus {
+        current_waveform: vec![-10.0; 100],
+        ..electrode(200.0, 0.0, 0.3)
+    };
+    let mut simulation = Simulation::new(&compartments, 0.1)
+        .unwrap()
+        .with_extracellular(&compartments, &[short])
+        .unwrap();
+    assert!(simulation.run(200, &stimuli).is_ok());
+    assert!(
+        Simulation::new(&compartments, 0.1)
+            .unwrap()
+            .with_extracellular(&axon(10), &[electrode(0.0, 1.0, 0.3)])
+            .is_err()
+    );
+}
@@ -0,0 +1,60 @@
+use std::collections::HashMap;
+
+use compartment_rs::{NodeFilter, ReaderOptions, swc_reader};
+
+#[test]
+fn extra_columns_and_trailing_comments_parse() {
+    let skeleton = swc_reader("data/extras.swc", &ReaderOptions::default()).unwrap();
+    assert_eq!(skeleton.nodes.len(), 5);
+    assert_eq!(skeleton.extra_columns, vec!["confidence", "segment"]);
+
+    // Breadth first renumbering: 1 2 4 3 5 in file IDs
+    let expected = HashMap::from([
+        (0, vec![0.9, 1.0]),
+        (1, vec![0.2, 1.0]),
+        (2, vec![0.51, 2.0]),
+        (3, vec![0.7, 1.0]),
+        (4, vec![0.5, 2.0]),
+    ]);
+    assert_eq!(*skeleton.extras, expected);
+    assert_eq!(skeleton.nodes[3].x_pos, 20.0);
+}
+
+#[test]
+fn extras_round_trip_through_writer() {
+    let out = std::env::temp_dir().join("compartment_rs_extras_round_trip.swc");
+    let options = ReaderOptions {
+        write_path: Some(out.clone()),
+        write_extras: true,
+        ..Default::default()
+    };
+    let original = swc_reader("data/extras.swc", &options).unwrap();
+    let reread = swc_reader(&out, &ReaderOptions::default()).unwrap();
+    assert_eq!(reread.extras, original.extras);
+    assert_eq!(reread.extra_columns, original.extra_columns);
+
+    // Without the flag the writer sticks to the standard columns
+    let options = ReaderOptions {
+        write_path: Some(out.clone()),
+        ..Default::default()
+    };
+    swc_reader("data/extras.swc", &options).unwrap();
+    let reread = swc_reader(&out, &ReaderOptions::default()).unwrap();
+    assert!(reread.extras.is_empty());
+}
+
+#[test]
+fn filter_on_extra_column() {
+    let skeleton = swc_reader("data/extras.swc", &ReaderOptions::default()).unwrap();
+
+    let by_index = NodeFilter::new().extra_gt(0, 0.5);
+    let ids: Vec<u64> = by_index.select(&skeleton).map(|n| n.node_id).collect();
+    assert_eq!(ids, vec![0, 2, 3]);
+
+    let by_name = NodeFilter::new().extra_gt("confidence", 0.5);
+    let named: Vec<u64> = by_name.select(&skeleton).map(|n| n.node_id).collect();
+    assert_eq!(named, ids);
+
+    // Bare nodes carry no extras to compare against
+    assert_eq!(by_index.apply(&skeleton.nodes).count(), 0);
+}
@@ -0,0 +1,94 @@
+use compartment_rs::features::{self, FeatureConfig};
+use compartment_rs::{ReaderOptions, Skeleton, swc_reader};
+
+fn basic() -> Skeleton {
+    swc_reader("data/basic.swc", &ReaderOptions::default()).unwrap()
+}
+
+fn config() -> FeatureConfig {
+    FeatureConfig {
+        branch_length_edges: vec![20.0, 42.0],
+        branch_order_edges: vec![1.0, 2.0, 3.0],
+        tortuosity_edges: vec![1.0 + 1e-9],
+        taper_edges: vec![0.0],
+    }
+}
+
+#[test]
+fn hand_computed_features() {
+    let skeleton = basic();
+    let branches = features::branches(&skeleton).unwrap();
+    // Three primary branches, then two basal and two apical daughters
+    assert_eq!(branches.len(), 7);
+    assert_eq!(branches[0].path, vec![0, 1, 4]);
+    assert_eq!(branches[0].length, 15.0);
+    // Every branch of the fixture is straight
+    assert_eq!(branches[0].tortuosity, 1.0);
+    assert!(branches.iter().all(|b| (b.tortuosity - 1.0).abs() < 1e-12));
+
+    let f = features::morphology_features(&skeleton, &config()).unwrap();
+    assert_eq!(f.names.len(), f.values.len());
+    assert_eq!(
+        &f.names[..3],
+        &[
+            "branch_length<20",
+            "branch_length[20,42)",
+            "branch_length>=42"
+        ]
+    );
+    // Lengths: 15, 10√2 twice, 10√5 twice, 40 and 45
+    assert_eq!(&f.values[..3], &[3.0, 3.0, 1.0]);
+    // Orders: the three primaries are 1, the four daughters 2
+    assert_eq!(&f.values[3..7], &[0.0, 3.0, 4.0, 0.0]);
+    assert_eq!(&f.values[7..9], &[7.0, 0.0]);
+}
+
+#[test]
+fn features_are_deterministic_and_batch_matches() {
+    let skeleton = basic();
+    let first = features::morphology_features(&skeleton, &FeatureConfig::default()).unwrap();
+    let second = features::morphology_features(&skeleton, &FeatureConfig::default()).unwrap();
+    assert_eq!(first, second);
+
+    let scaled = swc_reader(
+        "data/scaled.swc",
+        &ReaderOptions {
+            apply_scale: false,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let matrix =
+        features::feature_matrix(&[skeleton, scaled.clone()], &FeatureConfig::default()).unwrap();
+    assert_eq!(matrix[0], first.values);
+    assert_eq!(
+        matrix[1],
+        features::morphology_features(&scaled, &FeatureConfig::default())
+            .unwrap()
+            .values
+    );
+}
+
+#[test]
+fn geodesic_matrix_is_a_distance_matrix() {
+    let skeleton = basic();
+    let m = features::geodesic_matrix(&skeleton, None).unwrap();
+    assert_eq!(m.len(), 7);
+    for (i, row) in m.iter().enumerate() {
+        assert_eq!(row[i], 0.0);
+        for (j, d) in row.iter().enumerate() {
+            assert_eq!(*d, m[j][i]);
+        }
+    }
+    // Midpoints of the first basal branch (7.5 from the soma) and the axon
+    // (22.5 from the soma) meet at the soma
+    assert_eq!(m[0][2], 30.0);
+    // The first basal daughter hangs off the first basal branch: from 7.5 to
+    // 15 + 5√5
+    assert!((m[0][3] - (7.5 + 5.0 * 5f64.sqrt())).abs() < 1e-12);
+
+    assert_eq!(
+        features::geodesic_matrix(&skeleton, Some(3)).unwrap().len(),
+        3
+    );
+}
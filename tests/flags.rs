@@ -0,0 +1,51 @@
+use compartment_rs::{
+    Compartments, NodeFilter, NodeFlags, ReaderOptions, StructureIdentifier, swc_reader,
+};
+
+#[test]
+fn zero_radius_fix_is_flagged() {
+    let skeleton = swc_reader("data/basic.swc", &ReaderOptions::default()).unwrap();
+
+    // Only the axon tip at x = -45 has a zero radius in the fixture
+    let flagged: Vec<_> = skeleton
+        .nodes
+        .iter()
+        .filter(|n| !n.flags.is_empty())
+        .collect();
+    assert_eq!(flagged.len(), 1);
+    assert_eq!(flagged[0].x_pos, -45.0);
+    assert_eq!(flagged[0].flags, NodeFlags::ZERO_RADIUS_FIXED);
+}
+
+#[test]
+fn filter_excludes_repaired_nodes() {
+    let skeleton = swc_reader("data/basic.swc", &ReaderOptions::default()).unwrap();
+
+    let clean = NodeFilter::new().not_flag(NodeFlags::ZERO_RADIUS_FIXED);
+    assert_eq!(clean.apply(&skeleton.nodes).count(), 14);
+    assert!(clean.apply(&skeleton.nodes).all(|n| n.x_pos != -45.0));
+
+    let repaired_axon = NodeFilter::new()
+        .structure(StructureIdentifier::Axon)
+        .has_flag(NodeFlags::ZERO_RADIUS_FIXED);
+    assert_eq!(repaired_axon.apply(&skeleton.nodes).count(), 1);
+
+    let repaired_dendrite = NodeFilter::new()
+        .structure(StructureIdentifier::BasalDendrite)
+        .has_flag(NodeFlags::ZERO_RADIUS_FIXED);
+    assert_eq!(repaired_dendrite.apply(&skeleton.nodes).count(), 0);
+}
+
+#[test]
+fn compartments_carry_node_flags() {
+    let skeleton = swc_reader("data/basic.swc", &ReaderOptions::default()).unwrap();
+    let compartments = Compartments::from_skeleton(skeleton);
+
+    let flagged: Vec<_> = compartments
+        .components
+        .iter()
+        .filter(|c| c.flags.contains(NodeFlags::ZERO_RADIUS_FIXED))
+        .collect();
+    assert_eq!(flagged.len(), 1);
+    assert_eq!(flagged[0].diam, 2.0);
+}
@@ -0,0 +1,91 @@
+use compartment_rs::{ReaderOptions, Skeleton, swc_reader};
+
+#[test]
+fn decimal_comma_rejected_by_default() {
+    let err = swc_reader("data/decimal_comma.swc", &ReaderOptions::default())
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("Decimal comma"), "{}", err);
+    assert!(err.contains("line 2"), "{}", err);
+}
+
+#[test]
+fn decimal_comma_accepted_when_enabled() {
+    let options = ReaderOptions {
+        decimal_comma: true,
+        ..Default::default()
+    };
+    let skeleton = swc_reader("data/decimal_comma.swc", &options).unwrap();
+    let nodes = &skeleton.nodes;
+    assert_eq!(nodes[0].radius, 5.5);
+    assert_eq!(nodes[1].x_pos, 10.25);
+    assert_eq!((nodes[2].x_pos, nodes[2].y_pos), (20.5, 1.75));
+    assert_eq!(nodes[2].radius, 1.25);
+}
+
+#[test]
+fn written_output_is_golden() {
+    let xyz = [
+        [0.0, 0.0, 0.0],
+        [0.1, 1e-7, -0.0],
+        [123456789.125, -98765.4321, 1e17],
+    ];
+    let skeleton = Skeleton::from_arrays(
+        &[1, 2, 3],
+        &[1, 3, 3],
+        &xyz,
+        &[5.0, 0.3, 1e-7],
+        &[-1, 1, 2],
+        &ReaderOptions {
+            write_path: Some(std::env::temp_dir().join("compartment_rs_golden.swc")),
+            ..Default::default()
+        },
+    );
+    let skeleton = skeleton.unwrap();
+
+    let written = std::fs::read(std::env::temp_dir().join("compartment_rs_golden.swc")).unwrap();
+    let expected = b"# Processed SWC file\n\
+1 1 0.0 0.0 0.0 5.0 -1\n\
+2 3 0.1 1e-7 0.0 0.3 1\n\
+3 3 123456789.125 -98765.4321 1e17 1e-7 2\n";
+    assert_eq!(
+        String::from_utf8_lossy(&written),
+        String::from_utf8_lossy(expected)
+    );
+
+    // And the written file reads back to the exact same values
+    let reread = swc_reader(
+        std::env::temp_dir().join("compartment_rs_golden.swc"),
+        &ReaderOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(reread.nodes[1].y_pos, 1e-7);
+    assert_eq!(reread.nodes[2].x_pos, 123456789.125);
+    assert_eq!(reread.nodes, skeleton.nodes);
+    assert_eq!(reread.parent_child_map, skeleton.parent_child_map);
+}
+
+#[test]
+fn written_files_read_back_as_the_same_tree() {
+    let path = std::env::temp_dir().join(format!("compartment_rs_tree_{}.swc", std::process::id()));
+    let options = ReaderOptions {
+        write_path: Some(path.clone()),
+        ..Default::default()
+    };
+    // The soma has three children, each written with the root as parent
+    let skeleton = swc_reader("data/basic.swc", &options).unwrap();
+    assert_eq!(skeleton.parent_child_map[&0], [0, 1, 2, 3]);
+    let reread = swc_reader(&path, &ReaderOptions::default()).unwrap();
+    for (a, b) in reread.nodes.iter().zip(skeleton.nodes.iter()) {
+        assert_eq!(a.node_id, b.node_id);
+        assert_eq!(a.parent_id, b.parent_id, "node {}", a.node_id);
+        assert_eq!(
+            (a.x_pos, a.y_pos, a.z_pos, a.radius),
+            (b.x_pos, b.y_pos, b.z_pos, b.radius)
+        );
+    }
+    assert_eq!(reread.nodes.len(), skeleton.nodes.len());
+    assert_eq!(reread.parent_child_map, skeleton.parent_child_map);
+    assert_eq!(reread.child_parent_map, skeleton.child_parent_map);
+    std::fs::remove_file(path).unwrap();
+}
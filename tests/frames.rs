@@ -0,0 +1,92 @@
+use compartment_rs::{Compartments, ReaderOptions, Skeleton, swc_reader};
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn sub(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+/// A soma with one smoothly winding helical branch of 120 nodes
+fn helix() -> Compartments {
+    let n = 121;
+    let ids: Vec<i64> = (1..=n).collect();
+    let parents: Vec<i64> = (0..n).map(|i| if i == 0 { -1 } else { i }).collect();
+    let xyz: Vec<[f64; 3]> = (0..n)
+        .map(|i| {
+            let t = i as f64 * 0.1;
+            [10.0 * t.cos(), 10.0 * t.sin(), 2.0 * t]
+        })
+        .collect();
+    let radii: Vec<f64> = (0..n).map(|i| 2.0 - i as f64 * 0.01).collect();
+    let mut types = vec![3; n as usize];
+    types[0] = 1;
+    let skeleton = Skeleton::from_arrays(
+        &ids,
+        &types,
+        &xyz,
+        &radii,
+        &parents,
+        &ReaderOptions::default(),
+    )
+    .unwrap();
+    Compartments::from_skeleton(skeleton)
+}
+
+#[test]
+fn surface_points_sit_on_the_membrane() {
+    let compartments = helix();
+    for idx in 2..compartments.components.len() {
+        let c = &compartments.components[idx];
+        for (fraction, azimuth) in [(0.0, 0.3), (0.5, 2.0), (1.0, -1.2)] {
+            let p = compartments.surface_point(idx, fraction, azimuth).unwrap();
+            let frame = compartments.local_frame(idx, fraction).unwrap();
+            let offset = sub(p, frame.origin);
+            // Perpendicular to the centerline, one radius out
+            assert!(dot(offset, frame.tangent).abs() < 1e-9);
+            assert!((dot(offset, offset).sqrt() - c.diam / 2.0).abs() < 1e-9);
+        }
+    }
+    assert!(compartments.local_frame(0, 0.5).is_err());
+    assert!(compartments.local_frame(3, 1.5).is_err());
+}
+
+#[test]
+fn frames_are_orthonormal_and_continuous() {
+    let compartments = helix();
+    let frames: Vec<_> = (2..compartments.components.len())
+        .map(|idx| compartments.local_frame(idx, 0.5).unwrap())
+        .collect();
+    for f in &frames {
+        for v in [f.tangent, f.normal, f.binormal] {
+            assert!((dot(v, v) - 1.0).abs() < 1e-12);
+        }
+        assert!(dot(f.tangent, f.normal).abs() < 1e-12);
+        assert!(dot(f.tangent, f.binormal).abs() < 1e-12);
+    }
+    for pair in frames.windows(2) {
+        assert!(dot(pair[0].tangent, pair[1].tangent) > 0.99);
+        assert!(dot(pair[0].normal, pair[1].normal) > 0.99);
+    }
+}
+
+#[test]
+fn opposite_azimuths_are_antipodal() {
+    let compartments = Compartments::from_skeleton(
+        swc_reader("data/basic.swc", &ReaderOptions::default()).unwrap(),
+    );
+    for idx in 2..compartments.components.len() {
+        let c = &compartments.components[idx];
+        let frame = compartments.local_frame(idx, 0.25).unwrap();
+        let a = compartments.surface_point(idx, 0.25, 0.0).unwrap();
+        let b = compartments
+            .surface_point(idx, 0.25, std::f64::consts::PI)
+            .unwrap();
+        let gap = sub(a, b);
+        assert!((dot(gap, gap).sqrt() - c.diam).abs() < 1e-9);
+        for k in 0..3 {
+            assert!(((a[k] + b[k]) / 2.0 - frame.origin[k]).abs() < 1e-9);
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use compartment_rs::{Morphometry, ReaderOptions, Skeleton, swc_reader};
+
+type Columns = (Vec<i64>, Vec<u8>, Vec<[f64; 3]>, Vec<f64>, Vec<i64>);
+
+/// Column arrays for data/basic.swc, as a numpy user would hand them over
+fn basic_arrays() -> Columns {
+    let text = std::fs::read_to_string("data/basic.swc").unwrap();
+    let mut columns = (Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    for line in text.lines().filter(|l| !l.starts_with('#')) {
+        let v: Vec<&str> = line.split_whitespace().collect();
+        columns.0.push(v[0].parse().unwrap());
+        columns.1.push(v[1].parse().unwrap());
+        columns.2.push([
+            v[2].parse().unwrap(),
+            v[3].parse().unwrap(),
+            v[4].parse().unwrap(),
+        ]);
+        columns.3.push(v[5].parse().unwrap());
+        columns.4.push(v[6].parse().unwrap());
+    }
+    columns
+}
+
+#[test]
+fn arrays_match_file() {
+    let options = ReaderOptions::default();
+    let from_file = swc_reader("data/basic.swc", &options).unwrap();
+    let (ids, types, xyz, radii, parents) = basic_arrays();
+    let from_arrays =
+        Skeleton::from_arrays(&ids, &types, &xyz, &radii, &parents, &options).unwrap();
+
+    assert_eq!(from_file.nodes.len(), from_arrays.nodes.len());
+    for (a, b) in from_file.nodes.iter().zip(from_arrays.nodes.iter()) {
+        assert_eq!(a.node_id, b.node_id);
+        assert_eq!(a.parent_id, b.parent_id);
+        assert_eq!(a.structured_identifier, b.structured_identifier);
+        assert_eq!(
+            (a.x_pos, a.y_pos, a.z_pos, a.radius),
+            (b.x_pos, b.y_pos, b.z_pos, b.radius)
+        );
+    }
+    assert_eq!(from_file.parent_child_map, from_arrays.parent_child_map);
+    assert_eq!(from_file.child_parent_map, from_arrays.child_parent_map);
+    assert_eq!(
+        Morphometry::new(&from_file.nodes).total_length(),
+        Morphometry::new(&from_arrays.nodes).total_length()
+    );
+}
+
+#[test]
+fn unknown_parent_names_the_index() {
+    let (ids, types, xyz, radii, mut parents) = basic_arrays();
+    parents[6] = 99;
+    let err = Skeleton::from_arrays(
+        &ids,
+        &types,
+        &xyz,
+        &radii,
+        &parents,
+        &ReaderOptions::default(),
+    )
+    .unwrap_err()
+    .to_string();
+    assert!(err.contains("index 6"), "{}", err);
+    assert!(err.contains("99"), "{}", err);
+}
+
+#[test]
+fn length_mismatch_is_reported() {
+    let (ids, types, xyz, mut radii, parents) = basic_arrays();
+    radii.pop();
+    let err = Skeleton::from_arrays(
+        &ids,
+        &types,
+        &xyz,
+        &radii,
+        &parents,
+        &ReaderOptions::default(),
+    )
+    .unwrap_err()
+    .to_string();
+    assert!(err.contains("radii"), "{}", err);
+}
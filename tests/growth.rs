@@ -0,0 +1,309 @@
+use compartment_rs::growth::{DistalPolicy, MorphologySchedule, Reactivation, Selector};
+use compartment_rs::solver::{RESTING_POTENTIAL, Simulation, SolverOptions};
+use compartment_rs::soma::SomaStyle;
+use compartment_rs::units::{MicroFaradPerCm2, OhmCm, SiemensPerCm2};
+use compartment_rs::{Channel, Compartments, ReaderOptions, swc_reader_from_bytes};
+
+const DT: f64 = 0.025;
+const STEPS: usize = 4000;
+/// Step at whose end the upper twig is switched off, 15 ms
+const SWITCH: usize = 600;
+
+/// Passive soma and axon with a dendrite along +x that forks at 100 µm into
+/// two thin twigs
+fn cell() -> Compartments {
+    let mut swc = "1 1 0 0 0 10 -1\n".to_owned();
+    let mut id = 1;
+    let mut chain = |swc: &mut String, kind: u8, from: u64, step: [f64; 3], n: usize, r: f64| {
+        let mut parent = from;
+        let mut at = [0.0; 3];
+        if from != 1 {
+            at = [100.0, 0.0, 0.0];
+        }
+        for _ in 0..n {
+            id += 1;
+            at = [at[0] + step[0], at[1] + step[1], at[2] + step[2]];
+            swc.push_str(&format!(
+                "{} {} {} {} {} {} {}\n",
+                id, kind, at[0], at[1], at[2], r, parent
+            ));
+            parent = id;
+        }
+        parent
+    };
+    let fork = chain(&mut swc, 3, 1, [10.0, 0.0, 0.0], 10, 1.0);
+    chain(&mut swc, 3, fork, [8.0, 6.0, 0.0], 5, 0.25);
+    chain(&mut swc, 3, fork, [8.0, -6.0, 0.0], 5, 0.25);
+    chain(&mut swc, 2, 1, [-10.0, 0.0, 0.0], 10, 0.5);
+
+    let skeleton = swc_reader_from_bytes(swc.as_bytes(), &ReaderOptions::default()).unwrap();
+    let mut compartments = Compartments::from_skeleton(skeleton);
+    compartments.model_soma(SomaStyle::SinglePoint).unwrap();
+    for c in compartments.components.iter_mut().skip(1) {
+        c.set_channel(Channel::passive(
+            OhmCm::new(150.0).unwrap(),
+            MicroFaradPerCm2::new(1.0).unwrap(),
+            SiemensPerCm2::new(1e-4).unwrap(),
+        ));
+    }
+    compartments
+}
+
+/// Compartment whose distal end is at `(x, y)`
+fn at(compartments: &Compartments, x: f64, y: f64) -> usize {
+    compartments
+        .components
+        .iter()
+        .position(|c| (c.distal[0] - x).abs() < 1e-9 && (c.distal[1] - y).abs() < 1e-9)
+        .unwrap()
+}
+
+/// First compartment of the upper twig
+fn twig(compartments: &Compartments) -> usize {
+    at(compartments, 108.0, 6.0)
+}
+
+/// Steady current into the tip of the upper twig
+fn tip_current(compartments: &Compartments) -> Vec<(usize, Vec<f64>)> {
+    vec![(at(compartments, 140.0, 30.0), vec![0.05; STEPS])]
+}
+
+fn upper_twig_off() -> MorphologySchedule {
+    MorphologySchedule::new().deactivate(SWITCH as f64 * DT, Selector::Section("dend[1]".into()))
+}
+
+#[test]
+fn a_switched_off_subtree_no_longer_reaches_the_soma() {
+    let compartments = cell();
+    let tip = at(&compartments, 140.0, 30.0);
+    assert_eq!(compartments.section_of(tip).unwrap().0.name, "dend[1]");
+    let stimuli = tip_current(&compartments);
+
+    let unscheduled = Simulation::new(&compartments, DT)
+        .unwrap()
+        .run(STEPS, &stimuli)
+        .unwrap();
+    let mut sim = Simulation::new(&compartments, DT)
+        .unwrap()
+        .with_schedule(&compartments, upper_twig_off())
+        .unwrap();
+    let scheduled = sim.run(STEPS, &stimuli).unwrap();
+    let unstimulated = Simulation::new(&compartments, DT)
+        .unwrap()
+        .run(STEPS, &[])
+        .unwrap();
+
+    // Identical up to the switch
+    for (a, b) in unscheduled.voltages.iter().zip(&scheduled.voltages) {
+        let bits = |t: &[f64]| t[..=SWITCH].iter().map(|v| v.to_bits()).collect::<Vec<_>>();
+        assert_eq!(bits(a), bits(b));
+    }
+    let soma = |r: &compartment_rs::solver::SimulationResult| r.voltages[1].clone();
+    let (before, after) = (soma(&unscheduled), soma(&scheduled));
+    let rest = soma(&unstimulated);
+    assert!(before[SWITCH] - rest[SWITCH] > 0.1, "{}", before[SWITCH]);
+
+    // Then the soma falls back to where it is without the stimulus, and the
+    // twig keeps the voltage it had
+    assert!(before[STEPS] - rest[STEPS] > 0.1);
+    assert!(
+        (after[STEPS] - rest[STEPS]).abs() < 1e-3 * (before[STEPS] - rest[STEPS]),
+        "{} against {}",
+        after[STEPS],
+        rest[STEPS]
+    );
+    assert!(
+        scheduled.voltages[tip][SWITCH..]
+            .iter()
+            .all(|&v| v == scheduled.voltages[tip][SWITCH])
+    );
+
+    let section = compartments.section("dend[1]").unwrap();
+    let history = sim.activation_history();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].time, SWITCH as f64 * DT);
+    assert_eq!(history[0].deactivated, section.compartments);
+    assert!(history[0].activated.is_empty());
+    assert!(section.compartments.iter().all(|&i| !sim.is_active(i)));
+    assert_eq!(sim.active().iter().filter(|&&a| !a).count(), 5);
+}
+
+#[test]
+fn switching_back_on_restores_the_coupling() {
+    let compartments = cell();
+    let first = twig(&compartments);
+    let fork = at(&compartments, 100.0, 0.0);
+    let back = 2 * SWITCH;
+    for rule in [Reactivation::Parent, Reactivation::Rest] {
+        let schedule = upper_twig_off()
+            .activate(back as f64 * DT, Selector::Subtree(first))
+            .with_reactivation(rule);
+        let mut sim = Simulation::new(&compartments, DT)
+            .unwrap()
+            .with_schedule(&compartments, schedule)
+            .unwrap();
+        // Current into the fork as well, so the twig's parent is away from
+        // rest when the twig comes back
+        let (idx, current) = &tip_current(&compartments)[0];
+        for &current in &current[..back] {
+            sim.inject(*idx, current).unwrap();
+            sim.inject(fork, 0.05).unwrap();
+            sim.step().unwrap();
+        }
+
+        // Switched on at the end of the step, every compartment of the twig
+        // at the voltage the rule gives
+        let expected = match rule {
+            Reactivation::Parent => sim.voltages()[fork],
+            Reactivation::Rest => RESTING_POTENTIAL,
+        };
+        assert!(sim.voltages()[fork] - RESTING_POTENTIAL > 0.1);
+        for &i in &compartments.section("dend[1]").unwrap().compartments {
+            assert!(sim.is_active(i));
+            assert_eq!(sim.voltages()[i], expected, "{:?} {}", rule, i);
+        }
+        assert_eq!(sim.activation_history().len(), 2);
+        assert_eq!(sim.activation_history()[1].time, back as f64 * DT);
+
+        // And the tip current reaches the soma again: with it on for long
+        // enough the cell settles where it does without a schedule
+        let mut unscheduled = Simulation::new(&compartments, DT).unwrap();
+        for _ in 0..8 * STEPS {
+            for s in [&mut sim, &mut unscheduled] {
+                s.inject(*idx, 0.05).unwrap();
+                s.inject(fork, 0.05).unwrap();
+                s.step().unwrap();
+            }
+        }
+        for (a, b) in sim.voltages().iter().zip(unscheduled.voltages()) {
+            assert!((a - b).abs() < 1e-6, "{:?}: {} against {}", rule, a, b);
+        }
+    }
+}
+
+#[test]
+fn probes_elsewhere_keep_their_compartments() {
+    let compartments = cell();
+    let lower = at(&compartments, 140.0, -30.0);
+    let axon = at(&compartments, -50.0, 0.0);
+    let n = compartments.components.len();
+    let stimuli = vec![(lower, vec![0.05; STEPS]), (axon, vec![0.02; STEPS])];
+    let schedule = upper_twig_off().activate(
+        2.0 * SWITCH as f64 * DT,
+        Selector::Subtree(twig(&compartments)),
+    );
+
+    let unscheduled = Simulation::new(&compartments, DT)
+        .unwrap()
+        .run(STEPS, &stimuli)
+        .unwrap();
+    let mut sim = Simulation::new(&compartments, DT)
+        .unwrap()
+        .with_schedule(&compartments, schedule)
+        .unwrap();
+    let scheduled = sim.run(STEPS, &stimuli).unwrap();
+    assert_eq!(scheduled.voltages.len(), n);
+    assert_eq!(sim.voltages().len(), n);
+    assert_eq!(compartments.compartment_at("dend[2]", 1.0).unwrap(), lower);
+    for probe in [lower, axon, 1] {
+        assert!(sim.is_active(probe));
+        // Still driven by its own stimulus all along, and only changed by
+        // the missing twig while it is off
+        let (a, b) = (&unscheduled.voltages[probe], &scheduled.voltages[probe]);
+        assert!(b.iter().all(|v| v.is_finite()));
+        assert_eq!(a[..=SWITCH], b[..=SWITCH]);
+        assert!(
+            b[SWITCH + 1..]
+                .iter()
+                .zip(&a[SWITCH + 1..])
+                .any(|(x, y)| x != y)
+        );
+        assert!(b[STEPS] - RESTING_POTENTIAL > 0.1);
+    }
+}
+
+#[test]
+fn schedules_are_checked_on_registration() {
+    let compartments = cell();
+    let n = compartments.components.len();
+    let first = twig(&compartments);
+    let register = |schedule: MorphologySchedule| {
+        Simulation::new(&compartments, DT)
+            .unwrap()
+            .with_schedule(&compartments, schedule)
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    };
+
+    let error = register(
+        MorphologySchedule::new()
+            .deactivate(1.0, Selector::Subtree(first))
+            .deactivate(2.0, Selector::Compartment(n)),
+    )
+    .unwrap_err();
+    assert_eq!(
+        error,
+        format!("Scheduled change 1: No compartment at index {}", n)
+    );
+    let error =
+        register(MorphologySchedule::new().activate(1.0, Selector::Subtree(0))).unwrap_err();
+    assert!(error.contains("No compartment at index 0"), "{}", error);
+    let error =
+        register(MorphologySchedule::new().deactivate(1.0, Selector::Section("dend[9]".into())))
+            .unwrap_err();
+    assert!(error.starts_with("Scheduled change 0: "), "{}", error);
+    assert!(error.contains("dend[9]"), "{}", error);
+    let error = register(MorphologySchedule::new().deactivate(f64::NAN, Selector::Compartment(1)))
+        .unwrap_err();
+    assert!(error.contains("not finite"), "{}", error);
+
+    // Distal compartments go along, or the schedule is refused
+    let cut = MorphologySchedule::new().deactivate(1.0, Selector::Compartment(first));
+    assert!(register(cut.clone()).is_ok());
+    let error = register(cut.with_distal(DistalPolicy::Error)).unwrap_err();
+    assert!(error.contains("distal to it active"), "{}", error);
+    assert!(
+        register(
+            MorphologySchedule::new()
+                .deactivate(1.0, Selector::Subtree(first))
+                .with_distal(DistalPolicy::Error)
+        )
+        .is_ok()
+    );
+
+    // No active compartment under an inactive one, in order of time
+    let tip = at(&compartments, 140.0, 30.0);
+    let error = register(
+        MorphologySchedule::new()
+            .activate(2.0, Selector::Compartment(tip))
+            .deactivate(1.0, Selector::Subtree(first)),
+    )
+    .unwrap_err();
+    assert!(error.starts_with("Scheduled change 0: "), "{}", error);
+    assert!(error.contains("inactive compartment"), "{}", error);
+
+    // Changes in the past, and the parallel solver gives way
+    let mut sim = Simulation::new(&compartments, DT)
+        .unwrap()
+        .with_solver(SolverOptions::parallel_tree(2, 1));
+    sim.step().unwrap();
+    assert!(
+        sim.clone()
+            .with_schedule(
+                &compartments,
+                MorphologySchedule::new().deactivate(0.0, Selector::Compartment(first))
+            )
+            .unwrap_err()
+            .to_string()
+            .contains("before the current time")
+    );
+    let sim = sim
+        .with_schedule(
+            &compartments,
+            MorphologySchedule::new().deactivate(DT, Selector::Subtree(first)),
+        )
+        .unwrap();
+    assert!(sim.partition().is_none());
+    assert!(!sim.is_active(first));
+    assert_eq!(sim.activation_history()[0].time, DT);
+}
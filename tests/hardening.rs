@@ -0,0 +1,144 @@
+use std::fs;
+use std::io::Write;
+use std::panic;
+use std::time::{Duration, Instant};
+
+use compartment_rs::{Code, ReaderOptions, SwcError, swc_reader, swc_reader_from_bytes};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+fn code(path: &str) -> Code {
+    swc_reader(path, &ReaderOptions::default())
+        .unwrap_err()
+        .code()
+}
+
+#[test]
+fn pathological_inputs_are_typed_errors() {
+    assert_eq!(code("data/fuzz/empty.swc"), Code::NoRoot);
+    assert_eq!(code("data/fuzz/comments_only.swc"), Code::NoRoot);
+    assert_eq!(code("data/fuzz/huge_id.swc"), Code::InvalidField);
+    assert_eq!(code("data/fuzz/self_parent.swc"), Code::InvalidParentId);
+    assert_eq!(code("data/fuzz/duplicate_id.swc"), Code::DuplicateNodeId);
+    assert_eq!(code("data/fuzz/non_finite.swc"), Code::InvalidField);
+    assert_eq!(code("data/fuzz/negative_parent.swc"), Code::InvalidParentId);
+    assert_eq!(code("data/fuzz/float_parent.swc"), Code::InvalidField);
+    assert_eq!(code("data/fuzz/zero_id.swc"), Code::InvalidNodeId);
+}
+
+#[test]
+fn zero_based_ids_are_rejected() {
+    // Parent 0 marks the root, so node 0 would turn its children into roots
+    let error = swc_reader_from_bytes(
+        b"0 1 0 0 0 5 -1\n1 3 10 0 0 1 0\n2 3 20 0 0 1 1\n",
+        &ReaderOptions::default(),
+    )
+    .unwrap_err();
+    assert_eq!(error.code(), Code::InvalidNodeId);
+    assert_eq!(error.line(), Some(1));
+
+    // Numbered from 1, as the writer does, the same cell is one chain
+    let skeleton = swc_reader_from_bytes(
+        b"1 1 0 0 0 5 -1\n2 3 10 0 0 1 1\n3 3 20 0 0 1 2\n",
+        &ReaderOptions::default(),
+    )
+    .unwrap();
+    let parents: Vec<u64> = skeleton.nodes.iter().map(|n| n.parent_id).collect();
+    assert_eq!(parents, [0, 0, 1]);
+}
+
+/// One random edit of the kind that turns up in scraped files: flipped or
+/// inserted bytes, lost or repeated lines, truncation, or two files spliced
+fn mutate(input: &[u8], corpus: &[Vec<u8>], rng: &mut StdRng) -> Vec<u8> {
+    const INTERESTING: &[u8] = b"0123456789-+. ,#e\n\t\r\0\xff";
+    let mut out = input.to_vec();
+    let at = |rng: &mut StdRng, len: usize| rng.random_range(0..=len);
+    match rng.random_range(0..7) {
+        0 if !out.is_empty() => {
+            let i = rng.random_range(0..out.len());
+            out[i] ^= 1 << rng.random_range(0..8);
+        }
+        1 => {
+            let i = at(rng, out.len());
+            let byte = INTERESTING[rng.random_range(0..INTERESTING.len())];
+            out.insert(i, byte);
+        }
+        2 => {
+            let i = at(rng, out.len());
+            let j = at(rng, out.len() - i) + i;
+            out.drain(i..j.min(i + 16));
+        }
+        3 => {
+            let lines: Vec<&[u8]> = input.split(|&b| b == b'\n').collect();
+            let line = lines[rng.random_range(0..lines.len())];
+            let i = at(rng, out.len());
+            out.splice(i..i, line.iter().copied().chain([b'\n']));
+        }
+        4 => out.truncate(at(rng, out.len())),
+        5 => {
+            let other = &corpus[rng.random_range(0..corpus.len())];
+            let i = at(rng, out.len());
+            let j = at(rng, other.len());
+            out.truncate(i);
+            out.extend_from_slice(&other[j..]);
+        }
+        _ => {
+            let digits: String = (0..rng.random_range(1..40))
+                .map(|_| char::from(b'0' + rng.random_range(0..10)))
+                .collect();
+            let i = at(rng, out.len());
+            out.splice(i..i, digits.into_bytes());
+        }
+    }
+    out
+}
+
+/// Deterministic stand-in for the cargo-fuzz target in `fuzz/`: mutates the
+/// fixtures a few thousand times and checks every input ends in Ok or a typed
+/// error, quickly
+#[test]
+fn smoke_fuzz_reader() {
+    let mut corpus: Vec<Vec<u8>> = Vec::new();
+    for dir in ["data", "data/fuzz"] {
+        let mut paths: Vec<_> = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .filter(|p| p.is_file())
+            .collect();
+        paths.sort();
+        corpus.extend(paths.iter().map(|p| fs::read(p).unwrap()));
+    }
+    // A gzipped file, so the decompression path gets mangled too
+    let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+    gz.write_all(&corpus[0]).unwrap();
+    corpus.push(gz.finish().unwrap());
+
+    let options = ReaderOptions {
+        emit_warnings: false,
+        ..Default::default()
+    };
+    let mut rng = StdRng::seed_from_u64(430);
+    for iteration in 0..4000 {
+        let mut input = corpus[rng.random_range(0..corpus.len())].clone();
+        for _ in 0..rng.random_range(1..5) {
+            input = mutate(&input, &corpus, &mut rng);
+        }
+        let start = Instant::now();
+        let result = panic::catch_unwind(|| swc_reader_from_bytes(&input, &options));
+        let Ok(result) = result else {
+            panic!(
+                "Reader panicked at iteration {} on {:?}",
+                iteration,
+                String::from_utf8_lossy(&input)
+            );
+        };
+        assert!(start.elapsed() < Duration::from_secs(1));
+        if let Err(err) = result {
+            // Every error maps onto a registered code
+            assert!(!matches!(err, SwcError::Io { .. }));
+            let _ = err.code().info();
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use std::fs;
+
+use compartment_rs::history::{Edit, EditHistory};
+use compartment_rs::{ReaderOptions, RunLog, Skeleton, swc_reader};
+
+fn basic() -> Skeleton {
+    swc_reader("data/basic.swc", &ReaderOptions::default()).unwrap()
+}
+
+type State = (String, HashMap<u64, Vec<u64>>, HashMap<u64, Vec<u64>>);
+
+/// Everything an edit can change, comparable
+fn state(skeleton: &Skeleton) -> State {
+    (
+        format!("{:?}", skeleton.nodes),
+        (*skeleton.parent_child_map).clone(),
+        (*skeleton.child_parent_map).clone(),
+    )
+}
+
+fn edits() -> [Edit; 3] {
+    [
+        Edit::InsertNodeOnEdge {
+            parent_id: 4,
+            child_id: 8,
+            position_fraction: 0.5,
+            radius: 0.8,
+        },
+        Edit::Smooth { iterations: 2 },
+        Edit::Prune { node_id: 2 },
+    ]
+}
+
+#[test]
+fn undo_and_redo_walk_the_edits() {
+    let mut history = EditHistory::new(basic());
+    let original = state(history.skeleton());
+    let mut after = Vec::new();
+    for edit in edits() {
+        history.apply(edit).unwrap();
+        history.skeleton().validate_maps().unwrap();
+        after.push(state(history.skeleton()));
+    }
+
+    assert_eq!(history.undo(), Some(&Edit::Prune { node_id: 2 }));
+    history.undo().unwrap();
+    assert_eq!(state(history.skeleton()), after[0]);
+    assert_eq!(history.history(), [&edits()[0]]);
+
+    history.redo().unwrap();
+    assert_eq!(state(history.skeleton()), after[1]);
+    history.redo().unwrap();
+    assert_eq!(state(history.skeleton()), after[2]);
+    assert_eq!(history.redo(), None);
+
+    for _ in 0..3 {
+        history.undo().unwrap();
+    }
+    assert_eq!(history.undo(), None);
+    assert_eq!(state(history.skeleton()), original);
+
+    // A failed edit changes nothing and keeps what can be redone
+    assert!(history.apply(Edit::Prune { node_id: 0 }).is_err());
+    assert_eq!(history.redoable().len(), 3);
+    assert_eq!(state(history.skeleton()), original);
+}
+
+#[test]
+fn checkpoints_survive_intervening_edits() {
+    let mut history = EditHistory::new(basic());
+    let [first, rest @ ..] = edits();
+    history.apply(first).unwrap();
+    history.checkpoint("inserted");
+    let checkpoint = state(history.skeleton());
+    for edit in rest {
+        history.apply(edit).unwrap();
+    }
+    history.apply(Edit::Reroot { node_id: 11 }).unwrap();
+    history.checkpoint("rerooted");
+    let rerooted = state(history.skeleton());
+
+    history.restore("inserted").unwrap();
+    assert_eq!(state(history.skeleton()), checkpoint);
+    history.restore("rerooted").unwrap();
+    assert_eq!(state(history.skeleton()), rerooted);
+    history.skeleton().validate_maps().unwrap();
+
+    // Editing after going back discards the way forward
+    history.restore("inserted").unwrap();
+    history.apply(Edit::RepairZeroRadii).unwrap();
+    let error = history.restore("rerooted").unwrap_err();
+    assert!(error.contains("rerooted"), "{}", error);
+    history.restore("inserted").unwrap();
+    assert_eq!(state(history.skeleton()), checkpoint);
+    assert!(history.restore("nowhere").is_err());
+}
+
+#[test]
+fn history_describes_the_edits_in_order() {
+    let mut history = EditHistory::new(basic());
+    for edit in edits() {
+        history.apply(edit).unwrap();
+    }
+    assert_eq!(
+        history.descriptions(),
+        [
+            "insert_node_on_edge(parent_id=4, child_id=8, position_fraction=0.5, radius=0.8)",
+            "smooth(iterations=2)",
+            "prune(node_id=2)",
+        ]
+    );
+}
+
+#[test]
+fn history_is_bounded() {
+    let mut history = EditHistory::new(basic()).with_max_entries(2);
+    history.checkpoint("start");
+    let mut after = Vec::new();
+    for edit in edits() {
+        history.apply(edit).unwrap();
+        after.push(state(history.skeleton()));
+    }
+    assert_eq!(history.history().len(), 2);
+    history.undo().unwrap();
+    history.undo().unwrap();
+    assert_eq!(history.undo(), None);
+    assert_eq!(state(history.skeleton()), after[0]);
+    assert!(history.restore("start").is_err());
+}
+
+#[test]
+fn undos_are_logged() {
+    let path = std::env::temp_dir().join(format!(
+        "compartment_rs_history_{}.jsonl",
+        std::process::id()
+    ));
+    let mut history = EditHistory::new(basic()).with_run_log(RunLog::create(&path).unwrap());
+    for edit in edits() {
+        history.apply(edit).unwrap();
+    }
+    history.undo().unwrap();
+    history.redo().unwrap();
+    history.undo().unwrap();
+
+    let log = fs::read_to_string(&path).unwrap();
+    let ops: Vec<&str> = log
+        .lines()
+        .map(|l| {
+            let start = l.find("\"op\":\"").unwrap() + 6;
+            &l[start..start + l[start..].find('"').unwrap()]
+        })
+        .collect();
+    assert_eq!(ops, ["edit", "edit", "edit", "undo", "redo", "undo"]);
+    assert!(log.lines().last().unwrap().contains("\"edit\":\"prune\""));
+    let _ = fs::remove_file(path);
+}
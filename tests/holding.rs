@@ -0,0 +1,105 @@
+use compartment_rs::channels::{ChannelType, Dynamics, HodgkinHuxley, Passive};
+use compartment_rs::reduce::SiteLocation;
+use compartment_rs::units::{MicroFaradPerCm2, OhmCm, SiemensPerCm2};
+use compartment_rs::{Channel, Compartments, ReaderOptions, swc_reader_from_bytes};
+
+const SITE: SiteLocation = SiteLocation { idx: 2, x: 0.5 };
+
+fn hh() -> Channel {
+    let mut channel = Channel::default();
+    channel.channel_type = ChannelType::HodgkinHuxley(HodgkinHuxley::new());
+    channel.resistance = 100.0;
+    channel.capacitance = 1.0;
+    channel
+}
+
+/// Point soma with a 20 µm long, 10 µm thick HH cylinder at index 2, then
+/// `dendrite` passive compartments 50 µm long and 1 µm thick, leaking
+/// towards -60 mV
+fn cell(dendrite: usize) -> Compartments {
+    let mut swc = String::from("1 1 0 0 0 5 -1\n2 1 20 0 0 5 1\n");
+    for k in 0..dendrite {
+        swc.push_str(&format!(
+            "{} 3 {} 0 0 0.5 {}\n",
+            k + 3,
+            20 + 50 * (k + 1),
+            k + 2
+        ));
+    }
+    let skeleton = swc_reader_from_bytes(swc.as_bytes(), &ReaderOptions::default()).unwrap();
+    let mut compartments = Compartments::from_skeleton(skeleton);
+    for (i, c) in compartments.components.iter_mut().enumerate() {
+        if i <= 2 {
+            c.set_channel(hh());
+        } else {
+            let mut channel = Channel::passive(
+                OhmCm::new(100.0).unwrap(),
+                MicroFaradPerCm2::new(1.0).unwrap(),
+                SiemensPerCm2::new(1e-4).unwrap(),
+            );
+            channel.channel_type = ChannelType::Passive(Passive { e: -60.0 });
+            c.set_channel(channel);
+        }
+    }
+    compartments
+}
+
+#[test]
+fn a_single_compartment_needs_its_membrane_current() {
+    let compartments = cell(0);
+    let holding = compartments.compute_holding_current(-70.0, SITE).unwrap();
+    assert!(holding.converged, "{:?}", holding);
+
+    // Every gate at its steady state for -70 mV, in nA
+    let (hh, v) = (HodgkinHuxley::default(), -70.0);
+    let [m, h, n] = HodgkinHuxley::steady_state(v);
+    let density = hh.gnabar * m.powi(3) * h * (v - hh.ena)
+        + hh.gkbar * n.powi(4) * (v - hh.ek)
+        + hh.gl * (v - hh.el);
+    let expected = density * compartments.components[2].membrane_area() * 10.0 * 1e-3;
+    assert!(expected < 0.0);
+    assert!(
+        (holding.current - expected).abs() < 1e-12 * expected.abs(),
+        "{} against {}",
+        holding.current,
+        expected
+    );
+}
+
+#[test]
+fn a_held_cell_stays_at_the_target() {
+    let mut compartments = cell(6);
+    // Left alone, the HH soma rests well above -70 mV
+    let free = compartments.simulate(0.025, 100.0).unwrap();
+    assert!(free[2].last().unwrap() > &-68.0, "{:?}", free[2].last());
+
+    // The dendrite settles above the soma, so the axial part of the
+    // current changes while it does
+    let holding = compartments.hold_at(-70.0, SITE).unwrap();
+    assert!(holding.converged && holding.steps > 10, "{:?}", holding);
+    let alone = cell(0).compute_holding_current(-70.0, SITE).unwrap();
+    assert!(holding.current < alone.current, "{:?}", alone);
+    assert_eq!(compartments.holding_currents(), [(2, holding.current)]);
+    let held = compartments.simulate(0.025, 100.0).unwrap();
+    let worst = held[2].iter().map(|v| (v + 70.0).abs()).fold(0.0, f64::max);
+    assert!(worst < 0.5, "{} mV off", worst);
+
+    // Held already, so another one adds next to nothing
+    let again = compartments.compute_holding_current(-70.0, SITE).unwrap();
+    assert!(again.current.abs() < 1e-6, "{:?}", again);
+}
+
+#[test]
+fn bad_targets_are_refused() {
+    let mut compartments = cell(1);
+    assert!(
+        compartments
+            .compute_holding_current(f64::NAN, SITE)
+            .is_err()
+    );
+    let nowhere = SiteLocation { idx: 9, x: 0.5 };
+    assert!(compartments.hold_at(-70.0, nowhere).is_err());
+    let root = SiteLocation { idx: 0, x: 0.5 };
+    assert!(compartments.hold_at(-70.0, root).is_err());
+    assert!(compartments.holding_currents().is_empty());
+}
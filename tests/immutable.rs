@@ -0,0 +1,122 @@
+use std::sync::Arc;
+
+use compartment_rs::registration::{Transform, apply_transform};
+use compartment_rs::{ReaderOptions, Skeleton, swc_reader};
+
+fn basic() -> Skeleton {
+    swc_reader("data/basic.swc", &ReaderOptions::default()).unwrap()
+}
+
+fn snapshot(skeleton: &Skeleton) -> String {
+    format!(
+        "{:?} {:?} {:?} {:?}",
+        skeleton.nodes,
+        skeleton
+            .parent_child_map
+            .iter()
+            .collect::<std::collections::BTreeMap<_, _>>(),
+        skeleton
+            .child_parent_map
+            .iter()
+            .collect::<std::collections::BTreeMap<_, _>>(),
+        skeleton
+            .extras
+            .iter()
+            .collect::<std::collections::BTreeMap<_, _>>()
+    )
+}
+
+#[test]
+fn transformations_leave_the_original_untouched() {
+    let skeleton = basic();
+    let before = snapshot(&skeleton);
+    let smoothed = skeleton.smoothed(3);
+    let pruned = skeleton.pruned(2).unwrap();
+    let rerooted = skeleton.rerooted(11).unwrap();
+    let resampled = skeleton.resampled(2.0).unwrap();
+    let normalized = skeleton.normalized().unwrap();
+    let moved = apply_transform(
+        &skeleton,
+        &Transform {
+            translation: [1.0, 2.0, 3.0],
+            ..Transform::default()
+        },
+    );
+    assert_eq!(snapshot(&skeleton), before);
+
+    assert_ne!(snapshot(&smoothed), before);
+    assert_eq!(pruned.nodes.len(), skeleton.nodes.len() - 5);
+    assert!(resampled.nodes.len() > skeleton.nodes.len());
+    assert_ne!(snapshot(&moved), before);
+    for derived in [
+        &smoothed,
+        &pruned,
+        &rerooted,
+        &resampled,
+        &normalized,
+        &moved,
+    ] {
+        derived.validate_maps().unwrap();
+    }
+    assert!(skeleton.pruned(0).is_err());
+    assert!(skeleton.resampled(0.0).is_err());
+}
+
+#[test]
+fn unchanged_columns_are_shared() {
+    let skeleton = basic();
+    let copy = skeleton.clone();
+    assert!(Arc::ptr_eq(&copy.nodes, &skeleton.nodes));
+    assert!(Arc::ptr_eq(
+        &copy.parent_child_map,
+        &skeleton.parent_child_map
+    ));
+
+    // Smoothing and moving touch coordinates only
+    for derived in [
+        skeleton.smoothed(1),
+        apply_transform(&skeleton, &Transform::default()),
+    ] {
+        assert!(!Arc::ptr_eq(&derived.nodes, &skeleton.nodes));
+        assert!(Arc::ptr_eq(
+            &derived.parent_child_map,
+            &skeleton.parent_child_map
+        ));
+        assert!(Arc::ptr_eq(
+            &derived.child_parent_map,
+            &skeleton.child_parent_map
+        ));
+        assert!(Arc::ptr_eq(&derived.extras, &skeleton.extras));
+    }
+
+    // An edit session copies a column the first time it writes to it
+    let mut editing = skeleton.edit();
+    assert!(Arc::ptr_eq(&editing.nodes, &skeleton.nodes));
+    editing.nodes_mut()[1].radius = 9.0;
+    assert!(!Arc::ptr_eq(&editing.nodes, &skeleton.nodes));
+    assert_ne!(skeleton.nodes[1].radius, 9.0);
+    let committed = editing.commit().unwrap();
+    assert!(Arc::ptr_eq(
+        &committed.parent_child_map,
+        &skeleton.parent_child_map
+    ));
+}
+
+#[test]
+fn a_commit_is_consistent() {
+    let skeleton = basic();
+    let mut editing = skeleton.edit();
+    let new_id = editing.insert_node_on_edge(4, 8, 0.5, 0.8).unwrap();
+    editing.reattach_subtree(new_id, 7).unwrap();
+    editing.smooth_coordinates(1);
+    editing.prune_subtree(3).unwrap();
+    editing.finalize().unwrap();
+    let committed = editing.commit().unwrap();
+    committed.validate_maps().unwrap();
+    assert_eq!(committed.nodes.len(), skeleton.nodes.len() + 1 - 3);
+
+    // Breaking the maps by hand is caught on the way out
+    let mut editing = skeleton.edit();
+    editing.parent_child_map_mut().remove(&4);
+    assert!(editing.commit().is_err());
+}
@@ -0,0 +1,217 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use compartment_rs::index_map::{IndexMap, Link};
+use compartment_rs::units::{MicroFaradPerCm2, OhmCm, SiemensPerCm2};
+use compartment_rs::{
+    AttachmentKind, Channel, Compartments, ReaderOptions, ThinNeuritePolicy, swc_reader_from_bytes,
+};
+
+/// Builds compartments from `(id, x, y, radius, parent)` rows with a
+/// passive membrane everywhere
+fn model(rows: &[(usize, f64, f64, f64, i64)]) -> Compartments {
+    let swc: String = rows
+        .iter()
+        .map(|&(id, x, y, r, parent)| {
+            let kind = if parent == -1 { 1 } else { 3 };
+            format!("{} {} {} {} 0 {} {}\n", id, kind, x, y, r, parent)
+        })
+        .collect();
+    let skeleton = swc_reader_from_bytes(swc.as_bytes(), &ReaderOptions::default()).unwrap();
+    let mut compartments = Compartments::from_skeleton(skeleton);
+    for c in compartments.components.iter_mut().skip(1) {
+        c.set_channel(Channel::passive(
+            OhmCm::new(100.0).unwrap(),
+            MicroFaradPerCm2::new(1.0).unwrap(),
+            SiemensPerCm2::new(1e-4).unwrap(),
+        ));
+    }
+    compartments
+}
+
+/// Soma with two straight dendrites along x and y, 20 µm per node, and a
+/// thin twig off the middle of the first, so every section is straight
+fn straight_cell() -> Compartments {
+    let mut rows = vec![(1, 0.0, 0.0, 5.0, -1)];
+    for k in 1..=10 {
+        let parent = if k == 1 { 1 } else { k as i64 };
+        rows.push((k + 1, 20.0 * k as f64, 0.0, 1.0, parent));
+    }
+    for k in 1..=10 {
+        let parent = if k == 1 { 1 } else { k as i64 + 10 };
+        rows.push((k + 11, 0.0, 20.0 * k as f64, 1.0, parent));
+    }
+    rows.push((22, 100.0, 10.0, 0.1, 6));
+    model(&rows)
+}
+
+fn location(compartments: &Compartments, idx: usize, x: f64) -> [f64; 3] {
+    compartments.local_frame(idx, x).unwrap().origin
+}
+
+fn distance(a: [f64; 3], b: [f64; 3]) -> f64 {
+    ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+}
+
+#[test]
+fn fresh_models_map_to_themselves() {
+    let compartments = straight_cell();
+    let map = compartments.index_map();
+    assert_eq!(map, &IndexMap::identity(compartments.components.len()));
+    assert_eq!(map.forward_position(5, 0.3), Some((5, 0.3)));
+    assert_eq!(map.backward_position(5, 0.3), Some((5, 0.3)));
+    assert!(compartments.last_index_map().is_none());
+}
+
+#[test]
+fn attachments_follow_coarsening_and_splitting() {
+    let mut compartments = straight_cell();
+    compartments
+        .attach(AttachmentKind::Probe, "dend_v", 5, 0.25, 0.25)
+        .unwrap();
+    compartments
+        .attach(AttachmentKind::Stimulus, "iclamp", 14, 0.0, 1.0)
+        .unwrap();
+    let probe_at = location(&compartments, 5, 0.25);
+    let stimulus_from = location(&compartments, 14, 0.0);
+    let stimulus_to = location(&compartments, 14, 1.0);
+
+    compartments.coarsen_by_length(60.0);
+    let coarse = compartments.last_index_map().unwrap().clone();
+    let fine = compartments.d_lambda_rule(100.0, 0.005).unwrap();
+    let split = fine.last_index_map().unwrap().clone();
+    assert!(fine.components.len() > compartments.components.len());
+
+    let probe = &fine.attachments()[0];
+    assert_eq!(probe.name, "dend_v");
+    assert!(distance(location(&fine, probe.idx, probe.from), probe_at) < 1e-9);
+
+    // The stimulated compartment was merged, then the merged one split, so
+    // the stimulus spreads over pieces that all lie on the original
+    let pieces: Vec<_> = fine.attachments()[1..].iter().collect();
+    assert!(pieces.len() > 1);
+    let weight: f64 = pieces.iter().map(|a| a.weight).sum();
+    assert!((weight - 1.0).abs() < 1e-12);
+    let span = distance(stimulus_from, stimulus_to);
+    for piece in pieces {
+        assert_eq!(piece.kind, AttachmentKind::Stimulus);
+        for x in [piece.from, piece.to] {
+            let p = location(&fine, piece.idx, x);
+            let along = distance(stimulus_from, p) + distance(p, stimulus_to);
+            assert!((along - span).abs() < 1e-9);
+        }
+        let length = fine.components[piece.idx].length * (piece.to - piece.from);
+        assert!((length / span - piece.weight).abs() < 1e-9);
+    }
+
+    // The stored cumulative map is the two steps chained
+    assert_eq!(fine.index_map(), &coarse.then(&split).unwrap());
+    let (mid, x) = coarse.forward_position(5, 0.25).unwrap();
+    let (a, b) = (
+        fine.index_map().forward_position(5, 0.25).unwrap(),
+        split.forward_position(mid, x).unwrap(),
+    );
+    assert_eq!(a.0, b.0);
+    assert!((a.1 - b.1).abs() < 1e-12);
+}
+
+#[test]
+fn maps_run_backwards() {
+    let compartments = straight_cell();
+    let fine = compartments.d_lambda_rule(100.0, 0.005).unwrap();
+    let map = fine.last_index_map().unwrap();
+    for new in 1..fine.components.len() {
+        let (old, x) = map.backward_position(new, 0.5).unwrap();
+        let there = location(&compartments, old, x);
+        assert!(distance(there, location(&fine, new, 0.5)) < 1e-9);
+        assert_eq!(map.forward_position(old, x).unwrap().0, new);
+    }
+}
+
+#[test]
+fn removed_targets_are_named() {
+    let mut compartments = straight_cell();
+    let twig = compartments
+        .components
+        .iter()
+        .position(|c| c.diam > 0.0 && c.diam < 0.5)
+        .unwrap();
+    compartments
+        .attach(AttachmentKind::Probe, "twig_v", twig, 0.5, 0.5)
+        .unwrap();
+    let before = compartments.components.len();
+    let err = compartments
+        .apply_thin_neurite_policy(0.5, ThinNeuritePolicy::MergeIntoParent)
+        .unwrap_err();
+    assert!(err.contains("twig_v"), "{}", err);
+    assert_eq!(compartments.components.len(), before);
+    assert_eq!(compartments.attachments()[0].idx, twig);
+
+    let same = IndexMap::identity(before);
+    assert!(compartments.translate_attachments(&same).is_ok());
+    assert!(IndexMap::identity(3).then(&IndexMap::identity(4)).is_err());
+}
+
+#[test]
+fn bad_attachments_are_refused() {
+    let mut compartments = straight_cell();
+    let mut attach = |idx, from, to| compartments.attach(AttachmentKind::Tag, "t", idx, from, to);
+    assert!(attach(0, 0.5, 0.5).is_err());
+    assert!(attach(999, 0.5, 0.5).is_err());
+    assert!(attach(3, 1.5, 1.5).is_err());
+    assert!(attach(3, 0.6, 0.4).is_err());
+    assert!(attach(3, 0.4, 0.6).is_ok());
+}
+
+/// Random tree of `n` nodes with 10 µm segments in random directions
+fn random_cell(rng: &mut StdRng, n: usize) -> Compartments {
+    let mut rows = vec![(1, 0.0, 0.0, 5.0, -1)];
+    let mut xy = vec![(0.0, 0.0)];
+    for id in 2..=n {
+        let parent = rng.random_range(1..id);
+        let angle: f64 = rng.random_range(0.0..std::f64::consts::TAU);
+        let (px, py) = xy[parent - 1];
+        let (x, y) = (px + 10.0 * angle.cos(), py + 10.0 * angle.sin());
+        xy.push((x, y));
+        rows.push((id, x, y, rng.random_range(0.3..1.5), parent as i64));
+    }
+    model(&rows)
+}
+
+#[test]
+fn chained_maps_compose_on_random_trees() {
+    let mut rng = StdRng::seed_from_u64(459);
+    for _ in 0..20 {
+        let size = rng.random_range(5..60);
+        let mut compartments = random_cell(&mut rng, size);
+        let n = compartments.components.len();
+        compartments.coarsen_by_length(rng.random_range(5.0..40.0));
+        let first = compartments.last_index_map().unwrap().clone();
+        let fine = compartments
+            .d_lambda_rule(100.0, rng.random_range(0.002..0.05))
+            .unwrap();
+        let second = fine.last_index_map().unwrap();
+        let composed = first.then(second).unwrap();
+        assert_eq!(fine.index_map(), &composed);
+        assert_eq!(composed.old_len(), n);
+        assert_eq!(composed.new_len(), fine.components.len());
+
+        for _ in 0..50 {
+            let old = rng.random_range(1..n);
+            let x: f64 = rng.random_range(0.0..1.0);
+            let stepwise = first
+                .forward_position(old, x)
+                .and_then(|(mid, y)| second.forward_position(mid, y));
+            let (a, b) = (composed.forward_position(old, x), stepwise);
+            let (a, b) = (a.unwrap(), b.unwrap());
+            assert_eq!(a.0, b.0);
+            assert!((a.1 - b.1).abs() < 1e-9);
+        }
+        // Every old compartment is still covered end to end
+        for old in 1..n {
+            let links: Vec<Link> = composed.forward(old).unwrap();
+            let covered: f64 = links.iter().map(|l| l.old_to - l.old_from).sum();
+            assert!((covered - 1.0).abs() < 1e-9);
+        }
+    }
+}
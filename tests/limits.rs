@@ -0,0 +1,140 @@
+use std::time::{Duration, Instant};
+
+use compartment_rs::{
+    Code, Limit, ReaderOptions, SwcError, swc_reader, swc_reader_from_buf, swc_reader_from_bytes,
+};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// An unbranched chain of `n` nodes
+fn chain(n: usize) -> String {
+    let mut out = String::from("1 1 0 0 0 5 -1\n");
+    for id in 2..=n {
+        out.push_str(&format!("{} 3 {} 0 0 1 {}\n", id, id, id - 1));
+    }
+    out
+}
+
+#[test]
+fn too_many_nodes_stops_early_with_counts() {
+    let data = chain(200_000);
+    let options = ReaderOptions {
+        max_nodes: Some(1000),
+        ..Default::default()
+    };
+    let started = Instant::now();
+    let err = swc_reader_from_bytes(data.as_bytes(), &options).unwrap_err();
+    assert!(started.elapsed() < Duration::from_secs(1));
+    assert_eq!(
+        err,
+        SwcError::LimitExceeded {
+            which: Limit::Nodes,
+            limit: 1000,
+            observed: 1001,
+        }
+    );
+    assert_eq!(err.code(), Code::LimitExceeded);
+}
+
+#[test]
+fn byte_limit_covers_files_without_line_breaks() {
+    let data = vec![b'1'; 1 << 20];
+    let options = ReaderOptions {
+        max_file_bytes: Some(4096),
+        ..Default::default()
+    };
+    let err = swc_reader_from_buf(&data[..], &options).unwrap_err();
+    assert_eq!(
+        err,
+        SwcError::LimitExceeded {
+            which: Limit::FileBytes,
+            limit: 4096,
+            observed: 4097,
+        }
+    );
+}
+
+#[test]
+fn timeout_zero_trips_on_first_check() {
+    let options = ReaderOptions {
+        timeout: Some(Duration::ZERO),
+        ..Default::default()
+    };
+    let err = swc_reader_from_bytes(chain(10).as_bytes(), &options).unwrap_err();
+    assert!(matches!(
+        err,
+        SwcError::LimitExceeded {
+            which: Limit::Timeout,
+            limit: 0,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn binary_blob_is_reported_as_not_swc() {
+    let mut rng = StdRng::seed_from_u64(439);
+    // Newlines every so often so there are lines to probe, and no '#' so
+    // none of them pass for comments
+    let blob: Vec<u8> = (0..100_000)
+        .map(|i| {
+            if i % 64 == 63 {
+                b'\n'
+            } else {
+                loop {
+                    let b: u8 = rng.random();
+                    if b != b'#' && b != b'\n' {
+                        break b;
+                    }
+                }
+            }
+        })
+        .collect();
+    for probe in [3, 7] {
+        let options = ReaderOptions {
+            format_probe_lines: probe,
+            ..Default::default()
+        };
+        let err = swc_reader_from_bytes(&blob, &options).unwrap_err();
+        assert_eq!(err.code(), Code::NotSwc);
+        assert!(
+            err.to_string()
+                .contains(&format!("first {} data lines", probe))
+        );
+    }
+
+    // With the probe off, the first failure comes through as it is
+    let options = ReaderOptions {
+        format_probe_lines: 0,
+        ..Default::default()
+    };
+    let err = swc_reader_from_bytes(&blob, &options).unwrap_err();
+    assert_ne!(err.code(), Code::NotSwc);
+}
+
+#[test]
+fn single_bad_line_keeps_its_own_error() {
+    let err =
+        swc_reader_from_bytes(b"1 1 0 0 x 5 -1\n2 3 1 0 0 1 1\n", &Default::default()).unwrap_err();
+    assert_eq!(err.code(), Code::InvalidField);
+}
+
+#[test]
+fn generous_limits_change_nothing() {
+    let path = "data/extras.swc";
+    let plain = swc_reader(path, &ReaderOptions::default()).unwrap();
+    let limited = swc_reader(
+        path,
+        &ReaderOptions {
+            max_nodes: Some(10_000_000),
+            max_file_bytes: Some(1 << 32),
+            timeout: Some(Duration::from_secs(600)),
+            format_probe_lines: 1,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    // The id hashes the written-out file, extras and header included
+    assert_eq!(plain.cell_id().to_string(), limited.cell_id().to_string());
+    assert_eq!(plain.nodes.len(), limited.nodes.len());
+}
@@ -0,0 +1,80 @@
+use compartment_rs::manifest::{
+    BuildInfo, Manifest, NumericsChange, RuntimeInfo, check_compatibility,
+};
+
+#[test]
+fn every_field_is_filled_in() {
+    let build = BuildInfo::current();
+    assert_eq!(build.version, env!("CARGO_PKG_VERSION"));
+    assert!(!build.git_commit.is_empty());
+    assert!(!build.target_arch.is_empty());
+    assert!(!build.target_os.is_empty());
+    assert!(["debug", "release"].contains(&build.profile.as_str()));
+    assert_eq!(
+        build.features.contains(&"python".to_owned()),
+        cfg!(feature = "python")
+    );
+
+    let runtime = RuntimeInfo::capture("backward_euler", false);
+    assert_eq!(runtime.solver, "backward_euler");
+    // Rust never turns flushing on by itself
+    assert!(!runtime.flush_to_zero && !runtime.denormals_are_zero);
+}
+
+#[test]
+fn manifests_round_trip() {
+    let manifest = Manifest::capture("crank_nicolson", true);
+    let text = manifest.to_text();
+    assert_eq!(Manifest::parse(&text).unwrap(), manifest);
+
+    // Empty lists survive too
+    let mut bare = manifest.clone();
+    bare.build.features.clear();
+    bare.build.target_features.clear();
+    assert_eq!(Manifest::parse(&bare.to_text()).unwrap(), bare);
+
+    assert!(Manifest::parse("").is_err());
+    assert!(Manifest::parse(&text.replace("simd true", "simd maybe")).is_err());
+    assert!(Manifest::parse(&text.replace("solver crank_nicolson\n", "")).is_err());
+    assert!(Manifest::parse(&format!("{}version 9.9.9\n", text)).is_err());
+}
+
+#[test]
+fn the_same_build_is_compatible() {
+    let manifest = Manifest::capture("backward_euler", false);
+    assert!(check_compatibility(&manifest).is_empty());
+}
+
+#[test]
+fn differences_that_move_numbers_are_reported() {
+    let current = Manifest::capture("backward_euler", false);
+    let mut recorded = Manifest::parse(&current.to_text()).unwrap();
+    recorded.build.features.push("simd".to_owned());
+    let differences = check_compatibility(&recorded);
+    assert_eq!(differences.len(), 1);
+    assert!(differences[0].contains("features"), "{}", differences[0]);
+
+    // A commit alone does not change numbers
+    let mut recorded = current.clone();
+    recorded.build.git_commit = "0000000".to_owned();
+    assert!(check_compatibility(&recorded).is_empty());
+
+    let mut recorded = current.clone();
+    recorded.runtime.flush_to_zero = true;
+    recorded.runtime.solver = "crank_nicolson".to_owned();
+    let differences = recorded.numerics_differences(&current, &[]);
+    assert_eq!(differences.len(), 2);
+
+    // A release that changed numbers counts only for results recorded
+    // before it
+    let changes = [NumericsChange {
+        version: "0.1.0",
+        description: "new default time step",
+    }];
+    let mut old = current.clone();
+    old.build.version = "0.0.9".to_owned();
+    let differences = old.numerics_differences(&current, &changes);
+    assert_eq!(differences.len(), 1);
+    assert!(differences[0].contains("new default time step"));
+    assert!(current.numerics_differences(&current, &changes).is_empty());
+}
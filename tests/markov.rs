@@ -0,0 +1,104 @@
+use compartment_rs::{MarkovChannel, MarkovScheme, RateFn};
+
+// An HH-style gate: sigmoidal opening, exponential closing
+fn alpha() -> RateFn {
+    RateFn::sigmoid(0.4, -50.0, -6.0)
+}
+
+fn beta() -> RateFn {
+    RateFn::exp(0.125 * (-65.0f64 / 80.0).exp(), -1.0 / 80.0)
+}
+
+#[test]
+fn two_state_scheme_matches_a_single_gate() {
+    let scheme = MarkovScheme::new()
+        .state("C")
+        .open_state("O")
+        .rate("C", "O", alpha())
+        .rate("O", "C", beta());
+    let mut channel = MarkovChannel::new(scheme, 36.0, -77.0, -65.0).unwrap();
+
+    let gate_inf = |v: f64| alpha().rate(v) / (alpha().rate(v) + beta().rate(v));
+    let tau = |v: f64| 1.0 / (alpha().rate(v) + beta().rate(v));
+    let start = channel.open_probability();
+    assert!((start - gate_inf(-65.0)).abs() < 1e-15);
+
+    // Step to -20 mV: the gate relaxes exponentially towards its new value
+    for k in 1..=40 {
+        channel.step(-20.0, 0.05);
+        let t = k as f64 * 0.05;
+        let expected = gate_inf(-20.0) + (start - gate_inf(-20.0)) * (-t / tau(-20.0)).exp();
+        assert!((channel.open_probability() - expected).abs() < 1e-12);
+    }
+    let current = channel.current(-20.0);
+    assert!((current - 36.0 * channel.open_probability() * 57.0).abs() < 1e-12);
+}
+
+#[test]
+fn probability_is_conserved_over_a_long_run() {
+    // Stiff on purpose: rates spanning five orders of magnitude
+    let scheme = MarkovScheme::new()
+        .state("C1")
+        .state("C2")
+        .open_state("O")
+        .state("I")
+        .rate("C1", "C2", RateFn::exp(50.0, 0.05))
+        .rate("C2", "C1", RateFn::exp(20.0, -0.05))
+        .rate("C2", "O", RateFn::sigmoid(10.0, -35.0, -5.0))
+        .rate("O", "C2", RateFn::constant(1.0))
+        .rate("O", "I", RateFn::linear(0.5, 0.005))
+        .rate("I", "C1", RateFn::sigmoid(0.001, -70.0, 5.0));
+    let mut channel = MarkovChannel::new(scheme, 1.0, 50.0, -80.0).unwrap();
+    for k in 0..20_000 {
+        let v = -80.0 + 70.0 * ((k as f64) * 0.005).sin().abs();
+        channel.step(v, 0.025);
+        assert!(channel.probabilities.iter().all(|&p| p >= 0.0));
+    }
+    let total: f64 = channel.probabilities.iter().sum();
+    assert!((total - 1.0).abs() < 1e-9);
+
+    let broken = MarkovScheme::new()
+        .open_state("O")
+        .rate("O", "X", RateFn::constant(1.0));
+    assert_eq!(
+        MarkovChannel::new(broken, 1.0, 0.0, 0.0).unwrap_err(),
+        "Unknown state 'X'"
+    );
+}
+
+#[test]
+fn three_state_clamp_matches_golden_trace() {
+    let scheme = MarkovScheme::new()
+        .state("C")
+        .open_state("O")
+        .state("I")
+        .rate("C", "O", RateFn::sigmoid(4.0, -30.0, -8.0))
+        .rate("O", "C", RateFn::exp(0.25, -0.06))
+        .rate("O", "I", RateFn::linear(1.5, 0.01))
+        .rate("I", "C", RateFn::sigmoid(0.2, -60.0, 6.0));
+    let mut channel = MarkovChannel::new(scheme, 120.0, 50.0, -80.0).unwrap();
+
+    let golden: Vec<(f64, f64)> = std::fs::read_to_string("data/golden/markov_coi_clamp.csv")
+        .unwrap()
+        .lines()
+        .filter(|l| !l.starts_with('#'))
+        .skip(1)
+        .map(|l| {
+            let (t, i) = l.split_once(',').unwrap();
+            (t.parse().unwrap(), i.parse().unwrap())
+        })
+        .collect();
+    assert_eq!(golden.len(), 151);
+    assert!((channel.current(-80.0) - golden[0].1).abs() < 1e-9);
+
+    let peak = golden.iter().map(|(_, i)| i.abs()).fold(0.0, f64::max);
+    for (k, (t, expected)) in golden.iter().enumerate().skip(1) {
+        let v = if k <= 100 { -10.0 } else { -80.0 };
+        channel.step(v, 0.1);
+        assert!(
+            (channel.current(v) - expected).abs() < 1e-9 * peak,
+            "t = {}",
+            t
+        );
+    }
+}
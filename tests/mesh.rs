@@ -0,0 +1,201 @@
+use compartment_rs::mesh::{Mesh, MeshOptions, to_tube_mesh};
+use compartment_rs::{Compartments, ReaderOptions, Skeleton, swc_reader, swc_reader_from_bytes};
+
+fn sub(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+/// Point soma then `n` segments of 6 um along x, 1 um across
+fn cable(n: usize) -> Compartments {
+    let ids: Vec<i64> = (1..=n as i64 + 1).collect();
+    let mut types = vec![3; n + 1];
+    types[0] = 1;
+    let parents: Vec<i64> = (0..=n as i64)
+        .map(|i| if i == 0 { -1 } else { i })
+        .collect();
+    let xyz: Vec<[f64; 3]> = (0..=n).map(|i| [6.0 * i as f64, 0.0, 0.0]).collect();
+    let mut radii = vec![0.5; n + 1];
+    radii[0] = 5.0;
+    let skeleton = Skeleton::from_arrays(
+        &ids,
+        &types,
+        &xyz,
+        &radii,
+        &parents,
+        &ReaderOptions::default(),
+    )
+    .unwrap();
+    Compartments::from_skeleton(skeleton)
+}
+
+fn assert_well_formed(mesh: &Mesh) {
+    assert_eq!(mesh.normals.len(), mesh.vertices.len());
+    assert!(mesh.vertices.iter().flatten().all(|x| x.is_finite()));
+    for n in &mesh.normals {
+        assert!((dot(*n, *n).sqrt() - 1.0).abs() < 1e-12, "{:?}", n);
+    }
+    for f in &mesh.faces {
+        let [a, b, c] = f.map(|i| mesh.vertices[i]);
+        let area = dot(cross(sub(b, a), sub(c, a)), cross(sub(b, a), sub(c, a))).sqrt() / 2.0;
+        assert!(area > 1e-12, "degenerate face {:?}", f);
+    }
+}
+
+#[test]
+fn straight_cable_counts_match_formula() {
+    let (n, m) = (4, 8);
+    let options = MeshOptions {
+        radial_segments: m,
+        max_segment_length: 2.0,
+        ..Default::default()
+    };
+    let mesh = to_tube_mesh(&cable(n), None, &options).unwrap();
+    // Soma sphere: two poles and m/2 - 1 rings; tubes: 3 pieces, so 4 rings
+    // each; one cap at the tip
+    let sphere = (2 + (m / 2 - 1) * m, 2 * m + 2 * m * (m / 2 - 2));
+    let tubes = (n * 4 * m, n * 3 * 2 * m);
+    let cap = (m + 1, m);
+    assert_eq!(mesh.vertices.len(), sphere.0 + tubes.0 + cap.0);
+    assert_eq!(mesh.faces.len(), sphere.1 + tubes.1 + cap.1);
+    assert!(mesh.scalars.is_empty());
+    assert_well_formed(&mesh);
+
+    // Faces wind outwards: on the tubes, away from the x axis
+    let first_tube = sphere.1;
+    for f in &mesh.faces[first_tube..first_tube + tubes.1] {
+        let [a, b, c] = f.map(|i| mesh.vertices[i]);
+        let normal = cross(sub(b, a), sub(c, a));
+        let centroid = [0.0, (a[1] + b[1] + c[1]) / 3.0, (a[2] + b[2] + c[2]) / 3.0];
+        assert!(dot(normal, centroid) > 0.0);
+    }
+}
+
+#[test]
+fn degenerate_segments_stay_finite() {
+    // A duplicated point and a zero radius on the way
+    let text = b"1 1 0 0 0 3 -1\n2 3 5 0 0 1 1\n3 3 5 0 0 1 2\n4 3 9 0 0 0 3\n5 3 9 4 0 0.5 4\n";
+    let skeleton = swc_reader_from_bytes(text, &ReaderOptions::default()).unwrap();
+    let mesh = to_tube_mesh(
+        &Compartments::from_skeleton(skeleton),
+        None,
+        &MeshOptions::default(),
+    )
+    .unwrap();
+    assert!(!mesh.faces.is_empty());
+    assert_well_formed(&mesh);
+
+    let options = MeshOptions {
+        radial_segments: 2,
+        ..Default::default()
+    };
+    assert!(to_tube_mesh(&cable(1), None, &options).is_err());
+    assert!(to_tube_mesh(&cable(1), Some(&[1.0]), &MeshOptions::default()).is_err());
+}
+
+#[test]
+fn scalars_follow_their_compartments() {
+    let skeleton = swc_reader("data/basic.swc", &ReaderOptions::default()).unwrap();
+    let compartments = Compartments::from_skeleton(skeleton);
+    let ids: Vec<f64> = (0..compartments.components.len())
+        .map(|i| i as f64)
+        .collect();
+    let mesh = to_tube_mesh(&compartments, Some(&ids), &MeshOptions::default()).unwrap();
+    assert_eq!(mesh.scalars.len(), mesh.vertices.len());
+    assert_well_formed(&mesh);
+    for (v, &s) in mesh.vertices.iter().zip(&mesh.scalars) {
+        let c = &compartments.components[s as usize];
+        let axis = sub(c.distal, c.proximal);
+        let length = dot(axis, axis).sqrt();
+        let offset = sub(*v, c.proximal);
+        let distance = if length > 0.0 {
+            // Within the tube's length and at most a radius from the axis
+            let along = dot(offset, axis) / length;
+            assert!((-1e-9..=length + 1e-9).contains(&along));
+            (dot(offset, offset) - along * along).max(0.0).sqrt()
+        } else {
+            dot(offset, offset).sqrt()
+        };
+        assert!(distance <= c.diam / 2.0 + 1e-9, "{} from {}", distance, s);
+    }
+}
+
+/// Reads back what `Mesh::to_ply` writes, and nothing else
+fn read_ply(bytes: &[u8]) -> (Vec<Vec<f32>>, Vec<[u32; 3]>) {
+    let end = b"end_header\n";
+    let split = bytes.windows(end.len()).position(|w| w == end).unwrap() + end.len();
+    let header = std::str::from_utf8(&bytes[..split]).unwrap();
+    let count = |element: &str| -> usize {
+        let line = header
+            .lines()
+            .find(|l| l.starts_with(&format!("element {} ", element)))
+            .unwrap();
+        line.rsplit(' ').next().unwrap().parse().unwrap()
+    };
+    let properties = header
+        .lines()
+        .filter(|l| l.starts_with("property float"))
+        .count();
+    let mut body = &bytes[split..];
+    let mut take = |n: usize| {
+        let (head, rest) = body.split_at(n);
+        body = rest;
+        head
+    };
+    let vertices = (0..count("vertex"))
+        .map(|_| {
+            (0..properties)
+                .map(|_| f32::from_le_bytes(take(4).try_into().unwrap()))
+                .collect()
+        })
+        .collect();
+    let faces = (0..count("face"))
+        .map(|_| {
+            assert_eq!(take(1), [3]);
+            [0; 3].map(|_: u32| u32::from_le_bytes(take(4).try_into().unwrap()))
+        })
+        .collect();
+    assert!(body.is_empty());
+    (vertices, faces)
+}
+
+#[test]
+fn ply_round_trips() {
+    let compartments = cable(3);
+    let scalars: Vec<f64> = (0..5).map(|i| i as f64 * 10.0).collect();
+    let mesh = to_tube_mesh(&compartments, Some(&scalars), &MeshOptions::default()).unwrap();
+    let (vertices, faces) = read_ply(&mesh.to_ply());
+    assert_eq!(vertices.len(), mesh.vertices.len());
+    for (i, row) in vertices.iter().enumerate() {
+        let expected: Vec<f32> = mesh.vertices[i]
+            .iter()
+            .chain(&mesh.normals[i])
+            .chain([&mesh.scalars[i]])
+            .map(|&x| x as f32)
+            .collect();
+        assert_eq!(*row, expected);
+    }
+    let expected: Vec<[u32; 3]> = mesh.faces.iter().map(|f| f.map(|i| i as u32)).collect();
+    assert_eq!(faces, expected);
+
+    let obj = mesh.to_obj();
+    assert_eq!(
+        obj.lines().filter(|l| l.starts_with("v ")).count(),
+        mesh.vertices.len()
+    );
+    assert_eq!(
+        obj.lines().filter(|l| l.starts_with("f ")).count(),
+        mesh.faces.len()
+    );
+}
@@ -0,0 +1,64 @@
+use compartment_rs::{Morphometry, ReaderOptions, swc_reader};
+
+#[test]
+fn scale_is_applied_when_asked() {
+    let options = ReaderOptions {
+        apply_scale: true,
+        ..Default::default()
+    };
+    let scaled = swc_reader("data/scaled.swc", &options).unwrap();
+    let raw = swc_reader("data/scaled.swc", &ReaderOptions::default()).unwrap();
+    let prescaled = swc_reader("data/basic.swc", &ReaderOptions::default()).unwrap();
+    assert_eq!(scaled.metadata.scale, Some([0.5, 0.5, 0.5]));
+
+    for ((s, r), p) in scaled
+        .nodes
+        .iter()
+        .zip(raw.nodes.iter())
+        .zip(prescaled.nodes.iter())
+    {
+        assert_eq!(
+            (s.x_pos, s.y_pos, s.z_pos),
+            (r.x_pos / 2.0, r.y_pos / 2.0, r.z_pos / 2.0)
+        );
+        assert_eq!((s.x_pos, s.y_pos, s.z_pos), (p.x_pos, p.y_pos, p.z_pos));
+    }
+
+    let scaled_metrics = Morphometry::new(&scaled.nodes);
+    let prescaled_metrics = Morphometry::new(&prescaled.nodes);
+    assert_eq!(
+        scaled_metrics.total_length(),
+        prescaled_metrics.total_length()
+    );
+    assert_eq!(
+        scaled_metrics.spatial_metrics(),
+        prescaled_metrics.spatial_metrics()
+    );
+}
+
+#[test]
+fn unknown_keys_round_trip() {
+    let out = std::env::temp_dir().join("compartment_rs_metadata_round_trip.swc");
+    let options = ReaderOptions {
+        write_path: Some(out.clone()),
+        ..Default::default()
+    };
+    let original = swc_reader("data/scaled.swc", &options).unwrap();
+    assert_eq!(original.metadata.other["CREATURE"], "rat");
+    assert_eq!(
+        original.metadata.original_source.as_deref(),
+        Some("hand-built")
+    );
+
+    let reread = swc_reader(&out, &ReaderOptions::default()).unwrap();
+    assert_eq!(reread.metadata, original.metadata);
+}
+
+#[test]
+fn malformed_scale_is_an_error() {
+    let err = swc_reader("data/bad_scale.swc", &ReaderOptions::default())
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("SCALE"), "{}", err);
+    assert!(err.contains("line 1"), "{}", err);
+}
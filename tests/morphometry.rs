@@ -0,0 +1,103 @@
+use compartment_rs::{Morphometry, Node, StructureIdentifier};
+
+/// A chain of nodes visiting every point of an n x n x n lattice with the
+/// given spacing, so the arbor fills a cube.
+fn lattice(n: usize, spacing: f64, structure: StructureIdentifier) -> Vec<Node> {
+    let mut nodes = Vec::new();
+    for i in 0..n {
+        for j in 0..n {
+            for k in 0..n {
+                let id = nodes.len() as u64;
+                let parent = if id == 0 { 0 } else { id - 1 };
+                nodes.push(Node::new(
+                    id,
+                    structure,
+                    i as f64 * spacing,
+                    j as f64 * spacing,
+                    k as f64 * spacing,
+                    1.0,
+                    parent,
+                ));
+            }
+        }
+    }
+    nodes
+}
+
+#[test]
+fn cubic_lattice_box_and_hull() {
+    let nodes = lattice(4, 2.0, StructureIdentifier::BasalDendrite);
+    let metrics = Morphometry::new(&nodes).spatial_metrics();
+
+    assert!(!metrics.degenerate);
+    assert!((metrics.bounding_box.volume() - 216.0).abs() < 1e-9);
+    assert!((metrics.hull_volume - 216.0).abs() < 1e-9);
+    assert!((metrics.hull_area - 216.0).abs() < 1e-9);
+    assert!((metrics.oriented_volume - 216.0).abs() < 1e-6);
+    assert!((metrics.density - metrics.cable_length / 216.0).abs() < 1e-12);
+}
+
+#[test]
+fn planar_arbor_is_degenerate() {
+    let nodes: Vec<Node> = (0..10)
+        .map(|i| {
+            let x = (i % 5) as f64;
+            let y = (i / 5) as f64 * 3.0;
+            let parent = if i == 0 { 0 } else { i - 1 };
+            Node::new(i, StructureIdentifier::Axon, x, y, 7.0, 1.0, parent)
+        })
+        .collect();
+    let metrics = Morphometry::new(&nodes).spatial_metrics();
+
+    assert!(metrics.degenerate);
+    assert_eq!(metrics.hull_volume, 0.0);
+    assert_eq!(metrics.density, 0.0);
+    assert!(metrics.cable_length > 0.0);
+}
+
+#[test]
+fn collinear_arbor_is_degenerate() {
+    let nodes: Vec<Node> = (0..5)
+        .map(|i| {
+            let parent = if i == 0 { 0 } else { i - 1 };
+            Node::new(
+                i,
+                StructureIdentifier::Axon,
+                i as f64,
+                0.0,
+                0.0,
+                1.0,
+                parent,
+            )
+        })
+        .collect();
+    let metrics = Morphometry::new(&nodes).spatial_metrics();
+
+    assert!(metrics.degenerate);
+    assert_eq!(metrics.cable_length, 4.0);
+}
+
+#[test]
+fn per_type_metrics_only_use_that_type() {
+    let mut nodes = lattice(3, 1.0, StructureIdentifier::BasalDendrite);
+    let offset = nodes.len() as u64;
+    // A small axon cube far away from the dendrites
+    for mut node in lattice(2, 10.0, StructureIdentifier::Axon) {
+        node.node_id += offset;
+        node.parent_id += offset;
+        node.x_pos += 100.0;
+        nodes.push(node);
+    }
+    let morphometry = Morphometry::new(&nodes);
+
+    let axon = morphometry.spatial_metrics_for(StructureIdentifier::Axon);
+    assert_eq!(axon.bounding_box.min, [100.0, 0.0, 0.0]);
+    assert!((axon.hull_volume - 1000.0).abs() < 1e-9);
+
+    let dendrite = morphometry.spatial_metrics_for(StructureIdentifier::BasalDendrite);
+    assert!((dendrite.hull_volume - 8.0).abs() < 1e-9);
+
+    let by_type = morphometry.spatial_metrics_by_type();
+    assert_eq!(by_type.len(), 2);
+    assert_eq!(by_type[&StructureIdentifier::Axon], axon);
+}
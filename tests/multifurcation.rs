@@ -0,0 +1,226 @@
+use compartment_rs::analysis::rall_ratios;
+use compartment_rs::channels::{ChannelType, Passive};
+use compartment_rs::export::Column;
+use compartment_rs::features::branches;
+use compartment_rs::solver::Simulation;
+use compartment_rs::validation::{HIGH_BRANCHING_DEGREE, branching_qc};
+use compartment_rs::{
+    Channel, Code, Compartments, ExportColumns, Morphometry, ReaderOptions, Skeleton,
+    swc_reader_from_bytes,
+};
+
+const RA: f64 = 100.0;
+const GM: f64 = 1e-4;
+
+/// Point soma, a 20 µm trunk of radius 1 to a branch point at x = 40, and
+/// `children` 20 µm branches of radius 0.5 fanning out from it in the yz
+/// plane
+fn fan(children: usize) -> Skeleton {
+    let mut swc = "1 1 0 0 0 5 -1\n2 3 20 0 0 1 1\n3 3 40 0 0 1 2\n".to_owned();
+    for k in 0..children {
+        let angle = std::f64::consts::TAU * k as f64 / children as f64;
+        swc.push_str(&format!(
+            "{} 3 40 {} {} 0.5 3\n",
+            k + 4,
+            20.0 * angle.cos(),
+            20.0 * angle.sin()
+        ));
+    }
+    swc_reader_from_bytes(swc.as_bytes(), &ReaderOptions::default()).unwrap()
+}
+
+/// The skeleton's node id at `x, y` in the z = 0 plane
+fn at(skeleton: &Skeleton, x: f64, y: f64) -> u64 {
+    skeleton
+        .nodes
+        .iter()
+        .find(|n| (n.x_pos - x).abs() < 1e-9 && (n.y_pos - y).abs() < 1e-9 && n.z_pos.abs() < 1e-9)
+        .unwrap()
+        .node_id
+}
+
+#[test]
+fn branches_end_at_every_multifurcation() {
+    let skeleton = fan(4);
+    let fork = at(&skeleton, 40.0, 0.0);
+    let branches = branches(&skeleton).unwrap();
+    assert_eq!(branches.len(), 5);
+    assert_eq!(*branches[0].path.last().unwrap(), fork);
+    assert!((branches[0].length - 40.0).abs() < 1e-9);
+    for branch in &branches[1..] {
+        assert_eq!(branch.path.len(), 2);
+        assert_eq!(branch.path[0], fork);
+        assert_eq!(branch.order, 1);
+        assert!((branch.length - 20.0).abs() < 1e-9);
+    }
+    assert_eq!(Morphometry::new(&skeleton.nodes).max_branching_degree(), 4);
+}
+
+#[test]
+fn strahler_order_follows_the_generalized_rule() {
+    let column = |skeleton: &Skeleton| match skeleton
+        .node_table(ExportColumns::STRAHLER_ORDER)
+        .unwrap()
+        .column("strahler_order")
+        .unwrap()
+    {
+        Column::Int(v) => v.clone(),
+        _ => unreachable!(),
+    };
+    // Four order 1 tips tie at the fork, which is order 2, as is everything
+    // proximal to it
+    let skeleton = fan(4);
+    let orders = column(&skeleton);
+    let fork = skeleton
+        .nodes
+        .iter()
+        .position(|n| n.node_id == at(&skeleton, 40.0, 0.0))
+        .unwrap();
+    assert_eq!(orders[fork], 2);
+    assert_eq!(orders.iter().filter(|&&o| o == 1).count(), 4);
+    assert_eq!(orders.iter().filter(|&&o| o == 2).count(), 3);
+
+    // One order 2 child among order 1 siblings does not raise the order
+    let swc = "1 1 0 0 0 5 -1\n2 3 20 0 0 1 1\n3 3 40 0 0 1 2\n\
+               4 3 40 20 0 0.5 3\n5 3 40 -20 0 0.5 3\n6 3 40 0 20 0.5 3\n\
+               7 3 60 0 0 0.5 3\n8 3 80 10 0 0.3 7\n9 3 80 -10 0 0.3 7\n";
+    let skeleton = swc_reader_from_bytes(swc.as_bytes(), &ReaderOptions::default()).unwrap();
+    let orders = column(&skeleton);
+    let fork = skeleton
+        .nodes
+        .iter()
+        .position(|n| n.node_id == at(&skeleton, 40.0, 0.0))
+        .unwrap();
+    assert_eq!(orders[fork], 2);
+    assert_eq!(*orders.iter().max().unwrap(), 2);
+}
+
+#[test]
+fn rall_reports_list_every_child() {
+    let skeleton = fan(4);
+    let reports = rall_ratios(&skeleton.nodes, &skeleton.parent_child_map);
+    assert_eq!(reports.len(), 1);
+    assert_eq!(reports[0].node_id, at(&skeleton, 40.0, 0.0));
+    assert_eq!(reports[0].child_diams, [1.0; 4]);
+    assert!((reports[0].ratio - 4.0 / 2f64.powf(1.5)).abs() < 1e-12);
+}
+
+/// Steady-state deviation from rest of every compartment for `current` nA
+/// into `idx`, solving the resistor network of axial and membrane
+/// conductances directly
+fn resistor_network(compartments: &Compartments, idx: usize, current: f64) -> Vec<f64> {
+    let n = compartments.components.len();
+    let mut g = vec![vec![0.0; n]; n];
+    for (i, c) in compartments.components.iter().enumerate().skip(1) {
+        g[i][i] += c.channel.conductance * c.membrane_area() * 10.0;
+    }
+    for (p, c, axial) in compartments.axial_conductances() {
+        let axial = axial * 1e5;
+        g[p][p] += axial;
+        g[c][c] += axial;
+        g[p][c] -= axial;
+        g[c][p] -= axial;
+    }
+    let mut rhs = vec![0.0; n];
+    rhs[idx] = current * 1e3;
+    // Gaussian elimination over compartments 1..n, the network being
+    // symmetric and diagonally dominant
+    for k in 1..n {
+        let pivot = g[k].clone();
+        for i in k + 1..n {
+            let factor = g[i][k] / pivot[k];
+            for (x, p) in g[i][k..].iter_mut().zip(&pivot[k..]) {
+                *x -= factor * p;
+            }
+            rhs[i] -= factor * rhs[k];
+        }
+    }
+    let mut u = vec![0.0; n];
+    for i in (1..n).rev() {
+        let tail: f64 = (i + 1..n).map(|j| g[i][j] * u[j]).sum();
+        u[i] = (rhs[i] - tail) / g[i][i];
+    }
+    u
+}
+
+#[test]
+fn passive_steady_state_divides_across_all_children() {
+    let mut compartments = Compartments::from_skeleton(fan(4));
+    let fork = compartments
+        .components
+        .iter()
+        .position(|c| c.children_idxs.len() == 4)
+        .unwrap();
+    for c in compartments.components.iter_mut() {
+        let mut channel = Channel::default();
+        channel.channel_type = ChannelType::Passive(Passive::default());
+        channel.resistance = RA;
+        channel.capacitance = 1.0;
+        channel.conductance = GM;
+        c.set_channel(channel);
+    }
+    let e = Passive::default().e;
+    let current = 0.05;
+    let dt = 0.1;
+    let steps = 3000;
+    let mut simulation = Simulation::new(&compartments, dt).unwrap();
+    for idx in 1..compartments.components.len() {
+        simulation.set_voltage(idx, e).unwrap();
+    }
+    let result = simulation
+        .run(steps, &[(fork, vec![current; steps])])
+        .unwrap();
+
+    let expected = resistor_network(&compartments, fork, current);
+    let children = &compartments.components[fork].children_idxs;
+    assert_eq!(children.len(), 4);
+    for (idx, expected) in expected.iter().enumerate().skip(1) {
+        let v = result.voltages[idx][steps] - e;
+        assert!(
+            (v - expected).abs() < 1e-6,
+            "compartment {}: {} vs {}",
+            idx,
+            v,
+            expected
+        );
+    }
+    // Identical children share the current equally
+    let first = result.voltages[children[0] as usize][steps];
+    for &c in children {
+        assert!((result.voltages[c as usize][steps] - first).abs() < 1e-9);
+    }
+}
+
+#[test]
+fn high_branching_degree_is_flagged_at_the_threshold() {
+    let report = branching_qc(&fan(4), HIGH_BRANCHING_DEGREE);
+    assert!(report.passed(), "{}", report.summary());
+    assert_eq!(report.checked, 1);
+
+    let skeleton = fan(5);
+    let report = branching_qc(&skeleton, HIGH_BRANCHING_DEGREE);
+    assert_eq!(report.warnings.len(), 1);
+    assert_eq!(report.warnings[0].code, Code::HighBranchingDegree);
+    assert_eq!(report.warnings[0].node_id, at(&skeleton, 40.0, 0.0));
+    assert!(
+        report
+            .summary()
+            .starts_with("FAIL branching_degree: 1 of 1")
+    );
+
+    assert!(!branching_qc(&fan(4), 4).passed());
+
+    // Many stems off the soma are normal and not looked at
+    let stems: String = (0..6)
+        .map(|k| format!("{} 3 {} {} 0 1 1\n", k + 2, 10.0 * k as f64, 10.0))
+        .collect();
+    let soma = swc_reader_from_bytes(
+        format!("1 1 0 0 0 5 -1\n{}", stems).as_bytes(),
+        &ReaderOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(Morphometry::new(&soma.nodes).max_branching_degree(), 6);
+    let report = branching_qc(&soma, 2);
+    assert!(report.passed());
+    assert_eq!(report.checked, 0);
+}
@@ -0,0 +1,194 @@
+use std::sync::Arc;
+
+use compartment_rs::channels::{ChannelType, Dynamics, HodgkinHuxley};
+use compartment_rs::network::{LeakJitter, Network};
+use compartment_rs::units::{MicroFaradPerCm2, OhmCm, SiemensPerCm2};
+use compartment_rs::{Channel, Compartments, ReaderOptions, swc_reader_from_bytes};
+
+/// Point soma with `branches` straight dendrites of `per_branch` 2 µm
+/// segments each
+fn cell(branches: usize, per_branch: usize) -> Compartments {
+    let mut swc = "1 1 0 0 0 5 -1\n".to_owned();
+    let mut id = 1;
+    for b in 0..branches {
+        let angle = b as f64;
+        let mut parent = 1;
+        for k in 1..=per_branch {
+            id += 1;
+            let r = 2.0 * k as f64;
+            swc.push_str(&format!(
+                "{} 3 {} {} 0 0.5 {}\n",
+                id,
+                r * angle.cos(),
+                r * angle.sin(),
+                parent
+            ));
+            parent = id;
+        }
+    }
+    let skeleton = swc_reader_from_bytes(swc.as_bytes(), &ReaderOptions::default()).unwrap();
+    let mut compartments = Compartments::from_skeleton(skeleton);
+    for c in compartments.components.iter_mut() {
+        c.set_channel(Channel::passive(
+            OhmCm::new(100.0).unwrap(),
+            MicroFaradPerCm2::new(1.0).unwrap(),
+            SiemensPerCm2::new(1e-4).unwrap(),
+        ));
+    }
+    compartments
+}
+
+fn hh(compartments: &mut Compartments) {
+    for c in compartments.components.iter_mut() {
+        c.channel.channel_type = ChannelType::HodgkinHuxley(HodgkinHuxley::new());
+    }
+}
+
+#[test]
+fn clones_share_the_geometry() {
+    let template = cell(4, 1250);
+    assert_eq!(template.components.len(), 5002);
+    let mut network = Network::new();
+    let handles = network.add_cells(&template, 100);
+    assert_eq!(network.len(), 100);
+    assert!(
+        network
+            .cell(handles[0])
+            .shares_morphology_with(network.cell(handles[99]))
+    );
+
+    let deep_copies = 100 * template.accounted_bytes();
+    let clones = network.accounted_bytes();
+    assert!(
+        clones * 3 < deep_copies,
+        "{} bytes for clones, {} for copies",
+        clones,
+        deep_copies
+    );
+    // What each clone adds is its mechanisms and little else
+    let per_cell = network.cell(handles[0]).accounted_bytes();
+    assert!(clones < template.accounted_bytes() + 100 * per_cell + 1024);
+    assert!(per_cell < template.accounted_bytes() / 2);
+}
+
+#[test]
+fn clones_have_their_own_mechanisms() {
+    let mut template = cell(2, 10);
+    hh(&mut template);
+    let shared = Arc::new(template);
+    let mut a = shared.clone_shallow();
+    let b = shared.clone_shallow();
+    if let ChannelType::HodgkinHuxley(hh) = &mut a.channels[3].channel_type {
+        hh.gnabar = 0.5;
+    }
+    let gnabar = |channel: &Channel| match &channel.channel_type {
+        ChannelType::HodgkinHuxley(hh) => hh.gnabar,
+        _ => panic!("Not HH"),
+    };
+    assert_eq!(gnabar(&a.channels[3]), 0.5);
+    assert_eq!(gnabar(&b.channels[3]), 0.12);
+    assert_eq!(gnabar(&a.morphology().components[3].channel), 0.12);
+
+    // Cells move between threads and networks alike
+    let handle = std::thread::spawn(move || a.channels.len());
+    assert_eq!(handle.join().unwrap(), 22);
+    let mut network = Network::new();
+    network.add_cells(&shared, 2);
+    let mut other = Network::new();
+    other.add_cells(&shared, 3);
+    let moved = network.merge(other);
+    assert_eq!(
+        moved.iter().map(|h| h.index()).collect::<Vec<_>>(),
+        [2, 3, 4]
+    );
+    assert_eq!(network.len(), 5);
+}
+
+#[test]
+fn jittered_populations_follow_the_seed() {
+    let template = cell(2, 10);
+    let leaks = |seed| {
+        let mut network = Network::new();
+        let handles = network
+            .add_cells_jittered(
+                &template,
+                20,
+                LeakJitter {
+                    fraction: 0.1,
+                    seed,
+                },
+            )
+            .unwrap();
+        handles
+            .iter()
+            .map(|&h| network.cell(h).channels[5].conductance)
+            .collect::<Vec<_>>()
+    };
+    let first = leaks(7);
+    assert_eq!(first, leaks(7));
+    assert_ne!(first, leaks(8));
+    assert!(first.iter().all(|&g| (0.9e-4..=1.1e-4).contains(&g)));
+    // Cells differ from each other, compartments within a cell do not
+    assert!(first.windows(2).any(|w| w[0] != w[1]));
+
+    let mut network = Network::new();
+    let h = network
+        .add_cells_jittered(
+            &template,
+            1,
+            LeakJitter {
+                fraction: 0.1,
+                seed: 7,
+            },
+        )
+        .unwrap()[0];
+    let channels = &network.cell(h).channels;
+    assert!(
+        channels[1..]
+            .iter()
+            .all(|c| c.conductance == channels[1].conductance)
+    );
+    assert!(
+        network
+            .add_cells_jittered(
+                &template,
+                1,
+                LeakJitter {
+                    fraction: 1.5,
+                    seed: 0
+                }
+            )
+            .is_err()
+    );
+}
+
+#[test]
+fn identical_clones_give_identical_traces() {
+    let mut template = cell(2, 20);
+    hh(&mut template);
+    let mut network = Network::new();
+    let handles = network.add_cells(&template, 2);
+    let steps = 400;
+    let stimulus = [(1, vec![0.5; steps])];
+    let traces: Vec<_> = handles
+        .iter()
+        .map(|&h| {
+            network
+                .cell(h)
+                .simulation(0.025)
+                .unwrap()
+                .run(steps, &stimulus)
+                .unwrap()
+                .voltages
+        })
+        .collect();
+    assert_eq!(traces[0], traces[1]);
+    assert!(traces[0][1].iter().any(|&v| v > 0.0));
+
+    // And the same as simulating the template itself
+    let direct = compartment_rs::solver::Simulation::new(&template, 0.025)
+        .unwrap()
+        .run(steps, &stimulus)
+        .unwrap();
+    assert_eq!(direct.voltages, traces[0]);
+}
@@ -0,0 +1,267 @@
+use compartment_rs::manifest::Manifest;
+use compartment_rs::objectives::{
+    Objective, ObjectiveError, ObjectiveOptions, Side, WeightedObjective, feature_error,
+    frequency_error, rms_error, spike_count_error, spike_distance,
+};
+use compartment_rs::solver::SimulationResult;
+
+const REST: f64 = -65.0;
+const DT: f64 = 0.25;
+/// Samples of 20 ms at `DT`
+const SAMPLES: usize = 81;
+
+/// A triangle rising from 0 at `start` to `height` 1 ms later and back
+/// over the next ms
+fn triangle(t: f64, start: f64, height: f64) -> f64 {
+    height * (1.0 - (t - start - 1.0).abs()).max(0.0)
+}
+
+/// At rest but for a spike of `amplitude` from each onset, followed by an
+/// AHP of `ahp`, each a triangle 2 ms wide
+fn trace(
+    dt: f64,
+    samples: usize,
+    onsets: &[f64],
+    amplitude: f64,
+    ahp: f64,
+) -> (Vec<f64>, Vec<f64>) {
+    let times: Vec<f64> = (0..samples).map(|k| k as f64 * dt).collect();
+    let values = times
+        .iter()
+        .map(|&t| {
+            REST + onsets
+                .iter()
+                .map(|&o| triangle(t, o, amplitude) - triangle(t, o + 2.0, ahp))
+                .sum::<f64>()
+        })
+        .collect();
+    (times, values)
+}
+
+/// A run that recorded `values` as the soma voltage
+fn result(values: Vec<f64>) -> SimulationResult {
+    SimulationResult {
+        dt: DT,
+        voltages: vec![Vec::new(), values],
+        head_voltages: Vec::new(),
+        energy: None,
+        traces: Vec::new(),
+        axial_currents: Vec::new(),
+        snippets: Vec::new(),
+        manifest: Manifest::capture("serial", false),
+        stream: None,
+    }
+}
+
+fn scores(model: &SimulationResult, target: (&[f64], &[f64])) -> [f64; 5] {
+    let options = ObjectiveOptions::default();
+    [
+        Objective::Rms,
+        Objective::SpikeDistance,
+        Objective::SpikeCount,
+        Objective::Frequency,
+        Objective::Features,
+    ]
+    .map(|o| o.score(model, "comp[1].v", target, &options).unwrap())
+}
+
+fn close(actual: f64, expected: f64) {
+    assert!(
+        (actual - expected).abs() < 1e-9,
+        "{} against {}",
+        actual,
+        expected
+    );
+}
+
+#[test]
+fn identical_traces_score_zero() {
+    let (times, values) = trace(DT, SAMPLES, &[5.0, 12.0], 100.0, 10.0);
+    let model = result(values.clone());
+    assert_eq!(scores(&model, (&times, &values)), [0.0; 5]);
+    let features = feature_error(&model, "comp[1].v", (&times, &values), &Default::default())
+        .unwrap()
+        .model;
+    assert_eq!(features.spikes, 2);
+    close(features.amplitude, 100.0);
+    close(features.half_width, 1.0);
+    close(features.ahp_depth, 10.0);
+
+    // The same shape sampled twice as finely over half the time: the probe
+    // is interpolated onto it, over the 10 ms both cover
+    let (times, values) = trace(DT / 2.0, 81, &[5.0], 100.0, 10.0);
+    assert_eq!(scores(&model, (&times, &values)), [0.0; 5]);
+    let rms = rms_error(&model, "comp[1].v", (&times, &values)).unwrap();
+    assert_eq!(rms.samples, 81);
+}
+
+#[test]
+fn a_spike_shifted_by_two_ms() {
+    let (times, target) = trace(DT, SAMPLES, &[7.0], 100.0, 10.0);
+    let model = result(trace(DT, SAMPLES, &[5.0], 100.0, 10.0).1);
+    let target = (times.as_slice(), target.as_slice());
+
+    // Samples of a 2 ms triangle of height h square to h^2 / 16 (2 (1 + 4
+    // + 9) + 16) = 2.75 h^2. The model's spike alone from 5 to 7 ms gives
+    // 27500, its AHP under the target's spike from 7 to 9 ms a triangle of
+    // 110, 33275, and the target's AHP alone 275.
+    let rms = rms_error(&model, "comp[1].v", target).unwrap();
+    close(rms.score, (61050.0f64 / 81.0).sqrt());
+    assert_eq!(rms.samples, SAMPLES);
+    close(rms.max_error, 110.0);
+    close(rms.max_error_time, 8.0);
+
+    // Moving the one spike by 2 ms at 0.1 per ms
+    let distance = spike_distance(&model, "comp[1].v", target, &Default::default()).unwrap();
+    close(distance.score, 0.2);
+    assert_eq!((distance.shifted, distance.model_spikes), (1, 1));
+
+    let [_, _, count, frequency, features] = scores(&model, target);
+    assert_eq!([count, frequency, features], [0.0; 3]);
+}
+
+#[test]
+fn a_spike_ten_percent_larger() {
+    let (times, target) = trace(DT, SAMPLES, &[5.0], 100.0, 10.0);
+    let model = result(trace(DT, SAMPLES, &[5.0], 110.0, 11.0).1);
+    let target = (times.as_slice(), target.as_slice());
+
+    // A tenth of the spike and the AHP, 0.01 (27500 + 275)
+    let rms = rms_error(&model, "comp[1].v", target).unwrap();
+    close(rms.score, (277.75f64 / 81.0).sqrt());
+
+    // Both cross 0 mV on the way up, 65 mV above rest, the target 0.65 ms
+    // after onset and the model 65 / 110 ms
+    let distance = spike_distance(&model, "comp[1].v", target, &Default::default()).unwrap();
+    close(distance.score, 0.1 * (0.65 - 65.0 / 110.0));
+
+    // 10 mV more amplitude, the same width at half of it, 1 mV more AHP
+    let features = feature_error(&model, "comp[1].v", target, &Default::default()).unwrap();
+    close(features.score, 11.0);
+    close(features.model.amplitude, 110.0);
+    close(features.model.half_width, features.target.half_width);
+    close(features.model.ahp_depth, 11.0);
+}
+
+#[test]
+fn the_weighted_combination_is_the_weighted_sum() {
+    let (times, target) = trace(DT, SAMPLES, &[7.0], 90.0, 5.0);
+    let model = result(trace(DT, SAMPLES, &[5.0, 14.0], 100.0, 10.0).1);
+    let target = (times.as_slice(), target.as_slice());
+    let weights = [0.5, 2.0, 1.0, 0.1, 3.0];
+    let objectives = [
+        Objective::Rms,
+        Objective::SpikeDistance,
+        Objective::SpikeCount,
+        Objective::Frequency,
+        Objective::Features,
+    ];
+    let mut weighted = WeightedObjective::default();
+    for (&o, &w) in objectives.iter().zip(&weights) {
+        weighted = weighted.with(o, w).unwrap();
+    }
+    let combined = weighted.evaluate(&model, "comp[1].v", target).unwrap();
+    let expected: f64 = scores(&model, target)
+        .iter()
+        .zip(&weights)
+        .map(|(s, w)| s * w)
+        .sum();
+    close(combined.score, expected);
+    assert_eq!(combined.terms.len(), 5);
+    assert!(combined.terms.iter().all(|&(_, _, s)| s > 0.0));
+
+    assert_eq!(
+        WeightedObjective::default()
+            .with(Objective::Rms, -1.0)
+            .unwrap_err(),
+        ObjectiveError::BadWeight { weight: -1.0 }
+    );
+}
+
+#[test]
+fn a_silent_target_against_a_spiking_model() {
+    let (times, target) = trace(DT, SAMPLES, &[], 100.0, 10.0);
+    let model = result(trace(DT, SAMPLES, &[5.0], 100.0, 10.0).1);
+    let target = (times.as_slice(), target.as_slice());
+    let options = ObjectiveOptions::default();
+    // Removing the model's spike costs 1
+    let distance = spike_distance(&model, "comp[1].v", target, &options).unwrap();
+    assert_eq!((distance.score, distance.target_spikes), (1.0, 0));
+    assert_eq!(
+        spike_count_error(&model, "comp[1].v", target, &options)
+            .unwrap()
+            .score,
+        1.0
+    );
+    // One spike in 20 ms
+    close(
+        frequency_error(&model, "comp[1].v", target, &options)
+            .unwrap()
+            .score,
+        50.0,
+    );
+    assert_eq!(
+        feature_error(&model, "comp[1].v", target, &options).unwrap_err(),
+        ObjectiveError::NoSpikes { side: Side::Target }
+    );
+}
+
+#[test]
+fn bad_traces_are_typed_errors() {
+    let (times, mut values) = trace(DT, SAMPLES, &[5.0], 100.0, 10.0);
+    let model = result(values.clone());
+    let rms = |target: (&[f64], &[f64])| rms_error(&model, "comp[1].v", target).unwrap_err();
+
+    assert_eq!(
+        rms((&times[..10], &values)),
+        ObjectiveError::LengthMismatch {
+            times: 10,
+            values: SAMPLES
+        }
+    );
+    let late: Vec<f64> = times.iter().map(|t| t + 30.0).collect();
+    assert!(matches!(
+        rms((&late, &values)),
+        ObjectiveError::NoOverlap { .. }
+    ));
+    let backwards: Vec<f64> = times.iter().rev().copied().collect();
+    assert!(matches!(
+        rms((&backwards, &values)),
+        ObjectiveError::BadTrace {
+            side: Side::Target,
+            ..
+        }
+    ));
+    assert_eq!(
+        rms_error(&model, "comp[2].v", (&times, &values)).unwrap_err(),
+        ObjectiveError::NoProbe {
+            probe: "comp[2].v".to_owned()
+        }
+    );
+
+    values[3] = f64::NAN;
+    for objective in [
+        Objective::Rms,
+        Objective::SpikeDistance,
+        Objective::Features,
+    ] {
+        assert_eq!(
+            objective
+                .score(&model, "comp[1].v", (&times, &values), &Default::default())
+                .unwrap_err(),
+            ObjectiveError::NotFinite {
+                side: Side::Target,
+                sample: 3
+            }
+        );
+    }
+    let broken = result(values.clone());
+    let (times, values) = trace(DT, SAMPLES, &[5.0], 100.0, 10.0);
+    assert_eq!(
+        rms_error(&broken, "comp[1].v", (&times, &values)).unwrap_err(),
+        ObjectiveError::NotFinite {
+            side: Side::Model,
+            sample: 3
+        }
+    );
+}
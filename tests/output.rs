@@ -0,0 +1,156 @@
+use std::path::PathBuf;
+
+use compartment_rs::channels::{ChannelType, HodgkinHuxley};
+use compartment_rs::output::{OutputFormat, OutputSink};
+use compartment_rs::recording::{AroundEvents, Overlap, TriggeredProbe};
+use compartment_rs::solver::{SimError, Simulation, SimulationResult};
+use compartment_rs::{Channel, Compartments, ReaderOptions, swc_reader_from_bytes};
+
+const DT: f64 = 0.025;
+const STEPS: usize = 2000;
+
+/// A point soma and an HH cable of five 20 µm compartments, 2 µm across
+fn cell() -> Compartments {
+    let mut swc = String::from("1 1 0 0 0 5 -1\n");
+    for k in 2..=6 {
+        swc.push_str(&format!("{} 3 {} 0 0 1 {}\n", k, 20 * (k - 1), k - 1));
+    }
+    let skeleton = swc_reader_from_bytes(swc.as_bytes(), &ReaderOptions::default()).unwrap();
+    let mut compartments = Compartments::from_skeleton(skeleton);
+    for c in compartments.components.iter_mut() {
+        let mut channel = Channel::default();
+        channel.channel_type = ChannelType::HodgkinHuxley(HodgkinHuxley::default());
+        channel.resistance = 100.0;
+        channel.capacitance = 1.0;
+        c.set_channel(channel);
+    }
+    compartments
+}
+
+fn temp(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("{}-{}.trace", name, std::process::id()))
+}
+
+/// 1 ms pulses every 10 ms into compartment 2, with every kind of
+/// recording, into `sink`
+fn run(sink: OutputSink) -> SimulationResult {
+    let pulses: Vec<f64> = (0..STEPS)
+        .map(|s| if s % 400 < 40 { 0.3 } else { 0.0 })
+        .collect();
+    let mut simulation = Simulation::new(&cell(), DT)
+        .unwrap()
+        .with_output(sink)
+        .unwrap();
+    simulation.record("comp[4].hh.m").unwrap();
+    simulation.record("comp[3].i_membrane").unwrap();
+    simulation.add_axial_current_probe(2, 3).unwrap();
+    simulation
+        .record_around_events(TriggeredProbe {
+            path: "comp[6].v".to_owned(),
+            source: 2,
+            threshold: -20.0,
+            window: AroundEvents {
+                pre_ms: 1.0,
+                post_ms: 3.0,
+                overlap: Overlap::Separate,
+            },
+        })
+        .unwrap();
+    simulation.run(STEPS, &[(2, pulses)]).unwrap()
+}
+
+fn file(name: &str, chunk_steps: usize, format: OutputFormat) -> OutputSink {
+    OutputSink::File {
+        path: temp(name),
+        chunk_steps,
+        format,
+    }
+}
+
+#[test]
+fn a_streamed_run_loads_back_as_it_ran_in_memory() {
+    let memory = run(OutputSink::Memory);
+    let streamed = run(file("identical", 128, OutputFormat::F64));
+    let trace = streamed.stream.as_ref().unwrap();
+    assert_eq!(trace.samples(), STEPS + 1);
+    assert!(streamed.voltages.iter().all(Vec::is_empty));
+    assert!(streamed.traces.iter().all(|(_, t)| t.is_empty()));
+
+    let loaded = streamed.load().unwrap();
+    assert!(loaded.stream.is_none());
+    assert_eq!(loaded.voltages, memory.voltages);
+    assert_eq!(loaded.head_voltages, memory.head_voltages);
+    assert_eq!(loaded.traces, memory.traces);
+    assert_eq!(loaded.axial_currents, memory.axial_currents);
+    // The spike detector ran on the fly all the same
+    assert!(memory.snippets[0].1.len() >= 2);
+    assert_eq!(streamed.snippets, memory.snippets);
+    assert_eq!(loaded.samples(), memory.samples());
+
+    // Single precision keeps every value to f32
+    let single = run(file("single", 500, OutputFormat::F32)).load().unwrap();
+    for (a, b) in single.voltages.iter().zip(&memory.voltages) {
+        assert!(a.iter().zip(b).all(|(x, y)| *x == *y as f32 as f64));
+    }
+    std::fs::remove_file(temp("identical")).unwrap();
+    std::fs::remove_file(temp("single")).unwrap();
+}
+
+#[test]
+fn a_slice_is_exactly_the_window() {
+    let memory = run(OutputSink::Memory);
+    let streamed = run(file("slice", 300, OutputFormat::F64));
+    // 12.5 ms to 20 ms, samples 500 to 800, across chunk boundaries
+    for result in [&memory, &streamed] {
+        let window = result.slice(12.5, 20.0).unwrap();
+        assert_eq!(window.samples(), 301);
+        assert_eq!(window.voltages[4], memory.voltages[4][500..=800]);
+        assert_eq!(window.traces[1].1, memory.traces[1].1[500..=800]);
+        assert_eq!(
+            window.axial_currents[0].1,
+            memory.axial_currents[0].1[500..=800]
+        );
+        // Between samples the window starts at the next one
+        let window = result.slice(12.51, 12.56).unwrap();
+        assert_eq!(window.voltages[2], memory.voltages[2][501..=502]);
+        // The end is cut to the run
+        let window = result.slice(49.0, 60.0).unwrap();
+        assert_eq!(window.voltages[2], memory.voltages[2][1960..]);
+        assert!(result.slice(20.0, 12.5).is_err());
+        assert!(result.slice(60.0, 70.0).is_err());
+        assert!(result.slice(12.51, 12.52).is_err());
+    }
+    std::fs::remove_file(temp("slice")).unwrap();
+}
+
+#[test]
+fn the_buffers_never_hold_more_than_a_chunk() {
+    for chunk_steps in [1, 64, 1000] {
+        let streamed = run(file("bounded", chunk_steps, OutputFormat::F64));
+        assert_eq!(
+            streamed.stream.as_ref().unwrap().peak_buffered(),
+            chunk_steps
+        );
+        assert_eq!(streamed.load().unwrap().voltages[2].len(), STEPS + 1);
+    }
+    std::fs::remove_file(temp("bounded")).unwrap();
+    assert!(
+        Simulation::new(&cell(), DT)
+            .unwrap()
+            .with_output(file("empty", 0, OutputFormat::F64))
+            .is_err()
+    );
+}
+
+#[test]
+fn a_diverging_run_still_fails_when_streamed() {
+    let mut simulation = Simulation::new(&cell(), DT)
+        .unwrap()
+        .with_output(file("diverged", 16, OutputFormat::F64))
+        .unwrap();
+    let error = simulation
+        .run(100, &[(2, vec![f64::INFINITY; 100])])
+        .unwrap_err();
+    assert!(matches!(error, SimError::Diverged { .. }), "{}", error);
+    std::fs::remove_file(temp("diverged")).unwrap();
+}
@@ -0,0 +1,162 @@
+use std::fmt::Write as _;
+
+use compartment_rs::channels::{ChannelType, Dynamics, HodgkinHuxley};
+use compartment_rs::solver::{Simulation, SolverOptions};
+use compartment_rs::spines::{Spine, SpineShape};
+use compartment_rs::stochastic::GatingMode;
+use compartment_rs::{Channel, Compartments, ReaderOptions, swc_reader_from_bytes};
+
+/// A Hodgkin-Huxley cell of `nodes` compartments, with an unbranched run
+/// hanging off every 50th node, or one unbranched cable
+fn active(nodes: usize, branched: bool) -> Compartments {
+    let mut swc = String::from("1 1 0 0 0 5 -1\n");
+    for id in 2..=nodes {
+        let parent = if branched && id % 50 == 2 {
+            id / 2
+        } else {
+            id - 1
+        };
+        writeln!(
+            swc,
+            "{} 3 {}.5 {}.25 {} 0.5 {}",
+            id,
+            id % 100,
+            id % 70,
+            id % 30,
+            parent
+        )
+        .unwrap();
+    }
+    let skeleton = swc_reader_from_bytes(swc.as_bytes(), &ReaderOptions::default()).unwrap();
+    let mut compartments = Compartments::from_skeleton(skeleton);
+    for c in compartments.components.iter_mut() {
+        let mut channel = Channel::default();
+        channel.channel_type = ChannelType::HodgkinHuxley(HodgkinHuxley::new());
+        channel.resistance = 100.0;
+        channel.capacitance = 1.0;
+        c.set_channel(channel);
+    }
+    compartments
+}
+
+/// Every voltage and clamp current of a run, as bits
+fn run(mut simulation: Simulation, clamped: Option<usize>) -> Vec<u64> {
+    let steps = 600;
+    let stimulus = (0..steps).map(|s| if s < 80 { 2.0 } else { 0.0 }).collect();
+    let result = simulation.run(steps, &[(2, stimulus)]).unwrap();
+    let mut bits: Vec<u64> = result
+        .voltages
+        .iter()
+        .flatten()
+        .map(|v| v.to_bits())
+        .collect();
+    bits.extend(simulation.head_voltages().iter().map(|v| v.to_bits()));
+    if let Some(i) = clamped {
+        bits.push(simulation.clamp_current(i).unwrap().to_bits());
+    }
+    bits
+}
+
+#[test]
+fn parallel_solves_match_the_serial_sweep_to_the_bit() {
+    let mut compartments = active(2000, true);
+    let n = compartments.components.len();
+    for idx in [1, 700, n - 1] {
+        compartments
+            .add_spine(Spine {
+                idx,
+                x: 0.5,
+                shape: SpineShape::default(),
+            })
+            .unwrap();
+    }
+    let build = |options: Option<SolverOptions>| {
+        let mut simulation = Simulation::new(&compartments, 0.025).unwrap();
+        if let Some(options) = options {
+            simulation = simulation.with_solver(options);
+        }
+        simulation.clamp(n - 2, Some(-50.0)).unwrap();
+        simulation.set_spine_synapse(1, 1.0, 0.0).unwrap();
+        simulation
+    };
+
+    let serial = build(None);
+    assert_eq!(serial.partition(), None);
+    let serial = run(serial, Some(n - 2));
+    let peak = f64::from_bits(
+        serial[..n * 601]
+            .iter()
+            .copied()
+            .max_by(|a, b| f64::from_bits(*a).total_cmp(&f64::from_bits(*b)))
+            .unwrap(),
+    );
+    assert!(peak > 0.0, "no spike, peak at {} mV", peak);
+
+    for threads in [2, 3, 4, 8] {
+        let parallel = build(Some(SolverOptions::parallel_tree(threads, 50)));
+        let partition = parallel.partition().unwrap();
+        assert!(partition.threads <= threads);
+        assert!(partition.subtrees > 1);
+        assert!(partition.largest_share < n - 1);
+        assert!(partition.junctions + partition.largest_share < n);
+        assert!(run(parallel, Some(n - 2)) == serial, "{} threads", threads);
+    }
+}
+
+#[test]
+fn runs_are_deterministic() {
+    let compartments = active(1000, true);
+    let once = || {
+        let simulation = Simulation::new(&compartments, 0.025)
+            .unwrap()
+            .with_solver(SolverOptions::parallel_tree(4, 20));
+        let partition = simulation.partition().unwrap();
+        (partition, run(simulation, None))
+    };
+    let first = once();
+    for _ in 0..3 {
+        assert!(once() == first);
+    }
+}
+
+#[test]
+fn an_unbranched_cable_is_one_subtree() {
+    let compartments = active(300, false);
+    let serial = run(Simulation::new(&compartments, 0.025).unwrap(), None);
+    for threads in [1, 4] {
+        let simulation = Simulation::new(&compartments, 0.025)
+            .unwrap()
+            .with_solver(SolverOptions::parallel_tree(threads, 10));
+        let partition = simulation.partition().unwrap();
+        assert_eq!(partition.subtrees, 1);
+        assert_eq!(partition.threads, 1);
+        assert_eq!(partition.junctions + partition.largest_share, 300);
+        assert!(run(simulation, None) == serial);
+    }
+}
+
+#[test]
+fn small_and_stochastic_cells_are_solved_serially() {
+    let mut compartments = active(200, true);
+    let simulation = Simulation::new(&compartments, 0.025).unwrap();
+    let options = SolverOptions::parallel_tree(4, 101);
+    assert_eq!(simulation.with_solver(options).partition(), None);
+    let simulation = Simulation::new(&compartments, 0.025).unwrap();
+    assert_eq!(
+        simulation
+            .with_solver(SolverOptions::parallel_tree(0, 10))
+            .partition()
+            .map(|p| p.subtrees > 0),
+        Some(true)
+    );
+
+    if let ChannelType::HodgkinHuxley(hh) = &mut compartments.components[5].channel.channel_type {
+        hh.gating = GatingMode::Binomial;
+    }
+    let simulation = Simulation::new(&compartments, 0.025)
+        .unwrap()
+        .with_solver(SolverOptions::parallel_tree(4, 10));
+    assert_eq!(simulation.partition(), None);
+    let serial = Simulation::new(&compartments, 0.025).unwrap();
+    assert!(run(simulation, None) == run(serial, None));
+}
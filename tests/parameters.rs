@@ -0,0 +1,55 @@
+use compartment_rs::{
+    Compartments, NodeFilter, ParamError, ReaderOptions, StructureIdentifier, swc_reader,
+};
+
+fn basic() -> Compartments {
+    let skeleton = swc_reader("data/basic.swc", &ReaderOptions::default()).unwrap();
+    Compartments::from_skeleton(skeleton)
+}
+
+#[test]
+fn derived_maps_follow_the_geometry() {
+    let compartments = basic();
+    let path = compartments.parameter_map("path_distance").unwrap();
+    let order = compartments.parameter_map("branch_order").unwrap();
+    assert!(path[0].is_nan());
+
+    // Compartment 13 is the basal tip at (35, -10): soma -> (5, 0) -> (15, 0)
+    // -> (25, -5) -> (35, -10), branching at the soma and at (15, 0)
+    let expected = 5.0 + 10.0 + 2.0 * 125f64.sqrt();
+    assert!((path[13] - expected).abs() < 1e-12);
+    assert_eq!(order[13], 2.0);
+    assert_eq!((path[1], order[1]), (0.0, 0.0));
+    assert_eq!((path[2], order[2]), (5.0, 1.0));
+}
+
+#[test]
+fn map_matches_applied_correction() {
+    let mut compartments = basic();
+    let apical = NodeFilter::new().structure(StructureIdentifier::ApicalDendrite);
+    compartments.apply_spine_correction(&apical, 2.0).unwrap();
+
+    let factors = compartments.parameter_map("area_factor").unwrap();
+    for (c, factor) in compartments.components.iter().zip(&factors).skip(1) {
+        let expected = if c.structure == StructureIdentifier::ApicalDendrite {
+            2.0
+        } else {
+            1.0
+        };
+        assert_eq!(*factor, expected);
+    }
+    for name in compartments.list_parameters() {
+        let map = compartments.parameter_map(&name).unwrap();
+        assert_eq!(map.len(), compartments.components.len());
+    }
+}
+
+#[test]
+fn unknown_name_suggests_close_matches() {
+    let err = basic().parameter_map("capacitence").unwrap_err();
+    let ParamError::Unknown { suggestions, .. } = &err else {
+        panic!("{:?}", err);
+    };
+    assert_eq!(suggestions, &vec!["capacitance".to_owned()]);
+    assert!(err.to_string().contains("did you mean capacitance"));
+}
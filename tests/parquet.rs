@@ -0,0 +1,250 @@
+#![cfg(feature = "parquet")]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use arrow_array::{Array, Float64Array, Int64Array, RecordBatch, StringArray};
+use compartment_rs::analysis::rall_ratios;
+use compartment_rs::bulk::{MorphometricColumns, ParquetOptions, schema};
+use compartment_rs::features::morphology_features;
+use compartment_rs::morphometry::Morphometry;
+use compartment_rs::qc::{QcRubric, score_skeleton};
+use compartment_rs::standardize::Dataset;
+use compartment_rs::{FeatureConfig, ReaderOptions, swc_reader};
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("parquet-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// `count` small cells, each a soma with two branches of its own lengths;
+/// every third lies flat in the xy plane
+fn cells(dir: &Path, count: usize) -> Vec<PathBuf> {
+    (0..count)
+        .map(|i| {
+            let (a, b) = (10.0 + i as f64, 5.0 + 2.0 * i as f64);
+            let z = if i % 3 == 0 { 0.0 } else { 4.0 };
+            let swc = format!(
+                "1 1 0 0 0 5 -1\n2 3 {a} 0 0 1 1\n3 3 {a} {b} {z} 0.8 2\n4 3 {a} -{b} 0 0.6 2\n5 3 -{b} 0 0 1 1\n"
+            );
+            let path = dir.join(format!("cell{:02}.swc", i));
+            fs::write(&path, swc).unwrap();
+            path
+        })
+        .collect()
+}
+
+/// The row group count of the file at `path`, and all its rows as one batch
+fn read_back(path: &Path) -> (usize, RecordBatch) {
+    let builder = ParquetRecordBatchReaderBuilder::try_new(fs::File::open(path).unwrap()).unwrap();
+    let row_groups = builder.metadata().num_row_groups();
+    let mut batches: Vec<RecordBatch> = builder
+        .with_batch_size(1024)
+        .build()
+        .unwrap()
+        .map(|b| b.unwrap())
+        .collect();
+    assert_eq!(batches.len(), 1);
+    (row_groups, batches.remove(0))
+}
+
+fn strings<'a>(batch: &'a RecordBatch, name: &str) -> &'a StringArray {
+    batch
+        .column_by_name(name)
+        .unwrap()
+        .as_any()
+        .downcast_ref()
+        .unwrap()
+}
+
+fn floats<'a>(batch: &'a RecordBatch, name: &str) -> &'a Float64Array {
+    batch
+        .column_by_name(name)
+        .unwrap()
+        .as_any()
+        .downcast_ref()
+        .unwrap()
+}
+
+#[test]
+fn rows_match_individually_computed_morphometrics() {
+    let dir = scratch("rows");
+    let dataset = Dataset::from_paths(cells(&dir, 7));
+    let output = dir.join("out/morphometrics.parquet");
+    let summary = dataset
+        .morphometrics_to_parquet(&output, MorphometricColumns::all())
+        .unwrap();
+    assert_eq!((summary.rows, summary.failed), (7, 0));
+
+    let (_, batch) = read_back(&output);
+    assert_eq!(batch.num_rows(), 7);
+    let config = FeatureConfig::default();
+    for (row, path) in dataset.paths.iter().enumerate() {
+        let skeleton = swc_reader(path, &ReaderOptions::default()).unwrap();
+        let morphometry = Morphometry::new(&skeleton.nodes);
+        let spatial = morphometry.spatial_metrics();
+        assert_eq!(strings(&batch, "source").value(row), path.to_string_lossy());
+        assert_eq!(
+            strings(&batch, "cell_id").value(row),
+            skeleton.cell_id().to_string()
+        );
+        assert!(strings(&batch, "error").is_null(row));
+        let node_count: &Int64Array = batch
+            .column_by_name("node_count")
+            .unwrap()
+            .as_any()
+            .downcast_ref()
+            .unwrap();
+        assert_eq!(node_count.value(row), 5);
+        let degree: &Int64Array = batch
+            .column_by_name("max_branching_degree")
+            .unwrap()
+            .as_any()
+            .downcast_ref()
+            .unwrap();
+        assert_eq!(degree.value(row), 2);
+        assert_eq!(
+            floats(&batch, "total_length").value(row),
+            morphometry.total_length()
+        );
+        assert_eq!(
+            floats(&batch, "total_area").value(row),
+            morphometry.total_area()
+        );
+        assert_eq!(
+            floats(&batch, "bbox_y").value(row),
+            spatial.bounding_box.extents()[1]
+        );
+        // Flat cells have no hull, and say so with nulls
+        let hull = floats(&batch, "hull_volume");
+        if row % 3 == 0 {
+            assert!(spatial.degenerate);
+            assert!(hull.is_null(row));
+            assert!(floats(&batch, "density").is_null(row));
+        } else {
+            assert_eq!(hull.value(row), spatial.hull_volume);
+            assert_eq!(floats(&batch, "density").value(row), spatial.density);
+        }
+        let rall = rall_ratios(&skeleton.nodes, &skeleton.parent_child_map);
+        assert_eq!(rall.len(), 1);
+        assert_eq!(
+            floats(&batch, "rall_ratio_median").value(row),
+            rall[0].ratio
+        );
+        assert_eq!(
+            floats(&batch, "qc_score").value(row),
+            score_skeleton(&skeleton, &QcRubric::default()).score
+        );
+        let features = morphology_features(&skeleton, &config).unwrap();
+        for (name, value) in features.names.iter().zip(features.values) {
+            assert_eq!(floats(&batch, name).value(row), value, "{}", name);
+        }
+    }
+}
+
+#[test]
+fn failed_files_are_rows_with_nulls_and_an_error() {
+    let dir = scratch("failed");
+    let mut paths = cells(&dir, 2);
+    let broken = dir.join("broken.swc");
+    fs::write(&broken, "1 1 0 0 0 5 -1\n2 3 oops 0 0 1 1\n").unwrap();
+    let contour = dir.join("contour.asc");
+    fs::write(&contour, "(\"CellBody\")\n").unwrap();
+    paths.insert(1, broken);
+    paths.push(contour);
+    paths.push(dir.join("missing.swc"));
+    let output = dir.join("morphometrics.parquet");
+    let summary = Dataset::from_paths(&paths)
+        .morphometrics_to_parquet(&output, MorphometricColumns::all())
+        .unwrap();
+    assert_eq!((summary.rows, summary.failed), (5, 3));
+
+    let (_, batch) = read_back(&output);
+    let error = strings(&batch, "error");
+    for row in [1, 3, 4] {
+        assert!(!error.value(row).is_empty());
+        assert!(strings(&batch, "cell_id").is_null(row));
+        assert!(batch.column_by_name("node_count").unwrap().is_null(row));
+        assert!(floats(&batch, "total_length").is_null(row));
+        assert!(floats(&batch, "bbox_x").is_null(row));
+    }
+    assert!(error.value(3).contains(".asc"), "{}", error.value(3));
+    assert!(error.value(4).contains("Could not read"));
+    for row in [0, 2] {
+        assert!(error.is_null(row));
+        assert!(!floats(&batch, "total_length").is_null(row));
+    }
+    assert_eq!(
+        strings(&batch, "source").value(1),
+        paths[1].to_string_lossy()
+    );
+}
+
+#[test]
+fn row_groups_bound_the_rows_held_in_memory() {
+    let dir = scratch("streaming");
+    let dataset = Dataset::from_paths(cells(&dir, 23));
+    let output = dir.join("morphometrics.parquet");
+    let options = ParquetOptions {
+        row_group_size: 5,
+        threads: 3,
+        ..ParquetOptions::default()
+    };
+    let summary = dataset
+        .morphometrics_to_parquet_with(&output, MorphometricColumns::all(), &options)
+        .unwrap();
+    assert_eq!(summary.rows, 23);
+    assert_eq!(summary.row_groups, 5);
+    assert!(
+        (1..=5).contains(&summary.peak_buffered_rows),
+        "{:?}",
+        summary
+    );
+
+    let (row_groups, batch) = read_back(&output);
+    assert_eq!(row_groups, 5);
+    assert_eq!(batch.num_rows(), 23);
+    // Order follows the dataset, whichever thread measured a cell
+    for (row, path) in dataset.paths.iter().enumerate() {
+        assert_eq!(strings(&batch, "source").value(row), path.to_string_lossy());
+    }
+}
+
+#[test]
+fn selected_columns_make_the_schema() {
+    let dir = scratch("columns");
+    let dataset = Dataset::from_paths(cells(&dir, 2));
+    let output = dir.join("morphometrics.parquet");
+    let columns = MorphometricColumns::NODE_COUNT | MorphometricColumns::HULL;
+    dataset.morphometrics_to_parquet(&output, columns).unwrap();
+
+    let (_, batch) = read_back(&output);
+    let written = batch.schema();
+    let names: Vec<&str> = written.fields().iter().map(|f| f.name().as_str()).collect();
+    assert_eq!(
+        names,
+        [
+            "source",
+            "cell_id",
+            "error",
+            "node_count",
+            "hull_volume",
+            "hull_area"
+        ]
+    );
+    assert_eq!(*written, schema(columns, &FeatureConfig::default()));
+    assert!(!written.field(0).is_nullable());
+    assert!(written.fields()[1..].iter().all(|f| f.is_nullable()));
+
+    let features = schema(MorphometricColumns::FEATURES, &FeatureConfig::default());
+    let names: Vec<&str> = features
+        .fields()
+        .iter()
+        .map(|f| f.name().as_str())
+        .collect();
+    assert_eq!(names[3], "branch_length<10");
+    assert_eq!(names.len(), 3 + 7 + 7 + 6 + 7);
+}
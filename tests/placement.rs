@@ -0,0 +1,251 @@
+use compartment_rs::channels::{ChannelType, Dynamics};
+use compartment_rs::discretize::Discretization;
+use compartment_rs::filter::NodeFilter;
+use compartment_rs::placement::{DensitySpec, Placement, expected_counts, sample_synapses};
+use compartment_rs::protocols::{RepeatMode, RepeatedRun, repeat};
+use compartment_rs::stimulus::Stimulus;
+use compartment_rs::{Channel, Compartments, ReaderOptions, StructureIdentifier, swc_reader};
+
+/// `data/basic.swc`, passive, split finely so counts spread over many
+/// compartments
+fn model() -> Compartments {
+    let skeleton = swc_reader("data/basic.swc", &ReaderOptions::default()).unwrap();
+    let mut compartments = Compartments::from_skeleton(skeleton);
+    for c in compartments.components.iter_mut().skip(1) {
+        let mut channel = Channel::default();
+        channel.channel_type = ChannelType::Passive(Dynamics::new());
+        channel.resistance = 150.0;
+        channel.capacitance = 1.0;
+        channel.conductance = 3e-5;
+        c.set_channel(channel);
+    }
+    let discretization = Discretization::compute(&compartments, 1000.0, 0.01).unwrap();
+    compartments.build_with(&discretization).unwrap()
+}
+
+fn dendrites() -> NodeFilter {
+    NodeFilter::new()
+        .structure(StructureIdentifier::BasalDendrite)
+        .structure(StructureIdentifier::ApicalDendrite)
+}
+
+fn counts(compartments: &Compartments, placements: &[Placement]) -> Vec<usize> {
+    let mut counts = vec![0; compartments.components.len()];
+    for p in placements {
+        counts[p.idx] += 1;
+    }
+    counts
+}
+
+fn mean_distance(compartments: &Compartments, placements: &[Placement]) -> f64 {
+    let dist = compartments.parameter_map("path_distance").unwrap();
+    placements.iter().map(|p| dist[p.idx]).sum::<f64>() / placements.len() as f64
+}
+
+#[test]
+fn an_exact_count_places_exactly_that_many() {
+    let compartments = model();
+    assert!(compartments.components.len() > 30);
+    let spec = DensitySpec::exactly(500);
+    let placements = sample_synapses(&compartments, &dendrites(), &spec, 3).unwrap();
+    assert_eq!(placements.len(), 500);
+    let expected = expected_counts(&compartments, &dendrites(), &spec).unwrap();
+    assert!((expected.iter().sum::<f64>() - 500.0).abs() < 1e-9);
+    for p in &placements {
+        assert!((0.0..1.0).contains(&p.x));
+        assert!((0.0..std::f64::consts::TAU).contains(&p.azimuth));
+    }
+    assert!(
+        placements
+            .windows(2)
+            .all(|w| (w[0].idx, w[0].x) <= (w[1].idx, w[1].x))
+    );
+    assert!(
+        sample_synapses(&compartments, &dendrites(), &DensitySpec::exactly(0), 3)
+            .unwrap()
+            .is_empty()
+    );
+}
+
+#[test]
+fn a_density_places_about_area_times_density() {
+    let compartments = model();
+    let spec = DensitySpec::per_area(0.5);
+    let expected: f64 = expected_counts(&compartments, &dendrites(), &spec)
+        .unwrap()
+        .iter()
+        .sum();
+    let area: f64 = compartments.components[1..]
+        .iter()
+        .filter(|c| {
+            c.structure != StructureIdentifier::Soma && c.structure != StructureIdentifier::Axon
+        })
+        .map(|c| c.membrane_area())
+        .sum();
+    assert!((expected - 0.5 * area).abs() < 1e-9 * expected);
+    for seed in 0..5 {
+        let n = sample_synapses(&compartments, &dendrites(), &spec, seed)
+            .unwrap()
+            .len() as f64;
+        // Poisson, so within four standard deviations
+        assert!(
+            (n - expected).abs() < 4.0 * expected.sqrt(),
+            "{} placed, {} expected",
+            n,
+            expected
+        );
+    }
+    assert!(sample_synapses(&compartments, &dendrites(), &DensitySpec::per_area(-1.0), 0).is_err());
+}
+
+#[test]
+fn uniform_counts_follow_membrane_area() {
+    let compartments = model();
+    let n = 20_000;
+    let placements = sample_synapses(
+        &compartments,
+        &NodeFilter::new(),
+        &DensitySpec::exactly(n),
+        11,
+    )
+    .unwrap();
+    let counts = counts(&compartments, &placements);
+    let total_area: f64 = compartments.components[1..]
+        .iter()
+        .map(|c| c.membrane_area())
+        .sum();
+    let mut chi_squared = 0.0;
+    let mut cells = 0;
+    for (idx, c) in compartments.components.iter().enumerate().skip(1) {
+        let expected = n as f64 * c.membrane_area() / total_area;
+        if expected == 0.0 {
+            assert_eq!(counts[idx], 0);
+            continue;
+        }
+        chi_squared += (counts[idx] as f64 - expected).powi(2) / expected;
+        cells += 1;
+    }
+    // Mean df, standard deviation sqrt(2 df); five of those is far out
+    let df = (cells - 1) as f64;
+    assert!(
+        chi_squared < df + 5.0 * (2.0 * df).sqrt(),
+        "chi² {} over {} degrees of freedom",
+        chi_squared,
+        df
+    );
+}
+
+#[test]
+fn a_distance_dependent_density_shifts_synapses_distally() {
+    let compartments = model();
+    let dist = compartments.parameter_map("path_distance").unwrap();
+    let uniform = DensitySpec::exactly(5000);
+    let distal = DensitySpec::exactly(5000)
+        .relative_density("dist / 10")
+        .unwrap();
+
+    // As designed: the expected mean distance is weighted by distance
+    let designed = |spec: &DensitySpec| {
+        let expected = expected_counts(&compartments, &dendrites(), spec).unwrap();
+        expected
+            .iter()
+            .zip(&dist)
+            .skip(1)
+            .map(|(e, d)| e * d)
+            .sum::<f64>()
+            / 5000.0
+    };
+    let sampled = |spec: &DensitySpec| {
+        mean_distance(
+            &compartments,
+            &sample_synapses(&compartments, &dendrites(), spec, 5).unwrap(),
+        )
+    };
+    assert!(designed(&distal) > designed(&uniform) * 1.1);
+    for spec in [&uniform, &distal] {
+        let (d, s) = (designed(spec), sampled(spec));
+        assert!((s - d).abs() < 0.02 * d, "sampled {} designed {}", s, d);
+    }
+
+    let negative = DensitySpec::exactly(10)
+        .relative_density("1 - dist")
+        .unwrap();
+    let error = sample_synapses(&compartments, &dendrites(), &negative, 0).unwrap_err();
+    assert!(error.contains("is negative"), "{}", error);
+    assert!(DensitySpec::exactly(10).relative_density("dist *").is_err());
+}
+
+#[test]
+fn a_seed_reproduces_its_placements() {
+    let compartments = model();
+    for spec in [
+        DensitySpec::exactly(300),
+        DensitySpec::per_area(0.2)
+            .relative_density("exp(dist/200)")
+            .unwrap(),
+    ] {
+        let a = sample_synapses(&compartments, &dendrites(), &spec, 42).unwrap();
+        let b = sample_synapses(&compartments, &dendrites(), &spec, 42).unwrap();
+        let c = sample_synapses(&compartments, &dendrites(), &spec, 43).unwrap();
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+    }
+}
+
+#[test]
+fn filtered_out_compartments_get_no_synapses() {
+    let compartments = model();
+    let apical = NodeFilter::new().structure(StructureIdentifier::ApicalDendrite);
+    for spec in [DensitySpec::exactly(1000), DensitySpec::per_area(1.0)] {
+        let expected = expected_counts(&compartments, &apical, &spec).unwrap();
+        let placements = sample_synapses(&compartments, &apical, &spec, 7).unwrap();
+        assert!(!placements.is_empty());
+        for (idx, c) in compartments.components.iter().enumerate() {
+            if c.structure != StructureIdentifier::ApicalDendrite || idx == 0 {
+                assert_eq!(expected[idx], 0.0);
+                assert!(placements.iter().all(|p| p.idx != idx));
+            }
+        }
+    }
+    let nowhere = NodeFilter::new().structure(StructureIdentifier::Custom);
+    let error = sample_synapses(&compartments, &nowhere, &DensitySpec::exactly(5), 0).unwrap_err();
+    assert!(error.contains("Nowhere to place 5 synapses"), "{}", error);
+}
+
+#[test]
+fn placements_drive_a_run_as_poisson_inputs() {
+    let compartments = model();
+    let placements =
+        sample_synapses(&compartments, &dendrites(), &DensitySpec::exactly(20), 1).unwrap();
+    let kernel = Stimulus::Alpha {
+        onset: 0.0,
+        tau: 0.5,
+        amplitude: 0.01,
+    };
+    let inputs =
+        compartment_rs::placement::poisson_inputs(&placements, &kernel, 50.0, 0.0, 10.0, 9);
+    assert_eq!(inputs.len(), 20);
+    for (input, p) in inputs.iter().zip(&placements) {
+        assert_eq!(input.idx, p.idx);
+        assert_eq!(input.kernel, kernel);
+    }
+    assert_ne!(inputs[0].source, inputs[1].source);
+
+    let run = RepeatedRun {
+        steps: 400,
+        inputs,
+        threads: 1,
+        ..RepeatedRun::default()
+    };
+    let quiet = RepeatedRun {
+        inputs: Vec::new(),
+        ..run.clone()
+    };
+    let driven = repeat(&compartments, &run, 1, RepeatMode::KeepAll).unwrap();
+    let at_rest = repeat(&compartments, &quiet, 1, RepeatMode::KeepAll).unwrap();
+    let idx = placements[0].idx;
+    assert_ne!(
+        driven.trials[0].voltages[idx],
+        at_rest.trials[0].voltages[idx]
+    );
+}
@@ -0,0 +1,312 @@
+use compartment_rs::channels::{ChannelType, HodgkinHuxley};
+use compartment_rs::plasticity::{Plasticity, StdpParams, StdpState};
+use compartment_rs::solver::Simulation;
+use compartment_rs::spikes::{SpikeTrainSource, sub_seed};
+use compartment_rs::synapses::Synapse;
+use compartment_rs::{Channel, Compartments, ReaderOptions, swc_reader_from_bytes};
+
+fn params() -> StdpParams {
+    StdpParams {
+        a_plus: 0.01,
+        a_minus: 0.012,
+        tau_plus: 16.8,
+        tau_minus: 33.7,
+        w_min: 0.0,
+        w_max: 1.0,
+    }
+}
+
+/// Pairs `dt` ms apart (post minus pre), far enough apart that the traces
+/// of one pair have vanished by the next
+fn pairing(dt: f64, pairs: usize) -> (Vec<f64>, Vec<f64>) {
+    let onsets: Vec<f64> = (0..pairs).map(|k| 100.0 + 5000.0 * k as f64).collect();
+    let pre = onsets.iter().map(|t| t + (-dt).max(0.0)).collect();
+    let post = onsets.iter().map(|t| t + dt.max(0.0)).collect();
+    (pre, post)
+}
+
+#[test]
+fn pre_before_post_potentiates_by_closed_form() {
+    let p = params();
+    let mut state = StdpState::new(p, 0.5).unwrap();
+    let (pre, post) = pairing(10.0, 5);
+    let trajectory = state.run(&pre, &post);
+    assert_eq!(trajectory.len(), 10);
+    let expected = p.a_plus * (-10.0 / p.tau_plus).exp();
+    let mut w = 0.5;
+    for pair in trajectory.chunks(2) {
+        // The pre spike finds the post trace long gone
+        assert!((pair[0].1 - w).abs() < 1e-15);
+        assert!((pair[1].1 - w - expected).abs() < 1e-15);
+        w = pair[1].1;
+    }
+    assert!((state.weight - (0.5 + 5.0 * expected)).abs() < 1e-14);
+}
+
+#[test]
+fn post_before_pre_depresses_by_closed_form() {
+    let p = params();
+    let mut state = StdpState::new(p, 0.5).unwrap();
+    let (pre, post) = pairing(-10.0, 5);
+    state.run(&pre, &post);
+    let expected = p.a_minus * (-10.0 / p.tau_minus).exp();
+    assert!((state.weight - (0.5 - 5.0 * expected)).abs() < 1e-14);
+}
+
+#[test]
+fn weights_stay_within_bounds() {
+    let p = StdpParams {
+        a_plus: 0.3,
+        a_minus: 0.3,
+        ..params()
+    };
+    let mut up = StdpState::new(p, 0.9).unwrap();
+    let (pre, post) = pairing(1.0, 10);
+    assert!(up.run(&pre, &post).iter().all(|&(_, w)| w <= 1.0));
+    assert_eq!(up.weight, 1.0);
+
+    let mut down = StdpState::new(p, 0.1).unwrap();
+    let (pre, post) = pairing(-1.0, 10);
+    assert!(down.run(&pre, &post).iter().all(|&(_, w)| w >= 0.0));
+    assert_eq!(down.weight, 0.0);
+
+    assert_eq!(StdpState::new(p, 7.0).unwrap().weight, 1.0);
+    let reversed = StdpParams {
+        w_min: 1.0,
+        w_max: 0.0,
+        ..p
+    };
+    assert!(StdpState::new(reversed, 0.5).is_err());
+    let frozen = StdpParams { tau_plus: 0.0, ..p };
+    assert!(StdpState::new(frozen, 0.5).is_err());
+}
+
+/// Reference that decays every trace at every step, with spikes on the grid
+fn dense(p: StdpParams, w0: f64, pre: &[usize], post: &[usize], steps: usize, dt: f64) -> f64 {
+    let (decay_plus, decay_minus) = ((-dt / p.tau_plus).exp(), (-dt / p.tau_minus).exp());
+    let (mut w, mut x, mut y) = (w0, 0.0, 0.0);
+    for k in 0..steps {
+        x *= decay_plus;
+        y *= decay_minus;
+        for _ in pre.iter().filter(|&&s| s == k) {
+            w = (w - p.a_minus * y).clamp(p.w_min, p.w_max);
+            x += 1.0;
+        }
+        for _ in post.iter().filter(|&&s| s == k) {
+            w = (w + p.a_plus * x).clamp(p.w_min, p.w_max);
+            y += 1.0;
+        }
+    }
+    w
+}
+
+#[test]
+fn lazy_updates_match_dense_reference() {
+    let (dt, steps) = (0.1, 20_000);
+    let on_grid = |seed: u64| -> Vec<usize> {
+        let source = SpikeTrainSource::Poisson {
+            rate_hz: 20.0,
+            start: 0.0,
+            stop: (steps - 1) as f64 * dt,
+            seed,
+        };
+        let mut grid: Vec<usize> = source
+            .events()
+            .unwrap()
+            .iter()
+            .map(|t| (t / dt).round() as usize)
+            .collect();
+        grid.dedup();
+        grid
+    };
+    // Three inputs onto each of two cells
+    let posts: Vec<Vec<usize>> = (0..2).map(|cell| on_grid(sub_seed(446, cell))).collect();
+    for (cell, post) in posts.iter().enumerate() {
+        for input in 0..3 {
+            let pre = on_grid(sub_seed(446, 100 + 3 * cell as u64 + input));
+            let times =
+                |grid: &[usize]| -> Vec<f64> { grid.iter().map(|&k| k as f64 * dt).collect() };
+            let mut lazy = StdpState::new(params(), 0.5).unwrap();
+            lazy.run(&times(&pre), &times(post));
+            let reference = dense(params(), 0.5, &pre, post, steps, dt);
+            assert!(
+                (lazy.weight - reference).abs() < 1e-9,
+                "{} vs {}",
+                lazy.weight,
+                reference
+            );
+        }
+    }
+}
+
+/// An HH cylinder 20 µm long and 10 µm across at index 2
+fn hh_cell() -> Compartments {
+    let skeleton = swc_reader_from_bytes(
+        b"1 1 0 0 0 5 -1\n2 3 20 0 0 5 1\n",
+        &ReaderOptions::default(),
+    )
+    .unwrap();
+    let mut compartments = Compartments::from_skeleton(skeleton);
+    for c in compartments.components.iter_mut() {
+        let mut channel = Channel::default();
+        channel.channel_type = ChannelType::HodgkinHuxley(HodgkinHuxley::default());
+        channel.resistance = 100.0;
+        channel.capacitance = 1.0;
+        c.set_channel(channel);
+    }
+    compartments
+}
+
+/// Steps at whose end compartment 2 crossed -20 mV upwards, as grid
+/// indices of the time they ended
+fn crossings(v: &[f64]) -> Vec<usize> {
+    (1..v.len())
+        .filter(|&s| v[s - 1] < -20.0 && v[s] >= -20.0)
+        .collect()
+}
+
+/// A plastic synapse that opens no conductance, with one presynaptic
+/// spike at `pre` ms, on a cell made to spike by a 1 ms pulse at `pulse`
+/// ms. Returns the weight trace, the postsynaptic spike time and the
+/// weight after the run.
+fn forced_pairing(pre: f64, pulse: f64, p: StdpParams, w0: f64) -> (Vec<f64>, f64, f64) {
+    let dt = 0.025;
+    let steps = 2000;
+    let mut simulation = Simulation::new(&hh_cell(), dt).unwrap();
+    let synapse = Synapse::new(2, 0.0, 0.0, 2.0, SpikeTrainSource::FromTimes(vec![pre]))
+        .with_plasticity(Plasticity::Stdp(p), w0);
+    let k = simulation.add_synapse(synapse).unwrap();
+    simulation.record(&format!("syn[{}].w", k)).unwrap();
+    let drive = (0..steps)
+        .map(|s| {
+            let t = s as f64 * dt;
+            if (pulse..pulse + 1.0).contains(&t) {
+                0.5
+            } else {
+                0.0
+            }
+        })
+        .collect();
+    let result = simulation.run(steps, &[(2, drive)]).unwrap();
+    let spikes = crossings(&result.voltages[2]);
+    assert_eq!(spikes.len(), 1);
+    let weight = simulation.synapse_weight(k).unwrap();
+    (result.traces[0].1.clone(), spikes[0] as f64 * dt, weight)
+}
+
+#[test]
+fn a_simulated_pairing_changes_the_weight_by_closed_form() {
+    let p = params();
+    // Pre 5 ms before the pulse, which fires the cell a little later
+    let (trace, post, w) = forced_pairing(10.0, 15.0, p, 0.5);
+    let dt_pair = post - 10.0;
+    assert!((5.0..10.0).contains(&dt_pair), "{}", dt_pair);
+    let expected = 0.5 + p.a_plus * (-dt_pair / p.tau_plus).exp();
+    assert!((w - expected).abs() < 1e-12, "{} against {}", w, expected);
+    // Flat until the postsynaptic spike, then at the new weight
+    let at = (post / 0.025).round() as usize;
+    assert!(trace[..at].iter().all(|&x| x == 0.5));
+    assert!(trace[at..].iter().all(|&x| x == w));
+
+    // Post before pre depresses
+    let (_, post, w) = forced_pairing(20.0, 5.0, p, 0.5);
+    let expected = 0.5 - p.a_minus * (-(20.0 - post) / p.tau_minus).exp();
+    assert!(w < 0.5);
+    assert!((w - expected).abs() < 1e-12, "{} against {}", w, expected);
+
+    // And the bounds hold
+    let strong = StdpParams {
+        a_plus: 0.5,
+        a_minus: 0.5,
+        ..p
+    };
+    assert_eq!(forced_pairing(10.0, 15.0, strong, 0.9).2, p.w_max);
+    assert_eq!(forced_pairing(20.0, 5.0, strong, 0.1).2, p.w_min);
+}
+
+#[test]
+fn simulated_lazy_updates_match_the_dense_reference() {
+    let (dt, steps) = (0.025, 20_000);
+    let p = StdpParams {
+        w_max: 2.0,
+        ..params()
+    };
+    let mut simulation = Simulation::new(&hh_cell(), dt).unwrap();
+    let mut inputs = Vec::new();
+    for input in 0..3 {
+        let source = SpikeTrainSource::Poisson {
+            rate_hz: 20.0,
+            start: 0.0,
+            stop: (steps - 1) as f64 * dt,
+            seed: sub_seed(446, input),
+        };
+        let mut grid: Vec<usize> = source
+            .events()
+            .unwrap()
+            .iter()
+            .map(|t| (t / dt).round() as usize)
+            .collect();
+        grid.dedup();
+        let times = grid.iter().map(|&k| k as f64 * dt).collect();
+        let synapse = Synapse::new(2, 15.0, 0.0, 2.0, SpikeTrainSource::FromTimes(times))
+            .with_plasticity(Plasticity::Stdp(p), 1.0);
+        let k = simulation.add_synapse(synapse).unwrap();
+        simulation.record(&format!("syn[{}].w", k)).unwrap();
+        inputs.push(grid);
+    }
+    let result = simulation.run(steps, &[]).unwrap();
+    let post = crossings(&result.voltages[2]);
+    assert!(post.len() > 10, "{} spikes", post.len());
+    for (k, pre) in inputs.iter().enumerate() {
+        // The reference takes pre first at a shared step, the simulation
+        // post, as it ended the step before
+        assert!(pre.iter().all(|s| !post.contains(s)));
+        let reference = dense(p, 1.0, pre, &post, steps + 1, dt);
+        let w = simulation.synapse_weight(k).unwrap();
+        assert!((w - reference).abs() < 1e-9, "{} against {}", w, reference);
+        assert_eq!(result.traces[k].1[steps], w);
+    }
+    // The weights moved, so did the conductances they scale
+    assert!(
+        inputs
+            .iter()
+            .enumerate()
+            .all(|(k, _)| simulation.synapse_weight(k) != Some(1.0))
+    );
+}
+
+#[test]
+fn synapses_are_checked_when_added() {
+    let mut simulation = Simulation::new(&hh_cell(), 0.025).unwrap();
+    let source = || SpikeTrainSource::FromTimes(vec![1.0]);
+    assert!(
+        simulation
+            .add_synapse(Synapse::new(3, 1.0, 0.0, 2.0, source()))
+            .is_err()
+    );
+    assert!(
+        simulation
+            .add_synapse(Synapse::new(2, -1.0, 0.0, 2.0, source()))
+            .is_err()
+    );
+    assert!(
+        simulation
+            .add_synapse(Synapse::new(2, 1.0, 0.0, 0.0, source()))
+            .is_err()
+    );
+    let reversed = StdpParams {
+        w_min: 1.0,
+        w_max: 0.0,
+        ..params()
+    };
+    let plastic =
+        Synapse::new(2, 1.0, 0.0, 2.0, source()).with_plasticity(Plasticity::Stdp(reversed), 0.5);
+    assert!(simulation.add_synapse(plastic).is_err());
+    assert!(simulation.get("syn[0].w").is_err());
+    assert_eq!(
+        simulation.add_synapse(Synapse::new(2, 1.0, 0.0, 2.0, source())),
+        Ok(0)
+    );
+    assert_eq!(simulation.get("syn[0].w"), Ok(1.0));
+    assert!(simulation.set("syn[0].w", 0.5).is_err());
+}
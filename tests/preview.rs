@@ -0,0 +1,84 @@
+use std::collections::HashSet;
+
+use compartment_rs::{ReaderOptions, Skeleton};
+
+/// Long unbranched runs with a side branch starting every 100 nodes
+fn generated_tree(n: i64) -> Skeleton {
+    let ids: Vec<i64> = (1..=n).collect();
+    let parents: Vec<i64> = ids
+        .iter()
+        .map(|&i| match i {
+            1 => -1,
+            i if i % 100 == 1 => i / 2,
+            i => i - 1,
+        })
+        .collect();
+    let xyz: Vec<[f64; 3]> = ids.iter().map(|&i| [i as f64, 0.0, 0.0]).collect();
+    Skeleton::from_arrays(
+        &ids,
+        &vec![3; n as usize],
+        &xyz,
+        &vec![1.0; n as usize],
+        &parents,
+        &ReaderOptions::default(),
+    )
+    .unwrap()
+}
+
+fn structural(skeleton: &Skeleton) -> HashSet<u64> {
+    skeleton
+        .nodes
+        .iter()
+        .map(|n| n.node_id)
+        .filter(|&id| id == 0 || skeleton.children_of(id).len() != 1)
+        .collect()
+}
+
+#[test]
+fn preview_fits_budget_and_keeps_structure() {
+    let skeleton = generated_tree(200_000);
+    let preview = skeleton.preview(5000).unwrap();
+
+    assert!(preview.skeleton.nodes.len() <= 5000);
+    assert!(preview.skeleton.validate_maps().is_ok());
+
+    let kept: HashSet<u64> = (0..preview.skeleton.nodes.len() as u64)
+        .map(|id| preview.original_id(id).unwrap())
+        .collect();
+    assert!(structural(&skeleton).is_subset(&kept));
+
+    // Every original node is accounted for exactly once
+    let covered: Vec<u64> = preview.origin.iter().flatten().copied().collect();
+    assert_eq!(covered.len(), skeleton.nodes.len());
+    assert_eq!(
+        covered.into_iter().collect::<HashSet<_>>().len(),
+        skeleton.nodes.len()
+    );
+}
+
+#[test]
+fn branch_maps_back_to_original_branch() {
+    let skeleton = generated_tree(20_000);
+    let preview = skeleton.preview(1000).unwrap();
+
+    let tip = preview
+        .skeleton
+        .nodes
+        .iter()
+        .map(|n| n.node_id)
+        .find(|&id| id > 0 && preview.skeleton.children_of(id).is_empty())
+        .unwrap();
+    // Everything distal to the branch point, which belongs to another branch
+    let preview_branch = preview.skeleton.branch_path(tip).unwrap();
+    let mapped = preview.to_original(&preview_branch[1..]);
+
+    let original_tip = preview.original_id(tip).unwrap();
+    let original_branch = skeleton.branch_path(original_tip).unwrap();
+    assert_eq!(mapped, original_branch[1..].to_vec());
+}
+
+#[test]
+fn budget_below_structure_is_an_error() {
+    let skeleton = generated_tree(20_000);
+    assert!(skeleton.preview(10).is_err());
+}
@@ -0,0 +1,264 @@
+use std::f64::consts::PI;
+use std::path::Path;
+
+use compartment_rs::analysis::impedance_profile;
+use compartment_rs::experiment::{Experiment, StimulusSpec, Waveform};
+use compartment_rs::protocols::{Protocol, available};
+use compartment_rs::solver::Simulation;
+use compartment_rs::units::{MicroFaradPerCm2, OhmCm, SiemensPerCm2};
+use compartment_rs::{Channel, Compartments, ReaderOptions, Stimulus, swc_reader_from_bytes};
+
+/// Point soma with one passive cylinder, 20 µm long and 2 µm thick, at
+/// index 2: an RC circuit with a time constant of 10 ms
+fn passive_cylinder() -> Compartments {
+    let skeleton = swc_reader_from_bytes(
+        b"1 1 0 0 0 5 -1\n2 3 20 0 0 1 1\n",
+        &ReaderOptions::default(),
+    )
+    .unwrap();
+    let mut compartments = Compartments::from_skeleton(skeleton);
+    for c in compartments.components.iter_mut() {
+        c.set_channel(Channel::passive(
+            OhmCm::new(100.0).unwrap(),
+            MicroFaradPerCm2::new(1.0).unwrap(),
+            SiemensPerCm2::new(1e-4).unwrap(),
+        ));
+    }
+    compartments
+}
+
+/// Times at which `values`, sampled every `dt` from `dt / 2`, change sign
+fn zero_crossings(values: &[f64], dt: f64) -> Vec<f64> {
+    values
+        .windows(2)
+        .enumerate()
+        .filter(|(_, w)| (w[0] < 0.0) != (w[1] < 0.0))
+        .map(|(k, w)| (k as f64 + 0.5 + w[0] / (w[0] - w[1])) * dt)
+        .collect()
+}
+
+#[test]
+fn the_chirp_sweeps_as_specified() {
+    let (f0, f1, duration): (f64, f64, f64) = (20.0, 100.0, 4000.0);
+    let protocol =
+        Protocol::from_spec("zap start=0 f0=20 f1=100 duration=4000 amplitude=1").unwrap();
+    let dt = 0.01;
+    let steps = (duration / dt) as usize;
+    let current = protocol.render(0, dt, steps).unwrap();
+    let crossings = zero_crossings(&current, dt);
+    // The sine starts at zero, in the middle of the first step
+    assert!(crossings.len() > 100);
+
+    // Half a period between crossings, at the frequency half way between
+    let frequency = |t: f64| f0 * (f1 / f0).powf(t / duration);
+    let measured: Vec<(f64, f64)> = crossings
+        .windows(2)
+        .map(|w| ((w[0] + w[1]) / 2.0, 1000.0 / (2.0 * (w[1] - w[0]))))
+        .collect();
+    for &(t, f) in &measured {
+        let expected = frequency(t);
+        assert!((f - expected).abs() < 0.01 * expected, "{} ms: {} Hz", t, f);
+    }
+    let (_, first) = measured[0];
+    let (_, last) = measured[measured.len() - 1];
+    assert!((first - f0).abs() < 0.02 * f0, "{}", first);
+    assert!((last - f1).abs() < 0.02 * f1, "{}", last);
+
+    // Nothing before or after the sweep
+    let late = Protocol::from_spec("zap start=100 duration=1000").unwrap();
+    let current = late.render(0, 0.1, 12000).unwrap();
+    assert!(current[..1000].iter().all(|&i| i == 0.0));
+    assert!(current[11000..].iter().all(|&i| i == 0.0));
+    assert!(current[1000..11000].iter().any(|&i| i != 0.0));
+}
+
+#[test]
+fn a_passive_compartment_has_the_rc_impedance() {
+    let compartments = passive_cylinder();
+    let c = &compartments.components[2];
+    // nS to MΩ, and µF/cm² over µm² to pF
+    let r = 1e3 / (c.membrane_conductance() * 10.0);
+    let capacitance = 1.0 * c.membrane_area() * 1e-2;
+    // MΩ pF is µs
+    let tau = r * capacitance * 1e-3;
+    assert!((tau - 10.0).abs() < 1e-6, "{}", tau);
+
+    let protocol =
+        Protocol::from_spec("zap start=200 duration=4000 f0=1 f1=100 amplitude=0.001").unwrap();
+    let dt = 0.025;
+    // Long enough after the sweep for the response to die down
+    let steps = (4400.0 / dt) as usize;
+    let stimulus = protocol.render(0, dt, steps).unwrap();
+    let result = Simulation::new(&compartments, dt)
+        .unwrap()
+        .run(steps, &[(2, stimulus)])
+        .unwrap();
+    let profile = impedance_profile(&result, 2, &protocol).unwrap();
+    assert!(profile.frequencies.len() > 100);
+    assert!(profile.frequencies[0] >= 1.0);
+    assert!(*profile.frequencies.last().unwrap() <= 100.0);
+
+    for ((&f, magnitude), phase) in profile
+        .frequencies
+        .iter()
+        .zip(profile.magnitudes())
+        .zip(profile.phases())
+    {
+        let omega_tau = 2.0 * PI * f * tau / 1000.0;
+        let expected = r / (1.0 + omega_tau * omega_tau).sqrt();
+        assert!(
+            (magnitude - expected).abs() < 0.01 * expected,
+            "{} Hz: {} vs {} MΩ",
+            f,
+            magnitude,
+            expected
+        );
+        assert!(
+            (phase + omega_tau.atan()).abs() < 0.01,
+            "{} Hz: {}",
+            f,
+            phase
+        );
+    }
+    // A low-pass filter peaks at the lowest frequency
+    assert_eq!(profile.resonance_frequency, profile.frequencies[0]);
+    assert_eq!(profile.q, 1.0);
+
+    // Only chirps, fully inside the run
+    let steps_protocol = Protocol::preset("rheobase_steps").unwrap();
+    assert!(impedance_profile(&result, 2, &steps_protocol).is_err());
+    let longer = Protocol::from_spec("zap start=20 duration=5000").unwrap();
+    assert!(impedance_profile(&result, 2, &longer).is_err());
+    assert!(impedance_profile(&result, 9, &protocol).is_err());
+}
+
+#[test]
+fn theta_burst_events_match_the_spec() {
+    let protocol = Protocol::from_spec(
+        "theta_burst start=50 bursts=3 spikes=4 spike_interval=10 burst_interval=200",
+    )
+    .unwrap();
+    assert_eq!(
+        protocol.events(),
+        [
+            50.0, 60.0, 70.0, 80.0, 250.0, 260.0, 270.0, 280.0, 450.0, 460.0, 470.0, 480.0
+        ]
+    );
+    let Protocol::ThetaBurst { tau, amplitude, .. } = protocol else {
+        panic!("a theta burst");
+    };
+    let kernel = Stimulus::Alpha {
+        onset: 0.0,
+        tau,
+        amplitude,
+    };
+    assert_eq!(
+        protocol.render(0, 0.025, 24000).unwrap(),
+        kernel
+            .render_train(&protocol.events(), 0.025, 600.0)
+            .unwrap()
+    );
+    // The defaults: ten bursts at 5 Hz of four events at 100 Hz
+    let events = Protocol::preset("theta_burst").unwrap().events();
+    assert_eq!(events.len(), 40);
+    assert_eq!(events[3] - events[0], 30.0);
+    assert_eq!(events[4] - events[0], 200.0);
+    assert!(Protocol::preset("zap").unwrap().events().is_empty());
+}
+
+#[test]
+fn specs_round_trip_to_identical_waveforms() {
+    let mut protocols: Vec<Protocol> = available().into_iter().map(|p| p.defaults).collect();
+    protocols.push(Protocol::from_spec("zap f0=0.3 f1=17.1 amplitude=0.0123").unwrap());
+    protocols
+        .push(Protocol::from_spec("rheobase_steps first=-0.1 increment=0.03 count=4").unwrap());
+    protocols.push(Protocol::from_spec("theta_burst tau=1.7 spikes=5 amplitude=0.1").unwrap());
+    let names: Vec<&str> = available().iter().map(|p| p.name).collect();
+    assert_eq!(names, ["rheobase_steps", "zap", "theta_burst"]);
+
+    for protocol in &protocols {
+        let spec = protocol.to_spec();
+        let back = Protocol::from_spec(&spec).unwrap();
+        assert_eq!(&back, protocol, "{}", spec);
+        for sweep in 0..protocol.sweeps() {
+            assert_eq!(
+                back.render(sweep, 0.1, 3000).unwrap(),
+                protocol.render(sweep, 0.1, 3000).unwrap()
+            );
+        }
+
+        // Through an experiment file, at the last sweep
+        let stimulus = StimulusSpec {
+            site: "dend[0](1)".to_owned(),
+            waveform: Waveform::Protocol {
+                protocol: protocol.clone(),
+                sweep: protocol.sweeps() - 1,
+            },
+        };
+        let text = format!(
+            "[morphology]\npath = \"basic.swc\"\n\n[simulation]\ndt = 0.1\nduration = 300\n\n{}",
+            stimulus.to_toml()
+        );
+        let data = Path::new(env!("CARGO_MANIFEST_DIR")).join("data");
+        let experiment = Experiment::from_toml(&text, &data).unwrap();
+        assert_eq!(experiment.stimuli, [stimulus]);
+        let prepared = experiment.build().unwrap();
+        assert_eq!(
+            prepared.stimuli[0].1,
+            protocol
+                .render(protocol.sweeps() - 1, 0.1, experiment.steps())
+                .unwrap()
+        );
+    }
+    // Every parameter goes into the spec
+    assert_eq!(
+        Protocol::preset("rheobase_steps").unwrap().to_spec(),
+        "rheobase_steps start=100.0 duration=500.0 first=0.05 increment=0.05 count=10"
+    );
+}
+
+#[test]
+fn bad_specs_are_errors() {
+    let error = Protocol::from_spec("zapp").unwrap_err();
+    assert!(
+        error.contains("rheobase_steps, zap, theta_burst"),
+        "{}",
+        error
+    );
+    let error = Protocol::from_spec("zap f2=3").unwrap_err();
+    assert!(error.contains("did you mean 'f0'"), "{}", error);
+    assert!(Protocol::from_spec("").is_err());
+    assert!(Protocol::from_spec("zap f0").is_err());
+    assert!(Protocol::from_spec("zap f0=fast").is_err());
+    assert!(Protocol::from_spec("zap f0=0").is_err());
+    assert!(Protocol::from_spec("zap start=-1").is_err());
+    assert!(Protocol::from_spec("zap duration=inf").is_err());
+    assert!(Protocol::from_spec("rheobase_steps count=2.5").is_err());
+    assert!(Protocol::from_spec("rheobase_steps count=0").is_err());
+    // Four events 60 ms apart do not fit in a 150 ms cycle
+    assert!(Protocol::from_spec("theta_burst spike_interval=60 burst_interval=150").is_err());
+    let steps = Protocol::preset("rheobase_steps").unwrap();
+    assert!(steps.render(10, 0.1, 100).is_err());
+    assert!(steps.render(0, 0.0, 100).is_err());
+
+    let text = r#"
+[morphology]
+path = "basic.swc"
+
+[[stimuli]]
+site = "soma[0](0.5)"
+kind = "protocol"
+spec = "zap f9=1"
+
+[[stimuli]]
+site = "soma[0](0.5)"
+kind = "protocol"
+spec = "rheobase_steps count=3"
+sweep = 3
+"#;
+    let data = Path::new(env!("CARGO_MANIFEST_DIR")).join("data");
+    let errors = Experiment::from_toml(text, data).unwrap_err();
+    let keys: Vec<&str> = errors.iter().map(|e| e.key.as_str()).collect();
+    assert_eq!(keys, ["stimuli[0].spec", "stimuli[1].sweep"]);
+    assert_eq!(errors[1].location.unwrap().line, 14);
+}
@@ -0,0 +1,290 @@
+use compartment_rs::channels::{ChannelType, Dynamics, HodgkinHuxley};
+use compartment_rs::protocols::{
+    RepeatMode, RepeatedRun, StepProtocol, TrainInput, isolate_current, repeat, voltage_step_family,
+};
+use compartment_rs::spikes::SpikeTrainSource;
+use compartment_rs::stochastic::GatingMode;
+use compartment_rs::{Channel, Compartments, ReaderOptions, Stimulus, swc_reader_from_bytes};
+
+/// Point soma with one 20 µm long, 10 µm thick HH cylinder at index 2
+fn hh_cylinder() -> Compartments {
+    let skeleton = swc_reader_from_bytes(
+        b"1 1 0 0 0 5 -1\n2 3 20 0 0 5 1\n",
+        &ReaderOptions::default(),
+    )
+    .unwrap();
+    let mut compartments = Compartments::from_skeleton(skeleton);
+    for c in compartments.components.iter_mut() {
+        let mut channel = Channel::default();
+        channel.channel_type = ChannelType::HodgkinHuxley(HodgkinHuxley::new());
+        channel.resistance = 100.0;
+        channel.capacitance = 1.0;
+        c.set_channel(channel);
+    }
+    compartments
+}
+
+fn protocol(steps: Vec<f64>) -> StepProtocol {
+    StepProtocol {
+        holding: -80.0,
+        steps,
+        durations: [5.0, 50.0, 5.0],
+        dt: 0.025,
+    }
+}
+
+/// Membrane area in µm² times mA/cm², in nA
+fn to_na(area: f64, density: f64) -> f64 {
+    density * area * 1e-2
+}
+
+#[test]
+fn isolated_potassium_matches_the_closed_form() {
+    let compartments = hh_cylinder();
+    let area = compartments.components[2].membrane_area();
+    let steps: Vec<f64> = (0..11).map(|k| -60.0 + 10.0 * k as f64).collect();
+    let isolated = isolate_current(&compartments, 2, &protocol(steps), "gkbar_hh").unwrap();
+    let hh = HodgkinHuxley::default();
+    for &(v, current) in &isolated.steady_state {
+        let n = HodgkinHuxley::steady_state(v)[2];
+        let expected = to_na(area, hh.gkbar * n.powi(4) * (v - hh.ek));
+        assert!(
+            ((current - expected) / expected).abs() < 0.02,
+            "{} mV: {} vs {}",
+            v,
+            current,
+            expected
+        );
+    }
+}
+
+#[test]
+fn sodium_peak_iv_has_its_negative_region() {
+    let compartments = hh_cylinder();
+    let steps: Vec<f64> = (0..14).map(|k| -60.0 + 10.0 * k as f64).collect();
+    let isolated = isolate_current(&compartments, 2, &protocol(steps), "gnabar_hh").unwrap();
+    let peak = |v: f64| {
+        isolated
+            .peak
+            .iter()
+            .find(|(level, _)| *level == v)
+            .unwrap()
+            .1
+    };
+    // Inward between activation and the reversal potential, outward above
+    for v in [-40.0, -20.0, 0.0, 20.0] {
+        assert!(peak(v) < 0.0, "{} mV: {}", v, peak(v));
+    }
+    assert!(peak(70.0) > 0.0);
+    let (v_min, _) = isolated
+        .peak
+        .iter()
+        .copied()
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .unwrap();
+    assert!((-40.0..=0.0).contains(&v_min));
+    // Sodium inactivates, so the current has all but gone by the end
+    let steady = isolated
+        .steady_state
+        .iter()
+        .find(|(v, _)| *v == -20.0)
+        .unwrap()
+        .1;
+    assert!(steady.abs() < 0.1 * peak(-20.0).abs());
+}
+
+#[test]
+fn clamp_current_includes_the_capacitive_transient() {
+    let compartments = hh_cylinder();
+    let c = &compartments.components[2];
+    let p = protocol(vec![-40.0]);
+    let trace = &voltage_step_family(&compartments, 2, &p).unwrap()[0];
+    assert_eq!(trace.current.len(), 200 + 2000 + 200 + 1);
+    // 1 µF/cm² charged by 40 mV within one 0.025 ms step
+    let transient = to_na(c.membrane_area(), 1.0 * 40.0 / 0.025 * 1e-3);
+    let onset = trace.current[201];
+    assert!((onset - transient).abs() < 0.01 * transient);
+    // Back to holding, with the sodium and potassium tails on top
+    let offset = trace.current[2201];
+    assert!((offset + transient).abs() < 0.1 * transient);
+    // The capacitive part cancels out of a subtracted current
+    let isolated = isolate_current(&compartments, 2, &p, "gl_hh").unwrap();
+    let hh = HodgkinHuxley::default();
+    let leak = to_na(c.membrane_area(), hh.gl * (-40.0 - hh.el));
+    assert!((isolated.traces[0].current[201] - leak).abs() < 1e-9);
+}
+
+#[test]
+fn unknown_conductances_and_sites_error() {
+    let compartments = hh_cylinder();
+    let p = protocol(vec![0.0]);
+    let err = isolate_current(&compartments, 2, &p, "gcabar").unwrap_err();
+    assert!(err.contains("gnabar_hh, gkbar_hh, gl_hh"), "{}", err);
+    assert!(isolate_current(&compartments, 9, &p, "gkbar_hh").is_err());
+    // The point soma has no membrane
+    assert!(voltage_step_family(&compartments, 1, &p).is_err());
+}
+
+/// Point soma with a thin 20 µm HH cylinder at index 2, small enough for
+/// channel noise to show
+fn thin_cylinder(gating: GatingMode) -> Compartments {
+    let skeleton = swc_reader_from_bytes(
+        b"1 1 0 0 0 5 -1\n2 3 20 0 0 0.5 1\n",
+        &ReaderOptions::default(),
+    )
+    .unwrap();
+    let mut compartments = Compartments::from_skeleton(skeleton);
+    for c in compartments.components.iter_mut() {
+        let mut channel = Channel::default();
+        channel.channel_type = ChannelType::HodgkinHuxley(HodgkinHuxley {
+            gating,
+            ..HodgkinHuxley::new()
+        });
+        channel.resistance = 100.0;
+        channel.capacitance = 1.0;
+        c.set_channel(channel);
+    }
+    compartments
+}
+
+/// 20 ms of synaptic input into the cylinder from `source`
+fn driven(source: SpikeTrainSource, seed: u64) -> RepeatedRun {
+    RepeatedRun {
+        steps: 800,
+        inputs: vec![TrainInput {
+            idx: 2,
+            source,
+            kernel: Stimulus::Alpha {
+                onset: 0.0,
+                tau: 0.5,
+                amplitude: 0.02,
+            },
+        }],
+        seed,
+        threads: 3,
+        ..RepeatedRun::default()
+    }
+}
+
+fn poisson() -> SpikeTrainSource {
+    SpikeTrainSource::Poisson {
+        rate_hz: 200.0,
+        start: 0.0,
+        stop: 20.0,
+        seed: 0,
+    }
+}
+
+#[test]
+fn trials_without_randomness_are_identical() {
+    let compartments = thin_cylinder(GatingMode::Deterministic);
+    let regular = SpikeTrainSource::Regular {
+        rate_hz: 100.0,
+        start: 1.0,
+        stop: 20.0,
+    };
+    let result = repeat(&compartments, &driven(regular, 1), 5, RepeatMode::KeepAll).unwrap();
+    assert_eq!(result.trials.len(), 5);
+    for trial in &result.trials[1..] {
+        assert_eq!(trial.voltages, result.trials[0].voltages);
+    }
+    for variance in &result.summary.variance {
+        assert!(variance.iter().all(|&v| v == 0.0));
+    }
+    assert_eq!(result.summary.mean[2], result.trials[0].voltages[2]);
+    assert!(!result.rasters[2][0].is_empty());
+    assert!(result.rasters[2].iter().all(|r| *r == result.rasters[2][0]));
+}
+
+#[test]
+fn online_summary_matches_the_kept_trials() {
+    let compartments = thin_cylinder(GatingMode::Binomial);
+    let run = driven(poisson(), 2);
+    let trials = 7;
+    let kept = repeat(&compartments, &run, trials, RepeatMode::KeepAll).unwrap();
+    assert!(kept.trials[0].voltages[2] != kept.trials[1].voltages[2]);
+    for idx in 1..compartments.components.len() {
+        for s in 0..=run.steps {
+            let values: Vec<f64> = kept.trials.iter().map(|t| t.voltages[idx][s]).collect();
+            let mean = values.iter().sum::<f64>() / trials as f64;
+            let variance =
+                values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (trials - 1) as f64;
+            assert!((kept.summary.mean[idx][s] - mean).abs() < 1e-12);
+            assert!((kept.summary.variance[idx][s] - variance).abs() < 1e-12);
+        }
+    }
+
+    // The summary does not depend on whether trials are kept
+    let summary = repeat(
+        &compartments,
+        &run,
+        trials,
+        RepeatMode::SummaryOnly {
+            percentiles: Vec::new(),
+        },
+    )
+    .unwrap();
+    assert_eq!(summary.summary, kept.summary);
+    assert_eq!(summary.rasters, kept.rasters);
+}
+
+#[test]
+fn the_master_seed_reproduces_every_trial() {
+    let compartments = thin_cylinder(GatingMode::Binomial);
+    let first = repeat(&compartments, &driven(poisson(), 5), 4, RepeatMode::KeepAll).unwrap();
+    let again = repeat(&compartments, &driven(poisson(), 5), 4, RepeatMode::KeepAll).unwrap();
+    assert_eq!(first.seeds, again.seeds);
+    assert_eq!(first.trials.len(), 4);
+    for (a, b) in first.trials.iter().zip(&again.trials) {
+        assert_eq!(a.voltages, b.voltages);
+    }
+    assert_eq!(first.rasters, again.rasters);
+
+    // One thread or several, the same trials
+    let serial = RepeatedRun {
+        threads: 1,
+        ..driven(poisson(), 5)
+    };
+    let serial = repeat(&compartments, &serial, 4, RepeatMode::KeepAll).unwrap();
+    assert_eq!(serial.summary, first.summary);
+
+    let other = repeat(&compartments, &driven(poisson(), 6), 4, RepeatMode::KeepAll).unwrap();
+    assert_ne!(other.trials[0].voltages, first.trials[0].voltages);
+}
+
+#[test]
+fn summary_only_keeps_no_trials() {
+    let compartments = thin_cylinder(GatingMode::Binomial);
+    let run = RepeatedRun {
+        threads: 2,
+        record: vec![2],
+        ..driven(poisson(), 3)
+    };
+    let result = repeat(
+        &compartments,
+        &run,
+        12,
+        RepeatMode::SummaryOnly {
+            percentiles: vec![10.0, 50.0, 90.0],
+        },
+    )
+    .unwrap();
+    assert!(result.trials.is_empty());
+    assert!(result.peak_held_trials <= 2, "{}", result.peak_held_trials);
+    assert_eq!(result.summary.trials, 12);
+    assert_eq!(result.rasters[2].len(), 12);
+    assert!(result.rasters[1].is_empty() && result.summary.mean[1].is_empty());
+    let [(_, low), (_, median), (_, high)] = &result.summary.percentiles[..] else {
+        panic!("three percentiles");
+    };
+    for s in 0..=run.steps {
+        assert!(low[2][s] <= median[2][s] && median[2][s] <= high[2][s]);
+    }
+
+    let limited = RepeatedRun {
+        max_kept_bytes: Some(1000),
+        ..run.clone()
+    };
+    assert!(repeat(&compartments, &limited, 12, RepeatMode::KeepAll).is_err());
+    assert!(repeat(&compartments, &run, 0, RepeatMode::KeepAll).is_err());
+}
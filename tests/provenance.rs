@@ -0,0 +1,35 @@
+use compartment_rs::{Compartments, ReaderOptions, swc_reader};
+
+fn basic() -> Compartments {
+    Compartments::from_skeleton(swc_reader("data/basic.swc", &ReaderOptions::default()).unwrap())
+}
+
+#[test]
+fn every_node_is_covered_exactly_once() {
+    let compartments = basic();
+    assert_eq!(compartments.provenance.len(), compartments.components.len());
+    assert!(compartments.provenance[0].is_empty());
+
+    let mut covered = [0.0; 15];
+    for spans in &compartments.provenance {
+        for span in spans {
+            assert!(span.from < span.to);
+            covered[span.node_id as usize] += span.to - span.from;
+        }
+    }
+    assert!(covered.iter().all(|&c| c == 1.0));
+}
+
+#[test]
+fn node_lookup_goes_both_ways() {
+    let compartments = basic();
+    for (idx, spans) in compartments.provenance.iter().enumerate().skip(1) {
+        let node = spans.last().unwrap().node_id;
+        let (found, fraction) = compartments.node_to_compartment(node).unwrap();
+        assert_eq!(found, idx);
+        // Nodes sit at the distal end of the segment built from them
+        assert_eq!(fraction, 1.0);
+    }
+    assert_eq!(compartments.node_to_compartment(7).unwrap().0, 8);
+    assert!(compartments.node_to_compartment(15).is_none());
+}
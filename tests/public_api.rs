@@ -0,0 +1,34 @@
+//! Exercises the crate purely through its public surface, so that anything
+//! here failing to compile means we broke the public API.
+
+use compartment_rs::{Compartments, ReaderOptions, StructureIdentifier, swc_reader};
+
+#[test]
+fn read_basic_swc_and_build_compartments() {
+    let skeleton = swc_reader("data/basic.swc", &ReaderOptions::default()).unwrap();
+    let nodes = skeleton.nodes.clone();
+
+    assert_eq!(nodes.len(), 15);
+    assert_eq!(nodes[0].structured_identifier, StructureIdentifier::Soma);
+    // The root points to itself after remapping
+    assert_eq!(nodes[0].parent_id, nodes[0].node_id);
+    // Zero-radius tip gets patched up
+    assert!(nodes.iter().all(|n| n.radius > 0.0));
+
+    let compartments = Compartments::from_skeleton(skeleton);
+    // One compartment per node, plus the dummy root
+    assert_eq!(compartments.components.len(), 16);
+    assert_eq!(compartments.components[1].diam, 10.0);
+}
+
+#[test]
+fn strict_mode_rejects_zero_radius() {
+    let swc = std::env::temp_dir().join("compartment_rs_strict.swc");
+    std::fs::write(&swc, "1 1 0 0 0 5 -1\n2 3 1 0 0 0 1\n3 6 2 0 0 1 2\n").unwrap();
+
+    let options = ReaderOptions {
+        strict: true,
+        ..Default::default()
+    };
+    assert!(swc_reader(&swc, &options).is_err());
+}
@@ -0,0 +1,44 @@
+import pathlib
+
+import compartment_rs as crs
+
+BASIC = pathlib.Path(__file__).parents[2] / "data" / "basic.swc"
+
+
+def test_rall_ratios_per_branch_point():
+    morphology = crs.Morphology(str(BASIC))
+    reports = morphology.rall_ratios()
+    assert len(reports) == 2
+    for report in reports:
+        assert set(report) == {"node_id", "type", "parent_diam", "child_diams", "ratio"}
+        assert len(report["child_diams"]) >= 2
+        expected = sum(d**1.5 for d in report["child_diams"]) / report["parent_diam"] ** 1.5
+        assert abs(report["ratio"] - expected) < 1e-9
+
+
+def _cable(path, kind, y):
+    path.write_text(
+        "1 1 0 0 0 1 -1\n"
+        f"2 {kind} 0 {y} 0 0.5 1\n"
+        f"3 {kind} 50 {y} 0 0.5 2\n"
+        f"4 {kind} 100 {y} 0 0.5 3\n"
+    )
+    return str(path)
+
+
+def test_appositions_between_parallel_cables(tmp_path):
+    axon = crs.Morphology(_cable(tmp_path / "a.swc", 2, 10))
+    dendrite = crs.Morphology(_cable(tmp_path / "b.swc", 3, 13))
+    assert crs.appositions(axon, dendrite, 2.5) == []
+    zones = crs.appositions(axon, dendrite, 3.5)
+    assert len(zones) == 1
+    assert abs(zones[0]["distance"] - 3.0) < 1e-9
+
+    pairs = crs.dataset_appositions(str(tmp_path), 3.5)
+    assert {(p["a"].endswith("a.swc"), p["count"]) for p in pairs} == {(True, 1), (False, 0)}
+    try:
+        crs.dataset_appositions(str(tmp_path), 3.5, max_pairs=1)
+    except ValueError as error:
+        assert "2 pairs" in str(error)
+    else:
+        raise AssertionError("expected ValueError")
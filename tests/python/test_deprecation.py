@@ -0,0 +1,78 @@
+import pathlib
+import warnings
+
+import compartment_rs as crs
+
+DATA = pathlib.Path(__file__).parents[2] / "data"
+EXPECTED = DATA / "golden" / "deprecations.txt"
+
+
+def copy_basic(directory):
+    (directory / "basic.swc").write_bytes((DATA / "basic.swc").read_bytes())
+
+
+def contents(directory):
+    return sorted((p.name, p.read_bytes()) for p in directory.iterdir())
+
+
+def test_deprecations_match_the_expectations_file():
+    expected = [
+        dict(zip(("name", "replacement", "since", "removal"), line.split("\t")))
+        for line in EXPECTED.read_text().splitlines()
+    ]
+    assert crs.deprecations() == expected
+
+
+def test_a_shim_warns_once_per_call_site(tmp_path):
+    copy_basic(tmp_path)
+    with warnings.catch_warnings(record=True) as caught:
+        warnings.simplefilter("always")
+        results = [crs.load_dataset(str(tmp_path)) for _ in range(3)]
+        crs.load_dataset(str(tmp_path))
+    deprecations = [w for w in caught if issubclass(w.category, DeprecationWarning)]
+    # Three calls from the comprehension's line, one from the next
+    assert len(deprecations) == 2
+    assert deprecations[1].lineno == deprecations[0].lineno + 1
+    assert all(w.filename == __file__ for w in deprecations)
+    assert all(len(r) == 1 for r in results)
+
+
+def test_the_warning_names_the_replacement(tmp_path):
+    copy_basic(tmp_path)
+    with warnings.catch_warnings(record=True) as caught:
+        warnings.simplefilter("always")
+        crs.load_dataset(str(tmp_path))
+    (warning,) = [w.message for w in caught]
+    assert isinstance(warning, crs.CompartmentDeprecationWarning)
+    assert isinstance(warning, DeprecationWarning)
+    assert warning.name == "compartment_rs.load_dataset"
+    assert warning.replacement == "compartment_rs.io.load_dataset"
+    assert warning.removal == "0.2.0"
+
+
+def test_shims_match_their_replacements(tmp_path):
+    source = tmp_path / "source"
+    source.mkdir()
+    copy_basic(source)
+    with warnings.catch_warnings():
+        warnings.simplefilter("ignore", DeprecationWarning)
+        old = crs.load_dataset(str(source))
+        old_reports = crs.standardize(str(source), str(tmp_path / "old"))
+    new = crs.io.load_dataset(str(source))
+    new_reports = crs.io.standardize(str(source), str(tmp_path / "new"))
+    assert [m.to_swc() for m in old] == [m.to_swc() for m in new]
+    assert [r["operations"] for r in old_reports] == [
+        r["operations"] for r in new_reports
+    ]
+    assert contents(tmp_path / "old") == contents(tmp_path / "new")
+
+
+def test_downstream_ci_can_make_shims_fail(tmp_path):
+    with warnings.catch_warnings():
+        warnings.simplefilter("error", DeprecationWarning)
+        try:
+            crs.standardize(str(tmp_path), str(tmp_path / "out"))
+        except crs.CompartmentDeprecationWarning as e:
+            assert e.replacement == "compartment_rs.io.standardize"
+        else:
+            raise AssertionError("the shim did not warn")
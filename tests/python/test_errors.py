@@ -0,0 +1,31 @@
+import pytest
+
+import compartment_rs as crs
+
+SUBCLASSES = [
+    crs.SwcIoError,
+    crs.SwcParseError,
+    crs.SwcValidationError,
+    crs.ChecksumError,
+    crs.LimitExceededError,
+    crs.ParameterError,
+    crs.SimulationError,
+    crs.DivergenceError,
+    crs.SessionClosedError,
+]
+
+
+@pytest.mark.parametrize("cls", SUBCLASSES)
+def test_every_class_derives_from_the_base(cls):
+    assert issubclass(cls, crs.CompartmentError)
+    assert issubclass(crs.CompartmentError, Exception)
+
+
+def test_divergence_is_a_simulation_error():
+    assert issubclass(crs.DivergenceError, crs.SimulationError)
+    assert not issubclass(crs.SwcParseError, crs.SwcValidationError)
+
+
+def test_specific_classes_are_caught_by_the_base():
+    with pytest.raises(crs.CompartmentError):
+        raise crs.DivergenceError("voltage went to nan")
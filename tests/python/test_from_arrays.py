@@ -0,0 +1,59 @@
+import pathlib
+
+import numpy as np
+import pytest
+
+import compartment_rs as crs
+
+BASIC = pathlib.Path(__file__).parents[2] / "data" / "basic.swc"
+
+
+def columns():
+    rows = np.loadtxt(BASIC, comments="#")
+    return (
+        rows[:, 0].astype(np.int64),
+        rows[:, 1].astype(np.uint8),
+        np.ascontiguousarray(rows[:, 2:5]),
+        rows[:, 5].copy(),
+        rows[:, 6].astype(np.int64),
+    )
+
+
+def test_arrays_build_the_same_skeleton_as_the_file():
+    from_file = crs.Morphology(str(BASIC))
+    from_arrays = crs.Morphology.from_arrays(*columns())
+    assert len(from_arrays) == len(from_file)
+    assert from_arrays.to_swc() == from_file.to_swc()
+    assert [n.node_id for n in from_arrays] == [n.node_id for n in from_file]
+
+
+def test_strided_columns_are_read_too():
+    node_ids, types, xyz, radii, parent_ids = columns()
+    wide = np.zeros((len(radii), 2))
+    wide[:, 0] = radii
+    from_arrays = crs.Morphology.from_arrays(node_ids, types, xyz, wide[:, 0], parent_ids)
+    assert from_arrays.to_swc() == crs.Morphology(str(BASIC)).to_swc()
+
+
+def test_wrong_dtypes_and_shapes_are_value_errors():
+    node_ids, types, xyz, radii, parent_ids = columns()
+    with pytest.raises(ValueError) as error:
+        crs.Morphology.from_arrays(node_ids.astype(np.int32), types, xyz, radii, parent_ids)
+    assert "node_ids must be a 1-d int64 array" in str(error.value)
+    assert "int32" in str(error.value)
+    with pytest.raises(ValueError) as error:
+        crs.Morphology.from_arrays(node_ids, types, xyz[:, :2].copy(), radii, parent_ids)
+    assert "(15, 2)" in str(error.value)
+    with pytest.raises(ValueError) as error:
+        crs.Morphology.from_arrays(node_ids, types, xyz, [1.0] * 15, parent_ids)
+    assert "radii must be a 1-d float64 array, got list" in str(error.value)
+
+
+def test_an_unknown_parent_names_its_index():
+    node_ids, types, xyz, radii, parent_ids = columns()
+    parent_ids[6] = 99
+    with pytest.raises(crs.SwcValidationError) as error:
+        crs.Morphology.from_arrays(node_ids, types, xyz, radii, parent_ids)
+    assert "index 6" in str(error.value)
+    with pytest.raises(crs.SwcValidationError):
+        crs.Morphology.from_arrays(node_ids, types, xyz, radii[:-1].copy(), parent_ids)
@@ -0,0 +1,42 @@
+import pathlib
+
+import pytest
+
+import compartment_rs as crs
+
+BASIC = pathlib.Path(__file__).parents[2] / "data" / "basic.swc"
+
+
+def test_undo_redo_and_history():
+    morphology = crs.Morphology(str(BASIC))
+    morphology.insert_node_on_edge(4, 8, 0.5, 0.8)
+    after_first = morphology.to_swc()
+    morphology.smooth(2)
+    morphology.prune(2)
+    after_all = morphology.to_swc()
+
+    assert morphology.history() == [
+        "insert_node_on_edge(parent_id=4, child_id=8, position_fraction=0.5, radius=0.8)",
+        "smooth(iterations=2)",
+        "prune(node_id=2)",
+    ]
+    assert morphology.undo() == "prune(node_id=2)"
+    morphology.undo()
+    assert morphology.to_swc() == after_first
+    morphology.redo()
+    morphology.redo()
+    assert morphology.to_swc() == after_all
+
+
+def test_checkpoints_and_bad_edits():
+    morphology = crs.Morphology(str(BASIC), max_history=10)
+    morphology.checkpoint("start")
+    start = morphology.to_swc()
+    morphology.reroot(11)
+    morphology.repair_zero_radii()
+    morphology.restore("start")
+    assert morphology.to_swc() == start
+    with pytest.raises(ValueError):
+        morphology.prune(0)
+    with pytest.raises(ValueError):
+        morphology.restore("nowhere")
@@ -0,0 +1,60 @@
+import pathlib
+
+import pytest
+
+import compartment_rs as crs
+from compartment_rs import registry
+
+DATA = pathlib.Path(__file__).parents[2] / "data"
+
+
+@pytest.fixture
+def shared():
+    registry.clear()
+    cell_id, _ = registry.put(crs.Morphology(str(DATA / "basic.swc")))
+    yield registry.get(cell_id)
+    registry.clear()
+
+
+def test_transformations_leave_the_original_alone(shared):
+    before = shared.to_swc()
+    ptrs = shared.buffer_ptrs()
+    smoothed = shared.smoothed(2)
+    pruned = shared.pruned(2)
+    resampled = shared.resampled(2.0)
+    assert shared.to_swc() == before
+    assert shared.buffer_ptrs() == ptrs
+    assert smoothed.to_swc() != before
+    assert len(pruned) == len(shared) - 5
+    assert len(resampled) > len(shared)
+    with pytest.raises(ValueError):
+        shared.pruned(0)
+
+
+def test_unchanged_columns_are_shared(shared):
+    ptrs = shared.buffer_ptrs()
+    smoothed = shared.smoothed().buffer_ptrs()
+    assert smoothed["nodes"] != ptrs["nodes"]
+    for column in ["parent_child_map", "child_parent_map", "extras"]:
+        assert smoothed[column] == ptrs[column]
+
+    pruned = shared.pruned(2).buffer_ptrs()
+    assert pruned["parent_child_map"] != ptrs["parent_child_map"]
+
+
+def test_mutating_methods_are_absent(shared):
+    for name in ["smooth", "prune", "reroot", "repair_zero_radii", "insert_node_on_edge"]:
+        with pytest.raises(AttributeError):
+            getattr(shared, name)
+
+
+def test_an_edit_session_commits_a_new_object(shared):
+    editing = shared.edit()
+    editing.smooth(1)
+    editing.prune(2)
+    committed = editing.commit()
+    assert isinstance(committed, crs.SharedMorphology)
+    assert len(committed) == len(shared) - 5
+    assert committed.to_swc() == editing.to_swc()
+    with pytest.raises(AttributeError):
+        committed.undo
@@ -0,0 +1,23 @@
+import compartment_rs as crs
+
+FIELDS = {
+    "version",
+    "git_commit",
+    "features",
+    "target_arch",
+    "target_os",
+    "profile",
+    "target_features",
+}
+
+
+def test_build_info_has_every_field():
+    info = crs.build_info()
+    assert set(info) == FIELDS
+    assert info["version"]
+    assert info["git_commit"]
+    assert info["profile"] in ("debug", "release")
+
+
+def test_the_module_was_built_with_the_python_feature():
+    assert "python" in crs.build_info()["features"]
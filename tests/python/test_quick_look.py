@@ -0,0 +1,38 @@
+import pathlib
+
+import pytest
+
+import compartment_rs as crs
+from compartment_rs import io
+
+DATA = pathlib.Path(__file__).parents[2] / "data"
+
+
+def test_path_and_bytes_agree():
+    path = DATA / "basic.swc"
+    from_path = io.quick_look(str(path))
+    from_bytes = io.quick_look(path.read_bytes())
+    assert from_path == from_bytes
+    assert from_path["nodes"] == 15
+    assert from_path["type_counts"] == {1: 1, 2: 3, 3: 6, 4: 5}
+    assert from_path["zero_radius_fixed"] == 1
+    assert from_path["branch_points"] == 3
+    assert from_path["tips"] == 5
+    assert from_path["streamed"]
+    assert from_path["snapshot_status"] == "computed"
+    assert from_path["snapshot"]["input_resistance"] > 0
+
+
+def test_the_snapshot_is_skipped_past_the_threshold():
+    path = str(DATA / "basic.swc")
+    skipped = io.quick_look(path, snapshot_max_nodes=10)
+    assert skipped["snapshot_status"] == "skipped"
+    assert skipped["snapshot"] is None
+    assert skipped["total_area"] == io.quick_look(path)["total_area"]
+    off = io.quick_look(path, snapshot=False)
+    assert off["snapshot_status"] == "not_requested"
+
+
+def test_malformed_data_raises():
+    with pytest.raises(crs.SwcValidationError):
+        io.quick_look(b"1 1 0 0 0 5 -1\n2 3 1 0 0 1 7\n")
@@ -0,0 +1,67 @@
+import pathlib
+
+import pytest
+
+import compartment_rs as crs
+
+BASIC = pathlib.Path(__file__).parents[2] / "data" / "basic.swc"
+
+
+def data_lines(path):
+    return [line.split() for line in path.read_text().splitlines() if not line.startswith("#")]
+
+
+def test_basic_round_trips(tmp_path):
+    out = tmp_path / "nested" / "basic.swc"
+    skeleton = crs.io.read_swc(str(BASIC), write_path=str(out))
+    nodes = skeleton["nodes"]
+    # Renumbered from 0 in tree order, the same points on the same tree
+    assert [n["node_id"] for n in nodes] == list(range(len(nodes)))
+    point = lambda n: (n["type"], n["x"], n["y"], n["z"])
+    by_id = {n["node_id"]: n for n in nodes}
+    tree = {point(n): point(by_id[n["parent_id"]]) if n["parent_id"] >= 0 else None for n in nodes}
+    fields = {int(f[0]): (int(f[1]), *map(float, f[2:5])) for f in data_lines(BASIC)}
+    expected = {p: fields.get(int(f[6])) for f, p in zip(data_lines(BASIC), fields.values())}
+    assert tree == expected
+    radii = {point(n): n["radius"] for n in nodes}
+    assert radii[(1, 0.0, 0.0, 0.0)] == 5.0
+    assert sorted(skeleton["parent_child_map"][0]) == [0, 1, 2, 3]
+    assert skeleton["child_parent_map"][4] == [1]
+    # The last axon node has radius 0, read as 1
+    assert radii[(2, -45.0, 0.0, 0.0)] == 1.0
+    assert len(skeleton["warnings"]) == 1
+    assert "zero-radius" in skeleton["warnings"][0]
+
+    # Written out numbered from 1, it reads back as the same tree
+    written = [int(f[0]) for f in data_lines(out)]
+    assert written == [n["node_id"] + 1 for n in nodes]
+    again = crs.io.read_swc(str(out))
+    assert again["nodes"] == nodes
+    assert again["parent_child_map"] == skeleton["parent_child_map"]
+    assert again["child_parent_map"] == skeleton["child_parent_map"]
+    # Already repaired
+    assert again["warnings"] == []
+
+
+def test_a_bad_number_reports_its_line(tmp_path):
+    path = tmp_path / "bad.swc"
+    lines = BASIC.read_text().splitlines()
+    lines[4] = "3 3 15.0 oops 0.0 0.9 2"
+    path.write_text("\n".join(lines) + "\n")
+    with pytest.raises(crs.SwcParseError) as info:
+        crs.io.read_swc(str(path))
+    assert info.value.line_no == 5
+
+
+def test_a_missing_parent_raises(tmp_path):
+    path = tmp_path / "dangling.swc"
+    path.write_text(BASIC.read_text() + "9 3 0.0 -5.0 0.0 1.0 42\n")
+    with pytest.raises(crs.CompartmentError) as info:
+        crs.io.read_swc(str(path), strict=True)
+    assert info.value.line_no is not None
+
+
+def test_a_missing_file_raises(tmp_path):
+    with pytest.raises(crs.SwcIoError) as info:
+        crs.io.read_swc(str(tmp_path / "nowhere.swc"))
+    assert isinstance(info.value, crs.CompartmentError)
@@ -0,0 +1,111 @@
+import pathlib
+import threading
+
+import pytest
+
+import compartment_rs as crs
+from compartment_rs import registry
+
+DATA = pathlib.Path(__file__).parents[2] / "data"
+
+
+@pytest.fixture(autouse=True)
+def empty_registry():
+    registry.set_budget(None)
+    registry.clear()
+    yield
+    registry.set_budget(None)
+    registry.clear()
+
+
+def test_put_and_get_share_data():
+    cell_id, evicted = registry.put(crs.Morphology(str(DATA / "basic.swc")))
+    assert evicted == []
+    first = registry.get(cell_id)
+    second = registry.get(cell_id)
+    assert first is not second
+    assert first.data_ptr() == second.data_ptr()
+    assert first.cell_id == cell_id
+    assert registry.list() == [cell_id]
+
+    # Putting the shared object back keeps the same data
+    registry.put(first)
+    assert registry.get(cell_id).data_ptr() == first.data_ptr()
+
+    with pytest.raises(KeyError):
+        registry.get("0" * 16 + "-" + "0" * 8)
+    with pytest.raises(ValueError):
+        registry.get("not an id")
+
+
+def test_lru_eviction_at_the_budget():
+    names = ["basic.swc", "soma_single.swc", "soma_chain.swc"]
+    ids = []
+    sizes = []
+    for name in names:
+        cell_id, _ = registry.put(crs.Morphology(str(DATA / name)))
+        ids.append(cell_id)
+        sizes.append(registry.accounted_bytes() - sum(sizes))
+    registry.clear()
+
+    registry.set_budget(sizes[0] + max(sizes[1], sizes[2]))
+    registry.put(crs.Morphology(str(DATA / names[0])))
+    registry.put(crs.Morphology(str(DATA / names[1])))
+    registry.get(ids[0])
+    _, evicted = registry.put(crs.Morphology(str(DATA / names[2])))
+    assert evicted == [ids[1]]
+    assert registry.list() == [ids[0], ids[2]]
+
+
+def test_concurrent_puts_and_gets():
+    names = ["basic.swc", "soma_single.swc", "soma_chain.swc"]
+    morphologies = [crs.Morphology(str(DATA / name)) for name in names]
+    ids = [registry.put(m)[0] for m in morphologies]
+    errors = []
+
+    def work(k):
+        try:
+            for i in range(300):
+                cell_id = ids[(k + i) % len(ids)]
+                if i % 3 == 0:
+                    registry.put(morphologies[(k + i) % len(ids)])
+                elif i % 3 == 1:
+                    try:
+                        assert registry.get(cell_id).cell_id == cell_id
+                    except KeyError:
+                        pass
+                else:
+                    registry.evict(cell_id)
+        except Exception as e:  # noqa: BLE001
+            errors.append(e)
+
+    threads = [threading.Thread(target=work, args=(k,)) for k in range(8)]
+    for t in threads:
+        t.start()
+    for t in threads:
+        t.join(timeout=60)
+        assert not t.is_alive()
+    assert errors == []
+    for cell_id in registry.list():
+        assert registry.get(cell_id).cell_id == cell_id
+
+
+def test_clear_returns_to_baseline():
+    baseline = registry.accounted_bytes()
+    assert baseline == 0
+    registry.put(crs.Morphology(str(DATA / "basic.swc")))
+    assert registry.accounted_bytes() > baseline
+    assert registry.clear() == 1
+    assert registry.accounted_bytes() == baseline
+    assert registry.list() == []
+
+
+def test_load_dataset_populates_the_registry(tmp_path):
+    for name in ["basic.swc", "soma_single.swc"]:
+        (tmp_path / name).write_bytes((DATA / name).read_bytes())
+    first = crs.io.load_dataset(str(tmp_path), register=True)
+    assert len(registry.list()) == 2
+    second = crs.io.load_dataset(str(tmp_path), register=True)
+    assert [m.data_ptr() for m in first] == [m.data_ptr() for m in second]
+    unregistered = crs.io.load_dataset(str(tmp_path))
+    assert unregistered[0].data_ptr() != first[0].data_ptr()
@@ -0,0 +1,21 @@
+import pathlib
+
+import compartment_rs as crs
+
+DATA = pathlib.Path(__file__).parents[2] / "data"
+
+
+def test_tree_str_matches_golden():
+    morphology = crs.Morphology(str(DATA / "basic.swc"))
+    golden = (DATA / "golden" / "basic_tree.txt").read_text(encoding="utf-8")
+    assert morphology.tree_str() == golden
+    assert len(morphology.tree_str(max_depth=0).splitlines()) == 6
+    assert "4 more branches not shown" in morphology.tree_str(longest_paths=1)
+
+
+def test_dendrogram_svg(tmp_path):
+    path = tmp_path / "tree.svg"
+    crs.Morphology(str(DATA / "basic.swc")).render_dendrogram_svg(str(path))
+    svg = path.read_text(encoding="utf-8")
+    assert svg.startswith("<?xml")
+    assert svg.count("<path") == 7
@@ -0,0 +1,201 @@
+import pathlib
+
+import numpy as np
+import pytest
+
+import compartment_rs as crs
+
+BASIC = pathlib.Path(__file__).parents[2] / "data" / "basic.swc"
+
+
+def stimulus(steps):
+    return [0.2 if s < 100 else 0.0 for s in range(steps)]
+
+
+def test_a_session_runs_like_a_plain_one():
+    steps = 300
+    morphology = crs.Morphology(str(BASIC))
+    expected = crs.Session(morphology).run(steps, {2: stimulus(steps)})
+    with crs.simulation.session(morphology) as sim:
+        sim.record("comp[2].hh.m")
+        sim.add_axial_current_probe(2, 3)
+        results = sim.run(steps, {2: stimulus(steps)})
+        assert sim.results["voltages"].shape == results["voltages"].shape
+    assert results["voltages"].shape == (len(expected), steps + 1)
+    assert results["voltages"][2].tolist() == expected[2]
+    assert results["traces"]["comp[2].hh.m"].shape == (steps + 1,)
+    assert results["axial_currents"][(2, 3)].shape == (steps + 1,)
+    assert results["time"][-1] == pytest.approx(steps * 0.025)
+
+
+def test_a_closed_session_raises_on_use():
+    with crs.simulation.session(crs.Morphology(str(BASIC))) as sim:
+        sim.run(10)
+    assert sim.closed
+    for use in [
+        lambda: sim.run(10),
+        lambda: sim.results,
+        lambda: sim.time,
+        lambda: sim.record("comp[2].v"),
+        lambda: sim.add_axial_current_probe(2, 3),
+    ]:
+        with pytest.raises(crs.SessionClosedError) as error:
+            use()
+        assert error.value.code == "E_SIM_0001_SESSION_CLOSED"
+        assert "closed" in str(error.value)
+    with pytest.raises(crs.SessionClosedError):
+        with sim:
+            pass
+    sim.close()
+
+
+def test_results_are_copied_out_of_the_session():
+    with crs.simulation.session(crs.Morphology(str(BASIC))) as sim:
+        results = sim.run(40, {2: [0.5] * 40})
+        inside = results["voltages"].copy()
+    assert isinstance(results["voltages"], np.ndarray)
+    np.testing.assert_array_equal(results["voltages"], inside)
+    assert results["voltages"][2, -1] > results["voltages"][2, 0]
+
+
+def test_an_error_in_the_block_still_closes():
+    with pytest.raises(RuntimeError):
+        with crs.simulation.session(crs.Morphology(str(BASIC))) as sim:
+            raise RuntimeError("boom")
+    assert sim.closed
+
+
+def test_snippets_come_back_by_event_time():
+    steps = 800
+    current = [1.0 if s % 400 < 40 else 0.0 for s in range(steps)]
+    with crs.simulation.session(crs.Morphology(str(BASIC))) as sim:
+        sim.record_around_events("comp[2].v", 2, 0.0, 0.2, 2.0)
+        results = sim.run(steps, {2: current})
+    voltages = results["voltages"][2]
+    crossings = [
+        s for s in range(1, steps + 1) if voltages[s - 1] < 0.0 <= voltages[s]
+    ]
+    snippets = results["snippets"]["comp[2].v"]
+    assert len(crossings) == 2
+    assert [t for t, _ in snippets] == pytest.approx([s * 0.025 for s in crossings])
+    for (_, samples), s in zip(snippets, crossings):
+        assert isinstance(samples, np.ndarray)
+        assert len(samples) == 89
+        np.testing.assert_array_equal(samples, voltages[s - 8 : s + 81])
+
+
+def test_propagation_analysis_is_aligned_with_compartments():
+    steps = 400
+    current = [1.0 if s < 40 else 0.0 for s in range(steps)]
+    with crs.simulation.session(crs.Morphology(str(BASIC))) as sim:
+        with pytest.raises(ValueError):
+            sim.backpropagation_efficacy(2)
+        sim.record_voltages_of([2, 4])
+        results = sim.run(steps, {2: current})
+        efficacy = sim.backpropagation_efficacy(2)
+        peaks = sim.peak_depolarization(0.0, 10.0)
+    voltages = results["voltages"]
+    assert efficacy.shape == peaks.shape == (voltages.shape[0],)
+    recorded = [i for i in range(len(efficacy)) if not np.isnan(efficacy[i])]
+    assert recorded == [2, 4]
+    assert np.isnan(peaks).tolist() == np.isnan(efficacy).tolist()
+    assert efficacy[2] == 1.0
+    assert peaks[4] == voltages[4].max()
+
+
+def sweep_params(points):
+    return [
+        {"name": "conductance", "low": 1e-4, "high": 3e-4, "points": points},
+        {
+            "name": "amplitude",
+            "target": "protocol",
+            "low": 0.01 if points > 1 else 0.03,
+            "high": 0.09 if points > 1 else 0.03,
+            "points": points,
+            "scale": "log",
+        },
+    ]
+
+
+def test_a_sweep_ranks_with_python_callables(tmp_path):
+    morphology = crs.Morphology(str(BASIC))
+    steps = 400
+
+    def protocol(params):
+        return {2: [params["amplitude"]] * steps}
+
+    def final_voltage(params, results):
+        return float(results["voltages"][2, -1])
+
+    # The cell at the target parameters, on its own
+    reference = sweep_params(1)
+    reference[0]["low"] = reference[0]["high"] = 2e-4
+    [(_, target)] = crs.simulation.sweep(
+        morphology, reference, steps, protocol, final_voltage, mechanism="passive"
+    )
+
+    table = crs.simulation.sweep(
+        morphology,
+        sweep_params(3),
+        steps,
+        protocol,
+        lambda params, results: abs(final_voltage(params, results) - target),
+        mechanism="passive",
+        threads=3,
+        csv=tmp_path / "sweep.csv",
+    )
+    assert len(table) == 9
+    best, score = table[0]
+    assert best["conductance"] == pytest.approx(2e-4)
+    assert best["amplitude"] == pytest.approx(0.03)
+    assert score < 1e-9
+    assert [s for _, s in table] == sorted(s for _, s in table)
+    lines = (tmp_path / "sweep.csv").read_text().splitlines()
+    assert lines[0] == "conductance,amplitude,score"
+    assert len(lines) == 10
+
+    def draw(seed):
+        return crs.simulation.sweep(
+            morphology,
+            sweep_params(3),
+            10,
+            lambda params: {},
+            lambda params, results: params["conductance"],
+            mechanism="passive",
+            samples=5,
+            seed=seed,
+        )
+
+    assert draw(3) == draw(3)
+
+
+def test_errors_in_sweep_callables_propagate():
+    def protocol(params):
+        raise KeyError("amplitude")
+
+    with pytest.raises(KeyError):
+        crs.simulation.sweep(
+            crs.Morphology(str(BASIC)),
+            sweep_params(2),
+            10,
+            protocol,
+            lambda params, results: 0.0,
+        )
+
+
+def test_binned_membrane_currents_add_up_to_the_stimulus():
+    steps = 200
+    with crs.simulation.session(crs.Morphology(str(BASIC))) as sim:
+        with pytest.raises(ValueError):
+            sim.binned_membrane_current(0, [-1000.0, 1000.0])
+        sim.record_membrane_currents()
+        sim.run(steps, {2: stimulus(steps)})
+        binned = sim.binned_membrane_current(0, [-1000.0, 0.0, 1000.0])
+        narrow = sim.binned_membrane_current(0, [0.0, 1e-3])
+    assert binned["currents"].shape == (2, steps + 1)
+    assert binned["edges"].tolist() == [-1000.0, 0.0, 1000.0]
+    assert binned["unassigned"] == []
+    total = binned["currents"].sum(axis=0)
+    assert total[0] == 0.0
+    assert np.allclose(total[1:], stimulus(steps), atol=1e-9)
+    assert len(narrow["unassigned"]) > 0
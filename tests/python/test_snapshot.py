@@ -0,0 +1,27 @@
+import pathlib
+import shutil
+
+import compartment_rs as crs
+
+BASIC = pathlib.Path(__file__).parents[2] / "data" / "basic.swc"
+
+
+def test_compartments_snapshot():
+    compartments = crs.Compartments(crs.Morphology(str(BASIC)))
+    snapshot = compartments.passive_snapshot()
+    # 1 µF/cm² over 3e-5 S/cm²
+    assert abs(snapshot["tau"] - 100 / 3) < 0.33
+    assert snapshot["input_resistance"] > 0
+    assert len(snapshot["tips"]) == 3
+    distances = [tip["path_distance"] for tip in snapshot["tips"]]
+    assert distances == sorted(distances, reverse=True)
+    assert all(0 < tip["attenuation"] <= 1 for tip in snapshot["tips"])
+
+
+def test_dataset_snapshots(tmp_path):
+    shutil.copy(BASIC, tmp_path / "a.swc")
+    (tmp_path / "b.swc").write_text("not swc\n")
+    rows = crs.io.passive_snapshots(str(tmp_path), threads=2)
+    assert [pathlib.Path(r["source"]).name for r in rows] == ["a.swc", "b.swc"]
+    assert rows[0]["error"] is None and rows[0]["snapshot"]["tau"] > 0
+    assert rows[1]["snapshot"] is None and rows[1]["error"]
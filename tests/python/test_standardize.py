@@ -0,0 +1,23 @@
+import pathlib
+
+import pytest
+
+import compartment_rs as crs
+
+FIXTURES = pathlib.Path(__file__).parents[2] / "data" / "standardize"
+
+
+def test_standardize_reports_every_file(tmp_path):
+    reports = crs.io.standardize(str(FIXTURES), str(tmp_path), "max_spacing 10\n")
+    assert len(reports) == 6
+    failed = [r for r in reports if r["error"] is not None]
+    assert [pathlib.Path(r["source"]).name for r in failed] == ["broken.swc"]
+    by_name = {pathlib.Path(r["source"]).name: r for r in reports}
+    assert any(op.startswith("convert_units") for op in by_name["nanometres.swc"]["operations"])
+    assert (tmp_path / "standardize.txt").exists()
+
+
+def test_bad_recipes_raise(tmp_path):
+    with pytest.raises(crs.CompartmentError) as error:
+        crs.io.standardize(str(FIXTURES), str(tmp_path), "units furlongs\n")
+    assert error.value.code == "E_DATA_0001_MALFORMED_RECIPE"
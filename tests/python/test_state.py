@@ -0,0 +1,47 @@
+import pathlib
+
+import pytest
+
+import compartment_rs as crs
+
+BASIC = pathlib.Path(__file__).parents[2] / "data" / "basic.swc"
+
+
+def stimulus(steps):
+    return [0.2 if s < 100 else 0.0 for s in range(steps)]
+
+
+def test_stepping_from_python_reproduces_a_run():
+    steps = 300
+    morphology = crs.Morphology(str(BASIC))
+    expected = crs.Session(morphology).run(steps, {2: stimulus(steps)})
+
+    session = crs.Session(morphology)
+    trace = [session.get("comp[2].v")]
+    for current in stimulus(steps):
+        session.inject(2, current)
+        session.step()
+        trace.append(session.get("comp[2].v"))
+    assert trace == expected[2]
+    assert abs(session.time - steps * 0.025) < 1e-9
+
+
+def test_state_errors():
+    session = crs.Session(crs.Morphology(str(BASIC)))
+    assert session.list_paths("comp[2].") == [
+        "comp[2].v",
+        "comp[2].i_membrane",
+        "comp[2].hh.m",
+        "comp[2].hh.h",
+        "comp[2].hh.n",
+    ]
+    with pytest.raises(crs.SimulationError) as error:
+        session.get("comp[2].hh.mm")
+    assert error.value.code == "E_STATE_0001_UNKNOWN_PATH"
+    assert "comp[2].hh.m" in str(error.value)
+    with pytest.raises(crs.SimulationError) as error:
+        session.set("comp[2].hh.m", 1.5)
+    assert error.value.code == "E_STATE_0005_OUT_OF_RANGE"
+    assert error.value.compartment_idx == 2
+    session.set("comp[2].hh.m", 0.5)
+    assert session.get("comp[2].hh.m") == 0.5
@@ -0,0 +1,30 @@
+import pathlib
+
+import numpy as np
+
+import compartment_rs as crs
+
+BASIC = pathlib.Path(__file__).parents[2] / "data" / "basic.swc"
+
+
+def test_runs_go_in_and_come_back(tmp_path):
+    path = tmp_path / "runs.store"
+    store = crs.simulation.ResultStore(path)
+    stored = {}
+    with crs.simulation.session(crs.Morphology(str(BASIC))) as sim:
+        for k in range(4):
+            amplitude = 0.1 * k
+            results = sim.run(20, {2: [amplitude] * 20})
+            tag = "even" if k % 2 == 0 else "odd"
+            run_id = store.append(tag, {"k": k, "amplitude": amplitude}, sim)
+            stored[run_id] = results["voltages"]
+
+    assert len(store.query(tag="even")) == 2
+    assert len(store.query(params={"k": (1, 2)})) == 2
+    assert len(store.query(tag="odd", params={"k": 3})) == 1
+    assert store.query(tag="none") == []
+
+    reopened = crs.simulation.ResultStore(path)
+    assert sorted(reopened.query()) == sorted(stored)
+    for run_id, voltages in stored.items():
+        np.testing.assert_array_equal(reopened.load(run_id)["voltages"], voltages)
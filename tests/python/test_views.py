@@ -0,0 +1,73 @@
+import pathlib
+
+import pytest
+
+import compartment_rs as crs
+
+BASIC = pathlib.Path(__file__).parents[2] / "data" / "basic.swc"
+
+
+def test_nodes_index_and_iterate_like_a_list():
+    morphology = crs.Morphology(str(BASIC))
+    nodes = list(morphology)
+    assert len(nodes) == len(morphology) == 15
+    assert [n.node_id for n in nodes] == list(range(15))
+    assert nodes[0].parent_id is None
+    # The reader numbers nodes breadth first
+    assert morphology[11].node_id == 11
+    assert morphology[11].parent_id == 7
+    assert (morphology[11].x, morphology[11].y, morphology[11].radius) == (35.0, 10.0, 0.5)
+    assert morphology[-1].node_id == 14
+    assert morphology[-15].node_id == 0
+    assert morphology[2].type == 4
+
+    shared = morphology.commit()
+    assert [n.node_id for n in shared[10:13]] == [10, 11, 12]
+    assert [n.node_id for n in shared[::-4]] == [14, 10, 6, 2]
+    assert [n.node_id for n in shared[-3:]] == [12, 13, 14]
+    assert [n.node_id for n in shared[5:2:-1]] == [5, 4, 3]
+    assert shared[20:] == []
+    assert sum(1 for _ in shared) == 15
+
+
+def test_out_of_range_raises_index_error():
+    morphology = crs.Morphology(str(BASIC))
+    for index in (15, -16):
+        with pytest.raises(IndexError) as info:
+            morphology[index]
+        assert str(info.value) == "node index out of range"
+    compartments = crs.Compartments(morphology)
+    with pytest.raises(IndexError) as info:
+        compartments[len(compartments)]
+    assert str(info.value) == "compartment index out of range"
+    with pytest.raises(TypeError):
+        compartments["soma"]
+
+
+def test_views_are_read_only_snapshots():
+    morphology = crs.Morphology(str(BASIC))
+    node = morphology[11]
+    with pytest.raises(AttributeError):
+        node.radius = 2.0
+    morphology.prune(11)
+    assert len(morphology) == 14
+    assert morphology[11].node_id == 12
+    # Taken before the edit, so it still shows the pruned node
+    assert (node.node_id, node.radius) == (11, 0.5)
+
+
+def test_compartments_slice_into_views():
+    compartments = crs.Compartments(crs.Morphology(str(BASIC)), mechanism="passive")
+    views = list(compartments)
+    assert len(views) == len(compartments)
+    assert views[0].parent is None
+    assert [c.idx for c in views] == list(range(len(compartments)))
+    middle = compartments[3:6]
+    assert [c.idx for c in middle] == [3, 4, 5]
+    assert [c.idx for c in compartments[-1:2:-3]] == list(range(len(compartments)))[-1:2:-3]
+    for c in middle:
+        assert c.parent is not None and c.parent < c.idx
+        assert c.length > 0 and c.diam > 0
+        assert isinstance(c.name, str) and c.structure in (2, 3, 4)
+    with pytest.raises(AttributeError):
+        middle[0].length = 1.0
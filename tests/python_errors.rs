@@ -0,0 +1,276 @@
+#![cfg(feature = "python")]
+
+use std::io;
+use std::path::PathBuf;
+
+use compartment_rs::codes::REGISTRY;
+use compartment_rs::python::{
+    ChecksumError, CompartmentError, DivergenceError, LimitExceededError, ParameterError,
+    SessionClosedError, SimulationError, SwcIoError, SwcParseError, SwcValidationError,
+};
+use compartment_rs::solver::SimError;
+use compartment_rs::standardize::DatasetError;
+use compartment_rs::state::StateError;
+use compartment_rs::store::{RunId, StoreError};
+use compartment_rs::{Code, Limit, ParamError, SwcError};
+use pyo3::prelude::*;
+
+fn attr<'py, T: for<'a> FromPyObject<'a, 'py>>(py: Python<'py>, err: &PyErr, name: &str) -> T
+where
+    for<'a> <T as FromPyObject<'a, 'py>>::Error: std::fmt::Debug,
+{
+    err.value(py).getattr(name).unwrap().extract().unwrap()
+}
+
+#[test]
+fn every_error_code_raises_a_compartment_error_with_its_code() {
+    Python::initialize();
+    Python::attach(|py| {
+        for info in REGISTRY {
+            let err: PyErr = SwcError::Invalid {
+                code: info.code,
+                message: info.description.to_string(),
+                line: Some(7),
+            }
+            .into();
+            assert!(err.is_instance_of::<CompartmentError>(py), "{}", info.id);
+            assert_eq!(attr::<String>(py, &err, "code"), info.id);
+            assert_eq!(attr::<Option<usize>>(py, &err, "line_no"), Some(7));
+            assert_eq!(attr::<Option<String>>(py, &err, "path"), None);
+            assert_eq!(attr::<Option<usize>>(py, &err, "compartment_idx"), None);
+        }
+    });
+}
+
+#[test]
+fn codes_map_onto_their_exception_class() {
+    Python::initialize();
+    Python::attach(|py| {
+        let invalid = |code| -> PyErr {
+            SwcError::Invalid {
+                code,
+                message: String::new(),
+                line: None,
+            }
+            .into()
+        };
+        assert!(invalid(Code::DecimalComma).is_instance_of::<SwcParseError>(py));
+        assert!(invalid(Code::NotSwc).is_instance_of::<SwcParseError>(py));
+        assert!(invalid(Code::DanglingParent).is_instance_of::<SwcValidationError>(py));
+        assert!(invalid(Code::NoRoot).is_instance_of::<SwcValidationError>(py));
+        assert!(!invalid(Code::NoRoot).is_instance_of::<SwcParseError>(py));
+        assert!(invalid(Code::UnknownParameter).is_instance_of::<ParameterError>(py));
+    });
+}
+
+#[test]
+fn io_errors_carry_the_path() {
+    Python::initialize();
+    Python::attach(|py| {
+        let err: PyErr = SwcError::Io {
+            path: PathBuf::from("missing.swc"),
+            kind: io::ErrorKind::NotFound,
+            message: "No such file".to_string(),
+        }
+        .into();
+        assert!(err.is_instance_of::<SwcIoError>(py));
+        assert!(err.is_instance_of::<CompartmentError>(py));
+        assert_eq!(attr::<String>(py, &err, "code"), "E_SWC_0013_IO");
+        assert_eq!(
+            attr::<Option<String>>(py, &err, "path").as_deref(),
+            Some("missing.swc")
+        );
+        assert_eq!(attr::<Option<usize>>(py, &err, "line_no"), None);
+    });
+}
+
+#[test]
+fn checksum_and_limit_errors_carry_their_details() {
+    Python::initialize();
+    Python::attach(|py| {
+        let err: PyErr = SwcError::ChecksumMismatch {
+            expected: "ab".to_string(),
+            actual: "cd".to_string(),
+        }
+        .into();
+        assert!(err.is_instance_of::<ChecksumError>(py));
+        assert_eq!(attr::<String>(py, &err, "expected"), "ab");
+        assert_eq!(attr::<String>(py, &err, "actual"), "cd");
+
+        let err: PyErr = SwcError::LimitExceeded {
+            which: Limit::Nodes,
+            limit: 10,
+            observed: 11,
+        }
+        .into();
+        assert!(err.is_instance_of::<LimitExceededError>(py));
+        assert!(err.is_instance_of::<CompartmentError>(py));
+        assert_eq!(attr::<String>(py, &err, "unit"), "nodes");
+        assert_eq!(attr::<u64>(py, &err, "limit"), 10);
+        assert_eq!(attr::<u64>(py, &err, "observed"), 11);
+    });
+}
+
+#[test]
+fn parameter_errors_raise_parameter_error() {
+    Python::initialize();
+    Python::attach(|py| {
+        let err: PyErr = ParamError::MalformedRow {
+            line: 3,
+            message: "bad".to_string(),
+        }
+        .into();
+        assert!(err.is_instance_of::<ParameterError>(py));
+        assert_eq!(
+            attr::<String>(py, &err, "code"),
+            "E_PARAM_0005_MALFORMED_ROW"
+        );
+        assert_eq!(attr::<Option<usize>>(py, &err, "line_no"), Some(3));
+
+        let err: PyErr = ParamError::ReadOnly {
+            name: "ri".to_string(),
+        }
+        .into();
+        assert!(err.is_instance_of::<ParameterError>(py));
+        assert_eq!(attr::<Option<usize>>(py, &err, "line_no"), None);
+    });
+}
+
+#[test]
+fn divergence_raises_divergence_error_with_the_compartment() {
+    Python::initialize();
+    Python::attach(|py| {
+        let err: PyErr = SimError::Diverged {
+            compartment_idx: 4,
+            spine_idx: None,
+            time: 12.5,
+        }
+        .into();
+        assert!(err.is_instance_of::<DivergenceError>(py));
+        assert!(err.is_instance_of::<SimulationError>(py));
+        assert_eq!(attr::<String>(py, &err, "code"), "E_SIM_0002_DIVERGED");
+        assert_eq!(attr::<Option<usize>>(py, &err, "compartment_idx"), Some(4));
+        assert_eq!(attr::<f64>(py, &err, "time"), 12.5);
+        assert!(!err.value(py).hasattr("spine_idx").unwrap());
+
+        let err: PyErr = SimError::Diverged {
+            compartment_idx: 2,
+            spine_idx: Some(1),
+            time: 3.0,
+        }
+        .into();
+        assert!(err.is_instance_of::<DivergenceError>(py));
+        assert_eq!(attr::<Option<usize>>(py, &err, "compartment_idx"), Some(2));
+        assert_eq!(attr::<u64>(py, &err, "spine_idx"), 1);
+    });
+}
+
+#[test]
+fn every_simulation_error_raises_its_class() {
+    Python::initialize();
+    Python::attach(|py| {
+        let reason = || "bad".to_string();
+        let cases = [
+            (SimError::InvalidModel { reason: reason() }, None),
+            (SimError::NoCompartment { idx: 9 }, Some(9)),
+            (SimError::NoSpine { spine_idx: 3 }, None),
+            (SimError::InvalidInput { reason: reason() }, None),
+            (SimError::Depleted { reason: reason() }, None),
+            (
+                SimError::Output {
+                    path: PathBuf::from("traces.bin"),
+                    message: reason(),
+                },
+                None,
+            ),
+            (SimError::Panicked, None),
+            (
+                SimError::State(StateError::NoSuchCompartment {
+                    path: "comp[7].v".to_string(),
+                    idx: 7,
+                }),
+                Some(7),
+            ),
+        ];
+        for (sim, compartment_idx) in cases {
+            let code = sim.code();
+            let err: PyErr = sim.into();
+            assert!(err.is_instance_of::<CompartmentError>(py), "{:?}", code);
+            assert_eq!(attr::<String>(py, &err, "code"), code.id());
+            assert_eq!(
+                attr::<Option<usize>>(py, &err, "compartment_idx"),
+                compartment_idx
+            );
+            assert!(!err.is_instance_of::<DivergenceError>(py), "{:?}", code);
+            // Output failures are I/O; everything else is the simulation's
+            assert_eq!(
+                err.is_instance_of::<SimulationError>(py),
+                code != Code::OutputFailure,
+                "{:?}",
+                code
+            );
+        }
+
+        let err: PyErr = SimError::Output {
+            path: PathBuf::from("traces.bin"),
+            message: reason(),
+        }
+        .into();
+        assert!(err.is_instance_of::<SwcIoError>(py));
+        assert_eq!(
+            attr::<Option<String>>(py, &err, "path").as_deref(),
+            Some("traces.bin")
+        );
+
+        let err: PyErr = SimError::SessionClosed.into();
+        assert!(err.is_instance_of::<SessionClosedError>(py));
+        assert_eq!(
+            attr::<String>(py, &err, "code"),
+            "E_SIM_0001_SESSION_CLOSED"
+        );
+    });
+}
+
+#[test]
+fn store_and_dataset_errors_carry_their_code_and_path() {
+    Python::initialize();
+    Python::attach(|py| {
+        let err: PyErr = StoreError::UnknownRun {
+            path: PathBuf::from("runs.store"),
+            id: RunId(8),
+        }
+        .into();
+        assert!(err.is_instance_of::<CompartmentError>(py));
+        assert_eq!(attr::<String>(py, &err, "code"), "E_STORE_0004_UNKNOWN_RUN");
+        assert_eq!(
+            attr::<Option<String>>(py, &err, "path").as_deref(),
+            Some("runs.store")
+        );
+        // A streamed result that diverged still raises as a divergence
+        let err: PyErr = StoreError::Simulation(SimError::Diverged {
+            compartment_idx: 1,
+            spine_idx: None,
+            time: 0.5,
+        })
+        .into();
+        assert!(err.is_instance_of::<DivergenceError>(py));
+
+        let err: PyErr = DatasetError::MalformedRecipe {
+            reason: "Unknown key 'colour'".to_string(),
+        }
+        .into();
+        assert!(err.is_instance_of::<CompartmentError>(py));
+        assert_eq!(
+            attr::<String>(py, &err, "code"),
+            "E_DATA_0001_MALFORMED_RECIPE"
+        );
+        let err: PyErr = DatasetError::Read(SwcError::Invalid {
+            code: Code::DanglingParent,
+            message: String::new(),
+            line: Some(4),
+        })
+        .into();
+        assert!(err.is_instance_of::<SwcValidationError>(py));
+        assert_eq!(attr::<Option<usize>>(py, &err, "line_no"), Some(4));
+    });
+}
@@ -0,0 +1,275 @@
+use std::fs;
+use std::path::PathBuf;
+
+use compartment_rs::qc::{self, Check, QcReport, QcRubric};
+use compartment_rs::standardize::{Dataset, Pipeline, QUARANTINE_FILE, StandardizeOptions};
+use compartment_rs::{Code, ReaderOptions, swc_reader, swc_reader_from_bytes};
+
+const CLEAN: &str = "data/qc/clean.swc";
+
+fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("qc-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn clean() -> String {
+    fs::read_to_string(CLEAN).unwrap()
+}
+
+/// The clean cell with `edit` applied to the fields of every node line
+fn edited(edit: impl Fn(&mut Vec<String>)) -> String {
+    clean()
+        .lines()
+        .map(|line| {
+            if line.starts_with('#') {
+                return format!("{}\n", line);
+            }
+            let mut fields: Vec<String> = line.split(' ').map(str::to_owned).collect();
+            edit(&mut fields);
+            format!("{}\n", fields.join(" "))
+        })
+        .collect()
+}
+
+fn set(id: &str, column: usize, value: &str) -> impl Fn(&mut Vec<String>) {
+    move |fields: &mut Vec<String>| {
+        if fields[0] == id {
+            fields[column] = value.to_owned();
+        }
+    }
+}
+
+/// The clean cell broken so that exactly `check` should notice
+fn defective(check: Check) -> String {
+    match check {
+        // Read as 1.0, and flagged as repaired
+        Check::NonPositiveRadius => edited(set("60", 5, "0")),
+        Check::RadiusOutliers => edited(set("60", 5, "8")),
+        // A second tree, which the reader leaves out
+        Check::OrphanedFragments => clean() + "69 3 500 500 0 1 -1\n70 3 510 500 0 1 69\n",
+        // One daughter of the basal fork far too thin
+        Check::RallRatio => edited(set("12", 5, "0.3")),
+        // Five daughters off the tip of a twig, on the 3/2 power rule
+        Check::BranchingDegree => {
+            let mut text = clean();
+            for k in 0..5 {
+                let angle = k as f64 * 0.5 - 1.0;
+                text.push_str(&format!(
+                    "{} 3 {} {} 0 0.2155 19\n",
+                    69 + k,
+                    164.0 + 10.0 * angle.cos(),
+                    48.0 + 10.0 * angle.sin()
+                ));
+            }
+            text
+        }
+        // The last nine axon nodes 150 µm further out
+        Check::SpacingGaps => edited(|fields| {
+            if (60..=68).contains(&fields[0].parse::<u32>().unwrap()) {
+                let x: f64 = fields[2].parse().unwrap();
+                fields[2] = (x - 150.0).to_string();
+            }
+        }),
+        // Nanometres
+        Check::Units => edited(|fields| {
+            for field in &mut fields[2..6] {
+                let v: f64 = field.parse().unwrap();
+                *field = (v * 1000.0).to_string();
+            }
+        }),
+    }
+}
+
+fn score(text: &str, rubric: &QcRubric) -> QcReport {
+    let skeleton = swc_reader_from_bytes(text.as_bytes(), &ReaderOptions::default()).unwrap();
+    qc::score_skeleton(&skeleton, rubric)
+}
+
+fn code(check: Check) -> Code {
+    match check {
+        Check::NonPositiveRadius => Code::NonPositiveRadius,
+        Check::RadiusOutliers => Code::RadiusOutlier,
+        Check::OrphanedFragments => Code::OrphanedFragment,
+        Check::RallRatio => Code::RallMismatch,
+        Check::BranchingDegree => Code::HighBranchingDegree,
+        Check::SpacingGaps => Code::SpacingGap,
+        Check::Units => Code::SuspectUnits,
+    }
+}
+
+#[test]
+fn a_clean_cell_scores_one() {
+    let report = score(&clean(), &QcRubric::default());
+    assert!(report.score > 0.99, "{:?}", report);
+    assert!(report.passed);
+    assert!(report.failures.is_empty(), "{:?}", report.failures);
+    assert_eq!(report.sub_scores.len(), Check::ALL.len());
+    for (sub, check) in report.sub_scores.iter().zip(Check::ALL) {
+        assert_eq!(sub.check, check);
+        assert_eq!(sub.score, 1.0);
+        assert_eq!(sub.weight, 1.0);
+        assert!(sub.checked > 0, "{:?}", sub);
+    }
+
+    // The same from the nodes and their map
+    let skeleton = swc_reader(CLEAN, &ReaderOptions::default()).unwrap();
+    assert_eq!(
+        qc::score(
+            &skeleton.nodes,
+            &skeleton.parent_child_map,
+            &QcRubric::default()
+        ),
+        report
+    );
+}
+
+#[test]
+fn each_defect_depresses_its_own_sub_score() {
+    for check in Check::ALL {
+        let report = score(&defective(check), &QcRubric::default());
+        for sub in &report.sub_scores {
+            if sub.check == check {
+                assert!(sub.score < 1.0, "{:?}", sub);
+                assert!(sub.flagged > 0);
+            } else {
+                assert_eq!(sub.score, 1.0, "{:?} moved {:?}", check, sub);
+            }
+        }
+        assert!(report.score < 1.0);
+        assert_eq!(report.failed_checks(), [check]);
+        assert!(!report.failures.is_empty());
+        assert!(
+            report.failures.iter().all(|f| f.code == code(check)),
+            "{:?}",
+            report.failures
+        );
+    }
+
+    // Units fail their sub-score outright, which costs a seventh of the score
+    let units = score(&defective(Check::Units), &QcRubric::default());
+    assert_eq!(units.sub_score(Check::Units), 0.0);
+    assert!((units.score - 6.0 / 7.0).abs() < 1e-12);
+    assert!(units.passed);
+    let strict = QcRubric {
+        pass_threshold: 0.9,
+        ..QcRubric::default()
+    };
+    assert!(!score(&defective(Check::Units), &strict).passed);
+    let orphans = score(&defective(Check::OrphanedFragments), &QcRubric::default());
+    let sub = &orphans.sub_scores[2];
+    assert_eq!((sub.checked, sub.flagged), (70, 2));
+}
+
+#[test]
+fn a_check_weighted_zero_has_no_say() {
+    for check in Check::ALL {
+        let rubric = QcRubric::default().with_weight(check, 0.0);
+        let clean = score(&clean(), &rubric);
+        let broken = score(&defective(check), &rubric);
+        assert_eq!(broken.score, clean.score, "{:?}", check);
+        // Still measured, just not counted
+        assert!(broken.sub_score(check) < 1.0);
+        assert!(
+            score(&defective(check), &QcRubric::default()).score < clean.score,
+            "{:?}",
+            check
+        );
+    }
+    // With nothing weighed everything passes
+    let mut rubric = QcRubric::default();
+    rubric.weights.clear();
+    assert_eq!(score(&defective(Check::Units), &rubric).score, 1.0);
+}
+
+#[test]
+fn the_dataset_csv_matches_single_reports() {
+    let dir = scratch("csv");
+    let mut paths = vec![PathBuf::from(CLEAN)];
+    for check in Check::ALL {
+        let path = dir.join(format!("{}.swc", check.name()));
+        fs::write(&path, defective(check)).unwrap();
+        paths.push(path);
+    }
+    let broken = dir.join("broken.swc");
+    fs::write(&broken, "1 1 0 0 0 5 -1\n2 3 oops 0 0 1 1\n").unwrap();
+    paths.push(broken);
+    let dataset = Dataset::from_paths(paths);
+    let rubric = QcRubric::default();
+    let out = dir.join("qc.csv");
+    let reports = dataset.qc_to_csv(&out, &rubric).unwrap();
+    assert_eq!(reports.len(), 9);
+
+    let text = fs::read_to_string(&out).unwrap();
+    let mut lines = text.lines();
+    let header: Vec<&str> = lines.next().unwrap().split(',').collect();
+    assert_eq!(&header[..3], ["source", "qc_score", "passed"]);
+    assert_eq!(header.len(), 3 + Check::ALL.len() + 1);
+    let rows: Vec<Vec<&str>> = lines.map(|l| l.split(',').collect()).collect();
+    assert_eq!(rows.len(), 9);
+    for (row, path) in rows.iter().zip(&dataset.paths) {
+        assert_eq!(row[0], path.to_string_lossy());
+        if path.ends_with("broken.swc") {
+            assert!(row[1].is_empty());
+            assert!(!row[header.len() - 1].is_empty());
+            continue;
+        }
+        let skeleton = swc_reader(path, &ReaderOptions::default()).unwrap();
+        let report = qc::score_skeleton(&skeleton, &rubric);
+        assert_eq!(row[1].parse::<f64>().unwrap(), report.score, "{:?}", path);
+        assert_eq!(row[2], report.passed.to_string());
+        for (k, check) in Check::ALL.iter().enumerate() {
+            assert_eq!(header[3 + k], check.name());
+            assert_eq!(row[3 + k].parse::<f64>().unwrap(), report.sub_score(*check));
+        }
+        assert!(row[header.len() - 1].is_empty());
+    }
+}
+
+#[test]
+fn standardizing_quarantines_low_scores() {
+    let input = scratch("quarantine-in");
+    let out = scratch("quarantine-out");
+    fs::copy(CLEAN, input.join("clean.swc")).unwrap();
+    fs::write(input.join("rall.swc"), defective(Check::RallRatio)).unwrap();
+    fs::write(input.join("gap.swc"), defective(Check::SpacingGaps)).unwrap();
+    let options = StandardizeOptions {
+        quarantine_below: Some(0.9),
+        ..StandardizeOptions::default()
+    };
+    assert_eq!(
+        StandardizeOptions::parse(&options.to_text()).unwrap(),
+        options
+    );
+    assert!(StandardizeOptions::parse("quarantine_below 2\n").is_err());
+
+    let report = Pipeline::standardize(options)
+        .run(&Dataset::from_dir(&input).unwrap(), &out)
+        .unwrap();
+    let quarantined: Vec<_> = report.quarantined().collect();
+    assert_eq!(quarantined.len(), 1);
+    assert!(quarantined[0].source.ends_with("rall.swc"));
+    assert!(quarantined[0].output.is_none() && quarantined[0].error.is_none());
+    assert!(!out.join("rall.swc").exists());
+    // A slight defect still passes
+    assert_eq!(report.successes().count(), 2);
+    let gap = report
+        .files
+        .iter()
+        .find(|f| f.source.ends_with("gap.swc"))
+        .unwrap();
+    assert!(gap.qc.as_ref().unwrap().score < 1.0);
+    assert!(out.join("gap.swc").exists());
+
+    let list = fs::read_to_string(out.join(QUARANTINE_FILE)).unwrap();
+    assert_eq!(list.lines().count(), 2);
+    assert!(list.lines().nth(1).unwrap().contains("rall.swc"));
+
+    // Without a threshold nothing is held back, and the score is still kept
+    let report = Pipeline::standardize(StandardizeOptions::default())
+        .run(&Dataset::from_dir(&input).unwrap(), &out)
+        .unwrap();
+    assert_eq!(report.successes().count(), 3);
+    assert!(report.files.iter().all(|f| f.qc.is_some()));
+}
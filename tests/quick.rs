@@ -0,0 +1,248 @@
+use std::fmt::Write as _;
+use std::io::Write as _;
+
+use compartment_rs::analysis::{electrotonic_lengths, soma_transfer_impedances};
+use compartment_rs::morphometry::Morphometry;
+use compartment_rs::quick::{
+    QuickLook, QuickLookOptions, Snapshot, quick_look, quick_look_from_bytes,
+};
+use compartment_rs::units::{MicroFaradPerCm2, OhmCm, SiemensPerCm2};
+use compartment_rs::{Channel, Compartments, ReaderOptions, Skeleton, swc_reader_from_bytes};
+
+/// A sorted cell of `nodes` nodes with a side branch every 50th node and
+/// a zero radius every 97th
+fn generated(nodes: usize) -> String {
+    let mut out = String::from("# generated\n1 1 0 0 0 5 -1\n");
+    for id in 2..=nodes {
+        let parent = if id % 50 == 2 { id / 2 } else { id - 1 };
+        let radius = if id % 97 == 0 { 0.0 } else { 0.5 };
+        writeln!(
+            out,
+            "{} 3 {}.5 {}.25 {} {} {}",
+            id,
+            id % 100,
+            id % 70,
+            id % 30,
+            radius,
+            parent
+        )
+        .unwrap();
+    }
+    out
+}
+
+/// Every number `quick_look` reports, worked out with the separate calls
+fn composed(skeleton: &Skeleton, options: &QuickLookOptions) -> (Vec<u64>, [usize; 4]) {
+    let morphometry = Morphometry::new(&skeleton.nodes);
+    let children = |id: u64| {
+        skeleton
+            .parent_child_map
+            .get(&id)
+            .map_or(0, |c| c.iter().filter(|&&child| child != id).count())
+    };
+    let mut compartments = Compartments::from_skeleton(skeleton.clone());
+    for c in compartments.components.iter_mut() {
+        c.set_channel(Channel::passive(
+            options.resistance,
+            options.capacitance,
+            options.conductance,
+        ));
+    }
+    // As few as the branch points allow when that is not enough
+    if compartments.coarsen(options.snapshot_compartments).is_err() {
+        compartments.coarsen_by_length(f64::INFINITY);
+    }
+    let b = morphometry.spatial_metrics().bounding_box;
+    let floats = [
+        morphometry.total_length(),
+        morphometry.total_area(),
+        soma_transfer_impedances(&compartments, 0.0)[1].re,
+        electrotonic_lengths(&compartments)
+            .into_iter()
+            .fold(0.0, f64::max),
+    ]
+    .into_iter()
+    .chain(b.min)
+    .chain(b.max)
+    .map(f64::to_bits)
+    .collect();
+    let counts = [
+        morphometry.max_branching_degree(),
+        skeleton
+            .nodes
+            .iter()
+            .filter(|n| children(n.node_id) >= 2)
+            .count(),
+        skeleton
+            .nodes
+            .iter()
+            .filter(|n| n.parent_id != n.node_id && children(n.node_id) == 0)
+            .count(),
+        compartments.components.len() - 1,
+    ];
+    (floats, counts)
+}
+
+fn reported(look: &QuickLook) -> (Vec<u64>, [usize; 4]) {
+    let Snapshot::Computed(snapshot) = look.snapshot else {
+        panic!("no snapshot: {:?}", look.snapshot);
+    };
+    let b = look.bounding_box;
+    let floats = [
+        look.total_length,
+        look.total_area,
+        snapshot.input_resistance,
+        snapshot.max_electrotonic_length,
+    ]
+    .into_iter()
+    .chain(b.min)
+    .chain(b.max)
+    .map(f64::to_bits)
+    .collect();
+    (
+        floats,
+        [
+            look.max_branching_degree,
+            look.branch_points,
+            look.tips,
+            snapshot.compartments,
+        ],
+    )
+}
+
+#[test]
+fn quick_look_matches_the_separate_calls() {
+    let options = QuickLookOptions {
+        snapshot_max_nodes: 10_000,
+        ..QuickLookOptions::default()
+    };
+    for swc in [
+        std::fs::read_to_string("data/basic.swc").unwrap(),
+        generated(1000),
+        generated(3000),
+    ] {
+        let skeleton = swc_reader_from_bytes(swc.as_bytes(), &ReaderOptions::default()).unwrap();
+        let look = quick_look_from_bytes(swc.as_bytes(), &options).unwrap();
+        assert!(look.streamed);
+        assert_eq!(reported(&look), composed(&skeleton, &options));
+        assert_eq!(look.nodes, skeleton.nodes.len());
+        let stats = skeleton.stats.unwrap();
+        assert_eq!(look.type_counts, stats.type_counts);
+        assert_eq!(
+            look.zero_radius_fixed,
+            stats.zero_radius_fixed.values().sum::<usize>()
+        );
+    }
+}
+
+#[test]
+fn an_unsorted_file_gives_the_same_numbers() {
+    // The basic cell with its lines reversed, so children come first
+    let basic = std::fs::read_to_string("data/basic.swc").unwrap();
+    let reversed: String = basic.lines().rev().map(|l| format!("{}\n", l)).collect();
+    let options = QuickLookOptions {
+        snapshot_compartments: 5,
+        ..QuickLookOptions::default()
+    };
+    let sorted = quick_look_from_bytes(basic.as_bytes(), &options).unwrap();
+    let unsorted = quick_look_from_bytes(reversed.as_bytes(), &options).unwrap();
+    assert!(sorted.streamed);
+    assert!(!unsorted.streamed);
+    // The reader orders siblings as the file does, which changes the
+    // order of the snapshot's sums but nothing else
+    let (a, b) = (reported(&sorted), reported(&unsorted));
+    assert_eq!(a.1, b.1);
+    for k in [0, 1, 3, 4, 5, 6, 7, 8, 9] {
+        assert_eq!(a.0[k], b.0[k]);
+    }
+    let resistance = |look: &QuickLook| match look.snapshot {
+        Snapshot::Computed(s) => s.input_resistance,
+        _ => unreachable!(),
+    };
+    assert!((resistance(&sorted) - resistance(&unsorted)).abs() < 1e-12 * resistance(&sorted));
+    assert_eq!(sorted.type_counts, unsorted.type_counts);
+    let skeleton = swc_reader_from_bytes(reversed.as_bytes(), &ReaderOptions::default()).unwrap();
+    assert_eq!(reported(&unsorted), composed(&skeleton, &options));
+}
+
+#[test]
+fn the_snapshot_is_skipped_past_the_threshold() {
+    let swc = generated(1000);
+    let options = QuickLookOptions {
+        snapshot_max_nodes: 999,
+        ..QuickLookOptions::default()
+    };
+    let look = quick_look_from_bytes(swc.as_bytes(), &options).unwrap();
+    assert_eq!(
+        look.snapshot,
+        Snapshot::Skipped {
+            nodes: 1000,
+            max_nodes: 999
+        }
+    );
+    let full = quick_look_from_bytes(
+        swc.as_bytes(),
+        &QuickLookOptions {
+            snapshot_max_nodes: 1000,
+            ..QuickLookOptions::default()
+        },
+    )
+    .unwrap();
+    assert!(matches!(full.snapshot, Snapshot::Computed(_)));
+    assert_eq!(look.total_area.to_bits(), full.total_area.to_bits());
+
+    let off = QuickLookOptions {
+        snapshot: false,
+        ..QuickLookOptions::default()
+    };
+    let look = quick_look_from_bytes(swc.as_bytes(), &off).unwrap();
+    assert_eq!(look.snapshot, Snapshot::NotRequested);
+}
+
+#[test]
+fn bytes_and_paths_agree() {
+    let swc = generated(500);
+    let path = std::env::temp_dir().join("compartment_rs_quick_look.swc");
+    std::fs::write(&path, &swc).unwrap();
+    let mut gzipped = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+    gzipped.write_all(swc.as_bytes()).unwrap();
+    let gzipped = gzipped.finish().unwrap();
+
+    let options = QuickLookOptions {
+        resistance: OhmCm::new(100.0).unwrap(),
+        capacitance: MicroFaradPerCm2::new(0.9).unwrap(),
+        conductance: SiemensPerCm2::new(1e-4).unwrap(),
+        snapshot_max_nodes: 1000,
+        ..QuickLookOptions::default()
+    };
+    let from_path = quick_look(&path, &options).unwrap();
+    assert!(from_path.streamed);
+    assert!(matches!(from_path.snapshot, Snapshot::Computed(_)));
+    assert_eq!(
+        quick_look_from_bytes(swc.as_bytes(), &options).unwrap(),
+        from_path
+    );
+    assert_eq!(
+        quick_look_from_bytes(&gzipped, &options).unwrap(),
+        from_path
+    );
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn malformed_files_fail_as_the_reader_does() {
+    for swc in [
+        "1 1 0 0 0 5 -1\n2 3 1 0 0 1 7\n",
+        "1 1 0 0 0 5 -1\n1 3 1 0 0 1 1\n",
+        "1 1 0 0 0 five -1\n",
+        "",
+    ] {
+        let expected = swc_reader_from_bytes(swc.as_bytes(), &ReaderOptions::default())
+            .unwrap_err()
+            .code();
+        let error =
+            quick_look_from_bytes(swc.as_bytes(), &QuickLookOptions::default()).unwrap_err();
+        assert_eq!(error.code(), expected, "{:?}", swc);
+    }
+    assert!(quick_look("data/missing.swc", &QuickLookOptions::default()).is_err());
+}
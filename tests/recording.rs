@@ -0,0 +1,233 @@
+use compartment_rs::channels::{ChannelType, HodgkinHuxley};
+use compartment_rs::recording::{
+    AroundEvents, Overlap, ThresholdDetector, TriggeredProbe, TriggeredRecorder,
+};
+use compartment_rs::solver::{Simulation, SimulationResult};
+use compartment_rs::{Channel, Compartments, ReaderOptions, swc_reader_from_bytes};
+
+const DT: f64 = 0.025;
+
+/// Resting at -65 mV with a 100 mV Gaussian spike at each of `peaks`
+fn trace(peaks: &[f64], t_stop: f64) -> Vec<f64> {
+    (0..(t_stop / DT) as usize)
+        .map(|k| {
+            let t = k as f64 * DT;
+            -65.0
+                + peaks
+                    .iter()
+                    .map(|p| 100.0 * (-((t - p) / 0.5).powi(2)).exp())
+                    .sum::<f64>()
+        })
+        .collect()
+}
+
+fn record(v: &[f64], window: AroundEvents) -> Vec<compartment_rs::recording::Snippet> {
+    let mut detector = ThresholdDetector::new(-20.0);
+    let mut recorder = TriggeredRecorder::new(window, DT).unwrap();
+    for &x in v {
+        recorder.push(x, detector.push(x));
+    }
+    recorder.finish()
+}
+
+fn argmax(values: &[f64]) -> usize {
+    (0..values.len())
+        .max_by(|&a, &b| values[a].total_cmp(&values[b]))
+        .unwrap()
+}
+
+#[test]
+fn one_snippet_per_spike_with_the_peak_in_place() {
+    let peaks = [50.0, 150.0, 250.0, 350.0, 450.0];
+    let v = trace(&peaks, 500.0);
+    let window = AroundEvents {
+        pre_ms: 2.0,
+        post_ms: 5.0,
+        overlap: Overlap::Merge,
+    };
+    let snippets = record(&v, window);
+    assert_eq!(snippets.len(), 5);
+
+    let pre = (2.0 / DT) as usize;
+    let post = (5.0 / DT) as usize;
+    for (snippet, peak) in snippets.iter().zip(peaks) {
+        assert!(snippet.merged.is_empty());
+        assert_eq!(snippet.samples.len(), pre + 1 + post);
+        // The detector fires on the way up, shortly before the peak
+        assert!(snippet.event_time < peak && peak - snippet.event_time < 1.0);
+        let offset = ((peak - snippet.event_time) / DT).round() as usize;
+        assert_eq!(argmax(&snippet.samples), pre + offset);
+        assert!((snippet.start_time - (snippet.event_time - 2.0)).abs() < 1e-9);
+    }
+}
+
+#[test]
+fn snippets_match_the_full_recording() {
+    let v = trace(&[1.0, 20.0, 21.5, 60.0, 99.0], 100.0);
+    for overlap in [Overlap::Merge, Overlap::Separate] {
+        let window = AroundEvents {
+            pre_ms: 3.0,
+            post_ms: 4.0,
+            overlap,
+        };
+        for snippet in record(&v, window) {
+            let start = (snippet.start_time / DT).round() as usize;
+            assert_eq!(snippet.samples, v[start..start + snippet.samples.len()]);
+        }
+    }
+}
+
+#[test]
+fn bursts_merge_or_stay_separate() {
+    let v = trace(&[100.0, 103.0, 106.0], 200.0);
+    let window = AroundEvents {
+        pre_ms: 5.0,
+        post_ms: 10.0,
+        overlap: Overlap::Merge,
+    };
+    let merged = record(&v, window);
+    assert_eq!(merged.len(), 1);
+    assert_eq!(merged[0].merged.len(), 2);
+    let last = merged[0].merged[1];
+    let expected = ((last - merged[0].event_time) / DT).round() as usize + 1;
+    assert_eq!(
+        merged[0].samples.len(),
+        (5.0 / DT) as usize + expected + (10.0 / DT) as usize
+    );
+
+    let separate = record(
+        &v,
+        AroundEvents {
+            overlap: Overlap::Separate,
+            ..window
+        },
+    );
+    assert_eq!(separate.len(), 3);
+    let times: Vec<f64> = separate.iter().map(|s| s.event_time).collect();
+    assert_eq!(times, [merged[0].event_time, merged[0].merged[0], last]);
+    assert!(
+        separate
+            .iter()
+            .all(|s| s.samples.len() == merged[0].samples.len() - expected + 1)
+    );
+}
+
+#[test]
+fn windows_are_cut_at_both_ends_of_the_run() {
+    let v = trace(&[0.5, 9.9], 10.0);
+    let window = AroundEvents {
+        pre_ms: 2.0,
+        post_ms: 2.0,
+        overlap: Overlap::Merge,
+    };
+    let snippets = record(&v, window);
+    assert_eq!(snippets.len(), 2);
+    assert_eq!(snippets[0].start_time, 0.0);
+    let end = snippets[1].start_time + (snippets[1].samples.len() - 1) as f64 * DT;
+    assert!((end - (v.len() - 1) as f64 * DT).abs() < 1e-9);
+}
+
+#[test]
+fn rejects_bad_windows() {
+    let window = AroundEvents {
+        pre_ms: -1.0,
+        post_ms: 1.0,
+        overlap: Overlap::Merge,
+    };
+    assert!(TriggeredRecorder::new(window, DT).is_err());
+    let window = AroundEvents {
+        pre_ms: 1.0,
+        ..window
+    };
+    assert!(TriggeredRecorder::new(window, 0.0).is_err());
+}
+
+/// An HH cylinder 20 µm long and 10 µm across at index 2, driven by 1 ms,
+/// 0.5 nA pulses starting at each of `onsets` ms, for `t_stop` ms. Records
+/// its voltage around spikes as `window` says.
+fn simulated(onsets: &[f64], t_stop: f64, window: AroundEvents) -> SimulationResult {
+    let skeleton = swc_reader_from_bytes(
+        b"1 1 0 0 0 5 -1\n2 3 20 0 0 5 1\n",
+        &ReaderOptions::default(),
+    )
+    .unwrap();
+    let mut compartments = Compartments::from_skeleton(skeleton);
+    for c in compartments.components.iter_mut() {
+        let mut channel = Channel::default();
+        channel.channel_type = ChannelType::HodgkinHuxley(HodgkinHuxley::default());
+        channel.resistance = 100.0;
+        channel.capacitance = 1.0;
+        c.set_channel(channel);
+    }
+    let steps = (t_stop / DT) as usize;
+    let pulses = (0..steps)
+        .map(|s| {
+            let t = s as f64 * DT;
+            if onsets.iter().any(|o| (*o..o + 1.0).contains(&t)) {
+                0.5
+            } else {
+                0.0
+            }
+        })
+        .collect();
+    let mut simulation = Simulation::new(&compartments, DT).unwrap();
+    simulation
+        .record_around_events(TriggeredProbe {
+            path: "comp[2].v".to_owned(),
+            source: 2,
+            threshold: -20.0,
+            window,
+        })
+        .unwrap();
+    simulation.run(steps, &[(2, pulses)]).unwrap()
+}
+
+#[test]
+fn a_simulation_records_its_spikes_only() {
+    let window = AroundEvents {
+        pre_ms: 2.0,
+        post_ms: 5.0,
+        overlap: Overlap::Separate,
+    };
+    let onsets = [5.0, 25.0, 45.0, 65.0, 85.0];
+    let result = simulated(&onsets, 100.0, window);
+    let full = &result.voltages[2];
+    let (path, snippets) = &result.snippets[0];
+    assert_eq!(path, "comp[2].v");
+    assert_eq!(snippets.len(), 5);
+    for (snippet, onset) in snippets.iter().zip(onsets) {
+        assert!((onset..onset + 3.0).contains(&snippet.event_time));
+        // The same samples as the full recording over the window
+        let first = (snippet.start_time / DT).round() as usize;
+        assert_eq!(snippet.samples.len(), 80 + 1 + 200);
+        assert_eq!(snippet.samples, full[first..first + snippet.samples.len()]);
+        // Crossing at offset 80, the peak shortly after it
+        assert!(snippet.samples[79] < -20.0 && snippet.samples[80] >= -20.0);
+        let peak = argmax(&snippet.samples);
+        assert!((81..120).contains(&peak), "{}", peak);
+        assert!(snippet.samples[peak] > 20.0);
+    }
+}
+
+#[test]
+fn a_simulated_burst_merges_or_stays_separate() {
+    let onsets = [5.0, 15.0, 50.0];
+    let window = |overlap| AroundEvents {
+        pre_ms: 1.0,
+        post_ms: 15.0,
+        overlap,
+    };
+    let merged = simulated(&onsets, 80.0, window(Overlap::Merge));
+    let separate = simulated(&onsets, 80.0, window(Overlap::Separate));
+    let (merged, separate) = (&merged.snippets[0].1, &separate.snippets[0].1);
+    assert_eq!(separate.len(), 3);
+    assert_eq!(merged.len(), 2);
+    assert_eq!(merged[0].merged, [separate[1].event_time]);
+    // Extended to 15 ms past the second spike
+    let span = separate[1].event_time - separate[0].event_time;
+    assert_eq!(
+        merged[0].samples.len(),
+        separate[0].samples.len() + (span / DT).round() as usize
+    );
+    assert_eq!(merged[1], separate[2]);
+}
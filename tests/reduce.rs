@@ -0,0 +1,166 @@
+use std::fmt::Write as _;
+
+use compartment_rs::analysis::soma_transfer_impedances;
+use compartment_rs::reduce::{ReductionOptions, SiteLocation, equivalent_cable};
+use compartment_rs::solver::Simulation;
+use compartment_rs::units::{MicroFaradPerCm2, OhmCm, SiemensPerCm2};
+use compartment_rs::{Channel, Compartments, ReaderOptions, swc_reader_from_bytes};
+
+fn passive(swc: &str) -> Compartments {
+    let skeleton = swc_reader_from_bytes(swc.as_bytes(), &ReaderOptions::default()).unwrap();
+    let mut compartments = Compartments::from_skeleton(skeleton);
+    for c in compartments.components.iter_mut() {
+        c.set_channel(Channel::passive(
+            OhmCm::new(150.0).unwrap(),
+            MicroFaradPerCm2::new(1.0).unwrap(),
+            SiemensPerCm2::new(5e-5).unwrap(),
+        ));
+    }
+    compartments
+}
+
+/// Point soma and a 2 µm cable of 20 compartments of 10 µm
+fn cable() -> Compartments {
+    let mut swc = String::from("1 1 0 0 0 5 -1\n");
+    for id in 2..=21 {
+        writeln!(swc, "{} 3 {} 0 0 1 {}", id, (id - 1) * 10, id - 1).unwrap();
+    }
+    passive(&swc)
+}
+
+/// A cylindrical soma with two stems: a basal one forking unevenly and an
+/// apical one forking into two equal branches
+fn branched() -> Compartments {
+    let mut swc = String::from("1 1 0 0 0 5 -1\n2 1 10 0 0 5 1\n");
+    let mut id = 2;
+    let mut run = |swc: &mut String, from: usize, count: usize, dir: [f64; 3], radius: f64| {
+        let mut parent = from;
+        let (mut x, mut y, mut z) = (0.0, 0.0, 0.0);
+        for _ in 0..count {
+            id += 1;
+            (x, y, z) = (x + dir[0], y + dir[1], z + dir[2]);
+            writeln!(swc, "{} 3 {} {} {} {} {}", id, x, y, z, radius, parent).unwrap();
+            parent = id;
+        }
+        parent
+    };
+    let fork = run(&mut swc, 2, 10, [10.0, 0.0, 0.0], 1.0);
+    run(&mut swc, fork, 10, [10.0, 10.0, 0.0], 0.6);
+    run(&mut swc, fork, 20, [10.0, -10.0, 0.0], 0.4);
+    let fork = run(&mut swc, 1, 15, [-10.0, 0.0, 0.0], 0.8);
+    run(&mut swc, fork, 8, [-10.0, 10.0, 0.0], 0.5);
+    run(&mut swc, fork, 8, [-10.0, -10.0, 0.0], 0.5);
+    passive(&swc)
+}
+
+#[test]
+fn a_uniform_cable_reduces_to_itself() {
+    let compartments = cable();
+    let targets = [
+        SiteLocation { idx: 6, x: 0.5 },
+        SiteLocation { idx: 16, x: 0.5 },
+    ];
+    for refine in [false, true] {
+        let options = ReductionOptions {
+            refine,
+            ..ReductionOptions::default()
+        };
+        let (model, report) = equivalent_cable(&compartments, &targets, &options).unwrap();
+        assert_eq!(model.components.len(), compartments.components.len());
+        assert_eq!(report.cables.len(), 1);
+        let cable = report.cables[0];
+        assert_eq!((cable.stem, cable.start), (2, 2));
+        assert!((cable.diam - 2.0).abs() < 1e-9, "{}", cable.diam);
+        assert!((cable.length - 200.0).abs() < 1e-9, "{}", cable.length);
+        assert_eq!(report.sites[0].reduced_site.idx, 6);
+        assert!((report.sites[0].reduced_site.x - 0.5).abs() < 1e-9);
+        assert!(report.max_relative_error() < 1e-9, "{:?}", report);
+        for (a, b) in model
+            .components
+            .iter()
+            .zip(&compartments.components)
+            .skip(2)
+        {
+            assert!((a.length - b.length).abs() < 1e-9);
+            assert!((a.diam - b.diam).abs() < 1e-9);
+        }
+    }
+}
+
+#[test]
+fn a_branched_cell_keeps_its_input_resistance_and_time_constant() {
+    let compartments = branched();
+    let targets = [
+        SiteLocation { idx: 12, x: 0.5 },
+        SiteLocation { idx: 50, x: 0.5 },
+    ];
+    let options = ReductionOptions::default();
+    let (model, report) = equivalent_cable(&compartments, &targets, &options).unwrap();
+    assert_eq!(report.cables.len(), 2);
+    assert_eq!(model.components.len(), 2 + 2 * options.segments);
+    assert!(report.iterations > 0);
+
+    // The report's numbers are the models' own
+    let detailed = soma_transfer_impedances(&compartments, 0.0);
+    let reduced = soma_transfer_impedances(&model, 0.0);
+    assert_eq!(report.input_resistance.detailed, detailed[1].re);
+    assert_eq!(report.input_resistance.reduced, reduced[1].re);
+    for fit in report.sites.iter().filter(|f| f.frequency == 0.0) {
+        assert_eq!(fit.detailed, detailed[fit.site.idx]);
+        assert_eq!(fit.reduced, reduced[fit.reduced_site.idx]);
+    }
+    assert_eq!(report.sites.len(), 3 * targets.len());
+
+    assert!(report.input_resistance.relative() < 0.01);
+    assert!(report.time_constant.relative() < 0.01);
+    for fit in &report.sites {
+        assert!(fit.relative_error() < 0.1, "{:?}", fit);
+    }
+
+    // Refining does better than the collapse alone
+    let collapsed = ReductionOptions {
+        refine: false,
+        ..ReductionOptions::default()
+    };
+    let (_, plain) = equivalent_cable(&compartments, &targets, &collapsed).unwrap();
+    assert_eq!(plain.iterations, 0);
+    assert!(report.max_relative_error() < plain.max_relative_error());
+    for (a, b) in report.cables.iter().zip(&plain.cables) {
+        assert_eq!((a.collapsed_diam, a.collapsed_length), (b.diam, b.length));
+    }
+}
+
+#[test]
+fn the_reduced_model_simulates() {
+    let compartments = branched();
+    let (model, report) =
+        equivalent_cable(&compartments, &[], &ReductionOptions::default()).unwrap();
+    let mut simulation = Simulation::new(&model, 0.1).unwrap();
+    let steps = 5000;
+    let result = simulation.run(steps, &[(1, vec![0.05; steps])]).unwrap();
+    assert!(result.voltages.iter().flatten().all(|v| v.is_finite()));
+    // Leaking towards -70 mV
+    let expected = -70.0 + 0.05 * report.input_resistance.reduced;
+    let v = result.voltages[1][steps];
+    assert!((v - expected).abs() < 1e-3, "{} against {}", v, expected);
+}
+
+#[test]
+fn bad_requests_are_refused() {
+    let compartments = branched();
+    let options = ReductionOptions::default();
+    for site in [
+        SiteLocation { idx: 0, x: 0.5 },
+        SiteLocation { idx: 500, x: 0.5 },
+        SiteLocation { idx: 5, x: 1.5 },
+    ] {
+        assert!(equivalent_cable(&compartments, &[site], &options).is_err());
+    }
+    let no_segments = ReductionOptions {
+        segments: 0,
+        ..ReductionOptions::default()
+    };
+    assert!(equivalent_cable(&compartments, &[], &no_segments).is_err());
+    let soma_only = passive("1 1 0 0 0 5 -1\n2 1 10 0 0 5 1\n");
+    assert!(equivalent_cable(&soma_only, &[], &options).is_err());
+}
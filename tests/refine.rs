@@ -0,0 +1,206 @@
+use compartment_rs::discretize::Discretization;
+use compartment_rs::refine::{CalibrationProtocol, RefinementReport, RefinementStatus};
+use compartment_rs::solver::Simulation;
+use compartment_rs::units::{MicroFaradPerCm2, OhmCm, SiemensPerCm2};
+use compartment_rs::{Channel, Compartments, ReaderOptions, swc_reader_from_bytes};
+
+/// A 1 mm trunk along x with a 20 µm side branch every 100 µm, so the trunk
+/// breaks into ten sections, and a passive membrane
+fn comb() -> Compartments {
+    let mut swc = String::from("1 1 0 0 0 5 -1\n");
+    let mut id = 1;
+    let mut trunk = 1;
+    for k in 1..=50 {
+        id += 1;
+        swc += &format!("{} 3 {} 0 0 0.5 {}\n", id, k * 20, trunk);
+        trunk = id;
+        if k % 5 == 0 && k < 50 {
+            id += 1;
+            swc += &format!("{} 3 {} 20 0 0.5 {}\n", id, k * 20, trunk);
+        }
+    }
+    let skeleton = swc_reader_from_bytes(swc.as_bytes(), &ReaderOptions::default()).unwrap();
+    let mut compartments = Compartments::from_skeleton(skeleton);
+    for c in compartments.components.iter_mut().skip(1) {
+        c.set_channel(Channel::passive(
+            OhmCm::new(100.0).unwrap(),
+            MicroFaradPerCm2::new(1.0).unwrap(),
+            SiemensPerCm2::new(1e-4).unwrap(),
+        ));
+    }
+    compartments
+}
+
+/// The distal tip of the trunk
+fn tip(compartments: &Compartments) -> usize {
+    (1..compartments.components.len())
+        .max_by(|&a, &b| {
+            let x = |i: usize| compartments.components[i].distal[0];
+            x(a).total_cmp(&x(b))
+        })
+        .unwrap()
+}
+
+fn protocol(compartments: &Compartments) -> CalibrationProtocol {
+    let steps = 400;
+    CalibrationProtocol {
+        d_lambda: 0.3,
+        steps,
+        stimuli: vec![(tip(compartments), vec![0.1; steps])],
+        ..CalibrationProtocol::default()
+    }
+}
+
+/// Somatic trace of `protocol` on the model built with `discretization`
+fn soma_trace(
+    compartments: &Compartments,
+    discretization: &Discretization,
+    protocol: &CalibrationProtocol,
+) -> Vec<f64> {
+    let built = compartments.build_with(discretization).unwrap();
+    let map = built.last_index_map().unwrap();
+    let stimuli: Vec<_> = protocol
+        .stimuli
+        .iter()
+        .map(|(idx, waveform)| (map.forward_position(*idx, 0.5).unwrap().0, waveform.clone()))
+        .collect();
+    let result = Simulation::new(&built, protocol.dt)
+        .unwrap()
+        .run(protocol.steps, &stimuli)
+        .unwrap();
+    result.voltages[1].clone()
+}
+
+fn scaled(discretization: &Discretization, factor: usize) -> Discretization {
+    let mut scaled = discretization.clone();
+    for s in scaled.sections.iter_mut() {
+        s.ncomp *= factor;
+        s.splits = (1..s.ncomp).map(|k| k as f64 / s.ncomp as f64).collect();
+    }
+    scaled
+}
+
+fn max_difference(a: &[f64], b: &[f64]) -> f64 {
+    a.iter()
+        .zip(b)
+        .map(|(x, y)| (x - y).abs())
+        .fold(0.0, f64::max)
+}
+
+#[test]
+fn refinement_concentrates_near_the_injection() {
+    let compartments = comb();
+    let protocol = protocol(&compartments);
+    let report = compartments.auto_refine(&protocol, 0.05, 5).unwrap();
+    assert_eq!(report.status, RefinementStatus::Converged);
+    assert!(report.max_error <= 0.05);
+    assert_eq!(report.simulations, 2 * report.rounds.len());
+    assert!(report.rounds.len() > 1);
+    for pair in report.rounds.windows(2) {
+        assert!(pair[1].compartments > pair[0].compartments);
+        assert!(!pair[0].refined.is_empty());
+    }
+    assert!(report.rounds.last().unwrap().refined.is_empty());
+
+    // Where along the trunk each section ends, against how many
+    // compartments refinement added to it
+    let initial =
+        Discretization::compute(&compartments, protocol.frequency, protocol.d_lambda).unwrap();
+    let sections = compartments.sections();
+    let (mut near, mut far) = (0, 0);
+    for ((section, before), after) in sections
+        .iter()
+        .zip(&initial.sections)
+        .zip(&report.discretization.sections)
+    {
+        let last = *section.compartments.last().unwrap();
+        let end = &compartments.components[last].distal;
+        let added = after.ncomp - before.ncomp;
+        if end[1] != 0.0 || end[0] == 0.0 {
+            continue;
+        }
+        if end[0] > 500.0 {
+            near += added;
+        } else {
+            far += added;
+        }
+    }
+    assert!(near > far, "{} near the injection, {} away", near, far);
+    let tip_section = sections
+        .iter()
+        .position(|s| s.compartments.contains(&tip(&compartments)))
+        .unwrap();
+    assert!(
+        report.rounds[0]
+            .refined
+            .contains(&sections[tip_section].name)
+    );
+
+    // The refined model tracks a much finer one better than d_lambda does
+    let reference = soma_trace(&compartments, &scaled(&initial, 81), &protocol);
+    let before = max_difference(&soma_trace(&compartments, &initial, &protocol), &reference);
+    let after = max_difference(
+        &soma_trace(&compartments, &report.discretization, &protocol),
+        &reference,
+    );
+    assert!(after < before / 2.0, "{} after vs {} before", after, before);
+}
+
+#[test]
+fn an_impossible_tolerance_stops_at_the_cap() {
+    let compartments = comb();
+    let protocol = protocol(&compartments);
+    let report = compartments.auto_refine(&protocol, 0.0, 2).unwrap();
+    assert_eq!(report.status, RefinementStatus::NotConverged);
+    assert_eq!(report.rounds.len(), 3);
+    assert!(report.max_error > 0.0);
+    assert_eq!(report.max_error, report.rounds[2].max_error);
+    assert!(compartments.auto_refine(&protocol, -1.0, 2).is_err());
+    assert!(compartments.auto_refine(&protocol, f64::NAN, 2).is_err());
+}
+
+#[test]
+fn a_saved_report_skips_refinement() {
+    let compartments = comb();
+    let protocol = protocol(&compartments);
+    let report = compartments.auto_refine(&protocol, 0.1, 4).unwrap();
+    let text = report.to_text();
+    let saved = RefinementReport::parse(&text).unwrap();
+    assert_eq!(saved.to_text(), text);
+    assert_eq!(saved.rounds, report.rounds);
+    assert_eq!(saved.discretization, report.discretization);
+
+    let reused = compartments
+        .auto_refine_reusing(&protocol, 0.1, 4, &saved)
+        .unwrap();
+    assert_eq!(reused.simulations, 0);
+    assert_eq!(reused.discretization, report.discretization);
+
+    // Another tolerance, protocol or model means refining again
+    let again = compartments
+        .auto_refine_reusing(&protocol, 0.2, 4, &saved)
+        .unwrap();
+    assert!(again.simulations > 0);
+    let mut longer = protocol.clone();
+    longer.steps += 1;
+    longer.stimuli[0].1.push(0.1);
+    assert!(
+        compartments
+            .auto_refine_reusing(&longer, 0.1, 4, &saved)
+            .unwrap()
+            .simulations
+            > 0
+    );
+    let mut edited = compartments.clone();
+    edited.components[3].diam *= 2.0;
+    assert!(
+        edited
+            .auto_refine_reusing(&protocol, 0.1, 4, &saved)
+            .unwrap()
+            .simulations
+            > 0
+    );
+
+    assert!(RefinementReport::parse("").is_err());
+    assert!(RefinementReport::parse(&text.replacen("refinement", "refinemint", 1)).is_err());
+}
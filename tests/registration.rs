@@ -0,0 +1,270 @@
+use compartment_rs::registration::{self, FrameOptions, IcpOptions, IcpStatus, Transform};
+use compartment_rs::{ReaderOptions, Skeleton, augment, swc_reader};
+
+fn rotation(axis: [f64; 3], angle: f64) -> [[f64; 3]; 3] {
+    let n = (axis[0] * axis[0] + axis[1] * axis[1] + axis[2] * axis[2]).sqrt();
+    let [x, y, z] = axis.map(|v| v / n);
+    let (s, c) = angle.sin_cos();
+    let t = 1.0 - c;
+    [
+        [t * x * x + c, t * x * y - s * z, t * x * z + s * y],
+        [t * x * y + s * z, t * y * y + c, t * y * z - s * x],
+        [t * x * z - s * y, t * y * z + s * x, t * z * z + c],
+    ]
+}
+
+/// `data/basic.swc` with a few hundred nodes of curly, non-planar growth
+/// added to its tips, so the cloud has no symmetry for ICP to slip along
+fn extended_basic() -> Skeleton {
+    let basic = swc_reader("data/basic.swc", &ReaderOptions::default()).unwrap();
+    let mut ids: Vec<i64> = Vec::new();
+    let mut types = Vec::new();
+    let mut xyz = Vec::new();
+    let mut radii = Vec::new();
+    let mut parents = Vec::new();
+    for n in basic.nodes.iter() {
+        ids.push(n.node_id as i64 + 1);
+        types.push(if n.node_id == 0 { 1 } else { 3 });
+        xyz.push([n.x_pos, n.y_pos, n.z_pos]);
+        radii.push(n.radius.max(0.1));
+        parents.push(if n.node_id == 0 {
+            -1
+        } else {
+            n.parent_id as i64 + 1
+        });
+    }
+    let tips: Vec<u64> = basic
+        .nodes
+        .iter()
+        .filter(|n| {
+            basic
+                .parent_child_map
+                .get(&n.node_id)
+                .is_none_or(|c| c.is_empty())
+        })
+        .map(|n| n.node_id)
+        .collect();
+    for (k, tip) in tips.iter().enumerate() {
+        let mut parent = *tip as i64 + 1;
+        let mut p = xyz[*tip as usize];
+        for step in 0..60 {
+            let s = step as f64 * 0.2 + k as f64;
+            p = [
+                p[0] + 1.5 * (s * 0.7).cos() + 0.3 * k as f64,
+                p[1] + 1.5 * (s * 1.1).sin(),
+                p[2] + 0.8 + 0.4 * k as f64 * (s * 0.5).sin(),
+            ];
+            let id = ids.len() as i64 + 1;
+            ids.push(id);
+            types.push(3);
+            xyz.push(p);
+            radii.push(0.5);
+            parents.push(parent);
+            parent = id;
+        }
+    }
+    Skeleton::from_arrays(
+        &ids,
+        &types,
+        &xyz,
+        &radii,
+        &parents,
+        &ReaderOptions::default(),
+    )
+    .unwrap()
+}
+
+fn assert_close(a: &Transform, b: &Transform, tolerance: f64) {
+    for i in 0..3 {
+        assert!((a.translation[i] - b.translation[i]).abs() < tolerance);
+        for j in 0..3 {
+            assert!((a.rotation[i][j] - b.rotation[i][j]).abs() < tolerance);
+        }
+    }
+    assert!((a.scale - b.scale).abs() < tolerance);
+}
+
+#[test]
+fn landmarks_recover_a_known_transform() {
+    let truth = Transform {
+        rotation: rotation([1.0, -2.0, 0.5], 2.1),
+        translation: [12.0, -4.0, 30.0],
+        scale: 1.0,
+    };
+    let points = [
+        [0.0, 0.0, 0.0],
+        [10.0, 0.0, 1.0],
+        [0.0, 7.0, -2.0],
+        [3.0, 4.0, 9.0],
+        [-5.0, 2.0, 1.0],
+    ];
+    let pairs: Vec<_> = points.iter().map(|p| (*p, truth.apply(*p))).collect();
+    assert_close(
+        &registration::fit_landmarks(&pairs, false).unwrap(),
+        &truth,
+        1e-12,
+    );
+
+    let scaled = Transform {
+        scale: 2.5,
+        ..truth
+    };
+    let pairs: Vec<_> = points.iter().map(|p| (*p, scaled.apply(*p))).collect();
+    assert_close(
+        &registration::fit_landmarks(&pairs, true).unwrap(),
+        &scaled,
+        1e-12,
+    );
+
+    // Collinear landmarks leave the roll about the line free
+    let line: Vec<_> = (0..4)
+        .map(|i| ([i as f64, 0.0, 0.0], [0.0, i as f64, 0.0]))
+        .collect();
+    assert!(registration::fit_landmarks(&line, false).is_err());
+    assert!(registration::fit_landmarks(&pairs[..2], false).is_err());
+}
+
+#[test]
+fn icp_aligns_a_jittered_copy() {
+    let source = extended_basic();
+    let truth = Transform {
+        rotation: rotation([0.2, 1.0, 0.3], 0.25),
+        translation: [5.0, -3.0, 8.0],
+        scale: 1.0,
+    };
+    let jittered = augment::jitter_coordinates(&source, 0.05, 5.0, 11).unwrap();
+    let target = registration::apply_transform(&jittered, &truth);
+
+    let (fit, report) =
+        registration::icp(&source.nodes, &target.nodes, &IcpOptions::default()).unwrap();
+    assert_eq!(report.status, IcpStatus::Converged);
+    assert!(report.rms < 0.2, "rms {}", report.rms);
+    assert_close(&fit, &truth, 0.05);
+
+    // The transform plugs straight back in
+    let moved = registration::apply_transform(&source, &fit);
+    let node = &moved.nodes[40];
+    let expected = &target.nodes[40];
+    assert!((node.x_pos - expected.x_pos).abs() < 0.5);
+}
+
+#[test]
+fn icp_reports_failure_on_unrelated_trees() {
+    let source = extended_basic();
+    // Two arms at right angles, nothing like the source
+    let mut ids: Vec<i64> = (1..=40).collect();
+    let mut xyz: Vec<[f64; 3]> = (0..40).map(|i| [i as f64, 0.0, 0.0]).collect();
+    let mut parents: Vec<i64> = (0..40).map(|i| if i == 0 { -1 } else { i }).collect();
+    for i in 0..40 {
+        ids.push(41 + i);
+        xyz.push([0.0, 0.0, 1.0 + i as f64]);
+        parents.push(if i == 0 { 1 } else { 40 + i });
+    }
+    let n = ids.len();
+    let target = Skeleton::from_arrays(
+        &ids,
+        &vec![3; n],
+        &xyz,
+        &vec![1.0; n],
+        &parents,
+        &ReaderOptions::default(),
+    )
+    .unwrap();
+
+    let (_, report) =
+        registration::icp(&source.nodes, &target.nodes, &IcpOptions::default()).unwrap();
+    assert_ne!(report.status, IcpStatus::Converged);
+}
+
+fn max_distance(a: &Skeleton, b: &Skeleton) -> f64 {
+    a.nodes
+        .iter()
+        .zip(b.nodes.iter())
+        .map(|(p, q)| {
+            let d = [p.x_pos - q.x_pos, p.y_pos - q.y_pos, p.z_pos - q.z_pos];
+            (d[0] * d[0] + d[1] * d[1] + d[2] * d[2]).sqrt()
+        })
+        .fold(0.0, f64::max)
+}
+
+#[test]
+fn rotated_copies_normalize_alike() {
+    let cell = extended_basic();
+    let moved = registration::apply_transform(
+        &cell,
+        &Transform {
+            rotation: rotation([0.7, -0.2, 0.4], 2.3),
+            translation: [40.0, 12.0, -7.0],
+            scale: 1.0,
+        },
+    );
+    let options = FrameOptions::default();
+    let (a, info) = registration::normalize_frame(&cell, &options).unwrap();
+    let (b, _) = registration::normalize_frame(&moved, &options).unwrap();
+    assert!(max_distance(&a, &b) < 1e-9);
+    assert!(!info.isotropic);
+    assert!(info.variances[0] >= info.variances[1] && info.variances[1] >= info.variances[2]);
+
+    // The soma sits at the origin, and the transform maps straight back
+    let soma = &a.nodes[0];
+    assert!(soma.x_pos.abs() + soma.y_pos.abs() + soma.z_pos.abs() < 1e-9);
+    let back = registration::apply_transform(&a, &info.transform.inverse());
+    assert!(max_distance(&back, &cell) < 1e-9);
+}
+
+#[test]
+fn sign_convention_survives_jitter() {
+    let cell = swc_reader("data/basic.swc", &ReaderOptions::default()).unwrap();
+    let options = FrameOptions::default();
+    let (_, reference) = registration::normalize_frame(&cell, &options).unwrap();
+    // The apical dendrite grows along +y in the file, so +x leans that way
+    let x = reference.transform.rotation[0];
+    assert!(x[1] > 0.5, "{:?}", x);
+    for seed in 0..20 {
+        let jittered = augment::jitter_coordinates(&cell, 0.2, 5.0, seed).unwrap();
+        let (_, info) = registration::normalize_frame(&jittered, &options).unwrap();
+        for (axis, expected) in info
+            .transform
+            .rotation
+            .iter()
+            .zip(&reference.transform.rotation)
+        {
+            let agreement: f64 = axis.iter().zip(expected).map(|(a, b)| a * b).sum();
+            assert!(
+                agreement > 0.9,
+                "seed {}: {:?} vs {:?}",
+                seed,
+                axis,
+                expected
+            );
+        }
+    }
+}
+
+#[test]
+fn degenerate_clouds_fall_back_to_world_axes() {
+    // A straight cable along (1, 1, 0): y and z variances tie at zero
+    let ids: Vec<i64> = (1..=6).collect();
+    let types = [1, 3, 3, 3, 3, 3];
+    let xyz: Vec<[f64; 3]> = (0..6).map(|i| [i as f64, i as f64, 0.0]).collect();
+    let parents: Vec<i64> = (0..6).map(|i| if i == 0 { -1 } else { i }).collect();
+    let cable = Skeleton::from_arrays(
+        &ids,
+        &types,
+        &xyz,
+        &[1.0; 6],
+        &parents,
+        &ReaderOptions::default(),
+    )
+    .unwrap();
+    let (_, first) = registration::normalize_frame(&cable, &FrameOptions::default()).unwrap();
+    assert!(first.isotropic);
+    let r = first.transform.rotation;
+    let s = 0.5f64.sqrt();
+    let expected = [[s, s, 0.0], [s, -s, 0.0], [0.0, 0.0, -1.0]];
+    for (row, want) in r.iter().zip(&expected) {
+        for (a, b) in row.iter().zip(want) {
+            assert!((a - b).abs() < 1e-12, "{:?}", r);
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use std::sync::Arc;
+use std::thread;
+
+use compartment_rs::registry::Registry;
+use compartment_rs::standardize::Dataset;
+use compartment_rs::{ReaderOptions, Skeleton, swc_reader_from_bytes};
+
+/// A soma and a straight dendrite `k` µm long, a different cell per `k`
+fn cell(k: usize) -> Arc<Skeleton> {
+    let swc = format!("1 1 0 0 0 5 -1\n2 3 {} 0 0 1 1\n3 3 {} 0 0 1 2\n", k, 2 * k);
+    Arc::new(swc_reader_from_bytes(swc.as_bytes(), &ReaderOptions::default()).unwrap())
+}
+
+#[test]
+fn entries_share_the_stored_data() {
+    let registry = Registry::new(None);
+    let skeleton = cell(10);
+    let stored = registry.put(Arc::clone(&skeleton)).unwrap();
+    assert_eq!(stored.id, skeleton.cell_id());
+    assert!(stored.evicted.is_empty());
+
+    let first = registry.get(&stored.id).unwrap();
+    let second = registry.get(&stored.id).unwrap();
+    assert!(Arc::ptr_eq(&first, &skeleton));
+    assert!(Arc::ptr_eq(&first, &second));
+    assert_eq!(registry.accounted_bytes(), skeleton.accounted_bytes());
+
+    let other = registry.put(cell(20)).unwrap().id;
+    assert_eq!(registry.list(), [stored.id, other]);
+    registry.get(&stored.id).unwrap();
+    assert_eq!(registry.list(), [other, stored.id]);
+
+    assert!(registry.evict(&other));
+    assert!(!registry.evict(&other));
+    assert!(registry.get(&other).is_none());
+    assert_eq!(registry.len(), 1);
+}
+
+#[test]
+fn the_least_recently_used_entry_goes_first() {
+    let (a, b, c) = (cell(10), cell(20), cell(30));
+    let mut sizes = [
+        a.accounted_bytes(),
+        b.accounted_bytes(),
+        c.accounted_bytes(),
+    ];
+    sizes.sort_unstable();
+    let budget = sizes[1] + sizes[2];
+    let registry = Registry::new(Some(budget));
+
+    let a_id = registry.put(Arc::clone(&a)).unwrap().id;
+    let b_id = registry.put(Arc::clone(&b)).unwrap().id;
+    registry.get(&a_id).unwrap();
+    let stored = registry.put(Arc::clone(&c)).unwrap();
+    assert_eq!(stored.evicted, [b_id]);
+    assert_eq!(registry.list(), [a_id, stored.id]);
+    assert!(registry.accounted_bytes() <= budget);
+
+    // Lowering the budget evicts in the same order
+    assert_eq!(registry.set_budget(Some(c.accounted_bytes())), [a_id]);
+    assert_eq!(registry.list(), [stored.id]);
+    let big: String = (2..100)
+        .map(|i| format!("{} 3 {} 0 0 1 {}\n", i, i, i - 1))
+        .collect();
+    let big = swc_reader_from_bytes(
+        format!("1 1 0 0 0 5 -1\n{}", big).as_bytes(),
+        &ReaderOptions::default(),
+    )
+    .unwrap();
+    assert!(
+        registry
+            .put(Arc::new(big))
+            .unwrap_err()
+            .contains("over the budget")
+    );
+    assert_eq!(registry.list(), [stored.id]);
+    assert_eq!(registry.set_budget(None), []);
+}
+
+#[test]
+fn concurrent_use_keeps_the_accounting_straight() {
+    let cells: Vec<Arc<Skeleton>> = (1..=16).map(cell).collect();
+    let budget = cells.iter().take(6).map(|c| c.accounted_bytes()).sum();
+    let registry = Arc::new(Registry::new(Some(budget)));
+    let workers: Vec<_> = (0..8)
+        .map(|t| {
+            let registry = Arc::clone(&registry);
+            let cells = cells.clone();
+            thread::spawn(move || {
+                for i in 0..400 {
+                    let skeleton = &cells[(t * 7 + i) % cells.len()];
+                    match i % 4 {
+                        0 | 1 => {
+                            registry.put(Arc::clone(skeleton)).unwrap();
+                        }
+                        2 => {
+                            if let Some(found) = registry.get(&skeleton.cell_id()) {
+                                assert!(Arc::ptr_eq(&found, skeleton));
+                            }
+                        }
+                        _ => {
+                            registry.evict(&skeleton.cell_id());
+                        }
+                    }
+                }
+            })
+        })
+        .collect();
+    for worker in workers {
+        worker.join().unwrap();
+    }
+
+    let held: usize = registry
+        .list()
+        .iter()
+        .map(|id| registry.get(id).unwrap().accounted_bytes())
+        .sum();
+    assert_eq!(registry.accounted_bytes(), held);
+    assert!(held <= budget);
+}
+
+#[test]
+fn clear_releases_the_skeletons() {
+    let registry = Registry::new(None);
+    let skeleton = cell(10);
+    let id = registry.put(Arc::clone(&skeleton)).unwrap().id;
+    registry.put(cell(20)).unwrap();
+    assert_eq!(Arc::strong_count(&skeleton), 2);
+    assert_eq!(registry.clear(), 2);
+    assert_eq!(registry.accounted_bytes(), 0);
+    assert!(registry.is_empty());
+    assert_eq!(Arc::strong_count(&skeleton), 1);
+    assert!(registry.get(&id).is_none());
+}
+
+#[test]
+fn loading_through_the_registry_reuses_cells() {
+    let dataset = Dataset::from_paths(["data/basic.swc", "data/soma_single.swc"]);
+    let options = ReaderOptions::default();
+    let registry = Registry::new(None);
+    let first = dataset.load(&options, Some(&registry)).unwrap();
+    assert_eq!(registry.len(), 2);
+    let second = dataset.load(&options, Some(&registry)).unwrap();
+    for (a, b) in first.iter().zip(&second) {
+        assert!(Arc::ptr_eq(a, b));
+    }
+
+    // An evicted cell, other options or no registry mean reading again
+    registry.evict(&first[1].cell_id());
+    let fourth = dataset.load(&options, Some(&registry)).unwrap();
+    assert!(Arc::ptr_eq(&first[0], &fourth[0]));
+    assert!(!Arc::ptr_eq(&first[1], &fourth[1]));
+    let plain = dataset.load(&options, None).unwrap();
+    assert!(!Arc::ptr_eq(&first[0], &plain[0]));
+    assert_eq!(plain[0].cell_id(), first[0].cell_id());
+
+    let scaled_file = Dataset::from_paths(["data/scaled.swc"]);
+    let raw = scaled_file.load(&options, Some(&registry)).unwrap();
+    let scaled = ReaderOptions {
+        apply_scale: true,
+        ..ReaderOptions::default()
+    };
+    let applied = scaled_file.load(&scaled, Some(&registry)).unwrap();
+    assert_ne!(raw[0].cell_id(), applied[0].cell_id());
+    assert_eq!(registry.len(), 4);
+}
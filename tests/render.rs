@@ -0,0 +1,142 @@
+//! Regenerate the golden tree after an intended change with
+//! `UPDATE_TREE=1 cargo test --test render`.
+
+use std::process::Command;
+
+use compartment_rs::features::branches;
+use compartment_rs::render::{AsciiOptions, BAR_WIDTH, BranchScalar, bar_blocks};
+use compartment_rs::{ReaderOptions, Skeleton, swc_reader};
+
+const GOLDEN: &str = "data/golden/basic_tree.txt";
+
+fn basic() -> Skeleton {
+    swc_reader("data/basic.swc", &ReaderOptions::default()).unwrap()
+}
+
+#[test]
+fn tree_matches_golden() {
+    let tree = basic().render_ascii(&AsciiOptions::default()).unwrap();
+    if std::env::var_os("UPDATE_TREE").is_some() {
+        std::fs::write(GOLDEN, &tree).unwrap();
+    }
+    assert_eq!(tree, std::fs::read_to_string(GOLDEN).unwrap());
+
+    let output = Command::new(env!("CARGO_BIN_EXE_compartment-rs"))
+        .args(["inspect", "data/basic.swc", "--tree"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let (summary, printed) = stdout.split_once('\n').unwrap();
+    assert!(summary.contains("15 nodes"), "{}", summary);
+    assert_eq!(printed, tree);
+}
+
+#[test]
+fn truncation_caps_the_lines() {
+    let skeleton = basic();
+    let lines = |options: AsciiOptions| skeleton.render_ascii(&options).unwrap().lines().count();
+    // The root, its three branches, and a line each for what the basal and
+    // apical forks hide
+    let shallow = lines(AsciiOptions {
+        max_depth: Some(0),
+        ..Default::default()
+    });
+    assert_eq!(shallow, 6);
+    let full = lines(AsciiOptions::default());
+    assert_eq!(full, 1 + branches(&skeleton).unwrap().len());
+    assert_eq!(
+        lines(AsciiOptions {
+            max_depth: Some(1),
+            ..Default::default()
+        }),
+        full
+    );
+
+    // The longest path runs up the apical trunk; the rest fold into one line
+    let longest = skeleton
+        .render_ascii(&AsciiOptions {
+            longest_paths: Some(1),
+            ..Default::default()
+        })
+        .unwrap();
+    let longest: Vec<&str> = longest.lines().collect();
+    assert_eq!(longest.len(), 5, "{:#?}", longest);
+    assert!(longest[1].contains("apical 40.0 µm"));
+    assert!(longest[2].contains("apical 14.1 µm"));
+    assert!(longest[3].contains("… 1 more branch not shown"));
+    assert!(longest[4].contains("… 4 more branches not shown"));
+}
+
+#[test]
+fn bars_map_the_range_onto_the_blocks() {
+    assert_eq!(bar_blocks(1.0, 1.0, 5.0), 0);
+    assert_eq!(bar_blocks(5.0, 1.0, 5.0), BAR_WIDTH);
+    assert_eq!(bar_blocks(3.0, 1.0, 5.0), BAR_WIDTH / 2);
+    assert_eq!(bar_blocks(9.0, 1.0, 5.0), BAR_WIDTH);
+    assert_eq!(bar_blocks(-9.0, 1.0, 5.0), 0);
+    assert_eq!(bar_blocks(2.0, 2.0, 2.0), BAR_WIDTH);
+    assert_eq!(bar_blocks(f64::NAN, 1.0, 5.0), 0);
+
+    let tree = basic()
+        .render_ascii(&AsciiOptions {
+            bar: Some(BranchScalar::Length),
+            ..Default::default()
+        })
+        .unwrap();
+    let bar = |line: &str| {
+        line.rsplit_once("  [")
+            .unwrap()
+            .1
+            .trim_end_matches(']')
+            .to_owned()
+    };
+    let lines: Vec<&str> = tree.lines().skip(1).collect();
+    // The axon is the longest branch, the basal trunk the shortest
+    assert_eq!(bar(lines[6]), "█".repeat(BAR_WIDTH));
+    assert_eq!(bar(lines[0]), " ".repeat(BAR_WIDTH));
+}
+
+/// Checks every tag is closed in order, and returns the element names seen
+fn well_formed(xml: &str) -> Vec<String> {
+    let mut stack = Vec::new();
+    let mut names = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        let end = rest[start..].find('>').expect("unclosed tag") + start;
+        let tag = &rest[start + 1..end];
+        rest = &rest[end + 1..];
+        if tag.starts_with('?') {
+            assert!(tag.ends_with('?'), "{}", tag);
+            continue;
+        }
+        if let Some(name) = tag.strip_prefix('/') {
+            assert_eq!(stack.pop().as_deref(), Some(name), "mismatched </{}>", name);
+            continue;
+        }
+        let name = tag.split_whitespace().next().unwrap().trim_end_matches('/');
+        assert_eq!(tag.matches('"').count() % 2, 0, "{}", tag);
+        names.push(name.to_owned());
+        if !tag.ends_with('/') {
+            stack.push(name.to_owned());
+        }
+    }
+    assert!(stack.is_empty(), "unclosed {:?}", stack);
+    names
+}
+
+#[test]
+fn dendrogram_has_a_path_per_branch() {
+    let skeleton = basic();
+    let path = std::env::temp_dir().join(format!("dendrogram-{}.svg", std::process::id()));
+    skeleton.render_dendrogram_svg(&path).unwrap();
+    let svg = std::fs::read_to_string(&path).unwrap();
+    assert!(svg.starts_with("<?xml"));
+    let names = well_formed(&svg);
+    assert_eq!(names[0], "svg");
+    assert_eq!(
+        names.iter().filter(|n| *n == "path").count(),
+        branches(&skeleton).unwrap().len()
+    );
+    assert_eq!(svg, skeleton.dendrogram_svg().unwrap());
+}
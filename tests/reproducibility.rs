@@ -0,0 +1,186 @@
+//! Reference values for a battery of quantities on the fixture set, checked
+//! in under `data/golden/reproducibility.csv`. Tolerance 0 means bit-identical:
+//! those quantities only use IEEE-exact operations and order-independent
+//! sums, so they must match on every platform. Anything going through
+//! `exp`/`ln` depends on the platform's libm and gets a relative tolerance.
+//!
+//! Regenerate after an intended change with
+//! `UPDATE_REPRODUCIBILITY=1 cargo test --test reproducibility`.
+
+use compartment_rs::analysis::{electrotonic_lengths, soma_transfer_impedances};
+use compartment_rs::{
+    Channel, Compartments, Morphometry, ReaderOptions, Stimulus, swc_reader, swc_reader_from_bytes,
+};
+use rand::SeedableRng;
+use rand::seq::SliceRandom;
+
+const GOLDEN: &str = "data/golden/reproducibility.csv";
+const FIXTURES: &[&str] = &[
+    "data/basic.swc",
+    "data/extras.swc",
+    "data/scaled.swc",
+    "data/thin_spine.swc",
+];
+/// For results of libm transcendental functions, which may differ in the
+/// last bit between platforms
+const LIBM: f64 = 1e-12;
+
+struct Quantity {
+    source: String,
+    name: String,
+    value: f64,
+    tolerance: f64,
+}
+
+fn battery() -> Vec<Quantity> {
+    let mut out = Vec::new();
+    let mut push = |source: &str, name: &str, value: f64, tolerance: f64| {
+        out.push(Quantity {
+            source: source.to_owned(),
+            name: name.to_owned(),
+            value,
+            tolerance,
+        })
+    };
+    for path in FIXTURES {
+        let skeleton = swc_reader(path, &ReaderOptions::default()).unwrap();
+        let morphometry = Morphometry::new(&skeleton.nodes);
+        push(path, "total_length", morphometry.total_length(), 0.0);
+        push(path, "total_area", morphometry.total_area(), 0.0);
+        for (structure, metrics) in morphometry.spatial_metrics_by_type() {
+            let name = format!("cable_length.{:?}", structure);
+            push(path, &name, metrics.cable_length, 0.0);
+        }
+        let metrics = morphometry.spatial_metrics();
+        push(path, "hull_volume", metrics.hull_volume, 0.0);
+        push(path, "hull_area", metrics.hull_area, 0.0);
+
+        let mut compartments = Compartments::from_skeleton(skeleton);
+        for c in compartments.components.iter_mut() {
+            let mut channel = Channel::default();
+            channel.resistance = 100.0;
+            channel.conductance = 1e-4;
+            channel.capacitance = 1.0;
+            c.set_channel(channel);
+        }
+        let lengths = electrotonic_lengths(&compartments);
+        let longest = lengths.iter().copied().fold(0.0, f64::max);
+        push(path, "max_electrotonic_length", longest, 0.0);
+        let z = soma_transfer_impedances(&compartments, 0.0);
+        push(path, "input_resistance", z[1].re, 0.0);
+        let z = soma_transfer_impedances(&compartments, 100.0);
+        push(path, "input_impedance_100hz.re", z[1].re, 0.0);
+        push(path, "input_impedance_100hz.im", z[1].im, 0.0);
+    }
+
+    let stimulus = Stimulus::BiExp {
+        onset: 2.01,
+        tau_rise: 0.5,
+        tau_decay: 5.0,
+        amplitude: -0.2,
+    };
+    let onsets = [1.0, 3.3, 3.4, 10.0, 25.5];
+    let train = stimulus.render_train(&onsets, 0.025, 100.0).unwrap();
+    let peak = train.iter().copied().fold(0.0, |m: f64, v| m.max(v.abs()));
+    push("biexp_train", "peak", peak, LIBM);
+    let charge = train.iter().sum::<f64>() * 0.025;
+    push("biexp_train", "charge", charge, LIBM);
+    out
+}
+
+fn format(quantities: &[Quantity]) -> String {
+    let mut text = String::from(
+        "# Written by tests/reproducibility.rs, see there before editing\n\
+         source,quantity,value,tolerance\n",
+    );
+    for q in quantities {
+        text.push_str(&format!(
+            "{},{},{:?},{:?}\n",
+            q.source, q.name, q.value, q.tolerance
+        ));
+    }
+    text
+}
+
+#[test]
+fn battery_matches_reference_values() {
+    let quantities = battery();
+    if std::env::var_os("UPDATE_REPRODUCIBILITY").is_some() {
+        std::fs::write(GOLDEN, format(&quantities)).unwrap();
+    }
+    let golden = std::fs::read_to_string(GOLDEN).unwrap();
+    let rows: Vec<Vec<&str>> = golden
+        .lines()
+        .filter(|l| !l.starts_with('#'))
+        .skip(1)
+        .map(|l| l.split(',').collect())
+        .collect();
+    assert_eq!(rows.len(), quantities.len());
+    for (row, q) in rows.iter().zip(&quantities) {
+        assert_eq!((row[0], row[1]), (q.source.as_str(), q.name.as_str()));
+        let expected: f64 = row[2].parse().unwrap();
+        let tolerance: f64 = row[3].parse().unwrap();
+        assert_eq!(tolerance, q.tolerance);
+        if tolerance == 0.0 {
+            assert_eq!(
+                q.value.to_bits(),
+                expected.to_bits(),
+                "{} {}: {:?} vs {:?}",
+                q.source,
+                q.name,
+                q.value,
+                expected
+            );
+        } else {
+            assert!(
+                (q.value - expected).abs() <= tolerance * expected.abs(),
+                "{} {}: {:?} vs {:?}",
+                q.source,
+                q.name,
+                q.value,
+                expected
+            );
+        }
+    }
+}
+
+#[test]
+fn shuffled_nodes_give_identical_totals() {
+    let skeleton = swc_reader("data/basic.swc", &ReaderOptions::default()).unwrap();
+    let morphometry = Morphometry::new(&skeleton.nodes);
+    let (length, area) = (morphometry.total_length(), morphometry.total_area());
+    let cable = morphometry.spatial_metrics().cable_length;
+
+    let mut rng = rand::rngs::StdRng::seed_from_u64(445);
+    let mut nodes = skeleton.nodes.to_vec();
+    for _ in 0..20 {
+        nodes.shuffle(&mut rng);
+        let shuffled = Morphometry::new(&nodes);
+        assert_eq!(shuffled.total_length().to_bits(), length.to_bits());
+        assert_eq!(shuffled.total_area().to_bits(), area.to_bits());
+        assert_eq!(
+            shuffled.spatial_metrics().cable_length.to_bits(),
+            cable.to_bits()
+        );
+    }
+}
+
+#[test]
+fn shuffled_file_lines_give_identical_length() {
+    let text = std::fs::read_to_string("data/basic.swc").unwrap();
+    let (comments, mut lines): (Vec<&str>, Vec<&str>) =
+        text.lines().partition(|l| l.starts_with('#'));
+    let expected = {
+        let skeleton = swc_reader("data/basic.swc", &ReaderOptions::default()).unwrap();
+        Morphometry::new(&skeleton.nodes).total_length()
+    };
+    let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+    for _ in 0..10 {
+        lines.shuffle(&mut rng);
+        let shuffled = [comments.clone(), lines.clone()].concat().join("\n");
+        let skeleton =
+            swc_reader_from_bytes(shuffled.as_bytes(), &ReaderOptions::default()).unwrap();
+        let length = Morphometry::new(&skeleton.nodes).total_length();
+        assert_eq!(length.to_bits(), expected.to_bits());
+    }
+}
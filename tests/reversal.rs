@@ -0,0 +1,235 @@
+use compartment_rs::accumulation::{AccumulationConfig, IonAccumulation};
+use compartment_rs::channels::{ChannelType, Dynamics, HodgkinHuxley};
+use compartment_rs::protocols::{IsolatedCurrent, StepProtocol, isolate_current};
+use compartment_rs::reversal::ReversalCheck;
+use compartment_rs::solver::Simulation;
+use compartment_rs::{
+    Channel, Compartments, Ion, NodeFilter, ReaderOptions, StructureIdentifier,
+    swc_reader_from_bytes,
+};
+
+/// Point soma, `hh` dendrite compartments 2 to 4 and a passive axon
+/// compartment 5 branching off 3. Compartment 4 keeps its own E_K of -77 mV.
+fn model() -> Compartments {
+    let swc =
+        "1 1 0 0 0 5 -1\n2 3 50 0 0 1 1\n3 3 100 0 0 1 2\n4 3 150 0 0 1 3\n5 2 100 50 0 0.5 3\n";
+    let skeleton = swc_reader_from_bytes(swc.as_bytes(), &ReaderOptions::default()).unwrap();
+    let mut compartments = Compartments::from_skeleton(skeleton);
+    for c in compartments.components.iter_mut().skip(2) {
+        let mut channel = Channel::default();
+        channel.channel_type = match c.idx {
+            4 => {
+                let mut hh = HodgkinHuxley::new();
+                hh.set_explicit_reversal(Ion::K, -77.0).unwrap();
+                ChannelType::HodgkinHuxley(hh)
+            }
+            5 => ChannelType::Passive(Dynamics::new()),
+            _ => ChannelType::HodgkinHuxley(HodgkinHuxley::new()),
+        };
+        channel.resistance = 100.0;
+        channel.capacitance = 1.0;
+        channel.conductance = 1e-4;
+        c.set_channel(channel);
+    }
+    compartments
+}
+
+fn isolated(compartments: &Compartments, idx: usize, conductance: &str) -> IsolatedCurrent {
+    let protocol = StepProtocol {
+        holding: -65.0,
+        steps: vec![-20.0, 0.0, 20.0],
+        durations: [1.0, 10.0, 1.0],
+        dt: 0.025,
+    };
+    isolate_current(compartments, idx, &protocol, conductance).unwrap()
+}
+
+/// Equal up to the rounding of the subtraction that isolates the current
+fn same_current(a: &IsolatedCurrent, b: &IsolatedCurrent) {
+    for (a, b) in a.traces.iter().zip(&b.traces) {
+        assert_eq!(a.level, b.level);
+        let scale = a.current.iter().fold(0.0, |m: f64, i| m.max(i.abs()));
+        for (x, y) in a.current.iter().zip(&b.current) {
+            assert!((x - y).abs() <= 1e-12 * scale, "{} against {}", x, y);
+        }
+    }
+}
+
+fn voltages(compartments: &Compartments) -> Vec<Vec<f64>> {
+    let mut simulation = Simulation::new(compartments, 0.025).unwrap();
+    simulation
+        .run(200, &[(2, vec![0.05; 200])])
+        .unwrap()
+        .voltages
+}
+
+#[test]
+fn cell_scope_ek_moves_every_k_current_that_does_not_override() {
+    let before = model();
+    let mut after = model();
+    after.set_reversal(Ion::K, -90.0).unwrap();
+    assert_eq!(after.reversal(Ion::K), Some(-90.0));
+    assert_eq!(after.reversal(Ion::Na), None);
+
+    // g n^4 (v - E_K) with the same gates, so the steady currents scale
+    // with the driving force
+    for idx in [2, 3] {
+        let (old, new) = (
+            isolated(&before, idx, "gkbar_hh"),
+            isolated(&after, idx, "gkbar_hh"),
+        );
+        for ((level, a), (_, b)) in old.steady_state.iter().zip(&new.steady_state) {
+            let ratio = (level + 90.0) / (level + 77.0);
+            assert!(
+                (b / a - ratio).abs() < 1e-9,
+                "{}: {} against {}",
+                idx,
+                b / a,
+                ratio
+            );
+        }
+        same_current(
+            &isolated(&before, idx, "gnabar_hh"),
+            &isolated(&after, idx, "gnabar_hh"),
+        );
+    }
+    // The explicit one stays
+    assert_eq!(
+        isolated(&before, 4, "gkbar_hh"),
+        isolated(&after, 4, "gkbar_hh")
+    );
+
+    let ek = after.parameter_map("ek").unwrap();
+    assert!(ek[0].is_nan());
+    assert_eq!(ek[1..], [-90.0, -90.0, -90.0, -77.0, -90.0]);
+    assert_eq!(
+        ek.iter().map(|e| e.to_bits()).collect::<Vec<_>>(),
+        after
+            .effective_reversals(Ion::K)
+            .iter()
+            .map(|e| e.to_bits())
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(before.parameter_map("ek").unwrap()[2..5], [-77.0; 3]);
+    assert!(before.parameter_map("ek").unwrap()[5].is_nan());
+
+    // Setting the value the mechanisms carry anyway changes nothing in a run,
+    // another value does
+    let mut same = model();
+    same.set_reversal(Ion::K, -77.0).unwrap();
+    assert_eq!(voltages(&same), voltages(&before));
+    assert_ne!(voltages(&after), voltages(&before));
+}
+
+#[test]
+fn compartment_scope_beats_cell_scope_and_explicit_beats_both() {
+    let mut compartments = model();
+    compartments.set_reversal(Ion::K, -90.0).unwrap();
+    compartments.set_reversal_at(3, Ion::K, -80.0).unwrap();
+    let axon = NodeFilter::new().structure(StructureIdentifier::Axon);
+    assert_eq!(
+        compartments
+            .set_reversal_where(&axon, Ion::K, -100.0)
+            .unwrap(),
+        1
+    );
+    compartments
+        .set_section_param("dend[0]", "ena", 55.0)
+        .unwrap();
+    compartments.set_reversal_at(4, Ion::Na, 60.0).unwrap();
+    assert_eq!(
+        compartments.parameter_map("ek").unwrap()[1..],
+        [-90.0, -90.0, -80.0, -77.0, -100.0]
+    );
+    let ena = compartments.parameter_map("ena").unwrap();
+    assert_eq!(ena[2..5], [55.0, 55.0, 60.0]);
+    assert!(ena[5].is_nan());
+    assert_eq!(compartments.scoped_reversal(4, Ion::K), Some(-90.0));
+    assert!(compartments.set_reversal_at(0, Ion::K, -90.0).is_err());
+    assert!(compartments.set_reversal(Ion::K, f64::NAN).is_err());
+
+    let table = compartments.describe().to_table();
+    let lookup = |key: &str| {
+        table
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.clone())
+            .unwrap_or_else(|| panic!("no {}", key))
+    };
+    assert_eq!(lookup("dend.ek.min"), "-90.0");
+    assert_eq!(lookup("dend.ek.max"), "-77.0");
+    assert_eq!(lookup("axon.ek.max"), "-100.0");
+    assert_eq!(lookup("dend.ena.max"), "60.0");
+    assert!(!table.iter().any(|(k, _)| k.contains("eca")));
+}
+
+#[test]
+fn mismatches_name_the_compartment_and_mechanism() {
+    let mut compartments = model();
+    assert!(compartments.reversal_mismatches().is_empty());
+    compartments.set_reversal(Ion::K, -90.0).unwrap();
+    let mismatches = compartments.reversal_mismatches();
+    assert_eq!(mismatches.len(), 1);
+    let m = &mismatches[0];
+    assert_eq!((m.idx, m.mechanism.as_str(), m.ion), (4, "hh", Ion::K));
+    assert_eq!((m.explicit, m.scoped), (-77.0, -90.0));
+
+    // A warning by default, an error when strict
+    assert!(Simulation::new(&compartments, 0.025).is_ok());
+    compartments.set_reversal_check(ReversalCheck {
+        strict: true,
+        ..ReversalCheck::default()
+    });
+    let error = Simulation::new(&compartments, 0.025)
+        .err()
+        .unwrap()
+        .to_string();
+    assert!(error.contains("Compartment 4: hh"), "{}", error);
+    assert!(error.contains("K reversal"), "{}", error);
+
+    // Within tolerance agrees
+    compartments.set_reversal_check(ReversalCheck {
+        tolerance: 15.0,
+        strict: true,
+    });
+    assert!(compartments.reversal_mismatches().is_empty());
+    assert!(Simulation::new(&compartments, 0.025).is_ok());
+}
+
+#[test]
+fn nonspecific_currents_are_unaffected() {
+    let before = model();
+    let mut after = model();
+    assert!(after.set_reversal(Ion::NonSpecific, -50.0).is_err());
+    assert!(after.set_reversal_at(5, Ion::NonSpecific, -50.0).is_err());
+    after.set_reversal(Ion::K, -90.0).unwrap();
+    after.set_reversal(Ion::Na, 40.0).unwrap();
+    after.set_reversal(Ion::Ca, 120.0).unwrap();
+    for idx in [2, 3, 4] {
+        same_current(
+            &isolated(&before, idx, "gl_hh"),
+            &isolated(&after, idx, "gl_hh"),
+        );
+    }
+    assert_eq!(isolated(&before, 5, "g_pas"), isolated(&after, 5, "g_pas"));
+}
+
+#[test]
+fn accumulation_writes_the_compartment_slots() {
+    let mut compartments = model();
+    for c in compartments.components.iter_mut().skip(2).take(3) {
+        if let ChannelType::HodgkinHuxley(hh) = &mut c.channel.channel_type {
+            hh.use_dynamic_reversal = true;
+        }
+    }
+    compartments.set_reversal(Ion::K, -90.0).unwrap();
+    let acc = IonAccumulation::new(&compartments, AccumulationConfig::default()).unwrap();
+    acc.update_reversals(&mut compartments).unwrap();
+    let nernst = acc.reversals(Ion::K).unwrap();
+    let ek = compartments.parameter_map("ek").unwrap();
+    assert_eq!(ek[2..4], nernst[2..4]);
+    assert_eq!(compartments.scoped_reversal(3, Ion::K), Some(nernst[3]));
+    // The explicit mechanism and the passive axon keep theirs
+    assert_eq!(ek[4], -77.0);
+    assert_eq!(ek[5], -90.0);
+}
@@ -0,0 +1,94 @@
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+
+use compartment_rs::{
+    Compartments, NodeFilter, ReaderOptions, RunLog, StructureIdentifier, swc_reader,
+    swc_reader_from_bytes,
+};
+
+fn temp_log(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "compartment_rs_{}_{}.jsonl",
+        name,
+        std::process::id()
+    ))
+}
+
+/// Replaces each record's timestamp, the one thing that differs run to run
+fn normalized(text: &str) -> String {
+    text.lines()
+        .map(|line| {
+            let start = line.find("\"time\":").unwrap() + 7;
+            let end = start + line[start..].find(',').unwrap();
+            format!("{}0{}\n", &line[..start], &line[end..])
+        })
+        .collect()
+}
+
+#[test]
+fn scripted_pipeline_matches_golden_log() {
+    let path = temp_log("pipeline");
+    let log = RunLog::create(&path).unwrap();
+    let options = ReaderOptions {
+        run_log: Some(log.clone()),
+        ..Default::default()
+    };
+    let skeleton = swc_reader("data/basic.swc", &options).unwrap();
+    assert!(swc_reader_from_bytes(b"1 1 0 0 0 1 -1\n1 3 1 0 0 1 1\n", &options).is_err());
+
+    let mut compartments = Compartments::from_skeleton(skeleton).with_run_log(log);
+    let apical = NodeFilter::new().structure(StructureIdentifier::ApicalDendrite);
+    compartments.apply_spine_correction(&apical, 1.5).unwrap();
+    compartments
+        .apply_param_table("apic[0],,conductance,2e-4\naxon[0],0.5,resistance,150\n")
+        .unwrap();
+    compartments.coarsen_by_length(30.0);
+
+    let written = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    let golden = fs::read_to_string("data/golden/run_log.jsonl").unwrap();
+    assert_eq!(normalized(&written), golden);
+}
+
+#[test]
+fn concurrent_cells_get_clean_logs() {
+    let handles: Vec<_> = (0..4)
+        .map(|cell| {
+            thread::spawn(move || {
+                let path = temp_log(&format!("cell{}", cell));
+                let log = RunLog::create(&path).unwrap();
+                // Several threads per cell too, sharing one handle
+                let workers: Vec<_> = (0..4)
+                    .map(|_| {
+                        let options = ReaderOptions {
+                            run_log: Some(log.clone()),
+                            ..Default::default()
+                        };
+                        thread::spawn(move || {
+                            for _ in 0..25 {
+                                swc_reader("data/basic.swc", &options).unwrap();
+                            }
+                        })
+                    })
+                    .collect();
+                for w in workers {
+                    w.join().unwrap();
+                }
+                let text = fs::read_to_string(&path).unwrap();
+                fs::remove_file(&path).unwrap();
+                text
+            })
+        })
+        .collect();
+    for handle in handles {
+        let text = handle.join().unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 100);
+        for (seq, line) in lines.iter().enumerate() {
+            assert!(line.starts_with(&format!("{{\"seq\":{},", seq)), "{}", line);
+            assert!(line.ends_with("}}"));
+            assert!(line.contains("\"source\":\"data/basic.swc\""));
+        }
+    }
+}
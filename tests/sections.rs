@@ -0,0 +1,160 @@
+use compartment_rs::features::branches;
+use compartment_rs::{
+    Code, Compartments, ParamError, ReaderOptions, StructureIdentifier, swc_reader,
+};
+
+fn basic() -> Compartments {
+    let skeleton = swc_reader("data/basic.swc", &ReaderOptions::default()).unwrap();
+    Compartments::from_skeleton(skeleton)
+}
+
+#[test]
+fn sections_match_branches() {
+    let skeleton = swc_reader("data/basic.swc", &ReaderOptions::default()).unwrap();
+    let mut expected: Vec<Vec<usize>> = branches(&skeleton)
+        .unwrap()
+        .into_iter()
+        .map(|b| b.path[1..].iter().map(|&id| id as usize + 1).collect())
+        .collect();
+    expected.sort();
+
+    let compartments = Compartments::from_skeleton(skeleton);
+    let sections = compartments.sections();
+    assert_eq!(sections[0].name, "soma[0]");
+    assert_eq!(sections[0].compartments, vec![1]);
+    let mut actual: Vec<Vec<usize>> = sections[1..]
+        .iter()
+        .map(|s| s.compartments.clone())
+        .collect();
+    actual.sort();
+    assert_eq!(actual, expected);
+
+    let mut names: Vec<&str> = sections.iter().map(|s| s.name.as_str()).collect();
+    names.sort();
+    assert_eq!(
+        names,
+        [
+            "apic[0]", "apic[1]", "apic[2]", "axon[0]", "dend[0]", "dend[1]", "dend[2]", "soma[0]"
+        ]
+    );
+    for section in sections {
+        for &idx in &section.compartments {
+            assert_eq!(compartments.section_of(idx).unwrap().0, section);
+            assert_eq!(compartments.components[idx].structure, section.structure);
+        }
+    }
+    assert!(compartments.section_of(0).is_none());
+}
+
+#[test]
+fn middle_of_odd_section() {
+    let compartments = basic();
+    // Segments of 5, 20 and 20 um: the midpoint falls in the second
+    let axon = compartments.section("axon[0]").unwrap();
+    assert_eq!(axon.nseg(), 3);
+    assert_eq!(axon.structure, StructureIdentifier::Axon);
+    assert_eq!(
+        compartments.compartment_at("axon[0]", 0.5).unwrap(),
+        axon.compartments[1]
+    );
+    assert_eq!(
+        compartments.compartment_at("axon[0]", 0.0).unwrap(),
+        axon.compartments[0]
+    );
+    assert_eq!(
+        compartments.compartment_at("axon[0]", 1.0).unwrap(),
+        axon.compartments[2]
+    );
+    // Boundaries go distally, as in NEURON
+    assert_eq!(
+        compartments.compartment_at("axon[0]", 5.0 / 45.0).unwrap(),
+        axon.compartments[1]
+    );
+
+    let (_, x) = compartments.section_of(axon.compartments[1]).unwrap();
+    assert!((x - 15.0 / 45.0).abs() < 1e-12);
+}
+
+#[test]
+fn table_matches_direct_calls() {
+    let table = "\
+section,x,parameter,value
+# whole sections first, then single points
+apic[0],,conductance,2e-4
+dend[1],,resistance,150
+axon[0],0.5,conductance,0.12
+apic[0], 1 ,capacitance,0.75
+";
+    let mut from_table = basic();
+    assert_eq!(from_table.apply_param_table(table), Ok(4));
+
+    let mut direct = basic();
+    direct
+        .set_section_param("apic[0]", "conductance", 2e-4)
+        .unwrap();
+    direct
+        .set_section_param("dend[1]", "resistance", 150.0)
+        .unwrap();
+    direct
+        .set_param_at("axon[0]", 0.5, "conductance", 0.12)
+        .unwrap();
+    direct
+        .set_param_at("apic[0]", 1.0, "capacitance", 0.75)
+        .unwrap();
+
+    for name in ["capacitance", "conductance", "resistance"] {
+        let (a, b) = (
+            from_table.parameter_map(name).unwrap(),
+            direct.parameter_map(name).unwrap(),
+        );
+        assert_eq!(a[1..], b[1..]);
+    }
+    let conductance = direct.parameter_map("conductance").unwrap();
+    for &idx in &direct.section("apic[0]").unwrap().compartments {
+        assert_eq!(conductance[idx], 2e-4);
+    }
+}
+
+#[test]
+fn bad_addresses_are_typed_errors() {
+    let mut compartments = basic();
+    let err = compartments
+        .set_param_at("apic[9]", 0.5, "conductance", 1.0)
+        .unwrap_err();
+    assert_eq!(err.code(), Code::UnknownSection);
+    let err = compartments
+        .set_param_at("apic[0]", 1.5, "conductance", 1.0)
+        .unwrap_err();
+    assert_eq!(err, ParamError::InvalidPosition { x: 1.5 });
+    let err = compartments
+        .set_section_param("apic[0]", "area", 1.0)
+        .unwrap_err();
+    assert_eq!(err.code(), Code::ReadOnlyParameter);
+    let err = compartments
+        .set_section_param("apic[0]", "conductanse", 1.0)
+        .unwrap_err();
+    assert_eq!(err.code(), Code::UnknownParameter);
+    let err = compartments
+        .apply_param_table("apic[0],0.5,conductance\n")
+        .unwrap_err();
+    assert!(matches!(err, ParamError::MalformedRow { line: 1, .. }));
+}
+
+#[test]
+fn coarsening_keeps_sections_in_step() {
+    let mut compartments = basic();
+    let names: Vec<String> = compartments
+        .sections()
+        .iter()
+        .map(|s| s.name.clone())
+        .collect();
+    compartments.coarsen_by_length(1000.0);
+    let after: Vec<String> = compartments
+        .sections()
+        .iter()
+        .map(|s| s.name.clone())
+        .collect();
+    assert_eq!(names, after);
+    assert_eq!(compartments.section("apic[0]").unwrap().nseg(), 1);
+    assert_eq!(compartments.section("dend[0]").unwrap().nseg(), 1);
+}
@@ -0,0 +1,48 @@
+use compartment_rs::session::SimulationSession;
+use compartment_rs::solver::{SimError, Simulation};
+use compartment_rs::units::{MicroFaradPerCm2, OhmCm, SiemensPerCm2};
+use compartment_rs::{Channel, Compartments, ReaderOptions, swc_reader_from_bytes};
+
+fn session() -> SimulationSession {
+    let skeleton = swc_reader_from_bytes(
+        b"1 1 0 0 0 5 -1\n2 3 20 0 0 1 1\n",
+        &ReaderOptions::default(),
+    )
+    .unwrap();
+    let mut compartments = Compartments::from_skeleton(skeleton);
+    for c in compartments.components.iter_mut() {
+        c.set_channel(Channel::passive(
+            OhmCm::new(100.0).unwrap(),
+            MicroFaradPerCm2::new(1.0).unwrap(),
+            SiemensPerCm2::new(1e-4).unwrap(),
+        ));
+    }
+    SimulationSession::new(Simulation::new(&compartments, 0.025).unwrap())
+}
+
+#[test]
+fn a_session_runs_until_closed() {
+    let mut session = session();
+    assert_eq!(session.result(), Ok(None));
+    session.simulation().unwrap().record("comp[2].v").unwrap();
+    let voltages = session.run(10, &[(2, vec![0.1; 10])]).unwrap().voltages[2].clone();
+    let result = session.result().unwrap().unwrap();
+    assert_eq!(result.voltages[2], voltages);
+    assert_eq!(result.traces[0].1, voltages);
+
+    assert!(session.close());
+    assert!(session.is_closed());
+    assert_eq!(session.result().unwrap_err(), SimError::SessionClosed);
+    assert_eq!(session.run(1, &[]).unwrap_err(), SimError::SessionClosed);
+    assert!(session.simulation().is_err());
+}
+
+#[test]
+fn closing_twice_does_nothing() {
+    let mut session = session();
+    session.run(2, &[]).unwrap();
+    assert!(session.close());
+    assert!(!session.close());
+    assert!(session.is_closed());
+    assert_eq!(session.result().unwrap_err(), SimError::SessionClosed);
+}
@@ -0,0 +1,129 @@
+use compartment_rs::analysis::soma_transfer_impedances;
+use compartment_rs::simplify::simplify_electrotonic;
+use compartment_rs::units::{OhmCm, SiemensPerCm2};
+use compartment_rs::{
+    Channel, Compartments, NodeFlags, ReaderOptions, Skeleton, swc_reader_from_bytes,
+};
+
+const RA: f64 = 100.0;
+const GM: f64 = 1e-4;
+
+/// Soma at the origin with one straight 1 µm-spaced branch per
+/// `(radius, direction)`
+fn cell(branches: &[(f64, [f64; 3])], length: usize) -> Skeleton {
+    let mut swc = String::from("1 1 0 0 0 5 -1\n");
+    let mut id = 1;
+    for &(radius, dir) in branches {
+        let mut parent = 1;
+        for k in 1..=length {
+            id += 1;
+            let p = dir.map(|d| d * k as f64);
+            swc.push_str(&format!(
+                "{} 3 {} {} {} {} {}\n",
+                id, p[0], p[1], p[2], radius, parent
+            ));
+            parent = id;
+        }
+    }
+    swc_reader_from_bytes(swc.as_bytes(), &ReaderOptions::default()).unwrap()
+}
+
+fn simplify(skeleton: &Skeleton, attenuation: f64) -> Skeleton {
+    simplify_electrotonic(
+        skeleton,
+        OhmCm::new(RA).unwrap(),
+        SiemensPerCm2::new(GM).unwrap(),
+        attenuation,
+    )
+    .unwrap()
+    .0
+}
+
+#[test]
+fn uniform_cable_gets_the_analytic_spacing() {
+    // λ = sqrt(d / (4 Ra g)) = 500 µm for d = 1 µm; allowing 50.5 µm per
+    // segment leaves 50 of the 1 µm input segments in each
+    let skeleton = cell(&[(0.5, [1.0, 0.0, 0.0])], 1000);
+    let attenuation = 1.0 - (-50.5f64 / 500.0).exp();
+    let (simplified, report) = simplify_electrotonic(
+        &skeleton,
+        OhmCm::new(RA).unwrap(),
+        SiemensPerCm2::new(GM).unwrap(),
+        attenuation,
+    )
+    .unwrap();
+    let xs: Vec<f64> = simplified.nodes.iter().map(|n| n.x_pos).collect();
+    // The first dendrite node stays, where the type changes from the soma
+    assert_eq!(xs[..3], [0.0, 1.0, 51.0]);
+    for pair in xs[1..].windows(2).take(xs.len() - 3) {
+        assert_eq!(pair[1] - pair[0], 50.0);
+    }
+    assert_eq!(*xs.last().unwrap(), 1000.0);
+    assert!(
+        simplified.nodes[2..]
+            .iter()
+            .all(|n| n.flags.contains(NodeFlags::ELECTROTONIC_MERGED))
+    );
+
+    assert_eq!(report.len(), 2);
+    assert_eq!(report[1].segments_before, 999);
+    assert_eq!(report[1].segments_after, xs.len() - 2);
+    assert!(report[1].ratio() > 45.0);
+}
+
+#[test]
+fn thin_branches_keep_more_nodes() {
+    let skeleton = cell(&[(2.0, [1.0, 0.0, 0.0]), (0.25, [0.0, 1.0, 0.0])], 500);
+    let simplified = simplify(&skeleton, 0.05);
+    let along = |pick: fn(&compartment_rs::Node) -> f64| {
+        simplified.nodes.iter().filter(|n| pick(n) > 0.0).count()
+    };
+    let thick = along(|n| n.x_pos);
+    let thin = along(|n| n.y_pos);
+    assert!(thin > 2 * thick, "{} vs {}", thin, thick);
+    assert!(simplified.validate_maps().is_ok());
+}
+
+#[test]
+fn somatic_response_is_preserved() {
+    let skeleton = cell(
+        &[
+            (1.5, [1.0, 0.0, 0.0]),
+            (0.4, [0.0, 1.0, 0.0]),
+            (0.8, [0.0, 0.0, -1.0]),
+        ],
+        400,
+    );
+    let simplified = simplify(&skeleton, 0.02);
+    assert!(simplified.nodes.len() * 10 < skeleton.nodes.len());
+
+    let passive = |skeleton: Skeleton| {
+        let mut compartments = Compartments::from_skeleton(skeleton);
+        for c in compartments.components.iter_mut() {
+            let mut channel = Channel::default();
+            channel.resistance = RA;
+            channel.conductance = GM;
+            channel.capacitance = 1.0;
+            c.set_channel(channel);
+        }
+        compartments
+    };
+    let (full, reduced) = (passive(skeleton), passive(simplified));
+    // The step response follows from the input impedance across frequencies
+    for frequency in [0.0, 10.0, 100.0] {
+        let a = soma_transfer_impedances(&full, frequency)[1];
+        let b = soma_transfer_impedances(&reduced, frequency)[1];
+        let error = (a.re - b.re).hypot(a.im - b.im) / a.magnitude();
+        assert!(error < 0.01, "{} Hz: {:?} vs {:?}", frequency, a, b);
+    }
+}
+
+#[test]
+fn rejects_bad_thresholds() {
+    let skeleton = cell(&[(0.5, [1.0, 0.0, 0.0])], 10);
+    let ra = OhmCm::new(RA).unwrap();
+    let gm = SiemensPerCm2::new(GM).unwrap();
+    assert!(simplify_electrotonic(&skeleton, ra, gm, 0.0).is_err());
+    assert!(simplify_electrotonic(&skeleton, ra, gm, 1.0).is_err());
+    assert!(simplify_electrotonic(&skeleton, ra, SiemensPerCm2::new(0.0).unwrap(), 0.1).is_err());
+}
@@ -0,0 +1,179 @@
+use compartment_rs::channels::{ChannelType, Dynamics, HodgkinHuxley, Passive};
+use compartment_rs::solver::{RESTING_POTENTIAL, Simulation};
+use compartment_rs::units::{MicroFaradPerCm2, OhmCm, SiemensPerCm2};
+use compartment_rs::{AttachmentKind, Channel, Compartments, ReaderOptions, swc_reader_from_bytes};
+
+/// Point soma with one 20 µm long cylinder `thickness` µm across at index 2
+fn cylinder(thickness: f64, channel: impl Fn() -> Channel) -> Compartments {
+    let text = format!("1 1 0 0 0 5 -1\n2 3 20 0 0 {} 1\n", thickness / 2.0);
+    let skeleton = swc_reader_from_bytes(text.as_bytes(), &ReaderOptions::default()).unwrap();
+    let mut compartments = Compartments::from_skeleton(skeleton);
+    for c in compartments.components.iter_mut() {
+        c.set_channel(channel());
+    }
+    compartments
+}
+
+fn passive() -> Channel {
+    Channel::passive(
+        OhmCm::new(100.0).unwrap(),
+        MicroFaradPerCm2::new(1.0).unwrap(),
+        SiemensPerCm2::new(1e-4).unwrap(),
+    )
+}
+
+fn hh() -> Channel {
+    let mut channel = Channel::default();
+    channel.channel_type = ChannelType::HodgkinHuxley(HodgkinHuxley::new());
+    channel.resistance = 100.0;
+    channel.capacitance = 1.0;
+    channel
+}
+
+#[test]
+fn a_passive_compartment_charges_to_its_steady_state() {
+    let mut compartments = cylinder(2.0, passive);
+    // In nS, and 10 ms from the leak and the capacitance
+    let g = compartments.components[2].membrane_conductance() * 10.0;
+    let (dt, t) = (0.1, 200.0);
+    let steps = 2000;
+    // Settle for 100 ms, then step up
+    let stimulus: Vec<f64> = (0..steps)
+        .map(|s| if s < 1000 { 0.0 } else { 0.001 })
+        .collect();
+    compartments.attach_stimuli(2, stimulus).unwrap();
+    let voltages = compartments.simulate(dt, t).unwrap();
+    assert_eq!(voltages.len(), compartments.components.len());
+    assert!(voltages.iter().all(|v| v.len() == steps + 1));
+
+    let v = &voltages[2];
+    assert_eq!(v[0], RESTING_POTENTIAL);
+    assert!((v[1000] + 70.0).abs() < 1e-3, "{}", v[1000]);
+    let expected = -70.0 + 0.001 * 1e3 / g;
+    assert!((v[steps] - expected).abs() < 1e-3, "{}", v[steps]);
+    // Rising towards it without overshoot, about 63% of the way after tau
+    assert!(v[1000..].windows(2).all(|w| w[1] >= w[0]));
+    let charged = (v[1100] - v[1000]) / (expected - v[1000]);
+    assert!((charged - 0.632).abs() < 0.01, "{}", charged);
+    // The point soma has no length and follows its only neighbour
+    assert!((voltages[1][steps] - expected).abs() < 1e-3);
+    // The dummy root stays at rest
+    assert!(voltages[0].iter().all(|&v| v == RESTING_POTENTIAL));
+
+    // Stimuli on the same compartment add up
+    compartments.attach_stimuli(2, vec![-0.001; steps]).unwrap();
+    let cancelled = compartments.simulate(dt, t).unwrap();
+    assert!((cancelled[2][steps] + 70.0).abs() < 1e-3);
+    assert_eq!(
+        compartments
+            .attachments()
+            .iter()
+            .filter(|a| a.kind == AttachmentKind::Stimulus)
+            .count(),
+        2
+    );
+}
+
+#[test]
+fn a_suprathreshold_current_makes_an_hh_cell_spike() {
+    let steps = 2000;
+    let run = |amplitude: f64| {
+        let mut compartments = cylinder(10.0, hh);
+        let stimulus = (0..steps)
+            .map(|s| {
+                if (800..1200).contains(&s) {
+                    amplitude
+                } else {
+                    0.0
+                }
+            })
+            .collect();
+        compartments.attach_stimuli(2, stimulus).unwrap();
+        compartments.simulate(0.025, 50.0).unwrap()
+    };
+    let peak = |v: &[f64]| v.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+
+    let voltages = run(0.1);
+    let v = &voltages[2];
+    assert!(peak(v) > 20.0, "{}", peak(v));
+    // Not before the current starts, and back down afterwards
+    assert!(peak(&v[..800]) < -60.0);
+    assert!(v[steps] < -55.0, "{}", v[steps]);
+    // The soma rides along
+    assert!(peak(&voltages[1]) > 20.0);
+
+    let quiet = run(0.001);
+    assert!(peak(&quiet[2]) < -55.0, "{}", peak(&quiet[2]));
+}
+
+#[test]
+fn the_solver_advances_gates_through_dynamics() {
+    let compartments = cylinder(10.0, hh);
+    let mut simulation = Simulation::new(&compartments, 0.025).unwrap();
+    simulation.clamp(2, Some(-20.0)).unwrap();
+    let hh = HodgkinHuxley::new();
+    let mut gates = HodgkinHuxley::steady_state(RESTING_POTENTIAL);
+    let mut v = RESTING_POTENTIAL;
+    for _ in 0..200 {
+        simulation.step().unwrap();
+        hh.update(&mut gates, v, 0.025);
+        v = -20.0;
+    }
+    for (gate, expected) in ["m", "h", "n"].iter().zip(gates) {
+        let got = simulation.get(&format!("comp[2].hh.{}", gate)).unwrap();
+        assert!((got - expected).abs() < 1e-12, "{}: {}", gate, got);
+    }
+    // Held there, the clamp supplies the ionic current
+    let area = compartments.components[2].membrane_area();
+    let expected = hh.current(&gates, -20.0, 0.0) * area * 1e-2;
+    let supplied = simulation.clamp_current(2).unwrap();
+    assert!(
+        (supplied - expected).abs() < 1e-3 * expected.abs(),
+        "{}",
+        supplied
+    );
+
+    let pas = Passive::new();
+    assert_eq!(pas.current(&gates, -60.0, 1e-4), 1e-4 * 10.0);
+    let mut untouched = gates;
+    pas.update(&mut untouched, 0.0, 1.0);
+    assert_eq!(untouched, gates);
+}
+
+#[test]
+fn bad_stimuli_are_errors() {
+    let mut compartments = cylinder(2.0, passive);
+    assert!(compartments.attach_stimuli(3, vec![0.0; 10]).is_err());
+    assert!(compartments.attach_stimuli(0, vec![0.0; 10]).is_err());
+    assert!(compartments.attach_stimuli(2, vec![f64::NAN; 10]).is_err());
+    assert!(compartments.attachments().is_empty());
+
+    compartments.attach_stimuli(2, vec![0.0; 10]).unwrap();
+    let error = compartments.simulate(0.1, 2.0).unwrap_err();
+    assert!(error.contains("10 values for 20 steps"), "{}", error);
+    assert_eq!(compartments.simulate(0.1, 1.0).unwrap()[2].len(), 11);
+    assert!(compartments.simulate(0.1, 1.05).is_err());
+    assert!(compartments.simulate(0.0, 1.0).is_err());
+    assert!(compartments.simulate(0.1, -1.0).is_err());
+
+    // A stimulus without a waveform, even under a name attach_stimuli would
+    // pick, is not silently left out
+    compartments
+        .attach(AttachmentKind::Stimulus, "stimuli[1]", 2, 0.5, 0.5)
+        .unwrap();
+    let error = compartments.simulate(0.1, 1.0).unwrap_err();
+    assert!(error.contains("no waveform"), "{}", error);
+    compartments.attach_stimuli(2, vec![0.0; 10]).unwrap();
+    assert!(compartments.simulate(0.1, 1.0).is_err());
+
+    // Two zero-length compartments next to each other cannot be coupled
+    let skeleton = swc_reader_from_bytes(
+        b"1 1 0 0 0 5 -1\n2 3 0 0 0 1 1\n3 3 20 0 0 1 2\n",
+        &ReaderOptions::default(),
+    )
+    .unwrap();
+    let compartments = Compartments::from_skeleton(skeleton);
+    assert_eq!(compartments.components[2].length, 0.0);
+    let error = compartments.simulate(0.1, 1.0).unwrap_err();
+    assert!(error.contains("zero length"), "{}", error);
+}
@@ -0,0 +1,140 @@
+use std::fs;
+use std::path::PathBuf;
+
+use compartment_rs::quick::QuickLookOptions;
+use compartment_rs::snapshot::quick_passive_snapshot;
+use compartment_rs::standardize::Dataset;
+use compartment_rs::{Compartments, ReaderOptions, swc_reader_from_bytes};
+
+fn model(swc: &str) -> Compartments {
+    Compartments::from_skeleton(
+        swc_reader_from_bytes(swc.as_bytes(), &ReaderOptions::default()).unwrap(),
+    )
+}
+
+/// A point soma with straight branches of `lengths` µm off it in
+/// different directions, each 1 µm thick in 10 µm nodes
+fn branches(lengths: &[usize]) -> String {
+    let mut swc = String::from("1 1 0 0 0 5 -1\n");
+    let mut id = 1;
+    for (b, &length) in lengths.iter().enumerate() {
+        let (sin, cos) = (b as f64).sin_cos();
+        let mut parent = 1;
+        for k in 1..=length / 10 {
+            id += 1;
+            let r = 10.0 * k as f64;
+            swc.push_str(&format!(
+                "{} 3 {} {} 0 0.5 {}\n",
+                id,
+                r * cos,
+                r * sin,
+                parent
+            ));
+            parent = id;
+        }
+    }
+    swc
+}
+
+fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("snapshot-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn a_single_compartment_gives_rc_and_one_over_g() {
+    let compartments = model("1 1 0 0 0 5 -1\n2 3 20 0 0 5 1\n");
+    let snapshot = quick_passive_snapshot(&compartments).unwrap();
+
+    let options = QuickLookOptions::default();
+    let (cm, gm) = (options.capacitance.value(), options.conductance.value());
+    // µF over S is µs
+    let rc = cm / gm * 1e-3;
+    assert!(
+        ((snapshot.tau - rc) / rc).abs() < 0.01,
+        "{} against {}",
+        snapshot.tau,
+        rc
+    );
+    // In nS, then MΩ. The point soma reaches the membrane through half
+    // the cylinder, 10 µm long and 10 µm across, in series; Ω·cm over µm
+    // is 1e-2 MΩ.
+    let g = gm * compartments.components[2].membrane_area() * 10.0;
+    let axial = options.resistance.value() * 10.0 / (std::f64::consts::PI * 100.0 / 4.0) * 1e-2;
+    let expected = 1e3 / g + axial;
+    assert!(
+        ((snapshot.input_resistance - expected) / expected).abs() < 1e-6,
+        "{} against {}",
+        snapshot.input_resistance,
+        expected
+    );
+    assert!(snapshot.fit_points > 10);
+    assert_eq!(snapshot.tips.len(), 1);
+    // Only the axial drop is lost on the way
+    let attenuation = 1e3 / g / expected;
+    assert!((snapshot.tips[0].attenuation - attenuation).abs() < 1e-6);
+}
+
+#[test]
+fn attenuation_falls_with_path_distance() {
+    let snapshot = quick_passive_snapshot(&model(&branches(&[200, 800, 100, 400]))).unwrap();
+    let distances: Vec<f64> = snapshot.tips.iter().map(|t| t.path_distance).collect();
+    assert!(
+        distances
+            .iter()
+            .zip([800.0, 400.0, 200.0])
+            .all(|(d, e)| (d - e).abs() < 1e-9),
+        "{:?}",
+        distances
+    );
+    let attenuations: Vec<f64> = snapshot.tips.iter().map(|t| t.attenuation).collect();
+    assert!(
+        attenuations.windows(2).all(|w| w[0] < w[1]) && attenuations[2] < 1.0,
+        "{:?}",
+        attenuations
+    );
+    // The dendrites load the soma but share its membrane, so tau is the
+    // membrane's still
+    let options = QuickLookOptions::default();
+    let rc = options.capacitance.value() / options.conductance.value() * 1e-3;
+    assert!(((snapshot.tau - rc) / rc).abs() < 0.01, "{}", snapshot.tau);
+}
+
+#[test]
+fn a_dataset_is_snapshotted_in_order() {
+    let dir = scratch("dataset");
+    fs::write(dir.join("a.swc"), branches(&[100])).unwrap();
+    fs::write(dir.join("b.swc"), "not swc\n").unwrap();
+    fs::write(dir.join("c.swc"), branches(&[300, 50])).unwrap();
+    let dataset = Dataset::from_dir(&dir).unwrap();
+    let snapshots = dataset.passive_snapshots(2);
+    assert_eq!(snapshots.len(), 3);
+    assert!(snapshots[0].0.ends_with("a.swc") && snapshots[2].0.ends_with("c.swc"));
+    assert!(snapshots[1].1.is_err());
+    let (a, c) = (
+        snapshots[0].1.as_ref().unwrap(),
+        snapshots[2].1.as_ref().unwrap(),
+    );
+    // More membrane, less resistance
+    assert!(c.input_resistance < a.input_resistance);
+    assert_eq!(dataset.passive_snapshots(1), snapshots);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn inspect_adds_the_snapshot_to_its_line() {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_compartment-rs"))
+        .args(["inspect", "data/basic.swc", "--passive"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    let line = String::from_utf8(output.stdout).unwrap();
+    assert!(line.contains("15 nodes"), "{}", line);
+    assert!(
+        line.contains("MΩ, tau 33.33 ms, tip attenuation "),
+        "{}",
+        line
+    );
+}
@@ -0,0 +1,266 @@
+mod common;
+
+use common::{Membrane, assert_close, deviation};
+use compartment_rs::solver::{RESTING_POTENTIAL, SimError, Simulation};
+use compartment_rs::units::{MicroFaradPerCm2, OhmCm, SiemensPerCm2};
+use compartment_rs::{Channel, Compartments, ReaderOptions, swc_reader_from_bytes};
+
+/// Axial resistivity of every model here, in Ω·cm
+const RA: f64 = 100.0;
+/// The membrane of every model here
+const MEMBRANE: Membrane = Membrane {
+    cm: 1.0,
+    gm: 1e-4,
+    e: -70.0,
+};
+
+/// `swc` with every compartment passive, `ra` its axial resistivity
+fn passive(swc: &[u8], ra: f64) -> Compartments {
+    let skeleton = swc_reader_from_bytes(swc, &ReaderOptions::default()).unwrap();
+    let mut compartments = Compartments::from_skeleton(skeleton);
+    for c in compartments.components.iter_mut() {
+        c.set_channel(Channel::passive(
+            OhmCm::new(ra).unwrap(),
+            MicroFaradPerCm2::new(MEMBRANE.cm).unwrap(),
+            SiemensPerCm2::new(MEMBRANE.gm).unwrap(),
+        ));
+    }
+    compartments
+}
+
+/// Point soma with one passive cylinder, 20 µm long and 2 µm thick, at
+/// index 2, leaking towards -70 mV
+fn passive_cylinder() -> Compartments {
+    passive(b"1 1 0 0 0 5 -1\n2 3 20 0 0 1 1\n", RA)
+}
+
+#[test]
+fn passive_compartments_settle_at_ohms_law() {
+    let compartments = passive_cylinder();
+    let area = common::cylinder_area(20.0, 2.0);
+    assert!((compartments.components[2].membrane_area() - area).abs() < 1e-9);
+    let mut simulation = Simulation::new(&compartments, 0.1).unwrap();
+    let steps = 2000;
+    let result = simulation.run(steps, &[(2, vec![0.01; steps])]).unwrap();
+    let expected =
+        common::single_compartment_step(MEMBRANE, area, 0.01, RESTING_POTENTIAL, f64::INFINITY);
+    let v = &result.voltages[2];
+    assert_eq!(v[0], RESTING_POTENTIAL);
+    assert_close("steady state", &v[steps..], &[expected], 1e-6);
+    // The point soma has no membrane and follows its only neighbour
+    assert_close("soma", &result.voltages[1][steps..], &[expected], 1e-6);
+    assert_eq!(result.manifest.runtime.solver, "backward_euler");
+}
+
+#[test]
+fn a_single_compartment_follows_its_step_response() {
+    let area = common::cylinder_area(20.0, 2.0);
+    let (dt, steps, stimulus) = (0.005, 6000, 0.01);
+    let mut simulation = Simulation::new(&passive_cylinder(), dt).unwrap();
+    let result = simulation
+        .run(steps, &[(2, vec![stimulus; steps])])
+        .unwrap();
+    let expected: Vec<f64> = (0..=steps)
+        .map(|s| {
+            let t = s as f64 * dt;
+            common::single_compartment_step(MEMBRANE, area, stimulus, RESTING_POTENTIAL, t)
+        })
+        .collect();
+    // Backward Euler lags by about dt / 2 tau of the swing
+    assert_close("step response", &result.voltages[2], &expected, 0.02);
+}
+
+#[test]
+fn clamps_hold_and_report_their_current() {
+    let compartments = passive_cylinder();
+    let g = compartments.components[2].membrane_conductance() * 10.0;
+    let mut simulation = Simulation::new(&compartments, 0.1).unwrap();
+    simulation.clamp(2, Some(-20.0)).unwrap();
+    assert_eq!(simulation.clamp_current(1), None);
+    for _ in 0..100 {
+        simulation.step().unwrap();
+        assert_eq!(simulation.voltages()[2], -20.0);
+    }
+    // Only the leak is left to feed, in nA
+    let leak = g * 50.0 * 1e-3;
+    let current = simulation.clamp_current(2).unwrap();
+    assert!((current - leak).abs() < 1e-9 * leak, "{}", current);
+
+    simulation.clamp(2, None).unwrap();
+    simulation.step().unwrap();
+    assert!(simulation.voltages()[2] < -20.0);
+    assert!((simulation.time() - 10.1).abs() < 1e-9);
+}
+
+#[test]
+fn bad_inputs_are_errors() {
+    let compartments = passive_cylinder();
+    assert!(Simulation::new(&compartments, 0.0).is_err());
+    assert!(Simulation::new(&compartments, f64::NAN).is_err());
+    let mut simulation = Simulation::new(&compartments, 0.1).unwrap();
+    assert!(simulation.inject(0, 1.0).is_err());
+    assert!(simulation.clamp(3, Some(0.0)).is_err());
+    assert!(simulation.run(10, &[(2, vec![0.0; 9])]).is_err());
+    assert!(simulation.run(10, &[(5, vec![0.0; 10])]).is_err());
+}
+
+/// Point soma, then two passive cylinders, 100 µm long and 1 µm thick, at
+/// indices 2 and 3, one after the other
+fn passive_pair() -> Compartments {
+    passive(
+        b"1 1 0 0 0 5 -1\n2 3 100 0 0 0.5 1\n3 3 200 0 0 0.5 2\n",
+        RA,
+    )
+}
+
+#[test]
+fn axial_current_probes_follow_the_solver() {
+    let compartments = passive_pair();
+    // All in nS, as the solver has them
+    let gm = MEMBRANE.conductance(common::cylinder_area(100.0, 1.0));
+    let ga = common::axial_conductance(RA, 100.0, 1.0);
+    let (dt, steps, stimulus) = (0.1, 3000, 0.02);
+    let mut simulation = Simulation::new(&compartments, dt).unwrap();
+    simulation.add_axial_current_probe(2, 3).unwrap();
+    simulation.add_axial_current_probe(1, 2).unwrap();
+    let result = simulation
+        .run(steps, &[(2, vec![stimulus; steps])])
+        .unwrap();
+    let ((pair, current), (_, from_soma)) = (&result.axial_currents[0], &result.axial_currents[1]);
+    assert_eq!(*pair, (2, 3));
+    assert_eq!(current.len(), steps + 1);
+
+    let c = MEMBRANE.capacitance(common::cylinder_area(100.0, 1.0));
+    let [v2, v3] = common::two_compartments(
+        [c; 2],
+        [gm; 2],
+        ga,
+        MEMBRANE.e,
+        [stimulus, 0.0],
+        [RESTING_POTENTIAL; 2],
+        f64::INFINITY,
+    );
+    let expected = ga * (v2 - v3) * 1e-3;
+    assert_close(
+        "boundary current",
+        &current[steps..],
+        &[expected],
+        1e-6 * expected,
+    );
+
+    // Kirchhoff at compartment 2 on every step: capacitive and leak
+    // currents out, the stimulus and the axial currents in
+    let v = &result.voltages;
+    let c2 = compartments.components[2].membrane_area() * 1e-2;
+    for s in 0..steps {
+        let capacitive = c2 * (v[2][s + 1] - v[2][s]) / dt * 1e-3;
+        let leak = gm * (v[2][s + 1] - MEMBRANE.e) * 1e-3;
+        let balance = stimulus + from_soma[s + 1] - current[s + 1] - capacitive - leak;
+        assert!(balance.abs() < 1e-9, "step {}: {}", s, balance);
+    }
+
+    let error = simulation.add_axial_current_probe(3, 2).unwrap_err();
+    assert!(matches!(error, SimError::InvalidInput { .. }), "{}", error);
+    let message = error.to_string();
+    assert!(
+        message.contains('3') && message.contains('2'),
+        "{}",
+        message
+    );
+    assert!(simulation.add_axial_current_probe(1, 3).is_err());
+    assert!(simulation.add_axial_current_probe(2, 4).is_err());
+    assert_eq!(simulation.axial_current(1, 3), None);
+}
+
+/// Voltages of compartments 2 and 3 of a pair 100 and 50 µm long, 1 µm
+/// thick, built with axial resistivity `ra`, against the exact solution for
+/// `RA`, with 0.05 nA injected into the first
+fn coupled_pair(ra: f64) -> (Vec<f64>, Vec<f64>) {
+    let compartments = passive(
+        b"1 1 0 0 0 5 -1\n2 3 100 0 0 0.5 1\n3 3 150 0 0 0.5 2\n",
+        ra,
+    );
+    let areas = [100.0, 50.0].map(|l| common::cylinder_area(l, 1.0));
+    // Centre to centre, through half of each
+    let ga = common::axial_conductance(RA, 75.0, 1.0);
+    let (dt, steps, stimulus) = (0.005, 4000, 0.05);
+    let mut simulation = Simulation::new(&compartments, dt).unwrap();
+    let result = simulation
+        .run(steps, &[(2, vec![stimulus; steps])])
+        .unwrap();
+    let mut actual = Vec::new();
+    let mut expected = Vec::new();
+    for s in 0..=steps {
+        let v = common::two_compartments(
+            areas.map(|a| MEMBRANE.capacitance(a)),
+            areas.map(|a| MEMBRANE.conductance(a)),
+            ga,
+            MEMBRANE.e,
+            [stimulus, 0.0],
+            [RESTING_POTENTIAL; 2],
+            s as f64 * dt,
+        );
+        actual.extend([result.voltages[2][s], result.voltages[3][s]]);
+        expected.extend(v);
+    }
+    (actual, expected)
+}
+
+/// Steady voltages of the soma and a 50 compartment cable 500 µm long, one
+/// space constant, and 1 µm thick behind it, built with axial resistivity
+/// `ra`, against the sealed cable with `RA`, 0.05 nA injected at the soma
+fn sealed_cable(ra: f64) -> (Vec<f64>, Vec<f64>) {
+    let (n, length, stimulus) = (50, 500.0, 0.05);
+    let dx = length / n as f64;
+    let mut swc = String::from("1 1 0 0 0 5 -1\n");
+    for k in 1..=n {
+        swc.push_str(&format!("{} 3 {} 0 0 0.5 {}\n", k + 1, k as f64 * dx, k));
+    }
+    let compartments = passive(swc.as_bytes(), ra);
+    // Backward Euler settles where the continuous system does, so the
+    // step only needs to be short against the run
+    let steps = 1000;
+    let mut simulation = Simulation::new(&compartments, 0.5).unwrap();
+    let result = simulation
+        .run(steps, &[(1, vec![stimulus; steps])])
+        .unwrap();
+    let actual = result.voltages[1..].iter().map(|v| v[steps]).collect();
+    // The soma at 0, then every compartment at its centre
+    let expected = std::iter::once(0.0)
+        .chain((0..n).map(|k| (k as f64 + 0.5) * dx))
+        .map(|x| common::sealed_cable_steady_state(MEMBRANE, RA, 1.0, length, stimulus, x))
+        .collect();
+    (actual, expected)
+}
+
+/// In mV, a few times the time step error of the pair and the
+/// discretization error of the cable
+const PAIR_TOLERANCE: f64 = 0.02;
+const CABLE_TOLERANCE: f64 = 0.01;
+
+#[test]
+fn two_coupled_compartments_follow_the_exact_solution() {
+    let (actual, expected) = coupled_pair(RA);
+    assert_close("coupled pair", &actual, &expected, PAIR_TOLERANCE);
+}
+
+#[test]
+fn a_sealed_cable_settles_to_its_cosh_profile() {
+    let (actual, expected) = sealed_cable(RA);
+    assert_close("sealed cable", &actual, &expected, CABLE_TOLERANCE);
+    // From the soma down to the sealed end
+    assert!(actual.windows(2).all(|w| w[0] > w[1]));
+}
+
+#[test]
+fn a_broken_axial_conductance_is_caught() {
+    // A quarter of the resistivity makes the solver couple compartments by
+    // pi d² / (ra l), the area of the core without its factor of 4
+    for (what, (actual, expected), tolerance) in [
+        ("coupled pair", coupled_pair(RA / 4.0), PAIR_TOLERANCE),
+        ("sealed cable", sealed_cable(RA / 4.0), CABLE_TOLERANCE),
+    ] {
+        let d = deviation(&actual, &expected);
+        assert!(d.max > 10.0 * tolerance, "{} not caught: {:?}", what, d);
+    }
+}
@@ -0,0 +1,134 @@
+use std::f64::consts::PI;
+
+use compartment_rs::soma::{SomaStyle, classify_soma, detect_soma_style};
+use compartment_rs::{
+    Compartments, NodeFlags, ReaderOptions, Skeleton, swc_reader, swc_reader_from_bytes,
+};
+
+const FIXTURES: [(&str, SomaStyle); 4] = [
+    ("data/soma_single.swc", SomaStyle::SinglePoint),
+    ("data/soma_three_point.swc", SomaStyle::ThreePoint),
+    ("data/soma_contour.swc", SomaStyle::Contour),
+    ("data/soma_chain.swc", SomaStyle::Chain),
+];
+
+fn read(path: &str) -> Skeleton {
+    swc_reader(path, &ReaderOptions::default()).unwrap()
+}
+
+fn from_text(swc: &str) -> Skeleton {
+    swc_reader_from_bytes(swc.as_bytes(), &ReaderOptions::default()).unwrap()
+}
+
+#[test]
+fn fixtures_are_classified() {
+    for (path, style) in FIXTURES {
+        let skeleton = read(path);
+        assert_eq!(skeleton.soma_style(), style, "{}", path);
+        assert_eq!(
+            classify_soma(&skeleton.nodes, &skeleton.parent_child_map),
+            Ok(style)
+        );
+    }
+}
+
+#[test]
+fn three_points_make_the_neuromorpho_cylinder() {
+    let skeleton = read("data/soma_three_point.swc");
+    let geometry = skeleton.soma_geometry(SomaStyle::ThreePoint).unwrap();
+    // Length between the outer points, diameter twice the centre radius
+    assert_eq!(geometry.length, 10.0);
+    assert_eq!(geometry.diam, 10.0);
+    assert_eq!(geometry.center, [0.0; 3]);
+    assert_eq!(geometry.axis, [0.0, 1.0, 0.0]);
+
+    let mut compartments = Compartments::from_skeleton(skeleton);
+    let before = compartments.components.len();
+    compartments.model_soma(SomaStyle::ThreePoint).unwrap();
+    assert_eq!(compartments.components.len(), before - 2);
+    let soma = &compartments.components[1];
+    assert_eq!((soma.length, soma.diam), (10.0, 10.0));
+    assert_eq!(
+        (soma.proximal, soma.distal),
+        ([0.0, -5.0, 0.0], [0.0, 5.0, 0.0])
+    );
+    assert!(soma.flags.contains(NodeFlags::SOMA_MERGED));
+    // Both dendrites hang off the one soma compartment
+    assert_eq!(soma.children_idxs.len(), 2);
+    for &c in &soma.children_idxs {
+        assert_eq!(compartments.components[c as usize].parent_idxs, vec![1]);
+    }
+    assert!((soma.membrane_area() - 100.0 * PI).abs() < 1e-9);
+}
+
+#[test]
+fn soma_area_follows_the_convention() {
+    let area = |path: &str, style| read(path).soma_geometry(style).unwrap().area();
+    // The point, three-point and contour conventions all describe a sphere
+    // of radius 5; the chain is a 15 µm cylinder of that radius
+    let expected = [100.0 * PI, 100.0 * PI, 100.0 * PI, 150.0 * PI];
+    for ((path, style), expected) in FIXTURES.iter().zip(expected) {
+        let got = area(path, *style);
+        assert!(
+            (got - expected).abs() < 1e-6 * expected,
+            "{}: {}",
+            path,
+            got
+        );
+
+        let mut compartments = Compartments::from_skeleton(read(path));
+        compartments.model_soma(*style).unwrap();
+        let soma = compartments.components[1].membrane_area();
+        assert!((soma - got).abs() < 1e-9 * got);
+    }
+
+    // Read as the wrong convention, the same files give other areas: the
+    // outline's perimeter frusta, or the chain's first cross section alone
+    let contour_as_chain = area("data/soma_contour.swc", SomaStyle::Chain);
+    assert!(contour_as_chain < 0.5 * area("data/soma_contour.swc", SomaStyle::Contour));
+    let chain_as_point = area("data/soma_chain.swc", SomaStyle::SinglePoint);
+    assert!((chain_as_point - 100.0 * PI).abs() < 1e-9);
+    assert!(
+        read("data/soma_single.swc")
+            .soma_geometry(SomaStyle::ThreePoint)
+            .is_err()
+    );
+}
+
+#[test]
+fn unclear_somata_fall_back_to_a_point() {
+    let dendrites = "4 3 5 0 0 1 1\n5 3 25 0 0 1 4\n";
+    let cases = [
+        // Outer points not one radius from the centre
+        ("1 1 0 0 0 5 -1\n2 1 0 -3 0 5 1\n3 1 0 5 0 5 1\n", "radius"),
+        // A soma node off the tree's soma
+        (
+            "1 1 0 0 0 5 -1\n2 3 0 -3 0 1 1\n3 1 0 -8 0 5 2\n",
+            "not connected",
+        ),
+        // Branching soma nodes
+        (
+            "1 1 0 0 0 5 -1\n2 1 0 -3 0 5 1\n3 1 0 3 0 5 1\n6 1 0 6 0 5 3\n7 1 0 -6 0 5 2\n8 1 3 0 0 5 1\n",
+            "branch",
+        ),
+        // No soma at all
+        ("1 3 0 0 0 5 -1\n2 3 0 -3 0 1 1\n3 3 0 5 0 1 1\n", "root"),
+    ];
+    for (soma, reason) in cases {
+        let skeleton = from_text(&format!("{}{}", soma, dendrites));
+        let err = classify_soma(&skeleton.nodes, &skeleton.parent_child_map).unwrap_err();
+        assert!(err.contains(reason), "{}", err);
+        assert_eq!(
+            detect_soma_style(&skeleton.nodes, &skeleton.parent_child_map),
+            SomaStyle::SinglePoint
+        );
+    }
+
+    // Treated as a point, the other soma nodes stay as they were
+    let skeleton = from_text(&format!("{}{}", cases[0].0, dendrites));
+    let mut compartments = Compartments::from_skeleton(skeleton);
+    let before = compartments.components.len();
+    let geometry = compartments.model_soma(SomaStyle::SinglePoint).unwrap();
+    assert_eq!(compartments.components.len(), before);
+    assert_eq!((geometry.length, geometry.diam), (10.0, 10.0));
+}
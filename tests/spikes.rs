@@ -0,0 +1,102 @@
+use compartment_rs::spikes::{SpikeTrainSource, sampled_envelope, sub_seed};
+
+fn poisson(rate_hz: f64, stop: f64, seed: u64) -> SpikeTrainSource {
+    SpikeTrainSource::Poisson {
+        rate_hz,
+        start: 0.0,
+        stop,
+        seed,
+    }
+}
+
+#[test]
+fn poisson_rate_is_within_statistical_bounds() {
+    // 20 Hz for 500 s: 10000 expected spikes, standard deviation 100
+    let events = poisson(20.0, 500_000.0, 441).events().unwrap();
+    assert!(
+        (events.len() as f64 - 10_000.0).abs() < 400.0,
+        "{}",
+        events.len()
+    );
+    assert!(events.windows(2).all(|w| w[0] < w[1]));
+    assert!(events.iter().all(|&t| (0.0..500_000.0).contains(&t)));
+
+    // Intervals are exponential: coefficient of variation 1
+    let intervals: Vec<f64> = events.windows(2).map(|w| w[1] - w[0]).collect();
+    let mean = intervals.iter().sum::<f64>() / intervals.len() as f64;
+    let var = intervals.iter().map(|i| (i - mean).powi(2)).sum::<f64>() / intervals.len() as f64;
+    assert!((var.sqrt() / mean - 1.0).abs() < 0.05);
+}
+
+#[test]
+fn same_seed_same_train() {
+    let a = poisson(50.0, 10_000.0, 7).events().unwrap();
+    let b = poisson(50.0, 10_000.0, 7).events().unwrap();
+    let c = poisson(50.0, 10_000.0, 8).events().unwrap();
+    assert_eq!(a, b);
+    assert_ne!(a, c);
+}
+
+#[test]
+fn sub_seeds_are_stable_and_distinct() {
+    let seeds: Vec<u64> = (0..1000).map(|i| sub_seed(441, i)).collect();
+    let mut unique = seeds.clone();
+    unique.sort();
+    unique.dedup();
+    assert_eq!(unique.len(), seeds.len());
+    // Order of derivation does not matter
+    assert_eq!(sub_seed(441, 500), seeds[500]);
+    assert_ne!(sub_seed(442, 0), seeds[0]);
+}
+
+#[test]
+fn from_times_delivers_exactly_those_times() {
+    let source = SpikeTrainSource::FromTimes(vec![12.5, 3.0, 7.25]);
+    assert_eq!(source.events().unwrap(), vec![3.0, 7.25, 12.5]);
+    assert_eq!(
+        source.events_with_envelope(|_| 0.0).unwrap(),
+        vec![3.0, 7.25, 12.5]
+    );
+    assert!(
+        SpikeTrainSource::FromTimes(vec![f64::NAN])
+            .events()
+            .is_err()
+    );
+}
+
+#[test]
+fn regular_train_is_evenly_spaced() {
+    let source = SpikeTrainSource::Regular {
+        rate_hz: 40.0,
+        start: 10.0,
+        stop: 110.0,
+    };
+    assert_eq!(source.events().unwrap(), vec![10.0, 35.0, 60.0, 85.0]);
+    let enveloped = source.events_with_envelope(|_| 1.0).unwrap();
+    assert_eq!(enveloped.len(), 4);
+    for (a, b) in enveloped.iter().zip([10.0, 35.0, 60.0, 85.0]) {
+        assert!((a - b).abs() < 1e-9);
+    }
+}
+
+#[test]
+fn envelope_scales_the_rate() {
+    let source = poisson(40.0, 100_000.0, 3);
+    let flat = source.events_with_envelope(|_| 1.0).unwrap().len() as f64;
+    let half = source.events_with_envelope(|_| 0.5).unwrap().len() as f64;
+    // 4000 and 2000 expected
+    assert!((flat - 4000.0).abs() < 250.0, "{}", flat);
+    assert!((half - 2000.0).abs() < 180.0, "{}", half);
+
+    // Off for the first half, doubled for the second
+    let envelope = sampled_envelope(50_000.0, vec![0.0, 2.0]);
+    let events = source.events_with_envelope(envelope).unwrap();
+    assert!(events.iter().all(|&t| t >= 50_000.0));
+    assert!((events.len() as f64 - 4000.0).abs() < 250.0);
+}
+
+#[test]
+fn negative_rate_is_rejected() {
+    assert!(poisson(-1.0, 10.0, 0).events().is_err());
+    assert!(poisson(0.0, 10.0, 0).events().unwrap().is_empty());
+}
@@ -0,0 +1,84 @@
+use compartment_rs::{
+    Channel, Compartments, NodeFilter, ReaderOptions, StructureIdentifier, swc_reader,
+};
+
+fn passive_cell() -> Compartments {
+    let skeleton = swc_reader("data/basic.swc", &ReaderOptions::default()).unwrap();
+    let mut compartments = Compartments::from_skeleton(skeleton);
+    for c in compartments.components.iter_mut() {
+        let mut channel = Channel::default();
+        channel.resistance = 100.0;
+        channel.capacitance = 1.0;
+        channel.conductance = 1e-4;
+        c.set_channel(channel);
+    }
+    compartments
+}
+
+fn dendrites() -> NodeFilter {
+    NodeFilter::new()
+        .structure(StructureIdentifier::BasalDendrite)
+        .structure(StructureIdentifier::ApicalDendrite)
+}
+
+fn is_dendrite(structure: StructureIdentifier) -> bool {
+    matches!(
+        structure,
+        StructureIdentifier::BasalDendrite | StructureIdentifier::ApicalDendrite
+    )
+}
+
+#[test]
+fn doubles_dendritic_capacitance_only() {
+    let before = passive_cell();
+    let mut after = passive_cell();
+    let changed = after.apply_spine_correction(&dendrites(), 2.0).unwrap();
+    assert_eq!(changed, 11);
+
+    let (cap_before, cap_after) = (before.capacitances(), after.capacitances());
+    // Index 0 is the zero-sized dummy root
+    for (i, c) in after.components.iter().enumerate().skip(1) {
+        let expected = if is_dendrite(c.structure) { 2.0 } else { 1.0 };
+        assert!((cap_after[i] - expected * cap_before[i]).abs() < 1e-9);
+        assert_eq!(
+            c.axial_resistance(),
+            before.components[i].axial_resistance()
+        );
+    }
+}
+
+#[test]
+fn input_resistance_drops() {
+    // Isopotential limit of the steady state: the whole membrane in parallel
+    let input_resistance = |c: &Compartments| {
+        1.0 / c
+            .components
+            .iter()
+            .map(|c| c.membrane_conductance())
+            .sum::<f64>()
+    };
+    let before = passive_cell();
+    let mut after = passive_cell();
+    after.apply_spine_correction(&dendrites(), 2.0).unwrap();
+    assert!(input_resistance(&after) < input_resistance(&before));
+}
+
+#[test]
+fn repeated_correction_multiplies() {
+    let mut compartments = passive_cell();
+    compartments
+        .apply_spine_correction(&dendrites(), 2.0)
+        .unwrap();
+    compartments
+        .apply_spine_correction(&dendrites(), 1.5)
+        .unwrap();
+    for c in &compartments.components {
+        let expected = if is_dendrite(c.structure) { 3.0 } else { 1.0 };
+        assert_eq!(c.area_factor, expected);
+    }
+    assert!(
+        compartments
+            .apply_spine_correction(&dendrites(), 0.0)
+            .is_err()
+    );
+}
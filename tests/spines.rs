@@ -0,0 +1,257 @@
+use compartment_rs::channels::Passive;
+use compartment_rs::solver::{RESTING_POTENTIAL, Simulation};
+use compartment_rs::spines::{Spine, SpineShape};
+use compartment_rs::{
+    Channel, ChannelType, Compartments, NodeFilter, ReaderOptions, StructureIdentifier, swc_reader,
+    swc_reader_from_bytes,
+};
+
+/// Passive membrane resting at `RESTING_POTENTIAL`
+fn passive(conductance: f64, capacitance: f64) -> Channel {
+    let mut channel = Channel::default();
+    channel.channel_type = ChannelType::Passive(Passive {
+        e: RESTING_POTENTIAL,
+    });
+    channel.resistance = 100.0;
+    channel.capacitance = capacitance;
+    channel.conductance = conductance;
+    channel
+}
+
+/// A soma and a 100 µm dendrite of five compartments
+fn dendrite() -> Compartments {
+    let swc: String = (2..=6)
+        .map(|i| format!("{} 3 {} 0 0 1 {}\n", i, (i - 1) * 20, i - 1))
+        .collect();
+    let skeleton = swc_reader_from_bytes(
+        format!("1 1 0 0 0 5 -1\n{}", swc).as_bytes(),
+        &ReaderOptions::default(),
+    )
+    .unwrap();
+    with_membrane(Compartments::from_skeleton(skeleton))
+}
+
+fn with_membrane(mut compartments: Compartments) -> Compartments {
+    for c in compartments.components.iter_mut() {
+        c.set_channel(passive(1e-4, 1.0));
+    }
+    compartments
+}
+
+/// Runs until the voltages stop changing, returning the compartment and
+/// head voltages
+fn steady_state(simulation: &mut Simulation) -> (Vec<f64>, Vec<f64>) {
+    for _ in 0..20_000 {
+        simulation.step().unwrap();
+    }
+    (simulation.voltages().to_vec(), simulation.head_voltages())
+}
+
+#[test]
+fn the_neck_divides_the_synaptic_drive() {
+    let mut compartments = dendrite();
+    // No membrane current on the spine, so the neck and the synapse are
+    // two resistors in series between the shaft and the reversal
+    let shape = SpineShape {
+        channel: passive(0.0, 1.0),
+        ..SpineShape::default()
+    };
+    let g_neck = shape.neck_conductance();
+    // In MΩ
+    assert!((1e3 / g_neck - 127.3).abs() < 0.1, "{} MΩ", 1e3 / g_neck);
+    let k = compartments
+        .add_spine(Spine {
+            idx: 3,
+            x: 0.5,
+            shape,
+        })
+        .unwrap();
+    assert_eq!(k, 0);
+
+    let mut simulation = Simulation::new(&compartments, 0.025).unwrap();
+    let (g_syn, e_syn) = (2.0, 0.0);
+    simulation.set_spine_synapse(0, g_syn, e_syn).unwrap();
+    simulation.clamp(3, Some(RESTING_POTENTIAL)).unwrap();
+    let (v, heads) = steady_state(&mut simulation);
+
+    let shaft = v[3];
+    let current = (e_syn - shaft) / (1.0 / g_neck + 1.0 / g_syn);
+    let expected = shaft + current / g_neck;
+    assert!(
+        (heads[0] - expected).abs() < 1e-6,
+        "{} vs {}",
+        heads[0],
+        expected
+    );
+    let [neck, head] = simulation.spine_voltages(0).unwrap();
+    assert!((neck - (shaft + head) / 2.0).abs() < 1e-6);
+    // The clamp supplies what flows out through the synapse, in nA
+    let supplied = simulation.clamp_current(3).unwrap();
+    assert!((supplied + current * 1e-3).abs() < 1e-9, "{}", supplied);
+}
+
+#[test]
+fn a_thin_neck_isolates_the_head() {
+    let depolarization = |neck_diam: f64| {
+        let mut compartments = dendrite();
+        let shape = SpineShape {
+            neck_diam,
+            channel: passive(1e-4, 1.0),
+            ..SpineShape::default()
+        };
+        compartments
+            .add_spine(Spine {
+                idx: 4,
+                x: 0.3,
+                shape,
+            })
+            .unwrap();
+        let mut simulation = Simulation::new(&compartments, 0.025).unwrap();
+        simulation.set_spine_synapse(0, 0.5, 0.0).unwrap();
+        let (v, heads) = steady_state(&mut simulation);
+        (heads[0] - RESTING_POTENTIAL, v[4] - RESTING_POTENTIAL)
+    };
+    let (wide_head, wide_shaft) = depolarization(0.5);
+    let (thin_head, thin_shaft) = depolarization(0.05);
+    // A wide neck passes almost all of the head's depolarization on
+    assert!(wide_head > 0.0 && wide_shaft > 0.9 * wide_head);
+    // A thin one keeps it in the head, leaving the shaft with less
+    assert!(thin_head > wide_head);
+    assert!(thin_shaft < wide_shaft);
+    assert!(
+        thin_head - thin_shaft > 5.0 * (wide_head - wide_shaft),
+        "{} vs {}",
+        thin_head,
+        thin_shaft
+    );
+}
+
+fn dendrites() -> NodeFilter {
+    NodeFilter::new()
+        .structure(StructureIdentifier::BasalDendrite)
+        .structure(StructureIdentifier::ApicalDendrite)
+}
+
+#[test]
+fn bulk_placement_matches_the_density() {
+    let skeleton = swc_reader("data/basic.swc", &ReaderOptions::default()).unwrap();
+    let build = || Compartments::from_skeleton(skeleton.clone());
+    let mut compartments = build();
+    let density = 2.0;
+    let added = compartments
+        .add_spines(&dendrites(), density, &SpineShape::default(), 7)
+        .unwrap();
+    assert_eq!(added, compartments.spines().len());
+
+    let length: f64 = compartments
+        .components
+        .iter()
+        .filter(|c| {
+            matches!(
+                c.structure,
+                StructureIdentifier::BasalDendrite | StructureIdentifier::ApicalDendrite
+            )
+        })
+        .map(|c| c.length)
+        .sum();
+    let expected = density * length;
+    // Poisson, so within four standard deviations
+    assert!(
+        (added as f64 - expected).abs() < 4.0 * expected.sqrt(),
+        "{} spines over {} µm",
+        added,
+        length
+    );
+    for spine in compartments.spines() {
+        let c = &compartments.components[spine.idx];
+        assert_ne!(c.structure, StructureIdentifier::Axon);
+        assert!((0.0..1.0).contains(&spine.x));
+    }
+    let mean_x = compartments.spines().iter().map(|s| s.x).sum::<f64>() / added as f64;
+    assert!((mean_x - 0.5).abs() < 0.1, "{}", mean_x);
+
+    let placed = |seed: u64| {
+        let mut c = build();
+        c.add_spines(&dendrites(), density, &SpineShape::default(), seed)
+            .unwrap();
+        c.spines().iter().map(|s| (s.idx, s.x)).collect::<Vec<_>>()
+    };
+    assert_eq!(placed(7), placed(7));
+    assert_ne!(placed(7), placed(8));
+    assert!(
+        build()
+            .add_spines(&dendrites(), -1.0, &SpineShape::default(), 7)
+            .is_err()
+    );
+
+    // Spines keep to their compartments as those merge
+    let before: Vec<usize> = compartments.spines().iter().map(|s| s.idx).collect();
+    compartments.coarsen(9).unwrap();
+    assert_eq!(compartments.spines().len(), added);
+    let map = compartments.last_index_map().unwrap();
+    for (spine, old) in compartments.spines().iter().zip(before) {
+        assert!(map.forward(old).unwrap().iter().any(|l| l.new == spine.idx));
+    }
+}
+
+#[test]
+fn detached_spines_change_nothing() {
+    let skeleton = swc_reader("data/basic.swc", &ReaderOptions::default()).unwrap();
+    let plain = with_membrane(Compartments::from_skeleton(skeleton));
+    let steps = 400;
+    let stimuli = vec![(5, vec![0.2; steps])];
+    let run = |compartments: &Compartments| {
+        Simulation::new(compartments, 0.025)
+            .unwrap()
+            .run(steps, &stimuli)
+            .unwrap()
+    };
+    let reference = run(&plain);
+    assert!(reference.head_voltages.is_empty());
+
+    // A neck of zero diameter conducts nothing
+    let mut cut = plain.clone();
+    let shape = SpineShape {
+        neck_diam: 0.0,
+        ..SpineShape::default()
+    };
+    assert_eq!(shape.neck_conductance(), 0.0);
+    cut.add_spines(&dendrites(), 1.0, &shape, 3).unwrap();
+    let result = run(&cut);
+    assert_eq!(result.voltages, reference.voltages);
+    assert_eq!(result.head_voltages.len(), cut.spines().len());
+    for trace in &result.head_voltages {
+        assert_eq!(trace.len(), steps + 1);
+    }
+
+    // Neither does a spine without membrane, once settled
+    let mut bare = plain.clone();
+    let shape = SpineShape {
+        channel: passive(0.0, 0.0),
+        ..SpineShape::default()
+    };
+    bare.add_spines(&dendrites(), 1.0, &shape, 3).unwrap();
+    let result = run(&bare);
+    for (a, b) in result.voltages.iter().zip(&reference.voltages) {
+        for (a, b) in a.iter().zip(b) {
+            assert!((a - b).abs() < 1e-9);
+        }
+    }
+
+    let mut simulation = Simulation::new(&bare, 0.025).unwrap();
+    assert!(
+        simulation
+            .set_spine_synapse(bare.spines().len(), 1.0, 0.0)
+            .is_err()
+    );
+    assert!(simulation.set_spine_synapse(0, -1.0, 0.0).is_err());
+    assert!(
+        bare.clone()
+            .add_spine(Spine {
+                idx: 0,
+                x: 0.5,
+                shape: SpineShape::default()
+            })
+            .is_err()
+    );
+}
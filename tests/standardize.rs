@@ -0,0 +1,264 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use compartment_rs::codes::Code;
+use compartment_rs::morphometry::Morphometry;
+use compartment_rs::soma::SomaStyle;
+use compartment_rs::standardize::{
+    BatchReport, Dataset, LengthUnit, Pipeline, RECIPE_FILE, StandardizeOptions, ZeroRadiusPolicy,
+};
+use compartment_rs::{ReaderOptions, Skeleton, StructureIdentifier, swc_reader};
+
+const FIXTURES: &str = "data/standardize";
+const SPACING: f64 = 10.0;
+
+fn out_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("standardize-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+fn options() -> StandardizeOptions {
+    StandardizeOptions {
+        max_spacing: Some(SPACING),
+        ..StandardizeOptions::default()
+    }
+}
+
+fn run(input: &Dataset, out: &Path) -> BatchReport {
+    Pipeline::standardize(options())
+        .with_threads(3)
+        .run(input, out)
+        .unwrap()
+}
+
+fn report_for<'a>(
+    report: &'a BatchReport,
+    name: &str,
+) -> &'a compartment_rs::standardize::FileReport {
+    report
+        .files
+        .iter()
+        .find(|f| f.source.file_name().unwrap() == name)
+        .unwrap()
+}
+
+fn read(path: &Path) -> Skeleton {
+    swc_reader(path, &ReaderOptions::default()).unwrap()
+}
+
+#[test]
+fn mixed_conventions_come_out_alike() {
+    let out = out_dir("alike");
+    let report = run(&Dataset::from_dir(FIXTURES).unwrap(), &out);
+    assert_eq!(report.files.len(), 6);
+    assert_eq!(report.successes().count(), 5);
+
+    // Every fixture but the broken one is the same cell: a 5 µm soma, a
+    // 25 µm dendrite forking into two twigs, and a 40 µm axon
+    let total = 25.0 + 2.0 * 500f64.sqrt() + 40.0;
+    for file in report.successes() {
+        let output = file.output.as_ref().unwrap();
+        let text = fs::read_to_string(output).unwrap();
+        let first = text.lines().find(|l| !l.starts_with('#')).unwrap();
+        assert!(
+            first.starts_with("1 1 ") && first.ends_with(" -1"),
+            "{}",
+            first
+        );
+        assert!(text.contains("# SOURCE_FILE "));
+        assert!(text.contains(&format!("# STANDARDIZE_RECIPE {}", options().digest())));
+
+        let skeleton = read(output);
+        let soma: Vec<_> = skeleton
+            .nodes
+            .iter()
+            .filter(|n| n.structured_identifier == StructureIdentifier::Soma)
+            .collect();
+        assert_eq!(soma.len(), 1, "{}", output.display());
+        assert_eq!(soma[0].parent_id, soma[0].node_id);
+        assert_eq!(soma[0].radius, 5.0);
+        assert_eq!(skeleton.soma_style(), SomaStyle::SinglePoint);
+        assert!(
+            skeleton
+                .nodes
+                .iter()
+                .all(|n| n.radius > 0.0 && n.radius < 10.0)
+        );
+
+        let morphometry = Morphometry::new(&skeleton.nodes);
+        let length = morphometry.total_length();
+        assert!((length - total).abs() < 1e-9 * total, "{}", length);
+        for n in &skeleton.nodes[1..] {
+            let p = &skeleton.nodes[n.parent_id as usize];
+            let d = ((n.x_pos - p.x_pos).powi(2) + (n.y_pos - p.y_pos).powi(2)).sqrt();
+            assert!(d <= SPACING * (1.0 + 1e-9));
+        }
+    }
+    assert_eq!(
+        StandardizeOptions::parse(&fs::read_to_string(out.join(RECIPE_FILE)).unwrap()).unwrap(),
+        options()
+    );
+}
+
+#[test]
+fn reports_name_what_was_done() {
+    let out = out_dir("reports");
+    let report = run(&Dataset::from_dir(FIXTURES).unwrap(), &out);
+    let applied = |name: &str, step: &str| report_for(&report, name).applied(step);
+
+    assert!(applied("nanometres.swc", "convert_units"));
+    assert!(
+        report_for(&report, "nanometres.swc")
+            .detected
+            .contains(&"units nm".to_owned())
+    );
+    assert!(applied("unrooted.swc", "reroot"));
+    assert!(applied("three_point.swc", "single_point_soma"));
+    assert!(
+        report_for(&report, "three_point.swc")
+            .detected
+            .contains(&"soma ThreePoint".to_owned())
+    );
+    assert!(applied("compressed.swc.gz", "repair_zero_radius"));
+    assert!(applied("flywire.swc", "infer_types"));
+    assert!(applied("flywire.swc", "reroot"));
+    // Only what each file needed
+    assert!(!applied("three_point.swc", "convert_units"));
+    assert!(!applied("unrooted.swc", "single_point_soma"));
+    for file in report.successes() {
+        assert!(file.applied("resample"), "{:?}", file);
+    }
+    assert_eq!(
+        report_for(&report, "compressed.swc.gz").output,
+        Some(out.join("compressed.swc"))
+    );
+}
+
+#[test]
+fn failures_are_reported_without_stopping_the_batch() {
+    let out = out_dir("failures");
+    let report = run(&Dataset::from_dir(FIXTURES).unwrap(), &out);
+    let failures: Vec<_> = report.failures().collect();
+    assert_eq!(failures.len(), 1);
+    assert!(failures[0].source.ends_with("broken.swc"));
+    let error = failures[0].error.as_ref().unwrap();
+    assert_eq!(error.code(), Code::DanglingParent);
+    assert!(error.to_string().contains("parent"), "{}", error);
+    assert!(!out.join("broken.swc").exists());
+
+    // Rejecting zero radii fails the gzipped file instead
+    let strict = StandardizeOptions {
+        zero_radius: ZeroRadiusPolicy::Reject,
+        ..options()
+    };
+    let report = Pipeline::standardize(strict)
+        .run(&Dataset::from_dir(FIXTURES).unwrap(), &out)
+        .unwrap();
+    let failed: Vec<_> = report
+        .failures()
+        .map(|f| f.source.file_name().unwrap().to_owned())
+        .collect();
+    assert_eq!(failed, ["broken.swc", "compressed.swc.gz"]);
+    assert_eq!(
+        report_for(&report, "compressed.swc.gz")
+            .error
+            .as_ref()
+            .map(|e| e.code()),
+        Some(Code::RejectedCell)
+    );
+
+    // As do formats that cannot be read and clashing output names
+    let dataset = Dataset::from_paths([
+        "data/standardize/three_point.swc",
+        "data/standardize/missing.asc",
+        "data/standardize/missing.txt",
+    ]);
+    let report = run(&dataset, &out);
+    let codes: Vec<_> = report
+        .failures()
+        .map(|f| f.error.as_ref().unwrap().code())
+        .collect();
+    assert_eq!(codes, [Code::UnsupportedFile, Code::UnsupportedFile]);
+    let dataset = Dataset::from_paths(["data/standardize/unrooted.swc", "data/unrooted.swc.gz"]);
+    assert_eq!(run(&dataset, &out).failures().count(), 2);
+}
+
+#[test]
+fn standardizing_twice_changes_nothing() {
+    let first = out_dir("first");
+    let second = out_dir("second");
+    run(&Dataset::from_dir(FIXTURES).unwrap(), &first);
+    let report = run(&Dataset::from_dir(&first).unwrap(), &second);
+    assert_eq!(report.successes().count(), 5);
+    for file in &report.files {
+        // Nothing left to do but check
+        assert!(file.operations.is_empty(), "{:?}", file);
+        let name = file.source.file_name().unwrap();
+        assert_eq!(
+            fs::read(&file.source).unwrap(),
+            fs::read(second.join(name)).unwrap(),
+            "{:?}",
+            name
+        );
+    }
+}
+
+#[test]
+fn recipes_round_trip() {
+    let options = StandardizeOptions {
+        units: LengthUnit::Nanometers,
+        infer_types: false,
+        zero_radius: ZeroRadiusPolicy::Reject,
+        max_spacing: Some(2.5),
+        normalize_frame: true,
+        ..StandardizeOptions::default()
+    };
+    assert_eq!(
+        StandardizeOptions::parse(&options.to_text()).unwrap(),
+        options
+    );
+    assert_ne!(options.digest(), StandardizeOptions::default().digest());
+    // Left out keys keep their defaults
+    assert_eq!(
+        StandardizeOptions::parse("max_spacing 2\n").unwrap(),
+        StandardizeOptions {
+            max_spacing: Some(2.0),
+            ..StandardizeOptions::default()
+        }
+    );
+    assert!(StandardizeOptions::parse("units furlongs\n").is_err());
+    assert!(StandardizeOptions::parse("max_spacing -1\n").is_err());
+    assert!(StandardizeOptions::parse("soma keep\nsoma keep\n").is_err());
+    assert!(StandardizeOptions::parse("colour blue\n").is_err());
+}
+
+#[test]
+fn the_cli_runs_the_same_pipeline() {
+    let out = out_dir("cli");
+    let recipe = out_dir("cli-recipe");
+    fs::create_dir_all(&recipe).unwrap();
+    fs::write(recipe.join("recipe.txt"), options().to_text()).unwrap();
+    let status = Command::new(env!("CARGO_BIN_EXE_compartment-rs"))
+        .args(["standardize", FIXTURES])
+        .arg(&out)
+        .arg("--options")
+        .arg(recipe.join("recipe.txt"))
+        .output()
+        .unwrap();
+    // broken.swc fails, the rest go through
+    assert_eq!(status.status.code(), Some(1));
+    let stdout = String::from_utf8(status.stdout).unwrap();
+    assert!(stdout.contains("failed data/standardize/broken.swc"));
+    assert_eq!(stdout.lines().filter(|l| l.starts_with("ok ")).count(), 5);
+
+    let library = out_dir("library");
+    run(&Dataset::from_dir(FIXTURES).unwrap(), &library);
+    for name in ["three_point.swc", "nanometres.swc", "compressed.swc"] {
+        assert_eq!(
+            fs::read(out.join(name)).unwrap(),
+            fs::read(library.join(name)).unwrap()
+        );
+    }
+}
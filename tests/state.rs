@@ -0,0 +1,204 @@
+use compartment_rs::channels::{ChannelType, Dynamics, HodgkinHuxley};
+use compartment_rs::solver::{RESTING_POTENTIAL, Simulation};
+use compartment_rs::stochastic::GatingMode;
+use compartment_rs::{
+    Channel, Code, Compartments, ReaderOptions, RunLog, StateError, swc_reader_from_bytes,
+};
+
+const DT: f64 = 0.025;
+
+/// Point soma with two HH cylinders, the second compartment with `gating`
+fn cell(gating: GatingMode) -> Compartments {
+    let skeleton = swc_reader_from_bytes(
+        b"1 1 0 0 0 5 -1\n2 3 20 0 0 1 1\n3 3 40 0 0 0.5 2\n",
+        &ReaderOptions::default(),
+    )
+    .unwrap();
+    let mut compartments = Compartments::from_skeleton(skeleton);
+    for (i, c) in compartments.components.iter_mut().enumerate() {
+        let mut channel = Channel::default();
+        channel.channel_type = ChannelType::HodgkinHuxley(HodgkinHuxley {
+            gating: if i == 2 {
+                gating
+            } else {
+                GatingMode::default()
+            },
+            ..HodgkinHuxley::new()
+        });
+        channel.resistance = 100.0;
+        channel.capacitance = 1.0;
+        c.set_channel(channel);
+    }
+    compartments
+}
+
+fn stimulus(steps: usize) -> Vec<f64> {
+    (0..steps)
+        .map(|s| if s < 100 { 0.2 } else { 0.0 })
+        .collect()
+}
+
+#[test]
+fn stepping_and_reading_reproduces_a_run() {
+    let compartments = cell(GatingMode::default());
+    let steps = 400;
+    let result = Simulation::new(&compartments, DT)
+        .unwrap()
+        .run(steps, &[(2, stimulus(steps))])
+        .unwrap();
+
+    let mut simulation = Simulation::new(&compartments, DT).unwrap();
+    let rest = HodgkinHuxley::steady_state(RESTING_POTENTIAL);
+    for (gate, expected) in ["m", "h", "n"].iter().zip(rest) {
+        let path = format!("comp[3].hh.{}", gate);
+        assert_eq!(simulation.get(&path).unwrap(), expected);
+    }
+    for (s, current) in stimulus(steps).into_iter().enumerate() {
+        assert_eq!(simulation.get("t").unwrap(), simulation.time());
+        for idx in 1..4 {
+            let v = simulation.get(&format!("comp[{}].v", idx)).unwrap();
+            assert_eq!(v, result.voltages[idx][s], "comp {} step {}", idx, s);
+        }
+        simulation.inject(2, current).unwrap();
+        simulation.step().unwrap();
+    }
+    assert_eq!(
+        simulation.get("comp[2].v").unwrap(),
+        result.voltages[2][steps]
+    );
+    // The stimulus fired a spike, so the gates have moved off rest
+    assert!(simulation.get("comp[2].hh.h").unwrap() < rest[1]);
+
+    simulation.clamp(3, Some(-20.0)).unwrap();
+    simulation.step().unwrap();
+    assert_eq!(
+        simulation.get("comp[3].i_clamp").unwrap(),
+        simulation.clamp_current(3).unwrap()
+    );
+}
+
+#[test]
+fn paths_list_what_each_compartment_has() {
+    let simulation = Simulation::new(&cell(GatingMode::Binomial), DT).unwrap();
+    assert_eq!(
+        simulation.list_paths("comp[2]."),
+        [
+            "comp[2].v",
+            "comp[2].i_membrane",
+            "comp[2].hh.m",
+            "comp[2].hh.h",
+            "comp[2].hh.n",
+            "comp[2].hh.na_open",
+            "comp[2].hh.k_open"
+        ]
+    );
+    // The point soma has no membrane, so no gates; its membrane current
+    // stays 0
+    assert_eq!(
+        simulation.list_paths("comp[1]"),
+        ["comp[1].v", "comp[1].i_membrane"]
+    );
+    let all = simulation.list_paths("");
+    assert_eq!(all[0], "t");
+    for path in &all {
+        simulation.get(path).unwrap();
+    }
+    let open = simulation.open_channels(2).unwrap();
+    assert_eq!(simulation.get("comp[2].hh.na_open").unwrap(), open.na_open);
+    assert_eq!(simulation.get("comp[2].hh.k_open").unwrap(), open.k_open);
+}
+
+#[test]
+fn invalid_paths_are_errors_with_suggestions() {
+    let simulation = Simulation::new(&cell(GatingMode::default()), DT).unwrap();
+    let err = simulation.get("comp[2].hh.mm").unwrap_err();
+    assert_eq!(err.code(), Code::UnknownStatePath);
+    match &err {
+        StateError::Unknown { suggestions, .. } => {
+            assert_eq!(suggestions[0], "comp[2].hh.m");
+            assert!(suggestions.len() <= 5);
+        }
+        other => panic!("{:?}", other),
+    }
+    assert!(
+        err.to_string().contains("did you mean comp[2].hh.m"),
+        "{}",
+        err
+    );
+
+    let err = simulation.get("comp[9].v").unwrap_err();
+    assert_eq!(
+        err,
+        StateError::NoSuchCompartment {
+            path: "comp[9].v".to_owned(),
+            idx: 9,
+        }
+    );
+    assert_eq!(err.compartment_idx(), Some(9));
+    assert_eq!(
+        simulation.get("comp[0].v").unwrap_err().code(),
+        Code::NoSuchCompartment
+    );
+    assert_eq!(
+        simulation.get("comp[1].hh.m").unwrap_err().code(),
+        Code::StateUnavailable
+    );
+    assert_eq!(
+        simulation.get("comp[2].i_clamp").unwrap_err().code(),
+        Code::StateUnavailable
+    );
+    assert!(matches!(
+        simulation.get("cells[0].comp[2].v").unwrap_err(),
+        StateError::Unknown { .. }
+    ));
+}
+
+#[test]
+fn writes_are_checked_and_logged() {
+    let log_path = std::env::temp_dir().join(format!("state-{}.jsonl", std::process::id()));
+    let mut simulation = Simulation::new(&cell(GatingMode::default()), DT)
+        .unwrap()
+        .with_run_log(RunLog::create(&log_path).unwrap());
+
+    let err = simulation.set("comp[2].hh.m", 1.5).unwrap_err();
+    assert_eq!(err.code(), Code::StateOutOfRange);
+    let before = simulation.get("comp[2].hh.m").unwrap();
+    assert_eq!(
+        simulation.set("comp[2].hh.m", -0.1).unwrap_err().code(),
+        Code::StateOutOfRange
+    );
+    assert_eq!(simulation.get("comp[2].hh.m").unwrap(), before);
+    assert_eq!(
+        simulation.set("comp[2].v", f64::NAN).unwrap_err().code(),
+        Code::StateOutOfRange
+    );
+    assert_eq!(
+        simulation.set("t", 1.0).unwrap_err().code(),
+        Code::ReadOnlyState
+    );
+
+    simulation.set("comp[2].hh.m", 0.25).unwrap();
+    simulation.set("comp[3].v", -70.0).unwrap();
+    assert_eq!(simulation.get("comp[2].hh.m").unwrap(), 0.25);
+    assert_eq!(simulation.get("comp[3].v").unwrap(), -70.0);
+    assert_eq!(simulation.voltages()[3], -70.0);
+
+    let log = std::fs::read_to_string(&log_path).unwrap();
+    let lines: Vec<&str> = log.lines().collect();
+    assert_eq!(lines.len(), 2, "{}", log);
+    assert!(lines[0].contains(
+        "\"op\":\"set_state\",\"args\":{\"path\":\"comp[2].hh.m\",\"value\":0.25,\"time\":0"
+    ));
+    assert!(lines[1].contains("\"path\":\"comp[3].v\""));
+
+    // Stochastic gates follow the channel states and cannot be set
+    let mut noisy = Simulation::new(&cell(GatingMode::Binomial), DT).unwrap();
+    assert_eq!(
+        noisy.set("comp[2].hh.m", 0.5).unwrap_err().code(),
+        Code::StateUnavailable
+    );
+    assert_eq!(
+        noisy.set("comp[2].hh.na_open", 3.0).unwrap_err().code(),
+        Code::ReadOnlyState
+    );
+}
@@ -0,0 +1,49 @@
+use std::collections::BTreeMap;
+
+use compartment_rs::{ReaderOptions, StructureIdentifier, swc_reader};
+
+#[test]
+fn stats_count_types_and_fixed_radii() {
+    let skeleton = swc_reader("data/basic.swc", &ReaderOptions::default()).unwrap();
+    let stats = skeleton.stats.unwrap();
+    assert_eq!(
+        stats.type_counts,
+        BTreeMap::from([
+            (StructureIdentifier::Soma, 1),
+            (StructureIdentifier::Axon, 3),
+            (StructureIdentifier::BasalDendrite, 6),
+            (StructureIdentifier::ApicalDendrite, 5),
+        ])
+    );
+    assert_eq!(
+        stats.zero_radius_fixed,
+        BTreeMap::from([(StructureIdentifier::Axon, 1)])
+    );
+}
+
+#[test]
+fn disabling_stats_leaves_the_skeleton_alone() {
+    let with = swc_reader("data/basic.swc", &ReaderOptions::default()).unwrap();
+    let without = swc_reader(
+        "data/basic.swc",
+        &ReaderOptions {
+            collect_stats: false,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert!(without.stats.is_none());
+    assert_eq!(without.parent_child_map, with.parent_child_map);
+    assert_eq!(without.child_parent_map, with.child_parent_map);
+    for (a, b) in without.nodes.iter().zip(with.nodes.iter()) {
+        assert_eq!(
+            (a.node_id, a.parent_id, a.structured_identifier, a.flags),
+            (b.node_id, b.parent_id, b.structured_identifier, b.flags)
+        );
+        assert_eq!(
+            [a.x_pos, a.y_pos, a.z_pos, a.radius],
+            [b.x_pos, b.y_pos, b.z_pos, b.radius]
+        );
+    }
+    assert_eq!(without.warnings, with.warnings);
+}
@@ -0,0 +1,84 @@
+use compartment_rs::Stimulus;
+
+fn alpha() -> Stimulus {
+    Stimulus::Alpha {
+        onset: 1.03,
+        tau: 2.0,
+        amplitude: 0.5,
+    }
+}
+
+fn biexp() -> Stimulus {
+    Stimulus::BiExp {
+        onset: 2.01,
+        tau_rise: 0.5,
+        tau_decay: 5.0,
+        amplitude: -0.2,
+    }
+}
+
+#[test]
+fn injected_charge_is_exact_at_any_step() {
+    for stimulus in [alpha(), biexp()] {
+        for dt in [0.1, 0.025] {
+            // Long enough for the tail to be far below round-off
+            let rendered = stimulus.render(dt, 400.0).unwrap();
+            assert_eq!(rendered.len(), (400.0 / dt).round() as usize);
+            let charge: f64 = rendered.iter().sum::<f64>() * dt;
+            assert!(
+                (charge - stimulus.charge()).abs() < 1e-10,
+                "{:?} at dt {}: {} vs {}",
+                stimulus,
+                dt,
+                charge,
+                stimulus.charge()
+            );
+        }
+    }
+    // Alpha: amplitude * e * tau
+    assert!((alpha().charge() - 0.5 * std::f64::consts::E * 2.0).abs() < 1e-15);
+}
+
+#[test]
+fn peak_time_matches_the_formula() {
+    assert_eq!(alpha().peak_time(), 2.0);
+    let tp = 0.5 * 5.0 / 4.5 * 10f64.ln();
+    assert!((biexp().peak_time() - tp).abs() < 1e-15);
+
+    for (stimulus, onset, amplitude) in [(alpha(), 1.03, 0.5), (biexp(), 2.01, -0.2)] {
+        // The continuous waveform peaks at the amplitude
+        let peak = stimulus.value_at(onset + stimulus.peak_time());
+        assert!((peak - amplitude).abs() < 1e-14);
+
+        let dt = 0.01;
+        let rendered = stimulus.render(dt, 30.0).unwrap();
+        let argmax = (0..rendered.len())
+            .max_by(|&i, &j| rendered[i].abs().total_cmp(&rendered[j].abs()))
+            .unwrap();
+        let step_centre = (argmax as f64 + 0.5) * dt;
+        assert!((step_centre - (onset + stimulus.peak_time())).abs() <= dt);
+    }
+}
+
+#[test]
+fn trains_superpose() {
+    for stimulus in [alpha(), biexp()] {
+        let dt = 0.025;
+        let first = stimulus.render_train(&[1.03], dt, 50.0).unwrap();
+        let second = stimulus.render_train(&[3.51], dt, 50.0).unwrap();
+        let both = stimulus.render_train(&[3.51, 1.03], dt, 50.0).unwrap();
+        for ((a, b), ab) in first.iter().zip(&second).zip(&both) {
+            assert!((a + b - ab).abs() < 1e-12);
+        }
+    }
+    assert!(
+        Stimulus::BiExp {
+            onset: 0.0,
+            tau_rise: 5.0,
+            tau_decay: 1.0,
+            amplitude: 1.0
+        }
+        .render(0.1, 10.0)
+        .is_err()
+    );
+}
@@ -0,0 +1,163 @@
+use compartment_rs::channels::{ChannelType, Dynamics, HodgkinHuxley};
+use compartment_rs::solver::Simulation;
+use compartment_rs::stochastic::{GatingMode, channel_count};
+use compartment_rs::{Channel, Compartments, ReaderOptions, swc_reader_from_bytes};
+
+/// Point soma with one Hodgkin-Huxley cylinder of 1 µm diameter at index 2
+fn hh_cylinder(length: f64, gating: GatingMode) -> Compartments {
+    let swc = format!("1 1 0 0 0 5 -1\n2 3 {} 0 0 0.5 1\n", length);
+    let skeleton = swc_reader_from_bytes(swc.as_bytes(), &ReaderOptions::default()).unwrap();
+    let mut compartments = Compartments::from_skeleton(skeleton);
+    for c in compartments.components.iter_mut() {
+        let mut channel = Channel::default();
+        channel.channel_type = ChannelType::HodgkinHuxley(HodgkinHuxley {
+            gating,
+            ..HodgkinHuxley::new()
+        });
+        channel.resistance = 100.0;
+        channel.capacitance = 1.0;
+        c.set_channel(channel);
+    }
+    compartments
+}
+
+/// Voltage of the cylinder over `steps` steps of 0.025 ms under a
+/// constant current density, in µA/cm²
+fn trace(compartments: &Compartments, seed: u64, density: f64, steps: usize) -> Vec<f64> {
+    let mut simulation = Simulation::new(compartments, 0.025).unwrap();
+    simulation.reseed(seed);
+    // µA/cm² over µm² is 1e-5 nA
+    let current = density * compartments.components[2].membrane_area() * 1e-5;
+    let result = simulation.run(steps, &[(2, vec![current; steps])]).unwrap();
+    result.voltages[2].clone()
+}
+
+fn spikes(trace: &[f64]) -> usize {
+    trace
+        .windows(2)
+        .filter(|w| w[0] < 0.0 && w[1] >= 0.0)
+        .count()
+}
+
+#[test]
+fn deterministic_gating_is_unchanged() {
+    let skeleton = swc_reader_from_bytes(
+        b"1 1 0 0 0 5 -1\n2 3 20 0 0 2 1\n3 3 40 0 0 1 2\n",
+        &ReaderOptions::default(),
+    )
+    .unwrap();
+    let mut compartments = Compartments::from_skeleton(skeleton);
+    for c in compartments.components.iter_mut() {
+        let mut channel = Channel::default();
+        channel.channel_type = ChannelType::HodgkinHuxley(HodgkinHuxley::new());
+        channel.resistance = 100.0;
+        channel.capacitance = 1.0;
+        c.set_channel(channel);
+    }
+    let steps = 800;
+    let stimulus = (0..steps)
+        .map(|s| if s < 200 { 0.3 } else { 0.0 })
+        .collect();
+    let mut simulation = Simulation::new(&compartments, 0.025).unwrap();
+    simulation.reseed(7);
+    let result = simulation.run(steps, &[(2, stimulus)]).unwrap();
+    assert_eq!(simulation.open_channels(2), None);
+    // Bits of the traces from before stochastic gating existed
+    let expected = [
+        (300, 0xc052919250e43bf1, 0xc052918e5fdfb88e),
+        (500, 0xc051350cd202c290, 0xc051350b1e9ab883),
+        (799, 0xc0502323f527da33, 0xc050232356b27f85),
+    ];
+    for (s, v2, v3) in expected {
+        assert_eq!(result.voltages[2][s].to_bits(), v2, "step {}", s);
+        assert_eq!(result.voltages[3][s].to_bits(), v3, "step {}", s);
+    }
+}
+
+#[test]
+fn mean_trace_approaches_deterministic_with_area() {
+    let (steps, trials, density) = (800, 8, 1.0);
+    for mode in [GatingMode::Binomial, GatingMode::DiffusionApprox] {
+        let mut deviations = Vec::new();
+        for length in [100.0, 1000.0, 10000.0] {
+            let reference = trace(
+                &hh_cylinder(length, GatingMode::Deterministic),
+                0,
+                density,
+                steps,
+            );
+            let stochastic = hh_cylinder(length, mode);
+            let mut mean = vec![0.0; steps + 1];
+            for seed in 0..trials {
+                for (m, v) in mean
+                    .iter_mut()
+                    .zip(trace(&stochastic, seed, density, steps))
+                {
+                    *m += v / trials as f64;
+                }
+            }
+            let rms = (mean
+                .iter()
+                .zip(&reference)
+                .map(|(m, r)| (m - r).powi(2))
+                .sum::<f64>()
+                / mean.len() as f64)
+                .sqrt();
+            deviations.push(rms);
+        }
+        assert!(
+            deviations.windows(2).all(|d| d[1] < d[0]),
+            "{:?}: {:?}",
+            mode,
+            deviations
+        );
+        assert!(deviations[2] < 0.5, "{:?}: {:?}", mode, deviations);
+    }
+}
+
+#[test]
+fn small_compartments_fire_spontaneously_and_reproducibly() {
+    let compartments = hh_cylinder(1.0, GatingMode::Binomial);
+    let steps = 20000;
+    let first = trace(&compartments, 3, 0.0, steps);
+    let count = spikes(&first);
+    assert!(count > 0, "no spontaneous spikes in 500 ms");
+    assert_eq!(trace(&compartments, 3, 0.0, steps), first);
+    assert_ne!(trace(&compartments, 4, 0.0, steps), first);
+
+    // Without noise the same compartment stays at rest
+    let quiet = trace(&hh_cylinder(1.0, GatingMode::Deterministic), 3, 0.0, steps);
+    assert_eq!(spikes(&quiet), 0);
+}
+
+#[test]
+fn channel_counts_stay_within_bounds() {
+    for mode in [GatingMode::Binomial, GatingMode::DiffusionApprox] {
+        let compartments = hh_cylinder(5.0, mode);
+        let area = compartments.components[2].membrane_area();
+        let hh = HodgkinHuxley::new();
+        let mut simulation = Simulation::new(&compartments, 0.025).unwrap();
+        simulation.reseed(11);
+        for s in 0..4000 {
+            if s < 400 {
+                simulation.inject(2, 0.01).unwrap();
+            }
+            simulation.step().unwrap();
+            let open = simulation.open_channels(2).unwrap();
+            assert_eq!(
+                open.na_total,
+                channel_count(hh.gnabar, area, hh.gamma_na) as f64
+            );
+            assert_eq!(
+                open.k_total,
+                channel_count(hh.gkbar, area, hh.gamma_k) as f64
+            );
+            assert!((0.0..=open.na_total).contains(&open.na_open), "{:?}", open);
+            assert!((0.0..=open.k_total).contains(&open.k_open), "{:?}", open);
+            if mode == GatingMode::Binomial {
+                assert_eq!(open.na_open.fract(), 0.0);
+                assert_eq!(open.k_open.fract(), 0.0);
+            }
+        }
+    }
+}
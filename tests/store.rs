@@ -0,0 +1,152 @@
+use std::sync::Arc;
+use std::thread;
+
+use compartment_rs::solver::{Simulation, SimulationResult};
+use compartment_rs::store::{ResultStore, StoreError};
+use compartment_rs::sweep::ParamSet;
+use compartment_rs::units::{MicroFaradPerCm2, OhmCm, SiemensPerCm2};
+use compartment_rs::{Channel, Compartments, ReaderOptions, swc_reader_from_bytes};
+
+fn cell() -> Compartments {
+    let skeleton = swc_reader_from_bytes(
+        b"1 1 0 0 0 5 -1\n2 3 20 0 0 1 1\n3 3 40 0 0 1 2\n",
+        &ReaderOptions::default(),
+    )
+    .unwrap();
+    let mut compartments = Compartments::from_skeleton(skeleton);
+    for c in compartments.components.iter_mut() {
+        c.set_channel(Channel::passive(
+            OhmCm::new(100.0).unwrap(),
+            MicroFaradPerCm2::new(1.0).unwrap(),
+            SiemensPerCm2::new(1e-4).unwrap(),
+        ));
+    }
+    compartments
+}
+
+/// A short run whose traces depend on `k`, with every kind of recording
+fn result(k: usize) -> SimulationResult {
+    let mut simulation = Simulation::new(&cell(), 0.025).unwrap();
+    if k.is_multiple_of(3) {
+        simulation = simulation.with_energy_accounting();
+    }
+    simulation.record("comp[3].v").unwrap();
+    simulation.add_axial_current_probe(2, 3).unwrap();
+    simulation
+        .run(20, &[(2, vec![0.01 * k as f64; 20])])
+        .unwrap()
+}
+
+fn params(k: usize) -> ParamSet {
+    ParamSet {
+        names: vec!["k".to_owned(), "amplitude".to_owned()],
+        values: vec![k as f64, 0.01 * k as f64],
+    }
+}
+
+fn tag(k: usize) -> &'static str {
+    if k.is_multiple_of(2) { "even" } else { "odd" }
+}
+
+fn temp(name: &str) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("{}-{}.store", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+#[test]
+fn concurrent_appends_are_all_found_and_load_back() {
+    let path = temp("concurrent");
+    let store = Arc::new(ResultStore::open(&path).unwrap());
+    let workers: Vec<_> = (0..4)
+        .map(|w| {
+            let store = Arc::clone(&store);
+            thread::spawn(move || {
+                (0..25)
+                    .map(|i| {
+                        let k = w * 25 + i;
+                        (k, store.append(tag(k), &params(k), &result(k)).unwrap())
+                    })
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect();
+    let ids: Vec<_> = workers
+        .into_iter()
+        .flat_map(|w| w.join().unwrap())
+        .collect();
+    assert_eq!(store.runs().len(), 100);
+    assert_eq!(store.with_tag("even").len(), 50);
+    assert_eq!(store.with_tag("odd").len(), 50);
+    let large = store.query(|run| run.params.get("k").unwrap() >= 90.0);
+    assert_eq!(large.len(), 10);
+
+    // Reopened, the index is rebuilt from the file
+    drop(store);
+    let store = ResultStore::open(&path).unwrap();
+    assert_eq!(store.recovered_bytes(), 0);
+    for (k, id) in ids {
+        assert_eq!(store.load(id).unwrap(), result(k), "run {}", k);
+        let info = store.runs().into_iter().find(|r| r.id == id).unwrap();
+        assert_eq!((info.tag.as_str(), info.params), (tag(k), params(k)));
+    }
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn a_torn_record_is_cut_off_on_reopening() {
+    let path = temp("torn");
+    let store = ResultStore::open(&path).unwrap();
+    for k in 0..5 {
+        store.append(tag(k), &params(k), &result(k)).unwrap();
+    }
+    drop(store);
+    let size = std::fs::metadata(&path).unwrap().len();
+    let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+    file.set_len(size - 40).unwrap();
+    drop(file);
+
+    let store = ResultStore::open(&path).unwrap();
+    assert!(store.recovered_bytes() > 0);
+    let runs = store.runs();
+    assert_eq!(runs.len(), 4);
+    for (k, run) in runs.iter().enumerate() {
+        assert_eq!(store.load(run.id).unwrap(), result(k));
+    }
+    // Appending carries on after the last complete record
+    let id = store.append("again", &params(9), &result(9)).unwrap();
+    drop(store);
+    let store = ResultStore::open(&path).unwrap();
+    assert_eq!(store.recovered_bytes(), 0);
+    assert_eq!(store.with_tag("again"), [id]);
+    assert_eq!(store.load(id).unwrap(), result(9));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn bad_input_is_refused() {
+    let path = temp("refused");
+    std::fs::write(&path, b"not a store").unwrap();
+    let error = ResultStore::open(&path).err().unwrap();
+    assert!(matches!(error, StoreError::NotAStore { .. }), "{}", error);
+    assert!(
+        error.to_string().contains("not a result store"),
+        "{}",
+        error
+    );
+    std::fs::remove_file(&path).unwrap();
+
+    let store = ResultStore::open(&path).unwrap();
+    assert!(matches!(
+        store.append("two\nlines", &params(0), &result(0)),
+        Err(StoreError::InvalidEntry { .. })
+    ));
+    let mut bad = params(0);
+    bad.names[0] = "a=b".to_owned();
+    assert!(matches!(
+        store.append("ok", &bad, &result(0)),
+        Err(StoreError::InvalidEntry { .. })
+    ));
+    assert!(store.runs().is_empty());
+    std::fs::remove_file(&path).unwrap();
+}
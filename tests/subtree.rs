@@ -0,0 +1,181 @@
+use compartment_rs::channels::{ChannelType, Dynamics, HodgkinHuxley};
+use compartment_rs::solver::{Simulation, SimulationResult};
+use compartment_rs::soma::SomaStyle;
+use compartment_rs::subtree::SubtreeSimulation;
+use compartment_rs::units::{MicroFaradPerCm2, OhmCm, SiemensPerCm2};
+use compartment_rs::{Channel, Compartments, ReaderOptions, swc_reader_from_bytes};
+
+const DT: f64 = 0.025;
+const STEPS: usize = 1200;
+
+/// HH soma and axon with a passive dendrite along +x that forks at 100 µm
+/// into two thin twigs
+fn cell() -> Compartments {
+    let mut swc = "1 1 0 0 0 10 -1\n".to_owned();
+    let mut id = 1;
+    let mut chain = |swc: &mut String, kind: u8, from: u64, step: [f64; 3], n: usize, r: f64| {
+        let mut parent = from;
+        let mut at = [0.0; 3];
+        if from != 1 {
+            at = [100.0, 0.0, 0.0];
+        }
+        for _ in 0..n {
+            id += 1;
+            at = [at[0] + step[0], at[1] + step[1], at[2] + step[2]];
+            swc.push_str(&format!(
+                "{} {} {} {} {} {} {}\n",
+                id, kind, at[0], at[1], at[2], r, parent
+            ));
+            parent = id;
+        }
+        parent
+    };
+    let fork = chain(&mut swc, 3, 1, [10.0, 0.0, 0.0], 10, 1.0);
+    chain(&mut swc, 3, fork, [8.0, 6.0, 0.0], 5, 0.25);
+    chain(&mut swc, 3, fork, [8.0, -6.0, 0.0], 5, 0.25);
+    chain(&mut swc, 2, 1, [-10.0, 0.0, 0.0], 10, 0.5);
+
+    let skeleton = swc_reader_from_bytes(swc.as_bytes(), &ReaderOptions::default()).unwrap();
+    let mut compartments = Compartments::from_skeleton(skeleton);
+    compartments.model_soma(SomaStyle::SinglePoint).unwrap();
+    for c in compartments.components.iter_mut().skip(1) {
+        let channel = if c.distal[0] > 0.0 {
+            Channel::passive(
+                OhmCm::new(150.0).unwrap(),
+                MicroFaradPerCm2::new(1.0).unwrap(),
+                SiemensPerCm2::new(1e-4).unwrap(),
+            )
+        } else {
+            let mut channel = Channel::default();
+            channel.channel_type = ChannelType::HodgkinHuxley(HodgkinHuxley::new());
+            channel.resistance = 150.0;
+            channel.capacitance = 1.0;
+            channel
+        };
+        c.set_channel(channel);
+    }
+    compartments
+}
+
+/// Compartment whose distal end is at `(x, y)`
+fn at(compartments: &Compartments, x: f64, y: f64) -> usize {
+    compartments
+        .components
+        .iter()
+        .position(|c| (c.distal[0] - x).abs() < 1e-9 && (c.distal[1] - y).abs() < 1e-9)
+        .unwrap()
+}
+
+/// A current pulse into the soma that fires a spike, and a steady current
+/// into the tip of the upper twig
+fn stimuli(compartments: &Compartments) -> Vec<(usize, Vec<f64>)> {
+    let pulse = (0..STEPS)
+        .map(|s| if (40..120).contains(&s) { 1.0 } else { 0.0 })
+        .collect();
+    let tip = at(compartments, 140.0, 30.0);
+    vec![(1, pulse), (tip, vec![0.02; STEPS])]
+}
+
+fn full_run(compartments: &Compartments) -> SimulationResult {
+    Simulation::new(compartments, DT)
+        .unwrap()
+        .run(STEPS, &stimuli(compartments))
+        .unwrap()
+}
+
+fn set_leak(compartments: &mut Compartments, idx: usize, g: f64) {
+    compartments.components[idx].channel.conductance = g;
+}
+
+#[test]
+fn an_unchanged_subtree_reproduces_the_full_run() {
+    let compartments = cell();
+    let full = full_run(&compartments);
+    // The soma fired
+    assert!(full.voltages[1].iter().any(|&v| v > 0.0));
+
+    let cut = at(&compartments, 40.0, 0.0);
+    let subtree = SubtreeSimulation::from_full_run(&full, &compartments, cut).unwrap();
+    // The cut, the six dendrite compartments up to the fork and both twigs
+    assert_eq!(subtree.compartments.components.len(), 1 + 7 + 10);
+    assert_eq!(subtree.full_index(1), Some(cut));
+    assert_eq!(subtree.reduced_index(1), None);
+
+    let run = subtree.run(&stimuli(&compartments)).unwrap();
+    for (idx, trace) in run.voltages.iter().enumerate().skip(1) {
+        let full_trace = &full.voltages[subtree.full_index(idx).unwrap()];
+        for (a, b) in trace.iter().zip(full_trace) {
+            assert!((a - b).abs() < 1e-9, "{}: {} vs {}", idx, a, b);
+        }
+    }
+    assert!(subtree.validity(&run) < 1e-9);
+    // The spike reaches the cut and pulls current into the subtree
+    assert!(subtree.full_boundary_current().iter().any(|&i| i > 0.05));
+}
+
+#[test]
+fn distal_twig_changes_match_a_fresh_full_run() {
+    let mut compartments = cell();
+    let full = full_run(&compartments);
+    let cut = at(&compartments, 40.0, 0.0);
+    let mut subtree = SubtreeSimulation::from_full_run(&full, &compartments, cut).unwrap();
+
+    // Twice the leak along the whole lower twig
+    let twig: Vec<usize> = (1..=5)
+        .map(|k| at(&compartments, 100.0 + 8.0 * k as f64, -6.0 * k as f64))
+        .collect();
+    for &idx in &twig {
+        set_leak(&mut compartments, idx, 2e-4);
+        let reduced = subtree.reduced_index(idx).unwrap();
+        set_leak(&mut subtree.compartments, reduced, 2e-4);
+    }
+    let fresh = full_run(&compartments);
+    let run = subtree.run(&stimuli(&compartments)).unwrap();
+
+    let max_difference = |a: &[f64], b: &[f64]| {
+        a.iter()
+            .zip(b)
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0, f64::max)
+    };
+    // The change moves the twig itself by well over a millivolt
+    let tip = *twig.last().unwrap();
+    let moved = max_difference(&full.voltages[tip], &fresh.voltages[tip]);
+    assert!(moved > 1.5, "{}", moved);
+    // but the cut, 60 µm and a fork away, by a fraction of that: the reduced
+    // run stays within 0.5 mV of the fresh one everywhere
+    for (idx, trace) in run.voltages.iter().enumerate().skip(1) {
+        let fresh_trace = &fresh.voltages[subtree.full_index(idx).unwrap()];
+        let difference = max_difference(trace, fresh_trace);
+        assert!(difference < 0.5, "{}: {}", idx, difference);
+    }
+    let reduced_tip = subtree.reduced_index(tip).unwrap();
+    assert!(max_difference(&run.voltages[reduced_tip], &fresh.voltages[tip]) < 0.2 * moved);
+    assert!(subtree.validity(&run) < 0.05);
+}
+
+#[test]
+fn changes_next_to_the_cut_break_the_approximation() {
+    let compartments = cell();
+    let full = full_run(&compartments);
+    let cut = at(&compartments, 40.0, 0.0);
+    let mut subtree = SubtreeSimulation::from_full_run(&full, &compartments, cut).unwrap();
+    let unchanged = subtree.validity(&subtree.run(&stimuli(&compartments)).unwrap());
+
+    let next = subtree.reduced_index(at(&compartments, 50.0, 0.0)).unwrap();
+    set_leak(&mut subtree.compartments, next, 0.1);
+    let run = subtree.run(&stimuli(&compartments)).unwrap();
+    let broken = subtree.validity(&run);
+    assert!(broken > 1.0 && broken > 1e6 * unchanged, "{}", broken);
+}
+
+#[test]
+fn cuts_need_a_parent() {
+    let compartments = cell();
+    let full = full_run(&compartments);
+    assert!(SubtreeSimulation::from_full_run(&full, &compartments, 1).is_err());
+    assert!(SubtreeSimulation::from_full_run(&full, &compartments, 999).is_err());
+    let mut short = full.clone();
+    short.voltages.pop();
+    assert!(SubtreeSimulation::from_full_run(&short, &compartments, 5).is_err());
+}
@@ -0,0 +1,223 @@
+use compartment_rs::channels::Passive;
+use compartment_rs::objectives::{Objective, WeightedObjective};
+use compartment_rs::solver::Simulation;
+use compartment_rs::sweep::{Param, ParamSet, ParamSpace, ParamTarget, SweepRunner};
+use compartment_rs::units::{MicroFaradPerCm2, OhmCm, SiemensPerCm2};
+use compartment_rs::{Channel, Compartments, ReaderOptions, swc_reader_from_bytes};
+
+const DT: f64 = 0.1;
+const STEPS: usize = 2000;
+
+/// A point soma and one cylinder 100 um long and 10 um across, so the
+/// cell is the cylinder alone
+fn cell() -> Compartments {
+    let skeleton = swc_reader_from_bytes(
+        b"1 1 0 0 0 5 -1\n2 3 100 0 0 5 1\n",
+        &ReaderOptions::default(),
+    )
+    .unwrap();
+    let mut compartments = Compartments::from_skeleton(skeleton);
+    for c in compartments.components.iter_mut() {
+        c.set_channel(Channel::passive(
+            OhmCm::new(100.0).unwrap(),
+            MicroFaradPerCm2::new(1.0).unwrap(),
+            SiemensPerCm2::new(1e-4).unwrap(),
+        ));
+    }
+    compartments
+}
+
+/// Steady state of the cylinder: E + I / (g A), in mV for I in nA and g in
+/// S/cm²; g A in nS is g * area * 10
+fn steady_state(conductance: f64, amplitude: f64) -> f64 {
+    let area = std::f64::consts::PI * 10.0 * 100.0;
+    Passive::default().e + amplitude / (conductance * area * 10.0) * 1e3
+}
+
+fn space() -> ParamSpace {
+    let mut space = ParamSpace::new();
+    space
+        .add(Param::linear(
+            "conductance",
+            ParamTarget::Model,
+            1e-4,
+            3e-4,
+            3,
+        ))
+        .unwrap();
+    space
+        .add(Param::log(
+            "amplitude",
+            ParamTarget::Protocol,
+            0.01,
+            0.09,
+            3,
+        ))
+        .unwrap();
+    space
+}
+
+#[test]
+fn a_grid_ranks_the_analytically_best_cell_first() {
+    let runner = SweepRunner::grid(space()).with_threads(4);
+    assert_eq!(runner.sets().len(), 9);
+    let target = steady_state(2e-4, 0.03);
+    let table = runner
+        .run(
+            &cell(),
+            DT,
+            |set, simulation| {
+                let amplitude = set.get("amplitude").unwrap();
+                simulation.run(STEPS, &[(2, vec![amplitude; STEPS])])
+            },
+            |_, result| (result.voltages[2][STEPS] - target).abs(),
+        )
+        .unwrap();
+
+    assert_eq!(table.names, ["conductance", "amplitude"]);
+    let best = table.best().unwrap();
+    assert!((best.get("conductance").unwrap() - 2e-4).abs() < 1e-12);
+    assert!((best.get("amplitude").unwrap() - 0.03).abs() < 1e-12);
+    assert!(table.rows[0].score < 1e-3, "{:?}", table.rows[0]);
+    assert!(table.rows.windows(2).all(|w| w[0].score <= w[1].score));
+    // Every other cell is off by what the closed form says
+    for row in &table.rows[1..] {
+        let expected = (steady_state(row.values[0], row.values[1]) - target).abs();
+        assert!((row.score - expected).abs() < 1e-3, "{:?}", row);
+    }
+
+    let csv = table.to_csv();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines[0], "conductance,amplitude,score");
+    assert_eq!(lines.len(), 10);
+    assert!(lines[1].starts_with(&format!("{},{},", best.values[0], best.values[1])));
+}
+
+#[test]
+fn random_search_is_reproducible() {
+    let sweep = |seed: u64, threads: usize| {
+        SweepRunner::random(space(), 6, seed)
+            .with_threads(threads)
+            .run(
+                &cell(),
+                DT,
+                |set, simulation| {
+                    let amplitude = set.get("amplitude").unwrap();
+                    simulation.run(100, &[(2, vec![amplitude; 100])])
+                },
+                |_, result| result.voltages[2][100],
+            )
+            .unwrap()
+    };
+    let sets = SweepRunner::random(space(), 6, 7).sets();
+    assert_eq!(sets, SweepRunner::random(space(), 6, 7).sets());
+    assert_ne!(sets, SweepRunner::random(space(), 6, 8).sets());
+    for set in &sets {
+        let g = set.get("conductance").unwrap();
+        let a = set.get("amplitude").unwrap();
+        assert!(
+            (1e-4..=3e-4).contains(&g) && (0.01..=0.09).contains(&a),
+            "{}",
+            set
+        );
+    }
+    assert_eq!(sweep(7, 1), sweep(7, 3));
+}
+
+#[test]
+fn bad_spaces_and_failing_runs_are_reported() {
+    let mut space = space();
+    assert!(
+        space
+            .add(Param::linear(
+                "conductance",
+                ParamTarget::Model,
+                0.0,
+                1.0,
+                2
+            ))
+            .is_err()
+    );
+    assert!(
+        space
+            .add(Param::log("x", ParamTarget::Protocol, 0.0, 1.0, 2))
+            .is_err()
+    );
+    assert!(
+        space
+            .add(Param::linear("x", ParamTarget::Protocol, 2.0, 1.0, 2))
+            .is_err()
+    );
+    assert!(
+        space
+            .add(Param::linear("x", ParamTarget::Protocol, 1.0, 2.0, 0))
+            .is_err()
+    );
+
+    let mut typo = ParamSpace::new();
+    typo.add(Param::linear(
+        "conductanse",
+        ParamTarget::Model,
+        1e-4,
+        2e-4,
+        2,
+    ))
+    .unwrap();
+    let error = SweepRunner::grid(typo)
+        .run(
+            &cell(),
+            DT,
+            |_, s: &mut Simulation| s.run(1, &[]),
+            |_, _| 0.0,
+        )
+        .unwrap_err();
+    assert!(error.contains("conductanse=0.0001"), "{}", error);
+    assert!(error.contains("did you mean"), "{}", error);
+}
+
+#[test]
+fn a_fit_ranks_by_an_objective_against_a_target_trace() {
+    let runner = SweepRunner::grid(space()).with_threads(4);
+    let protocol = |set: &ParamSet, simulation: &mut Simulation| {
+        let amplitude = set.get("amplitude").unwrap();
+        simulation.run(STEPS, &[(2, vec![amplitude; STEPS])])
+    };
+    // The target is the cell at the middle of the grid
+    let mut model = cell();
+    for c in model.components.iter_mut() {
+        c.channel.conductance = 2e-4;
+    }
+    let recorded = Simulation::new(&model, DT)
+        .unwrap()
+        .run(STEPS, &[(2, vec![0.03; STEPS])])
+        .unwrap();
+    let values = recorded.voltages[2].clone();
+    let times: Vec<f64> = (0..values.len()).map(|k| k as f64 * DT).collect();
+    let target = (times.as_slice(), values.as_slice());
+
+    let rms = WeightedObjective::default()
+        .with(Objective::Rms, 1.0)
+        .unwrap();
+    let table = runner
+        .fit(&cell(), DT, protocol, &rms, "comp[2].v", target)
+        .unwrap();
+    let best = table.best().unwrap();
+    assert!((best.get("conductance").unwrap() - 2e-4).abs() < 1e-12);
+    assert!((best.get("amplitude").unwrap() - 0.03).abs() < 1e-12);
+    assert!(table.rows[0].score < 1e-9, "{:?}", table.rows[0]);
+    assert!(table.rows[1].score > 1e-3, "{:?}", table.rows[1]);
+
+    // A passive cell never spikes, so there are no features to compare and
+    // every set ranks last
+    let features = WeightedObjective::default()
+        .with(Objective::Features, 1.0)
+        .unwrap();
+    let table = runner
+        .fit(&cell(), DT, protocol, &features, "comp[2].v", target)
+        .unwrap();
+    assert!(table.rows.iter().all(|row| row.score.is_nan()));
+    let error = runner
+        .fit(&cell(), DT, protocol, &rms, "comp[3].v", target)
+        .unwrap_err();
+    assert!(error.contains("comp[3].v"), "{}", error);
+}
@@ -0,0 +1,103 @@
+use compartment_rs::channels::{ChannelType, Dynamics, HodgkinHuxley};
+use compartment_rs::{
+    Channel, Compartments, NodeFlags, ReaderOptions, ThinNeuritePolicy, swc_reader,
+};
+
+/// Every compartment active, with resistivity, capacitance and conductance
+fn spine() -> Compartments {
+    let skeleton = swc_reader("data/thin_spine.swc", &ReaderOptions::default()).unwrap();
+    let mut compartments = Compartments::from_skeleton(skeleton);
+    for c in compartments.components.iter_mut() {
+        let mut channel = Channel::default();
+        channel.channel_type = ChannelType::HodgkinHuxley(HodgkinHuxley::new());
+        channel.resistance = 100.0;
+        channel.capacitance = 1.0;
+        channel.conductance = 0.036;
+        c.set_channel(channel);
+    }
+    compartments
+}
+
+fn neck(compartments: &Compartments) -> usize {
+    compartments
+        .components
+        .iter()
+        .position(|c| c.idx > 0 && c.diam < 0.1)
+        .unwrap()
+}
+
+fn total_area(compartments: &Compartments) -> f64 {
+    compartments.components[1..]
+        .iter()
+        .map(|c| c.membrane_area())
+        .sum()
+}
+
+#[test]
+fn make_passive_swaps_only_the_thin_neck() {
+    let mut compartments = spine();
+    let neck = neck(&compartments);
+    let affected = compartments
+        .apply_thin_neurite_policy(0.05, ThinNeuritePolicy::MakePassive)
+        .unwrap();
+    assert_eq!(affected, vec![neck]);
+    for (i, c) in compartments.components.iter().enumerate().skip(1) {
+        let passive = matches!(c.channel.channel_type, ChannelType::Passive(_));
+        assert_eq!(passive, i == neck);
+        assert_eq!(c.flags.contains(NodeFlags::MECHANISM_PASSIVATED), i == neck);
+    }
+    assert_eq!(compartments.components[neck].channel.conductance, 0.036);
+}
+
+#[test]
+fn merge_folds_the_neck_into_its_parent() {
+    let mut compartments = spine();
+    let neck = neck(&compartments);
+    let parent = compartments.components[neck].parent_idxs[0] as usize;
+    let area = total_area(&compartments);
+    let capacitance: f64 = compartments.capacitances()[1..].iter().sum();
+
+    let affected = compartments
+        .apply_thin_neurite_policy(0.05, ThinNeuritePolicy::MergeIntoParent)
+        .unwrap();
+    assert_eq!(affected, vec![neck]);
+    assert_eq!(compartments.components.len(), 6);
+    assert!(compartments.components[1..].iter().all(|c| c.diam >= 0.1));
+    assert!((total_area(&compartments) - area).abs() <= 1e-12 * area);
+    let after: f64 = compartments.capacitances()[1..].iter().sum();
+    assert!((after - capacitance).abs() <= 1e-12 * capacitance);
+
+    // The head now hangs off the neck's old parent, which is flagged
+    let absorbing = &compartments.components[parent];
+    assert!(absorbing.flags.contains(NodeFlags::THIN_MERGED));
+    let head = compartments
+        .components
+        .iter()
+        .find(|c| (c.diam - 0.6).abs() < 1e-12)
+        .unwrap();
+    assert_eq!(head.parent_idxs, vec![parent as u64]);
+    assert!(absorbing.children_idxs.contains(&head.idx));
+    for (i, c) in compartments.components.iter().enumerate() {
+        assert_eq!(c.idx, i as u64);
+        for &child in &c.children_idxs {
+            assert_eq!(
+                compartments.components[child as usize].parent_idxs,
+                vec![i as u64]
+            );
+        }
+    }
+}
+
+#[test]
+fn error_names_the_compartment() {
+    let mut compartments = spine();
+    let name = compartments.components[neck(&compartments)].name.clone();
+    let err = compartments
+        .apply_thin_neurite_policy(0.05, ThinNeuritePolicy::Error)
+        .unwrap_err();
+    assert!(err.contains(&name), "{}", err);
+    assert_eq!(
+        compartments.apply_thin_neurite_policy(0.005, ThinNeuritePolicy::Error),
+        Ok(vec![])
+    );
+}
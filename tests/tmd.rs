@@ -0,0 +1,59 @@
+use compartment_rs::tmd::{self, Filtration};
+use compartment_rs::{ReaderOptions, Skeleton, swc_reader};
+
+/// Two levels of bifurcation, every branch 5 long
+fn binary_tree() -> Skeleton {
+    let xyz = [
+        [0.0, 0.0, 0.0],
+        [3.0, 4.0, 0.0],
+        [-3.0, 4.0, 0.0],
+        [6.0, 8.0, 0.0],
+        [3.0, 4.0, 5.0],
+        [-6.0, 8.0, 0.0],
+        [-3.0, 4.0, 5.0],
+    ];
+    Skeleton::from_arrays(
+        &[1, 2, 3, 4, 5, 6, 7],
+        &[1, 3, 3, 3, 3, 3, 3],
+        &xyz,
+        &[1.0; 7],
+        &[-1, 1, 1, 2, 2, 3, 3],
+        &ReaderOptions::default(),
+    )
+    .unwrap()
+}
+
+#[test]
+fn binary_tree_barcode() {
+    let mut bars = tmd::tmd_barcode(&binary_tree(), Filtration::PathDistance).unwrap();
+    bars.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    assert_eq!(
+        bars,
+        vec![(0.0, 10.0), (0.0, 10.0), (5.0, 10.0), (5.0, 10.0)]
+    );
+}
+
+#[test]
+fn one_bar_per_tip() {
+    let skeleton = swc_reader("data/basic.swc", &ReaderOptions::default()).unwrap();
+    let tips = skeleton
+        .nodes
+        .iter()
+        .filter(|n| skeleton.children_of(n.node_id).is_empty())
+        .count();
+    for filtration in [Filtration::PathDistance, Filtration::RadialDistance] {
+        let bars = tmd::tmd_barcode(&skeleton, filtration).unwrap();
+        assert_eq!(bars.len(), tips);
+        assert!(bars.iter().all(|(birth, death)| birth <= death));
+    }
+}
+
+#[test]
+fn persistence_image_integrates_to_bar_count() {
+    let skeleton = swc_reader("data/basic.swc", &ReaderOptions::default()).unwrap();
+    let bars = tmd::tmd_barcode(&skeleton, Filtration::PathDistance).unwrap();
+    let image = tmd::barcode_to_image(&bars, 64, 2.0);
+    assert_eq!((image.len(), image[0].len()), (64, 64));
+    let total: f64 = image.iter().flatten().sum();
+    assert!((total - bars.len() as f64).abs() < 1e-5, "{}", total);
+}
@@ -0,0 +1,76 @@
+use compartment_rs::units::{
+    self, MicroFaradPerCm2, Micron, Ms, Mv, NanoAmp, OhmCm, SiemensPerCm2,
+};
+use compartment_rs::{Channel, Compartments, ReaderOptions, swc_reader};
+
+#[test]
+fn conversions_round_trip() {
+    let g = SiemensPerCm2::from_millisiemens(120.0).unwrap();
+    assert_eq!(g.value(), 0.12);
+    assert_eq!(g.millisiemens(), 120.0);
+    assert_eq!(
+        SiemensPerCm2::from_siemens_per_m2(1.0).unwrap().value(),
+        1e-4
+    );
+    assert_eq!(Mv::from_volts(-0.065).unwrap().value(), -65.0);
+    assert_eq!(Mv::new(-65.0).unwrap().volts(), -0.065);
+    assert_eq!(Ms::from_seconds(0.5).unwrap().value(), 500.0);
+    assert_eq!(Ms::from_microseconds(25.0).unwrap().value(), 0.025);
+    assert_eq!(Ms::new(500.0).unwrap().seconds(), 0.5);
+    assert_eq!(NanoAmp::from_picoamps(250.0).unwrap().value(), 0.25);
+    assert_eq!(NanoAmp::from_microamps(0.5).unwrap().picoamps(), 500_000.0);
+    assert_eq!(
+        MicroFaradPerCm2::from_farads_per_m2(0.01).unwrap().value(),
+        1.0
+    );
+    assert_eq!(OhmCm::from_ohm_m(1.5).unwrap().ohm_m(), 1.5);
+    assert_eq!(Micron::from_mm(0.2).unwrap().value(), 200.0);
+    assert_eq!(Micron::from_cm(1.0).unwrap().mm(), 10.0);
+    assert_eq!(g.to_string(), "0.12 S/cm2");
+}
+
+#[test]
+fn out_of_range_values_are_explained() {
+    assert_eq!(
+        SiemensPerCm2::new(-0.1).unwrap_err(),
+        "SiemensPerCm2 cannot be negative, got -0.1 S/cm2"
+    );
+    assert!(Ms::new(f64::NAN).is_err());
+    // Potentials and currents may well be negative
+    assert!(Mv::new(-80.0).is_ok());
+    assert!(NanoAmp::new(-0.1).is_ok());
+}
+
+#[test]
+fn typed_channel_matches_raw_values() {
+    let skeleton = swc_reader("data/basic.swc", &ReaderOptions::default()).unwrap();
+    let mut typed = Compartments::from_skeleton(skeleton.clone());
+    let mut raw = Compartments::from_skeleton(skeleton);
+    for (t, r) in typed.components.iter_mut().zip(raw.components.iter_mut()) {
+        t.set_channel(Channel::passive(
+            OhmCm::from_ohm_m(1.0).unwrap(),
+            MicroFaradPerCm2::new(1.0).unwrap(),
+            SiemensPerCm2::from_millisiemens(0.3).unwrap(),
+        ));
+        let mut channel = Channel::default();
+        channel.resistance = 100.0;
+        channel.capacitance = 1.0;
+        channel.conductance = 0.0003;
+        r.set_channel(channel);
+    }
+    assert_eq!(typed.capacitances(), raw.capacitances());
+    for (t, r) in typed.components.iter().zip(&raw.components).skip(1) {
+        assert_eq!(t.membrane_conductance(), r.membrane_conductance());
+        assert_eq!(t.axial_resistance(), r.axial_resistance());
+    }
+}
+
+#[test]
+fn implausible_time_steps_warn() {
+    assert!(units::check_dt(Ms::new(0.025).unwrap()).is_none());
+    // A 250 us step passed without converting to ms
+    assert!(units::check_dt(Ms::new(250.0).unwrap()).is_some());
+    // 0.025 s is 25 ms: long, but a plausible coarse step
+    assert!(units::check_dt(Ms::from_seconds(0.025).unwrap()).is_none());
+    assert!(units::check_dt(Ms::new(0.0).unwrap()).is_some());
+}
@@ -0,0 +1,267 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use compartment_rs::channels::{ChannelType, Dynamics, HodgkinHuxley};
+use compartment_rs::index_map::AttachmentKind;
+use compartment_rs::solver::{Simulation, SimulationResult};
+use compartment_rs::validation::{
+    ComparisonOptions, ReferenceTrace, compare_probes, compare_to_reference,
+};
+use compartment_rs::{Channel, Compartments, ReaderOptions, swc_reader_from_bytes};
+
+const DT: f64 = 0.025;
+
+fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("validation-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Two Hodgkin-Huxley cylinders off a point soma, the first driven to spike
+/// for 5 ms of a 20 ms run
+fn spiking_run() -> (Compartments, SimulationResult) {
+    let skeleton = swc_reader_from_bytes(
+        b"1 1 0 0 0 5 -1\n2 3 20 0 0 2 1\n3 3 40 0 0 1 2\n",
+        &ReaderOptions::default(),
+    )
+    .unwrap();
+    let mut compartments = Compartments::from_skeleton(skeleton);
+    for c in compartments.components.iter_mut() {
+        let mut channel = Channel::default();
+        channel.channel_type = ChannelType::HodgkinHuxley(HodgkinHuxley::new());
+        channel.resistance = 100.0;
+        channel.capacitance = 1.0;
+        c.set_channel(channel);
+    }
+    let steps = 800;
+    let stimulus = (0..steps)
+        .map(|s| if s < 200 { 0.3 } else { 0.0 })
+        .collect();
+    let result = Simulation::new(&compartments, DT)
+        .unwrap()
+        .run(steps, &[(2, stimulus)])
+        .unwrap();
+    (compartments, result)
+}
+
+fn csv(trace: &ReferenceTrace) -> String {
+    let mut text = "time,value\n".to_owned();
+    for (t, v) in trace.times().iter().zip(trace.values()) {
+        text.push_str(&format!("{},{}\n", t, v));
+    }
+    text
+}
+
+/// `numpy.save` of the `(n, 2)` float64 array of `trace`
+fn npy(trace: &ReferenceTrace, fortran: bool) -> Vec<u8> {
+    let n = trace.times().len();
+    let mut header = format!(
+        "{{'descr': '<f8', 'fortran_order': {}, 'shape': ({}, 2), }}",
+        if fortran { "True" } else { "False" },
+        n
+    );
+    while (10 + header.len() + 1) % 64 != 0 {
+        header.push(' ');
+    }
+    header.push('\n');
+    let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+    bytes.extend((header.len() as u16).to_le_bytes());
+    bytes.extend(header.as_bytes());
+    let values: Vec<f64> = if fortran {
+        trace
+            .times()
+            .iter()
+            .chain(trace.values())
+            .copied()
+            .collect()
+    } else {
+        trace
+            .times()
+            .iter()
+            .zip(trace.values())
+            .flat_map(|(&t, &v)| [t, v])
+            .collect()
+    };
+    for v in values {
+        bytes.extend(v.to_le_bytes());
+    }
+    bytes
+}
+
+#[test]
+fn a_trace_matches_itself() {
+    let (_, result) = spiking_run();
+    let reference = ReferenceTrace::from_simulation(&result, 2).unwrap();
+    let report =
+        compare_to_reference(&result, 2, &reference, &ComparisonOptions::default()).unwrap();
+    assert!(report.passed(), "{}", report.summary());
+    assert_eq!(report.rms_error, 0.0);
+    assert_eq!(report.max_error, 0.0);
+    assert_eq!(report.samples, 801);
+    assert!(report.simulated_spikes > 0);
+    assert_eq!(report.spike_offsets, vec![0.0; report.simulated_spikes]);
+    assert_eq!(report.unmatched_reference, 0);
+    assert!(report.to_json().contains("\"passed\":true"));
+}
+
+#[test]
+fn a_shift_of_one_step_shows_in_errors_and_spike_times() {
+    let (_, result) = spiking_run();
+    let v = &result.voltages[2];
+    let mut shifted = vec![v[0]];
+    shifted.extend_from_slice(&v[..v.len() - 1]);
+    let times = (0..v.len()).map(|k| k as f64 * DT).collect();
+    let reference = ReferenceTrace::new(times, shifted).unwrap();
+
+    let report =
+        compare_to_reference(&result, 2, &reference, &ComparisonOptions::default()).unwrap();
+    // The largest jump between neighbouring samples, and where it lands
+    let (mut k, mut expected) = (0, 0.0);
+    for i in 1..v.len() {
+        if (v[i] - v[i - 1]).abs() > expected {
+            (k, expected) = (i, (v[i] - v[i - 1]).abs());
+        }
+    }
+    assert!(
+        (report.max_error - expected).abs() < 1e-12,
+        "{}",
+        report.max_error
+    );
+    assert!((report.max_error_time - k as f64 * DT).abs() < 1e-12);
+    assert!(!report.spike_offsets.is_empty());
+    for offset in &report.spike_offsets {
+        assert!((offset + DT).abs() < 1e-9, "{}", offset);
+    }
+    assert!((report.max_spike_offset() - DT).abs() < 1e-9);
+
+    let strict = ComparisonOptions {
+        spike_time_tolerance: DT / 2.0,
+        ..ComparisonOptions::default()
+    };
+    let report = compare_to_reference(&result, 2, &reference, &strict).unwrap();
+    assert!(!report.passed());
+    assert!(report.summary().starts_with("FAIL 2:"));
+}
+
+#[test]
+fn csv_and_npy_load_the_same_trace() {
+    let (_, result) = spiking_run();
+    let trace = ReferenceTrace::from_simulation(&result, 3).unwrap();
+    let dir = scratch("loaders");
+    fs::write(dir.join("trace.csv"), csv(&trace)).unwrap();
+    fs::write(dir.join("trace.npy"), npy(&trace, false)).unwrap();
+    fs::write(dir.join("fortran.npy"), npy(&trace, true)).unwrap();
+
+    let from_csv = ReferenceTrace::load(dir.join("trace.csv")).unwrap();
+    assert_eq!(from_csv, trace);
+    assert_eq!(
+        ReferenceTrace::load(dir.join("trace.npy")).unwrap(),
+        from_csv
+    );
+    assert_eq!(
+        ReferenceTrace::load(dir.join("fortran.npy")).unwrap(),
+        from_csv
+    );
+
+    assert!(ReferenceTrace::parse_csv("time,value\n0,1\n1,oops\n").is_err());
+    assert!(ReferenceTrace::parse_csv("0,1\n0,2\n").is_err());
+    assert!(ReferenceTrace::parse_npy(b"not numpy").is_err());
+}
+
+#[test]
+fn mismatched_time_ranges_are_errors() {
+    let (_, result) = spiking_run();
+    let full = ReferenceTrace::from_simulation(&result, 2).unwrap();
+    let half = full.times().len() / 2;
+    let short = ReferenceTrace::new(
+        full.times()[..half].to_vec(),
+        full.values()[..half].to_vec(),
+    )
+    .unwrap();
+    let error =
+        compare_to_reference(&result, 2, &short, &ComparisonOptions::default()).unwrap_err();
+    assert!(error.contains("time ranges differ"), "{}", error);
+
+    let late = ReferenceTrace::new(
+        full.times().iter().map(|t| t + 1.0).collect(),
+        full.values().to_vec(),
+    )
+    .unwrap();
+    assert!(compare_to_reference(&result, 2, &late, &ComparisonOptions::default()).is_err());
+
+    // A coarser reference over the same range is resampled, not rejected
+    let coarse = ReferenceTrace::new(
+        full.times().iter().step_by(4).copied().collect(),
+        full.values().iter().step_by(4).copied().collect(),
+    )
+    .unwrap();
+    let report = compare_to_reference(&result, 2, &coarse, &ComparisonOptions::default()).unwrap();
+    assert_eq!(report.samples, 801);
+    assert!(report.max_error > 0.0);
+}
+
+#[test]
+fn probes_and_directories_are_compared_by_name() {
+    let (mut compartments, result) = spiking_run();
+    compartments
+        .attach(AttachmentKind::Probe, "proximal", 2, 0.5, 0.5)
+        .unwrap();
+    compartments
+        .attach(AttachmentKind::Probe, "distal", 3, 0.5, 0.5)
+        .unwrap();
+    let references = scratch("references");
+    let simulated = scratch("simulated");
+    for (name, idx) in [("proximal", 2), ("distal", 3)] {
+        let trace = ReferenceTrace::from_simulation(&result, idx).unwrap();
+        fs::write(references.join(format!("{}.npy", name)), npy(&trace, false)).unwrap();
+        fs::write(simulated.join(format!("{}.csv", name)), csv(&trace)).unwrap();
+    }
+
+    let reports = compare_probes(
+        &result,
+        &compartments,
+        &references,
+        &ComparisonOptions::default(),
+    )
+    .unwrap();
+    let names: Vec<&str> = reports.iter().map(|r| r.probe.as_str()).collect();
+    assert_eq!(names, ["proximal", "distal"]);
+    assert!(reports.iter().all(|r| r.passed()));
+
+    compartments
+        .attach(AttachmentKind::Probe, "soma", 1, 0.5, 0.5)
+        .unwrap();
+    let error = compare_probes(
+        &result,
+        &compartments,
+        &references,
+        &ComparisonOptions::default(),
+    )
+    .unwrap_err();
+    assert!(error.contains("soma"), "{}", error);
+
+    let json = simulated.join("report.json");
+    let output = Command::new(env!("CARGO_BIN_EXE_compartment-rs"))
+        .arg("compare")
+        .arg(&simulated)
+        .arg(&references)
+        .arg("--json")
+        .arg(&json)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.contains("PASS distal:") && stdout.contains("PASS proximal:"),
+        "{}",
+        stdout
+    );
+    let json = fs::read_to_string(json).unwrap();
+    assert!(
+        json.starts_with("[{\"probe\":\"distal\",\"passed\":true,"),
+        "{}",
+        json
+    );
+}
@@ -0,0 +1,69 @@
+use compartment_rs::{ReaderOptions, StructureIdentifier, WarningKind, swc_reader};
+
+/// A soma followed by `n` zero-radius nodes, alternating axon and end point.
+/// Returns the path; the zero-radius nodes sit on lines 3..=n + 2.
+fn zero_radius_file(name: &str, n: usize) -> std::path::PathBuf {
+    let mut text = String::from("# generated\n1 1 0 0 0 5 -1\n");
+    for i in 0..n {
+        let id = i + 2;
+        let structure = if i % 2 == 0 { 2 } else { 6 };
+        text.push_str(&format!(
+            "{} {} {} 0 0 0 {}\n",
+            id,
+            structure,
+            i + 1,
+            id - 1
+        ));
+    }
+    let path = std::env::temp_dir().join(name);
+    std::fs::write(&path, text).unwrap();
+    path
+}
+
+#[test]
+fn zero_radius_warnings_are_aggregated() {
+    let path = zero_radius_file("compartment_rs_warn_agg.swc", 10_000);
+    let skeleton = swc_reader(&path, &ReaderOptions::default()).unwrap();
+
+    assert_eq!(skeleton.warnings.len(), 1);
+    let warning = &skeleton.warnings[0];
+    assert_eq!(warning.kind, WarningKind::ZeroRadius);
+    assert_eq!(warning.count, 10_000);
+    assert_eq!(warning.first_line, 3);
+    assert_eq!(warning.last_line, 10_002);
+    assert_eq!(warning.by_type[&StructureIdentifier::Axon], 5_000);
+    assert_eq!(warning.by_type[&StructureIdentifier::EndPoint], 5_000);
+    assert_eq!(
+        warning.to_string(),
+        "10,000 zero-radius nodes (Axon: 5,000, EndPoint: 5,000); first at line 3, last at line 10,002"
+    );
+}
+
+#[test]
+fn verbose_warnings_are_capped() {
+    let path = zero_radius_file("compartment_rs_warn_verbose.swc", 10_000);
+    let options = ReaderOptions {
+        verbose_warnings: true,
+        warning_cap: 20,
+        ..Default::default()
+    };
+    let skeleton = swc_reader(&path, &options).unwrap();
+
+    // 20 individual warnings, then the summary
+    assert_eq!(skeleton.warnings.len(), 21);
+    for (i, warning) in skeleton.warnings[..20].iter().enumerate() {
+        assert_eq!(warning.count, 1);
+        assert_eq!(warning.first_line, i + 3);
+    }
+    assert_eq!(skeleton.warnings[20].count, 10_000);
+}
+
+#[test]
+fn no_warnings_when_disabled() {
+    let path = zero_radius_file("compartment_rs_warn_off.swc", 10);
+    let options = ReaderOptions {
+        emit_warnings: false,
+        ..Default::default()
+    };
+    assert!(swc_reader(&path, &options).unwrap().warnings.is_empty());
+}
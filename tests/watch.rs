@@ -0,0 +1,126 @@
+use std::fs;
+use std::path::PathBuf;
+
+use compartment_rs::{Morphometry, ReaderOptions, SkeletonWatcher};
+
+const BASIC: &str = include_str!("../data/basic.swc");
+
+fn temp_copy(name: &str, text: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "compartment_rs_watch_{}_{}.swc",
+        name,
+        std::process::id()
+    ));
+    fs::write(&path, text).unwrap();
+    path
+}
+
+fn fresh_length(watcher: &SkeletonWatcher) -> f64 {
+    Morphometry::new(&watcher.skeleton().nodes).total_length()
+}
+
+#[test]
+fn unchanged_file_is_not_parsed_again() {
+    let path = temp_copy("unchanged", BASIC);
+    let mut watcher = SkeletonWatcher::open(&path, &ReaderOptions::default()).unwrap();
+    assert!(watcher.reload_if_changed().unwrap().is_none());
+    // Written again with the same bytes: read and hashed, but not parsed
+    fs::write(&path, BASIC).unwrap();
+    assert!(watcher.reload_if_changed().unwrap().is_none());
+    assert_eq!(watcher.parse_count(), 1);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn appended_node_updates_length_incrementally() {
+    let path = temp_copy("append", BASIC);
+    let mut watcher = SkeletonWatcher::open(&path, &ReaderOptions::default()).unwrap();
+    let before = watcher.total_length();
+    let hull = watcher.spatial_metrics().clone();
+
+    // Off the deepest tip, so every existing ID stays put
+    fs::write(&path, format!("{}16 3 45.0 10.0 0.0 0.4 5\n", BASIC)).unwrap();
+    let summary = watcher.reload_if_changed().unwrap().unwrap();
+    assert_eq!(summary.added.len(), 1);
+    assert!(summary.removed.is_empty() && summary.moved.is_empty());
+    assert!(!summary.by_fingerprint);
+    assert_eq!(watcher.parse_count(), 2);
+    assert_eq!(
+        watcher.total_length().to_bits(),
+        fresh_length(&watcher).to_bits()
+    );
+    assert!((watcher.total_length() - before - 10.0).abs() < 1e-12);
+    let fresh = Morphometry::new(&watcher.skeleton().nodes).spatial_metrics();
+    assert_eq!(*watcher.spatial_metrics(), fresh);
+    assert_ne!(fresh, hull);
+
+    // Straight off the soma: IDs shift, nodes are matched by fingerprint
+    let text = fs::read_to_string(&path).unwrap();
+    fs::write(&path, format!("{}17 3 0.0 -5.0 0.0 0.4 1\n", text)).unwrap();
+    let summary = watcher.reload_if_changed().unwrap().unwrap();
+    assert!(summary.by_fingerprint);
+    assert_eq!(summary.added.len(), 1);
+    assert!(summary.removed.is_empty() && summary.moved.is_empty());
+    assert_eq!(
+        watcher.total_length().to_bits(),
+        fresh_length(&watcher).to_bits()
+    );
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn moved_node_is_reported() {
+    let path = temp_copy("move", BASIC);
+    let mut watcher = SkeletonWatcher::open(&path, &ReaderOptions::default()).unwrap();
+    fs::write(&path, BASIC.replace("5 3 35.0 10.0", "5 3 36.0 10.0")).unwrap();
+    let summary = watcher.reload_if_changed().unwrap().unwrap();
+    assert_eq!(summary.moved.len(), 1);
+    let (before, after) = summary.moved[0];
+    assert_eq!((before.x_pos, after.x_pos), (35.0, 36.0));
+    assert!(summary.added.is_empty() && summary.removed.is_empty());
+    assert_eq!(
+        watcher.total_length().to_bits(),
+        fresh_length(&watcher).to_bits()
+    );
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn rewrite_with_new_ids_falls_back_to_fingerprints() {
+    let path = temp_copy("rewrite", BASIC);
+    let mut watcher = SkeletonWatcher::open(&path, &ReaderOptions::default()).unwrap();
+    let old_count = watcher.skeleton().nodes.len();
+
+    // New IDs, lines in reverse order, and the last axon node moved
+    let remap = |id: i64| if id < 0 { id } else { 1000 - 7 * id };
+    let mut lines: Vec<String> = BASIC
+        .lines()
+        .filter(|l| !l.starts_with('#'))
+        .map(|l| {
+            let mut f: Vec<String> = l.split_whitespace().map(str::to_owned).collect();
+            f[0] = remap(f[0].parse().unwrap()).to_string();
+            f[6] = remap(f[6].parse().unwrap()).to_string();
+            if f[2] == "-45.0" {
+                f[3] = "3.0".to_owned();
+            }
+            f.join(" ")
+        })
+        .collect();
+    lines.reverse();
+    fs::write(&path, lines.join("\n")).unwrap();
+
+    let summary = watcher.reload_if_changed().unwrap().unwrap();
+    assert!(summary.by_fingerprint);
+    assert_eq!(summary.removed.len(), 1);
+    assert_eq!(summary.added.len(), 1);
+    assert_eq!(summary.added[0].y_pos, 3.0);
+    assert_eq!(
+        watcher.skeleton().nodes.len(),
+        old_count - summary.removed.len() + summary.added.len()
+    );
+    assert_eq!(
+        watcher.total_length().to_bits(),
+        fresh_length(&watcher).to_bits()
+    );
+    fs::remove_file(&path).unwrap();
+}
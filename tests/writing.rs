@@ -0,0 +1,104 @@
+use std::path::PathBuf;
+
+use compartment_rs::{ConflictPolicy, ReaderOptions, SwcError, swc_reader};
+
+/// Fresh scratch directory per test
+fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("compartment_rs_{}", name));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn write_to(path: PathBuf, policy: ConflictPolicy) -> Result<(), SwcError> {
+    let options = ReaderOptions {
+        write_path: Some(path),
+        write_conflict: policy,
+        ..Default::default()
+    };
+    swc_reader("data/basic.swc", &options).map(|_| ())
+}
+
+#[test]
+fn creates_missing_directories() {
+    let path = scratch("nested").join("a/b/c/out.swc");
+    write_to(path.clone(), ConflictPolicy::Overwrite).unwrap();
+    let reread = swc_reader(&path, &ReaderOptions::default()).unwrap();
+    assert_eq!(reread.nodes.len(), 15);
+    // No temporary files left behind
+    assert_eq!(
+        std::fs::read_dir(path.parent().unwrap()).unwrap().count(),
+        1
+    );
+}
+
+#[test]
+fn conflict_policies() {
+    let path = scratch("conflict").join("out.swc");
+    std::fs::write(&path, "existing").unwrap();
+    let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+
+    write_to(path.clone(), ConflictPolicy::Skip).unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "existing");
+    assert_eq!(
+        std::fs::metadata(&path).unwrap().modified().unwrap(),
+        modified
+    );
+
+    match write_to(path.clone(), ConflictPolicy::Error) {
+        Err(SwcError::Io {
+            path: err_path,
+            kind,
+            ..
+        }) => {
+            assert_eq!(err_path, path);
+            assert_eq!(kind, std::io::ErrorKind::AlreadyExists);
+        }
+        other => panic!("Expected an IO error, got {:?}", other),
+    }
+
+    write_to(path.clone(), ConflictPolicy::Overwrite).unwrap();
+    assert!(
+        std::fs::read_to_string(&path)
+            .unwrap()
+            .starts_with("# Processed SWC file")
+    );
+}
+
+#[test]
+fn write_failure_names_the_path() {
+    // A regular file where a directory should be fails even when running as
+    // root, unlike a read-only directory
+    let dir = scratch("blocked");
+    let blocker = dir.join("not_a_dir");
+    std::fs::write(&blocker, "").unwrap();
+
+    let err = write_to(blocker.join("out.swc"), ConflictPolicy::Overwrite).unwrap_err();
+    assert!(matches!(err, SwcError::Io { .. }), "{:?}", err);
+    assert!(err.to_string().contains("not_a_dir"), "{}", err);
+}
+
+#[test]
+fn concurrent_writes_into_one_directory() {
+    let dir = scratch("concurrent");
+    let handles: Vec<_> = (0..8)
+        .map(|i| {
+            let path = dir.join(format!("out_{}.swc", i));
+            std::thread::spawn(move || write_to(path, ConflictPolicy::Overwrite))
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap().unwrap();
+    }
+    for i in 0..8 {
+        let path = dir.join(format!("out_{}.swc", i));
+        assert_eq!(
+            swc_reader(&path, &ReaderOptions::default())
+                .unwrap()
+                .nodes
+                .len(),
+            15
+        );
+    }
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 8);
+}